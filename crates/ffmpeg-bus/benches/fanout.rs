@@ -0,0 +1,101 @@
+//! Fan-out of input packets to consumers of one stream each: through the
+//! all-streams channel, which every consumer filters, or through each
+//! stream's own channel. Reads scripts/test.mp4 a number of times and prints
+//! how many packets each subscriber was handed and how many it wanted.
+//!
+//! `cargo bench -p ffmpeg-bus --bench fanout`
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ffmpeg_bus::input::{AvInput, AvInputTask};
+use ffmpeg_bus::packet::{RawPacketCmd, RawPacketReceiver};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Subscribers of each stream of the file.
+const CONSUMERS_PER_STREAM: usize = 4;
+/// Reads of the file per mode.
+const PASSES: usize = 20;
+/// Room for a whole pass, so that no subscriber lags and the counts are exact.
+const CAPACITY: usize = 1 << 14;
+
+#[derive(Default)]
+struct Tally {
+    subscribers: u64,
+    /// Packets handed to the subscribers.
+    received: u64,
+    /// Of those, packets of the subscriber's own stream.
+    wanted: u64,
+    elapsed: Duration,
+}
+
+async fn consume(mut rx: RawPacketReceiver, index: usize) -> (u64, u64) {
+    let (mut received, mut wanted) = (0, 0);
+    loop {
+        match rx.recv().await {
+            Ok(RawPacketCmd::Data(packet)) => {
+                received += 1;
+                if packet.index() == index {
+                    wanted += 1;
+                }
+            }
+            Ok(RawPacketCmd::EOF) | Err(RecvError::Closed) => break,
+            Ok(RawPacketCmd::Reconnected) | Err(RecvError::Lagged(_)) => {}
+        }
+    }
+    (received, wanted)
+}
+
+async fn pass(path: &str, per_stream: bool, tally: &mut Tally) -> anyhow::Result<()> {
+    let input = AvInput::new(path, None, None)?;
+    let task = AvInputTask::with_capacity(CAPACITY);
+    let mut consumers = Vec::new();
+    for &index in input.streams().keys() {
+        for _ in 0..CONSUMERS_PER_STREAM {
+            let rx = if per_stream {
+                task.subscribe_stream(index)
+            } else {
+                task.subscribe()
+            };
+            consumers.push(tokio::spawn(consume(rx, index)));
+        }
+    }
+    let start = Instant::now();
+    task.start(input).await;
+    tally.subscribers += consumers.len() as u64;
+    for consumer in consumers {
+        let (received, wanted) = consumer.await?;
+        tally.received += received;
+        tally.wanted += wanted;
+    }
+    tally.elapsed += start.elapsed();
+    task.stop();
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    ffmpeg_bus::init()?;
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../scripts/test.mp4");
+    let path = path.to_string_lossy();
+
+    println!(
+        "{:<12} {:>14} {:>14} {:>12}",
+        "channel", "packets/sub", "wanted/sub", "time/pass"
+    );
+    for (label, per_stream) in [("all streams", false), ("per stream", true)] {
+        let mut tally = Tally::default();
+        for _ in 0..PASSES {
+            pass(&path, per_stream, &mut tally).await?;
+        }
+        let per_sub = |n: u64| n as f64 / tally.subscribers as f64;
+        println!(
+            "{:<12} {:>14.1} {:>14.1} {:>12.2?}",
+            label,
+            per_sub(tally.received),
+            per_sub(tally.wanted),
+            tally.elapsed / PASSES as u32
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! Audio timeline continuity. IP camera audio loses the odd RTP packet; the
+//! holes are invisible in the timestamps alone, and an MP4 muxer (or the
+//! encoder's resampler, which counts samples) packs what is left back to
+//! back, so a long recording drifts further and further ahead of its video.
+//!
+//! A [`GapFiller`] follows one audio stream and knows the timestamp the next
+//! frame or packet should have. A hole longer than
+//! [`GapFillConfig::threshold`] is closed: with silence frames on the encode
+//! path ([`GapFiller::push_frame`]), by stretching the duration of the packet
+//! before it on the copy path ([`GapFiller::push_packet`]). A hole longer
+//! than [`GapFillConfig::max_fill`] is an outage rather than packet loss: it
+//! is reported ([`Gap::Left`]) and left in the timeline.
+
+use std::time::Duration;
+
+use ffmpeg_next::Rational;
+use ffmpeg_next::frame::Audio;
+
+use crate::bus::BusEvent;
+use crate::frame::RawAudioFrame;
+use crate::packet::RawPacket;
+
+/// Bounds of the holes a [`GapFiller`] closes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GapFillConfig {
+    /// Holes up to this long are timestamp jitter and pass unchanged.
+    pub threshold: Duration,
+    /// Holes longer than this are left in the timeline.
+    pub max_fill: Duration,
+}
+
+impl Default for GapFillConfig {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_millis(40),
+            max_fill: Duration::from_secs(2),
+        }
+    }
+}
+
+/// A hole a [`GapFiller`] found; `at` is where it starts, in the stream's
+/// time base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gap {
+    /// Closed with silence or a stretched packet.
+    Filled { at: i64, duration: Duration },
+    /// Longer than [`GapFillConfig::max_fill`]: left as it is.
+    Left { at: i64, duration: Duration },
+}
+
+/// Tracks the expected next timestamp of one audio stream, see the module
+/// docs. Feed it either frames or packets, not both.
+pub struct GapFiller {
+    config: GapFillConfig,
+    time_base: Rational,
+    /// Where the next frame or packet should start.
+    next: Option<i64>,
+    /// Copy path: the packet whose duration the next one may stretch.
+    held: Option<RawPacket>,
+    /// Copy path: the length of one packet, from their durations or, when
+    /// they have none, their spacing.
+    step: i64,
+    filled: Duration,
+}
+
+impl GapFiller {
+    /// A filler for a stream whose timestamps are in `time_base`.
+    pub fn new(time_base: Rational, config: GapFillConfig) -> Self {
+        Self {
+            config,
+            time_base,
+            next: None,
+            held: None,
+            step: 0,
+            filled: Duration::ZERO,
+        }
+    }
+
+    /// Total length of the holes closed so far.
+    pub fn filled(&self) -> Duration {
+        self.filled
+    }
+
+    /// Encode path: `frame`, preceded by the silence that closes the hole
+    /// before it, if any. Frames without a timestamp or rate pass unchanged.
+    pub fn push_frame(
+        &mut self,
+        frame: RawAudioFrame,
+    ) -> anyhow::Result<(Vec<RawAudioFrame>, Option<Gap>)> {
+        let audio = frame.as_audio();
+        let (Some(pts), rate) = (audio.pts(), audio.rate()) else {
+            return Ok((vec![frame], None));
+        };
+        if rate == 0 {
+            return Ok((vec![frame], None));
+        }
+        let mut out = Vec::new();
+        let gap = self.check(pts);
+        if let (Some(Gap::Filled { at, duration }), Some(next)) = (gap, self.next) {
+            let samples = (duration.as_nanos() * rate as u128 / 1_000_000_000) as usize;
+            let chunk = audio.samples().max(1);
+            let mut done = 0;
+            while done < samples {
+                let n = chunk.min(samples - done);
+                let pts = next + self.sample_ticks(done, rate);
+                out.push(silence(audio, n, pts)?.into());
+                done += n;
+            }
+            tracing::debug!("audio gap at {at}: {duration:?} of silence ({samples} samples)");
+        }
+        self.next = Some(pts + self.sample_ticks(audio.samples(), rate));
+        out.push(frame);
+        Ok((out, gap))
+    }
+
+    /// Copy path: the packet before `packet`, its duration stretched over the
+    /// hole between them if there is one. Holds `packet` back until the next
+    /// one (or [`flush`](Self::flush)) shows whether it needs stretching.
+    pub fn push_packet(&mut self, packet: RawPacket) -> (Option<RawPacket>, Option<Gap>) {
+        let Some(pts) = packet.pts() else {
+            return (Some(packet), None);
+        };
+        let duration = packet.packet().duration();
+        if duration > 0 {
+            self.step = duration;
+        } else if let Some(prev) = self.held.as_ref().and_then(RawPacket::pts)
+            && pts > prev
+            && (self.step == 0 || pts - prev < self.step)
+        {
+            // No durations: the shortest spacing seen is one packet.
+            self.step = pts - prev;
+        }
+        let gap = self.check(pts);
+        let mut ready = self.held.take();
+        if let (Some(Gap::Filled { .. }), Some(prev)) = (gap, ready.as_mut())
+            && let Some(prev_pts) = prev.pts()
+        {
+            prev.set_duration(pts - prev_pts);
+        }
+        self.next = (self.step > 0).then(|| pts + self.step);
+        self.held = Some(packet);
+        (ready, gap)
+    }
+
+    /// Copy path: the packet still held back, at the end of the stream.
+    pub fn flush(&mut self) -> Option<RawPacket> {
+        self.held.take()
+    }
+
+    /// The [`BusEvent::AudioGap`] of a hole left in input stream
+    /// `stream_index`; filled ones are not reported.
+    pub(crate) fn event(&self, stream_index: usize, gap: Gap) -> Option<BusEvent> {
+        let Gap::Left { at, duration } = gap else {
+            return None;
+        };
+        let at_ms = at as i128 * self.time_base.numerator() as i128 * 1000
+            / self.time_base.denominator().max(1) as i128;
+        tracing::warn!("audio of stream {stream_index} stopped for {duration:?}, leaving the gap");
+        Some(BusEvent::AudioGap {
+            stream_index,
+            at_ms: at_ms as i64,
+            duration_ms: duration.as_millis() as u64,
+        })
+    }
+
+    /// The hole before a frame or packet starting at `pts`, if one is due
+    /// there; counts it when it is closed.
+    fn check(&mut self, pts: i64) -> Option<Gap> {
+        let next = self.next?;
+        let hole = pts - next;
+        let duration = self.duration(hole)?;
+        if duration <= self.config.threshold {
+            return None;
+        }
+        if duration > self.config.max_fill {
+            return Some(Gap::Left { at: next, duration });
+        }
+        self.filled += duration;
+        Some(Gap::Filled { at: next, duration })
+    }
+
+    /// `ticks` of the time base as a duration; `None` when negative.
+    fn duration(&self, ticks: i64) -> Option<Duration> {
+        let (num, den) = (self.time_base.numerator(), self.time_base.denominator());
+        if ticks < 0 || num <= 0 || den <= 0 {
+            return None;
+        }
+        let nanos = ticks as i128 * num as i128 * 1_000_000_000 / den as i128;
+        Some(Duration::from_nanos(nanos as u64))
+    }
+
+    /// `samples` at `rate` in ticks of the time base.
+    fn sample_ticks(&self, samples: usize, rate: u32) -> i64 {
+        let (num, den) = (self.time_base.numerator(), self.time_base.denominator());
+        if num <= 0 {
+            return 0;
+        }
+        (samples as i128 * den as i128 / (rate as i128 * num as i128)) as i64
+    }
+}
+
+/// `samples` of silence in the format, rate and channel layout of `template`.
+fn silence(template: &Audio, samples: usize, pts: i64) -> anyhow::Result<Audio> {
+    let mut frame = Audio::empty();
+    frame.set_format(template.format());
+    frame.set_rate(template.rate());
+    frame.set_samples(samples);
+    let format: ffmpeg_next::ffi::AVSampleFormat = template.format().into();
+    // SAFETY: both frames are valid; the buffer is allocated for the layout
+    // copied just before, and silenced within its bounds.
+    unsafe {
+        let (out, src) = (frame.as_mut_ptr(), template.as_ptr());
+        if ffmpeg_next::ffi::av_channel_layout_copy(&mut (*out).ch_layout, &(*src).ch_layout) < 0
+            || ffmpeg_next::ffi::av_frame_get_buffer(out, 0) < 0
+        {
+            anyhow::bail!("allocating {samples} samples of silence failed");
+        }
+        ffmpeg_next::ffi::av_samples_set_silence(
+            (*out).extended_data,
+            0,
+            samples as i32,
+            (*out).ch_layout.nb_channels,
+            format,
+        );
+    }
+    frame.set_pts(Some(pts));
+    Ok(frame)
+}
+
+#[cfg(test)]
+#[path = "audio_gap_test.rs"]
+mod audio_gap_test;
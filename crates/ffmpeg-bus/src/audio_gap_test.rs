@@ -0,0 +1,158 @@
+use ffmpeg_next::ChannelLayout;
+use ffmpeg_next::format::{Sample, sample::Type};
+
+use super::*;
+
+const RATE: u32 = 48_000;
+/// 20 ms at 48 kHz.
+const SAMPLES: usize = 960;
+
+fn tb() -> Rational {
+    Rational(1, RATE as i32)
+}
+
+fn frame(pts: i64) -> RawAudioFrame {
+    let mut audio = Audio::new(Sample::I16(Type::Packed), SAMPLES, ChannelLayout::STEREO);
+    audio.set_rate(RATE);
+    audio.set_pts(Some(pts));
+    audio.data_mut(0).fill(0x11);
+    audio.into()
+}
+
+fn packet(pts: i64) -> RawPacket {
+    let mut packet = ffmpeg_next::codec::packet::Packet::copy(&[0u8; 8]);
+    packet.set_pts(Some(pts));
+    packet.set_dts(Some(pts));
+    packet.set_duration(SAMPLES as i64);
+    RawPacket::from((packet, tb()))
+}
+
+/// Push frames at `starts`; returns every frame out and every gap found.
+fn fill_frames(filler: &mut GapFiller, starts: &[i64]) -> (Vec<RawAudioFrame>, Vec<Gap>) {
+    let mut frames = Vec::new();
+    let mut gaps = Vec::new();
+    for &pts in starts {
+        let (out, gap) = filler.push_frame(frame(pts)).unwrap();
+        frames.extend(out);
+        gaps.extend(gap);
+    }
+    (frames, gaps)
+}
+
+#[test]
+fn a_200ms_hole_is_filled_with_silence() {
+    crate::init().unwrap();
+    let mut filler = GapFiller::new(tb(), GapFillConfig::default());
+    let step = SAMPLES as i64;
+    // Five frames, 200 ms (9600 samples) lost, three more; plus 5 ms of
+    // jitter, under the threshold.
+    let hole = 9600;
+    let starts: Vec<i64> = (0..5)
+        .map(|i| i * step)
+        .chain((5..8).map(|i| i * step + hole))
+        .chain([8 * step + hole + 240])
+        .collect();
+    let (frames, gaps) = fill_frames(&mut filler, &starts);
+
+    assert_eq!(
+        gaps,
+        [Gap::Filled {
+            at: 5 * step,
+            duration: Duration::from_millis(200),
+        }]
+    );
+    assert_eq!(filler.filled(), Duration::from_millis(200));
+    assert_eq!(frames.len(), starts.len() + 10);
+    let silent: Vec<_> = frames[5..15].iter().map(|f| f.as_audio()).collect();
+    assert_eq!(
+        silent.iter().map(|a| a.samples()).sum::<usize>(),
+        hole as usize
+    );
+    for audio in &silent {
+        assert_eq!(audio.rate(), RATE);
+        assert_eq!(audio.channels(), 2);
+        assert_eq!(audio.format(), Sample::I16(Type::Packed));
+        assert!(audio.data(0)[..audio.samples() * 4].iter().all(|&b| b == 0));
+    }
+
+    // Downstream the timeline is continuous up to the jitter.
+    for pair in frames[..frames.len() - 1].windows(2) {
+        let (a, b) = (pair[0].as_audio(), pair[1].as_audio());
+        assert_eq!(b.pts().unwrap(), a.pts().unwrap() + a.samples() as i64);
+    }
+}
+
+#[test]
+fn an_outage_is_left_as_a_gap() {
+    crate::init().unwrap();
+    let mut filler = GapFiller::new(tb(), GapFillConfig::default());
+    let step = SAMPLES as i64;
+    let outage = 3 * RATE as i64;
+    let (frames, gaps) = fill_frames(&mut filler, &[0, step, 2 * step + outage]);
+
+    assert_eq!(
+        gaps,
+        [Gap::Left {
+            at: 2 * step,
+            duration: Duration::from_secs(3),
+        }]
+    );
+    // No silence: just the frames pushed, the gap still in their timestamps.
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[2].pts(), Some(2 * step + outage));
+    assert_eq!(filler.filled(), Duration::ZERO);
+}
+
+#[test]
+fn copied_packets_stretch_over_a_hole() {
+    let mut filler = GapFiller::new(tb(), GapFillConfig::default());
+    let step = SAMPLES as i64;
+    let hole = 9600;
+    let outage = 3 * RATE as i64;
+    let starts = [
+        0,
+        step,
+        2 * step + hole,
+        3 * step + hole,
+        4 * step + hole + outage,
+    ];
+
+    let mut out = Vec::new();
+    let mut gaps = Vec::new();
+    for pts in starts {
+        let (ready, gap) = filler.push_packet(packet(pts));
+        out.extend(ready);
+        gaps.extend(gap);
+    }
+    out.extend(filler.flush());
+
+    assert_eq!(
+        gaps,
+        [
+            Gap::Filled {
+                at: 2 * step,
+                duration: Duration::from_millis(200),
+            },
+            Gap::Left {
+                at: 4 * step + hole,
+                duration: Duration::from_secs(3),
+            },
+        ]
+    );
+    let timeline: Vec<_> = out
+        .iter()
+        .map(|p| (p.pts().unwrap(), p.packet().duration()))
+        .collect();
+    assert_eq!(
+        timeline,
+        [
+            (0, step),
+            // Runs up to the packet after the hole.
+            (step, step + hole),
+            (2 * step + hole, step),
+            // The outage is not stretched over.
+            (3 * step + hole, step),
+            (4 * step + hole + outage, step),
+        ]
+    );
+}
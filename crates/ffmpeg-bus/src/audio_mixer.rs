@@ -10,7 +10,9 @@
 //! stereo format and mixed by straight PCM summation (per-input gain, then a
 //! single saturating clamp). That gives: a variable number of inputs, silence
 //! for inputs that momentarily have no data, unity/`normalize=0` behaviour, and
-//! live per-input volume/mute — exactly a mixing console.
+//! live per-input volume/mute — exactly a mixing console. There are no slots to
+//! run out of: an added input is mixed from the next tick, and a removed (or
+//! ended) one stops contributing at once instead of lingering as silence.
 //!
 //! Threading: the mix runs on one dedicated blocking thread that owns all the
 //! non-`Send` ffmpeg objects (per-input resamplers). Only `Send` values cross
@@ -75,8 +77,10 @@ pub(crate) fn clamp_to_i16(acc: &[i32]) -> Vec<i16> {
 
 // ---- per-input resampler --------------------------------------------------
 
-/// Resamples an arbitrary decoded audio frame to interleaved s16 stereo at the
-/// mixer rate. The `swr` context is rebuilt only when the input format changes.
+/// Resamples an arbitrary decoded audio frame (8 kHz mono G.711 and 16 kHz AAC
+/// alike) to interleaved s16 stereo at the mixer rate. The `swr` context is
+/// detected from and rebuilt only when the input format changes, so several
+/// differently formatted inputs mix side by side.
 struct SlotResampler {
     rate: u32,
     swr: Option<(ffmpeg_next::software::resampling::Context, Sample, u32, u16)>,
@@ -88,6 +92,9 @@ impl SlotResampler {
     }
 
     fn convert(&mut self, input: &Audio) -> anyhow::Result<Vec<i16>> {
+        anyhow::ensure!(input.rate() > 0, "audio frame has zero sample rate");
+        let labelled = with_known_layout(input);
+        let input = labelled.as_ref().unwrap_or(input);
         let in_fmt = input.format();
         let in_rate = input.rate();
         let in_ch = input.channels();
@@ -125,6 +132,27 @@ impl SlotResampler {
     }
 }
 
+/// `frame` given the default layout for its channel count when it carries
+/// only the count (raw PCM decoders leave it unspecified), which swr refuses.
+/// `None` when the layout is known already.
+fn with_known_layout(frame: &Audio) -> Option<Audio> {
+    // SAFETY: `frame` is valid; the clone references the same buffers and
+    // only its own layout is rewritten.
+    unsafe {
+        let layout = &(*frame.as_ptr()).ch_layout;
+        if layout.order != ffmpeg_next::ffi::AVChannelOrder::AV_CHANNEL_ORDER_UNSPEC {
+            return None;
+        }
+        let channels = layout.nb_channels.max(1);
+        let clone = ffmpeg_next::ffi::av_frame_clone(frame.as_ptr());
+        if clone.is_null() {
+            return None;
+        }
+        ffmpeg_next::ffi::av_channel_layout_default(&mut (*clone).ch_layout, channels);
+        Some(Audio::wrap(clone))
+    }
+}
+
 // ---- public task ----------------------------------------------------------
 
 /// Shared, live-tunable control for one input. The same `Arc`s are held by the
@@ -153,6 +181,8 @@ pub struct DynamicMixerTask {
     cancel: CancellationToken,
     out_tx: RawFrameSender,
     controls: Arc<Mutex<HashMap<String, InputControl>>>,
+    /// Ids the mix thread is currently mixing, sorted.
+    active: Arc<Mutex<Vec<String>>>,
     cmd_tx: UnboundedSender<MixerCmd>,
     cmd_rx: Mutex<Option<UnboundedReceiver<MixerCmd>>>,
 }
@@ -168,6 +198,7 @@ impl DynamicMixerTask {
             cancel: CancellationToken::new(),
             out_tx,
             controls: Arc::new(Mutex::new(HashMap::new())),
+            active: Arc::new(Mutex::new(Vec::new())),
             cmd_tx,
             cmd_rx: Mutex::new(Some(cmd_rx)),
         }
@@ -178,7 +209,7 @@ impl DynamicMixerTask {
         self.out_tx.subscribe()
     }
 
-    /// Start the mix loop on a dedicated blocking thread. Call once.
+    /// Start the mix loop on a blocking worker (see [`crate::worker`]). Call once.
     pub fn start(&self) {
         let rx = self
             .cmd_rx
@@ -189,7 +220,8 @@ impl DynamicMixerTask {
         let rate = self.sample_rate;
         let cancel = self.cancel.clone();
         let out = self.out_tx.clone();
-        tokio::task::spawn_blocking(move || mix_loop(rate, cancel, rx, out));
+        let active = self.active.clone();
+        crate::worker::spawn("bus-mixer", move || mix_loop(rate, cancel, rx, out, active));
     }
 
     /// Add (or replace) an input at the given volume (percent).
@@ -256,6 +288,13 @@ impl DynamicMixerTask {
             .collect()
     }
 
+    /// Ids of the inputs the running loop is mixing, sorted. Unlike
+    /// [`inputs`](Self::inputs) this leaves out inputs whose stream has ended
+    /// and includes an added one only once the loop has picked it up.
+    pub fn active_inputs(&self) -> Vec<String> {
+        self.active.lock().unwrap().clone()
+    }
+
     /// Stop the mix loop.
     pub fn cancel(&self) {
         self.cancel.cancel();
@@ -279,6 +318,7 @@ fn mix_loop(
     cancel: CancellationToken,
     mut cmd_rx: UnboundedReceiver<MixerCmd>,
     out: RawFrameSender,
+    active: Arc<Mutex<Vec<String>>>,
 ) {
     let max_buffer = rate as usize * CHANNELS; // ~1 s per input
     let tick = Duration::from_micros(FRAME_SAMPLES as u64 * 1_000_000 / rate.max(1) as u64);
@@ -321,7 +361,7 @@ fn mix_loop(
                     Ok(RawFrameCmd::Data(RawFrame::Audio(frame))) => {
                         match active.resampler.convert(frame.as_audio()) {
                             Ok(samples) => active.buffer.extend(samples),
-                            Err(e) => log::warn!("mixer resample '{id}': {e:#}"),
+                            Err(e) => tracing::warn!("mixer resample '{id}': {e:#}"),
                         }
                         if active.buffer.len() > max_buffer {
                             let overflow = active.buffer.len() - max_buffer;
@@ -335,7 +375,7 @@ fn mix_loop(
                     }
                     Err(TryRecvError::Empty) => break,
                     Err(TryRecvError::Lagged(n)) => {
-                        log::warn!("mixer input '{id}' lagged, dropped {n}");
+                        tracing::warn!("mixer input '{id}' lagged, dropped {n}");
                     }
                     Err(TryRecvError::Closed) => {
                         dead.push(id.clone());
@@ -347,6 +387,7 @@ fn mix_loop(
         for id in dead {
             inputs.remove(&id);
         }
+        publish_active(&inputs, &active);
 
         // 3) Mix one tick: gain-scaled sum, then a single saturating clamp.
         let mut acc = vec![0i32; FRAME_LEN];
@@ -376,7 +417,15 @@ fn mix_loop(
             next = now;
         }
     }
-    log::info!("audio mixer loop stopped");
+    active.lock().unwrap().clear();
+    tracing::info!("audio mixer loop stopped");
+}
+
+/// Publish the ids in `inputs` to `active`, sorted.
+fn publish_active(inputs: &HashMap<String, Active>, active: &Mutex<Vec<String>>) {
+    let mut ids: Vec<String> = inputs.keys().cloned().collect();
+    ids.sort();
+    *active.lock().unwrap() = ids;
 }
 
 /// Pop one tick of interleaved samples, silence-padded to exactly `FRAME_LEN`.
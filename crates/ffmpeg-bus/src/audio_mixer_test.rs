@@ -46,6 +46,59 @@ fn take_frame_pads_short_buffer_with_silence() {
     assert!(buf.is_empty());
 }
 
+// ---- per-input resampling ------------------------------------------------
+
+/// `samples` per channel of packed s16 at a constant `level`.
+fn pcm(layout: ChannelLayout, rate: u32, samples: usize, level: i16) -> Audio {
+    let mut frame = Audio::new(OUT_FMT, samples, layout);
+    frame.set_rate(rate);
+    let count = samples * frame.channels() as usize;
+    let data = frame.data_mut(0);
+    for i in 0..count {
+        data[i * 2..i * 2 + 2].copy_from_slice(&level.to_ne_bytes());
+    }
+    frame
+}
+
+#[test]
+fn narrowband_mono_comes_out_as_mixer_rate_stereo() {
+    crate::init().unwrap();
+    let mut resampler = SlotResampler::new(48_000);
+    let mut out = Vec::new();
+    // 20 ms G.711-style frames, half a second of them.
+    for _ in 0..25 {
+        let frame = pcm(ChannelLayout::MONO, 8_000, 160, 1000);
+        out.extend(resampler.convert(&frame).unwrap());
+    }
+    assert_eq!(out.len() % CHANNELS, 0);
+    // Six output samples per input sample, less what swr still holds back.
+    let pairs = out.len() / CHANNELS;
+    assert!((23_000..=24_000).contains(&pairs), "{pairs} samples");
+    // Past the filter's start-up, both channels carry the level.
+    for &sample in &out[out.len() / 2..] {
+        assert!((sample - 1000).abs() <= 10, "{sample}");
+    }
+}
+
+#[test]
+fn a_frame_with_only_a_channel_count_is_resampled() {
+    crate::init().unwrap();
+    let mut frame = pcm(ChannelLayout::MONO, 16_000, 320, 500);
+    // What raw PCM decoders hand out: a count, no layout.
+    unsafe {
+        let layout = &mut (*frame.as_mut_ptr()).ch_layout;
+        ffmpeg_next::ffi::av_channel_layout_uninit(layout);
+        layout.nb_channels = 1;
+    }
+    let mut resampler = SlotResampler::new(48_000);
+    let out = resampler.convert(&frame).unwrap();
+    assert!(!out.is_empty());
+    assert_eq!(out.len() % CHANNELS, 0);
+
+    let rateless = pcm(ChannelLayout::STEREO, 0, 16, 0);
+    assert!(resampler.convert(&rateless).is_err());
+}
+
 // ---- task control surface (no running loop required) ----------------------
 
 #[test]
@@ -84,3 +137,120 @@ fn control_of_missing_input_errors() {
     assert!(task.set_volume("nope", 50).is_err());
     assert!(task.set_muted("nope", true).is_err());
 }
+
+// ---- running loop ---------------------------------------------------------
+
+/// `count` ticks of a constant `level` on both channels.
+fn send_level(tx: &RawFrameSender, level: i16, count: usize) {
+    let samples = vec![level; FRAME_LEN];
+    for i in 0..count {
+        let frame = build_frame(&samples, 48_000, (i * FRAME_SAMPLES) as i64);
+        tx.send(RawFrameCmd::Data(RawFrame::Audio(frame.into())))
+            .unwrap();
+    }
+}
+
+/// Wait up to 5 s for an active set equal to `ids`.
+async fn wait_active(task: &DynamicMixerTask, ids: &[&str]) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while task.active_inputs() != ids {
+        assert!(
+            Instant::now() < deadline,
+            "active: {:?}",
+            task.active_inputs()
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn inputs_come_and_go_while_the_mix_keeps_running() {
+    crate::init().unwrap();
+    let task = DynamicMixerTask::new(48_000);
+    let mut out = task.subscribe();
+    task.start();
+
+    // There is no input limit to outgrow: a third input joins the mix just
+    // like the first two.
+    let senders: Vec<_> = ["a", "b", "c"]
+        .into_iter()
+        .map(|id| {
+            let (tx, rx) = tokio::sync::broadcast::channel::<RawFrameCmd>(64);
+            task.add_input(id, rx, DEFAULT_VOLUME);
+            tx
+        })
+        .collect();
+    wait_active(&task, &["a", "b", "c"]).await;
+    for tx in &senders {
+        send_level(tx, 100, 40);
+    }
+
+    let mixed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let Ok(RawFrameCmd::Data(RawFrame::Audio(frame))) = out.recv().await else {
+                continue;
+            };
+            let audio = frame.as_audio();
+            let first = i16::from_ne_bytes([audio.data(0)[0], audio.data(0)[1]]);
+            if first == 300 {
+                break audio.samples();
+            }
+        }
+    })
+    .await
+    .expect("no frame mixing all three inputs");
+    assert_eq!(mixed, FRAME_SAMPLES);
+
+    task.remove_input("b").unwrap();
+    wait_active(&task, &["a", "c"]).await;
+
+    // An ended input drops out of the mix but stays listed until removed.
+    senders[2].send(RawFrameCmd::EOF).unwrap();
+    wait_active(&task, &["a"]).await;
+    assert_eq!(task.inputs().len(), 2);
+
+    task.cancel();
+    wait_active(&task, &[]).await;
+}
+
+#[tokio::test]
+async fn inputs_of_different_formats_mix_into_one_stereo_stream() {
+    crate::init().unwrap();
+    let task = DynamicMixerTask::new(48_000);
+    let mut out = task.subscribe();
+    task.start();
+
+    let (phone, rx) = tokio::sync::broadcast::channel::<RawFrameCmd>(64);
+    task.add_input("phone", rx, DEFAULT_VOLUME);
+    let (music, rx) = tokio::sync::broadcast::channel::<RawFrameCmd>(64);
+    task.add_input("music", rx, DEFAULT_VOLUME);
+    wait_active(&task, &["music", "phone"]).await;
+    for _ in 0..25 {
+        let frame = pcm(ChannelLayout::MONO, 8_000, 160, 100);
+        phone
+            .send(RawFrameCmd::Data(RawFrame::Audio(frame.into())))
+            .unwrap();
+    }
+    send_level(&music, 200, 12);
+
+    let frame = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let Ok(RawFrameCmd::Data(RawFrame::Audio(frame))) = out.recv().await else {
+                continue;
+            };
+            let audio = frame.as_audio();
+            let at = FRAME_LEN; // the middle of the frame, in bytes
+            let middle = i16::from_ne_bytes([audio.data(0)[at], audio.data(0)[at + 1]]);
+            if (middle - 300).abs() <= 5 {
+                break frame;
+            }
+        }
+    })
+    .await
+    .expect("no frame mixing both inputs");
+    let audio = frame.as_audio();
+    assert_eq!(audio.rate(), 48_000);
+    assert_eq!(audio.channels() as usize, CHANNELS);
+    assert_eq!(audio.samples(), FRAME_SAMPLES);
+    task.cancel();
+}
@@ -0,0 +1,207 @@
+//! Bridge FFmpeg's `av_log` output into the `log` crate, so it is filtered and
+//! formatted like the rest of the application instead of going to stderr.
+//!
+//! Lines are logged with target `ffmpeg::<item>` (the logging context's item
+//! name, e.g. `ffmpeg::rtsp`, `ffmpeg::h264`), or plain `ffmpeg` when FFmpeg
+//! logs without a context. Installed by [`crate::init_with`].
+
+use std::ffi::{CStr, c_char, c_int, c_void};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ffmpeg_next::ffi;
+use ffmpeg_next::util::log::Level;
+
+/// Longest formatted line forwarded; longer ones are truncated by FFmpeg.
+const LINE_CAP: usize = 1024;
+
+/// Longest item name used in a target.
+const ITEM_CAP: usize = 64;
+
+/// How FFmpeg log output is forwarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogConfig {
+    /// Most verbose level forwarded; FFmpeg does not even format lines above it.
+    pub max_level: log::LevelFilter,
+    /// Identical consecutive lines within this window are collapsed into one
+    /// "repeated N times" line. `Duration::ZERO` forwards every line.
+    pub rate_limit: Duration,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_level: log::LevelFilter::Info,
+            rate_limit: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Last forwarded line and how many identical ones have been swallowed since.
+struct Repeat {
+    target: String,
+    level: log::Level,
+    message: String,
+    since: Instant,
+    suppressed: u64,
+}
+
+struct State {
+    config: LogConfig,
+    last: Option<Repeat>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    config: LogConfig {
+        max_level: log::LevelFilter::Info,
+        rate_limit: Duration::ZERO,
+    },
+    last: None,
+});
+
+/// Map an `AV_LOG_*` level onto a `log` level (`None` for `AV_LOG_QUIET`).
+pub fn map_level(level: c_int) -> Option<log::Level> {
+    match level {
+        l if l <= c_int::from(Level::Quiet) => None,
+        l if l <= c_int::from(Level::Error) => Some(log::Level::Error),
+        l if l <= c_int::from(Level::Warning) => Some(log::Level::Warn),
+        l if l <= c_int::from(Level::Info) => Some(log::Level::Info),
+        l if l <= c_int::from(Level::Debug) => Some(log::Level::Debug),
+        _ => Some(log::Level::Trace),
+    }
+}
+
+/// The FFmpeg level matching `filter`; FFmpeg skips formatting lines above it.
+fn av_level(filter: log::LevelFilter) -> Level {
+    match filter {
+        log::LevelFilter::Off => Level::Quiet,
+        log::LevelFilter::Error => Level::Error,
+        log::LevelFilter::Warn => Level::Warning,
+        log::LevelFilter::Info => Level::Info,
+        log::LevelFilter::Debug => Level::Debug,
+        log::LevelFilter::Trace => Level::Trace,
+    }
+}
+
+/// Install the callback. Only called once, from [`crate::init_with`].
+pub(crate) fn install(config: LogConfig) {
+    lock().config = config;
+    ffmpeg_next::util::log::set_level(av_level(config.max_level));
+    unsafe { ffi::av_log_set_callback(Some(callback)) };
+}
+
+fn lock() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+unsafe extern "C" fn callback(
+    avcl: *mut c_void,
+    raw_level: c_int,
+    fmt: *const c_char,
+    vl: ffi::va_list,
+) {
+    // FFmpeg hands every line to the callback; the level check is ours.
+    if raw_level > unsafe { ffi::av_log_get_level() } {
+        return;
+    }
+    let Some(level) = map_level(raw_level) else {
+        return;
+    };
+    // Format into a fixed buffer: FFmpeg truncates long lines for us.
+    let mut line = [0 as c_char; LINE_CAP];
+    let mut print_prefix: c_int = 0;
+    unsafe {
+        ffi::av_log_format_line2(
+            avcl,
+            raw_level,
+            fmt,
+            vl,
+            line.as_mut_ptr(),
+            LINE_CAP as c_int,
+            &mut print_prefix,
+        );
+    }
+    // Never unwind into C.
+    let _ = std::panic::catch_unwind(|| {
+        let item = unsafe { item_name(avcl) };
+        let message = unsafe { CStr::from_ptr(line.as_ptr()) }.to_string_lossy();
+        forward(item, level, message.trim_end());
+    });
+}
+
+/// The logging context's item name (e.g. the demuxer name), if it has one.
+unsafe fn item_name(avcl: *mut c_void) -> Option<String> {
+    if avcl.is_null() {
+        return None;
+    }
+    let class = unsafe { *(avcl as *const *const ffi::AVClass) };
+    if class.is_null() {
+        return None;
+    }
+    let name = unsafe {
+        match (*class).item_name {
+            Some(item_name) => item_name(avcl),
+            None => (*class).class_name,
+        }
+    };
+    if name.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    // Contexts without a format/codec yet report "NULL".
+    (!name.is_empty() && name != "NULL").then(|| name.chars().take(ITEM_CAP).collect())
+}
+
+/// Emit one formatted line, collapsing repeats per the configured rate limit.
+pub(crate) fn forward(item: Option<String>, level: log::Level, message: &str) {
+    if message.is_empty() {
+        return;
+    }
+    let target = match item {
+        Some(item) => format!("ffmpeg::{item}"),
+        None => "ffmpeg".to_string(),
+    };
+    if !log::log_enabled!(target: &target, level) {
+        return;
+    }
+
+    let mut state = lock();
+    let rate_limit = state.config.rate_limit;
+    if let Some(last) = state.last.as_mut()
+        && last.level == level
+        && last.target == target
+        && last.message == message
+        && last.since.elapsed() < rate_limit
+    {
+        last.suppressed += 1;
+        return;
+    }
+    let previous = state.last.take();
+    if rate_limit > Duration::ZERO {
+        state.last = Some(Repeat {
+            target: target.clone(),
+            level,
+            message: message.to_string(),
+            since: Instant::now(),
+            suppressed: 0,
+        });
+    }
+    drop(state);
+
+    if let Some(prev) = previous
+        && prev.suppressed > 0
+    {
+        log::log!(
+            target: &prev.target,
+            prev.level,
+            "last message repeated {} times: {}",
+            prev.suppressed,
+            prev.message
+        );
+    }
+    log::log!(target: &target, level, "{message}");
+}
+
+#[cfg(test)]
+#[path = "av_log_test.rs"]
+mod av_log_test;
@@ -0,0 +1,176 @@
+use std::ffi::CString;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+
+/// Captures every record so tests can assert what reached the `log` crate.
+struct TestLogger {
+    records: Mutex<Vec<(String, log::Level, String)>>,
+}
+
+impl log::Log for TestLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.records.lock().unwrap().push((
+            record.target().to_string(),
+            record.level(),
+            record.args().to_string(),
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: TestLogger = TestLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+/// Install the capturing logger and the bridge; serializes the tests here,
+/// which share the bridge's repeat state.
+fn setup() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    INSTALLED.get_or_init(|| {
+        log::set_logger(&LOGGER).expect("no other logger in ffmpeg-bus tests");
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+    crate::init().unwrap();
+    guard
+}
+
+fn captured(needle: &str) -> Vec<(String, log::Level, String)> {
+    LOGGER
+        .records
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, _, msg)| msg.contains(needle))
+        .cloned()
+        .collect()
+}
+
+fn av_log(level: Level, message: &str) {
+    let message = CString::new(message).unwrap();
+    unsafe {
+        ffi::av_log(
+            std::ptr::null_mut(),
+            c_int::from(level),
+            c"%s\n".as_ptr(),
+            message.as_ptr(),
+        );
+    }
+}
+
+#[test]
+fn levels_map_onto_log_levels() {
+    assert_eq!(map_level(c_int::from(Level::Quiet)), None);
+    assert_eq!(
+        map_level(c_int::from(Level::Panic)),
+        Some(log::Level::Error)
+    );
+    assert_eq!(
+        map_level(c_int::from(Level::Error)),
+        Some(log::Level::Error)
+    );
+    assert_eq!(
+        map_level(c_int::from(Level::Warning)),
+        Some(log::Level::Warn)
+    );
+    assert_eq!(map_level(c_int::from(Level::Info)), Some(log::Level::Info));
+    assert_eq!(
+        map_level(c_int::from(Level::Verbose)),
+        Some(log::Level::Debug)
+    );
+    assert_eq!(
+        map_level(c_int::from(Level::Debug)),
+        Some(log::Level::Debug)
+    );
+    assert_eq!(
+        map_level(c_int::from(Level::Trace)),
+        Some(log::Level::Trace)
+    );
+}
+
+#[test]
+fn init_is_idempotent_across_threads() {
+    let threads: Vec<_> = (0..8).map(|_| std::thread::spawn(crate::init)).collect();
+    for t in threads {
+        t.join().unwrap().unwrap();
+    }
+    crate::init_with(LogConfig::default()).unwrap();
+}
+
+#[test]
+fn ffmpeg_lines_reach_the_log_crate() {
+    let _guard = setup();
+    av_log(Level::Warning, "av_log bridge test line");
+    let lines = captured("av_log bridge test line");
+    assert_eq!(
+        lines,
+        vec![(
+            "ffmpeg".to_string(),
+            log::Level::Warn,
+            "av_log bridge test line".to_string()
+        )]
+    );
+
+    // Above the default max level (info): FFmpeg never hands it over.
+    av_log(Level::Debug, "av_log bridge debug line");
+    assert!(captured("av_log bridge debug line").is_empty());
+}
+
+#[tokio::test]
+async fn bogus_option_is_reported_through_the_bridge() {
+    // Rendered before taking the lock, which is not held across an await.
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let _guard = setup();
+    let mut options = ffmpeg_next::Dictionary::new();
+    options.set("probesize", "not-a-number");
+    assert!(ffmpeg_next::format::input_with_dictionary(&path, options).is_err());
+
+    let lines = captured("not-a-number");
+    assert!(!lines.is_empty(), "no FFmpeg line about the bad option");
+    let (target, level, _) = &lines[0];
+    assert!(target.starts_with("ffmpeg"), "{target}");
+    assert!(*level <= log::Level::Warn, "{level}");
+}
+
+#[test]
+fn repeated_lines_are_collapsed() {
+    let _guard = setup();
+    for _ in 0..5 {
+        av_log(Level::Warning, "av_log bridge repeated line");
+    }
+    av_log(Level::Warning, "av_log bridge different line");
+
+    // Other tests may log in between (resetting the repeat), so check that
+    // the five lines are all accounted for rather than the exact split.
+    let repeated = captured("av_log bridge repeated line");
+    assert_eq!(repeated[0].2, "av_log bridge repeated line");
+    let total: u64 = repeated
+        .iter()
+        .map(
+            |(_, _, msg)| match msg.strip_prefix("last message repeated ") {
+                Some(rest) => rest.split(' ').next().unwrap().parse::<u64>().unwrap(),
+                None => 1,
+            },
+        )
+        .sum();
+    assert_eq!(total, 5, "{repeated:?}");
+    assert_eq!(captured("av_log bridge different line").len(), 1);
+}
+
+#[test]
+fn long_lines_are_truncated() {
+    let _guard = setup();
+    let long = format!("av_log bridge long line {}", "x".repeat(LINE_CAP * 4));
+    av_log(Level::Warning, &long);
+    let lines = captured("av_log bridge long line");
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].2.len() < LINE_CAP, "{}", lines[0].2.len());
+}
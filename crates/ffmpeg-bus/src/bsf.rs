@@ -1,12 +1,16 @@
 use anyhow::Result;
 use bytes::{Bytes, BytesMut};
-use ffmpeg_next::{Rational, codec::Parameters};
+use ffmpeg_next::{
+    Rational,
+    codec::{Id, Parameters},
+};
 
 use crate::packet::RawPacket;
+use crate::remux::{NalFraming, annexb_units};
 
 /// Reads extradata from codec parameters via the raw AVCodecParameters pointer.
 /// Returns None if extradata is null or empty.
-fn get_extradata(codec_params: &Parameters) -> Option<&[u8]> {
+pub(crate) fn get_extradata(codec_params: &Parameters) -> Option<&[u8]> {
     unsafe {
         // AVCodecParameters has extradata (uint8_t*) and extradata_size (int)
         let p = codec_params.as_ptr() as *const ffmpeg_next::ffi::AVCodecParameters;
@@ -141,6 +145,110 @@ pub fn convert_avcc_to_annexb(avcc: &[u8]) -> Bytes {
     out.freeze()
 }
 
+/// Rewrites the AVCC/HVCC (length-prefixed) packets of one H.264 or H.265
+/// stream as Annex B, like FFmpeg's `h264_mp4toannexb`: the parameter sets of
+/// the extradata go in front of every keyframe that does not carry its own,
+/// so a consumer can start decoding at any keyframe. Demuxed bus outputs get
+/// one with [`OutputConfig::with_annexb`](crate::bus::OutputConfig::with_annexb).
+/// It holds no packets back, so there is nothing to flush at the end.
+pub struct AnnexBFilter {
+    hevc: bool,
+    length_size: usize,
+    /// The parameter sets, each behind a start code.
+    parameter_sets: Bytes,
+}
+
+impl AnnexBFilter {
+    /// The filter for a stream of `parameters`; `None` when its packets are
+    /// Annex B already or not H.264/H.265 (see [`needs_annexb_conversion`]).
+    pub fn new(parameters: &Parameters) -> Option<Self> {
+        let id = parameters.id();
+        if !matches!(id, Id::H264 | Id::HEVC) || !needs_annexb_conversion(parameters) {
+            return None;
+        }
+        let framing = match NalFraming::of(id, get_extradata(parameters)?) {
+            Ok(framing) => framing,
+            Err(e) => {
+                tracing::warn!("annexb: unreadable {:?} extradata: {e:#}", id);
+                return None;
+            }
+        };
+        let mut parameter_sets = BytesMut::new();
+        for unit in &framing.parameter_sets {
+            parameter_sets.extend_from_slice(START_CODE);
+            parameter_sets.extend_from_slice(unit);
+        }
+        Some(Self {
+            hevc: id == Id::HEVC,
+            length_size: framing.length_size()?,
+            parameter_sets: parameter_sets.freeze(),
+        })
+    }
+
+    /// One packet of the stream as Annex B. Units after one whose length runs
+    /// past the packet are dropped, as by [`convert_avcc_to_annexb`].
+    pub fn filter(&self, data: &[u8], is_key: bool) -> Bytes {
+        let mut units = Vec::new();
+        let mut i = 0;
+        while i + self.length_size <= data.len() {
+            let len = data[i..i + self.length_size]
+                .iter()
+                .fold(0usize, |len, &byte| len << 8 | usize::from(byte));
+            i += self.length_size;
+            if len == 0 || i + len > data.len() {
+                break;
+            }
+            units.push(&data[i..i + len]);
+            i += len;
+        }
+        let in_band = units.iter().any(|unit| self.is_parameter_set(unit));
+        let mut out = BytesMut::with_capacity(data.len() + self.parameter_sets.len());
+        if is_key && !in_band {
+            out.extend_from_slice(&self.parameter_sets);
+        }
+        for unit in units {
+            out.extend_from_slice(START_CODE);
+            out.extend_from_slice(unit);
+        }
+        out.freeze()
+    }
+
+    fn is_parameter_set(&self, unit: &[u8]) -> bool {
+        is_parameter_set(self.hevc, unit)
+    }
+}
+
+/// Whether the NAL `unit` of an H.264 (or with `hevc` H.265) stream is a
+/// VPS, SPS or PPS.
+fn is_parameter_set(hevc: bool, unit: &[u8]) -> bool {
+    let Some(&header) = unit.first() else {
+        return false;
+    };
+    if hevc {
+        (32..=34).contains(&((header >> 1) & 0x3f))
+    } else {
+        matches!(header & 0x1f, 7 | 8)
+    }
+}
+
+/// The parameter sets an Annex B H.264/H.265 packet carries in band, each
+/// behind a start code: extradata for a stream whose encoder was opened
+/// without global headers. `None` for other codecs, length-prefixed packets
+/// and packets without any.
+pub fn in_band_parameter_sets(id: Id, data: &[u8]) -> Option<Bytes> {
+    if !matches!(id, Id::H264 | Id::HEVC) || !is_annexb_packet(data) {
+        return None;
+    }
+    let mut out = BytesMut::new();
+    for unit in annexb_units(data) {
+        if is_parameter_set(id == Id::HEVC, unit) {
+            out.extend_from_slice(START_CODE);
+            out.extend_from_slice(unit);
+        }
+    }
+    (!out.is_empty()).then(|| out.freeze())
+}
+
 /// A packet in Annex B format.
 #[derive(Debug, Clone)]
 pub struct FilteredPacket {
@@ -39,3 +39,21 @@ fn test_convert_avcc_to_annexb_invalid_length_truncated() {
     assert!(out.is_empty());
 }
 
+#[test]
+fn test_in_band_parameter_sets_of_an_annexb_keyframe() {
+    // SPS, PPS, then an IDR slice.
+    let key = [
+        0, 0, 0, 1, 0x67, 0x64, 0x00, 0x1f, 0, 0, 0, 1, 0x68, 0xee, 0x3c, 0, 0, 1, 0x65, 0x88,
+    ];
+    let sets = in_band_parameter_sets(Id::H264, &key).unwrap();
+    assert_eq!(
+        &sets[..],
+        &[
+            0, 0, 0, 1, 0x67, 0x64, 0x00, 0x1f, 0, 0, 0, 1, 0x68, 0xee, 0x3c
+        ][..]
+    );
+    // A slice alone, a length-prefixed packet, another codec.
+    assert!(in_band_parameter_sets(Id::H264, &[0, 0, 0, 1, 0x65, 0x88]).is_none());
+    assert!(in_band_parameter_sets(Id::H264, &[0, 0, 0, 2, 0x67, 0x64]).is_none());
+    assert!(in_band_parameter_sets(Id::VP9, &key).is_none());
+}
@@ -1,42 +1,77 @@
 use std::{
     backtrace::Backtrace,
+    cell::Cell,
     collections::{HashMap, HashSet},
     hash::Hasher,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use futures::{Stream, StreamExt};
-use log::error;
-use tokio_stream::wrappers::BroadcastStream;
+use serde::{Deserialize, Serialize};
+use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
 use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, error};
 
-use ffmpeg_next::Dictionary;
+use ffmpeg_next::{Dictionary, Rational, Rescale};
 
 use crate::{
-    decoder::{Decoder, DecoderTask},
-    encoder::{AudioSettings, Encoder, EncoderTask, Settings, pixel_format_for_libx264},
-    frame::{RawFrameCmd, VideoFrame, packet_to_raw_video_frame},
-    input::{AvInput, AvInputTask},
-    output::{AvOutput, AvOutputStream},
-    packet::{RawPacket, RawPacketCmd, RawPacketReceiver},
+    audio_gap::{GapFillConfig, GapFiller},
+    container::{SupportLevel, UnsupportedCodec},
+    decoder::{Decoder, DecoderSettings, DecoderTask},
+    encoder::{
+        AudioSettings, Encoder, EncoderRecovery, EncoderTask, InvalidEncodeConfig, Settings,
+        ValidationIssue, pixel_format_for_libx264,
+    },
+    frame::{AudioFrame, RawFrame, RawFrameCmd, Rect, VideoFrame, packet_to_raw_video_frame},
+    frame_hub::FrameQueueConfig,
+    hook::{self, PacketHook},
+    input::{AvInput, AvInputTask, InputFactory, PlaybackState},
+    output::{AvOutput, AvOutputStream, STREAMING_FLUSH_EVERY},
+    packet::{GopBuffer, GopLimits, RawPacket, RawPacketCmd, RawPacketReceiver},
+    push::{PushAudio, PushInputHandle},
+    segment::{Segment, Segmenter, Step},
+    stats::{BusStats, OutputCounters},
     stream::AvStream,
+    teardown::{Stage, Teardown, TeardownConfig},
+    watchdog::{Progress, TaskKind, Watchdog, WatchdogConfig},
+    write_error::{WriteError, WriteErrorKind},
 };
 
 /// Destination for the multi-stream muxer.
 enum MuxTarget {
     File(String),
-    Net { url: String, format: Option<String> },
+    Net {
+        url: String,
+        format: Option<String>,
+        open_policy: OpenPolicy,
+        reconnect: Option<RetryPolicy>,
+    },
+    Hls {
+        path: String,
+        segment_seconds: u32,
+        list_size: u32,
+    },
+    Segments {
+        dir: String,
+        pattern: String,
+        segment_seconds: u32,
+    },
 }
 
 /// An item flowing into the multi-stream muxer: a packet for a given output
-/// stream index, or the end-of-stream signal for one source.
+/// stream index, the end-of-stream signal for one source, or how many packets
+/// a source lost to lag.
 enum MuxSignal {
     Packet(usize, RawPacket),
     Eof,
+    Lagged(u64),
 }
 
-/// One stream's role in a File/Net mux: copy the demuxed input through, or
+/// One stream's role in a File/Net/Hls mux: copy the demuxed input through, or
 /// transcode it via its encoder task.
 struct MuxPlanEntry {
     input_index: usize,
@@ -45,196 +80,969 @@ struct MuxPlanEntry {
     encode: Option<EncodeConfig>,
     /// Target codec id for the muxed output stream.
     codec_id: ffmpeg_next::codec::Id,
+    /// Degrees the transcoded frames are turned.
+    rotation: u32,
+}
+
+impl MuxTarget {
+    fn label(&self) -> &str {
+        match self {
+            MuxTarget::File(path) => path,
+            MuxTarget::Net { url, .. } => url,
+            MuxTarget::Hls { path, .. } => path,
+            MuxTarget::Segments { dir, .. } => dir,
+        }
+    }
+}
+
+/// Create the muxer for `target` with one output stream per `streams` entry.
+/// With `flush_every`, the output is flushed that often (see
+/// [`AvOutput::set_flush_every`]). With `connect`, the header is written too,
+/// which is when muxers doing their own I/O (RTSP) actually connect.
+fn open_mux_target(
+    target: &MuxTarget,
+    streams: &[AvStream],
+    flush_every: Option<std::time::Duration>,
+    connect: bool,
+) -> anyhow::Result<AvOutput> {
+    let mut output = match target {
+        MuxTarget::File(path) => AvOutput::new(path, None, None)?,
+        MuxTarget::Net { url, format, .. } => {
+            let mut output = AvOutput::new(url, format.as_deref(), None)
+                .map_err(|e| anyhow::anyhow!("mux AvOutput::new(url={:?}): {:?}", url, e))?;
+            // RTSP output often needs TCP interleaving. The RTSP muxer does its
+            // own I/O, so this is a muxer option rather than an avio one.
+            if format.as_deref() == Some("rtsp") {
+                output.set_muxer_option("rtsp_transport", "tcp")?;
+            }
+            output
+        }
+        MuxTarget::Hls {
+            path,
+            segment_seconds,
+            list_size,
+        } => {
+            let mut output = AvOutput::new(path, Some("hls"), None)
+                .map_err(|e| anyhow::anyhow!("hls AvOutput::new(path={:?}): {:?}", path, e))?;
+            output.set_muxer_option("hls_time", &segment_seconds.to_string())?;
+            output.set_muxer_option("hls_list_size", &list_size.to_string())?;
+            // A sliding window (live) prunes old segments from disk too.
+            let flags = if *list_size > 0 {
+                "independent_segments+delete_segments"
+            } else {
+                "independent_segments"
+            };
+            output.set_muxer_option("hls_flags", flags)?;
+            output
+        }
+        MuxTarget::Segments { .. } => {
+            anyhow::bail!("a segmented output opens its files as it goes")
+        }
+    };
+    for stream in streams {
+        output.add_stream(stream)?;
+    }
+    // Each stream's packets come from a task of its own, so the streams of a
+    // file reach the muxer out of step with each other; the interleaving
+    // queue puts them back in DTS order, so its audio and video chunks sit
+    // close together. Live targets write packets as they come rather than
+    // hold one stream back until the other catches up.
+    if matches!(target, MuxTarget::File(_)) && streams.len() > 1 {
+        output.set_interleaved(true);
+    }
+    output.set_flush_every(flush_every)?;
+    if connect {
+        output
+            .write_header()
+            .map_err(|e| anyhow::anyhow!("mux connect {:?}: {:#}", target.label(), e))?;
+    }
+    Ok(output)
+}
+
+/// Open the file of one segment of a `Segments` target at `path`. Its
+/// timestamps are shifted to start at zero, so it plays from its own start.
+fn open_segment(
+    path: &std::path::Path,
+    streams: &[AvStream],
+    flush_every: Option<std::time::Duration>,
+) -> anyhow::Result<AvOutput> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let target = MuxTarget::File(path.to_string_lossy().into_owned());
+    let mut output = open_mux_target(&target, streams, flush_every, false)?;
+    output.set_muxer_option("avoid_negative_ts", "make_zero")?;
+    Ok(output)
+}
+
+/// The [`BusEvent::SegmentClosed`] of output `id` for file `closed`.
+fn closed_event(id: &str, closed: Segment) -> BusEvent {
+    BusEvent::SegmentClosed {
+        id: id.to_string(),
+        path: closed.path.to_string_lossy().into_owned(),
+        start_ms: closed.start.timestamp_millis(),
+        duration_ms: (closed.duration * 1000.0).round() as u64,
+    }
+}
+
+/// Most bytes a lazy `Net` output holds while it is not connected. A GOP
+/// larger than this is dropped and buffering restarts at the next keyframe.
+const LAZY_PENDING_BYTES: usize = 32 << 20;
+
+/// The stream whose keyframes start what a mux buffers: its input index and
+/// codec.
+#[derive(Clone, Copy)]
+struct KeyStream {
+    index: usize,
+    codec: ffmpeg_next::codec::Id,
+}
+
+/// Connection state of an [`OpenPolicy::Lazy`] mux that is not open yet.
+struct LazyOpen {
+    retry: RetryPolicy,
+    attempts: u32,
+    /// Whether a connection that was up broke, so the next one reconnects.
+    reconnecting: bool,
+    next_attempt: tokio::time::Instant,
+    /// Packets from the latest keyframe of the key stream on.
+    pending: GopBuffer,
+}
+
+impl LazyOpen {
+    fn new(retry: RetryPolicy, key: KeyStream) -> Self {
+        let limits = GopLimits {
+            max_bytes: Some(LAZY_PENDING_BYTES),
+            // Only the newest GOP: the stream starts as close to live as it can.
+            max_duration: Some(std::time::Duration::ZERO),
+        };
+        Self {
+            retry,
+            attempts: 0,
+            reconnecting: false,
+            next_attempt: tokio::time::Instant::now(),
+            pending: GopBuffer::new(limits).with_key_stream(key.index, Some(key.codec)),
+        }
+    }
+
+    /// Whether a keyframe is held, i.e. the stream could start now.
+    fn ready(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Buffer a packet, restarting at each keyframe of the key stream.
+    fn hold(&mut self, idx: usize, packet: RawPacket) {
+        self.pending.push_indexed(idx, packet);
+    }
+
+    /// Start over after the connection broke: a fresh round of attempts from
+    /// the next keyframe.
+    fn restart(&mut self) {
+        self.attempts = 0;
+        self.reconnecting = true;
+        self.next_attempt = tokio::time::Instant::now();
+        self.pending.clear();
+    }
+
+    /// Make one connection attempt. `Ok(None)` means it failed and the next
+    /// one is scheduled; `Err` that the retries are exhausted.
+    fn attempt(
+        &mut self,
+        label: &str,
+        open: impl FnOnce() -> anyhow::Result<AvOutput>,
+    ) -> anyhow::Result<Option<AvOutput>> {
+        self.attempts += 1;
+        match open() {
+            Ok(output) => {
+                tracing::info!("mux {}: connected (attempt {})", label, self.attempts);
+                Ok(Some(output))
+            }
+            Err(e) if self.attempts >= self.retry.max_attempts.max(1) => {
+                Err(e.context(format!("no connection after {} attempts", self.attempts)))
+            }
+            Err(e) => {
+                let delay = self.retry.backoff(self.attempts);
+                tracing::warn!(
+                    "mux {}: attempt {} failed, retrying in {:?}: {:#}",
+                    label,
+                    self.attempts,
+                    delay,
+                    e
+                );
+                self.next_attempt = tokio::time::Instant::now() + delay;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// How a mux task goes on after a failed write.
+#[derive(Debug, PartialEq, Eq)]
+enum WriteOutcome {
+    /// Drop the packet and keep writing.
+    Continue,
+    /// Close the connection and open a new one.
+    Reconnect,
+    /// Close the output for good.
+    Stop,
+}
+
+/// What a failed write of `kind` means for a mux to `target`: a network
+/// output whose connection broke reconnects if it has a reconnect policy,
+/// anything writing on would not fix (full or failing storage, data the
+/// muxer refuses, a broken connection not to be reopened) stops the output,
+/// and the rest costs the one packet.
+fn write_outcome(target: &MuxTarget, kind: WriteErrorKind) -> WriteOutcome {
+    match kind {
+        WriteErrorKind::Network | WriteErrorKind::Stalled
+            if matches!(
+                target,
+                MuxTarget::Net {
+                    reconnect: Some(_),
+                    ..
+                }
+            ) =>
+        {
+            WriteOutcome::Reconnect
+        }
+        kind if kind.is_fatal() => WriteOutcome::Stop,
+        _ => WriteOutcome::Continue,
+    }
+}
+
+/// The mux task state a failed write acts on.
+struct MuxWriteState<'a> {
+    id: &'a str,
+    label: &'a str,
+    target: &'a MuxTarget,
+    /// The stream whose keyframes restart a reconnecting output.
+    key: KeyStream,
+    events: &'a tokio::sync::broadcast::Sender<BusEvent>,
+    /// The bus watchdog and the task's progress on it, when watched.
+    watch: Option<(Watchdog, Arc<Progress>)>,
+    /// Stalled writes recovered by reconnecting.
+    stalls: Cell<u32>,
+    counters: Arc<OutputCounters>,
+}
+
+impl MuxWriteState<'_> {
+    /// Let the watchdog interrupt a stalled write to a `Net` `output`; other
+    /// outputs' stalls are only reported.
+    fn watch(&self, output: &AvOutput) {
+        if let (Some((_, progress)), MuxTarget::Net { .. }) = (&self.watch, self.target) {
+            progress.set_interrupt(Some(output.interrupt_flag()));
+        }
+    }
+
+    /// Write a packet to `output`, on the watchdog's clock. A write the
+    /// watchdog interrupted fails as [`WriteErrorKind::Stalled`].
+    fn write(
+        &self,
+        output: &mut AvOutput,
+        idx: usize,
+        packet: RawPacket,
+    ) -> Result<(), WriteError> {
+        let _busy = self.watch.as_ref().map(|(_, progress)| progress.enter());
+        let size = packet.size();
+        match output.write_packet(idx, packet) {
+            Ok(()) => {
+                self.counters.wrote(size, idx == self.key.index);
+                Ok(())
+            }
+            Err(_) if output.interrupted() => Err(WriteError::stalled("write_packet")),
+            written => written,
+        }
+    }
+
+    /// What to do about `error`: [`write_outcome`], but a stalled write is
+    /// recovered at most [`WatchdogConfig::max_recoveries`] times.
+    fn outcome(&self, error: &WriteError) -> WriteOutcome {
+        let outcome = write_outcome(self.target, error.kind);
+        match &self.watch {
+            Some((watchdog, progress))
+                if error.kind == WriteErrorKind::Stalled && outcome == WriteOutcome::Reconnect =>
+            {
+                let mut stalls = self.stalls.get();
+                let recover = watchdog.may_recover(progress, &mut stalls);
+                self.stalls.set(stalls);
+                if !recover {
+                    return WriteOutcome::Stop;
+                }
+                watchdog.recovered(progress, stalls);
+                outcome
+            }
+            _ => outcome,
+        }
+    }
+
+    /// Apply [`write_outcome`] of `error` to the task's output. False when
+    /// the task must end. A reconnecting output hands its packet hook back
+    /// to `hook` for the next connection.
+    fn on_error(
+        &self,
+        error: WriteError,
+        output: &mut Option<AvOutput>,
+        lazy: &mut Option<LazyOpen>,
+        hook: &mut Option<PacketHook>,
+    ) -> bool {
+        match self.outcome(&error) {
+            WriteOutcome::Continue => {
+                tracing::error!("mux {}: {}", self.label, error);
+                true
+            }
+            WriteOutcome::Reconnect => {
+                tracing::warn!("mux {}: {}, reconnecting", self.label, error);
+                // A broken connection takes no trailer.
+                if let Some(mut output) = output.take() {
+                    *hook = output.take_packet_hook();
+                }
+                let retry = match self.target {
+                    MuxTarget::Net {
+                        reconnect: Some(retry),
+                        ..
+                    } => retry.clone(),
+                    _ => RetryPolicy::default(),
+                };
+                let lazy = lazy.get_or_insert_with(|| LazyOpen::new(retry.clone(), self.key));
+                lazy.retry = retry;
+                lazy.restart();
+                let _ = self.events.send(BusEvent::OutputInterrupted {
+                    id: self.id.to_string(),
+                    error: error.to_string(),
+                    kind: error.kind,
+                });
+                true
+            }
+            WriteOutcome::Stop => {
+                tracing::error!("mux {}: {}, stopping the output", self.label, error);
+                // Close what was written properly if the muxer still can.
+                if let Some(mut output) = output.take()
+                    && let Err(e) = output.finish()
+                {
+                    tracing::warn!("mux {}: {}", self.label, e);
+                }
+                let _ = self.events.send(BusEvent::OutputFailed {
+                    id: self.id.to_string(),
+                    error: error.to_string(),
+                    kind: Some(error.kind),
+                });
+                false
+            }
+        }
+    }
+}
+
+/// Log a failed write of an in-memory mux stream; when its kind is fatal,
+/// report the output failed and return true: the stream ends.
+fn stream_write_failed(
+    id: &str,
+    error: WriteError,
+    events: &tokio::sync::broadcast::Sender<BusEvent>,
+) -> bool {
+    tracing::error!("mux stream {}: {}", id, error);
+    if !error.kind.is_fatal() {
+        return false;
+    }
+    let _ = events.send(BusEvent::OutputFailed {
+        id: id.to_string(),
+        error: error.to_string(),
+        kind: Some(error.kind),
+    });
+    true
 }
 
 pub struct Bus {
     id: String,
     cancel: CancellationToken,
     tx: tokio::sync::mpsc::Sender<BusCommand>,
+    /// Frames `Raw` outputs dropped because they failed to convert.
+    raw_frame_drops: Arc<AtomicU64>,
+    events: tokio::sync::broadcast::Sender<BusEvent>,
+}
+
+/// Capacity of the [`BusEvent`] broadcast.
+const EVENT_CAPACITY: usize = 64;
+
+/// Id of the input [`Bus::add_input`] sets, which outputs read unless
+/// [`OutputConfig::input_id`] names another.
+pub const DEFAULT_INPUT: &str = "default";
+
+/// Tunables of a [`Bus`], see [`Bus::with_options`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BusOptions {
+    /// What encoders do about frames they keep rejecting; recoveries and
+    /// failures are reported as [`BusEvent::EncoderRecovered`] and
+    /// [`BusEvent::EncoderFailed`].
+    pub encoder_recovery: EncoderRecovery,
+    /// Capacity, in packets, of each of the input's packet channels (one
+    /// per stream plus one of all streams, see
+    /// [`AvInputTask::subscribe_stream`]). A consumer falling further behind
+    /// loses packets.
+    pub input_packet_capacity: usize,
+    /// Close holes in the audio timeline (see [`crate::audio_gap`]): with
+    /// silence before audio encoders, by stretching packets in muxing outputs
+    /// that copy audio. Holes too long to fill are reported as
+    /// [`BusEvent::AudioGap`]. `None` (the default) leaves audio as it comes.
+    pub audio_gap_fill: Option<GapFillConfig>,
+    /// What a muxing output does with a stream it would copy in a codec its
+    /// container cannot hold (see [`crate::container`]): transcode it to the
+    /// container's default codec (H.264 / AAC, VP9 / Opus for WebM) when set,
+    /// else (the default) `add_output` fails with [`UnsupportedCodec`].
+    pub auto_transcode: bool,
+    /// Watch decoder, encoder and mux loops for FFmpeg calls that hang (see
+    /// [`crate::watchdog`]), reported as [`BusEvent::TaskStalled`] and
+    /// recovered when the task can be, [`BusEvent::TaskRecovered`], up to
+    /// [`WatchdogConfig::max_recoveries`] times before
+    /// [`BusEvent::TaskFailed`]. `None` watches nothing.
+    pub watchdog: Option<WatchdogConfig>,
+    /// How long [`Bus::shutdown`] and [`Bus::stop`] wait for each stage of
+    /// the bus to end before forcing it (see [`crate::teardown`]).
+    pub teardown: TeardownConfig,
+    /// How decoders decode video: on the first hardware decoder that opens
+    /// (the default), in software only, or on a given device (see
+    /// [`HwPreference`](crate::hw::HwPreference)). A hardware decoder failing
+    /// falls back to software.
+    pub decoder: DecoderSettings,
+}
+
+impl Default for BusOptions {
+    fn default() -> Self {
+        Self {
+            encoder_recovery: EncoderRecovery::default(),
+            input_packet_capacity: AvInputTask::PACKET_CHAN_CAP,
+            audio_gap_fill: None,
+            auto_transcode: false,
+            watchdog: Some(WatchdogConfig::default()),
+            teardown: TeardownConfig::default(),
+            decoder: DecoderSettings::default(),
+        }
+    }
 }
 
 impl Bus {
     pub fn new(id: &str) -> Self {
+        Self::with_options(id, BusOptions::default())
+    }
+
+    pub fn with_options(id: &str, options: BusOptions) -> Self {
         let id = id.to_string();
         let cancel = CancellationToken::new();
         let (tx, rx) = tokio::sync::mpsc::channel(1024);
 
+        let raw_frame_drops = Arc::new(AtomicU64::new(0));
+        let (events, _) = tokio::sync::broadcast::channel(EVENT_CAPACITY);
+
         let cancel_clone = cancel.clone();
-        tokio::spawn(async move { Self::inner_loop(cancel_clone, rx).await });
-        Self { id: id, cancel, tx }
+        // Every command runs in the bus span, and so does every task it starts
+        // (input/decoder/encoder spans are its children, mux loops run in
+        // their output's span), so log lines carry the bus id.
+        let span = tracing::info_span!("bus", bus_id = %id);
+        let state = BusState::new(
+            raw_frame_drops.clone(),
+            events.clone(),
+            options,
+            span.clone(),
+        );
+        span.in_scope(|| {
+            if let Some(watchdog) = &state.watchdog {
+                watchdog.spawn(cancel.clone());
+            }
+            crate::worker::spawn_task("bus", Self::inner_loop(cancel_clone, rx, state))
+        });
+        Self {
+            id: id,
+            cancel,
+            tx,
+            raw_frame_drops,
+            events,
+        }
     }
 
     async fn inner_loop(
         cancel: CancellationToken,
         mut rx: tokio::sync::mpsc::Receiver<BusCommand>,
+        mut state: BusState,
     ) {
         let cancel_clone = cancel.clone();
-        let mut state = BusState::new();
-        loop {
+        let shutdown = loop {
             tokio::select! {
                 _ = cancel_clone.cancelled() => {
-                    break;
+                    break None;
                 },
                 Some(cmd) = rx.recv() => {
+                    if let BusCommand::Shutdown { result } = cmd {
+                        break Some(result);
+                    }
                     if let Err(e) = Self::inner_command_handler(&mut state, cmd).await {
                         error!("inner_command_handler error: {:#?}\nbacktrace:\n{}", e, Backtrace::capture());
                     }
                 },
             }
+        };
+        // Stage by stage either way; a stop just waits less for each.
+        let config = &state.options.teardown;
+        let stage_timeout = match shutdown {
+            Some(_) => config.shutdown_stage_timeout,
+            None => config.stop_stage_timeout,
+        };
+        // An input never started has nothing to end.
+        for input in state.inputs.values_mut() {
+            input.pending = None;
+        }
+        let forced = state.teardown.run(stage_timeout).await;
+        if let Some(result) = shutdown {
+            let _ = result.send(forced);
         }
+        // The state, and the channel senders it holds, go only now: nothing
+        // sees a channel close before the EOF that went through it.
+        cancel.cancel();
     }
 
     async fn inner_command_handler(state: &mut BusState, cmd: BusCommand) -> anyhow::Result<()> {
         match cmd {
             BusCommand::AddInput {
+                id,
                 input,
                 options,
                 result,
             } => {
                 result
-                    .send(Self::add_input_internal(state, input, options).await)
+                    .send(Self::add_input_internal(state, id, input, options).await)
                     .map_err(|e| anyhow::anyhow!("send result error: {:#?}", e))?;
             }
-            BusCommand::RemoveInput { result } => {
-                if let Some(input) = state.input_task.take() {
-                    input.stop();
-                    drop(input);
-                }
-                state.pending_input = None;
-                state.input_config = None;
+            BusCommand::RemoveInput { id, result } => {
                 result
-                    .send(Ok(()))
+                    .send(Self::remove_input_internal(state, &id))
                     .map_err(|e| anyhow::anyhow!("send result error: {:#?}", e))?;
             }
             BusCommand::AddOutput { output, result } => {
-                let id = &output.id;
-                if state.output_config.contains_key(id) {
-                    let _ = result.send(Err(anyhow::anyhow!("output already exists")));
-                    return Err(anyhow::anyhow!("output already exists"));
-                }
-
-                // try to start input task
-                if state.input_task.is_none() && state.input_config.is_some() {
-                    if let Err(e) = Self::prepare_input_task(state).await {
-                        let msg = format!("{:#}", e);
-                        let _ = result.send(Err(anyhow::anyhow!("{}", msg)));
-                        return Err(anyhow::anyhow!("{}", msg));
-                    }
-                }
-                let input_stream = state
-                    .input_streams
-                    .iter()
-                    .find(|s| match output.av_type {
-                        OutputAvType::Video => s.is_video(),
-                        OutputAvType::Audio => s.is_audio(),
-                    })
-                    .ok_or(anyhow::anyhow!("stream not found"))?;
-                let input_stream_index = input_stream.index();
-                let need_decoder = Self::try_decoder(input_stream, &output)?;
-                let need_encoder = Self::try_encoder(input_stream, &output)?;
-                let is_file_net = matches!(
-                    &output.dest,
-                    OutputDest::File { .. } | OutputDest::Net { .. }
-                );
-                // File/Net decide copy vs transcode per stream and start their
-                // decoder/encoder tasks inside the muxer builder; every other
-                // dest starts the primary stream's tasks here.
-                if !is_file_net {
-                    // Live/streaming outputs keep the lossy (low-latency) path.
-                    if need_decoder {
-                        Self::start_decoder_task(state, input_stream_index, false).await?;
-                    }
-                    if need_encoder {
-                        Self::start_encoder_task(
-                            state,
-                            input_stream_index,
-                            output.encode.as_ref(),
-                            false,
-                        )
-                        .await?;
-                    }
-                }
-
-                let stream_result = match &output.dest {
-                    OutputDest::Raw => {
-                        Self::create_decoder_raw_output_stream(state, input_stream_index).await
-                    }
-                    OutputDest::File { path } => {
-                        Self::create_mux_to_file(state, path, input_stream_index, &output).await
-                    }
-                    OutputDest::Net { url, format } => {
-                        Self::create_mux_to_net(
-                            state,
-                            url,
-                            format.as_deref(),
-                            input_stream_index,
-                            &output,
-                        )
-                        .await
-                    }
-                    OutputDest::Mux { format } => {
-                        if need_encoder {
-                            Self::create_mux_output_stream_from_encoder(
-                                state,
-                                format,
-                                input_stream_index,
-                            )
-                            .await
-                        } else {
-                            Self::create_mux_output_stream(state, format, input_stream_index).await
+                let span = output_span(&output.id);
+                let input = output.input_id.clone();
+                let id = output.id.clone();
+                let added = match Self::add_output_internal(state, output)
+                    .instrument(span)
+                    .await
+                {
+                    Ok(added) => match Self::start_input_task(state, &input).await {
+                        Ok(()) => Ok(added),
+                        Err(e) => {
+                            let _ = Self::remove_output_internal(state, &id);
+                            Err(e)
                         }
-                    }
-                    OutputDest::Encoded => {
-                        Self::create_encoded_output_stream(state, input_stream_index).await
-                    }
-                    OutputDest::Demuxed => {
-                        Self::create_demuxed_output_stream(state, input_stream_index).await
-                    }
+                    },
+                    Err(e) => Err(e),
                 };
-
-                match stream_result {
-                    Ok((av, stream)) => {
-                        state.output_config.insert(id.clone(), output);
-                        if let Err(e) = Self::start_input_task(state).await {
-                            let msg = format!("{:#}", e);
-                            let _ = result.send(Err(anyhow::anyhow!("{}", msg)));
-                            return Err(anyhow::anyhow!("{}", msg));
-                        }
+                match added {
+                    Ok(added) => {
                         result
-                            .send(Ok((av, stream)))
+                            .send(Ok(added))
                             .map_err(|_| anyhow::anyhow!("send result error: receiver dropped"))?;
                     }
                     Err(e) => {
+                        // The caller gets the error itself, so it can
+                        // downcast it (e.g. to `InvalidEncodeConfig`).
                         let msg = format!("{:#}", e);
-                        let _ = result.send(Err(anyhow::anyhow!("{}", msg)));
+                        let _ = result.send(Err(e));
                         return Err(anyhow::anyhow!("{}", msg));
                     }
                 }
             }
+            BusCommand::RemoveOutput { id, result } => {
+                let span = output_span(&id);
+                let _ = result.send(span.in_scope(|| Self::remove_output_internal(state, &id)));
+            }
+            BusCommand::AddOutputs { outputs, result } => {
+                // Register every output before the input starts reading, so all
+                // of them see the stream from its first packet.
+                let mut added = Vec::with_capacity(outputs.len());
+                // The outputs added on each input: their ids and positions.
+                let mut inputs: Vec<(String, Vec<(usize, String)>)> = Vec::new();
+                for (at, output) in outputs.into_iter().enumerate() {
+                    let span = output_span(&output.id);
+                    let (id, input) = (output.id.clone(), output.input_id.clone());
+                    let r = Self::add_output_internal(state, output)
+                        .instrument(span)
+                        .await;
+                    if r.is_ok() {
+                        match inputs.iter_mut().find(|(name, _)| *name == input) {
+                            Some((_, ids)) => ids.push((at, id)),
+                            None => inputs.push((input, vec![(at, id)])),
+                        }
+                    }
+                    added.push(r);
+                }
+                // An input that will not start fails only its own outputs; the
+                // others keep theirs, and outputs refused already keep their
+                // own error.
+                for (input, ids) in &inputs {
+                    if let Err(e) = Self::start_input_task(state, input).await {
+                        let msg = format!("{:#}", e);
+                        for (at, id) in ids {
+                            let _ = Self::remove_output_internal(state, id);
+                            added[*at] = Err(anyhow::anyhow!("{}", msg));
+                        }
+                    }
+                }
+                let _ = result.send(added);
+            }
+            BusCommand::RenegotiateOutput {
+                id,
+                acceptable,
+                result,
+            } => {
+                let span = output_span(&id);
+                let r = Self::renegotiate_output_internal(state, &id, acceptable)
+                    .instrument(span)
+                    .await;
+                let _ = result.send(r);
+            }
             BusCommand::SubscribeAudio { result } => {
-                let r = Self::subscribe_audio_internal(state).await;
+                let r = Self::subscribe_audio_internal(state, DEFAULT_INPUT).await;
                 let _ = result.send(r);
             }
             BusCommand::SubscribeVideo { result } => {
-                let r = Self::subscribe_video_internal(state).await;
+                let r = Self::subscribe_video_internal(state, DEFAULT_INPUT).await;
+                let _ = result.send(r);
+            }
+            BusCommand::InputStreams { result } => {
+                let r = match Self::prepare_input_task(state, DEFAULT_INPUT).await {
+                    Ok(()) => state
+                        .input(DEFAULT_INPUT)
+                        .map(|input| input.streams.clone()),
+                    Err(e) => Err(e),
+                };
+                let _ = result.send(r);
+            }
+            BusCommand::ClockOffset { result } => {
+                let task = state
+                    .inputs
+                    .get(DEFAULT_INPUT)
+                    .and_then(|i| i.task.as_ref());
+                let _ = result.send(task.and_then(|task| task.clock_offset()));
+            }
+            BusCommand::InputStats { result } => {
+                let task = state
+                    .inputs
+                    .get(DEFAULT_INPUT)
+                    .and_then(|i| i.task.as_ref());
+                let _ = result.send(task.map(|task| task.stats()));
+            }
+            BusCommand::PauseInput { id, result } => {
+                let r = state.input_task(&id).map(|task| {
+                    task.pause();
+                    task.playback()
+                });
+                let _ = result.send(r);
+            }
+            BusCommand::ResumeInput { id, result } => {
+                let r = state.input_task(&id).map(|task| {
+                    task.resume();
+                    task.playback()
+                });
+                let _ = result.send(r);
+            }
+            BusCommand::SetPlaybackRate { id, rate, result } => {
+                let r = Self::set_playback_rate_internal(state, &id, rate);
                 let _ = result.send(r);
             }
+            BusCommand::GetStats { result } => {
+                let _ = result.send(state.stats());
+            }
+            BusCommand::StateSnapshot { result } => {
+                let _ = result.send(state.snapshot());
+            }
+            // Handled by the loop, which ends with it.
+            BusCommand::Shutdown { result } => {
+                let _ = result.send(Vec::new());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Register one output: start the decoder/encoder tasks it needs and build
+    /// its stream. Does not start the input; callers do once all outputs of a
+    /// command are in. When that fails part way, what was started for the
+    /// output goes again (see [`Self::discard_output`]).
+    async fn add_output_internal(
+        state: &mut BusState,
+        output: OutputConfig,
+    ) -> anyhow::Result<(AvStream, RawOutputStream)> {
+        if state.output_config.contains_key(&output.id) {
+            return Err(AddOutputError::AlreadyExists(output.id).into());
+        }
+        let (id, input) = (output.id.clone(), output.input_id.clone());
+        let added = Self::build_output(state, output).await;
+        if let Err(e) = &added {
+            tracing::warn!("output refused, undoing what it started: {:#}", e);
+            Self::discard_output(state, &input, &id);
+        }
+        added
+    }
+
+    /// The work of [`Self::add_output_internal`].
+    async fn build_output(
+        state: &mut BusState,
+        mut output: OutputConfig,
+    ) -> anyhow::Result<(AvStream, RawOutputStream)> {
+        let hook = output.packet_hook.get_mut().ok().and_then(Option::take);
+        if hook.is_some() && matches!(output.dest, OutputDest::Raw | OutputDest::Encoded) {
+            return Err(anyhow::anyhow!(
+                "packet hooks need a muxing or demuxed output"
+            ));
+        }
+        if matches!(output.av_type, OutputAvType::Data | OutputAvType::All)
+            && matches!(output.dest, OutputDest::Raw | OutputDest::Encoded)
+        {
+            return Err(anyhow::anyhow!(
+                "raw and encoded outputs take one video or audio stream"
+            ));
+        }
+        if output.acceptable_codecs.is_some() {
+            if !matches!(output.dest, OutputDest::Demuxed) {
+                return Err(anyhow::anyhow!("acceptable codecs need a demuxed output"));
+            }
+            if output.encode.is_some() {
+                return Err(anyhow::anyhow!(
+                    "acceptable codecs replace the encode config, set only one"
+                ));
+            }
+        }
+        Self::validate_encode(&output)?;
+
+        // try to start input task
+        let input = output.input_id.clone();
+        Self::prepare_input_task(state, &input).await?;
+        let streams = &state.input(&input)?.streams;
+        let input_stream = output
+            .av_type
+            .primary(streams)
+            .ok_or(AddOutputError::StreamNotFound(output.av_type))?;
+        if let Some(acceptable) = &output.acceptable_codecs {
+            output.encode = Self::negotiate(input_stream, acceptable)?;
+            Self::validate_encode(&output)?;
+        }
+        Self::check_container(
+            streams,
+            input_stream,
+            &mut output,
+            state.options.auto_transcode,
+        )?;
+        let input_stream_index = input_stream.index();
+        let need_decoder = Self::try_decoder(input_stream, &output)?;
+        let need_encoder = Self::try_encoder(input_stream, &output)?;
+        let is_file_net = matches!(
+            &output.dest,
+            OutputDest::File { .. }
+                | OutputDest::Net { .. }
+                | OutputDest::Hls { .. }
+                | OutputDest::Segments { .. }
+        );
+        // File/Net/Hls/Segments decide copy vs transcode per stream and start their
+        // decoder/encoder tasks inside the muxer builder; every other
+        // dest starts the primary stream's tasks here.
+        let rotation = output.rotation(input_stream);
+        if !is_file_net {
+            // Live/streaming outputs keep the lossy (low-latency) path.
+            if need_decoder {
+                Self::start_decoder_task(
+                    state,
+                    &input,
+                    input_stream_index,
+                    false,
+                    Some(&output.id),
+                )
+                .await?;
+            }
+            if need_encoder {
+                Self::start_encoder_task(
+                    state,
+                    &input,
+                    input_stream_index,
+                    output.encode.as_ref(),
+                    rotation,
+                    false,
+                    &output.id,
+                )
+                .await?;
+            }
         }
 
+        let stream_result = match &output.dest {
+            OutputDest::Raw => {
+                Self::create_decoder_raw_output_stream(
+                    state,
+                    &input,
+                    input_stream_index,
+                    output.av_type,
+                    output.roi,
+                    rotation,
+                    output.frame_queue,
+                )
+                .await
+            }
+            OutputDest::File { path } => {
+                Self::create_mux_to_file(state, path, input_stream_index, &output, hook)
+                    .await
+                    .map(RawOutputStream::from_video)
+            }
+            OutputDest::Net {
+                url,
+                format,
+                open_policy,
+                reconnect,
+            } => Self::create_mux_to_net(
+                state,
+                url,
+                format.as_deref(),
+                open_policy,
+                reconnect.as_ref(),
+                input_stream_index,
+                &output,
+                hook,
+            )
+            .await
+            .map(RawOutputStream::from_video),
+            OutputDest::Hls {
+                path,
+                segment_seconds,
+                list_size,
+            } => {
+                let target = MuxTarget::Hls {
+                    path: path.clone(),
+                    segment_seconds: *segment_seconds,
+                    list_size: *list_size,
+                };
+                Self::create_mux_to_target(state, target, input_stream_index, &output, hook)
+                    .await
+                    .map(RawOutputStream::from_video)
+            }
+            OutputDest::Segments {
+                dir,
+                pattern,
+                segment_seconds,
+            } => {
+                crate::segment::check_pattern(pattern)?;
+                std::fs::create_dir_all(dir)
+                    .map_err(|e| anyhow::anyhow!("segment dir {:?}: {}", dir, e))?;
+                let target = MuxTarget::Segments {
+                    dir: dir.clone(),
+                    pattern: pattern.clone(),
+                    segment_seconds: *segment_seconds,
+                };
+                Self::create_mux_to_target(state, target, input_stream_index, &output, hook)
+                    .await
+                    .map(RawOutputStream::from_video)
+            }
+            OutputDest::Mux { format } => {
+                let flush_every = output.flush_every();
+                let stream = if need_encoder {
+                    Self::create_mux_output_stream_from_encoder(
+                        state,
+                        &output.id,
+                        format,
+                        &input,
+                        input_stream_index,
+                        output.encode.as_ref(),
+                        rotation,
+                        flush_every,
+                        hook,
+                    )
+                    .await
+                } else {
+                    Self::create_mux_output_stream(
+                        state,
+                        &output.id,
+                        format,
+                        &input,
+                        input_stream_index,
+                        flush_every,
+                        hook,
+                    )
+                    .await
+                };
+                stream.map(RawOutputStream::from_video)
+            }
+            OutputDest::Encoded => Self::create_encoded_output_stream(
+                state,
+                &input,
+                input_stream_index,
+                output.encode.as_ref(),
+                rotation,
+            )
+            .await
+            .map(RawOutputStream::from_video),
+            OutputDest::Demuxed => Self::create_demuxed_output_stream(
+                state,
+                &output.id,
+                &input,
+                input_stream_index,
+                output.encode.as_ref(),
+                output.acceptable_codecs.is_some(),
+                output.annexb,
+                hook,
+            )
+            .await
+            .map(RawOutputStream::from_video),
+        };
+        let (av, stream) = stream_result?;
+        // These hand over the channels themselves, so removing them ends the
+        // stream rather than a task.
+        let stream = match output.dest {
+            OutputDest::Raw | OutputDest::Encoded => stream
+                .until(state.output_token(&output.id))
+                .counted(state.output_counters(&output.id)),
+            _ => stream,
+        };
+        state.output_config.insert(output.id.clone(), output);
+        Ok((av, stream))
+    }
+
+    /// End output `id`: its task finishes the output with what it has (a
+    /// file gets its trailer), a Raw or Encoded stream ends at its next
+    /// item. The decoders and encoders no other output uses stop with it.
+    fn remove_output_internal(state: &mut BusState, id: &str) -> anyhow::Result<()> {
+        let output = state
+            .output_config
+            .remove(id)
+            .ok_or(anyhow::anyhow!("output not found"))?;
+        Self::discard_output(state, &output.input_id, id);
+        tracing::info!("output removed");
         Ok(())
     }
 
+    /// Let go of what output `id` of `input` holds: its task is stopped and
+    /// the decoders and encoders no other output uses stop with it.
+    fn discard_output(state: &mut BusState, input: &str, id: &str) {
+        if let Some(token) = state.output_tokens.remove(id) {
+            token.cancel();
+        }
+        state.output_counters.remove(id);
+        state.renegotiations.remove(id);
+        if let Some(input) = state.inputs.get_mut(input) {
+            input.release(id);
+        }
+    }
+
     fn try_decoder(input_stream: &AvStream, output: &OutputConfig) -> anyhow::Result<bool> {
         let input_codec = input_stream.parameters().id();
 
-        // RAWVIDEO: packets are raw pixels, no decoder. WRAPPED_AVFRAME: packets wrap AVFrame, need decoder to unwrap.
-        if input_codec == ffmpeg_next::codec::Id::RAWVIDEO {
+        // Subtitle and data packets are only ever copied.
+        if input_stream.is_data() {
             return Ok(false);
         }
 
+        // RAWVIDEO: packets are raw pixels, encoders take them without a
+        // decoder; only Raw outputs want them decoded into frames.
+        // WRAPPED_AVFRAME: packets wrap AVFrame, need decoder to unwrap.
+        if input_codec == ffmpeg_next::codec::Id::RAWVIDEO {
+            return Ok(matches!(output.dest, OutputDest::Raw));
+        }
+
         match &output.dest {
             OutputDest::Raw => Ok(true),
-            OutputDest::File { .. } => Ok(false),
+            OutputDest::File { .. } | OutputDest::Hls { .. } | OutputDest::Segments { .. } => {
+                Ok(false)
+            }
             // Mux: need decoder only when encoder is also needed (e.g. WRAPPED_AVFRAME needs unwrap → encode).
             // If input is already the target codec (e.g. H.264 → h264 mux), no decoder needed.
             // For audio passthrough (e.g. AAC → adts mux), no decoder needed.
@@ -264,6 +1072,9 @@ impl Bus {
     fn try_encoder(input_stream: &AvStream, output: &OutputConfig) -> anyhow::Result<bool> {
         let input_codec = input_stream.parameters().id();
 
+        if input_stream.is_data() {
+            return Ok(false);
+        }
         if let OutputDest::Raw = output.dest {
             return Ok(false);
         }
@@ -301,42 +1112,179 @@ impl Bus {
         // requested params actually differ from the input; if they match, the
         // stream is copied through unchanged (no decoder, no encoder).
         if let Some(encode) = &output.encode {
-            return Ok(Self::encode_needed(input_stream, encode));
+            return Ok(
+                output.rotation(input_stream) != 0 || Self::encode_needed(input_stream, encode)
+            );
         }
 
         Ok(false)
     }
 
-    /// Map an [`EncodeConfig::codec`] name to its codec id (best-effort). Covers
-    /// the codecs this pipeline emits; unknown names yield `None` (treated as a
-    /// codec change, i.e. transcode).
-    fn codec_id_from_name(name: &str) -> Option<ffmpeg_next::codec::Id> {
-        use ffmpeg_next::codec::Id;
-        Some(match name.to_ascii_lowercase().as_str() {
-            "h264" | "avc" | "libx264" => Id::H264,
-            "hevc" | "h265" | "libx265" => Id::HEVC,
-            "aac" => Id::AAC,
-            "opus" | "libopus" => Id::OPUS,
-            "mjpeg" => Id::MJPEG,
-            "vp8" | "libvpx" => Id::VP8,
-            "vp9" | "libvpx-vp9" => Id::VP9,
-            "av1" => Id::AV1,
-            "mp3" | "libmp3lame" => Id::MP3,
-            "rawvideo" => Id::RAWVIDEO,
-            _ => return None,
-        })
-    }
+    /// Check the container of a muxing output against the codec of every
+    /// stream it carries, before anything is opened for it. A stream it would
+    /// copy in a codec the container cannot hold is transcoded instead when
+    /// `auto_transcode` is on (its encode config set to the container's
+    /// default codec); otherwise, and for a transcode into such a codec, the
+    /// output is refused with [`UnsupportedCodec`].
+    fn check_container(
+        streams: &[AvStream],
+        primary: &AvStream,
+        output: &mut OutputConfig,
+        auto_transcode: bool,
+    ) -> anyhow::Result<()> {
+        let container = match &output.dest {
+            OutputDest::File { path } => crate::container::guess_format(path),
+            OutputDest::Net { url, format, .. } => format
+                .clone()
+                .or_else(|| crate::container::guess_format(url)),
+            OutputDest::Hls { .. } => Some("hls".to_string()),
+            OutputDest::Segments { pattern, .. } => crate::container::guess_format(pattern),
+            OutputDest::Mux { format } => Some(format.clone()),
+            OutputDest::Raw | OutputDest::Encoded | OutputDest::Demuxed => None,
+        };
+        let Some(container) = container else {
+            return Ok(());
+        };
+        let carried = match &output.dest {
+            OutputDest::File { .. }
+            | OutputDest::Net { .. }
+            | OutputDest::Hls { .. }
+            | OutputDest::Segments { .. } => Self::carried_streams(streams, primary, output),
+            _ => Vec::new(),
+        };
 
-    /// Whether an explicit encode config actually requires a transcode of the
-    /// input stream, or whether it can be copied through unchanged. Only the
-    /// *structural* parameters a stream-copy cannot alter are compared: the
-    /// codec, plus geometry (video) or sample rate + channel count (audio).
-    /// Quality knobs (bitrate, preset, pixel_format) do not by themselves force
-    /// a transcode when the structural params already match.
-    fn encode_needed(input_stream: &AvStream, encode: &EncodeConfig) -> bool {
-        Self::encode_needed_params(
-            input_stream.parameters().id(),
-            input_stream.is_video(),
+        for (stream, is_primary) in
+            std::iter::once((primary, true)).chain(carried.into_iter().map(|s| (s, false)))
+        {
+            // The table covers audio and video codecs: whether a container
+            // takes a subtitle or data stream is left to its muxer.
+            if stream.is_data() {
+                continue;
+            }
+            let (transcoded, encode) = if is_primary {
+                (Self::try_encoder(stream, output)?, output.encode.as_ref())
+            } else {
+                let encode = output.audio_encode.as_ref().filter(|_| stream.is_audio());
+                (
+                    encode.is_some_and(|e| Self::encode_needed(stream, e)),
+                    encode,
+                )
+            };
+            let codec = if transcoded {
+                // An encoder the bus picks itself is left to the muxer.
+                match encode.and_then(|e| Self::codec_id_from_name(&e.codec)) {
+                    Some(codec) => codec,
+                    None => continue,
+                }
+            } else {
+                stream.parameters().id()
+            };
+            if crate::container::container_supports(&container, codec) != SupportLevel::Unsupported
+            {
+                continue;
+            }
+            match crate::container::transcode_target(&container, stream.is_video()) {
+                Some(target) if auto_transcode && !transcoded => {
+                    tracing::info!(
+                        "output {}: {} cannot be copied into {container}, transcoding it to {target}",
+                        output.id,
+                        codec.name()
+                    );
+                    let encode = Some(EncodeConfig {
+                        codec: target.to_string(),
+                        ..EncodeConfig::default()
+                    });
+                    if is_primary {
+                        output.encode = encode;
+                    } else {
+                        output.audio_encode = encode;
+                    }
+                }
+                _ => return Err(UnsupportedCodec { codec, container }.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject an output whose encode configs no encoder would take (see
+    /// [`crate::encoder::validate`]) before anything is started for it, with
+    /// every issue of both configs in one [`InvalidEncodeConfig`].
+    fn validate_encode(output: &OutputConfig) -> anyhow::Result<()> {
+        use ffmpeg_next::media::Type;
+
+        let mut issues = Vec::new();
+        let primary = match output.av_type {
+            OutputAvType::Video | OutputAvType::All => Type::Video,
+            OutputAvType::Audio => Type::Audio,
+            OutputAvType::Data => {
+                if output.encode.is_some() {
+                    issues.push(ValidationIssue::new(
+                        "codec",
+                        "data streams are copied, not encoded".to_string(),
+                        None,
+                    ));
+                }
+                Type::Data
+            }
+        };
+        for (encode, medium) in [
+            (
+                output.encode.as_ref().filter(|_| primary != Type::Data),
+                primary,
+            ),
+            (output.audio_encode.as_ref(), Type::Audio),
+        ] {
+            let Some(encode) = encode else {
+                continue;
+            };
+            match crate::encoder::validate(encode) {
+                Ok(validated) if validated.medium != medium => issues.push(ValidationIssue::new(
+                    "codec",
+                    format!("{} does not encode {medium:?}", validated.encoder),
+                    None,
+                )),
+                Ok(_) => {}
+                Err(found) => issues.extend(found),
+            }
+        }
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidEncodeConfig(issues).into())
+        }
+    }
+
+    /// Map an [`EncodeConfig::codec`] name to its codec id (best-effort). Covers
+    /// the codecs this pipeline emits; unknown names yield `None` (treated as a
+    /// codec change, i.e. transcode).
+    fn codec_id_from_name(name: &str) -> Option<ffmpeg_next::codec::Id> {
+        use ffmpeg_next::codec::Id;
+        Some(match name.to_ascii_lowercase().as_str() {
+            "h264" | "avc" | "libx264" => Id::H264,
+            "hevc" | "h265" | "libx265" => Id::HEVC,
+            "aac" => Id::AAC,
+            "opus" | "libopus" => Id::OPUS,
+            "mjpeg" => Id::MJPEG,
+            "vp8" | "libvpx" => Id::VP8,
+            "vp9" | "libvpx-vp9" => Id::VP9,
+            "av1" => Id::AV1,
+            "mp3" | "libmp3lame" => Id::MP3,
+            "rawvideo" => Id::RAWVIDEO,
+            _ => return None,
+        })
+    }
+
+    /// Whether an explicit encode config actually requires a transcode of the
+    /// input stream, or whether it can be copied through unchanged. Only the
+    /// *structural* parameters a stream-copy cannot alter are compared: the
+    /// codec, plus geometry and a forced keyframe cadence (video) or sample
+    /// rate + channel count (audio).
+    /// Quality knobs (bitrate, preset, pixel_format) do not by themselves force
+    /// a transcode when the structural params already match.
+    fn encode_needed(input_stream: &AvStream, encode: &EncodeConfig) -> bool {
+        Self::encode_needed_params(
+            input_stream.parameters().id(),
+            input_stream.is_video(),
             input_stream.width(),
             input_stream.height(),
             input_stream.sample_rate(),
@@ -361,7 +1309,10 @@ impl Bus {
             _ => return true,
         }
         if is_video {
-            encode.width.is_some_and(|w| w != width) || encode.height.is_some_and(|h| h != height)
+            // A forced keyframe cadence can't be imposed on copied packets.
+            encode.keyframe_interval.is_some()
+                || encode.width.is_some_and(|w| w != width)
+                || encode.height.is_some_and(|h| h != height)
         } else {
             encode.sample_rate.is_some_and(|sr| sr != sample_rate)
                 || encode.channels.is_some_and(|c| c != channels)
@@ -376,10 +1327,16 @@ impl Bus {
         path: &str,
         primary_index: usize,
         output: &OutputConfig,
+        hook: Option<PacketHook>,
     ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
-        let plan = Self::build_mux_plan(state, primary_index, output)?;
-        Self::start_mux_transcoders(state, &plan).await?;
-        Self::spawn_multi_stream_mux(state, MuxTarget::File(path.to_string()), plan).await
+        Self::create_mux_to_target(
+            state,
+            MuxTarget::File(path.to_string()),
+            primary_index,
+            output,
+            hook,
+        )
+        .await
     }
 
     /// Mux to a network URL (rtmp://, rtsp://, ...). Per stream, copies the
@@ -388,23 +1345,38 @@ impl Bus {
         state: &mut BusState,
         url: &str,
         format: Option<&str>,
+        open_policy: &OpenPolicy,
+        reconnect: Option<&RetryPolicy>,
+        primary_index: usize,
+        output: &OutputConfig,
+        hook: Option<PacketHook>,
+    ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
+        let target = MuxTarget::Net {
+            url: url.to_string(),
+            format: format.map(str::to_string),
+            open_policy: open_policy.clone(),
+            reconnect: reconnect.cloned(),
+        };
+        Self::create_mux_to_target(state, target, primary_index, output, hook).await
+    }
+
+    /// Plan, start the transcoders for and spawn one multi-stream mux.
+    async fn create_mux_to_target(
+        state: &mut BusState,
+        target: MuxTarget,
         primary_index: usize,
         output: &OutputConfig,
+        hook: Option<PacketHook>,
     ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
+        let input = &output.input_id;
         let plan = Self::build_mux_plan(state, primary_index, output)?;
-        Self::start_mux_transcoders(state, &plan).await?;
-        Self::spawn_multi_stream_mux(
-            state,
-            MuxTarget::Net {
-                url: url.to_string(),
-                format: format.map(str::to_string),
-            },
-            plan,
-        )
-        .await
+        Self::start_mux_transcoders(state, &output.id, input, &plan).await?;
+        let flush_every = output.flush_every();
+        Self::spawn_multi_stream_mux(state, &output.id, input, target, plan, flush_every, hook)
+            .await
     }
 
-    /// Plan the streams a File/Net output muxes and whether each is copied or
+    /// Plan the streams a File/Net/Hls output muxes and whether each is copied or
     /// transcoded. The primary (`av_type`) stream uses `output.encode`; the
     /// audio stream carried via `include_audio` uses `output.audio_encode`.
     /// A stream is transcoded when its encode config differs from the input
@@ -414,25 +1386,47 @@ impl Bus {
         primary_index: usize,
         output: &OutputConfig,
     ) -> anyhow::Result<Vec<MuxPlanEntry>> {
-        let primary = state
-            .input_streams
+        let streams = &state.input(&output.input_id)?.streams;
+        let primary = streams
             .iter()
             .find(|s| s.index() == primary_index)
             .ok_or(anyhow::anyhow!("no matching stream in input"))?;
-        let mut plan = vec![Self::plan_entry(primary, output.encode.as_ref())];
+        let mut plan = vec![Self::plan_entry(
+            primary,
+            output.encode.as_ref(),
+            output.rotation(primary),
+        )];
 
-        if output.include_audio
-            && primary.is_video()
-            && let Some(audio) = state.input_streams.iter().find(|s| s.is_audio())
-        {
-            plan.push(Self::plan_entry(audio, output.audio_encode.as_ref()));
+        for stream in Self::carried_streams(streams, primary, output) {
+            let encode = output.audio_encode.as_ref().filter(|_| stream.is_audio());
+            plan.push(Self::plan_entry(stream, encode, 0));
         }
         Ok(plan)
     }
 
-    fn plan_entry(stream: &AvStream, encode: Option<&EncodeConfig>) -> MuxPlanEntry {
+    /// The streams a File/Net/Hls/Segments output muxes besides `primary`:
+    /// the audio stream with `include_audio`, every other stream of the input
+    /// for [`OutputAvType::All`].
+    fn carried_streams<'a>(
+        streams: &'a [AvStream],
+        primary: &AvStream,
+        output: &OutputConfig,
+    ) -> Vec<&'a AvStream> {
+        if output.av_type == OutputAvType::All {
+            return streams
+                .iter()
+                .filter(|s| s.index() != primary.index())
+                .collect();
+        }
+        if output.include_audio && primary.is_video() {
+            return streams.iter().find(|s| s.is_audio()).into_iter().collect();
+        }
+        Vec::new()
+    }
+
+    fn plan_entry(stream: &AvStream, encode: Option<&EncodeConfig>, rotation: u32) -> MuxPlanEntry {
         let input_codec = stream.parameters().id();
-        let transcode = encode.is_some_and(|e| Self::encode_needed(stream, e));
+        let transcode = encode.is_some_and(|e| rotation != 0 || Self::encode_needed(stream, e));
 
         let codec_id = if transcode {
             encode
@@ -446,20 +1440,35 @@ impl Bus {
             transcode,
             encode: if transcode { encode.cloned() } else { None },
             codec_id,
+            rotation: if transcode { rotation } else { 0 },
         }
     }
 
-    /// Start a decoder + encoder task for each transcoded stream in the plan.
+    /// Start a decoder + encoder task for each transcoded stream in the plan
+    /// of output `id`.
     async fn start_mux_transcoders(
         state: &mut BusState,
+        id: &str,
+        input: &str,
         plan: &[MuxPlanEntry],
     ) -> anyhow::Result<()> {
         // File/Net transcode must be lossless (no dropped frames), or audio/video
         // gaps and A/V drift appear when a fast source (e.g. a file) is decoded
         // in a burst. Backpressure is a no-op for realtime sources.
         for entry in plan.iter().filter(|e| e.transcode) {
-            Self::start_decoder_task(state, entry.input_index, true).await?;
-            Self::start_encoder_task(state, entry.input_index, entry.encode.as_ref(), true).await?;
+            if !Self::encodes_packets(state, input, entry.input_index) {
+                Self::start_decoder_task(state, input, entry.input_index, true, Some(id)).await?;
+            }
+            Self::start_encoder_task(
+                state,
+                input,
+                entry.input_index,
+                entry.encode.as_ref(),
+                entry.rotation,
+                true,
+                id,
+            )
+            .await?;
         }
         Ok(())
     }
@@ -467,41 +1476,29 @@ impl Bus {
     /// Build the muxer and spawn the task that merges every planned stream —
     /// copied input packets plus transcoded encoder packets — into one
     /// container. Each output track keeps its input stream index so the muxer's
-    /// index-keyed mapping stays unambiguous.
+    /// index-keyed mapping stays unambiguous. A lazy `Net` target is opened
+    /// by the task instead (see [`OpenPolicy::Lazy`]).
     async fn spawn_multi_stream_mux(
         state: &mut BusState,
+        id: &str,
+        input: &str,
         target: MuxTarget,
         plan: Vec<MuxPlanEntry>,
+        flush_every: Option<std::time::Duration>,
+        mut hook: Option<PacketHook>,
     ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
-        let (mut output, label) = match &target {
-            MuxTarget::File(path) => (AvOutput::new(path, None, None)?, path.clone()),
-            MuxTarget::Net { url, format } => {
-                // RTSP output often needs rtsp_transport=tcp for avio_open2.
-                let options = match format.as_deref() {
-                    Some("rtsp") => {
-                        let mut opts = Dictionary::new();
-                        opts.set("rtsp_transport", "tcp");
-                        Some(opts)
-                    }
-                    _ => None,
-                };
-                (
-                    AvOutput::new(url, format.as_deref(), options).map_err(|e| {
-                        anyhow::anyhow!("mux AvOutput::new(url={:?}): {:?}", url, e)
-                    })?,
-                    url.clone(),
-                )
-            }
-        };
-
-        // Add one output stream per planned stream; collect the packet sources.
+        // Resolve the output stream of every planned stream; collect the
+        // packet sources.
+        let mut out_streams: Vec<AvStream> = Vec::new();
         let mut copied_indices: HashSet<usize> = HashSet::new();
         let mut enc_receivers: Vec<(usize, RawPacketReceiver)> = Vec::new();
-        let mut primary_av: Option<AvStream> = None;
+        // The copied audio stream, when its holes are to be closed.
+        let mut gaps: Option<(usize, GapFiller)> = None;
 
+        let source = state.input(input)?;
         for entry in &plan {
-            let input_stream = state
-                .input_streams
+            let input_stream = source
+                .streams
                 .iter()
                 .find(|s| s.index() == entry.input_index)
                 .ok_or(anyhow::anyhow!("no matching stream in input"))?
@@ -510,9 +1507,9 @@ impl Bus {
                 // Use the encoder's real output params (rate/channels/dims +
                 // extradata), captured when its task started, so the muxed
                 // header matches the transcoded packets.
-                state
+                source
                     .encoder_output_streams
-                    .get(&entry.input_index)
+                    .get(&(entry.input_index, entry.encode.clone(), entry.rotation))
                     .cloned()
                     .ok_or_else(|| {
                         anyhow::anyhow!(
@@ -521,16 +1518,21 @@ impl Bus {
                         )
                     })?
             } else {
+                if input_stream.is_audio()
+                    && let Some(config) = state.options.audio_gap_fill
+                {
+                    gaps = Some((
+                        entry.input_index,
+                        GapFiller::new(input_stream.time_base(), config),
+                    ));
+                }
                 input_stream
             };
-            output.add_stream(&out_stream)?;
-            if primary_av.is_none() {
-                primary_av = Some(out_stream.clone());
-            }
+            out_streams.push(out_stream);
             if entry.transcode {
-                let recv = state
+                let recv = source
                     .encoder_tasks
-                    .get(&entry.input_index)
+                    .get(&(entry.input_index, entry.encode.clone(), entry.rotation))
                     .ok_or(anyhow::anyhow!("encoder task not found"))?
                     .subscribe();
                 enc_receivers.push((entry.input_index, recv));
@@ -538,22 +1540,101 @@ impl Bus {
                 copied_indices.insert(entry.input_index);
             }
         }
-        let primary_av = primary_av.ok_or(anyhow::anyhow!("mux plan is empty"))?;
+        let primary_av = out_streams
+            .first()
+            .cloned()
+            .ok_or(anyhow::anyhow!("mux plan is empty"))?;
+
+        let label = target.label().to_string();
+        let key = KeyStream {
+            index: plan[0].input_index,
+            codec: primary_av.parameters().id(),
+        };
+        let mut lazy = match &target {
+            MuxTarget::Net {
+                open_policy: OpenPolicy::Lazy { retry },
+                ..
+            } => Some(LazyOpen::new(retry.clone(), key)),
+            _ => None,
+        };
+        // A segmented target opens its first file at the first keyframe.
+        let mut segments = match &target {
+            MuxTarget::Segments {
+                dir,
+                pattern,
+                segment_seconds,
+            } => Some(Segmenter::new(
+                dir,
+                pattern,
+                *segment_seconds,
+                key.index,
+                Some(key.codec),
+            )?),
+            _ => None,
+        };
+        // The hook moves into each output the task opens.
+        let output =
+            if lazy.is_some() || segments.is_some() {
+                None
+            } else {
+                let mut output = open_mux_target(&target, &out_streams, flush_every, false)
+                    .map_err(|e| AddOutputError::MuxOpen {
+                        url: target.label().to_string(),
+                        cause: format!("{e:#}"),
+                    })?;
+                output.set_packet_hook(hook.take());
+                Some(output)
+            };
 
-        let input_receiver = state
-            .input_task
+        // Copied packets come from the input. One copied stream gets its own
+        // channel; several share the all-streams one, which keeps them in the
+        // demuxer's interleaved order. With none copied the encoders end the
+        // mux on their own.
+        let input = source
+            .task
             .as_ref()
-            .ok_or(anyhow::anyhow!("input task not found"))?
-            .subscribe();
+            .ok_or(anyhow::anyhow!("input task not found"))?;
+        let input_receiver = match copied_indices.len() {
+            0 => None,
+            1 => copied_indices
+                .iter()
+                .next()
+                .map(|i| input.subscribe_stream(*i)),
+            _ => Some(input.subscribe()),
+        };
+        let events = state.events.clone();
+        let id = id.to_string();
+        let watch = state.watchdog.clone().map(|watchdog| {
+            let progress = watchdog.register(TaskKind::Mux, id.clone());
+            (watchdog, progress)
+        });
+        let done = state.teardown.outputs.enter();
+        let stopped = state.output_token(&id);
+        let counters = state.output_counters(&id);
+        let _ = events.send(BusEvent::OutputStarted { id: id.clone() });
 
-        tokio::spawn(async move {
+        crate::worker::spawn_task("bus-mux", async move {
+            let _done = done;
+            let writes = MuxWriteState {
+                id: &id,
+                label: &label,
+                target: &target,
+                key,
+                events: &events,
+                watch,
+                stalls: Cell::new(0),
+                counters,
+            };
+            if let Some(output) = &output {
+                writes.watch(output);
+            }
             // One MuxSignal stream per source. A source's channel may stay open
             // after its logical end (the input/encoder tasks keep a sender), so
             // termination is driven by the EOF *signal* (one per source), not by
             // channel close.
             let mut sources: Vec<Pin<Box<dyn Stream<Item = MuxSignal> + Send>>> = Vec::new();
             let copied = Arc::new(copied_indices);
-            {
+            if let Some(input_receiver) = input_receiver {
                 let copied = copied.clone();
                 let s = BroadcastStream::new(input_receiver).filter_map(move |r| {
                     let copied = copied.clone();
@@ -563,8 +1644,12 @@ impl Bus {
                                 Some(MuxSignal::Packet(p.index(), p))
                             }
                             Ok(RawPacketCmd::Data(_)) => None, // packet for a transcoded stream
+                            Ok(RawPacketCmd::Reconnected) => None,
                             Ok(RawPacketCmd::EOF) => Some(MuxSignal::Eof),
-                            Err(_) => None, // Lagged / Closed
+                            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                                tracing::warn!("mux: input lagged, lost {} packets", n);
+                                Some(MuxSignal::Lagged(n))
+                            }
                         }
                     }
                 });
@@ -574,8 +1659,12 @@ impl Bus {
                 let s = BroadcastStream::new(recv).filter_map(move |r| async move {
                     match r {
                         Ok(RawPacketCmd::Data(p)) => Some(MuxSignal::Packet(idx, p)),
+                        Ok(RawPacketCmd::Reconnected) => None,
                         Ok(RawPacketCmd::EOF) => Some(MuxSignal::Eof),
-                        Err(_) => None,
+                        Err(BroadcastStreamRecvError::Lagged(n)) => {
+                            tracing::warn!("mux: encoder {} lagged, lost {} packets", idx, n);
+                            Some(MuxSignal::Lagged(n))
+                        }
                     }
                 });
                 sources.push(Box::pin(s));
@@ -583,190 +1672,434 @@ impl Bus {
 
             let total_sources = sources.len();
             let mut eofs = 0usize;
-            let mut merged = futures::stream::select_all(sources);
+            let mut inputs_done = false;
+            // Forcing the output stage ends the sources: the file is finished
+            // with what it has.
+            let mut merged = futures::stream::select_all(sources)
+                .take_until(Box::pin(stopped.clone().cancelled_owned()));
             let mut output = output;
-            while let Some(sig) = merged.next().await {
+            loop {
+                // A lazy target holding a keyframe connects when its next
+                // attempt is due, and keeps buffering until then.
+                let mut retry_at = None;
+                // A write of the held packets that failed on the new connection.
+                let mut broke = None;
+                if output.is_none()
+                    && let Some(lazy) = lazy.as_mut()
+                    && lazy.ready()
+                {
+                    if tokio::time::Instant::now() < lazy.next_attempt {
+                        retry_at = Some(lazy.next_attempt);
+                    } else {
+                        match lazy.attempt(&label, || {
+                            open_mux_target(&target, &out_streams, flush_every, true)
+                        }) {
+                            Ok(Some(mut opened)) => {
+                                if std::mem::take(&mut lazy.reconnecting) {
+                                    let _ = events.send(BusEvent::OutputReconnected {
+                                        id: id.clone(),
+                                        attempts: lazy.attempts,
+                                    });
+                                }
+                                opened.set_packet_hook(hook.take());
+                                writes.watch(&opened);
+                                let mut failed = None;
+                                for (idx, packet) in lazy.pending.drain_indexed() {
+                                    if let Err(e) = writes.write(&mut opened, idx, packet) {
+                                        failed = Some(e);
+                                        break;
+                                    }
+                                }
+                                output = Some(opened);
+                                broke = failed;
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                tracing::error!("mux {}: giving up: {:#}", label, e);
+                                let _ = events.send(BusEvent::OutputFailed {
+                                    id: id.clone(),
+                                    error: format!("{:#}", e),
+                                    kind: Some(WriteErrorKind::Network),
+                                });
+                                return;
+                            }
+                        }
+                        if broke.is_none() {
+                            continue;
+                        }
+                    }
+                }
+                if let Some(e) = broke {
+                    if !writes.on_error(e, &mut output, &mut lazy, &mut hook) {
+                        return;
+                    }
+                    continue;
+                }
+                let sig = match retry_at {
+                    // No connection to wait for once forced.
+                    Some(_) if inputs_done && stopped.is_cancelled() => break,
+                    Some(at) if inputs_done => {
+                        tokio::time::sleep_until(at).await;
+                        continue;
+                    }
+                    Some(at) => tokio::select! {
+                        sig = merged.next() => sig,
+                        _ = tokio::time::sleep_until(at) => continue,
+                    },
+                    None if inputs_done => break,
+                    None => merged.next().await,
+                };
                 match sig {
-                    MuxSignal::Packet(idx, packet) => {
-                        if let Err(e) = output.write_packet(idx, packet) {
-                            log::error!("mux write_packet error: {:#?}", e);
+                    Some(MuxSignal::Packet(idx, packet)) => {
+                        let packet = match gaps.as_mut() {
+                            Some((audio, filler)) if *audio == idx => {
+                                let (ready, gap) = filler.push_packet(packet);
+                                if let Some(event) = gap.and_then(|gap| filler.event(idx, gap)) {
+                                    let _ = events.send(event);
+                                }
+                                match ready {
+                                    Some(packet) => packet,
+                                    None => continue,
+                                }
+                            }
+                            _ => packet,
+                        };
+                        if let Some(segments) = segments.as_mut() {
+                            match segments.step(idx, &packet) {
+                                Step::Skip => continue,
+                                Step::Write => {}
+                                Step::Open(path) => {
+                                    if let Some(mut done) = output.take() {
+                                        hook = done.take_packet_hook();
+                                        let closed = segments.take_closed();
+                                        match done.finish() {
+                                            Ok(()) => {
+                                                if let Some(closed) = closed {
+                                                    let _ = events.send(closed_event(&id, closed));
+                                                }
+                                            }
+                                            Err(e) => {
+                                                if !writes
+                                                    .on_error(e, &mut None, &mut lazy, &mut hook)
+                                                {
+                                                    return;
+                                                }
+                                            }
+                                        }
+                                    }
+                                    match open_segment(&path, &out_streams, flush_every) {
+                                        Ok(mut next) => {
+                                            tracing::info!(
+                                                "mux {}: segment {}",
+                                                label,
+                                                path.display()
+                                            );
+                                            next.set_packet_hook(hook.take());
+                                            output = Some(next);
+                                        }
+                                        Err(e) => {
+                                            tracing::error!("mux {}: {:#}", label, e);
+                                            let _ = events.send(BusEvent::OutputFailed {
+                                                id: id.clone(),
+                                                error: format!("{:#}", e),
+                                                kind: Some(WriteErrorKind::Io),
+                                            });
+                                            return;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        let written = match (output.as_mut(), lazy.as_mut()) {
+                            (Some(output), _) => writes.write(output, idx, packet),
+                            (None, Some(lazy)) => {
+                                lazy.hold(idx, packet);
+                                Ok(())
+                            }
+                            (None, None) => Ok(()),
+                        };
+                        if let Err(e) = written
+                            && !writes.on_error(e, &mut output, &mut lazy, &mut hook)
+                        {
+                            return;
                         }
                     }
-                    MuxSignal::Eof => {
+                    Some(MuxSignal::Eof) => {
                         eofs += 1;
-                        if eofs >= total_sources {
-                            break;
-                        }
+                        inputs_done = eofs >= total_sources;
                     }
+                    Some(MuxSignal::Lagged(count)) => {
+                        writes.counters.lagged(count);
+                        let _ = events.send(BusEvent::PacketLagged {
+                            id: id.clone(),
+                            count,
+                        });
+                    }
+                    None => inputs_done = true,
                 }
             }
-            if let Err(e) = output.finish() {
-                log::error!(
+            // The audio packet held back for the next one.
+            if let (Some(output), Some((idx, filler))) = (output.as_mut(), gaps.as_mut())
+                && let Some(packet) = filler.flush()
+                && let Err(e) = writes.write(output, *idx, packet)
+            {
+                tracing::warn!("mux {}: writing the last audio packet: {:#}", label, e);
+            }
+            if let Some(mut output) = output
+                && let Err(e) = output.finish()
+            {
+                tracing::error!(
                     "mux finish error: {:#?}\nbacktrace:\n{}",
                     e,
                     Backtrace::capture()
                 );
+                let _ = events.send(BusEvent::OutputFailed {
+                    id: id.clone(),
+                    error: e.to_string(),
+                    kind: Some(e.kind),
+                });
+            } else {
+                if let Some(closed) = segments.as_mut().and_then(Segmenter::finish) {
+                    let _ = events.send(closed_event(&id, closed));
+                }
+                let _ = events.send(BusEvent::OutputFinished { id: id.clone() });
             }
-            log::info!("mux finished: {}", label);
+            tracing::info!("mux finished: {}", label);
         });
 
-        Ok((
-            primary_av,
-            Box::pin(futures::stream::empty::<Option<VideoFrame>>()),
-        ))
+        Ok((primary_av, Box::pin(futures::stream::empty::<FrameEvent>())))
     }
 
     async fn create_encoded_output_stream(
         state: &mut BusState,
+        input: &str,
         input_stream_index: usize,
+        encode: Option<&EncodeConfig>,
+        rotation: u32,
     ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
-        let av = state
-            .input_streams
+        let source = state.input(input)?;
+        let av = source
+            .streams
             .iter()
             .find(|s| s.index() == input_stream_index)
             .ok_or(anyhow::anyhow!("stream not found"))?;
-        let encoder_receiver = state
+        let encoder_receiver = source
             .encoder_tasks
-            .get(&input_stream_index)
+            .get(&(input_stream_index, encode.cloned(), rotation))
             .ok_or(anyhow::anyhow!("encoder task not found"))?
             .subscribe();
 
         let stream = BroadcastStream::new(encoder_receiver).filter_map(|r| async move {
             match r {
-                Ok(RawPacketCmd::Data(packet)) => Some(Some(VideoFrame::from(packet))),
-                Ok(RawPacketCmd::EOF) => Some(None),
-                Err(_) => None,
+                Ok(RawPacketCmd::Data(packet)) => Some(FrameEvent::Frame(VideoFrame::from(packet))),
+                Ok(RawPacketCmd::EOF) => Some(FrameEvent::Eof),
+                Ok(RawPacketCmd::Reconnected) => None,
+                Err(BroadcastStreamRecvError::Lagged(n)) => Some(FrameEvent::Lagged(n)),
             }
         });
 
         Ok((av.clone(), Box::pin(stream)))
     }
 
-    /// Mux encoded packets (from encoder_tasks) into format (e.g. "h264"). Used when input
-    /// was not already that codec and encoder was started.
+    /// Mux encoded packets (from encoder_tasks) into format (e.g. "h264"), with the stream
+    /// the encoder reported when it started. Used when input was not already that codec and
+    /// encoder was started.
+    #[allow(clippy::too_many_arguments)]
     async fn create_mux_output_stream_from_encoder(
         state: &mut BusState,
+        id: &str,
         format: &str,
+        input: &str,
         input_stream_index: usize,
+        encode: Option<&EncodeConfig>,
+        rotation: u32,
+        flush_every: Option<std::time::Duration>,
+        hook: Option<PacketHook>,
     ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
-        let mut encoder_receiver = state
+        let source = state.input(input)?;
+        let mut encoder_receiver = source
             .encoder_tasks
-            .get(&input_stream_index)
+            .get(&(input_stream_index, encode.cloned(), rotation))
             .ok_or(anyhow::anyhow!("encoder task not found"))?
             .subscribe();
 
-        let input_stream = state
-            .input_streams
-            .iter()
-            .find(|s| s.index() == input_stream_index)
-            .ok_or(anyhow::anyhow!("no matching stream in input"))?;
-
-        let codec_id = match format {
-            "h264" => ffmpeg_next::codec::Id::H264,
-            "hevc" | "h265" => ffmpeg_next::codec::Id::HEVC,
-            "aac" | "adts" => ffmpeg_next::codec::Id::AAC,
-            "opus" => ffmpeg_next::codec::Id::OPUS,
-            _ => {
-                return Err(anyhow::anyhow!(
-                    "unsupported mux format for encoder output: {}",
-                    format
-                ));
-            }
-        };
-        let encoder_output_stream = AvStream::for_encoder_output(input_stream, codec_id);
+        // What the encoder actually produces (codec, size, extradata), not
+        // what the muxer's name suggests.
+        let encoder_output_stream = source
+            .encoder_output_streams
+            .get(&(input_stream_index, encode.cloned(), rotation))
+            .cloned()
+            .ok_or_else(|| {
+                anyhow::anyhow!("no encoder output stream for input {}", input_stream_index)
+            })?;
 
         let mut stream = AvOutputStream::new(format)?;
+        stream.set_flush_every(flush_every);
         stream.add_stream(&encoder_output_stream)?;
-        let (writer, reader) = stream.into_split();
+        let (mut writer, reader) = stream.into_split();
+        writer.set_packet_hook(hook);
 
-        tokio::spawn(async move {
+        let events = state.events.clone();
+        let id = id.to_string();
+        let done = state.teardown.outputs.enter();
+        let forced = state.output_token(&id);
+        let counters = state.output_counters(&id);
+        crate::worker::spawn_task("bus-mux-stream", async move {
+            let _done = done;
             let mut writer = writer;
+            let mut stopped = false;
             loop {
-                match encoder_receiver.recv().await {
+                let received = tokio::select! {
+                    received = encoder_receiver.recv() => received,
+                    _ = forced.cancelled() => break,
+                };
+                match received {
                     Ok(cmd) => match cmd {
                         RawPacketCmd::Data(mut packet) => {
                             packet.get_mut().set_stream(0);
-                            if let Err(e) = writer.write_packet(packet) {
-                                log::error!("mux write_packet error: {}", e.to_string());
+                            let size = packet.size();
+                            match writer.write_packet(packet) {
+                                Ok(()) => counters.wrote(size, true),
+                                Err(e) => {
+                                    if stream_write_failed(&id, e, &events) {
+                                        stopped = true;
+                                        break;
+                                    }
+                                }
                             }
+                            writer.deliver().await;
                         }
+                        RawPacketCmd::Reconnected => {}
                         RawPacketCmd::EOF => break,
                     },
                     Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        log::warn!("mux encoder_receiver lagged, dropped {} messages", n);
+                        tracing::warn!("mux encoder_receiver lagged, dropped {} messages", n);
+                        counters.lagged(n);
+                        let _ = events.send(BusEvent::PacketLagged {
+                            id: id.clone(),
+                            count: n,
+                        });
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
                 }
             }
             if let Err(e) = writer.finish() {
-                log::error!(
+                tracing::error!(
                     "mux finish error: {:#?}\nbacktrace:\n{}",
                     e,
                     Backtrace::capture()
                 );
+                if !stopped {
+                    let _ = events.send(BusEvent::OutputFailed {
+                        id: id.clone(),
+                        error: e.to_string(),
+                        kind: Some(e.kind),
+                    });
+                }
             }
-            log::info!("mux stream finished");
+            // The reader gets every byte up to the trailer before it ends.
+            writer.deliver().await;
+            tracing::info!("mux stream finished");
         });
 
         Ok((
             encoder_output_stream.clone(),
-            Box::pin(reader.map(|pkg| Some(VideoFrame::from(pkg)))),
+            Box::pin(reader.map(|pkg| FrameEvent::Frame(VideoFrame::from(pkg)))),
         ))
     }
 
     async fn create_mux_output_stream(
         state: &mut BusState,
+        id: &str,
         format: &str,
+        input: &str,
         input_stream_index: usize,
+        flush_every: Option<std::time::Duration>,
+        hook: Option<PacketHook>,
     ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
-        let mut input_receiver = state
-            .input_task
+        let source = state.input(input)?;
+        let mut input_receiver = source
+            .task
             .as_ref()
             .ok_or(anyhow::anyhow!("input task not found"))?
-            .subscribe();
+            .subscribe_stream(input_stream_index);
 
-        let target_stream = state
-            .input_streams
+        let target_stream = source
+            .streams
             .iter()
             .find(|s| s.index() == input_stream_index)
-            .ok_or(anyhow::anyhow!("no matching stream in input"))?;
-        let target_stream_index = target_stream.index();
+            .ok_or(anyhow::anyhow!("no matching stream in input"))?
+            .clone();
         let mut stream = AvOutputStream::new(format)?;
+        stream.set_flush_every(flush_every);
         stream.add_stream(&target_stream)?;
-        let (writer, reader) = stream.into_split();
+        let (mut writer, reader) = stream.into_split();
+        writer.set_packet_hook(hook);
 
-        tokio::spawn(async move {
+        let events = state.events.clone();
+        let id = id.to_string();
+        let done = state.teardown.outputs.enter();
+        let forced = state.output_token(&id);
+        let counters = state.output_counters(&id);
+        crate::worker::spawn_task("bus-mux-stream", async move {
+            let _done = done;
             let mut writer = writer;
+            let mut stopped = false;
             loop {
-                match input_receiver.recv().await {
+                let received = tokio::select! {
+                    received = input_receiver.recv() => received,
+                    _ = forced.cancelled() => break,
+                };
+                match received {
                     Ok(RawPacketCmd::Data(packet)) => {
-                        if packet.index() == target_stream_index {
-                            if let Err(e) = writer.write_packet(packet) {
-                                log::error!("mux write_packet error: {}", e.to_string());
+                        let size = packet.size();
+                        match writer.write_packet(packet) {
+                            Ok(()) => counters.wrote(size, true),
+                            Err(e) => {
+                                if stream_write_failed(&id, e, &events) {
+                                    stopped = true;
+                                    break;
+                                }
                             }
                         }
+                        writer.deliver().await;
                     }
+                    Ok(RawPacketCmd::Reconnected) => continue,
                     Ok(RawPacketCmd::EOF) => break,
                     Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        log::warn!("mux input_receiver lagged, dropped {} messages", n);
+                        tracing::warn!("mux input_receiver lagged, dropped {} messages", n);
+                        counters.lagged(n);
+                        let _ = events.send(BusEvent::PacketLagged {
+                            id: id.clone(),
+                            count: n,
+                        });
                         continue;
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
                 }
             }
             if let Err(e) = writer.finish() {
-                log::error!(
+                tracing::error!(
                     "mux finish error: {:#?}\nbacktrace:\n{}",
                     e,
                     Backtrace::capture()
                 );
+                if !stopped {
+                    let _ = events.send(BusEvent::OutputFailed {
+                        id: id.clone(),
+                        error: e.to_string(),
+                        kind: Some(e.kind),
+                    });
+                }
             }
-            log::info!("mux stream finished");
+            // The reader gets every byte up to the trailer before it ends.
+            writer.deliver().await;
+            tracing::info!("mux stream finished");
         });
 
         Ok((
-            target_stream.clone(),
-            Box::pin(reader.map(|pkg| Some(VideoFrame::from(pkg)))),
+            target_stream,
+            Box::pin(reader.map(|pkg| FrameEvent::Frame(VideoFrame::from(pkg)))),
         ))
     }
 
@@ -774,48 +2107,169 @@ impl Bus {
     /// requested stream as a `VideoFrame`. No decoder, no encoder, no muxer —
     /// the packet bytes are exactly what came out of the input demuxer
     /// (raw codec frames, no container framing). Suitable for codec-aware
-    /// downstream consumers like ZLMediaKit.
+    /// downstream consumers like ZLMediaKit. A packet hook sees each packet
+    /// first; one that panics ends the stream.
+    /// A Demuxed output: the input's packets of `input_stream_index`, or with
+    /// `encode` those of its encoder (see [`Self::packet_route`]). A
+    /// `renegotiable` output also takes new routes from
+    /// [`Bus::renegotiate_output`]: it stays on the current one up to its next
+    /// keyframe, then continues from the first keyframe of the new one past
+    /// the last packet it sent, so the stream never goes back in time.
     async fn create_demuxed_output_stream(
         state: &mut BusState,
+        id: &str,
+        input: &str,
         input_stream_index: usize,
+        encode: Option<&EncodeConfig>,
+        renegotiable: bool,
+        annexb: bool,
+        mut hook: Option<PacketHook>,
     ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
-        let mut input_receiver = state
-            .input_task
-            .as_ref()
-            .ok_or(anyhow::anyhow!("input task not found"))?
-            .subscribe();
+        let (target_stream, mut route) =
+            Self::packet_route(state, id, input, input_stream_index, encode).await?;
+        if annexb {
+            route = route.with_annexb(&target_stream);
+        }
+        let time_base = target_stream.time_base();
+        let mut control = if renegotiable {
+            let (control_tx, control_rx) = tokio::sync::mpsc::unbounded_channel();
+            state.renegotiations.insert(id.to_string(), control_tx);
+            Some(control_rx)
+        } else {
+            None
+        };
 
-        let target_stream = state
-            .input_streams
-            .iter()
-            .find(|s| s.index() == input_stream_index)
-            .ok_or(anyhow::anyhow!("no matching stream in input"))?
-            .clone();
-        let target_stream_index = target_stream.index();
+        /// What woke the output's loop.
+        enum Wake {
+            Route(Option<PacketRoute>),
+            Current(Result<RawPacketCmd, tokio::sync::broadcast::error::RecvError>),
+            Next(Result<RawPacketCmd, tokio::sync::broadcast::error::RecvError>),
+        }
 
-        let (tx, rx) = tokio::sync::mpsc::channel::<Option<VideoFrame>>(256);
-        tokio::spawn(async move {
+        let (tx, rx) = tokio::sync::mpsc::channel::<FrameEvent>(256);
+        let events = state.events.clone();
+        let id = id.to_string();
+        let done = state.teardown.outputs.enter();
+        let stopped = state.output_token(&id);
+        let counters = state.output_counters(&id);
+        crate::worker::spawn_task("bus-demuxed", async move {
+            let _done = done;
+            // The route being switched to, and whether the current one has
+            // reached the keyframe it stops at.
+            let mut next: Option<PacketRoute> = None;
+            let mut cut = false;
+            let mut last_pts: Option<i64> = None;
             loop {
-                match input_receiver.recv().await {
-                    Ok(RawPacketCmd::Data(packet)) => {
-                        if packet.index() == target_stream_index {
-                            if tx.send(Some(VideoFrame::from(packet))).await.is_err() {
-                                break;
-                            }
+                let wake = tokio::select! {
+                    new = async {
+                        match control.as_mut() {
+                            Some(control) => control.recv().await,
+                            None => std::future::pending().await,
+                        }
+                    } => Wake::Route(new),
+                    cmd = route.receiver.recv() => Wake::Current(cmd),
+                    cmd = async {
+                        match next.as_mut() {
+                            Some(next) => next.receiver.recv().await,
+                            None => std::future::pending().await,
+                        }
+                    } => Wake::Next(cmd),
+                    _ = stopped.cancelled() => break,
+                };
+                let packet = match wake {
+                    Wake::Route(Some(new)) => {
+                        next = Some(new);
+                        continue;
+                    }
+                    Wake::Route(None) => {
+                        control = None;
+                        continue;
+                    }
+                    Wake::Current(Ok(RawPacketCmd::Data(packet))) => {
+                        // Cut at a keyframe while switching; resumed at one
+                        // if the new route ends before it could take over.
+                        if packet.is_key() && last_pts.is_some() {
+                            cut = next.is_some();
+                        }
+                        if cut {
+                            continue;
+                        }
+                        route.rescale(packet, time_base)
+                    }
+                    Wake::Next(Ok(RawPacketCmd::Data(packet))) => {
+                        let Some(candidate) = next.as_ref() else {
+                            continue;
+                        };
+                        if !packet.is_key() {
+                            continue;
+                        }
+                        let packet = candidate.rescale(packet, time_base);
+                        if last_pts.is_some_and(|last| packet.pts().unwrap_or(0) <= last) {
+                            continue;
                         }
+                        route = next.take().expect("checked above");
+                        cut = false;
+                        tracing::info!("demuxed {}: switched to {}", id, route.codec.name());
+                        let _ = events.send(BusEvent::OutputRenegotiated {
+                            id: id.clone(),
+                            codec: route.codec.name().to_string(),
+                            transcoded: route.transcoded,
+                        });
+                        packet
                     }
-                    Ok(RawPacketCmd::EOF) => {
-                        let _ = tx.send(None).await;
+                    Wake::Current(Ok(RawPacketCmd::Reconnected))
+                    | Wake::Next(Ok(RawPacketCmd::Reconnected)) => continue,
+                    Wake::Current(Ok(RawPacketCmd::EOF)) | Wake::Next(Ok(RawPacketCmd::EOF)) => {
+                        let _ = tx.send(FrameEvent::Eof).await;
                         break;
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        log::warn!("demuxed input_receiver lagged, dropped {} messages", n);
+                    Wake::Current(Err(tokio::sync::broadcast::error::RecvError::Lagged(n)))
+                    | Wake::Next(Err(tokio::sync::broadcast::error::RecvError::Lagged(n))) => {
+                        tracing::warn!("demuxed input_receiver lagged, dropped {} messages", n);
+                        counters.lagged(n);
+                        let _ = events.send(BusEvent::PacketLagged {
+                            id: id.clone(),
+                            count: n,
+                        });
+                        if tx.send(FrameEvent::Lagged(n)).await.is_err() {
+                            break;
+                        }
                         continue;
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    Wake::Current(Err(tokio::sync::broadcast::error::RecvError::Closed)) => break,
+                    Wake::Next(Err(tokio::sync::broadcast::error::RecvError::Closed)) => {
+                        next = None;
+                        continue;
+                    }
+                };
+                let applied = match hook.as_mut() {
+                    Some(f) => hook::apply(f, packet),
+                    None => Ok(Some(packet)),
+                };
+                let packet = match applied {
+                    Ok(Some(packet)) => packet,
+                    Ok(None) => continue,
+                    Err(message) => {
+                        let error = WriteError::hook(format!("packet hook: {message}"));
+                        tracing::error!("demuxed {}: {}", id, error);
+                        let _ = events.send(BusEvent::OutputFailed {
+                            id: id.clone(),
+                            error: error.to_string(),
+                            kind: Some(error.kind),
+                        });
+                        let _ = tx.send(FrameEvent::Error(error.to_string())).await;
+                        break;
+                    }
+                };
+                last_pts = packet.pts().or(last_pts);
+                let size = packet.size();
+                let frame = FrameEvent::Frame(route.frame(packet));
+                if tx.send(frame).await.is_err() {
+                    break;
                 }
+                counters.wrote(size, true);
             }
-            log::info!("demuxed stream finished");
+            tracing::info!("demuxed stream finished");
         });
 
         Ok((
@@ -824,61 +2278,248 @@ impl Bus {
         ))
     }
 
-    async fn create_decoder_raw_output_stream(
+    /// Where a Demuxed output of input stream `input_stream_index` reads: the
+    /// input itself without `encode`, else the encoder of `encode`, started
+    /// (with its decoder) unless another output already runs it. The stream
+    /// returned describes the route's packets in the input stream's time
+    /// base, which the output rescales them to.
+    async fn packet_route(
         state: &mut BusState,
-        stream_index: usize,
-    ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
-        let av = state
-            .input_streams
+        id: &str,
+        input: &str,
+        input_stream_index: usize,
+        encode: Option<&EncodeConfig>,
+    ) -> anyhow::Result<(AvStream, PacketRoute)> {
+        let input_stream = state
+            .input(input)?
+            .streams
             .iter()
-            .find(|s| s.index() == stream_index)
-            .ok_or(anyhow::anyhow!("stream not found"))?;
-        let stream = BroadcastStream::new(
-            state
-                .decoder_tasks
-                .get(&stream_index)
-                .ok_or(anyhow::anyhow!("decoder task not found"))?
-                .subscribe(),
-        )
-        .map(|cmd| match cmd {
-            Ok(cmd) => match cmd {
-                RawFrameCmd::Data(frame) => Some(VideoFrame::try_from(frame).unwrap()),
-                RawFrameCmd::EOF => None,
-            },
-            Err(e) => {
-                log::error!(
-                    "decoder task error: {:#?}\nbacktrace:\n{}",
-                    e,
-                    Backtrace::capture()
-                );
-                None
-            }
-        });
+            .find(|s| s.index() == input_stream_index)
+            .ok_or(anyhow::anyhow!("no matching stream in input"))?
+            .clone();
+        let Some(encode) = encode else {
+            let receiver = state
+                .input(input)?
+                .task
+                .as_ref()
+                .ok_or(anyhow::anyhow!("input task not found"))?
+                .subscribe_stream(input_stream_index);
+            let route = PacketRoute::new(receiver, &input_stream, false);
+            return Ok((input_stream, route));
+        };
 
-        Ok((av.clone(), Box::pin(stream)))
+        if !Self::encodes_packets(state, input, input_stream_index) {
+            Self::start_decoder_task(state, input, input_stream_index, false, Some(id)).await?;
+        }
+        Self::start_encoder_task(state, input, input_stream_index, Some(encode), 0, false, id)
+            .await?;
+        let key: EncoderKey = (input_stream_index, Some(encode.clone()), 0);
+        let source = state.input(input)?;
+        let receiver = source
+            .encoder_tasks
+            .get(&key)
+            .ok_or(anyhow::anyhow!("encoder task not found"))?
+            .subscribe();
+        let encoded = source
+            .encoder_output_streams
+            .get(&key)
+            .ok_or(anyhow::anyhow!("encoder output stream not found"))?;
+        let route = PacketRoute::new(receiver, encoded, true);
+        let stream = AvStream::new(
+            input_stream_index,
+            encoded.parameters().clone(),
+            input_stream.time_base(),
+            input_stream.rate(),
+        );
+        Ok((stream, route))
     }
 
-    /// Ensure the input + audio decoder are running and return a subscription to
-    /// the decoded-audio broadcast. Mirrors the `OutputDest::Raw` audio path.
-    async fn subscribe_audio_internal(
+    /// What a consumer taking `acceptable` codecs gets of `input_stream`: a
+    /// copy (`None`) when its codec is one of them, else a transcode to the
+    /// first, with the encoder's defaults so outputs negotiating the same
+    /// codec share the encoder.
+    fn negotiate(
+        input_stream: &AvStream,
+        acceptable: &[ffmpeg_next::codec::Id],
+    ) -> anyhow::Result<Option<EncodeConfig>> {
+        let preferred = acceptable
+            .first()
+            .ok_or(anyhow::anyhow!("no acceptable codecs"))?;
+        if acceptable.contains(&input_stream.parameters().id()) {
+            return Ok(None);
+        }
+        Ok(Some(EncodeConfig {
+            codec: preferred.name().to_string(),
+            ..EncodeConfig::default()
+        }))
+    }
+
+    async fn renegotiate_output_internal(
         state: &mut BusState,
-    ) -> anyhow::Result<crate::frame::RawFrameReceiver> {
-        if state.input_task.is_none() && state.input_config.is_some() {
-            Self::prepare_input_task(state).await?;
+        id: &str,
+        acceptable: Vec<ffmpeg_next::codec::Id>,
+    ) -> anyhow::Result<AvStream> {
+        let output = state
+            .output_config
+            .get(id)
+            .ok_or(anyhow::anyhow!("output not found"))?;
+        if output.acceptable_codecs.is_none() {
+            return Err(anyhow::anyhow!(
+                "output was not added with acceptable codecs"
+            ));
         }
-        let audio_index = state
-            .input_streams
-            .iter()
-            .find(|s| s.is_audio())
-            .ok_or_else(|| anyhow::anyhow!("pipe has no audio stream"))?
+        let input = output.input_id.clone();
+        let input_stream = output
+            .av_type
+            .primary(&state.input(&input)?.streams)
+            .ok_or(anyhow::anyhow!("stream not found"))?;
+        let input_stream_index = input_stream.index();
+        let encode = Self::negotiate(input_stream, &acceptable)?;
+        if let Some(encode) = &encode {
+            crate::encoder::validate(encode).map_err(InvalidEncodeConfig)?;
+        }
+        let unchanged = output.encode == encode;
+        let annexb = output.annexb;
+
+        let (stream, mut route) =
+            Self::packet_route(state, id, &input, input_stream_index, encode.as_ref()).await?;
+        if annexb {
+            route = route.with_annexb(&stream);
+        }
+        if !unchanged {
+            state
+                .renegotiations
+                .get(id)
+                .ok_or(anyhow::anyhow!("output is not renegotiable"))?
+                .send(route)
+                .map_err(|_| anyhow::anyhow!("output has ended"))?;
+            tracing::info!(
+                "renegotiating to {}",
+                encode
+                    .as_ref()
+                    .map_or("a copy of the input", |e| e.codec.as_str())
+            );
+        }
+        if let Some(output) = state.output_config.get_mut(id) {
+            output.acceptable_codecs = Some(acceptable);
+            output.encode = encode;
+        }
+        Ok(stream)
+    }
+
+    async fn create_decoder_raw_output_stream(
+        state: &mut BusState,
+        input: &str,
+        stream_index: usize,
+        av_type: OutputAvType,
+        roi: Option<Rect>,
+        rotation: u32,
+        frame_queue: Option<FrameQueueConfig>,
+    ) -> anyhow::Result<(AvStream, RawOutputStream)> {
+        let source = state.input(input)?;
+        let av = source
+            .streams
+            .iter()
+            .find(|s| s.index() == stream_index)
+            .ok_or(anyhow::anyhow!("stream not found"))?;
+        // Validate the ROI against the stream up front and report the geometry
+        // the consumer will actually receive.
+        let roi = match roi {
+            Some(_) if av_type == OutputAvType::Audio => {
+                anyhow::bail!("roi only applies to video outputs")
+            }
+            Some(rect) => {
+                let (w, h, format) = Self::raw_video_params_from_parameters(av.parameters());
+                Some(rect.aligned(format, w, h)?)
+            }
+            None => None,
+        };
+        let decoder = source
+            .decoder_tasks
+            .get(&stream_index)
+            .ok_or(anyhow::anyhow!("decoder task not found"))?;
+        let rx: RawFrameSource = match frame_queue {
+            Some(config) => Box::pin(
+                decoder
+                    .subscribe_queue(config)
+                    .into_stream()
+                    .map(Ok::<_, BroadcastStreamRecvError>),
+            ),
+            None => Box::pin(BroadcastStream::new(decoder.subscribe())),
+        };
+        // The frames a range input decodes on its way from the keyframe to
+        // its start.
+        let rx: RawFrameSource = match source.start_us {
+            Some(start_us) => {
+                let start = start_us.rescale(Rational(1, 1_000_000), av.time_base());
+                Box::pin(rx.filter(move |cmd| {
+                    let pts = match cmd {
+                        Ok(RawFrameCmd::Data(RawFrame::Video(frame))) => frame.pts(),
+                        Ok(RawFrameCmd::Data(RawFrame::Audio(frame))) => frame.pts(),
+                        _ => None,
+                    };
+                    futures::future::ready(pts.is_none_or(|pts| pts >= start))
+                }))
+            }
+            None => rx,
+        };
+        let drops = state.raw_frame_drops.clone();
+        let stream = match av_type {
+            OutputAvType::Video if roi.is_none() && rotation == 0 => RawOutputStream::Video(
+                Box::pin(raw_frame_stream(rx, av_type, drops, VideoFrame::try_from)),
+            ),
+            OutputAvType::Video => RawOutputStream::Video(Box::pin(raw_frame_stream(
+                rx,
+                av_type,
+                drops,
+                move |frame| reshape_video(frame, roi, rotation),
+            ))),
+            OutputAvType::Audio => RawOutputStream::Audio(Box::pin(raw_frame_stream(
+                rx,
+                av_type,
+                drops,
+                AudioFrame::try_from,
+            ))),
+            OutputAvType::Data | OutputAvType::All => {
+                anyhow::bail!("raw and encoded outputs take one video or audio stream")
+            }
+        };
+
+        let (w, h) = match roi {
+            Some(rect) => (rect.w, rect.h),
+            None => (av.width(), av.height()),
+        };
+        let av = match rotation {
+            0 if roi.is_none() => av.clone(),
+            0 => av.clone().with_dimensions(w, h),
+            90 | 270 => av.clone().with_dimensions(h, w).with_display_matrix(None),
+            _ => av.clone().with_dimensions(w, h).with_display_matrix(None),
+        };
+        Ok((av, stream))
+    }
+
+    /// Ensure the input + audio decoder are running and return a subscription to
+    /// the decoded-audio broadcast. Mirrors the `OutputDest::Raw` audio path.
+    async fn subscribe_audio_internal(
+        state: &mut BusState,
+        input: &str,
+    ) -> anyhow::Result<crate::frame::RawFrameReceiver> {
+        Self::prepare_input_task(state, input).await?;
+        let audio_index = state
+            .input(input)?
+            .streams
+            .iter()
+            .find(|s| s.is_audio())
+            .ok_or_else(|| anyhow::anyhow!("pipe has no audio stream"))?
             .index();
-        Self::start_decoder_task(state, audio_index, false).await?;
+        Self::start_decoder_task(state, input, audio_index, false, None).await?;
         let receiver = state
+            .input(input)?
             .decoder_tasks
             .get(&audio_index)
             .ok_or_else(|| anyhow::anyhow!("audio decoder task not found after start"))?
             .subscribe();
-        Self::start_input_task(state).await?;
+        Self::start_input_task(state, input).await?;
         Ok(receiver)
     }
 
@@ -886,42 +2527,81 @@ impl Bus {
     /// the decoded-video broadcast. Mirrors `subscribe_audio_internal`.
     async fn subscribe_video_internal(
         state: &mut BusState,
+        input: &str,
     ) -> anyhow::Result<crate::frame::RawFrameReceiver> {
-        if state.input_task.is_none() && state.input_config.is_some() {
-            Self::prepare_input_task(state).await?;
-        }
+        Self::prepare_input_task(state, input).await?;
         let video_index = state
-            .input_streams
+            .input(input)?
+            .streams
             .iter()
             .find(|s| s.is_video())
             .ok_or_else(|| anyhow::anyhow!("pipe has no video stream"))?
             .index();
-        Self::start_decoder_task(state, video_index, false).await?;
+        Self::start_decoder_task(state, input, video_index, false, None).await?;
         let receiver = state
+            .input(input)?
             .decoder_tasks
             .get(&video_index)
             .ok_or_else(|| anyhow::anyhow!("video decoder task not found after start"))?
             .subscribe();
-        Self::start_input_task(state).await?;
+        Self::start_input_task(state, input).await?;
         Ok(receiver)
     }
 
     async fn add_input_internal(
         state: &mut BusState,
+        id: String,
         input: InputConfig,
         options: Option<HashMap<String, String>>,
-    ) -> anyhow::Result<()> {
-        if state.input_config.is_some() {
-            return Err(anyhow::anyhow!("input already exists"));
-        } else {
-            state.input_config = Some(input);
-            state.input_options = options;
+    ) -> anyhow::Result<Option<PushInputHandle>> {
+        if state.inputs.contains_key(&id) {
+            return Err(anyhow::anyhow!("input '{id}' already exists"));
         }
+        let mut entry = InputTaskEntry::new(input, options);
+        let handle = match &entry.config {
+            InputConfig::Push {
+                width,
+                height,
+                pixel_format,
+                frame_rate,
+                audio,
+            } => {
+                let (handle, source) =
+                    crate::push::push_input(*width, *height, *pixel_format, *frame_rate, *audio)?;
+                entry.push_source = Some(source);
+                Some(handle)
+            }
+            _ => None,
+        };
+        state.inputs.insert(id, entry);
+        Ok(handle)
+    }
 
-        if !state.output_config.is_empty() && state.input_task.is_none() {
-            Self::prepare_input_task(state).await?;
-            Self::start_input_task(state).await?;
+    /// Stop input `id` and forget it, with every task and output of its
+    /// streams. The input sends its EOF as it stops, which its decoders and
+    /// encoders flush and pass on, so its outputs finish as at the end of the
+    /// input; one never started closes their channels instead.
+    fn remove_input_internal(state: &mut BusState, id: &str) -> anyhow::Result<()> {
+        let input = state
+            .inputs
+            .remove(id)
+            .ok_or_else(|| anyhow::anyhow!("input '{id}' not found"))?;
+        if let Some(task) = &input.task {
+            task.stop();
         }
+        state
+            .output_config
+            .retain(|_, output| output.input_id != id);
+        state
+            .renegotiations
+            .retain(|output, _| state.output_config.contains_key(output));
+        state
+            .output_tokens
+            .retain(|output, _| state.output_config.contains_key(output));
+        state
+            .output_counters
+            .retain(|output, _| state.output_config.contains_key(output));
+        tracing::info!("input {} removed", id);
         Ok(())
     }
 
@@ -934,10 +2614,7 @@ impl Bus {
             let w = (*ptr).width.max(0) as u32;
             let h = (*ptr).height.max(0) as u32;
             let fmt = (*ptr).format;
-            let pixel_format = ffmpeg_next::format::Pixel::from(std::mem::transmute::<
-                i32,
-                ffmpeg_next::ffi::AVPixelFormat,
-            >(fmt));
+            let pixel_format = crate::frame::pixel_from_raw(fmt);
             (w, h, pixel_format)
         }
     }
@@ -956,13 +2633,57 @@ impl Bus {
         let encode = encode?;
         let mut opts = Dictionary::new();
         opts.set("preset", encode.preset.as_deref().unwrap_or("ultrafast"));
-        opts.set("tune", "zerolatency");
+        match encode.b_frames.filter(|&n| n > 0) {
+            // The low-latency tune rules out B-frames.
+            Some(n) => opts.set("bf", n.to_string().as_str()),
+            None => opts.set("tune", "zerolatency"),
+        }
         if let Some(b) = encode.bitrate {
             opts.set("b", b.to_string().as_str());
         }
+        if encode.keyframe_interval.is_some() {
+            // Keyframes only on the fixed cadence (no scene-cut extras), and as
+            // IDRs so segmenters can cut on every one of them.
+            opts.set("sc_threshold", "0");
+            opts.set("forced-idr", "1");
+        }
         Some(opts)
     }
 
+    /// Video encoder settings for a source of `size` (already turned for the
+    /// rotation) whose frames suit `pixel_format`. The width, height and
+    /// pixel format `encode` asks for take precedence; the encoder scales and
+    /// converts the frames to them.
+    fn video_settings_from_config(
+        encode: Option<&EncodeConfig>,
+        size: (u32, u32),
+        pixel_format: ffmpeg_next::format::Pixel,
+    ) -> Settings {
+        let width = encode.and_then(|e| e.width).unwrap_or(size.0);
+        let height = encode.and_then(|e| e.height).unwrap_or(size.1);
+        let (width, height) = Self::ensure_video_dimensions(width, height);
+        // `validate` has reported a name that does not parse.
+        let pixel_format = encode
+            .and_then(|e| e.pixel_format.as_deref())
+            .and_then(|name| name.parse::<ffmpeg_next::format::Pixel>().ok())
+            .filter(|format| *format != ffmpeg_next::format::Pixel::None)
+            .unwrap_or(pixel_format);
+        Settings {
+            width,
+            height,
+            pixel_format,
+            keyframe_interval: Self::keyframe_interval_from_config(encode),
+            codec: Some(Self::encoder_codec_from_config(encode)),
+        }
+    }
+
+    fn keyframe_interval_from_config(encode: Option<&EncodeConfig>) -> u64 {
+        encode
+            .and_then(|e| e.keyframe_interval)
+            .map(u64::from)
+            .unwrap_or(Settings::default().keyframe_interval)
+    }
+
     fn encoder_codec_from_config(encode: Option<&EncodeConfig>) -> String {
         encode
             .map(|e| e.codec.as_str())
@@ -985,45 +2706,80 @@ impl Bus {
         }
     }
 
+    /// Start the encoder of `encode` for an input stream unless it runs
+    /// already, and count output `user` among those using it; a video encoder
+    /// turns its frames `rotation` degrees, and is sized for the turned
+    /// picture.
     async fn start_encoder_task(
         state: &mut BusState,
+        input: &str,
         input_stream_index: usize,
         encode: Option<&EncodeConfig>,
+        rotation: u32,
         lossless: bool,
+        user: &str,
     ) -> anyhow::Result<()> {
-        let input_stream = state
-            .input_streams
+        let source = state.input(input)?;
+        let input_stream = source
+            .streams
             .iter()
             .find(|s| s.index() == input_stream_index)
             .ok_or(anyhow::anyhow!("stream not found"))?;
-        if state.encoder_tasks.contains_key(&input_stream_index) {
+        // Outputs asking for the same params share one encoder; different
+        // params (e.g. HLS renditions) each get their own, fed by the one decoder.
+        let key: EncoderKey = (input_stream_index, encode.cloned(), rotation);
+        if source.encoder_tasks.contains_key(&key) {
+            state.input_mut(input)?.claim_encoder(key, user);
             return Ok(());
         }
+        // Shared by every output using it, so a child of the bus span rather
+        // than of the output that happened to start it.
+        let span = tracing::info_span!(
+            parent: &state.span,
+            "encoder",
+            input_id = %input,
+            stream_index = input_stream_index
+        );
 
         // Audio encoder path
         if input_stream.is_audio() {
-            let encoder_task = EncoderTask::new();
-            let encoder_receiver = state
+            let encoder_task = EncoderTask::new()
+                .with_stage(&state.teardown.encoders)
+                .with_recovery(state.options.encoder_recovery.clone(), state.events.clone())
+                .with_gap_fill(state.options.audio_gap_fill);
+            let encoder_receiver = source
                 .decoder_tasks
                 .get(&input_stream_index)
                 .ok_or(anyhow::anyhow!("decoder task not found for audio stream"))?
                 .subscribe();
             let audio_settings = Self::audio_settings_from_config(encode);
-            let encoder = Encoder::new_audio(input_stream, audio_settings, None)?;
+            let encoder_task = Self::watch_encoder(state, encoder_task, {
+                let (stream, settings) = (input_stream.clone(), audio_settings.clone());
+                move || Encoder::new_audio(&stream, settings.clone(), None)
+            });
+            let encoder = Encoder::new_audio(input_stream, audio_settings, None)
+                .map_err(AddOutputError::encoder_init)?;
             let out_stream = encoder.output_stream(input_stream_index);
             encoder_task
                 .start(encoder, encoder_receiver, lossless)
+                .instrument(span)
                 .await;
-            state.encoder_tasks.insert(input_stream_index, encoder_task);
-            state
+            let source = state.input_mut(input)?;
+            source.encoder_tasks.insert(key.clone(), encoder_task);
+            source
                 .encoder_output_streams
-                .insert(input_stream_index, out_stream);
+                .insert(key.clone(), out_stream);
+            source.claim_encoder(key, user);
             return Ok(());
         }
 
         // Video encoder path
         let codec_id = input_stream.parameters().id();
-        let encoder_task = EncoderTask::new();
+        // The picture size the encoder sees, with a quarter turn swapped.
+        let turned = |(w, h): (u32, u32)| if rotation % 180 == 90 { (h, w) } else { (w, h) };
+        let encoder_task = EncoderTask::new()
+            .with_stage(&state.teardown.encoders)
+            .with_recovery(state.options.encoder_recovery.clone(), state.events.clone());
         // Encoder-derived output stream descriptor for the muxer, set in each branch.
         let out_stream: AvStream;
         // Only RAWVIDEO has raw pixel data in packets; use packet->frame conversion.
@@ -1031,194 +2787,372 @@ impl Bus {
         if codec_id == ffmpeg_next::codec::Id::RAWVIDEO {
             let (width, height, pixel_format) =
                 Self::raw_video_params_from_parameters(input_stream.parameters());
-            let (width, height) = Self::ensure_video_dimensions(width, height);
-            let codec = Self::encoder_codec_from_config(encode);
-            let encoder_settings = Settings {
-                width,
-                height,
-                pixel_format: pixel_format_for_libx264(pixel_format),
-                codec: Some(codec),
-                ..Settings::default()
-            };
-            let packet_receiver: tokio::sync::broadcast::Receiver<RawPacketCmd> = state
-                .input_task
+            let size = turned(Self::ensure_video_dimensions(width, height));
+            let encoder_settings = Self::video_settings_from_config(
+                encode,
+                size,
+                pixel_format_for_libx264(pixel_format),
+            );
+            let packet_receiver: tokio::sync::broadcast::Receiver<RawPacketCmd> = source
+                .task
                 .as_ref()
                 .ok_or(anyhow::anyhow!("input task not found"))?
-                .subscribe();
+                .subscribe_stream(input_stream_index);
             /// Raw frames; balance memory vs avoiding Lagged (dropped frames break stream).
             const RAW_FRAME_CHAN_CAP: usize = 16;
             let (frame_tx, frame_rx) =
                 tokio::sync::broadcast::channel::<RawFrameCmd>(RAW_FRAME_CHAN_CAP);
+            let encoder_task = Self::watch_encoder(state, encoder_task, {
+                let (stream, settings) = (input_stream.clone(), encoder_settings.clone());
+                let encode = encode.cloned();
+                move || {
+                    let options = Self::encoder_options_from_config(encode.as_ref());
+                    Ok(Encoder::new(&stream, settings.clone(), options)?.with_rotation(rotation))
+                }
+            });
             let encoder_opts = Self::encoder_options_from_config(encode);
-            let encoder = Encoder::new(input_stream, encoder_settings, encoder_opts)?;
+            let encoder = Encoder::new(input_stream, encoder_settings, encoder_opts)
+                .map_err(AddOutputError::encoder_init)?
+                .with_rotation(rotation);
             // Spawn task: packet -> frame conversion, then forward to encoder
             {
                 let mut packet_rx = packet_receiver;
                 let frame_tx = frame_tx;
-                tokio::spawn(async move {
+                // Lossless outputs wait for the encoder to read, as the
+                // decoder does; the EOF always waits, so it lands behind every
+                // frame.
+                async fn room(frame_tx: &tokio::sync::broadcast::Sender<RawFrameCmd>) {
+                    while frame_tx.len() >= RAW_FRAME_CHAN_CAP && frame_tx.receiver_count() > 0 {
+                        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+                    }
+                }
+                // Part of the decoder stage: it stands in for a decoder.
+                let done = state.teardown.decoders.enter();
+                let stopped = state.teardown.decoders.token();
+                let task = async move {
+                    let _done = done;
                     loop {
-                        match packet_rx.recv().await {
+                        let received = tokio::select! {
+                            received = packet_rx.recv() => received,
+                            _ = stopped.cancelled() => break,
+                        };
+                        match received {
                             Ok(RawPacketCmd::Data(packet)) => {
                                 if let Ok(frame) =
                                     packet_to_raw_video_frame(packet, width, height, pixel_format)
                                 {
+                                    if lossless {
+                                        room(&frame_tx).await;
+                                    }
                                     let _ = frame_tx.send(RawFrameCmd::Data(frame));
                                 }
                             }
-                            Ok(RawPacketCmd::EOF) => {
+                            Ok(RawPacketCmd::Reconnected) => {}
+                            Ok(RawPacketCmd::EOF)
+                            | Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                                room(&frame_tx).await;
                                 let _ = frame_tx.send(RawFrameCmd::EOF);
                                 break;
                             }
-                            Err(_) => continue,
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                                tracing::warn!("raw video relay: lagged, lost {} packets", n);
+                            }
                         }
                     }
-                });
+                };
+                span.in_scope(|| crate::worker::spawn_task("bus-raw-video", task));
             }
             out_stream = encoder.output_stream(input_stream_index);
-            encoder_task.start(encoder, frame_rx, lossless).await;
+            encoder_task
+                .start(encoder, frame_rx, lossless)
+                .instrument(span)
+                .await;
         } else {
-            let encoder_receiver = state
+            let encoder_receiver = source
                 .decoder_tasks
                 .get(&input_stream_index)
                 .ok_or(anyhow::anyhow!("decoder task not found"))?
                 .subscribe();
             // Decoded path: decoder outputs RawFrame; encoder needs correct size/format.
             // For WRAPPED_AVFRAME (e.g. lavfi testsrc), use stream params so output resolution matches source.
-            let codec = Self::encoder_codec_from_config(encode);
             let encoder_settings = if codec_id == ffmpeg_next::codec::Id::WRAPPED_AVFRAME {
                 let (width, height, pixel_format) =
                     Self::raw_video_params_from_parameters(input_stream.parameters());
-                let (width, height) = Self::ensure_video_dimensions(width, height);
-                Settings {
-                    width,
-                    height,
-                    pixel_format: pixel_format_for_libx264(pixel_format),
-                    codec: Some(codec.clone()),
-                    ..Settings::default()
-                }
+                Self::video_settings_from_config(
+                    encode,
+                    turned(Self::ensure_video_dimensions(width, height)),
+                    pixel_format_for_libx264(pixel_format),
+                )
             } else {
                 // Decoded video transcode: size the encoder to the input (so a
-                // codec-only transcode preserves resolution), honoring explicit
-                // width/height overrides. The encoder's send_frame scaler handles
-                // any resize/format conversion.
-                let target_w = encode
-                    .and_then(|e| e.width)
-                    .unwrap_or_else(|| input_stream.width());
-                let target_h = encode
-                    .and_then(|e| e.height)
-                    .unwrap_or_else(|| input_stream.height());
-                let (target_w, target_h) = Self::ensure_video_dimensions(target_w, target_h);
-                Settings {
-                    width: target_w,
-                    height: target_h,
-                    pixel_format: ffmpeg_next::format::Pixel::YUV420P,
-                    codec: Some(codec),
-                    ..Settings::default()
-                }
+                // codec-only transcode preserves resolution) unless `encode`
+                // says otherwise.
+                Self::video_settings_from_config(
+                    encode,
+                    turned((input_stream.width(), input_stream.height())),
+                    ffmpeg_next::format::Pixel::YUV420P,
+                )
             };
+            let encoder_task = Self::watch_encoder(state, encoder_task, {
+                let (stream, settings) = (input_stream.clone(), encoder_settings.clone());
+                let encode = encode.cloned();
+                move || {
+                    let options = Self::encoder_options_from_config(encode.as_ref());
+                    Ok(Encoder::new(&stream, settings.clone(), options)?.with_rotation(rotation))
+                }
+            });
             let encoder_opts = Self::encoder_options_from_config(encode);
-            let encoder = Encoder::new(input_stream, encoder_settings, encoder_opts)?;
+            let encoder = Encoder::new(input_stream, encoder_settings, encoder_opts)
+                .map_err(AddOutputError::encoder_init)?
+                .with_rotation(rotation);
             out_stream = encoder.output_stream(input_stream_index);
             encoder_task
                 .start(encoder, encoder_receiver, lossless)
+                .instrument(span)
                 .await;
         }
 
-        state.encoder_tasks.insert(input_stream_index, encoder_task);
-        state
+        let source = state.input_mut(input)?;
+        source.encoder_tasks.insert(key.clone(), encoder_task);
+        source
             .encoder_output_streams
-            .insert(input_stream_index, out_stream);
+            .insert(key.clone(), out_stream);
+        source.claim_encoder(key, user);
         Ok(())
     }
 
+    /// Have the bus watchdog, if any, watch `task`, which then rebuilds a
+    /// stalled encoder with `rebuild`.
+    fn watch_encoder(
+        state: &BusState,
+        task: EncoderTask,
+        rebuild: impl Fn() -> anyhow::Result<Encoder> + Send + Sync + 'static,
+    ) -> EncoderTask {
+        match &state.watchdog {
+            Some(watchdog) => task.with_watchdog(watchdog.clone(), rebuild),
+            None => task,
+        }
+    }
+
+    /// Whether the encoders of stream `index` read its packets as frames
+    /// themselves (RAWVIDEO), so only frame consumers (Raw outputs,
+    /// subscriptions) need its decoder.
+    fn encodes_packets(state: &BusState, input: &str, index: usize) -> bool {
+        state.inputs.get(input).is_some_and(|entry| {
+            entry.streams.iter().any(|s| {
+                s.index() == index && s.parameters().id() == ffmpeg_next::codec::Id::RAWVIDEO
+            })
+        })
+    }
+
+    /// Start the decoder of an input stream for `user` unless it runs
+    /// already; `None` is a subscription, which keeps the decoder until the
+    /// input goes.
     async fn start_decoder_task(
         state: &mut BusState,
+        input: &str,
         input_stream_index: usize,
         lossless: bool,
+        user: Option<&str>,
     ) -> anyhow::Result<()> {
-        let input_stream = state
-            .input_streams
+        let source = state.input(input)?;
+        let input_stream = source
+            .streams
             .iter()
             .find(|s| s.index() == input_stream_index)
             .ok_or(anyhow::anyhow!("stream not found"))?;
-        if state.decoder_tasks.contains_key(&input_stream_index) {
-            return Ok(());
-        }
-        let codec_id = input_stream.parameters().id();
-        if codec_id == ffmpeg_next::codec::Id::RAWVIDEO {
+        if source.decoder_tasks.contains_key(&input_stream_index) {
+            state
+                .input_mut(input)?
+                .claim_decoder(input_stream_index, user);
             return Ok(());
         }
-        let decoder_receiver = state
-            .input_task
+        let decoder_receiver = source
+            .task
             .as_ref()
             .ok_or(anyhow::anyhow!("input task not found"))?
-            .subscribe();
-        let decoder = Decoder::new(input_stream)?;
-        let decoder_task = DecoderTask::new();
+            .subscribe_stream(input_stream_index);
+        let decoder = Decoder::new(input_stream, Some(state.options.decoder.clone()))?;
+        let decoder_task = DecoderTask::new().with_stage(&state.teardown.decoders);
+        let decoder_task = match &state.watchdog {
+            Some(watchdog) => decoder_task.with_watchdog(watchdog.clone()),
+            None => decoder_task,
+        };
+        let span = tracing::info_span!(
+            parent: &state.span,
+            "decoder",
+            input_id = %input,
+            stream_index = input_stream_index
+        );
         decoder_task
             .start(decoder, decoder_receiver, lossless)
+            .instrument(span)
             .await;
-        state.decoder_tasks.insert(input_stream_index, decoder_task);
+        let source = state.input_mut(input)?;
+        source
+            .decoder_tasks
+            .insert(input_stream_index, decoder_task);
+        source.claim_decoder(input_stream_index, user);
 
         Ok(())
     }
 
-    async fn prepare_input_task(state: &mut BusState) -> anyhow::Result<()> {
-        if state.input_task.is_some() {
+    /// Open input `id` unless it is open already, leaving it to
+    /// [`Self::start_input_task`] to read.
+    async fn prepare_input_task(state: &mut BusState, id: &str) -> anyhow::Result<()> {
+        let capacity = state.options.input_packet_capacity;
+        let stage = state.teardown.input.clone();
+        let events = state.events.clone();
+        let entry = state.input_mut(id)?;
+        if entry.task.is_some() {
             return Ok(());
         }
-        let options = state.input_options.as_ref().map(|options| {
-            ffmpeg_next::Dictionary::from_iter(
-                options.iter().map(|(k, v)| (k.as_str(), v.as_str())),
-            )
-        });
-        let input = match state.input_config.as_ref() {
-            Some(InputConfig::Net { url }) => AvInput::new(url, None, options)?,
-            Some(InputConfig::File { path }) => AvInput::new(path, None, options)?,
-            Some(InputConfig::Device { display, format }) => {
-                AvInput::new(display, Some(format), options)?
+        let options = match &entry.config {
+            InputConfig::Net { net, .. } => net.dictionary(entry.options.as_ref()),
+            _ => entry.options.as_ref().map(|options| {
+                ffmpeg_next::Dictionary::from_iter(
+                    options.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+                )
+            }),
+        };
+        let opened = match &entry.config {
+            InputConfig::Net { url, net, .. } => AvInput::new(&net.url(url), None, options),
+            InputConfig::File { path } => AvInput::new(path, None, options).map(AvInput::playback),
+            InputConfig::FileRange { path, start, end } => {
+                AvInput::new(path, None, options).and_then(|input| input.range(*start, *end))
+            }
+            InputConfig::FileLoop { path, realtime } => {
+                AvInput::new(path, None, options).map(|input| input.looping(*realtime))
+            }
+            InputConfig::Device { display, format } => AvInput::new(display, Some(format), options),
+            InputConfig::Reader { open, format } => {
+                open().and_then(|reader| AvInput::from_reader(reader, format, options))
+            }
+            InputConfig::Push { .. } => entry
+                .push_source
+                .take()
+                .map(AvInput::from_push)
+                .ok_or_else(|| anyhow::anyhow!("push input was already read")),
+        };
+        let input = match opened {
+            Ok(input) => input,
+            Err(e) => {
+                let _ = events.send(BusEvent::InputError {
+                    input: id.to_string(),
+                    error: format!("{:#}", e),
+                });
+                return Err(e);
             }
-            None => return Err(anyhow::anyhow!("input config is not set")),
         };
 
+        entry.start_us = input.range_start_micros();
         let streams = input.streams();
-        log::info!("start add input streams:");
+        tracing::info!("start add input {} streams:", id);
         for (index, stream) in streams {
-            log::info!(
+            tracing::info!(
                 "stream index: {}, stream id: {:#?}, time_base: {:#?}",
                 index,
                 stream.parameters().id(),
                 stream.time_base()
             );
-            state.input_streams.push(stream.clone());
+            entry.streams.push(stream.clone());
         }
 
-        state.input_task = Some(AvInputTask::new());
-        state.pending_input = Some(input);
+        let mut codecs: Vec<_> = streams
+            .iter()
+            .map(|(index, stream)| (*index, stream.parameters().id().name().to_string()))
+            .collect();
+        codecs.sort_by_key(|(index, _)| *index);
+        let _ = events.send(BusEvent::InputOpened {
+            input: id.to_string(),
+            streams: codecs.into_iter().map(|(_, codec)| codec).collect(),
+        });
+        entry.task = Some(
+            AvInputTask::with_capacity(capacity)
+                .with_stage(&stage)
+                .with_events(events, id),
+        );
+        entry.pending = Some(input);
         Ok(())
     }
 
-    async fn start_input_task(state: &mut BusState) -> anyhow::Result<()> {
-        let input = match state.pending_input.take() {
+    async fn start_input_task(state: &mut BusState, id: &str) -> anyhow::Result<()> {
+        let Some(entry) = state.inputs.get_mut(id) else {
+            return Ok(());
+        };
+        let input = match entry.pending.take() {
             Some(input) => input,
             None => return Ok(()),
         };
 
-        if let Some(task) = state.input_task.as_ref() {
-            task.start(input).await;
+        if let Some(task) = entry.task.as_ref() {
+            let span = tracing::info_span!(parent: &state.span, "input", input_id = %id);
+            match &entry.config {
+                InputConfig::Net {
+                    url,
+                    reconnect: Some(retry),
+                    net,
+                } => {
+                    let (url, options) = (net.url(url), entry.options.clone());
+                    let net = net.clone();
+                    let reopen: InputFactory = Box::new(move || {
+                        AvInput::new(&url, None, net.dictionary(options.as_ref()))
+                    });
+                    task.start_with_retry(input, reopen, retry.clone())
+                        .instrument(span)
+                        .await;
+                }
+                _ => task.start(input).instrument(span).await,
+            }
         }
 
         Ok(())
     }
 
+    fn set_playback_rate_internal(
+        state: &BusState,
+        id: &str,
+        rate: f32,
+    ) -> anyhow::Result<PlaybackState> {
+        if !rate.is_finite() || rate <= 0.0 {
+            anyhow::bail!("playback rate must be a positive number, not {rate}");
+        }
+        let task = state.input_task(id)?;
+        let file = matches!(
+            state.input(id)?.config,
+            InputConfig::File { .. } | InputConfig::FileRange { .. } | InputConfig::FileLoop { .. }
+        );
+        if !file {
+            anyhow::bail!("input '{id}' is live: only file inputs have a playback rate");
+        }
+        task.set_rate(rate);
+        Ok(task.playback())
+    }
+
+    /// Set the bus's [`DEFAULT_INPUT`]; it is opened once the first output
+    /// is added. An [`InputConfig::Push`] input comes with the handle frames
+    /// are sent through.
     pub async fn add_input(
         &self,
         input: InputConfig,
         options: Option<HashMap<String, String>>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Option<PushInputHandle>> {
+        self.add_named_input(DEFAULT_INPUT, input, options).await
+    }
+
+    /// Add input `id` besides the others, e.g. a second camera of a composite
+    /// view; outputs set to it with [`OutputConfig::with_input`] read its
+    /// streams. Opened, like the default one, once its first output is added.
+    pub async fn add_named_input(
+        &self,
+        id: &str,
+        input: InputConfig,
+        options: Option<HashMap<String, String>>,
+    ) -> anyhow::Result<Option<PushInputHandle>> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.tx
             .send(BusCommand::AddInput {
+                id: id.to_string(),
                 input,
                 options,
                 result: tx,
@@ -1227,16 +3161,23 @@ impl Bus {
         rx.await?
     }
 
-    pub async fn remove_input(&self) -> anyhow::Result<()> {
+    /// Stop input `id`. Its decoders and encoders flush and end, and so do
+    /// the outputs reading it; those of other inputs keep running.
+    pub async fn remove_input(&self, id: &str) -> anyhow::Result<()> {
         let (tx, rx) = tokio::sync::oneshot::channel();
-        self.tx.send(BusCommand::RemoveInput { result: tx }).await?;
+        self.tx
+            .send(BusCommand::RemoveInput {
+                id: id.to_string(),
+                result: tx,
+            })
+            .await?;
         rx.await?
     }
 
     pub async fn add_output(
         &self,
         output: OutputConfig,
-    ) -> anyhow::Result<(AvStream, VideoRawFrameStream)> {
+    ) -> anyhow::Result<(AvStream, RawOutputStream)> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.tx
             .send(BusCommand::AddOutput { output, result: tx })
@@ -1244,6 +3185,142 @@ impl Bus {
         rx.await?
     }
 
+    /// Detach output `id` at runtime, e.g. stop a recording while the live
+    /// outputs of the same input go on. A muxed output is finished, trailer
+    /// included; decoders and encoders only it used stop.
+    pub async fn remove_output(&self, id: &str) -> anyhow::Result<()> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx
+            .send(BusCommand::RemoveOutput {
+                id: id.to_string(),
+                result: tx,
+            })
+            .await?;
+        rx.await?
+    }
+
+    /// Add several outputs as one batch, so that all of them start from the
+    /// input's first packet (e.g. the renditions of an HLS ladder must cut
+    /// identical segments). One result per output, in order.
+    pub async fn add_outputs(
+        &self,
+        outputs: Vec<OutputConfig>,
+    ) -> anyhow::Result<Vec<anyhow::Result<(AvStream, RawOutputStream)>>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx
+            .send(BusCommand::AddOutputs {
+                outputs,
+                result: tx,
+            })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Re-pick the codec of an output added with
+    /// [`OutputConfig::with_acceptable_codecs`], e.g. when its client
+    /// reconnects with other capabilities. The output keeps its stream and
+    /// switches at a keyframe; frames from then on carry the new codec, and
+    /// [`BusEvent::OutputRenegotiated`] reports the switch. Returns the stream
+    /// the output carries after it.
+    pub async fn renegotiate_output(
+        &self,
+        id: &str,
+        acceptable: Vec<ffmpeg_next::codec::Id>,
+    ) -> anyhow::Result<AvStream> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx
+            .send(BusCommand::RenegotiateOutput {
+                id: id.to_string(),
+                acceptable,
+                result: tx,
+            })
+            .await?;
+        rx.await?
+    }
+
+    /// The default input's streams, opening the input if no output has yet
+    /// (it is not read until the first output is added). Lets callers size
+    /// outputs to the source, e.g. skip HLS renditions larger than it.
+    pub async fn input_streams(&self) -> anyhow::Result<Vec<AvStream>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx
+            .send(BusCommand::InputStreams { result: tx })
+            .await?;
+        rx.await?
+    }
+
+    /// The default input's clock offset from ours (see [`crate::clock`]).
+    /// `None` until the input is read and reports sender wall clock times.
+    pub async fn clock_offset(&self) -> anyhow::Result<Option<crate::clock::ClockOffset>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx.send(BusCommand::ClockOffset { result: tx }).await?;
+        Ok(rx.await?)
+    }
+
+    /// Packet counters of the default input (see
+    /// [`crate::input::InputStats`]). `None` until the input is open.
+    pub async fn input_stats(&self) -> anyhow::Result<Option<crate::input::InputStats>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx.send(BusCommand::InputStats { result: tx }).await?;
+        Ok(rx.await?)
+    }
+
+    /// Stop reading input `id`, keeping it open, until
+    /// [`Self::resume_input`]: its outputs get no packets meanwhile. Errors
+    /// until the input is open (the first output was added).
+    pub async fn pause_input(&self, id: &str) -> anyhow::Result<PlaybackState> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let id = id.to_string();
+        self.tx
+            .send(BusCommand::PauseInput { id, result: tx })
+            .await?;
+        rx.await?
+    }
+
+    /// Read on a paused input from where it stopped.
+    pub async fn resume_input(&self, id: &str) -> anyhow::Result<PlaybackState> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let id = id.to_string();
+        self.tx
+            .send(BusCommand::ResumeInput { id, result: tx })
+            .await?;
+        rx.await?
+    }
+
+    /// Read file input `id` at `rate` times the pace of its timestamps (see
+    /// [`AvInputTask::set_rate`]). Errors for a live input, which comes at
+    /// its own pace, and for a rate that is not positive.
+    pub async fn set_playback_rate(&self, id: &str, rate: f32) -> anyhow::Result<PlaybackState> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let id = id.to_string();
+        self.tx
+            .send(BusCommand::SetPlaybackRate {
+                id,
+                rate,
+                result: tx,
+            })
+            .await?;
+        rx.await?
+    }
+
+    /// Counters of every open input and every output (see
+    /// [`crate::stats`]).
+    pub async fn stats(&self) -> anyhow::Result<BusStats> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx.send(BusCommand::GetStats { result: tx }).await?;
+        Ok(rx.await?)
+    }
+
+    /// The outputs, decoders and encoders the bus holds right now, for tests
+    /// and debugging.
+    pub async fn state_snapshot(&self) -> anyhow::Result<BusStateSnapshot> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx
+            .send(BusCommand::StateSnapshot { result: tx })
+            .await?;
+        Ok(rx.await?)
+    }
+
     /// Subscribe to this pipe's decoded-audio broadcast, starting the audio
     /// decoder if needed. The receiver yields `RawFrameCmd` (filter `Audio`).
     pub async fn subscribe_audio(&self) -> anyhow::Result<crate::frame::RawFrameReceiver> {
@@ -1264,6 +3341,31 @@ impl Bus {
         rx.await?
     }
 
+    /// Decoded frames this bus's `Raw` outputs dropped because they failed to
+    /// convert, since the bus was created.
+    pub fn raw_frame_drops(&self) -> u64 {
+        self.raw_frame_drops.load(Ordering::Relaxed)
+    }
+
+    /// Events of the outputs and encoders added from now on (see
+    /// [`BusEvent`]).
+    pub fn events(&self) -> tokio::sync::broadcast::Receiver<BusEvent> {
+        self.events.subscribe()
+    }
+
+    /// Tear the bus down in order (see [`crate::teardown`]): the input stops
+    /// and sends its EOF, then decoders, encoders and outputs each flush and
+    /// end, waiting up to [`TeardownConfig::shutdown_stage_timeout`] per
+    /// stage. Resolves once done, with the stages that had to be forced.
+    pub async fn shutdown(&self) -> anyhow::Result<Vec<Stage>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tx.send(BusCommand::Shutdown { result: tx }).await?;
+        Ok(rx.await?)
+    }
+
+    /// Start tearing the bus down, as [`Self::shutdown`] does but waiting
+    /// only up to [`TeardownConfig::stop_stage_timeout`] per stage, and
+    /// without waiting for it to be done.
     pub fn stop(&self) {
         self.cancel.cancel();
     }
@@ -1276,50 +3378,491 @@ impl Drop for Bus {
 }
 
 struct BusState {
-    input_config: Option<InputConfig>,
-    input_options: Option<HashMap<String, String>>,
+    /// By input id, see [`Bus::add_named_input`].
+    inputs: HashMap<String, InputTaskEntry>,
     output_config: HashMap<String, OutputConfig>,
-    input_task: Option<AvInputTask>,
-    pending_input: Option<AvInput>,
-    input_streams: Vec<AvStream>,
+    /// Ends the task (or stream) of each output, see [`Bus::remove_output`].
+    output_tokens: HashMap<String, CancellationToken>,
+    /// What each output has written, see [`Bus::stats`].
+    output_counters: HashMap<String, Arc<OutputCounters>>,
+    /// Where [`Bus::renegotiate_output`] sends a negotiated output's new
+    /// packet source, by output id.
+    renegotiations: HashMap<String, tokio::sync::mpsc::UnboundedSender<PacketRoute>>,
+    /// Shared with [`Bus::raw_frame_drops`].
+    raw_frame_drops: Arc<AtomicU64>,
+    /// Sender behind [`Bus::events`].
+    events: tokio::sync::broadcast::Sender<BusEvent>,
+    options: BusOptions,
+    /// Built from [`BusOptions::watchdog`].
+    watchdog: Option<Watchdog>,
+    /// What every task is started as part of, to end them in order.
+    teardown: Teardown,
+    /// The `bus` span (`bus_id`); parent of the shared task spans.
+    span: tracing::Span,
+}
+
+/// One input of the bus and the tasks working on its streams, which end with
+/// it on [`Bus::remove_input`].
+struct InputTaskEntry {
+    config: InputConfig,
+    options: Option<HashMap<String, String>>,
+    task: Option<AvInputTask>,
+    pending: Option<AvInput>,
+    /// The read side of an [`InputConfig::Push`] input, until it is opened.
+    push_source: Option<crate::push::PushSource>,
+    streams: Vec<AvStream>,
+    /// Where a [`InputConfig::FileRange`] input starts, see
+    /// [`AvInput::range_start_micros`].
+    start_us: Option<i64>,
     decoder_tasks: HashMap<usize, DecoderTask>,
-    encoder_tasks: HashMap<usize, EncoderTask>,
-    /// Encoder-derived output stream descriptors, keyed by input stream index.
+    encoder_tasks: HashMap<EncoderKey, EncoderTask>,
+    /// Encoder-derived output stream descriptors, keyed like `encoder_tasks`.
     /// Populated when an encoder task starts; the muxer uses these (not the
     /// input params) for transcoded streams so the header matches the packets.
-    encoder_output_streams: HashMap<usize, AvStream>,
+    encoder_output_streams: HashMap<EncoderKey, AvStream>,
+    /// Who uses each decoder and encoder, see [`Bus::remove_output`].
+    decoder_users: HashMap<usize, TaskUsers>,
+    encoder_users: HashMap<EncoderKey, TaskUsers>,
 }
 
-impl BusState {
-    fn new() -> Self {
+impl InputTaskEntry {
+    fn new(config: InputConfig, options: Option<HashMap<String, String>>) -> Self {
         Self {
-            input_config: None,
-            output_config: HashMap::new(),
-            input_task: None,
-            pending_input: None,
-            input_streams: Vec::new(),
+            config,
+            options,
+            task: None,
+            pending: None,
+            push_source: None,
+            streams: Vec::new(),
+            start_us: None,
             decoder_tasks: HashMap::new(),
             encoder_tasks: HashMap::new(),
             encoder_output_streams: HashMap::new(),
-            input_options: None,
+            decoder_users: HashMap::new(),
+            encoder_users: HashMap::new(),
+        }
+    }
+
+    fn claim_decoder(&mut self, index: usize, user: Option<&str>) {
+        self.decoder_users.entry(index).or_default().add(user);
+    }
+
+    fn claim_encoder(&mut self, key: EncoderKey, user: &str) {
+        self.encoder_users.entry(key).or_default().add(Some(user));
+    }
+
+    /// Let go of the decoders and encoders output `id` used, stopping those
+    /// it was the last user of.
+    fn release(&mut self, id: &str) {
+        for key in TaskUsers::release_all(&mut self.encoder_users, id) {
+            tracing::info!("encoder of stream {} no longer used, stopping it", key.0);
+            self.encoder_output_streams.remove(&key);
+            if let Some(task) = self.encoder_tasks.remove(&key) {
+                task.stop();
+            }
+        }
+        for index in TaskUsers::release_all(&mut self.decoder_users, id) {
+            tracing::info!("decoder of stream {} no longer used, stopping it", index);
+            if let Some(task) = self.decoder_tasks.remove(&index) {
+                task.stop();
+            }
+        }
+    }
+}
+
+/// The outputs using a decoder or encoder. The task stops with the last of
+/// them, unless a subscription (which cannot be removed) uses it too.
+#[derive(Default)]
+struct TaskUsers {
+    outputs: HashSet<String>,
+    subscribed: bool,
+}
+
+impl TaskUsers {
+    fn add(&mut self, user: Option<&str>) {
+        match user {
+            Some(id) => {
+                self.outputs.insert(id.to_string());
+            }
+            None => self.subscribed = true,
+        }
+    }
+
+    /// Forget output `id`; whether it was the last user.
+    fn release(&mut self, id: &str) -> bool {
+        self.outputs.remove(id) && self.outputs.is_empty() && !self.subscribed
+    }
+
+    /// Forget output `id` everywhere in `tasks`, returning (and dropping) the
+    /// keys no one uses any more.
+    fn release_all<K: Clone + Eq + std::hash::Hash>(
+        tasks: &mut HashMap<K, TaskUsers>,
+        id: &str,
+    ) -> Vec<K> {
+        let idle: Vec<K> = tasks
+            .iter_mut()
+            .filter_map(|(key, users)| users.release(id).then(|| key.clone()))
+            .collect();
+        tasks.retain(|key, _| !idle.contains(key));
+        idle
+    }
+}
+
+/// Encoders are per input stream, encode config *and* the degrees their
+/// frames are turned (see [`OutputConfig::auto_rotate`]).
+type EncoderKey = (usize, Option<EncodeConfig>, u32);
+
+/// The packets a Demuxed output forwards: the input's, or an encoder's.
+struct PacketRoute {
+    receiver: RawPacketReceiver,
+    codec: ffmpeg_next::codec::Id,
+    time_base: ffmpeg_next::Rational,
+    width: u32,
+    height: u32,
+    transcoded: bool,
+    /// Rewrites the packets as Annex B (see [`OutputConfig::annexb`]).
+    annexb: Option<crate::bsf::AnnexBFilter>,
+}
+
+impl PacketRoute {
+    fn new(receiver: RawPacketReceiver, stream: &AvStream, transcoded: bool) -> Self {
+        Self {
+            receiver,
+            codec: stream.parameters().id(),
+            time_base: stream.time_base(),
+            width: stream.width(),
+            height: stream.height(),
+            transcoded,
+            annexb: None,
+        }
+    }
+
+    /// Deliver the packets of `stream`, the route's, as Annex B when they
+    /// are length-prefixed.
+    fn with_annexb(mut self, stream: &AvStream) -> Self {
+        self.annexb = crate::bsf::AnnexBFilter::new(stream.parameters());
+        self
+    }
+
+    /// `packet` with its timestamps in `time_base`.
+    fn rescale(&self, mut packet: RawPacket, time_base: ffmpeg_next::Rational) -> RawPacket {
+        if self.time_base != time_base {
+            packet.get_mut().rescale_ts(self.time_base, time_base);
+        }
+        packet
+    }
+
+    /// `packet` as sent, tagged with the route's codec and picture size so
+    /// the consumer sees a switch.
+    fn frame(&self, packet: RawPacket) -> VideoFrame {
+        let mut frame = VideoFrame {
+            width: self.width,
+            height: self.height,
+            codec_id: ffmpeg_next::ffi::AVCodecID::from(self.codec) as i32,
+            ..VideoFrame::from(packet)
+        };
+        if let Some(annexb) = &self.annexb {
+            frame.data = annexb.filter(&frame.data, frame.is_key);
+        }
+        frame
+    }
+}
+
+impl BusState {
+    fn new(
+        raw_frame_drops: Arc<AtomicU64>,
+        events: tokio::sync::broadcast::Sender<BusEvent>,
+        options: BusOptions,
+        span: tracing::Span,
+    ) -> Self {
+        Self {
+            inputs: HashMap::new(),
+            output_config: HashMap::new(),
+            output_tokens: HashMap::new(),
+            output_counters: HashMap::new(),
+            renegotiations: HashMap::new(),
+            raw_frame_drops,
+            watchdog: options
+                .watchdog
+                .clone()
+                .map(|config| Watchdog::new(config, events.clone())),
+            teardown: Teardown::default(),
+            events,
+            options,
+            span,
+        }
+    }
+
+    fn input(&self, id: &str) -> anyhow::Result<&InputTaskEntry> {
+        self.inputs
+            .get(id)
+            .ok_or_else(|| anyhow::anyhow!("input '{id}' not found"))
+    }
+
+    /// A new token for output `id`'s task, cancelled when the output is
+    /// removed or the output stage forced.
+    fn output_token(&mut self, id: &str) -> CancellationToken {
+        let token = self.teardown.outputs.token();
+        self.output_tokens.insert(id.to_string(), token.clone());
+        token
+    }
+
+    /// The counters of output `id`, created with its first task or stream.
+    fn output_counters(&mut self, id: &str) -> Arc<OutputCounters> {
+        self.output_counters
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Every open input's counters and every output's, sorted by id.
+    fn stats(&self) -> BusStats {
+        let mut inputs = self
+            .inputs
+            .iter()
+            .filter_map(|(id, input)| Some((id.clone(), input.task.as_ref()?.stats())))
+            .collect::<Vec<_>>();
+        inputs.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut outputs = self
+            .output_counters
+            .iter()
+            .map(|(id, counters)| counters.snapshot(id))
+            .collect::<Vec<_>>();
+        outputs.sort_by(|a, b| a.id.cmp(&b.id));
+        BusStats { inputs, outputs }
+    }
+
+    fn snapshot(&self) -> BusStateSnapshot {
+        let mut snapshot = BusStateSnapshot {
+            outputs: self.output_config.keys().cloned().collect(),
+            ..BusStateSnapshot::default()
+        };
+        for (id, input) in &self.inputs {
+            let decoders = input.decoder_tasks.keys().map(|index| (id.clone(), *index));
+            snapshot.decoder_tasks.extend(decoders);
+            let encoders = input.encoder_tasks.keys().map(|key| (id.clone(), key.0));
+            snapshot.encoder_tasks.extend(encoders);
+        }
+        snapshot.outputs.sort();
+        snapshot.decoder_tasks.sort();
+        snapshot.encoder_tasks.sort();
+        snapshot
+    }
+
+    fn input_mut(&mut self, id: &str) -> anyhow::Result<&mut InputTaskEntry> {
+        self.inputs
+            .get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("input '{id}' not found"))
+    }
+
+    /// The reader of input `id`, once the input is open.
+    fn input_task(&self, id: &str) -> anyhow::Result<&AvInputTask> {
+        self.input(id)?
+            .task
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("input '{id}' is not open yet"))
+    }
+}
+
+/// Span of one output's registration and of the tasks it spawns.
+fn output_span(id: &str) -> tracing::Span {
+    tracing::info_span!("output", output_id = %id)
+}
+
+/// One item of an output's stream.
+#[derive(Debug)]
+pub enum FrameEvent<T = VideoFrame> {
+    /// A frame; for encoded, muxed and Demuxed outputs, a packet or muxed chunk.
+    Frame(T),
+    /// The input ended. Nothing follows.
+    Eof,
+    /// The consumer fell this many items behind and they were skipped. Decoded
+    /// frames stand on their own; a packet after the gap may reference one
+    /// never received, so whatever decodes packets should wait for a keyframe.
+    Lagged(u64),
+    /// An item was lost to an error: a frame that could not be converted (the
+    /// stream goes on) or a failed packet hook (the stream ends after it).
+    Error(String),
+}
+
+impl<T> FrameEvent<T> {
+    /// The frame, if this is one.
+    pub fn into_frame(self) -> Option<T> {
+        match self {
+            Self::Frame(frame) => Some(frame),
+            _ => None,
+        }
+    }
+}
+
+pub type VideoRawFrameStream = Pin<Box<dyn Stream<Item = FrameEvent<VideoFrame>> + Send + Sync>>;
+pub type AudioRawFrameStream = Pin<Box<dyn Stream<Item = FrameEvent<AudioFrame>> + Send + Sync>>;
+
+/// What [`Bus::add_output`] hands back. `OutputDest::Raw` yields the kind
+/// matching the output's `av_type`; every other destination yields `Video`
+/// (its packet stream, carried as `VideoFrame`s).
+pub enum RawOutputStream {
+    Video(VideoRawFrameStream),
+    Audio(AudioRawFrameStream),
+}
+
+impl RawOutputStream {
+    fn from_video((av, stream): (AvStream, VideoRawFrameStream)) -> (AvStream, Self) {
+        (av, Self::Video(stream))
+    }
+
+    pub fn into_video(self) -> anyhow::Result<VideoRawFrameStream> {
+        match self {
+            Self::Video(stream) => Ok(stream),
+            Self::Audio(_) => Err(anyhow::anyhow!("expected a video stream, got audio")),
+        }
+    }
+
+    pub fn into_audio(self) -> anyhow::Result<AudioRawFrameStream> {
+        match self {
+            Self::Audio(stream) => Ok(stream),
+            Self::Video(_) => Err(anyhow::anyhow!("expected an audio stream, got video")),
+        }
+    }
+
+    /// This stream, ending at its next item once `token` is cancelled.
+    fn until(self, token: CancellationToken) -> Self {
+        match self {
+            Self::Video(stream) => Self::Video(Box::pin(
+                stream.take_while(move |_| futures::future::ready(!token.is_cancelled())),
+            )),
+            Self::Audio(stream) => Self::Audio(Box::pin(
+                stream.take_while(move |_| futures::future::ready(!token.is_cancelled())),
+            )),
+        }
+    }
+
+    /// This stream, counting the items its consumer takes on `counters`.
+    fn counted(self, counters: Arc<OutputCounters>) -> Self {
+        match self {
+            Self::Video(stream) => Self::Video(Box::pin(stream.inspect(move |item| {
+                if let FrameEvent::Frame(frame) = item {
+                    counters.wrote(frame.data.len(), true);
+                }
+            }))),
+            Self::Audio(stream) => Self::Audio(Box::pin(stream.inspect(move |item| {
+                if let FrameEvent::Frame(frame) = item {
+                    counters.wrote(frame.data.len(), true);
+                }
+            }))),
         }
     }
 }
 
-pub type VideoRawFrameStream = Pin<Box<dyn Stream<Item = Option<VideoFrame>> + Send + Sync>>;
+/// A Raw video output's frame: `frame` cropped to `roi` (in the decoded
+/// picture's coordinates), then turned `rotation` degrees clockwise.
+fn reshape_video(frame: RawFrame, roi: Option<Rect>, rotation: u32) -> anyhow::Result<VideoFrame> {
+    let RawFrame::Video(frame) = frame else {
+        anyhow::bail!("not a video frame");
+    };
+    let cropped = roi
+        .map(|rect| crate::frame::crop_video(frame.as_video(), rect))
+        .transpose()?;
+    let rotated = match rotation {
+        0 => None,
+        degrees => Some(crate::frame::rotate_video(
+            cropped.as_ref().unwrap_or(frame.as_video()),
+            degrees,
+        )?),
+    };
+    match rotated.or(cropped) {
+        Some(video) => VideoFrame::try_from(RawFrame::Video(video.into())),
+        None => VideoFrame::try_from(RawFrame::Video(frame)),
+    }
+}
+
+/// A decoder's frames for a Raw output: from its broadcast, or from a queue
+/// of its frame hub (see [`OutputConfig::frame_queue`]), which never lags.
+type RawFrameSource =
+    Pin<Box<dyn Stream<Item = Result<RawFrameCmd, BroadcastStreamRecvError>> + Send + Sync>>;
+
+/// Decoded frames of `av_type`'s kind from a decoder, converted with
+/// `convert`. Frames of the other kind are skipped; a failed conversion is
+/// logged, counted in `drops` and reported as [`FrameEvent::Error`] instead
+/// of ending the stream.
+/// The stream is polled by the consumer, outside any bus task, so it logs in
+/// the span it was created in (the output's).
+fn raw_frame_stream<T, F>(
+    rx: RawFrameSource,
+    av_type: OutputAvType,
+    drops: Arc<AtomicU64>,
+    convert: F,
+) -> impl Stream<Item = FrameEvent<T>> + Send + Sync + 'static
+where
+    T: Send + Sync + 'static,
+    F: Fn(RawFrame) -> anyhow::Result<T> + Send + Sync + 'static,
+{
+    let span = tracing::Span::current();
+    rx.filter_map(move |cmd| {
+        let _enter = span.enter();
+        let item = match cmd {
+            Ok(RawFrameCmd::Data(frame)) => {
+                let same_kind = matches!(
+                    (&frame, av_type),
+                    (RawFrame::Video(_), OutputAvType::Video)
+                        | (RawFrame::Audio(_), OutputAvType::Audio)
+                );
+                if !same_kind {
+                    None
+                } else {
+                    match convert(frame) {
+                        Ok(frame) => Some(FrameEvent::Frame(frame)),
+                        Err(e) => {
+                            drops.fetch_add(1, Ordering::Relaxed);
+                            tracing::warn!("raw output: dropping frame: {:#}", e);
+                            Some(FrameEvent::Error(format!("{e:#}")))
+                        }
+                    }
+                }
+            }
+            Ok(RawFrameCmd::EOF) => Some(FrameEvent::Eof),
+            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                tracing::warn!("raw output lagged, dropped {} frames", n);
+                Some(FrameEvent::Lagged(n))
+            }
+        };
+        futures::future::ready(item)
+    })
+}
 
 pub enum BusCommand {
     AddInput {
+        id: String,
         input: InputConfig,
         options: Option<HashMap<String, String>>,
-        result: tokio::sync::oneshot::Sender<anyhow::Result<()>>,
+        result: tokio::sync::oneshot::Sender<anyhow::Result<Option<PushInputHandle>>>,
     },
+    /// Stop an input and end the outputs reading it.
     RemoveInput {
+        id: String,
         result: tokio::sync::oneshot::Sender<anyhow::Result<()>>,
     },
     AddOutput {
         output: OutputConfig,
-        result: tokio::sync::oneshot::Sender<anyhow::Result<(AvStream, VideoRawFrameStream)>>,
+        result: tokio::sync::oneshot::Sender<anyhow::Result<(AvStream, RawOutputStream)>>,
+    },
+    /// End one output, leaving the others running.
+    RemoveOutput {
+        id: String,
+        result: tokio::sync::oneshot::Sender<anyhow::Result<()>>,
+    },
+    /// Add several outputs at once; the input starts reading only after all
+    /// of them are registered. One result per output, in order.
+    AddOutputs {
+        outputs: Vec<OutputConfig>,
+        result: tokio::sync::oneshot::Sender<Vec<anyhow::Result<(AvStream, RawOutputStream)>>>,
+    },
+    /// Re-pick a negotiated output's codec; replies with the stream it
+    /// carries from the switch on.
+    RenegotiateOutput {
+        id: String,
+        acceptable: Vec<ffmpeg_next::codec::Id>,
+        result: tokio::sync::oneshot::Sender<anyhow::Result<AvStream>>,
     },
     /// Subscribe to the pipe's decoded audio broadcast (ensures the audio
     /// decoder task is running). Receiver yields `RawFrame::Audio` (and may
@@ -1332,23 +3875,281 @@ pub enum BusCommand {
     SubscribeVideo {
         result: tokio::sync::oneshot::Sender<anyhow::Result<crate::frame::RawFrameReceiver>>,
     },
+    /// The default input's streams (opens the input if not yet open).
+    InputStreams {
+        result: tokio::sync::oneshot::Sender<anyhow::Result<Vec<AvStream>>>,
+    },
+    /// The running default input's source clock offset, if it reports one.
+    ClockOffset {
+        result: tokio::sync::oneshot::Sender<Option<crate::clock::ClockOffset>>,
+    },
+    /// The running default input's packet counters.
+    InputStats {
+        result: tokio::sync::oneshot::Sender<Option<crate::input::InputStats>>,
+    },
+    /// Hold input `id` where it is (see [`AvInputTask::pause`]).
+    PauseInput {
+        id: String,
+        result: tokio::sync::oneshot::Sender<anyhow::Result<PlaybackState>>,
+    },
+    ResumeInput {
+        id: String,
+        result: tokio::sync::oneshot::Sender<anyhow::Result<PlaybackState>>,
+    },
+    /// Play file input `id` at `rate` (see [`AvInputTask::set_rate`]).
+    SetPlaybackRate {
+        id: String,
+        rate: f32,
+        result: tokio::sync::oneshot::Sender<anyhow::Result<PlaybackState>>,
+    },
+    GetStats {
+        result: tokio::sync::oneshot::Sender<BusStats>,
+    },
+    StateSnapshot {
+        result: tokio::sync::oneshot::Sender<BusStateSnapshot>,
+    },
+    /// Tear the bus down in order; replies with the stages forced.
+    Shutdown {
+        result: tokio::sync::oneshot::Sender<Vec<Stage>>,
+    },
+}
+
+/// The RTSP transport of a network input, FFmpeg's `rtsp_transport`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtspTransport {
+    /// FFmpeg's choice: UDP, then TCP.
+    #[default]
+    Auto,
+    Tcp,
+    Udp,
+}
+
+/// How an [`InputConfig::Net`] connects, in typed form: turned into demuxer
+/// options, and credentials into the URL, when the input opens.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetInputOptions {
+    pub transport: RtspTransport,
+    /// Socket I/O timeout (`stimeout`), milliseconds.
+    pub stimeout_ms: Option<u64>,
+    /// How long the demuxer may hold packets to reorder them (`max_delay`),
+    /// milliseconds.
+    pub max_delay_ms: Option<u64>,
+    /// Percent-encoded into the URL unless it carries credentials already.
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+impl NetInputOptions {
+    /// The demuxer options these stand for.
+    pub fn options(&self) -> Vec<(&'static str, String)> {
+        let mut options = Vec::new();
+        match self.transport {
+            RtspTransport::Auto => {}
+            RtspTransport::Tcp => options.push(("rtsp_transport", "tcp".to_string())),
+            RtspTransport::Udp => options.push(("rtsp_transport", "udp".to_string())),
+        }
+        if let Some(ms) = self.stimeout_ms {
+            options.push(("stimeout", (ms * 1000).to_string()));
+        }
+        if let Some(ms) = self.max_delay_ms {
+            options.push(("max_delay", (ms * 1000).to_string()));
+        }
+        options
+    }
+
+    /// The options to open the input with: [`options`](Self::options) with
+    /// `extra` merged over them; `None` when there are none.
+    pub fn dictionary(
+        &self,
+        extra: Option<&HashMap<String, String>>,
+    ) -> Option<Dictionary<'static>> {
+        let mut dictionary = Dictionary::new();
+        for (key, value) in self.options() {
+            dictionary.set(key, &value);
+        }
+        for (key, value) in extra.into_iter().flatten() {
+            dictionary.set(key, value);
+        }
+        dictionary.iter().next().is_some().then_some(dictionary)
+    }
+
+    /// `url` with [`user`](Self::user) and [`password`](Self::password) in
+    /// its authority; as it is without a user, or if it has one.
+    pub fn url(&self, url: &str) -> String {
+        let Some(user) = &self.user else {
+            return url.to_string();
+        };
+        let Some((scheme, rest)) = url.split_once("://") else {
+            return url.to_string();
+        };
+        let authority = rest.split('/').next().unwrap_or_default();
+        if authority.contains('@') {
+            return url.to_string();
+        }
+        let password = self
+            .password
+            .as_deref()
+            .map(|password| format!(":{}", percent_encode(password)))
+            .unwrap_or_default();
+        format!("{scheme}://{}{password}@{rest}", percent_encode(user))
+    }
 }
 
+/// `value` with everything but RFC 3986's unreserved characters
+/// percent-encoded, for the userinfo of a URL.
+fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+/// Where the bus reads from. `FileLoop` plays a file over and over with
+/// timestamps continuing across passes (demos and tests without a camera);
+/// `realtime` paces reading to the timestamps, like a live source. `Reader`
+/// demuxes, as `format`, the bytes of a stream the caller receives itself
+/// (e.g. MPEG-PS reassembled from RTP). `Push` takes frames the caller
+/// produces itself, through the [`PushInputHandle`] `add_input` returns (see
+/// [`crate::push`]).
 pub enum InputConfig {
-    Net { url: String },
-    File { path: String },
-    Device { display: String, format: String },
+    Net {
+        url: String,
+        /// Open the url again under this policy when the connection drops,
+        /// instead of ending the input (see
+        /// [`AvInputTask::start_with_retry`]); `None` ends it.
+        reconnect: Option<RetryPolicy>,
+        /// Transport, timeouts and credentials; the options `add_input` is
+        /// given are merged over them.
+        net: NetInputOptions,
+    },
+    File {
+        path: String,
+    },
+    /// The part of a file from `start` to `end`, offsets from its beginning
+    /// (see [`AvInput::range`]); `None` for the beginning or the end of the
+    /// file. Raw outputs leave out the frames before `start`.
+    FileRange {
+        path: String,
+        start: Option<std::time::Duration>,
+        end: Option<std::time::Duration>,
+    },
+    FileLoop {
+        path: String,
+        realtime: bool,
+    },
+    Device {
+        display: String,
+        format: String,
+    },
+    Reader {
+        open: ReaderFactory,
+        format: String,
+    },
+    Push {
+        width: u32,
+        height: u32,
+        pixel_format: ffmpeg_next::format::Pixel,
+        frame_rate: ffmpeg_next::Rational,
+        /// An audio stream besides the video.
+        audio: Option<PushAudio>,
+    },
 }
 
+/// Opens the byte stream of an [`InputConfig::Reader`], once per opening of
+/// the input. A read returning 0 bytes ends the input.
+pub type ReaderFactory =
+    Arc<dyn Fn() -> anyhow::Result<Box<dyn std::io::Read + Send>> + Send + Sync>;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OutputAvType {
     Video,
     Audio,
+    /// The first stream that is neither video nor audio (subtitles, KLV or
+    /// other timed metadata), always copied.
+    Data,
+    /// Every stream of the input. The video stream (else the audio, else the
+    /// first) is the primary; a File/Net/Hls/Segments output carries the
+    /// others alongside it, copied unless audio with an `audio_encode`.
+    All,
+}
+
+impl OutputAvType {
+    /// The stream of `streams` an output of this type is primarily about.
+    fn primary(self, streams: &[AvStream]) -> Option<&AvStream> {
+        match self {
+            OutputAvType::Video => streams.iter().find(|s| s.is_video()),
+            OutputAvType::Audio => streams.iter().find(|s| s.is_audio()),
+            OutputAvType::Data => streams.iter().find(|s| s.is_data()),
+            OutputAvType::All => streams
+                .iter()
+                .find(|s| s.is_video())
+                .or_else(|| streams.iter().find(|s| s.is_audio()))
+                .or_else(|| streams.first()),
+        }
+    }
+}
+
+/// Why [`Bus::add_output`] refused an output, for callers to downcast to
+/// (besides [`InvalidEncodeConfig`] and [`UnsupportedCodec`]). Whatever the
+/// call had started for the output is stopped again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddOutputError {
+    /// The input has no stream of the output's `av_type`.
+    StreamNotFound(OutputAvType),
+    /// The encoder of a stream to transcode would not open.
+    EncoderInit(String),
+    /// The file or URL to mux to would not open.
+    MuxOpen { url: String, cause: String },
+    /// The bus has an output of this id already.
+    AlreadyExists(String),
+}
+
+impl AddOutputError {
+    fn encoder_init(e: anyhow::Error) -> anyhow::Error {
+        Self::EncoderInit(format!("{e:#}")).into()
+    }
+}
+
+impl std::fmt::Display for AddOutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StreamNotFound(av_type) => write!(f, "the input has no {av_type:?} stream"),
+            Self::EncoderInit(cause) => write!(f, "encoder init failed: {cause}"),
+            Self::MuxOpen { url, cause } => write!(f, "cannot open {url}: {cause}"),
+            Self::AlreadyExists(id) => write!(f, "output {id} already exists"),
+        }
+    }
+}
+
+impl std::error::Error for AddOutputError {}
+
+/// What [`Bus::state_snapshot`] reports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BusStateSnapshot {
+    /// The output ids, sorted.
+    pub outputs: Vec<String>,
+    /// The input id and stream index of each decoder task, sorted.
+    pub decoder_tasks: Vec<(String, usize)>,
+    /// The input id and stream index of each encoder task (one per encode
+    /// config of a stream), sorted.
+    pub encoder_tasks: Vec<(String, usize)>,
 }
 
 pub struct OutputConfig {
     pub id: String,
+    /// The input whose streams the output carries, see
+    /// [`Bus::add_named_input`]. [`DEFAULT_INPUT`] unless set.
+    pub input_id: String,
     pub dest: OutputDest,
+    /// The primary stream. A muxing output of `Video` carries the audio
+    /// stream too with [`with_audio`](Self::with_audio).
     pub av_type: OutputAvType,
     /// Encode config for the primary (`av_type`) stream. `None` = copy.
     pub encode: Option<EncodeConfig>,
@@ -1358,20 +4159,69 @@ pub struct OutputConfig {
     pub audio_encode: Option<EncodeConfig>,
     /// When true, include both video and audio streams in File/Net outputs.
     pub include_audio: bool,
+    /// Raw video outputs only: deliver just this region of each frame. Snapped
+    /// to the chroma grid (even values for 4:2:0); the returned `AvStream`
+    /// carries the resulting size.
+    pub roi: Option<Rect>,
+    /// Raw outputs only: take the decoder's frames through a bounded queue
+    /// of their own, whose policy decides what a consumer falling behind
+    /// loses (see [`crate::frame_hub`]). `None` reads the decoder's
+    /// broadcast, and the stream ends when the consumer lags past it.
+    pub frame_queue: Option<FrameQueueConfig>,
+    /// Muxing outputs: flush the muxer after every keyframe and at least this
+    /// often, in milliseconds; 0 turns it off. `None` picks by destination:
+    /// [`STREAMING_FLUSH_EVERY`] for Net, Hls and Mux, off for File.
+    pub flush_every_ms: Option<u64>,
+    /// Demuxed outputs only: the codecs the consumer can take, preferred
+    /// first. The input's packets pass through when its codec is one of
+    /// them; otherwise the output carries those of an encoder to the first,
+    /// shared with every output encoding the same. Replaces `encode`, and
+    /// can be changed on the fly with [`Bus::renegotiate_output`].
+    pub acceptable_codecs: Option<Vec<ffmpeg_next::codec::Id>>,
+    /// Raw video outputs, and muxing or Encoded outputs encoding their
+    /// video: turn the frames as the input's display matrix says (see
+    /// [`AvStream::rotation_degrees`]) rather than passing the matrix on, for
+    /// consumers that ignore it. An output with an encode config then
+    /// transcodes a rotated input even when its params match; copied streams
+    /// keep the matrix.
+    pub auto_rotate: bool,
+    /// Demuxed video outputs: deliver H.264/H.265 as Annex B, with the
+    /// parameter sets in front of every keyframe, when the input carries it
+    /// length-prefixed (MP4 and the like; see [`crate::bsf::AnnexBFilter`]).
+    pub annexb: bool,
+    /// Muxing and Demuxed outputs: sees every packet just before it is
+    /// written (see [`crate::hook`]). Behind a mutex so the config stays
+    /// `Sync` with a hook that is only `Send`.
+    packet_hook: std::sync::Mutex<Option<PacketHook>>,
 }
 
 impl OutputConfig {
     pub fn new(id: String, av_type: OutputAvType, dest: OutputDest) -> Self {
         Self {
             id,
+            input_id: DEFAULT_INPUT.to_string(),
             dest,
             av_type,
             encode: None,
             audio_encode: None,
             include_audio: false,
+            roi: None,
+            frame_queue: None,
+            flush_every_ms: None,
+            acceptable_codecs: None,
+            auto_rotate: false,
+            annexb: false,
+            packet_hook: std::sync::Mutex::new(None),
         }
     }
 
+    /// Read the streams of input `input_id` rather than those of
+    /// [`DEFAULT_INPUT`].
+    pub fn with_input(mut self, input_id: String) -> Self {
+        self.input_id = input_id;
+        self
+    }
+
     pub fn with_encode(mut self, encode: EncodeConfig) -> Self {
         self.encode = Some(encode);
         self
@@ -1383,10 +4233,79 @@ impl OutputConfig {
         self
     }
 
+    /// Carry the input's audio stream next to the video one (File, Net, Hls
+    /// and Segments outputs); see [`OutputConfig::include_audio`].
     pub fn with_audio(mut self) -> Self {
         self.include_audio = true;
         self
     }
+
+    /// Crop Raw video frames to `roi` (see [`OutputConfig::roi`]).
+    pub fn with_roi(mut self, roi: Rect) -> Self {
+        self.roi = Some(roi);
+        self
+    }
+
+    /// Queue a Raw output's frames (see [`OutputConfig::frame_queue`]).
+    pub fn with_frame_queue(mut self, config: FrameQueueConfig) -> Self {
+        self.frame_queue = Some(config);
+        self
+    }
+
+    /// Set the muxer flush interval (see [`OutputConfig::flush_every_ms`]).
+    pub fn with_flush_every_ms(mut self, ms: u64) -> Self {
+        self.flush_every_ms = Some(ms);
+        self
+    }
+
+    /// Negotiate a Demuxed output's codec (see
+    /// [`OutputConfig::acceptable_codecs`]).
+    pub fn with_acceptable_codecs(mut self, codecs: Vec<ffmpeg_next::codec::Id>) -> Self {
+        self.acceptable_codecs = Some(codecs);
+        self
+    }
+
+    /// Turn rotated video upright (see [`OutputConfig::auto_rotate`]).
+    pub fn with_auto_rotate(mut self) -> Self {
+        self.auto_rotate = true;
+        self
+    }
+
+    /// Deliver a Demuxed output's video as Annex B (see
+    /// [`OutputConfig::annexb`]).
+    pub fn with_annexb(mut self) -> Self {
+        self.annexb = true;
+        self
+    }
+
+    /// Run `hook` on every packet of a muxing or Demuxed output just before
+    /// it is written (see [`crate::hook`]).
+    pub fn with_packet_hook(mut self, hook: PacketHook) -> Self {
+        self.packet_hook = std::sync::Mutex::new(Some(hook));
+        self
+    }
+
+    /// Degrees clockwise this output turns the video of `stream`.
+    fn rotation(&self, stream: &AvStream) -> u32 {
+        if self.auto_rotate && stream.is_video() {
+            stream.rotation_degrees()
+        } else {
+            0
+        }
+    }
+
+    fn flush_every(&self) -> Option<std::time::Duration> {
+        match self.flush_every_ms {
+            Some(0) => None,
+            Some(ms) => Some(std::time::Duration::from_millis(ms)),
+            None => match self.dest {
+                OutputDest::Net { .. } | OutputDest::Hls { .. } | OutputDest::Mux { .. } => {
+                    Some(STREAMING_FLUSH_EVERY)
+                }
+                _ => None,
+            },
+        }
+    }
 }
 
 pub enum OutputDest {
@@ -1394,10 +4313,40 @@ pub enum OutputDest {
     ///! eg: rtmp://localhost:1935/live/stream
     ///! eg: rtsp://host:8554/path
     ///! format: e.g. "rtsp", "flv" (required for URL-only outputs; None = guess from URL)
-    Net { url: String, format: Option<String> },
+    ///! open_policy: when the URL is opened (see [`OpenPolicy`])
+    ///! reconnect: how a connection that breaks while writing (the ingest
+    ///! restarting, a relay dropping the publisher) is opened again, from the
+    ///! next keyframe; `None` fails the output instead
+    Net {
+        url: String,
+        format: Option<String>,
+        open_policy: OpenPolicy,
+        reconnect: Option<RetryPolicy>,
+    },
     /// Mux to a file (seekable). Produces standard MP4 that any player can open.
     File { path: String },
-    /// Raw video frames (only support decode, no encoding)
+    /// HLS media playlist at `path` (e.g. `/data/hls/720p/index.m3u8`), with
+    /// MPEG-TS segments written next to it. Segments are cut on keyframes at
+    /// least `segment_seconds` apart, so set `EncodeConfig::keyframe_interval`
+    /// to fps * segment_seconds for evenly sized segments. `list_size` = number
+    /// of segments kept in the playlist (0 = keep all, VOD style).
+    Hls {
+        path: String,
+        segment_seconds: u32,
+        list_size: u32,
+    },
+    /// Recording cut into MP4 (or whatever `pattern`'s extension names)
+    /// files in `dir`, a new one at the first keyframe `segment_seconds` or
+    /// more into the current one, so each plays on its own. Files are named
+    /// by the strftime `pattern` (e.g. `rec_%Y%m%d_%H%M%S.mp4`) with the
+    /// local time of their first frame; see [`crate::segment`].
+    Segments {
+        dir: String,
+        pattern: String,
+        segment_seconds: u32,
+    },
+    /// Decoded raw frames of the output's `av_type` (only support decode, no
+    /// encoding): `RawOutputStream::Video` or `RawOutputStream::Audio`.
     Raw,
     /// Mux to a stream (no seekable)
     Mux { format: String },
@@ -1411,6 +4360,162 @@ pub enum OutputDest {
     Demuxed,
 }
 
+/// When a `Net` output connects to its URL.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum OpenPolicy {
+    /// Open while the output is added; `add_output` fails if the remote is
+    /// not reachable.
+    #[default]
+    Immediate,
+    /// Add the output at once and connect from the mux task once the first
+    /// keyframe arrives, retrying per `retry`. Packets from the latest
+    /// keyframe on are held meanwhile, so the stream starts at a keyframe.
+    /// When the retries run out the output ends with
+    /// [`BusEvent::OutputFailed`].
+    Lazy { retry: RetryPolicy },
+}
+
+/// Capped exponential backoff between connection attempts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts before giving up, including the first (at least 1).
+    pub max_attempts: u32,
+    pub initial_backoff: std::time::Duration,
+    pub max_backoff: std::time::Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            initial_backoff: std::time::Duration::from_millis(500),
+            max_backoff: std::time::Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay after failed attempt number `attempt` (1-based).
+    pub fn backoff(&self, attempt: u32) -> std::time::Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1).min(16));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Something that happened to a bus output after it was added.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusEvent {
+    /// The output stopped for good: a lazy `Net` output ran out of
+    /// connection attempts, or a write failed in a way writing on would not
+    /// fix (see [`WriteErrorKind::is_fatal`]). `kind` is `None` when the
+    /// failure was not a write.
+    OutputFailed {
+        id: String,
+        error: String,
+        kind: Option<WriteErrorKind>,
+    },
+    /// A `Net` output's connection broke while writing. It reconnects from
+    /// the next keyframe under its `reconnect` policy, and fails if that
+    /// runs out.
+    OutputInterrupted {
+        id: String,
+        error: String,
+        kind: WriteErrorKind,
+    },
+    /// The interrupted output is connected again, after `attempts` tries,
+    /// and writes on from a keyframe.
+    OutputReconnected { id: String, attempts: u32 },
+    /// The encoder of input stream `stream_index` kept rejecting frames in
+    /// format `from` and now converts each frame from its actual format to
+    /// its own (`to`); `downconverted` when that drops bits per sample, e.g.
+    /// a 10-bit source encoded as 8-bit.
+    EncoderRecovered {
+        stream_index: usize,
+        encoder: String,
+        from: String,
+        to: String,
+        downconverted: bool,
+    },
+    /// The encoder of input stream `stream_index` kept rejecting frames in
+    /// `input_format` and could not be recovered, so it stopped, ending the
+    /// outputs it fed.
+    EncoderFailed {
+        stream_index: usize,
+        encoder: String,
+        input_format: String,
+        encoder_format: String,
+        error: String,
+    },
+    /// Audio of input stream `stream_index` stopped for `duration_ms` from
+    /// `at_ms` (stream time), longer than [`GapFillConfig::max_fill`], so the
+    /// hole was left in the timeline. Only with
+    /// [`BusOptions::audio_gap_fill`].
+    AudioGap {
+        stream_index: usize,
+        at_ms: i64,
+        duration_ms: u64,
+    },
+    /// A negotiated output switched, at a keyframe, to packets in `codec`
+    /// after [`Bus::renegotiate_output`]; `transcoded` when they come from an
+    /// encoder rather than the input.
+    OutputRenegotiated {
+        id: String,
+        codec: String,
+        transcoded: bool,
+    },
+    /// A `task` loop (`name`: its stream or output) has been in one FFmpeg
+    /// call for `stalled_ms`, longer than its [`WatchdogConfig`] threshold.
+    /// Only with [`BusOptions::watchdog`].
+    TaskStalled {
+        task: TaskKind,
+        name: String,
+        stalled_ms: u64,
+    },
+    /// The stalled loop was replaced: a decoder or encoder rebuilt, a `Net`
+    /// output reconnecting. `recoveries` counts them for this task.
+    TaskRecovered {
+        task: TaskKind,
+        name: String,
+        recoveries: u32,
+    },
+    /// The stalled task could not be recovered, or stalled again after
+    /// [`WatchdogConfig::max_recoveries`], and stopped, ending what it fed.
+    TaskFailed {
+        task: TaskKind,
+        name: String,
+        error: String,
+    },
+    /// Input `input` was opened; `streams` names the codec of each of its
+    /// streams, in index order.
+    InputOpened { input: String, streams: Vec<String> },
+    /// Input `input` could not be opened, or lost its connection and ran out
+    /// of attempts to reconnect (see [`InputConfig::Net`]).
+    InputError { input: String, error: String },
+    /// Input `input` was read to its end. Not sent when the bus stops it.
+    InputEof { input: String },
+    /// A `File`, `Net`, `Hls` or `Segments` output was added and its mux
+    /// task started.
+    OutputStarted { id: String },
+    /// That output's mux wrote its trailer and ended, its sources done or
+    /// the output removed; one that fails sends
+    /// [`BusEvent::OutputFailed`] instead.
+    OutputFinished { id: String },
+    /// A `Segments` output wrote the trailer of file `path`, on moving to the
+    /// next file or at its end (before [`BusEvent::OutputFinished`]).
+    /// `start_ms` is the wall clock (unix ms) at its first packet and
+    /// `duration_ms` how long it plays.
+    SegmentClosed {
+        id: String,
+        path: String,
+        start_ms: i64,
+        duration_ms: u64,
+    },
+    /// Output `id` fell behind its source and lost `count` packets.
+    PacketLagged { id: String, count: u64 },
+}
+
 #[derive(Clone, Debug)]
 pub struct EncodeConfig {
     // "h264", "hevc", "rawvideo", "aac", "opus"
@@ -1431,6 +4536,11 @@ pub struct EncodeConfig {
     pub channels: Option<u32>,
     // Audio: bitrate in bps (e.g. 128000)
     pub audio_bitrate: Option<u64>,
+    // Video: GOP length in frames, forced (scene-cut keyframes disabled) so
+    // outputs cut on the same frames. None = encoder default cadence.
+    pub keyframe_interval: Option<u32>,
+    // Video: most consecutive B-frames. None or 0 = none (low-latency tune).
+    pub b_frames: Option<u32>,
 }
 
 impl Default for EncodeConfig {
@@ -1445,6 +4555,8 @@ impl Default for EncodeConfig {
             sample_rate: None,
             channels: None,
             audio_bitrate: None,
+            keyframe_interval: None,
+            b_frames: None,
         }
     }
 }
@@ -1460,6 +4572,8 @@ impl PartialEq for EncodeConfig {
             && self.sample_rate == other.sample_rate
             && self.channels == other.channels
             && self.audio_bitrate == other.audio_bitrate
+            && self.keyframe_interval == other.keyframe_interval
+            && self.b_frames == other.b_frames
     }
 }
 
@@ -1476,6 +4590,8 @@ impl std::hash::Hash for EncodeConfig {
         self.sample_rate.hash(state);
         self.channels.hash(state);
         self.audio_bitrate.hash(state);
+        self.keyframe_interval.hash(state);
+        self.b_frames.hash(state);
     }
 }
 
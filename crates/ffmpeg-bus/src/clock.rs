@@ -0,0 +1,105 @@
+//! Source clock offset: how far a camera's wall clock is from ours.
+//!
+//! RTP senders periodically send RTCP sender reports (SR) pairing an NTP wall
+//! clock time with an RTP timestamp. FFmpeg's RTSP demuxer turns the first SR
+//! it receives into `AVFormatContext::start_time_realtime` (the sender's wall
+//! clock at pts 0), so every later packet's sender wall clock time is that
+//! plus its pts. Comparing it with our clock at the time the packet is read
+//! gives `offset - network delay`; the largest of a window of samples is the
+//! least delayed one and so the best offset estimate.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ffmpeg_next::Rational;
+
+/// Seconds from the NTP epoch (1900-01-01) to the Unix epoch.
+pub const NTP_UNIX_EPOCH_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// Samples kept by [`OffsetEstimator`].
+const WINDOW: usize = 256;
+
+/// A 64-bit NTP timestamp (32.32 fixed point seconds since 1900) as Unix
+/// microseconds.
+pub fn ntp_to_unix_micros(ntp: u64) -> i64 {
+    let secs = (ntp >> 32) as i64 - NTP_UNIX_EPOCH_OFFSET_SECS as i64;
+    let frac = ((ntp & 0xFFFF_FFFF) * 1_000_000) >> 32;
+    secs * 1_000_000 + frac as i64
+}
+
+/// The NTP/RTP timestamp pair of an RTCP sender report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderReport {
+    pub ntp: u64,
+    pub rtp_timestamp: u32,
+    /// RTP clock rate of the stream (e.g. 90000 for video).
+    pub clock_rate: u32,
+}
+
+impl SenderReport {
+    /// Sender wall clock (Unix microseconds) of a packet with `rtp_timestamp`.
+    /// RTP timestamps wrap; packets up to half the range either side of the
+    /// report are placed correctly.
+    pub fn wallclock_micros(&self, rtp_timestamp: u32) -> i64 {
+        let delta = rtp_timestamp.wrapping_sub(self.rtp_timestamp) as i32 as i64;
+        ntp_to_unix_micros(self.ntp) + delta * 1_000_000 / i64::from(self.clock_rate.max(1))
+    }
+}
+
+/// Sender wall clock (Unix microseconds) of a demuxed packet, from the
+/// input's `start_time_realtime` and the packet's pts.
+pub fn packet_wallclock_micros(start_time_realtime: i64, pts: i64, time_base: Rational) -> i64 {
+    let num = i64::from(time_base.numerator());
+    let den = i64::from(time_base.denominator().max(1));
+    start_time_realtime + pts.saturating_mul(num).saturating_mul(1_000_000) / den
+}
+
+/// Our wall clock, Unix microseconds.
+pub fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+/// An estimated source clock offset (source minus ours).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOffset {
+    pub offset_ms: i64,
+    /// Samples the estimate is based on.
+    pub samples: usize,
+    /// When the latest sample was taken, Unix milliseconds.
+    pub measured_at_ms: i64,
+}
+
+/// Running offset estimate over the last [`WINDOW`] samples.
+#[derive(Debug, Default)]
+pub struct OffsetEstimator {
+    samples: VecDeque<i64>,
+    measured_at_us: i64,
+}
+
+impl OffsetEstimator {
+    /// Record one packet: the sender's wall clock time for it and ours when
+    /// it arrived, both Unix microseconds.
+    pub fn observe(&mut self, source_us: i64, local_us: i64) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(source_us - local_us);
+        self.measured_at_us = local_us;
+    }
+
+    pub fn estimate(&self) -> Option<ClockOffset> {
+        let best = self.samples.iter().copied().max()?;
+        Some(ClockOffset {
+            offset_ms: best.div_euclid(1000),
+            samples: self.samples.len(),
+            measured_at_ms: self.measured_at_us / 1000,
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "clock_test.rs"]
+mod clock_test;
@@ -0,0 +1,77 @@
+use super::*;
+
+/// 2024-01-01T00:00:00Z.
+const JAN_2024_UNIX: u64 = 1_704_067_200;
+
+fn ntp(unix_secs: u64, frac: u32) -> u64 {
+    ((unix_secs + NTP_UNIX_EPOCH_OFFSET_SECS) << 32) | u64::from(frac)
+}
+
+#[test]
+fn ntp_timestamps_convert_to_unix_micros() {
+    assert_eq!(ntp_to_unix_micros(ntp(0, 0)), 0);
+    assert_eq!(
+        ntp_to_unix_micros(ntp(JAN_2024_UNIX, 0x8000_0000)),
+        JAN_2024_UNIX as i64 * 1_000_000 + 500_000
+    );
+}
+
+#[test]
+fn sender_report_maps_rtp_timestamps_across_wraparound() {
+    let sr = SenderReport {
+        ntp: ntp(JAN_2024_UNIX, 0),
+        rtp_timestamp: u32::MAX - 44_999,
+        clock_rate: 90_000,
+    };
+    let base = JAN_2024_UNIX as i64 * 1_000_000;
+    assert_eq!(sr.wallclock_micros(u32::MAX - 44_999), base);
+    // 90000 ticks later, after the 32-bit timestamp wrapped.
+    assert_eq!(sr.wallclock_micros(45_000), base + 1_000_000);
+    // Packets from just before the report.
+    assert_eq!(sr.wallclock_micros(u32::MAX - 134_999), base - 1_000_000);
+}
+
+#[test]
+fn packet_wallclock_adds_pts_to_start_time_realtime() {
+    let start = JAN_2024_UNIX as i64 * 1_000_000;
+    assert_eq!(
+        packet_wallclock_micros(start, 45_000, Rational(1, 90_000)),
+        start + 500_000
+    );
+}
+
+#[test]
+fn offset_is_the_least_delayed_sample() {
+    // Camera 3 s ahead; packets arrive 40..=120 ms after they were stamped.
+    let sr = SenderReport {
+        ntp: ntp(JAN_2024_UNIX + 3, 0),
+        rtp_timestamp: 0,
+        clock_rate: 90_000,
+    };
+    let local_start = JAN_2024_UNIX as i64 * 1_000_000;
+    let mut est = OffsetEstimator::default();
+    assert_eq!(est.estimate(), None);
+    for (i, delay_ms) in [120, 40, 80, 95].into_iter().enumerate() {
+        let rtp = i as u32 * 3_600; // 25 fps
+        let sent_local = local_start + i as i64 * 40_000;
+        est.observe(sr.wallclock_micros(rtp), sent_local + delay_ms * 1000);
+    }
+    let offset = est.estimate().unwrap();
+    assert_eq!(offset.offset_ms, 3_000 - 40);
+    assert_eq!(offset.samples, 4);
+    assert_eq!(
+        offset.measured_at_ms,
+        (local_start + 3 * 40_000 + 95_000) / 1000
+    );
+}
+
+#[test]
+fn estimator_forgets_old_samples() {
+    let mut est = OffsetEstimator::default();
+    est.observe(10_000_000, 0);
+    for i in 0..WINDOW as i64 {
+        est.observe(i, i);
+    }
+    assert_eq!(est.estimate().unwrap().offset_ms, 0);
+    assert_eq!(est.estimate().unwrap().samples, WINDOW);
+}
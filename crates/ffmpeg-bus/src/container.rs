@@ -0,0 +1,200 @@
+//! Codec-copy compatibility of output containers. Copying a camera's
+//! streams into whatever container an output names fails late and
+//! confusingly otherwise: the muxer only refuses the codec in
+//! `write_header`, after the file or connection was opened (A-law audio into
+//! FLV or MP4, HEVC into FLV).
+//!
+//! [`container_supports`] encodes the common matrix (MP4/MOV, Matroska,
+//! WebM, FLV, MPEG-TS and HLS, ADTS, raw elementary streams); the bus
+//! consults it before opening a muxing output, and [`AvOutput`] applies the
+//! stream tag a container needs (`hvc1` for HEVC in MP4). Containers outside
+//! the matrix (RTSP, ...) report [`SupportLevel::Native`]: their muxer is
+//! left to judge.
+//!
+//! [`AvOutput`]: crate::output::AvOutput
+
+use std::ffi::{CStr, CString};
+
+use ffmpeg_next::codec::Id;
+
+/// How a container takes a codec's packets as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupportLevel {
+    Native,
+    /// Only under this stream tag (fourcc), e.g. `hvc1`.
+    WithTag(&'static str),
+    /// Not at all: the stream has to be transcoded.
+    Unsupported,
+}
+
+/// Containers of the matrix, by what they take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    Mp4,
+    Mov,
+    Matroska,
+    WebM,
+    Flv,
+    MpegTs,
+    Adts,
+    /// A raw elementary stream of one codec (`h264`, `alaw`, ...).
+    Raw(Id),
+}
+
+fn family(format: &str) -> Option<Family> {
+    Some(match format.to_ascii_lowercase().as_str() {
+        "mp4" | "m4a" | "m4v" | "ipod" | "ismv" => Family::Mp4,
+        "mov" => Family::Mov,
+        "matroska" | "mkv" => Family::Matroska,
+        "webm" => Family::WebM,
+        "flv" | "live_flv" => Family::Flv,
+        // HLS segments are MPEG-TS.
+        "mpegts" | "ts" | "hls" => Family::MpegTs,
+        "adts" | "aac" => Family::Adts,
+        "h264" => Family::Raw(Id::H264),
+        "hevc" | "h265" => Family::Raw(Id::HEVC),
+        "mjpeg" => Family::Raw(Id::MJPEG),
+        "mp3" => Family::Raw(Id::MP3),
+        "opus" => Family::Raw(Id::OPUS),
+        "alaw" => Family::Raw(Id::PCM_ALAW),
+        "mulaw" => Family::Raw(Id::PCM_MULAW),
+        _ => return None,
+    })
+}
+
+/// Whether muxer `format` (a muxer name such as `mp4`, `flv` or `mpegts`)
+/// takes copied `codec_id` packets. Unknown formats report `Native`.
+pub fn container_supports(format: &str, codec_id: Id) -> SupportLevel {
+    use SupportLevel::{Native, Unsupported, WithTag};
+
+    let Some(family) = family(format) else {
+        return Native;
+    };
+    match (family, codec_id) {
+        // Everything has a CodecID or an ACM/VfW mapping in Matroska.
+        (Family::Matroska, _) => Native,
+
+        // `hev1` (parameter sets in-band) is what FFmpeg writes by default;
+        // Apple players only take `hvc1`.
+        (Family::Mp4 | Family::Mov, Id::HEVC) => WithTag("hvc1"),
+        (Family::Mp4, Id::MJPEG) => WithTag("mp4v"),
+        (Family::Mov, Id::MJPEG) => WithTag("jpeg"),
+        (
+            Family::Mp4 | Family::Mov,
+            Id::H264
+            | Id::AV1
+            | Id::VP9
+            | Id::MPEG4
+            | Id::MPEG2VIDEO
+            | Id::AAC
+            | Id::MP3
+            | Id::OPUS
+            | Id::FLAC
+            | Id::ALAC
+            | Id::AC3
+            | Id::EAC3,
+        ) => Native,
+        // PCM is QuickTime only.
+        (Family::Mov, Id::PCM_S16LE | Id::PCM_S16BE | Id::PCM_ALAW | Id::PCM_MULAW) => Native,
+
+        (Family::WebM, Id::VP8 | Id::VP9 | Id::AV1 | Id::OPUS | Id::VORBIS) => Native,
+
+        // Classic FLV only: enhanced FLV (HEVC, AV1, Opus) and G.711 are
+        // refused by most RTMP servers and players.
+        (
+            Family::Flv,
+            Id::H264 | Id::FLV1 | Id::VP6F | Id::AAC | Id::MP3 | Id::SPEEX | Id::NELLYMOSER,
+        ) => Native,
+
+        (
+            Family::MpegTs,
+            Id::H264
+            | Id::HEVC
+            | Id::MPEG2VIDEO
+            | Id::MPEG4
+            | Id::AAC
+            | Id::MP3
+            | Id::MP2
+            | Id::AC3
+            | Id::EAC3
+            | Id::OPUS,
+        ) => Native,
+
+        (Family::Adts, Id::AAC) => Native,
+        (Family::Raw(raw), codec) if raw == codec => Native,
+
+        _ => Unsupported,
+    }
+}
+
+/// The codec (an [`EncodeConfig::codec`](crate::bus::EncodeConfig::codec)
+/// name) to transcode a stream the container `format` refuses into; `None`
+/// when there is no sensible default (raw streams of another codec).
+pub fn transcode_target(format: &str, video: bool) -> Option<&'static str> {
+    match (family(format)?, video) {
+        (Family::WebM, true) => Some("vp9"),
+        (Family::WebM, false) => Some("opus"),
+        (Family::Adts, false) => Some("aac"),
+        (Family::Raw(_) | Family::Adts, _) => None,
+        (_, true) => Some("h264"),
+        (_, false) => Some("aac"),
+    }
+}
+
+/// The muxer FFmpeg picks for `filename` from its extension, as for an
+/// output opened without a format (`out.mp4` is `mp4`).
+pub fn guess_format(filename: &str) -> Option<String> {
+    let filename = CString::new(filename).ok()?;
+    // SAFETY: a valid C string in; the returned format is static.
+    unsafe {
+        let format = ffmpeg_next::ffi::av_guess_format(
+            std::ptr::null(),
+            filename.as_ptr(),
+            std::ptr::null(),
+        );
+        if format.is_null() || (*format).name.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*format).name)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// A muxing output refused up front: its `container` takes no `codec`
+/// stream. What [`Bus::add_output`](crate::bus::Bus::add_output) fails
+/// with, so callers can downcast for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedCodec {
+    pub codec: Id,
+    pub container: String,
+}
+
+impl std::fmt::Display for UnsupportedCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} cannot go into {}: transcode it to a codec the container takes, \
+             or pick another container",
+            self.codec.name(),
+            self.container
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedCodec {}
+
+/// The little-endian fourcc of `tag`, as stored in `codec_tag`.
+pub(crate) fn fourcc(tag: &str) -> u32 {
+    let mut bytes = [b' '; 4];
+    for (byte, tag) in bytes.iter_mut().zip(tag.bytes()) {
+        *byte = tag;
+    }
+    u32::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+#[path = "container_test.rs"]
+mod container_test;
@@ -0,0 +1,67 @@
+use super::*;
+
+#[test]
+fn matrix_of_common_combinations() {
+    use SupportLevel::{Native, Unsupported, WithTag};
+    let cases = [
+        ("mp4", Id::H264, Native),
+        ("mp4", Id::HEVC, WithTag("hvc1")),
+        ("mp4", Id::MJPEG, WithTag("mp4v")),
+        ("mp4", Id::AAC, Native),
+        ("mp4", Id::PCM_ALAW, Unsupported),
+        ("mov", Id::PCM_ALAW, Native),
+        ("matroska", Id::PCM_ALAW, Native),
+        ("mkv", Id::MJPEG, Native),
+        ("webm", Id::H264, Unsupported),
+        ("flv", Id::H264, Native),
+        ("flv", Id::HEVC, Unsupported),
+        ("flv", Id::PCM_ALAW, Unsupported),
+        ("mpegts", Id::HEVC, Native),
+        ("hls", Id::AAC, Native),
+        ("mpegts", Id::PCM_MULAW, Unsupported),
+        ("adts", Id::AAC, Native),
+        ("adts", Id::MP3, Unsupported),
+        ("h264", Id::H264, Native),
+        ("h264", Id::HEVC, Unsupported),
+        // Outside the matrix: left to the muxer.
+        ("rtsp", Id::PCM_ALAW, Native),
+    ];
+    for (format, codec, level) in cases {
+        assert_eq!(
+            container_supports(format, codec),
+            level,
+            "{} in {format}",
+            codec.name()
+        );
+    }
+}
+
+#[test]
+fn transcode_targets_fit_their_container() {
+    for format in ["mp4", "flv", "mpegts", "webm"] {
+        for video in [true, false] {
+            let target = transcode_target(format, video).unwrap();
+            let id = match target {
+                "h264" => Id::H264,
+                "aac" => Id::AAC,
+                "vp9" => Id::VP9,
+                "opus" => Id::OPUS,
+                other => panic!("unexpected target {other}"),
+            };
+            assert_ne!(container_supports(format, id), SupportLevel::Unsupported);
+        }
+    }
+    assert_eq!(transcode_target("adts", false), Some("aac"));
+    assert_eq!(transcode_target("h264", true), None);
+    assert_eq!(transcode_target("rtsp", true), None);
+}
+
+#[test]
+fn formats_guessed_from_file_names() {
+    crate::init().unwrap();
+    assert_eq!(guess_format("clip.mp4").as_deref(), Some("mp4"));
+    assert_eq!(guess_format("/tmp/rec.mkv").as_deref(), Some("matroska"));
+    assert_eq!(guess_format("seg.ts").as_deref(), Some("mpegts"));
+    assert_eq!(guess_format("no-extension"), None);
+    assert_eq!(fourcc("hvc1"), u32::from_le_bytes(*b"hvc1"));
+}
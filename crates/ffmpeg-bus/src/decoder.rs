@@ -1,4 +1,4 @@
-use std::{backtrace::Backtrace, time::Duration};
+use std::{backtrace::Backtrace, sync::Arc, time::Duration};
 
 use ffmpeg_next::Rational;
 use tokio_util::sync::CancellationToken;
@@ -6,10 +6,14 @@ use tokio_util::sync::CancellationToken;
 use crate::{
     frame::{
         RawAudioFrame, RawFrame, RawFrameCmd, RawFrameReceiver, RawFrameSender, RawVideoFrame,
+        download, is_hw_format,
     },
-    hw,
+    frame_hub::{FrameHub, FrameQueue, FrameQueueConfig},
+    hw::{self, HwDevice, HwPreference},
     packet::{RawPacket, RawPacketCmd, RawPacketReceiver},
     stream::AvStream,
+    teardown::StageTasks,
+    watchdog::{Progress, TaskKind, Watchdog, WatchedLoop},
 };
 
 /// Decoder output ring-buffer size. Balances memory vs avoiding Lagged
@@ -17,26 +21,41 @@ use crate::{
 /// as the backpressure high-water mark in lossless mode.
 const FRAME_CHAN_CAP: usize = 16;
 
+/// Consecutive `receive_frame` errors tolerated while flushing at EOF before
+/// the decoder's tail is given up on.
+const FLUSH_ERROR_LIMIT: u32 = 8;
+
 /// Send a decoded frame downstream. In `lossless` mode (file/net transcode),
 /// wait for ring-buffer room so a fast producer (e.g. a whole file decoded in a
 /// burst) does not overwrite unconsumed frames. Realtime sources keep the
 /// buffer near-empty, so this never actually waits for them. Not lossless:
 /// send immediately (old behaviour), dropping the oldest if consumers lag.
+/// The queues of the frame hub deal with a full queue as their own policy
+/// says.
 fn send_frame_backpressure(
-    sender: &RawFrameSender,
+    sender: &FrameOut,
     cancel: &CancellationToken,
     lossless: bool,
     msg: RawFrameCmd,
 ) {
     if lossless {
-        while sender.len() >= FRAME_CHAN_CAP
-            && sender.receiver_count() > 0
+        while sender.chan.len() >= FRAME_CHAN_CAP
+            && sender.chan.receiver_count() > 0
             && !cancel.is_cancelled()
         {
             std::thread::sleep(Duration::from_millis(2));
         }
     }
-    let _ = sender.send(msg);
+    sender.hub.send(msg.clone(), cancel);
+    let _ = sender.chan.send(msg);
+}
+
+/// Where a decoder's frames go: its broadcast channel, which encoders and
+/// mixers read, and its [`FrameHub`], whose queues feed Raw outputs.
+#[derive(Clone)]
+struct FrameOut {
+    chan: RawFrameSender,
+    hub: FrameHub,
 }
 
 enum DecoderType {
@@ -85,6 +104,10 @@ impl DecoderType {
             DecoderType::Video(video_decoder) => {
                 let mut frame = ffmpeg_next::frame::Video::empty();
                 match video_decoder.receive_frame(&mut frame) {
+                    // Downstream scales and encodes frames in system memory.
+                    Ok(()) if is_hw_format(frame.format()) => Ok(Some(RawFrame::Video(
+                        RawVideoFrame::from(download(&frame)?),
+                    ))),
                     Ok(()) => Ok(Some(RawFrame::Video(RawVideoFrame::from(frame)))),
                     Err(ffmpeg_next::Error::Eof) => Ok(None),
                     Err(ffmpeg_next::Error::Other { errno })
@@ -112,8 +135,18 @@ impl DecoderType {
     }
 }
 
+/// How a [`Decoder`] decodes, see
+/// [`BusOptions::decoder`](crate::bus::BusOptions::decoder).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecoderSettings {
+    /// Hardware or software decoding of video; audio always decodes in
+    /// software.
+    pub hw: HwPreference,
+}
+
 pub struct Decoder {
     stream: AvStream,
+    settings: DecoderSettings,
     inner: DecoderType,
     decoder_time_base: Rational,
     /// True while decoding on a hardware codec; cleared after a runtime
@@ -136,6 +169,28 @@ impl Decoder {
         Ok((video_decoder, decoder_time_base))
     }
 
+    /// Open the decoder [`hw::find_hw_decoder`] picks for `device_type`, on
+    /// a device opened on `device`.
+    fn open_device_video(
+        stream: &AvStream,
+        device_type: &str,
+        device: Option<&str>,
+    ) -> anyhow::Result<(ffmpeg_next::codec::decoder::Video, Rational, String)> {
+        let codec_id = stream.parameters().id();
+        let codec = hw::find_hw_decoder(codec_id, device_type)
+            .ok_or_else(|| anyhow::anyhow!("no {device_type} decoder for {codec_id:?}"))?;
+        let device = HwDevice::open(device_type, device)?;
+        let mut decoder_ctx = ffmpeg_next::codec::Context::new_with_codec(codec);
+        unsafe {
+            (*decoder_ctx.as_mut_ptr()).time_base = stream.time_base().into();
+        }
+        decoder_ctx.set_parameters(stream.parameters().clone())?;
+        device.attach(&mut decoder_ctx)?;
+        let video_decoder = decoder_ctx.decoder().video()?;
+        let time_base = video_decoder.time_base();
+        Ok((video_decoder, time_base, codec.name().to_string()))
+    }
+
     /// Open the default (software) decoder for this video stream, bypassing all
     /// hardware candidates. Used as the ultimate fallback in [`Decoder::new`]
     /// and for the runtime downgrade when a hardware decoder fails mid-stream.
@@ -152,68 +207,113 @@ impl Decoder {
         Ok((video_decoder, time_base))
     }
 
-    pub fn new(stream: &AvStream) -> anyhow::Result<Self> {
-        let s = if stream.is_video() {
-            let mut selected_name = "default".to_string();
-            let mut selected_is_hw = false;
-            let mut first_hw_failure: Option<String> = None;
-            let mut opened: Option<(ffmpeg_next::codec::decoder::Video, Rational)> = None;
-            for candidate in hw::video_decoder_candidates(stream.parameters().id()) {
-                let Some(codec) = ffmpeg_next::decoder::find_by_name(&candidate.name) else {
-                    continue;
-                };
-                match Self::open_video_decoder_with_codec(stream, codec) {
-                    Ok(v) => {
-                        selected_name = candidate.name.clone();
-                        selected_is_hw = candidate.is_hw;
-                        opened = Some(v);
-                        break;
-                    }
-                    Err(e) => {
-                        if candidate.is_hw && first_hw_failure.is_none() {
-                            first_hw_failure =
-                                Some(format!("{} open failed: {}", candidate.name, e));
-                        }
-                        log::info!(
-                            "video decoder candidate rejected: name={}, hw={}, reason={}",
-                            candidate.name,
-                            candidate.is_hw,
-                            e
-                        );
-                    }
+    /// Open the first of [`hw::video_decoder_candidates`] that opens, else
+    /// the default software decoder.
+    fn open_first_candidate(
+        stream: &AvStream,
+    ) -> anyhow::Result<(ffmpeg_next::codec::decoder::Video, Rational, bool)> {
+        let mut selected_name = "default".to_string();
+        let mut selected_is_hw = false;
+        let mut first_hw_failure: Option<String> = None;
+        let mut opened: Option<(ffmpeg_next::codec::decoder::Video, Rational)> = None;
+        for candidate in hw::video_decoder_candidates(stream.parameters().id()) {
+            let Some(codec) = ffmpeg_next::decoder::find_by_name(&candidate.name) else {
+                continue;
+            };
+            match Self::open_video_decoder_with_codec(stream, codec) {
+                Ok(v) => {
+                    selected_name = candidate.name.clone();
+                    selected_is_hw = candidate.is_hw;
+                    opened = Some(v);
+                    break;
                 }
-            }
-            if opened.is_none() {
-                // ultimate software fallback: default codec from stream parameters
-                opened = Some(Self::open_software_video(stream)?);
-            }
-            let (video_decoder, decoder_time_base) =
-                opened.ok_or_else(|| anyhow::anyhow!("unable to open video decoder"))?;
-            if selected_is_hw {
-                log::info!(
-                    "video decoder selected: {} (hardware), stream_index={}",
-                    selected_name,
-                    stream.index()
-                );
-            } else {
-                if let Some(reason) = first_hw_failure {
-                    log::info!(
-                        "hardware decode unavailable, fallback to software: {}",
-                        reason
+                Err(e) => {
+                    if candidate.is_hw && first_hw_failure.is_none() {
+                        first_hw_failure = Some(format!("{} open failed: {}", candidate.name, e));
+                    }
+                    tracing::info!(
+                        "video decoder candidate rejected: name={}, hw={}, reason={}",
+                        candidate.name,
+                        candidate.is_hw,
+                        e
                     );
                 }
-                log::info!(
-                    "video decoder selected: {} (software), stream_index={}",
-                    selected_name,
-                    stream.index()
+            }
+        }
+        if opened.is_none() {
+            // ultimate software fallback: default codec from stream parameters
+            opened = Some(Self::open_software_video(stream)?);
+        }
+        let (video_decoder, decoder_time_base) =
+            opened.ok_or_else(|| anyhow::anyhow!("unable to open video decoder"))?;
+        if selected_is_hw {
+            tracing::info!(
+                "video decoder selected: {} (hardware), stream_index={}",
+                selected_name,
+                stream.index()
+            );
+        } else {
+            if let Some(reason) = first_hw_failure {
+                tracing::info!(
+                    "hardware decode unavailable, fallback to software: {}",
+                    reason
                 );
             }
+            tracing::info!(
+                "video decoder selected: {} (software), stream_index={}",
+                selected_name,
+                stream.index()
+            );
+        }
+        Ok((video_decoder, decoder_time_base, selected_is_hw))
+    }
 
+    /// A decoder for `stream`; video decodes as `settings` prefer
+    /// ([`HwPreference::Auto`] when `None`). A hardware decoder that fails to
+    /// open, or later fails to decode, is replaced by the software one.
+    pub fn new(stream: &AvStream, settings: Option<DecoderSettings>) -> anyhow::Result<Self> {
+        let settings = settings.unwrap_or_default();
+        let s = if stream.is_video() {
+            let (video_decoder, decoder_time_base, is_hw) = match &settings.hw {
+                HwPreference::Auto => Self::open_first_candidate(stream)?,
+                HwPreference::Software => {
+                    let (video_decoder, time_base) = Self::open_software_video(stream)?;
+                    tracing::info!(
+                        "video decoder selected: default (software), stream_index={}",
+                        stream.index()
+                    );
+                    (video_decoder, time_base, false)
+                }
+                HwPreference::Device {
+                    device_type,
+                    device,
+                } => match Self::open_device_video(stream, device_type, device.as_deref()) {
+                    Ok((video_decoder, time_base, name)) => {
+                        tracing::info!(
+                            "video decoder selected: {} on {} (hardware), stream_index={}",
+                            name,
+                            device_type,
+                            stream.index()
+                        );
+                        (video_decoder, time_base, true)
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "stream {}: {device_type} decoding unavailable ({e:#}); \
+                             falling back to software decoder",
+                            stream.index()
+                        );
+                        let (video_decoder, time_base) = Self::open_software_video(stream)?;
+                        (video_decoder, time_base, false)
+                    }
+                },
+            };
             Self {
                 stream: stream.clone(),
+                settings,
                 inner: DecoderType::Video(video_decoder),
                 decoder_time_base,
-                is_hw: selected_is_hw,
+                is_hw,
             }
         } else if stream.is_audio() {
             let mut decoder_ctx = ffmpeg_next::codec::Context::new();
@@ -225,6 +325,7 @@ impl Decoder {
             let decoder_time_base = audio_decoder.time_base();
             Self {
                 stream: stream.clone(),
+                settings,
                 inner: DecoderType::Audio(audio_decoder),
                 decoder_time_base,
                 is_hw: false,
@@ -243,40 +344,60 @@ impl Decoder {
             // packet (e.g. QSV "MFX session" errors), with no built-in fallback.
             // Downgrade to software once and keep going: the failed packet is
             // dropped and the software decoder resyncs at the next keyframe.
-            Err(e) if self.is_hw => {
-                log::warn!(
-                    "stream {}: hardware decode failed at runtime ({e:#}); \
-                     falling back to software decoder",
-                    self.stream.index()
-                );
-                let (video_decoder, time_base) = Self::open_software_video(&self.stream)?;
-                self.inner = DecoderType::Video(video_decoder);
-                self.decoder_time_base = time_base;
-                self.is_hw = false;
-                Ok(())
-            }
+            Err(e) if self.is_hw => self.fall_back_to_software(&e),
             Err(e) => Err(e),
         }
     }
 
+    /// Replace the failing hardware decoder by the software one.
+    fn fall_back_to_software(&mut self, e: &anyhow::Error) -> anyhow::Result<()> {
+        tracing::warn!(
+            "stream {}: hardware decode failed at runtime ({e:#}); \
+             falling back to software decoder",
+            self.stream.index()
+        );
+        let (video_decoder, time_base) = Self::open_software_video(&self.stream)?;
+        self.inner = DecoderType::Video(video_decoder);
+        self.decoder_time_base = time_base;
+        self.is_hw = false;
+        Ok(())
+    }
+
     pub fn send_eof(&mut self) -> anyhow::Result<()> {
         self.inner.send_eof()
     }
 
     pub fn receive_frame(&mut self) -> anyhow::Result<Option<RawFrame>> {
-        self.inner.receive_frame()
+        match self.inner.receive_frame() {
+            // Frames the hardware decoder still held are lost, as in
+            // `send_packet`.
+            Err(e) if self.is_hw => self.fall_back_to_software(&e).map(|()| None),
+            result => result,
+        }
     }
 
     pub fn stream_index(&self) -> usize {
         self.stream.index()
     }
+
+    /// Whether video decodes on a hardware codec.
+    pub fn is_hw(&self) -> bool {
+        self.is_hw
+    }
 }
 
 pub struct DecoderTask {
     cancel: CancellationToken,
-    raw_chan: RawFrameSender,
+    out: FrameOut,
+    /// Watches the loop, which is rebuilt when it stalls.
+    watchdog: Option<Watchdog>,
+    /// The bus's decoder stage, once the task is part of one.
+    stage: Option<StageTasks>,
 }
 
+/// Bounded queue: when decoder is slower than producer, back-pressure instead of unbounded growth (OOM).
+const PACKET_QUEUE_BOUND: usize = 16;
+
 impl DecoderTask {
     pub fn new() -> Self {
         let cancel = CancellationToken::new();
@@ -284,12 +405,39 @@ impl DecoderTask {
 
         Self {
             cancel,
-            raw_chan: sender,
+            out: FrameOut {
+                chan: sender,
+                hub: FrameHub::new(),
+            },
+            watchdog: None,
+            stage: None,
         }
     }
 
+    /// Make the task one of `stage` (see [`crate::teardown`]): forcing the
+    /// stage stops it, and it is counted in until it has sent its EOF.
+    pub fn with_stage(mut self, stage: &StageTasks) -> Self {
+        self.cancel = stage.token();
+        self.stage = Some(stage.clone());
+        self
+    }
+
+    /// Have `watchdog` watch the decoder loop: when it stalls, it is left
+    /// behind and a new decoder for the stream takes over from the next
+    /// keyframe (see [`crate::watchdog`]).
+    pub fn with_watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
     pub fn subscribe(&self) -> RawFrameReceiver {
-        self.raw_chan.subscribe()
+        self.out.chan.subscribe()
+    }
+
+    /// A bounded queue of the frames decoded from now on, see
+    /// [`crate::frame_hub`].
+    pub fn subscribe_queue(&self, config: FrameQueueConfig) -> FrameQueue {
+        self.out.hub.subscribe(config)
     }
 
     pub fn stop(&self) {
@@ -302,41 +450,77 @@ impl DecoderTask {
         mut decoder_receiver: RawPacketReceiver,
         lossless: bool,
     ) {
-        log::info!(
+        tracing::info!(
             "decoder loop started, stream index: {}, lossless: {}",
             decoder.stream_index(),
             lossless
         );
         let cancel_clone = self.cancel.clone();
-        let sender_clone = self.raw_chan.clone();
-        /// Bounded queue: when decoder is slower than producer, back-pressure instead of unbounded growth (OOM).
-        const PACKET_QUEUE_BOUND: usize = 16;
-        tokio::spawn(async move {
-            let (packet_tx, packet_rx) =
-                std::sync::mpsc::sync_channel::<RawPacketCmd>(PACKET_QUEUE_BOUND);
+        let sender_clone = self.out.clone();
+        let watchdog = self.watchdog.clone();
+        let done = self.stage.as_ref().map(StageTasks::enter);
+        crate::worker::spawn_task("bus-decoder", async move {
+            let _done = done;
             let current_stream_index = decoder.stream_index();
-
-            let handle_cancel = cancel_clone.clone();
-            let handle = tokio::task::spawn_blocking(move || {
-                Self::decoder_loop(decoder, handle_cancel, packet_rx, sender_clone, lossless)
-            });
+            let stream = decoder.stream.clone();
+            let settings = decoder.settings.clone();
+            let name = format!("stream {}", current_stream_index);
+            let register = || {
+                watchdog
+                    .as_ref()
+                    .map(|watchdog| watchdog.register(TaskKind::Decoder, name.clone()))
+            };
+            let mut running =
+                Self::spawn_loop(decoder, &cancel_clone, &sender_clone, lossless, register());
+            let mut recoveries = 0;
+            // A stalled loop the task could not replace sends no EOF.
+            let mut failed = false;
+            // A rebuilt decoder starts at a keyframe.
+            let mut awaiting_key = false;
             loop {
                 tokio::select! {
                     _ = cancel_clone.cancelled() => {
                         break;
                     }
-                    Ok(packet) = decoder_receiver.recv() => {
+                    _ = crate::watchdog::stalled(running.progress.as_deref()) => {
+                        running.retire();
+                        let Some(decoder) = Self::rebuild(
+                            &stream,
+                            &settings,
+                            &running,
+                            watchdog.as_ref(),
+                            &mut recoveries,
+                        ) else {
+                            failed = true;
+                            break;
+                        };
+                        running = Self::spawn_loop(
+                            decoder,
+                            &cancel_clone,
+                            &sender_clone,
+                            lossless,
+                            register(),
+                        );
+                        awaiting_key = true;
+                        if let (Some(watchdog), Some(progress)) = (&watchdog, &running.progress) {
+                            watchdog.recovered(progress, recoveries);
+                        }
+                    }
+                    packet = decoder_receiver.recv() => {
                         match packet {
-                            RawPacketCmd::Data(packet) => {
-                                if packet.index() != current_stream_index {
+                            Ok(RawPacketCmd::Data(packet)) => {
+                                if packet.index() != current_stream_index
+                                    || (awaiting_key && !packet.is_key())
+                                {
                                     continue;
                                 }
+                                awaiting_key = false;
                                 // Async backpressure (never block this worker): if
                                 // the decode loop is paused applying its own
                                 // backpressure, yield so sibling tasks (e.g. the
                                 // encoder relay) can run instead of deadlocking.
                                 if Self::packet_send_backpressure(
-                                    &packet_tx,
+                                    &running.tx,
                                     &cancel_clone,
                                     RawPacketCmd::Data(packet),
                                 )
@@ -345,9 +529,17 @@ impl DecoderTask {
                                     break;
                                 }
                             }
-                            RawPacketCmd::EOF => {
+                            // The new connection's stream picks up at its
+                            // next keyframe.
+                            Ok(RawPacketCmd::Reconnected) => awaiting_key = true,
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                                tracing::warn!("decoder relay: lagged, lost {} packets", n);
+                            }
+                            // A closed input ends the stream like its EOF does.
+                            Ok(RawPacketCmd::EOF)
+                            | Err(tokio::sync::broadcast::error::RecvError::Closed) => {
                                 let _ = Self::packet_send_backpressure(
-                                    &packet_tx,
+                                    &running.tx,
                                     &cancel_clone,
                                     RawPacketCmd::EOF,
                                 )
@@ -358,10 +550,59 @@ impl DecoderTask {
                     }
                 }
             }
-            let _ = handle.await;
+            if failed {
+                // In place of the loop left behind, which never will.
+                send_frame_backpressure(&sender_clone, &cancel_clone, false, RawFrameCmd::EOF);
+            } else {
+                // The decode loop drains what is queued, then sees the EOF
+                // (or the disconnect).
+                drop(running.tx);
+                let _ = running.handle.await;
+            }
         });
     }
 
+    fn spawn_loop(
+        decoder: Decoder,
+        cancel: &CancellationToken,
+        out_sender: &FrameOut,
+        lossless: bool,
+        progress: Option<Arc<Progress>>,
+    ) -> WatchedLoop<RawPacketCmd> {
+        let out_sender = out_sender.clone();
+        WatchedLoop::spawn(
+            "bus-decoder",
+            PACKET_QUEUE_BOUND,
+            cancel,
+            progress,
+            move |packet_rx, cancel, progress| {
+                Self::decoder_loop(decoder, cancel, packet_rx, out_sender, lossless, progress)
+            },
+        )
+    }
+
+    /// A decoder for `stream` taking over from the stalled `running`, unless
+    /// the recoveries are spent or none opens; the task then fails.
+    fn rebuild(
+        stream: &AvStream,
+        settings: &DecoderSettings,
+        running: &WatchedLoop<RawPacketCmd>,
+        watchdog: Option<&Watchdog>,
+        recoveries: &mut u32,
+    ) -> Option<Decoder> {
+        let (watchdog, progress) = (watchdog?, running.progress.as_deref()?);
+        if !watchdog.may_recover(progress, recoveries) {
+            return None;
+        }
+        match Decoder::new(stream, Some(settings.clone())) {
+            Ok(decoder) => Some(decoder),
+            Err(e) => {
+                watchdog.failed(progress, format!("rebuilding the decoder: {e:#}"));
+                None
+            }
+        }
+    }
+
     /// Send a packet into the bounded decode queue, waiting (async, so the
     /// worker stays free) for room instead of blocking the executor thread.
     /// Returns true if the decode loop's receiver has gone away.
@@ -386,24 +627,33 @@ impl DecoderTask {
         }
     }
 
+    /// Decode what comes in on `packet_rx`. Each call into the decoder is
+    /// marked on `progress`; once retired, the loop stops without sending
+    /// anything.
     fn decoder_loop(
         mut decoder: Decoder,
         cancel: CancellationToken,
         packet_rx: std::sync::mpsc::Receiver<RawPacketCmd>,
-        out_sender: RawFrameSender,
+        out_sender: FrameOut,
         lossless: bool,
+        progress: Option<Arc<Progress>>,
     ) {
+        let progress = progress.as_deref();
+        let retired = || progress.is_some_and(Progress::is_retired);
         loop {
             if cancel.is_cancelled() {
                 break;
             }
-            let mut eof = false;
             match packet_rx.recv_timeout(Duration::from_millis(1)) {
                 Ok(packet) => {
                     match packet {
                         RawPacketCmd::Data(packet) => {
-                            if let Err(e) = decoder.send_packet(packet) {
-                                log::error!(
+                            let sent = {
+                                let _busy = progress.map(Progress::enter);
+                                decoder.send_packet(packet)
+                            };
+                            if let Err(e) = sent {
+                                tracing::error!(
                                     "send packet error: {}\nbacktrace:\n{}",
                                     e,
                                     Backtrace::capture()
@@ -411,20 +661,22 @@ impl DecoderTask {
                                 continue;
                             }
                         }
+                        RawPacketCmd::Reconnected => continue,
                         RawPacketCmd::EOF => {
-                            if let Err(e) = decoder.send_eof() {
-                                log::error!(
-                                    "decoder send eof error: {}\nbacktrace:\n{}",
-                                    e,
-                                    Backtrace::capture()
-                                );
-                            }
-                            eof = true;
+                            Self::flush(&mut decoder, &out_sender, &cancel, progress);
+                            break;
                         }
                     };
 
                     'outer: loop {
-                        match decoder.receive_frame() {
+                        if retired() {
+                            break 'outer;
+                        }
+                        let received = {
+                            let _busy = progress.map(Progress::enter);
+                            decoder.receive_frame()
+                        };
+                        match received {
                             Ok(Some(RawFrame::Video(frame))) => {
                                 send_frame_backpressure(
                                     &out_sender,
@@ -443,7 +695,7 @@ impl DecoderTask {
                             }
                             Ok(None) => break 'outer,
                             Err(e) => {
-                                log::error!(
+                                tracing::error!(
                                     "receive frame error: {}\nbacktrace:\n{}",
                                     e,
                                     Backtrace::capture()
@@ -453,19 +705,78 @@ impl DecoderTask {
                         }
                     }
                 }
-                Err(_) => (),
-            }
-
-            if eof {
-                break;
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                    Self::flush(&mut decoder, &out_sender, &cancel, progress);
+                    break;
+                }
             }
         }
-        log::info!(
+        if retired() {
+            tracing::warn!("decoder loop left behind by the watchdog returned, exiting");
+            return;
+        }
+        tracing::info!(
             "end of av decode task loop, stream base_time: {:#?}, decoder_time_base: {:#?}",
             decoder.stream.time_base(),
             decoder.decoder_time_base
         );
         // Backpressure EOF too, so it doesn't evict an unread tail frame.
-        send_frame_backpressure(&out_sender, &cancel, lossless, RawFrameCmd::EOF);
+        send_frame_backpressure(&out_sender, &cancel, true, RawFrameCmd::EOF);
+    }
+
+    /// End of input: drain the decoder until it reports its own EOF, so every
+    /// frame it still holds (B-frame reordering, frame threads) goes out
+    /// before the EOF that follows. The tail is sent losslessly whatever the
+    /// mode: the stream is ending, there is no latency left to protect.
+    fn flush(
+        decoder: &mut Decoder,
+        out_sender: &FrameOut,
+        cancel: &CancellationToken,
+        progress: Option<&Progress>,
+    ) {
+        let retired = || progress.is_some_and(Progress::is_retired);
+        if retired() {
+            return;
+        }
+        let sent = {
+            let _busy = progress.map(Progress::enter);
+            decoder.send_eof()
+        };
+        if let Err(e) = sent {
+            tracing::error!(
+                "decoder send eof error: {}\nbacktrace:\n{}",
+                e,
+                Backtrace::capture()
+            );
+            return;
+        }
+        let mut errors = 0;
+        let mut flushed = 0usize;
+        // After send_eof the decoder never asks for more input, so `None`
+        // is its EOF.
+        while errors < FLUSH_ERROR_LIMIT && !cancel.is_cancelled() && !retired() {
+            let received = {
+                let _busy = progress.map(Progress::enter);
+                decoder.receive_frame()
+            };
+            match received {
+                Ok(Some(frame)) => {
+                    errors = 0;
+                    flushed += 1;
+                    send_frame_backpressure(out_sender, cancel, true, RawFrameCmd::Data(frame));
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    errors += 1;
+                    tracing::warn!("decoder flush: receive frame error: {}", e);
+                }
+            }
+        }
+        tracing::debug!("decoder flushed {} frames at EOF", flushed);
     }
 }
+
+#[cfg(test)]
+#[path = "decoder_test.rs"]
+mod decoder_test;
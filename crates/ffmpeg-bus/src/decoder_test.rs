@@ -0,0 +1,72 @@
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+use crate::input::AvInput;
+
+/// A decoder for the video of the fixture opened with `hw`, and the first
+/// `count` frames it decodes.
+async fn decode_video(hw: HwPreference, count: usize) -> (Decoder, Vec<RawVideoFrame>) {
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let mut input = AvInput::new(&path.to_string_lossy(), None, None).unwrap();
+    let stream = input
+        .streams()
+        .values()
+        .find(|stream| stream.is_video())
+        .cloned()
+        .unwrap();
+    let mut decoder = Decoder::new(&stream, Some(DecoderSettings { hw })).unwrap();
+    let mut frames = Vec::new();
+    while frames.len() < count
+        && let Some(packet) = input.read_packet()
+    {
+        if packet.index() != stream.index() {
+            continue;
+        }
+        decoder.send_packet(packet).unwrap();
+        while let Some(RawFrame::Video(frame)) = decoder.receive_frame().unwrap() {
+            frames.push(frame);
+        }
+    }
+    (decoder, frames)
+}
+
+#[tokio::test]
+async fn a_bogus_hw_device_falls_back_to_software() {
+    for hw in [
+        HwPreference::Device {
+            device_type: "bogus".to_string(),
+            device: None,
+        },
+        HwPreference::Device {
+            device_type: "vaapi".to_string(),
+            device: Some("/dev/dri/no-such-render-node".to_string()),
+        },
+    ] {
+        let (decoder, frames) = decode_video(hw.clone(), 5).await;
+        assert!(!decoder.is_hw(), "{hw:?}");
+        assert!(frames.len() >= 5, "{hw:?}: {} frames", frames.len());
+    }
+}
+
+#[test]
+fn an_unknown_device_type_has_no_hw_decoder() {
+    crate::init().unwrap();
+    assert!(hw::find_hw_decoder(ffmpeg_next::codec::Id::H264, "bogus").is_none());
+    assert!(HwDevice::open("bogus", None).is_err());
+}
+
+/// Run with `--ignored` on a machine with a VAAPI render node.
+#[tokio::test]
+#[ignore = "needs a VAAPI device at /dev/dri/renderD128"]
+async fn vaapi_frames_are_downloaded_to_system_memory() {
+    let hw = HwPreference::Device {
+        device_type: "vaapi".to_string(),
+        device: Some("/dev/dri/renderD128".to_string()),
+    };
+    let (decoder, frames) = decode_video(hw, 5).await;
+    assert!(decoder.is_hw());
+    assert!(frames.len() >= 5, "{} frames", frames.len());
+    for frame in &frames {
+        assert!(!is_hw_format(frame.format()), "{:?}", frame.format());
+        assert!(frame.width() > 0 && frame.height() > 0);
+    }
+}
@@ -1,14 +1,18 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use ffmpeg_next::{Dictionary, Rational, picture};
+use ffmpeg_next::{Dictionary, Rational, Rescale};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
+    audio_gap::{GapFillConfig, GapFiller},
+    bus::{BusEvent, EncodeConfig},
     frame::{RawFrame, RawFrameCmd, RawFrameReceiver},
     hw,
     packet::{RawPacket, RawPacketCmd, RawPacketReceiver, RawPacketSender},
     scaler::Scaler,
     stream::AvStream,
+    teardown::StageTasks,
+    watchdog::{Progress, TaskKind, Watchdog, WatchedLoop},
 };
 
 #[derive(Debug, Clone)]
@@ -38,18 +42,12 @@ pub enum EncoderType {
 }
 
 impl EncoderType {
-    pub fn send_frame(&mut self, frame: RawFrame, frame_index: i64) -> anyhow::Result<()> {
+    /// Send one frame, its timestamps in the encoder's time base. Keyframes
+    /// come on the encoder's own GOP (see [`Settings::keyframe_interval`]).
+    pub fn send_frame(&mut self, frame: RawFrame) -> anyhow::Result<()> {
         match (self, frame) {
             (EncoderType::Video(encoder), RawFrame::Video(mut frame)) => {
                 let frame = frame.get_mut();
-                // todo
-                if frame_index % 5 == 0 {
-                    frame.set_kind(picture::Type::I);
-                }
-                // Set PTS if not already set
-                if frame.pts().is_none() {
-                    frame.set_pts(Some(frame_index));
-                }
                 encoder.send_frame(frame)?;
             }
             (EncoderType::Audio(encoder), RawFrame::Audio(mut frame)) => {
@@ -97,6 +95,8 @@ impl EncoderType {
 pub struct Settings {
     pub width: u32,
     pub height: u32,
+    /// GOP length in frames, set as the encoder's gop size. 0 = encoder
+    /// default.
     pub keyframe_interval: u64,
     pub codec: Option<String>,
     pub pixel_format: ffmpeg_next::format::Pixel,
@@ -107,7 +107,8 @@ impl Default for Settings {
         Self {
             width: 1920,
             height: 1080,
-            keyframe_interval: 25,
+            // Short GOP so live viewers can join quickly.
+            keyframe_interval: 5,
             codec: Some("h264".to_string()),
             pixel_format: ffmpeg_next::format::Pixel::YUV420P,
         }
@@ -300,10 +301,37 @@ pub struct Encoder {
     stream: AvStream,
     inner: EncoderType,
     encoder_time_base: Rational,
+    /// What the timestamps of the frames sent are in: the input stream's
+    /// time base unless [`with_frame_time_base`](Self::with_frame_time_base)
+    /// says otherwise.
+    frame_time_base: Rational,
     interleaved: bool,
+    /// Video frames sent, for placing those without a timestamp.
     frame_index: i64,
     scaler: Option<Scaler>,
     audio_resampler: Option<AudioResampler>,
+    /// Name of the opened codec (`libx264`, `h264_vaapi`, `aac`).
+    name: String,
+    /// Set by [`recover`](Self::recover): convert every frame from its actual
+    /// format, rebuilding the scaler when that changes to any other; the flag
+    /// allows dropping to fewer bits per sample.
+    recovered: Option<bool>,
+    /// Format and size the scaler was built for.
+    scaler_input: Option<(ffmpeg_next::format::Pixel, u32, u32)>,
+    /// Degrees clockwise video frames are turned before encoding (see
+    /// [`with_rotation`](Self::with_rotation)).
+    rotation: u32,
+}
+
+/// What [`Encoder::recover`] switched to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovery {
+    /// The format of the frames the encoder rejected.
+    pub from: ffmpeg_next::format::Pixel,
+    /// The encoder's format they are now converted to.
+    pub to: ffmpeg_next::format::Pixel,
+    /// Whether that drops bits per sample (a 10-bit source to 8 bits).
+    pub downconverted: bool,
 }
 
 impl Encoder {
@@ -320,6 +348,9 @@ impl Encoder {
         encoder.set_format(settings.pixel_format);
         encoder.set_frame_rate(Some(stream.rate()));
         encoder.set_time_base(ffmpeg_next::util::mathematics::rescale::TIME_BASE);
+        if settings.keyframe_interval > 0 {
+            encoder.set_gop(settings.keyframe_interval as u32);
+        }
 
         let need_defaults = options.is_none();
         let mut opts = options.unwrap_or_default();
@@ -360,7 +391,7 @@ impl Encoder {
                         first_hw_failure =
                             Some(format!("{} open failed: {}", candidate.name, e));
                     }
-                    log::info!(
+                    tracing::info!(
                         "video encoder candidate rejected: name={}, hw={}, reason={}",
                         candidate.name,
                         candidate.is_hw,
@@ -372,23 +403,24 @@ impl Encoder {
 
         let (encoder, encoder_time_base) = opened.ok_or_else(|| {
             anyhow::anyhow!(
-                "no usable video encoder for requested codec {:?}",
-                settings.codec
+                "no usable video encoder for requested codec {:?} (available: {})",
+                settings.codec,
+                available_codecs().join(", ")
             )
         })?;
         if selected_is_hw {
-            log::info!(
+            tracing::info!(
                 "video encoder selected: {} (hardware), stream_index={}",
                 selected_name.as_deref().unwrap_or("unknown"),
                 stream.index()
             );
         } else {
             if let Some(reason) = first_hw_failure {
-                log::info!("hardware encode unavailable, fallback to software: {}", reason);
+                tracing::info!("hardware encode unavailable, fallback to software: {}", reason);
             } else {
-                log::info!("video encoder selected: software fallback");
+                tracing::info!("video encoder selected: software fallback");
             }
-            log::info!(
+            tracing::info!(
                 "video encoder selected: {} (software), stream_index={}",
                 selected_name.as_deref().unwrap_or("unknown"),
                 stream.index()
@@ -399,10 +431,15 @@ impl Encoder {
             stream: stream.clone(),
             inner: EncoderType::Video(encoder),
             encoder_time_base: encoder_time_base,
+            frame_time_base: stream.time_base(),
             interleaved: false,
             frame_index: 0,
             scaler: None,
             audio_resampler: None,
+            name: selected_name.unwrap_or_default(),
+            recovered: None,
+            scaler_input: None,
+            rotation: 0,
         })
     }
 
@@ -489,7 +526,7 @@ impl Encoder {
         };
         let encoder_time_base: Rational = unsafe { (*encoder.0.as_ptr()).time_base.into() };
 
-        log::info!(
+        tracing::info!(
             "audio encoder selected: {} (software), stream_index={}, sample_rate={}, channels={}",
             codec_name,
             stream.index(),
@@ -501,13 +538,33 @@ impl Encoder {
             stream: stream.clone(),
             inner: EncoderType::Audio(encoder),
             encoder_time_base,
+            frame_time_base: stream.time_base(),
             interleaved: false,
             frame_index: 0,
             scaler: None,
             audio_resampler: None,
+            name: codec_name.to_string(),
+            recovered: None,
+            scaler_input: None,
+            rotation: 0,
         })
     }
 
+    /// Turn every video frame `degrees` clockwise (90, 180 or 270) before
+    /// encoding it, to bake in a source's display matrix: the settings give
+    /// the turned size, and the output stream drops the input's matrix.
+    pub fn with_rotation(mut self, degrees: u32) -> Self {
+        self.rotation = degrees;
+        self
+    }
+
+    /// Frames sent come with timestamps in `time_base` rather than the
+    /// input stream's (e.g. a compositor's own clock).
+    pub fn with_frame_time_base(mut self, time_base: Rational) -> Self {
+        self.frame_time_base = time_base;
+        self
+    }
+
     pub fn send_frame(&mut self, mut frame: RawFrame) -> anyhow::Result<()> {
         // What to hand the encoder: either the input frame unchanged, or a set
         // of derived frames (a scaled video frame, or resampled/reframed audio
@@ -525,26 +582,26 @@ impl Encoder {
                     _ => anyhow::bail!("video frame sent to non-video encoder"),
                 };
                 let f = vf.get_mut();
-                if f.format() != ef || f.width() != ew || f.height() != eh {
-                    if self.scaler.is_none() {
-                        self.scaler =
-                            Some(Scaler::new(ffmpeg_next::software::scaling::Context::get(
-                                f.format(),
-                                f.width(),
-                                f.height(),
-                                ef,
-                                ew,
-                                eh,
-                                ffmpeg_next::software::scaling::flag::Flags::empty(),
-                            )?));
-                    }
-
-                    let mut converted = ffmpeg_next::frame::Video::empty();
-                    self.scaler.as_mut().unwrap().run(f, &mut converted)?;
-                    // Copy over PTS from old frame.
-                    converted.set_pts(f.pts());
+                let timing = self.timing(f);
+                if self.rotation != 0 {
+                    let rotated =
+                        crate::frame::rotate_video(&crate::frame::to_software(f)?, self.rotation)?;
+                    let mut converted = if rotated.format() != ef
+                        || rotated.width() != ew
+                        || rotated.height() != eh
+                    {
+                        self.scale(&rotated, ef, ew, eh)?
+                    } else {
+                        rotated
+                    };
+                    set_timing(&mut converted, timing);
+                    Outbound::Frames(vec![RawFrame::Video(converted.into())])
+                } else if f.format() != ef || f.width() != ew || f.height() != eh {
+                    let mut converted = self.scale(f, ef, ew, eh)?;
+                    set_timing(&mut converted, timing);
                     Outbound::Frames(vec![RawFrame::Video(converted.into())])
                 } else {
+                    set_timing(f, timing);
                     Outbound::Original
                 }
             }
@@ -573,20 +630,155 @@ impl Encoder {
         };
 
         match action {
-            Outbound::Original => {
-                self.inner.send_frame(frame, self.frame_index)?;
-                self.frame_index += 1;
-            }
+            Outbound::Original => self.inner.send_frame(frame)?,
             Outbound::Frames(frames) => {
                 for f in frames {
-                    self.inner.send_frame(f, self.frame_index)?;
-                    self.frame_index += 1;
+                    self.inner.send_frame(f)?;
                 }
             }
         }
         Ok(())
     }
 
+    /// The pts and duration of video frame `frame` in the encoder's time
+    /// base. A frame without a timestamp (a source that has none) is placed
+    /// by its index at the stream's frame rate.
+    fn timing(&mut self, frame: &ffmpeg_next::frame::Video) -> (i64, i64) {
+        let (from, to) = (self.frame_time_base, self.encoder_time_base);
+        let valid = |tb: Rational| tb.0 > 0 && tb.1 > 0;
+        let rescale = |ts: i64| {
+            if valid(from) && from != to {
+                ts.rescale(from, to)
+            } else {
+                ts
+            }
+        };
+        let pts = match frame.pts().or(frame.timestamp()) {
+            Some(pts) => rescale(pts),
+            None if valid(self.stream.rate()) => {
+                self.frame_index.rescale(self.stream.rate().invert(), to)
+            }
+            None => self.frame_index,
+        };
+        let duration = unsafe { (*frame.as_ptr()).duration }.max(0);
+        self.frame_index += 1;
+        (pts, rescale(duration))
+    }
+
+    /// `f` converted to the encoder's format and size. The scaler is built
+    /// for the first frame and rebuilt when the frames change size (a camera
+    /// coming back at another resolution) or switch to another software
+    /// format of no more bits per sample. A switch to a hardware surface or
+    /// a deeper format is left to [`recover`](Self::recover), after which
+    /// hardware frames are downloaded first and the scaler follows any change.
+    fn scale(
+        &mut self,
+        f: &ffmpeg_next::frame::Video,
+        ef: ffmpeg_next::format::Pixel,
+        ew: u32,
+        eh: u32,
+    ) -> anyhow::Result<ffmpeg_next::frame::Video> {
+        let src = match self.recovered {
+            Some(downconvert) => {
+                let src = crate::frame::to_software(f)?;
+                if !downconvert && drops_bits(src.format(), ef) {
+                    anyhow::bail!(
+                        "{} frames need downconverting to {}",
+                        pixel_name(src.format()),
+                        pixel_name(ef)
+                    );
+                }
+                src
+            }
+            None => std::borrow::Cow::Borrowed(f),
+        };
+        let input = (src.format(), src.width(), src.height());
+        let follows = self.recovered.is_some()
+            || self.scaler_input.is_none_or(|(format, ..)| {
+                format == input.0
+                    || !(crate::frame::is_hw_format(input.0) || drops_bits(input.0, ef))
+            });
+        if follows && self.scaler_input != Some(input) {
+            self.scaler = None;
+        }
+        if self.scaler.is_none() {
+            self.scaler = Some(Scaler::new(ffmpeg_next::software::scaling::Context::get(
+                input.0,
+                input.1,
+                input.2,
+                ef,
+                ew,
+                eh,
+                ffmpeg_next::software::scaling::flag::Flags::empty(),
+            )?));
+            self.scaler_input = Some(input);
+        }
+
+        let mut converted = ffmpeg_next::frame::Video::empty();
+        self.scaler.as_mut().unwrap().run(&src, &mut converted)?;
+        // Copy over PTS from old frame.
+        converted.set_pts(f.pts());
+        Ok(converted)
+    }
+
+    /// Stop trusting the scaler built for the first frame, after the encoder
+    /// kept rejecting frames in format `source`: from now on each frame is
+    /// converted from its actual format (see [`scale`](Self::scale)).
+    /// `downconvert` allows dropping to the encoder's fewer bits per sample.
+    /// Fails when that conversion cannot work either.
+    pub fn recover(
+        &mut self,
+        source: ffmpeg_next::format::Pixel,
+        downconvert: bool,
+    ) -> anyhow::Result<Recovery> {
+        let EncoderType::Video(encoder) = &self.inner else {
+            anyhow::bail!("only video encoders convert frames");
+        };
+        let target = encoder.format();
+        // A hardware surface's own format is only known once downloaded.
+        let hw = crate::frame::is_hw_format(source);
+        let downconverted = !hw && drops_bits(source, target);
+        if downconverted && !downconvert {
+            anyhow::bail!(
+                "{} needs downconverting to {}, which is turned off",
+                pixel_name(source),
+                pixel_name(target)
+            );
+        }
+        let supported = unsafe {
+            ffmpeg_next::ffi::sws_isSupportedInput(source.into()) > 0
+                && ffmpeg_next::ffi::sws_isSupportedOutput(target.into()) > 0
+        };
+        if !hw && !supported {
+            anyhow::bail!(
+                "cannot convert {} to {}",
+                pixel_name(source),
+                pixel_name(target)
+            );
+        }
+        self.scaler = None;
+        self.scaler_input = None;
+        self.recovered = Some(downconvert);
+        Ok(Recovery {
+            from: source,
+            to: target,
+            downconverted,
+        })
+    }
+
+    /// Name of the opened codec.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The format frames are encoded from: the pixel or sample format name.
+    pub fn format_name(&self) -> String {
+        match &self.inner {
+            EncoderType::Video(e) => pixel_name(e.format()),
+            EncoderType::Audio(e) => e.format().name().to_string(),
+        }
+    }
+
     pub fn send_eof(&mut self) -> anyhow::Result<()> {
         // Flush the audio resampler's buffered/tail samples before EOF so no
         // audio is dropped at end of stream.
@@ -596,25 +788,40 @@ impl Encoder {
             Vec::new()
         };
         for chunk in chunks {
-            self.inner
-                .send_frame(RawFrame::Audio(chunk.into()), self.frame_index)?;
-            self.frame_index += 1;
+            self.inner.send_frame(RawFrame::Audio(chunk.into()))?;
         }
         self.inner.send_eof()
     }
 
+    /// The codec parameters of what this encoder produces, from its opened
+    /// context (`avcodec_parameters_from_context`): codec, size or sample
+    /// layout, and the extradata it generated. An H.264/H.265 encoder opened
+    /// without global headers has none; its keyframes carry the parameter
+    /// sets in band, and a muxer takes them from the first one.
+    pub fn output_parameters(&self) -> ffmpeg_next::codec::Parameters {
+        match &self.inner {
+            EncoderType::Video(e) => ffmpeg_next::codec::Parameters::from(e),
+            EncoderType::Audio(e) => ffmpeg_next::codec::Parameters::from(e),
+        }
+    }
+
     /// Describe this encoder's output stream for muxing, taking the codec
     /// parameters (sample rate / channels / dimensions and the encoder-generated
     /// extradata) from the encoder context rather than the input stream. A
     /// param-changing transcode (e.g. 44100/mono -> 48000/stereo) must advertise
     /// the *encoder's* params, or the muxed header won't match the packets.
-    /// `index` keys the muxer's input->output stream mapping.
+    /// `index` keys the muxer's input->output stream mapping. The input's
+    /// metadata carries over, and so does its display matrix unless the
+    /// frames are turned (see [`with_rotation`](Self::with_rotation)).
     pub fn output_stream(&self, index: usize) -> AvStream {
-        let params = match &self.inner {
-            EncoderType::Video(e) => ffmpeg_next::codec::Parameters::from(e),
-            EncoderType::Audio(e) => ffmpeg_next::codec::Parameters::from(e),
+        let params = self.output_parameters();
+        let matrix = match self.rotation {
+            0 => self.stream.display_matrix(),
+            _ => None,
         };
         AvStream::new(index, params, self.encoder_time_base, self.stream.rate())
+            .with_metadata(self.stream.metadata().to_vec())
+            .with_display_matrix(matrix)
     }
 
     pub fn encoder_receive_packet(&mut self) -> anyhow::Result<Option<RawPacket>> {
@@ -622,13 +829,15 @@ impl Encoder {
 
         if let Some(ref mut p) = pkt {
             match &self.inner {
-                EncoderType::Video(_) => {
+                // Frames without a duration give packets without one; assume
+                // the stream's frame rate for those.
+                EncoderType::Video(_) if p.packet().duration() <= 0 => {
                     let rate = self.stream.rate();
-                    if rate.0 > 0 {
-                        let duration = 1_000_000i64 * rate.1 as i64 / rate.0 as i64;
-                        p.set_duration(duration);
+                    if rate.0 > 0 && rate.1 > 0 {
+                        p.set_duration(1i64.rescale(rate.invert(), self.encoder_time_base));
                     }
                 }
+                EncoderType::Video(_) => {}
                 EncoderType::Audio(encoder) => {
                     let frame_size = encoder.frame_size() as i64;
                     let rate = encoder.rate() as i64;
@@ -646,24 +855,222 @@ impl Encoder {
     }
 }
 
+/// What an encoder loop drives: an [`Encoder`], or a stand-in in tests.
+pub(crate) trait FrameEncoder: Send {
+    fn send_frame(&mut self, frame: RawFrame) -> anyhow::Result<()>;
+    fn encoder_receive_packet(&mut self) -> anyhow::Result<Option<RawPacket>>;
+    fn send_eof(&mut self) -> anyhow::Result<()>;
+    fn stream(&self) -> &AvStream;
+    fn name(&self) -> &str;
+    fn format_name(&self) -> String;
+    fn recover(
+        &mut self,
+        source: ffmpeg_next::format::Pixel,
+        downconvert: bool,
+    ) -> anyhow::Result<Recovery>;
+}
+
+impl FrameEncoder for Encoder {
+    fn send_frame(&mut self, frame: RawFrame) -> anyhow::Result<()> {
+        Encoder::send_frame(self, frame)
+    }
+
+    fn encoder_receive_packet(&mut self) -> anyhow::Result<Option<RawPacket>> {
+        Encoder::encoder_receive_packet(self)
+    }
+
+    fn send_eof(&mut self) -> anyhow::Result<()> {
+        Encoder::send_eof(self)
+    }
+
+    fn stream(&self) -> &AvStream {
+        &self.stream
+    }
+
+    fn name(&self) -> &str {
+        Encoder::name(self)
+    }
+
+    fn format_name(&self) -> String {
+        Encoder::format_name(self)
+    }
+
+    fn recover(
+        &mut self,
+        source: ffmpeg_next::format::Pixel,
+        downconvert: bool,
+    ) -> anyhow::Result<Recovery> {
+        Encoder::recover(self, source, downconvert)
+    }
+}
+
+/// Opens the encoder a stalled encoder loop is replaced with.
+pub(crate) type Rebuild = Arc<dyn Fn() -> anyhow::Result<Box<dyn FrameEncoder>> + Send + Sync>;
+
+/// Stamp `frame` with a pts and duration from [`Encoder::timing`]; the
+/// encoder hands the duration on to the frame's packet.
+fn set_timing(frame: &mut ffmpeg_next::frame::Video, (pts, duration): (i64, i64)) {
+    frame.set_pts(Some(pts));
+    unsafe { (*frame.as_mut_ptr()).duration = duration };
+}
+
+/// FFmpeg's name of `format` (`yuv420p`, `p010le`).
+fn pixel_name(format: ffmpeg_next::format::Pixel) -> String {
+    format
+        .descriptor()
+        .map(|d| d.name().to_string())
+        .unwrap_or_else(|| format!("{format:?}"))
+}
+
+/// Whether converting `source` to `target` loses bits per sample.
+fn drops_bits(source: ffmpeg_next::format::Pixel, target: ffmpeg_next::format::Pixel) -> bool {
+    match (
+        crate::frame::bit_depth(source),
+        crate::frame::bit_depth(target),
+    ) {
+        (Some(s), Some(t)) => s > t,
+        _ => false,
+    }
+}
+
+/// How an encoder loop reacts to frames the encoder keeps rejecting, e.g. a
+/// hardware decoder switching to a surface or 10-bit format the scaler built
+/// for the first frame cannot take.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncoderRecovery {
+    /// Consecutive rejected frames before acting (at least 1).
+    pub failure_threshold: u32,
+    /// Then try converting each frame from its actual format (see
+    /// [`Encoder::recover`]) before giving up.
+    pub rebuild_scaler: bool,
+    /// Let that conversion drop a deeper source to the encoder's bits per
+    /// sample (10-bit to 8-bit), with a warning.
+    pub downconvert: bool,
+}
+
+impl Default for EncoderRecovery {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 30,
+            rebuild_scaler: true,
+            downconvert: true,
+        }
+    }
+}
+
 pub struct EncoderTask {
     cancel: CancellationToken,
     raw_chan: RawPacketSender,
+    recovery: EncoderRecovery,
+    events: Option<tokio::sync::broadcast::Sender<BusEvent>>,
+    gap_fill: Option<GapFillConfig>,
+    /// Watches the loop, and rebuilds its encoder when it stalls.
+    watchdog: Option<(Watchdog, Rebuild)>,
+    /// The bus's encoder stage, once the task is part of one.
+    stage: Option<StageTasks>,
+}
+
+/// Encoder output = encoded packets (small). Moderate capacity for bursts.
+/// Also the backpressure high-water mark in lossless mode.
+const PACKET_CHAN_CAP: usize = 64;
+
+/// Bounded queue: when encoder is slower than producer, back-pressure instead of unbounded growth (OOM).
+const FRAME_QUEUE_BOUND: usize = 128;
+
+/// Send an encoded packet downstream; in `lossless` mode wait for room first,
+/// as the decoder does for its frames (see `decoder::send_frame_backpressure`).
+/// An encoder flushing at EOF emits its whole lookahead at once, which would
+/// otherwise overwrite packets the muxer has not read yet.
+fn send_packet_backpressure(
+    sender: &RawPacketSender,
+    cancel: &CancellationToken,
+    lossless: bool,
+    msg: RawPacketCmd,
+) {
+    if lossless {
+        while sender.len() >= PACKET_CHAN_CAP
+            && sender.receiver_count() > 0
+            && !cancel.is_cancelled()
+        {
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+    let _ = sender.send(msg);
+}
+
+/// What every encoder loop of a task works with, rebuilt ones included.
+#[derive(Clone)]
+struct LoopContext {
+    out: RawPacketSender,
+    lossless: bool,
+    recovery: EncoderRecovery,
+    events: Option<tokio::sync::broadcast::Sender<BusEvent>>,
+    gap_fill: Option<GapFillConfig>,
 }
 
 impl EncoderTask {
     pub fn new() -> Self {
         let cancel = CancellationToken::new();
-        /// Encoder output = encoded packets (small). Moderate capacity for bursts.
-        const PACKET_CHAN_CAP: usize = 64;
         let (sender, _) = tokio::sync::broadcast::channel(PACKET_CHAN_CAP);
 
         Self {
             cancel,
             raw_chan: sender,
+            recovery: EncoderRecovery::default(),
+            events: None,
+            gap_fill: None,
+            watchdog: None,
+            stage: None,
         }
     }
 
+    /// React to rejected frames per `recovery`, reporting recoveries and
+    /// failures to `events`.
+    pub fn with_recovery(
+        mut self,
+        recovery: EncoderRecovery,
+        events: tokio::sync::broadcast::Sender<BusEvent>,
+    ) -> Self {
+        self.recovery = recovery;
+        self.events = Some(events);
+        self
+    }
+
+    /// Audio encoders: close holes in the frames' timeline with silence
+    /// first (see [`crate::audio_gap`]), reporting those left to the events
+    /// of [`with_recovery`](Self::with_recovery). `None` turns it off.
+    pub fn with_gap_fill(mut self, gap_fill: Option<GapFillConfig>) -> Self {
+        self.gap_fill = gap_fill;
+        self
+    }
+
+    /// Have `watchdog` watch the encoder loop: when it stalls, it is left
+    /// behind and a loop on an encoder from `rebuild` takes over (see
+    /// [`crate::watchdog`]).
+    pub fn with_watchdog(
+        self,
+        watchdog: Watchdog,
+        rebuild: impl Fn() -> anyhow::Result<Encoder> + Send + Sync + 'static,
+    ) -> Self {
+        self.watched(
+            watchdog,
+            Arc::new(move || Ok(Box::new(rebuild()?) as Box<dyn FrameEncoder>)),
+        )
+    }
+
+    pub(crate) fn watched(mut self, watchdog: Watchdog, rebuild: Rebuild) -> Self {
+        self.watchdog = Some((watchdog, rebuild));
+        self
+    }
+
+    /// Make the task one of `stage` (see [`crate::teardown`]): forcing the
+    /// stage stops it, and it is counted in until it has sent its EOF.
+    pub fn with_stage(mut self, stage: &StageTasks) -> Self {
+        self.cancel = stage.token();
+        self.stage = Some(stage.clone());
+        self
+    }
+
     pub fn subscribe(&self) -> RawPacketReceiver {
         self.raw_chan.subscribe()
     }
@@ -675,56 +1082,107 @@ impl EncoderTask {
     pub async fn start(
         &self,
         encoder: Encoder,
+        encoder_receiver: RawFrameReceiver,
+        lossless: bool,
+    ) {
+        self.start_boxed(Box::new(encoder), encoder_receiver, lossless)
+            .await;
+    }
+
+    pub(crate) async fn start_boxed(
+        &self,
+        encoder: Box<dyn FrameEncoder>,
         mut encoder_receiver: RawFrameReceiver,
         lossless: bool,
     ) {
         let cancel_clone = self.cancel.clone();
-        let sender_clone = self.raw_chan.clone();
-        log::info!(
+        let context = LoopContext {
+            out: self.raw_chan.clone(),
+            lossless,
+            recovery: self.recovery.clone(),
+            events: self.events.clone(),
+            gap_fill: self.gap_fill,
+        };
+        let watchdog = self.watchdog.clone();
+        tracing::info!(
             "encoder loop started, stream index: {}, lossless: {}",
-            encoder.stream.index(),
+            encoder.stream().index(),
             lossless
         );
-        /// Bounded queue: when encoder is slower than producer, back-pressure instead of unbounded growth (OOM).
-        const FRAME_QUEUE_BOUND: usize = 128;
         /// Log "queue full" at most every N drops; use debug level so info logs stay clean.
         const DROP_LOG_INTERVAL: u64 = 120;
-        tokio::spawn(async move {
-            let (tx, rx) = std::sync::mpsc::sync_channel::<RawFrameCmd>(FRAME_QUEUE_BOUND);
-            let handle_cancel = cancel_clone.clone();
-            let handle = tokio::task::spawn_blocking(move || {
-                Self::encoder_loop(encoder, handle_cancel, rx, sender_clone)
-            });
+        let done = self.stage.as_ref().map(StageTasks::enter);
+        crate::worker::spawn_task("bus-encoder", async move {
+            let _done = done;
+            let name = format!("stream {} ({})", encoder.stream().index(), encoder.name());
+            let register = || {
+                watchdog
+                    .as_ref()
+                    .map(|(watchdog, _)| watchdog.register(TaskKind::Encoder, name.clone()))
+            };
+            let mut running = Self::spawn_loop(encoder, &cancel_clone, context.clone(), register());
+            let mut recoveries = 0;
+            // A stalled loop the task could not replace sends no EOF.
+            let mut failed = false;
             let mut dropped_count: u64 = 0;
             loop {
                 tokio::select! {
                     _ = cancel_clone.cancelled() => {
                         break;
                     }
+                    _ = crate::watchdog::stalled(running.progress.as_deref()) => {
+                        running.retire();
+                        let Some(encoder) =
+                            Self::rebuild(&running, watchdog.as_ref(), &mut recoveries)
+                        else {
+                            failed = true;
+                            break;
+                        };
+                        running = Self::spawn_loop(
+                            encoder,
+                            &cancel_clone,
+                            context.clone(),
+                            register(),
+                        );
+                        if let (Some((watchdog, _)), Some(progress)) =
+                            (&watchdog, &running.progress)
+                        {
+                            watchdog.recovered(progress, recoveries);
+                        }
+                    }
                     result = encoder_receiver.recv() => {
                     match result {
                     Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        log::debug!("encoder relay: lagged, lost {} frames", n);
+                        tracing::debug!("encoder relay: lagged, lost {} frames", n);
                         continue;
                     }
+                    // A closed decoder ends the stream like its EOF does.
                     Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        let _ = Self::relay_send_backpressure(
+                            &running.tx,
+                            &cancel_clone,
+                            RawFrameCmd::EOF,
+                        )
+                        .await;
                         break;
                     }
                     Ok(frame) => {
                         let is_eof = matches!(&frame, RawFrameCmd::EOF);
-                        // EOF must always land; lossless mode (file/net transcode)
-                        // backpressures every frame so none are dropped. Lossy
-                        // mode (live) drops DATA when the queue is full to bound
-                        // latency/memory.
+                        // EOF must always land, and behind every frame queued
+                        // before it: the queue is FIFO, so waiting for room
+                        // lets the encoder loop drain them first. Lossless mode
+                        // (file/net transcode) backpressures every frame so
+                        // none are dropped. Lossy mode (live) drops DATA when
+                        // the queue is full to bound latency/memory.
                         let disconnected = if is_eof || lossless {
-                            Self::relay_send_backpressure(&tx, &cancel_clone, frame).await
+                            Self::relay_send_backpressure(&running.tx, &cancel_clone, frame).await
                         } else {
-                            match tx.try_send(frame) {
+                            match running.tx.try_send(frame) {
                                 Ok(()) => false,
                                 Err(std::sync::mpsc::TrySendError::Full(_)) => {
                                     dropped_count += 1;
                                     if dropped_count % DROP_LOG_INTERVAL == 1 {
-                                        log::debug!(
+                                        tracing::debug!(
                                             "encoder frame queue full, dropped {} frames (back-pressure)",
                                             dropped_count
                                         );
@@ -734,7 +1192,8 @@ impl EncoderTask {
                                 Err(std::sync::mpsc::TrySendError::Disconnected(_)) => true,
                             }
                         };
-                        if disconnected {
+                        // Nothing comes after the EOF.
+                        if disconnected || is_eof {
                             break;
                         }
                     }
@@ -742,11 +1201,54 @@ impl EncoderTask {
                     }
                 }
             }
-            let _ = handle.await;
-            log::info!("encoder task finished");
+            if failed {
+                // In place of the loop left behind, which never will.
+                let _ = context.out.send(RawPacketCmd::EOF);
+            } else {
+                // The encoder loop drains what is queued, then sees the EOF
+                // (or the disconnect).
+                drop(running.tx);
+                let _ = running.handle.await;
+            }
+            tracing::info!("encoder task finished");
         });
     }
 
+    fn spawn_loop(
+        encoder: Box<dyn FrameEncoder>,
+        cancel: &CancellationToken,
+        context: LoopContext,
+        progress: Option<Arc<Progress>>,
+    ) -> WatchedLoop<RawFrameCmd> {
+        WatchedLoop::spawn(
+            "bus-encoder",
+            FRAME_QUEUE_BOUND,
+            cancel,
+            progress,
+            move |rx, cancel, progress| Self::encoder_loop(encoder, cancel, rx, context, progress),
+        )
+    }
+
+    /// The encoder for a loop taking over from the stalled `running`, unless
+    /// the recoveries are spent or none opens; the task then fails.
+    fn rebuild(
+        running: &WatchedLoop<RawFrameCmd>,
+        watchdog: Option<&(Watchdog, Rebuild)>,
+        recoveries: &mut u32,
+    ) -> Option<Box<dyn FrameEncoder>> {
+        let ((watchdog, rebuild), progress) = (watchdog?, running.progress.as_deref()?);
+        if !watchdog.may_recover(progress, recoveries) {
+            return None;
+        }
+        match rebuild() {
+            Ok(encoder) => Some(encoder),
+            Err(e) => {
+                watchdog.failed(progress, format!("rebuilding the encoder: {e:#}"));
+                None
+            }
+        }
+    }
+
     /// Send a frame into the bounded encoder queue, waiting (async, so the
     /// executor stays free) for room instead of dropping. Returns true if the
     /// encoder loop's receiver has gone away, so the caller should stop.
@@ -771,56 +1273,669 @@ impl EncoderTask {
         }
     }
 
+    /// `frame`, after the silence `gaps` wants before it; a hole left is
+    /// reported to the escalation's events.
+    fn fill_gaps(
+        encoder: &dyn FrameEncoder,
+        gaps: &mut Option<GapFiller>,
+        escalation: &Escalation,
+        frame: RawFrame,
+    ) -> Vec<RawFrame> {
+        let (Some(gaps), RawFrame::Audio(audio)) = (gaps.as_mut(), &frame) else {
+            return vec![frame];
+        };
+        let (frames, gap) = match gaps.push_frame(audio.clone()) {
+            Ok(filled) => filled,
+            Err(e) => {
+                tracing::error!("filling an audio gap failed: {:#}", e);
+                return vec![frame];
+            }
+        };
+        if let Some(event) = gap.and_then(|gap| gaps.event(encoder.stream().index(), gap))
+            && let Some(events) = &escalation.events
+        {
+            let _ = events.send(event);
+        }
+        frames.into_iter().map(RawFrame::Audio).collect()
+    }
+
+    /// Encode what comes in on `rx`. Each call into the encoder is marked on
+    /// `progress`; once retired, the loop stops without sending anything.
     fn encoder_loop(
-        mut encoder: Encoder,
+        mut encoder: Box<dyn FrameEncoder>,
         cancel: CancellationToken,
         rx: std::sync::mpsc::Receiver<RawFrameCmd>,
-        out: RawPacketSender,
+        context: LoopContext,
+        progress: Option<Arc<Progress>>,
     ) {
+        let LoopContext {
+            out,
+            lossless,
+            recovery,
+            events,
+            gap_fill,
+        } = context;
+        let mut gaps = gap_fill
+            .filter(|_| encoder.stream().is_audio())
+            .map(|config| GapFiller::new(encoder.stream().time_base(), config));
+        let mut escalation = Escalation {
+            recovery,
+            events,
+            failures: 0,
+            recovered: false,
+        };
+        let progress = progress.as_deref();
+        let retired = || progress.is_some_and(Progress::is_retired);
         loop {
             if cancel.is_cancelled() {
                 break;
             }
-            let mut eof = false;
             match rx.recv_timeout(Duration::from_millis(1)) {
                 Ok(frame) => {
                     match frame {
                         RawFrameCmd::Data(frame) => {
-                            if let Err(e) = encoder.send_frame(frame) {
-                                log::error!("send packet error: {}", e);
-                                continue;
+                            let frames = Self::fill_gaps(&*encoder, &mut gaps, &escalation, frame);
+                            let mut stop = false;
+                            for frame in frames {
+                                let source = FrameFormat::of(&frame);
+                                let sent = {
+                                    let _busy = progress.map(Progress::enter);
+                                    encoder.send_frame(frame)
+                                };
+                                match sent {
+                                    Ok(()) => escalation.failures = 0,
+                                    Err(e) => {
+                                        if !escalation.rejected(&mut *encoder, source, e) {
+                                            stop = true;
+                                            break;
+                                        }
+                                    }
+                                }
+                            }
+                            if stop {
+                                break;
                             }
                         }
                         RawFrameCmd::EOF => {
-                            if let Err(e) = encoder.send_eof() {
-                                log::error!("send eof error: {}", e);
-                            }
-                            eof = true;
+                            Self::flush(&mut *encoder, &out, &cancel, progress);
+                            break;
                         }
                     };
 
                     'outer: loop {
-                        match encoder.encoder_receive_packet() {
+                        if retired() {
+                            break 'outer;
+                        }
+                        let received = {
+                            let _busy = progress.map(Progress::enter);
+                            encoder.encoder_receive_packet()
+                        };
+                        match received {
                             Ok(Some(packet)) => {
-                                let _ = out.send(RawPacketCmd::Data(packet));
+                                send_packet_backpressure(
+                                    &out,
+                                    &cancel,
+                                    lossless,
+                                    RawPacketCmd::Data(packet),
+                                );
                             }
                             Ok(None) => {
                                 break 'outer;
                             }
                             Err(e) => {
-                                log::error!("receive packet error: {}", e);
+                                tracing::error!("receive packet error: {}", e);
                                 break 'outer;
                             }
                         }
                     }
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                    Self::flush(&mut *encoder, &out, &cancel, progress);
+                    break;
+                }
+            }
+        }
+        if retired() {
+            tracing::warn!("encoder loop left behind by the watchdog returned, exiting");
+            return;
+        }
+        // Behind every packet, whatever the mode.
+        send_packet_backpressure(&out, &cancel, true, RawPacketCmd::EOF);
+    }
 
-                    if eof {
-                        break;
-                    }
+    /// End of input: flush the encoder (resampler tail included) and send
+    /// every packet it still holds, losslessly, before the EOF that follows.
+    fn flush(
+        encoder: &mut dyn FrameEncoder,
+        out: &RawPacketSender,
+        cancel: &CancellationToken,
+        progress: Option<&Progress>,
+    ) {
+        let retired = || progress.is_some_and(Progress::is_retired);
+        if retired() {
+            return;
+        }
+        let sent = {
+            let _busy = progress.map(Progress::enter);
+            encoder.send_eof()
+        };
+        if let Err(e) = sent {
+            tracing::error!("send eof error: {}", e);
+            return;
+        }
+        let mut flushed = 0usize;
+        // After send_eof the encoder never asks for more input, so `None`
+        // is its EOF.
+        while !retired() {
+            let received = {
+                let _busy = progress.map(Progress::enter);
+                encoder.encoder_receive_packet()
+            };
+            match received {
+                Ok(Some(packet)) => {
+                    flushed += 1;
+                    send_packet_backpressure(out, cancel, true, RawPacketCmd::Data(packet));
                 }
-                Err(_) => (),
+                Ok(None) => break,
+                Err(e) => {
+                    tracing::error!("receive packet error while flushing: {}", e);
+                    break;
+                }
+            }
+        }
+        tracing::debug!("encoder flushed {} packets at EOF", flushed);
+    }
+}
+
+/// The pixel or sample format of a frame, for reporting it.
+#[derive(Clone, Copy)]
+enum FrameFormat {
+    Video(ffmpeg_next::format::Pixel),
+    Audio(ffmpeg_next::format::Sample),
+}
+
+impl FrameFormat {
+    fn of(frame: &RawFrame) -> Self {
+        match frame {
+            RawFrame::Video(f) => FrameFormat::Video(f.format()),
+            RawFrame::Audio(f) => FrameFormat::Audio(f.format()),
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            FrameFormat::Video(pixel) => pixel_name(pixel),
+            FrameFormat::Audio(sample) => sample.name().to_string(),
+        }
+    }
+}
+
+/// An encoder loop's count of rejected frames, and what it does once they
+/// reach the threshold.
+struct Escalation {
+    recovery: EncoderRecovery,
+    events: Option<tokio::sync::broadcast::Sender<BusEvent>>,
+    /// Frames rejected in a row.
+    failures: u32,
+    /// Whether [`Encoder::recover`] already ran.
+    recovered: bool,
+}
+
+impl Escalation {
+    /// Count a frame in `source` format that `encoder` rejected with `error`.
+    /// Returns false when the loop must stop: the threshold was reached and
+    /// recovery is off, already spent or impossible.
+    fn rejected(
+        &mut self,
+        encoder: &mut dyn FrameEncoder,
+        source: FrameFormat,
+        error: anyhow::Error,
+    ) -> bool {
+        self.failures += 1;
+        let threshold = self.recovery.failure_threshold.max(1);
+        if self.failures == 1 {
+            tracing::error!("send frame error: {}", error);
+        } else {
+            tracing::debug!("send frame error ({} in a row): {}", self.failures, error);
+        }
+        if self.failures < threshold {
+            return true;
+        }
+        self.failures = 0;
+
+        let recovered = match source {
+            FrameFormat::Video(pixel) if self.recovery.rebuild_scaler && !self.recovered => {
+                encoder.recover(pixel, self.recovery.downconvert)
+            }
+            _ => Err(error),
+        };
+        let (event, keep_going) = match recovered {
+            Ok(r) => {
+                self.recovered = true;
+                let how = if r.downconverted {
+                    "downconverting them"
+                } else {
+                    "converting each from its own format"
+                };
+                tracing::warn!(
+                    "encoder {} rejected {} {} frames: {} to {}",
+                    encoder.name(),
+                    threshold,
+                    pixel_name(r.from),
+                    how,
+                    pixel_name(r.to)
+                );
+                let event = BusEvent::EncoderRecovered {
+                    stream_index: encoder.stream().index(),
+                    encoder: encoder.name().to_string(),
+                    from: pixel_name(r.from),
+                    to: pixel_name(r.to),
+                    downconverted: r.downconverted,
+                };
+                (event, true)
             }
+            Err(e) => {
+                tracing::error!(
+                    "encoder {} stopped: {} {} frames rejected in a row: {:#}",
+                    encoder.name(),
+                    threshold,
+                    source.name(),
+                    e
+                );
+                let event = BusEvent::EncoderFailed {
+                    stream_index: encoder.stream().index(),
+                    encoder: encoder.name().to_string(),
+                    input_format: source.name(),
+                    encoder_format: encoder.format_name(),
+                    error: format!("{e:#}"),
+                };
+                (event, false)
+            }
+        };
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
         }
-        let _ = out.send(RawPacketCmd::EOF);
+        keep_going
     }
 }
+
+/// Presets x264 and x265 take; their `preset` AVOption is a plain string the
+/// library checks itself, so FFmpeg cannot list them.
+const X264_PRESETS: [&str; 10] = [
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+    "placebo",
+];
+
+/// One problem [`validate`] found in an [`EncodeConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// The field at fault: `codec`, `preset`, `pixel_format`, `width` or
+    /// `height`.
+    pub field: &'static str,
+    pub message: String,
+    /// What would work instead: the closest valid name, or the supported
+    /// values.
+    pub suggestion: Option<String>,
+}
+
+impl ValidationIssue {
+    pub(crate) fn new(field: &'static str, message: String, suggestion: Option<String>) -> Self {
+        Self {
+            field,
+            message,
+            suggestion,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " ({suggestion})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every issue of a rejected [`EncodeConfig`], as an error; what
+/// [`Bus::add_output`](crate::bus::Bus::add_output) fails with, so callers can
+/// downcast for the list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidEncodeConfig(pub Vec<ValidationIssue>);
+
+impl std::fmt::Display for InvalidEncodeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid encode config: ")?;
+        for (i, issue) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{issue}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidEncodeConfig {}
+
+/// An [`EncodeConfig`] [`validate`] accepted, and what it resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedEncodeConfig {
+    pub config: EncodeConfig,
+    /// The encoder that takes the config: for video, the first of the
+    /// codec's fallback chain ([`hw::video_encoder_candidates`]) this FFmpeg
+    /// build has and the config fits.
+    pub encoder: String,
+    pub is_hw: bool,
+    pub medium: ffmpeg_next::media::Type,
+    /// The pixel format of the encoded video; `None` for audio.
+    pub pixel_format: Option<ffmpeg_next::format::Pixel>,
+}
+
+/// Check `config` against the encoder it would get, before any encoder is
+/// opened: the codec must resolve to an encoder, and for video the preset
+/// must be one the encoder offers (an encoder without presets would silently
+/// ignore it), the pixel format one it takes, and the size a multiple of the
+/// format's chroma subsampling. Returns every issue at once, with
+/// suggestions.
+///
+/// Hardware encoders are chosen by name only: one compiled in but without a
+/// device still passes, and the encoder then falls back to software when it
+/// fails to open. The tune is not checked: it is not configurable.
+pub fn validate(config: &EncodeConfig) -> Result<ValidatedEncodeConfig, Vec<ValidationIssue>> {
+    use ffmpeg_next::media::Type;
+
+    let requested = match config.codec.trim() {
+        "" => "h264",
+        codec => codec,
+    };
+    if let Some(codec) =
+        ffmpeg_next::encoder::find_by_name(requested).filter(|c| c.medium() == Type::Audio)
+    {
+        return Ok(ValidatedEncodeConfig {
+            config: config.clone(),
+            encoder: codec.name().to_string(),
+            is_hw: false,
+            medium: Type::Audio,
+            pixel_format: None,
+        });
+    }
+
+    let mut last_issues = None;
+    for candidate in hw::video_encoder_candidates(Some(requested)) {
+        let Some(codec) = ffmpeg_next::encoder::find_by_name(&candidate.name) else {
+            continue;
+        };
+        if codec.medium() != Type::Video {
+            return Err(vec![ValidationIssue::new(
+                "codec",
+                format!("{} is not a video or audio encoder", candidate.name),
+                None,
+            )]);
+        }
+        let (pixel_format, issues) = check_video(config, codec);
+        if issues.is_empty() {
+            return Ok(ValidatedEncodeConfig {
+                config: config.clone(),
+                encoder: candidate.name,
+                is_hw: candidate.is_hw,
+                medium: Type::Video,
+                pixel_format: Some(pixel_format),
+            });
+        }
+        // The chain ends in software; its issues are the ones that stand.
+        last_issues = Some(issues);
+    }
+    Err(last_issues.unwrap_or_else(|| {
+        let names = encoder_names();
+        let available = format!("available: {}", available_codecs().join(", "));
+        vec![ValidationIssue::new(
+            "codec",
+            format!("no encoder for {requested:?} in this FFmpeg build"),
+            Some(match closest(requested, names.iter().map(String::as_str)) {
+                Some(name) => format!("did you mean {name}? {available}"),
+                None => available,
+            }),
+        )]
+    }))
+}
+
+/// The issues of a video `config` for `codec`, and the pixel format it would
+/// encode in.
+fn check_video(
+    config: &EncodeConfig,
+    codec: ffmpeg_next::Codec,
+) -> (ffmpeg_next::format::Pixel, Vec<ValidationIssue>) {
+    use ffmpeg_next::format::Pixel;
+
+    let mut issues = Vec::new();
+    let name = codec.name();
+    let formats = supported_pixel_formats(codec);
+    let format_names = || {
+        formats
+            .iter()
+            .filter_map(|f| Some(f.descriptor()?.name()))
+            .collect::<Vec<_>>()
+    };
+    let default_format = if formats.is_empty() || formats.contains(&Pixel::YUV420P) {
+        Pixel::YUV420P
+    } else {
+        formats[0]
+    };
+
+    let mut pixel_format = default_format;
+    if let Some(wanted) = config.pixel_format.as_deref() {
+        match wanted.parse::<Pixel>() {
+            Ok(format) if format != Pixel::None => {
+                if formats.is_empty() || formats.contains(&format) {
+                    pixel_format = format;
+                } else {
+                    issues.push(ValidationIssue::new(
+                        "pixel_format",
+                        format!("{name} does not take {wanted}"),
+                        Some(format!("supported: {}", format_names().join(", "))),
+                    ));
+                }
+            }
+            _ => issues.push(ValidationIssue::new(
+                "pixel_format",
+                format!("unknown pixel format {wanted:?}"),
+                match closest(wanted, format_names()) {
+                    Some(format) => Some(format!("did you mean {format}?")),
+                    None => (!formats.is_empty())
+                        .then(|| format!("supported: {}", format_names().join(", "))),
+                },
+            )),
+        }
+    }
+
+    if let Some(preset) = config.preset.as_deref() {
+        match encoder_presets(codec) {
+            Some(presets) if presets.is_empty() => {}
+            Some(presets) if presets.iter().any(|p| p == preset) => {}
+            Some(presets) => issues.push(ValidationIssue::new(
+                "preset",
+                format!("{name} has no preset {preset:?}"),
+                Some(match closest(preset, presets.iter().map(String::as_str)) {
+                    Some(p) => format!("did you mean {p}?"),
+                    None => format!("supported: {}", presets.join(", ")),
+                }),
+            )),
+            None => issues.push(ValidationIssue::new(
+                "preset",
+                format!("{name} has no presets and would ignore {preset:?}"),
+                None,
+            )),
+        }
+    }
+
+    // 4:2:0 formats need even sizes, 4:1:1 widths a multiple of 4, and so on.
+    let (log2_w, log2_h) = pixel_format.descriptor().map_or((0, 0), |d| unsafe {
+        let d = &*d.as_ptr();
+        (d.log2_chroma_w, d.log2_chroma_h)
+    });
+    for (field, value, log2) in [
+        ("width", config.width, log2_w),
+        ("height", config.height, log2_h),
+    ] {
+        let Some(value) = value else {
+            continue;
+        };
+        let step = 1u32 << log2;
+        if value == 0 {
+            issues.push(ValidationIssue::new(
+                field,
+                "must not be zero".to_string(),
+                None,
+            ));
+        } else if value % step != 0 {
+            let format = pixel_format.descriptor().map_or("", |d| d.name());
+            issues.push(ValidationIssue::new(
+                field,
+                format!("{value} is not a multiple of {step}, as {format} needs"),
+                Some(format!("use {}", (value - value % step).max(step))),
+            ));
+        }
+    }
+    (pixel_format, issues)
+}
+
+/// The pixel formats `codec` takes; empty when it does not say.
+fn supported_pixel_formats(codec: ffmpeg_next::Codec) -> Vec<ffmpeg_next::format::Pixel> {
+    let mut out = Vec::new();
+    unsafe {
+        let mut p = (*codec.as_ptr()).pix_fmts;
+        if p.is_null() {
+            return out;
+        }
+        while *p != ffmpeg_next::ffi::AVPixelFormat::AV_PIX_FMT_NONE {
+            out.push((*p).into());
+            p = p.add(1);
+        }
+    }
+    out
+}
+
+/// The presets `codec` offers: `None` when it has no `preset` option, empty
+/// when it has one but cannot list the values.
+fn encoder_presets(codec: ffmpeg_next::Codec) -> Option<Vec<String>> {
+    use ffmpeg_next::ffi::AVOptionType;
+    use std::ffi::CStr;
+
+    let options = unsafe {
+        let class = (*codec.as_ptr()).priv_class;
+        if class.is_null() || (*class).option.is_null() {
+            return None;
+        }
+        let mut options = Vec::new();
+        let mut opt = (*class).option;
+        while !(*opt).name.is_null() {
+            options.push(&*opt);
+            opt = opt.add(1);
+        }
+        options
+    };
+    let name = |p: *const std::ffi::c_char| unsafe { CStr::from_ptr(p) }.to_string_lossy();
+    let preset = options.iter().find(|o| name(o.name) == "preset")?;
+    if preset.unit.is_null() {
+        // x264 and x265 check their own (string) presets.
+        let x264 = codec.name().starts_with("libx264") || codec.name() == "libx265";
+        return Some(if x264 {
+            X264_PRESETS.iter().map(|p| p.to_string()).collect()
+        } else {
+            Vec::new()
+        });
+    }
+    let unit = name(preset.unit);
+    Some(
+        options
+            .iter()
+            .filter(|o| {
+                o.type_ == AVOptionType::AV_OPT_TYPE_CONST
+                    && !o.unit.is_null()
+                    && name(o.unit) == unit
+            })
+            .map(|o| name(o.name).into_owned())
+            .collect(),
+    )
+}
+
+/// The codec names [`EncodeConfig::codec`] commonly takes that this FFmpeg
+/// build can encode, each with the encoder it resolves to first, e.g.
+/// `hevc (hevc_nvenc)`. Any encoder name is accepted as well.
+pub fn available_codecs() -> Vec<String> {
+    ["h264", "hevc", "vp9", "mjpeg", "rawvideo", "aac", "opus"]
+        .into_iter()
+        .filter_map(|codec| {
+            let encoder = hw::video_encoder_candidates(Some(codec))
+                .into_iter()
+                .find(|candidate| ffmpeg_next::encoder::find_by_name(&candidate.name).is_some())?;
+            Some(format!("{codec} ({})", encoder.name))
+        })
+        .collect()
+}
+
+/// Names of every encoder in this FFmpeg build.
+fn encoder_names() -> Vec<String> {
+    let mut names = Vec::new();
+    let mut opaque = std::ptr::null_mut();
+    unsafe {
+        loop {
+            let codec = ffmpeg_next::ffi::av_codec_iterate(&mut opaque);
+            if codec.is_null() {
+                break;
+            }
+            if ffmpeg_next::ffi::av_codec_is_encoder(codec) != 0 {
+                names.push(
+                    std::ffi::CStr::from_ptr((*codec).name)
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+            }
+        }
+    }
+    names
+}
+
+/// The candidate closest to `wanted` by edit distance, if close enough to be
+/// a likely typo.
+fn closest<'a>(wanted: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let wanted = wanted.to_ascii_lowercase();
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(&wanted, &c.to_ascii_lowercase()), c))
+        .filter(|(d, _)| *d <= (wanted.len() / 3).max(2))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+#[path = "encoder_test.rs"]
+mod encoder_test;
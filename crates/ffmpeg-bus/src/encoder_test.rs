@@ -0,0 +1,435 @@
+use ffmpeg_next::{format::Pixel, media::Type};
+
+use super::*;
+
+fn h264() -> EncodeConfig {
+    EncodeConfig {
+        codec: "libx264".to_string(),
+        width: Some(1280),
+        height: Some(720),
+        preset: Some("veryfast".to_string()),
+        pixel_format: Some("yuv420p".to_string()),
+        ..Default::default()
+    }
+}
+
+fn fields(issues: &[ValidationIssue]) -> Vec<&str> {
+    issues.iter().map(|i| i.field).collect()
+}
+
+#[test]
+fn valid_config_resolves_an_encoder() {
+    crate::init().unwrap();
+    let validated = validate(&h264()).unwrap();
+    assert_eq!(validated.medium, Type::Video);
+    assert_eq!(validated.pixel_format, Some(Pixel::YUV420P));
+    assert!(
+        hw::video_encoder_candidates(Some("libx264"))
+            .iter()
+            .any(|c| c.name == validated.encoder && c.is_hw == validated.is_hw)
+    );
+    if !validated.is_hw {
+        assert_eq!(validated.encoder, "libx264");
+    }
+
+    let aac = validate(&EncodeConfig {
+        codec: "aac".to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(aac.medium, Type::Audio);
+    assert_eq!(aac.pixel_format, None);
+}
+
+#[test]
+fn invalid_preset_suggests_the_closest() {
+    crate::init().unwrap();
+    let issues = validate(&EncodeConfig {
+        preset: Some("ultrafst".to_string()),
+        ..h264()
+    })
+    .unwrap_err();
+    assert_eq!(fields(&issues), ["preset"]);
+    assert_eq!(
+        issues[0].suggestion.as_deref(),
+        Some("did you mean ultrafast?")
+    );
+
+    let issues = validate(&EncodeConfig {
+        preset: Some("zzz".to_string()),
+        ..h264()
+    })
+    .unwrap_err();
+    let suggestion = issues[0].suggestion.as_deref().unwrap();
+    assert!(
+        suggestion.starts_with("supported: ultrafast, "),
+        "{suggestion}"
+    );
+}
+
+#[test]
+fn odd_dimensions_with_yuv420p_are_rejected() {
+    crate::init().unwrap();
+    let issues = validate(&EncodeConfig {
+        width: Some(1279),
+        height: Some(721),
+        ..h264()
+    })
+    .unwrap_err();
+    assert_eq!(fields(&issues), ["width", "height"]);
+    assert_eq!(issues[0].suggestion.as_deref(), Some("use 1278"));
+    assert!(issues[1].message.contains("yuv420p"), "{}", issues[1]);
+
+    // Without chroma subsampling any size goes.
+    assert!(
+        validate(&EncodeConfig {
+            width: Some(1279),
+            pixel_format: Some("yuv444p".to_string()),
+            ..h264()
+        })
+        .is_ok()
+    );
+}
+
+#[test]
+fn unsupported_pixel_format_lists_the_supported() {
+    crate::init().unwrap();
+    let issues = validate(&EncodeConfig {
+        pixel_format: Some("rgb24".to_string()),
+        ..h264()
+    })
+    .unwrap_err();
+    assert_eq!(fields(&issues), ["pixel_format"]);
+    let suggestion = issues[0].suggestion.as_deref().unwrap();
+    assert!(suggestion.contains("yuv420p"), "{suggestion}");
+
+    let issues = validate(&EncodeConfig {
+        pixel_format: Some("yuv420".to_string()),
+        ..h264()
+    })
+    .unwrap_err();
+    assert_eq!(fields(&issues), ["pixel_format"]);
+}
+
+#[test]
+fn unknown_codec_is_one_issue() {
+    crate::init().unwrap();
+    let issues = validate(&EncodeConfig {
+        codec: "no-such-codec".to_string(),
+        ..h264()
+    })
+    .unwrap_err();
+    assert_eq!(fields(&issues), ["codec"]);
+    assert!(issues[0].message.contains("no-such-codec"));
+    // With what this build can encode instead.
+    let suggestion = issues[0].suggestion.as_deref().unwrap_or_default();
+    assert!(suggestion.contains("available: h264 ("), "{suggestion}");
+
+    let all = InvalidEncodeConfig(issues).to_string();
+    assert!(all.starts_with("invalid encode config: codec: "), "{all}");
+}
+
+#[test]
+fn closest_matches_typos_only() {
+    let names = ["ultrafast", "superfast", "medium"];
+    assert_eq!(closest("ultrafst", names), Some("ultrafast"));
+    assert_eq!(closest("MEDIUM", names), Some("medium"));
+    assert_eq!(closest("zzz", names), None);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+}
+
+/// A libx264 encoder for 64x48 yuv420p, fed by an NV12 source: its scaler is
+/// built for NV12 by the first frame.
+fn x264_for_nv12() -> Encoder {
+    let stream = AvStream::new(
+        0,
+        ffmpeg_next::codec::Parameters::new(),
+        Rational(1, 25),
+        Rational(25, 1),
+    );
+    let settings = Settings {
+        width: 64,
+        height: 48,
+        codec: Some("libx264".to_string()),
+        ..Settings::default()
+    };
+    Encoder::new(&stream, settings, None).unwrap()
+}
+
+fn frame(format: Pixel) -> RawFrameCmd {
+    RawFrameCmd::Data(RawFrame::Video(
+        ffmpeg_next::frame::Video::new(format, 64, 48).into(),
+    ))
+}
+
+/// Feed one NV12 frame, then P010 frames (a decoder switching to 10-bit
+/// output), to an encoder task under `recovery`. Returns the packets it
+/// produced before its stream ended, and its events.
+async fn feed_p010(recovery: EncoderRecovery) -> (usize, Vec<BusEvent>) {
+    let (events_tx, mut events) = tokio::sync::broadcast::channel(16);
+    let task = EncoderTask::new().with_recovery(recovery, events_tx);
+    let mut packets = task.subscribe();
+    let (frames, frames_rx) = tokio::sync::broadcast::channel(64);
+    task.start(x264_for_nv12(), frames_rx, true).await;
+
+    frames.send(frame(Pixel::NV12)).unwrap();
+    for _ in 0..20 {
+        frames.send(frame(Pixel::P010LE)).unwrap();
+    }
+    frames.send(RawFrameCmd::EOF).unwrap();
+
+    let mut count = 0;
+    let ended = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Ok(RawPacketCmd::Data(_)) = packets.recv().await {
+            count += 1;
+        }
+    })
+    .await;
+    assert!(ended.is_ok(), "encoder never ended its stream");
+    let mut seen = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        seen.push(event);
+    }
+    (count, seen)
+}
+
+#[tokio::test]
+async fn rejected_10bit_frames_are_downconverted() {
+    crate::init().unwrap();
+    let (packets, events) = feed_p010(EncoderRecovery {
+        failure_threshold: 5,
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(
+        events,
+        [BusEvent::EncoderRecovered {
+            stream_index: 0,
+            encoder: "libx264".to_string(),
+            from: "p010le".to_string(),
+            to: "yuv420p".to_string(),
+            downconverted: true,
+        }]
+    );
+    // The NV12 frame, then the P010 frames after the recovery: at most 15.
+    assert!((2..=16).contains(&packets), "{packets} packets");
+}
+
+#[tokio::test]
+async fn unrecoverable_encoder_fails_within_the_threshold() {
+    crate::init().unwrap();
+    let (packets, events) = feed_p010(EncoderRecovery {
+        failure_threshold: 5,
+        rebuild_scaler: true,
+        downconvert: false,
+    })
+    .await;
+    assert!(packets <= 1, "{packets} packets");
+    let [
+        BusEvent::EncoderFailed {
+            encoder,
+            input_format,
+            encoder_format,
+            error,
+            ..
+        },
+    ] = &events[..]
+    else {
+        panic!("{events:?}");
+    };
+    assert_eq!(encoder, "libx264");
+    assert_eq!(input_format, "p010le");
+    assert_eq!(encoder_format, "yuv420p");
+    assert!(error.contains("downconverting"), "{error}");
+
+    // Without recovery the threshold alone stops it.
+    let (_, events) = feed_p010(EncoderRecovery {
+        failure_threshold: 3,
+        rebuild_scaler: false,
+        downconvert: true,
+    })
+    .await;
+    assert!(matches!(events[..], [BusEvent::EncoderFailed { .. }]));
+}
+
+#[test]
+fn a_source_changing_resolution_keeps_encoding_at_the_encoder_size() {
+    crate::init().unwrap();
+    let mut encoder = x264_for_nv12();
+    let mut packets = Vec::new();
+    // A camera rebooting at another resolution, and back.
+    for (w, h) in [(64, 48), (128, 96), (128, 96), (32, 24), (64, 48)] {
+        for _ in 0..3 {
+            let frame = ffmpeg_next::frame::Video::new(Pixel::NV12, w, h);
+            encoder.send_frame(RawFrame::Video(frame.into())).unwrap();
+            while let Some(packet) = encoder.encoder_receive_packet().unwrap() {
+                packets.push(packet);
+            }
+        }
+        assert_eq!(encoder.scaler_input, Some((Pixel::NV12, w, h)));
+    }
+    encoder.send_eof().unwrap();
+    while let Some(packet) = encoder.encoder_receive_packet().unwrap() {
+        packets.push(packet);
+    }
+
+    let codec = ffmpeg_next::decoder::find(ffmpeg_next::codec::Id::H264).unwrap();
+    let mut decoder = ffmpeg_next::codec::Context::new_with_codec(codec)
+        .decoder()
+        .video()
+        .unwrap();
+    let mut decoded = ffmpeg_next::frame::Video::empty();
+    let mut sizes = Vec::new();
+    for packet in &packets {
+        decoder.send_packet(packet.packet()).unwrap();
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            sizes.push((decoded.width(), decoded.height()));
+        }
+    }
+    decoder.send_eof().unwrap();
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        sizes.push((decoded.width(), decoded.height()));
+    }
+    assert_eq!(sizes.len(), 15);
+    assert!(sizes.iter().all(|size| *size == (64, 48)), "{sizes:?}");
+}
+
+/// An encoder whose `stall_at`-th frame hangs until `release` is dropped,
+/// as a wedged hardware encoder would.
+struct Stalling {
+    inner: Encoder,
+    frames: usize,
+    stall_at: usize,
+    release: std::sync::mpsc::Receiver<()>,
+}
+
+impl FrameEncoder for Stalling {
+    fn send_frame(&mut self, frame: RawFrame) -> anyhow::Result<()> {
+        self.frames += 1;
+        if self.frames == self.stall_at {
+            let _ = self.release.recv();
+            anyhow::bail!("released");
+        }
+        self.inner.send_frame(frame)
+    }
+
+    fn encoder_receive_packet(&mut self) -> anyhow::Result<Option<RawPacket>> {
+        self.inner.encoder_receive_packet()
+    }
+
+    fn send_eof(&mut self) -> anyhow::Result<()> {
+        FrameEncoder::send_eof(&mut self.inner)
+    }
+
+    fn stream(&self) -> &AvStream {
+        FrameEncoder::stream(&self.inner)
+    }
+
+    fn name(&self) -> &str {
+        FrameEncoder::name(&self.inner)
+    }
+
+    fn format_name(&self) -> String {
+        FrameEncoder::format_name(&self.inner)
+    }
+
+    fn recover(
+        &mut self,
+        source: ffmpeg_next::format::Pixel,
+        downconvert: bool,
+    ) -> anyhow::Result<Recovery> {
+        self.inner.recover(source, downconvert)
+    }
+}
+
+/// The next packet, `None` after 10 s or when the channel fails.
+async fn next(packets: &mut RawPacketReceiver) -> Option<RawPacketCmd> {
+    tokio::time::timeout(Duration::from_secs(10), packets.recv())
+        .await
+        .ok()?
+        .ok()
+}
+
+#[tokio::test]
+async fn a_stalled_encoder_is_rebuilt_and_output_resumes() {
+    crate::init().unwrap();
+    let (events_tx, mut events) = tokio::sync::broadcast::channel(16);
+    let watchdog = Watchdog::new(
+        crate::watchdog::WatchdogConfig {
+            interval: Duration::from_millis(20),
+            encoder_stall: Duration::from_millis(200),
+            ..Default::default()
+        },
+        events_tx.clone(),
+    );
+    let cancel = CancellationToken::new();
+    watchdog.spawn(cancel.clone());
+    let task = EncoderTask::new()
+        .with_recovery(Default::default(), events_tx)
+        .watched(
+            watchdog,
+            Arc::new(|| Ok(Box::new(x264_for_nv12()) as Box<dyn FrameEncoder>)),
+        );
+    let mut packets = task.subscribe();
+    let (frames, frames_rx) = tokio::sync::broadcast::channel(64);
+    let (release, release_rx) = std::sync::mpsc::channel();
+    let stalling = Stalling {
+        inner: x264_for_nv12(),
+        frames: 0,
+        stall_at: 4,
+        release: release_rx,
+    };
+    task.start_boxed(Box::new(stalling), frames_rx, true).await;
+
+    for _ in 0..3 {
+        frames.send(frame(Pixel::NV12)).unwrap();
+        assert!(matches!(
+            next(&mut packets).await,
+            Some(RawPacketCmd::Data(_))
+        ));
+    }
+    // The fourth frame hangs the encoder.
+    frames.send(frame(Pixel::NV12)).unwrap();
+    let mut seen = Vec::new();
+    let recovered = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Ok(event) = events.recv().await {
+            let done = matches!(event, BusEvent::TaskRecovered { .. });
+            seen.push(event);
+            if done {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(recovered.is_ok(), "the stalled encoder was never rebuilt");
+    let [
+        BusEvent::TaskStalled {
+            task: TaskKind::Encoder,
+            name,
+            ..
+        },
+        BusEvent::TaskRecovered { recoveries: 1, .. },
+    ] = &seen[..]
+    else {
+        panic!("{seen:?}");
+    };
+    assert_eq!(name, "stream 0 (libx264)");
+    drop(release);
+
+    for _ in 0..5 {
+        frames.send(frame(Pixel::NV12)).unwrap();
+    }
+    frames.send(RawFrameCmd::EOF).unwrap();
+    let mut resumed = 0;
+    loop {
+        match next(&mut packets).await {
+            Some(RawPacketCmd::Data(_)) => resumed += 1,
+            Some(RawPacketCmd::Reconnected) => {}
+            Some(RawPacketCmd::EOF) => break,
+            None => panic!("the stream never ended"),
+        }
+    }
+    // Nothing from the encoder left behind.
+    assert_eq!(resumed, 5);
+    cancel.cancel();
+}
@@ -0,0 +1,181 @@
+//! Synthesized media for tests (`test-util` feature). [`ensure_fixture`]
+//! renders an MP4 from lavfi sources through this crate's own input, encoder
+//! and file mux path, so tests need no checked-in media. Files are cached
+//! under `<target>/fixtures`, named by a hash of their spec, so only the first
+//! run of a spec pays for the encode.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::bus::{Bus, EncodeConfig, InputConfig, OutputAvType, OutputConfig, OutputDest};
+
+/// How long one fixture may take to render.
+const RENDER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a fixture contains: an H.264 (or H.265) `testsrc` picture and
+/// optionally an AAC 440 Hz sine tone, muxed into MP4.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FixtureSpec {
+    pub duration_secs: u32,
+    pub fps: u32,
+    pub width: u32,
+    pub height: u32,
+    /// GOP length in frames.
+    pub keyframe_interval: u32,
+    /// Most consecutive B-frames; 0 for none.
+    pub b_frames: u32,
+    /// Encode the picture as H.265 instead of H.264.
+    pub hevc: bool,
+    /// Also carry the sine tone as AAC.
+    pub aac: bool,
+    /// Sample rate of the tone.
+    pub sample_rate: u32,
+}
+
+impl Default for FixtureSpec {
+    /// 5s of 320x240 at 10 fps with AAC audio at 44.1 kHz, no B-frames.
+    fn default() -> Self {
+        Self {
+            duration_secs: 5,
+            fps: 10,
+            width: 320,
+            height: 240,
+            keyframe_interval: 10,
+            b_frames: 0,
+            hevc: false,
+            aac: true,
+            sample_rate: 44_100,
+        }
+    }
+}
+
+impl FixtureSpec {
+    pub fn video_only(mut self) -> Self {
+        self.aac = false;
+        self
+    }
+
+    pub fn with_b_frames(mut self, b_frames: u32) -> Self {
+        self.b_frames = b_frames;
+        self
+    }
+
+    pub fn with_hevc(mut self) -> Self {
+        self.hevc = true;
+        self
+    }
+
+    fn file_name(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        format!("fixture-{:016x}.mp4", hasher.finish())
+    }
+
+    /// The lavfi graph: `testsrc` on `out0`, plus `sine` on `out1` with audio.
+    fn graph(&self) -> String {
+        let video = format!(
+            "testsrc=duration={}:size={}x{}:rate={}[out0]",
+            self.duration_secs, self.width, self.height, self.fps
+        );
+        if !self.aac {
+            return video;
+        }
+        format!(
+            "{video};sine=frequency=440:sample_rate={}:duration={}[out1]",
+            self.sample_rate, self.duration_secs
+        )
+    }
+}
+
+/// Where fixtures are cached: `$CARGO_TARGET_DIR/fixtures`, else the
+/// workspace's `target/fixtures`.
+fn cache_dir() -> PathBuf {
+    std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../target"))
+        .join("fixtures")
+}
+
+/// The path of an MP4 matching `spec`, rendering it first unless cached.
+pub async fn ensure_fixture(spec: &FixtureSpec) -> anyhow::Result<PathBuf> {
+    // Tests of one binary asking for the same fixture render it once.
+    static RENDERING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    crate::init()?;
+    let path = cache_dir().join(spec.file_name());
+    if path.exists() {
+        return Ok(path);
+    }
+    let _guard = RENDERING.lock().await;
+    if path.exists() {
+        return Ok(path);
+    }
+    std::fs::create_dir_all(cache_dir())?;
+    // Rendered under a unique name and renamed into place, so a concurrent
+    // test binary never sees a partial file.
+    let tmp = cache_dir().join(format!(
+        "{}.{}.tmp.mp4",
+        spec.file_name(),
+        std::process::id()
+    ));
+    let result = render(spec, &tmp).await;
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.context(format!("render fixture {spec:?}")));
+    }
+    std::fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+async fn render(spec: &FixtureSpec, path: &std::path::Path) -> anyhow::Result<()> {
+    let bus = Bus::new("fixture");
+    bus.add_input(
+        InputConfig::Device {
+            display: spec.graph(),
+            format: "lavfi".to_string(),
+        },
+        None,
+    )
+    .await?;
+
+    let mut output = OutputConfig::new(
+        "fixture".to_string(),
+        OutputAvType::Video,
+        OutputDest::File {
+            path: path.to_string_lossy().into_owned(),
+        },
+    )
+    .with_encode(EncodeConfig {
+        codec: if spec.hevc { "hevc" } else { "h264" }.to_string(),
+        keyframe_interval: Some(spec.keyframe_interval.max(1)),
+        b_frames: Some(spec.b_frames),
+        ..EncodeConfig::default()
+    });
+    if spec.aac {
+        output = output.with_audio().with_audio_encode(EncodeConfig {
+            codec: "aac".to_string(),
+            sample_rate: Some(spec.sample_rate),
+            ..EncodeConfig::default()
+        });
+    }
+    bus.add_output(output).await?;
+
+    // The MP4 only opens once the muxer has written its index at EOF.
+    let streams = if spec.aac { 2 } else { 1 };
+    let deadline = tokio::time::Instant::now() + RENDER_TIMEOUT;
+    let path = path.to_string_lossy().into_owned();
+    loop {
+        if crate::metadata::probe(&path).is_ok_and(|info| info.streams.len() == streams) {
+            return Ok(());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("{path} was not finalized within {RENDER_TIMEOUT:?}");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[cfg(test)]
+#[path = "fixture_test.rs"]
+mod fixture_test;
@@ -0,0 +1,81 @@
+use super::*;
+
+fn reorders_frames(path: &std::path::Path) -> anyhow::Result<bool> {
+    let mut input = ffmpeg_next::format::input(path)?;
+    let index = input
+        .streams()
+        .best(ffmpeg_next::media::Type::Video)
+        .ok_or_else(|| anyhow::anyhow!("no video stream"))?
+        .index();
+    let mut last_pts = None;
+    for (stream, packet) in input.packets() {
+        if stream.index() != index {
+            continue;
+        }
+        if let (Some(last), Some(pts)) = (last_pts, packet.pts())
+            && pts < last
+        {
+            return Ok(true);
+        }
+        last_pts = packet.pts();
+    }
+    Ok(false)
+}
+
+#[tokio::test]
+async fn fixture_probe_matches_spec() -> anyhow::Result<()> {
+    let spec = FixtureSpec {
+        duration_secs: 2,
+        fps: 15,
+        width: 160,
+        height: 120,
+        sample_rate: 48_000,
+        ..FixtureSpec::default()
+    }
+    .with_b_frames(2);
+    let path = ensure_fixture(&spec).await?;
+    assert_eq!(
+        path.file_name().unwrap().to_string_lossy(),
+        spec.file_name()
+    );
+
+    let info = crate::metadata::probe(&path.to_string_lossy())?;
+    let duration = info.format.duration_sec.unwrap_or_default();
+    assert!((1.6..=2.4).contains(&duration), "duration {duration}");
+    let video = info
+        .streams
+        .iter()
+        .find(|s| s.codec_type == "video")
+        .unwrap();
+    assert_eq!(video.codec_name, "h264");
+    assert_eq!((video.width, video.height), (Some(160), Some(120)));
+    let audio = info
+        .streams
+        .iter()
+        .find(|s| s.codec_type == "audio")
+        .unwrap();
+    assert_eq!(audio.codec_name, "aac");
+    assert_eq!(audio.sample_rate, Some(48_000));
+    assert!(reorders_frames(&path)?, "expected B-frames");
+
+    // Cached: the same file, not rendered again.
+    let modified = std::fs::metadata(&path)?.modified()?;
+    assert_eq!(ensure_fixture(&spec).await?, path);
+    assert_eq!(std::fs::metadata(&path)?.modified()?, modified);
+    Ok(())
+}
+
+#[tokio::test]
+async fn video_only_fixture_has_no_audio_or_b_frames() -> anyhow::Result<()> {
+    let spec = FixtureSpec {
+        duration_secs: 1,
+        ..FixtureSpec::default()
+    }
+    .video_only();
+    let path = ensure_fixture(&spec).await?;
+    let info = crate::metadata::probe(&path.to_string_lossy())?;
+    assert_eq!(info.streams.len(), 1);
+    assert_eq!(info.streams[0].codec_type, "video");
+    assert!(!reorders_frames(&path)?);
+    Ok(())
+}
@@ -3,9 +3,13 @@ use std::sync::Arc;
 
 use bytes::Bytes;
 use ffmpeg_next::Rational;
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling::Context;
 
+use crate::memory::{Charged, frame_bytes};
 use crate::output::OutputMessage;
 use crate::packet::RawPacket;
+use crate::scaler::{Scaler, ScalerKey};
 
 pub type RawFrameSender = tokio::sync::broadcast::Sender<RawFrameCmd>;
 pub type RawFrameReceiver = tokio::sync::broadcast::Receiver<RawFrameCmd>;
@@ -24,7 +28,7 @@ pub enum RawFrame {
 
 #[derive(Clone)]
 pub struct RawAudioFrame {
-    frame: Arc<ffmpeg_next::frame::Audio>,
+    frame: Arc<Charged<ffmpeg_next::frame::Audio>>,
 }
 
 impl RawAudioFrame {
@@ -51,49 +55,250 @@ impl RawAudioFrame {
 
 impl From<ffmpeg_next::frame::Audio> for RawAudioFrame {
     fn from(frame: ffmpeg_next::frame::Audio) -> Self {
+        let bytes = frame_bytes(&frame);
         Self {
-            frame: Arc::new(frame),
+            frame: Arc::new(Charged::global(frame, bytes)),
         }
     }
 }
 
 #[derive(Clone)]
 pub struct RawVideoFrame {
-    frame: Arc<ffmpeg_next::frame::Video>,
+    frame: Arc<Charged<ffmpeg_next::frame::Video>>,
 }
 
 impl From<ffmpeg_next::frame::Video> for RawVideoFrame {
     fn from(frame: ffmpeg_next::frame::Video) -> Self {
+        let bytes = frame_bytes(&frame);
         Self {
-            frame: Arc::new(frame),
+            frame: Arc::new(Charged::global(frame, bytes)),
         }
     }
 }
 
 /// Converts a raw video packet into a RawFrame::Video. Used when input is already raw (e.g. RAWVIDEO)
-/// and needs to be fed to the encoder as frame. Requires stream dimensions and pixel format.
+/// and needs to be fed to the encoder as frame. Requires stream dimensions and pixel format; the
+/// packet holds every plane of `pixel_format` back to back without row padding.
 pub fn packet_to_raw_video_frame(
     packet: RawPacket,
     width: u32,
     height: u32,
     pixel_format: ffmpeg_next::format::Pixel,
 ) -> anyhow::Result<RawFrame> {
-    use ffmpeg_next::frame::Video;
     if width == 0 || height == 0 {
         anyhow::bail!("invalid video size {}x{}", width, height);
     }
     if pixel_format == ffmpeg_next::format::Pixel::None {
         anyhow::bail!("invalid pixel format for raw video");
     }
-    let mut frame = Video::new(pixel_format, width, height);
-    let packet_data = packet.data();
-    let frame_buf = frame.data_mut(0);
-    let copy_len = packet_data.len().min(frame_buf.len());
-    frame_buf[..copy_len].copy_from_slice(&packet_data[..copy_len]);
+    let mut frame = unpack_planes(&packet.data(), pixel_format, width, height)?;
     frame.set_pts(packet.pts());
     Ok(RawFrame::Video(RawVideoFrame::from(frame)))
 }
 
+/// `format` of a [`VideoFrame`] (an `AVPixelFormat` value) as a [`Pixel`];
+/// `Pixel::None` for values outside the enum.
+pub fn pixel_from_raw(format: i32) -> Pixel {
+    if !(-1..ffmpeg_next::ffi::AVPixelFormat::AV_PIX_FMT_NB as i32).contains(&format) {
+        return Pixel::None;
+    }
+    Pixel::from(unsafe { std::mem::transmute::<i32, ffmpeg_next::ffi::AVPixelFormat>(format) })
+}
+
+/// Bytes per row and rows of each plane of a `width`x`height` image in
+/// system memory.
+fn plane_layout(format: Pixel, width: u32, height: u32) -> anyhow::Result<Vec<(usize, usize)>> {
+    use ffmpeg_next::ffi;
+
+    let desc = format
+        .descriptor()
+        .ok_or_else(|| anyhow::anyhow!("unknown pixel format {:?}", format))?;
+    let flags = unsafe { (*desc.as_ptr()).flags };
+    if flags & (ffi::AV_PIX_FMT_FLAG_HWACCEL as u64 | ffi::AV_PIX_FMT_FLAG_PAL as u64) != 0 {
+        anyhow::bail!("unsupported pixel format {:?}", format);
+    }
+    let planes = unsafe { ffi::av_pix_fmt_count_planes(format.into()) };
+    if planes <= 0 {
+        anyhow::bail!("no planes in pixel format {:?}", format);
+    }
+    (0..planes)
+        .map(|plane| {
+            let bytewidth =
+                unsafe { ffi::av_image_get_linesize(format.into(), width as i32, plane) };
+            if bytewidth <= 0 {
+                anyhow::bail!("no linesize for plane {} of {:?}", plane, format);
+            }
+            // Planes 1 and 2 are the (possibly subsampled) chroma planes.
+            let rows = if plane == 1 || plane == 2 {
+                (height as usize).div_ceil(1 << desc.log2_chroma_h())
+            } else {
+                height as usize
+            };
+            Ok((bytewidth as usize, rows))
+        })
+        .collect()
+}
+
+/// Every plane of a software frame back to back, without row padding (the
+/// `av_image_copy_to_buffer` layout with alignment 1).
+pub fn pack_planes(frame: &ffmpeg_next::frame::Video) -> anyhow::Result<Vec<u8>> {
+    let layout = plane_layout(frame.format(), frame.width(), frame.height())?;
+    let mut out = Vec::with_capacity(layout.iter().map(|(w, rows)| w * rows).sum());
+    for (plane, (bytewidth, rows)) in layout.into_iter().enumerate() {
+        let stride = frame.stride(plane);
+        let data = frame.data(plane);
+        for row in 0..rows {
+            out.extend_from_slice(&data[row * stride..row * stride + bytewidth]);
+        }
+    }
+    Ok(out)
+}
+
+/// The inverse of [`pack_planes`]: a new frame from tightly packed planes.
+pub fn unpack_planes(
+    data: &[u8],
+    format: Pixel,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<ffmpeg_next::frame::Video> {
+    let layout = plane_layout(format, width, height)?;
+    let needed: usize = layout.iter().map(|(w, rows)| w * rows).sum();
+    if data.len() < needed {
+        anyhow::bail!(
+            "{}x{} {:?} frame needs {} bytes, got {}",
+            width,
+            height,
+            format,
+            needed,
+            data.len()
+        );
+    }
+    let mut frame = ffmpeg_next::frame::Video::new(format, width, height);
+    let mut offset = 0;
+    for (plane, (bytewidth, rows)) in layout.into_iter().enumerate() {
+        let stride = frame.stride(plane);
+        let dst = frame.data_mut(plane);
+        for row in 0..rows {
+            dst[row * stride..row * stride + bytewidth]
+                .copy_from_slice(&data[offset..offset + bytewidth]);
+            offset += bytewidth;
+        }
+    }
+    Ok(frame)
+}
+
+/// Whether frames of `format` live on a hardware surface.
+pub(crate) fn is_hw_format(format: Pixel) -> bool {
+    format.descriptor().is_some_and(|desc| {
+        unsafe { (*desc.as_ptr()).flags }
+        &ffmpeg_next::ffi::AV_PIX_FMT_FLAG_HWACCEL as u64 != 0
+    })
+}
+
+/// Bits per sample of `format`'s first component (8 for YUV420P, 10 for
+/// P010); `None` for unknown formats.
+pub(crate) fn bit_depth(format: Pixel) -> Option<u32> {
+    let desc = format.descriptor()?;
+    let depth = unsafe { (*desc.as_ptr()).comp[0].depth };
+    u32::try_from(depth).ok().filter(|&d| d > 0)
+}
+
+/// `frame` itself if it is in system memory, else a copy downloaded from its
+/// hardware surface (typically NV12).
+pub(crate) fn to_software(
+    frame: &ffmpeg_next::frame::Video,
+) -> anyhow::Result<std::borrow::Cow<'_, ffmpeg_next::frame::Video>> {
+    if !is_hw_format(frame.format()) {
+        return Ok(std::borrow::Cow::Borrowed(frame));
+    }
+    download(frame).map(std::borrow::Cow::Owned)
+}
+
+/// A copy of the hardware `frame` in system memory, with its timestamps,
+/// color properties and side data.
+pub(crate) fn download(
+    frame: &ffmpeg_next::frame::Video,
+) -> anyhow::Result<ffmpeg_next::frame::Video> {
+    use ffmpeg_next::ffi;
+
+    let mut sw = ffmpeg_next::frame::Video::empty();
+    let ret = unsafe { ffi::av_hwframe_transfer_data(sw.as_mut_ptr(), frame.as_ptr(), 0) };
+    if ret < 0 {
+        anyhow::bail!(
+            "download {:?} frame: {}",
+            frame.format(),
+            ffmpeg_next::Error::from(ret)
+        );
+    }
+    let ret = unsafe { ffi::av_frame_copy_props(sw.as_mut_ptr(), frame.as_ptr()) };
+    if ret < 0 {
+        anyhow::bail!("copy frame properties: {}", ffmpeg_next::Error::from(ret));
+    }
+    Ok(sw)
+}
+
+/// Convert a decoded frame of any pixel format (hardware frames are
+/// downloaded first) to `format` at the same size, via swscale. Full-range
+/// sources flagged only through the frame's color range (e.g. NV12 from a
+/// hardware decoder, as opposed to the YUVJ formats) are read as full range.
+pub fn convert_video(
+    frame: &ffmpeg_next::frame::Video,
+    format: Pixel,
+) -> anyhow::Result<ffmpeg_next::frame::Video> {
+    scale_video(frame, format, frame.width(), frame.height())
+}
+
+/// Like [`convert_video`], also resizing to `width`x`height` (bilinear).
+pub fn scale_video(
+    frame: &ffmpeg_next::frame::Video,
+    format: Pixel,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<ffmpeg_next::frame::Video> {
+    let frame = to_software(frame)?;
+    let mut scaler = Scaler::for_key(&ScalerKey::of(&frame, format, width, height)?)?;
+    let mut dst = ffmpeg_next::frame::Video::empty();
+    scaler.run(&frame, &mut dst)?;
+    dst.set_pts(frame.pts());
+    Ok(dst)
+}
+
+pub(crate) fn set_full_range_source(ctx: &mut Context) {
+    use ffmpeg_next::ffi;
+
+    unsafe {
+        let ptr = ctx.as_mut_ptr();
+        let mut inv_table = std::ptr::null_mut();
+        let mut table = std::ptr::null_mut();
+        let (mut src_range, mut dst_range) = (0, 0);
+        let (mut brightness, mut contrast, mut saturation) = (0, 0, 0);
+        if ffi::sws_getColorspaceDetails(
+            ptr,
+            &mut inv_table,
+            &mut src_range,
+            &mut table,
+            &mut dst_range,
+            &mut brightness,
+            &mut contrast,
+            &mut saturation,
+        ) >= 0
+        {
+            ffi::sws_setColorspaceDetails(
+                ptr, inv_table, 1, table, dst_range, brightness, contrast, saturation,
+            );
+        }
+    }
+}
+
+/// Packed RGB24 (`width * height * 3` bytes) of a decoded frame of any pixel
+/// format.
+pub fn video_to_rgb(frame: &ffmpeg_next::frame::Video) -> anyhow::Result<Vec<u8>> {
+    if frame.format() == Pixel::RGB24 {
+        return pack_planes(frame);
+    }
+    pack_planes(&convert_video(frame, Pixel::RGB24)?)
+}
+
 impl RawVideoFrame {
     pub fn width(&self) -> u32 {
         self.frame.width()
@@ -115,10 +320,34 @@ impl RawVideoFrame {
         Arc::make_mut(&mut self.frame)
     }
 
+    /// Plane 0 as stored, row padding included; see [`pack_planes`] for all
+    /// planes.
     pub fn data(&self) -> Bytes {
         Bytes::copy_from_slice(self.frame.data(0))
     }
 
+    /// This frame in `format`, see [`convert_video`].
+    pub fn convert(&self, format: Pixel) -> anyhow::Result<ffmpeg_next::frame::Video> {
+        convert_video(&self.frame, format)
+    }
+
+    /// This frame in `format` resized to `width`x`height`, see
+    /// [`scale_video`].
+    pub fn scale(
+        &self,
+        format: Pixel,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<ffmpeg_next::frame::Video> {
+        scale_video(&self.frame, format, width, height)
+    }
+
+    /// Packed RGB24 (`width * height * 3` bytes), whatever the frame's pixel
+    /// format.
+    pub fn to_rgb(&self) -> anyhow::Result<Vec<u8>> {
+        video_to_rgb(&self.frame)
+    }
+
     /// Borrow the inner decoded frame (all planes) — needed to feed a scaler.
     /// `data()` only exposes plane 0.
     pub fn as_video(&self) -> &ffmpeg_next::frame::Video {
@@ -139,7 +368,200 @@ impl RawVideoFrame {
     }
 }
 
-#[derive(Debug, Default)]
+/// A region of a video frame, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, w: u32, h: u32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    /// Validate against a `width`x`height` frame and snap to the chroma grid
+    /// of `format` (e.g. even x/y/w/h for yuv420p): offsets round down, sizes
+    /// round down, so the result never leaves the frame.
+    pub fn aligned(
+        self,
+        format: ffmpeg_next::format::Pixel,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Rect> {
+        if self.w == 0 || self.h == 0 {
+            anyhow::bail!("roi {:?}: empty", self);
+        }
+        if self.x.checked_add(self.w).is_none_or(|r| r > width)
+            || self.y.checked_add(self.h).is_none_or(|b| b > height)
+        {
+            anyhow::bail!("roi {:?}: outside the {}x{} frame", self, width, height);
+        }
+        let (mask_w, mask_h) = match format.descriptor() {
+            Some(desc) => (
+                (1u32 << desc.log2_chroma_w()) - 1,
+                (1u32 << desc.log2_chroma_h()) - 1,
+            ),
+            None => (0, 0),
+        };
+        let rect = Rect {
+            x: self.x & !mask_w,
+            y: self.y & !mask_h,
+            w: self.w & !mask_w,
+            h: self.h & !mask_h,
+        };
+        if rect.w == 0 || rect.h == 0 {
+            anyhow::bail!("roi {:?}: smaller than one chroma block", self);
+        }
+        Ok(rect)
+    }
+}
+
+/// Copy `rect` (snapped via [`Rect::aligned`]) out of a decoded software frame
+/// into a new frame of the ROI size, keeping format, pts and key flag.
+pub fn crop_video(
+    src: &ffmpeg_next::frame::Video,
+    rect: Rect,
+) -> anyhow::Result<ffmpeg_next::frame::Video> {
+    use ffmpeg_next::ffi;
+
+    let format = src.format();
+    let desc = format
+        .descriptor()
+        .ok_or_else(|| anyhow::anyhow!("crop: unknown pixel format {:?}", format))?;
+    let flags = unsafe { (*desc.as_ptr()).flags };
+    if flags & (ffi::AV_PIX_FMT_FLAG_HWACCEL as u64 | ffi::AV_PIX_FMT_FLAG_PAL as u64) != 0 {
+        anyhow::bail!("crop: unsupported pixel format {:?}", format);
+    }
+    let rect = rect.aligned(format, src.width(), src.height())?;
+    let (log2_w, log2_h) = (desc.log2_chroma_w(), desc.log2_chroma_h());
+
+    let mut max_step = [0i32; 4];
+    unsafe {
+        ffi::av_image_fill_max_pixsteps(max_step.as_mut_ptr(), std::ptr::null_mut(), desc.as_ptr());
+    }
+
+    let mut dst = ffmpeg_next::frame::Video::new(format, rect.w, rect.h);
+    for plane in 0..src.planes().min(4) {
+        // Planes 1 and 2 are the (possibly subsampled) chroma planes.
+        let (shift_w, shift_h) = if plane == 1 || plane == 2 {
+            (log2_w, log2_h)
+        } else {
+            (0, 0)
+        };
+        let bytewidth =
+            unsafe { ffi::av_image_get_linesize(format.into(), rect.w as i32, plane as i32) };
+        if bytewidth <= 0 {
+            anyhow::bail!("crop: no linesize for plane {} of {:?}", plane, format);
+        }
+        let bytewidth = bytewidth as usize;
+        let rows = (rect.h as usize).div_ceil(1 << shift_h);
+        let src_stride = src.stride(plane);
+        let offset = (rect.y as usize >> shift_h) * src_stride
+            + ((rect.x as usize * max_step[plane] as usize) >> shift_w);
+        let dst_stride = dst.stride(plane);
+        let src_data = src.data(plane);
+        let dst_data = dst.data_mut(plane);
+        for row in 0..rows {
+            let s = offset + row * src_stride;
+            let d = row * dst_stride;
+            dst_data[d..d + bytewidth].copy_from_slice(&src_data[s..s + bytewidth]);
+        }
+    }
+    dst.set_pts(src.pts());
+    dst.set_kind(src.kind());
+    unsafe {
+        (*dst.as_mut_ptr()).flags = (*src.as_ptr()).flags;
+    }
+    Ok(dst)
+}
+
+/// Turn a decoded software frame `degrees` clockwise (90, 180 or 270; 0
+/// copies it), keeping format, pts and key flag. Quarter turns swap width
+/// and height, so the chroma must be subsampled alike both ways (4:2:0 and
+/// 4:4:4, planar or semi-planar; not 4:2:2).
+pub fn rotate_video(
+    src: &ffmpeg_next::frame::Video,
+    degrees: u32,
+) -> anyhow::Result<ffmpeg_next::frame::Video> {
+    use ffmpeg_next::ffi;
+
+    let format = src.format();
+    let desc = format
+        .descriptor()
+        .ok_or_else(|| anyhow::anyhow!("rotate: unknown pixel format {:?}", format))?;
+    let flags = unsafe { (*desc.as_ptr()).flags };
+    if flags
+        & (ffi::AV_PIX_FMT_FLAG_HWACCEL as u64
+            | ffi::AV_PIX_FMT_FLAG_PAL as u64
+            | ffi::AV_PIX_FMT_FLAG_BITSTREAM as u64)
+        != 0
+    {
+        anyhow::bail!("rotate: unsupported pixel format {:?}", format);
+    }
+    let (log2_w, log2_h) = (desc.log2_chroma_w(), desc.log2_chroma_h());
+    let quarter = match degrees {
+        0 | 180 => false,
+        90 | 270 => true,
+        _ => anyhow::bail!("rotate: {} degrees is not a quarter turn", degrees),
+    };
+    if log2_w != log2_h {
+        anyhow::bail!("rotate: unsupported chroma subsampling of {:?}", format);
+    }
+
+    let mut max_step = [0i32; 4];
+    unsafe {
+        ffi::av_image_fill_max_pixsteps(max_step.as_mut_ptr(), std::ptr::null_mut(), desc.as_ptr());
+    }
+
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    let mut dst = if quarter {
+        ffmpeg_next::frame::Video::new(format, src.height(), src.width())
+    } else {
+        ffmpeg_next::frame::Video::new(format, src.width(), src.height())
+    };
+    for plane in 0..src.planes().min(4) {
+        let (shift_w, shift_h) = if plane == 1 || plane == 2 {
+            (log2_w, log2_h)
+        } else {
+            (0, 0)
+        };
+        // Size of the plane in pixels (chroma samples), and bytes per pixel.
+        let w = width.div_ceil(1 << shift_w);
+        let h = height.div_ceil(1 << shift_h);
+        let step = max_step[plane].max(1) as usize;
+        let (src_stride, dst_stride) = (src.stride(plane), dst.stride(plane));
+        let src_data = src.data(plane);
+        let dst_data = dst.data_mut(plane);
+        for y in 0..h {
+            for x in 0..w {
+                let (dx, dy) = match degrees {
+                    90 => (h - 1 - y, x),
+                    180 => (w - 1 - x, h - 1 - y),
+                    270 => (y, w - 1 - x),
+                    _ => (x, y),
+                };
+                let s = y * src_stride + x * step;
+                let d = dy * dst_stride + dx * step;
+                dst_data[d..d + step].copy_from_slice(&src_data[s..s + step]);
+            }
+        }
+    }
+    dst.set_pts(src.pts());
+    dst.set_kind(src.kind());
+    unsafe {
+        (*dst.as_mut_ptr()).flags = (*src.as_ptr()).flags;
+    }
+    Ok(dst)
+}
+
+/// A video frame detached from ffmpeg: either decoded (Raw outputs), with
+/// `data` holding every plane back to back without row padding (see
+/// [`pack_planes`]) and `format` its real `AVPixelFormat`, or encoded, with
+/// `format` `AV_PIX_FMT_NONE` (-1) and `codec_id` set.
+#[derive(Debug)]
 pub struct VideoFrame {
     pub data: Bytes,
     pub width: u32,
@@ -153,6 +575,21 @@ pub struct VideoFrame {
     pub codec_id: i32,
 }
 
+impl Default for VideoFrame {
+    fn default() -> Self {
+        Self {
+            data: Bytes::new(),
+            width: 0,
+            height: 0,
+            format: ffmpeg_next::ffi::AVPixelFormat::AV_PIX_FMT_NONE as i32,
+            pts: 0,
+            dts: 0,
+            is_key: false,
+            codec_id: 0,
+        }
+    }
+}
+
 impl VideoFrame {
     pub fn new(
         data: Vec<u8>,
@@ -186,6 +623,32 @@ impl VideoFrame {
         }
     }
 
+    pub fn pixel_format(&self) -> Pixel {
+        pixel_from_raw(self.format)
+    }
+
+    /// Whether this carries an encoded packet rather than a decoded picture.
+    pub fn is_encoded(&self) -> bool {
+        self.format == ffmpeg_next::ffi::AVPixelFormat::AV_PIX_FMT_NONE as i32
+    }
+
+    /// Rebuild the decoded frame; fails for encoded frames.
+    pub fn to_video(&self) -> anyhow::Result<ffmpeg_next::frame::Video> {
+        let format = self.pixel_format();
+        if format == Pixel::None {
+            anyhow::bail!("not a decoded frame (pixel format {})", self.format);
+        }
+        let mut frame = unpack_planes(&self.data, format, self.width, self.height)?;
+        frame.set_pts(Some(self.pts));
+        Ok(frame)
+    }
+
+    /// Packed RGB24 (`width * height * 3` bytes) of a decoded frame in any
+    /// pixel format.
+    pub fn to_rgb(&self) -> anyhow::Result<Vec<u8>> {
+        video_to_rgb(&self.to_video()?)
+    }
+
     pub fn pts_ms(&self, time_base: Rational) -> f64 {
         let pts_u = self.pts.max(0) as f64;
         let num = time_base.numerator() as f64;
@@ -237,11 +700,12 @@ impl TryFrom<RawFrame> for VideoFrame {
     type Error = anyhow::Error;
     fn try_from(value: RawFrame) -> Result<Self, Self::Error> {
         if let RawFrame::Video(frame) = value {
+            let video = to_software(frame.as_video())?;
             Ok(Self {
-                data: frame.data(),
-                width: frame.width(),
-                height: frame.height(),
-                format: frame.format() as i32,
+                data: Bytes::from(pack_planes(&video)?),
+                width: video.width(),
+                height: video.height(),
+                format: ffmpeg_next::ffi::AVPixelFormat::from(video.format()) as i32,
                 pts: frame.pts().unwrap_or(0),
                 dts: 0,
                 is_key: frame.is_key(),
@@ -253,13 +717,83 @@ impl TryFrom<RawFrame> for VideoFrame {
     }
 }
 
+/// A decoded audio frame, detached from ffmpeg. `data` holds the planes back to
+/// back (a single plane for packed formats), each trimmed to
+/// `samples * bytes_per_sample` (times `channels` when packed).
+#[derive(Debug, Default, Clone)]
+pub struct AudioFrame {
+    pub data: Bytes,
+    // AVSampleFormat
+    pub format: i32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Samples per channel.
+    pub samples: usize,
+    pub pts: i64,
+}
+
+impl AudioFrame {
+    pub fn pts_ms(&self, time_base: Rational) -> f64 {
+        let pts_u = self.pts.max(0) as f64;
+        let num = time_base.numerator() as f64;
+        let den = time_base.denominator() as f64;
+        pts_u * num * 1000.0 / den
+    }
+}
+
+impl Display for AudioFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "AudioFrame data_len: {}, format: {}, sample_rate: {}, channels: {}, samples: {}, pts: {}",
+            self.data.len(),
+            self.format,
+            self.sample_rate,
+            self.channels,
+            self.samples,
+            self.pts
+        )
+    }
+}
+
+impl TryFrom<RawFrame> for AudioFrame {
+    type Error = anyhow::Error;
+    fn try_from(value: RawFrame) -> Result<Self, Self::Error> {
+        let RawFrame::Audio(frame) = value else {
+            return Err(anyhow::anyhow!("not an audio frame"));
+        };
+        let audio = frame.as_audio();
+        let format = audio.format();
+        let channels = audio.channels();
+        let samples = audio.samples();
+        let plane_len = if format.is_planar() {
+            samples * format.bytes()
+        } else {
+            samples * format.bytes() * channels as usize
+        };
+        let mut data = Vec::with_capacity(plane_len * audio.planes());
+        for plane in 0..audio.planes() {
+            let bytes = audio.data(plane);
+            data.extend_from_slice(&bytes[..plane_len.min(bytes.len())]);
+        }
+        Ok(Self {
+            data: Bytes::from(data),
+            format: ffmpeg_next::ffi::AVSampleFormat::from(format) as i32,
+            sample_rate: audio.rate(),
+            channels,
+            samples,
+            pts: audio.pts().unwrap_or(0),
+        })
+    }
+}
+
 impl From<OutputMessage> for VideoFrame {
     fn from(value: OutputMessage) -> Self {
         Self {
             data: value.data,
             width: value.width,
             height: value.height,
-            format: 0,
+            format: ffmpeg_next::ffi::AVPixelFormat::AV_PIX_FMT_NONE as i32,
             pts: value.pts.unwrap_or(0),
             dts: value.dts.unwrap_or(0),
             is_key: value.is_key,
@@ -274,7 +808,7 @@ impl From<RawPacket> for VideoFrame {
             data: packet.data(),
             width: 0,
             height: 0,
-            format: 0,
+            format: ffmpeg_next::ffi::AVPixelFormat::AV_PIX_FMT_NONE as i32,
             pts: packet.pts().unwrap_or(0),
             dts: packet.dts().unwrap_or(0),
             is_key: packet.is_key(),
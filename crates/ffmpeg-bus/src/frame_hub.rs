@@ -0,0 +1,224 @@
+//! Fan-out of a decoder's frames to Raw outputs, each through a bounded
+//! queue of its own.
+//!
+//! The decoder's broadcast channel keeps one ring for every subscriber: a
+//! subscriber that falls a ring behind lags, and its raw stream ends. A
+//! [`FrameHub`] instead hands every [`FrameQueue`] its own copy of each frame
+//! (a reference to the same decoded frame, not its pixels), and a full queue
+//! is dealt with by the queue's [`DropPolicy`], so a stalled consumer only
+//! loses its own frames and holds at most `capacity` of them.
+//!
+//! Raw outputs get one with
+//! [`OutputConfig::with_frame_queue`](crate::bus::OutputConfig::with_frame_queue).
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::Stream;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::frame::RawFrameCmd;
+
+/// What a queue does with a frame coming while it is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the oldest queued frame to make room: the consumer always gets
+    /// the latest frames.
+    #[default]
+    DropOldest,
+    /// Drop the frame coming: the consumer gets what was queued first.
+    DropNewest,
+    /// Have the decoder wait for room. Every other consumer of the decoder,
+    /// encoders included, then waits on this one.
+    Block,
+}
+
+/// Size and [`DropPolicy`] of one [`FrameQueue`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameQueueConfig {
+    /// Frames the queue holds; at least 1.
+    pub capacity: usize,
+    pub policy: DropPolicy,
+}
+
+impl Default for FrameQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 8,
+            policy: DropPolicy::default(),
+        }
+    }
+}
+
+struct Queue {
+    config: FrameQueueConfig,
+    frames: Mutex<VecDeque<RawFrameCmd>>,
+    /// Woken when a frame is queued or the hub goes away.
+    ready: Notify,
+    dropped: AtomicU64,
+    /// The [`FrameQueue`] was dropped.
+    detached: AtomicBool,
+    /// Every [`FrameHub`] was dropped: nothing more comes.
+    closed: AtomicBool,
+}
+
+impl Queue {
+    /// Queue `cmd` as the policy says. An EOF is always queued, over the
+    /// capacity if need be, so the consumer sees the end.
+    fn push(&self, cmd: RawFrameCmd, cancel: &CancellationToken) {
+        let capacity = self.config.capacity.max(1);
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() >= capacity && !matches!(cmd, RawFrameCmd::EOF) {
+            match self.config.policy {
+                DropPolicy::DropOldest => {
+                    frames.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                DropPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                DropPolicy::Block => {
+                    // The decoder loop runs on a thread of its own; waiting
+                    // there is its backpressure, as in lossless mode.
+                    while frames.len() >= capacity {
+                        drop(frames);
+                        if cancel.is_cancelled() || self.detached.load(Ordering::Acquire) {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            return;
+                        }
+                        std::thread::sleep(Duration::from_millis(2));
+                        frames = self.frames.lock().unwrap();
+                    }
+                }
+            }
+        }
+        frames.push_back(cmd);
+        drop(frames);
+        self.ready.notify_one();
+    }
+}
+
+#[derive(Default)]
+struct HubInner {
+    queues: Mutex<Vec<Arc<Queue>>>,
+}
+
+impl Drop for HubInner {
+    fn drop(&mut self) {
+        for queue in self.queues.get_mut().unwrap().iter() {
+            queue.closed.store(true, Ordering::Release);
+            queue.ready.notify_one();
+        }
+    }
+}
+
+/// The sending side: every frame sent reaches every [`FrameQueue`]
+/// subscribed. Clones share the subscribers; the queues end once every
+/// clone is dropped.
+#[derive(Clone, Default)]
+pub struct FrameHub {
+    inner: Arc<HubInner>,
+}
+
+impl FrameHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A queue getting every frame sent from now on.
+    pub fn subscribe(&self, config: FrameQueueConfig) -> FrameQueue {
+        let queue = Arc::new(Queue {
+            config,
+            frames: Mutex::new(VecDeque::with_capacity(config.capacity.max(1))),
+            ready: Notify::new(),
+            dropped: AtomicU64::new(0),
+            detached: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        });
+        self.inner.queues.lock().unwrap().push(queue.clone());
+        FrameQueue { queue }
+    }
+
+    /// Queue `cmd` on every subscriber. A [`DropPolicy::Block`] queue that
+    /// is full makes this wait until it has room, its consumer goes away or
+    /// `cancel` is cancelled.
+    pub fn send(&self, cmd: RawFrameCmd, cancel: &CancellationToken) {
+        let queues = {
+            let mut queues = self.inner.queues.lock().unwrap();
+            queues.retain(|queue| !queue.detached.load(Ordering::Acquire));
+            queues.clone()
+        };
+        for queue in queues {
+            queue.push(cmd.clone(), cancel);
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        let queues = self.inner.queues.lock().unwrap();
+        queues
+            .iter()
+            .filter(|queue| !queue.detached.load(Ordering::Acquire))
+            .count()
+    }
+}
+
+/// One subscriber's queue of a [`FrameHub`].
+pub struct FrameQueue {
+    queue: Arc<Queue>,
+}
+
+impl FrameQueue {
+    /// The next frame or EOF; `None` once the hub is gone and the queue
+    /// empty.
+    pub async fn recv(&mut self) -> Option<RawFrameCmd> {
+        loop {
+            let ready = self.queue.ready.notified();
+            tokio::pin!(ready);
+            ready.as_mut().enable();
+            if let Some(cmd) = self.queue.frames.lock().unwrap().pop_front() {
+                return Some(cmd);
+            }
+            if self.queue.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            ready.await;
+        }
+    }
+
+    /// Frames queued and not yet received.
+    pub fn len(&self) -> usize {
+        self.queue.frames.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frames the policy dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The queue as a stream, ending when [`recv`](Self::recv) does.
+    pub fn into_stream(self) -> impl Stream<Item = RawFrameCmd> + Send + Sync + 'static {
+        futures::stream::unfold(self, |mut queue| async move {
+            let cmd = queue.recv().await?;
+            Some((cmd, queue))
+        })
+    }
+}
+
+impl Drop for FrameQueue {
+    fn drop(&mut self) {
+        self.queue.detached.store(true, Ordering::Release);
+        self.queue.frames.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+#[path = "frame_hub_test.rs"]
+mod frame_hub_test;
@@ -0,0 +1,105 @@
+use ffmpeg_next::format::Pixel;
+
+use super::*;
+use crate::frame::{RawFrame, RawVideoFrame};
+
+fn frame(pts: i64) -> RawFrameCmd {
+    let mut frame = ffmpeg_next::frame::Video::new(Pixel::GRAY8, 16, 16);
+    frame.set_pts(Some(pts));
+    RawFrameCmd::Data(RawFrame::Video(RawVideoFrame::from(frame)))
+}
+
+fn pts(cmd: &RawFrameCmd) -> Option<i64> {
+    match cmd {
+        RawFrameCmd::Data(RawFrame::Video(frame)) => frame.pts(),
+        _ => None,
+    }
+}
+
+fn queue(capacity: usize, policy: DropPolicy) -> FrameQueueConfig {
+    FrameQueueConfig { capacity, policy }
+}
+
+/// Every queued frame up to and including the EOF.
+async fn drain(queue: &mut FrameQueue) -> Vec<Option<i64>> {
+    let mut got = Vec::new();
+    while let Some(cmd) = queue.recv().await {
+        let eof = matches!(cmd, RawFrameCmd::EOF);
+        got.push(pts(&cmd));
+        if eof {
+            break;
+        }
+    }
+    got
+}
+
+#[tokio::test]
+async fn a_stalled_queue_stays_bounded_while_another_gets_every_frame() {
+    let hub = FrameHub::new();
+    let mut stalled = hub.subscribe(queue(4, DropPolicy::DropOldest));
+    let mut live = hub.subscribe(queue(2, DropPolicy::Block));
+
+    let sender = std::thread::spawn(move || {
+        let cancel = CancellationToken::new();
+        for i in 0..100 {
+            hub.send(frame(i), &cancel);
+        }
+        hub.send(RawFrameCmd::EOF, &cancel);
+    });
+    let got = drain(&mut live).await;
+    sender.join().unwrap();
+
+    assert_eq!(got.len(), 101);
+    assert!(got[..100].iter().copied().eq((0..100).map(Some)));
+    // The last 4 frames and the EOF; nothing more was ever held.
+    assert_eq!(stalled.len(), 5);
+    assert_eq!(stalled.dropped(), 96);
+    assert_eq!(
+        drain(&mut stalled).await,
+        vec![Some(96), Some(97), Some(98), Some(99), None]
+    );
+}
+
+#[tokio::test]
+async fn drop_newest_keeps_the_first_frames() {
+    let hub = FrameHub::new();
+    let mut queue = hub.subscribe(queue(3, DropPolicy::DropNewest));
+    let cancel = CancellationToken::new();
+    for i in 0..10 {
+        hub.send(frame(i), &cancel);
+    }
+    hub.send(RawFrameCmd::EOF, &cancel);
+    assert_eq!(queue.dropped(), 7);
+    assert_eq!(
+        drain(&mut queue).await,
+        vec![Some(0), Some(1), Some(2), None]
+    );
+}
+
+#[tokio::test]
+async fn a_blocked_sender_gives_up_when_its_consumer_goes() {
+    let hub = FrameHub::new();
+    let full = hub.subscribe(queue(1, DropPolicy::Block));
+    let cancel = CancellationToken::new();
+    hub.send(frame(0), &cancel);
+
+    let sender = {
+        let hub = hub.clone();
+        std::thread::spawn(move || hub.send(frame(1), &cancel))
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(!sender.is_finished());
+    drop(full);
+    sender.join().unwrap();
+    assert_eq!(hub.receiver_count(), 0);
+}
+
+#[tokio::test]
+async fn queues_end_when_the_hub_is_dropped() {
+    let hub = FrameHub::new();
+    let mut queue = hub.subscribe(FrameQueueConfig::default());
+    hub.send(frame(0), &CancellationToken::new());
+    drop(hub);
+    assert_eq!(queue.recv().await.as_ref().and_then(pts), Some(0));
+    assert!(queue.recv().await.is_none());
+}
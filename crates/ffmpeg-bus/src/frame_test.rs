@@ -58,3 +58,193 @@ fn raw_video_frame_exposes_inner_via_as_video() {
     assert_eq!(inner.height(), 2);
     assert_eq!(inner.format(), ffmpeg_next::format::Pixel::RGB24);
 }
+
+#[test]
+fn test_audio_frame_from_packed_raw_frame() {
+    use ffmpeg_next::{ChannelLayout, format::Sample, format::sample::Type};
+    let mut audio =
+        ffmpeg_next::frame::Audio::new(Sample::I16(Type::Packed), 1024, ChannelLayout::STEREO);
+    audio.set_rate(48_000);
+    audio.set_pts(Some(960));
+    let raw = RawFrame::Audio(RawAudioFrame::from(audio));
+
+    let frame = AudioFrame::try_from(raw).unwrap();
+    assert_eq!(frame.samples, 1024);
+    assert_eq!(frame.channels, 2);
+    assert_eq!(frame.sample_rate, 48_000);
+    assert_eq!(frame.pts, 960);
+    assert_eq!(frame.data.len(), 1024 * 2 * 2);
+}
+
+#[test]
+fn test_audio_frame_from_planar_raw_frame_concatenates_planes() {
+    use ffmpeg_next::{ChannelLayout, format::Sample, format::sample::Type};
+    let audio =
+        ffmpeg_next::frame::Audio::new(Sample::F32(Type::Planar), 1024, ChannelLayout::STEREO);
+    let frame = AudioFrame::try_from(RawFrame::Audio(RawAudioFrame::from(audio))).unwrap();
+    assert_eq!(frame.data.len(), 2 * 1024 * 4);
+}
+
+#[test]
+fn test_frame_kind_mismatch_is_an_error() {
+    use ffmpeg_next::{ChannelLayout, format::Sample, format::sample::Type};
+    let audio = ffmpeg_next::frame::Audio::new(Sample::I16(Type::Packed), 64, ChannelLayout::MONO);
+    assert!(VideoFrame::try_from(RawFrame::Audio(RawAudioFrame::from(audio))).is_err());
+
+    let video = ffmpeg_next::frame::Video::new(ffmpeg_next::format::Pixel::YUV420P, 16, 16);
+    assert!(AudioFrame::try_from(RawFrame::Video(RawVideoFrame::from(video))).is_err());
+}
+
+#[test]
+fn rect_aligned_snaps_to_chroma_grid() {
+    use ffmpeg_next::format::Pixel;
+    let rect = Rect::new(161, 101, 99, 101)
+        .aligned(Pixel::YUV420P, 320, 240)
+        .unwrap();
+    assert_eq!(rect, Rect::new(160, 100, 98, 100));
+    // No subsampling: kept as is.
+    let rect = Rect::new(161, 101, 99, 101)
+        .aligned(Pixel::RGB24, 320, 240)
+        .unwrap();
+    assert_eq!(rect, Rect::new(161, 101, 99, 101));
+}
+
+#[test]
+fn rect_aligned_rejects_out_of_frame_and_empty() {
+    use ffmpeg_next::format::Pixel;
+    assert!(
+        Rect::new(300, 0, 100, 100)
+            .aligned(Pixel::YUV420P, 320, 240)
+            .is_err()
+    );
+    assert!(
+        Rect::new(0, 0, 0, 10)
+            .aligned(Pixel::YUV420P, 320, 240)
+            .is_err()
+    );
+    assert!(
+        Rect::new(0, 0, 1, 1)
+            .aligned(Pixel::YUV420P, 320, 240)
+            .is_err()
+    );
+}
+
+/// 0x3366cc, the color of [`lavfi_frame`].
+const LAVFI_RGB: [f64; 3] = [51.0, 102.0, 204.0];
+
+/// First frame of a solid color lavfi source in `pix_fmt`, as decoded.
+fn lavfi_frame(pix_fmt: &str) -> RawVideoFrame {
+    crate::init().unwrap();
+    let graph = format!("color=c=0x3366cc:s=64x48:d=1,format={pix_fmt}");
+    let mut input = crate::input::AvInput::new(&graph, Some("lavfi"), None).unwrap();
+    let mut decoder = crate::decoder::Decoder::new(&input.streams()[&0], None).unwrap();
+    while let Some(packet) = input.read_packet() {
+        decoder.send_packet(packet).unwrap();
+        if let Some(RawFrame::Video(frame)) = decoder.receive_frame().unwrap() {
+            return frame;
+        }
+    }
+    panic!("no frame from {graph}");
+}
+
+fn average_rgb(rgb: &[u8]) -> [f64; 3] {
+    let pixels = (rgb.len() / 3) as f64;
+    let mut sum = [0.0; 3];
+    for px in rgb.chunks_exact(3) {
+        for (s, &v) in sum.iter_mut().zip(px) {
+            *s += v as f64;
+        }
+    }
+    sum.map(|s| s / pixels)
+}
+
+fn assert_lavfi_color(rgb: &[u8], pix_fmt: &str) {
+    assert_eq!(rgb.len(), 64 * 48 * 3, "{pix_fmt}");
+    let avg = average_rgb(rgb);
+    for (got, want) in avg.iter().zip(LAVFI_RGB) {
+        assert!(
+            (got - want).abs() < 6.0,
+            "{pix_fmt}: average {avg:?}, want {LAVFI_RGB:?}"
+        );
+    }
+}
+
+#[test]
+fn to_rgb_is_correct_for_common_pixel_formats() {
+    for (pix_fmt, pixel) in [
+        ("nv12", Pixel::NV12),
+        ("yuv420p", Pixel::YUV420P),
+        ("yuvj420p", Pixel::YUVJ420P),
+        ("rgb24", Pixel::RGB24),
+    ] {
+        let frame = lavfi_frame(pix_fmt);
+        assert_eq!(frame.format(), pixel);
+        assert_lavfi_color(&frame.to_rgb().unwrap(), pix_fmt);
+
+        // Detached: every plane survives, tagged with the real format.
+        let detached = VideoFrame::try_from(RawFrame::Video(frame)).unwrap();
+        assert_eq!(detached.pixel_format(), pixel, "{pix_fmt}");
+        assert_eq!((detached.width, detached.height), (64, 48));
+        assert_lavfi_color(&detached.to_rgb().unwrap(), pix_fmt);
+    }
+}
+
+#[test]
+fn detached_planes_are_tightly_packed() {
+    let frame = VideoFrame::try_from(RawFrame::Video(lavfi_frame("nv12"))).unwrap();
+    // Full size luma, then interleaved UV at half height.
+    assert_eq!(frame.data.len(), 64 * 48 + 64 * 24);
+    let frame = VideoFrame::try_from(RawFrame::Video(lavfi_frame("yuv420p"))).unwrap();
+    assert_eq!(frame.data.len(), 64 * 48 + 2 * 32 * 24);
+}
+
+#[test]
+fn encoded_frames_have_no_pixel_format() {
+    let frame = VideoFrame::new_encoded(vec![0, 0, 0, 1], 64, 48, 27);
+    assert_eq!(frame.pixel_format(), Pixel::None);
+    assert!(frame.is_encoded());
+    assert!(frame.to_rgb().is_err());
+    assert!(!VideoFrame::new(vec![], 64, 48, 0, 0, 0, true, 0).is_encoded());
+    assert_eq!(pixel_from_raw(i32::MAX), Pixel::None);
+    assert_eq!(
+        pixel_from_raw(ffmpeg_next::ffi::AVPixelFormat::AV_PIX_FMT_NV12 as i32),
+        Pixel::NV12
+    );
+}
+
+#[test]
+fn unpack_planes_rejects_short_data() {
+    assert!(unpack_planes(&[0; 16], Pixel::YUV420P, 64, 48).is_err());
+}
+
+#[test]
+fn rotate_video_turns_every_plane() {
+    // 4x2 4:2:0: luma 0..8 row by row, one row of two chroma samples each.
+    let planes = [0, 1, 2, 3, 4, 5, 6, 7, 10, 11, 20, 21];
+    let frame = unpack_planes(&planes, Pixel::YUV420P, 4, 2).unwrap();
+    let turned = |degrees| {
+        let rotated = rotate_video(&frame, degrees).unwrap();
+        (
+            rotated.width(),
+            rotated.height(),
+            pack_planes(&rotated).unwrap(),
+        )
+    };
+    assert_eq!(
+        turned(90),
+        (2, 4, vec![4, 0, 5, 1, 6, 2, 7, 3, 10, 11, 20, 21])
+    );
+    assert_eq!(
+        turned(180),
+        (4, 2, vec![7, 6, 5, 4, 3, 2, 1, 0, 11, 10, 21, 20])
+    );
+    assert_eq!(
+        turned(270),
+        (2, 4, vec![3, 7, 2, 6, 1, 5, 0, 4, 11, 10, 21, 20])
+    );
+    assert_eq!(turned(0), (4, 2, planes.to_vec()));
+
+    assert!(rotate_video(&frame, 45).is_err());
+    let yuv422 = ffmpeg_next::frame::Video::new(Pixel::YUV422P, 4, 2);
+    assert!(rotate_video(&yuv422, 90).is_err());
+}
@@ -0,0 +1,629 @@
+//! Per-GOP statistics of a video packet stream, without decoding, to tell
+//! when a camera's encoder degrades (bitrate starvation makes GOPs shrink
+//! and their quantizer climb while the frame rate holds).
+//!
+//! A [`GopStats`] is fed the packets of any path — an input subscription,
+//! or an output through [`GopStats::into_hook`] — and closes a
+//! [`GopSummary`] at each keyframe of its video stream: duration, bytes,
+//! frames, the keyframe's share of the bytes and, for H.264, the average
+//! slice QP where the slice headers parse cheaply (no weighted prediction,
+//! one slice group). Packets of other streams (interleaved audio) are
+//! skipped. As in [`GopBuffer`](crate::packet::GopBuffer), keyframes of
+//! H.264 and H.265 are told by their NAL unit types, the flag only counting
+//! for packets without NAL units to tell by.
+//!
+//! A [`GopWindow`] keeps the last GOPs and aggregates them; every input keeps
+//! one for its video stream, reported as
+//! [`InputStats::gop`](crate::input::InputStats::gop).
+
+use std::collections::{HashMap, VecDeque};
+
+use ffmpeg_next::codec::Id;
+
+use crate::hook::{HookAction, PacketHook, nal_units};
+use crate::packet::{RawPacket, nal_keyframe, seconds};
+use crate::stream::AvStream;
+
+/// GOPs an input's [`GopWindow`] aggregates.
+pub const GOP_WINDOW: usize = 30;
+
+/// H.264 NAL unit types.
+const NAL_SLICE: u8 = 1;
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+/// Bytes of a slice NAL unit read for its header: far more than a header
+/// without weight tables takes.
+const SLICE_HEADER_BYTES: usize = 64;
+
+/// One closed GOP, from a keyframe to the packet before the next one.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GopSummary {
+    /// Presentation time of its keyframe, in seconds.
+    pub start_secs: Option<f64>,
+    /// From its keyframe to the next one; the sum of its packets' durations
+    /// where the timestamps do not tell.
+    pub duration_secs: f64,
+    pub bytes: u64,
+    /// Video packets, about one per frame.
+    pub frames: u32,
+    pub keyframe_bytes: u64,
+    /// The keyframe's share of `bytes`.
+    pub keyframe_ratio: f64,
+    /// Mean slice QP of the frames whose header parsed; `None` for codecs
+    /// other than H.264, or when none did.
+    pub avg_qp: Option<f64>,
+}
+
+/// The GOP being received.
+#[derive(Default)]
+struct OpenGop {
+    start_secs: Option<f64>,
+    /// Sum of the packets' durations, in seconds.
+    durations: f64,
+    bytes: u64,
+    frames: u32,
+    keyframe_bytes: u64,
+    qp_sum: f64,
+    qp_frames: u32,
+}
+
+impl OpenGop {
+    fn close(self, next_start: Option<f64>) -> GopSummary {
+        let span = self
+            .start_secs
+            .zip(next_start)
+            .map(|(start, next)| next - start)
+            .filter(|span| *span > 0.0);
+        GopSummary {
+            start_secs: self.start_secs,
+            duration_secs: span.unwrap_or(self.durations),
+            bytes: self.bytes,
+            frames: self.frames,
+            keyframe_bytes: self.keyframe_bytes,
+            keyframe_ratio: if self.bytes > 0 {
+                self.keyframe_bytes as f64 / self.bytes as f64
+            } else {
+                0.0
+            },
+            avg_qp: (self.qp_frames > 0).then(|| self.qp_sum / self.qp_frames as f64),
+        }
+    }
+}
+
+/// Splits the packets of one video stream into GOPs.
+pub struct GopStats {
+    index: usize,
+    codec: Option<Id>,
+    /// Parameter sets of an H.264 stream, for the slice QPs.
+    h264: Option<H264Headers>,
+    open: Option<OpenGop>,
+}
+
+impl GopStats {
+    /// Statistics of the packets of `stream`, with the parameter sets of its
+    /// extradata.
+    pub fn new(stream: &AvStream) -> Self {
+        let parameters = stream.parameters();
+        Self::with_codec(
+            stream.index(),
+            Some(parameters.id()),
+            crate::bsf::get_extradata(parameters).unwrap_or_default(),
+        )
+    }
+
+    /// Statistics of the packets of stream `index`, which is of `codec`
+    /// (`None`: unknown, the keyframe flags decide). `extradata` (avcC or
+    /// Annex B) may hold the parameter sets, which can also come in band.
+    pub fn with_codec(index: usize, codec: Option<Id>, extradata: &[u8]) -> Self {
+        let h264 = (codec == Some(Id::H264)).then(|| {
+            let mut headers = H264Headers::default();
+            if let Ok(framing) = crate::remux::NalFraming::of(Id::H264, extradata) {
+                for unit in &framing.parameter_sets {
+                    headers.parameter_set(unit);
+                }
+            }
+            headers
+        });
+        Self {
+            index,
+            codec,
+            h264,
+            open: None,
+        }
+    }
+
+    /// Account for `packet`; returns the GOP its keyframe closes, if any.
+    /// Packets of other streams, and those before the first keyframe, are
+    /// ignored.
+    pub fn observe(&mut self, packet: &RawPacket) -> Option<GopSummary> {
+        if packet.index() != self.index {
+            return None;
+        }
+        let data = packet.packet().data().unwrap_or_default();
+        let keyframe = self
+            .codec
+            .and_then(|codec| nal_keyframe(data, codec))
+            .unwrap_or_else(|| packet.is_key());
+        let at = seconds(packet);
+        let closed = if keyframe {
+            self.open
+                .replace(OpenGop {
+                    start_secs: at,
+                    ..OpenGop::default()
+                })
+                .map(|gop| gop.close(at))
+        } else {
+            None
+        };
+        let qp = self.h264.as_mut().and_then(|h264| h264.frame_qp(data));
+        let gop = self.open.as_mut()?;
+        let size = packet.size() as u64;
+        gop.bytes += size;
+        gop.frames += 1;
+        if keyframe {
+            gop.keyframe_bytes = size;
+        }
+        let tb = packet.time_base();
+        if tb.denominator() != 0 {
+            gop.durations +=
+                packet.packet().duration() as f64 * tb.numerator() as f64 / tb.denominator() as f64;
+        }
+        if let Some(qp) = qp {
+            gop.qp_sum += qp as f64;
+            gop.qp_frames += 1;
+        }
+        closed
+    }
+
+    /// A packet hook (see [`crate::hook`]) handing `on_gop` each GOP of the
+    /// output's stream `index` this was made for; packets pass untouched.
+    pub fn into_hook(mut self, mut on_gop: impl FnMut(GopSummary) + Send + 'static) -> PacketHook {
+        Box::new(move |packet| {
+            if let Some(summary) = self.observe(packet) {
+                on_gop(summary);
+            }
+            HookAction::Keep
+        })
+    }
+}
+
+/// The last GOPs of a stream.
+pub struct GopWindow {
+    capacity: usize,
+    gops: VecDeque<GopSummary>,
+}
+
+/// Aggregates of a [`GopWindow`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GopAggregate {
+    /// GOPs aggregated.
+    pub gops: usize,
+    pub avg_bytes: f64,
+    pub avg_duration_secs: f64,
+    pub avg_frames: f64,
+    pub min_keyframe_bytes: u64,
+    pub max_keyframe_bytes: u64,
+    /// Mean of the GOPs' average QPs, over those that have one.
+    pub avg_qp: Option<f64>,
+    /// The newest GOP.
+    pub last: GopSummary,
+}
+
+impl Default for GopWindow {
+    fn default() -> Self {
+        Self::new(GOP_WINDOW)
+    }
+}
+
+impl GopWindow {
+    /// A window of the last `capacity` GOPs.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            gops: VecDeque::new(),
+        }
+    }
+
+    /// Add the newest GOP, dropping the oldest beyond the capacity.
+    pub fn push(&mut self, summary: GopSummary) {
+        if self.gops.len() == self.capacity {
+            self.gops.pop_front();
+        }
+        self.gops.push_back(summary);
+    }
+
+    /// `None` until a GOP has closed.
+    pub fn aggregate(&self) -> Option<GopAggregate> {
+        let last = *self.gops.back()?;
+        let n = self.gops.len() as f64;
+        let mean = |f: fn(&GopSummary) -> f64| self.gops.iter().map(f).sum::<f64>() / n;
+        let qps = self
+            .gops
+            .iter()
+            .filter_map(|g| g.avg_qp)
+            .collect::<Vec<_>>();
+        Some(GopAggregate {
+            gops: self.gops.len(),
+            avg_bytes: mean(|g| g.bytes as f64),
+            avg_duration_secs: mean(|g| g.duration_secs),
+            avg_frames: mean(|g| g.frames as f64),
+            min_keyframe_bytes: self.gops.iter().map(|g| g.keyframe_bytes).min()?,
+            max_keyframe_bytes: self.gops.iter().map(|g| g.keyframe_bytes).max()?,
+            avg_qp: (!qps.is_empty()).then(|| qps.iter().sum::<f64>() / qps.len() as f64),
+            last,
+        })
+    }
+}
+
+/// What the slice headers of an H.264 stream need from its SPS.
+#[derive(Debug, Clone, Copy)]
+struct Sps {
+    separate_colour_plane: bool,
+    log2_max_frame_num: u32,
+    poc_type: u32,
+    log2_max_poc_lsb: u32,
+    delta_pic_order_always_zero: bool,
+    frame_mbs_only: bool,
+}
+
+/// What the slice headers of an H.264 stream need from a PPS.
+#[derive(Debug, Clone, Copy)]
+struct Pps {
+    sps_id: u32,
+    cabac: bool,
+    bottom_field_pic_order: bool,
+    /// Slice groups, whose maps this does not parse.
+    slice_groups: bool,
+    weighted_pred: bool,
+    weighted_bipred_idc: u32,
+    pic_init_qp: i32,
+    redundant_pic_cnt: bool,
+}
+
+/// The parameter sets of an H.264 stream seen so far.
+#[derive(Default)]
+struct H264Headers {
+    sps: HashMap<u32, Sps>,
+    pps: HashMap<u32, Pps>,
+}
+
+impl H264Headers {
+    /// Keep an SPS or PPS NAL unit (header byte first); others are ignored.
+    fn parameter_set(&mut self, nal: &[u8]) {
+        let Some(header) = nal.first() else {
+            return;
+        };
+        let rbsp = unescape(&nal[1..], usize::MAX);
+        let mut bits = Bits::new(&rbsp);
+        match header & 0x1f {
+            NAL_SPS => {
+                if let Some((id, sps)) = parse_sps(&mut bits) {
+                    self.sps.insert(id, sps);
+                }
+            }
+            NAL_PPS => {
+                if let Some((id, pps)) = parse_pps(&mut bits) {
+                    self.pps.insert(id, pps);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The QP of the first slice of a packet, keeping the parameter sets it
+    /// carries.
+    fn frame_qp(&mut self, data: &[u8]) -> Option<i32> {
+        let mut qp = None;
+        for (_, nal) in nal_units(data) {
+            let Some(header) = nal.first().copied() else {
+                continue;
+            };
+            match header & 0x1f {
+                NAL_SPS | NAL_PPS => self.parameter_set(nal),
+                NAL_SLICE | NAL_IDR if qp.is_none() => qp = self.slice_qp(nal),
+                _ => {}
+            }
+        }
+        qp
+    }
+
+    /// SliceQP of a slice NAL unit: the PPS's initial QP plus the header's
+    /// delta. `None` when the header cannot be read up to the delta.
+    fn slice_qp(&self, nal: &[u8]) -> Option<i32> {
+        let header = nal[0];
+        let nal_type = header & 0x1f;
+        let nal_ref_idc = (header >> 5) & 3;
+        let rbsp = unescape(&nal[1..], SLICE_HEADER_BYTES);
+        let mut b = Bits::new(&rbsp);
+
+        b.ue()?; // first_mb_in_slice
+        let slice_type = b.ue()? % 5;
+        let (p, bi, i) = (
+            slice_type == 0 || slice_type == 3,
+            slice_type == 1,
+            slice_type == 2,
+        );
+        let intra = i || slice_type == 4;
+        let pps = self.pps.get(&b.ue()?)?;
+        let sps = self.sps.get(&pps.sps_id)?;
+        if pps.slice_groups {
+            return None;
+        }
+        if sps.separate_colour_plane {
+            b.bits(2)?; // colour_plane_id
+        }
+        b.bits(sps.log2_max_frame_num)?; // frame_num
+        let mut field = false;
+        if !sps.frame_mbs_only {
+            field = b.flag()?;
+            if field {
+                b.flag()?; // bottom_field_flag
+            }
+        }
+        if nal_type == NAL_IDR {
+            b.ue()?; // idr_pic_id
+        }
+        if sps.poc_type == 0 {
+            b.bits(sps.log2_max_poc_lsb)?;
+            if pps.bottom_field_pic_order && !field {
+                b.se()?;
+            }
+        }
+        if sps.poc_type == 1 && !sps.delta_pic_order_always_zero {
+            b.se()?;
+            if pps.bottom_field_pic_order && !field {
+                b.se()?;
+            }
+        }
+        if pps.redundant_pic_cnt {
+            b.ue()?;
+        }
+        if bi {
+            b.flag()?; // direct_spatial_mv_pred_flag
+        }
+        if (p || bi) && b.flag()? {
+            // num_ref_idx_active_override_flag
+            b.ue()?;
+            if bi {
+                b.ue()?;
+            }
+        }
+        // ref_pic_list_modification
+        let lists = if bi {
+            2
+        } else if intra {
+            0
+        } else {
+            1
+        };
+        for _ in 0..lists {
+            if b.flag()? {
+                loop {
+                    match b.ue()? {
+                        0..=2 => {
+                            b.ue()?;
+                        }
+                        3 => break,
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        if (pps.weighted_pred && p) || (pps.weighted_bipred_idc == 1 && bi) {
+            // pred_weight_table: not worth parsing for a statistic.
+            return None;
+        }
+        if nal_ref_idc != 0 {
+            // dec_ref_pic_marking
+            if nal_type == NAL_IDR {
+                b.flag()?;
+                b.flag()?;
+            } else if b.flag()? {
+                loop {
+                    match b.ue()? {
+                        0 => break,
+                        1 | 2 | 4 | 6 => {
+                            b.ue()?;
+                        }
+                        3 => {
+                            b.ue()?;
+                            b.ue()?;
+                        }
+                        5 => {}
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        if pps.cabac && !intra {
+            b.ue()?; // cabac_init_idc
+        }
+        let qp = pps.pic_init_qp + b.se()?;
+        (0..=51).contains(&qp).then_some(qp)
+    }
+}
+
+fn parse_sps(b: &mut Bits) -> Option<(u32, Sps)> {
+    let profile = b.bits(8)?;
+    b.bits(16)?; // constraint flags, level
+    let id = b.ue()?;
+    let mut separate_colour_plane = false;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        let chroma_format = b.ue()?;
+        if chroma_format == 3 {
+            separate_colour_plane = b.flag()?;
+        }
+        b.ue()?; // bit_depth_luma_minus8
+        b.ue()?; // bit_depth_chroma_minus8
+        b.flag()?; // qpprime_y_zero_transform_bypass_flag
+        if b.flag()? {
+            let lists = if chroma_format == 3 { 12 } else { 8 };
+            for n in 0..lists {
+                if b.flag()? {
+                    skip_scaling_list(b, if n < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    let log2_max_frame_num = b.ue()? + 4;
+    let poc_type = b.ue()?;
+    let mut log2_max_poc_lsb = 0;
+    let mut delta_pic_order_always_zero = false;
+    match poc_type {
+        0 => log2_max_poc_lsb = b.ue()? + 4,
+        1 => {
+            delta_pic_order_always_zero = b.flag()?;
+            b.se()?;
+            b.se()?;
+            for _ in 0..b.ue()? {
+                b.se()?;
+            }
+        }
+        _ => {}
+    }
+    b.ue()?; // max_num_ref_frames
+    b.flag()?; // gaps_in_frame_num_value_allowed_flag
+    b.ue()?; // pic_width_in_mbs_minus1
+    b.ue()?; // pic_height_in_map_units_minus1
+    let frame_mbs_only = b.flag()?;
+    (log2_max_frame_num <= 16 && log2_max_poc_lsb <= 16).then_some((
+        id,
+        Sps {
+            separate_colour_plane,
+            log2_max_frame_num,
+            poc_type,
+            log2_max_poc_lsb,
+            delta_pic_order_always_zero,
+            frame_mbs_only,
+        },
+    ))
+}
+
+fn parse_pps(b: &mut Bits) -> Option<(u32, Pps)> {
+    let id = b.ue()?;
+    let sps_id = b.ue()?;
+    let cabac = b.flag()?;
+    let bottom_field_pic_order = b.flag()?;
+    let slice_groups = b.ue()? > 0;
+    if slice_groups {
+        // The slice group map that follows is not parsed.
+        return Some((
+            id,
+            Pps {
+                sps_id,
+                cabac,
+                bottom_field_pic_order,
+                slice_groups,
+                weighted_pred: false,
+                weighted_bipred_idc: 0,
+                pic_init_qp: 26,
+                redundant_pic_cnt: false,
+            },
+        ));
+    }
+    b.ue()?; // num_ref_idx_l0_default_active_minus1
+    b.ue()?; // num_ref_idx_l1_default_active_minus1
+    let weighted_pred = b.flag()?;
+    let weighted_bipred_idc = b.bits(2)?;
+    let pic_init_qp = 26 + b.se()?;
+    b.se()?; // pic_init_qs_minus26
+    b.se()?; // chroma_qp_index_offset
+    b.flag()?; // deblocking_filter_control_present_flag
+    b.flag()?; // constrained_intra_pred_flag
+    let redundant_pic_cnt = b.flag()?;
+    Some((
+        id,
+        Pps {
+            sps_id,
+            cabac,
+            bottom_field_pic_order,
+            slice_groups,
+            weighted_pred,
+            weighted_bipred_idc,
+            pic_init_qp,
+            redundant_pic_cnt,
+        },
+    ))
+}
+
+fn skip_scaling_list(b: &mut Bits, size: usize) -> Option<()> {
+    let (mut last, mut next) = (8i32, 8i32);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + b.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/// The first `max` bytes of a NAL unit's payload with its emulation
+/// prevention bytes (the 3 of `00 00 03`) removed.
+fn unescape(data: &[u8], max: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().min(max));
+    let mut zeros = 0;
+    for &byte in data.iter().take(max) {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// Reads an RBSP bit by bit, MSB first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        Some(self.bit()? == 1)
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        (0..n).try_fold(0u32, |value, _| Some(value << 1 | self.bit()?))
+    }
+
+    /// Unsigned Exp-Golomb.
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    /// Signed Exp-Golomb.
+    fn se(&mut self) -> Option<i32> {
+        let k = self.ue()? as i64;
+        Some(if k % 2 == 1 { (k + 1) / 2 } else { -(k / 2) } as i32)
+    }
+}
+
+#[cfg(test)]
+#[path = "gop_test.rs"]
+mod gop_test;
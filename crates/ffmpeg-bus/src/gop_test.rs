@@ -0,0 +1,312 @@
+use std::sync::{Arc, Mutex};
+
+use ffmpeg_next::Rational;
+use ffmpeg_next::codec::packet::{Flags, Packet};
+
+use super::*;
+
+const VIDEO: usize = 0;
+const AUDIO: usize = 1;
+
+/// Writes an RBSP MSB first, with Exp-Golomb codes.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn bit(&mut self, set: bool) -> &mut Self {
+        if self.len % 8 == 0 {
+            self.bytes.push(0);
+        }
+        if set {
+            *self.bytes.last_mut().unwrap() |= 1 << (7 - self.len % 8);
+        }
+        self.len += 1;
+        self
+    }
+
+    fn bits(&mut self, n: u32, value: u32) -> &mut Self {
+        for i in (0..n).rev() {
+            self.bit(value >> i & 1 == 1);
+        }
+        self
+    }
+
+    fn ue(&mut self, value: u32) -> &mut Self {
+        let code = value + 1;
+        let width = 32 - code.leading_zeros();
+        self.bits(width - 1, 0).bits(width, code)
+    }
+
+    fn se(&mut self, value: i32) -> &mut Self {
+        self.ue(if value > 0 {
+            2 * value as u32 - 1
+        } else {
+            2 * value.unsigned_abs()
+        })
+    }
+
+    /// The NAL unit of `header` with this payload, the stop bit, `padding`
+    /// bytes of slice data and emulation prevention.
+    fn nal(&mut self, header: u8, padding: usize) -> Vec<u8> {
+        self.bit(true);
+        let mut rbsp = std::mem::take(&mut self.bytes);
+        rbsp.extend(std::iter::repeat_n(0x5a, padding));
+        let mut nal = vec![header];
+        let mut zeros = 0;
+        for byte in rbsp {
+            if zeros >= 2 && byte <= 3 {
+                nal.push(3);
+                zeros = 0;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            nal.push(byte);
+        }
+        nal
+    }
+}
+
+/// A baseline SPS of a 320x240 picture, 4-bit frame numbers, POC type 2.
+fn sps() -> Vec<u8> {
+    BitWriter::default()
+        .bits(8, 66)
+        .bits(16, 30)
+        .ue(0)
+        .ue(0)
+        .ue(2)
+        .ue(1)
+        .bit(false)
+        .ue(19)
+        .ue(14)
+        .bit(true)
+        .bit(false)
+        .bit(false)
+        .bit(false)
+        .nal(0x67, 0)
+}
+
+/// A CAVLC PPS with a QP of 30.
+fn pps() -> Vec<u8> {
+    BitWriter::default()
+        .ue(0)
+        .ue(0)
+        .bit(false)
+        .bit(false)
+        .ue(0)
+        .ue(0)
+        .ue(0)
+        .bit(false)
+        .bits(2, 0)
+        .se(4)
+        .se(0)
+        .se(0)
+        .bit(true)
+        .bit(false)
+        .bit(false)
+        .nal(0x68, 0)
+}
+
+/// An IDR I slice whose QP is 30 + `qp_delta`.
+fn idr(qp_delta: i32, padding: usize) -> Vec<u8> {
+    BitWriter::default()
+        .ue(0)
+        .ue(7)
+        .ue(0)
+        .bits(4, 0)
+        .ue(0)
+        .bit(false)
+        .bit(false)
+        .se(qp_delta)
+        .nal(0x65, padding)
+}
+
+/// A reference P slice (frame `frame_num`) whose QP is 30 + `qp_delta`.
+fn p_slice(frame_num: u32, qp_delta: i32, padding: usize) -> Vec<u8> {
+    BitWriter::default()
+        .ue(0)
+        .ue(5)
+        .ue(0)
+        .bits(4, frame_num)
+        .bit(false)
+        .bit(false)
+        .bit(false)
+        .se(qp_delta)
+        .nal(0x41, padding)
+}
+
+fn annexb(nals: &[Vec<u8>]) -> Vec<u8> {
+    nals.iter()
+        .flat_map(|nal| [0, 0, 0, 1].into_iter().chain(nal.iter().copied()))
+        .collect()
+}
+
+fn avcc(nals: &[Vec<u8>]) -> Vec<u8> {
+    nals.iter()
+        .flat_map(|nal| {
+            (nal.len() as u32)
+                .to_be_bytes()
+                .into_iter()
+                .chain(nal.clone())
+        })
+        .collect()
+}
+
+/// A packet of stream `index` at frame `n` of a 25 fps stream.
+fn packet(index: usize, n: i64, data: &[u8], key: bool) -> RawPacket {
+    let mut p = Packet::copy(data);
+    p.set_stream(index);
+    p.set_pts(Some(n));
+    p.set_dts(Some(n));
+    p.set_duration(1);
+    if key {
+        p.set_flags(Flags::KEY);
+    }
+    RawPacket::from((p, Rational(1, 25)))
+}
+
+fn feed(stats: &mut GopStats, packets: &[RawPacket]) -> Vec<GopSummary> {
+    packets.iter().filter_map(|p| stats.observe(p)).collect()
+}
+
+#[test]
+fn gops_close_at_keyframes_skipping_the_audio() {
+    let mut stats = GopStats::with_codec(VIDEO, None, &[]);
+    let mut packets = Vec::new();
+    // Before the first keyframe: not a whole GOP.
+    for n in -3..0 {
+        packets.push(packet(VIDEO, n, &[0xaa; 700], false));
+    }
+    for n in 0..=50 {
+        let key = n % 25 == 0;
+        let size = if key { 5000 } else { 1000 };
+        packets.push(packet(VIDEO, n, &vec![0xaa; size], key));
+        // Audio packets are all flagged key, and heavy.
+        packets.push(packet(AUDIO, n, &[0xbb; 3000], true));
+    }
+    let gops = feed(&mut stats, &packets);
+    let gop = |start| GopSummary {
+        start_secs: Some(start),
+        duration_secs: 1.0,
+        bytes: 29_000,
+        frames: 25,
+        keyframe_bytes: 5000,
+        keyframe_ratio: 5000.0 / 29_000.0,
+        avg_qp: None,
+    };
+    assert_eq!(gops, [gop(0.0), gop(1.0)]);
+}
+
+#[test]
+fn nal_types_decide_over_the_flags() {
+    let mut stats = GopStats::with_codec(VIDEO, Some(Id::H264), &[]);
+    let mut packets = Vec::new();
+    for gop in 0..3 {
+        let start = gop * 4;
+        // The IDR with its parameter sets, without a key flag.
+        packets.push(packet(
+            VIDEO,
+            start,
+            &annexb(&[sps(), pps(), idr(-2, 2000)]),
+            false,
+        ));
+        for n in 1..4 {
+            // A camera flagging every packet key.
+            packets.push(packet(
+                VIDEO,
+                start + n,
+                &annexb(&[p_slice(n as u32, 3, 300)]),
+                true,
+            ));
+        }
+    }
+    let gops = feed(&mut stats, &packets);
+    assert_eq!(gops.len(), 2);
+    for (i, gop) in gops.iter().enumerate() {
+        assert_eq!(gop.frames, 4);
+        assert_eq!(gop.start_secs, Some(i as f64 * 4.0 / 25.0));
+        assert!((gop.duration_secs - 0.16).abs() < 1e-9);
+        let bytes = packets[i * 4..i * 4 + 4]
+            .iter()
+            .map(|p| p.size() as u64)
+            .sum::<u64>();
+        assert_eq!(gop.bytes, bytes);
+        assert_eq!(gop.keyframe_bytes, packets[i * 4].size() as u64);
+        // 28 on the IDR, 33 on each P frame.
+        assert_eq!(gop.avg_qp, Some(31.75));
+    }
+}
+
+#[test]
+fn qp_uses_the_parameter_sets_of_the_extradata() {
+    let sps = sps();
+    let pps = pps();
+    let mut extradata = vec![1, 66, 0, 30, 0xff, 0xe1];
+    extradata.extend((sps.len() as u16).to_be_bytes());
+    extradata.extend(&sps);
+    extradata.push(1);
+    extradata.extend((pps.len() as u16).to_be_bytes());
+    extradata.extend(&pps);
+
+    let mut stats = GopStats::with_codec(VIDEO, Some(Id::H264), &extradata);
+    let packets = [
+        packet(VIDEO, 0, &avcc(&[idr(0, 1000)]), true),
+        packet(VIDEO, 1, &avcc(&[p_slice(1, 10, 50)]), false),
+        packet(VIDEO, 2, &avcc(&[idr(0, 1000)]), true),
+    ];
+    let gops = feed(&mut stats, &packets);
+    assert_eq!(gops.len(), 1);
+    assert_eq!(gops[0].avg_qp, Some(35.0));
+
+    // Without them (nor in band) the QP is unknown; the GOPs still close.
+    let mut stats = GopStats::with_codec(VIDEO, Some(Id::H264), &[]);
+    let gops = feed(&mut stats, &packets);
+    assert_eq!((gops.len(), gops[0].avg_qp), (1, None));
+}
+
+#[test]
+fn a_hook_reports_gops_and_keeps_the_packets() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = seen.clone();
+    let mut hook = GopStats::with_codec(VIDEO, None, &[])
+        .into_hook(move |gop| sink.lock().unwrap().push(gop.frames));
+    for n in 0..7 {
+        let mut p = packet(VIDEO, n, &[0xaa; 100], n % 3 == 0);
+        assert!(matches!(hook(&mut p), HookAction::Keep));
+    }
+    assert_eq!(*seen.lock().unwrap(), [3, 3]);
+}
+
+#[test]
+fn the_window_aggregates_the_last_gops() {
+    let mut window = GopWindow::new(3);
+    assert_eq!(window.aggregate(), None);
+    let gop = |bytes: u64, keyframe_bytes, avg_qp| GopSummary {
+        duration_secs: bytes as f64 / 100.0,
+        bytes,
+        frames: 50,
+        keyframe_bytes,
+        avg_qp,
+        ..GopSummary::default()
+    };
+    window.push(gop(100, 5, Some(20.0)));
+    window.push(gop(200, 50, Some(30.0)));
+    window.push(gop(300, 10, None));
+    window.push(gop(400, 80, Some(38.0)));
+    let aggregate = window.aggregate().unwrap();
+    // The first one is out of the window.
+    assert_eq!(
+        aggregate,
+        GopAggregate {
+            gops: 3,
+            avg_bytes: 300.0,
+            avg_duration_secs: 3.0,
+            avg_frames: 50.0,
+            min_keyframe_bytes: 10,
+            max_keyframe_bytes: 80,
+            avg_qp: Some(34.0),
+            last: gop(400, 80, Some(38.0)),
+        }
+    );
+}
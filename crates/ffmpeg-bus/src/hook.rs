@@ -0,0 +1,140 @@
+//! Per-output packet hooks: a closure that sees every packet an output is
+//! about to write, with its final timestamps (after rescaling to the output's
+//! time base), and keeps, drops or replaces it. For light packet-level
+//! transformations that do not belong in the bus itself: dropping SEI that
+//! confuses a downstream decoder, rewriting AUDs, per-GOP statistics.
+//!
+//! Set with [`OutputConfig::with_packet_hook`](crate::bus::OutputConfig::with_packet_hook)
+//! on muxing (File/Net/Hls/Mux) and Demuxed outputs. The hook lives in the
+//! output's task; one that panics fails the output with
+//! [`WriteErrorKind::Hook`](crate::write_error::WriteErrorKind::Hook) instead
+//! of taking the task down. [`strip_sei`] is a ready-made one.
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use bytes::BytesMut;
+
+use crate::bsf::is_annexb_packet;
+use crate::packet::RawPacket;
+
+/// What to do with a packet, as decided by a [`PacketHook`].
+pub enum HookAction {
+    /// Write the packet, including any changes the hook made in place.
+    Keep,
+    /// Do not write it.
+    Drop,
+    /// Write this packet instead (e.g. from [`RawPacket::with_data`]).
+    Replace(RawPacket),
+}
+
+pub type PacketHook = Box<dyn FnMut(&mut RawPacket) -> HookAction + Send>;
+
+/// Run `hook` on `packet`: the packet to write, `None` to drop it, or the
+/// panic message when the hook panicked.
+pub(crate) fn apply(
+    hook: &mut PacketHook,
+    mut packet: RawPacket,
+) -> Result<Option<RawPacket>, String> {
+    match catch_unwind(AssertUnwindSafe(|| hook(&mut packet))) {
+        Ok(HookAction::Keep) => Ok(Some(packet)),
+        Ok(HookAction::Drop) => Ok(None),
+        Ok(HookAction::Replace(replacement)) => Ok(Some(replacement)),
+        Err(panic) => Err(panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "packet hook panicked".to_string())),
+    }
+}
+
+/// H.264 NAL unit type of SEI.
+const NAL_SEI: u8 = 6;
+
+/// A hook removing the SEI NAL units from H.264 packets, in Annex B (start
+/// codes) or AVCC (4-byte lengths) form. A packet of nothing but SEI is
+/// dropped; packets without SEI pass untouched.
+pub fn strip_sei() -> PacketHook {
+    Box::new(|packet| {
+        let data = packet.data();
+        match without_sei(&data) {
+            None => HookAction::Keep,
+            Some(kept) if kept.is_empty() => HookAction::Drop,
+            Some(kept) => HookAction::Replace(packet.with_data(&kept)),
+        }
+    })
+}
+
+/// `data` with its SEI NAL units removed, or `None` if it has none.
+pub(crate) fn without_sei(data: &[u8]) -> Option<Vec<u8>> {
+    let nals = nal_units(data);
+    if !nals.iter().any(|(_, nal)| is_sei(nal)) {
+        return None;
+    }
+    let mut out = BytesMut::with_capacity(data.len());
+    for (prefix, nal) in nals.iter().filter(|(_, nal)| !is_sei(nal)) {
+        out.extend_from_slice(prefix);
+        out.extend_from_slice(nal);
+    }
+    Some(out.to_vec())
+}
+
+/// The NAL units of an H.264/H.265 packet in Annex B or AVCC form, each
+/// with its start code or length before it.
+pub(crate) fn nal_units(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    if is_annexb_packet(data) {
+        annexb_nals(data)
+    } else {
+        avcc_nals(data)
+    }
+}
+
+fn is_sei(nal: &[u8]) -> bool {
+    nal.first().is_some_and(|header| header & 0x1f == NAL_SEI)
+}
+
+/// The NAL units of an Annex B packet, each with the start code before it.
+fn annexb_nals(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    // Start codes: (offset, length), 3 or 4 bytes.
+    let mut codes = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            let four = i > 0 && data[i - 1] == 0;
+            codes.push(if four { (i - 1, 4) } else { (i, 3) });
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    codes
+        .iter()
+        .enumerate()
+        .map(|(n, &(at, len))| {
+            let end = codes.get(n + 1).map_or(data.len(), |&(next, _)| next);
+            (&data[at..at + len], &data[at + len..end])
+        })
+        .collect()
+}
+
+/// The NAL units of an AVCC packet, each with its length before it. A
+/// truncated tail is kept as it is.
+fn avcc_nals(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut nals = Vec::new();
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        let len = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        if i + 4 + len > data.len() {
+            break;
+        }
+        nals.push((&data[i..i + 4], &data[i + 4..i + 4 + len]));
+        i += 4 + len;
+    }
+    if i < data.len() {
+        nals.push((&data[i..], &data[data.len()..]));
+    }
+    nals
+}
+
+#[cfg(test)]
+#[path = "hook_test.rs"]
+mod hook_test;
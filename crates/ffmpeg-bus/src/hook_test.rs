@@ -0,0 +1,80 @@
+use ffmpeg_next::{Rational, codec::packet::Packet};
+
+use super::*;
+
+// An SPS, an SEI and an IDR slice, with 4- and 3-byte start codes.
+const SPS: [u8; 3] = [0x67, 0x64, 0x1f];
+const SEI: [u8; 3] = [0x06, 0x05, 0x01];
+const IDR: [u8; 3] = [0x65, 0x88, 0x81];
+
+fn packet(data: &[u8]) -> RawPacket {
+    let mut packet = Packet::copy(data);
+    packet.set_pts(Some(3000));
+    packet.set_dts(Some(3000));
+    packet.set_flags(ffmpeg_next::codec::packet::Flags::KEY);
+    RawPacket::from((packet, Rational::new(1, 90000)))
+}
+
+fn avcc(nals: &[&[u8]]) -> Vec<u8> {
+    nals.iter()
+        .flat_map(|nal| {
+            (nal.len() as u32)
+                .to_be_bytes()
+                .into_iter()
+                .chain(nal.iter().copied())
+        })
+        .collect()
+}
+
+#[test]
+fn without_sei_strips_annexb() {
+    let data = [
+        &[0, 0, 0, 1][..],
+        &SPS,
+        &[0, 0, 1],
+        &SEI,
+        &[0, 0, 0, 1],
+        &IDR,
+    ]
+    .concat();
+    let kept = without_sei(&data).unwrap();
+    assert_eq!(
+        kept,
+        [&[0, 0, 0, 1][..], &SPS, &[0, 0, 0, 1], &IDR].concat()
+    );
+}
+
+#[test]
+fn without_sei_strips_avcc() {
+    let data = avcc(&[&SEI, &IDR]);
+    assert_eq!(without_sei(&data).unwrap(), avcc(&[&IDR]));
+    // Nothing to strip.
+    assert_eq!(without_sei(&avcc(&[&SPS, &IDR])), None);
+    // A truncated tail is not mistaken for a NAL.
+    let mut truncated = avcc(&[&IDR]);
+    truncated.extend_from_slice(&[0, 0, 0, 9, 0x06]);
+    assert_eq!(without_sei(&truncated), None);
+}
+
+#[test]
+fn strip_sei_keeps_timestamps_and_drops_sei_only_packets() {
+    let mut hook = strip_sei();
+    let kept = apply(&mut hook, packet(&avcc(&[&SEI, &IDR])))
+        .unwrap()
+        .unwrap();
+    assert_eq!(&kept.data()[..], &avcc(&[&IDR])[..]);
+    assert_eq!(kept.pts(), Some(3000));
+    assert!(kept.is_key());
+
+    let untouched = apply(&mut hook, packet(&avcc(&[&IDR]))).unwrap().unwrap();
+    assert_eq!(&untouched.data()[..], &avcc(&[&IDR])[..]);
+
+    assert!(apply(&mut hook, packet(&avcc(&[&SEI]))).unwrap().is_none());
+}
+
+#[test]
+fn apply_catches_a_panicking_hook() {
+    let mut hook: PacketHook = Box::new(|_: &mut RawPacket| -> HookAction { panic!("bad packet") });
+    let error = apply(&mut hook, packet(&avcc(&[&IDR]))).err().unwrap();
+    assert_eq!(error, "bad packet");
+}
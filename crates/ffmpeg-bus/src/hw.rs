@@ -1,4 +1,7 @@
+use std::ffi::CString;
+
 use ffmpeg_next::codec::Id as CodecId;
+use ffmpeg_next::ffi;
 
 #[derive(Clone, Debug)]
 pub struct CodecCandidate {
@@ -48,6 +51,13 @@ fn hevc_sw_candidates() -> Vec<CodecCandidate> {
     vec![CodecCandidate::sw("libx265"), CodecCandidate::sw("hevc")]
 }
 
+fn vp9_hw_candidates() -> Vec<CodecCandidate> {
+    vec![
+        CodecCandidate::hw("vp9_qsv"),
+        CodecCandidate::hw("vp9_vaapi"),
+    ]
+}
+
 fn dedup_by_name(candidates: Vec<CodecCandidate>) -> Vec<CodecCandidate> {
     let mut out = Vec::with_capacity(candidates.len());
     for c in candidates {
@@ -71,6 +81,10 @@ pub fn video_encoder_candidates(requested: Option<&str>) -> Vec<CodecCandidate>
             out.extend(hevc_hw_candidates());
             out.extend(hevc_sw_candidates());
         }
+        "vp9" | "libvpx-vp9" => {
+            out.extend(vp9_hw_candidates());
+            out.push(CodecCandidate::sw("libvpx-vp9"));
+        }
         "h264_videotoolbox" => {
             out.push(CodecCandidate::hw("h264_videotoolbox"));
             out.extend(h264_sw_candidates());
@@ -139,3 +153,121 @@ pub fn video_decoder_candidates(codec_id: CodecId) -> Vec<CodecCandidate> {
     }
     dedup_by_name(out)
 }
+
+/// Which decoder a [`Decoder`](crate::decoder::Decoder) opens for video, see
+/// [`DecoderSettings`](crate::decoder::DecoderSettings).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum HwPreference {
+    /// The first of [`video_decoder_candidates`] that opens, software last.
+    #[default]
+    Auto,
+    /// The software decoder only.
+    Software,
+    /// Decode on a hardware device of `device_type` (`cuda`, `vaapi`, `qsv`,
+    /// `videotoolbox`, …, as FFmpeg names them) opened on `device` (e.g.
+    /// `/dev/dri/renderD128`; `None` for the default one), with the decoder
+    /// [`find_hw_decoder`] picks.
+    Device {
+        device_type: String,
+        device: Option<String>,
+    },
+}
+
+/// The decoder of `codec_id` that decodes on a `device_type` device: the
+/// native one when it has a hwaccel for the device (VAAPI, NVDEC through
+/// `cuda`, VideoToolbox), else a wrapper such as `h264_cuvid` or `hevc_qsv`.
+/// `None` for an unknown device type, when this FFmpeg build has neither, or
+/// when FFMPEG_BUS_DISABLE_HWDEC is set.
+pub fn find_hw_decoder(codec_id: CodecId, device_type: &str) -> Option<ffmpeg_next::Codec> {
+    if std::env::var_os("FFMPEG_BUS_DISABLE_HWDEC").is_some() {
+        return None;
+    }
+    let kind = device_type_by_name(device_type)?;
+    if let Some(codec) = ffmpeg_next::decoder::find(codec_id)
+        && has_device_config(codec, kind)
+    {
+        return Some(codec);
+    }
+    // The wrappers are named after the API, which is not always the device's.
+    let suffix = match device_type {
+        "cuda" => "_cuvid",
+        "videotoolbox" => "_videotoolbox",
+        "qsv" => "_qsv",
+        _ => return None,
+    };
+    video_decoder_candidates(codec_id)
+        .into_iter()
+        .filter(|c| c.is_hw && c.name.ends_with(suffix))
+        .find_map(|c| ffmpeg_next::decoder::find_by_name(&c.name))
+}
+
+fn device_type_by_name(name: &str) -> Option<ffi::AVHWDeviceType> {
+    let name = CString::new(name).ok()?;
+    let kind = unsafe { ffi::av_hwdevice_find_type_by_name(name.as_ptr()) };
+    (kind != ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_NONE).then_some(kind)
+}
+
+/// Whether `codec` decodes on a device of `kind` given through the codec
+/// context's `hw_device_ctx`.
+fn has_device_config(codec: ffmpeg_next::Codec, kind: ffi::AVHWDeviceType) -> bool {
+    (0..)
+        .map_while(|i| unsafe { ffi::avcodec_get_hw_config(codec.as_ptr(), i).as_ref() })
+        .any(|config| {
+            config.device_type == kind
+                && config.methods & ffi::AV_CODEC_HW_CONFIG_METHOD_HW_DEVICE_CTX as i32 != 0
+        })
+}
+
+/// An FFmpeg hardware device context, released on drop.
+pub struct HwDevice(*mut ffi::AVBufferRef);
+
+impl HwDevice {
+    /// Open a device of `device_type` on `device` (`None` for the default
+    /// one).
+    pub fn open(device_type: &str, device: Option<&str>) -> anyhow::Result<Self> {
+        let kind = device_type_by_name(device_type)
+            .ok_or_else(|| anyhow::anyhow!("unknown hardware device type {device_type:?}"))?;
+        let device_name = device.map(CString::new).transpose()?;
+        let mut ctx = std::ptr::null_mut();
+        let ret = unsafe {
+            ffi::av_hwdevice_ctx_create(
+                &mut ctx,
+                kind,
+                device_name
+                    .as_ref()
+                    .map_or(std::ptr::null(), |d| d.as_ptr()),
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if ret < 0 {
+            anyhow::bail!(
+                "open {device_type} device {}: {}",
+                device.unwrap_or("(default)"),
+                ffmpeg_next::Error::from(ret)
+            );
+        }
+        Ok(Self(ctx))
+    }
+
+    /// Hand a reference to the device to `ctx`, whose decoder then decodes
+    /// on it.
+    pub fn attach(&self, ctx: &mut ffmpeg_next::codec::Context) -> anyhow::Result<()> {
+        let device = unsafe { ffi::av_buffer_ref(self.0) };
+        if device.is_null() {
+            anyhow::bail!("out of memory referencing the hardware device");
+        }
+        unsafe {
+            let ctx = ctx.as_mut_ptr();
+            ffi::av_buffer_unref(&mut (*ctx).hw_device_ctx);
+            (*ctx).hw_device_ctx = device;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HwDevice {
+    fn drop(&mut self) {
+        unsafe { ffi::av_buffer_unref(&mut self.0) };
+    }
+}
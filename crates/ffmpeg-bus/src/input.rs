@@ -1,92 +1,696 @@
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::ffi::{CString, c_int, c_void};
+use std::io::Read;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use ffmpeg_next::Dictionary;
+use ffmpeg_next::{Dictionary, Rational, Rescale};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
+    bus::{BusEvent, RetryPolicy},
+    clock::{ClockOffset, OffsetEstimator},
+    gop::{GopAggregate, GopStats, GopWindow},
+    memory::MemoryBudget,
     packet::{RawPacket, RawPacketCmd, RawPacketReceiver, RawPacketSender},
+    push::PushSource,
     stream::AvStream,
+    teardown::StageTasks,
 };
 
 pub struct AvInputTask {
     cancel: CancellationToken,
+    /// Every packet, for [`Self::subscribe`].
     raw_chan: RawPacketSender,
+    /// One channel per stream index, created by the first
+    /// [`Self::subscribe_stream`] of that stream.
+    stream_chans: Arc<Mutex<HashMap<usize, RawPacketSender>>>,
+    /// Capacity of each of the channels.
+    capacity: usize,
+    clock: Arc<Mutex<OffsetEstimator>>,
+    counters: Arc<InputCounters>,
+    /// The bus's input stage, once the task is part of one.
+    stage: Option<StageTasks>,
+    /// Where the end of the input is reported, and the input's id there.
+    events: Option<(tokio::sync::broadcast::Sender<BusEvent>, String)>,
+    playback: Arc<Playback>,
+}
+
+/// Whether an input is paused and the rate a file plays at, see
+/// [`AvInputTask::pause`] and [`AvInputTask::set_rate`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlaybackState {
+    pub paused: bool,
+    /// `None` while the file is read at its own pace.
+    pub rate: Option<f32>,
+}
+
+/// What an input has read since it started (see [`AvInputTask::stats`]), for
+/// health monitoring and data usage: rates come from the difference of two
+/// readings.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InputStats {
+    /// Packets of every stream read.
+    pub packets: u64,
+    /// Packets of the video stream and their total size in bytes.
+    pub video_packets: u64,
+    pub video_bytes: u64,
+    /// Size in bytes of the packets of every stream.
+    pub bytes: u64,
+    /// Packets the demuxer flagged as corrupt (e.g. RTP packet loss).
+    pub corrupt_packets: u64,
+    /// Packets the slowest consumer of a packet channel lost by lagging
+    /// behind it.
+    pub lagged_packets: u64,
+    /// The video stream's nominal frame rate, if it declares one.
+    pub nominal_fps: Option<f64>,
+    /// The last [`GOP_WINDOW`](crate::gop::GOP_WINDOW) GOPs of the video
+    /// stream, once one has closed.
+    pub gop: Option<GopAggregate>,
+    /// Times the connection dropped and was opened again (see
+    /// [`AvInputTask::start_with_retry`]).
+    pub reconnects: u64,
+    /// Our wall clock (Unix microseconds) when the last packet was read;
+    /// `None` before the first.
+    pub last_packet_us: Option<i64>,
+}
+
+/// Opens an input again after its connection dropped, see
+/// [`AvInputTask::start_with_retry`].
+pub type InputFactory = Box<dyn FnMut() -> anyhow::Result<AvInput> + Send>;
+
+/// How often a reconnecting reader checks for a stop while it waits out a
+/// backoff.
+const RECONNECT_POLL: Duration = Duration::from_millis(50);
+
+/// How often a paused reader checks for a resume or a stop.
+const PAUSE_POLL: Duration = Duration::from_millis(20);
+
+/// The state [`AvInputTask`] sets and its reader follows between packets.
+#[derive(Default)]
+struct Playback {
+    paused: AtomicBool,
+    /// `f32` bits; 0 while no rate is set.
+    rate: AtomicU32,
+    /// Bumped on every change, so the reader paces again from its next
+    /// packet.
+    changes: AtomicU64,
+}
+
+impl Playback {
+    fn state(&self) -> PlaybackState {
+        let rate = f32::from_bits(self.rate.load(Ordering::Relaxed));
+        PlaybackState {
+            paused: self.paused.load(Ordering::Relaxed),
+            rate: (rate > 0.0).then_some(rate),
+        }
+    }
+
+    fn changed(&self) {
+        self.changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Block while paused. False if `cancel` fired first.
+    fn wait_while_paused(&self, cancel: &CancellationToken) -> bool {
+        while self.paused.load(Ordering::Relaxed) {
+            if cancel.is_cancelled() {
+                return false;
+            }
+            std::thread::sleep(PAUSE_POLL);
+        }
+        true
+    }
+}
+
+#[derive(Default)]
+struct InputCounters {
+    packets: AtomicU64,
+    video_packets: AtomicU64,
+    video_bytes: AtomicU64,
+    bytes: AtomicU64,
+    corrupt_packets: AtomicU64,
+    lagged_packets: AtomicU64,
+    /// `f64` bits; 0 when unknown.
+    nominal_fps: AtomicU64,
+    gops: Mutex<GopWindow>,
+    reconnects: AtomicU64,
+    /// Unix microseconds; 0 before the first packet.
+    last_packet_us: AtomicI64,
+}
+
+impl InputCounters {
+    fn observe(&self, packet: &RawPacket, video_index: Option<usize>) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.last_packet_us
+            .store(crate::clock::now_micros(), Ordering::Relaxed);
+        self.bytes
+            .fetch_add(packet.size() as u64, Ordering::Relaxed);
+        if packet.packet().is_corrupt() {
+            self.corrupt_packets.fetch_add(1, Ordering::Relaxed);
+        }
+        if Some(packet.index()) == video_index {
+            self.video_packets.fetch_add(1, Ordering::Relaxed);
+            self.video_bytes
+                .fetch_add(packet.size() as u64, Ordering::Relaxed);
+        }
+    }
 }
 
 impl AvInputTask {
-    /// Input packet channel. Bounded per-frame size; balance memory vs avoiding Lagged drop.
-    const PACKET_CHAN_CAP: usize = 4096;
+    /// Default capacity of the input packet channels, in packets. Balance
+    /// memory vs avoiding Lagged drop.
+    pub const PACKET_CHAN_CAP: usize = 4096;
+
+    /// Slowest and fastest playback rates of [`Self::set_rate`].
+    pub const MIN_RATE: f32 = 1.0 / 16.0;
+    pub const MAX_RATE: f32 = 16.0;
+
     pub fn new() -> Self {
+        Self::with_capacity(Self::PACKET_CHAN_CAP)
+    }
+
+    /// An input task whose packet channels hold up to `capacity` packets
+    /// each before the slowest receiver starts losing them.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
         let cancel = CancellationToken::new();
-        let (sender, _) = tokio::sync::broadcast::channel(Self::PACKET_CHAN_CAP);
+        let (sender, _) = tokio::sync::broadcast::channel(capacity);
 
         Self {
             cancel,
             raw_chan: sender,
+            stream_chans: Arc::default(),
+            capacity,
+            clock: Arc::default(),
+            counters: Arc::default(),
+            stage: None,
+            events: None,
+            playback: Arc::default(),
         }
     }
 
-    pub async fn start(&self, mut input: AvInput) {
+    /// Make the reader a task of `stage` (see [`crate::teardown`]): it is
+    /// stopped by forcing the stage, and counted in until it has sent its
+    /// EOF.
+    pub fn with_stage(mut self, stage: &StageTasks) -> Self {
+        self.cancel = stage.token();
+        self.stage = Some(stage.clone());
+        self
+    }
+
+    /// Report the input's end to `events`, as [`BusEvent::InputEof`] (or
+    /// [`BusEvent::InputError`] once reconnecting gave up) of input `id`.
+    pub fn with_events(
+        mut self,
+        events: tokio::sync::broadcast::Sender<BusEvent>,
+        id: &str,
+    ) -> Self {
+        self.events = Some((events, id.to_string()));
+        self
+    }
+
+    pub async fn start(&self, input: AvInput) {
+        self.spawn_reader(input, None);
+    }
+
+    /// [`Self::start`] for a network input that comes back: when `input`
+    /// ends (the camera dropped the connection), `reopen` opens it again
+    /// under `retry`, waiting its backoff before each attempt. Subscribers
+    /// then get [`RawPacketCmd::Reconnected`] and the new connection's
+    /// packets, shifted to go on from the old one's timestamps; the EOF
+    /// only comes once the attempts run out or the task is stopped.
+    pub async fn start_with_retry(&self, input: AvInput, reopen: InputFactory, retry: RetryPolicy) {
+        self.spawn_reader(input, Some((reopen, retry)));
+    }
+
+    fn spawn_reader(&self, mut input: AvInput, mut reconnect: Option<(InputFactory, RetryPolicy)>) {
         let cancel_clone = self.cancel.clone();
         let sender_clone = self.raw_chan.clone();
-        tokio::spawn(async move {
+        let stream_chans = self.stream_chans.clone();
+        let capacity = self.capacity;
+        let clock = self.clock.clone();
+        let counters = self.counters.clone();
+        let events = self.events.clone();
+        let playback = self.playback.clone();
+        let budget = input.budget.clone();
+        let done = self.stage.as_ref().map(StageTasks::enter);
+        let video = input.streams.values().find(|s| s.is_video());
+        let video_index = video.map(|s| s.index());
+        let mut gop_stats = video.map(GopStats::new);
+        if let Some(fps) = video
+            .map(|s| s.fps() as f64)
+            .filter(|fps| fps.is_finite() && *fps > 0.0)
+        {
+            counters.nominal_fps.store(fps.to_bits(), Ordering::Relaxed);
+        }
+        crate::worker::spawn_task("bus-input", async move {
             let cancel_inner = cancel_clone.clone();
-            let handle = tokio::task::spawn_blocking(move || {
+            let handle = crate::worker::spawn("bus-input", move || {
+                let mut continuity = Continuity::default();
+                let mut paced = 0;
                 loop {
                     if cancel_inner.is_cancelled() {
                         break;
                     }
+                    // Paused: the input stays open, nothing is read.
+                    if !playback.wait_while_paused(&cancel_inner) {
+                        break;
+                    }
+                    let changes = playback.changes.load(Ordering::Relaxed);
+                    if changes != paced {
+                        paced = changes;
+                        input.set_rate(playback.state().rate);
+                    }
+                    // Backpressure: hold off reading while too much is in
+                    // flight.
+                    if !budget.wait_for_room(&cancel_inner) {
+                        break;
+                    }
                     match input.read_packet() {
-                        Some(packet) => {
+                        Some(mut packet) => {
+                            if let Some(source_us) = input.packet_wallclock_micros(&packet) {
+                                clock
+                                    .lock()
+                                    .unwrap()
+                                    .observe(source_us, crate::clock::now_micros());
+                            }
+                            continuity.apply(&mut packet);
+                            counters.observe(&packet, video_index);
+                            if let Some(gop) =
+                                gop_stats.as_mut().and_then(|g| g.observe(&packet))
+                            {
+                                counters.gops.lock().unwrap().push(gop);
+                            }
+                            let chans = stream_chans.lock().unwrap();
+                            let stream_chan = chans.get(&packet.index());
+                            // A full channel means this send overwrites a packet
+                            // the slowest receiver has not read yet.
+                            let full = |chan: &RawPacketSender| chan.len() >= capacity;
+                            if full(&sender_clone) || stream_chan.is_some_and(full) {
+                                counters.lagged_packets.fetch_add(1, Ordering::Relaxed);
+                            }
                             // Attempt to send, ignore send error (receiver dropped)
+                            if let Some(chan) = stream_chan {
+                                let _ = chan.send(RawPacketCmd::Data(packet.clone()));
+                            }
                             let _ = sender_clone.send(RawPacketCmd::Data(packet));
                         }
                         None => {
+                            let next = match reconnect.as_mut() {
+                                Some((reopen, retry)) if !cancel_inner.is_cancelled() => {
+                                    reconnect_input(reopen, retry, &cancel_inner)
+                                }
+                                _ => None,
+                            };
+                            if let Some(next) = next {
+                                warn_changed_streams(&input, &next);
+                                input = next.with_budget(budget.clone());
+                                continuity.resync = true;
+                                counters.reconnects.fetch_add(1, Ordering::Relaxed);
+                                for chan in stream_chans.lock().unwrap().values() {
+                                    let _ = chan.send(RawPacketCmd::Reconnected);
+                                }
+                                let _ = sender_clone.send(RawPacketCmd::Reconnected);
+                                continue;
+                            }
+                            if !cancel_inner.is_cancelled()
+                                && let Some((events, id)) = &events
+                            {
+                                let input = id.clone();
+                                let _ = events.send(match reconnect {
+                                    Some(_) => BusEvent::InputError {
+                                        input,
+                                        error: "connection lost, out of reconnect attempts"
+                                            .to_string(),
+                                    },
+                                    None => BusEvent::InputEof { input },
+                                });
+                            }
                             // End of stream, break the loop
-                            log::info!("end of read input stream:");
+                            tracing::info!("end of read input stream:");
                             for (index, stream) in input.streams.iter() {
-                                log::info!(
+                                tracing::info!(
                                     "stream index: {}, stream id: {:#?}, time_base: {:#?}",
                                     index,
                                     stream.parameters().id(),
                                     stream.time_base()
                                 );
                             }
-                            let _ = sender_clone.send(RawPacketCmd::EOF);
                             break;
                         }
                     }
                 }
 
+                // Every subscriber, of one stream or all, sees the end, be it
+                // the input's or a stop.
+                for chan in stream_chans.lock().unwrap().values() {
+                    let _ = chan.send(RawPacketCmd::EOF);
+                }
+                let _ = sender_clone.send(RawPacketCmd::EOF);
                 drop(sender_clone);
+                drop(done);
             });
 
             tokio::select! {
                 _ = handle => {
-                    log::info!("read input packet task finished");
+                    tracing::info!("read input packet task finished");
                     cancel_clone.cancel();
                 }
                 _ = cancel_clone.cancelled() => {
-                    log::info!("read input packet task cancelled");
+                    tracing::info!("read input packet task cancelled");
                 }
             }
         });
     }
 
+    /// Packets of every stream, and the EOF.
     pub fn subscribe(&self) -> RawPacketReceiver {
         self.raw_chan.subscribe()
     }
 
+    /// Packets of stream `index` only, and the EOF. A consumer of one stream
+    /// is then neither woken for the packets of the others nor lags on them,
+    /// as it would filtering [`Self::subscribe`].
+    pub fn subscribe_stream(&self, index: usize) -> RawPacketReceiver {
+        self.stream_chans
+            .lock()
+            .unwrap()
+            .entry(index)
+            .or_insert_with(|| tokio::sync::broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+
+    /// The source's clock offset from ours, once the input reports sender
+    /// wall clock times (RTSP after the first RTCP sender report).
+    pub fn clock_offset(&self) -> Option<ClockOffset> {
+        self.clock.lock().unwrap().estimate()
+    }
+
+    /// Counters of what the input has read so far.
+    pub fn stats(&self) -> InputStats {
+        let c = &self.counters;
+        let fps = f64::from_bits(c.nominal_fps.load(Ordering::Relaxed));
+        let last_packet_us = c.last_packet_us.load(Ordering::Relaxed);
+        InputStats {
+            packets: c.packets.load(Ordering::Relaxed),
+            video_packets: c.video_packets.load(Ordering::Relaxed),
+            video_bytes: c.video_bytes.load(Ordering::Relaxed),
+            bytes: c.bytes.load(Ordering::Relaxed),
+            corrupt_packets: c.corrupt_packets.load(Ordering::Relaxed),
+            lagged_packets: c.lagged_packets.load(Ordering::Relaxed),
+            nominal_fps: (fps > 0.0).then_some(fps),
+            gop: c.gops.lock().unwrap().aggregate(),
+            reconnects: c.reconnects.load(Ordering::Relaxed),
+            last_packet_us: (last_packet_us > 0).then_some(last_packet_us),
+        }
+    }
+
+    /// Stop reading until [`Self::resume`], keeping the input open:
+    /// subscribers get no packets meanwhile, and no EOF.
+    pub fn pause(&self) {
+        self.playback.paused.store(true, Ordering::Relaxed);
+        self.playback.changed();
+    }
+
+    /// Read on after [`Self::pause`], from where the input stopped. A paced
+    /// file is paced from there, not caught up on the time paused.
+    pub fn resume(&self) {
+        self.playback.paused.store(false, Ordering::Relaxed);
+        self.playback.changed();
+    }
+
+    /// Read a file input at `rate` times the pace of its timestamps (2.0 is
+    /// twice as fast as it plays), clamped to [`Self::MIN_RATE`] and
+    /// [`Self::MAX_RATE`], instead of as fast as it can be read (or in real
+    /// time, for a realtime looping file). Live inputs come at their own pace
+    /// and ignore it (see [`AvInput::playback`]).
+    pub fn set_rate(&self, rate: f32) {
+        let rate = rate.clamp(Self::MIN_RATE, Self::MAX_RATE);
+        self.playback.rate.store(rate.to_bits(), Ordering::Relaxed);
+        self.playback.changed();
+    }
+
+    /// Whether the input is paused, and its playback rate.
+    pub fn playback(&self) -> PlaybackState {
+        self.playback.state()
+    }
+
+    /// Stop reading; subscribers then get the EOF.
     pub fn stop(&self) {
         self.cancel.cancel();
     }
 }
 
+/// Open a dropped input again with `reopen`, up to `retry.max_attempts`
+/// times, waiting the backoff before each attempt. `None` once the attempts
+/// run out or `cancel` fires.
+fn reconnect_input(
+    reopen: &mut InputFactory,
+    retry: &RetryPolicy,
+    cancel: &CancellationToken,
+) -> Option<AvInput> {
+    let attempts = retry.max_attempts.max(1);
+    for attempt in 1..=attempts {
+        let due = Instant::now() + retry.backoff(attempt);
+        while Instant::now() < due {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            std::thread::sleep(RECONNECT_POLL.min(due.saturating_duration_since(Instant::now())));
+        }
+        match reopen() {
+            Ok(input) => {
+                tracing::info!("input: reconnected (attempt {})", attempt);
+                return Some(input);
+            }
+            Err(e) => tracing::warn!("input: reconnect attempt {} failed: {:#}", attempt, e),
+        }
+    }
+    tracing::error!(
+        "input: no connection after {} attempts, giving up",
+        attempts
+    );
+    None
+}
+
+/// The bus keeps the streams of the first connection: a new one whose
+/// streams differ (the camera was reconfigured) is read as is, with a
+/// warning.
+fn warn_changed_streams(old: &AvInput, new: &AvInput) {
+    let codecs = |input: &AvInput| {
+        let mut codecs: Vec<_> = input
+            .streams
+            .iter()
+            .map(|(index, stream)| (*index, stream.parameters().id()))
+            .collect();
+        codecs.sort_by_key(|(index, _)| *index);
+        codecs
+    };
+    if codecs(old) != codecs(new) {
+        tracing::warn!(
+            "input: streams changed on reconnect, {:?} -> {:?}",
+            codecs(old),
+            codecs(new)
+        );
+    }
+}
+
+/// Keeps a reconnected input's timestamps going on from where the dropped
+/// connection left off: a new connection starts its own clock, often at 0.
+#[derive(Default)]
+struct Continuity {
+    /// Added to every timestamp, microseconds.
+    offset_us: i64,
+    /// Latest dts + duration passed on, microseconds.
+    end_us: Option<i64>,
+    /// Latest dts passed on per stream, in its time base.
+    last: HashMap<usize, i64>,
+    /// Set on a reconnect: the next packet fixes the offset.
+    resync: bool,
+    /// Whether the input reconnected at all.
+    reconnected: bool,
+}
+
+impl Continuity {
+    fn apply(&mut self, packet: &mut RawPacket) {
+        let (index, time_base) = (packet.index(), packet.time_base());
+        let p = packet.get_mut();
+        let Some(ts) = p.dts().or(p.pts()) else {
+            return;
+        };
+        if self.resync {
+            self.resync = false;
+            self.reconnected = true;
+            self.offset_us = self
+                .end_us
+                .map_or(0, |end| end - ts.rescale(time_base, MICROS));
+        }
+        let mut offset = self.offset_us.rescale(MICROS, time_base);
+        // A stream whose first packet on the new connection comes earlier
+        // than the one the offset was fixed at pushes it on, so no stream
+        // goes back.
+        if self.reconnected
+            && let Some(last) = self.last.get(&index)
+            && ts + offset <= *last
+        {
+            offset = last - ts + 1;
+            self.offset_us = offset.rescale(time_base, MICROS);
+        }
+        if offset != 0 {
+            p.set_pts(p.pts().map(|pts| pts + offset));
+            p.set_dts(p.dts().map(|dts| dts + offset));
+        }
+        self.last.insert(index, ts + offset);
+        // At least one tick, so the next connection starts after this packet.
+        let end = (ts + offset + p.duration().max(1)).rescale(time_base, MICROS);
+        self.end_us = Some(self.end_us.map_or(end, |e| e.max(end)));
+    }
+}
+
 pub struct AvInput {
-    inner: ffmpeg_next::format::context::Input,
+    inner: Source,
     streams: HashMap<usize, AvStream>,
+    looping: Option<LoopState>,
+    range: Option<PlayRange>,
+    /// Whether [`Self::set_rate`] paces it, see [`Self::playback`].
+    file: bool,
+    pacer: Option<Pacer>,
+    /// What the packets read are charged to, and what pauses reading.
+    budget: Arc<MemoryBudget>,
+    /// The IO of a [`Self::from_reader`] input. After `inner`, so it is
+    /// freed once the format context is closed.
+    _reader_io: Option<ReaderIo>,
+}
+
+/// What an [`AvInput`] reads its packets from.
+enum Source {
+    Demuxer(ffmpeg_next::format::context::Input),
+    /// The frames sent to an [`InputConfig::Push`](crate::bus::InputConfig::Push).
+    Push(PushSource),
+}
+
+/// The custom IO context a [`AvInput::from_reader`] input reads through, and
+/// the reader behind it.
+struct ReaderIo {
+    io: *mut ffmpeg_next::ffi::AVIOContext,
+    reader: *mut Box<dyn Read + Send>,
+}
+
+// SAFETY: both are only used by whichever thread reads the input, and the
+// reader is `Send`.
+unsafe impl Send for ReaderIo {}
+
+impl Drop for ReaderIo {
+    fn drop(&mut self) {
+        use ffmpeg_next::ffi;
+        unsafe {
+            // FFmpeg may have swapped the buffer for one of its own.
+            ffi::av_freep(&mut (*self.io).buffer as *mut *mut u8 as *mut c_void);
+            ffi::avio_context_free(&mut self.io);
+            drop(Box::from_raw(self.reader));
+        }
+    }
+}
+
+const MICROS: Rational = Rational(1, 1_000_000);
+
+/// The part of a file an [`AvInput::range`] input plays, microseconds on the
+/// container's clock.
+struct PlayRange {
+    start_us: Option<i64>,
+    end_us: Option<i64>,
+    /// A packet past `end_us` was read: the input has ended.
+    ended: bool,
+}
+
+/// State of a file input that restarts at EOF (see [`AvInput::looping`]).
+struct LoopState {
+    realtime: bool,
+    /// Completed passes over the file.
+    loops: i64,
+    /// Length of one pass, microseconds; fixed at the first EOF.
+    loop_us: Option<i64>,
+    /// Earliest dts and latest dts + duration seen in the first pass,
+    /// microseconds.
+    first_us: Option<i64>,
+    end_us: i64,
+}
+
+/// Holds a file's packets back to `rate` times the pace of their
+/// timestamps.
+struct Pacer {
+    rate: f64,
+    /// Wall clock and timestamp (microseconds) of the first packet paced.
+    origin: Option<(Instant, i64)>,
+}
+
+impl Pacer {
+    fn new(rate: f32) -> Self {
+        Self {
+            rate: f64::from(rate),
+            origin: None,
+        }
+    }
+
+    /// Sleep until `packet` is due on the wall clock.
+    fn pace(&mut self, packet: &ffmpeg_next::Packet, time_base: Rational) {
+        let Some(ts) = packet.dts().or(packet.pts()) else {
+            return;
+        };
+        let ts_us = ts.rescale(time_base, MICROS);
+        let (origin, origin_us) = *self.origin.get_or_insert((Instant::now(), ts_us));
+        let played = (ts_us - origin_us).max(0) as f64 / self.rate;
+        let due = origin + Duration::from_micros(played as u64);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+    }
+}
+
+impl LoopState {
+    fn new(realtime: bool) -> Self {
+        Self {
+            realtime,
+            loops: 0,
+            loop_us: None,
+            first_us: None,
+            end_us: i64::MIN,
+        }
+    }
+
+    /// Track the span of the first pass. `packet` is still unshifted.
+    fn observe(&mut self, packet: &ffmpeg_next::Packet, time_base: Rational) {
+        if self.loop_us.is_some() {
+            return;
+        }
+        let Some(dts) = packet.dts().or(packet.pts()) else {
+            return;
+        };
+        let start = dts.rescale(time_base, MICROS);
+        let end = (dts + packet.duration().max(0)).rescale(time_base, MICROS);
+        self.first_us = Some(self.first_us.map_or(start, |f| f.min(start)));
+        self.end_us = self.end_us.max(end);
+    }
+
+    /// End of a pass: fix the pass length (the container duration, or the
+    /// span of the packets read if longer, so the next pass starts after the
+    /// last packet of this one) and count it.
+    fn rewind(&mut self, container_us: i64) {
+        if self.loop_us.is_none() {
+            let span = self.first_us.map_or(0, |f| self.end_us - f);
+            self.loop_us = Some(span.max(container_us).max(1));
+        }
+        self.loops += 1;
+    }
+
+    /// Offset added to every timestamp of the current pass, in `time_base`.
+    /// Rescaled from the total in microseconds so rounding does not build up.
+    fn offset(&self, time_base: Rational) -> i64 {
+        (self.loops * self.loop_us.unwrap_or(0)).rescale(MICROS, time_base)
+    }
 }
 
 impl AvInput {
@@ -134,21 +738,282 @@ impl AvInput {
         }
 
         Ok(Self {
-            inner: input,
+            inner: Source::Demuxer(input),
             streams,
+            looping: None,
+            range: None,
+            file: false,
+            pacer: None,
+            budget: MemoryBudget::global().clone(),
+            _reader_io: None,
         })
     }
 
+    /// An input demuxing, as `format` (e.g. "mpeg"), the bytes `reader`
+    /// yields, for streams FFmpeg cannot open by URL. It ends when a read
+    /// returns 0 bytes or fails; it cannot seek.
+    pub fn from_reader(
+        reader: Box<dyn Read + Send>,
+        format: &str,
+        options: Option<Dictionary>,
+    ) -> anyhow::Result<Self> {
+        use crate::metadata::{READER_BUFFER_SIZE, read_reader};
+        use ffmpeg_next::ffi;
+
+        let fmt = Self::find_input_format(format)?;
+        let reader = Box::into_raw(Box::new(reader));
+        let (input, io) = unsafe {
+            let buffer = ffi::av_malloc(READER_BUFFER_SIZE) as *mut u8;
+            let io = ReaderIo {
+                io: ffi::avio_alloc_context(
+                    buffer,
+                    READER_BUFFER_SIZE as c_int,
+                    // Read only.
+                    0,
+                    reader as *mut c_void,
+                    Some(read_reader::<Box<dyn Read + Send>>),
+                    None,
+                    None,
+                ),
+                reader,
+            };
+            let mut ctx = ffi::avformat_alloc_context();
+            (*ctx).pb = io.io;
+            (*ctx).flags |= ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+            let mut opts = options.map_or(std::ptr::null_mut(), Dictionary::disown);
+            // Frees `ctx` (not `io`) on failure.
+            let opened =
+                ffi::avformat_open_input(&mut ctx, std::ptr::null(), fmt.as_ptr(), &mut opts);
+            // Whatever the demuxer did not take.
+            drop(Dictionary::own(opts));
+            if opened < 0 {
+                return Err(ffmpeg_next::Error::from(opened).into());
+            }
+            // Closing the input leaves a custom `pb` alone.
+            let mut input = ffmpeg_next::format::context::Input::wrap(ctx);
+            let found = ffi::avformat_find_stream_info(input.as_mut_ptr(), std::ptr::null_mut());
+            if found < 0 {
+                return Err(ffmpeg_next::Error::from(found).into());
+            }
+            (input, io)
+        };
+
+        let mut streams = HashMap::new();
+        for stream in input.streams() {
+            streams.insert(stream.index(), AvStream::from(stream));
+        }
+        Ok(Self {
+            inner: Source::Demuxer(input),
+            streams,
+            looping: None,
+            range: None,
+            file: false,
+            pacer: None,
+            budget: MemoryBudget::global().clone(),
+            _reader_io: Some(io),
+        })
+    }
+
+    /// An input of the packets pushed into `source`, charged to and paused
+    /// on its own budget (see [`crate::push`]).
+    pub(crate) fn from_push(source: PushSource) -> Self {
+        Self {
+            streams: source.streams.clone(),
+            looping: None,
+            range: None,
+            file: false,
+            pacer: None,
+            budget: source.budget.clone(),
+            inner: Source::Push(source),
+            _reader_io: None,
+        }
+    }
+
+    /// Charge the packets read to `budget` instead of the global one, and
+    /// pause reading on it (see [`crate::memory`]).
+    pub fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Restart at EOF instead of ending, shifting each pass's timestamps past
+    /// the previous one so they stay monotonic across passes. With `realtime`,
+    /// packets are read no faster than their timestamps advance, as from a
+    /// live source. Only meaningful for seekable (file) inputs.
+    pub fn looping(mut self, realtime: bool) -> Self {
+        self.looping = Some(LoopState::new(realtime));
+        self.file = true;
+        self.pacer = realtime.then(|| Pacer::new(1.0));
+        self
+    }
+
+    /// Mark a file input as one [`Self::set_rate`] paces. [`Self::looping`]
+    /// and [`Self::range`] inputs are files already.
+    pub fn playback(mut self) -> Self {
+        self.file = true;
+        self
+    }
+
+    /// Read no faster than `rate` times the pace of the timestamps (2.0 is
+    /// twice as fast as the file plays), from the next packet on; `None` goes
+    /// back to the input's own pace. Ignored, returning false, by inputs
+    /// not read from a file, which come at their own pace.
+    pub fn set_rate(&mut self, rate: Option<f32>) -> bool {
+        if !self.file {
+            return false;
+        }
+        let realtime = self.looping.as_ref().is_some_and(|state| state.realtime);
+        self.pacer = match rate {
+            Some(rate) if rate.is_finite() && rate > 0.0 => Some(Pacer::new(rate)),
+            _ => realtime.then(|| Pacer::new(1.0)),
+        };
+        true
+    }
+
+    /// Play only `start..end` of the file, both offsets from its beginning:
+    /// seek to the keyframe at or before `start`, and end at the first packet
+    /// past `end`. The packets between the keyframe and `start` are still
+    /// read, the decoder needs them; leaving out the frames they decode to is
+    /// up to the consumer (see [`Self::range_start_micros`]). Only for
+    /// seekable (file) inputs.
+    pub fn range(mut self, start: Option<Duration>, end: Option<Duration>) -> anyhow::Result<Self> {
+        let Source::Demuxer(input) = &mut self.inner else {
+            anyhow::bail!("only a demuxed input can seek");
+        };
+        let origin = unsafe { (*input.as_ptr()).start_time };
+        let origin = if origin == ffmpeg_next::ffi::AV_NOPTS_VALUE {
+            0
+        } else {
+            origin
+        };
+        // The container clock is in AV_TIME_BASE units, microseconds.
+        let at = |offset: Duration| origin.saturating_add(offset.as_micros() as i64);
+        let start_us = start.filter(|start| !start.is_zero()).map(at);
+        if let Some(ts) = start_us {
+            input
+                .seek(ts, ..ts)
+                .map_err(|e| anyhow::anyhow!("cannot seek to {:?}: {}", start, e))?;
+        }
+        self.range = Some(PlayRange {
+            start_us,
+            end_us: end.map(at),
+            ended: false,
+        });
+        self.file = true;
+        Ok(self)
+    }
+
+    /// Where a [`Self::range`] input starts, microseconds on the
+    /// container's clock: frames earlier than this only lead up to it.
+    pub fn range_start_micros(&self) -> Option<i64> {
+        self.range.as_ref().and_then(|range| range.start_us)
+    }
+
     pub fn streams(&self) -> &HashMap<usize, AvStream> {
         &self.streams
     }
 
+    /// The sender's wall clock time at pts 0, Unix microseconds. FFmpeg's RTSP
+    /// demuxer sets it from the first RTCP sender report, so it is `None` for
+    /// other inputs and until that report has been read.
+    pub fn start_time_realtime(&self) -> Option<i64> {
+        let Source::Demuxer(input) = &self.inner else {
+            return None;
+        };
+        let value = unsafe { (*input.as_ptr()).start_time_realtime };
+        (value != ffmpeg_next::ffi::AV_NOPTS_VALUE).then_some(value)
+    }
+
+    /// Sender wall clock time of `packet`, Unix microseconds (see
+    /// [`Self::start_time_realtime`]).
+    pub fn packet_wallclock_micros(&self, packet: &RawPacket) -> Option<i64> {
+        let start = self.start_time_realtime()?;
+        let pts = packet.pts()?;
+        Some(crate::clock::packet_wallclock_micros(
+            start,
+            pts,
+            packet.time_base(),
+        ))
+    }
+
     pub fn read_packet(&mut self) -> Option<RawPacket> {
+        if self.looping.is_some() {
+            return self.read_looping_packet();
+        }
         // One packet per call, or None at end of stream. No loop here: both match
         // arms returned, so a `loop` never actually iterated (clippy::never_loop).
-        self.inner
+        let input = match &mut self.inner {
+            Source::Demuxer(input) => input,
+            Source::Push(source) => return source.recv(),
+        };
+        if self.range.as_ref().is_some_and(|range| range.ended) {
+            return None;
+        }
+        let (packet, time_base) = input
             .packets()
             .next()
-            .map(|(stream, packet)| (packet, stream.time_base()).into())
+            .map(|(stream, packet)| (packet, stream.time_base()))?;
+        if let Some(range) = self.range.as_mut()
+            && let (Some(end), Some(pts)) = (range.end_us, packet.pts())
+            && pts.rescale(time_base, MICROS) > end
+        {
+            range.ended = true;
+            return None;
+        }
+        if let Some(pacer) = self.pacer.as_mut() {
+            pacer.pace(&packet, time_base);
+        }
+        Some(RawPacket::with_budget(packet, time_base, &self.budget))
+    }
+
+    /// [`Self::read_packet`] for a looping input: at EOF seek back to the
+    /// start and continue with the next pass. `None` only if the file cannot
+    /// be rewound or is empty.
+    fn read_looping_packet(&mut self) -> Option<RawPacket> {
+        let Source::Demuxer(input) = &mut self.inner else {
+            return None;
+        };
+        let mut rewound = false;
+        loop {
+            let next = input
+                .packets()
+                .next()
+                .map(|(stream, packet)| (packet, stream.time_base()));
+            let (mut packet, time_base) = match next {
+                Some(next) => next,
+                None => {
+                    // A pass with no packets would loop forever.
+                    if rewound {
+                        tracing::warn!("input: looping file has no packets");
+                        return None;
+                    }
+                    let container_us = input.duration().max(0);
+                    if let Err(e) = input.seek(0, ..) {
+                        tracing::warn!("input: cannot rewind looping file: {e}");
+                        return None;
+                    }
+                    let state = self.looping.as_mut()?;
+                    state.rewind(container_us);
+                    tracing::debug!("input: looping file, pass {}", state.loops + 1);
+                    rewound = true;
+                    continue;
+                }
+            };
+            let state = self.looping.as_mut()?;
+            state.observe(&packet, time_base);
+            let offset = state.offset(time_base);
+            if offset != 0 {
+                packet.set_pts(packet.pts().map(|pts| pts + offset));
+                packet.set_dts(packet.dts().map(|dts| dts + offset));
+            }
+            if let Some(pacer) = self.pacer.as_mut() {
+                pacer.pace(&packet, time_base);
+            }
+            return Some(RawPacket::with_budget(packet, time_base, &self.budget));
+        }
     }
 }
+
+#[cfg(test)]
+#[path = "input_test.rs"]
+mod input_test;
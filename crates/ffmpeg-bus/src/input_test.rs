@@ -0,0 +1,269 @@
+use std::path::{Path, PathBuf};
+
+use super::*;
+
+/// scripts/test.mp4 at the workspace root: H.264 video and AAC audio.
+fn test_mp4_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../scripts/test.mp4")
+}
+
+/// Every packet `rx` gets until the EOF, by stream index.
+async fn drain(mut rx: RawPacketReceiver) -> Vec<usize> {
+    let mut indices = Vec::new();
+    loop {
+        match rx.recv().await.expect("no packet lost, no early close") {
+            RawPacketCmd::Data(packet) => indices.push(packet.index()),
+            RawPacketCmd::Reconnected => {}
+            RawPacketCmd::EOF => return indices,
+        }
+    }
+}
+
+#[tokio::test]
+async fn audio_subscriber_never_sees_video() {
+    crate::init().unwrap();
+    let input = AvInput::new(test_mp4_path().to_str().unwrap(), None, None).unwrap();
+    let stream = |audio: bool| {
+        input
+            .streams()
+            .values()
+            .find(|s| s.is_audio() == audio)
+            .unwrap()
+            .index()
+    };
+    let (audio, video) = (stream(true), stream(false));
+
+    // Room for the whole file, so no subscriber lags.
+    let task = AvInputTask::with_capacity(1 << 14);
+    let audio_rx = task.subscribe_stream(audio);
+    let video_rx = task.subscribe_stream(video);
+    let all_rx = task.subscribe();
+    task.start(input).await;
+    let (audio_seen, video_seen, all_seen) =
+        tokio::join!(drain(audio_rx), drain(video_rx), drain(all_rx));
+    task.stop();
+
+    assert!(!audio_seen.is_empty());
+    assert!(audio_seen.iter().all(|i| *i == audio));
+    assert!(video_seen.iter().all(|i| *i == video));
+    // Between them the two see every packet, each exactly once.
+    let of = |index| all_seen.iter().filter(|i| **i == index).count();
+    assert_eq!(audio_seen.len(), of(audio));
+    assert_eq!(video_seen.len(), of(video));
+    assert_eq!(task.stats().lagged_packets, 0);
+}
+
+#[tokio::test]
+async fn every_stream_channel_gets_the_eof() {
+    crate::init().unwrap();
+    let input = AvInput::new(test_mp4_path().to_str().unwrap(), None, None).unwrap();
+    let task = AvInputTask::new();
+    // The file has no stream 7: its channel carries the EOF alone.
+    let missing = task.subscribe_stream(7);
+    let streams: Vec<_> = input
+        .streams()
+        .keys()
+        .map(|i| task.subscribe_stream(*i))
+        .collect();
+    task.start(input).await;
+    assert!(drain(missing).await.is_empty());
+    for rx in streams {
+        assert!(!drain(rx).await.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn a_stopped_input_still_sends_the_eof() {
+    crate::init().unwrap();
+    // Looped in real time: it would never end on its own.
+    let input = AvInput::new(test_mp4_path().to_str().unwrap(), None, None)
+        .unwrap()
+        .looping(true);
+    let stage = crate::teardown::StageTasks::default();
+    let task = AvInputTask::new().with_stage(&stage);
+    let mut rx = task.subscribe();
+    task.start(input).await;
+    assert!(matches!(rx.recv().await, Ok(RawPacketCmd::Data(_))));
+    assert_eq!(stage.running(), 1);
+
+    stage.force();
+    let seen = tokio::time::timeout(Duration::from_secs(5), drain(rx))
+        .await
+        .expect("no EOF after the stop");
+    assert!(seen.len() < 1000, "{} packets after the stop", seen.len());
+    tokio::time::timeout(Duration::from_secs(5), stage.finished())
+        .await
+        .expect("the reader still counts as running");
+}
+
+#[tokio::test]
+async fn a_paused_file_sends_nothing_until_resumed() {
+    crate::init().unwrap();
+    let spec = crate::fixture::FixtureSpec::default().video_only();
+    let path = crate::fixture::ensure_fixture(&spec).await.unwrap();
+    let path = path.to_str().unwrap();
+    let mut whole = AvInput::new(path, None, None).unwrap();
+    let total = std::iter::from_fn(|| whole.read_packet()).count();
+
+    let input = AvInput::new(path, None, None).unwrap().playback();
+    let task = AvInputTask::new();
+    // Four times its pace, 1.25 s for the 5 s file: still playing when
+    // paused.
+    task.set_rate(4.0);
+    let mut rx = task.subscribe();
+    task.start(input).await;
+    let mut seen = 0;
+    while seen < 10 {
+        match rx.recv().await.unwrap() {
+            RawPacketCmd::Data(_) => seen += 1,
+            RawPacketCmd::Reconnected => {}
+            RawPacketCmd::EOF => panic!("the file ended before the pause"),
+        }
+    }
+
+    task.pause();
+    let paused = PlaybackState {
+        paused: true,
+        rate: Some(4.0),
+    };
+    assert_eq!(task.playback(), paused);
+    // The packet the reader was holding back may still come.
+    while let Ok(cmd) = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await {
+        assert!(
+            matches!(cmd, Ok(RawPacketCmd::Data(_))),
+            "ended while paused"
+        );
+        seen += 1;
+    }
+    let waited = tokio::time::timeout(Duration::from_millis(500), rx.recv()).await;
+    assert!(waited.is_err(), "a packet while paused");
+
+    task.resume();
+    assert!(!task.playback().paused);
+    let rest = tokio::time::timeout(Duration::from_secs(10), drain(rx))
+        .await
+        .expect("no EOF after the resume");
+    assert_eq!(seen + rest.len(), total);
+}
+
+#[test]
+fn a_rate_only_paces_file_inputs() {
+    crate::init().unwrap();
+    let path = test_mp4_path();
+    let mut live = AvInput::new(path.to_str().unwrap(), None, None).unwrap();
+    assert!(!live.set_rate(Some(2.0)));
+    let mut file = AvInput::new(path.to_str().unwrap(), None, None)
+        .unwrap()
+        .playback();
+    assert!(file.set_rate(Some(2.0)));
+    assert!(file.set_rate(None));
+    let mut looping = AvInput::new(path.to_str().unwrap(), None, None)
+        .unwrap()
+        .looping(false);
+    assert!(looping.set_rate(Some(0.5)));
+}
+
+#[test]
+fn reader_input_demuxes_a_byte_stream() {
+    crate::init().unwrap();
+    let ts = std::env::temp_dir().join(format!("reader-input-{}.ts", std::process::id()));
+    let summary = crate::remux::remux_file(
+        &test_mp4_path(),
+        &ts,
+        &crate::remux::RemuxOptions::default(),
+    )
+    .unwrap();
+    // Unseekable, like a live stream.
+    let reader = std::io::BufReader::new(std::fs::File::open(&ts).unwrap());
+    let mut input = AvInput::from_reader(Box::new(reader), "mpegts", None).unwrap();
+    assert_eq!(input.streams().len(), summary.streams);
+    assert!(input.streams().values().any(|s| s.is_video()));
+
+    let mut packets = 0;
+    while input.read_packet().is_some() {
+        packets += 1;
+    }
+    // To the end: the video alone is most of what was written.
+    assert!(packets >= summary.packets.iter().max().copied().unwrap());
+    drop(input);
+    std::fs::remove_file(&ts).unwrap();
+
+    assert!(AvInput::from_reader(Box::new(std::io::empty()), "no-such-format", None).is_err());
+}
+
+#[tokio::test]
+async fn a_dropped_input_reconnects_without_an_eof() {
+    crate::init().unwrap();
+    let path = test_mp4_path().to_string_lossy().into_owned();
+    let input = AvInput::new(&path, None, None).unwrap();
+    // The first attempt fails, the second gets the file again; after that
+    // every attempt fails.
+    let mut opened = 0;
+    let reopen: InputFactory = Box::new(move || {
+        opened += 1;
+        match opened {
+            2 => AvInput::new(&path, None, None),
+            _ => anyhow::bail!("connection refused"),
+        }
+    });
+    let retry = RetryPolicy {
+        max_attempts: 2,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(20),
+    };
+
+    let task = AvInputTask::with_capacity(1 << 15);
+    let mut rx = task.subscribe();
+    task.start_with_retry(input, reopen, retry).await;
+    let (mut reconnects, mut last_dts) = (0, HashMap::new());
+    loop {
+        match rx.recv().await.expect("no packet lost, no early close") {
+            RawPacketCmd::Data(packet) => {
+                let dts = packet.packet().dts().unwrap();
+                if let Some(last) = last_dts.insert(packet.index(), dts) {
+                    assert!(dts > last, "stream {}: {dts} after {last}", packet.index());
+                }
+            }
+            RawPacketCmd::Reconnected => reconnects += 1,
+            RawPacketCmd::EOF => break,
+        }
+    }
+    task.stop();
+
+    assert_eq!(reconnects, 1);
+    assert_eq!(task.stats().reconnects, 1);
+}
+
+#[test]
+fn a_range_input_seeks_to_a_keyframe_and_ends_past_its_end() {
+    crate::init().unwrap();
+    let (start, end) = (Duration::from_secs(2), Duration::from_secs(4));
+    let mut input = AvInput::new(&test_mp4_path().to_string_lossy(), None, None)
+        .unwrap()
+        .range(Some(start), Some(end))
+        .unwrap();
+    let start_us = input.range_start_micros().unwrap();
+    let end_us = start_us + (end - start).as_micros() as i64;
+    let video = input
+        .streams()
+        .values()
+        .find(|s| s.is_video())
+        .unwrap()
+        .index();
+
+    let mut video_pts = Vec::new();
+    let mut first_key = None;
+    while let Some(packet) = input.read_packet() {
+        let pts = packet.pts().unwrap().rescale(packet.time_base(), MICROS);
+        assert!(pts <= end_us, "{pts} past the end");
+        if packet.index() == video {
+            first_key.get_or_insert(packet.packet().is_key());
+            video_pts.push(pts);
+        }
+    }
+    assert_eq!(first_key, Some(true), "the video starts on a keyframe");
+    assert!(video_pts.iter().min().unwrap() <= &start_us);
+    assert!(video_pts.iter().any(|pts| *pts >= start_us));
+    // Ended, and stays so.
+    assert!(input.read_packet().is_none());
+}
@@ -1,23 +1,66 @@
 #![allow(dead_code)]
 
-/// Registers FFmpeg components (format, device, etc.). Call once at startup
-/// before using device inputs like x11grab or v4l2.
+use std::sync::OnceLock;
+
+pub use av_log::LogConfig;
+pub use remux::{RemuxJob, RemuxOptions, RemuxSummary, remux_file};
+
+static INIT: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// Registers FFmpeg components (format, device, etc.) and forwards FFmpeg's
+/// log output into the `log` crate with the default [`LogConfig`]. Call at
+/// startup before using device inputs like x11grab or v4l2.
 pub fn init() -> anyhow::Result<()> {
-    ffmpeg_next::init().map_err(|e| anyhow::anyhow!("ffmpeg_next init: {}", e))
+    init_with(LogConfig::default())
+}
+
+/// Like [`init`] with an explicit log config. Idempotent and safe to call
+/// from several threads: only the first call does the work (and picks the
+/// config); later calls return its result.
+pub fn init_with(config: LogConfig) -> anyhow::Result<()> {
+    INIT.get_or_init(|| {
+        ffmpeg_next::init().map_err(|e| format!("ffmpeg_next init: {}", e))?;
+        av_log::install(config);
+        Ok(())
+    })
+    .clone()
+    .map_err(anyhow::Error::msg)
 }
 
+pub mod audio_gap;
 pub mod audio_mixer;
+pub mod av_log;
 pub mod bsf;
 pub mod bus;
+pub mod clock;
+pub mod container;
 pub mod decoder;
 pub mod device;
 pub mod encoder;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fixture;
 pub mod frame;
+pub mod frame_hub;
+pub mod gop;
+pub mod hook;
 pub mod hw;
 pub mod input;
+pub mod memory;
 pub mod metadata;
 pub mod output;
 pub mod packet;
+pub mod pipeline;
+pub mod push;
+pub mod remux;
 pub mod scaler;
+pub mod segment;
 pub mod sink;
+pub mod snapshot;
+pub mod stats;
+pub mod storyboard;
 pub mod stream;
+pub mod teardown;
+pub mod thumbnail;
+pub mod watchdog;
+pub mod worker;
+pub mod write_error;
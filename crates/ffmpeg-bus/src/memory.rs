@@ -0,0 +1,217 @@
+//! Byte accounting of the media in flight, with backpressure at the source.
+//!
+//! Channels are bounded in messages, but a 4K keyframe and an audio packet
+//! differ by orders of magnitude, so a channel's capacity says little about
+//! its memory. Every [`RawPacket`](crate::packet::RawPacket),
+//! [`RawFrame`](crate::frame::RawFrame) and
+//! [`OutputMessage`](crate::output::OutputMessage) is charged to a
+//! [`MemoryBudget`] when it is created and released when its last clone is
+//! dropped: one atomic add and one atomic sub per message. An input whose
+//! budget is exceeded stops reading until usage falls under the budget's
+//! low-water mark, so a slow consumer holds the source back instead of
+//! growing memory without bound.
+//!
+//! Everything is charged to [`MemoryBudget::global`] (unlimited until
+//! [`MemoryBudget::set_limit`] is called), so all buses of a process share it
+//! and one slow consumer pauses every input.
+//! [`AvInput::with_budget`](crate::input::AvInput::with_budget) gives an
+//! input's packets a budget of their own. Pick a limit well above what outputs
+//! hold by design (a lazy `Net` output buffers a GOP while it connects): data
+//! that is only released once more is read would hold its input paused for
+//! good.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use tokio_util::sync::CancellationToken;
+
+/// A limit that is never reached.
+pub const UNLIMITED: usize = usize::MAX;
+
+/// How often a paused input re-checks usage.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Capacity of the [`BudgetEvent`] broadcast.
+const EVENT_CAPACITY: usize = 64;
+
+static GLOBAL: LazyLock<Arc<MemoryBudget>> = LazyLock::new(|| MemoryBudget::new(UNLIMITED));
+
+/// A pause of an input for memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BudgetEvent {
+    /// An input stopped reading with `used` bytes held against `limit`.
+    Paused { used: usize, limit: usize },
+    /// It read again after `waited`.
+    Resumed { waited: Duration },
+}
+
+/// Bytes held by the messages charged to it, against a limit.
+pub struct MemoryBudget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+    pauses: AtomicU64,
+    paused_us: AtomicU64,
+    events: tokio::sync::broadcast::Sender<BudgetEvent>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Arc<Self> {
+        let (events, _) = tokio::sync::broadcast::channel(EVENT_CAPACITY);
+        Arc::new(Self {
+            limit: AtomicUsize::new(limit),
+            used: AtomicUsize::new(0),
+            pauses: AtomicU64::new(0),
+            paused_us: AtomicU64::new(0),
+            events,
+        })
+    }
+
+    /// The process-wide budget every message is charged to by default.
+    pub fn global() -> &'static Arc<MemoryBudget> {
+        &GLOBAL
+    }
+
+    /// Change the limit; paused inputs see it at their next check.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Where a paused input resumes: three quarters of the limit.
+    pub fn low_water(&self) -> usize {
+        let limit = self.limit();
+        limit - limit / 4
+    }
+
+    /// Bytes held right now.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.used() > self.limit()
+    }
+
+    /// Times an input paused for this budget.
+    pub fn pauses(&self) -> u64 {
+        self.pauses.load(Ordering::Relaxed)
+    }
+
+    /// Total time inputs spent paused.
+    pub fn paused_time(&self) -> Duration {
+        Duration::from_micros(self.paused_us.load(Ordering::Relaxed))
+    }
+
+    pub fn events(&self) -> tokio::sync::broadcast::Receiver<BudgetEvent> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn charge(self: &Arc<Self>, bytes: usize) -> Charge {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+        Charge {
+            budget: Arc::clone(self),
+            bytes,
+        }
+    }
+
+    /// If the budget is exceeded, block until usage falls to the low-water
+    /// mark. False if `cancel` fired first.
+    pub(crate) fn wait_for_room(&self, cancel: &CancellationToken) -> bool {
+        if !self.is_exceeded() {
+            return true;
+        }
+        let (used, limit) = (self.used(), self.limit());
+        self.pauses.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!("memory budget: {used} of {limit} bytes held, pausing input");
+        let _ = self.events.send(BudgetEvent::Paused { used, limit });
+        let started = Instant::now();
+        while self.used() > self.low_water() && !cancel.is_cancelled() {
+            std::thread::sleep(POLL);
+        }
+        let waited = started.elapsed();
+        self.paused_us
+            .fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+        if cancel.is_cancelled() {
+            return false;
+        }
+        tracing::info!("memory budget: input resumed after {waited:?}");
+        let _ = self.events.send(BudgetEvent::Resumed { waited });
+        true
+    }
+}
+
+/// `bytes` held against a budget until dropped. A clone is a copy of the
+/// data, charged again.
+pub(crate) struct Charge {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+impl Clone for Charge {
+    fn clone(&self) -> Self {
+        self.budget.charge(self.bytes)
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// A value charged to a budget for as long as it lives. Messages keep it
+/// behind their `Arc`, so clones of a message share one charge.
+#[derive(Clone)]
+pub(crate) struct Charged<T> {
+    value: T,
+    charge: Charge,
+}
+
+impl<T> Charged<T> {
+    pub(crate) fn new(value: T, bytes: usize, budget: &Arc<MemoryBudget>) -> Self {
+        Self {
+            value,
+            charge: budget.charge(bytes),
+        }
+    }
+
+    /// Charged to the global budget.
+    pub(crate) fn global(value: T, bytes: usize) -> Self {
+        Self::new(value, bytes, MemoryBudget::global())
+    }
+}
+
+impl<T> Deref for Charged<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Charged<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+/// Bytes of the data buffers of a decoded frame.
+pub(crate) fn frame_bytes(frame: &ffmpeg_next::Frame) -> usize {
+    unsafe {
+        (*frame.as_ptr())
+            .buf
+            .iter()
+            .filter(|buf| !buf.is_null())
+            .map(|buf| (**buf).size)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+#[path = "memory_test.rs"]
+mod memory_test;
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+use crate::input::{AvInput, AvInputTask};
+use crate::packet::RawPacketCmd;
+
+#[test]
+fn charges_follow_the_last_clone() {
+    let budget = MemoryBudget::new(1000);
+    let a = Charged::new(7u8, 600, &budget);
+    let b = std::sync::Arc::new(a);
+    let shared = b.clone();
+    assert_eq!(budget.used(), 600);
+    assert!(!budget.is_exceeded());
+    // A copy of the data is a second allocation.
+    let copy = Charged::clone(&shared);
+    assert_eq!(*copy, 7);
+    assert_eq!(budget.used(), 1200);
+    assert!(budget.is_exceeded());
+    drop(copy);
+    drop(b);
+    assert_eq!(budget.used(), 600);
+    drop(shared);
+    assert_eq!(budget.used(), 0);
+}
+
+#[test]
+fn waits_for_the_low_water_mark() {
+    let budget = MemoryBudget::new(1000);
+    assert_eq!(budget.low_water(), 750);
+    let cancel = CancellationToken::new();
+    // Under the limit: no pause.
+    let held = Charged::new((), 1000, &budget);
+    assert!(budget.wait_for_room(&cancel));
+    assert_eq!(budget.pauses(), 0);
+
+    let mut events = budget.events();
+    let extra = Charged::new((), 100, &budget);
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        // 900 held: still above the low-water mark.
+        drop(extra);
+        std::thread::sleep(Duration::from_millis(50));
+        drop(held);
+    });
+    assert!(budget.wait_for_room(&cancel));
+    release.join().unwrap();
+    assert_eq!(budget.pauses(), 1);
+    assert!(budget.paused_time() >= Duration::from_millis(90));
+    assert_eq!(
+        events.try_recv().unwrap(),
+        BudgetEvent::Paused {
+            used: 1100,
+            limit: 1000
+        }
+    );
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        BudgetEvent::Resumed { waited } if waited >= Duration::from_millis(90)
+    ));
+
+    // A cancelled wait gives up.
+    let _held = Charged::new((), 2000, &budget);
+    cancel.cancel();
+    assert!(!budget.wait_for_room(&cancel));
+}
+
+/// Read `path` under `budget` with a consumer that takes 2 ms per packet.
+/// Returns the most memory held at any packet and the largest packet.
+async fn read_slowly(path: &str, budget: &Arc<MemoryBudget>) -> (usize, usize) {
+    let input = AvInput::new(path, None, None)
+        .unwrap()
+        .with_budget(budget.clone());
+    let task = AvInputTask::new();
+    let mut packets = task.subscribe();
+    task.start(input).await;
+    let (mut peak, mut largest) = (0, 0);
+    loop {
+        match packets.recv().await.unwrap() {
+            RawPacketCmd::Data(packet) => {
+                peak = peak.max(budget.used());
+                largest = largest.max(packet.size());
+                drop(packet);
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+            RawPacketCmd::Reconnected => {}
+            RawPacketCmd::EOF => break,
+        }
+    }
+    task.stop();
+    (peak, largest)
+}
+
+#[tokio::test]
+async fn slow_consumer_pauses_the_input() {
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let file_len = std::fs::metadata(&path).unwrap().len() as usize;
+    let path = path.to_str().unwrap();
+
+    // Unbounded, the input reads the whole file ahead of the consumer.
+    let unbounded = MemoryBudget::new(UNLIMITED);
+    let (peak, _) = read_slowly(path, &unbounded).await;
+    assert!(peak > file_len / 2, "{peak} of {file_len}");
+    assert_eq!(unbounded.pauses(), 0);
+
+    let limit = file_len / 8;
+    let budget = MemoryBudget::new(limit);
+    let mut events = budget.events();
+    let (peak, largest) = read_slowly(path, &budget).await;
+    // Reading stops once over the limit, so at most one packet more is held.
+    assert!(peak <= limit + largest, "{peak} > {limit} + {largest}");
+    assert!(budget.pauses() > 0);
+    assert!(budget.paused_time() > Duration::ZERO);
+    assert!(matches!(events.try_recv(), Ok(BudgetEvent::Paused { .. })));
+    // Every packet was released.
+    assert_eq!(budget.used(), 0);
+}
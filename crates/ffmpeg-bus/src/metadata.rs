@@ -1,11 +1,17 @@
 //! Media file metadata (similar to ffprobe).
 
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void};
 use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use serde::Serialize;
 
 use crate::stream::AvStream;
 
 /// Format-level info (corresponds to ffprobe format).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FormatInfo {
     /// Format name, e.g. "mov,mp4,m4a,3gp,3g2,mj2"
     pub format_name: String,
@@ -18,7 +24,7 @@ pub struct FormatInfo {
 }
 
 /// Per-stream info (corresponds to ffprobe stream).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct StreamInfo {
     /// Stream index.
     pub index: usize,
@@ -40,10 +46,15 @@ pub struct StreamInfo {
     pub sample_rate: Option<u32>,
     /// Audio only: channel count.
     pub channels: Option<u32>,
+    /// Video only: degrees clockwise a player turns the picture (display
+    /// matrix), 0 when upright.
+    pub rotation: Option<u32>,
+    /// The `language` tag, e.g. "eng".
+    pub language: Option<String>,
 }
 
 /// Full probe result (format + streams, like ffprobe).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MediaInfo {
     pub format: FormatInfo,
     pub streams: Vec<StreamInfo>,
@@ -83,6 +94,12 @@ impl fmt::Display for MediaInfo {
             if let Some(c) = s.channels {
                 writeln!(f, "channels={}", c)?;
             }
+            if let Some(r) = s.rotation {
+                writeln!(f, "rotation={}", r)?;
+            }
+            if let Some(l) = &s.language {
+                writeln!(f, "language={}", l)?;
+            }
             writeln!(f, "[/STREAM]")?;
         }
         Ok(())
@@ -100,7 +117,154 @@ impl fmt::Display for MediaInfo {
 /// ```
 pub fn probe(path: &str) -> anyhow::Result<MediaInfo> {
     let input = ffmpeg_next::format::input(path)?;
+    probe_input(&input)
+}
+
+/// [`probe_with_options`] gave up on an input that did not answer in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeTimedOut(pub Duration);
+
+impl fmt::Display for ProbeTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "probe timed out after {:?}", self.0)
+    }
+}
 
+impl std::error::Error for ProbeTimedOut {}
+
+/// Like [`probe`], for network URLs too: opens `url` with the demuxer
+/// `options` and gives up after `timeout` with a [`ProbeTimedOut`].
+///
+/// Unless `options` say otherwise, RTSP is read over TCP, and the socket
+/// timeouts of RTSP (`stimeout`, `timeout`) and of other protocols
+/// (`timeout`, `rw_timeout`) are set to `timeout`, so the blocking open
+/// left behind on a timeout ends soon after too.
+pub async fn probe_with_options(
+    url: &str,
+    options: Option<HashMap<String, String>>,
+    timeout: Duration,
+) -> anyhow::Result<MediaInfo> {
+    let mut options = options.unwrap_or_default();
+    for (key, value) in network_defaults(url, timeout) {
+        options.entry(key.to_string()).or_insert(value);
+    }
+    let url = url.to_string();
+    let open = tokio::task::spawn_blocking(move || {
+        let mut dict = ffmpeg_next::Dictionary::new();
+        for (key, value) in &options {
+            dict.set(key, value);
+        }
+        let input = ffmpeg_next::format::input_with_dictionary(&url, dict)?;
+        probe_input(&input)
+    });
+    match tokio::time::timeout(timeout, open).await {
+        Ok(probed) => probed?,
+        Err(_) => Err(ProbeTimedOut(timeout).into()),
+    }
+}
+
+/// The demuxer options [`probe_with_options`] sets for `url` by default.
+fn network_defaults(url: &str, timeout: Duration) -> Vec<(&'static str, String)> {
+    let micros = timeout.as_micros().to_string();
+    let scheme = url.split_once("://").map_or("", |(scheme, _)| scheme);
+    match scheme.to_ascii_lowercase().as_str() {
+        // `stimeout` for FFmpeg before 5, which renamed it `timeout`.
+        "rtsp" | "rtsps" => vec![
+            ("rtsp_transport", "tcp".to_string()),
+            ("stimeout", micros.clone()),
+            ("timeout", micros),
+        ],
+        "http" | "https" => vec![("timeout", micros.clone()), ("rw_timeout", micros)],
+        "" | "file" => Vec::new(),
+        _ => vec![("rw_timeout", micros)],
+    }
+}
+
+/// Size of the buffer FFmpeg reads a [`probe_reader`] source through.
+pub(crate) const READER_BUFFER_SIZE: usize = 64 * 1024;
+/// `whence` flags of an AVIO seek: report the size, seek even if costly.
+const AVSEEK_SIZE: c_int = 0x10000;
+const AVSEEK_FORCE: c_int = 0x20000;
+
+/// Like [`probe`], over media FFmpeg cannot open by name: `reader` is read
+/// (and seeked) through a custom IO context, e.g. a decrypting reader.
+pub fn probe_reader<R: Read + Seek>(mut reader: R) -> anyhow::Result<MediaInfo> {
+    use ffmpeg_next::ffi;
+
+    unsafe {
+        let buffer = ffi::av_malloc(READER_BUFFER_SIZE) as *mut u8;
+        let mut io = ffi::avio_alloc_context(
+            buffer,
+            READER_BUFFER_SIZE as c_int,
+            // Read only.
+            0,
+            &mut reader as *mut R as *mut c_void,
+            Some(read_reader::<R>),
+            None,
+            Some(seek_reader::<R>),
+        );
+        let mut ctx = ffi::avformat_alloc_context();
+        (*ctx).pb = io;
+        (*ctx).flags |= ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+        // Frees `ctx` (not `io`) on failure.
+        let opened = ffi::avformat_open_input(
+            &mut ctx,
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null_mut(),
+        );
+        let result = if opened < 0 {
+            Err(anyhow::Error::from(ffmpeg_next::Error::from(opened)))
+        } else {
+            // Closing the input leaves a custom `pb` alone.
+            let mut input = ffmpeg_next::format::context::Input::wrap(ctx);
+            match ffi::avformat_find_stream_info(input.as_mut_ptr(), std::ptr::null_mut()) {
+                found if found < 0 => Err(ffmpeg_next::Error::from(found).into()),
+                _ => probe_input(&input),
+            }
+        };
+        // FFmpeg may have swapped the buffer for one of its own.
+        ffi::av_freep(&mut (*io).buffer as *mut *mut u8 as *mut c_void);
+        ffi::avio_context_free(&mut io);
+        result
+    }
+}
+
+pub(crate) unsafe extern "C" fn read_reader<R: Read>(
+    opaque: *mut c_void,
+    buf: *mut u8,
+    size: c_int,
+) -> c_int {
+    // SAFETY: `opaque` is the reader `probe_reader` (or `AvInput::from_reader`)
+    // holds for as long as the IO context lives.
+    let reader = unsafe { &mut *(opaque as *mut R) };
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf, size.max(0) as usize) };
+    match reader.read(buf) {
+        Ok(0) => ffmpeg_next::ffi::AVERROR_EOF,
+        Ok(read) => read as c_int,
+        Err(_) => ffmpeg_next::ffi::AVERROR_EXTERNAL,
+    }
+}
+
+unsafe extern "C" fn seek_reader<R: Seek>(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    // SAFETY: as in `read_reader`.
+    let reader = unsafe { &mut *(opaque as *mut R) };
+    let sought = match whence & !AVSEEK_FORCE {
+        AVSEEK_SIZE => reader.stream_position().and_then(|at| {
+            let size = reader.seek(SeekFrom::End(0))?;
+            reader.seek(SeekFrom::Start(at))?;
+            Ok(size)
+        }),
+        0 => reader.seek(SeekFrom::Start(offset.max(0) as u64)),
+        1 => reader.seek(SeekFrom::Current(offset)),
+        2 => reader.seek(SeekFrom::End(offset)),
+        _ => return -1,
+    };
+    sought.map_or(ffmpeg_next::ffi::AVERROR_EXTERNAL as i64, |at| at as i64)
+}
+
+/// The [`MediaInfo`] of an opened input.
+fn probe_input(input: &ffmpeg_next::format::context::Input) -> anyhow::Result<MediaInfo> {
     let format_name = input.format().name().to_string();
     let nb_streams = input.nb_streams();
     let bit_rate = input.bit_rate();
@@ -156,6 +320,8 @@ pub fn probe(path: &str) -> anyhow::Result<MediaInfo> {
             height,
             sample_rate,
             channels,
+            rotation: av_stream.is_video().then(|| av_stream.rotation_degrees()),
+            language: av_stream.language().map(str::to_string),
         });
     }
 
@@ -189,3 +355,7 @@ fn audio_params_from_parameters(params: &ffmpeg_next::codec::Parameters) -> (u32
         (sr.max(0) as u32, ch.max(0) as u32)
     }
 }
+
+#[cfg(test)]
+#[path = "metadata_test.rs"]
+mod metadata_test;
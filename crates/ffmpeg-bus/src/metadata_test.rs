@@ -0,0 +1,70 @@
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+
+#[tokio::test]
+async fn probe_reader_matches_probe() -> anyhow::Result<()> {
+    crate::init()?;
+    let path = ensure_fixture(&FixtureSpec::default()).await?;
+    let by_path = probe(&path.to_string_lossy())?;
+    let by_reader = probe_reader(std::io::BufReader::new(std::fs::File::open(&path)?))?;
+
+    assert_eq!(by_reader.format.format_name, by_path.format.format_name);
+    assert_eq!(by_reader.format.duration_sec, by_path.format.duration_sec);
+    assert_eq!(by_reader.streams.len(), by_path.streams.len());
+    for (reader, path) in by_reader.streams.iter().zip(&by_path.streams) {
+        assert_eq!(reader.codec_name, path.codec_name);
+        assert_eq!(reader.width, path.width);
+        assert_eq!(reader.sample_rate, path.sample_rate);
+    }
+    Ok(())
+}
+
+#[test]
+fn probe_reader_fails_on_an_empty_reader() {
+    crate::init().unwrap();
+    assert!(probe_reader(std::io::Cursor::new(Vec::new())).is_err());
+}
+
+#[tokio::test]
+async fn probe_with_options_reads_a_file() -> anyhow::Result<()> {
+    crate::init()?;
+    let path = ensure_fixture(&FixtureSpec::default()).await?;
+    let path = path.to_string_lossy();
+    let info = probe_with_options(&path, None, Duration::from_secs(10)).await?;
+    assert_eq!(info.streams.len(), probe(&path)?.streams.len());
+    Ok(())
+}
+
+#[tokio::test]
+async fn probe_with_options_gives_up_on_a_silent_server() -> anyhow::Result<()> {
+    crate::init()?;
+    // Accepts the connection and never answers the RTSP handshake.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("rtsp://{}/stream", listener.local_addr()?);
+    let started = std::time::Instant::now();
+    let err = probe_with_options(&url, None, Duration::from_millis(500))
+        .await
+        .unwrap_err();
+    assert!(
+        started.elapsed() < Duration::from_secs(3),
+        "{:?}",
+        started.elapsed()
+    );
+    // FFmpeg's own socket timeout may fire first.
+    assert!(
+        err.is::<ProbeTimedOut>() || err.is::<ffmpeg_next::Error>(),
+        "{err:?}"
+    );
+    drop(listener);
+    Ok(())
+}
+
+#[test]
+fn rtsp_urls_default_to_tcp_with_socket_timeouts() {
+    let defaults = network_defaults("RTSP://cam/main", Duration::from_secs(2));
+    assert!(defaults.contains(&("rtsp_transport", "tcp".to_string())));
+    assert!(defaults.contains(&("timeout", "2000000".to_string())));
+    assert!(network_defaults("/var/media/a.mp4", Duration::from_secs(2)).is_empty());
+    let http = network_defaults("https://cam/live.flv", Duration::from_secs(2));
+    assert!(http.contains(&("rw_timeout", "2000000".to_string())));
+}
@@ -1,20 +1,100 @@
-use std::{collections::HashMap, pin::Pin};
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use futures::Stream;
 
-use crate::{packet::RawPacket, stream::AvStream};
+use crate::{
+    bsf::{get_extradata, in_band_parameter_sets},
+    container,
+    hook::{self, PacketHook},
+    memory::{Charge, MemoryBudget},
+    packet::RawPacket,
+    stream::AvStream,
+    write_error::WriteError,
+};
 use bytes::Bytes;
 use ffmpeg_next::{
     Dictionary, Rational,
+    codec::Id,
     ffi::{
-        AV_OPT_SEARCH_CHILDREN, AVIOContext, av_free, av_malloc, av_opt_set,
-        avformat_alloc_output_context2, avio_alloc_context, avio_flush,
+        AV_OPT_SEARCH_CHILDREN, AVIO_FLAG_WRITE, AVIOContext, AVIOInterruptCB, av_free, av_malloc,
+        av_opt_set, av_write_frame, avformat_alloc_output_context2, avio_alloc_context, avio_flush,
+        avio_open2,
     },
     format::context::Output,
     media::Type as MediaType,
 };
 use std::ffi::CString;
 
+/// Flush interval of streaming outputs (network, HLS, in-memory mux). Muxers
+/// such as mp4 otherwise hold data until a fragment or their buffer fills,
+/// which delays live viewers by up to a GOP.
+pub const STREAMING_FLUSH_EVERY: Duration = Duration::from_millis(200);
+
+/// When muxed data is pushed out: after every keyframe and at least every
+/// `every`. Off (`every == None`) leaves it to the muxer, which suits files.
+#[derive(Default)]
+struct Flusher {
+    every: Option<Duration>,
+    last: Option<Instant>,
+}
+
+impl Flusher {
+    fn new(every: Option<Duration>) -> Self {
+        Self { every, last: None }
+    }
+
+    /// Whether to flush after writing a packet (`is_key`: a video keyframe).
+    fn due(&mut self, is_key: bool) -> bool {
+        let Some(every) = self.every else {
+            return false;
+        };
+        let now = Instant::now();
+        let last = *self.last.get_or_insert(now);
+        if is_key || now.duration_since(last) >= every {
+            self.last = Some(now);
+            return true;
+        }
+        false
+    }
+}
+
+/// Force out what the muxer holds: `av_write_frame(ctx, NULL)` makes muxers
+/// that buffer (fragmented mp4 writes the fragment so far) emit it, then the
+/// avio buffer is flushed. Muxers doing their own I/O have no `pb`.
+fn flush_output(output: &mut Output) {
+    unsafe {
+        let ctx = output.as_mut_ptr();
+        av_write_frame(ctx, std::ptr::null_mut());
+        if !(*ctx).pb.is_null() {
+            avio_flush((*ctx).pb);
+        }
+    }
+}
+
+/// Pass `packet` through the output's hook, if any: the packet to write, or
+/// `None` to skip it. A panicking hook is removed and fails the write.
+fn run_hook(
+    hook: &mut Option<PacketHook>,
+    packet: RawPacket,
+    out_idx: usize,
+) -> Result<Option<RawPacket>, WriteError> {
+    let Some(f) = hook.as_mut() else {
+        return Ok(Some(packet));
+    };
+    hook::apply(f, packet).map_err(|message| {
+        *hook = None;
+        WriteError::hook(format!("packet hook (stream {out_idx}): {message}"))
+    })
+}
+
 pub struct AvOutput {
     inner: Output,
     /// input stream index -> AvStream (for time_base etc.)
@@ -26,24 +106,36 @@ pub struct AvOutput {
     have_written_trailer: bool,
     /// output stream index -> last DTS written (enforce monotonically increasing DTS)
     last_dts: HashMap<usize, i64>,
+    flush: Flusher,
+    hook: Option<PacketHook>,
+    /// Raised to make blocked I/O give up (see
+    /// [`interrupt_flag`](Self::interrupt_flag)). Declared after `inner`, so
+    /// it is dropped after the context reading it.
+    interrupt: Arc<AtomicBool>,
 }
 
-/// Allocate RTSP output context without opening AVIO. The RTSP muxer will open
-/// the URL when write_header() is called (FFmpeg design: do not call avio_open for RTSP).
-fn output_rtsp_alloc_only(url: &str) -> anyhow::Result<Output> {
+/// Allocate an output context without opening AVIO, for muxers that open their
+/// own I/O (AVFMT_NOFILE): RTSP opens the URL in write_header(), HLS writes its
+/// playlist and segment files itself (FFmpeg design: do not call avio_open).
+/// Without `format` it is guessed from the URL.
+fn output_alloc_only(url: &str, format: Option<&str>) -> anyhow::Result<Output> {
     unsafe {
         let mut output_ptr = std::ptr::null_mut();
         let url_c = CString::new(url).map_err(|e| anyhow::anyhow!("url CString: {}", e))?;
-        let format = CString::new("rtsp").unwrap();
+        let format_c = format
+            .map(CString::new)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("format CString: {}", e))?;
         match avformat_alloc_output_context2(
             &mut output_ptr,
             std::ptr::null_mut(),
-            format.as_ptr(),
+            format_c.as_ref().map_or(std::ptr::null(), |f| f.as_ptr()),
             url_c.as_ptr(),
         ) {
             0 => Ok(Output::wrap(output_ptr)),
             e => Err(anyhow::anyhow!(
-                "avformat_alloc_output_context2(rtsp, url={:?}): {}",
+                "avformat_alloc_output_context2({:?}, url={:?}): {}",
+                format,
                 url,
                 e
             )),
@@ -51,25 +143,78 @@ fn output_rtsp_alloc_only(url: &str) -> anyhow::Result<Output> {
     }
 }
 
+/// `AVIOInterruptCB` callback: nonzero once the flag `opaque` points to is
+/// raised.
+unsafe extern "C" fn interrupt_requested(opaque: *mut std::ffi::c_void) -> std::ffi::c_int {
+    let flag = unsafe { &*(opaque as *const AtomicBool) };
+    flag.load(Ordering::Acquire) as std::ffi::c_int
+}
+
+/// Make the blocking I/O `output` opens give up once `flag` is raised.
+/// `flag` must outlive the context.
+fn set_interrupt_callback(output: &mut Output, flag: &Arc<AtomicBool>) {
+    unsafe {
+        (*output.as_mut_ptr()).interrupt_callback = AVIOInterruptCB {
+            callback: Some(interrupt_requested),
+            opaque: Arc::as_ptr(flag) as *mut std::ffi::c_void,
+        };
+    }
+}
+
+/// Allocate an output context and open its AVIO for writing, like
+/// `ffmpeg_next::format::output_as_with` does, but with the interrupt
+/// callback of `interrupt` on both.
+fn output_open(
+    url: &str,
+    format: Option<&str>,
+    options: Option<Dictionary>,
+    interrupt: &Arc<AtomicBool>,
+) -> anyhow::Result<Output> {
+    let mut output = output_alloc_only(url, format)?;
+    set_interrupt_callback(&mut output, interrupt);
+    let url_c = CString::new(url).map_err(|e| anyhow::anyhow!("url CString: {}", e))?;
+    unsafe {
+        let ctx = output.as_mut_ptr();
+        let mut opts = options.map_or(std::ptr::null_mut(), |o| o.disown());
+        let ret = avio_open2(
+            &mut (*ctx).pb,
+            url_c.as_ptr(),
+            AVIO_FLAG_WRITE,
+            &(*ctx).interrupt_callback,
+            &mut opts,
+        );
+        // What the protocol did not take.
+        drop(Dictionary::own(opts));
+        if ret < 0 {
+            anyhow::bail!(
+                "avio_open2(url={:?}): {}",
+                url,
+                ffmpeg_next::Error::from(ret)
+            );
+        }
+    }
+    Ok(output)
+}
+
 impl AvOutput {
     pub fn new(
         url: &str,
         format: Option<&str>,
         options: Option<Dictionary>,
     ) -> anyhow::Result<Self> {
-        let output = match (format, options) {
-            // RTSP: do not call avio_open; muxer opens URL in write_header().
-            (Some("rtsp"), _) => output_rtsp_alloc_only(url)
-                .map_err(|e| anyhow::anyhow!("output_rtsp_alloc_only(url={:?}): {}", url, e))?,
-            (Some(fmt), Some(opts)) => ffmpeg_next::format::output_as_with(url, fmt, opts)
-                .map_err(|e| {
-                    anyhow::anyhow!("output_as_with(url={:?}, format={:?}): {:?}", url, fmt, e)
-                })?,
-            (Some(fmt), None) => ffmpeg_next::format::output_as(url, fmt).map_err(|e| {
-                anyhow::anyhow!("output_as(url={:?}, format={:?}): {:?}", url, fmt, e)
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let output = match format {
+            // RTSP/HLS: do not call avio_open; the muxer does its own I/O,
+            // with the context's interrupt callback.
+            Some(fmt @ ("rtsp" | "hls")) => {
+                let mut output = output_alloc_only(url, Some(fmt))
+                    .map_err(|e| anyhow::anyhow!("output_alloc_only(url={:?}): {}", url, e))?;
+                set_interrupt_callback(&mut output, &interrupt);
+                output
+            }
+            format => output_open(url, format, options, &interrupt).map_err(|e| {
+                anyhow::anyhow!("output_open(url={:?}, format={:?}): {}", url, format, e)
             })?,
-            (None, _) => ffmpeg_next::format::output(url)
-                .map_err(|e| anyhow::anyhow!("output(url={:?}): {:?}", url, e))?,
         };
         Ok(Self {
             inner: output,
@@ -79,19 +224,84 @@ impl AvOutput {
             have_written_header: false,
             have_written_trailer: false,
             last_dts: HashMap::new(),
+            flush: Flusher::default(),
+            hook: None,
+            interrupt,
         })
     }
 
+    /// The flag that, once raised, makes this output's blocked I/O fail with
+    /// `AVERROR_EXIT` instead of waiting on (see [`crate::watchdog`]).
+    /// Network protocols check it; a file write is not interrupted.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    /// Whether the [`interrupt_flag`](Self::interrupt_flag) was raised.
+    pub fn interrupted(&self) -> bool {
+        self.interrupt.load(Ordering::Acquire)
+    }
+
+    /// Run `hook` on every packet before it is written (see [`crate::hook`]).
+    pub fn set_packet_hook(&mut self, hook: Option<PacketHook>) {
+        self.hook = hook;
+    }
+
+    /// Take the packet hook back, e.g. to move it to a reconnected output.
+    pub fn take_packet_hook(&mut self) -> Option<PacketHook> {
+        self.hook.take()
+    }
+
+    /// Write packets through FFmpeg's interleaving queue, which orders the
+    /// streams by DTS, instead of as they come (off by default).
+    pub fn set_interleaved(&mut self, interleaved: bool) {
+        self.interleaved = interleaved;
+    }
+
+    /// Flush the muxer after every keyframe and at least every `every` (off
+    /// by default; see [`STREAMING_FLUSH_EVERY`] for live outputs). Also makes
+    /// the muxer flush its avio buffer after each packet (`flush_packets`).
+    pub fn set_flush_every(&mut self, every: Option<Duration>) -> anyhow::Result<()> {
+        if every.is_some() {
+            set_format_option(&mut self.inner, "flush_packets", "1")?;
+        }
+        self.flush = Flusher::new(every);
+        Ok(())
+    }
+
+    /// Set a muxer private option (e.g. `hls_time`). Must be called before the
+    /// first packet is written, since options are read in write_header().
+    pub fn set_muxer_option(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        set_format_option(&mut self.inner, name, value)
+    }
+
     pub fn add_stream(&mut self, stream: &AvStream) -> anyhow::Result<()> {
         let codec_parameters = stream.parameters();
         let codec_id = codec_parameters.id();
-        let encoder = ffmpeg_next::encoder::find(codec_id)
-            .ok_or_else(|| anyhow::anyhow!("encoder not found for codec_id {:?}", codec_id))?;
+        // Subtitle and data codecs mostly have no encoder: their stream is
+        // created without one and takes just the copied parameters.
+        let encoder =
+            match ffmpeg_next::encoder::find(codec_id) {
+                None if stream.is_data() => None,
+                encoder => Some(encoder.ok_or_else(|| {
+                    anyhow::anyhow!("encoder not found for codec_id {:?}", codec_id)
+                })?),
+            };
+        // The input's tag belongs to its own container (an MKV has none, an
+        // MP4 source `hev1`): use the one this container needs, else let the
+        // muxer pick its default.
+        let tag = match container::container_supports(self.inner.format().name(), codec_id) {
+            container::SupportLevel::WithTag(tag) => container::fourcc(tag),
+            _ => 0,
+        };
         let mut writer_stream = self
             .inner
             .add_stream(encoder)
             .map_err(|e| anyhow::anyhow!("add_stream(codec_id={:?}): {:?}", codec_id, e))?;
+        // With the parameters go their side data, e.g. the display matrix.
         writer_stream.set_parameters(codec_parameters.clone());
+        writer_stream.set_metadata(stream_metadata(stream));
+        unsafe { (*(*writer_stream.as_mut_ptr()).codecpar).codec_tag = tag };
         let out_idx = writer_stream.index();
         self.output_stream_index.insert(stream.index(), out_idx);
         self.output_streams.insert(stream.index(), stream.clone());
@@ -102,20 +312,37 @@ impl AvOutput {
         self.output_streams.get(&stream_index).unwrap().time_base()
     }
 
+    /// Write the container header now rather than with the first packet. For
+    /// muxers doing their own I/O (RTSP) this is when the URL is connected.
+    pub fn write_header(&mut self) -> Result<(), WriteError> {
+        if !self.have_written_header {
+            self.inner
+                .write_header()
+                .map_err(|e| WriteError::from_ffmpeg(e, "write_header"))?;
+            self.have_written_header = true;
+        }
+        Ok(())
+    }
+
     /// Write a packet. `input_stream_index` is the input stream index (packet.stream() from input).
     pub fn write_packet(
         &mut self,
         input_stream_index: usize,
         mut packet: RawPacket,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), WriteError> {
         let out_idx = match self.output_stream_index.get(&input_stream_index) {
             Some(&i) => i,
-            None => return Err(anyhow::anyhow!("stream not found: {}", input_stream_index)),
+            None => {
+                return Err(WriteError::invalid(format!(
+                    "stream not found: {}",
+                    input_stream_index
+                )));
+            }
         };
-        if !self.have_written_header {
-            self.inner.write_header()?;
-            self.have_written_header = true;
+        if !self.have_written_header && !ready_for_header(&mut self.inner, out_idx, &packet) {
+            return Ok(());
         }
+        self.write_header()?;
         let time_base = packet.time_base();
 
         let p = packet.get_mut();
@@ -156,18 +383,36 @@ impl AvOutput {
         }
         self.last_dts.insert(out_idx, new_dts);
 
-        if self.interleaved {
-            p.write_interleaved(&mut self.inner)?;
+        let mut packet = match run_hook(&mut self.hook, packet, out_idx)? {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+        let p = packet.get_mut();
+        let is_key = p.is_key()
+            && self
+                .inner
+                .stream(out_idx)
+                .is_some_and(|stream| stream.parameters().medium() == MediaType::Video);
+        let written = if self.interleaved {
+            p.write_interleaved(&mut self.inner)
         } else {
-            p.write(&mut self.inner)?;
+            p.write(&mut self.inner)
+        };
+        written.map_err(|e| {
+            WriteError::from_ffmpeg(e, format!("write_packet (stream {})", out_idx))
+        })?;
+        if self.flush.due(is_key) {
+            flush_output(&mut self.inner);
         }
         Ok(())
     }
 
-    pub fn finish(&mut self) -> anyhow::Result<()> {
+    pub fn finish(&mut self) -> Result<(), WriteError> {
         if self.have_written_header && !self.have_written_trailer {
             self.have_written_trailer = true;
-            self.inner.write_trailer()?;
+            self.inner
+                .write_trailer()
+                .map_err(|e| WriteError::from_ffmpeg(e, "write_trailer"))?;
         }
         Ok(())
     }
@@ -179,6 +424,9 @@ const MUX_OUTPUT_CHAN_CAP: usize = 256;
 
 pub struct PacketContext {
     buffer: PacketBufferType,
+    /// Muxed bytes the reader had no room for yet, in order; handed over by
+    /// [`AvOutputStreamWriter::deliver`].
+    overflow: VecDeque<OutputMessage>,
     current_pts: Option<i64>,
     current_dts: Option<i64>,
     /// Video only: key frame flag
@@ -197,8 +445,9 @@ pub struct AvOutputStream {
     have_written_trailer: bool,
     context: Box<PacketContext>,
     receiver: tokio::sync::mpsc::Receiver<OutputMessage>,
-    /// Input stream index we're muxing (only one stream supported for now).
-    input_stream_index: Option<usize>,
+    /// Input stream index -> output stream index, as [`AvOutput`] keeps it.
+    output_stream_index: HashMap<usize, usize>,
+    flush: Flusher,
 }
 
 pub type PacketBufferType = tokio::sync::mpsc::Sender<OutputMessage>;
@@ -212,6 +461,8 @@ pub struct OutputMessage {
     pub codec_id: i32,
     pub width: u32,
     pub height: u32,
+    /// Holds `data`'s bytes against the global memory budget.
+    charge: Charge,
 }
 
 /// Writer half of a split `AvOutputStream`. Used to write packets from a separate task.
@@ -220,38 +471,54 @@ pub struct AvOutputStreamWriter {
     have_written_header: bool,
     have_written_trailer: bool,
     context: Box<PacketContext>,
-    /// Input stream index we're muxing (only write packets with this stream index).
-    input_stream_index: Option<usize>,
-    /// Last DTS written (enforce monotonically increasing DTS for muxer).
-    last_dts: Option<i64>,
+    /// Input stream index -> output stream index; packets of other streams
+    /// are skipped.
+    output_stream_index: HashMap<usize, usize>,
+    /// Last DTS written per output stream (enforce monotonically increasing
+    /// DTS for muxer).
+    last_dts: HashMap<usize, i64>,
+    flush: Flusher,
+    hook: Option<PacketHook>,
 }
 
 impl AvOutputStreamWriter {
-    pub fn write_packet(&mut self, mut packet: RawPacket) -> anyhow::Result<()> {
-        let input_stream_index = match self.input_stream_index {
-            Some(idx) => idx,
-            None => return Err(anyhow::anyhow!("no stream added to output")),
-        };
-        if packet.index() != input_stream_index {
-            return Ok(());
+    /// Run `hook` on every packet before it is written (see [`crate::hook`]).
+    pub fn set_packet_hook(&mut self, hook: Option<PacketHook>) {
+        self.hook = hook;
+    }
+
+    pub fn write_packet(&mut self, mut packet: RawPacket) -> Result<(), WriteError> {
+        if self.output_stream_index.is_empty() {
+            return Err(WriteError::invalid("no stream added to output"));
         }
+        let Some(&out_idx) = self.output_stream_index.get(&packet.index()) else {
+            return Ok(());
+        };
 
         if !self.have_written_header {
-            self.inner.write_header()?;
+            if !ready_for_header(&mut self.inner, out_idx, &packet) {
+                return Ok(());
+            }
+            // Tagged like the video packets, so a reader knows the picture
+            // size from the first message.
+            self.tag_video(self.video_stream());
+            let written = self.inner.write_header();
+            self.tag_video(None);
+            written.map_err(|e| WriteError::from_ffmpeg(e, "write_header"))?;
             self.have_written_header = true;
         }
 
         let time_base = packet.time_base();
         let p = packet.get_mut();
-        p.set_stream(0);
+        p.set_stream(out_idx);
         p.set_position(-1);
-        let out_time_base = self.inner.stream(0).unwrap().time_base();
+        let out_time_base = self.inner.stream(out_idx).unwrap().time_base();
         p.rescale_ts(time_base, out_time_base);
 
         // Enforce monotonically increasing DTS (muxer requirement)
         let dts = p.dts().unwrap_or(0);
-        let new_dts = match self.last_dts {
-            Some(last) if dts <= last => last + 1,
+        let new_dts = match self.last_dts.get(&out_idx) {
+            Some(&last) if dts <= last => last + 1,
             _ => dts,
         };
         if new_dts != dts {
@@ -260,46 +527,96 @@ impl AvOutputStreamWriter {
                 p.set_pts(Some(new_dts));
             }
         }
-        self.last_dts = Some(new_dts);
+        self.last_dts.insert(out_idx, new_dts);
 
+        let mut packet = match run_hook(&mut self.hook, packet, out_idx)? {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+        let p = packet.get_mut();
         self.context.current_pts = p.pts();
         self.context.current_dts = p.dts();
         self.context.current_is_key = p.is_key();
-        if let Some(stream) = self.inner.stream(0) {
-            let params = stream.parameters();
-            if params.medium() == MediaType::Video {
-                self.context.current_codec_id = params.id() as i32;
-                let (w, h) = video_size_from_parameters(&params);
-                self.context.current_width = w;
-                self.context.current_height = h;
-            }
-        }
+        self.tag_video(Some(out_idx));
 
-        log::debug!("write_packet: pts={:?}, dts={:?}", p.pts(), p.dts());
-        log::debug!(
+        tracing::debug!("write_packet: pts={:?}, dts={:?}", p.pts(), p.dts());
+        tracing::debug!(
             "write_packet: time_base={:?}, out_time_base={:?}",
             time_base,
             out_time_base
         );
-        p.write(&mut self.inner)?;
+        // With more than one stream the muxer orders them by dts.
+        let written = if self.output_stream_index.len() > 1 {
+            p.write_interleaved(&mut self.inner)
+        } else {
+            p.write(&mut self.inner)
+        };
+        written.map_err(|e| WriteError::from_ffmpeg(e, "write_packet"))?;
+        // Flushed data is tagged with the packet that triggered it.
+        if self
+            .flush
+            .due(self.context.current_is_key && self.context.current_codec_id != 0)
+        {
+            flush_output(&mut self.inner);
+        }
 
         self.context.current_pts = None;
         self.context.current_dts = None;
         self.context.current_is_key = false;
-        self.context.current_codec_id = 0;
-        self.context.current_width = 0;
-        self.context.current_height = 0;
+        self.tag_video(None);
 
         Ok(())
     }
 
-    pub fn finish(&mut self) -> anyhow::Result<()> {
+    /// The first video output stream, which the header and trailer are
+    /// tagged with.
+    fn video_stream(&self) -> Option<usize> {
+        self.inner
+            .streams()
+            .find(|stream| stream.parameters().medium() == MediaType::Video)
+            .map(|stream| stream.index())
+    }
+
+    /// Tag the bytes muxed next with the codec and size of output stream
+    /// `out_idx` if it is video; untagged (all 0) otherwise.
+    fn tag_video(&mut self, out_idx: Option<usize>) {
+        let params = out_idx
+            .and_then(|i| self.inner.stream(i))
+            .map(|stream| stream.parameters())
+            .filter(|params| params.medium() == MediaType::Video);
+        let (codec_id, (w, h)) = match params {
+            Some(params) => (params.id() as i32, video_size_from_parameters(&params)),
+            None => (0, (0, 0)),
+        };
+        self.context.current_codec_id = codec_id;
+        self.context.current_width = w;
+        self.context.current_height = h;
+    }
+
+    pub fn finish(&mut self) -> Result<(), WriteError> {
         if self.have_written_header && !self.have_written_trailer {
             self.have_written_trailer = true;
-            self.inner.write_trailer()?;
+            self.tag_video(self.video_stream());
+            let written = self.inner.write_trailer();
+            self.tag_video(None);
+            written.map_err(|e| WriteError::from_ffmpeg(e, "write_trailer"))?;
         }
         Ok(())
     }
+
+    /// Hand the reader the muxed bytes it had no room for, waiting for it as
+    /// long as it takes. Call after every write and after [`Self::finish`]:
+    /// the muxer's callback cannot wait, so without this a slow reader loses
+    /// chunks mid-packet, and the trailer at the end. Bytes for a reader that
+    /// is gone are dropped.
+    pub async fn deliver(&mut self) {
+        while let Some(msg) = self.context.overflow.pop_front() {
+            if self.context.buffer.send(msg).await.is_err() {
+                self.context.overflow.clear();
+                return;
+            }
+        }
+    }
 }
 
 impl Drop for AvOutputStreamWriter {
@@ -332,14 +649,18 @@ impl AvOutputStream {
     /// callbacks (which would produce invalid NALUs for consumers like ZLMediaKit).
     const PACKET_SIZE_H264: usize = 256 * 1024;
 
+    /// A stream is a live output: it flushes every [`STREAMING_FLUSH_EVERY`]
+    /// unless changed with [`Self::set_flush_every`].
     pub fn new(format: &str) -> anyhow::Result<Self> {
         let mut inner = output_raw(format)?;
+        set_format_option(&mut inner, "flush_packets", "1")?;
         if format == "mp4" {
             set_mp4_movflags(&mut inner)?;
         }
         let (sender, receiver) = tokio::sync::mpsc::channel(MUX_OUTPUT_CHAN_CAP);
         let mut context = Box::new(PacketContext {
             buffer: sender,
+            overflow: VecDeque::new(),
             current_pts: None,
             current_dts: None,
             current_is_key: false,
@@ -363,18 +684,28 @@ impl AvOutputStream {
             have_written_trailer: false,
             context,
             receiver,
-            input_stream_index: None,
+            output_stream_index: HashMap::new(),
+            flush: Flusher::new(Some(STREAMING_FLUSH_EVERY)),
         })
     }
 
-    /// Add one output stream (e.g. video). Must be called before writing. Only one stream is supported.
+    /// Flush after every keyframe and at least every `every`; `None` leaves
+    /// it to the muxer. Must be called before writing.
+    pub fn set_flush_every(&mut self, every: Option<Duration>) {
+        self.flush = Flusher::new(every);
+    }
+
+    /// Add an output stream for input stream `stream` (e.g. video, then
+    /// audio for an MPEG-TS stream). Must be called before writing.
     pub fn add_stream(&mut self, stream: &AvStream) -> anyhow::Result<()> {
         let codec_parameters = stream.parameters();
         let mut writer_stream = self
             .inner
             .add_stream(ffmpeg_next::encoder::find(codec_parameters.id()))?;
         writer_stream.set_parameters(codec_parameters.clone());
-        self.input_stream_index = Some(stream.index());
+        writer_stream.set_metadata(stream_metadata(stream));
+        self.output_stream_index
+            .insert(stream.index(), writer_stream.index());
         Ok(())
     }
 
@@ -387,15 +718,18 @@ impl AvOutputStream {
             let have_written_trailer = this.have_written_trailer;
             let context = std::ptr::read(&this.context);
             let receiver = std::ptr::read(&this.receiver);
-            let input_stream_index = this.input_stream_index;
+            let output_stream_index = std::ptr::read(&this.output_stream_index);
+            let flush = std::ptr::read(&this.flush);
             (
                 AvOutputStreamWriter {
                     inner,
                     have_written_header,
                     have_written_trailer,
                     context,
-                    input_stream_index,
-                    last_dts: None,
+                    output_stream_index,
+                    last_dts: HashMap::new(),
+                    flush,
+                    hook: None,
                 },
                 AvOutputStreamReader { receiver },
             )
@@ -403,6 +737,55 @@ impl AvOutputStream {
     }
 }
 
+/// Before the header is written: whether `packet`, of output stream
+/// `out_idx`, may be written now. An H.264/H.265 stream without extradata
+/// (an encoder opened without global headers) holds the header back until its
+/// first keyframe, whose in-band parameter sets become the extradata, so
+/// `avcC`/`hvcC` (fragmented MP4, FLV) are not written empty. Packets before
+/// that keyframe are dropped; they could not be decoded anyway.
+fn ready_for_header(output: &mut Output, out_idx: usize, packet: &RawPacket) -> bool {
+    let missing: Vec<usize> = output
+        .streams()
+        .filter(|stream| {
+            let params = stream.parameters();
+            matches!(params.id(), Id::H264 | Id::HEVC) && get_extradata(&params).is_none()
+        })
+        .map(|stream| stream.index())
+        .collect();
+    if missing.is_empty() {
+        return true;
+    }
+    if !missing.contains(&out_idx) || !packet.is_key() {
+        return false;
+    }
+    let Some(mut stream) = output.stream_mut(out_idx) else {
+        return true;
+    };
+    let id = stream.parameters().id();
+    // Without parameter sets the header goes out as it is.
+    if let Some(sets) = in_band_parameter_sets(id, &packet.data()) {
+        unsafe { set_extradata((*stream.as_mut_ptr()).codecpar, &sets) };
+    }
+    true
+}
+
+/// Replace the extradata of `params` with a copy of `data`.
+unsafe fn set_extradata(params: *mut ffmpeg_next::ffi::AVCodecParameters, data: &[u8]) {
+    unsafe {
+        let padding = ffmpeg_next::ffi::AV_INPUT_BUFFER_PADDING_SIZE as usize;
+        let buf = ffmpeg_next::ffi::av_mallocz(data.len() + padding) as *mut u8;
+        if buf.is_null() {
+            return;
+        }
+        std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+        ffmpeg_next::ffi::av_freep(
+            &mut (*params).extradata as *mut *mut u8 as *mut std::ffi::c_void,
+        );
+        (*params).extradata = buf;
+        (*params).extradata_size = data.len() as i32;
+    }
+}
+
 /// Reads video width/height from codec parameters (not exposed by ffmpeg-next).
 fn video_size_from_parameters(params: &ffmpeg_next::codec::Parameters) -> (u32, u32) {
     unsafe {
@@ -413,20 +796,34 @@ fn video_size_from_parameters(params: &ffmpeg_next::codec::Parameters) -> (u32,
     }
 }
 
+/// The metadata dictionary the muxer writes for `stream` (its language,
+/// title).
+fn stream_metadata(stream: &AvStream) -> Dictionary<'static> {
+    let mut dict = Dictionary::new();
+    for (key, value) in stream.metadata() {
+        dict.set(key, value);
+    }
+    dict
+}
+
 /// Set movflags for MP4 so the muxer works with non-seekable output (e.g. our custom IO).
 /// Without this, the muxer would need to seek to write moov and would produce an invalid file.
 fn set_mp4_movflags(output: &mut Output) -> anyhow::Result<()> {
+    set_format_option(output, "movflags", "frag_keyframe+empty_moov")
+}
+
+fn set_format_option(output: &mut Output, name: &str, value: &str) -> anyhow::Result<()> {
+    let name_c = CString::new(name).map_err(|e| anyhow::anyhow!("option CString: {}", e))?;
+    let value_c = CString::new(value).map_err(|e| anyhow::anyhow!("value CString: {}", e))?;
     unsafe {
-        let name = CString::new("movflags").unwrap();
-        let value = CString::new("frag_keyframe+empty_moov").unwrap();
         let ret = av_opt_set(
             output.as_mut_ptr() as *mut std::ffi::c_void,
-            name.as_ptr(),
-            value.as_ptr(),
+            name_c.as_ptr(),
+            value_c.as_ptr(),
             AV_OPT_SEARCH_CHILDREN,
         );
         if ret != 0 {
-            return Err(anyhow::anyhow!("av_opt_set movflags failed: {}", ret));
+            return Err(anyhow::anyhow!("av_opt_set {} failed: {}", name, ret));
         }
     }
     Ok(())
@@ -572,6 +969,7 @@ extern "C" fn output_raw_buf_start_callback(
         let buf = std::slice::from_raw_parts(buffer, buffer_size as usize);
         let data = Bytes::copy_from_slice(buf);
         let msg = OutputMessage {
+            charge: MemoryBudget::global().charge(data.len()),
             data,
             pts: packet_context.current_pts,
             dts: packet_context.current_dts,
@@ -580,14 +978,21 @@ extern "C" fn output_raw_buf_start_callback(
             width: packet_context.current_width,
             height: packet_context.current_height,
         };
-        if packet_context.buffer.try_send(msg).is_err() {
-            log::warn!(
-                "mux output channel full, dropping packet ({} bytes)",
-                buffer_size
-            );
+        // Queue behind bytes already waiting, so the reader sees them in
+        // order; `deliver` hands them over.
+        if !packet_context.overflow.is_empty() {
+            packet_context.overflow.push_back(msg);
+        } else if let Err(tokio::sync::mpsc::error::TrySendError::Full(msg)) =
+            packet_context.buffer.try_send(msg)
+        {
+            packet_context.overflow.push_back(msg);
         }
     }
 
     // Number of bytes written.
     buffer_size
 }
+
+#[cfg(test)]
+#[path = "output_test.rs"]
+mod output_test;
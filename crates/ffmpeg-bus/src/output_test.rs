@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use futures::FutureExt;
+use futures::StreamExt;
+
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+use crate::input::AvInput;
+
+/// Time between two writes: the pacing of a ~25 fps camera.
+const FRAME_GAP: Duration = Duration::from_millis(40);
+
+/// Write the first `frames` video packets of the fixture into an fMP4
+/// `AvOutputStream`, one every [`FRAME_GAP`], and return for each write
+/// whether any `OutputMessage` came out of it.
+async fn emitted_per_frame(flush_every: Option<Duration>, frames: usize) -> Vec<bool> {
+    let path = ensure_fixture(&FixtureSpec::default().video_only())
+        .await
+        .unwrap();
+    let mut input = AvInput::new(path.to_string_lossy().as_ref(), None, None).unwrap();
+    let video = input
+        .streams()
+        .values()
+        .find(|s| s.is_video())
+        .unwrap()
+        .clone();
+
+    let mut stream = AvOutputStream::new("mp4").unwrap();
+    stream.set_flush_every(flush_every);
+    stream.add_stream(&video).unwrap();
+    let (mut writer, mut reader) = stream.into_split();
+
+    let mut emitted = Vec::new();
+    while emitted.len() < frames {
+        let packet = input.read_packet().expect("fixture too short");
+        if packet.index() != video.index() {
+            continue;
+        }
+        writer.write_packet(packet).unwrap();
+        let mut any = false;
+        while let Some(Some(_)) = reader.next().now_or_never() {
+            any = true;
+        }
+        emitted.push(any);
+        tokio::time::sleep(FRAME_GAP).await;
+    }
+    emitted
+}
+
+/// Longest run of writes that produced no output.
+fn longest_silence(emitted: &[bool]) -> usize {
+    emitted
+        .split(|&any| any)
+        .map(<[bool]>::len)
+        .max()
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn flush_interval_bounds_fmp4_output_gaps() {
+    // GOPs of 10 frames: unflushed, a fragment only comes out when the next
+    // keyframe closes it.
+    let unflushed = emitted_per_frame(None, 25).await;
+    assert!(longest_silence(&unflushed) >= 8, "{unflushed:?}");
+
+    let flushed = emitted_per_frame(Some(Duration::from_millis(100)), 25).await;
+    assert!(longest_silence(&flushed) <= 3, "{flushed:?}");
+}
+
+#[test]
+fn flusher_fires_on_keyframes_and_interval() {
+    let mut off = Flusher::new(None);
+    assert!(!off.due(true));
+
+    let mut flusher = Flusher::new(Some(Duration::from_millis(50)));
+    assert!(!flusher.due(false));
+    assert!(flusher.due(true));
+    assert!(!flusher.due(false));
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(flusher.due(false));
+    assert!(!flusher.due(false));
+}
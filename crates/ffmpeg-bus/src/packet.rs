@@ -1,7 +1,12 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
 use ffmpeg_next::Rational;
+use ffmpeg_next::codec::Id;
+
+use crate::memory::{Charged, MemoryBudget};
 
 pub type RawPacketSender = tokio::sync::broadcast::Sender<RawPacketCmd>;
 pub type RawPacketReceiver = tokio::sync::broadcast::Receiver<RawPacketCmd>;
@@ -9,16 +14,34 @@ pub type RawPacketReceiver = tokio::sync::broadcast::Receiver<RawPacketCmd>;
 #[derive(Clone)]
 pub enum RawPacketCmd {
     Data(RawPacket),
+    /// The input's connection dropped and was opened again (see
+    /// [`AvInputTask::start_with_retry`](crate::input::AvInputTask::start_with_retry)):
+    /// packets go on, timestamps continuing where they left off, from the
+    /// new connection's first packet, which need not be a keyframe.
+    Reconnected,
     EOF,
 }
 
 #[derive(Clone)]
 pub struct RawPacket {
-    packet: Arc<ffmpeg_next::codec::packet::Packet>,
+    packet: Arc<Charged<ffmpeg_next::codec::packet::Packet>>,
     time_base: Rational,
 }
 
 impl RawPacket {
+    /// A packet charged to `budget` instead of the global one.
+    pub fn with_budget(
+        packet: ffmpeg_next::codec::packet::Packet,
+        time_base: Rational,
+        budget: &Arc<MemoryBudget>,
+    ) -> Self {
+        let size = packet.size();
+        Self {
+            packet: Arc::new(Charged::new(packet, size, budget)),
+            time_base,
+        }
+    }
+
     pub fn pts(&self) -> Option<i64> {
         self.packet.pts()
     }
@@ -62,6 +85,19 @@ impl RawPacket {
         Arc::make_mut(&mut self.packet)
     }
 
+    /// This packet (timestamps, flags, stream, time base) carrying `data`
+    /// instead.
+    pub fn with_data(&self, data: &[u8]) -> RawPacket {
+        let mut packet = ffmpeg_next::codec::packet::Packet::copy(data);
+        packet.set_pts(self.packet.pts());
+        packet.set_dts(self.packet.dts());
+        packet.set_duration(self.packet.duration());
+        packet.set_flags(self.packet.flags());
+        packet.set_stream(self.packet.stream());
+        packet.set_position(self.packet.position());
+        RawPacket::from((packet, self.time_base))
+    }
+
     /// Get a reference to the inner packet (for BSF and other FFmpeg operations).
     pub fn packet(&self) -> &ffmpeg_next::codec::packet::Packet {
         &self.packet
@@ -70,9 +106,216 @@ impl RawPacket {
 
 impl From<(ffmpeg_next::codec::packet::Packet, Rational)> for RawPacket {
     fn from((packet, time_base): (ffmpeg_next::codec::packet::Packet, Rational)) -> Self {
+        Self::with_budget(packet, time_base, MemoryBudget::global())
+    }
+}
+
+/// Bounds of a [`GopBuffer`]; `None` leaves one open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GopLimits {
+    /// Most bytes of packet data held, across all streams. A GOP that alone
+    /// outgrows it is dropped, and buffering restarts at the next keyframe.
+    pub max_bytes: Option<usize>,
+    /// Longest span held, from the oldest keyframe to the newest packet of
+    /// the key stream. The GOP being received is always kept, however long.
+    pub max_duration: Option<Duration>,
+}
+
+/// Packets held by GOP of a key stream (the video), for consumers that start
+/// a stream later than it began: a lazy output connecting, a recording
+/// starting on an event. Whatever [`drain`](Self::drain) returns starts at a
+/// keyframe of the key stream; packets of other streams (audio) go with the
+/// GOP they arrived in, so whole GOPs, oldest first, are evicted together
+/// with them.
+///
+/// Keyframe flags are not always right (some cameras flag every packet, or
+/// none). For H.264 and H.265 key streams the NAL unit types decide instead,
+/// the flag only counting for packets without NAL units to tell by.
+pub struct GopBuffer {
+    limits: GopLimits,
+    key_index: usize,
+    key_codec: Option<Id>,
+    gops: VecDeque<Gop>,
+    bytes: usize,
+    /// Newest timestamp of the key stream held, in seconds.
+    newest: Option<f64>,
+}
+
+/// Packets from one keyframe of the key stream to the next, with the input
+/// index each is for.
+struct Gop {
+    packets: Vec<(usize, RawPacket)>,
+    bytes: usize,
+    /// Timestamp of its keyframe, in seconds.
+    start: Option<f64>,
+}
+
+impl GopBuffer {
+    /// An empty buffer keyed on stream 0, trusting its keyframe flags.
+    pub fn new(limits: GopLimits) -> Self {
         Self {
-            packet: Arc::new(packet),
-            time_base,
+            limits,
+            key_index: 0,
+            key_codec: None,
+            gops: VecDeque::new(),
+            bytes: 0,
+            newest: None,
+        }
+    }
+
+    /// Start GOPs at the keyframes of stream `index`, which is of `codec`
+    /// (`None`: unknown, the flags decide).
+    pub fn with_key_stream(mut self, index: usize, codec: Option<Id>) -> Self {
+        self.key_index = index;
+        self.key_codec = codec;
+        self
+    }
+
+    /// Hold `packet`, evicting what the limits no longer allow. Nothing is
+    /// held before the first keyframe: there is nothing to start from.
+    pub fn push(&mut self, packet: RawPacket) {
+        self.push_indexed(packet.index(), packet);
+    }
+
+    /// [`push`](Self::push) for input stream `idx`, where the packet's own
+    /// index may differ (encoded packets).
+    pub(crate) fn push_indexed(&mut self, idx: usize, packet: RawPacket) {
+        if idx == self.key_index {
+            let at = seconds(&packet);
+            if self.is_keyframe(&packet) {
+                self.gops.push_back(Gop {
+                    packets: Vec::new(),
+                    bytes: 0,
+                    start: at,
+                });
+            }
+            if !self.gops.is_empty()
+                && let Some(at) = at
+            {
+                // The max: with B-frames presentation times go back and forth.
+                self.newest = Some(self.newest.map_or(at, |newest| newest.max(at)));
+            }
+        }
+        let Some(gop) = self.gops.back_mut() else {
+            return;
+        };
+        let size = packet.size();
+        gop.packets.push((idx, packet));
+        gop.bytes += size;
+        self.bytes += size;
+
+        if let Some(max) = self.limits.max_duration {
+            self.truncate_to(max);
+        }
+        if let Some(max) = self.limits.max_bytes {
+            while self.bytes > max {
+                self.evict_oldest();
+            }
+        }
+    }
+
+    /// Evict whole oldest GOPs until the buffer spans at most `duration`, or
+    /// only the newest GOP is left. Where a span cannot be measured (packets
+    /// without timestamps) it counts as too long.
+    pub fn truncate_to(&mut self, duration: Duration) {
+        while self.gops.len() > 1 && self.span().is_none_or(|span| span > duration) {
+            self.evict_oldest();
         }
     }
+
+    /// Everything held, in arrival order, leaving the buffer empty. Starts at
+    /// a keyframe of the key stream, unless empty.
+    pub fn drain(&mut self) -> Vec<RawPacket> {
+        self.drain_indexed().into_iter().map(|(_, p)| p).collect()
+    }
+
+    /// [`drain`](Self::drain), with the input index of each packet.
+    pub(crate) fn drain_indexed(&mut self) -> Vec<(usize, RawPacket)> {
+        let packets = self.gops.drain(..).flat_map(|gop| gop.packets).collect();
+        self.clear();
+        packets
+    }
+
+    /// Drop everything held; buffering restarts at the next keyframe.
+    pub fn clear(&mut self) {
+        self.gops.clear();
+        self.bytes = 0;
+        self.newest = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gops.is_empty()
+    }
+
+    /// Packets held.
+    pub fn len(&self) -> usize {
+        self.gops.iter().map(|gop| gop.packets.len()).sum()
+    }
+
+    /// GOPs held; the newest may still be growing.
+    pub fn gops(&self) -> usize {
+        self.gops.len()
+    }
+
+    /// Bytes of packet data held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The span held, from the oldest keyframe to the newest packet of the
+    /// key stream; zero when unknown.
+    pub fn duration(&self) -> Duration {
+        self.span().unwrap_or_default()
+    }
+
+    fn span(&self) -> Option<Duration> {
+        let start = self.gops.front()?.start?;
+        Some(Duration::from_secs_f64((self.newest? - start).max(0.0)))
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(gop) = self.gops.pop_front() {
+            self.bytes -= gop.bytes;
+        }
+        if self.gops.is_empty() {
+            self.newest = None;
+        }
+    }
+
+    fn is_keyframe(&self, packet: &RawPacket) -> bool {
+        self.key_codec
+            .and_then(|codec| nal_keyframe(packet.packet().data()?, codec))
+            .unwrap_or_else(|| packet.is_key())
+    }
+}
+
+/// Whether an H.264/H.265 packet holds an IDR (H.264) or IRAP (H.265)
+/// picture, by its NAL unit types. `None` for other codecs, and for data
+/// that does not parse as NAL units.
+pub(crate) fn nal_keyframe(data: &[u8], codec: Id) -> Option<bool> {
+    let is_key: fn(u8) -> bool = match codec {
+        Id::H264 => |header| header & 0x1f == 5,
+        Id::HEVC => |header| (16..=23).contains(&((header >> 1) & 0x3f)),
+        _ => return None,
+    };
+    let headers: Vec<u8> = crate::hook::nal_units(data)
+        .iter()
+        .filter_map(|(_, nal)| nal.first().copied())
+        .collect();
+    // A set forbidden_zero_bit: not NAL units after all.
+    if headers.is_empty() || headers.iter().any(|header| header & 0x80 != 0) {
+        return None;
+    }
+    Some(headers.into_iter().any(is_key))
 }
+
+/// The presentation (else decoding) time of `packet`, in seconds.
+pub(crate) fn seconds(packet: &RawPacket) -> Option<f64> {
+    let ts = packet.pts().or(packet.dts())?;
+    let tb = packet.time_base();
+    (tb.denominator() != 0).then(|| ts as f64 * tb.numerator() as f64 / tb.denominator() as f64)
+}
+
+#[cfg(test)]
+#[path = "packet_test.rs"]
+mod packet_test;
@@ -0,0 +1,182 @@
+use ffmpeg_next::codec::packet::{Flags, Packet};
+
+use super::*;
+
+const VIDEO: usize = 0;
+const AUDIO: usize = 1;
+
+/// A packet of `size` bytes of stream `index` at `ms`, in a 1/1000 time base.
+fn packet(index: usize, ms: i64, size: usize, key: bool) -> RawPacket {
+    let mut p = Packet::copy(&vec![0xaa; size]);
+    p.set_stream(index);
+    p.set_pts(Some(ms));
+    p.set_dts(Some(ms));
+    if key {
+        p.set_flags(Flags::KEY);
+    }
+    RawPacket::from((p, Rational(1, 1000)))
+}
+
+/// An Annex B H.264 packet of one NAL unit of type `nal_type`, flagged key
+/// or not regardless of it.
+fn h264(ms: i64, nal_type: u8, flagged: bool) -> RawPacket {
+    let mut p = Packet::copy(&[0, 0, 0, 1, 0x60 | nal_type, 0x88, 0x84]);
+    p.set_pts(Some(ms));
+    if flagged {
+        p.set_flags(Flags::KEY);
+    }
+    RawPacket::from((p, Rational(1, 1000)))
+}
+
+fn starts(packets: &[RawPacket]) -> Vec<(usize, Option<i64>)> {
+    packets.iter().map(|p| (p.index(), p.pts())).collect()
+}
+
+#[test]
+fn drain_starts_at_a_keyframe_with_the_audio_of_its_gops() {
+    let mut buffer = GopBuffer::new(GopLimits::default());
+    // Before the first keyframe: nothing to start from.
+    buffer.push(packet(AUDIO, 0, 10, true));
+    buffer.push(packet(VIDEO, 0, 10, false));
+    assert!(buffer.is_empty());
+
+    buffer.push(packet(VIDEO, 40, 100, true));
+    buffer.push(packet(AUDIO, 45, 10, true));
+    buffer.push(packet(VIDEO, 80, 20, false));
+    buffer.push(packet(VIDEO, 120, 100, true));
+    buffer.push(packet(AUDIO, 125, 10, true));
+    assert_eq!((buffer.gops(), buffer.len(), buffer.bytes()), (2, 5, 240));
+    assert_eq!(buffer.duration(), Duration::from_millis(80));
+
+    let drained = buffer.drain();
+    assert_eq!(
+        starts(&drained),
+        [
+            (VIDEO, Some(40)),
+            (AUDIO, Some(45)),
+            (VIDEO, Some(80)),
+            (VIDEO, Some(120)),
+            (AUDIO, Some(125)),
+        ]
+    );
+    assert!(buffer.is_empty() && buffer.bytes() == 0);
+}
+
+#[test]
+fn eviction_takes_whole_gops_with_their_audio() {
+    let mut buffer = GopBuffer::new(GopLimits {
+        max_bytes: Some(300),
+        max_duration: None,
+    });
+    buffer.push(packet(VIDEO, 0, 100, true));
+    buffer.push(packet(AUDIO, 5, 60, true));
+    buffer.push(packet(VIDEO, 40, 100, true));
+    buffer.push(packet(AUDIO, 45, 50, true));
+    // 310 bytes: the first GOP goes, audio and all.
+    assert_eq!(buffer.bytes(), 150);
+    assert_eq!(
+        starts(&buffer.drain()),
+        [(VIDEO, Some(40)), (AUDIO, Some(45))]
+    );
+
+    // A GOP that alone outgrows the bound is dropped up to the next keyframe.
+    buffer.push(packet(VIDEO, 80, 200, true));
+    buffer.push(packet(VIDEO, 120, 200, false));
+    assert!(buffer.is_empty());
+    buffer.push(packet(VIDEO, 160, 50, false));
+    assert!(buffer.is_empty());
+    buffer.push(packet(VIDEO, 200, 50, true));
+    assert_eq!(starts(&buffer.drain()), [(VIDEO, Some(200))]);
+}
+
+#[test]
+fn duration_bound_keeps_the_newest_gop() {
+    let mut buffer = GopBuffer::new(GopLimits {
+        max_bytes: None,
+        max_duration: Some(Duration::from_secs(2)),
+    });
+    // One-second GOPs of 25 fps.
+    for frame in 0..125 {
+        buffer.push(packet(VIDEO, frame * 40, 10, frame % 25 == 0));
+    }
+    assert!(buffer.duration() <= Duration::from_secs(2));
+    assert_eq!(buffer.gops(), 2);
+    assert_eq!(buffer.drain()[0].pts(), Some(3000));
+
+    // Much longer than the bound, but the one GOP there is.
+    for frame in 0..200 {
+        buffer.push(packet(VIDEO, frame * 40, 10, frame == 0));
+    }
+    assert_eq!((buffer.gops(), buffer.len()), (1, 200));
+
+    buffer.push(packet(VIDEO, 8000, 10, true));
+    buffer.truncate_to(Duration::ZERO);
+    assert_eq!(starts(&buffer.drain()), [(VIDEO, Some(8000))]);
+}
+
+#[test]
+fn h264_keyframes_are_told_by_their_nal_units() {
+    let mut buffer = GopBuffer::new(GopLimits::default()).with_key_stream(0, Some(Id::H264));
+    // Flagged key, but a non-IDR slice: not a start.
+    buffer.push(h264(0, 1, true));
+    assert!(buffer.is_empty());
+    // An IDR slice without the flag is one.
+    buffer.push(h264(40, 5, false));
+    buffer.push(h264(80, 1, true));
+    assert_eq!(buffer.gops(), 1);
+    // Data that is no NAL units falls back to the flag.
+    buffer.push(packet(0, 120, 8, true));
+    assert_eq!(buffer.gops(), 2);
+
+    assert_eq!(nal_keyframe(&[0, 0, 1, 0x26, 0x01], Id::HEVC), Some(true));
+    assert_eq!(nal_keyframe(&[0, 0, 1, 0x02, 0x01], Id::HEVC), Some(false));
+    assert_eq!(nal_keyframe(&[0, 0, 1, 0x65], Id::AAC), None);
+}
+
+/// xorshift64*, for reproducible pseudo-random interleavings.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+#[test]
+fn random_interleavings_drain_from_a_keyframe_within_the_bound() {
+    for seed in 1..=200u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let max_bytes = 200 + rng.below(5000) as usize;
+        let mut buffer = GopBuffer::new(GopLimits {
+            max_bytes: Some(max_bytes),
+            max_duration: (rng.below(2) == 0).then(|| Duration::from_millis(rng.below(3000))),
+        });
+        let mut ms = 0;
+        for _ in 0..rng.below(400) {
+            ms += rng.below(40) as i64;
+            let index = if rng.below(3) == 0 { AUDIO } else { VIDEO };
+            let key = index == AUDIO || rng.below(8) == 0;
+            buffer.push(packet(index, ms, 1 + rng.below(600) as usize, key));
+            assert!(buffer.bytes() <= max_bytes, "seed {seed}");
+
+            if rng.below(50) == 0 {
+                let drained = buffer.drain();
+                if let Some(first) = drained.first() {
+                    assert!(first.index() == VIDEO && first.is_key(), "seed {seed}");
+                }
+                assert!(drained.iter().map(|p| p.size()).sum::<usize>() <= max_bytes);
+            }
+        }
+        let drained = buffer.drain();
+        if let Some(first) = drained.first() {
+            assert!(first.index() == VIDEO && first.is_key(), "seed {seed}");
+        }
+    }
+}
@@ -0,0 +1,638 @@
+//! High-level pipeline API over [`Bus`].
+//!
+//! [`PipelineBuilder`] collects one input and its outputs, validates them
+//! upfront, and builds a [`Pipeline`] that owns the bus and the tasks
+//! consuming each output's stream. Outputs are registered in one batch before
+//! the input starts reading, so every output sees the stream from its first
+//! packet.
+//!
+//! Three kinds of output:
+//! - [`PipelineBuilder::output`]: muxed in-bus (File/Net/Hls/Segments); nothing
+//!   to consume.
+//! - [`PipelineBuilder::sink`]: a callback fed every frame/packet until it
+//!   returns `false` or the stream ends.
+//! - [`PipelineBuilder::handler`]: an [`OutputHandler`] that takes the whole
+//!   stream (plus its codec metadata) and spawns its own task.
+//!
+//! ```no_run
+//! use ffmpeg_bus::bus::{InputConfig, OutputAvType, OutputConfig, OutputDest};
+//! use ffmpeg_bus::pipeline::{PipelineBuilder, PipelineItem};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let mut pipeline = PipelineBuilder::new("cam1")
+//!     .input(InputConfig::Net {
+//!         url: "rtsp://127.0.0.1:8554/cam1".to_string(),
+//!         reconnect: Some(Default::default()),
+//!         net: Default::default(),
+//!     })
+//!     .output(OutputConfig::new(
+//!         "record".to_string(),
+//!         OutputAvType::Video,
+//!         OutputDest::File {
+//!             path: "cam1.mp4".to_string(),
+//!         },
+//!     ))
+//!     .sink(
+//!         OutputConfig::new("frames".to_string(), OutputAvType::Video, OutputDest::Raw),
+//!         |item| {
+//!             if let PipelineItem::Video(frame) = item {
+//!                 println!("{}x{} pts={}", frame.width, frame.height, frame.pts);
+//!             }
+//!             true
+//!         },
+//!     )
+//!     .build()?;
+//!
+//! let accepted = pipeline.start().await?;
+//! println!("outputs running: {accepted:?}");
+//! pipeline.wait().await; // first consumer done (e.g. input EOF)
+//! println!("{:?}", pipeline.stats());
+//! pipeline.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use futures::StreamExt;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::{
+    bus::{
+        Bus, BusEvent, BusOptions, FrameEvent, InputConfig, OutputAvType, OutputConfig, OutputDest,
+        RawOutputStream,
+    },
+    frame::{AudioFrame, VideoFrame},
+    stream::AvStream,
+    write_error::WriteErrorKind,
+};
+
+/// Capacity of the [`PipelineEvent`] broadcast.
+const EVENT_CHAN_CAP: usize = 64;
+
+/// One item delivered to a [`PipelineBuilder::sink`] callback.
+pub enum PipelineItem {
+    /// A decoded video frame, or a packet for packet-style outputs
+    /// (Encoded/Mux/Demuxed), carried as a `VideoFrame`.
+    Video(VideoFrame),
+    /// A decoded audio frame (`OutputDest::Raw` with `OutputAvType::Audio`).
+    Audio(AudioFrame),
+}
+
+/// Consumer of one output's stream.
+pub trait OutputHandler: Send + 'static {
+    /// Take over the accepted output's stream; the pipeline owns the returned
+    /// task and treats its completion as the output ending.
+    fn start(self: Box<Self>, av: AvStream, stream: RawOutputStream) -> JoinHandle<()>;
+
+    /// The bus refused the output (e.g. the input has no matching stream).
+    fn rejected(self: Box<Self>, _error: &anyhow::Error) {}
+}
+
+/// [`OutputHandler`] forwarding every frame to a callback, up to the input's
+/// end. Lagged and errored items are skipped.
+struct FnSink<F>(F);
+
+impl<F> OutputHandler for FnSink<F>
+where
+    F: FnMut(PipelineItem) -> bool + Send + 'static,
+{
+    fn start(self: Box<Self>, _av: AvStream, stream: RawOutputStream) -> JoinHandle<()> {
+        let mut f = self.0;
+        tokio::spawn(async move {
+            match stream {
+                RawOutputStream::Video(mut s) => {
+                    while let Some(event) = s.next().await {
+                        match event {
+                            FrameEvent::Frame(frame) => {
+                                if !f(PipelineItem::Video(frame)) {
+                                    break;
+                                }
+                            }
+                            FrameEvent::Eof => break,
+                            FrameEvent::Lagged(_) | FrameEvent::Error(_) => {}
+                        }
+                    }
+                }
+                RawOutputStream::Audio(mut s) => {
+                    while let Some(event) = s.next().await {
+                        match event {
+                            FrameEvent::Frame(frame) => {
+                                if !f(PipelineItem::Audio(frame)) {
+                                    break;
+                                }
+                            }
+                            FrameEvent::Eof => break,
+                            FrameEvent::Lagged(_) | FrameEvent::Error(_) => {}
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PipelineEvent {
+    OutputStarted {
+        id: String,
+    },
+    OutputFailed {
+        id: String,
+        error: String,
+        kind: Option<WriteErrorKind>,
+    },
+    /// See [`BusEvent::OutputInterrupted`].
+    OutputInterrupted {
+        id: String,
+        error: String,
+        kind: WriteErrorKind,
+    },
+    /// See [`BusEvent::OutputReconnected`].
+    OutputReconnected {
+        id: String,
+        attempts: u32,
+    },
+    /// The output's consumer finished (stream ended or the consumer stopped).
+    OutputEnded {
+        id: String,
+    },
+    /// See [`BusEvent::EncoderRecovered`].
+    EncoderRecovered {
+        stream_index: usize,
+        encoder: String,
+        from: String,
+        to: String,
+        downconverted: bool,
+    },
+    /// See [`BusEvent::EncoderFailed`].
+    EncoderFailed {
+        stream_index: usize,
+        encoder: String,
+        input_format: String,
+        encoder_format: String,
+        error: String,
+    },
+    /// See [`BusEvent::AudioGap`].
+    AudioGap {
+        stream_index: usize,
+        at_ms: i64,
+        duration_ms: u64,
+    },
+    Stopped,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputStats {
+    pub id: String,
+    /// Items handed to the output's consumer (0 for in-bus outputs).
+    pub items: u64,
+    pub running: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub outputs: Vec<OutputStats>,
+}
+
+struct OutputCounter {
+    id: String,
+    items: AtomicU64,
+    running: AtomicBool,
+}
+
+struct PendingOutput {
+    config: OutputConfig,
+    handler: Option<Box<dyn OutputHandler>>,
+}
+
+pub struct PipelineBuilder {
+    id: String,
+    input: Option<InputConfig>,
+    input_options: Option<HashMap<String, String>>,
+    options: BusOptions,
+    outputs: Vec<PendingOutput>,
+}
+
+impl PipelineBuilder {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            input: None,
+            input_options: None,
+            options: BusOptions::default(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn input(mut self, input: InputConfig) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Demuxer options for the input (e.g. `rtsp_transport=tcp`).
+    pub fn input_options(mut self, options: HashMap<String, String>) -> Self {
+        self.input_options = Some(options);
+        self
+    }
+
+    /// Tunables of the pipeline's bus, e.g. audio gap filling for
+    /// recordings (see [`BusOptions`]).
+    pub fn options(mut self, options: BusOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// An output muxed inside the bus (File/Net/Hls/Segments).
+    pub fn output(mut self, config: OutputConfig) -> Self {
+        self.outputs.push(PendingOutput {
+            config,
+            handler: None,
+        });
+        self
+    }
+
+    /// An output whose stream is fed to `f`, item by item, until `f` returns
+    /// `false` or the stream ends.
+    pub fn sink<F>(self, config: OutputConfig, f: F) -> Self
+    where
+        F: FnMut(PipelineItem) -> bool + Send + 'static,
+    {
+        self.handler(config, FnSink(f))
+    }
+
+    /// An output whose stream is taken over by `handler`.
+    pub fn handler(mut self, config: OutputConfig, handler: impl OutputHandler) -> Self {
+        self.outputs.push(PendingOutput {
+            config,
+            handler: Some(Box::new(handler)),
+        });
+        self
+    }
+
+    /// Validate the input and every output, then build the (not yet started)
+    /// pipeline.
+    pub fn build(self) -> anyhow::Result<Pipeline> {
+        let input = self
+            .input
+            .ok_or_else(|| anyhow::anyhow!("pipeline {}: input is required", self.id))?;
+        let (events, _) = tokio::sync::broadcast::channel(EVENT_CHAN_CAP);
+        let (bus_events, _) = tokio::sync::broadcast::channel(EVENT_CHAN_CAP);
+        let mut pipeline = Pipeline {
+            id: self.id,
+            input: Some(input),
+            input_options: self.input_options,
+            options: self.options,
+            outputs: Vec::new(),
+            bus: None,
+            started: false,
+            tasks: JoinSet::new(),
+            counters: Vec::new(),
+            events,
+            bus_events,
+        };
+        for output in self.outputs {
+            pipeline.push_output(output.config, output.handler)?;
+        }
+        Ok(pipeline)
+    }
+}
+
+/// Check one output's config against what its destination supports.
+pub fn validate_output(config: &OutputConfig, has_handler: bool) -> anyhow::Result<()> {
+    let id = &config.id;
+    if id.is_empty() {
+        anyhow::bail!("output id must not be empty");
+    }
+    let has_encode = config.encode.is_some() || config.audio_encode.is_some();
+    match &config.dest {
+        OutputDest::Raw if has_encode => {
+            anyhow::bail!("output {id}: Raw yields decoded frames and takes no encode config")
+        }
+        OutputDest::Demuxed if has_encode => {
+            anyhow::bail!("output {id}: Demuxed passes packets through and takes no encode config")
+        }
+        OutputDest::Mux { format } if format.is_empty() => {
+            anyhow::bail!("output {id}: Mux format must not be empty")
+        }
+        OutputDest::File { path } if path.is_empty() => {
+            anyhow::bail!("output {id}: File path must not be empty")
+        }
+        OutputDest::Net { url, .. } if url.is_empty() => {
+            anyhow::bail!("output {id}: Net url must not be empty")
+        }
+        OutputDest::Hls {
+            path,
+            segment_seconds,
+            ..
+        } if path.is_empty() || *segment_seconds == 0 => {
+            anyhow::bail!("output {id}: Hls needs a path and segment_seconds > 0")
+        }
+        OutputDest::Segments {
+            dir,
+            pattern,
+            segment_seconds,
+        } => {
+            if dir.is_empty() || *segment_seconds == 0 {
+                anyhow::bail!("output {id}: Segments needs a dir and segment_seconds > 0")
+            }
+            crate::segment::check_pattern(pattern)
+                .map_err(|e| anyhow::anyhow!("output {id}: {e}"))?;
+        }
+        _ => {}
+    }
+    if config.roi.is_some()
+        && !(matches!(config.dest, OutputDest::Raw) && config.av_type == OutputAvType::Video)
+    {
+        anyhow::bail!("output {id}: roi only applies to Raw video outputs");
+    }
+    let in_bus = matches!(
+        config.dest,
+        OutputDest::File { .. }
+            | OutputDest::Net { .. }
+            | OutputDest::Hls { .. }
+            | OutputDest::Segments { .. }
+    );
+    if in_bus && has_handler {
+        anyhow::bail!(
+            "output {id}: File/Net/Hls/Segments are muxed in-bus and have no stream to consume"
+        );
+    }
+    if !in_bus && !has_handler {
+        anyhow::bail!("output {id}: stream outputs need a sink or handler");
+    }
+    Ok(())
+}
+
+pub struct Pipeline {
+    id: String,
+    input: Option<InputConfig>,
+    input_options: Option<HashMap<String, String>>,
+    options: BusOptions,
+    outputs: Vec<PendingOutput>,
+    bus: Option<Arc<Bus>>,
+    started: bool,
+    tasks: JoinSet<()>,
+    counters: Vec<Arc<OutputCounter>>,
+    events: tokio::sync::broadcast::Sender<PipelineEvent>,
+    /// Every event of the bus, relayed for [`Self::bus_events`].
+    bus_events: tokio::sync::broadcast::Sender<BusEvent>,
+}
+
+impl Pipeline {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Add one more output before [`Self::start`] (validated like the builder's).
+    pub fn push_output(
+        &mut self,
+        config: OutputConfig,
+        handler: Option<Box<dyn OutputHandler>>,
+    ) -> anyhow::Result<()> {
+        if self.started {
+            anyhow::bail!("pipeline {}: already started", self.id);
+        }
+        validate_output(&config, handler.is_some())?;
+        if self.outputs.iter().any(|o| o.config.id == config.id) {
+            anyhow::bail!("pipeline {}: duplicate output id {}", self.id, config.id);
+        }
+        self.outputs.push(PendingOutput { config, handler });
+        Ok(())
+    }
+
+    /// Open the input (without reading it yet) and return its streams, e.g. to
+    /// size outputs to the source before adding them. Optional: `start` opens
+    /// the input itself.
+    pub async fn open(&mut self) -> anyhow::Result<Vec<AvStream>> {
+        let bus = self.ensure_bus().await?;
+        bus.input_streams().await
+    }
+
+    async fn ensure_bus(&mut self) -> anyhow::Result<Arc<Bus>> {
+        if let Some(bus) = &self.bus {
+            return Ok(Arc::clone(bus));
+        }
+        let input = self
+            .input
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("pipeline {}: input already consumed", self.id))?;
+        let bus = Arc::new(Bus::with_options(&self.id, self.options.clone()));
+        // In-bus outputs that fail after being added (lazy Net outputs out of
+        // retries, fatal write errors) and stuck encoders surface as pipeline
+        // events too.
+        let mut bus_events = bus.events();
+        let events = self.events.clone();
+        let relay = self.bus_events.clone();
+        crate::worker::spawn_task("pipeline-events", async move {
+            loop {
+                let received = bus_events.recv().await;
+                if let Ok(event) = &received {
+                    let _ = relay.send(event.clone());
+                }
+                match received {
+                    Ok(BusEvent::OutputFailed { id, error, kind }) => {
+                        let _ = events.send(PipelineEvent::OutputFailed { id, error, kind });
+                    }
+                    Ok(BusEvent::OutputInterrupted { id, error, kind }) => {
+                        let _ = events.send(PipelineEvent::OutputInterrupted { id, error, kind });
+                    }
+                    Ok(BusEvent::OutputReconnected { id, attempts }) => {
+                        let _ = events.send(PipelineEvent::OutputReconnected { id, attempts });
+                    }
+                    Ok(BusEvent::EncoderRecovered {
+                        stream_index,
+                        encoder,
+                        from,
+                        to,
+                        downconverted,
+                    }) => {
+                        let _ = events.send(PipelineEvent::EncoderRecovered {
+                            stream_index,
+                            encoder,
+                            from,
+                            to,
+                            downconverted,
+                        });
+                    }
+                    Ok(BusEvent::EncoderFailed {
+                        stream_index,
+                        encoder,
+                        input_format,
+                        encoder_format,
+                        error,
+                    }) => {
+                        let _ = events.send(PipelineEvent::EncoderFailed {
+                            stream_index,
+                            encoder,
+                            input_format,
+                            encoder_format,
+                            error,
+                        });
+                    }
+                    Ok(BusEvent::AudioGap {
+                        stream_index,
+                        at_ms,
+                        duration_ms,
+                    }) => {
+                        let _ = events.send(PipelineEvent::AudioGap {
+                            stream_index,
+                            at_ms,
+                            duration_ms,
+                        });
+                    }
+                    // Input and mux lifecycle events are the bus's own.
+                    Ok(_) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        bus.add_input(input, self.input_options.take()).await?;
+        self.bus = Some(Arc::clone(&bus));
+        Ok(bus)
+    }
+
+    /// The underlying bus once opened, e.g. for `subscribe_audio`.
+    pub fn bus(&self) -> Option<Arc<Bus>> {
+        self.bus.clone()
+    }
+
+    /// Register every output in one batch, start the input and spawn the
+    /// output consumers. Returns the ids of the outputs the bus accepted;
+    /// refused ones are reported to their handler and as
+    /// [`PipelineEvent::OutputFailed`].
+    pub async fn start(&mut self) -> anyhow::Result<Vec<String>> {
+        if self.started {
+            anyhow::bail!("pipeline {}: already started", self.id);
+        }
+        let bus = self.ensure_bus().await?;
+        self.started = true;
+
+        let (configs, handlers): (Vec<_>, Vec<_>) = std::mem::take(&mut self.outputs)
+            .into_iter()
+            .map(|o| ((o.config.id.clone(), o.config), o.handler))
+            .unzip();
+        let (ids, configs): (Vec<_>, Vec<_>) = configs.into_iter().unzip();
+        let results = bus.add_outputs(configs).await?;
+
+        let mut accepted = Vec::new();
+        for ((id, handler), result) in ids.into_iter().zip(handlers).zip(results) {
+            match result {
+                Ok((av, stream)) => {
+                    let counter = Arc::new(OutputCounter {
+                        id: id.clone(),
+                        items: AtomicU64::new(0),
+                        running: AtomicBool::new(true),
+                    });
+                    self.counters.push(Arc::clone(&counter));
+                    let _ = self
+                        .events
+                        .send(PipelineEvent::OutputStarted { id: id.clone() });
+                    if let Some(handler) = handler {
+                        let handle = handler.start(av, counted(stream, Arc::clone(&counter)));
+                        let events = self.events.clone();
+                        self.tasks.spawn(async move {
+                            let _ = handle.await;
+                            counter.running.store(false, Ordering::Relaxed);
+                            let _ = events.send(PipelineEvent::OutputEnded {
+                                id: counter.id.clone(),
+                            });
+                        });
+                    }
+                    accepted.push(id);
+                }
+                Err(e) => {
+                    tracing::warn!("pipeline {}: output {} failed: {:#}", self.id, id, e);
+                    let _ = self.events.send(PipelineEvent::OutputFailed {
+                        id: id.clone(),
+                        error: format!("{:#}", e),
+                        kind: None,
+                    });
+                    if let Some(handler) = handler {
+                        handler.rejected(&e);
+                    }
+                }
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Resolve when the first output consumer ends (e.g. input EOF); never
+    /// resolves when no output has a consumer.
+    pub async fn wait(&mut self) {
+        if self.tasks.join_next().await.is_none() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Tear the bus down in order (see [`Bus::shutdown`]) and drain the
+    /// remaining consumers.
+    pub async fn shutdown(&mut self) {
+        if let Some(bus) = self.bus.take() {
+            match bus.shutdown().await {
+                Ok(forced) if !forced.is_empty() => {
+                    tracing::warn!("pipeline {}: forced {:?} to stop", self.id, forced);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("pipeline {}: shutdown failed: {:#}", self.id, e),
+            }
+        }
+        while self.tasks.join_next().await.is_some() {}
+        for counter in &self.counters {
+            counter.running.store(false, Ordering::Relaxed);
+        }
+        let _ = self.events.send(PipelineEvent::Stopped);
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            outputs: self
+                .counters
+                .iter()
+                .map(|c| OutputStats {
+                    id: c.id.clone(),
+                    items: c.items.load(Ordering::Relaxed),
+                    running: c.running.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn events(&self) -> tokio::sync::broadcast::Receiver<PipelineEvent> {
+        self.events.subscribe()
+    }
+
+    /// Every [`BusEvent`] of the pipeline's bus, from its creation on: unlike
+    /// [`Bus::events`], this can be subscribed to before the bus exists, so the
+    /// input opening is not missed.
+    pub fn bus_events(&self) -> tokio::sync::broadcast::Receiver<BusEvent> {
+        self.bus_events.subscribe()
+    }
+}
+
+/// Count the items flowing into an output's consumer.
+fn counted(stream: RawOutputStream, counter: Arc<OutputCounter>) -> RawOutputStream {
+    match stream {
+        RawOutputStream::Video(s) => RawOutputStream::Video(Box::pin(s.inspect(move |item| {
+            if matches!(item, FrameEvent::Frame(_)) {
+                counter.items.fetch_add(1, Ordering::Relaxed);
+            }
+        }))),
+        RawOutputStream::Audio(s) => RawOutputStream::Audio(Box::pin(s.inspect(move |item| {
+            if matches!(item, FrameEvent::Frame(_)) {
+                counter.items.fetch_add(1, Ordering::Relaxed);
+            }
+        }))),
+    }
+}
+
+#[cfg(test)]
+#[path = "pipeline_test.rs"]
+mod pipeline_test;
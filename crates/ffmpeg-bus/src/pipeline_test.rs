@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use super::*;
+use crate::bus::EncodeConfig;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+
+/// A file input of `path`, which nothing opens before the pipeline starts.
+fn file_input(path: &Path) -> InputConfig {
+    InputConfig::File {
+        path: path.to_string_lossy().into_owned(),
+    }
+}
+
+fn raw(id: &str, av_type: OutputAvType) -> OutputConfig {
+    OutputConfig::new(id.to_string(), av_type, OutputDest::Raw)
+}
+
+fn h264() -> EncodeConfig {
+    EncodeConfig {
+        codec: "h264".to_string(),
+        width: Some(320),
+        height: Some(240),
+        ..Default::default()
+    }
+}
+
+fn build_err(builder: PipelineBuilder) -> String {
+    match builder.build() {
+        Ok(_) => panic!("expected build to fail"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn build_requires_input() {
+    let err = build_err(PipelineBuilder::new("p").sink(raw("v", OutputAvType::Video), |_| true));
+    assert!(err.contains("input is required"), "{err}");
+}
+
+#[test]
+fn raw_output_with_encode_is_rejected() {
+    let err = build_err(
+        PipelineBuilder::new("p")
+            .input(file_input(Path::new("unopened.mp4")))
+            .sink(raw("v", OutputAvType::Video).with_encode(h264()), |_| true),
+    );
+    assert!(err.contains("Raw"), "{err}");
+}
+
+#[test]
+fn stream_output_needs_a_consumer_and_muxed_output_takes_none() {
+    let err = build_err(
+        PipelineBuilder::new("p")
+            .input(file_input(Path::new("unopened.mp4")))
+            .output(raw("v", OutputAvType::Video)),
+    );
+    assert!(err.contains("sink or handler"), "{err}");
+
+    let file = OutputConfig::new(
+        "f".to_string(),
+        OutputAvType::Video,
+        OutputDest::File {
+            path: "out.mp4".to_string(),
+        },
+    );
+    let err = build_err(
+        PipelineBuilder::new("p")
+            .input(file_input(Path::new("unopened.mp4")))
+            .sink(file, |_| true),
+    );
+    assert!(err.contains("muxed in-bus"), "{err}");
+}
+
+#[test]
+fn duplicate_output_ids_are_rejected() {
+    let err = build_err(
+        PipelineBuilder::new("p")
+            .input(file_input(Path::new("unopened.mp4")))
+            .sink(raw("v", OutputAvType::Video), |_| true)
+            .sink(raw("v", OutputAvType::Audio), |_| true),
+    );
+    assert!(err.contains("duplicate output id"), "{err}");
+}
+
+#[test]
+fn hls_output_needs_segment_duration() {
+    let hls = OutputConfig::new(
+        "h".to_string(),
+        OutputAvType::Video,
+        OutputDest::Hls {
+            path: "index.m3u8".to_string(),
+            segment_seconds: 0,
+            list_size: 0,
+        },
+    );
+    assert!(validate_output(&hls, false).is_err());
+}
+
+#[test]
+fn segments_output_needs_a_valid_pattern() {
+    let segments = |pattern: &str| {
+        OutputConfig::new(
+            "s".to_string(),
+            OutputAvType::Video,
+            OutputDest::Segments {
+                dir: "rec".to_string(),
+                pattern: pattern.to_string(),
+                segment_seconds: 60,
+            },
+        )
+    };
+    assert!(validate_output(&segments("rec_%Y%m%d_%H%M%S.mp4"), false).is_ok());
+    let err = validate_output(&segments("rec_%"), false)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("invalid segment pattern"), "{err}");
+}
+
+/// Same scenario as `bus_test::test_mux_h264`, through the pipeline API.
+#[tokio::test]
+async fn pipeline_mux_h264_sink() -> anyhow::Result<()> {
+    let input_path = ensure_fixture(&FixtureSpec::default()).await?;
+
+    let mux = OutputConfig::new(
+        "mux_h264".to_string(),
+        OutputAvType::Video,
+        OutputDest::Mux {
+            format: "h264".to_string(),
+        },
+    );
+    let mut pipeline = PipelineBuilder::new("pipeline_mux")
+        .input(file_input(&input_path))
+        .sink(
+            mux,
+            |item| matches!(item, PipelineItem::Video(frame) if !frame.data.is_empty()),
+        )
+        .build()?;
+    let mut events = pipeline.events();
+
+    assert_eq!(pipeline.start().await?, vec!["mux_h264".to_string()]);
+    pipeline.wait().await;
+    let stats = pipeline.stats();
+    pipeline.shutdown().await;
+
+    assert_eq!(
+        events.recv().await?,
+        PipelineEvent::OutputStarted {
+            id: "mux_h264".to_string()
+        }
+    );
+    assert_eq!(stats.outputs.len(), 1);
+    assert!(stats.outputs[0].items > 0, "no muxed data received");
+    assert!(!stats.outputs[0].running);
+    Ok(())
+}
+
+/// Same scenario as `bus_test::test_transcode_video_to_file`; a raw sink
+/// alongside the file output tells when the input has been read to the end.
+#[tokio::test]
+async fn pipeline_transcode_video_to_file() -> anyhow::Result<()> {
+    let file_name = "output_pipeline_transcode.mp4";
+    if Path::new(file_name).exists() {
+        std::fs::remove_file(file_name).ok();
+    }
+    let input_path = ensure_fixture(&FixtureSpec::default()).await?;
+
+    let file = OutputConfig::new(
+        "transcode_file".to_string(),
+        OutputAvType::Video,
+        OutputDest::File {
+            path: file_name.to_string(),
+        },
+    )
+    .with_encode(h264());
+    let mut pipeline = PipelineBuilder::new("pipeline_transcode")
+        .input(file_input(&input_path))
+        .output(file)
+        .sink(raw("eof", OutputAvType::Video), |_| true)
+        .build()?;
+
+    let accepted = pipeline.start().await?;
+    assert_eq!(accepted.len(), 2, "accepted: {accepted:?}");
+    pipeline.wait().await;
+    // Let the muxer write its trailer before tearing the bus down.
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    pipeline.shutdown().await;
+
+    assert!(std::fs::metadata(file_name)?.len() > 0);
+    Ok(())
+}
+
+/// Same scenario as `bus_test::test_raw_outputs_split_video_and_audio`.
+#[tokio::test]
+async fn pipeline_raw_video_and_audio_sinks() -> anyhow::Result<()> {
+    let input_path = ensure_fixture(&FixtureSpec::default()).await?;
+
+    let mut pipeline = PipelineBuilder::new("pipeline_raw_split")
+        .input(file_input(&input_path))
+        .sink(
+            raw("raw_video", OutputAvType::Video),
+            |item| matches!(item, PipelineItem::Video(f) if f.width > 0 && f.height > 0),
+        )
+        .sink(
+            raw("raw_audio", OutputAvType::Audio),
+            |item| matches!(item, PipelineItem::Audio(f) if f.samples > 0),
+        )
+        .build()?;
+
+    pipeline.start().await?;
+    // Both sinks run to the end of the file.
+    pipeline.wait().await;
+    pipeline.wait().await;
+    let stats = pipeline.stats();
+    pipeline.shutdown().await;
+
+    for output in &stats.outputs {
+        assert!(output.items > 0, "no frames on {}", output.id);
+    }
+    Ok(())
+}
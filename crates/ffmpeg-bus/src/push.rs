@@ -0,0 +1,309 @@
+//! Programmatic input: frames the application produces itself (rendered
+//! overlays, panoramas stitched from other buses' frames) fed into a
+//! [`Bus`](crate::bus::Bus) like any other input, through the
+//! [`PushInputHandle`] that `add_input` returns for an
+//! [`InputConfig::Push`](crate::bus::InputConfig::Push).
+//!
+//! The input has a RAWVIDEO video stream (index 0) of the configured size,
+//! pixel format and frame rate, and, with [`PushAudio`], a PCM audio stream
+//! (index 1): each frame sent becomes one packet of its planes back to back,
+//! so every output works downstream of it as it does of a raw capture device.
+//! Timestamps are in the stream's time base, the inverse of the frame rate
+//! (video) or of the sample rate (audio); frames sent without one get the
+//! next after the last.
+//!
+//! Pushed packets are charged to a [`MemoryBudget`] of their own, a few
+//! frames large: once what the bus has not consumed outgrows it, the input
+//! stops taking packets and `send_frame` / `send_audio` wait, so a producer
+//! faster than the slowest lossless output (a file) is slowed down to it
+//! instead of growing memory. Live outputs drop frames as they do for any
+//! live source. Nothing is taken before the bus starts reading, i.e. before
+//! its first output is added.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use ffmpeg_next::codec::packet::{Flags, Packet};
+use ffmpeg_next::codec::{Id, Parameters};
+use ffmpeg_next::format::{Pixel, Sample, sample};
+use ffmpeg_next::{Rational, ffi};
+use tokio::sync::mpsc;
+
+use crate::frame::{RawAudioFrame, RawVideoFrame, is_hw_format, pack_planes, scale_video};
+use crate::memory::MemoryBudget;
+use crate::packet::RawPacket;
+use crate::stream::AvStream;
+
+/// Stream index of the pushed video.
+pub const VIDEO_INDEX: usize = 0;
+/// Stream index of the pushed audio, with [`PushAudio`].
+pub const AUDIO_INDEX: usize = 1;
+
+/// Video frames' worth of pushed media the bus may hold before
+/// `send_frame` waits.
+pub(crate) const QUEUE_FRAMES: usize = 8;
+/// Packets sent but not yet taken by the input.
+pub(crate) const CHANNEL_CAP: usize = 2;
+
+/// The audio stream of a push input. Frames sent must be in exactly this
+/// format; `sample_format` must be packed (interleaved).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PushAudio {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub sample_format: Sample,
+}
+
+/// Sends frames into a push input; dropping it (or [`Self::finish`]) ends the
+/// input once the bus has read what was sent.
+pub struct PushInputHandle {
+    tx: mpsc::Sender<RawPacket>,
+    video: AvStream,
+    pixel_format: Pixel,
+    audio: Option<(AvStream, PushAudio)>,
+    next_video_pts: AtomicI64,
+    next_audio_pts: AtomicI64,
+    budget: Arc<MemoryBudget>,
+}
+
+/// The input side of a push input, read by [`AvInput`](crate::input::AvInput).
+pub(crate) struct PushSource {
+    pub(crate) streams: HashMap<usize, AvStream>,
+    pub(crate) budget: Arc<MemoryBudget>,
+    rx: mpsc::Receiver<RawPacket>,
+}
+
+impl PushSource {
+    /// The next packet sent, blocking; `None` once the handle is gone and
+    /// every packet it sent has been read.
+    pub(crate) fn recv(&mut self) -> Option<RawPacket> {
+        self.rx.blocking_recv()
+    }
+}
+
+/// A push input of `width`x`height` `pixel_format` video at `frame_rate`,
+/// with `audio` if set.
+pub(crate) fn push_input(
+    width: u32,
+    height: u32,
+    pixel_format: Pixel,
+    frame_rate: Rational,
+    audio: Option<PushAudio>,
+) -> anyhow::Result<(PushInputHandle, PushSource)> {
+    if width == 0 || height == 0 {
+        anyhow::bail!("push input needs a picture size, got {width}x{height}");
+    }
+    if frame_rate.numerator() <= 0 || frame_rate.denominator() <= 0 {
+        anyhow::bail!("push input needs a positive frame rate, got {frame_rate}");
+    }
+    if pixel_format == Pixel::None || is_hw_format(pixel_format) {
+        anyhow::bail!("push input needs a software pixel format, got {pixel_format:?}");
+    }
+    let frame_size = unsafe {
+        ffi::av_image_get_buffer_size(pixel_format.into(), width as i32, height as i32, 1)
+    };
+    if frame_size <= 0 {
+        anyhow::bail!("unsupported push pixel format {pixel_format:?}");
+    }
+
+    let video = video_stream(width, height, pixel_format, frame_rate);
+    let mut streams = HashMap::from([(VIDEO_INDEX, video.clone())]);
+    let audio = match audio {
+        Some(config) => {
+            let stream = audio_stream(&config)?;
+            streams.insert(AUDIO_INDEX, stream.clone());
+            Some((stream, config))
+        }
+        None => None,
+    };
+    let budget = MemoryBudget::new(QUEUE_FRAMES * frame_size as usize);
+    let (tx, rx) = mpsc::channel(CHANNEL_CAP);
+    let handle = PushInputHandle {
+        tx,
+        video,
+        pixel_format,
+        audio,
+        next_video_pts: AtomicI64::new(0),
+        next_audio_pts: AtomicI64::new(0),
+        budget: budget.clone(),
+    };
+    Ok((
+        handle,
+        PushSource {
+            streams,
+            budget,
+            rx,
+        },
+    ))
+}
+
+fn video_stream(width: u32, height: u32, format: Pixel, frame_rate: Rational) -> AvStream {
+    let params = Parameters::new();
+    unsafe {
+        let ptr = params.as_ptr() as *mut ffi::AVCodecParameters;
+        (*ptr).codec_type = ffmpeg_next::media::Type::Video.into();
+        (*ptr).codec_id = Id::RAWVIDEO.into();
+        (*ptr).width = width as i32;
+        (*ptr).height = height as i32;
+        (*ptr).format = ffi::AVPixelFormat::from(format) as i32;
+        (*ptr).framerate = frame_rate.into();
+    }
+    AvStream::new(VIDEO_INDEX, params, frame_rate.invert(), frame_rate)
+}
+
+fn audio_stream(config: &PushAudio) -> anyhow::Result<AvStream> {
+    let codec = pcm_codec(config.sample_format).ok_or_else(|| {
+        anyhow::anyhow!(
+            "push audio needs a packed sample format, got {:?}",
+            config.sample_format
+        )
+    })?;
+    if config.sample_rate == 0 || config.channels == 0 {
+        anyhow::bail!(
+            "push audio needs a sample rate and channels, got {} Hz, {} channels",
+            config.sample_rate,
+            config.channels
+        );
+    }
+    let bytes = config.sample_format.bytes() as i32;
+    let params = Parameters::new();
+    unsafe {
+        let ptr = params.as_ptr() as *mut ffi::AVCodecParameters;
+        (*ptr).codec_type = ffmpeg_next::media::Type::Audio.into();
+        (*ptr).codec_id = codec.into();
+        (*ptr).format = ffi::AVSampleFormat::from(config.sample_format) as i32;
+        (*ptr).sample_rate = config.sample_rate as i32;
+        ffi::av_channel_layout_default(&mut (*ptr).ch_layout, config.channels as i32);
+        (*ptr).bits_per_coded_sample = bytes * 8;
+        (*ptr).block_align = bytes * config.channels as i32;
+    }
+    let time_base = Rational::new(1, config.sample_rate as i32);
+    Ok(AvStream::new(
+        AUDIO_INDEX,
+        params,
+        time_base,
+        time_base.invert(),
+    ))
+}
+
+/// The PCM codec carrying samples of a packed `format` as they are in memory.
+fn pcm_codec(format: Sample) -> Option<Id> {
+    let little = cfg!(target_endian = "little");
+    Some(match format {
+        Sample::U8(sample::Type::Packed) => Id::PCM_U8,
+        Sample::I16(sample::Type::Packed) if little => Id::PCM_S16LE,
+        Sample::I16(sample::Type::Packed) => Id::PCM_S16BE,
+        Sample::I32(sample::Type::Packed) if little => Id::PCM_S32LE,
+        Sample::I32(sample::Type::Packed) => Id::PCM_S32BE,
+        Sample::F32(sample::Type::Packed) if little => Id::PCM_F32LE,
+        Sample::F32(sample::Type::Packed) => Id::PCM_F32BE,
+        Sample::F64(sample::Type::Packed) if little => Id::PCM_F64LE,
+        Sample::F64(sample::Type::Packed) => Id::PCM_F64BE,
+        _ => return None,
+    })
+}
+
+/// The pts a frame gets: its own, else the next after the last one sent.
+/// `next` advances past it by `duration`.
+fn take_pts(next: &AtomicI64, pts: Option<i64>, duration: i64) -> i64 {
+    match pts {
+        Some(pts) => {
+            next.store(pts + duration, Ordering::Relaxed);
+            pts
+        }
+        None => next.fetch_add(duration, Ordering::Relaxed),
+    }
+}
+
+impl PushInputHandle {
+    /// The pushed video stream, as outputs see it.
+    pub fn video_stream(&self) -> &AvStream {
+        &self.video
+    }
+
+    /// Send one video frame, waiting while the bus is behind. A frame of
+    /// another size or pixel format is converted to the input's first. Its
+    /// pts, if any, counts frames (the stream's time base is one frame).
+    pub async fn send_frame(&self, frame: RawVideoFrame) -> anyhow::Result<()> {
+        let (width, height, format) = (self.video.width(), self.video.height(), self.pixel_format);
+        let picture = frame.as_video();
+        let data =
+            if (picture.width(), picture.height(), picture.format()) == (width, height, format) {
+                pack_planes(picture)?
+            } else {
+                pack_planes(&scale_video(picture, format, width, height)?)?
+            };
+        let pts = take_pts(&self.next_video_pts, frame.pts(), 1);
+        let mut packet = Packet::copy(&data);
+        packet.set_duration(1);
+        packet.set_flags(Flags::KEY);
+        self.send(packet, VIDEO_INDEX, pts, self.video.time_base())
+            .await
+    }
+
+    /// Send one audio frame, waiting while the bus is behind. It must be in
+    /// the input's [`PushAudio`] format; its pts, if any, counts samples.
+    pub async fn send_audio(&self, frame: RawAudioFrame) -> anyhow::Result<()> {
+        let Some((stream, config)) = &self.audio else {
+            anyhow::bail!("push input has no audio stream");
+        };
+        let audio = frame.as_audio();
+        let channels = unsafe { (*audio.as_ptr()).ch_layout.nb_channels } as u32;
+        if (audio.format(), audio.rate(), channels)
+            != (config.sample_format, config.sample_rate, config.channels)
+        {
+            anyhow::bail!(
+                "audio frame is {:?} {} Hz {} channels, the push input takes {:?} {} Hz {} channels",
+                audio.format(),
+                audio.rate(),
+                channels,
+                config.sample_format,
+                config.sample_rate,
+                config.channels
+            );
+        }
+        let samples = audio.samples();
+        let len = samples * channels as usize * config.sample_format.bytes();
+        let data = audio
+            .data(0)
+            .get(..len)
+            .ok_or_else(|| anyhow::anyhow!("audio frame holds fewer than {samples} samples"))?;
+        let pts = take_pts(&self.next_audio_pts, frame.pts(), samples as i64);
+        let mut packet = Packet::copy(data);
+        packet.set_duration(samples as i64);
+        packet.set_flags(Flags::KEY);
+        self.send(packet, AUDIO_INDEX, pts, stream.time_base())
+            .await
+    }
+
+    async fn send(
+        &self,
+        mut packet: Packet,
+        index: usize,
+        pts: i64,
+        time_base: Rational,
+    ) -> anyhow::Result<()> {
+        packet.set_stream(index);
+        packet.set_pts(Some(pts));
+        packet.set_dts(Some(pts));
+        let packet = RawPacket::with_budget(packet, time_base, &self.budget);
+        self.tx
+            .send(packet)
+            .await
+            .map_err(|_| anyhow::anyhow!("push input closed"))
+    }
+
+    /// Bytes of pushed media the bus holds that are not consumed yet, bounded
+    /// by the backpressure.
+    pub fn queued_bytes(&self) -> usize {
+        self.budget.used()
+    }
+
+    /// End the input: outputs finish once they have what was sent.
+    pub fn finish(self) {}
+}
+
+#[cfg(test)]
+#[path = "push_test.rs"]
+mod push_test;
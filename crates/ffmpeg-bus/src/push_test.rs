@@ -0,0 +1,185 @@
+use std::time::{Duration, Instant};
+
+use super::*;
+use crate::bus::{Bus, InputConfig, OutputAvType, OutputConfig, OutputDest};
+use crate::hook::{HookAction, PacketHook};
+use crate::metadata::probe;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// A YUV420P frame without a pts whose luma changes with `n`.
+fn synthetic_frame(n: usize) -> RawVideoFrame {
+    let mut frame = ffmpeg_next::frame::Video::new(Pixel::YUV420P, WIDTH, HEIGHT);
+    frame.data_mut(0).fill((n * 5 % 256) as u8);
+    frame.data_mut(1).fill(128);
+    frame.data_mut(2).fill(128);
+    RawVideoFrame::from(frame)
+}
+
+/// A bus pushing 25 fps frames into a File output at `path`.
+async fn push_to_file(
+    name: &str,
+    path: &str,
+    hook: Option<PacketHook>,
+) -> anyhow::Result<(Bus, PushInputHandle)> {
+    crate::init()?;
+    std::fs::remove_file(path).ok();
+    let bus = Bus::new(name);
+    let handle = bus
+        .add_input(
+            InputConfig::Push {
+                width: WIDTH,
+                height: HEIGHT,
+                pixel_format: Pixel::YUV420P,
+                frame_rate: Rational::new(25, 1),
+                audio: None,
+            },
+            None,
+        )
+        .await?
+        .expect("a push input comes with its handle");
+    let mut output = OutputConfig::new(
+        name.to_string(),
+        OutputAvType::Video,
+        OutputDest::File {
+            path: path.to_string(),
+        },
+    );
+    if let Some(hook) = hook {
+        output = output.with_packet_hook(hook);
+    }
+    bus.add_output(output).await?;
+    Ok((bus, handle))
+}
+
+/// Video packets of `path` once it has been finalized.
+async fn finished_packets(path: &str) -> anyhow::Result<usize> {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let counted = ffmpeg_next::format::input(path).ok().and_then(|mut input| {
+            let index = input
+                .streams()
+                .best(ffmpeg_next::media::Type::Video)?
+                .index();
+            let packets = input.packets().filter(|(s, _)| s.index() == index).count();
+            (packets > 0).then_some(packets)
+        });
+        if let Some(counted) = counted {
+            return Ok(counted);
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!("{path} was not finalized in time");
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_pushed_frames_are_encoded_like_any_input() -> anyhow::Result<()> {
+    let path = "output_push.mp4";
+    let (_bus, handle) = push_to_file("push", path, None).await?;
+    assert_eq!(handle.video_stream().time_base(), Rational::new(1, 25));
+    for n in 0..50 {
+        handle.send_frame(synthetic_frame(n)).await?;
+    }
+    handle.finish();
+
+    assert_eq!(finished_packets(path).await?, 50);
+    let info = probe(path)?;
+    let duration = info.format.duration_sec.unwrap_or_default();
+    assert!((duration - 2.0).abs() < 0.1, "duration {duration}s");
+    let video = &info.streams[0];
+    assert_eq!(
+        (video.codec_name.as_str(), video.width, video.height),
+        ("h264", Some(WIDTH), Some(HEIGHT))
+    );
+    std::fs::remove_file(path).ok();
+    Ok(())
+}
+
+/// An output writing a packet every 10 ms holds a pusher sending as fast as
+/// it can back: once the channels in between are full each send waits for a
+/// write, while what the bus holds of the pushed frames stays bounded.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_a_slow_output_holds_the_pusher_back() -> anyhow::Result<()> {
+    const FRAMES: usize = 240;
+    let path = "output_push_slow.mp4";
+    let hook: PacketHook = Box::new(|_| {
+        std::thread::sleep(Duration::from_millis(10));
+        HookAction::Keep
+    });
+    let (_bus, handle) = push_to_file("push_slow", path, Some(hook)).await?;
+    let mut waits = Vec::with_capacity(FRAMES);
+    let mut peak = 0;
+    for n in 0..FRAMES {
+        let started = Instant::now();
+        handle.send_frame(synthetic_frame(n)).await?;
+        waits.push(started.elapsed());
+        peak = peak.max(handle.queued_bytes());
+    }
+    handle.finish();
+
+    let first: Duration = waits[..40].iter().sum();
+    let last: Duration = waits[FRAMES - 40..].iter().sum();
+    assert!(
+        last >= Duration::from_millis(200) && last > first * 4,
+        "first 40 sends took {first:?}, the last 40 {last:?}"
+    );
+    let frame_bytes = (WIDTH * HEIGHT * 3 / 2) as usize;
+    assert!(
+        peak <= (QUEUE_FRAMES + CHANNEL_CAP + 2) * frame_bytes,
+        "{peak} bytes queued"
+    );
+    // Nothing was dropped on the way.
+    assert_eq!(finished_packets(path).await?, FRAMES);
+    std::fs::remove_file(path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_push_config_is_checked() {
+    let push =
+        |width, audio| push_input(width, HEIGHT, Pixel::YUV420P, Rational::new(25, 1), audio);
+    assert!(push(0, None).is_err());
+    let planar = PushAudio {
+        sample_rate: 48_000,
+        channels: 2,
+        sample_format: Sample::F32(sample::Type::Planar),
+    };
+    assert!(push(WIDTH, Some(planar)).is_err());
+    let (handle, source) = push(
+        WIDTH,
+        Some(PushAudio {
+            sample_format: Sample::I16(sample::Type::Packed),
+            ..planar
+        }),
+    )
+    .unwrap();
+    let audio = &source.streams[&AUDIO_INDEX];
+    assert_eq!(audio.parameters().id(), Id::PCM_S16LE);
+    assert_eq!((audio.sample_rate(), audio.channels()), (48_000, 2));
+
+    // Audio frames get pts that count samples; a frame of another format is
+    // refused.
+    let mut frame = ffmpeg_next::frame::Audio::new(
+        Sample::I16(sample::Type::Packed),
+        480,
+        ffmpeg_next::ChannelLayout::STEREO,
+    );
+    frame.set_rate(48_000);
+    handle
+        .send_audio(RawAudioFrame::from(frame.clone()))
+        .await
+        .unwrap();
+    handle.send_audio(RawAudioFrame::from(frame)).await.unwrap();
+    assert_eq!(handle.next_audio_pts.load(Ordering::Relaxed), 960);
+    let mut mono = ffmpeg_next::frame::Audio::new(
+        Sample::I16(sample::Type::Packed),
+        480,
+        ffmpeg_next::ChannelLayout::MONO,
+    );
+    mono.set_rate(48_000);
+    assert!(handle.send_audio(RawAudioFrame::from(mono)).await.is_err());
+    drop(source);
+}
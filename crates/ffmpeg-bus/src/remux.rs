@@ -0,0 +1,828 @@
+//! Offline remuxing of recorded files, without transcoding.
+//!
+//! [`remux_clip`] is the clip export: it copies the packets of consecutive
+//! recorded segments covering a wall clock window into one file. Every
+//! recorded segment restarts its timestamps, so each source carries the wall
+//! clock time it starts at; packets are placed on that clock and re-based
+//! onto the clip's origin, which gives one continuous timeline across
+//! segments. A remuxed clip can only start on a keyframe, so by default
+//! ([`TrimMode::KeyframeBefore`]) it begins at the last video keyframe at or
+//! before the requested start (up to a GOP early). [`TrimMode::Exact`] starts
+//! on the requested frame instead: the frames of that GOP from the start on
+//! are decoded and re-encoded, and the rest of the clip is copied after them.
+//! Either way packets at or after the requested end are dropped.
+//!
+//! [`remux_file`] copies one whole file into another container layout, by
+//! default a faststart MP4 (index before the media, which players seek in
+//! quickly); [`RemuxJob`] does that in place for a closed recording.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use ffmpeg_next::codec::{Id, Parameters};
+use ffmpeg_next::media::Type;
+use ffmpeg_next::{Packet, Rational, Rescale};
+
+const MICROS: Rational = Rational(1, 1_000_000);
+
+/// Where a clip starts relative to the requested start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrimMode {
+    /// At the last keyframe at or before it: pure copy, up to a GOP early.
+    #[default]
+    KeyframeBefore,
+    /// On the first frame at or after it. The partial GOP it falls in is
+    /// re-encoded (H.264 and H.265 only); where that cannot be done the clip
+    /// falls back to [`TrimMode::KeyframeBefore`] with a warning.
+    Exact,
+}
+
+/// One recorded file and where it sits on the wall clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipSource {
+    pub path: PathBuf,
+    /// Wall clock time of the file's start, Unix milliseconds.
+    pub start_ms: i64,
+}
+
+/// What [`remux_clip`] wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClipInfo {
+    /// Wall clock time of the clip's first frame, Unix milliseconds; at or
+    /// before the requested start, and within a frame of it for an exact
+    /// trim.
+    pub start_ms: i64,
+    /// Wall clock time of the last packet written, Unix milliseconds.
+    pub end_ms: i64,
+    pub packets: usize,
+}
+
+/// A packet read before the clip start, held until we know whether the clip
+/// starts at its GOP.
+struct Held {
+    packet: Packet,
+    out_index: usize,
+    time_base: Rational,
+    /// Wall clock pts/dts, microseconds.
+    pts_us: i64,
+    dts_us: i64,
+}
+
+struct Writer {
+    octx: ffmpeg_next::format::context::Output,
+    time_bases: Vec<Rational>,
+    last_dts: Vec<Option<i64>>,
+    origin_us: i64,
+    last_us: i64,
+    packets: usize,
+}
+
+impl Writer {
+    fn write(&mut self, held: Held) -> Result<()> {
+        let Held {
+            mut packet,
+            out_index,
+            time_base,
+            pts_us,
+            dts_us,
+        } = held;
+        let out_tb = self.time_bases[out_index];
+        let pts = (pts_us - self.origin_us).max(0);
+        let mut dts = (dts_us - self.origin_us).rescale(MICROS, out_tb);
+        // Keep dts strictly increasing per stream: rounding onto the output
+        // time base, or a segment boundary overlapping the previous file by a
+        // frame, must not make the muxer refuse the packet.
+        if let Some(last) = self.last_dts[out_index]
+            && dts <= last
+        {
+            dts = last + 1;
+        }
+        let pts = pts.rescale(MICROS, out_tb).max(dts);
+        self.last_dts[out_index] = Some(dts);
+        self.last_us = self.last_us.max(pts_us);
+        packet.set_duration(packet.duration().rescale(time_base, out_tb));
+        packet.set_pts(Some(pts));
+        packet.set_dts(Some(dts));
+        packet.set_position(-1);
+        packet.set_stream(out_index);
+        packet
+            .write_interleaved(&mut self.octx)
+            .context("write packet")?;
+        self.packets += 1;
+        Ok(())
+    }
+}
+
+/// Copy the video and audio of `sources` (consecutive, in wall clock order)
+/// between `start_ms` and `end_ms` (Unix milliseconds) into `output`; the
+/// container follows its extension. The stream layout and codecs are taken
+/// from the first source; streams of later sources that do not match are
+/// skipped.
+pub fn remux_clip(
+    sources: &[ClipSource],
+    start_ms: i64,
+    end_ms: i64,
+    output: &Path,
+) -> Result<ClipInfo> {
+    remux_clip_with(sources, start_ms, end_ms, output, TrimMode::default())
+}
+
+/// [`remux_clip`], starting the clip as `trim` says.
+pub fn remux_clip_with(
+    sources: &[ClipSource],
+    start_ms: i64,
+    end_ms: i64,
+    output: &Path,
+    trim: TrimMode,
+) -> Result<ClipInfo> {
+    anyhow::ensure!(end_ms > start_ms, "empty clip window {start_ms}..{end_ms}");
+    let first = sources.first().context("no recordings cover the clip")?;
+    let (start_us, end_us) = (start_ms * 1000, end_ms * 1000);
+
+    let layout = {
+        let ictx = ffmpeg_next::format::input(&first.path)
+            .with_context(|| format!("open {}", first.path.display()))?;
+        [Type::Video, Type::Audio]
+            .into_iter()
+            .filter_map(|medium| {
+                ictx.streams()
+                    .best(medium)
+                    .map(|s| (medium, s.parameters()))
+            })
+            .collect::<Vec<_>>()
+    };
+    anyhow::ensure!(
+        layout.iter().any(|(medium, _)| *medium == Type::Video),
+        "{} has no video stream",
+        first.path.display()
+    );
+
+    let mut octx = ffmpeg_next::format::output(output)
+        .with_context(|| format!("create {}", output.display()))?;
+    for (_, parameters) in &layout {
+        let mut ost = octx.add_stream(ffmpeg_next::encoder::find(parameters.id()))?;
+        ost.set_parameters(parameters.clone());
+        // The source container's codec tag may not be valid in the output
+        // one; let the muxer pick.
+        unsafe { (*(*ost.as_mut_ptr()).codecpar).codec_tag = 0 };
+    }
+    octx.write_header().context("write header")?;
+    let time_bases = (0..layout.len())
+        .map(|i| octx.stream(i).map(|s| s.time_base()).unwrap_or(MICROS))
+        .collect();
+
+    let mut writer = Writer {
+        octx,
+        time_bases,
+        last_dts: vec![None; layout.len()],
+        origin_us: 0,
+        last_us: start_us,
+        packets: 0,
+    };
+    let video_out = layout
+        .iter()
+        .position(|(medium, _)| *medium == Type::Video)
+        .unwrap_or_default();
+    let splice = Splice {
+        parameters: &layout[video_out].1,
+        video_out,
+        start_us,
+    };
+    // Packets since the last video keyframe before the clip start; `None`
+    // once the clip has started. An exact trim holds on to the whole GOP the
+    // start falls in, up to the next keyframe.
+    let mut gop: Option<Vec<Held>> = Some(Vec::new());
+
+    'sources: for source in sources {
+        let source_us = source.start_ms * 1000;
+        if source_us >= end_us {
+            break;
+        }
+        let mut ictx = match ffmpeg_next::format::input(&source.path) {
+            Ok(ictx) => ictx,
+            Err(e) => {
+                tracing::warn!("remux: skip {}: {e}", source.path.display());
+                continue;
+            }
+        };
+        let file_start = unsafe { (*ictx.as_ptr()).start_time };
+        let file_start_us = if file_start == ffmpeg_next::ffi::AV_NOPTS_VALUE {
+            0
+        } else {
+            file_start
+        };
+        // Input stream index -> output stream index.
+        let mut mapping = vec![None; ictx.nb_streams() as usize];
+        for (out_index, (medium, parameters)) in layout.iter().enumerate() {
+            if let Some(stream) = ictx.streams().best(*medium)
+                && stream.parameters().id() == parameters.id()
+            {
+                mapping[stream.index()] = Some(out_index);
+            }
+        }
+
+        for (stream, packet) in ictx.packets() {
+            let Some(out_index) = mapping.get(stream.index()).copied().flatten() else {
+                continue;
+            };
+            let time_base = stream.time_base();
+            let Some(dts) = packet.dts().or(packet.pts()) else {
+                continue;
+            };
+            let pts = packet.pts().unwrap_or(dts);
+            let wall = |ts: i64| source_us + ts.rescale(time_base, MICROS) - file_start_us;
+            let held = Held {
+                pts_us: wall(pts),
+                dts_us: wall(dts),
+                packet,
+                out_index,
+                time_base,
+            };
+            if held.pts_us >= end_us {
+                if out_index == video_out {
+                    break 'sources;
+                }
+                continue;
+            }
+            let Some(pending) = gop.as_mut() else {
+                writer.write(held)?;
+                continue;
+            };
+            let is_video = out_index == video_out;
+            let reaches_start = is_video && held.pts_us >= start_us;
+            // Exact: a GOP with no frame from the start on is not the clip's.
+            let holds_start = trim == TrimMode::KeyframeBefore
+                || pending
+                    .iter()
+                    .any(|h| h.out_index == video_out && h.pts_us >= start_us);
+            if is_video && held.packet.is_key() {
+                if held.pts_us > start_us && !pending.is_empty() && holds_start {
+                    // The clip starts with the GOP held so far.
+                    let mut held = held;
+                    match trim {
+                        TrimMode::KeyframeBefore => start(&mut writer, &mut gop)?,
+                        TrimMode::Exact => {
+                            start_exact(&mut writer, &mut gop, Some(&mut held), &splice)?
+                        }
+                    }
+                    writer.write(held)?;
+                    continue;
+                }
+                pending.clear();
+                pending.push(held);
+            } else if !pending.is_empty() {
+                pending.push(held);
+            }
+            // A GOP starting on the requested start needs no re-encoding.
+            let starts_here = gop
+                .as_ref()
+                .and_then(|p| p.first())
+                .is_some_and(|key| trim == TrimMode::KeyframeBefore || key.pts_us >= start_us);
+            if reaches_start && starts_here {
+                start(&mut writer, &mut gop)?;
+            }
+        }
+    }
+    // An exact clip ending within the GOP its start falls in.
+    if trim == TrimMode::Exact
+        && gop.as_ref().is_some_and(|p| {
+            p.iter()
+                .any(|h| h.out_index == video_out && h.pts_us >= start_us)
+        })
+    {
+        start_exact(&mut writer, &mut gop, None, &splice)?;
+    }
+
+    anyhow::ensure!(
+        writer.packets > 0,
+        "no footage between {start_ms} and {end_ms}"
+    );
+    writer.octx.write_trailer().context("write trailer")?;
+    Ok(ClipInfo {
+        start_ms: writer.origin_us.div_euclid(1000),
+        end_ms: writer.last_us.div_euclid(1000),
+        packets: writer.packets,
+    })
+}
+
+/// Begin the clip at the held GOP's keyframe and flush it.
+fn start(writer: &mut Writer, gop: &mut Option<Vec<Held>>) -> Result<()> {
+    let held = gop.take().unwrap_or_default();
+    writer.origin_us = held.first().map(|h| h.pts_us).unwrap_or_default();
+    for packet in held {
+        // Audio read before the keyframe would get negative timestamps.
+        if packet.pts_us >= writer.origin_us {
+            writer.write(packet)?;
+        }
+    }
+    Ok(())
+}
+
+/// What an exact trim re-encodes with: the clip's video layout and start.
+struct Splice<'a> {
+    parameters: &'a Parameters,
+    video_out: usize,
+    start_us: i64,
+}
+
+/// Begin the clip on the first frame at or after the requested start: the
+/// held GOP's frames from there on are re-encoded, and `next_key`, the first
+/// copied keyframe after them, gets the source's parameter sets in band so
+/// decoding switches back to them. Falls back to [`start`] if the GOP cannot
+/// be re-encoded.
+fn start_exact(
+    writer: &mut Writer,
+    gop: &mut Option<Vec<Held>>,
+    next_key: Option<&mut Held>,
+    splice: &Splice,
+) -> Result<()> {
+    let held = gop.take().unwrap_or_default();
+    // Re-encoded frames come without reordering; their dts lag the pts by as
+    // much as the copied ones do, so dts keep increasing across the splice.
+    let delay_us = next_key
+        .as_ref()
+        .map(|key| (key.pts_us - key.dts_us).max(0))
+        .unwrap_or_default();
+    let head = match reencode_head(&held, splice, delay_us) {
+        Ok(head) => head,
+        Err(e) => {
+            tracing::warn!("remux: cannot trim exactly, starting at the keyframe before: {e:#}");
+            *gop = Some(held);
+            return start(writer, gop);
+        }
+    };
+    if let Some(key) = next_key
+        && let Some(data) = key.packet.data()
+    {
+        let mut packet = Packet::copy(&[head.parameter_sets.as_slice(), data].concat());
+        packet.set_flags(key.packet.flags());
+        packet.set_duration(key.packet.duration());
+        key.packet = packet;
+    }
+    writer.origin_us = head.packets.first().map(|h| h.pts_us).unwrap_or_default();
+    let audio = held.into_iter().filter(|h| h.out_index != splice.video_out);
+    for packet in head.packets.into_iter().chain(audio) {
+        if packet.pts_us >= writer.origin_us {
+            writer.write(packet)?;
+        }
+    }
+    Ok(())
+}
+
+/// The re-encoded start of an exact clip.
+struct Head {
+    /// Video packets from the requested start, framed as the source's.
+    packets: Vec<Held>,
+    /// The source's parameter sets, framed as its packets.
+    parameter_sets: Vec<u8>,
+}
+
+/// Decode the video of `held` (a GOP, keyframe first) and re-encode its
+/// frames at or after the splice start with an encoder matching the source.
+fn reencode_head(held: &[Held], splice: &Splice, delay_us: i64) -> Result<Head> {
+    let parameters = splice.parameters;
+    let id = parameters.id();
+    anyhow::ensure!(
+        matches!(id, Id::H264 | Id::HEVC),
+        "{id:?} is not re-encoded"
+    );
+    let framing = NalFraming::of(
+        id,
+        crate::bsf::get_extradata(parameters).unwrap_or_default(),
+    )?;
+    let video = held
+        .iter()
+        .filter(|h| h.out_index == splice.video_out)
+        .collect::<Vec<_>>();
+    let frame_us = video
+        .iter()
+        .map(|h| h.packet.duration().rescale(h.time_base, MICROS))
+        .find(|d| *d > 0)
+        .unwrap_or(40_000);
+
+    let mut context = ffmpeg_next::codec::Context::from_parameters(parameters.clone())?;
+    // Packets are fed with wall clock timestamps.
+    unsafe { (*context.as_mut_ptr()).pkt_timebase = MICROS.into() };
+    let mut decoder = context.decoder().video().context("open decoder")?;
+    let mut encoder = None;
+    let mut packets = Vec::new();
+    let mut frame = ffmpeg_next::frame::Video::empty();
+    for (i, source) in video.iter().enumerate() {
+        let mut packet = source.packet.clone();
+        packet.set_pts(Some(source.pts_us));
+        packet.set_dts(Some(source.dts_us));
+        decoder.send_packet(&packet).context("decode")?;
+        if i + 1 == video.len() {
+            decoder.send_eof()?;
+        }
+        while decoder.receive_frame(&mut frame).is_ok() {
+            let Some(pts) = frame.timestamp().or(frame.pts()) else {
+                continue;
+            };
+            if pts < splice.start_us {
+                continue;
+            }
+            let encoder = match encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => encoder.insert(open_encoder(id, parameters, &frame, frame_us)?),
+            };
+            frame.set_pts(Some(pts));
+            frame.set_kind(ffmpeg_next::picture::Type::None);
+            encoder.send_frame(&frame).context("encode")?;
+            receive_packets(encoder, &framing, splice, frame_us, delay_us, &mut packets)?;
+        }
+    }
+    let mut encoder = encoder.context("no frames decoded from the requested start")?;
+    encoder.send_eof()?;
+    receive_packets(
+        &mut encoder,
+        &framing,
+        splice,
+        frame_us,
+        delay_us,
+        &mut packets,
+    )?;
+    anyhow::ensure!(
+        packets.first().is_some_and(|h| h.packet.is_key()),
+        "the re-encoded GOP does not start on a keyframe"
+    );
+    Ok(Head {
+        packets,
+        parameter_sets: framing.frame(&framing.parameter_sets),
+    })
+}
+
+/// An encoder for the source codec at the decoded frames' size and format,
+/// on wall clock microseconds. One GOP without B-frames, and no global
+/// header: its own parameter sets go in band, ahead of its keyframe.
+fn open_encoder(
+    id: Id,
+    parameters: &Parameters,
+    frame: &ffmpeg_next::frame::Video,
+    frame_us: i64,
+) -> Result<ffmpeg_next::codec::encoder::Video> {
+    let codec = ffmpeg_next::encoder::find(id).with_context(|| format!("no {id:?} encoder"))?;
+    let mut encoder = ffmpeg_next::codec::Context::new_with_codec(codec)
+        .encoder()
+        .video()?;
+    encoder.set_width(frame.width());
+    encoder.set_height(frame.height());
+    encoder.set_format(frame.format());
+    encoder.set_time_base(MICROS);
+    encoder.set_frame_rate(Some(Rational(1_000_000, frame_us.max(1) as i32)));
+    encoder.set_gop(u32::MAX >> 1);
+    encoder.set_max_b_frames(0);
+    let mut options = ffmpeg_next::Dictionary::new();
+    options.set("preset", "veryfast");
+    let bit_rate = unsafe { (*parameters.as_ptr()).bit_rate };
+    if bit_rate > 0 {
+        encoder.set_bit_rate(bit_rate as usize);
+    } else {
+        options.set("crf", "18");
+    }
+    encoder
+        .open_with(options)
+        .with_context(|| format!("open {} encoder", codec.name()))
+}
+
+fn receive_packets(
+    encoder: &mut ffmpeg_next::codec::encoder::Video,
+    framing: &NalFraming,
+    splice: &Splice,
+    frame_us: i64,
+    delay_us: i64,
+    packets: &mut Vec<Held>,
+) -> Result<()> {
+    let mut encoded = Packet::empty();
+    while encoder.receive_packet(&mut encoded).is_ok() {
+        let (Some(pts), Some(data)) = (encoded.pts(), encoded.data()) else {
+            continue;
+        };
+        let mut packet = Packet::copy(&framing.frame(&annexb_units(data)));
+        packet.set_flags(encoded.flags());
+        packet.set_duration(frame_us);
+        packets.push(Held {
+            packet,
+            out_index: splice.video_out,
+            time_base: MICROS,
+            pts_us: pts,
+            dts_us: encoded.dts().unwrap_or(pts) - delay_us,
+        });
+    }
+    Ok(())
+}
+
+/// How a source's H.264/H.265 packets carry their NAL units, and its
+/// parameter sets (SPS, PPS and for H.265 VPS) from the extradata.
+pub(crate) struct NalFraming {
+    /// Bytes of the big-endian length before each unit (avcC/hvcC);
+    /// `None` for Annex B start codes.
+    length_size: Option<usize>,
+    pub(crate) parameter_sets: Vec<Vec<u8>>,
+}
+
+impl NalFraming {
+    pub(crate) fn of(id: Id, extradata: &[u8]) -> Result<Self> {
+        if extradata.first() != Some(&1) {
+            return Ok(Self {
+                length_size: None,
+                parameter_sets: annexb_units(extradata)
+                    .into_iter()
+                    .map(<[u8]>::to_vec)
+                    .collect(),
+            });
+        }
+        let mut reader = Reader(extradata);
+        let mut parameter_sets = Vec::new();
+        let length_size;
+        if id == Id::H264 {
+            // avcC: version, profile, compatibility, level, length size,
+            // then the SPS and the PPS each behind their count.
+            length_size = (reader.bytes(5)?[4] & 3) as usize + 1;
+            let sps = reader.bytes(1)?[0] & 0x1f;
+            for _ in 0..sps {
+                parameter_sets.push(reader.unit()?.to_vec());
+            }
+            let pps = reader.bytes(1)?[0];
+            for _ in 0..pps {
+                parameter_sets.push(reader.unit()?.to_vec());
+            }
+        } else {
+            // hvcC: 22 bytes of profile and format, the length size last,
+            // then arrays of units by type.
+            length_size = (reader.bytes(22)?[21] & 3) as usize + 1;
+            let arrays = reader.bytes(1)?[0];
+            for _ in 0..arrays {
+                let count = reader.bytes(3)?;
+                for _ in 0..u16::from_be_bytes([count[1], count[2]]) {
+                    parameter_sets.push(reader.unit()?.to_vec());
+                }
+            }
+        }
+        anyhow::ensure!(
+            !parameter_sets.is_empty(),
+            "no parameter sets in the extradata"
+        );
+        Ok(Self {
+            length_size: Some(length_size),
+            parameter_sets,
+        })
+    }
+
+    /// Bytes of the length before each unit; `None` for Annex B.
+    pub(crate) fn length_size(&self) -> Option<usize> {
+        self.length_size
+    }
+
+    /// `units` as packet data of the source.
+    fn frame<T: AsRef<[u8]>>(&self, units: &[T]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for unit in units.iter().map(AsRef::as_ref) {
+            match self.length_size {
+                Some(size) => out.extend_from_slice(&(unit.len() as u32).to_be_bytes()[4 - size..]),
+                None => out.extend_from_slice(&[0, 0, 0, 1]),
+            }
+            out.extend_from_slice(unit);
+        }
+        out
+    }
+}
+
+/// Reads avcC/hvcC fields front to back.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        anyhow::ensure!(self.0.len() >= len, "truncated extradata");
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    /// A unit behind its 16-bit length.
+    fn unit(&mut self) -> Result<&'a [u8]> {
+        let len = self.bytes(2)?;
+        self.bytes(u16::from_be_bytes([len[0], len[1]]) as usize)
+    }
+}
+
+/// The NAL units of Annex B `data`, without their start codes.
+pub(crate) fn annexb_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, &start)| {
+            let end = starts.get(n + 1).map(|next| next - 3).unwrap_or(data.len());
+            // A four-byte start code leaves a zero on the unit before.
+            let mut unit = &data[start..end.max(start)];
+            while starts.get(n + 1).is_some() && unit.last() == Some(&0) {
+                unit = &unit[..unit.len() - 1];
+            }
+            unit
+        })
+        .filter(|unit| !unit.is_empty())
+        .collect()
+}
+
+/// How [`remux_file`] writes its output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemuxOptions {
+    /// Container of the output; `None` guesses it from the file extension.
+    pub format: Option<String>,
+    /// MP4/MOV `movflags`; `+faststart` (the default) moves the index in front
+    /// of the media in a second pass once everything is written. Ignored by
+    /// other containers.
+    pub movflags: Option<String>,
+}
+
+impl Default for RemuxOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            movflags: Some("+faststart".to_string()),
+        }
+    }
+}
+
+/// What [`remux_file`] wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemuxSummary {
+    pub streams: usize,
+    /// Packets copied, per stream.
+    pub packets: Vec<usize>,
+    /// From the earliest to the latest packet end, over all streams.
+    pub duration: Duration,
+    /// Size of the output file.
+    pub bytes: u64,
+}
+
+/// Copy every stream and packet of `src` into `dst`, unchanged: timestamps,
+/// container and stream metadata (`creation_time` included) are kept. The
+/// output is synced to disk before this returns. Fails, without writing
+/// anything, if a stream's codec cannot be stored in the output container.
+pub fn remux_file(src: &Path, dst: &Path, options: &RemuxOptions) -> Result<RemuxSummary> {
+    let mut ictx =
+        ffmpeg_next::format::input(src).with_context(|| format!("open {}", src.display()))?;
+    let mut octx = match &options.format {
+        Some(format) => ffmpeg_next::format::output_as(dst, format),
+        None => ffmpeg_next::format::output(dst),
+    }
+    .with_context(|| format!("create {}", dst.display()))?;
+
+    let format_name = octx.format().name().to_string();
+    let mut time_bases = Vec::new();
+    for stream in ictx.streams() {
+        let parameters = stream.parameters();
+        let supported = unsafe {
+            ffmpeg_next::ffi::avformat_query_codec(
+                (*octx.as_ptr()).oformat,
+                parameters.id().into(),
+                0, // FF_COMPLIANCE_NORMAL
+            )
+        };
+        // Negative: the muxer cannot tell, so let it try.
+        if supported == 0 {
+            drop(octx);
+            let _ = std::fs::remove_file(dst);
+            anyhow::bail!(
+                "{} cannot hold stream {} ({:?}) of {}",
+                format_name,
+                stream.index(),
+                parameters.id(),
+                src.display()
+            );
+        }
+        let mut ost = octx.add_stream(ffmpeg_next::encoder::find(parameters.id()))?;
+        ost.set_parameters(parameters);
+        ost.set_time_base(stream.time_base());
+        ost.set_metadata(stream.metadata().to_owned());
+        // The source container's codec tag may not be valid in the output
+        // one; let the muxer pick.
+        unsafe { (*(*ost.as_mut_ptr()).codecpar).codec_tag = 0 };
+        time_bases.push(stream.time_base());
+    }
+    octx.set_metadata(ictx.metadata().to_owned());
+
+    let mut muxer_options = ffmpeg_next::Dictionary::new();
+    if let Some(movflags) = &options.movflags
+        && matches!(format_name.as_str(), "mp4" | "mov" | "ipod" | "ismv")
+    {
+        muxer_options.set("movflags", movflags);
+    }
+    octx.write_header_with(muxer_options)
+        .context("write header")?;
+    let out_time_bases = (0..time_bases.len())
+        .map(|i| octx.stream(i).map(|s| s.time_base()).unwrap_or(MICROS))
+        .collect::<Vec<_>>();
+
+    let mut packets = vec![0; time_bases.len()];
+    let (mut first_us, mut end_us) = (i64::MAX, i64::MIN);
+    for (stream, mut packet) in ictx.packets() {
+        let index = stream.index();
+        let (in_tb, out_tb) = (time_bases[index], out_time_bases[index]);
+        if let Some(ts) = packet.pts().or(packet.dts()) {
+            let start = ts.rescale(in_tb, MICROS);
+            first_us = first_us.min(start);
+            end_us = end_us.max(start + packet.duration().rescale(in_tb, MICROS));
+        }
+        packet.rescale_ts(in_tb, out_tb);
+        packet.set_position(-1);
+        packet.set_stream(index);
+        packet
+            .write_interleaved(&mut octx)
+            .context("write packet")?;
+        packets[index] += 1;
+    }
+    octx.write_trailer().context("write trailer")?;
+    drop(octx);
+
+    let file = std::fs::File::open(dst)?;
+    file.sync_all()
+        .with_context(|| format!("sync {}", dst.display()))?;
+    Ok(RemuxSummary {
+        streams: packets.len(),
+        packets,
+        duration: Duration::from_micros((end_us - first_us).max(0) as u64),
+        bytes: file.metadata()?.len(),
+    })
+}
+
+/// Rewrite a closed recording in place (by default as a faststart MP4): it is
+/// remuxed next to itself and the result renamed over it, so readers see
+/// either the old or the new file. On failure the original is left as is.
+#[derive(Debug, Clone)]
+pub struct RemuxJob {
+    pub path: PathBuf,
+    pub options: RemuxOptions,
+}
+
+impl RemuxJob {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            options: RemuxOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: RemuxOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Remux and replace the file; blocks until done.
+    pub fn run(&self) -> Result<RemuxSummary> {
+        let name = self
+            .path
+            .file_name()
+            .with_context(|| format!("{} is not a file", self.path.display()))?
+            .to_string_lossy();
+        let tmp = self.path.with_file_name(format!(".{name}.remux.tmp"));
+        // The temporary name has no usable extension.
+        let mut options = self.options.clone();
+        if options.format.is_none() {
+            options.format = self
+                .path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+        }
+        let summary = remux_file(&self.path, &tmp, &options).and_then(|summary| {
+            std::fs::rename(&tmp, &self.path)?;
+            Ok(summary)
+        });
+        match summary {
+            Ok(summary) => {
+                // Make the rename itself durable (best-effort).
+                if let Some(dir) = self.path.parent()
+                    && let Ok(dir) = std::fs::File::open(dir)
+                {
+                    let _ = dir.sync_all();
+                }
+                Ok(summary)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp);
+                Err(e.context(format!("remux {}", self.path.display())))
+            }
+        }
+    }
+
+    /// Queue the job on tokio's blocking pool.
+    pub fn spawn(self) -> tokio::task::JoinHandle<Result<RemuxSummary>> {
+        tokio::task::spawn_blocking(move || self.run())
+    }
+}
+
+#[cfg(test)]
+#[path = "remux_test.rs"]
+mod remux_test;
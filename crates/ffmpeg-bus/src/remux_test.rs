@@ -0,0 +1,264 @@
+use std::path::{Path, PathBuf};
+
+use ffmpeg_next::Rescale;
+
+use super::*;
+use crate::metadata::probe;
+
+/// The fixture twice back to back, as two recorded segments starting at wall
+/// clock 0. Returns the sources and the file's length in ms.
+async fn two_segments() -> (Vec<ClipSource>, i64) {
+    use crate::fixture::{FixtureSpec, ensure_fixture};
+
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let duration_sec = probe(path.to_str().unwrap()).unwrap().format.duration_sec;
+    let length_ms = (duration_sec.unwrap() * 1000.0) as i64;
+    let sources = vec![
+        ClipSource {
+            path: path.clone(),
+            start_ms: 0,
+        },
+        ClipSource {
+            path,
+            start_ms: length_ms,
+        },
+    ];
+    (sources, length_ms)
+}
+
+fn clip_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn test_clip_spans_segments() -> anyhow::Result<()> {
+    let (sources, length_ms) = two_segments().await;
+    let output = clip_path("remux_spans_segments.mp4");
+    let (start_ms, end_ms) = (length_ms / 2, length_ms + length_ms / 2);
+
+    let info = remux_clip(&sources, start_ms, end_ms, &output)?;
+    // Starts on the keyframe at or before the requested start, ends before
+    // the requested end, and crosses into the second segment.
+    assert!(info.start_ms <= start_ms, "{info:?}");
+    assert!(info.end_ms < end_ms && info.end_ms > length_ms, "{info:?}");
+
+    let probed = probe(output.to_str().unwrap())?;
+    let duration_ms = (probed.format.duration_sec.unwrap() * 1000.0) as i64;
+    let expected = info.end_ms - info.start_ms;
+    assert!(
+        (duration_ms - expected).abs() <= 500,
+        "clip lasts {duration_ms} ms, expected about {expected} ms"
+    );
+    assert!(probed.streams.iter().any(|s| s.codec_type == "video"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_clip_outside_footage_fails() {
+    let (sources, length_ms) = two_segments().await;
+    let output = clip_path("remux_outside.mp4");
+    let after = 2 * length_ms + 1000;
+    assert!(remux_clip(&sources, after, after + 1000, &output).is_err());
+    assert!(remux_clip(&sources, 1000, 1000, &output).is_err());
+    assert!(remux_clip(&[], 0, 1000, &output).is_err());
+}
+
+/// Types of the top-level MP4 boxes of `path`, in file order.
+fn top_level_boxes(path: &Path) -> Vec<String> {
+    let data = std::fs::read(path).unwrap();
+    let mut boxes = Vec::new();
+    let mut offset = 0usize;
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        boxes.push(String::from_utf8_lossy(&data[offset + 4..offset + 8]).into_owned());
+        let size = match size {
+            0 => data.len() - offset,
+            1 => u64::from_be_bytes(data[offset + 8..offset + 16].try_into().unwrap()) as usize,
+            size => size,
+        };
+        offset += size.max(8);
+    }
+    boxes
+}
+
+/// Packet count and total duration (microseconds) of each stream of `path`.
+fn packet_layout(path: &Path) -> Vec<(usize, i64)> {
+    let mut ictx = ffmpeg_next::format::input(path).unwrap();
+    let mut layout = vec![(0, 0); ictx.nb_streams() as usize];
+    for (stream, packet) in ictx.packets() {
+        let entry = &mut layout[stream.index()];
+        entry.0 += 1;
+        entry.1 += packet.duration().rescale(stream.time_base(), MICROS);
+    }
+    layout
+}
+
+fn position(boxes: &[String], name: &str) -> usize {
+    boxes
+        .iter()
+        .position(|b| b == name)
+        .unwrap_or_else(|| panic!("no {name} box in {boxes:?}"))
+}
+
+#[tokio::test]
+async fn test_fragmented_segment_becomes_faststart() -> anyhow::Result<()> {
+    use crate::fixture::{FixtureSpec, ensure_fixture};
+
+    let fixture = ensure_fixture(&FixtureSpec::default()).await?;
+    let segment = clip_path("remux_fragmented_segment.mp4");
+    // A crash-safe recording: fragmented, index up front and per fragment.
+    let fragmented = RemuxOptions {
+        movflags: Some("frag_keyframe+empty_moov".to_string()),
+        ..RemuxOptions::default()
+    };
+    let summary = remux_file(&fixture, &segment, &fragmented)?;
+    assert_eq!(summary.streams, 2);
+    assert!(top_level_boxes(&segment).contains(&"moof".to_string()));
+    let before = packet_layout(&segment);
+    assert_eq!(
+        summary.packets,
+        before.iter().map(|s| s.0).collect::<Vec<_>>()
+    );
+
+    let summary = RemuxJob::new(&segment).run()?;
+    let boxes = top_level_boxes(&segment);
+    assert!(!boxes.contains(&"moof".to_string()), "{boxes:?}");
+    assert!(
+        position(&boxes, "moov") < position(&boxes, "mdat"),
+        "{boxes:?}"
+    );
+    assert_eq!(packet_layout(&segment), before);
+    assert_eq!(summary.bytes, std::fs::metadata(&segment)?.len());
+    assert!(
+        (summary.duration.as_secs_f64() - 5.0).abs() < 0.5,
+        "{summary:?}"
+    );
+    // No temporary file is left behind.
+    let dir = segment.parent().unwrap();
+    assert!(!dir.join(".remux_fragmented_segment.mp4.remux.tmp").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_remux_refuses_codecs_the_container_cannot_hold() -> anyhow::Result<()> {
+    use crate::fixture::{FixtureSpec, ensure_fixture};
+
+    let fixture = ensure_fixture(&FixtureSpec::default()).await?;
+    let output = clip_path("remux_refused.wav");
+    let err = remux_file(&fixture, &output, &RemuxOptions::default()).unwrap_err();
+    assert!(format!("{err:#}").contains("cannot hold"), "{err:#}");
+    assert!(!output.exists());
+
+    // A job that fails keeps the original untouched.
+    let copy = clip_path("remux_keep_original.mp4");
+    std::fs::copy(&fixture, &copy)?;
+    let job = RemuxJob::new(&copy).with_options(RemuxOptions {
+        format: Some("wav".to_string()),
+        ..RemuxOptions::default()
+    });
+    assert!(job.run().is_err());
+    assert_eq!(std::fs::read(&copy)?, std::fs::read(&fixture)?);
+    Ok(())
+}
+
+/// Timestamps (ms) of the video frames decoded from `path`, in display order.
+fn decoded_frame_ms(path: &Path) -> anyhow::Result<Vec<i64>> {
+    let mut ictx = ffmpeg_next::format::input(path)?;
+    let stream = ictx
+        .streams()
+        .best(Type::Video)
+        .context("no video stream")?;
+    let (index, time_base) = (stream.index(), stream.time_base());
+    let mut decoder = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+    let mut frames = Vec::new();
+    let mut frame = ffmpeg_next::frame::Video::empty();
+    let mut drain = |decoder: &mut ffmpeg_next::decoder::Video| {
+        while decoder.receive_frame(&mut frame).is_ok() {
+            if let Some(ts) = frame.timestamp() {
+                frames.push(ts.rescale(time_base, Rational(1, 1000)));
+            }
+        }
+    };
+    for (stream, packet) in ictx.packets() {
+        if stream.index() == index {
+            decoder.send_packet(&packet)?;
+            drain(&mut decoder);
+        }
+    }
+    decoder.send_eof()?;
+    drain(&mut decoder);
+    Ok(frames)
+}
+
+#[tokio::test]
+async fn test_exact_trim_starts_on_the_requested_frame() -> anyhow::Result<()> {
+    use crate::fixture::{FixtureSpec, ensure_fixture};
+
+    // 10 fps with keyframes at 0s and 5s only.
+    let fixture = ensure_fixture(&FixtureSpec {
+        duration_secs: 10,
+        keyframe_interval: 50,
+        ..FixtureSpec::default()
+    })
+    .await?;
+    let sources = [ClipSource {
+        path: fixture,
+        start_ms: 0,
+    }];
+    let (start_ms, end_ms) = (3300, 7700);
+
+    let copied = clip_path("remux_trim_keyframe.mp4");
+    let info = remux_clip_with(
+        &sources,
+        start_ms,
+        end_ms,
+        &copied,
+        TrimMode::KeyframeBefore,
+    )?;
+    assert!(info.start_ms < 100, "{info:?}");
+    assert_eq!(decoded_frame_ms(&copied)?.len(), 77);
+
+    let exact = clip_path("remux_trim_exact.mp4");
+    let info = remux_clip_with(&sources, start_ms, end_ms, &exact, TrimMode::Exact)?;
+    assert!((info.start_ms - start_ms).abs() < 100, "{info:?}");
+    assert!(info.end_ms < end_ms, "{info:?}");
+    // 3.3s..7.7s is 44 frames, the first at the clip's start, evenly spaced
+    // across the splice onto the copied GOP at 5s.
+    let frames = decoded_frame_ms(&exact)?;
+    assert_eq!(frames.len(), 44, "{frames:?}");
+    assert!(frames[0].abs() < 100, "{frames:?}");
+    assert!(
+        frames.windows(2).all(|w| (w[1] - w[0] - 100).abs() <= 1),
+        "{frames:?}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_nal_units_are_reframed() {
+    // avcC with a 4-byte length size, one SPS and one PPS.
+    let avcc = [
+        1, 0x64, 0, 0x1f, 0xff, 0xe1, 0, 2, 0x67, 0xaa, 1, 0, 1, 0x68,
+    ];
+    let framing = NalFraming::of(Id::H264, &avcc).unwrap();
+    assert_eq!(framing.parameter_sets, [vec![0x67, 0xaa], vec![0x68]]);
+    let annexb = [0, 0, 0, 1, 0x65, 0x88, 0, 0, 1, 0x06, 0x05];
+    assert_eq!(
+        annexb_units(&annexb),
+        [&[0x65, 0x88][..], &[0x06, 0x05][..]]
+    );
+    assert_eq!(
+        framing.frame(&annexb_units(&annexb)),
+        [0, 0, 0, 2, 0x65, 0x88, 0, 0, 0, 2, 0x06, 0x05]
+    );
+    assert!(NalFraming::of(Id::H264, &avcc[..9]).is_err());
+
+    // Annex B extradata keeps its start codes.
+    let framing = NalFraming::of(Id::H264, &annexb).unwrap();
+    assert_eq!(framing.length_size, None);
+    assert_eq!(framing.frame(&[[0x68]]), [0, 0, 0, 1, 0x68]);
+}
@@ -1,3 +1,8 @@
+use std::collections::VecDeque;
+
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling::{Context, flag::Flags};
+
 pub struct Scaler {
     context: ffmpeg_next::software::scaling::Context,
 }
@@ -7,6 +12,23 @@ impl Scaler {
         Self { context }
     }
 
+    /// A scaler for `key`: bilinear when it resizes, reading a full-range
+    /// source as such.
+    pub fn for_key(key: &ScalerKey) -> anyhow::Result<Self> {
+        let (src, (sw, sh)) = (key.src.0, (key.src.1, key.src.2));
+        let (dst, (dw, dh)) = (key.dst.0, (key.dst.1, key.dst.2));
+        let flags = if (sw, sh) == (dw, dh) {
+            Flags::empty()
+        } else {
+            Flags::BILINEAR
+        };
+        let mut context = Context::get(src, sw, sh, dst, dw, dh, flags)?;
+        if key.full_range {
+            crate::frame::set_full_range_source(&mut context);
+        }
+        Ok(Self::new(context))
+    }
+
     pub fn run(
         &mut self,
         frame: &ffmpeg_next::frame::Video,
@@ -17,3 +39,102 @@ impl Scaler {
 }
 
 unsafe impl Send for Scaler {}
+
+/// What a scaler converts: source format and size, destination format and
+/// size, and whether the source is full range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScalerKey {
+    pub src: (Pixel, u32, u32),
+    pub dst: (Pixel, u32, u32),
+    pub full_range: bool,
+}
+
+impl ScalerKey {
+    /// The conversion of the software frame `frame` to `format` at
+    /// `width`x`height`.
+    pub fn of(
+        frame: &ffmpeg_next::frame::Video,
+        format: Pixel,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let (w, h) = (frame.width(), frame.height());
+        if w == 0 || h == 0 || width == 0 || height == 0 {
+            anyhow::bail!("zero-sized frame");
+        }
+        Ok(Self {
+            src: (frame.format(), w, h),
+            dst: (format, width, height),
+            full_range: frame.color_range() == ffmpeg_next::color::Range::JPEG,
+        })
+    }
+}
+
+/// Scalers kept for reuse by what they convert, for callers converting the
+/// same few sources to the same few sizes over and over (snapshots and
+/// previews at the sizes clients ask for). Past `capacity` the least recently
+/// used one is dropped.
+pub struct ScalerCache {
+    capacity: usize,
+    /// Most recently used first.
+    scalers: VecDeque<(ScalerKey, Scaler)>,
+}
+
+impl ScalerCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            scalers: VecDeque::new(),
+        }
+    }
+
+    /// The scaler for `key`, built if there is none.
+    pub fn get(&mut self, key: &ScalerKey) -> anyhow::Result<&mut Scaler> {
+        match self.scalers.iter().position(|(cached, _)| cached == key) {
+            Some(0) => {}
+            Some(at) => {
+                let entry = self.scalers.remove(at).expect("position is in range");
+                self.scalers.push_front(entry);
+            }
+            None => {
+                let scaler = Scaler::for_key(key)?;
+                self.scalers.truncate(self.capacity - 1);
+                self.scalers.push_front((*key, scaler));
+            }
+        }
+        Ok(&mut self.scalers[0].1)
+    }
+
+    /// Like [`scale_video`](crate::frame::scale_video), on a cached scaler.
+    pub fn scale(
+        &mut self,
+        frame: &ffmpeg_next::frame::Video,
+        format: Pixel,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<ffmpeg_next::frame::Video> {
+        let frame = crate::frame::to_software(frame)?;
+        let key = ScalerKey::of(&frame, format, width, height)?;
+        let mut dst = ffmpeg_next::frame::Video::empty();
+        self.get(&key)?.run(&frame, &mut dst)?;
+        dst.set_pts(frame.pts());
+        Ok(dst)
+    }
+
+    /// The conversions cached, most recently used first.
+    pub fn keys(&self) -> impl Iterator<Item = &ScalerKey> {
+        self.scalers.iter().map(|(key, _)| key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.scalers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scalers.is_empty()
+    }
+}
+
+#[cfg(test)]
+#[path = "scaler_test.rs"]
+mod scaler_test;
@@ -0,0 +1,44 @@
+use super::*;
+
+fn frame(width: u32, height: u32) -> ffmpeg_next::frame::Video {
+    ffmpeg_next::frame::Video::new(Pixel::YUV420P, width, height)
+}
+
+fn sources(cache: &ScalerCache) -> Vec<(u32, u32)> {
+    cache.keys().map(|key| (key.src.1, key.src.2)).collect()
+}
+
+#[test]
+fn cached_scalers_are_reused_and_the_least_recent_dropped() {
+    crate::init().unwrap();
+    let mut cache = ScalerCache::new(2);
+    for (w, h) in [(640, 360), (1280, 720), (640, 360)] {
+        let scaled = cache
+            .scale(&frame(w, h), Pixel::YUVJ420P, 320, 180)
+            .unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (320, 180));
+        assert_eq!(scaled.format(), Pixel::YUVJ420P);
+    }
+    // The 640x360 one was used again, not built again.
+    assert_eq!(sources(&cache), [(640, 360), (1280, 720)]);
+
+    cache
+        .scale(&frame(320, 240), Pixel::YUVJ420P, 160, 120)
+        .unwrap();
+    assert_eq!(sources(&cache), [(320, 240), (640, 360)]);
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn each_output_size_has_a_scaler_of_its_own() {
+    crate::init().unwrap();
+    let mut cache = ScalerCache::new(4);
+    let source = frame(640, 360);
+    let small = cache.scale(&source, Pixel::YUV420P, 160, 90).unwrap();
+    let large = cache.scale(&source, Pixel::YUV420P, 320, 180).unwrap();
+    assert_eq!((small.width(), large.width()), (160, 320));
+    let sizes: Vec<_> = cache.keys().map(|key| key.dst.1).collect();
+    assert_eq!(sizes, [320, 160]);
+    assert!(cache.scale(&source, Pixel::YUV420P, 0, 90).is_err());
+    assert_eq!(cache.len(), 2);
+}
@@ -0,0 +1,221 @@
+//! Cutting a recording into files of about `segment_seconds` each, for
+//! [`OutputDest::Segments`](crate::bus::OutputDest::Segments).
+//!
+//! A [`Segmenter`] is shown every packet its mux writes and tells where the
+//! packet goes. The first file opens at the first keyframe of the key stream
+//! (the output's primary stream), and each next one at the first such
+//! keyframe `segment_seconds` or more into the current file, so every file
+//! starts on a keyframe and plays on its own. Packets before the first
+//! keyframe are dropped. As in [`GopBuffer`](crate::packet::GopBuffer),
+//! keyframes of H.264 and H.265 are told by their NAL unit types.
+//!
+//! Files are named by the strftime `pattern` (e.g. `rec_%Y%m%d_%H%M%S.mp4`)
+//! with the local time of their first packet: the wall clock at the
+//! recording's first packet plus the media time since, so a file read faster
+//! than real time still gets one name per segment. A name the previous file
+//! took already gets `_1`, `_2`, … before its extension.
+//!
+//! Each file the mux is done with comes back as a [`Segment`]: its path, the
+//! wall clock at its first packet and how long its packets play, enough to
+//! index it without probing.
+
+use std::path::{Path, PathBuf};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use ffmpeg_next::codec::Id;
+
+use crate::packet::{RawPacket, nal_keyframe, seconds};
+
+/// Where one packet goes.
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    /// Drop it: no file is open yet.
+    Skip,
+    /// Write it to the current file.
+    Write,
+    /// Finish the current file (if any) and write it to a new one at this
+    /// path.
+    Open(PathBuf),
+}
+
+/// A file of the recording, once no more packets go to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub path: PathBuf,
+    /// Wall clock at its first packet.
+    pub start: DateTime<Local>,
+    /// Seconds from its first packet to the end of its last one.
+    pub duration: f64,
+}
+
+/// The file packets go to now.
+struct Current {
+    path: PathBuf,
+    start: DateTime<Local>,
+    /// Media time the file starts at.
+    started: f64,
+    /// Media time its last packet ends at.
+    ended: f64,
+}
+
+pub struct Segmenter {
+    dir: PathBuf,
+    pattern: String,
+    segment_seconds: f64,
+    key_index: usize,
+    key_codec: Option<Id>,
+    /// Wall clock and media time of the first packet seen.
+    origin: Option<(DateTime<Local>, f64)>,
+    current: Option<Current>,
+    /// The file the last [`Step::Open`] moved on from.
+    closed: Option<Segment>,
+    /// The current file's name as the pattern gave it, and how many files
+    /// before it had the same one.
+    last_name: Option<(String, u32)>,
+}
+
+/// Fail for a `pattern` strftime cannot expand.
+pub fn check_pattern(pattern: &str) -> anyhow::Result<()> {
+    if pattern.is_empty() {
+        anyhow::bail!("segment pattern must not be empty");
+    }
+    if StrftimeItems::new(pattern).any(|item| matches!(item, Item::Error)) {
+        anyhow::bail!("invalid segment pattern {pattern:?}");
+    }
+    Ok(())
+}
+
+impl Segmenter {
+    /// Files in `dir` named by `pattern`, cut at keyframes of stream
+    /// `key_index` in `key_codec`.
+    pub fn new(
+        dir: &str,
+        pattern: &str,
+        segment_seconds: u32,
+        key_index: usize,
+        key_codec: Option<Id>,
+    ) -> anyhow::Result<Self> {
+        check_pattern(pattern)?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            pattern: pattern.to_string(),
+            segment_seconds: f64::from(segment_seconds),
+            key_index,
+            key_codec,
+            origin: None,
+            current: None,
+            closed: None,
+            last_name: None,
+        })
+    }
+
+    /// Where `packet` of stream `index` goes.
+    pub fn step(&mut self, index: usize, packet: &RawPacket) -> Step {
+        let at = seconds(packet);
+        if self.origin.is_none()
+            && let Some(at) = at
+        {
+            self.origin = Some((Local::now(), at));
+        }
+        let cut = index == self.key_index
+            && self.is_keyframe(packet)
+            && at.is_some_and(|at| {
+                self.current
+                    .as_ref()
+                    .is_none_or(|current| at - current.started >= self.segment_seconds)
+            });
+        let step = match (cut, at) {
+            (true, Some(at)) => {
+                let (path, start) = self.path_at(at);
+                self.closed = self.current.take().map(Current::into_segment);
+                self.current = Some(Current {
+                    path: path.clone(),
+                    start,
+                    started: at,
+                    ended: at,
+                });
+                Step::Open(path)
+            }
+            _ if self.current.is_some() => Step::Write,
+            _ => Step::Skip,
+        };
+        if step != Step::Skip
+            && let (Some(current), Some(at)) = (self.current.as_mut(), at)
+        {
+            current.ended = current.ended.max(at + length(packet));
+        }
+        step
+    }
+
+    /// The file the last [`Step::Open`] moved on from, once: the mux takes it
+    /// after writing that file's trailer.
+    pub fn take_closed(&mut self) -> Option<Segment> {
+        self.closed.take()
+    }
+
+    /// The file still open, at the end of the recording.
+    pub fn finish(&mut self) -> Option<Segment> {
+        self.current.take().map(Current::into_segment)
+    }
+
+    fn is_keyframe(&self, packet: &RawPacket) -> bool {
+        self.key_codec
+            .and_then(|codec| nal_keyframe(packet.packet().data()?, codec))
+            .unwrap_or_else(|| packet.is_key())
+    }
+
+    /// The path of a file starting at media time `at`, and the wall clock
+    /// then.
+    fn path_at(&mut self, at: f64) -> (PathBuf, DateTime<Local>) {
+        let (wall, media) = self.origin.unwrap_or((Local::now(), at));
+        let time = wall + chrono::TimeDelta::milliseconds(((at - media) * 1000.0) as i64);
+        let name = time.format(&self.pattern).to_string();
+        let repeats = match &self.last_name {
+            Some((last, repeats)) if *last == name => repeats + 1,
+            _ => 0,
+        };
+        let file = if repeats == 0 {
+            PathBuf::from(&name)
+        } else {
+            numbered(&name, repeats)
+        };
+        self.last_name = Some((name, repeats));
+        (self.dir.join(file), time)
+    }
+}
+
+impl Current {
+    fn into_segment(self) -> Segment {
+        Segment {
+            path: self.path,
+            start: self.start,
+            duration: self.ended - self.started,
+        }
+    }
+}
+
+/// How long `packet` plays, in seconds; 0 when it does not say.
+fn length(packet: &RawPacket) -> f64 {
+    let tb = packet.time_base();
+    let duration = packet.packet().duration().max(0);
+    if tb.denominator() == 0 {
+        return 0.0;
+    }
+    duration as f64 * tb.numerator() as f64 / tb.denominator() as f64
+}
+
+/// `name` with `_n` before its extension.
+fn numbered(name: &str, n: u32) -> PathBuf {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file = match path.extension() {
+        Some(ext) => format!("{stem}_{n}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{n}"),
+    };
+    path.with_file_name(file)
+}
+
+#[cfg(test)]
+#[path = "segment_test.rs"]
+mod segment_test;
@@ -0,0 +1,126 @@
+use ffmpeg_next::Rational;
+use ffmpeg_next::codec::packet::{Flags, Packet};
+
+use super::*;
+
+const VIDEO: usize = 0;
+const AUDIO: usize = 1;
+
+/// A packet at `secs`, in milliseconds.
+fn packet(index: usize, secs: f64, key: bool) -> RawPacket {
+    let mut p = Packet::copy(&[0]);
+    p.set_stream(index);
+    p.set_pts(Some((secs * 1000.0) as i64));
+    p.set_dts(Some((secs * 1000.0) as i64));
+    if key {
+        p.set_flags(Flags::KEY);
+    }
+    RawPacket::from((p, Rational(1, 1000)))
+}
+
+fn opened(step: Step) -> String {
+    match step {
+        Step::Open(path) => path.to_string_lossy().into_owned(),
+        step => panic!("expected a new file, got {step:?}"),
+    }
+}
+
+#[test]
+fn files_start_at_keyframes_segment_seconds_apart() {
+    let mut segments = Segmenter::new("rec", "cam.mp4", 2, VIDEO, None).unwrap();
+    // Before the first keyframe: nowhere to write.
+    assert_eq!(segments.step(AUDIO, &packet(AUDIO, 0.0, true)), Step::Skip);
+    assert_eq!(segments.step(VIDEO, &packet(VIDEO, 0.0, false)), Step::Skip);
+    assert_eq!(
+        opened(segments.step(VIDEO, &packet(VIDEO, 0.5, true))),
+        "rec/cam.mp4"
+    );
+    // Keyframes too early, and audio, do not cut.
+    assert_eq!(segments.step(VIDEO, &packet(VIDEO, 1.5, true)), Step::Write);
+    assert_eq!(segments.step(AUDIO, &packet(AUDIO, 2.6, true)), Step::Write);
+    assert_eq!(
+        segments.step(VIDEO, &packet(VIDEO, 2.6, false)),
+        Step::Write
+    );
+    // The first keyframe once 2 s are in.
+    assert_eq!(
+        opened(segments.step(VIDEO, &packet(VIDEO, 2.7, true))),
+        "rec/cam_1.mp4"
+    );
+    assert_eq!(segments.step(VIDEO, &packet(VIDEO, 4.5, true)), Step::Write);
+    assert_eq!(
+        opened(segments.step(VIDEO, &packet(VIDEO, 4.8, true))),
+        "rec/cam_2.mp4"
+    );
+}
+
+#[test]
+fn closed_files_span_their_packets() {
+    let mut segments = Segmenter::new("rec", "cam.mp4", 2, VIDEO, None).unwrap();
+    assert_eq!(segments.finish(), None);
+    let mut frame = packet(VIDEO, 0.0, true);
+    frame.set_duration(100);
+    segments.step(VIDEO, &frame);
+    segments.step(VIDEO, &packet(VIDEO, 1.9, false));
+    assert_eq!(segments.take_closed(), None);
+
+    segments.step(VIDEO, &packet(VIDEO, 2.0, true));
+    let first = segments.take_closed().unwrap();
+    assert_eq!(first.path, PathBuf::from("rec/cam.mp4"));
+    assert!((first.duration - 1.9).abs() < 1e-9, "{first:?}");
+    // Taken once.
+    assert_eq!(segments.take_closed(), None);
+
+    // The last packet plays on for its own duration.
+    let mut last = packet(VIDEO, 3.0, false);
+    last.set_duration(100);
+    segments.step(VIDEO, &last);
+    let second = segments.finish().unwrap();
+    assert_eq!(second.path, PathBuf::from("rec/cam_1.mp4"));
+    assert!((second.duration - 1.1).abs() < 1e-9, "{second:?}");
+    assert!(second.start >= first.start);
+    assert_eq!(segments.finish(), None);
+}
+
+#[test]
+fn names_follow_the_media_time() {
+    let mut segments = Segmenter::new("", "%H%M%S.mp4", 2, VIDEO, None).unwrap();
+    let origin = Local::now();
+    segments.origin = Some((origin, 10.0));
+    let name = |secs| {
+        (origin + chrono::TimeDelta::seconds(secs))
+            .format("%H%M%S.mp4")
+            .to_string()
+    };
+
+    assert_eq!(
+        opened(segments.step(VIDEO, &packet(VIDEO, 10.0, true))),
+        name(0)
+    );
+    // Read at once, still a second per second of media.
+    assert_eq!(
+        opened(segments.step(VIDEO, &packet(VIDEO, 13.0, true))),
+        name(3)
+    );
+    assert_eq!(
+        opened(segments.step(VIDEO, &packet(VIDEO, 15.0, true))),
+        name(5)
+    );
+}
+
+#[test]
+fn numbered_keeps_the_directory_and_extension() {
+    assert_eq!(
+        numbered("2024/05/rec.mp4", 2),
+        PathBuf::from("2024/05/rec_2.mp4")
+    );
+    assert_eq!(numbered("rec", 1), PathBuf::from("rec_1"));
+}
+
+#[test]
+fn bad_patterns_are_refused() {
+    assert!(check_pattern("rec_%Y%m%d_%H%M%S.mp4").is_ok());
+    assert!(check_pattern("").is_err());
+    assert!(check_pattern("rec_%").is_err());
+    assert!(Segmenter::new("rec", "rec_%", 2, VIDEO, None).is_err());
+}
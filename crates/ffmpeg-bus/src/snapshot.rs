@@ -0,0 +1,338 @@
+//! Single frames grabbed from a file, without a bus: [`frame_at`] seeks to the
+//! last keyframe at or before the requested time and decodes forward to the
+//! frame shown at that time; [`jpeg_at`] encodes it as a JPEG (e.g. a poster
+//! image for a recording). Opening, seeking and reading are bounded by
+//! [`SnapshotOpts::timeout`] through FFmpeg's interrupt callback, so a stalled
+//! disk or network mount fails the call instead of hanging it.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::media::Type;
+use ffmpeg_next::{Packet, Rational, Rescale};
+
+use crate::frame::{VideoFrame, pack_planes, scale_video};
+
+const MICROS: Rational = Rational(1, 1_000_000);
+
+/// Default bound on one [`frame_at`] call.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `FF_QP2LAMBDA`: MJPEG qscale to the encoder's lambda units.
+const QP2LAMBDA: i32 = 118;
+
+/// How [`frame_at`] renders its frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotOpts {
+    /// Output width; `None` follows `height` at the file's aspect ratio (even
+    /// sizes), or keeps the file's width if both are `None`.
+    pub width: Option<u32>,
+    /// Output height, likewise.
+    pub height: Option<u32>,
+    /// Output pixel format; `None` keeps the decoder's.
+    pub format: Option<Pixel>,
+    /// Bound on the whole call.
+    pub timeout: Duration,
+}
+
+impl Default for SnapshotOpts {
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            format: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// The frame of `path`'s video shown `at` after the start of the file,
+/// scaled and converted per `opts`. `at` past the end gives the last frame.
+/// The frame's `pts` (and `dts`) is its presentation time in microseconds
+/// from the start of the file. Fails for files without video. Blocking.
+pub fn frame_at(path: &str, at: Duration, opts: SnapshotOpts) -> Result<VideoFrame> {
+    let (frame, pts_us) = decode_at(path, at, &opts)?;
+    let (width, height) = output_size(frame.width(), frame.height(), opts.width, opts.height);
+    let format = opts.format.unwrap_or(frame.format());
+    let frame = if (width, height, format) == (frame.width(), frame.height(), frame.format()) {
+        frame
+    } else {
+        scale_video(&frame, format, width, height)?
+    };
+    Ok(VideoFrame::new(
+        pack_planes(&frame)?,
+        width,
+        height,
+        ffmpeg_next::ffi::AVPixelFormat::from(format) as i32,
+        pts_us,
+        pts_us,
+        frame.is_key(),
+        ffmpeg_next::codec::Id::None as i32,
+    ))
+}
+
+/// [`frame_at`] at the file's size, as a baseline JPEG. `quality` runs from
+/// 1 (smallest) to 100 (best).
+pub fn jpeg_at(path: &str, at: Duration, quality: u8) -> Result<Vec<u8>> {
+    let (frame, _) = decode_at(path, at, &SnapshotOpts::default())?;
+    // The MJPEG encoder takes full-range YUV.
+    let yuv = scale_video(&frame, Pixel::YUVJ420P, frame.width(), frame.height())?;
+    encode_jpeg(yuv, quality)
+}
+
+/// `yuv`, a `YUVJ420P` frame, as a baseline JPEG of `quality` (1..=100).
+pub fn encode_jpeg(mut yuv: ffmpeg_next::frame::Video, quality: u8) -> Result<Vec<u8>> {
+    yuv.set_pts(Some(0));
+    // quality 1..=100 onto qscale 31..=2.
+    let lambda = (31 - (i32::from(quality.clamp(1, 100)) - 1) * 29 / 99) * QP2LAMBDA;
+
+    let codec = ffmpeg_next::encoder::find(ffmpeg_next::codec::Id::MJPEG)
+        .context("mjpeg encoder not available")?;
+    let mut encoder = ffmpeg_next::codec::Context::new_with_codec(codec)
+        .encoder()
+        .video()?;
+    encoder.set_width(yuv.width());
+    encoder.set_height(yuv.height());
+    encoder.set_format(Pixel::YUVJ420P);
+    encoder.set_time_base(Rational(1, 25));
+    unsafe {
+        let ctx = encoder.as_mut_ptr();
+        (*ctx).flags |= ffmpeg_next::ffi::AV_CODEC_FLAG_QSCALE as i32;
+        (*ctx).global_quality = lambda;
+        (*yuv.as_mut_ptr()).quality = lambda;
+    }
+    let mut encoder = encoder.open()?;
+    encoder.send_frame(&yuv)?;
+    encoder.send_eof()?;
+    let mut packet = Packet::empty();
+    encoder.receive_packet(&mut packet)?;
+    packet
+        .data()
+        .map(<[u8]>::to_vec)
+        .context("mjpeg encoder produced an empty packet")
+}
+
+/// The decoded frame shown at `at`, and its time from the start of the file
+/// in microseconds.
+fn decode_at(
+    path: &str,
+    at: Duration,
+    opts: &SnapshotOpts,
+) -> Result<(ffmpeg_next::frame::Video, i64)> {
+    Seeker::open(path, opts.timeout)?.frame_at(at, true)
+}
+
+/// A file's video opened for grabbing frames at one time after another.
+/// Each open, seek and decode is bounded by the timeout it was opened with.
+pub(crate) struct Seeker {
+    path: String,
+    ictx: ffmpeg_next::format::context::Input,
+    decoder: ffmpeg_next::decoder::Video,
+    index: usize,
+    time_base: Rational,
+    start: i64,
+    timeout: Duration,
+    deadline: Arc<Mutex<Instant>>,
+    /// The decoder has been sent EOF and needs a flush before more packets.
+    drained: bool,
+}
+
+impl Seeker {
+    pub(crate) fn open(path: &str, timeout: Duration) -> Result<Seeker> {
+        let deadline = Arc::new(Mutex::new(Instant::now() + timeout));
+        let interrupt = deadline.clone();
+        let ictx = ffmpeg_next::format::input_with_interrupt(path, move || {
+            Instant::now() >= *interrupt.lock().unwrap()
+        })
+        .map_err(|e| {
+            if Instant::now() >= *deadline.lock().unwrap() {
+                anyhow::anyhow!("no frame from {path} within {timeout:?}")
+            } else {
+                anyhow::Error::from(e).context(format!("open {path}"))
+            }
+        })?;
+        let (index, time_base, start, parameters) = {
+            let stream = ictx
+                .streams()
+                .best(Type::Video)
+                .with_context(|| format!("{path} has no video stream"))?;
+            let start = stream.start_time();
+            let start = if start == ffmpeg_next::ffi::AV_NOPTS_VALUE as i64 {
+                0
+            } else {
+                start
+            };
+            (
+                stream.index(),
+                stream.time_base(),
+                start,
+                stream.parameters(),
+            )
+        };
+        let decoder = ffmpeg_next::codec::context::Context::from_parameters(parameters)?
+            .decoder()
+            .video()?;
+        Ok(Seeker {
+            path: path.to_string(),
+            ictx,
+            decoder,
+            index,
+            time_base,
+            start,
+            timeout,
+            deadline,
+            drained: false,
+        })
+    }
+
+    /// The file's duration, if its container knows it.
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        let us = self.ictx.duration();
+        (us > 0).then(|| Duration::from_micros(us as u64))
+    }
+
+    /// The frame shown `at` after the start of the file, or with `exact`
+    /// false the keyframe it is decoded from (no decoding past it), and its
+    /// time from the start of the file in microseconds.
+    pub(crate) fn frame_at(
+        &mut self,
+        at: Duration,
+        exact: bool,
+    ) -> Result<(ffmpeg_next::frame::Video, i64)> {
+        let path = self.path.clone();
+        let limit = self.timeout;
+        let deadline = Instant::now() + limit;
+        *self.deadline.lock().unwrap() = deadline;
+        let timeout = || anyhow::anyhow!("no frame from {path} within {limit:?}");
+
+        let target = self.start + (at.as_micros() as i64).rescale(MICROS, self.time_base);
+        // To the last keyframe at or before `target`, which for `at` past the
+        // end is the last one. A file that cannot seek is decoded from its
+        // start.
+        let ret = unsafe {
+            ffmpeg_next::ffi::avformat_seek_file(
+                self.ictx.as_mut_ptr(),
+                self.index as i32,
+                i64::MIN,
+                target,
+                target,
+                0,
+            )
+        };
+        if ret < 0 {
+            tracing::debug!(
+                "snapshot: seek in {path} failed ({}), decoding from the start",
+                ffmpeg_next::Error::from(ret)
+            );
+        }
+        // Frames of an earlier call must not come out of this one.
+        if self.drained || ret >= 0 {
+            self.decoder.flush();
+            self.drained = false;
+        }
+
+        let mut picker = Picker {
+            target: if exact { target } else { i64::MIN },
+            shown: ffmpeg_next::frame::Video::empty(),
+            shown_ts: None,
+            decoded: ffmpeg_next::frame::Video::empty(),
+            found: false,
+        };
+        let mut packet = Packet::empty();
+        loop {
+            match packet.read(&mut self.ictx) {
+                Ok(()) => {}
+                Err(ffmpeg_next::Error::Eof) => break,
+                Err(_) if Instant::now() >= deadline => return Err(timeout()),
+                Err(e) => {
+                    // A cut-off file: use what was read.
+                    tracing::debug!("snapshot: reading {path} stopped: {e}");
+                    break;
+                }
+            }
+            if packet.stream() != self.index {
+                continue;
+            }
+            if let Err(e) = self.decoder.send_packet(&packet) {
+                tracing::debug!("snapshot: skip undecodable packet in {path}: {e}");
+                continue;
+            }
+            if picker.drain(&mut self.decoder) {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Err(timeout());
+            }
+        }
+        if !picker.found {
+            self.decoder.send_eof()?;
+            self.drained = true;
+            picker.drain(&mut self.decoder);
+        }
+        let ts = picker
+            .shown_ts
+            .with_context(|| format!("no video frame decoded from {path}"))?;
+        let pts_us = (ts - self.start).rescale(self.time_base, MICROS).max(0);
+        Ok((picker.shown, pts_us))
+    }
+}
+
+/// Keeps the last decoded frame at or before `target` (stream time base).
+/// Frames come out of the decoder in presentation order.
+struct Picker {
+    target: i64,
+    shown: ffmpeg_next::frame::Video,
+    shown_ts: Option<i64>,
+    decoded: ffmpeg_next::frame::Video,
+    /// No later frame can replace `shown`.
+    found: bool,
+}
+
+impl Picker {
+    /// Take the frames the decoder has ready; true once the frame shown at
+    /// `target` is known.
+    fn drain(&mut self, decoder: &mut ffmpeg_next::decoder::Video) -> bool {
+        while decoder.receive_frame(&mut self.decoded).is_ok() {
+            let Some(ts) = self.decoded.timestamp().or(self.decoded.pts()) else {
+                continue;
+            };
+            if ts > self.target && self.shown_ts.is_some() {
+                // The frame before this one is shown at `target`.
+                self.found = true;
+                return true;
+            }
+            // `receive_frame` unreferences the frame it is given first.
+            std::mem::swap(&mut self.shown, &mut self.decoded);
+            self.shown_ts = Some(ts);
+            if ts >= self.target {
+                self.found = true;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// `width`x`height` per [`SnapshotOpts`] for a `src_w`x`src_h` source.
+pub(crate) fn output_size(
+    src_w: u32,
+    src_h: u32,
+    width: Option<u32>,
+    height: Option<u32>,
+) -> (u32, u32) {
+    let follow = |len: u32, by: u32, of: u32| {
+        ((u64::from(len) * u64::from(by) / u64::from(of.max(1))) as u32 & !1).max(2)
+    };
+    match (width, height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, follow(src_h, w, src_w)),
+        (None, Some(h)) => (follow(src_w, h, src_h), h),
+        (None, None) => (src_w, src_h),
+    }
+}
+
+#[cfg(test)]
+#[path = "snapshot_test.rs"]
+mod snapshot_test;
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+
+/// 5s of 320x240 at 10 fps, so the last frame is shown from 4.9s.
+async fn fixture() -> String {
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// The audio track of `src` alone, in an M4A.
+fn audio_only(src: &str) -> PathBuf {
+    let dst = std::env::temp_dir().join(format!("snapshot-audio-{}.m4a", std::process::id()));
+    let mut ictx = ffmpeg_next::format::input(src).unwrap();
+    let mut octx = ffmpeg_next::format::output(&dst).unwrap();
+    let audio = ictx.streams().best(Type::Audio).unwrap();
+    let (index, time_base) = (audio.index(), audio.time_base());
+    let mut ost = octx
+        .add_stream(ffmpeg_next::encoder::find(audio.parameters().id()))
+        .unwrap();
+    ost.set_parameters(audio.parameters());
+    unsafe { (*(*ost.as_mut_ptr()).codecpar).codec_tag = 0 };
+    octx.write_header().unwrap();
+    let out_time_base = octx.stream(0).unwrap().time_base();
+    for (stream, mut packet) in ictx.packets() {
+        if stream.index() == index {
+            packet.rescale_ts(time_base, out_time_base);
+            packet.set_stream(0);
+            packet.set_position(-1);
+            packet.write_interleaved(&mut octx).unwrap();
+        }
+    }
+    octx.write_trailer().unwrap();
+    dst
+}
+
+#[tokio::test]
+async fn frames_at_start_middle_and_past_the_end() {
+    let path = fixture().await;
+
+    let first = frame_at(&path, Duration::ZERO, SnapshotOpts::default()).unwrap();
+    assert_eq!((first.width, first.height), (320, 240));
+    assert_eq!(first.pts, 0);
+    assert!(first.is_key);
+    assert_eq!(first.pixel_format(), Pixel::YUV420P);
+
+    let opts = SnapshotOpts {
+        width: Some(160),
+        format: Some(Pixel::RGB24),
+        ..SnapshotOpts::default()
+    };
+    let middle = frame_at(&path, Duration::from_millis(2500), opts).unwrap();
+    assert_eq!((middle.width, middle.height), (160, 120));
+    assert_eq!(middle.pixel_format(), Pixel::RGB24);
+    assert_eq!(middle.data.len(), 160 * 120 * 3);
+    // Between keyframes (every second): decoded forward from the one at 2s.
+    assert_eq!(middle.pts, 2_500_000);
+    assert!(!middle.is_key);
+    // A moment later still shows the same frame.
+    let held = frame_at(&path, Duration::from_millis(2550), SnapshotOpts::default()).unwrap();
+    assert_eq!(held.pts, 2_500_000);
+
+    let last = frame_at(&path, Duration::from_secs(60), SnapshotOpts::default()).unwrap();
+    assert_eq!(last.pts, 4_900_000);
+    assert_eq!((last.width, last.height), (320, 240));
+}
+
+#[tokio::test]
+async fn jpeg_quality_and_failures() {
+    let path = fixture().await;
+    let best = jpeg_at(&path, Duration::from_secs(1), 95).unwrap();
+    let small = jpeg_at(&path, Duration::from_secs(1), 10).unwrap();
+    assert!(best.starts_with(&[0xff, 0xd8]), "not a JPEG");
+    assert!(
+        small.len() < best.len(),
+        "{} >= {}",
+        small.len(),
+        best.len()
+    );
+
+    let audio = audio_only(&path);
+    let err = frame_at(
+        audio.to_str().unwrap(),
+        Duration::ZERO,
+        SnapshotOpts::default(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("no video stream"), "{err:#}");
+    let _ = std::fs::remove_file(&audio);
+
+    let opts = SnapshotOpts {
+        timeout: Duration::ZERO,
+        ..SnapshotOpts::default()
+    };
+    let err = frame_at(&path, Duration::ZERO, opts).unwrap_err();
+    assert!(err.to_string().contains("within"), "{err:#}");
+}
@@ -0,0 +1,95 @@
+//! Counters of a bus's inputs and outputs, read with
+//! [`Bus::stats`](crate::bus::Bus::stats).
+//!
+//! Every output gets an [`OutputCounters`] when it is added. In-bus muxers
+//! count the packets they write, `Mux` streams the packets they mux, and
+//! `Raw`, `Encoded` and `Demuxed` streams the items their consumer takes.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::input::InputStats;
+
+/// How far back [`OutputStats::fps`] looks.
+pub const FPS_WINDOW: Duration = Duration::from_secs(5);
+
+/// What [`Bus::stats`](crate::bus::Bus::stats) reports.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BusStats {
+    /// Inputs that are open, by id.
+    pub inputs: Vec<(String, InputStats)>,
+    /// Outputs added and not removed, by id (including those whose task
+    /// has ended).
+    pub outputs: Vec<OutputStats>,
+}
+
+/// Counters of one output since it was added.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputStats {
+    pub id: String,
+    pub packets_written: u64,
+    pub bytes_written: u64,
+    /// Packets (or frames) a second of the output's primary stream over the
+    /// last [`FPS_WINDOW`].
+    pub fps: f64,
+    /// Packets lost by lagging behind the input or an encoder.
+    pub lagged: u64,
+}
+
+/// The live counters behind an [`OutputStats`].
+#[derive(Default)]
+pub(crate) struct OutputCounters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    lagged: AtomicU64,
+    /// When the primary stream's packets of the last [`FPS_WINDOW`] were
+    /// written, oldest first.
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+impl OutputCounters {
+    /// Count a written packet of `bytes`; `primary` when it is of the
+    /// stream [`OutputStats::fps`] is about.
+    pub(crate) fn wrote(&self, bytes: usize, primary: bool) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        if primary {
+            let now = Instant::now();
+            let mut recent = self.recent.lock().unwrap();
+            recent.push_back(now);
+            prune(&mut recent, now);
+        }
+    }
+
+    pub(crate) fn lagged(&self, count: u64) {
+        self.lagged.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, id: &str) -> OutputStats {
+        let mut recent = self.recent.lock().unwrap();
+        prune(&mut recent, Instant::now());
+        OutputStats {
+            id: id.to_string(),
+            packets_written: self.packets.load(Ordering::Relaxed),
+            bytes_written: self.bytes.load(Ordering::Relaxed),
+            fps: recent.len() as f64 / FPS_WINDOW.as_secs_f64(),
+            lagged: self.lagged.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Forget the writes older than [`FPS_WINDOW`].
+fn prune(recent: &mut VecDeque<Instant>, now: Instant) {
+    while recent
+        .front()
+        .is_some_and(|at| now.duration_since(*at) > FPS_WINDOW)
+    {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+#[path = "stats_test.rs"]
+mod stats_test;
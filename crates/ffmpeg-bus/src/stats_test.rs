@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn counters_add_up_and_fps_counts_the_primary_stream() {
+    let counters = OutputCounters::default();
+    for _ in 0..10 {
+        counters.wrote(100, true);
+    }
+    counters.wrote(50, false);
+    counters.lagged(3);
+    let stats = counters.snapshot("out");
+    assert_eq!(stats.id, "out");
+    assert_eq!(stats.packets_written, 11);
+    assert_eq!(stats.bytes_written, 1050);
+    assert_eq!(stats.lagged, 3);
+    assert_eq!(stats.fps, 10.0 / FPS_WINDOW.as_secs_f64());
+}
+
+#[test]
+fn writes_older_than_the_window_leave_the_fps() {
+    let now = Instant::now();
+    let mut recent = VecDeque::from([now - FPS_WINDOW * 2, now - FPS_WINDOW / 2, now]);
+    prune(&mut recent, now);
+    assert_eq!(recent.len(), 2);
+}
@@ -0,0 +1,187 @@
+//! Storyboards of a file for timeline scrubbing: a tile every
+//! [`StoryboardOpts::interval`], laid out in a grid over one or more JPEG
+//! sprite sheets, and a WebVTT index whose cues point at each tile
+//! (`sheet1.jpg#xywh=160,0,160,90`).
+//!
+//! Each tile is the keyframe at or before its time (see
+//! [`crate::snapshot`]), so no frames are decoded past it. Tiles are scaled
+//! and copied into the sheet being composed as they are decoded, a sheet is
+//! encoded and written once it has [`StoryboardOpts::rows`] rows, and
+//! memory stays at one sheet and one frame at any length of file.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use ffmpeg_next::format::Pixel;
+
+use crate::frame::scale_video;
+use crate::snapshot::{DEFAULT_TIMEOUT, Seeker, encode_jpeg};
+
+/// How [`generate`] lays out a storyboard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoryboardOpts {
+    /// Time between two tiles.
+    pub interval: Duration,
+    /// Tile size; both even. Frames are scaled to it whatever their aspect
+    /// ratio.
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// Tiles per row of a sheet.
+    pub columns: u32,
+    /// Rows of a sheet, at most; the last sheet has only the rows it fills.
+    pub rows: u32,
+    /// JPEG quality of the sheets, 1..=100.
+    pub quality: u8,
+    /// Put before each sheet's file name in the cues, e.g. a URL path.
+    pub sheet_prefix: String,
+    /// Bound on grabbing one tile.
+    pub timeout: Duration,
+}
+
+impl Default for StoryboardOpts {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            tile_width: 160,
+            tile_height: 90,
+            columns: 10,
+            rows: 10,
+            quality: 75,
+            sheet_prefix: String::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// The sheets [`generate`] wrote and the WebVTT index of their tiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoryboardResult {
+    /// `sheet1.jpg`, `sheet2.jpg`, … in the output directory, in order.
+    pub sheets: Vec<PathBuf>,
+    /// One cue per tile; together they cover the file's duration.
+    pub vtt: String,
+}
+
+/// Write the storyboard sheets of `path`'s video into `dir` (created if
+/// missing) and index them. Fails for files without video or a known
+/// duration. Blocking.
+pub fn generate(path: &str, dir: &Path, opts: &StoryboardOpts) -> Result<StoryboardResult> {
+    if opts.interval.is_zero() {
+        bail!("storyboard interval must be positive");
+    }
+    let even = |side: u32| side >= 2 && side & 1 == 0;
+    if !even(opts.tile_width) || !even(opts.tile_height) {
+        bail!(
+            "storyboard tiles must have even sides, not {}x{}",
+            opts.tile_width,
+            opts.tile_height
+        );
+    }
+    if opts.columns == 0 || opts.rows == 0 {
+        bail!("storyboard sheets need at least one row and column");
+    }
+    let mut seeker = Seeker::open(path, opts.timeout)?;
+    let duration = seeker
+        .duration()
+        .with_context(|| format!("{path} has no known duration"))?;
+    std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+
+    let tiles = tile_count(duration, opts.interval);
+    let per_sheet = opts.columns * opts.rows;
+    let mut result = StoryboardResult {
+        sheets: Vec::new(),
+        vtt: "WEBVTT\n".to_string(),
+    };
+    let mut sheet = None;
+    for tile in 0..tiles {
+        let (index, slot) = (tile / per_sheet, tile % per_sheet);
+        let at = opts.interval * tile;
+        let (frame, _) = seeker.frame_at(at, false)?;
+        let scaled = scale_video(&frame, Pixel::YUVJ420P, opts.tile_width, opts.tile_height)?;
+        let (x, y) = (
+            slot % opts.columns * opts.tile_width,
+            slot / opts.columns * opts.tile_height,
+        );
+        let rows = (tiles - tile).min(per_sheet).div_ceil(opts.columns);
+        place(
+            sheet.get_or_insert_with(|| blank_sheet(opts, rows)),
+            &scaled,
+            x,
+            y,
+        );
+
+        let name = format!("sheet{}.jpg", index + 1);
+        let end = (at + opts.interval).min(duration);
+        let _ = write!(
+            result.vtt,
+            "\n{} --> {}\n{}{name}#xywh={x},{y},{},{}\n",
+            timestamp(at),
+            timestamp(end),
+            opts.sheet_prefix,
+            opts.tile_width,
+            opts.tile_height
+        );
+        if slot + 1 == per_sheet || tile + 1 == tiles {
+            let full = sheet.take().expect("a tile was just placed");
+            let file = dir.join(name);
+            std::fs::write(&file, encode_jpeg(full, opts.quality)?)
+                .with_context(|| format!("write {}", file.display()))?;
+            result.sheets.push(file);
+        }
+    }
+    Ok(result)
+}
+
+/// Tiles of a `duration` long file, one every `interval` from the start.
+fn tile_count(duration: Duration, interval: Duration) -> u32 {
+    (duration.as_micros().div_ceil(interval.as_micros())).max(1) as u32
+}
+
+/// A black sheet `rows` of tiles tall.
+fn blank_sheet(opts: &StoryboardOpts, rows: u32) -> ffmpeg_next::frame::Video {
+    let mut sheet = ffmpeg_next::frame::Video::new(
+        Pixel::YUVJ420P,
+        opts.columns * opts.tile_width,
+        rows * opts.tile_height,
+    );
+    for (plane, value) in [(0, 0), (1, 128), (2, 128)] {
+        sheet.data_mut(plane).fill(value);
+    }
+    sheet
+}
+
+/// Copy `tile` into `sheet` with its top left corner at `x`, `y` (both even).
+fn place(sheet: &mut ffmpeg_next::frame::Video, tile: &ffmpeg_next::frame::Video, x: u32, y: u32) {
+    for plane in 0..3 {
+        let shift = if plane == 0 { 0 } else { 1 };
+        let (w, h) = (
+            (tile.width() >> shift) as usize,
+            (tile.height() >> shift) as usize,
+        );
+        let (x, y) = ((x >> shift) as usize, (y >> shift) as usize);
+        let (src_stride, dst_stride) = (tile.stride(plane), sheet.stride(plane));
+        for row in 0..h {
+            let src = &tile.data(plane)[row * src_stride..row * src_stride + w];
+            let at = (y + row) * dst_stride + x;
+            sheet.data_mut(plane)[at..at + w].copy_from_slice(src);
+        }
+    }
+}
+
+/// `at` as a WebVTT timestamp, `HH:MM:SS.mmm`.
+fn timestamp(at: Duration) -> String {
+    let ms = at.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+#[path = "storyboard_test.rs"]
+mod storyboard_test;
@@ -0,0 +1,92 @@
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+
+/// The cues of `vtt` as (start, end, target).
+fn cues(vtt: &str) -> Vec<(Duration, Duration, String)> {
+    let parse = |ts: &str| {
+        let (hms, ms) = ts.split_once('.').unwrap();
+        let secs = hms
+            .split(':')
+            .fold(0u64, |acc, part| acc * 60 + part.parse::<u64>().unwrap());
+        Duration::from_secs(secs) + Duration::from_millis(ms.parse().unwrap())
+    };
+    let mut lines = vtt.lines();
+    assert_eq!(lines.next(), Some("WEBVTT"));
+    let mut cues = Vec::new();
+    while let Some(line) = lines.next() {
+        if let Some((start, end)) = line.split_once(" --> ") {
+            cues.push((parse(start), parse(end), lines.next().unwrap().to_string()));
+        }
+    }
+    cues
+}
+
+#[tokio::test]
+async fn one_tile_a_second_over_bounded_sheets() {
+    crate::init().unwrap();
+    let fixture = ensure_fixture(&FixtureSpec::default().video_only())
+        .await
+        .unwrap();
+    let path = fixture.to_string_lossy().into_owned();
+    let duration = Duration::from_secs_f64(
+        crate::metadata::probe(&path)
+            .unwrap()
+            .format
+            .duration_sec
+            .unwrap(),
+    );
+    let dir = std::env::temp_dir().join(format!("storyboard-{}", std::process::id()));
+    let opts = StoryboardOpts {
+        interval: Duration::from_secs(1),
+        tile_width: 80,
+        tile_height: 60,
+        columns: 2,
+        rows: 2,
+        sheet_prefix: "storyboard/".into(),
+        ..StoryboardOpts::default()
+    };
+    let result = generate(&path, &dir, &opts).unwrap();
+
+    let cues = cues(&result.vtt);
+    let tiles = duration.as_millis().div_ceil(1000) as usize;
+    assert_eq!(cues.len(), tiles);
+    // Four tiles a sheet; the last one only as tall as the rows it fills.
+    assert_eq!(result.sheets.len(), tiles.div_ceil(4));
+    for (i, sheet) in result.sheets.iter().enumerate() {
+        assert_eq!(sheet, &dir.join(format!("sheet{}.jpg", i + 1)));
+        let left = tiles - i * 4;
+        let rows = left.min(4).div_ceil(2) as u32;
+        let info = crate::metadata::probe(&sheet.to_string_lossy()).unwrap();
+        assert_eq!(info.streams[0].width, Some(160));
+        assert_eq!(info.streams[0].height, Some(60 * rows));
+    }
+
+    // Back to back from the start to the end of the file.
+    assert_eq!(cues[0].0, Duration::ZERO);
+    for pair in cues.windows(2) {
+        assert_eq!(pair[0].1, pair[1].0);
+    }
+    assert_eq!(
+        cues.last().unwrap().1.as_millis(),
+        duration.as_millis(),
+        "cues end with the file"
+    );
+    assert_eq!(cues[0].2, "storyboard/sheet1.jpg#xywh=0,0,80,60");
+    assert_eq!(cues[3].2, "storyboard/sheet1.jpg#xywh=80,60,80,60");
+    assert_eq!(cues[4].2, "storyboard/sheet2.jpg#xywh=0,0,80,60");
+}
+
+#[test]
+fn rejects_odd_tiles_and_empty_grids() {
+    let dir = std::env::temp_dir();
+    let odd = StoryboardOpts {
+        tile_width: 81,
+        ..StoryboardOpts::default()
+    };
+    assert!(generate("unused.mp4", &dir, &odd).is_err());
+    let empty = StoryboardOpts {
+        columns: 0,
+        ..StoryboardOpts::default()
+    };
+    assert!(generate("unused.mp4", &dir, &empty).is_err());
+}
@@ -8,6 +8,9 @@ pub struct AvStream {
     parameters: Parameters,
     time_base: Rational,
     rate: Rational,
+    /// The stream's metadata dictionary (`language`, `title`,
+    /// `handler_name`), in the order the demuxer set it.
+    metadata: Vec<(String, String)>,
 }
 
 impl AvStream {
@@ -20,6 +23,7 @@ impl AvStream {
             parameters,
             time_base,
             rate,
+            metadata: Vec::new(),
         }
     }
 
@@ -28,14 +32,101 @@ impl AvStream {
     }
 
     /// Return a copy with a different stream index. Used when muxing an
-    /// encoder-output stream (which [`for_encoder_output`](Self::for_encoder_output)
-    /// creates with index 0) alongside copied input streams, so each output
-    /// stream keeps a distinct index for the muxer's index-keyed mapping.
+    /// encoder-output stream (see [`crate::encoder::Encoder::output_stream`])
+    /// alongside copied input streams, so each output stream keeps a
+    /// distinct index for the muxer's index-keyed mapping.
     pub fn with_index(mut self, index: usize) -> Self {
         self.index = index;
         self
     }
 
+    /// Return a copy reporting a different picture size. Used for outputs that
+    /// deliver frames of another geometry than the input (e.g. a Raw ROI).
+    pub fn with_dimensions(self, width: u32, height: u32) -> Self {
+        let params = self.parameters.clone();
+        unsafe {
+            let ptr = params.as_ptr() as *mut ffmpeg_next::ffi::AVCodecParameters;
+            (*ptr).width = width as i32;
+            (*ptr).height = height as i32;
+        }
+        Self {
+            parameters: params,
+            ..self
+        }
+    }
+
+    /// Return a copy carrying `metadata` instead, e.g. the input's on an
+    /// encoder's output stream.
+    pub fn with_metadata(mut self, metadata: Vec<(String, String)>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn metadata(&self) -> &[(String, String)] {
+        &self.metadata
+    }
+
+    /// The `language` tag (ISO 639-2, e.g. `eng`), if the stream has one.
+    pub fn language(&self) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(key, _)| key == "language")
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The display matrix side data: how a player turns decoded pictures to
+    /// show them (phones record sideways and set one).
+    pub fn display_matrix(&self) -> Option<[i32; 9]> {
+        unsafe {
+            let ptr = self.parameters.as_ptr();
+            let sd = ffmpeg_next::ffi::av_packet_side_data_get(
+                (*ptr).coded_side_data,
+                (*ptr).nb_coded_side_data,
+                ffmpeg_next::ffi::AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX,
+            );
+            if sd.is_null() || (*sd).size < std::mem::size_of::<[i32; 9]>() {
+                return None;
+            }
+            Some(std::ptr::read_unaligned((*sd).data as *const [i32; 9]))
+        }
+    }
+
+    /// Clockwise rotation a player applies to the pictures, snapped to 0, 90,
+    /// 180 or 270 degrees; 0 without a display matrix.
+    pub fn rotation_degrees(&self) -> u32 {
+        self.display_matrix().map_or(0, rotation_from_matrix)
+    }
+
+    /// Return a copy with `matrix` as its display matrix, or with none.
+    pub fn with_display_matrix(self, matrix: Option<[i32; 9]>) -> Self {
+        let params = self.parameters.clone();
+        unsafe {
+            let ptr = params.as_ptr() as *mut ffmpeg_next::ffi::AVCodecParameters;
+            let kind = ffmpeg_next::ffi::AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX;
+            ffmpeg_next::ffi::av_packet_side_data_remove(
+                (*ptr).coded_side_data,
+                &mut (*ptr).nb_coded_side_data,
+                kind,
+            );
+            if let Some(matrix) = matrix {
+                let sd = ffmpeg_next::ffi::av_packet_side_data_new(
+                    &mut (*ptr).coded_side_data,
+                    &mut (*ptr).nb_coded_side_data,
+                    kind,
+                    std::mem::size_of::<[i32; 9]>(),
+                    0,
+                );
+                if !sd.is_null() {
+                    std::ptr::write_unaligned((*sd).data as *mut [i32; 9], matrix);
+                }
+            }
+        }
+        Self {
+            parameters: params,
+            ..self
+        }
+    }
+
     pub fn parameters(&self) -> &Parameters {
         &self.parameters
     }
@@ -56,6 +147,11 @@ impl AvStream {
         self.parameters.medium() == ffmpeg_next::media::Type::Audio
     }
 
+    /// Neither video nor audio: subtitles, KLV and other timed metadata.
+    pub fn is_data(&self) -> bool {
+        !self.is_video() && !self.is_audio()
+    }
+
     pub fn width(&self) -> u32 {
         unsafe {
             let ptr = self.parameters.as_ptr() as *const ffmpeg_next::ffi::AVCodecParameters;
@@ -88,28 +184,22 @@ impl AvStream {
             n.max(0) as u32
         }
     }
+}
 
-    /// Build an AvStream suitable for mux encoder output: same dimensions/time_base/rate as
-    /// `input`, but with `codec_id` (e.g. H264). Used when muxing encoded packets.
-    pub fn for_encoder_output(input: &AvStream, codec_id: ffmpeg_next::codec::Id) -> Self {
-        let codec_type = if input.is_audio() {
-            ffmpeg_next::media::Type::Audio
-        } else {
-            ffmpeg_next::media::Type::Video
-        };
-        let params = input.parameters().clone();
-        unsafe {
-            let ptr = params.as_ptr() as *mut ffmpeg_next::ffi::AVCodecParameters;
-            (*ptr).codec_type = codec_type.into();
-            (*ptr).codec_id = codec_id.into();
-        }
-        Self {
-            index: 0,
-            parameters: params,
-            time_base: input.time_base(),
-            rate: input.rate(),
-        }
-    }
+/// The clockwise rotation of a display matrix, snapped to a quarter turn:
+/// the negated `av_display_rotation_get` (which counts counter-clockwise),
+/// ignoring scaling and flips.
+pub(crate) fn rotation_from_matrix(matrix: [i32; 9]) -> u32 {
+    // The scale cancels out, so the 16.16 fixed point can stay as it is.
+    let (a, b) = (matrix[0] as f64, matrix[1] as f64);
+    let (c, d) = (matrix[3] as f64, matrix[4] as f64);
+    let (scale_x, scale_y) = (a.hypot(c), b.hypot(d));
+    if scale_x == 0.0 || scale_y == 0.0 {
+        return 0;
+    }
+    let clockwise = (b / scale_y).atan2(a / scale_x).to_degrees();
+    let quarters = (clockwise / 90.0).round() as i64;
+    (quarters.rem_euclid(4) * 90) as u32
 }
 
 impl From<stream::Stream<'_>> for AvStream {
@@ -119,6 +209,11 @@ impl From<stream::Stream<'_>> for AvStream {
             parameters: stream.parameters(),
             time_base: stream.time_base(),
             rate: stream.avg_frame_rate(),
+            metadata: stream
+                .metadata()
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
         }
     }
 }
@@ -130,6 +225,7 @@ impl Clone for AvStream {
             parameters: self.parameters.clone(),
             time_base: self.time_base,
             rate: self.rate,
+            metadata: self.metadata.clone(),
         }
     }
 }
@@ -0,0 +1,217 @@
+//! Ordered teardown of a bus. Its tasks form a chain — the input feeds the
+//! decoders, decoders feed encoders, and all of them feed the outputs (muxers
+//! and packet streams) — and each stage only ends cleanly once the one before
+//! it has delivered its EOF: an encoder stopped along with its decoder loses
+//! the frames still being flushed, a muxer stopped along with its encoder
+//! writes no trailer.
+//!
+//! So a bus comes down one [`Stage`] at a time: the input stops reading and
+//! sends its EOF, then the bus waits for every task of each stage in turn to
+//! end, each having flushed and passed the EOF on. Every task of a stage holds
+//! a [`StageGuard`] of its [`StageTasks`] until it ends; the wait for a stage
+//! is over when the last one drops. A stage that is not over within the
+//! stage timeout is forced, and so is every stage after it: their tokens are
+//! cancelled, decoders and encoders stop where they are, outputs finish with
+//! what they have.
+//!
+//! [`Bus::shutdown`](crate::bus::Bus::shutdown) and
+//! [`Bus::stop`](crate::bus::Bus::stop) both take this path, with the stage
+//! timeouts of [`TeardownConfig`].
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Stage timeouts of a bus's teardown, see
+/// [`BusOptions::teardown`](crate::bus::BusOptions::teardown).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TeardownConfig {
+    /// Longest [`Bus::shutdown`](crate::bus::Bus::shutdown) waits for one
+    /// stage.
+    pub shutdown_stage_timeout: Duration,
+    /// Longest [`Bus::stop`](crate::bus::Bus::stop), or dropping the bus,
+    /// waits for one stage.
+    pub stop_stage_timeout: Duration,
+}
+
+impl Default for TeardownConfig {
+    fn default() -> Self {
+        Self {
+            shutdown_stage_timeout: Duration::from_secs(10),
+            stop_stage_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// A step of the teardown, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// The input's reader, until it has sent its EOF. A push input's reader
+    /// waits for its handle's next frame, so it only gets to stop once one
+    /// comes or the handle is dropped.
+    Input,
+    /// Decoders (and the relay turning raw video packets into frames).
+    Decoders,
+    Encoders,
+    /// Muxers and packet streams.
+    Outputs,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Self::Input, Self::Decoders, Self::Encoders, Self::Outputs];
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Input => "input",
+            Self::Decoders => "decoders",
+            Self::Encoders => "encoders",
+            Self::Outputs => "outputs",
+        })
+    }
+}
+
+/// The running tasks of one stage, and the token that forces them.
+#[derive(Clone, Default)]
+pub struct StageTasks {
+    inner: Arc<StageInner>,
+}
+
+#[derive(Default)]
+struct StageInner {
+    running: AtomicUsize,
+    /// Woken when the last task ends.
+    idle: Notify,
+    cancel: CancellationToken,
+}
+
+/// One task of a stage, from [`StageTasks::enter`] until dropped.
+pub struct StageGuard(Arc<StageInner>);
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        if self.0.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl StageTasks {
+    /// Count a task in; the stage waits for it until the guard drops.
+    pub fn enter(&self) -> StageGuard {
+        self.inner.running.fetch_add(1, Ordering::AcqRel);
+        StageGuard(self.inner.clone())
+    }
+
+    /// A token for one task, cancelled when the stage is forced.
+    pub fn token(&self) -> CancellationToken {
+        self.inner.cancel.child_token()
+    }
+
+    pub fn running(&self) -> usize {
+        self.inner.running.load(Ordering::Acquire)
+    }
+
+    /// Resolves once no task of the stage runs.
+    pub async fn finished(&self) {
+        loop {
+            let idle = self.inner.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if self.running() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Cancel the tokens of every task of the stage.
+    pub fn force(&self) {
+        self.inner.cancel.cancel();
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.inner.cancel.is_cancelled()
+    }
+}
+
+/// The stages of one bus.
+#[derive(Clone, Default)]
+pub struct Teardown {
+    pub input: StageTasks,
+    pub decoders: StageTasks,
+    pub encoders: StageTasks,
+    pub outputs: StageTasks,
+}
+
+impl Teardown {
+    pub fn stage(&self, stage: Stage) -> &StageTasks {
+        match stage {
+            Stage::Input => &self.input,
+            Stage::Decoders => &self.decoders,
+            Stage::Encoders => &self.encoders,
+            Stage::Outputs => &self.outputs,
+        }
+    }
+
+    /// Stop the input, then wait for each stage in order, up to
+    /// `stage_timeout` each. The first stage not over in time is forced
+    /// along with every later one; forced tasks get one more
+    /// `stage_timeout` to wind down. Returns the stages forced, none when
+    /// every stage ended on its own.
+    pub async fn run(&self, stage_timeout: Duration) -> Vec<Stage> {
+        // The input's token is its reader's: cancelling it is how the input
+        // stops, and it sends its EOF on the way out.
+        self.input.force();
+        let Some(first) = self.first_late(stage_timeout).await else {
+            return Vec::new();
+        };
+        let forced = Stage::ALL
+            .into_iter()
+            .skip_while(|stage| *stage != first)
+            .collect::<Vec<_>>();
+        tracing::warn!(
+            "teardown: {} did not finish within {:?}, forcing {:?}",
+            first,
+            stage_timeout,
+            forced
+        );
+        for stage in &forced {
+            self.stage(*stage).force();
+        }
+        let wind_down =
+            futures::future::join_all(forced.iter().map(|stage| self.stage(*stage).finished()));
+        if tokio::time::timeout(stage_timeout, wind_down)
+            .await
+            .is_err()
+        {
+            tracing::warn!("teardown: forced tasks still running, leaving them behind");
+        }
+        forced
+    }
+
+    /// The first stage that did not finish within `stage_timeout` of the
+    /// one before it.
+    async fn first_late(&self, stage_timeout: Duration) -> Option<Stage> {
+        for stage in Stage::ALL {
+            let tasks = self.stage(stage);
+            if tokio::time::timeout(stage_timeout, tasks.finished())
+                .await
+                .is_err()
+            {
+                return Some(stage);
+            }
+            tracing::debug!("teardown: {} finished", stage);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+#[path = "teardown_test.rs"]
+mod teardown_test;
@@ -0,0 +1,87 @@
+use std::sync::Mutex;
+
+use super::*;
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A task of `stage` that ends once `before` has finished, logging its stage
+/// in `ended`.
+fn chained(
+    teardown: &Teardown,
+    stage: Stage,
+    before: Option<Stage>,
+    ended: &Arc<Mutex<Vec<Stage>>>,
+) {
+    let guard = teardown.stage(stage).enter();
+    let token = teardown.stage(stage).token();
+    let before = before.map(|before| teardown.stage(before).clone());
+    let ended = ended.clone();
+    tokio::spawn(async move {
+        match before {
+            Some(before) => before.finished().await,
+            // The input ends when stopped.
+            None => token.cancelled().await,
+        }
+        // Flushing.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        ended.lock().unwrap().push(stage);
+        drop(guard);
+    });
+}
+
+#[tokio::test]
+async fn stages_end_in_order() {
+    let teardown = Teardown::default();
+    let ended = Arc::new(Mutex::new(Vec::new()));
+    // Registered backwards: the order comes from the chain alone.
+    chained(&teardown, Stage::Outputs, Some(Stage::Encoders), &ended);
+    chained(&teardown, Stage::Outputs, Some(Stage::Decoders), &ended);
+    chained(&teardown, Stage::Encoders, Some(Stage::Decoders), &ended);
+    chained(&teardown, Stage::Decoders, Some(Stage::Input), &ended);
+    chained(&teardown, Stage::Input, None, &ended);
+
+    assert_eq!(teardown.run(TIMEOUT).await, []);
+    assert_eq!(
+        *ended.lock().unwrap(),
+        [
+            Stage::Input,
+            Stage::Decoders,
+            Stage::Encoders,
+            Stage::Outputs,
+            Stage::Outputs
+        ]
+    );
+    assert!(!teardown.outputs.is_forced());
+}
+
+#[tokio::test]
+async fn a_late_stage_forces_itself_and_the_rest() {
+    let teardown = Teardown::default();
+    let ended = Arc::new(Mutex::new(Vec::new()));
+    chained(&teardown, Stage::Input, None, &ended);
+    chained(&teardown, Stage::Decoders, Some(Stage::Input), &ended);
+    // An encoder that never flushes, and an output waiting on it: both end
+    // when forced.
+    let wedged = |stage| {
+        let guard = teardown.stage(stage).enter();
+        let token = teardown.stage(stage).token();
+        tokio::spawn(async move {
+            token.cancelled().await;
+            drop(guard);
+        });
+    };
+    wedged(Stage::Encoders);
+    wedged(Stage::Outputs);
+    // One left behind: forcing does not stop it.
+    std::mem::forget(teardown.outputs.enter());
+
+    let started = tokio::time::Instant::now();
+    let forced = teardown.run(TIMEOUT).await;
+    assert_eq!(forced, [Stage::Encoders, Stage::Outputs]);
+    assert!(!teardown.decoders.is_forced());
+    assert_eq!(*ended.lock().unwrap(), [Stage::Input, Stage::Decoders]);
+    assert_eq!(teardown.encoders.running(), 0);
+    assert_eq!(teardown.outputs.running(), 1);
+    // One timeout for the encoders, one for the wind-down.
+    assert!(started.elapsed() < TIMEOUT * 3, "{:?}", started.elapsed());
+}
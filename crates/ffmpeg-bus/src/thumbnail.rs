@@ -0,0 +1,38 @@
+//! Small JPEGs of a recording at arbitrary times, e.g. for its timeline:
+//! [`extract`] grabs the frame shown at a time the way
+//! [`crate::snapshot::frame_at`] does (from the last keyframe at or before
+//! it, decoded forward) and scales it down to a thumbnail width.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use ffmpeg_next::format::Pixel;
+
+use crate::frame::scale_video;
+use crate::snapshot::{DEFAULT_TIMEOUT, Seeker, encode_jpeg, output_size};
+
+/// JPEG quality of a thumbnail (1..=100).
+const QUALITY: u8 = 75;
+
+/// The frame of `path`'s video shown `at` after the start of the file, as a
+/// JPEG at most `max_width` wide: narrower sources keep their width, and the
+/// height follows at the file's aspect ratio (even sizes). `at` past the end
+/// gives the last frame. Blocking; async callers run it in `spawn_blocking`.
+pub fn extract(path: &str, at: Duration, max_width: u32) -> Result<Vec<u8>> {
+    let (frame, _) = Seeker::open(path, DEFAULT_TIMEOUT)?.frame_at(at, true)?;
+    let (width, height) = thumbnail_size(frame.width(), frame.height(), max_width);
+    // The MJPEG encoder takes full-range YUV rather than RGB.
+    let yuv = scale_video(&frame, Pixel::YUVJ420P, width, height)?;
+    encode_jpeg(yuv, QUALITY)
+}
+
+/// The size of a thumbnail at most `max_width` wide of a `src_w`x`src_h`
+/// frame.
+fn thumbnail_size(src_w: u32, src_h: u32, max_width: u32) -> (u32, u32) {
+    let width = (max_width.min(src_w) & !1).max(2);
+    output_size(src_w, src_h, Some(width), None)
+}
+
+#[cfg(test)]
+#[path = "thumbnail_test.rs"]
+mod thumbnail_test;
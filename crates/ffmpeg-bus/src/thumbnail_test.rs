@@ -0,0 +1,57 @@
+use ffmpeg_next::media::Type;
+
+use super::*;
+use crate::fixture::{FixtureSpec, ensure_fixture};
+
+/// The size of `jpeg` once decoded, which fails for anything but a JPEG.
+fn decoded_size(jpeg: &[u8]) -> (u32, u32) {
+    let codec = ffmpeg_next::decoder::find(ffmpeg_next::codec::Id::MJPEG).unwrap();
+    let mut decoder = ffmpeg_next::codec::Context::new_with_codec(codec)
+        .decoder()
+        .video()
+        .unwrap();
+    decoder
+        .send_packet(&ffmpeg_next::Packet::copy(jpeg))
+        .unwrap();
+    decoder.send_eof().unwrap();
+    let mut frame = ffmpeg_next::frame::Video::empty();
+    decoder.receive_frame(&mut frame).unwrap();
+    (frame.width(), frame.height())
+}
+
+#[tokio::test]
+async fn a_thumbnail_at_one_second_keeps_the_aspect_ratio() {
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let (src_w, src_h) = {
+        let ictx = ffmpeg_next::format::input(&path).unwrap();
+        let video = ictx.streams().best(Type::Video).unwrap();
+        let decoder = ffmpeg_next::codec::Context::from_parameters(video.parameters())
+            .unwrap()
+            .decoder()
+            .video()
+            .unwrap();
+        (decoder.width(), decoder.height())
+    };
+
+    let jpeg = extract(path.to_str().unwrap(), Duration::from_secs(1), 160).unwrap();
+    assert!(jpeg.starts_with(&[0xff, 0xd8]), "not a JPEG");
+    assert_eq!(decoded_size(&jpeg), thumbnail_size(src_w, src_h, 160));
+    assert_eq!(decoded_size(&jpeg).0, 160.min(src_w) & !1);
+}
+
+#[tokio::test]
+async fn thumbnails_scale_down_only_and_past_the_end_show_the_last_frame() {
+    // 5s of 320x240.
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let path = path.to_str().unwrap();
+
+    let small = extract(path, Duration::from_secs(1), 160).unwrap();
+    assert_eq!(decoded_size(&small), (160, 120));
+    let full = extract(path, Duration::from_secs(1), 1920).unwrap();
+    assert_eq!(decoded_size(&full), (320, 240));
+    let odd = extract(path, Duration::from_secs(1), 101).unwrap();
+    assert_eq!(decoded_size(&odd), (100, 74));
+
+    let last = extract(path, Duration::from_secs(60), 160).unwrap();
+    assert_eq!(decoded_size(&last), (160, 120));
+}
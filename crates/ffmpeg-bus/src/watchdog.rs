@@ -0,0 +1,390 @@
+//! Stall detection for the bus's worker loops. A decoder, encoder or mux loop
+//! stuck in a call into FFmpeg (a wedged hardware encoder, a network write
+//! the peer stopped reading) looks alive from the outside: its task runs and
+//! its channels stay open, only nothing comes out of it. Each loop holds a
+//! [`Progress`] and marks every such call with [`Progress::enter`]; a
+//! [`Watchdog`] checks them every [`WatchdogConfig::interval`] and reports a
+//! call that outlasts its kind's threshold as [`BusEvent::TaskStalled`].
+//!
+//! The loop's task then recovers it, reporting [`BusEvent::TaskRecovered`]:
+//! - an encoder task leaves the stuck thread behind and rebuilds its encoder
+//!   on a new one;
+//! - a decoder task does the same from the stream's parameters, feeding the
+//!   new decoder from the next keyframe;
+//! - a `Net` mux has its blocked I/O interrupted and reconnects under its
+//!   retry policy (see [`WriteErrorKind::Stalled`]). Other outputs have no
+//!   I/O to interrupt; their stalls are only reported.
+//!
+//! After [`WatchdogConfig::max_recoveries`] recoveries the next stall fails
+//! the task ([`BusEvent::TaskFailed`]), ending the outputs it feeds. A thread
+//! left behind cannot be killed: should its call ever return, it sees it was
+//! retired and exits without sending anything.
+//!
+//! [`WriteErrorKind::Stalled`]: crate::write_error::WriteErrorKind::Stalled
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::bus::BusEvent;
+
+/// Thresholds of a [`Watchdog`], see
+/// [`BusOptions::watchdog`](crate::bus::BusOptions::watchdog).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// How often the loops are checked.
+    pub interval: Duration,
+    /// Longest a decoder may spend in one call (a packet, a frame).
+    pub decoder_stall: Duration,
+    /// Longest an encoder may spend in one call (a frame, a packet).
+    pub encoder_stall: Duration,
+    /// Longest a mux may spend writing one packet; a network write waits on
+    /// the peer, so more than the others.
+    pub mux_stall: Duration,
+    /// Recoveries of one task; it fails when it stalls once more.
+    pub max_recoveries: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            decoder_stall: Duration::from_secs(10),
+            encoder_stall: Duration::from_secs(10),
+            mux_stall: Duration::from_secs(30),
+            max_recoveries: 3,
+        }
+    }
+}
+
+impl WatchdogConfig {
+    /// How long a call of a `kind` loop may last.
+    pub fn threshold(&self, kind: TaskKind) -> Duration {
+        match kind {
+            TaskKind::Decoder => self.decoder_stall,
+            TaskKind::Encoder => self.encoder_stall,
+            TaskKind::Mux => self.mux_stall,
+        }
+    }
+}
+
+/// The kind of loop a [`Progress`] tracks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TaskKind {
+    Decoder,
+    Encoder,
+    Mux,
+}
+
+impl fmt::Display for TaskKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Decoder => "decoder",
+            Self::Encoder => "encoder",
+            Self::Mux => "mux",
+        })
+    }
+}
+
+/// One loop's liveness: when the call it is in began, if it is in one.
+/// Shared by the loop, its task and the [`Watchdog`]; a recovered loop gets
+/// a new one.
+pub struct Progress {
+    kind: TaskKind,
+    /// Whom the loop works for, in events: `stream 0 (libx264)`, an output id.
+    name: String,
+    origin: Instant,
+    /// Milliseconds after `origin`, plus one, the current call began at; 0
+    /// between calls.
+    entered: AtomicU64,
+    /// The `entered` of the last stall reported, so each is reported once.
+    reported: AtomicU64,
+    retired: AtomicBool,
+    /// Woken on a stall; the loop's task waits on it.
+    alarm: Notify,
+    /// Raised on a stall to interrupt the loop's blocking I/O.
+    interrupt: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+/// A call in progress, from [`Progress::enter`] until dropped.
+pub struct Busy<'a>(&'a Progress);
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.entered.store(0, Ordering::Release);
+    }
+}
+
+impl Progress {
+    fn new(kind: TaskKind, name: String) -> Self {
+        Self {
+            kind,
+            name,
+            origin: Instant::now(),
+            entered: AtomicU64::new(0),
+            reported: AtomicU64::new(0),
+            retired: AtomicBool::new(false),
+            alarm: Notify::new(),
+            interrupt: Mutex::new(None),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.origin.elapsed().as_millis() as u64 + 1
+    }
+
+    /// Mark a call that must return within the loop's threshold. Waiting for
+    /// input or for room downstream is not one.
+    pub fn enter(&self) -> Busy<'_> {
+        self.entered.store(self.now(), Ordering::Release);
+        Busy(self)
+    }
+
+    pub fn kind(&self) -> TaskKind {
+        self.kind
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// How long the call in progress has lasted so far.
+    pub fn busy_for(&self) -> Option<Duration> {
+        match self.entered.load(Ordering::Acquire) {
+            0 => None,
+            at => Some(Duration::from_millis(self.now().saturating_sub(at))),
+        }
+    }
+
+    /// Give the loop up: the watchdog forgets it, and the loop, should its
+    /// call ever return, must stop without sending anything.
+    pub fn retire(&self) {
+        self.retired.store(true, Ordering::Release);
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::Acquire)
+    }
+
+    /// Wait until the watchdog finds the loop stalled.
+    pub async fn stalled(&self) {
+        self.alarm.notified().await;
+    }
+
+    /// Raise `flag` when the loop stalls (see
+    /// [`AvOutput::interrupt_flag`](crate::output::AvOutput::interrupt_flag));
+    /// `None` raises nothing.
+    pub fn set_interrupt(&self, flag: Option<Arc<AtomicBool>>) {
+        *self.interrupt.lock().unwrap_or_else(|e| e.into_inner()) = flag;
+    }
+
+    /// How long the call in progress has lasted, when longer than `threshold`
+    /// and not reported yet.
+    fn take_stall(&self, threshold: Duration) -> Option<Duration> {
+        let entered = self.entered.load(Ordering::Acquire);
+        if entered == 0 {
+            return None;
+        }
+        let busy = Duration::from_millis(self.now().saturating_sub(entered));
+        if busy <= threshold || self.reported.swap(entered, Ordering::AcqRel) == entered {
+            return None;
+        }
+        Some(busy)
+    }
+}
+
+/// Resolves when the watchdog finds the loop of `progress` stalled; never
+/// when there is none.
+pub(crate) async fn stalled(progress: Option<&Progress>) {
+    match progress {
+        Some(progress) => progress.stalled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// A blocking loop on its worker thread, fed through a bounded queue, as the
+/// task relaying to it holds it. A stalled one is retired and replaced.
+pub(crate) struct WatchedLoop<T> {
+    pub(crate) tx: std::sync::mpsc::SyncSender<T>,
+    pub(crate) handle: tokio::sync::oneshot::Receiver<()>,
+    /// A child of the task's token, to stop this loop alone.
+    cancel: CancellationToken,
+    pub(crate) progress: Option<Arc<Progress>>,
+}
+
+impl<T: Send + 'static> WatchedLoop<T> {
+    /// Run `body` (its queue, its token, its progress) on a worker thread
+    /// named `name`, behind a queue of `bound` items.
+    pub(crate) fn spawn(
+        name: &str,
+        bound: usize,
+        task_cancel: &CancellationToken,
+        progress: Option<Arc<Progress>>,
+        body: impl FnOnce(std::sync::mpsc::Receiver<T>, CancellationToken, Option<Arc<Progress>>)
+        + Send
+        + 'static,
+    ) -> Self {
+        let (tx, rx) = std::sync::mpsc::sync_channel(bound);
+        let cancel = task_cancel.child_token();
+        let loop_cancel = cancel.clone();
+        let loop_progress = progress.clone();
+        let handle = crate::worker::spawn(name, move || body(rx, loop_cancel, loop_progress));
+        Self {
+            tx,
+            handle,
+            cancel,
+            progress,
+        }
+    }
+
+    /// Leave the loop behind: it stops at its next check, should it get
+    /// there, and sends nothing more.
+    pub(crate) fn retire(&self) {
+        if let Some(progress) = &self.progress {
+            progress.retire();
+        }
+        self.cancel.cancel();
+    }
+}
+
+/// Watches the [`Progress`] of a bus's loops. Clones share the loops.
+#[derive(Clone)]
+pub struct Watchdog {
+    inner: Arc<WatchdogInner>,
+}
+
+struct WatchdogInner {
+    config: WatchdogConfig,
+    tasks: Mutex<Vec<Arc<Progress>>>,
+    events: tokio::sync::broadcast::Sender<BusEvent>,
+}
+
+impl Watchdog {
+    /// A watchdog reporting to `events`; it checks nothing until
+    /// [`spawn`](Self::spawn)ed or [`scan`](Self::scan)ned.
+    pub fn new(config: WatchdogConfig, events: tokio::sync::broadcast::Sender<BusEvent>) -> Self {
+        Self {
+            inner: Arc::new(WatchdogInner {
+                config,
+                tasks: Mutex::new(Vec::new()),
+                events,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &WatchdogConfig {
+        &self.inner.config
+    }
+
+    /// Watch a new loop of `kind` working for `name`, until it is retired or
+    /// every other holder of the returned progress is gone.
+    pub fn register(&self, kind: TaskKind, name: impl Into<String>) -> Arc<Progress> {
+        let progress = Arc::new(Progress::new(kind, name.into()));
+        self.tasks().push(progress.clone());
+        progress
+    }
+
+    fn tasks(&self) -> std::sync::MutexGuard<'_, Vec<Arc<Progress>>> {
+        self.inner.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Check every loop once: report each newly stalled one, raise its
+    /// interrupt flag and wake its task. Returns how many stalled.
+    pub fn scan(&self) -> usize {
+        let mut tasks = self.tasks();
+        tasks.retain(|p| !p.is_retired() && Arc::strong_count(p) > 1);
+        let mut stalls = 0;
+        for progress in tasks.iter() {
+            let Some(busy) = progress.take_stall(self.config().threshold(progress.kind)) else {
+                continue;
+            };
+            stalls += 1;
+            tracing::warn!(
+                "{} {} stalled: one call running for {:?}",
+                progress.kind,
+                progress.name,
+                busy
+            );
+            if let Some(flag) = progress
+                .interrupt
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_ref()
+            {
+                flag.store(true, Ordering::Release);
+            }
+            let _ = self.inner.events.send(BusEvent::TaskStalled {
+                task: progress.kind,
+                name: progress.name.clone(),
+                stalled_ms: busy.as_millis() as u64,
+            });
+            progress.alarm.notify_one();
+        }
+        stalls
+    }
+
+    /// [`scan`](Self::scan) every [`WatchdogConfig::interval`] until `cancel`.
+    pub fn spawn(&self, cancel: CancellationToken) {
+        let watchdog = self.clone();
+        let every = self.config().interval.max(Duration::from_millis(10));
+        crate::worker::spawn_task("bus-watchdog", async move {
+            let mut ticks = tokio::time::interval(every);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = ticks.tick() => {
+                        watchdog.scan();
+                    }
+                }
+            }
+        });
+    }
+
+    /// Count a recovery of the stalled loop of `progress`. Past
+    /// [`WatchdogConfig::max_recoveries`] its task fails instead, and this
+    /// returns false.
+    pub fn may_recover(&self, progress: &Progress, recoveries: &mut u32) -> bool {
+        *recoveries += 1;
+        if *recoveries <= self.config().max_recoveries {
+            return true;
+        }
+        self.failed(progress, format!("stalled {} times", *recoveries));
+        false
+    }
+
+    /// Report the loop of `progress` running again after its `recoveries`-th
+    /// recovery.
+    pub fn recovered(&self, progress: &Progress, recoveries: u32) {
+        tracing::warn!(
+            "{} {} recovered ({} of {})",
+            progress.kind,
+            progress.name,
+            recoveries,
+            self.config().max_recoveries
+        );
+        let _ = self.inner.events.send(BusEvent::TaskRecovered {
+            task: progress.kind,
+            name: progress.name.clone(),
+            recoveries,
+        });
+    }
+
+    /// Report the task of `progress` failed with `error`.
+    pub fn failed(&self, progress: &Progress, error: String) {
+        tracing::error!("{} {} failed: {}", progress.kind, progress.name, error);
+        let _ = self.inner.events.send(BusEvent::TaskFailed {
+            task: progress.kind,
+            name: progress.name.clone(),
+            error,
+        });
+    }
+}
+
+#[cfg(test)]
+#[path = "watchdog_test.rs"]
+mod watchdog_test;
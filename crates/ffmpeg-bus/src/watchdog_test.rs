@@ -0,0 +1,119 @@
+use super::*;
+
+const STALL: Duration = Duration::from_millis(20);
+
+fn config() -> WatchdogConfig {
+    WatchdogConfig {
+        decoder_stall: STALL,
+        encoder_stall: STALL,
+        mux_stall: Duration::from_secs(60),
+        max_recoveries: 1,
+        ..WatchdogConfig::default()
+    }
+}
+
+fn watchdog() -> (Watchdog, tokio::sync::broadcast::Receiver<BusEvent>) {
+    let (events, rx) = tokio::sync::broadcast::channel(16);
+    (Watchdog::new(config(), events), rx)
+}
+
+fn drain(rx: &mut tokio::sync::broadcast::Receiver<BusEvent>) -> Vec<BusEvent> {
+    std::iter::from_fn(|| rx.try_recv().ok()).collect()
+}
+
+fn past_the_threshold() {
+    std::thread::sleep(STALL + Duration::from_millis(15));
+}
+
+#[tokio::test]
+async fn a_call_outlasting_the_threshold_is_reported_once() {
+    let (watchdog, mut events) = watchdog();
+    let progress = watchdog.register(TaskKind::Encoder, "stream 0");
+    // Waiting between calls is not a stall.
+    past_the_threshold();
+    assert_eq!(watchdog.scan(), 0);
+
+    let call = progress.enter();
+    assert_eq!(watchdog.scan(), 0);
+    past_the_threshold();
+    assert_eq!(watchdog.scan(), 1);
+    assert_eq!(watchdog.scan(), 0);
+    // The task was woken.
+    tokio::time::timeout(Duration::from_secs(1), progress.stalled())
+        .await
+        .expect("the stall woke nobody");
+    drop(call);
+    let seen = drain(&mut events);
+    let [
+        BusEvent::TaskStalled {
+            task,
+            name,
+            stalled_ms,
+        },
+    ] = &seen[..]
+    else {
+        panic!("expected one stall");
+    };
+    assert_eq!((*task, name.as_str()), (TaskKind::Encoder, "stream 0"));
+    assert!(*stalled_ms > STALL.as_millis() as u64, "{stalled_ms} ms");
+
+    // The next call is timed afresh.
+    assert_eq!(progress.busy_for(), None);
+    let _call = progress.enter();
+    past_the_threshold();
+    assert_eq!(watchdog.scan(), 1);
+}
+
+#[test]
+fn thresholds_are_per_kind_and_left_loops_are_forgotten() {
+    let (watchdog, _events) = watchdog();
+    let mux = watchdog.register(TaskKind::Mux, "rtmp-out");
+    let decoder = watchdog.register(TaskKind::Decoder, "stream 1");
+    let mux_flag = Arc::new(AtomicBool::new(false));
+    let decoder_flag = Arc::new(AtomicBool::new(false));
+    mux.set_interrupt(Some(mux_flag.clone()));
+    decoder.set_interrupt(Some(decoder_flag.clone()));
+
+    let _write = mux.enter();
+    let decode = decoder.enter();
+    past_the_threshold();
+    // The mux has a minute.
+    assert_eq!(watchdog.scan(), 1);
+    assert!(decoder_flag.load(Ordering::Acquire));
+    assert!(!mux_flag.load(Ordering::Acquire));
+
+    drop(decode);
+    decoder.retire();
+    let _decode = decoder.enter();
+    let gone = watchdog.register(TaskKind::Encoder, "stream 2");
+    std::mem::forget(gone.enter());
+    drop(gone);
+    past_the_threshold();
+    assert_eq!(watchdog.scan(), 0);
+    assert_eq!(watchdog.tasks().len(), 1);
+}
+
+#[test]
+fn recoveries_are_bounded() {
+    let (watchdog, mut events) = watchdog();
+    let progress = watchdog.register(TaskKind::Decoder, "stream 0");
+    let mut recoveries = 0;
+    assert!(watchdog.may_recover(&progress, &mut recoveries));
+    watchdog.recovered(&progress, recoveries);
+    assert!(!watchdog.may_recover(&progress, &mut recoveries));
+    assert_eq!(
+        drain(&mut events),
+        [
+            BusEvent::TaskRecovered {
+                task: TaskKind::Decoder,
+                name: "stream 0".to_string(),
+                recoveries: 1,
+            },
+            BusEvent::TaskFailed {
+                task: TaskKind::Decoder,
+                name: "stream 0".to_string(),
+                error: "stalled 2 times".to_string(),
+            },
+        ]
+    );
+}
@@ -0,0 +1,129 @@
+//! Threads for the bus's long-running blocking loops (input read, decode,
+//! encode, audio mix).
+//!
+//! Each loop lives as long as its task, so on tokio's shared blocking pool
+//! every running bus pins several pool threads for good; with many buses in
+//! one process that starves everything else using `spawn_blocking` (DB, file
+//! IO) and eventually hits the pool's thread cap. By default each loop gets a
+//! dedicated, named OS thread instead; [`configure`] can switch back to the
+//! tokio pool or change the stack size.
+//!
+//! Both threads and tasks started here run in the tracing span (and under the
+//! subscriber) current at the spawn site, so their log lines keep the
+//! bus/output/stream identity.
+
+use std::future::Future;
+use std::sync::RwLock;
+
+use tracing::instrument::{Instrument, WithSubscriber};
+
+/// Where blocking loops run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerMode {
+    /// One dedicated OS thread per loop (default).
+    Dedicated,
+    /// tokio's shared blocking pool (`spawn_blocking`).
+    TokioPool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerConfig {
+    pub mode: WorkerMode,
+    /// Stack size of dedicated threads, bytes. `None` uses the std default.
+    pub stack_size: Option<usize>,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            mode: WorkerMode::Dedicated,
+            stack_size: None,
+        }
+    }
+}
+
+static CONFIG: RwLock<WorkerConfig> = RwLock::new(WorkerConfig {
+    mode: WorkerMode::Dedicated,
+    stack_size: None,
+});
+
+/// Set where loops started from now on run. Running loops are not moved.
+pub fn configure(config: WorkerConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config;
+}
+
+pub fn config() -> WorkerConfig {
+    *CONFIG.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Run the blocking `f` per the current [`WorkerConfig`]. The receiver yields
+/// its result, or an error if it panicked (or its thread could not start).
+pub(crate) fn spawn<T, F>(name: &str, f: F) -> tokio::sync::oneshot::Receiver<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let config = config();
+    let span = tracing::Span::current();
+    let dispatch = tracing::dispatcher::get_default(|d| d.clone());
+    let f = move || tracing::dispatcher::with_default(&dispatch, || span.in_scope(f));
+    match config.mode {
+        WorkerMode::TokioPool => {
+            tokio::task::spawn_blocking(move || {
+                let _ = tx.send(f());
+            });
+        }
+        WorkerMode::Dedicated => {
+            let mut builder = std::thread::Builder::new().name(name.to_string());
+            if let Some(size) = config.stack_size {
+                builder = builder.stack_size(size);
+            }
+            if let Err(e) = builder.spawn(move || {
+                let _ = tx.send(f());
+            }) {
+                // Dropping the closure drops `tx`, so the receiver errors.
+                tracing::error!("worker: failed to start thread {name}: {e}");
+            }
+        }
+    }
+    rx
+}
+
+/// Spawn `future` as a tokio task named `name`, in the current tracing span
+/// and subscriber.
+pub(crate) fn spawn_task<F>(name: &str, future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_named(
+        name,
+        future
+            .instrument(tracing::Span::current())
+            .with_current_subscriber(),
+    )
+}
+
+/// Task names need tokio's unstable task builder; without it tasks are
+/// spawned unnamed.
+#[cfg(all(tokio_unstable, feature = "task-names"))]
+fn spawn_named<F>(name: &str, future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("spawn outside a tokio runtime")
+}
+
+#[cfg(not(all(tokio_unstable, feature = "task-names")))]
+fn spawn_named<F>(_name: &str, future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future)
+}
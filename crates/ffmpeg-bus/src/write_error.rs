@@ -0,0 +1,148 @@
+//! Mux write failures, classified by what a caller should do about them. The
+//! muxer reports an `AVERROR` code; a full disk, a broken connection and data
+//! the muxer refuses all look alike as a number, but call for stopping and
+//! alerting, reconnecting and giving up respectively.
+//! [`AvOutput`](crate::output::AvOutput) and
+//! [`AvOutputStreamWriter`](crate::output::AvOutputStreamWriter) return a
+//! [`WriteError`] from `write_packet` and `finish`; the bus's mux tasks react
+//! per [`WriteErrorKind`] and report it in
+//! [`BusEvent::OutputFailed`](crate::bus::BusEvent::OutputFailed).
+
+use std::fmt;
+
+use ffmpeg_next::util::error::{
+    ECONNABORTED, ECONNREFUSED, ECONNRESET, EFBIG, EHOSTUNREACH, EINVAL, EIO, ENETDOWN, ENETRESET,
+    ENETUNREACH, ENOSPC, ENOTCONN, EPIPE, EROFS, ETIMEDOUT,
+};
+
+/// What kind of failure a write was.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WriteErrorKind {
+    /// No space left (or the file hit its size limit): writing on only loses
+    /// more data, so the output stops.
+    DiskFull,
+    /// The storage failed (I/O error, read-only filesystem).
+    Io,
+    /// A network output's connection broke; a new connection may work.
+    Network,
+    /// The muxer refused the data or its parameters; it would again.
+    Format,
+    /// The output's packet hook (see [`crate::hook`]) panicked.
+    Hook,
+    /// The write blocked past the watchdog's threshold and was interrupted
+    /// (see [`crate::watchdog`]); a network output reconnects.
+    Stalled,
+    Other,
+}
+
+impl WriteErrorKind {
+    /// The kind of `AVERROR` code `raw`.
+    pub fn from_raw(raw: i32) -> Self {
+        match ffmpeg_next::Error::from(raw) {
+            ffmpeg_next::Error::Other { errno } => match errno {
+                ENOSPC | EFBIG => Self::DiskFull,
+                EIO | EROFS => Self::Io,
+                EPIPE | ECONNRESET | ECONNREFUSED | ECONNABORTED | ENOTCONN | ETIMEDOUT
+                | ENETDOWN | ENETRESET | ENETUNREACH | EHOSTUNREACH => Self::Network,
+                EINVAL => Self::Format,
+                _ => Self::Other,
+            },
+            ffmpeg_next::Error::InvalidData
+            | ffmpeg_next::Error::MuxerNotFound
+            | ffmpeg_next::Error::EncoderNotFound
+            | ffmpeg_next::Error::StreamNotFound
+            | ffmpeg_next::Error::PatchWelcome => Self::Format,
+            _ => Self::Other,
+        }
+    }
+
+    /// Whether the same output may work again after reconnecting.
+    pub fn is_transient(self) -> bool {
+        matches!(self, Self::Network | Self::Stalled)
+    }
+
+    /// Whether the output must stop: everything but [`Self::Other`], which
+    /// costs one packet.
+    pub fn is_fatal(self) -> bool {
+        self != Self::Other
+    }
+}
+
+impl fmt::Display for WriteErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::DiskFull => "disk full",
+            Self::Io => "storage error",
+            Self::Network => "network error",
+            Self::Format => "format error",
+            Self::Hook => "packet hook failed",
+            Self::Stalled => "write stalled",
+            Self::Other => "write error",
+        })
+    }
+}
+
+/// A failed `write_packet`, `write_header` or `finish`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteError {
+    pub kind: WriteErrorKind,
+    /// The `AVERROR` code.
+    pub raw: i32,
+    /// What was being written.
+    pub context: String,
+}
+
+impl WriteError {
+    pub(crate) fn from_ffmpeg(error: ffmpeg_next::Error, context: impl Into<String>) -> Self {
+        let raw = i32::from(error);
+        Self {
+            kind: WriteErrorKind::from_raw(raw),
+            raw,
+            context: context.into(),
+        }
+    }
+
+    /// A write refused before it reached the muxer.
+    pub(crate) fn invalid(context: impl Into<String>) -> Self {
+        Self::from_ffmpeg(ffmpeg_next::Error::Other { errno: EINVAL }, context)
+    }
+
+    /// A write the watchdog interrupted.
+    pub(crate) fn stalled(context: impl Into<String>) -> Self {
+        Self {
+            kind: WriteErrorKind::Stalled,
+            raw: i32::from(ffmpeg_next::Error::Exit),
+            context: context.into(),
+        }
+    }
+
+    /// A packet hook that panicked; `context` says where and with what.
+    pub(crate) fn hook(context: impl Into<String>) -> Self {
+        Self {
+            kind: WriteErrorKind::Hook,
+            raw: i32::from(ffmpeg_next::Error::External),
+            context: context.into(),
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.kind == WriteErrorKind::Hook {
+            return write!(f, "{}: {}", self.context, self.kind);
+        }
+        write!(
+            f,
+            "{}: {} ({})",
+            self.context,
+            self.kind,
+            ffmpeg_next::Error::from(self.raw)
+        )
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+#[cfg(test)]
+#[path = "write_error_test.rs"]
+mod write_error_test;
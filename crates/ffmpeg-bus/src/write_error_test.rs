@@ -0,0 +1,89 @@
+use ffmpeg_next::util::error::EAGAIN;
+
+use super::*;
+
+/// `AVERROR(errno)`.
+fn averror(errno: i32) -> i32 {
+    -errno
+}
+
+#[test]
+fn errno_codes_map_to_kinds() {
+    for (errno, kind) in [
+        (ENOSPC, WriteErrorKind::DiskFull),
+        (EFBIG, WriteErrorKind::DiskFull),
+        (EIO, WriteErrorKind::Io),
+        (EROFS, WriteErrorKind::Io),
+        (EPIPE, WriteErrorKind::Network),
+        (ECONNRESET, WriteErrorKind::Network),
+        (ECONNREFUSED, WriteErrorKind::Network),
+        (ETIMEDOUT, WriteErrorKind::Network),
+        (EINVAL, WriteErrorKind::Format),
+        (EAGAIN, WriteErrorKind::Other),
+    ] {
+        assert_eq!(
+            WriteErrorKind::from_raw(averror(errno)),
+            kind,
+            "errno {errno}"
+        );
+    }
+}
+
+#[test]
+fn ffmpeg_codes_map_to_kinds() {
+    let raw = |e: ffmpeg_next::Error| i32::from(e);
+    assert_eq!(
+        WriteErrorKind::from_raw(raw(ffmpeg_next::Error::InvalidData)),
+        WriteErrorKind::Format
+    );
+    assert_eq!(
+        WriteErrorKind::from_raw(raw(ffmpeg_next::Error::MuxerNotFound)),
+        WriteErrorKind::Format
+    );
+    assert_eq!(
+        WriteErrorKind::from_raw(raw(ffmpeg_next::Error::Eof)),
+        WriteErrorKind::Other
+    );
+}
+
+#[test]
+fn only_network_and_stalled_errors_are_transient() {
+    assert!(WriteErrorKind::Network.is_transient());
+    assert!(WriteErrorKind::Stalled.is_transient());
+    assert_eq!(
+        WriteError::stalled("write_packet").kind,
+        WriteErrorKind::Stalled
+    );
+    for kind in [
+        WriteErrorKind::DiskFull,
+        WriteErrorKind::Io,
+        WriteErrorKind::Format,
+        WriteErrorKind::Hook,
+        WriteErrorKind::Other,
+    ] {
+        assert!(!kind.is_transient(), "{kind}");
+    }
+    assert!(WriteErrorKind::DiskFull.is_fatal());
+    assert!(WriteErrorKind::Hook.is_fatal());
+    assert!(!WriteErrorKind::Other.is_fatal());
+}
+
+#[test]
+fn errors_keep_code_and_context() {
+    let error =
+        WriteError::from_ffmpeg(ffmpeg_next::Error::Other { errno: ENOSPC }, "write_packet");
+    assert_eq!(error.kind, WriteErrorKind::DiskFull);
+    assert_eq!(error.raw, averror(ENOSPC));
+    let message = error.to_string();
+    assert!(
+        message.starts_with("write_packet: disk full ("),
+        "{message}"
+    );
+
+    let invalid = WriteError::invalid("stream not found: 3");
+    assert_eq!(invalid.kind, WriteErrorKind::Format);
+    assert_eq!(invalid.raw, averror(EINVAL));
+    // Usable with `?` in anyhow code, and recoverable from it.
+    let any: anyhow::Error = invalid.clone().into();
+    assert_eq!(any.downcast_ref::<WriteError>(), Some(&invalid));
+}
@@ -11,6 +11,9 @@ pub enum AuthConfig {
     Open,
     Shared(String),
     Provider(PasswordProvider),
+    /// The provider's password where it knows the device, otherwise whatever
+    /// the fallback decides (e.g. a shared password for the rest).
+    ProviderOr(PasswordProvider, Box<AuthConfig>),
 }
 
 impl AuthConfig {
@@ -23,6 +26,10 @@ impl AuthConfig {
                 Some(pw) => AuthDecision::Require(pw),
                 None => AuthDecision::Reject,
             },
+            AuthConfig::ProviderOr(f, fallback) => match f(device_id) {
+                Some(pw) => AuthDecision::Require(pw),
+                None => fallback.password_for(device_id),
+            },
         }
     }
 }
@@ -124,4 +131,16 @@ mod tests {
         assert!(matches!(cfg.password_for("known"), AuthDecision::Require(p) if p == "secret"));
         assert!(matches!(cfg.password_for("unknown"), AuthDecision::Reject));
     }
+
+    #[test]
+    fn config_provider_falls_back() {
+        let cfg = AuthConfig::ProviderOr(
+            Box::new(|id| (id == "known").then(|| "own".to_string())),
+            Box::new(AuthConfig::Shared("shared".into())),
+        );
+        assert!(matches!(cfg.password_for("known"), AuthDecision::Require(p) if p == "own"));
+        assert!(matches!(cfg.password_for("other"), AuthDecision::Require(p) if p == "shared"));
+        let open = AuthConfig::ProviderOr(Box::new(|_| None), Box::new(AuthConfig::Open));
+        assert!(matches!(open.password_for("any"), AuthDecision::Allow));
+    }
 }
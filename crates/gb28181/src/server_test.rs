@@ -149,6 +149,47 @@ async fn provider_unknown_device_is_forbidden() {
     server.shutdown();
 }
 
+#[tokio::test]
+async fn per_device_password_overrides_the_shared_one() {
+    let mut cfg = GbServerConfig::new(PLATFORM, DOMAIN, "127.0.0.1:0".parse().unwrap());
+    cfg.auth = crate::auth::AuthConfig::ProviderOr(
+        Box::new(|id| (id == DEVICE).then(|| "own-pw".to_string())),
+        Box::new(crate::auth::AuthConfig::Shared("s3cret".into())),
+    );
+    let (server, mut events) = GbServer::bind(cfg).await.unwrap();
+
+    // The shared password is not this device's.
+    let resp = raw_register(
+        DEVICE,
+        "s3cret",
+        DOMAIN,
+        PLATFORM,
+        server.local_addr(),
+        3600,
+    )
+    .await;
+    assert_ne!(resp.status_code, rsip::StatusCode::OK);
+    assert!(server.devices().is_empty());
+
+    let resp = raw_register(
+        DEVICE,
+        "own-pw",
+        DOMAIN,
+        PLATFORM,
+        server.local_addr(),
+        3600,
+    )
+    .await;
+    assert_eq!(resp.status_code, rsip::StatusCode::OK);
+    wait_for(&mut events, |e| matches!(e, GbEvent::Registered { .. })).await;
+
+    // Devices without one of their own still use the shared password.
+    let other = "34020000001320000099";
+    let resp = raw_register(other, "s3cret", DOMAIN, PLATFORM, server.local_addr(), 3600).await;
+    assert_eq!(resp.status_code, rsip::StatusCode::OK);
+    server.shutdown();
+}
+
 /// Send one MANSCDP body as a MESSAGE from a throwaway endpoint.
 pub(crate) async fn raw_message(
     server_addr: std::net::SocketAddr,
@@ -0,0 +1,171 @@
+//! Adaptive (multi-rendition) HLS output.
+//!
+//! One [`OutputDest::HlsAdaptive`](crate::types::OutputDest::HlsAdaptive)
+//! output expands into one bus output per rendition: they all share the
+//! input's video decoder, each gets its own encoder (encoders are keyed by
+//! encode config) and writes a variant playlist under `<dir>/<name>/`. The
+//! master playlist `<dir>/master.m3u8` lists the variants that were started.
+//!
+//! Segments line up across variants because every encoder is forced onto the
+//! same keyframe cadence (fps * `segment_seconds`) and every muxer cuts at the
+//! same `segment_seconds`.
+
+use std::path::{Path, PathBuf};
+
+use ffmpeg_bus::{
+    bus::{
+        EncodeConfig as FbEncodeConfig, OutputAvType, OutputConfig as FbOutputConfig,
+        OutputDest as FbOutputDest,
+    },
+    stream::AvStream,
+};
+
+/// One variant of the ladder. `bitrate` (bps) is the encoder target and the
+/// advertised `BANDWIDTH`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HlsRendition {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub bitrate: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct HlsLadder {
+    /// Output directory; holds `master.m3u8` and one sub-directory per rendition.
+    pub dir: String,
+    pub segment_seconds: u32,
+    /// Segments kept per variant playlist (0 = keep all).
+    pub list_size: u32,
+    pub renditions: Vec<HlsRendition>,
+}
+
+impl HlsLadder {
+    pub fn master_path(&self) -> PathBuf {
+        Path::new(&self.dir).join("master.m3u8")
+    }
+
+    pub fn variant_path(&self, rendition: &HlsRendition) -> PathBuf {
+        Path::new(&self.dir)
+            .join(&rendition.name)
+            .join("index.m3u8")
+    }
+
+    /// Renditions that fit within a `width`x`height` source. Larger ones (and
+    /// ones whose name is not a plain directory name) are skipped with a warning.
+    pub fn fitting(&self, width: u32, height: u32) -> Vec<&HlsRendition> {
+        self.renditions
+            .iter()
+            .filter(|r| {
+                if matches!(r.name.as_str(), "" | "." | "..") || r.name.contains(['/', '\\']) {
+                    log::warn!("hls: skip rendition with invalid name {:?}", r.name);
+                    return false;
+                }
+                if r.width > width || r.height > height {
+                    log::warn!(
+                        "hls: skip rendition {} ({}x{}): larger than source {}x{}",
+                        r.name,
+                        r.width,
+                        r.height,
+                        width,
+                        height
+                    );
+                    return false;
+                }
+                true
+            })
+            .collect()
+    }
+
+    /// Keyframe interval in frames that puts an IDR at every segment boundary.
+    pub fn keyframe_interval(&self, fps: f64) -> u32 {
+        let fps = if fps.is_finite() && fps > 0.0 {
+            fps
+        } else {
+            25.0
+        };
+        ((fps * f64::from(self.segment_seconds.max(1))).round() as u32).max(1)
+    }
+
+    fn encode_config(rendition: &HlsRendition, keyframe_interval: u32) -> FbEncodeConfig {
+        FbEncodeConfig {
+            codec: "h264".to_string(),
+            width: Some(rendition.width),
+            height: Some(rendition.height),
+            bitrate: Some(rendition.bitrate),
+            keyframe_interval: Some(keyframe_interval),
+            ..FbEncodeConfig::default()
+        }
+    }
+}
+
+/// Master playlist referencing `renditions`, each at `<name>/index.m3u8`.
+pub fn master_playlist(renditions: &[HlsRendition]) -> String {
+    let mut out = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    for r in renditions {
+        out.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{}\n{}/index.m3u8\n",
+            r.bitrate, r.width, r.height, r.name
+        ));
+    }
+    out
+}
+
+/// Expand a ladder into one bus output per rendition fitting the input
+/// `streams` (ids `<id>_<name>`), creating the variant directories. The caller
+/// adds them in one batch (see `ffmpeg_bus::pipeline`) so all variants start on
+/// the same frame.
+pub(crate) fn plan_ladder(
+    streams: &[AvStream],
+    id: &str,
+    ladder: &HlsLadder,
+    include_audio: bool,
+) -> anyhow::Result<Vec<(FbOutputConfig, HlsRendition)>> {
+    let video = streams
+        .iter()
+        .find(|s| s.is_video())
+        .ok_or_else(|| anyhow::anyhow!("input has no video stream"))?;
+    let renditions = ladder.fitting(video.width(), video.height());
+    if renditions.is_empty() {
+        anyhow::bail!(
+            "no rendition fits the {}x{} source",
+            video.width(),
+            video.height()
+        );
+    }
+    let keyframe_interval = ladder.keyframe_interval(f64::from(video.rate()));
+
+    let mut planned = Vec::with_capacity(renditions.len());
+    for rendition in renditions {
+        let path = ladder.variant_path(rendition);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let dest = FbOutputDest::Hls {
+            path: path.to_string_lossy().into_owned(),
+            segment_seconds: ladder.segment_seconds,
+            list_size: ladder.list_size,
+        };
+        let mut output = FbOutputConfig::new(
+            format!("{}_{}", id, rendition.name),
+            OutputAvType::Video,
+            dest,
+        )
+        .with_encode(HlsLadder::encode_config(rendition, keyframe_interval));
+        if include_audio {
+            output = output.with_audio();
+        }
+        planned.push((output, rendition.clone()));
+    }
+    Ok(planned)
+}
+
+/// Write `<dir>/master.m3u8` listing the renditions that started.
+pub(crate) fn write_master(ladder: &HlsLadder, started: &[HlsRendition]) -> anyhow::Result<()> {
+    std::fs::write(ladder.master_path(), master_playlist(started))?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "hls_test.rs"]
+mod hls_test;
@@ -0,0 +1,80 @@
+use super::*;
+
+fn rendition(name: &str, width: u32, height: u32, bitrate: u64) -> HlsRendition {
+    HlsRendition {
+        name: name.to_string(),
+        width,
+        height,
+        bitrate,
+    }
+}
+
+fn ladder(renditions: Vec<HlsRendition>) -> HlsLadder {
+    HlsLadder {
+        dir: "/data/hls/cam1".to_string(),
+        segment_seconds: 2,
+        list_size: 0,
+        renditions,
+    }
+}
+
+#[test]
+fn fitting_skips_renditions_larger_than_source() {
+    let l = ladder(vec![
+        rendition("1080p", 1920, 1080, 5_000_000),
+        rendition("720p", 1280, 720, 2_800_000),
+        rendition("360p", 640, 360, 800_000),
+    ]);
+    let names = l
+        .fitting(1280, 720)
+        .iter()
+        .map(|r| r.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["720p", "360p"]);
+}
+
+#[test]
+fn fitting_skips_names_that_escape_the_directory() {
+    let l = ladder(vec![
+        rendition("../x", 640, 360, 1),
+        rendition("", 640, 360, 1),
+        rendition("ok", 640, 360, 1),
+    ]);
+    assert_eq!(l.fitting(1920, 1080).len(), 1);
+}
+
+#[test]
+fn keyframe_interval_spans_one_segment() {
+    let l = ladder(Vec::new());
+    assert_eq!(l.keyframe_interval(25.0), 50);
+    assert_eq!(l.keyframe_interval(29.97), 60);
+    // Unknown frame rate falls back to 25 fps.
+    assert_eq!(l.keyframe_interval(0.0), 50);
+}
+
+#[test]
+fn paths_are_under_the_ladder_dir() {
+    let l = ladder(Vec::new());
+    assert_eq!(
+        l.master_path(),
+        std::path::PathBuf::from("/data/hls/cam1/master.m3u8")
+    );
+    assert_eq!(
+        l.variant_path(&rendition("360p", 640, 360, 800_000)),
+        std::path::PathBuf::from("/data/hls/cam1/360p/index.m3u8")
+    );
+}
+
+#[test]
+fn master_playlist_lists_every_variant() {
+    let master = master_playlist(&[
+        rendition("720p", 1280, 720, 2_800_000),
+        rendition("360p", 640, 360, 800_000),
+    ]);
+    assert_eq!(
+        master,
+        "#EXTM3U\n#EXT-X-VERSION:3\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=2800000,RESOLUTION=1280x720\n720p/index.m3u8\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360\n360p/index.m3u8\n"
+    );
+}
@@ -5,10 +5,12 @@
 //! caller-provided sink ([`RawSinkSource`] for frames/packets, or a
 //! [`DemuxedSink`] implementation such as `media-pipe-zlm`'s `ZlmSink`).
 
+pub mod hls;
 pub mod pipe;
 pub mod stream;
 pub mod types;
 
+pub use hls::{HlsLadder, HlsRendition};
 pub use pipe::{Pipe, dest_name};
 pub use stream::RawSinkSource;
 pub use types::{
@@ -7,15 +7,29 @@ use std::{
     },
 };
 
-use ffmpeg_bus::bus::{Bus as FbBus, VideoRawFrameStream};
+use ffmpeg_bus::{
+    bus::{
+        Bus as FbBus, BusEvent, DEFAULT_INPUT, FrameEvent, RawOutputStream, VideoRawFrameStream,
+    },
+    input::PlaybackState,
+    pipeline::{OutputHandler, PipelineBuilder},
+    stream::AvStream,
+};
 use futures::StreamExt;
+use tokio::{sync::broadcast, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
+    hls::{self, HlsRendition},
     stream::RawSinkSource,
-    types::{EncodeConfig, InputConfig, OutputConfig, OutputDest, PipeConfig, VideoRawFrame},
+    types::{
+        DemuxedSink, EncodeConfig, InputConfig, OutputConfig, OutputDest, PipeConfig, VideoRawFrame,
+    },
 };
 
+/// Capacity of [`Pipe::events`].
+const EVENT_CAP: usize = 64;
+
 /// Pipeline: media processing using ffmpeg-bus
 pub struct Pipe {
     config: PipeConfig,
@@ -25,6 +39,8 @@ pub struct Pipe {
     /// cleared on teardown). Lets consumers such as ASR subscribe to the pipe's
     /// decoded audio without owning its internals.
     bus: Mutex<Option<Arc<FbBus>>>,
+    /// The bus events of every run, for [`Self::events`].
+    events: broadcast::Sender<BusEvent>,
 }
 
 impl Pipe {
@@ -34,9 +50,17 @@ impl Pipe {
             cancel: CancellationToken::new(),
             started: AtomicBool::new(false),
             bus: Mutex::new(None),
+            events: broadcast::channel(EVENT_CAP).0,
         }
     }
 
+    /// Events of the pipe's bus (input opened, ended or failed, outputs
+    /// started and finished), across restarts: subscribe once, before the
+    /// first `start`.
+    pub fn events(&self) -> broadcast::Receiver<BusEvent> {
+        self.events.subscribe()
+    }
+
     /// Subscribe to this pipe's decoded-audio broadcast (for ASR). Errors if the
     /// pipe is not currently started.
     pub async fn subscribe_audio(&self) -> anyhow::Result<ffmpeg_bus::frame::RawFrameReceiver> {
@@ -61,6 +85,80 @@ impl Pipe {
         bus.subscribe_video().await
     }
 
+    /// Add `output` to the running bus, e.g. a stream of its own for one
+    /// viewer. Errors if the pipe is not currently started.
+    pub async fn add_output(
+        &self,
+        output: ffmpeg_bus::bus::OutputConfig,
+    ) -> anyhow::Result<(AvStream, RawOutputStream)> {
+        let bus = self
+            .bus
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("pipe not started"))?;
+        bus.add_output(output).await
+    }
+
+    /// Remove an output [`Self::add_output`] added. Nothing to do once the
+    /// pipe has stopped: its outputs went with the bus.
+    pub async fn remove_output(&self, id: &str) -> anyhow::Result<()> {
+        let Some(bus) = self.bus.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        bus.remove_output(id).await
+    }
+
+    /// The camera's clock offset from ours, once the input reports sender
+    /// wall clock times (RTSP RTCP sender reports). `None` if the pipe is not
+    /// started or its input has no such timing.
+    pub async fn clock_offset(&self) -> Option<ffmpeg_bus::clock::ClockOffset> {
+        let bus = self.bus.lock().unwrap().clone()?;
+        bus.clock_offset().await.ok().flatten()
+    }
+
+    /// Packet counters of the running input (for health scoring). `None` if
+    /// the pipe is not started.
+    pub async fn input_stats(&self) -> Option<ffmpeg_bus::input::InputStats> {
+        let bus = self.bus.lock().unwrap().clone()?;
+        bus.input_stats().await.ok().flatten()
+    }
+
+    /// Hold the input where it is, keeping it open (see
+    /// `ffmpeg_bus::input::AvInputTask::pause`). Errors if the pipe is not
+    /// started.
+    pub async fn pause(&self) -> anyhow::Result<PlaybackState> {
+        self.running_bus()?.pause_input(DEFAULT_INPUT).await
+    }
+
+    /// Read on after [`Self::pause`].
+    pub async fn resume(&self) -> anyhow::Result<PlaybackState> {
+        self.running_bus()?.resume_input(DEFAULT_INPUT).await
+    }
+
+    /// Play a file input at `rate` times its pace. Errors for a live input,
+    /// or if the pipe is not started.
+    pub async fn set_playback_rate(&self, rate: f32) -> anyhow::Result<PlaybackState> {
+        self.running_bus()?
+            .set_playback_rate(DEFAULT_INPUT, rate)
+            .await
+    }
+
+    fn running_bus(&self) -> anyhow::Result<Arc<FbBus>> {
+        self.bus
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("pipe not started"))
+    }
+
+    /// Packet counters of the bus's inputs and outputs. `None` if the pipe
+    /// is not started.
+    pub async fn stats(&self) -> Option<ffmpeg_bus::stats::BusStats> {
+        let bus = self.bus.lock().unwrap().clone()?;
+        bus.stats().await.ok()
+    }
+
     pub fn cancel(&self) {
         self.cancel.cancel();
     }
@@ -70,12 +168,23 @@ impl Pipe {
         self.started.load(Ordering::Relaxed)
     }
 
+    /// Whether the bus of a run is up: outputs can be added and frames
+    /// subscribed to.
+    pub fn is_running(&self) -> bool {
+        self.bus.lock().unwrap().is_some()
+    }
+
     /// Check if the pipeline has been cancelled
-    #[allow(dead_code)]
     pub fn is_cancelled(&self) -> bool {
         self.cancel.is_cancelled()
     }
 
+    /// Resolves once the pipeline is cancelled, so a supervisor waiting to
+    /// restart it can stop as well.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
     /// Start the pipeline. `input_options` are passed straight to the demuxer
     /// (e.g. `rtsp_transport=tcp` for RTSP); the caller decides transport policy
     /// so the core stays input-agnostic.
@@ -86,43 +195,92 @@ impl Pipe {
         }
 
         let log_input = match &self.config.input {
-            InputConfig::Network { url } => format!("net://{}", url),
+            InputConfig::Network { url, .. } => format!("net://{}", url),
             InputConfig::File { path } => format!("file://{}", path),
+            InputConfig::FileLoop { path, .. } => format!("file://{} (loop)", path),
             InputConfig::Device { display, format } => format!("device://{} ({})", display, format),
+            InputConfig::Reader { format, .. } => format!("reader ({})", format),
         };
 
         log::info!("Pipe: starting with input {}", log_input);
 
-        let bus = Arc::new(FbBus::new("pipe"));
-        // Publish the handle so consumers (ASR) can subscribe while we run.
-        *self.bus.lock().unwrap() = Some(Arc::clone(&bus));
+        let mut builder = PipelineBuilder::new("pipe").input(self.config.input.clone().into());
+        if let Some(options) = input_options {
+            builder = builder.input_options(options);
+        }
+        let mut pipeline = match builder.build() {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                log::error!("Pipe: build pipeline failed: {:#}", e);
+                self.started.store(false, Ordering::Relaxed);
+                return;
+            }
+        };
+        let mut bus_events = pipeline.bus_events();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            loop {
+                match bus_events.recv().await {
+                    Ok(event) => {
+                        let _ = events.send(event);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
         let cancel = self.cancel.clone();
 
-        // Map and add input
-        let fb_input = self.config.input.clone().into();
-        if let Err(e) = bus.add_input(fb_input, input_options).await {
-            log::error!(
-                "Pipe: add_input failed: {:#}\nbacktrace:\n{}",
-                e,
-                Backtrace::capture()
-            );
-            self.started.store(false, Ordering::Relaxed);
-            return;
-        }
+        // HLS ladders are sized to the source, so open the input first.
+        let has_ladder = self
+            .config
+            .outputs
+            .iter()
+            .any(|o| matches!(o.dest, OutputDest::HlsAdaptive { .. }));
+        let streams = if has_ladder {
+            match pipeline.open().await {
+                Ok(streams) => streams,
+                Err(e) => {
+                    log::error!(
+                        "Pipe: add_input failed: {:#}\nbacktrace:\n{}",
+                        e,
+                        Backtrace::capture()
+                    );
+                    self.started.store(false, Ordering::Relaxed);
+                    return;
+                }
+            }
+        } else {
+            Vec::new()
+        };
 
-        // First pass: register all outputs with the bus; collect successes. An
-        // output may fail (e.g. an audio output when the input has no audio); we
-        // notify a Demuxed sink so it can drop the missing sibling from any
-        // coordination it does across video + audio.
-        let mut accepted: Vec<(
-            usize,
-            ffmpeg_bus::stream::AvStream,
-            VideoRawFrameStream,
-            OutputConfig,
-        )> = Vec::new();
+        // Register every output with the pipeline; it adds them to the bus in
+        // one batch (so they all start from the input's first packet) and owns
+        // the forwarders. An output may be refused (e.g. an audio output when
+        // the input has no audio); a Demuxed sink is notified so it can drop
+        // the missing sibling from any coordination it does across video +
+        // audio. An HLS ladder expands into one bus output per rendition.
+        let mut ladders: Vec<(usize, Vec<(String, HlsRendition)>)> = Vec::new();
         for (i, output_config) in self.config.outputs.iter().enumerate() {
             let id = format!("out_{}", i);
-            let fb_output = match output_config.clone().into() {
+            if let OutputDest::HlsAdaptive { ladder } = &output_config.dest {
+                match hls::plan_ladder(&streams, &id, ladder, output_config.include_audio) {
+                    Ok(planned) => {
+                        let mut renditions = Vec::new();
+                        for (fb_output, rendition) in planned {
+                            let fb_id = fb_output.id.clone();
+                            match pipeline.push_output(fb_output, None) {
+                                Ok(()) => renditions.push((fb_id, rendition)),
+                                Err(e) => log::warn!("Pipe: skip rendition {}: {:#}", fb_id, e),
+                            }
+                        }
+                        ladders.push((i, renditions));
+                    }
+                    Err(e) => log::warn!("Pipe: hls ladder {} failed: {:#}", id, e),
+                }
+                continue;
+            }
+            let fb_output: ffmpeg_bus::bus::OutputConfig = match output_config.clone().into() {
                 Some(o) => o,
                 None => {
                     log::warn!(
@@ -132,72 +290,77 @@ impl Pipe {
                     continue;
                 }
             };
-            match bus.add_output(fb_output).await {
-                Ok((av, stream)) => {
-                    accepted.push((i, av, stream, output_config.clone()));
+            let handler: Option<Box<dyn OutputHandler>> = match &output_config.dest {
+                OutputDest::RawFrame { sink } | OutputDest::RawPacket { sink } => {
+                    Some(Box::new(RawSinkHandler {
+                        sink: Arc::clone(sink),
+                    }))
                 }
-                Err(e) => {
-                    log::warn!("Pipe: add_output {} failed: {:#}", id, e);
-                    if let OutputDest::Demuxed { sink } = &output_config.dest {
-                        sink.on_rejected();
-                    }
+                OutputDest::Demuxed { sink } => Some(Box::new(DemuxedHandler {
+                    sink: Arc::clone(sink),
+                })),
+                OutputDest::Network { .. } | OutputDest::HlsAdaptive { .. } => None,
+            };
+            if let Err(e) = pipeline.push_output(fb_output, handler) {
+                log::warn!("Pipe: add_output {} failed: {:#}", id, e);
+                if let OutputDest::Demuxed { sink } = &output_config.dest {
+                    sink.on_rejected();
                 }
             }
         }
 
-        // Second pass: spawn forwarder tasks into a JoinSet so the wait below
-        // can observe the first one ending, then drain the rest on shutdown.
-        let mut outputs = tokio::task::JoinSet::new();
-        for (_, av, stream, output_config) in accepted {
-            match &output_config.dest {
-                OutputDest::RawFrame { sink } | OutputDest::RawPacket { sink } => {
-                    let sink = Arc::clone(sink);
-                    outputs.spawn(async move {
-                        forward_frame_stream_to_sink(stream, sink).await;
-                    });
-                }
-                OutputDest::Demuxed { sink } => {
-                    let handle = sink.start(av, stream);
-                    outputs.spawn(async move {
-                        let _ = handle.await;
-                    });
-                }
-                OutputDest::Network { .. } => {}
+        let accepted = match pipeline.start().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::error!(
+                    "Pipe: start failed: {:#}\nbacktrace:\n{}",
+                    e,
+                    Backtrace::capture()
+                );
+                pipeline.shutdown().await;
+                self.started.store(false, Ordering::Relaxed);
+                return;
+            }
+        };
+        for (i, renditions) in ladders {
+            let started = renditions
+                .into_iter()
+                .filter(|(id, _)| accepted.contains(id))
+                .map(|(_, rendition)| rendition)
+                .collect::<Vec<_>>();
+            if let OutputDest::HlsAdaptive { ladder } = &self.config.outputs[i].dest
+                && let Err(e) = hls::write_master(ladder, &started)
+            {
+                log::warn!("Pipe: write hls master playlist failed: {:#}", e);
             }
         }
-
-        if outputs.is_empty() && !self.config.outputs.is_empty() {
-            log::warn!("Pipe: no output task running");
+        if accepted.is_empty() && !self.config.outputs.is_empty() {
+            log::warn!("Pipe: no output running");
         }
-
-        // Wait for cancellation — or for an output task to end. Forwarders only
-        // end when the input side is done (EOF, read error, sink gone), so the
-        // first completion means the session is dead and start() must unwind
-        // instead of idling forever; that lets a supervisor observe stream
-        // death and restart (e.g. re-resolving an expired live-stream URL).
-        // Pipes whose outputs are all in-bus (Network) keep the cancel-only wait.
-        if outputs.is_empty() {
-            cancel.cancelled().await;
-            log::info!("Pipe: cancelled");
-        } else {
-            tokio::select! {
-                _ = cancel.cancelled() => {
-                    log::info!("Pipe: cancelled");
-                }
-                _ = outputs.join_next() => {
-                    log::info!("Pipe: output ended (input finished), stopping");
-                }
+        // Publish the handle so consumers (ASR) can subscribe while we run.
+        *self.bus.lock().unwrap() = pipeline.bus();
+
+        // Wait for cancellation — or for an output forwarder to end. Forwarders
+        // only end when the input side is done (EOF, read error, sink gone), so
+        // the first completion means the session is dead and start() must
+        // unwind instead of idling forever; that lets a supervisor observe
+        // stream death and restart (e.g. re-resolving an expired live-stream
+        // URL). Pipes whose outputs are all in-bus (Network, HLS) have no
+        // forwarder, so only cancellation ends them.
+        tokio::select! {
+            _ = cancel.cancelled() => {
+                log::info!("Pipe: cancelled");
+            }
+            _ = pipeline.wait() => {
+                log::info!("Pipe: output ended (input finished), stopping");
             }
         }
 
-        // Stop input and outputs: remove input first so the bus stops feeding streams
-        if let Err(e) = bus.remove_input().await {
-            log::warn!("Pipe: remove_input failed: {:#}", e);
-        }
-        bus.stop();
         // Unpublish before dropping the last handle; new subscribers now error.
         *self.bus.lock().unwrap() = None;
-        while outputs.join_next().await.is_some() {}
+        // Removes the input first so the bus stops feeding streams, then stops
+        // the bus and drains the forwarders.
+        pipeline.shutdown().await;
 
         self.started.store(false, Ordering::Relaxed);
     }
@@ -209,26 +372,81 @@ impl Drop for Pipe {
     }
 }
 
-/// Forwards ffmpeg-bus VideoFrame stream to a [`RawSinkSource`] (VideoRawFrame).
-async fn forward_frame_stream_to_sink(
-    mut stream: ffmpeg_bus::bus::VideoRawFrameStream,
+/// Forwards an output's VideoFrame stream to a [`RawSinkSource`] (VideoRawFrame).
+struct RawSinkHandler {
     sink: Arc<RawSinkSource>,
-) {
-    while let Some(opt) = stream.next().await {
-        if let Some(frame) = opt {
-            let vf = VideoRawFrame::new(
-                frame.data.to_vec(),
-                frame.width,
-                frame.height,
-                frame.format,
-                frame.pts,
-                frame.dts,
-                frame.is_key,
-                frame.codec_id,
-            );
-            if sink.writer.try_send(vf).is_err() {
-                break;
+}
+
+impl OutputHandler for RawSinkHandler {
+    fn start(self: Box<Self>, _av: AvStream, stream: RawOutputStream) -> JoinHandle<()> {
+        let sink = self.sink;
+        tokio::spawn(async move {
+            match stream {
+                RawOutputStream::Video(stream) => forward_frame_stream_to_sink(stream, sink).await,
+                RawOutputStream::Audio(_) => {
+                    // Raw sinks carry `VideoRawFrame`s; decoded audio is
+                    // consumed via `Bus::subscribe_audio` instead.
+                    log::warn!("Pipe: raw sinks do not support decoded audio outputs");
+                }
+            }
+        })
+    }
+}
+
+/// Hands an output's demuxed packets to a [`DemuxedSink`].
+struct DemuxedHandler {
+    sink: Arc<dyn DemuxedSink>,
+}
+
+impl OutputHandler for DemuxedHandler {
+    fn start(self: Box<Self>, av: AvStream, stream: RawOutputStream) -> JoinHandle<()> {
+        match stream.into_video() {
+            Ok(stream) => self.sink.start(av, stream),
+            Err(e) => {
+                log::warn!("Pipe: demuxed output: {:#}", e);
+                self.sink.on_rejected();
+                tokio::spawn(async {})
+            }
+        }
+    }
+
+    fn rejected(self: Box<Self>, _error: &anyhow::Error) {
+        self.sink.on_rejected();
+    }
+}
+
+/// Forwards ffmpeg-bus VideoFrame stream to a [`RawSinkSource`] (VideoRawFrame),
+/// up to the input's end. After a lag, encoded packets are held back until
+/// the next keyframe, since the ones in between reference what was skipped.
+async fn forward_frame_stream_to_sink(mut stream: VideoRawFrameStream, sink: Arc<RawSinkSource>) {
+    let mut await_key = false;
+    while let Some(event) = stream.next().await {
+        match event {
+            FrameEvent::Frame(frame) => {
+                if await_key && frame.is_encoded() && !frame.is_key {
+                    continue;
+                }
+                await_key = false;
+                let vf = VideoRawFrame::new(
+                    frame.data.to_vec(),
+                    frame.width,
+                    frame.height,
+                    frame.format,
+                    frame.pts,
+                    frame.dts,
+                    frame.is_key,
+                    frame.codec_id,
+                );
+                if sink.writer.try_send(vf).is_err() {
+                    break;
+                }
+            }
+            FrameEvent::Eof => break,
+            FrameEvent::Lagged(n) => {
+                log::warn!("Pipe: raw sink lagged, {} frames skipped", n);
+                await_key = true;
             }
+            FrameEvent::Error(e) => log::warn!("Pipe: raw sink: {}", e),
         }
     }
 }
@@ -240,6 +458,7 @@ pub fn dest_name(dest: &OutputDest) -> String {
         OutputDest::RawFrame { .. } => "RawFrame".to_string(),
         OutputDest::RawPacket { .. } => "RawPacket".to_string(),
         OutputDest::Demuxed { .. } => "Demuxed".to_string(),
+        OutputDest::HlsAdaptive { ladder } => ladder.dir.clone(),
     }
 }
 
@@ -261,7 +480,10 @@ pub struct PipeConfigBuilder {
 impl PipeConfigBuilder {
     /// Set network input source
     pub fn input_url(mut self, url: impl Into<String>) -> Self {
-        self.input = Some(InputConfig::Network { url: url.into() });
+        self.input = Some(InputConfig::Network {
+            url: url.into(),
+            net: Default::default(),
+        });
         self
     }
 
@@ -271,6 +493,15 @@ impl PipeConfigBuilder {
         self
     }
 
+    /// Set a looping file input source
+    pub fn input_file_loop(mut self, path: impl Into<String>, realtime: bool) -> Self {
+        self.input = Some(InputConfig::FileLoop {
+            path: path.into(),
+            realtime,
+        });
+        self
+    }
+
     /// Add RTSP output
     /// if encode is None, the output will be remuxed
     /// if encode is Some, the output will be encoded
@@ -14,7 +14,7 @@ fn test_builder_input_url() {
         .build();
 
     match &config.input {
-        InputConfig::Network { url } => {
+        InputConfig::Network { url, .. } => {
             assert_eq!(url, "rtsp://localhost:8554/stream");
         }
         _ => panic!("Expected Network input"),
@@ -442,3 +442,89 @@ async fn test_pipe_raw_frame_output() {
 
     assert!(frame_count > 0, "Should have received at least one frame");
 }
+
+/// A two-rendition HLS ladder from test.mp4: the master playlist lists both
+/// variants, and the variants (same segment_seconds, same forced keyframe
+/// cadence) cut the same number of segments.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[ignore = "requires ffmpeg libs + scripts/test.mp4"]
+async fn test_hls_ladder_segments_aligned() {
+    use std::time::Duration;
+
+    use crate::hls::{HlsLadder, HlsRendition};
+    use crate::types::OutputConfig;
+
+    let media = concat!(env!("CARGO_MANIFEST_DIR"), "/../../scripts/test.mp4");
+    let dir = std::env::temp_dir().join(format!("hls-ladder-{}", uuid::Uuid::new_v4()));
+    let ladder = HlsLadder {
+        dir: dir.to_string_lossy().into_owned(),
+        segment_seconds: 1,
+        list_size: 0,
+        renditions: vec![
+            HlsRendition {
+                name: "small".to_string(),
+                width: 320,
+                height: 180,
+                bitrate: 400_000,
+            },
+            HlsRendition {
+                name: "tiny".to_string(),
+                width: 160,
+                height: 90,
+                bitrate: 150_000,
+            },
+        ],
+    };
+    let config = PipeConfig {
+        input: InputConfig::File {
+            path: media.to_string(),
+        },
+        outputs: vec![OutputConfig::new(
+            OutputDest::HlsAdaptive {
+                ladder: ladder.clone(),
+            },
+            None,
+        )],
+    };
+    let pipe = Arc::new(Pipe::new(config));
+    {
+        let p = pipe.clone();
+        tokio::spawn(async move { p.start(None).await });
+    }
+
+    // A variant is complete once its muxer wrote the trailer (#EXT-X-ENDLIST).
+    let variants = ladder
+        .renditions
+        .iter()
+        .map(|r| ladder.variant_path(r))
+        .collect::<Vec<_>>();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(60);
+    let playlists = loop {
+        let playlists = variants
+            .iter()
+            .map(|p| std::fs::read_to_string(p).unwrap_or_default())
+            .collect::<Vec<_>>();
+        if playlists.iter().all(|p| p.contains("#EXT-X-ENDLIST")) {
+            break playlists;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "variants did not finish: {playlists:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    };
+    pipe.cancel();
+
+    let master = std::fs::read_to_string(ladder.master_path()).unwrap();
+    assert!(master.contains("BANDWIDTH=400000,RESOLUTION=320x180\nsmall/index.m3u8"));
+    assert!(master.contains("BANDWIDTH=150000,RESOLUTION=160x90\ntiny/index.m3u8"));
+
+    let segments = playlists
+        .iter()
+        .map(|p| p.lines().filter(|l| l.starts_with("#EXTINF")).count())
+        .collect::<Vec<_>>();
+    assert!(segments[0] > 1, "expected several segments: {segments:?}");
+    assert_eq!(segments[0], segments[1], "segment counts differ");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
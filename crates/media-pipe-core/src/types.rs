@@ -7,6 +7,7 @@ use ffmpeg_bus::bus::{OutputAvType, VideoRawFrameStream};
 use ffmpeg_bus::stream::AvStream;
 use tokio::task::JoinHandle;
 
+use crate::hls::HlsLadder;
 use crate::stream::RawSinkSource;
 
 use ffmpeg_bus::bus::{OutputConfig as FbOutputConfig, OutputDest as FbOutputDest};
@@ -27,6 +28,13 @@ pub trait DemuxedSink: Send + Sync + 'static {
     /// video+audio pair sharing one media) drop the missing one from its
     /// expected set. Default: no-op.
     fn on_rejected(&self) {}
+
+    /// Whether the sink takes H.264/H.265 as Annex B only: length-prefixed
+    /// input (MP4) is then rewritten by the bus, parameter sets included
+    /// (see `ffmpeg_bus::bsf::AnnexBFilter`). Default: packets as demuxed.
+    fn annexb(&self) -> bool {
+        false
+    }
 }
 
 /// Encode configuration (used as HashMap key, same config shares encoder)
@@ -59,6 +67,17 @@ impl Default for EncodeConfig {
     }
 }
 
+impl EncodeConfig {
+    /// Check against the encoder the config would get (see
+    /// [`ffmpeg_bus::encoder::validate`]), e.g. for form-time feedback.
+    pub fn validate(
+        &self,
+    ) -> Result<ffmpeg_bus::encoder::ValidatedEncodeConfig, Vec<ffmpeg_bus::encoder::ValidationIssue>>
+    {
+        ffmpeg_bus::encoder::validate(&to_fb_encode_config(self))
+    }
+}
+
 impl PartialEq for EncodeConfig {
     fn eq(&self, other: &Self) -> bool {
         self.codec == other.codec
@@ -97,6 +116,10 @@ pub enum OutputDest {
     /// Demuxed (raw codec) passthrough delivered to a [`DemuxedSink`], e.g. a
     /// ZLMediaKit media. One demuxed input packet per emitted item.
     Demuxed { sink: Arc<dyn DemuxedSink> },
+    /// Adaptive HLS: one encoder per ladder rendition (sharing the decoder),
+    /// each writing a variant playlist, plus a master playlist. Expanded by
+    /// the [`Pipe`](crate::pipe::Pipe) into several bus outputs.
+    HlsAdaptive { ladder: HlsLadder },
 }
 
 /// Configuration for a single output
@@ -153,19 +176,47 @@ impl OutputConfig {
 /// Input configuration
 #[derive(Clone)]
 pub enum InputConfig {
-    Network { url: String },
-    File { path: String },
-    Device { display: String, format: String },
+    Network {
+        url: String,
+        net: ffmpeg_bus::bus::NetInputOptions,
+    },
+    File {
+        path: String,
+    },
+    FileLoop {
+        path: String,
+        realtime: bool,
+    },
+    Device {
+        display: String,
+        format: String,
+    },
+    /// Demuxed as `format` from the reader `open` returns (see
+    /// [`ffmpeg_bus::bus::InputConfig::Reader`]).
+    Reader {
+        open: ffmpeg_bus::bus::ReaderFactory,
+        format: String,
+    },
 }
 
 impl Into<ffmpeg_bus::bus::InputConfig> for InputConfig {
     fn into(self) -> ffmpeg_bus::bus::InputConfig {
         match self {
-            InputConfig::Network { url } => ffmpeg_bus::bus::InputConfig::Net { url },
+            InputConfig::Network { url, net } => ffmpeg_bus::bus::InputConfig::Net {
+                url,
+                reconnect: None,
+                net,
+            },
             InputConfig::File { path } => ffmpeg_bus::bus::InputConfig::File { path },
+            InputConfig::FileLoop { path, realtime } => {
+                ffmpeg_bus::bus::InputConfig::FileLoop { path, realtime }
+            }
             InputConfig::Device { display, format } => {
                 ffmpeg_bus::bus::InputConfig::Device { display, format }
             }
+            InputConfig::Reader { open, format } => {
+                ffmpeg_bus::bus::InputConfig::Reader { open, format }
+            }
         }
     }
 }
@@ -247,14 +298,20 @@ fn to_fb_output(config: &OutputConfig) -> Option<FbOutputConfig> {
         OutputDest::Network { url, format } => FbOutputDest::Net {
             url: url.clone(),
             format: Some(format.clone()),
+            open_policy: Default::default(),
+            // A pushed stream outlives a restart of whatever it is pushed to.
+            reconnect: Some(Default::default()),
         },
         OutputDest::RawFrame { .. } => FbOutputDest::Raw,
         OutputDest::RawPacket { .. } => FbOutputDest::Encoded,
         // A demuxed sink (e.g. ZLM) consumes raw codec frames directly (no
         // container framing). `Demuxed` gives one demuxed input packet per
         // emitted item with no re-encoding or muxing, so video gets clean
-        // Annex B / AVCC NALs and audio gets one raw AAC frame per packet.
+        // Annex B / AVCC NALs (Annex B only if the sink says so) and audio
+        // gets one raw AAC frame per packet.
         OutputDest::Demuxed { .. } => FbOutputDest::Demuxed,
+        // Not a single bus output; see `hls::plan_ladder`.
+        OutputDest::HlsAdaptive { .. } => return None,
     };
     let id = config
         .id
@@ -267,6 +324,11 @@ fn to_fb_output(config: &OutputConfig) -> Option<FbOutputConfig> {
     if config.include_audio {
         fb = fb.with_audio();
     }
+    if let OutputDest::Demuxed { sink } = &config.dest
+        && sink.annexb()
+    {
+        fb = fb.with_annexb();
+    }
     Some(fb)
 }
 
@@ -281,5 +343,7 @@ fn to_fb_encode_config(e: &EncodeConfig) -> ffmpeg_bus::bus::EncodeConfig {
         sample_rate: None,
         channels: None,
         audio_bitrate: None,
+        keyframe_interval: None,
+        b_frames: None,
     }
 }
@@ -3,11 +3,15 @@
 //! Implements [`DemuxedSink`] by forwarding a pipe's demuxed packet stream into
 //! a ZLMediaKit `Media` as `Track`/`Frame`s. Video + audio tracks of one
 //! `Media` are gated by a shared [`ZlmTrackCoordinator`] so `init_complete()` is
-//! only called once both sides have registered.
+//! only called once both sides have registered. Sinks given a
+//! [`ts::TsSession`] put their frames on a timeline shared with the other
+//! input sessions of the same `Media` (see [`ts`]).
+
+pub mod ts;
 
 use std::sync::{Arc, Mutex as SyncMutex};
 
-use ffmpeg_bus::bus::{OutputAvType, VideoRawFrameStream};
+use ffmpeg_bus::bus::{FrameEvent, OutputAvType, VideoRawFrameStream};
 use ffmpeg_bus::stream::AvStream;
 use futures::StreamExt;
 use media_pipe_core::{DemuxedSink, OutputConfig, OutputDest};
@@ -18,11 +22,14 @@ use rszlm::{
 };
 use tokio::{sync::watch, task::JoinHandle};
 
+use crate::ts::TsSession;
+
 /// A [`DemuxedSink`] that forwards demuxed packets to a ZLMediaKit `Media`.
 pub struct ZlmSink {
     media: Arc<Media>,
     coordinator: Option<Arc<ZlmTrackCoordinator>>,
     av_type: OutputAvType,
+    session: Option<TsSession>,
 }
 
 impl ZlmSink {
@@ -35,8 +42,15 @@ impl ZlmSink {
             media,
             coordinator,
             av_type,
+            session: None,
         }
     }
+
+    /// Map timestamps through `session` (see [`ts::TsNormalizer`]).
+    pub fn with_session(mut self, session: TsSession) -> Self {
+        self.session = Some(session);
+        self
+    }
 }
 
 impl DemuxedSink for ZlmSink {
@@ -44,8 +58,10 @@ impl DemuxedSink for ZlmSink {
         let media = Arc::clone(&self.media);
         let coordinator = self.coordinator.clone();
         let av_type = self.av_type;
+        let session = self.session.clone();
         tokio::spawn(async move {
-            forward_raw_packet_stream_to_zlm(stream, av, media, coordinator, av_type).await;
+            forward_raw_packet_stream_to_zlm(stream, av, media, coordinator, av_type, session)
+                .await;
         })
     }
 
@@ -57,20 +73,48 @@ impl DemuxedSink for ZlmSink {
             coord.expect_one_less();
         }
     }
+
+    /// ZLM splits video frames on start codes, and a player joining needs the
+    /// SPS/PPS ahead of the first keyframe it gets.
+    fn annexb(&self) -> bool {
+        true
+    }
 }
 
 /// Convenience: build the ZLM outputs for one `Media` — a video track plus an
 /// optional audio track, sharing a coordinator. Mirrors the device pipeline.
 pub fn zlm_outputs(media: Arc<Media>, include_audio: bool) -> Vec<OutputConfig> {
+    build_zlm_outputs(media, include_audio, None)
+}
+
+/// [`zlm_outputs`] for one of several input sessions feeding the same `Media`
+/// in turn (failover between URLs): timestamps go through `session`, and
+/// tracks are only initialized by the first session.
+pub fn zlm_outputs_with_session(
+    media: Arc<Media>,
+    include_audio: bool,
+    session: TsSession,
+) -> Vec<OutputConfig> {
+    build_zlm_outputs(media, include_audio, Some(session))
+}
+
+fn build_zlm_outputs(
+    media: Arc<Media>,
+    include_audio: bool,
+    session: Option<TsSession>,
+) -> Vec<OutputConfig> {
     let expected = if include_audio { 2 } else { 1 };
     let coordinator = ZlmTrackCoordinator::new(Arc::clone(&media), expected);
+    let sink = |av_type| {
+        let sink = ZlmSink::new(Arc::clone(&media), Some(Arc::clone(&coordinator)), av_type);
+        match &session {
+            Some(session) => sink.with_session(session.clone()),
+            None => sink,
+        }
+    };
     let mut outs = vec![OutputConfig::new(
         OutputDest::Demuxed {
-            sink: Arc::new(ZlmSink::new(
-                Arc::clone(&media),
-                Some(Arc::clone(&coordinator)),
-                OutputAvType::Video,
-            )),
+            sink: Arc::new(sink(OutputAvType::Video)),
         },
         None,
     )];
@@ -78,7 +122,7 @@ pub fn zlm_outputs(media: Arc<Media>, include_audio: bool) -> Vec<OutputConfig>
         outs.push(
             OutputConfig::new(
                 OutputDest::Demuxed {
-                    sink: Arc::new(ZlmSink::new(media, Some(coordinator), OutputAvType::Audio)),
+                    sink: Arc::new(sink(OutputAvType::Audio)),
                 },
                 None,
             )
@@ -169,20 +213,29 @@ impl ZlmTrackCoordinator {
 
 /// Forward a raw (demuxed) packet stream from ffmpeg-bus to a ZLMediaKit Media.
 /// Each emitted item is one raw codec frame — for audio one AAC frame (no ADTS
-/// header), for video a NALU group in Annex B (or AVCC, converted below). PTS/DTS
-/// are converted to ms. Track init is gated by [`ZlmTrackCoordinator`].
+/// header), for video a NALU group in Annex B (the bus converts AVCC, see
+/// [`ZlmSink`]'s `DemuxedSink::annexb`). PTS/DTS
+/// are converted to ms, then mapped through `session` when given. Track init is
+/// gated by [`ZlmTrackCoordinator`]. After the stream lags, video resumes at
+/// the next keyframe: the track stays as it is, only the packets that reference
+/// skipped ones are left out.
 async fn forward_raw_packet_stream_to_zlm(
     mut stream: VideoRawFrameStream,
     av: AvStream,
     media: Arc<Media>,
     coordinator: Option<Arc<ZlmTrackCoordinator>>,
     av_type: OutputAvType,
+    session: Option<TsSession>,
 ) {
-    use ffmpeg_bus::bsf::{convert_avcc_to_annexb, is_annexb_packet};
-
+    // ZLM tracks are video or audio: a data stream has nowhere to go, and the
+    // primary of `All` is its video.
+    if av_type == OutputAvType::Data {
+        log::warn!("ZLM: data streams are not forwarded");
+        return;
+    }
     let make_codec_id = || match av_type {
-        OutputAvType::Video => CodecId::H264,
         OutputAvType::Audio => CodecId::AAC,
+        _ => CodecId::H264,
     };
 
     let default_width = av.width();
@@ -191,18 +244,40 @@ async fn forward_raw_packet_stream_to_zlm(
     let audio_sample_rate = av.sample_rate();
     let audio_channels = av.channels();
     let mut track_initialized = false;
-    let mut needs_conversion = false;
-    let mut conversion_checked = false;
+    let mut await_key = false;
 
-    while let Some(opt) = stream.next().await {
-        let Some(frame) = opt else { continue };
+    while let Some(event) = stream.next().await {
+        let frame = match event {
+            FrameEvent::Frame(frame) => frame,
+            FrameEvent::Eof => break,
+            FrameEvent::Lagged(n) => {
+                log::warn!("ZLM: {:?} stream lagged, {} packets skipped", av_type, n);
+                await_key = matches!(av_type, OutputAvType::Video | OutputAvType::All);
+                continue;
+            }
+            FrameEvent::Error(e) => {
+                log::warn!("ZLM: {:?} stream: {}", av_type, e);
+                continue;
+            }
+        };
+        if await_key {
+            if !frame.is_key {
+                continue;
+            }
+            await_key = false;
+        }
+
+        // An earlier session of this `Media` already set its tracks up.
+        if !track_initialized && session.as_ref().is_some_and(TsSession::tracks_ready) {
+            track_initialized = true;
+        }
 
         if !track_initialized {
             // Build + register Track in a sync block so the non-`Send` Track is
             // dropped before any `.await` (track holds a raw FFI pointer).
             let mut completion_rx = {
                 let track = match av_type {
-                    OutputAvType::Video => {
+                    OutputAvType::Video | OutputAvType::Data | OutputAvType::All => {
                         let w = if frame.width > 0 {
                             frame.width as i32
                         } else {
@@ -249,33 +324,28 @@ async fn forward_raw_packet_stream_to_zlm(
                 ZlmTrackCoordinator::wait_complete(rx).await;
             }
             track_initialized = true;
-
-            if matches!(av_type, OutputAvType::Video) && !conversion_checked {
-                needs_conversion = !is_annexb_packet(frame.data.as_ref());
-                conversion_checked = true;
-                log::info!(
-                    "ZLM: video format {}",
-                    if needs_conversion {
-                        "MP4 (AVCC) — BSF conversion enabled"
-                    } else {
-                        "Annex B — no conversion"
-                    }
-                );
+            if let Some(session) = &session {
+                session.set_tracks_ready();
             }
         }
 
         let time_base = av.time_base();
         let pts_ms = frame.pts_ms(time_base);
         let dts_ms = frame.dts_ms(time_base);
+        let (dts_ms, pts_ms) = match &session {
+            Some(session) => {
+                let video = matches!(av_type, OutputAvType::Video | OutputAvType::All);
+                match session.map(video, frame.is_key, dts_ms, pts_ms) {
+                    Some(mapped) => mapped,
+                    // Not this session's turn (yet, or any more).
+                    None => continue,
+                }
+            }
+            None => (dts_ms as u64, pts_ms as u64),
+        };
 
-        let data: std::borrow::Cow<'_, [u8]> =
-            if matches!(av_type, OutputAvType::Video) && needs_conversion {
-                std::borrow::Cow::Owned(convert_avcc_to_annexb(frame.data.as_ref()).to_vec())
-            } else {
-                std::borrow::Cow::Borrowed(frame.data.as_ref())
-            };
-
-        let zlm_frame = ZlmFrame::new(make_codec_id(), dts_ms as u64, pts_ms as u64, data.as_ref());
+        let data = frame.data.as_ref();
+        let zlm_frame = ZlmFrame::new(make_codec_id(), dts_ms, pts_ms, data);
         if !media.input_frame(&zlm_frame) {
             log::warn!(
                 "ZLM: input_frame failed (av={:?}, pts_ms={}, dts_ms={}, len={})",
@@ -0,0 +1,124 @@
+//! One continuous timeline for a `Media` fed by successive input sessions.
+//!
+//! Every new pipe (a reconnect, or a switch to another URL of the same
+//! camera) restarts its timestamps, which a recording would see as time
+//! running backwards. A [`TsNormalizer`] is shared by all sessions feeding one
+//! `Media`: each session's frames are re-based to continue right after the
+//! last frame forwarded, and kept monotonic per track. A session only takes
+//! over at a video keyframe; until then the session before it keeps
+//! forwarding, so a switch never cuts into a GOP.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+
+/// Gap left between the last frame of one session and the first of the next,
+/// milliseconds (about one frame).
+const SESSION_GAP_MS: i64 = 40;
+
+#[derive(Default)]
+struct State {
+    /// The session whose frames are forwarded.
+    active: Option<u64>,
+    next_id: u64,
+    /// Added to the active session's timestamps.
+    offset_ms: i64,
+    /// Last dts forwarded per track (video, audio), output clock.
+    last_dts: [Option<i64>; 2],
+    /// Whether the `Media`'s tracks have been initialized by some session.
+    tracks_ready: bool,
+}
+
+pub struct TsNormalizer {
+    state: Mutex<State>,
+    active_tx: watch::Sender<Option<u64>>,
+}
+
+impl TsNormalizer {
+    pub fn new() -> Arc<Self> {
+        let (active_tx, _) = watch::channel(None);
+        Arc::new(Self {
+            state: Mutex::new(State::default()),
+            active_tx,
+        })
+    }
+
+    /// Register a new input session. It takes over from the current one at
+    /// its first video keyframe.
+    pub fn session(self: &Arc<Self>) -> TsSession {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        TsSession {
+            normalizer: Arc::clone(self),
+            id: state.next_id,
+        }
+    }
+
+    fn map(&self, id: u64, video: bool, key: bool, dts_ms: f64, pts_ms: f64) -> Option<(u64, u64)> {
+        let mut state = self.state.lock().unwrap();
+        let (dts, pts) = (dts_ms.round() as i64, pts_ms.round() as i64);
+        if state.active != Some(id) {
+            // Superseded, or not yet at a keyframe.
+            if state.active.is_some_and(|active| id < active) || !(video && key) {
+                return None;
+            }
+            state.offset_ms = match state.last_dts.iter().flatten().max() {
+                Some(last) => last + SESSION_GAP_MS - dts,
+                None => 0,
+            };
+            state.active = Some(id);
+            self.active_tx.send_replace(Some(id));
+        }
+        let track = if video { 0 } else { 1 };
+        let mut out_dts = dts + state.offset_ms;
+        if let Some(last) = state.last_dts[track]
+            && out_dts <= last
+        {
+            out_dts = last + 1;
+        }
+        let out_pts = (pts + state.offset_ms).max(out_dts);
+        state.last_dts[track] = Some(out_dts);
+        Some((out_dts.max(0) as u64, out_pts.max(0) as u64))
+    }
+}
+
+/// One input session's handle on a [`TsNormalizer`].
+#[derive(Clone)]
+pub struct TsSession {
+    normalizer: Arc<TsNormalizer>,
+    id: u64,
+}
+
+impl TsSession {
+    /// A frame's dts/pts (milliseconds, this session's clock) on the shared
+    /// timeline, or `None` if the frame must be dropped: the session has not
+    /// taken over yet, or a later one has.
+    pub fn map(&self, video: bool, key: bool, dts_ms: f64, pts_ms: f64) -> Option<(u64, u64)> {
+        self.normalizer.map(self.id, video, key, dts_ms, pts_ms)
+    }
+
+    /// Whether this session's frames are the ones forwarded.
+    pub fn is_active(&self) -> bool {
+        *self.normalizer.active_tx.borrow() == Some(self.id)
+    }
+
+    /// Wait until this session has taken over (its first keyframe).
+    pub async fn wait_active(&self) {
+        let mut rx = self.normalizer.active_tx.subscribe();
+        let _ = rx.wait_for(|active| *active == Some(self.id)).await;
+    }
+
+    /// Whether an earlier session already initialized the `Media`'s tracks,
+    /// so this one must not register them again.
+    pub fn tracks_ready(&self) -> bool {
+        self.normalizer.state.lock().unwrap().tracks_ready
+    }
+
+    pub fn set_tracks_ready(&self) {
+        self.normalizer.state.lock().unwrap().tracks_ready = true;
+    }
+}
+
+#[cfg(test)]
+#[path = "ts_test.rs"]
+mod ts_test;
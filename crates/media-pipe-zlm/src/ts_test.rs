@@ -0,0 +1,43 @@
+use super::*;
+
+#[test]
+fn sessions_continue_the_timeline_from_a_keyframe() {
+    let normalizer = TsNormalizer::new();
+    let first = normalizer.session();
+    // Nothing is forwarded before the first keyframe.
+    assert_eq!(first.map(true, false, 0.0, 0.0), None);
+    assert_eq!(first.map(false, false, 5.0, 5.0), None);
+    assert_eq!(first.map(true, true, 40.0, 80.0), Some((40, 80)));
+    assert!(first.is_active());
+    assert_eq!(first.map(false, false, 50.0, 50.0), Some((50, 50)));
+    assert_eq!(first.map(true, false, 1000.0, 1000.0), Some((1000, 1000)));
+
+    // A newer session restarts at 0; the first keeps going until the new
+    // one reaches a keyframe.
+    let second = normalizer.session();
+    assert_eq!(second.map(true, false, 0.0, 0.0), None);
+    assert_eq!(first.map(true, false, 1040.0, 1040.0), Some((1040, 1040)));
+    assert_eq!(second.map(true, true, 20.0, 60.0), Some((1080, 1120)));
+    assert!(second.is_active() && !first.is_active());
+    // From now on the first session is dropped.
+    assert_eq!(first.map(true, false, 1080.0, 1080.0), None);
+    assert_eq!(second.map(true, false, 60.0, 60.0), Some((1120, 1120)));
+    // Audio continues after the last timestamp too, and never goes back.
+    assert_eq!(second.map(false, false, 20.0, 20.0), Some((1080, 1080)));
+    assert_eq!(second.map(false, false, 10.0, 10.0), Some((1081, 1081)));
+}
+
+#[tokio::test]
+async fn wait_active_resolves_at_takeover() {
+    let normalizer = TsNormalizer::new();
+    let session = normalizer.session();
+    let waiter = tokio::spawn({
+        let session = session.clone();
+        async move { session.wait_active().await }
+    });
+    assert!(session.map(true, true, 0.0, 0.0).is_some());
+    tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+        .await
+        .expect("takeover not signalled")
+        .unwrap();
+}
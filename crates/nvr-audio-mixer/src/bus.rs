@@ -159,6 +159,7 @@ fn spawn_publish(
                         log::warn!("audio bus '{id}' write_packet: {e:#}");
                     }
                 }
+                Ok(RawPacketCmd::Reconnected) => {}
                 Ok(RawPacketCmd::EOF) => break,
                 Err(RecvError::Lagged(n)) => {
                     log::warn!("audio bus '{id}' publish lagged, dropped {n}");
@@ -32,7 +32,7 @@ impl AudioSource {
             .ok_or_else(|| anyhow::anyhow!("audio source {id}: no audio stream in {url}"))?;
 
         let input_task = AvInputTask::new();
-        let decoder = Decoder::new(&audio_stream)?;
+        let decoder = Decoder::new(&audio_stream, None)?;
         let decoder_task = DecoderTask::new();
         decoder_task
             .start(decoder, input_task.subscribe(), false)
@@ -185,7 +185,7 @@ async fn open_connection(id: &str, url: &str) -> Result<Connection> {
         .ok_or_else(|| anyhow::anyhow!("source {id}: no video stream in {url}"))?;
 
     let input_task = AvInputTask::new();
-    let decoder = Decoder::new(&video_stream)?;
+    let decoder = Decoder::new(&video_stream, None)?;
     let decoder_task = DecoderTask::new();
     // Compositor keeps only the latest frame per source, so lossy is fine.
     decoder_task.start(decoder, input_task.subscribe(), false).await;
@@ -3,7 +3,7 @@ use schema::onvif as onvif_xsd;
 use url::Url;
 
 use crate::config::OnvifConfig;
-use crate::types::{DeviceInfo, OnvifError, Preset, Profile, PtzVelocity};
+use crate::types::{CameraTime, DeviceInfo, OnvifError, Preset, Profile, PtzVelocity};
 
 /// A connected ONVIF camera: per-service SOAP clients resolved once at connect.
 pub struct OnvifCamera {
@@ -92,6 +92,26 @@ impl OnvifCamera {
         })
     }
 
+    /// The camera's clock (`GetSystemDateAndTime`), in UTC.
+    pub async fn system_time(&self) -> Result<CameraTime, OnvifError> {
+        let resp =
+            schema::devicemgmt::get_system_date_and_time(&self.devicemgmt, &Default::default())
+                .await
+                .map_err(map_soap)?;
+        let utc = resp
+            .system_date_and_time
+            .utc_date_time
+            .ok_or_else(|| OnvifError::Protocol("device reports no UTC date/time".into()))?;
+        Ok(CameraTime {
+            year: utc.date.year,
+            month: utc.date.month,
+            day: utc.date.day,
+            hour: utc.time.hour,
+            minute: utc.time.minute,
+            second: utc.time.second,
+        })
+    }
+
     pub async fn profiles(&self) -> Result<Vec<Profile>, OnvifError> {
         let resp = schema::media::get_profiles(&self.media, &Default::default())
             .await
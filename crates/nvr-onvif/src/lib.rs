@@ -9,5 +9,5 @@ pub mod uri;
 pub use camera::OnvifCamera;
 pub use config::OnvifConfig;
 pub use discovery::discover;
-pub use types::{DeviceInfo, Discovered, OnvifError, Preset, Profile, PtzVelocity};
+pub use types::{CameraTime, DeviceInfo, Discovered, OnvifError, Preset, Profile, PtzVelocity};
 pub use uri::inject_credentials;
@@ -51,6 +51,45 @@ impl PtzVelocity {
     }
 }
 
+/// A camera's UTC date and time as reported by `GetSystemDateAndTime`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CameraTime {
+    pub year: i32,
+    pub month: i32,
+    pub day: i32,
+    pub hour: i32,
+    pub minute: i32,
+    pub second: i32,
+}
+
+impl CameraTime {
+    /// Seconds since the Unix epoch, or `None` for an impossible date/time.
+    pub fn unix_seconds(&self) -> Option<i64> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || !(0..24).contains(&self.hour)
+            || !(0..60).contains(&self.minute)
+            || !(0..=60).contains(&self.second)
+        {
+            return None;
+        }
+        // Days from 1970-01-01 to the civil date (proleptic Gregorian).
+        let y = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let m = i64::from(self.month);
+        let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        Some(
+            days * 86_400
+                + i64::from(self.hour) * 3_600
+                + i64::from(self.minute) * 60
+                + i64::from(self.second),
+        )
+    }
+}
+
 #[derive(Debug)]
 pub enum OnvifError {
     Connect(String),
@@ -21,3 +21,22 @@ fn error_display_is_human_readable() {
         "profile not found: P1"
     );
 }
+
+#[test]
+fn camera_time_converts_to_unix_seconds() {
+    let t = |year, month, day, hour, minute, second| CameraTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    };
+    assert_eq!(t(1970, 1, 1, 0, 0, 0).unix_seconds(), Some(0));
+    assert_eq!(t(2024, 1, 1, 0, 0, 0).unix_seconds(), Some(1_704_067_200));
+    assert_eq!(
+        t(2024, 2, 29, 12, 30, 15).unix_seconds(),
+        Some(1_709_209_815)
+    );
+    assert_eq!(t(2024, 13, 1, 0, 0, 0).unix_seconds(), None);
+}
@@ -197,6 +197,7 @@ impl Recorder {
                         }
                     };
                     match cmd {
+                        RawPacketCmd::Reconnected => continue,
                         RawPacketCmd::EOF => {
                             self.close_writer(&mut writer).await?;
                             task.stop();
@@ -61,16 +61,12 @@ impl ProgramSink {
         if let Some(b) = cfg.bitrate {
             opts.set("b", &b.to_string());
         }
-        let encoder = Encoder::new(template, settings, Some(opts))?;
+        // `push` stamps frames on its own microsecond clock.
+        let encoder = Encoder::new(template, settings, Some(opts))?
+            .with_frame_time_base(ffmpeg_next::Rational(1, 1_000_000));
 
-        // `for_encoder_output` copies the template's (source) dimensions;
-        // overwrite them with the canvas size so the muxed stream is honest.
-        let out_stream = AvStream::for_encoder_output(template, ffmpeg_next::codec::Id::H264);
-        unsafe {
-            let p = out_stream.parameters().as_ptr() as *mut ffmpeg_next::ffi::AVCodecParameters;
-            (*p).width = cfg.width as i32;
-            (*p).height = cfg.height as i32;
-        }
+        // The encoder's own parameters: the canvas size, not the template's.
+        let out_stream = encoder.output_stream(0);
         let mut output = AvOutput::new(&cfg.publish_url, Some(&cfg.format), None)?;
         output.add_stream(&out_stream)?;
 
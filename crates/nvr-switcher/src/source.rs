@@ -42,7 +42,7 @@ impl Source {
         // Subscribe the decoder to the input BEFORE the input starts reading, so
         // no packets are missed.
         let input_task = AvInputTask::new();
-        let decoder = Decoder::new(&video_stream)?;
+        let decoder = Decoder::new(&video_stream, None)?;
         let decoder_task = DecoderTask::new();
         // Switcher keeps only the latest frame per source, so lossy is fine.
         decoder_task.start(decoder, input_task.subscribe(), false).await;
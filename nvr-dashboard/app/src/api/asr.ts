@@ -1,4 +1,5 @@
 import { getAuthToken } from '../auth/token'
+import { API_BASE } from './request'
 
 // The ASR control endpoints return a short plain-text status ("started" /
 // "stopped" / "no audio: ..."), not the shared `{ code, message, data }`
@@ -9,7 +10,7 @@ async function post(path: string): Promise<string> {
   if (token) {
     headers.set('Authorization', `Bearer ${token}`)
   }
-  const res = await fetch(`/api${path}`, { method: 'POST', headers })
+  const res = await fetch(`${API_BASE}${path}`, { method: 'POST', headers })
   const text = await res.text().catch(() => '')
   if (!res.ok) {
     throw new Error(text || `请求失败 (${res.status})`)
@@ -8,9 +8,57 @@ export interface DeviceItem {
   description: string
   include_audio: boolean
   record: boolean
+  /** ZLM stream name (the name in its live URLs); unique across devices. */
+  stream_key?: string
+  /** IANA zone; empty for the server default. */
+  timezone?: string
+  /** The zone in effect (local devices). */
+  tz?: string
+  detection?: DetectionSettings
+  /** Device group (site, floor); empty for none. */
+  group?: string
+  /** What its input last reported: `online`, `offline` or `error`; empty before. */
+  status?: string
   created_at: string
   updated_at: string
   flv_url?: string
+  /** Privacy mode currently blanks the device. */
+  privacy?: boolean
+  /** Live viewer sessions (local devices). */
+  viewers?: number
+}
+
+export interface DetectionSettings {
+  /** `http` (the inference sidecar), `stub` or `none`. */
+  detector: string
+  /** Labels that make events; empty for every label. */
+  labels?: string[]
+  /** Lowest confidence per label (0..1). */
+  thresholds?: Record<string, number>
+  /** For labels without a threshold; defaults to 0.5. */
+  min_confidence?: number
+  /** Bookmark each event's start on the timeline. */
+  bookmark?: boolean
+}
+
+export interface PrivacyWindow {
+  /** Local time, HH:MM. An end before the start spans midnight. */
+  start: string
+  end: string
+}
+
+export interface DevicePrivacy {
+  enabled: boolean
+  until?: string | null
+  windows: PrivacyWindow[]
+  /** Private right now, manually or by a window. */
+  active: boolean
+}
+
+export interface PrivacyPayload {
+  enabled: boolean
+  until?: string | null
+  windows?: PrivacyWindow[]
 }
 
 export interface DevicePayload {
@@ -21,6 +69,14 @@ export interface DevicePayload {
   description?: string
   include_audio?: boolean
   record?: boolean
+  stream_key?: string
+  /** On update: move to the default key for the name. */
+  regenerate_stream_key?: boolean
+  timezone?: string
+  /** Unchanged on update when absent. */
+  detection?: DetectionSettings
+  /** Unchanged on update when absent. */
+  group?: string
 }
 
 export function listDevices() {
@@ -35,7 +91,7 @@ export function addDevice(payload: DevicePayload) {
 }
 
 export function updateDevice(id: string, payload: DevicePayload) {
-  return request<DeviceItem>(`/device/update/${encodeURIComponent(id)}`, {
+  return request<DeviceItem & { stream_key_changed: boolean }>(`/device/update/${encodeURIComponent(id)}`, {
     method: 'POST',
     body: payload,
   })
@@ -46,3 +102,43 @@ export function removeDevice(id: string) {
     method: 'POST',
   })
 }
+
+export function getDevicePrivacy(id: string) {
+  return request<DevicePrivacy>(`/device/privacy/${encodeURIComponent(id)}`)
+}
+
+export function setDevicePrivacy(id: string, payload: PrivacyPayload) {
+  return request<DevicePrivacy>(`/device/privacy/${encodeURIComponent(id)}`, {
+    method: 'POST',
+    body: payload,
+  })
+}
+
+export interface InputStats {
+  id: string
+  packets: number
+  bytes: number
+  corrupt_packets: number
+  lagged_packets: number
+  reconnects: number
+  /** When the last packet was read, unix milliseconds. */
+  last_packet_ms?: number | null
+}
+
+export interface OutputStats {
+  id: string
+  packets_written: number
+  bytes_written: number
+  /** Over the last 5 seconds. */
+  fps: number
+  lagged: number
+}
+
+export interface DeviceStats {
+  inputs: InputStats[]
+  outputs: OutputStats[]
+}
+
+export function getDeviceStats(id: string) {
+  return request<DeviceStats>(`/device/${encodeURIComponent(id)}/stats`)
+}
@@ -1,5 +1,5 @@
 import { getAuthToken } from '../auth/token'
-import { request } from './request'
+import { API_BASE, request } from './request'
 
 export interface PlaybackSegmentItem {
   id: string
@@ -107,13 +107,13 @@ function withAuthToken(url: string) {
 }
 
 export function buildPlaybackSegmentUrl(id: string) {
-  return withAuthToken(`/api/playback/segment/${encodeURIComponent(id)}`)
+  return withAuthToken(`${API_BASE}/playback/segment/${encodeURIComponent(id)}`)
 }
 
 export function buildPlaybackSegmentPlaylistUrl(id: string) {
-  return withAuthToken(`/api/playback/segment-playlist/${encodeURIComponent(id)}`)
+  return withAuthToken(`${API_BASE}/playback/segment-playlist/${encodeURIComponent(id)}`)
 }
 
 export function buildPlaybackPlaylistUrl(deviceId: string) {
-  return withAuthToken(`/api/playback/playlist/${encodeURIComponent(deviceId)}`)
+  return withAuthToken(`${API_BASE}/playback/playlist/${encodeURIComponent(deviceId)}`)
 }
@@ -3,7 +3,7 @@ import { clearAuthToken, getAuthToken } from '../auth/token'
 // Project convention: REST API uses only GET and POST (no PUT/PATCH/DELETE).
 type RequestMethod = 'GET' | 'POST'
 
-const API_BASE = '/api'
+export const API_BASE = '/api/v1'
 
 interface RequestOptions extends Omit<RequestInit, 'method' | 'body'> {
   method?: RequestMethod
@@ -33,8 +33,9 @@ export async function request<T>(path: string, options: RequestOptions = {}): Pr
     ...rest,
   })
 
-  if (response.status === 401) {
+  if (response.status === 401 && path !== '/user/login') {
     // Session missing/expired/revoked: drop local auth and return to login.
+    // A refused login is just a wrong password, shown like any other error.
     clearAuthToken()
     const loginUrl = `${import.meta.env.BASE_URL}login`
     if (!window.location.pathname.startsWith(loginUrl)) {
@@ -43,6 +44,15 @@ export async function request<T>(path: string, options: RequestOptions = {}): Pr
     throw new Error('登录已过期，请重新登录')
   }
 
+  if (response.status === 503 && !path.startsWith('/setup')) {
+    // First-run setup not done yet: every call but setup's is refused.
+    const setupUrl = `${import.meta.env.BASE_URL}setup`
+    if (!window.location.pathname.startsWith(setupUrl)) {
+      window.location.assign(setupUrl)
+    }
+    throw new Error('请先完成初始化设置')
+  }
+
   let payload: BaseResponse<T>
   try {
     payload = (await response.json()) as BaseResponse<T>
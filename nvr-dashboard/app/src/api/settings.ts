@@ -24,6 +24,8 @@ export interface CleanupConfig {
   max_total_gb: number
   /** How often the worker runs, in minutes. */
   interval_minutes: number
+  /** Delete API audit log entries older than this many days (0 = keep all). */
+  audit_retention_days?: number
 }
 
 export function getCleanup() {
@@ -33,3 +35,43 @@ export function getCleanup() {
 export function saveCleanup(config: CleanupConfig) {
   return request<CleanupConfig>('/system/cleanup', { method: 'POST', body: config })
 }
+
+/** Camera clock-skew policy (measured by a periodic worker). */
+export interface ClockConfig {
+  /** Warn when a camera's clock is off by more than this many ms (0 = off). */
+  warn_threshold_ms: number
+  /** Stamp recordings with the camera's time instead of the server's. */
+  correct_recordings: boolean
+  /** How often offsets are measured, in seconds. */
+  interval_seconds: number
+}
+
+export interface DeviceClock {
+  /** Camera clock minus server clock. */
+  offset_ms: number
+  source: 'rtcp' | 'onvif'
+  measured_at_ms: number
+  skewed: boolean
+}
+
+export interface SkewEvent {
+  device_id: string
+  kind: 'exceeded' | 'recovered'
+  offset_ms: number
+  threshold_ms: number
+  ts_ms: number
+}
+
+export interface ClockStatus {
+  config: ClockConfig
+  devices: Record<string, DeviceClock>
+  events: SkewEvent[]
+}
+
+export function getClock() {
+  return request<ClockStatus>('/system/clock')
+}
+
+export function saveClock(config: ClockConfig) {
+  return request<ClockConfig>('/system/clock', { method: 'POST', body: config })
+}
@@ -0,0 +1,31 @@
+import { request } from './request'
+
+export interface SetupStatus {
+  required: boolean
+  // Defaults the server uses if none is picked; only while setup is required.
+  record_dir?: string
+  timezone?: string
+}
+
+export function getSetupStatus() {
+  return request<SetupStatus>('/setup/status')
+}
+
+export interface SetupRequest {
+  username: string
+  password: string
+  record_dir: string
+  timezone: string
+}
+
+export interface SetupResponse {
+  token: string
+  username: string
+}
+
+export function completeSetup(payload: SetupRequest) {
+  return request<SetupResponse>('/setup', {
+    method: 'POST',
+    body: payload,
+  })
+}
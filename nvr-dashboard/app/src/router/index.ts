@@ -1,9 +1,16 @@
 import { createRouter, createWebHistory } from 'vue-router'
 import { useAuth } from '../composables/useAuth'
+import { getSetupStatus } from '../api/setup'
 
 const router = createRouter({
   history: createWebHistory(import.meta.env.BASE_URL),
   routes: [
+    {
+      path: '/setup',
+      name: 'setup',
+      component: () => import('../views/SetupView.vue'),
+      meta: { setup: true },
+    },
     {
       path: '/login',
       name: 'login',
@@ -51,7 +58,30 @@ const router = createRouter({
   ],
 })
 
-router.beforeEach((to) => {
+// Once setup is done it stays done, so only a pending answer is re-checked.
+let setupDone = false
+
+async function setupRequired() {
+  if (setupDone) {
+    return false
+  }
+  try {
+    setupDone = !(await getSetupStatus()).required
+  } catch {
+    return false
+  }
+  return !setupDone
+}
+
+router.beforeEach(async (to) => {
+  const onSetup = to.matched.some((r) => r.meta.setup)
+  if (await setupRequired()) {
+    return onSetup ? true : { name: 'setup' }
+  }
+  if (onSetup) {
+    return { name: 'login' }
+  }
+
   const { isLoggedIn } = useAuth()
   const requiresAuth = to.matched.some((r) => r.meta.requiresAuth)
   const guestOnly = to.matched.some((r) => r.meta.guest)
@@ -0,0 +1,20 @@
+-- Audit trail of mutating API calls: who did what to which resource, and how
+-- it went. `ts` is unix milliseconds (UTC) so range filters and retention
+-- pruning are plain integer comparisons. `summary` is the request body with
+-- secrets redacted (capped in size); `resource_id` is the device/pipe/... id
+-- taken from the path (or the body's `id` on create).
+CREATE TABLE IF NOT EXISTS "audit" (
+    "id" INTEGER NOT NULL,
+    "ts" INTEGER NOT NULL,
+    "username" TEXT NOT NULL DEFAULT '',
+    "method" TEXT NOT NULL DEFAULT '',
+    "route" TEXT NOT NULL DEFAULT '',
+    "resource_id" TEXT NOT NULL DEFAULT '',
+    "summary" TEXT NOT NULL DEFAULT '',
+    "status" INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE INDEX IF NOT EXISTS "audit_ts_idx" ON "audit" ("ts");
+CREATE INDEX IF NOT EXISTS "audit_username_idx" ON "audit" ("username", "ts");
+CREATE INDEX IF NOT EXISTS "audit_resource_idx" ON "audit" ("resource_id", "ts");
@@ -0,0 +1,15 @@
+-- Operator bookmarks on a device's timeline. `ts` and `created_at` are unix
+-- milliseconds (UTC); `export_id` is the clip export last started from the
+-- bookmark, empty if none.
+CREATE TABLE IF NOT EXISTS "bookmark" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "device_id" TEXT NOT NULL,
+    "ts" INTEGER NOT NULL,
+    "label" TEXT NOT NULL DEFAULT '',
+    "color" TEXT NOT NULL DEFAULT '',
+    "created_by" TEXT NOT NULL DEFAULT '',
+    "created_at" INTEGER NOT NULL,
+    "export_id" TEXT NOT NULL DEFAULT ''
+);
+
+CREATE INDEX IF NOT EXISTS "bookmark_device_ts_idx" ON "bookmark" ("device_id", "ts");
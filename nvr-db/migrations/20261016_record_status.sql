@@ -0,0 +1,4 @@
+-- Segment status: empty for a normal segment, 'missing' once the recording
+-- reconciliation finds its file gone (the row is kept for audit, but no
+-- longer listed for playback).
+ALTER TABLE "record_segments" ADD COLUMN "status" TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,16 @@
+-- Text timestamps are RFC 3339 in UTC. Rows written by a column default
+-- (`datetime('now')`: UTC, but as "YYYY-MM-DD HH:MM:SS") or with another
+-- offset are rewritten in UTC, so they parse and compare as text with the
+-- rest; anything that does not parse as a time is left alone.
+UPDATE "record_segments" SET "create_time" = strftime('%Y-%m-%dT%H:%M:%S+00:00', "create_time")
+WHERE "create_time" NOT LIKE '%+00:00' AND strftime('%s', "create_time") IS NOT NULL;
+UPDATE "record_segments" SET "update_time" = strftime('%Y-%m-%dT%H:%M:%S+00:00', "update_time")
+WHERE "update_time" NOT LIKE '%+00:00' AND strftime('%s', "update_time") IS NOT NULL;
+UPDATE "transport_targets" SET "create_time" = strftime('%Y-%m-%dT%H:%M:%S+00:00', "create_time")
+WHERE "create_time" NOT LIKE '%+00:00' AND strftime('%s', "create_time") IS NOT NULL;
+UPDATE "transport_targets" SET "update_time" = strftime('%Y-%m-%dT%H:%M:%S+00:00', "update_time")
+WHERE "update_time" NOT LIKE '%+00:00' AND strftime('%s', "update_time") IS NOT NULL;
+UPDATE "transport_jobs" SET "create_time" = strftime('%Y-%m-%dT%H:%M:%S+00:00', "create_time")
+WHERE "create_time" NOT LIKE '%+00:00' AND strftime('%s', "create_time") IS NOT NULL;
+UPDATE "transport_jobs" SET "update_time" = strftime('%Y-%m-%dT%H:%M:%S+00:00', "update_time")
+WHERE "update_time" NOT LIKE '%+00:00' AND strftime('%s', "update_time") IS NOT NULL;
@@ -0,0 +1,21 @@
+-- Long-running operations (clip export, ...) queued for the job runner.
+-- `payload` and `result` are kind-specific JSON (`result` empty until done);
+-- `progress` is a percentage. Timestamps are unix milliseconds (UTC), 0 while
+-- unset. `cancel_requested` is the flag a running job checks to stop early.
+CREATE TABLE IF NOT EXISTS "jobs" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "kind" TEXT NOT NULL,
+    "payload" TEXT NOT NULL DEFAULT '{}',
+    "status" TEXT NOT NULL DEFAULT 'queued',
+    "progress" INTEGER NOT NULL DEFAULT 0,
+    "result" TEXT NOT NULL DEFAULT '',
+    "error" TEXT NOT NULL DEFAULT '',
+    "cancel_requested" INTEGER NOT NULL DEFAULT 0,
+    "created_by" TEXT NOT NULL DEFAULT '',
+    "created_at" INTEGER NOT NULL,
+    "started_at" INTEGER NOT NULL DEFAULT 0,
+    "finished_at" INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS "jobs_status_created_idx" ON "jobs" ("status", "created_at");
+CREATE INDEX IF NOT EXISTS "jobs_kind_created_idx" ON "jobs" ("kind", "created_at");
@@ -0,0 +1,25 @@
+-- Data each device moved, per hour: `ingress_bytes` read from the camera,
+-- `egress_bytes` served from it (live viewers, uploads). `hour` is the unix
+-- milliseconds (UTC) the hour starts at; `rolled` is set once the hour has
+-- been added to its day and month in `usage_rollup`.
+CREATE TABLE IF NOT EXISTS "usage" (
+    "device_id" TEXT NOT NULL,
+    "hour" INTEGER NOT NULL,
+    "ingress_bytes" INTEGER NOT NULL DEFAULT 0,
+    "egress_bytes" INTEGER NOT NULL DEFAULT 0,
+    "rolled" INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY ("device_id", "hour")
+);
+
+CREATE INDEX IF NOT EXISTS "usage_rolled_hour_idx" ON "usage" ("rolled", "hour");
+
+-- The hours of `usage` summed per day and month (`granularity` "day" or
+-- "month"); `period_start` is unix milliseconds (UTC) of local midnight.
+CREATE TABLE IF NOT EXISTS "usage_rollup" (
+    "device_id" TEXT NOT NULL,
+    "granularity" TEXT NOT NULL,
+    "period_start" INTEGER NOT NULL,
+    "ingress_bytes" INTEGER NOT NULL DEFAULT 0,
+    "egress_bytes" INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY ("device_id", "granularity", "period_start")
+);
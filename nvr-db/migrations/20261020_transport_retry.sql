@@ -0,0 +1,4 @@
+-- When a failed transport job may be retried: the worker backs off
+-- exponentially between attempts instead of retrying every pass. Empty means
+-- due now.
+ALTER TABLE "transport_jobs" ADD COLUMN "next_attempt_time" TEXT NOT NULL DEFAULT '';
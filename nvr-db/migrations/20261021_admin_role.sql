@@ -0,0 +1,6 @@
+-- Admins are users with the admin role, whatever their name. Installs from
+-- before first-run setup were seeded with an "admin" account and no role; it
+-- gets the admin role unless it already has another one.
+UPDATE "kvs" SET "value" = json_set("value", '$.metadata.role', 'admin')
+WHERE "module" = 'user' AND "key" = 'admin'
+  AND coalesce(json_extract("value", '$.metadata.role'), '') = '';
@@ -0,0 +1,128 @@
+//! Audit trail of mutating API calls (`audit` table). Rows are written by the
+//! `nvr` audit middleware; redaction and retention policy live there.
+
+use serde::{Deserialize, Serialize};
+use turso::Connection;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Assigned by the DB on insert; ignored by [`insert`].
+    pub id: i64,
+    /// Unix milliseconds (UTC).
+    pub ts: i64,
+    pub username: String,
+    pub method: String,
+    pub route: String,
+    pub resource_id: String,
+    pub summary: String,
+    pub status: i64,
+}
+
+/// Filter for [`list`] / [`count`]. Empty strings and `None` match everything;
+/// `from`/`to` are inclusive unix milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub username: Option<String>,
+    pub resource_id: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+const COLS: &str = "id, ts, username, method, route, resource_id, summary, status";
+
+fn sql_text(value: &str) -> String {
+    value.replace('\'', "''")
+}
+
+fn from_row(row: &turso::Row) -> anyhow::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: row.get::<i64>(0)?,
+        ts: row.get::<i64>(1)?,
+        username: row.get::<String>(2)?,
+        method: row.get::<String>(3)?,
+        route: row.get::<String>(4)?,
+        resource_id: row.get::<String>(5)?,
+        summary: row.get::<String>(6)?,
+        status: row.get::<i64>(7)?,
+    })
+}
+
+fn where_clause(filter: &AuditFilter) -> String {
+    let mut conds = Vec::new();
+    if let Some(username) = filter.username.as_deref().filter(|s| !s.is_empty()) {
+        conds.push(format!("username = '{}'", sql_text(username)));
+    }
+    if let Some(resource_id) = filter.resource_id.as_deref().filter(|s| !s.is_empty()) {
+        conds.push(format!("resource_id = '{}'", sql_text(resource_id)));
+    }
+    if let Some(from) = filter.from {
+        conds.push(format!("ts >= {from}"));
+    }
+    if let Some(to) = filter.to {
+        conds.push(format!("ts <= {to}"));
+    }
+    if conds.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conds.join(" AND "))
+    }
+}
+
+pub async fn insert(entry: &AuditEntry, conn: &Connection) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO audit (ts, username, method, route, resource_id, summary, status) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        (
+            entry.ts,
+            entry.username.as_str(),
+            entry.method.as_str(),
+            entry.route.as_str(),
+            entry.resource_id.as_str(),
+            entry.summary.as_str(),
+            entry.status,
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Matching entries, newest first.
+pub async fn list(
+    filter: &AuditFilter,
+    page: usize,
+    page_size: usize,
+    conn: &Connection,
+) -> anyhow::Result<Vec<AuditEntry>> {
+    let offset = page.saturating_sub(1) * page_size;
+    let sql = format!(
+        "SELECT {COLS} FROM audit {} ORDER BY ts DESC, id DESC LIMIT {page_size} OFFSET {offset}",
+        where_clause(filter)
+    );
+    let mut rows = conn.query(&sql, ()).await?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(from_row(&row)?);
+    }
+    Ok(out)
+}
+
+pub async fn count(filter: &AuditFilter, conn: &Connection) -> anyhow::Result<usize> {
+    let sql = format!("SELECT COUNT(*) FROM audit {}", where_clause(filter));
+    let mut rows = conn.query(&sql, ()).await?;
+    match rows.next().await? {
+        Some(row) => Ok(row.get::<i64>(0)? as usize),
+        None => Ok(0),
+    }
+}
+
+/// Delete entries recorded before `ts` (unix milliseconds). Returns how many
+/// were removed.
+pub async fn delete_older_than(ts: i64, conn: &Connection) -> anyhow::Result<u64> {
+    Ok(conn
+        .execute("DELETE FROM audit WHERE ts < ?1", (ts,))
+        .await?)
+}
+
+#[cfg(test)]
+#[path = "audit_test.rs"]
+mod audit_test;
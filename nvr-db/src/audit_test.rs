@@ -0,0 +1,74 @@
+use turso::Connection;
+
+use crate::audit::{self, AuditEntry, AuditFilter};
+use crate::db::{DatabaseConfig, NvrDatabase};
+
+async fn test_conn() -> Connection {
+    let db = NvrDatabase::new(&DatabaseConfig::new(":memory:"))
+        .await
+        .unwrap();
+    let mut conn = db.connect().unwrap();
+    crate::migrations::migrate_conn(&mut conn).await.unwrap();
+    conn
+}
+
+fn entry(ts: i64, username: &str, resource_id: &str) -> AuditEntry {
+    AuditEntry {
+        ts,
+        username: username.to_string(),
+        method: "POST".to_string(),
+        route: format!("/device/update/{resource_id}"),
+        resource_id: resource_id.to_string(),
+        status: 200,
+        ..AuditEntry::default()
+    }
+}
+
+#[tokio::test]
+async fn list_filters_and_pages_newest_first() {
+    let conn = test_conn().await;
+    for (ts, user, res) in [
+        (1, "alice", "cam1"),
+        (2, "bob", "cam1"),
+        (3, "alice", "cam2"),
+    ] {
+        audit::insert(&entry(ts, user, res), &conn).await.unwrap();
+    }
+
+    let alice = AuditFilter {
+        username: Some("alice".to_string()),
+        ..AuditFilter::default()
+    };
+    let rows = audit::list(&alice, 1, 10, &conn).await.unwrap();
+    assert_eq!(rows.iter().map(|r| r.ts).collect::<Vec<_>>(), vec![3, 1]);
+    assert_eq!(audit::count(&alice, &conn).await.unwrap(), 2);
+
+    let cam1_from_2 = AuditFilter {
+        resource_id: Some("cam1".to_string()),
+        from: Some(2),
+        ..AuditFilter::default()
+    };
+    let rows = audit::list(&cam1_from_2, 1, 10, &conn).await.unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].username, "bob");
+
+    let page2 = audit::list(&AuditFilter::default(), 2, 2, &conn)
+        .await
+        .unwrap();
+    assert_eq!(page2.iter().map(|r| r.ts).collect::<Vec<_>>(), vec![1]);
+}
+
+#[tokio::test]
+async fn delete_older_than_prunes_only_old_rows() {
+    let conn = test_conn().await;
+    for ts in [10, 20, 30] {
+        audit::insert(&entry(ts, "alice", "cam1"), &conn)
+            .await
+            .unwrap();
+    }
+    assert_eq!(audit::delete_older_than(25, &conn).await.unwrap(), 2);
+    let left = audit::list(&AuditFilter::default(), 1, 10, &conn)
+        .await
+        .unwrap();
+    assert_eq!(left.iter().map(|r| r.ts).collect::<Vec<_>>(), vec![30]);
+}
@@ -0,0 +1,125 @@
+//! Operator bookmarks on a device's timeline (`bookmark` table): a labelled
+//! moment, optionally linked to the clip export started from it.
+
+use serde::{Deserialize, Serialize};
+use turso::Connection;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Bookmark {
+    pub id: String,
+    pub device_id: String,
+    /// The bookmarked moment, unix milliseconds (UTC).
+    pub ts: i64,
+    pub label: String,
+    /// Display color (e.g. `#ff0000`); empty for the default.
+    pub color: String,
+    pub created_by: String,
+    /// Unix milliseconds (UTC).
+    pub created_at: i64,
+    /// Clip export last started from this bookmark; empty if none.
+    pub export_id: String,
+}
+
+const COLS: &str = "id, device_id, ts, label, color, created_by, created_at, export_id";
+
+fn from_row(row: &turso::Row) -> anyhow::Result<Bookmark> {
+    Ok(Bookmark {
+        id: row.get::<String>(0)?,
+        device_id: row.get::<String>(1)?,
+        ts: row.get::<i64>(2)?,
+        label: row.get::<String>(3)?,
+        color: row.get::<String>(4)?,
+        created_by: row.get::<String>(5)?,
+        created_at: row.get::<i64>(6)?,
+        export_id: row.get::<String>(7)?,
+    })
+}
+
+pub async fn insert(bookmark: &Bookmark, conn: &Connection) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO bookmark (id, device_id, ts, label, color, created_by, created_at, export_id) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        (
+            bookmark.id.as_str(),
+            bookmark.device_id.as_str(),
+            bookmark.ts,
+            bookmark.label.as_str(),
+            bookmark.color.as_str(),
+            bookmark.created_by.as_str(),
+            bookmark.created_at,
+            bookmark.export_id.as_str(),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn get(id: &str, conn: &Connection) -> anyhow::Result<Option<Bookmark>> {
+    let mut rows = conn
+        .query(&format!("SELECT {COLS} FROM bookmark WHERE id = ?1"), (id,))
+        .await?;
+    match rows.next().await? {
+        Some(row) => Ok(Some(from_row(&row)?)),
+        None => Ok(None),
+    }
+}
+
+/// Bookmarks of `device_id` with `from <= ts <= to` (unix milliseconds),
+/// oldest first.
+pub async fn list_by_device(
+    device_id: &str,
+    from: i64,
+    to: i64,
+    conn: &Connection,
+) -> anyhow::Result<Vec<Bookmark>> {
+    let mut rows = conn
+        .query(
+            &format!(
+                "SELECT {COLS} FROM bookmark WHERE device_id = ?1 AND ts >= ?2 AND ts <= ?3 \
+                 ORDER BY ts ASC, created_at ASC"
+            ),
+            (device_id, from, to),
+        )
+        .await?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(from_row(&row)?);
+    }
+    Ok(out)
+}
+
+/// Overwrite the editable fields (label, color, export link) of an existing
+/// bookmark. Returns whether it exists.
+pub async fn update(bookmark: &Bookmark, conn: &Connection) -> anyhow::Result<bool> {
+    let changed = conn
+        .execute(
+            "UPDATE bookmark SET label = ?1, color = ?2, export_id = ?3 WHERE id = ?4",
+            (
+                bookmark.label.as_str(),
+                bookmark.color.as_str(),
+                bookmark.export_id.as_str(),
+                bookmark.id.as_str(),
+            ),
+        )
+        .await?;
+    Ok(changed > 0)
+}
+
+/// Returns whether the bookmark existed.
+pub async fn delete(id: &str, conn: &Connection) -> anyhow::Result<bool> {
+    Ok(conn
+        .execute("DELETE FROM bookmark WHERE id = ?1", (id,))
+        .await?
+        > 0)
+}
+
+/// Delete every bookmark of `device_id`. Returns how many were removed.
+pub async fn delete_by_device(device_id: &str, conn: &Connection) -> anyhow::Result<u64> {
+    Ok(conn
+        .execute("DELETE FROM bookmark WHERE device_id = ?1", (device_id,))
+        .await?)
+}
+
+#[cfg(test)]
+#[path = "bookmark_test.rs"]
+mod bookmark_test;
@@ -0,0 +1,89 @@
+use turso::Connection;
+
+use crate::bookmark::{self, Bookmark};
+use crate::db::{DatabaseConfig, NvrDatabase};
+
+async fn test_conn() -> Connection {
+    let db = NvrDatabase::new(&DatabaseConfig::new(":memory:"))
+        .await
+        .unwrap();
+    let mut conn = db.connect().unwrap();
+    crate::migrations::migrate_conn(&mut conn).await.unwrap();
+    conn
+}
+
+fn bookmark(id: &str, device_id: &str, ts: i64) -> Bookmark {
+    Bookmark {
+        id: id.to_string(),
+        device_id: device_id.to_string(),
+        ts,
+        label: format!("mark {id}"),
+        created_by: "alice".to_string(),
+        created_at: 1_000,
+        ..Bookmark::default()
+    }
+}
+
+#[tokio::test]
+async fn crud_round_trip() {
+    let conn = test_conn().await;
+    let mut mark = bookmark("b1", "cam1", 5_000);
+    mark.color = "#ff0000".to_string();
+    bookmark::insert(&mark, &conn).await.unwrap();
+    assert_eq!(
+        bookmark::get("b1", &conn).await.unwrap(),
+        Some(mark.clone())
+    );
+
+    mark.label = "person at gate".to_string();
+    mark.export_id = "export-1".to_string();
+    assert!(bookmark::update(&mark, &conn).await.unwrap());
+    assert_eq!(
+        bookmark::get("b1", &conn).await.unwrap(),
+        Some(mark.clone())
+    );
+    assert!(
+        !bookmark::update(&bookmark("missing", "cam1", 0), &conn)
+            .await
+            .unwrap()
+    );
+
+    assert!(bookmark::delete("b1", &conn).await.unwrap());
+    assert!(!bookmark::delete("b1", &conn).await.unwrap());
+    assert_eq!(bookmark::get("b1", &conn).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn list_range_is_inclusive_and_per_device() {
+    let conn = test_conn().await;
+    for (id, device, ts) in [
+        ("a", "cam1", 100),
+        ("b", "cam1", 200),
+        ("c", "cam1", 300),
+        ("d", "cam2", 200),
+    ] {
+        bookmark::insert(&bookmark(id, device, ts), &conn)
+            .await
+            .unwrap();
+    }
+
+    let ids = |marks: Vec<Bookmark>| marks.into_iter().map(|m| m.id).collect::<Vec<_>>();
+    let all = bookmark::list_by_device("cam1", 100, 300, &conn)
+        .await
+        .unwrap();
+    assert_eq!(ids(all), vec!["a", "b", "c"]);
+    let inner = bookmark::list_by_device("cam1", 101, 299, &conn)
+        .await
+        .unwrap();
+    assert_eq!(ids(inner), vec!["b"]);
+    let at = bookmark::list_by_device("cam1", 300, 300, &conn)
+        .await
+        .unwrap();
+    assert_eq!(ids(at), vec!["c"]);
+
+    assert_eq!(bookmark::delete_by_device("cam1", &conn).await.unwrap(), 3);
+    let rest = bookmark::list_by_device("cam2", 0, i64::MAX, &conn)
+        .await
+        .unwrap();
+    assert_eq!(ids(rest), vec!["d"]);
+}
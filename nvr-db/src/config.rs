@@ -6,7 +6,7 @@ use crate::kv;
 
 /// KV module namespace configuration values live under (`kvs.module`, keyed by
 /// config key; `sub_key` is unused).
-const MODULE: &str = "config";
+pub(crate) const MODULE: &str = "config";
 
 /// Fetch a raw config value by key. `Ok(None)` means the key is unset.
 pub async fn get(key: &str, conn: &Connection) -> anyhow::Result<Option<String>> {
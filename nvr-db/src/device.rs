@@ -1,8 +1,10 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use turso::Connection;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct DeviceInfo {
     pub id: String,
     pub name: String,
@@ -15,6 +17,31 @@ pub struct DeviceInfo {
     /// to true so devices created before this field keep recording.
     #[serde(default = "default_record")]
     pub record: bool,
+    /// ZLM stream name the device publishes under (`device` app), unique across
+    /// devices. Empty for devices stored before keys existed, until startup
+    /// fills it in; such a device publishes under its id.
+    #[serde(default)]
+    pub stream_key: String,
+    /// IANA zone (`Europe/Berlin`) of the device's schedules, segment file
+    /// names and timeline days. Empty uses the server's default zone.
+    #[serde(default)]
+    pub timezone: String,
+    /// Object detection on the device's live stream; `None` runs none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detection: Option<DetectionSettings>,
+    /// The group (site, floor) the device belongs to, for views of several
+    /// devices such as the snapshot wall; empty for none.
+    #[serde(default)]
+    pub group: String,
+    /// Whether its archived segments are encrypted at rest, also when the
+    /// server does not encrypt every device's.
+    #[serde(default)]
+    pub encrypt: bool,
+    /// What its input last reported: [`STATUS_ONLINE`], [`STATUS_OFFLINE`]
+    /// or [`STATUS_ERROR`]; empty until its pipe first reports. Kept up by
+    /// [`update_status`].
+    #[serde(default)]
+    pub status: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -23,6 +50,167 @@ fn default_record() -> bool {
     true
 }
 
+/// The input is open and streaming.
+pub const STATUS_ONLINE: &str = "online";
+/// The input ended, or its pipe is down.
+pub const STATUS_OFFLINE: &str = "offline";
+/// The input could not be opened or reconnected.
+pub const STATUS_ERROR: &str = "error";
+
+/// Which detector watches a device and which of its detections become
+/// events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DetectionSettings {
+    /// `http` (the inference sidecar), `stub` (detects nothing) or `none`.
+    pub detector: String,
+    /// Labels that make events (`person`, `car`); empty for every label.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Lowest confidence of an event's detections, per label.
+    #[serde(default)]
+    pub thresholds: HashMap<String, f32>,
+    /// Lowest confidence for labels without a threshold of their own.
+    #[serde(default = "default_min_confidence")]
+    pub min_confidence: f32,
+    /// Bookmark each event's start on the device's timeline.
+    #[serde(default)]
+    pub bookmark: bool,
+    /// Watch the image for a frozen or black picture; `None` does not.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_air: Option<DeadAirSettings>,
+    /// Watch the image for motion; `None` does not.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<MotionSettings>,
+}
+
+fn default_min_confidence() -> f32 {
+    0.5
+}
+
+/// When a device's picture counts as dead air. A scene that is still on
+/// purpose (a parking lot at night) wants a longer `duration_secs` or a
+/// larger `tolerance`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DeadAirSettings {
+    /// How long the picture must stay frozen or black before it is reported.
+    #[serde(default = "default_dead_air_secs")]
+    pub duration_secs: u64,
+    /// Bits (of 64) a frame's hash may differ by and still be the same
+    /// picture, for compression noise.
+    #[serde(default = "default_dead_air_tolerance")]
+    pub tolerance: u32,
+    /// Mean luma (0–255) below which a frame is black.
+    #[serde(default = "default_black_below")]
+    pub black_below: u8,
+}
+
+fn default_dead_air_secs() -> u64 {
+    60
+}
+
+fn default_dead_air_tolerance() -> u32 {
+    4
+}
+
+fn default_black_below() -> u8 {
+    16
+}
+
+impl Default for DeadAirSettings {
+    fn default() -> Self {
+        Self {
+            duration_secs: default_dead_air_secs(),
+            tolerance: default_dead_air_tolerance(),
+            black_below: default_black_below(),
+        }
+    }
+}
+
+/// When a device's picture counts as moving. Motion starts once a sample
+/// changed more than `enter_ratio` of its pixels and ends once every sample
+/// for `min_gap_secs` changed less than `exit_ratio`, so a pause shorter than
+/// that does not split one event in two.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct MotionSettings {
+    /// Share (0–1) of pixels that must change for motion to start.
+    #[serde(default = "default_motion_enter")]
+    pub enter_ratio: f32,
+    /// Share (0–1) of pixels below which a sample is still; at most
+    /// `enter_ratio`.
+    #[serde(default = "default_motion_exit")]
+    pub exit_ratio: f32,
+    /// Luma steps (0–255) a pixel must change by to count as changed, for
+    /// sensor noise.
+    #[serde(default = "default_motion_pixel_threshold")]
+    pub pixel_threshold: u8,
+    /// How long the picture must stay still before motion ends.
+    #[serde(default = "default_motion_gap_secs")]
+    pub min_gap_secs: u64,
+    /// Record the device's stream while motion lasts.
+    #[serde(default)]
+    pub record: bool,
+}
+
+fn default_motion_enter() -> f32 {
+    0.02
+}
+
+fn default_motion_exit() -> f32 {
+    0.005
+}
+
+fn default_motion_pixel_threshold() -> u8 {
+    25
+}
+
+fn default_motion_gap_secs() -> u64 {
+    10
+}
+
+impl Default for MotionSettings {
+    fn default() -> Self {
+        Self {
+            enter_ratio: default_motion_enter(),
+            exit_ratio: default_motion_exit(),
+            pixel_threshold: default_motion_pixel_threshold(),
+            min_gap_secs: default_motion_gap_secs(),
+            record: false,
+        }
+    }
+}
+
+impl DeviceInfo {
+    /// The ZLM stream name in use: the stream key, or the id without one.
+    pub fn stream_name(&self) -> &str {
+        if self.stream_key.is_empty() {
+            &self.id
+        } else {
+            &self.stream_key
+        }
+    }
+}
+
+/// A device change refused for what is stored rather than failed by the
+/// database; the functions below return it inside their `anyhow::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    /// No device has this id.
+    NotFound(String),
+    /// A device with this id is stored already.
+    Exists(String),
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "device {id} not found"),
+            Self::Exists(id) => write!(f, "device {id} exists already"),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
 pub async fn list(conn: &Connection) -> anyhow::Result<Vec<DeviceInfo>> {
     let kvs = crate::kv::by_module("device", conn).await?;
     let mut devices = kvs
@@ -78,6 +266,34 @@ pub async fn get(id: &str, conn: &Connection) -> anyhow::Result<Option<DeviceInf
     }
 }
 
+/// Device `id`, or [`DeviceError::NotFound`].
+pub async fn require(id: &str, conn: &Connection) -> anyhow::Result<DeviceInfo> {
+    get(id, conn)
+        .await?
+        .ok_or_else(|| DeviceError::NotFound(id.to_string()).into())
+}
+
+/// Store a new device; [`DeviceError::Exists`] if its id is taken. The check
+/// and the insert are one statement, so of two inserts of an id racing each
+/// other one fails.
+pub async fn insert(device: &DeviceInfo, conn: &Connection) -> anyhow::Result<()> {
+    let value = serde_json::to_string(device)?;
+    let inserted = conn
+        .execute(
+            r#"
+            INSERT INTO kvs (module, key, sub_key, value)
+            SELECT ?1, ?2, ?3, ?4
+            WHERE NOT EXISTS (SELECT 1 FROM kvs WHERE module = ?1 AND key = ?2)
+            "#,
+            ("device", device.id.as_str(), "", value.as_str()),
+        )
+        .await?;
+    if inserted == 0 {
+        return Err(DeviceError::Exists(device.id.clone()).into());
+    }
+    Ok(())
+}
+
 pub async fn upsert(device: &DeviceInfo, conn: &Connection) -> anyhow::Result<()> {
     let value = serde_json::to_string(device)?;
     if crate::kv::by_module_and_key("device", &device.id, conn)
@@ -100,11 +316,31 @@ pub async fn upsert(device: &DeviceInfo, conn: &Connection) -> anyhow::Result<()
     Ok(())
 }
 
-pub async fn delete(id: &str, conn: &Connection) -> anyhow::Result<()> {
+/// Set device `id`'s `status`, leaving the rest of it as stored: an edit
+/// saved meanwhile is not overwritten. No-op for an unknown id.
+pub async fn update_status(id: &str, status: &str, conn: &Connection) -> anyhow::Result<()> {
     conn.execute(
-        "DELETE FROM kvs WHERE module = ?1 AND key = ?2",
-        ("device", id),
+        "UPDATE kvs SET value = json_set(value, '$.status', ?1) WHERE module = ?2 AND key = ?3",
+        (status, "device", id),
     )
     .await?;
     Ok(())
 }
+
+/// Delete device `id`; [`DeviceError::NotFound`] if there is none.
+pub async fn delete(id: &str, conn: &Connection) -> anyhow::Result<()> {
+    let deleted = conn
+        .execute(
+            "DELETE FROM kvs WHERE module = ?1 AND key = ?2",
+            ("device", id),
+        )
+        .await?;
+    if deleted == 0 {
+        return Err(DeviceError::NotFound(id.to_string()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "device_test.rs"]
+mod device_test;
@@ -0,0 +1,105 @@
+use chrono::Utc;
+use turso::Connection;
+
+use crate::db::{DatabaseConfig, NvrDatabase};
+use crate::device::{self, DeviceInfo};
+
+async fn test_conn() -> Connection {
+    let db = NvrDatabase::new(&DatabaseConfig::new(":memory:"))
+        .await
+        .unwrap();
+    let conn = db.connect().unwrap();
+    conn.execute_batch(
+        r#"CREATE TABLE kvs (
+            id INTEGER NOT NULL,
+            module VARCHAR NOT NULL,
+            key VARCHAR NOT NULL,
+            sub_key VARCHAR NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY(id AUTOINCREMENT)
+        );"#,
+    )
+    .await
+    .unwrap();
+    conn
+}
+
+fn camera(id: &str) -> DeviceInfo {
+    DeviceInfo {
+        id: id.to_string(),
+        name: "Front door".to_string(),
+        input_type: "rtsp".to_string(),
+        input_value: "rtsp://cam".to_string(),
+        description: String::new(),
+        include_audio: false,
+        record: true,
+        stream_key: String::new(),
+        timezone: String::new(),
+        detection: None,
+        group: String::new(),
+        encrypt: false,
+        status: String::new(),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+#[tokio::test]
+async fn update_status_keeps_the_rest_of_the_device() {
+    let conn = test_conn().await;
+    device::upsert(&camera("cam1"), &conn).await.unwrap();
+    device::upsert(&camera("cam2"), &conn).await.unwrap();
+
+    device::update_status("cam1", device::STATUS_ONLINE, &conn)
+        .await
+        .unwrap();
+    let cam1 = device::get("cam1", &conn).await.unwrap().unwrap();
+    assert_eq!(cam1.status, device::STATUS_ONLINE);
+    assert_eq!(cam1.name, "Front door");
+    assert_eq!(cam1.input_value, "rtsp://cam");
+    let cam2 = device::get("cam2", &conn).await.unwrap().unwrap();
+    assert_eq!(cam2.status, "");
+
+    device::update_status("cam1", device::STATUS_ERROR, &conn)
+        .await
+        .unwrap();
+    let cam1 = device::get("cam1", &conn).await.unwrap().unwrap();
+    assert_eq!(cam1.status, device::STATUS_ERROR);
+    // An unknown device is left alone.
+    device::update_status("gone", device::STATUS_OFFLINE, &conn)
+        .await
+        .unwrap();
+    assert!(device::get("gone", &conn).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn refusals_are_typed_device_errors() {
+    let conn = test_conn().await;
+    device::insert(&camera("cam1"), &conn).await.unwrap();
+
+    let taken = device::insert(&camera("cam1"), &conn).await.unwrap_err();
+    assert_eq!(
+        taken.downcast_ref::<device::DeviceError>(),
+        Some(&device::DeviceError::Exists("cam1".to_string()))
+    );
+    assert_eq!(device::require("cam1", &conn).await.unwrap().id, "cam1");
+
+    // Of two inserts racing for an id, one stores it.
+    let (first, second) = tokio::join!(
+        device::insert(&camera("cam2"), &conn),
+        device::insert(&camera("cam2"), &conn)
+    );
+    assert_eq!(u8::from(first.is_ok()) + u8::from(second.is_ok()), 1);
+    assert_eq!(device::list(&conn).await.unwrap().len(), 2);
+
+    device::delete("cam1", &conn).await.unwrap();
+    for missing in [
+        device::require("cam1", &conn).await.map(|_| ()),
+        device::delete("cam1", &conn).await,
+    ] {
+        assert_eq!(
+            missing.unwrap_err().downcast_ref::<device::DeviceError>(),
+            Some(&device::DeviceError::NotFound("cam1".to_string()))
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! Queued long-running operations (`jobs` table). Rows are written by the
+//! `nvr` job runner: it claims queued jobs, records their progress and
+//! outcome, and on boot decides what becomes of the jobs a crash left
+//! running.
+
+use serde::{Deserialize, Serialize};
+use turso::Connection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "queued" => JobStatus::Queued,
+            "running" => JobStatus::Running,
+            "done" => JobStatus::Done,
+            "failed" => JobStatus::Failed,
+            "cancelled" => JobStatus::Cancelled,
+            _ => return None,
+        })
+    }
+
+    /// Whether the job is over, one way or another.
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Job {
+    pub id: String,
+    /// What the job does, e.g. `export`; picks its handler.
+    pub kind: String,
+    /// The kind's input.
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    pub status: JobStatus,
+    /// Percent done, 0 to 100.
+    pub progress: u8,
+    /// The kind's output once done.
+    #[schema(value_type = Option<Object>)]
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    /// Set by a cancel of a running job; the job stops at its next check.
+    pub cancel_requested: bool,
+    pub created_by: String,
+    /// Unix milliseconds (UTC).
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+impl Job {
+    /// A queued job of `kind`, created now.
+    pub fn new(kind: &str, payload: serde_json::Value, created_by: &str, now: i64) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind: kind.to_string(),
+            payload,
+            status: JobStatus::Queued,
+            progress: 0,
+            result: None,
+            error: None,
+            cancel_requested: false,
+            created_by: created_by.to_string(),
+            created_at: now,
+            started_at: None,
+            finished_at: None,
+        }
+    }
+}
+
+/// Filter for [`list`]; `None` matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    pub kind: Option<String>,
+    pub status: Option<JobStatus>,
+}
+
+const COLS: &str = "id, kind, payload, status, progress, result, error, cancel_requested, \
+                    created_by, created_at, started_at, finished_at";
+
+fn sql_text(value: &str) -> String {
+    value.replace('\'', "''")
+}
+
+/// Timestamps are stored as 0 while unset.
+fn optional_ts(ts: i64) -> Option<i64> {
+    (ts != 0).then_some(ts)
+}
+
+fn from_row(row: &turso::Row) -> anyhow::Result<Job> {
+    let status = row.get::<String>(3)?;
+    let result = row.get::<String>(5)?;
+    let error = row.get::<String>(6)?;
+    Ok(Job {
+        id: row.get::<String>(0)?,
+        kind: row.get::<String>(1)?,
+        payload: serde_json::from_str(&row.get::<String>(2)?)?,
+        status: JobStatus::parse(&status)
+            .ok_or_else(|| anyhow::anyhow!("unknown job status {status}"))?,
+        progress: row.get::<i64>(4)?.clamp(0, 100) as u8,
+        result: if result.is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(&result)?)
+        },
+        error: (!error.is_empty()).then_some(error),
+        cancel_requested: row.get::<i64>(7)? != 0,
+        created_by: row.get::<String>(8)?,
+        created_at: row.get::<i64>(9)?,
+        started_at: optional_ts(row.get::<i64>(10)?),
+        finished_at: optional_ts(row.get::<i64>(11)?),
+    })
+}
+
+pub async fn insert(job: &Job, conn: &Connection) -> anyhow::Result<()> {
+    let payload = serde_json::to_string(&job.payload)?;
+    let result = match &job.result {
+        Some(result) => serde_json::to_string(result)?,
+        None => String::new(),
+    };
+    conn.execute(
+        "INSERT INTO jobs (id, kind, payload, status, progress, result, error, cancel_requested, \
+         created_by, created_at, started_at, finished_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        (
+            job.id.as_str(),
+            job.kind.as_str(),
+            payload.as_str(),
+            job.status.as_str(),
+            job.progress as i64,
+            result.as_str(),
+            job.error.as_deref().unwrap_or(""),
+            job.cancel_requested as i64,
+            job.created_by.as_str(),
+            job.created_at,
+            job.started_at.unwrap_or(0),
+            job.finished_at.unwrap_or(0),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn get(id: &str, conn: &Connection) -> anyhow::Result<Option<Job>> {
+    let mut rows = conn
+        .query(&format!("SELECT {COLS} FROM jobs WHERE id = ?1"), (id,))
+        .await?;
+    match rows.next().await? {
+        Some(row) => Ok(Some(from_row(&row)?)),
+        None => Ok(None),
+    }
+}
+
+/// Matching jobs, newest first, at most `limit`.
+pub async fn list(filter: &JobFilter, limit: usize, conn: &Connection) -> anyhow::Result<Vec<Job>> {
+    let mut conds = Vec::new();
+    if let Some(kind) = filter.kind.as_deref() {
+        conds.push(format!("kind = '{}'", sql_text(kind)));
+    }
+    if let Some(status) = filter.status {
+        conds.push(format!("status = '{}'", status.as_str()));
+    }
+    let where_clause = if conds.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conds.join(" AND "))
+    };
+    let sql = format!(
+        "SELECT {COLS} FROM jobs {where_clause} ORDER BY created_at DESC, id DESC LIMIT {limit}"
+    );
+    let mut rows = conn.query(&sql, ()).await?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(from_row(&row)?);
+    }
+    Ok(out)
+}
+
+/// Take the oldest queued job of one of `kinds`: mark it running, started at
+/// `now`, and return it. `None` when nothing is queued. The check and the
+/// update are one transaction, so a job is claimed at most once.
+pub async fn claim(kinds: &[&str], now: i64, conn: &mut Connection) -> anyhow::Result<Option<Job>> {
+    if kinds.is_empty() {
+        return Ok(None);
+    }
+    let kinds = kinds
+        .iter()
+        .map(|kind| format!("'{}'", sql_text(kind)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let tx = conn.transaction().await?;
+    let mut rows = tx
+        .query(
+            &format!(
+                "SELECT {COLS} FROM jobs WHERE status = 'queued' AND kind IN ({kinds}) \
+                 ORDER BY created_at ASC, id ASC LIMIT 1"
+            ),
+            (),
+        )
+        .await?;
+    let Some(row) = rows.next().await? else {
+        drop(rows);
+        tx.commit().await?;
+        return Ok(None);
+    };
+    let mut job = from_row(&row)?;
+    drop(rows);
+    let claimed = tx
+        .execute(
+            "UPDATE jobs SET status = 'running', started_at = ?1 \
+             WHERE id = ?2 AND status = 'queued'",
+            (now, job.id.as_str()),
+        )
+        .await?;
+    tx.commit().await?;
+    if claimed == 0 {
+        return Ok(None);
+    }
+    job.status = JobStatus::Running;
+    job.started_at = Some(now);
+    Ok(Some(job))
+}
+
+/// Record the progress (percent) of a running job.
+pub async fn set_progress(id: &str, progress: u8, conn: &Connection) -> anyhow::Result<()> {
+    conn.execute(
+        "UPDATE jobs SET progress = ?1 WHERE id = ?2 AND status = 'running'",
+        (progress.min(100) as i64, id),
+    )
+    .await?;
+    Ok(())
+}
+
+/// End a running job as `status` (done, failed or cancelled), at `now`.
+/// Returns whether it was running.
+pub async fn finish(
+    id: &str,
+    status: JobStatus,
+    result: Option<&serde_json::Value>,
+    error: Option<&str>,
+    now: i64,
+    conn: &Connection,
+) -> anyhow::Result<bool> {
+    let result = match result {
+        Some(result) => serde_json::to_string(result)?,
+        None => String::new(),
+    };
+    let progress = if status == JobStatus::Done {
+        ", progress = 100"
+    } else {
+        ""
+    };
+    let changed = conn
+        .execute(
+            &format!(
+                "UPDATE jobs SET status = ?1, result = ?2, error = ?3, finished_at = ?4{progress} \
+                 WHERE id = ?5 AND status = 'running'"
+            ),
+            (
+                status.as_str(),
+                result.as_str(),
+                error.unwrap_or(""),
+                now,
+                id,
+            ),
+        )
+        .await?;
+    Ok(changed > 0)
+}
+
+/// Cancel a job: a queued one is cancelled at once, a running one gets its
+/// cancel flag. Returns the job afterwards, `None` if there is none.
+pub async fn cancel(id: &str, now: i64, conn: &mut Connection) -> anyhow::Result<Option<Job>> {
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "UPDATE jobs SET status = 'cancelled', finished_at = ?1 WHERE id = ?2 AND status = 'queued'",
+        (now, id),
+    )
+    .await?;
+    tx.execute(
+        "UPDATE jobs SET cancel_requested = 1 WHERE id = ?1 AND status = 'running'",
+        (id,),
+    )
+    .await?;
+    tx.commit().await?;
+    get(id, conn).await
+}
+
+/// Put a job back in the queue, as if it had never started.
+pub async fn requeue(id: &str, conn: &Connection) -> anyhow::Result<()> {
+    conn.execute(
+        "UPDATE jobs SET status = 'queued', progress = 0, started_at = 0 WHERE id = ?1",
+        (id,),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "job_test.rs"]
+mod job_test;
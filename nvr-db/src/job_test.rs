@@ -0,0 +1,196 @@
+use serde_json::json;
+use turso::Connection;
+
+use crate::db::{DatabaseConfig, NvrDatabase};
+use crate::job::{self, Job, JobFilter, JobStatus};
+
+async fn test_conn() -> Connection {
+    let db = NvrDatabase::new(&DatabaseConfig::new(":memory:"))
+        .await
+        .unwrap();
+    let mut conn = db.connect().unwrap();
+    crate::migrations::migrate_conn(&mut conn).await.unwrap();
+    conn
+}
+
+async fn queued(kind: &str, created_at: i64, conn: &Connection) -> Job {
+    let job = Job::new(kind, json!({ "n": created_at }), "alice", created_at);
+    job::insert(&job, conn).await.unwrap();
+    job
+}
+
+#[tokio::test]
+async fn claims_oldest_first_and_once() {
+    let mut conn = test_conn().await;
+    let second = queued("export", 2_000, &conn).await;
+    let first = queued("export", 1_000, &conn).await;
+    // Older, but of a kind this claimer does not run.
+    let other = queued("timelapse", 500, &conn).await;
+
+    let claimed = job::claim(&["export"], 5_000, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claimed.id, first.id);
+    assert_eq!(claimed.status, JobStatus::Running);
+    assert_eq!(claimed.started_at, Some(5_000));
+    assert_eq!(claimed.payload, json!({ "n": 1_000 }));
+
+    let next = job::claim(&["export"], 5_001, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(next.id, second.id);
+    assert!(
+        job::claim(&["export"], 5_002, &mut conn)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let stored = job::get(&first.id, &conn).await.unwrap().unwrap();
+    assert_eq!(stored.status, JobStatus::Running);
+    let other = job::get(&other.id, &conn).await.unwrap().unwrap();
+    assert_eq!(other.status, JobStatus::Queued);
+}
+
+#[tokio::test]
+async fn progress_and_finish_apply_to_running_jobs_only() {
+    let mut conn = test_conn().await;
+    let job = queued("export", 1_000, &conn).await;
+
+    job::set_progress(&job.id, 50, &conn).await.unwrap();
+    assert!(
+        !job::finish(&job.id, JobStatus::Done, None, None, 2_000, &conn)
+            .await
+            .unwrap()
+    );
+    assert_eq!(job::get(&job.id, &conn).await.unwrap().unwrap().progress, 0);
+
+    job::claim(&["export"], 2_000, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    job::set_progress(&job.id, 40, &conn).await.unwrap();
+    assert_eq!(
+        job::get(&job.id, &conn).await.unwrap().unwrap().progress,
+        40
+    );
+
+    let result = json!({ "file_size": 1234 });
+    assert!(
+        job::finish(&job.id, JobStatus::Done, Some(&result), None, 3_000, &conn)
+            .await
+            .unwrap()
+    );
+    let done = job::get(&job.id, &conn).await.unwrap().unwrap();
+    assert_eq!(done.status, JobStatus::Done);
+    assert_eq!(done.progress, 100);
+    assert_eq!(done.result, Some(result));
+    assert_eq!(done.error, None);
+    assert_eq!(done.finished_at, Some(3_000));
+
+    // Finished is final.
+    assert!(
+        !job::finish(&job.id, JobStatus::Failed, None, Some("late"), 4_000, &conn)
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn cancel_stops_queued_and_flags_running() {
+    let mut conn = test_conn().await;
+    let running = queued("export", 1_000, &conn).await;
+    job::claim(&["export"], 1_500, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    let waiting = queued("export", 2_000, &conn).await;
+
+    let cancelled = job::cancel(&waiting.id, 3_000, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(cancelled.status, JobStatus::Cancelled);
+    assert_eq!(cancelled.finished_at, Some(3_000));
+    // A cancelled job is never claimed.
+    assert!(
+        job::claim(&["export"], 3_500, &mut conn)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let flagged = job::cancel(&running.id, 4_000, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(flagged.status, JobStatus::Running);
+    assert!(flagged.cancel_requested);
+
+    assert!(
+        job::cancel("missing", 5_000, &mut conn)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn list_filters_newest_first_and_requeue_resets() {
+    let mut conn = test_conn().await;
+    let old = queued("export", 1_000, &conn).await;
+    queued("timelapse", 2_000, &conn).await;
+    let new = queued("export", 3_000, &conn).await;
+
+    let exports = job::list(
+        &JobFilter {
+            kind: Some("export".to_string()),
+            ..JobFilter::default()
+        },
+        10,
+        &conn,
+    )
+    .await
+    .unwrap();
+    let ids: Vec<_> = exports.iter().map(|j| j.id.as_str()).collect();
+    assert_eq!(ids, [new.id.as_str(), old.id.as_str()]);
+    assert_eq!(
+        job::list(&JobFilter::default(), 2, &conn)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let claimed = job::claim(&["export"], 4_000, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claimed.id, old.id);
+    let running = job::list(
+        &JobFilter {
+            status: Some(JobStatus::Running),
+            ..JobFilter::default()
+        },
+        10,
+        &conn,
+    )
+    .await
+    .unwrap();
+    assert_eq!(running.len(), 1);
+
+    job::requeue(&old.id, &conn).await.unwrap();
+    let requeued = job::get(&old.id, &conn).await.unwrap().unwrap();
+    assert_eq!(requeued.status, JobStatus::Queued);
+    assert_eq!(requeued.started_at, None);
+    assert_eq!(
+        job::claim(&["export"], 5_000, &mut conn)
+            .await
+            .unwrap()
+            .unwrap()
+            .id,
+        old.id
+    );
+}
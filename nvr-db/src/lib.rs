@@ -1,10 +1,15 @@
+pub mod audit;
+pub mod bookmark;
 pub mod config;
 pub mod db;
 pub mod device;
+pub mod job;
 pub mod kv;
 pub mod migrations;
 pub mod record_segment;
 pub mod session;
+pub mod setup;
 pub mod transport_job;
 pub mod transport_target;
+pub mod usage;
 pub mod user;
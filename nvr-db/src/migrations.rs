@@ -19,12 +19,18 @@ pub async fn migrate(url: &str) -> anyhow::Result<()> {
     let db = NvrDatabase::new(&config).await?;
 
     let mut conn = db.connect()?;
-    ensure_migrations_table(&conn).await?;
+    migrate_conn(&mut conn).await
+}
+
+/// Apply pending migrations on an already-open connection (e.g. an in-memory
+/// database shared with the caller).
+pub async fn migrate_conn(conn: &mut turso::Connection) -> anyhow::Result<()> {
+    ensure_migrations_table(conn).await?;
     let mut migrations = load_migrations()?;
     migrations.sort_by_key(|m| m.version);
 
     for migration in migrations {
-        if is_migration_applied(&conn, migration.version).await? {
+        if is_migration_applied(conn, migration.version).await? {
             continue;
         }
         let tx = conn.transaction().await?;
@@ -40,27 +46,6 @@ pub async fn migrate(url: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn ensure_default_admin_user(url: &str) -> anyhow::Result<()> {
-    let config = DatabaseConfig::new(url);
-    let db = NvrDatabase::new(&config).await?;
-    let conn = db.connect()?;
-    if crate::user::exists("admin", &conn).await? {
-        return Ok(());
-    }
-
-    let now = chrono::Utc::now();
-    let user = crate::user::UserInfo {
-        username: "admin".to_string(),
-        password_hash: crate::user::hash_password("admin")?,
-        metadata: std::collections::HashMap::new(),
-        create_time: now,
-        update_time: now,
-    };
-    crate::user::insert(&user, &conn).await?;
-
-    Ok(())
-}
-
 async fn ensure_migrations_table(conn: &turso::Connection) -> anyhow::Result<()> {
     conn.execute_batch(MIGRATIONS_TABLE_SQL).await?;
     Ok(())
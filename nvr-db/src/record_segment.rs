@@ -2,7 +2,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use turso::Connection;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct RecordSegment {
     pub id: String,
     pub record_type: i32,
@@ -31,8 +31,16 @@ pub struct RecordSegment {
     pub reserve_int2: i64,
     pub create_time: DateTime<Utc>,
     pub update_time: DateTime<Utc>,
+    /// Empty for a normal segment, [`STATUS_MISSING`] once its file is gone.
+    #[serde(default)]
+    pub status: String,
 }
 
+/// `status` of a segment whose file no longer exists. Such rows are kept for
+/// audit but left out of every listing, count and size below except
+/// [`get`], [`list_all`] and [`list_older_than_days`].
+pub const STATUS_MISSING: &str = "missing";
+
 pub async fn upsert(record: &RecordSegment, conn: &Connection) -> anyhow::Result<()> {
     let create_time = record.create_time.to_rfc3339();
     let update_time = record.update_time.to_rfc3339();
@@ -42,12 +50,12 @@ pub async fn upsert(record: &RecordSegment, conn: &Connection) -> anyhow::Result
             id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
             video_codec, video_width, video_height, video_fps, video_bit_rate,
             audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
-            reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time
+            reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
         ) VALUES (
             '{id}', {record_type}, {start_time}, {duration}, {file_size}, '{file_name}', '{file_path}', '{folder}', '{app}', '{stream}', '{vhost}',
             '{video_codec}', {video_width}, {video_height}, {video_fps}, {video_bit_rate},
             '{audio_codec}', {audio_sample_rate}, {audio_channels}, {audio_bit_rate},
-            '{reserve_text1}', '{reserve_text2}', '{reserve_text3}', {reserve_int1}, {reserve_int2}, '{create_time}', '{update_time}'
+            '{reserve_text1}', '{reserve_text2}', '{reserve_text3}', {reserve_int1}, {reserve_int2}, '{create_time}', '{update_time}', '{status}'
         )
         ON CONFLICT(file_path) DO UPDATE SET
             record_type=excluded.record_type,
@@ -73,7 +81,8 @@ pub async fn upsert(record: &RecordSegment, conn: &Connection) -> anyhow::Result
             reserve_text3=excluded.reserve_text3,
             reserve_int1=excluded.reserve_int1,
             reserve_int2=excluded.reserve_int2,
-            update_time=excluded.update_time
+            update_time=excluded.update_time,
+            status=excluded.status
         "#,
         id = sql_text(&record.id),
         record_type = record.record_type,
@@ -102,6 +111,7 @@ pub async fn upsert(record: &RecordSegment, conn: &Connection) -> anyhow::Result
         reserve_int2 = record.reserve_int2,
         create_time = sql_text(&create_time),
         update_time = sql_text(&update_time),
+        status = sql_text(&record.status),
     );
     conn.execute_batch(sql).await?;
     Ok(())
@@ -115,8 +125,9 @@ pub async fn list(conn: &Connection) -> anyhow::Result<Vec<RecordSegment>> {
                 id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
                 video_codec, video_width, video_height, video_fps, video_bit_rate,
                 audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
-                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time
+                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
             FROM record_segments
+            WHERE status != 'missing'
             ORDER BY start_time DESC, update_time DESC
             "#,
             (),
@@ -137,9 +148,9 @@ pub async fn list_by_stream(stream: &str, conn: &Connection) -> anyhow::Result<V
                 id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
                 video_codec, video_width, video_height, video_fps, video_bit_rate,
                 audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
-                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time
+                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
             FROM record_segments
-            WHERE stream = ?1
+            WHERE stream = ?1 AND status != 'missing'
             ORDER BY start_time DESC, update_time DESC
             "#,
             [stream],
@@ -166,9 +177,9 @@ pub async fn list_by_stream_page(
                 id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
                 video_codec, video_width, video_height, video_fps, video_bit_rate,
                 audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
-                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time
+                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
             FROM record_segments
-            WHERE stream = ?1
+            WHERE stream = ?1 AND status != 'missing'
             ORDER BY start_time DESC, update_time DESC
             LIMIT ?2 OFFSET ?3
             "#,
@@ -195,9 +206,9 @@ pub async fn list_by_stream_time_range(
                 id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
                 video_codec, video_width, video_height, video_fps, video_bit_rate,
                 audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
-                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time
+                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
             FROM record_segments
-            WHERE stream = ?1 AND start_time >= ?2 AND start_time < ?3
+            WHERE stream = ?1 AND status != 'missing' AND start_time >= ?2 AND start_time < ?3
             ORDER BY start_time ASC, update_time ASC
             "#,
             (stream, start_time as i64, end_time as i64),
@@ -213,7 +224,10 @@ pub async fn list_by_stream_time_range(
 /// Total number of record segments across all streams.
 pub async fn count(conn: &Connection) -> anyhow::Result<usize> {
     let mut rows = conn
-        .query("SELECT COUNT(*) FROM record_segments", ())
+        .query(
+            "SELECT COUNT(*) FROM record_segments WHERE status != 'missing'",
+            (),
+        )
         .await?;
     let Some(row) = rows.next().await? else {
         return Ok(0);
@@ -225,7 +239,7 @@ pub async fn count(conn: &Connection) -> anyhow::Result<usize> {
 pub async fn total_size(conn: &Connection) -> anyhow::Result<u64> {
     let mut rows = conn
         .query(
-            "SELECT COALESCE(SUM(file_size), 0) FROM record_segments",
+            "SELECT COALESCE(SUM(file_size), 0) FROM record_segments WHERE status != 'missing'",
             (),
         )
         .await?;
@@ -238,7 +252,7 @@ pub async fn total_size(conn: &Connection) -> anyhow::Result<u64> {
 pub async fn count_by_stream(stream: &str, conn: &Connection) -> anyhow::Result<usize> {
     let mut rows = conn
         .query(
-            "SELECT COUNT(*) FROM record_segments WHERE stream = ?1",
+            "SELECT COUNT(*) FROM record_segments WHERE stream = ?1 AND status != 'missing'",
             [stream],
         )
         .await?;
@@ -264,7 +278,7 @@ pub async fn count_by_streams(
         .collect::<Vec<_>>()
         .join(", ");
     let sql = format!(
-        "SELECT stream, COUNT(*) FROM record_segments WHERE stream IN ({}) GROUP BY stream",
+        "SELECT stream, COUNT(*) FROM record_segments WHERE stream IN ({}) AND status != 'missing' GROUP BY stream",
         in_clause
     );
     let mut rows = conn.query(sql, ()).await?;
@@ -276,24 +290,28 @@ pub async fn count_by_streams(
 }
 
 /// Record segments that still need copying to `target_id`: either no transport
-/// job exists yet, or the last attempt failed and is still under `max_attempts`.
+/// job exists yet, or the last attempt failed, is still under `max_attempts`
+/// and due by `now` (RFC 3339). Only segments of `streams`, unless empty.
 /// Oldest first (transport in recording order), capped at `limit`.
 pub async fn list_needing_transport(
     target_id: &str,
+    streams: &[String],
     max_attempts: i64,
+    now: &str,
     limit: usize,
     conn: &Connection,
 ) -> anyhow::Result<Vec<RecordSegment>> {
+    let pending = needing_transport_clause(streams, max_attempts, Some(now));
     let sql = format!(
         r#"
         SELECT
             rs.id, rs.record_type, rs.start_time, rs.duration, rs.file_size, rs.file_name, rs.file_path, rs.folder, rs.app, rs.stream, rs.vhost,
             rs.video_codec, rs.video_width, rs.video_height, rs.video_fps, rs.video_bit_rate,
             rs.audio_codec, rs.audio_sample_rate, rs.audio_channels, rs.audio_bit_rate,
-            rs.reserve_text1, rs.reserve_text2, rs.reserve_text3, rs.reserve_int1, rs.reserve_int2, rs.create_time, rs.update_time
+            rs.reserve_text1, rs.reserve_text2, rs.reserve_text3, rs.reserve_int1, rs.reserve_int2, rs.create_time, rs.update_time, rs.status
         FROM record_segments rs
         LEFT JOIN transport_jobs tj ON tj.segment_id = rs.id AND tj.target_id = ?1
-        WHERE tj.id IS NULL OR (tj.status = 2 AND tj.attempts < {max_attempts})
+        WHERE {pending}
         ORDER BY rs.start_time ASC
         LIMIT {limit}
         "#,
@@ -306,6 +324,48 @@ pub async fn list_needing_transport(
     Ok(records)
 }
 
+/// How many segments [`list_needing_transport`] has left for `target_id`,
+/// whether or not their retry is due yet.
+pub async fn count_needing_transport(
+    target_id: &str,
+    streams: &[String],
+    max_attempts: i64,
+    conn: &Connection,
+) -> anyhow::Result<usize> {
+    let pending = needing_transport_clause(streams, max_attempts, None);
+    let sql = format!(
+        r#"
+        SELECT COUNT(*)
+        FROM record_segments rs
+        LEFT JOIN transport_jobs tj ON tj.segment_id = rs.id AND tj.target_id = ?1
+        WHERE {pending}
+        "#,
+    );
+    let mut rows = conn.query(&sql, [target_id]).await?;
+    let Some(row) = rows.next().await? else {
+        return Ok(0);
+    };
+    Ok(row.get::<i64>(0)? as usize)
+}
+
+fn needing_transport_clause(streams: &[String], max_attempts: i64, due: Option<&str>) -> String {
+    let due = due
+        .map(|now| format!(" AND tj.next_attempt_time <= '{}'", sql_text(now)))
+        .unwrap_or_default();
+    let mut clause = format!(
+        "rs.status != 'missing' AND (tj.id IS NULL OR (tj.status = 2 AND tj.attempts < {max_attempts}{due}))"
+    );
+    if !streams.is_empty() {
+        let in_clause = streams
+            .iter()
+            .map(|stream| format!("'{}'", sql_text(stream)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        clause.push_str(&format!(" AND rs.stream IN ({in_clause})"));
+    }
+    clause
+}
+
 pub async fn get(id: &str, conn: &Connection) -> anyhow::Result<Option<RecordSegment>> {
     let mut rows = conn
         .query(
@@ -314,7 +374,7 @@ pub async fn get(id: &str, conn: &Connection) -> anyhow::Result<Option<RecordSeg
                 id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
                 video_codec, video_width, video_height, video_fps, video_bit_rate,
                 audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
-                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time
+                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
             FROM record_segments
             WHERE id = ?1
             LIMIT 1
@@ -335,7 +395,8 @@ pub async fn list_older_than_days(
     days: u32,
     conn: &Connection,
 ) -> anyhow::Result<Vec<RecordSegment>> {
-    let modifier = format!("-{days} days");
+    // RFC 3339 UTC text compares in time order.
+    let cutoff = (Utc::now() - chrono::Duration::days(days.into())).to_rfc3339();
     let mut rows = conn
         .query(
             r#"
@@ -343,12 +404,35 @@ pub async fn list_older_than_days(
                 id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
                 video_codec, video_width, video_height, video_fps, video_bit_rate,
                 audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
-                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time
+                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
             FROM record_segments
-            WHERE create_time < datetime('now', ?1)
+            WHERE create_time < ?1
             ORDER BY start_time ASC
             "#,
-            [modifier.as_str()],
+            [cutoff.as_str()],
+        )
+        .await?;
+    let mut records = Vec::new();
+    while let Some(row) = rows.next().await? {
+        records.push(record_from_row(&row)?);
+    }
+    Ok(records)
+}
+
+/// Every segment, missing ones included, newest first.
+pub async fn list_all(conn: &Connection) -> anyhow::Result<Vec<RecordSegment>> {
+    let mut rows = conn
+        .query(
+            r#"
+            SELECT
+                id, record_type, start_time, duration, file_size, file_name, file_path, folder, app, stream, vhost,
+                video_codec, video_width, video_height, video_fps, video_bit_rate,
+                audio_codec, audio_sample_rate, audio_channels, audio_bit_rate,
+                reserve_text1, reserve_text2, reserve_text3, reserve_int1, reserve_int2, create_time, update_time, status
+            FROM record_segments
+            ORDER BY start_time DESC, update_time DESC
+            "#,
+            (),
         )
         .await?;
     let mut records = Vec::new();
@@ -358,6 +442,18 @@ pub async fn list_older_than_days(
     Ok(records)
 }
 
+/// Set a segment's `status` (e.g. [`STATUS_MISSING`], or empty once its file
+/// is back).
+pub async fn set_status(id: &str, status: &str, conn: &Connection) -> anyhow::Result<()> {
+    let update_time = Utc::now().to_rfc3339();
+    conn.execute(
+        "UPDATE record_segments SET status = ?1, update_time = ?2 WHERE id = ?3",
+        (status, update_time.as_str(), id),
+    )
+    .await?;
+    Ok(())
+}
+
 pub async fn delete(id: &str, conn: &Connection) -> anyhow::Result<()> {
     conn.execute("DELETE FROM record_segments WHERE id = ?1", [id])
         .await?;
@@ -405,5 +501,6 @@ fn record_from_row(row: &turso::Row) -> anyhow::Result<RecordSegment> {
         reserve_int2: row.get::<i64>(24)?,
         create_time,
         update_time,
+        status: row.get::<String>(27)?,
     })
 }
@@ -10,7 +10,7 @@ use turso::Connection;
 use crate::kv;
 
 /// KV module namespace session records live under (`kvs.module`).
-const MODULE: &str = "session";
+pub(crate) const MODULE: &str = "session";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
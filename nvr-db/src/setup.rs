@@ -0,0 +1,65 @@
+//! First-run setup records: the first admin and the setup settings are
+//! written together, and [`reset`] undoes both so setup can run again.
+
+use turso::Connection;
+
+use crate::user::UserInfo;
+
+/// Config key (see [`crate::config`]) the settings of a completed setup
+/// live under, as JSON; its presence is what marks setup completed.
+pub const SETUP_KEY: &str = "setup";
+
+/// Create the first admin `user` and record the completed setup as
+/// `settings` (JSON), in one transaction: either both are stored or neither.
+pub async fn complete(
+    user: &UserInfo,
+    settings: &str,
+    conn: &mut Connection,
+) -> anyhow::Result<()> {
+    let value = serde_json::to_string(user)?;
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "INSERT INTO kvs (module, key, sub_key, value) VALUES (?1, ?2, ?3, ?4)",
+        (
+            crate::user::MODULE,
+            user.username.as_str(),
+            "",
+            value.as_str(),
+        ),
+    )
+    .await?;
+    tx.execute(
+        "DELETE FROM kvs WHERE module = ?1 AND key = ?2",
+        (crate::config::MODULE, SETUP_KEY),
+    )
+    .await?;
+    tx.execute(
+        "INSERT INTO kvs (module, key, sub_key, value) VALUES (?1, ?2, ?3, ?4)",
+        (crate::config::MODULE, SETUP_KEY, "", settings),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Forget the completed setup: delete every user, every session and the
+/// setup settings. Devices, recordings and other settings stay.
+pub async fn reset(conn: &mut Connection) -> anyhow::Result<()> {
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "DELETE FROM kvs WHERE module IN (?1, ?2)",
+        (crate::user::MODULE, crate::session::MODULE),
+    )
+    .await?;
+    tx.execute(
+        "DELETE FROM kvs WHERE module = ?1 AND key = ?2",
+        (crate::config::MODULE, SETUP_KEY),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "setup_test.rs"]
+mod setup_test;
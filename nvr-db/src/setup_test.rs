@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use turso::Connection;
+
+use crate::db::{DatabaseConfig, NvrDatabase};
+use crate::session::{self, Session};
+use crate::setup::{self, SETUP_KEY};
+use crate::user::{self, UserInfo};
+
+async fn test_conn() -> Connection {
+    let db = NvrDatabase::new(&DatabaseConfig::new(":memory:"))
+        .await
+        .unwrap();
+    let conn = db.connect().unwrap();
+    conn.execute_batch(
+        r#"CREATE TABLE kvs (
+            id INTEGER NOT NULL,
+            module VARCHAR NOT NULL,
+            key VARCHAR NOT NULL,
+            sub_key VARCHAR NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY(id AUTOINCREMENT)
+        );"#,
+    )
+    .await
+    .unwrap();
+    conn
+}
+
+fn admin() -> UserInfo {
+    let now = Utc::now();
+    UserInfo {
+        username: "root".to_string(),
+        password_hash: user::hash_password("secret").unwrap(),
+        metadata: HashMap::new(),
+        create_time: now,
+        update_time: now,
+    }
+}
+
+#[tokio::test]
+async fn complete_stores_the_admin_and_the_settings() {
+    let mut conn = test_conn().await;
+    setup::complete(&admin(), r#"{"timezone":"UTC"}"#, &mut conn)
+        .await
+        .unwrap();
+
+    let found = user::get_by_username("root", &conn).await.unwrap().unwrap();
+    assert!(user::verify_password("secret", &found.password_hash));
+    assert_eq!(
+        crate::config::get(SETUP_KEY, &conn)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some(r#"{"timezone":"UTC"}"#)
+    );
+}
+
+#[tokio::test]
+async fn reset_forgets_users_sessions_and_setup_only() {
+    let mut conn = test_conn().await;
+    setup::complete(&admin(), "{}", &mut conn).await.unwrap();
+    session::insert(
+        &Session {
+            token: "tok".to_string(),
+            username: "root".to_string(),
+            expires_at: Utc::now() + Duration::hours(1),
+        },
+        &conn,
+    )
+    .await
+    .unwrap();
+    crate::config::set("dashboard_settings", "{}", &conn)
+        .await
+        .unwrap();
+
+    setup::reset(&mut conn).await.unwrap();
+    assert!(user::list(&conn).await.unwrap().is_empty());
+    assert!(session::get_by_token("tok", &conn).await.unwrap().is_none());
+    assert!(!crate::config::exists(SETUP_KEY, &conn).await.unwrap());
+    assert!(
+        crate::config::exists("dashboard_settings", &conn)
+            .await
+            .unwrap()
+    );
+}
@@ -5,8 +5,8 @@ pub const STATUS_PENDING: i64 = 0;
 pub const STATUS_DONE: i64 = 1;
 pub const STATUS_FAILED: i64 = 2;
 
-/// Upload bookkeeping for one (segment, target) pair. Since transport is a copy
-/// (local files are kept), this only records whether the remote copy exists.
+/// Upload bookkeeping for one (segment, target) pair: whether the remote copy
+/// exists, and when a failed upload is next tried.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransportJob {
     pub id: String,
@@ -19,9 +19,12 @@ pub struct TransportJob {
     pub error: String,
     pub create_time: String,
     pub update_time: String,
+    /// RFC 3339 time a failed job is retried at; empty for due now.
+    #[serde(default)]
+    pub next_attempt_time: String,
 }
 
-const COLS: &str = "id, segment_id, target_id, status, attempts, remote_key, file_size, error, create_time, update_time";
+const COLS: &str = "id, segment_id, target_id, status, attempts, remote_key, file_size, error, create_time, update_time, next_attempt_time";
 
 fn sql_text(value: &str) -> String {
     value.replace('\'', "''")
@@ -39,21 +42,23 @@ fn from_row(row: &turso::Row) -> anyhow::Result<TransportJob> {
         error: row.get::<String>(7)?,
         create_time: row.get::<String>(8)?,
         update_time: row.get::<String>(9)?,
+        next_attempt_time: row.get::<String>(10)?,
     })
 }
 
 pub async fn upsert(job: &TransportJob, conn: &Connection) -> anyhow::Result<()> {
     let sql = format!(
         r#"
-        INSERT INTO transport_jobs (id, segment_id, target_id, status, attempts, remote_key, file_size, error, create_time, update_time)
-        VALUES ('{id}', '{segment_id}', '{target_id}', {status}, {attempts}, '{remote_key}', {file_size}, '{error}', '{create_time}', '{update_time}')
+        INSERT INTO transport_jobs (id, segment_id, target_id, status, attempts, remote_key, file_size, error, create_time, update_time, next_attempt_time)
+        VALUES ('{id}', '{segment_id}', '{target_id}', {status}, {attempts}, '{remote_key}', {file_size}, '{error}', '{create_time}', '{update_time}', '{next_attempt_time}')
         ON CONFLICT(segment_id, target_id) DO UPDATE SET
             status=excluded.status,
             attempts=excluded.attempts,
             remote_key=excluded.remote_key,
             file_size=excluded.file_size,
             error=excluded.error,
-            update_time=excluded.update_time
+            update_time=excluded.update_time,
+            next_attempt_time=excluded.next_attempt_time
         "#,
         id = sql_text(&job.id),
         segment_id = sql_text(&job.segment_id),
@@ -65,6 +70,7 @@ pub async fn upsert(job: &TransportJob, conn: &Connection) -> anyhow::Result<()>
         error = sql_text(&job.error),
         create_time = sql_text(&job.create_time),
         update_time = sql_text(&job.update_time),
+        next_attempt_time = sql_text(&job.next_attempt_time),
     );
     conn.execute_batch(sql).await?;
     Ok(())
@@ -102,6 +108,45 @@ pub async fn list_recent(
     Ok(out)
 }
 
+/// Every job of the segments `segment_ids`, for any target.
+pub async fn list_by_segments(
+    segment_ids: &[String],
+    conn: &Connection,
+) -> anyhow::Result<Vec<TransportJob>> {
+    if segment_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let in_clause = segment_ids
+        .iter()
+        .map(|id| format!("'{}'", sql_text(id)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!("SELECT {COLS} FROM transport_jobs WHERE segment_id IN ({in_clause})");
+    let mut rows = conn.query(&sql, ()).await?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(from_row(&row)?);
+    }
+    Ok(out)
+}
+
+/// Jobs of `target_id` whose last attempt failed, the soonest retried first.
+pub async fn list_failed(
+    target_id: &str,
+    limit: usize,
+    conn: &Connection,
+) -> anyhow::Result<Vec<TransportJob>> {
+    let sql = format!(
+        "SELECT {COLS} FROM transport_jobs WHERE target_id = ?1 AND status = {STATUS_FAILED} ORDER BY next_attempt_time ASC LIMIT {limit}"
+    );
+    let mut rows = conn.query(&sql, [target_id]).await?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(from_row(&row)?);
+    }
+    Ok(out)
+}
+
 /// (done, failed, pending) counts for a target.
 pub async fn counts_by_status(
     target_id: &str,
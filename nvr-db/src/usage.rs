@@ -0,0 +1,195 @@
+//! Per-device data usage: hourly buckets (`usage` table) that the `nvr`
+//! usage sampler adds to, and their day and month sums (`usage_rollup`),
+//! which outlive the hours they were rolled up from.
+
+use serde::{Deserialize, Serialize};
+use turso::Connection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Hour,
+    Day,
+    Month,
+}
+
+impl Granularity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Month => "month",
+        }
+    }
+}
+
+/// Bytes one device moved in one period.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Usage {
+    pub device_id: String,
+    /// Start of the period, unix milliseconds (UTC).
+    pub start: i64,
+    /// Read from the camera.
+    pub ingress_bytes: i64,
+    /// Served from the device: live viewers, uploads.
+    pub egress_bytes: i64,
+}
+
+fn from_row(row: &turso::Row) -> anyhow::Result<Usage> {
+    Ok(Usage {
+        device_id: row.get::<String>(0)?,
+        start: row.get::<i64>(1)?,
+        ingress_bytes: row.get::<i64>(2)?,
+        egress_bytes: row.get::<i64>(3)?,
+    })
+}
+
+/// Add `usage` to the hour it starts (`usage.start`, the hour's start).
+pub async fn add_hour(usage: &Usage, conn: &Connection) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO usage (device_id, hour, ingress_bytes, egress_bytes) \
+         VALUES (?1, ?2, ?3, ?4) \
+         ON CONFLICT(device_id, hour) DO UPDATE SET \
+         ingress_bytes = ingress_bytes + excluded.ingress_bytes, \
+         egress_bytes = egress_bytes + excluded.egress_bytes",
+        (
+            usage.device_id.as_str(),
+            usage.start,
+            usage.ingress_bytes,
+            usage.egress_bytes,
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Hours starting before `before` that are not rolled up yet, oldest first.
+pub async fn unrolled_before(before: i64, conn: &Connection) -> anyhow::Result<Vec<Usage>> {
+    let mut rows = conn
+        .query(
+            "SELECT device_id, hour, ingress_bytes, egress_bytes FROM usage \
+             WHERE rolled = 0 AND hour < ?1 ORDER BY hour ASC, device_id ASC",
+            (before,),
+        )
+        .await?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(from_row(&row)?);
+    }
+    Ok(out)
+}
+
+/// Add the hour `hour` to its day (starting `day`) and month (starting
+/// `month`), and mark it rolled up. One transaction, so an hour is counted
+/// in its day and month exactly once.
+pub async fn roll_up(
+    hour: &Usage,
+    day: i64,
+    month: i64,
+    conn: &mut Connection,
+) -> anyhow::Result<()> {
+    let tx = conn.transaction().await?;
+    let marked = tx
+        .execute(
+            "UPDATE usage SET rolled = 1 WHERE device_id = ?1 AND hour = ?2 AND rolled = 0",
+            (hour.device_id.as_str(), hour.start),
+        )
+        .await?;
+    if marked == 0 {
+        tx.commit().await?;
+        return Ok(());
+    }
+    for (granularity, start) in [(Granularity::Day, day), (Granularity::Month, month)] {
+        tx.execute(
+            "INSERT INTO usage_rollup \
+             (device_id, granularity, period_start, ingress_bytes, egress_bytes) \
+             VALUES (?1, ?2, ?3, ?4, ?5) \
+             ON CONFLICT(device_id, granularity, period_start) DO UPDATE SET \
+             ingress_bytes = ingress_bytes + excluded.ingress_bytes, \
+             egress_bytes = egress_bytes + excluded.egress_bytes",
+            (
+                hour.device_id.as_str(),
+                granularity.as_str(),
+                start,
+                hour.ingress_bytes,
+                hour.egress_bytes,
+            ),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Delete the rolled-up hours starting before `before`. Returns how many
+/// were removed.
+pub async fn prune_rolled(before: i64, conn: &Connection) -> anyhow::Result<u64> {
+    Ok(conn
+        .execute(
+            "DELETE FROM usage WHERE rolled = 1 AND hour < ?1",
+            (before,),
+        )
+        .await?)
+}
+
+/// The periods of `device_id` at `granularity` starting in `from..=to` (unix
+/// milliseconds), oldest first.
+pub async fn list_by_device(
+    device_id: &str,
+    granularity: Granularity,
+    from: i64,
+    to: i64,
+    conn: &Connection,
+) -> anyhow::Result<Vec<Usage>> {
+    let mut rows = match granularity {
+        Granularity::Hour => {
+            conn.query(
+                "SELECT device_id, hour, ingress_bytes, egress_bytes FROM usage \
+                 WHERE device_id = ?1 AND hour >= ?2 AND hour <= ?3 ORDER BY hour ASC",
+                (device_id, from, to),
+            )
+            .await?
+        }
+        _ => {
+            conn.query(
+                "SELECT device_id, period_start, ingress_bytes, egress_bytes FROM usage_rollup \
+                 WHERE device_id = ?1 AND granularity = ?2 \
+                 AND period_start >= ?3 AND period_start <= ?4 ORDER BY period_start ASC",
+                (device_id, granularity.as_str(), from, to),
+            )
+            .await?
+        }
+    };
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(from_row(&row)?);
+    }
+    Ok(out)
+}
+
+/// Each device's days starting in `from..=to` summed, `start` set to `from`;
+/// by device id.
+pub async fn totals_by_device(from: i64, to: i64, conn: &Connection) -> anyhow::Result<Vec<Usage>> {
+    let mut rows = conn
+        .query(
+            "SELECT device_id, SUM(ingress_bytes), SUM(egress_bytes) FROM usage_rollup \
+             WHERE granularity = 'day' AND period_start >= ?1 AND period_start <= ?2 \
+             GROUP BY device_id ORDER BY device_id ASC",
+            (from, to),
+        )
+        .await?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().await? {
+        out.push(Usage {
+            device_id: row.get::<String>(0)?,
+            start: from,
+            ingress_bytes: row.get::<i64>(1)?,
+            egress_bytes: row.get::<i64>(2)?,
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+#[path = "usage_test.rs"]
+mod usage_test;
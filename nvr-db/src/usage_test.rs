@@ -0,0 +1,100 @@
+use turso::Connection;
+
+use crate::db::{DatabaseConfig, NvrDatabase};
+use crate::usage::{self, Granularity, Usage};
+
+const HOUR: i64 = 3_600_000;
+const DAY: i64 = 24 * HOUR;
+
+async fn test_conn() -> Connection {
+    let db = NvrDatabase::new(&DatabaseConfig::new(":memory:"))
+        .await
+        .unwrap();
+    let mut conn = db.connect().unwrap();
+    crate::migrations::migrate_conn(&mut conn).await.unwrap();
+    conn
+}
+
+fn usage(device_id: &str, start: i64, ingress_bytes: i64, egress_bytes: i64) -> Usage {
+    Usage {
+        device_id: device_id.to_string(),
+        start,
+        ingress_bytes,
+        egress_bytes,
+    }
+}
+
+#[tokio::test]
+async fn samples_add_up_in_their_hour() {
+    let conn = test_conn().await;
+    usage::add_hour(&usage("cam1", 0, 100, 10), &conn)
+        .await
+        .unwrap();
+    usage::add_hour(&usage("cam1", 0, 50, 5), &conn)
+        .await
+        .unwrap();
+    usage::add_hour(&usage("cam1", HOUR, 7, 0), &conn)
+        .await
+        .unwrap();
+    usage::add_hour(&usage("cam2", 0, 1, 1), &conn)
+        .await
+        .unwrap();
+
+    let hours = usage::list_by_device("cam1", Granularity::Hour, 0, HOUR, &conn)
+        .await
+        .unwrap();
+    assert_eq!(
+        hours,
+        vec![usage("cam1", 0, 150, 15), usage("cam1", HOUR, 7, 0)]
+    );
+    let first = usage::list_by_device("cam1", Granularity::Hour, 0, 0, &conn)
+        .await
+        .unwrap();
+    assert_eq!(first.len(), 1);
+}
+
+#[tokio::test]
+async fn hours_roll_up_once_and_outlive_pruning() {
+    let mut conn = test_conn().await;
+    for (start, ingress) in [(0, 10), (HOUR, 20), (DAY, 40)] {
+        usage::add_hour(&usage("cam1", start, ingress, 1), &conn)
+            .await
+            .unwrap();
+    }
+    usage::add_hour(&usage("cam2", HOUR, 5, 5), &conn)
+        .await
+        .unwrap();
+
+    // The hour being written (from DAY on) is left for a later pass.
+    let pending = usage::unrolled_before(DAY, &conn).await.unwrap();
+    assert_eq!(pending.len(), 3);
+    for hour in &pending {
+        let day = hour.start - hour.start % DAY;
+        usage::roll_up(hour, day, 0, &mut conn).await.unwrap();
+        // Rolling the same hour again changes nothing.
+        usage::roll_up(hour, day, 0, &mut conn).await.unwrap();
+    }
+    assert!(usage::unrolled_before(DAY, &conn).await.unwrap().is_empty());
+
+    let days = usage::list_by_device("cam1", Granularity::Day, 0, DAY, &conn)
+        .await
+        .unwrap();
+    assert_eq!(days, vec![usage("cam1", 0, 30, 2)]);
+
+    // Only rolled hours are pruned; their sums stay.
+    assert_eq!(usage::prune_rolled(2 * DAY, &conn).await.unwrap(), 3);
+    let hours = usage::list_by_device("cam1", Granularity::Hour, 0, 2 * DAY, &conn)
+        .await
+        .unwrap();
+    assert_eq!(hours, vec![usage("cam1", DAY, 40, 1)]);
+    let months = usage::list_by_device("cam1", Granularity::Month, 0, 0, &conn)
+        .await
+        .unwrap();
+    assert_eq!(months, vec![usage("cam1", 0, 30, 2)]);
+
+    let totals = usage::totals_by_device(0, DAY, &conn).await.unwrap();
+    assert_eq!(
+        totals,
+        vec![usage("cam1", 0, 30, 2), usage("cam2", 0, 5, 5)]
+    );
+}
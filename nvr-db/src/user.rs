@@ -8,7 +8,7 @@ use turso::Connection;
 use crate::kv;
 
 /// KV module namespace user records live under (`kvs.module`, keyed by username).
-const MODULE: &str = "user";
+pub(crate) const MODULE: &str = "user";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserInfo {
@@ -84,3 +84,24 @@ async fn delete_and_list() {
     assert!(!user::exists("alice", &conn).await.unwrap());
     assert!(user::exists("bob", &conn).await.unwrap());
 }
+
+#[tokio::test]
+async fn the_seeded_admin_account_gets_the_admin_role() {
+    let conn = test_conn().await;
+    user::insert(&user("admin", "admin"), &conn).await.unwrap();
+    let mut viewer = user("viewer", "v");
+    viewer
+        .metadata
+        .insert("role".to_string(), "viewer".to_string());
+    user::insert(&viewer, &conn).await.unwrap();
+
+    conn.execute_batch(include_str!("../migrations/20261021_admin_role.sql"))
+        .await
+        .unwrap();
+
+    let role = |user: Option<UserInfo>| user.unwrap().metadata.get("role").cloned();
+    let admin = user::get_by_username("admin", &conn).await.unwrap();
+    assert_eq!(role(admin).as_deref(), Some("admin"));
+    let viewer = user::get_by_username("viewer", &conn).await.unwrap();
+    assert_eq!(role(viewer).as_deref(), Some("viewer"));
+}
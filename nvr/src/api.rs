@@ -1,37 +1,98 @@
-use axum::Router;
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    extract::Request,
+    http::{HeaderName, HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
 use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
 
+use crate::setup::Setup;
+
+/// Where the current version of the API is mounted.
+pub(crate) const V1: &str = "/api/v1";
+
+/// Every API route, relative to [`V1`] (and to `/api`, its deprecated alias).
+pub(crate) fn api_router() -> Router {
+    api_router_with(crate::setup::global())
+}
+
+/// [`api_router`], in setup mode while `setup` is required.
+pub(crate) fn api_router_with(setup: Arc<Setup>) -> Router {
+    Router::new()
+        .nest("/setup", crate::setup::setup_router(setup.clone()))
+        .nest("/device", crate::handler::device::device_router())
+        .nest("/input_profiles", crate::latency::latency_router())
+        .nest("/probe", crate::handler::probe::probe_router())
+        .nest("/playback", crate::handler::playback::playback_router())
+        .nest("/recordings", crate::handler::recording::recording_router())
+        .nest("/bookmark", crate::handler::bookmark::bookmark_router())
+        .nest("/export", crate::export::export_router())
+        .nest("/jobs", crate::jobs::jobs_router())
+        .nest("/user", crate::handler::user::user_router())
+        .nest("/pipe", crate::handler::media_pipe::media_pipe_router())
+        .nest("/system", crate::handler::system::system_router())
+        .nest("/gb", crate::gb::api::gb_router())
+        .nest("/transport", crate::transport::api::transport_router())
+        .nest("/storage", crate::transport::api::storage_router())
+        .nest("/program", crate::program::api::program_router())
+        .nest("/compositor", crate::compositor::api::compositor_router())
+        .nest("/audiomixer", crate::audiomixer::api::audiomixer_router())
+        .nest("/asr", crate::asr::api::asr_router())
+        .nest("/onvif", crate::onvif::api::onvif_router())
+        .nest("/discovery", crate::discovery::discovery_router())
+        .nest("/detect", crate::detect::api::detect_router())
+        .nest("/federation", crate::federation::api::federation_router())
+        .nest("/audit", crate::audit::audit_router())
+        .nest("/snapshot", crate::snapshot::snapshot_router())
+        .nest("/groups", crate::wall::wall_router())
+        .nest("/usage", crate::usage::usage_router())
+        .nest(
+            "/admin",
+            crate::reconcile::admin_router().merge(crate::maintenance::admin_router()),
+        )
+        .merge(crate::openapi::openapi_router())
+        // Viewers may only read; inside the audit layer, so refusals are
+        // recorded too.
+        .layer(axum::middleware::from_fn(crate::auth::read_only_viewers))
+        // Audit mutating calls; layered inside auth so it sees `AuthUser`.
+        .layer(axum::middleware::from_fn(crate::audit::record))
+        // Session auth for everything above; sees the nest-stripped path
+        // (e.g. `/user/login`), which is what the exempt list matches on.
+        .layer(axum::middleware::from_fn(crate::auth::require_auth))
+        // First-run setup mode: 503 for all but `/setup` until it is done.
+        .layer(axum::middleware::from_fn_with_state(
+            setup,
+            crate::setup::gate,
+        ))
+}
+
 pub(crate) fn start_api_server(cancel: CancellationToken, port: u16) {
     tokio::spawn(async move {
-        let api = Router::new()
-            .nest("/device", crate::handler::device::device_router())
-            .nest("/playback", crate::handler::playback::playback_router())
-            .nest("/user", crate::handler::user::user_router())
-            .nest("/pipe", crate::handler::media_pipe::media_pipe_router())
-            .nest("/system", crate::handler::system::system_router())
-            .nest("/gb", crate::gb::api::gb_router())
-            .nest("/transport", crate::transport::api::transport_router())
-            .nest("/program", crate::program::api::program_router())
-            .nest("/compositor", crate::compositor::api::compositor_router())
-            .nest("/audiomixer", crate::audiomixer::api::audiomixer_router())
-            .nest("/asr", crate::asr::api::asr_router())
-            .nest("/onvif", crate::onvif::api::onvif_router())
-            .nest("/detect", crate::detect::api::detect_router())
-            // Session auth for everything above; sees the nest-stripped path
-            // (e.g. `/user/login`), which is what the exempt list matches on.
-            .layer(axum::middleware::from_fn(crate::auth::require_auth));
+        let api = api_router();
 
         let (asr_layer, asr_io) = crate::asr::build_socketio();
 
         let app = Router::new()
-            .nest("/api", api)
+            .nest(V1, api.clone())
+            // The unversioned paths of earlier releases, kept for one more.
+            .nest(
+                "/api",
+                api.layer(axum::middleware::from_fn(deprecated_alias)),
+            )
             // Mount the dashboard via its prefix-aware branch (nest_service), which
             // serves the bare SPA root `/nvr/`. Nesting the fallback-based
             // `app_router(None)` under `/nvr` instead makes axum 404 `/nvr/`.
             .merge(nvr_dashboard::app_router(Some("/nvr")))
+            // `/healthz` and `/readyz` for load balancers and orchestrators.
+            .merge(crate::maintenance::probe_router())
             // Reverse-proxy `/media/*` to ZLM's HTTP service (HTTP + WS).
             .merge(crate::proxy::media_proxy_router())
+            // Live media of peer nodes' devices, same exposure as `/media`.
+            .merge(crate::federation::api::federation_media_router())
             // Socket.IO `/asr` namespace for live transcripts.
             .layer(asr_layer);
 
@@ -45,6 +106,8 @@ pub(crate) fn start_api_server(cancel: CancellationToken, port: u16) {
             .await
             .unwrap();
         log::info!("API server started on port {}", port);
+        // Client addresses tell HLS viewers apart (see `crate::proxy`).
+        let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
         if let Err(e) = axum::serve(listener, app)
             .with_graceful_shutdown(shutdown_signal(cancel))
             .await
@@ -61,3 +124,23 @@ async fn shutdown_signal(cancel: CancellationToken) {
         }
     }
 }
+
+/// Mark responses of the unversioned alias deprecated (RFC 9745), linking
+/// their `/api/v1` successor.
+async fn deprecated_alias(req: Request, next: Next) -> Response {
+    let successor = format!("<{V1}{}>; rel=\"successor-version\"", req.uri().path());
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, link);
+    }
+    response
+}
+
+#[cfg(test)]
+#[path = "api_test.rs"]
+mod api_test;
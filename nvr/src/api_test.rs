@@ -0,0 +1,33 @@
+use axum::{body::Body, routing::get};
+use tower::ServiceExt;
+
+use super::*;
+
+fn app() -> Router {
+    let api = Router::new().route("/device/list", get(async || "devices"));
+    Router::new().nest(V1, api.clone()).nest(
+        "/api",
+        api.layer(axum::middleware::from_fn(deprecated_alias)),
+    )
+}
+
+async fn get_headers(uri: &str) -> axum::http::HeaderMap {
+    let req = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+    let response = app().oneshot(req).await.unwrap();
+    assert_eq!(response.status(), axum::http::StatusCode::OK, "{uri}");
+    response.headers().clone()
+}
+
+#[tokio::test]
+async fn unversioned_alias_is_marked_deprecated() {
+    let headers = get_headers("/api/device/list").await;
+    assert_eq!(headers["deprecation"], "true");
+    assert_eq!(
+        headers[header::LINK],
+        "</api/v1/device/list>; rel=\"successor-version\""
+    );
+
+    let headers = get_headers("/api/v1/device/list").await;
+    assert!(!headers.contains_key("deprecation"));
+    assert!(!headers.contains_key(header::LINK));
+}
@@ -15,6 +15,9 @@ async fn start(Path(pipe): Path<String>) -> impl IntoResponse {
     let Some(hub) = AsrHub::get() else {
         return (StatusCode::SERVICE_UNAVAILABLE, "asr not initialized").into_response();
     };
+    if crate::privacy::is_private(&pipe) {
+        return (StatusCode::LOCKED, "privacy mode enabled").into_response();
+    }
     if hub.is_running(&pipe) {
         return (StatusCode::OK, "already running").into_response();
     }
@@ -32,10 +32,12 @@ const RESTORE_RETRY_BUDGET: Duration = Duration::from_secs(60);
 
 static MIXER: LazyLock<AudioMixer> = LazyLock::new(AudioMixer::new);
 
-/// A device's audio stream URL on ZLM (source id == device id).
+/// A device's audio stream URL on ZLM (source id == device id), under its
+/// stream key.
 fn device_audio_url(source_id: &str) -> String {
     let app = crate::init::device::DEVICE_APP;
-    format!("{ZLM_RTSP}/{app}/{source_id}")
+    let stream = crate::stream_key::stream_of(source_id);
+    format!("{ZLM_RTSP}/{app}/{stream}")
 }
 
 /// Default publish URL for a bus when the caller doesn't give one.
@@ -198,7 +200,7 @@ fn migrate_publish_url(url: &str) -> String {
 }
 
 /// Restore persisted buses at startup. Call AFTER device pipes have started so
-/// the sources' ZLM streams exist (buses pull `rtsp://127.0.0.1:8554/device/{id}`).
+/// the sources' ZLM streams exist (buses pull `rtsp://127.0.0.1:8554/device/{key}`).
 /// Best-effort: a short grace period, then retry each bus's `create_bus()` with
 /// backing-off attempts over a ~1-minute window so a bus whose device streams
 /// are slow to come up on a cold boot still restores.
@@ -10,16 +10,15 @@ use std::collections::HashMap;
 use std::sync::{LazyLock, RwLock};
 
 use axum::{
-    Json,
     extract::Request,
-    http::{StatusCode, header},
+    http::{HeaderMap, header},
     middleware::Next,
     response::{IntoResponse, Response},
 };
 use chrono::{DateTime, Duration, Utc};
 
 use crate::db::app_db_conn;
-use crate::handler::BaseResponse;
+use crate::handler::ApiError;
 
 /// Sessions live this long from login. Fixed, not sliding — renewal would
 /// cost a DB write per request.
@@ -27,7 +26,7 @@ const SESSION_TTL_DAYS: i64 = 30;
 
 /// Paths (relative to the `/api` router the middleware is layered on, which
 /// sees the nest-stripped URI) that skip auth.
-const EXEMPT_PATHS: &[&str] = &["/user/login"];
+const EXEMPT_PATHS: &[&str] = &["/user/login", "/setup", "/setup/status"];
 
 /// The authenticated caller, inserted into request extensions by
 /// [`require_auth`]; handlers take it via `Extension<AuthUser>`.
@@ -111,15 +110,54 @@ pub async fn revoke_user(username: &str, except_token: Option<&str>) -> anyhow::
     nvr_db::session::delete_by_username(username, except_token, &app_db_conn()?).await
 }
 
+/// User metadata key holding the role; `viewer` users may only read (see
+/// [`read_only_viewers`]), anything else (or no role) has full access. `admin`
+/// users, the one created by first-run setup, also get the admin-only calls,
+/// managing users among them.
+pub const ROLE_KEY: &str = "role";
+pub const ROLE_VIEWER: &str = "viewer";
+pub const ROLE_ADMIN: &str = "admin";
+
+/// Whether `username` has the read-only viewer role.
+pub async fn is_viewer(username: &str) -> anyhow::Result<bool> {
+    let user = nvr_db::user::get_by_username(username, &app_db_conn()?).await?;
+    Ok(user.is_some_and(|u| u.metadata.get(ROLE_KEY).map(String::as_str) == Some(ROLE_VIEWER)))
+}
+
+/// Whether `username` may make the admin-only calls: a user with the admin
+/// role. The name means nothing, since any name can be taken.
+pub async fn is_admin(username: &str) -> anyhow::Result<bool> {
+    let user = nvr_db::user::get_by_username(username, &app_db_conn()?).await?;
+    Ok(user.is_some_and(|u| u.metadata.get(ROLE_KEY).map(String::as_str) == Some(ROLE_ADMIN)))
+}
+
+/// A session of a user with the admin role, created on first use.
+#[cfg(test)]
+pub(crate) async fn admin_session() -> String {
+    const USERNAME: &str = "test-admin";
+    let conn = app_db_conn().unwrap();
+    if !nvr_db::user::exists(USERNAME, &conn).await.unwrap() {
+        let user = nvr_db::user::UserInfo {
+            username: USERNAME.to_string(),
+            password_hash: nvr_db::user::hash_password("admin").unwrap(),
+            metadata: HashMap::from([(ROLE_KEY.to_string(), ROLE_ADMIN.to_string())]),
+            create_time: Utc::now(),
+            update_time: Utc::now(),
+        };
+        nvr_db::user::insert(&user, &conn).await.unwrap();
+    }
+    create_session(USERNAME).await.unwrap()
+}
+
 /// Middleware guarding the `/api` router. Accepts `Authorization: Bearer` or
 /// a `?token=` query param (hls.js / Safari-native playback can't always set
-/// headers), exempts login, and stamps the request with [`AuthUser`].
+/// headers), exempts login and first-run setup, and stamps the request with [`AuthUser`].
 pub async fn require_auth(mut req: Request, next: Next) -> Response {
     if EXEMPT_PATHS.contains(&req.uri().path()) {
         return next.run(req).await;
     }
 
-    let token = bearer_token(&req).or_else(|| query_token(req.uri().query()));
+    let token = bearer_token(req.headers()).or_else(|| query_token(req.uri().query()));
     let Some(token) = token else {
         return unauthorized();
     };
@@ -131,8 +169,41 @@ pub async fn require_auth(mut req: Request, next: Next) -> Response {
     next.run(req).await
 }
 
-fn bearer_token(req: &Request) -> Option<String> {
-    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+/// Mutating calls a viewer may still make: ending their session and
+/// changing their own password.
+const VIEWER_WRITES: &[&str] = &["/user/logout", "/user/password"];
+
+/// Middleware refusing viewers every mutating call (as the audit log counts
+/// them, see [`crate::audit::is_mutating`]) but [`VIEWER_WRITES`] with 403.
+/// Layered inside [`require_auth`], so it sees [`AuthUser`].
+pub async fn read_only_viewers(req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if !crate::audit::is_mutating(req.method(), path) || VIEWER_WRITES.contains(&path) {
+        return next.run(req).await;
+    }
+    let Some(username) = req
+        .extensions()
+        .get::<AuthUser>()
+        .map(|u| u.username.clone())
+    else {
+        return next.run(req).await;
+    };
+    match is_viewer(&username).await {
+        Ok(false) => next.run(req).await,
+        Ok(true) => ApiError::forbidden("viewers have read-only access").into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// The signed-in caller of a request outside the `/api` router (live media),
+/// if it carries a valid token the way [`require_auth`] accepts one.
+pub(crate) async fn optional_user(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    let token = bearer_token(headers).or_else(|| query_token(query))?;
+    validate(&token).await
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
     value
         .strip_prefix("Bearer ")
         .map(|t| t.trim().to_string())
@@ -148,15 +219,7 @@ fn query_token(query: Option<&str>) -> Option<String> {
 }
 
 fn unauthorized() -> Response {
-    (
-        StatusCode::UNAUTHORIZED,
-        Json(BaseResponse::<()> {
-            code: 401,
-            message: "unauthorized".to_string(),
-            data: None,
-        }),
-    )
-        .into_response()
+    ApiError::unauthorized("unauthorized").into_response()
 }
 
 #[cfg(test)]
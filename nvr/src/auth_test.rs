@@ -10,35 +10,9 @@ use tower::ServiceExt;
 
 use super::*;
 
-/// Serializes the DB-writing tests: turso allows one WAL writer, and parallel
-/// test bodies hitting the shared in-memory APP_DB otherwise fail with
-/// intermittent "database is locked" (see the write-contention note in
-/// `crate::db`).
-static DB_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
-
-/// Initialize the process-wide APP_DB once (all tests share one binary) with
-/// an in-memory database carrying the `kvs` table sessions live in, and take
-/// the serialization lock for the calling test.
+/// Shared in-memory APP_DB (see `crate::db::test_db`).
 async fn ensure_test_db() -> tokio::sync::MutexGuard<'static, ()> {
-    static INIT: tokio::sync::OnceCell<()> = tokio::sync::OnceCell::const_new();
-    INIT.get_or_init(|| async {
-        let db = crate::db::init_app_db(":memory:").await.unwrap();
-        let conn = db.connect().unwrap();
-        conn.execute_batch(
-            r#"CREATE TABLE kvs (
-                id INTEGER NOT NULL,
-                module VARCHAR NOT NULL,
-                key VARCHAR NOT NULL,
-                sub_key VARCHAR NOT NULL,
-                value TEXT NOT NULL,
-                PRIMARY KEY(id AUTOINCREMENT)
-            );"#,
-        )
-        .await
-        .unwrap();
-    })
-    .await;
-    DB_LOCK.lock().await
+    crate::db::test_db().await
 }
 
 fn protected_app() -> Router {
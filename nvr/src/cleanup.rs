@@ -2,7 +2,10 @@
 //! (`record_cleanup`, editable from the dashboard Settings page) and is applied
 //! by a background worker: delete segments older than `max_age_days`, then, if a
 //! total-size cap is set, prune the oldest until the total is under it. Each
-//! removal drops both the file and the DB row. Disabled by default (a no-op).
+//! removal drops both the file and the DB row. A segment some transport target
+//! still has to upload is the only copy of it and is kept until it is
+//! uploaded. Disabled by default (a no-op).
+//! The same pass prunes the API audit log (`audit_retention_days`).
 
 use std::time::Duration;
 
@@ -34,12 +37,20 @@ pub struct CleanupConfig {
     /// How often the worker runs, in minutes (clamped to >= 1).
     #[serde(default = "default_interval")]
     pub interval_minutes: u32,
+    /// Delete audit log entries older than this many days. 0 keeps them
+    /// forever. Applied on every pass, independent of `enabled`.
+    #[serde(default = "default_audit_retention")]
+    pub audit_retention_days: u32,
 }
 
 fn default_interval() -> u32 {
     60
 }
 
+fn default_audit_retention() -> u32 {
+    90
+}
+
 impl Default for CleanupConfig {
     fn default() -> Self {
         Self {
@@ -47,6 +58,7 @@ impl Default for CleanupConfig {
             max_age_days: 0,
             max_total_gb: 0,
             interval_minutes: default_interval(),
+            audit_retention_days: default_audit_retention(),
         }
     }
 }
@@ -99,20 +111,30 @@ pub fn spawn_worker(cancel: CancellationToken) {
     });
 }
 
-/// One retention pass. No-op unless enabled.
+/// One retention pass: audit pruning, then segment cleanup (no-op unless
+/// enabled).
 async fn run_once() -> Result<()> {
     let cfg = load_config().await?;
+    if let Err(e) = crate::audit::prune(cfg.audit_retention_days).await {
+        log::warn!("record cleanup: audit prune failed: {e:#}");
+    }
     if !cfg.enabled {
         return Ok(());
     }
     let conn = app_db_conn()?;
+    let targets = nvr_db::transport_target::list_enabled(&conn).await?;
     let mut removed = 0usize;
     let mut freed: u64 = 0;
+    let mut kept = 0usize;
 
     // 1) Age rule: drop everything older than the cutoff.
     if cfg.max_age_days > 0 {
         let expired = record_segment::list_older_than_days(cfg.max_age_days, &conn).await?;
         for seg in expired {
+            if crate::transport::awaits_upload(&seg, &targets, &conn).await? {
+                kept += 1;
+                continue;
+            }
             freed += seg.file_size as u64;
             remove_segment(&seg, &conn).await;
             removed += 1;
@@ -131,6 +153,10 @@ async fn run_once() -> Result<()> {
                 if total <= cap {
                     break;
                 }
+                if crate::transport::awaits_upload(&seg, &targets, &conn).await? {
+                    kept += 1;
+                    continue;
+                }
                 let size = seg.file_size as u64;
                 remove_segment(&seg, &conn).await;
                 total = total.saturating_sub(size);
@@ -146,12 +172,16 @@ async fn run_once() -> Result<()> {
             freed / (1024 * 1024)
         );
     }
+    if kept > 0 {
+        log::info!("record cleanup: kept {kept} segment(s) not uploaded yet");
+    }
     Ok(())
 }
 
 /// Remove one segment's file (best-effort) and its DB row.
-async fn remove_segment(seg: &RecordSegment, conn: &turso::Connection) {
+pub(crate) async fn remove_segment(seg: &RecordSegment, conn: &turso::Connection) {
     remove_file(&seg.file_path).await;
+    crate::storyboard::remove_cache(&seg.file_path).await;
     if let Err(e) = record_segment::delete(&seg.id, conn).await {
         log::warn!("record cleanup: db delete '{}' failed: {e:#}", seg.id);
     }
@@ -168,3 +198,7 @@ async fn remove_file(path: &str) {
         Err(e) => log::warn!("record cleanup: delete file '{path}' failed: {e:#}"),
     }
 }
+
+#[cfg(test)]
+#[path = "cleanup_test.rs"]
+mod cleanup_test;
@@ -0,0 +1,81 @@
+use nvr_db::transport_job::{self, STATUS_DONE, TransportJob};
+use nvr_db::transport_target::{self, TransportTarget};
+
+use super::*;
+
+#[tokio::test]
+async fn retention_keeps_segments_not_uploaded_yet() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    save_config(&CleanupConfig {
+        enabled: true,
+        max_age_days: 1,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let stream = format!("cam-{}", uuid::Uuid::new_v4().simple());
+    let path = std::env::temp_dir().join(format!("nvr-cleanup-{stream}.mp4"));
+    std::fs::write(&path, b"only copy").unwrap();
+    let expired = chrono::Utc::now() - chrono::Duration::days(3);
+    let segment = RecordSegment {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        file_name: "old.mp4".to_string(),
+        file_path: path.to_string_lossy().into_owned(),
+        stream: stream.clone(),
+        create_time: expired,
+        update_time: expired,
+        ..Default::default()
+    };
+    record_segment::upsert(&segment, &conn).await.unwrap();
+    let now = chrono::Utc::now().to_rfc3339();
+    let target = TransportTarget {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        name: "offsite".to_string(),
+        kind: "s3".to_string(),
+        enabled: true,
+        config: format!(
+            r#"{{"endpoint":"http://127.0.0.1:9","bucket":"b","devices":["{stream}"]}}"#
+        ),
+        remark: String::new(),
+        create_time: now.clone(),
+        update_time: now.clone(),
+    };
+    transport_target::upsert(&target, &conn).await.unwrap();
+
+    // Expired, but the target has no copy yet.
+    run_once().await.unwrap();
+    assert!(path.exists());
+    assert!(
+        record_segment::get(&segment.id, &conn)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    let job = TransportJob {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        segment_id: segment.id.clone(),
+        target_id: target.id.clone(),
+        status: STATUS_DONE,
+        attempts: 1,
+        remote_key: format!("{stream}/old.mp4"),
+        file_size: 9,
+        error: String::new(),
+        create_time: now.clone(),
+        update_time: now,
+        next_attempt_time: String::new(),
+    };
+    transport_job::upsert(&job, &conn).await.unwrap();
+    run_once().await.unwrap();
+    assert!(!path.exists());
+    assert!(
+        record_segment::get(&segment.id, &conn)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    transport_target::delete(&target.id, &conn).await.unwrap();
+    save_config(&CleanupConfig::default()).await.unwrap();
+}
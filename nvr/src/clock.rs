@@ -0,0 +1,308 @@
+//! Camera clock skew detection. A background worker estimates each device's
+//! clock offset from ours (camera minus server): from RTCP sender reports for
+//! RTSP pipes (`Pipe::clock_offset`), falling back to ONVIF
+//! `GetSystemDateAndTime` for ONVIF devices without RTCP timing. The latest
+//! offset per device is kept in memory for the device list and diagnostics,
+//! and crossing the configured threshold (either way) emits a skew event.
+//!
+//! The policy lives in the KV config (`clock_skew`). With
+//! `correct_recordings` set, record segments are stamped with the camera's
+//! time (server time plus the measured offset) so they line up with the
+//! burned-in timestamp overlay.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::db::app_db_conn;
+
+/// KV config key for the skew policy.
+const CLOCK_KEY: &str = "clock_skew";
+/// Delay before the first pass, so pipes have read a few RTCP reports.
+const STARTUP_DELAY: Duration = Duration::from_secs(20);
+/// Skew events kept for diagnostics.
+const EVENT_CAP: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockConfig {
+    /// Warn when a camera's |offset| exceeds this many milliseconds. 0
+    /// disables warnings (offsets are still measured).
+    #[serde(default = "default_threshold")]
+    pub warn_threshold_ms: u64,
+    /// Stamp record segments with the camera's time instead of ours.
+    #[serde(default)]
+    pub correct_recordings: bool,
+    /// How often offsets are measured, in seconds (clamped to >= 10).
+    #[serde(default = "default_interval")]
+    pub interval_seconds: u32,
+}
+
+fn default_threshold() -> u64 {
+    2000
+}
+
+fn default_interval() -> u32 {
+    60
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            warn_threshold_ms: default_threshold(),
+            correct_recordings: false,
+            interval_seconds: default_interval(),
+        }
+    }
+}
+
+impl ClockConfig {
+    /// Normalize user input (clamp the measuring interval).
+    pub fn sanitized(mut self) -> Self {
+        self.interval_seconds = self.interval_seconds.max(10);
+        self
+    }
+}
+
+pub async fn load_config() -> Result<ClockConfig> {
+    let conn = app_db_conn()?;
+    Ok(nvr_db::config::get_json::<ClockConfig>(CLOCK_KEY, &conn)
+        .await?
+        .unwrap_or_default())
+}
+
+pub async fn save_config(cfg: &ClockConfig) -> Result<()> {
+    let conn = app_db_conn()?;
+    nvr_db::config::set_json(CLOCK_KEY, cfg, &conn).await
+}
+
+/// Where an offset was measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OffsetSource {
+    Rtcp,
+    Onvif,
+}
+
+/// The latest measured offset of one device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct DeviceClock {
+    /// Camera clock minus server clock.
+    pub offset_ms: i64,
+    pub source: OffsetSource,
+    /// Unix milliseconds.
+    pub measured_at_ms: i64,
+    /// Whether |offset| exceeds the warning threshold.
+    pub skewed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkewEventKind {
+    /// |offset| rose above the threshold.
+    Exceeded,
+    /// |offset| fell back to within the threshold.
+    Recovered,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkewEvent {
+    pub device_id: String,
+    pub kind: SkewEventKind,
+    pub offset_ms: i64,
+    pub threshold_ms: u64,
+    /// Unix milliseconds.
+    pub ts_ms: i64,
+}
+
+static CLOCKS: LazyLock<RwLock<HashMap<String, DeviceClock>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+static EVENTS: LazyLock<Mutex<VecDeque<SkewEvent>>> = LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+/// The latest offset of `device_id`, if one has been measured.
+pub fn device_clock(device_id: &str) -> Option<DeviceClock> {
+    CLOCKS.read().unwrap().get(device_id).cloned()
+}
+
+/// Every device's latest offset.
+pub fn device_clocks() -> HashMap<String, DeviceClock> {
+    CLOCKS.read().unwrap().clone()
+}
+
+/// Recent skew events, oldest first.
+pub fn recent_events() -> Vec<SkewEvent> {
+    EVENTS.lock().unwrap().iter().cloned().collect()
+}
+
+fn is_skewed(offset_ms: i64, threshold_ms: u64) -> bool {
+    threshold_ms > 0 && offset_ms.unsigned_abs() > threshold_ms
+}
+
+/// The event (if any) a new measurement triggers for a device that was (or
+/// was not) skewed: only threshold crossings fire, so a camera that stays
+/// off does not warn on every measurement.
+pub(crate) fn evaluate(
+    was_skewed: bool,
+    offset_ms: i64,
+    threshold_ms: u64,
+) -> Option<SkewEventKind> {
+    match (was_skewed, is_skewed(offset_ms, threshold_ms)) {
+        (false, true) => Some(SkewEventKind::Exceeded),
+        (true, false) => Some(SkewEventKind::Recovered),
+        _ => None,
+    }
+}
+
+/// Store a measurement and emit the skew event it triggers, if any.
+pub(crate) fn record(
+    device_id: &str,
+    offset_ms: i64,
+    source: OffsetSource,
+    measured_at_ms: i64,
+    threshold_ms: u64,
+) -> Option<SkewEvent> {
+    let was_skewed = device_clock(device_id).is_some_and(|c| c.skewed);
+    CLOCKS.write().unwrap().insert(
+        device_id.to_string(),
+        DeviceClock {
+            offset_ms,
+            source,
+            measured_at_ms,
+            skewed: is_skewed(offset_ms, threshold_ms),
+        },
+    );
+    let kind = evaluate(was_skewed, offset_ms, threshold_ms)?;
+    let event = SkewEvent {
+        device_id: device_id.to_string(),
+        kind,
+        offset_ms,
+        threshold_ms,
+        ts_ms: measured_at_ms,
+    };
+    match kind {
+        SkewEventKind::Exceeded => log::warn!(
+            "clock: device {device_id} clock is off by {offset_ms} ms (> {threshold_ms} ms, via {source:?}); \
+             burned-in timestamps will not match recording times"
+        ),
+        SkewEventKind::Recovered => log::info!(
+            "clock: device {device_id} clock back within {threshold_ms} ms (offset {offset_ms} ms)"
+        ),
+    }
+    let mut events = EVENTS.lock().unwrap();
+    if events.len() == EVENT_CAP {
+        events.pop_front();
+    }
+    events.push_back(event.clone());
+    Some(event)
+}
+
+/// Drop the offsets of devices not in `ids`.
+fn retain(ids: &[String]) {
+    CLOCKS.write().unwrap().retain(|id, _| ids.contains(id));
+}
+
+/// `start_secs` (a record segment's start, server clock) shifted onto the
+/// camera's clock by its last measured offset.
+pub(crate) fn correct(start_secs: u64, clock: Option<&DeviceClock>) -> u64 {
+    match clock {
+        Some(c) => {
+            let shifted = start_secs as i64 * 1000 + c.offset_ms;
+            (shifted.max(0) as f64 / 1000.0).round() as u64
+        }
+        None => start_secs,
+    }
+}
+
+/// A record segment's start time as stored: corrected by the device's offset
+/// when `correct_recordings` is on, unchanged otherwise.
+pub async fn recording_start_time(stream: &str, start_secs: u64) -> u64 {
+    match load_config().await {
+        Ok(cfg) if cfg.correct_recordings => correct(start_secs, device_clock(stream).as_ref()),
+        _ => start_secs,
+    }
+}
+
+/// Measure one device: RTCP timing of its running pipe, else ONVIF.
+async fn measure(device_id: &str) -> Option<(i64, OffsetSource, i64)> {
+    if let Some(pipe) = crate::manager::get_pipe(device_id).await
+        && let Some(offset) = pipe.clock_offset().await
+    {
+        return Some((offset.offset_ms, OffsetSource::Rtcp, offset.measured_at_ms));
+    }
+    let cfg = crate::onvif::get(device_id)?;
+    match measure_onvif(&cfg).await {
+        Ok(offset_ms) => Some((offset_ms, OffsetSource::Onvif, now_ms())),
+        Err(e) => {
+            log::debug!("clock: onvif time of {device_id} unavailable: {e}");
+            None
+        }
+    }
+}
+
+/// Camera minus server time from `GetSystemDateAndTime`, taken against the
+/// midpoint of the request. The camera reports whole seconds, so this is
+/// only good to about ±500 ms.
+async fn measure_onvif(cfg: &nvr_onvif::OnvifConfig) -> Result<i64, nvr_onvif::OnvifError> {
+    let camera = nvr_onvif::OnvifCamera::connect(cfg).await?;
+    let before = now_ms();
+    let time = camera.system_time().await?;
+    let after = now_ms();
+    let camera_ms = time
+        .unix_seconds()
+        .ok_or_else(|| nvr_onvif::OnvifError::Protocol(format!("invalid camera time {time:?}")))?
+        * 1000;
+    Ok(camera_ms - (before + after) / 2)
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+async fn run_once() -> Result<()> {
+    let cfg = load_config().await?;
+    let devices = nvr_db::device::list(&app_db_conn()?).await?;
+    let ids = devices.iter().map(|d| d.id.clone()).collect::<Vec<_>>();
+    retain(&ids);
+    for id in &ids {
+        if let Some((offset_ms, source, measured_at_ms)) = measure(id).await {
+            record(id, offset_ms, source, measured_at_ms, cfg.warn_threshold_ms);
+        }
+    }
+    Ok(())
+}
+
+/// Spawn the measuring worker; it runs until `cancel` fires. The cadence is
+/// read from the config each cycle.
+pub fn spawn_worker(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        log::info!("clock: worker started");
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(STARTUP_DELAY) => {}
+        }
+        loop {
+            if let Err(e) = run_once().await {
+                log::warn!("clock: pass failed: {e:#}");
+            }
+            let seconds = load_config()
+                .await
+                .map(|c| c.interval_seconds.max(10))
+                .unwrap_or(60);
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    log::info!("clock: worker stopped");
+                    return;
+                }
+                _ = tokio::time::sleep(Duration::from_secs(seconds as u64)) => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+#[path = "clock_test.rs"]
+mod clock_test;
@@ -0,0 +1,62 @@
+use super::*;
+
+#[test]
+fn only_threshold_crossings_fire() {
+    assert_eq!(evaluate(false, 500, 2000), None);
+    assert_eq!(evaluate(false, -2500, 2000), Some(SkewEventKind::Exceeded));
+    assert_eq!(evaluate(true, 3000, 2000), None);
+    assert_eq!(evaluate(true, 1999, 2000), Some(SkewEventKind::Recovered));
+    // Exactly at the threshold is still fine.
+    assert_eq!(evaluate(false, 2000, 2000), None);
+}
+
+#[test]
+fn zero_threshold_disables_warnings() {
+    assert_eq!(evaluate(false, 60_000, 0), None);
+    assert_eq!(evaluate(true, 60_000, 0), Some(SkewEventKind::Recovered));
+}
+
+#[test]
+fn record_keeps_the_latest_offset_and_logs_crossings() {
+    let id = "clock-test-cam";
+    assert_eq!(record(id, 300, OffsetSource::Rtcp, 1, 2000), None);
+    let exceeded = record(id, 5_000, OffsetSource::Rtcp, 2, 2000).unwrap();
+    assert_eq!(exceeded.kind, SkewEventKind::Exceeded);
+    assert_eq!(record(id, 5_100, OffsetSource::Rtcp, 3, 2000), None);
+    let recovered = record(id, -100, OffsetSource::Onvif, 4, 2000).unwrap();
+    assert_eq!(recovered.kind, SkewEventKind::Recovered);
+
+    assert_eq!(
+        device_clock(id),
+        Some(DeviceClock {
+            offset_ms: -100,
+            source: OffsetSource::Onvif,
+            measured_at_ms: 4,
+            skewed: false,
+        })
+    );
+    let events = recent_events()
+        .into_iter()
+        .filter(|e| e.device_id == id)
+        .map(|e| (e.kind, e.offset_ms))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        vec![
+            (SkewEventKind::Exceeded, 5_000),
+            (SkewEventKind::Recovered, -100)
+        ]
+    );
+}
+
+#[test]
+fn recording_start_is_shifted_onto_the_camera_clock() {
+    let clock = DeviceClock {
+        offset_ms: -3_400,
+        source: OffsetSource::Rtcp,
+        measured_at_ms: 0,
+        skewed: true,
+    };
+    assert_eq!(correct(1_700_000_000, Some(&clock)), 1_699_999_997);
+    assert_eq!(correct(1_700_000_000, None), 1_700_000_000);
+}
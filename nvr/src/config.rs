@@ -1,7 +1,16 @@
 use std::path::PathBuf;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, RwLock};
 
+use crate::detect::analytics::AnalyticsConfig;
+use crate::encryption::EncryptionConfig;
+use crate::federation::FederationConfig;
 use crate::gb::config::GbConfig;
+use crate::supervisor::RestartPolicy;
+use crate::thumbnail::ThumbnailConfig;
+use crate::viewers::ViewerLimits;
+
+/// Default of `NVR_ON_DEMAND_GRACE_SECS`.
+const DEFAULT_ON_DEMAND_GRACE_SECS: u64 = 30;
 
 pub struct NvrConfig {
     db_url: String,
@@ -10,6 +19,39 @@ pub struct NvrConfig {
     record_dir: Option<String>,
     /// GB28181 platform config, or `None` when disabled (`NVR_GB_ENABLE != 1`).
     gb: Option<GbConfig>,
+    /// Peer NVR nodes whose devices are merged into the local listing.
+    federation: FederationConfig,
+    /// Warm device-grid thumbnails (`NVR_THUMBNAIL_*`).
+    thumbnail: ThumbnailConfig,
+    /// Rewrite closed MP4 segments as faststart MP4 (`NVR_RECORD_FASTSTART=1`).
+    record_faststart: bool,
+    /// Encryption at rest of recordings (`NVR_ENCRYPT_RECORDINGS`, ...).
+    encryption: EncryptionConfig,
+    /// Bytes of media in flight across all pipes (`NVR_MEMORY_BUDGET_MB`).
+    memory_budget: Option<usize>,
+    /// Zone of devices without their own (`NVR_TIMEZONE`).
+    timezone: Option<chrono_tz::Tz>,
+    /// The server's zone, the fallback of [`Self::timezone`].
+    system_timezone: chrono_tz::Tz,
+    /// Recordings root and zone picked in first-run setup, used where the
+    /// environment sets none (see [`crate::setup`]).
+    chosen: RwLock<Chosen>,
+    /// Concurrent live sessions allowed (`NVR_MAX_VIEWERS*`).
+    viewer_limits: ViewerLimits,
+    /// Per-device object detection (`NVR_DETECT_*`).
+    analytics: AnalyticsConfig,
+    /// Serve the Swagger UI (`NVR_API_DOCS=1`).
+    api_docs: bool,
+    /// Days of hourly data usage kept (`NVR_USAGE_HOUR_RETENTION_DAYS`).
+    usage_hour_retention_days: u32,
+    /// When ended pipes are started again (`NVR_RESTART_*`).
+    restart_policy: RestartPolicy,
+    /// Where path-valued device input options may point
+    /// (`NVR_INPUT_FILE_DIRS`).
+    input_file_dirs: Vec<PathBuf>,
+    /// Grace period of on-demand pipes, `None` when every pipe runs all the
+    /// time (`NVR_ON_DEMAND`, `NVR_ON_DEMAND_GRACE_SECS`).
+    on_demand: Option<std::time::Duration>,
 }
 
 impl NvrConfig {
@@ -21,6 +63,49 @@ impl NvrConfig {
                 .map(|dir| dir.trim().to_string())
                 .filter(|dir| !dir.is_empty()),
             gb: GbConfig::from_env(),
+            federation: FederationConfig::from_env(),
+            thumbnail: ThumbnailConfig::from_env(),
+            record_faststart: std::env::var("NVR_RECORD_FASTSTART")
+                .is_ok_and(|v| matches!(v.trim(), "1" | "true")),
+            encryption: EncryptionConfig::from_env(),
+            memory_budget: std::env::var("NVR_MEMORY_BUDGET_MB")
+                .ok()
+                .and_then(|mb| mb.trim().parse::<usize>().ok())
+                .filter(|mb| *mb > 0)
+                .map(|mb| mb.saturating_mul(1024 * 1024)),
+            timezone: std::env::var("NVR_TIMEZONE")
+                .ok()
+                .and_then(|name| name.trim().parse().ok()),
+            system_timezone: iana_time_zone::get_timezone()
+                .ok()
+                .and_then(|name| name.parse().ok())
+                .unwrap_or(chrono_tz::UTC),
+            chosen: RwLock::default(),
+            viewer_limits: ViewerLimits::from_env(),
+            analytics: AnalyticsConfig::from_env(),
+            api_docs: std::env::var("NVR_API_DOCS").is_ok_and(|v| matches!(v.trim(), "1" | "true")),
+            usage_hour_retention_days: std::env::var("NVR_USAGE_HOUR_RETENTION_DAYS")
+                .ok()
+                .and_then(|days| days.trim().parse::<u32>().ok())
+                .filter(|days| *days > 0)
+                .unwrap_or(crate::usage::DEFAULT_HOUR_RETENTION_DAYS),
+            restart_policy: RestartPolicy::from_env(),
+            input_file_dirs: std::env::var("NVR_INPUT_FILE_DIRS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            on_demand: std::env::var("NVR_ON_DEMAND")
+                .is_ok_and(|v| matches!(v.trim(), "1" | "true"))
+                .then(|| {
+                    let secs = std::env::var("NVR_ON_DEMAND_GRACE_SECS")
+                        .ok()
+                        .and_then(|secs| secs.trim().parse::<u64>().ok())
+                        .unwrap_or(DEFAULT_ON_DEMAND_GRACE_SECS);
+                    std::time::Duration::from_secs(secs)
+                }),
         }
     }
 
@@ -33,16 +118,129 @@ impl NvrConfig {
         self.gb.as_ref()
     }
 
-    /// Root directory where recordings are archived. Set via `NVR_RECORD_DIR`;
-    /// when unset, defaults to `<cwd>/data/records`.
+    /// Federation config (`NVR_NODE_NAME`, `NVR_FEDERATION_PEERS`); no peers
+    /// when unset.
+    pub fn federation(&self) -> &FederationConfig {
+        &self.federation
+    }
+
+    /// Thumbnail capture settings (`NVR_THUMBNAIL_INTERVAL_SECS`,
+    /// `NVR_THUMBNAIL_HISTORY`, `NVR_THUMBNAIL_WIDTH`, `NVR_THUMBNAIL_DIR`).
+    pub fn thumbnail(&self) -> &ThumbnailConfig {
+        &self.thumbnail
+    }
+
+    /// Whether archived MP4 segments are rewritten as faststart MP4 (index
+    /// first) once closed; set via `NVR_RECORD_FASTSTART=1`, off by default.
+    pub fn record_faststart(&self) -> bool {
+        self.record_faststart
+    }
+
+    /// Encryption at rest settings: whether every device's segments are
+    /// sealed (`NVR_ENCRYPT_RECORDINGS=1`), the key file
+    /// (`NVR_ENCRYPTION_KEYS`) and where decrypted copies go
+    /// (`NVR_DECRYPT_DIR`).
+    pub fn encryption(&self) -> &EncryptionConfig {
+        &self.encryption
+    }
+
+    /// Limit on the bytes of media in flight across all pipes, past which
+    /// inputs pause reading; set via `NVR_MEMORY_BUDGET_MB`, unlimited when
+    /// unset.
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// IANA zone that schedules, segment file names and timeline days of
+    /// devices without a zone of their own are in; set via `NVR_TIMEZONE` or
+    /// in first-run setup, defaulting to the server's zone (UTC if it cannot
+    /// be determined).
+    pub fn timezone(&self) -> chrono_tz::Tz {
+        self.timezone
+            .or(self.chosen.read().unwrap().timezone)
+            .unwrap_or(self.system_timezone)
+    }
+
+    /// Most concurrent live sessions, globally (`NVR_MAX_VIEWERS`), per
+    /// device (`NVR_MAX_VIEWERS_PER_DEVICE`) and per user
+    /// (`NVR_MAX_VIEWERS_PER_USER`); unlimited when unset.
+    pub fn viewer_limits(&self) -> ViewerLimits {
+        self.viewer_limits
+    }
+
+    /// Analytics tap settings: the inference sidecar (`NVR_DETECT_URL`,
+    /// `NVR_DETECT_TIMEOUT_MS`), the sample rate and size
+    /// (`NVR_DETECT_INTERVAL_MS`, `NVR_DETECT_WIDTH`) and when events end
+    /// (`NVR_DETECT_EVENT_GAP_SECS`).
+    pub fn analytics(&self) -> &AnalyticsConfig {
+        &self.analytics
+    }
+
+    /// Whether `GET /api/v1/docs` serves a Swagger UI of the OpenAPI
+    /// document; set via `NVR_API_DOCS=1`, off by default.
+    pub fn api_docs(&self) -> bool {
+        self.api_docs
+    }
+
+    /// How long hourly data usage is kept once rolled up into days and
+    /// months, which are kept for good; set via
+    /// `NVR_USAGE_HOUR_RETENTION_DAYS`, 31 days by default.
+    pub fn usage_hour_retention(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(u64::from(self.usage_hour_retention_days) * 86_400)
+    }
+
+    /// Backoff and flap parking of pipes that end on their own
+    /// (`NVR_RESTART_INITIAL_SECS`, `NVR_RESTART_MAX_SECS`,
+    /// `NVR_RESTART_HEALTHY_SECS`, `NVR_RESTART_FLAP_COUNT`,
+    /// `NVR_RESTART_FLAP_WINDOW_SECS`, `NVR_RESTART_COOL_DOWN_SECS`).
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy
+    }
+
+    /// Directories the path-valued custom input options of a device (TLS
+    /// certificates and keys) may point into; set via `NVR_INPUT_FILE_DIRS`
+    /// (comma-separated absolute paths), none by default.
+    pub fn input_file_dirs(&self) -> &[PathBuf] {
+        &self.input_file_dirs
+    }
+
+    /// With `NVR_ON_DEMAND=1`, how long the pipe of a device that does not
+    /// record keeps running once its last viewer goes (see
+    /// [`crate::manager::acquire_pipe`]); `NVR_ON_DEMAND_GRACE_SECS`, 30
+    /// seconds by default. `None`, every pipe running all the time, when off.
+    pub fn on_demand_grace(&self) -> Option<std::time::Duration> {
+        self.on_demand
+    }
+
+    /// Root directory where recordings are archived. Set via `NVR_RECORD_DIR`
+    /// or in first-run setup; when unset, defaults to `<cwd>/data/records`.
     pub fn record_dir(&self) -> PathBuf {
         if let Some(dir) = &self.record_dir {
             return PathBuf::from(dir);
         }
+        if let Some(dir) = &self.chosen.read().unwrap().record_dir {
+            return dir.clone();
+        }
         std::env::current_dir()
             .map(|cwd| cwd.join("data").join("records"))
             .unwrap_or_else(|_| PathBuf::from("data").join("records"))
     }
+
+    /// Apply the recordings root and zone picked in first-run setup; the
+    /// environment variables still take precedence.
+    pub fn choose(&self, record_dir: Option<PathBuf>, timezone: Option<chrono_tz::Tz>) {
+        *self.chosen.write().unwrap() = Chosen {
+            record_dir,
+            timezone,
+        };
+    }
+}
+
+/// Settings picked in first-run setup; `None` where the default applies.
+#[derive(Default)]
+struct Chosen {
+    record_dir: Option<PathBuf>,
+    timezone: Option<chrono_tz::Tz>,
 }
 
 pub fn config() -> &'static NvrConfig {
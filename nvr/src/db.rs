@@ -58,3 +58,24 @@ fn get_app_db() -> anyhow::Result<&'static NvrDatabase> {
 pub(crate) fn app_db_conn() -> anyhow::Result<turso::Connection> {
     get_app_db()?.connect()
 }
+
+/// Serializes the DB-writing tests: turso allows one WAL writer, and parallel
+/// test bodies hitting the shared in-memory APP_DB otherwise fail with
+/// intermittent "database is locked" (see the write-contention note above).
+#[cfg(test)]
+static TEST_DB_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Initialize the process-wide APP_DB once (all tests share one binary) with
+/// an in-memory database carrying the full migrated schema, and take the
+/// serialization lock for the calling test.
+#[cfg(test)]
+pub(crate) async fn test_db() -> tokio::sync::MutexGuard<'static, ()> {
+    static INIT: tokio::sync::OnceCell<()> = tokio::sync::OnceCell::const_new();
+    INIT.get_or_init(|| async {
+        let db = init_app_db(":memory:").await.unwrap();
+        let mut conn = db.connect().unwrap();
+        nvr_db::migrations::migrate_conn(&mut conn).await.unwrap();
+    })
+    .await;
+    TEST_DB_LOCK.lock().await
+}
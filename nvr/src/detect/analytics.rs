@@ -0,0 +1,464 @@
+//! Always-on object detection for devices that ask for it. A device's
+//! [`DetectionSettings`] name a detector (see [`super::sidecar`]), the labels
+//! it cares about and their confidence thresholds. A background worker keeps
+//! one analytics tap running per such device while its pipe is up (and out of
+//! privacy mode): the tap samples the decoded video every
+//! `NVR_DETECT_INTERVAL_MS`, downscales the frame to `NVR_DETECT_WIDTH` and
+//! hands it to the detector. A frame arriving while the previous one is still
+//! being inferred is skipped, never queued, so a slow sidecar lowers the
+//! sample rate instead of building a backlog.
+//!
+//! Detections become events through an [`EventTracker`]; with `bookmark` set
+//! each event's start is bookmarked on the device's timeline, so the
+//! recording around it is one click away. With `dead_air` set the tap also
+//! watches for a frozen or black picture (see [`super::deadair`]), and with
+//! `motion` for a moving one (see [`super::motion`]), with or without a
+//! detector.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use ffmpeg_bus::frame::{RawFrame, RawFrameCmd, RawFrameReceiver, RawVideoFrame};
+use nvr_db::device::DetectionSettings;
+use nvr_detect::Detection;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::deadair::{self, DeadAirDetector, Fingerprint};
+use super::events::{EventTracker, EventUpdate};
+use super::motion::{self, MotionDetector, MotionEvent, MotionEventKind};
+use super::sidecar::{AnalyticsFrame, Detector, build_detector};
+use crate::config::config;
+use crate::db::app_db_conn;
+
+/// Time between passes matching the running taps to the devices.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(10);
+/// How often a tap ends the events whose label is gone.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Analytics settings, parsed from environment variables.
+#[derive(Debug, Clone)]
+pub struct AnalyticsConfig {
+    /// The inference sidecar of the `http` detector (`NVR_DETECT_URL`).
+    pub url: Option<String>,
+    /// Longest a sidecar request may take.
+    pub timeout: Duration,
+    /// Time between sampled frames of a device.
+    pub interval: Duration,
+    /// Frames are downscaled to at most this width before detection.
+    pub width: u32,
+    /// An event ends once its label has been unseen this long.
+    pub event_gap: Duration,
+}
+
+impl AnalyticsConfig {
+    /// The sidecar and sampling settings from the `NVR_DETECT_*` values `get`
+    /// returns. No `NVR_DETECT_URL` means no sidecar; the timeout and interval
+    /// are 100ms at least.
+    pub fn from_map(get: impl Fn(&str) -> Option<String>) -> AnalyticsConfig {
+        let number = |key: &str, default: u64| {
+            get(key)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        AnalyticsConfig {
+            url: get("NVR_DETECT_URL")
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty()),
+            timeout: Duration::from_millis(number("NVR_DETECT_TIMEOUT_MS", 5000).max(100)),
+            interval: Duration::from_millis(number("NVR_DETECT_INTERVAL_MS", 1000).max(100)),
+            width: (number("NVR_DETECT_WIDTH", 640) as u32).max(16),
+            event_gap: Duration::from_secs(number("NVR_DETECT_EVENT_GAP_SECS", 10)),
+        }
+    }
+
+    /// Parse from the real process environment.
+    pub fn from_env() -> AnalyticsConfig {
+        Self::from_map(|k| std::env::var(k).ok())
+    }
+}
+
+/// Reject settings no tap could run: an unknown detector, `http` without a
+/// sidecar configured, a threshold outside 0..=1, dead-air settings that
+/// would call any picture frozen, or motion thresholds out of order.
+pub fn validate(settings: &DetectionSettings) -> anyhow::Result<()> {
+    check(settings, config().analytics().url.is_some())
+}
+
+fn check(settings: &DetectionSettings, has_url: bool) -> anyhow::Result<()> {
+    match settings.detector.as_str() {
+        "none" | "stub" => {}
+        "http" if !has_url => {
+            anyhow::bail!("the http detector needs an inference endpoint (NVR_DETECT_URL)")
+        }
+        "http" => {}
+        other => anyhow::bail!("unknown detector: {other} (expected http, stub or none)"),
+    }
+    let thresholds = settings.thresholds.iter().map(|(l, t)| (l.as_str(), *t));
+    for (label, threshold) in thresholds.chain([("min_confidence", settings.min_confidence)]) {
+        if !(0.0..=1.0).contains(&threshold) {
+            anyhow::bail!("threshold of {label} must be within 0..=1, got {threshold}");
+        }
+    }
+    if let Some(dead_air) = &settings.dead_air {
+        if dead_air.duration_secs < deadair::SAMPLE_INTERVAL.as_secs() {
+            anyhow::bail!(
+                "dead air duration_secs must be at least {}, got {}",
+                deadair::SAMPLE_INTERVAL.as_secs(),
+                dead_air.duration_secs
+            );
+        }
+        if dead_air.tolerance > 16 {
+            anyhow::bail!(
+                "dead air tolerance must be at most 16 bits, got {}",
+                dead_air.tolerance
+            );
+        }
+    }
+    if let Some(motion) = &settings.motion {
+        for (name, ratio) in [("enter", motion.enter_ratio), ("exit", motion.exit_ratio)] {
+            if !(0.0..=1.0).contains(&ratio) {
+                anyhow::bail!("motion {name}_ratio must be within 0..=1, got {ratio}");
+            }
+        }
+        if motion.exit_ratio > motion.enter_ratio {
+            anyhow::bail!(
+                "motion exit_ratio ({}) must not be above enter_ratio ({})",
+                motion.exit_ratio,
+                motion.enter_ratio
+            );
+        }
+    }
+    Ok(())
+}
+
+struct Tap {
+    settings: DetectionSettings,
+    cancel: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+/// Spawn the analytics worker; it runs until `cancel` fires, stopping every
+/// tap with it.
+pub fn spawn_worker(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(crate::manager::STARTUP_DELAY) => {}
+        }
+        let mut taps = HashMap::new();
+        loop {
+            if let Err(e) = reconcile(&mut taps, &cancel).await {
+                log::warn!("analytics: pass failed: {e:#}");
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tokio::time::sleep(RECONCILE_INTERVAL) => {}
+            }
+        }
+        for tap in taps.into_values() {
+            let _ = tap.task.await;
+        }
+        log::info!("analytics: worker stopped");
+    });
+}
+
+/// Stop the taps of devices that no longer want one (detection, dead-air and
+/// motion checks off, pipe stopped, privacy mode) or whose settings changed,
+/// and start the missing ones.
+async fn reconcile(
+    taps: &mut HashMap<String, Tap>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let mut wanted = HashMap::new();
+    for device in nvr_db::device::list(&app_db_conn()?).await? {
+        let Some(settings) = device.detection else {
+            continue;
+        };
+        if (settings.detector == "none" && settings.dead_air.is_none() && settings.motion.is_none())
+            || crate::privacy::is_private(&device.id)
+            || crate::manager::status(&device.id).await != Some(true)
+        {
+            continue;
+        }
+        wanted.insert(device.id, settings);
+    }
+
+    taps.retain(|id, tap| {
+        let keep = !tap.task.is_finished() && wanted.get(id) == Some(&tap.settings);
+        if !keep {
+            tap.cancel.cancel();
+        }
+        keep
+    });
+    for (id, settings) in wanted {
+        if taps.contains_key(&id) {
+            continue;
+        }
+        match start(&id, &settings, cancel).await {
+            Ok(tap) => {
+                log::info!("analytics[{id}]: tap started ({})", settings.detector);
+                taps.insert(id, tap);
+            }
+            Err(e) => log::debug!("analytics[{id}]: tap not started: {e:#}"),
+        }
+    }
+    Ok(())
+}
+
+async fn start(
+    id: &str,
+    settings: &DetectionSettings,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Tap> {
+    let cfg = config().analytics().clone();
+    let detector = build_detector(settings, cfg.url.as_deref(), cfg.timeout)?;
+    if detector.is_none() && settings.dead_air.is_none() && settings.motion.is_none() {
+        anyhow::bail!("no detector");
+    }
+    let pipe = crate::manager::get_pipe(id)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("pipe not found"))?;
+    let video = pipe.subscribe_video().await?;
+    let cancel = cancel.child_token();
+    let task = tokio::spawn(run(
+        id.to_string(),
+        settings.clone(),
+        detector,
+        video,
+        cfg,
+        cancel.clone(),
+    ));
+    Ok(Tap {
+        settings: settings.clone(),
+        cancel,
+        task,
+    })
+}
+
+type Inference = JoinHandle<anyhow::Result<(AnalyticsFrame, Vec<Detection>)>>;
+
+/// Drive one device's tap until `cancel` fires or the video broadcast ends,
+/// then end its open events and motion.
+async fn run(
+    device_id: String,
+    settings: DetectionSettings,
+    detector: Option<Arc<dyn Detector>>,
+    mut video: RawFrameReceiver,
+    cfg: AnalyticsConfig,
+    cancel: CancellationToken,
+) {
+    let gap_ms = cfg.event_gap.as_millis() as i64;
+    let mut tracker = EventTracker::new(&device_id, settings.clone(), gap_ms);
+    let mut expire = tokio::time::interval(EXPIRE_INTERVAL);
+    let mut last: Option<Instant> = None;
+    let mut inflight: Option<Inference> = None;
+    let mut skipped = 0u64;
+    let mut dead_air = settings.dead_air.clone().map(DeadAirDetector::new);
+    let mut last_fingerprint: Option<Instant> = None;
+    let mut movement = settings.motion.clone().map(MotionDetector::new);
+    let mut last_movement: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = expire.tick() => {
+                let updates = tracker.expire(chrono::Utc::now().timestamp_millis());
+                apply(&settings, updates).await;
+            }
+            done = async { inflight.as_mut().unwrap().await }, if inflight.is_some() => {
+                inflight = None;
+                match done {
+                    Ok(Ok((frame, detections))) => {
+                        let updates = tracker.observe(frame.ts, frame.width, frame.height, &detections);
+                        apply(&settings, updates).await;
+                    }
+                    Ok(Err(e)) => log::debug!("analytics[{device_id}]: {} failed: {e:#}", settings.detector),
+                    Err(e) => log::warn!("analytics[{device_id}]: inference task died: {e}"),
+                }
+            }
+            cmd = video.recv() => match cmd {
+                Ok(RawFrameCmd::Data(RawFrame::Video(vf))) => {
+                    let now = Instant::now();
+                    if let Some(dead_air) = dead_air.as_mut()
+                        && last_fingerprint.is_none_or(|l| now.duration_since(l) >= deadair::SAMPLE_INTERVAL)
+                    {
+                        last_fingerprint = Some(now);
+                        watch(&device_id, dead_air, &vf);
+                    }
+                    if let Some(movement) = movement.as_mut()
+                        && last_movement.is_none_or(|l| now.duration_since(l) >= motion::SAMPLE_INTERVAL)
+                    {
+                        last_movement = Some(now);
+                        sense(&device_id, &settings, movement, &vf).await;
+                    }
+                    let Some(detector) = &detector else {
+                        continue;
+                    };
+                    if last.is_some_and(|l| now.duration_since(l) < cfg.interval) {
+                        continue; // faster than the sample rate
+                    }
+                    if inflight.is_some() {
+                        // The detector is still busy with the last sample.
+                        skipped += 1;
+                        continue;
+                    }
+                    last = Some(now);
+                    inflight = Some(tokio::spawn(infer(
+                        device_id.clone(),
+                        vf,
+                        cfg.width,
+                        detector.clone(),
+                    )));
+                }
+                Ok(RawFrameCmd::Data(RawFrame::Audio(_))) => {}
+                Ok(RawFrameCmd::EOF) => break,
+                Err(RecvError::Lagged(n)) => {
+                    log::debug!("analytics[{device_id}]: dropped {n} frames (lag)");
+                }
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+    if let Some(task) = inflight {
+        task.abort();
+    }
+    apply(&settings, tracker.finish()).await;
+    deadair::forget(&device_id);
+    if let Some(movement) = movement.as_mut()
+        && movement.finish()
+    {
+        let ts = chrono::Utc::now().timestamp_millis();
+        moved(&device_id, &settings, MotionEventKind::Ended, 0.0, ts).await;
+    }
+    log::info!("analytics[{device_id}]: tap stopped ({skipped} samples skipped while busy)");
+}
+
+/// Fingerprint a sampled frame and record the dead-air change it makes.
+fn watch(device_id: &str, dead_air: &mut DeadAirDetector, frame: &RawVideoFrame) {
+    let fingerprint = match Fingerprint::of_frame(frame) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            log::debug!("analytics[{device_id}]: fingerprint failed: {e:#}");
+            return;
+        }
+    };
+    let ts = chrono::Utc::now().timestamp_millis();
+    if let Some(kind) = dead_air.observe(ts, fingerprint) {
+        deadair::record(device_id, kind, ts);
+    }
+}
+
+/// Compare a sampled frame with the last one and act on the motion change it
+/// makes.
+async fn sense(
+    device_id: &str,
+    settings: &DetectionSettings,
+    movement: &mut MotionDetector,
+    frame: &RawVideoFrame,
+) {
+    let picture = match motion::downsample(frame) {
+        Ok(picture) => picture,
+        Err(e) => {
+            log::debug!("analytics[{device_id}]: downsample failed: {e:#}");
+            return;
+        }
+    };
+    let ts = chrono::Utc::now().timestamp_millis();
+    if let Some((kind, score)) = movement.observe(ts, picture) {
+        moved(device_id, settings, kind, score, ts).await;
+    }
+}
+
+/// Publish a motion change, and start or stop the recording of a device that
+/// records motion.
+async fn moved(
+    device_id: &str,
+    settings: &DetectionSettings,
+    kind: MotionEventKind,
+    score: f32,
+    timestamp: i64,
+) {
+    motion::publish(MotionEvent {
+        device_id: device_id.to_string(),
+        kind,
+        score,
+        timestamp,
+    });
+    if !settings.motion.as_ref().is_some_and(|m| m.record) {
+        return;
+    }
+    let recorded = match kind {
+        MotionEventKind::Started => crate::manager::start_motion_recording(device_id).await,
+        MotionEventKind::Ended => crate::manager::stop_motion_recording(device_id).await,
+    };
+    if let Err(e) = recorded {
+        log::warn!("analytics[{device_id}]: motion recording: {e:#}");
+    }
+}
+
+/// Encode one sampled frame and run the detector on it.
+async fn infer(
+    device_id: String,
+    frame: RawVideoFrame,
+    max_width: u32,
+    detector: Arc<dyn Detector>,
+) -> anyhow::Result<(AnalyticsFrame, Vec<Detection>)> {
+    let ts = chrono::Utc::now().timestamp_millis();
+    let (width, height) = crate::snapshot::scaled_size(frame.width(), frame.height(), max_width);
+    let jpeg =
+        tokio::task::spawn_blocking(move || crate::snapshot::to_jpeg_scaled(&frame, max_width))
+            .await??;
+    let frame = AnalyticsFrame {
+        device_id,
+        ts,
+        width,
+        height,
+        jpeg: Bytes::from(jpeg),
+    };
+    let detections = detector.detect(&frame).await?;
+    Ok((frame, detections))
+}
+
+/// Record `updates` in the recent-event log, bookmarking started events when
+/// the device asks for it.
+async fn apply(settings: &DetectionSettings, updates: Vec<EventUpdate>) {
+    for update in updates {
+        super::events::record(&update);
+        let EventUpdate::Started(event) = update else {
+            continue;
+        };
+        log::info!(
+            "analytics[{}]: {} detected ({:.2})",
+            event.device_id,
+            event.label,
+            event.confidence
+        );
+        if settings.bookmark
+            && let Err(e) = bookmark(&event).await
+        {
+            log::warn!("analytics[{}]: bookmark failed: {e:#}", event.device_id);
+        }
+    }
+}
+
+async fn bookmark(event: &super::events::DetectionEvent) -> anyhow::Result<()> {
+    let bookmark = nvr_db::bookmark::Bookmark {
+        id: uuid::Uuid::new_v4().to_string(),
+        device_id: event.device_id.clone(),
+        ts: event.started_at,
+        label: format!("{} detected", event.label),
+        color: String::new(),
+        created_by: "detect".to_string(),
+        created_at: chrono::Utc::now().timestamp_millis(),
+        export_id: String::new(),
+    };
+    nvr_db::bookmark::insert(&bookmark, &app_db_conn()?).await
+}
+
+#[cfg(test)]
+#[path = "analytics_test.rs"]
+mod analytics_test;
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use super::*;
+
+fn settings(detector: &str) -> DetectionSettings {
+    DetectionSettings {
+        detector: detector.to_string(),
+        labels: vec!["person".to_string()],
+        thresholds: HashMap::new(),
+        min_confidence: 0.5,
+        bookmark: false,
+        dead_air: None,
+        motion: None,
+    }
+}
+
+#[test]
+fn config_defaults_and_overrides() {
+    let cfg = AnalyticsConfig::from_map(|_| None);
+    assert_eq!(cfg.url, None);
+    assert_eq!(cfg.timeout, Duration::from_secs(5));
+    assert_eq!(cfg.interval, Duration::from_secs(1));
+    assert_eq!(cfg.width, 640);
+    assert_eq!(cfg.event_gap, Duration::from_secs(10));
+
+    let env = HashMap::from([
+        ("NVR_DETECT_URL", " http://sidecar:8000/detect "),
+        ("NVR_DETECT_TIMEOUT_MS", "2000"),
+        ("NVR_DETECT_INTERVAL_MS", "10"),
+        ("NVR_DETECT_WIDTH", "416"),
+        ("NVR_DETECT_EVENT_GAP_SECS", "30"),
+    ]);
+    let cfg = AnalyticsConfig::from_map(|k| env.get(k).map(|v| v.to_string()));
+    assert_eq!(cfg.url.as_deref(), Some("http://sidecar:8000/detect"));
+    assert_eq!(cfg.timeout, Duration::from_secs(2));
+    // Clamped so a typo cannot run the detector on every frame.
+    assert_eq!(cfg.interval, Duration::from_millis(100));
+    assert_eq!(cfg.width, 416);
+    assert_eq!(cfg.event_gap, Duration::from_secs(30));
+}
+
+#[test]
+fn check_rejects_unrunnable_settings() {
+    assert!(check(&settings("none"), false).is_ok());
+    assert!(check(&settings("stub"), false).is_ok());
+    assert!(check(&settings("http"), true).is_ok());
+    assert!(check(&settings("http"), false).is_err());
+    assert!(check(&settings("yolo"), true).is_err());
+
+    let mut bad = settings("stub");
+    bad.thresholds.insert("car".to_string(), 1.5);
+    assert!(check(&bad, false).is_err());
+    let mut bad = settings("stub");
+    bad.min_confidence = -0.1;
+    assert!(check(&bad, false).is_err());
+
+    let dead_air = |duration_secs, tolerance| DetectionSettings {
+        dead_air: Some(nvr_db::device::DeadAirSettings {
+            duration_secs,
+            tolerance,
+            ..Default::default()
+        }),
+        ..settings("none")
+    };
+    assert!(check(&dead_air(60, 4), false).is_ok());
+    // Shorter than a sample, or so tolerant any two pictures match.
+    assert!(check(&dead_air(1, 4), false).is_err());
+    assert!(check(&dead_air(60, 40), false).is_err());
+}
+
+#[test]
+fn check_rejects_motion_thresholds_out_of_order() {
+    let motion = |enter_ratio, exit_ratio| DetectionSettings {
+        motion: Some(nvr_db::device::MotionSettings {
+            enter_ratio,
+            exit_ratio,
+            ..Default::default()
+        }),
+        ..settings("none")
+    };
+    assert!(check(&motion(0.02, 0.005), false).is_ok());
+    assert!(check(&motion(0.02, 0.02), false).is_ok());
+    assert!(check(&motion(0.01, 0.02), false).is_err());
+    assert!(check(&motion(1.5, 0.02), false).is_err());
+    assert!(check(&motion(0.02, -0.1), false).is_err());
+}
@@ -1,5 +1,6 @@
 //! Detection control + read endpoints. Opt-in start/stop per pipe; GET latest
-//! per-frame multi-model result. GET/POST only; session auth is applied by the
+//! per-frame multi-model result; GET the recent events of a device's analytics
+//! tap. GET/POST only; session auth is applied by the
 //! parent `/api` router.
 
 use axum::{
@@ -14,7 +15,7 @@ use tokio_util::sync::CancellationToken;
 
 use super::hub::DetectHub;
 
-#[derive(Deserialize, Default)]
+#[derive(Deserialize, Default, utoipa::ToSchema)]
 pub struct StartBody {
     /// Subset of configured model names to run. Absent/empty = all.
     #[serde(default)]
@@ -26,9 +27,25 @@ pub fn detect_router() -> Router {
         .route("/{pipe}/start", post(start))
         .route("/{pipe}/stop", post(stop))
         .route("/{pipe}/latest", get(latest))
+        .route("/{pipe}/events", get(events))
         .route("/models", get(models))
 }
 
+/// The schema of [`detect_router`], mounted at `/api/v1/detect`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(start, stop, latest, events, models))]
+pub(crate) struct DetectApi;
+
+/// Names of the configured models.
+#[utoipa::path(
+    get,
+    path = "/models",
+    tag = "events",
+    responses(
+        (status = 200, body = Vec<String>),
+        (status = 503, description = "Detection is not initialized", body = String),
+    )
+)]
 async fn models() -> impl IntoResponse {
     let Some(hub) = DetectHub::get() else {
         return (StatusCode::SERVICE_UNAVAILABLE, "detect not initialized").into_response();
@@ -36,6 +53,18 @@ async fn models() -> impl IntoResponse {
     Json(hub.config_names()).into_response()
 }
 
+/// The latest per-frame result of an opt-in detection run.
+#[utoipa::path(
+    get,
+    path = "/{pipe}/latest",
+    tag = "events",
+    params(("pipe" = String, Path)),
+    responses(
+        (status = 200, body = super::result::FrameResult),
+        (status = 404, description = "No result yet", body = String),
+        (status = 503, description = "Detection is not initialized", body = String),
+    )
+)]
 async fn latest(Path(pipe): Path<String>) -> impl IntoResponse {
     let Some(hub) = DetectHub::get() else {
         return (StatusCode::SERVICE_UNAVAILABLE, "detect not initialized").into_response();
@@ -46,6 +75,28 @@ async fn latest(Path(pipe): Path<String>) -> impl IntoResponse {
     }
 }
 
+/// The recent events of a device's analytics tap, oldest first.
+#[utoipa::path(
+    get,
+    path = "/{pipe}/events",
+    tag = "events",
+    params(("pipe" = String, Path, description = "Device id")),
+    responses((status = 200, body = Vec<super::events::DetectionEvent>))
+)]
+async fn events(Path(pipe): Path<String>) -> impl IntoResponse {
+    Json(super::events::recent(&pipe))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{pipe}/stop",
+    tag = "events",
+    params(("pipe" = String, Path)),
+    responses(
+        (status = 200, description = "`stopped` or `not running`", body = String),
+        (status = 503, description = "Detection is not initialized", body = String),
+    )
+)]
 async fn stop(Path(pipe): Path<String>) -> impl IntoResponse {
     let Some(hub) = DetectHub::get() else {
         return (StatusCode::SERVICE_UNAVAILABLE, "detect not initialized").into_response();
@@ -57,10 +108,28 @@ async fn stop(Path(pipe): Path<String>) -> impl IntoResponse {
     }
 }
 
+/// Start an opt-in detection run on a pipe's video.
+#[utoipa::path(
+    post,
+    path = "/{pipe}/start",
+    tag = "events",
+    params(("pipe" = String, Path)),
+    request_body(content = Option<StartBody>, description = "Models to run; all when absent"),
+    responses(
+        (status = 200, description = "`started` or `already running`", body = String),
+        (status = 400, description = "No video or no matching models", body = String),
+        (status = 404, description = "No such pipe", body = String),
+        (status = 423, description = "The device is in privacy mode", body = String),
+        (status = 503, description = "Detection is not initialized", body = String),
+    )
+)]
 async fn start(Path(pipe): Path<String>, body: Option<Json<StartBody>>) -> impl IntoResponse {
     let Some(hub) = DetectHub::get() else {
         return (StatusCode::SERVICE_UNAVAILABLE, "detect not initialized").into_response();
     };
+    if crate::privacy::is_private(&pipe) {
+        return (StatusCode::LOCKED, "privacy mode enabled").into_response();
+    }
     if hub.is_running(&pipe) {
         return (StatusCode::OK, "already running").into_response();
     }
@@ -0,0 +1,216 @@
+//! Dead air: a camera that keeps streaming while its picture is frozen (the
+//! encoder repeating its last frame) or black (lens covered, failed sensor),
+//! which the transport counters behind [`crate::health`] cannot tell from a
+//! working one. The analytics tap of a device with [`DeadAirSettings`]
+//! samples a frame every [`SAMPLE_INTERVAL`] and reduces it to a
+//! [`Fingerprint`]: the difference hash of a 9x8 grey thumbnail and its mean
+//! luma. A [`DeadAirDetector`] calls the picture frozen once every sample for
+//! the settings' `duration_secs` stayed within `tolerance` bits (and a few
+//! luma steps) of the first, and black once each was darker than
+//! `black_below`; the first sample that differs again clears it.
+//!
+//! Changes are kept as recent [`DeadAirEvent`]s, and a device's current
+//! condition costs it health points.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::Duration;
+
+use ffmpeg_bus::frame::RawVideoFrame;
+use ffmpeg_next::format::Pixel;
+use nvr_db::device::DeadAirSettings;
+use serde::Serialize;
+
+/// Time between two fingerprinted frames of a device (0.2 fps).
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// Size of the grey thumbnail a frame is hashed from: a row of 9 compares 8
+/// neighbours, 8 rows make 64 bits.
+const HASH_WIDTH: usize = 9;
+const HASH_HEIGHT: usize = 8;
+/// Mean luma a frame may drift by and still be the same picture; a flat
+/// picture hashes the same at any brightness.
+const LUMA_TOLERANCE: f64 = 8.0;
+/// Dead-air events kept for diagnostics.
+const EVENT_CAP: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadAir {
+    Frozen,
+    Black,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadAirEventKind {
+    /// The picture stopped changing.
+    Frozen,
+    /// The picture went dark.
+    Black,
+    /// The picture is live again.
+    Cleared,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct DeadAirEvent {
+    pub device_id: String,
+    pub kind: DeadAirEventKind,
+    /// Unix milliseconds of the sample that made the change.
+    pub ts_ms: i64,
+}
+
+/// What a sampled frame is compared by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    /// One bit per pair of horizontal neighbours: whether the right one is
+    /// brighter.
+    pub hash: u64,
+    /// Mean luma, 0–255.
+    pub luma: f64,
+}
+
+impl Fingerprint {
+    /// The fingerprint of a 9x8 grey picture, row by row.
+    pub fn of_gray(pixels: &[u8]) -> Fingerprint {
+        assert_eq!(pixels.len(), HASH_WIDTH * HASH_HEIGHT);
+        let mut hash = 0u64;
+        for row in pixels.chunks(HASH_WIDTH) {
+            for pair in row.windows(2) {
+                hash = hash << 1 | u64::from(pair[0] < pair[1]);
+            }
+        }
+        let luma = pixels.iter().map(|p| *p as f64).sum::<f64>() / pixels.len() as f64;
+        Fingerprint { hash, luma }
+    }
+
+    /// The fingerprint of a decoded frame, scaled down to 9x8 grey.
+    pub fn of_frame(frame: &RawVideoFrame) -> anyhow::Result<Fingerprint> {
+        let gray = frame.scale(Pixel::GRAY8, HASH_WIDTH as u32, HASH_HEIGHT as u32)?;
+        let stride = gray.stride(0);
+        let data = gray.data(0);
+        let pixels = (0..HASH_HEIGHT)
+            .flat_map(|y| &data[y * stride..y * stride + HASH_WIDTH])
+            .copied()
+            .collect::<Vec<_>>();
+        Ok(Fingerprint::of_gray(&pixels))
+    }
+}
+
+/// Bits two hashes differ by.
+fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// One device's dead-air condition, fed a fingerprint per sample.
+pub struct DeadAirDetector {
+    settings: DeadAirSettings,
+    /// The first sample of the picture that has not changed since.
+    still: Option<(Fingerprint, i64)>,
+    /// Since when every sample was black.
+    dark_since: Option<i64>,
+    state: Option<DeadAir>,
+}
+
+impl DeadAirDetector {
+    pub fn new(settings: DeadAirSettings) -> Self {
+        Self {
+            settings,
+            still: None,
+            dark_since: None,
+            state: None,
+        }
+    }
+
+    pub fn state(&self) -> Option<DeadAir> {
+        self.state
+    }
+
+    /// Feed the fingerprint of a frame sampled at `ts` (Unix milliseconds);
+    /// returns the change it makes, if any. A black picture is reported as
+    /// black rather than frozen, and turning black ends being frozen.
+    pub fn observe(&mut self, ts: i64, fingerprint: Fingerprint) -> Option<DeadAirEventKind> {
+        if fingerprint.luma < f64::from(self.settings.black_below) {
+            self.still = None;
+            self.dark_since.get_or_insert(ts);
+        } else {
+            self.dark_since = None;
+            let same = self.still.is_some_and(|(first, _)| {
+                distance(first.hash, fingerprint.hash) <= self.settings.tolerance
+                    && (first.luma - fingerprint.luma).abs() <= LUMA_TOLERANCE
+            });
+            if !same {
+                self.still = Some((fingerprint, ts));
+            }
+        }
+
+        let duration_ms = self.settings.duration_secs.saturating_mul(1000) as i64;
+        let lasted = |since: Option<i64>| since.is_some_and(|since| ts - since >= duration_ms);
+        let state = if lasted(self.dark_since) {
+            Some(DeadAir::Black)
+        } else if lasted(self.still.map(|(_, since)| since)) {
+            Some(DeadAir::Frozen)
+        } else {
+            None
+        };
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        Some(match state {
+            Some(DeadAir::Frozen) => DeadAirEventKind::Frozen,
+            Some(DeadAir::Black) => DeadAirEventKind::Black,
+            None => DeadAirEventKind::Cleared,
+        })
+    }
+}
+
+static STATES: LazyLock<RwLock<HashMap<String, DeadAir>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+static EVENTS: LazyLock<Mutex<VecDeque<DeadAirEvent>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+/// Whether the picture of `device_id` is dead air right now.
+pub fn current(device_id: &str) -> Option<DeadAir> {
+    STATES.read().unwrap().get(device_id).copied()
+}
+
+/// Recent dead-air events, oldest first.
+pub fn recent_events() -> Vec<DeadAirEvent> {
+    EVENTS.lock().unwrap().iter().cloned().collect()
+}
+
+/// Apply a change a device's detector reported at `ts_ms`.
+pub(crate) fn record(device_id: &str, kind: DeadAirEventKind, ts_ms: i64) {
+    match kind {
+        DeadAirEventKind::Frozen => log::warn!("deadair: device {device_id} picture is frozen"),
+        DeadAirEventKind::Black => log::warn!("deadair: device {device_id} picture is black"),
+        DeadAirEventKind::Cleared => log::info!("deadair: device {device_id} picture is live"),
+    }
+    {
+        let mut states = STATES.write().unwrap();
+        match kind {
+            DeadAirEventKind::Frozen => states.insert(device_id.to_string(), DeadAir::Frozen),
+            DeadAirEventKind::Black => states.insert(device_id.to_string(), DeadAir::Black),
+            DeadAirEventKind::Cleared => states.remove(device_id),
+        };
+    }
+    let mut events = EVENTS.lock().unwrap();
+    if events.len() == EVENT_CAP {
+        events.pop_front();
+    }
+    events.push_back(DeadAirEvent {
+        device_id: device_id.to_string(),
+        kind,
+        ts_ms,
+    });
+}
+
+/// The tap of `device_id` stopped: its picture is no longer watched.
+pub(crate) fn forget(device_id: &str) {
+    STATES.write().unwrap().remove(device_id);
+}
+
+#[cfg(test)]
+#[path = "deadair_test.rs"]
+mod deadair_test;
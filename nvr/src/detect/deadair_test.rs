@@ -0,0 +1,192 @@
+use super::*;
+
+/// A 9x8 grey picture with a diagonal pattern shifted by `shift`, so that
+/// neighbouring pixels differ a lot more than compression noise does.
+fn picture(shift: u8) -> Vec<u8> {
+    (0..HASH_HEIGHT)
+        .flat_map(|y| {
+            (0..HASH_WIDTH).map(move |x| ((x * 53 + y * 31) as u8).wrapping_add(shift) % 200 + 40)
+        })
+        .collect()
+}
+
+/// `pixels` with every pixel nudged by up to ±3, and the first two pairs of
+/// neighbours of one row (another one for each seed) swapped, flipping two or
+/// three hash bits.
+fn noisy(mut pixels: Vec<u8>, seed: usize) -> Vec<u8> {
+    for (i, p) in pixels.iter_mut().enumerate() {
+        let nudge = ((i * 7 + seed * 13) % 7) as i16 - 3;
+        *p = (*p as i16 + nudge).clamp(0, 255) as u8;
+    }
+    let row = seed % HASH_HEIGHT * HASH_WIDTH;
+    for x in [row, row + 2] {
+        pixels.swap(x, x + 1);
+    }
+    pixels
+}
+
+fn dark(level: u8) -> Vec<u8> {
+    vec![level; HASH_WIDTH * HASH_HEIGHT]
+}
+
+fn settings() -> DeadAirSettings {
+    DeadAirSettings {
+        duration_secs: 30,
+        tolerance: 6,
+        black_below: 16,
+    }
+}
+
+/// Feed one sample every 5 s from `start_ms`; returns each change with the
+/// time it happened at.
+fn feed(
+    detector: &mut DeadAirDetector,
+    start_ms: i64,
+    pictures: &[Vec<u8>],
+) -> Vec<(i64, DeadAirEventKind)> {
+    pictures
+        .iter()
+        .enumerate()
+        .filter_map(|(i, pixels)| {
+            let ts = start_ms + i as i64 * 5000;
+            detector
+                .observe(ts, Fingerprint::of_gray(pixels))
+                .map(|kind| (ts, kind))
+        })
+        .collect()
+}
+
+#[test]
+fn fingerprints_tell_pictures_apart() {
+    let a = Fingerprint::of_gray(&picture(0));
+    assert_eq!(a, Fingerprint::of_gray(&picture(0)));
+    assert!(distance(a.hash, Fingerprint::of_gray(&noisy(picture(0), 1)).hash) <= 4);
+    assert!(distance(a.hash, Fingerprint::of_gray(&picture(97)).hash) > 8);
+    assert_eq!(Fingerprint::of_gray(&dark(10)).luma, 10.0);
+}
+
+#[test]
+fn an_unchanged_picture_freezes_after_the_duration() {
+    let mut detector = DeadAirDetector::new(settings());
+    let still = vec![picture(0); 8];
+    // 30 s after the first sample, not before.
+    assert_eq!(
+        feed(&mut detector, 0, &still),
+        [(30_000, DeadAirEventKind::Frozen)]
+    );
+    assert_eq!(detector.state(), Some(DeadAir::Frozen));
+    // Nothing more while it stays frozen; a new picture clears it at once.
+    assert_eq!(
+        feed(
+            &mut detector,
+            40_000,
+            &[picture(0), picture(97), picture(97)]
+        ),
+        [(45_000, DeadAirEventKind::Cleared)]
+    );
+    assert_eq!(detector.state(), None);
+}
+
+#[test]
+fn compression_noise_stays_within_the_tolerance() {
+    let mut detector = DeadAirDetector::new(settings());
+    let noisy_still = (0..8).map(|i| noisy(picture(0), i)).collect::<Vec<_>>();
+    assert_eq!(
+        feed(&mut detector, 0, &noisy_still),
+        [(30_000, DeadAirEventKind::Frozen)]
+    );
+
+    // With no tolerance at all the same noise keeps the picture alive.
+    let mut strict = DeadAirDetector::new(DeadAirSettings {
+        tolerance: 0,
+        ..settings()
+    });
+    assert_eq!(feed(&mut strict, 0, &noisy_still), []);
+}
+
+#[test]
+fn a_moving_picture_never_freezes() {
+    let mut detector = DeadAirDetector::new(settings());
+    let moving = (0..20u32)
+        .map(|i| picture((i * 37 % 200) as u8))
+        .collect::<Vec<_>>();
+    assert_eq!(feed(&mut detector, 0, &moving), []);
+}
+
+#[test]
+fn a_dark_picture_goes_black_and_clears() {
+    let mut detector = DeadAirDetector::new(settings());
+    // Dark but not identical (sensor noise): black, not frozen.
+    let dark_frames = (0..8).map(|i| dark(4 + i as u8)).collect::<Vec<_>>();
+    assert_eq!(
+        feed(&mut detector, 0, &dark_frames),
+        [(30_000, DeadAirEventKind::Black)]
+    );
+    assert_eq!(detector.state(), Some(DeadAir::Black));
+    assert_eq!(
+        feed(&mut detector, 40_000, &[picture(0), picture(50)]),
+        [(40_000, DeadAirEventKind::Cleared)]
+    );
+
+    // A picture brighter than the threshold is not black, and one whose
+    // brightness keeps changing is not frozen either, flat as it is.
+    let mut detector = DeadAirDetector::new(settings());
+    let dim = (0..8).map(|i| dark(20 + i as u8 * 10)).collect::<Vec<_>>();
+    assert_eq!(feed(&mut detector, 0, &dim), []);
+}
+
+#[test]
+fn a_frozen_picture_turning_black_is_reported_again() {
+    let mut detector = DeadAirDetector::new(settings());
+    let mut pictures = vec![picture(0); 7];
+    pictures.extend(vec![dark(0); 7]);
+    pictures.push(picture(0));
+    assert_eq!(
+        feed(&mut detector, 0, &pictures),
+        [
+            (30_000, DeadAirEventKind::Frozen),
+            // The picture changed when it went dark...
+            (35_000, DeadAirEventKind::Cleared),
+            // ...and stayed dark for the duration.
+            (65_000, DeadAirEventKind::Black),
+            (70_000, DeadAirEventKind::Cleared),
+        ]
+    );
+}
+
+#[test]
+fn a_longer_duration_spares_still_scenes() {
+    let mut detector = DeadAirDetector::new(DeadAirSettings {
+        duration_secs: 600,
+        ..settings()
+    });
+    assert_eq!(feed(&mut detector, 0, &vec![picture(0); 100]), []);
+    assert_eq!(
+        feed(&mut detector, 500_000, &vec![picture(0); 21]),
+        [(600_000, DeadAirEventKind::Frozen)]
+    );
+}
+
+#[test]
+fn recorded_changes_set_the_device_condition() {
+    record("deadair-cam", DeadAirEventKind::Frozen, 1000);
+    assert_eq!(current("deadair-cam"), Some(DeadAir::Frozen));
+    record("deadair-cam", DeadAirEventKind::Cleared, 2000);
+    assert_eq!(current("deadair-cam"), None);
+    let kinds = recent_events()
+        .into_iter()
+        .filter(|e| e.device_id == "deadair-cam")
+        .map(|e| (e.kind, e.ts_ms))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            (DeadAirEventKind::Frozen, 1000),
+            (DeadAirEventKind::Cleared, 2000)
+        ]
+    );
+
+    record("deadair-cam", DeadAirEventKind::Black, 3000);
+    forget("deadair-cam");
+    assert_eq!(current("deadair-cam"), None);
+}
@@ -0,0 +1,191 @@
+//! Detections turned into events: a label seen above its confidence threshold
+//! opens an event, later sightings extend it (`last_seen`, the best box so
+//! far) instead of each sampled frame making its own, and it ends once the
+//! label has been gone for the tracker's gap. Devices' recent events are kept
+//! in memory for `GET /api/detect/{pipe}/events`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+
+use nvr_db::device::DetectionSettings;
+use nvr_detect::{BBox, Detection};
+use serde::Serialize;
+
+/// How many events [`recent`] keeps per device.
+const LOG_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct DetectionEvent {
+    pub id: String,
+    pub device_id: String,
+    pub label: String,
+    /// Unix milliseconds of the first and latest sighting.
+    pub started_at: i64,
+    pub last_seen: i64,
+    /// The highest confidence seen, and its box (in pixels of a
+    /// `frame_w` x `frame_h` frame).
+    pub confidence: f32,
+    /// `{x1, y1, x2, y2}`.
+    #[schema(value_type = Object)]
+    pub bbox: BBox,
+    pub frame_w: u32,
+    pub frame_h: u32,
+    pub ongoing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventUpdate {
+    Started(DetectionEvent),
+    Seen(DetectionEvent),
+    Ended(DetectionEvent),
+}
+
+/// One device's open events.
+pub struct EventTracker {
+    device_id: String,
+    settings: DetectionSettings,
+    gap_ms: i64,
+    open: HashMap<String, DetectionEvent>,
+}
+
+impl EventTracker {
+    /// A tracker ending an event once its label is unseen for `gap_ms`.
+    pub fn new(device_id: &str, settings: DetectionSettings, gap_ms: i64) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            settings,
+            gap_ms,
+            open: HashMap::new(),
+        }
+    }
+
+    /// The confidence a `label` detection needs to count.
+    pub fn threshold(&self, label: &str) -> f32 {
+        self.settings
+            .thresholds
+            .get(label)
+            .copied()
+            .unwrap_or(self.settings.min_confidence)
+    }
+
+    fn wanted(&self, label: &str) -> bool {
+        self.settings.labels.is_empty() || self.settings.labels.iter().any(|l| l == label)
+    }
+
+    /// Feed the detections of a `w` x `h` frame sampled at `ts`; returns what
+    /// changed, including events this sighting is too late for.
+    pub fn observe(
+        &mut self,
+        ts: i64,
+        w: u32,
+        h: u32,
+        detections: &[Detection],
+    ) -> Vec<EventUpdate> {
+        // The best detection per label that passes its threshold.
+        let mut best: HashMap<&str, &Detection> = HashMap::new();
+        for d in detections {
+            if !self.wanted(&d.label) || d.confidence < self.threshold(&d.label) {
+                continue;
+            }
+            let slot = best.entry(d.label.as_str()).or_insert(d);
+            if d.confidence > slot.confidence {
+                *slot = d;
+            }
+        }
+
+        let mut updates = self.expire(ts);
+        for (label, d) in best {
+            match self.open.get_mut(label) {
+                Some(event) => {
+                    event.last_seen = ts;
+                    if d.confidence >= event.confidence {
+                        event.confidence = d.confidence;
+                        event.bbox = d.bbox.clone();
+                        event.frame_w = w;
+                        event.frame_h = h;
+                    }
+                    updates.push(EventUpdate::Seen(event.clone()));
+                }
+                None => {
+                    let event = DetectionEvent {
+                        id: uuid::Uuid::new_v4().to_string(),
+                        device_id: self.device_id.clone(),
+                        label: label.to_string(),
+                        started_at: ts,
+                        last_seen: ts,
+                        confidence: d.confidence,
+                        bbox: d.bbox.clone(),
+                        frame_w: w,
+                        frame_h: h,
+                        ongoing: true,
+                    };
+                    self.open.insert(label.to_string(), event.clone());
+                    updates.push(EventUpdate::Started(event));
+                }
+            }
+        }
+        updates
+    }
+
+    /// End the events not seen within the gap before `now`.
+    pub fn expire(&mut self, now: i64) -> Vec<EventUpdate> {
+        let gap = self.gap_ms;
+        let stale: Vec<String> = self
+            .open
+            .iter()
+            .filter(|(_, e)| now - e.last_seen > gap)
+            .map(|(label, _)| label.clone())
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|label| self.open.remove(&label))
+            .map(ended)
+            .collect()
+    }
+
+    /// End every open event (the tap is stopping).
+    pub fn finish(&mut self) -> Vec<EventUpdate> {
+        self.open.drain().map(|(_, e)| ended(e)).collect()
+    }
+}
+
+fn ended(mut event: DetectionEvent) -> EventUpdate {
+    event.ongoing = false;
+    EventUpdate::Ended(event)
+}
+
+static LOG: LazyLock<Mutex<HashMap<String, VecDeque<DetectionEvent>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Apply `update` to its device's recent events.
+pub fn record(update: &EventUpdate) {
+    let mut log = LOG.lock().unwrap();
+    match update {
+        EventUpdate::Started(event) => {
+            let events = log.entry(event.device_id.clone()).or_default();
+            events.push_front(event.clone());
+            events.truncate(LOG_CAPACITY);
+        }
+        EventUpdate::Seen(event) | EventUpdate::Ended(event) => {
+            let found = log
+                .get_mut(&event.device_id)
+                .and_then(|events| events.iter_mut().find(|e| e.id == event.id));
+            if let Some(slot) = found {
+                *slot = event.clone();
+            }
+        }
+    }
+}
+
+/// A device's recent events, newest first.
+pub fn recent(device_id: &str) -> Vec<DetectionEvent> {
+    LOG.lock()
+        .unwrap()
+        .get(device_id)
+        .map(|events| events.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+#[path = "events_test.rs"]
+mod events_test;
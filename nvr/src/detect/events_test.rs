@@ -0,0 +1,148 @@
+use super::*;
+
+fn settings(labels: &[&str], thresholds: &[(&str, f32)]) -> DetectionSettings {
+    DetectionSettings {
+        detector: "stub".to_string(),
+        labels: labels.iter().map(|l| l.to_string()).collect(),
+        thresholds: thresholds
+            .iter()
+            .map(|(l, t)| (l.to_string(), *t))
+            .collect(),
+        min_confidence: 0.5,
+        bookmark: false,
+        dead_air: None,
+        motion: None,
+    }
+}
+
+fn det(label: &str, confidence: f32, x1: f32) -> Detection {
+    Detection {
+        class_id: 0,
+        label: label.to_string(),
+        bbox: BBox {
+            x1,
+            y1: 0.0,
+            x2: x1 + 10.0,
+            y2: 10.0,
+        },
+        confidence,
+    }
+}
+
+#[test]
+fn thresholds_and_labels_filter_detections() {
+    let mut tracker = EventTracker::new(
+        "cam1",
+        settings(&["person", "car"], &[("car", 0.8)]),
+        10_000,
+    );
+    assert_eq!(tracker.threshold("person"), 0.5);
+    assert_eq!(tracker.threshold("car"), 0.8);
+    let updates = tracker.observe(
+        1000,
+        640,
+        360,
+        &[
+            det("person", 0.6, 0.0),
+            det("car", 0.7, 0.0),  // under its own threshold
+            det("dog", 0.99, 0.0), // not a wanted label
+            det("person", 0.4, 0.0),
+        ],
+    );
+    assert_eq!(updates.len(), 1);
+    let EventUpdate::Started(event) = &updates[0] else {
+        panic!("{updates:?}");
+    };
+    assert_eq!(event.label, "person");
+    assert_eq!(event.device_id, "cam1");
+    assert!(event.ongoing);
+}
+
+#[test]
+fn no_labels_means_every_label() {
+    let mut tracker = EventTracker::new("cam1", settings(&[], &[]), 10_000);
+    let updates = tracker.observe(0, 640, 360, &[det("dog", 0.9, 0.0), det("cat", 0.9, 0.0)]);
+    assert_eq!(updates.len(), 2);
+}
+
+#[test]
+fn consecutive_detections_coalesce_into_one_event() {
+    let mut tracker = EventTracker::new("cam1", settings(&[], &[]), 10_000);
+    let EventUpdate::Started(first) = tracker
+        .observe(0, 640, 360, &[det("person", 0.6, 1.0)])
+        .remove(0)
+    else {
+        panic!("not started");
+    };
+    let updates = tracker.observe(
+        1000,
+        640,
+        360,
+        &[det("person", 0.9, 2.0), det("person", 0.7, 3.0)],
+    );
+    assert_eq!(updates.len(), 1);
+    let EventUpdate::Seen(seen) = &updates[0] else {
+        panic!("{updates:?}");
+    };
+    assert_eq!(seen.id, first.id);
+    assert_eq!(seen.started_at, 0);
+    assert_eq!(seen.last_seen, 1000);
+    assert_eq!(seen.confidence, 0.9);
+    assert_eq!(seen.bbox.x1, 2.0);
+
+    // A weaker sighting keeps the best box but moves last_seen.
+    let EventUpdate::Seen(seen) = tracker
+        .observe(2000, 640, 360, &[det("person", 0.55, 5.0)])
+        .remove(0)
+    else {
+        panic!("not seen");
+    };
+    assert_eq!(seen.last_seen, 2000);
+    assert_eq!(seen.bbox.x1, 2.0);
+}
+
+#[test]
+fn events_end_after_the_gap() {
+    let mut tracker = EventTracker::new("cam1", settings(&[], &[]), 10_000);
+    tracker.observe(0, 640, 360, &[det("person", 0.6, 0.0)]);
+    assert!(tracker.expire(10_000).is_empty());
+    let ended = tracker.expire(10_001);
+    assert_eq!(ended.len(), 1);
+    let EventUpdate::Ended(event) = &ended[0] else {
+        panic!("{ended:?}");
+    };
+    assert!(!event.ongoing);
+    assert_eq!(event.last_seen, 0);
+
+    // Seen again after the gap: a new event.
+    let first = tracker.observe(20_000, 640, 360, &[det("person", 0.6, 0.0)]);
+    let later = tracker.observe(40_000, 640, 360, &[det("person", 0.6, 0.0)]);
+    assert!(matches!(first[..], [EventUpdate::Started(_)]));
+    assert!(matches!(
+        later[..],
+        [EventUpdate::Ended(_), EventUpdate::Started(_)]
+    ));
+    assert_eq!(tracker.finish().len(), 1);
+    assert!(tracker.finish().is_empty());
+}
+
+#[test]
+fn record_keeps_recent_events_up_to_date() {
+    let mut tracker = EventTracker::new("record-cam", settings(&[], &[]), 1000);
+    for update in tracker.observe(0, 640, 360, &[det("person", 0.6, 0.0)]) {
+        record(&update);
+    }
+    for update in tracker.observe(500, 640, 360, &[det("person", 0.6, 0.0)]) {
+        record(&update);
+    }
+    let events = recent("record-cam");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].last_seen, 500);
+    assert!(events[0].ongoing);
+
+    for update in tracker.finish() {
+        record(&update);
+    }
+    assert!(!recent("record-cam")[0].ongoing);
+    assert!(recent("other-cam").is_empty());
+}
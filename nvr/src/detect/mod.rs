@@ -1,10 +1,18 @@
 //! Real-time object detection for live pipes: taps decoded video, samples,
 //! fans out to N models, and serves the latest per-frame comparison over REST.
+//! Devices can also run a detector continuously, turning its detections into
+//! events (see [`analytics`]), and watch its picture for dead air (see
+//! [`deadair`]) and motion (see [`motion`]).
 
+pub mod analytics;
 pub mod api;
 pub mod convert;
+pub mod deadair;
+pub mod events;
 pub mod hub;
+pub mod motion;
 pub mod result;
+pub mod sidecar;
 pub mod tap;
 
 use std::path::PathBuf;
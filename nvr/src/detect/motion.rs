@@ -0,0 +1,166 @@
+//! Motion: the analytics tap of a device with [`MotionSettings`] samples a
+//! frame every [`SAMPLE_INTERVAL`] and scales its luma down to a
+//! [`GRID_WIDTH`]x[`GRID_HEIGHT`] grey picture. A [`MotionDetector`] compares
+//! each picture with the one before, pixel by pixel, and scores it by the
+//! share of pixels that changed by more than `pixel_threshold`. Motion starts
+//! on the first sample scoring above `enter_ratio` and ends once every sample
+//! for `min_gap_secs` scored below `exit_ratio`; the two thresholds keep a
+//! score hovering around one of them from flapping.
+//!
+//! Starts and ends go out as [`MotionEvent`]s to every [`subscribe`]r. With
+//! `record` set the tap also records the device while motion lasts (see
+//! [`crate::manager::start_motion_recording`]).
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use ffmpeg_bus::frame::RawVideoFrame;
+use ffmpeg_next::format::Pixel;
+use nvr_db::device::MotionSettings;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Time between two compared frames of a device (5 fps).
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+/// Size of the grey picture a frame is compared by: coarse enough to average
+/// out sensor noise, fine enough for a person across a room.
+pub const GRID_WIDTH: usize = 64;
+pub const GRID_HEIGHT: usize = 36;
+/// Events a slow subscriber may fall behind by before it lags.
+const CHANNEL_CAP: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MotionEventKind {
+    /// The picture started moving.
+    Started,
+    /// The picture has been still for the settings' gap.
+    Ended,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct MotionEvent {
+    pub device_id: String,
+    pub kind: MotionEventKind,
+    /// Share (0–1) of the pixels the sample that made the change changed.
+    pub score: f32,
+    /// Unix milliseconds of that sample.
+    pub timestamp: i64,
+}
+
+/// The luma of a decoded frame, scaled down to the grid, row by row.
+pub fn downsample(frame: &RawVideoFrame) -> anyhow::Result<Vec<u8>> {
+    let gray = frame.scale(Pixel::GRAY8, GRID_WIDTH as u32, GRID_HEIGHT as u32)?;
+    let stride = gray.stride(0);
+    let data = gray.data(0);
+    Ok((0..GRID_HEIGHT)
+        .flat_map(|y| &data[y * stride..y * stride + GRID_WIDTH])
+        .copied()
+        .collect())
+}
+
+/// Share of the pixels of `next` that differ from those of `previous` by
+/// more than `threshold`.
+pub fn changed_ratio(previous: &[u8], next: &[u8], threshold: u8) -> f32 {
+    assert_eq!(previous.len(), next.len());
+    if next.is_empty() {
+        return 0.0;
+    }
+    let changed = previous
+        .iter()
+        .zip(next)
+        .filter(|(a, b)| a.abs_diff(**b) > threshold)
+        .count();
+    changed as f32 / next.len() as f32
+}
+
+/// One device's motion, fed a downsampled picture per sample.
+pub struct MotionDetector {
+    settings: MotionSettings,
+    previous: Option<Vec<u8>>,
+    moving: bool,
+    /// Since when every sample of the ongoing motion scored below the exit
+    /// ratio.
+    still_since: Option<i64>,
+}
+
+impl MotionDetector {
+    pub fn new(settings: MotionSettings) -> Self {
+        Self {
+            settings,
+            previous: None,
+            moving: false,
+            still_since: None,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.moving
+    }
+
+    /// Feed the downsampled picture of a frame sampled at `ts` (Unix
+    /// milliseconds); returns the change it makes, if any, with the
+    /// sample's score. The first picture only sets what the next is
+    /// compared with.
+    pub fn observe(&mut self, ts: i64, picture: Vec<u8>) -> Option<(MotionEventKind, f32)> {
+        let threshold = self.settings.pixel_threshold;
+        let score = self
+            .previous
+            .as_ref()
+            .map(|previous| changed_ratio(previous, &picture, threshold));
+        self.previous = Some(picture);
+        let score = score?;
+        if !self.moving {
+            if score <= self.settings.enter_ratio {
+                return None;
+            }
+            self.moving = true;
+            return Some((MotionEventKind::Started, score));
+        }
+        if score >= self.settings.exit_ratio {
+            self.still_since = None;
+            return None;
+        }
+        let since = *self.still_since.get_or_insert(ts);
+        let gap_ms = self.settings.min_gap_secs.saturating_mul(1000) as i64;
+        if ts - since < gap_ms {
+            return None;
+        }
+        self.moving = false;
+        self.still_since = None;
+        Some((MotionEventKind::Ended, score))
+    }
+
+    /// Stop watching: whether motion was ongoing, which then ends.
+    pub fn finish(&mut self) -> bool {
+        self.previous = None;
+        self.still_since = None;
+        std::mem::take(&mut self.moving)
+    }
+}
+
+static EVENTS: LazyLock<broadcast::Sender<MotionEvent>> =
+    LazyLock::new(|| broadcast::channel(CHANNEL_CAP).0);
+
+/// Every motion event of every device from now on.
+pub fn subscribe() -> broadcast::Receiver<MotionEvent> {
+    EVENTS.subscribe()
+}
+
+/// Send a change a device's detector reported to the subscribers.
+pub(crate) fn publish(event: MotionEvent) {
+    match event.kind {
+        MotionEventKind::Started => log::info!(
+            "motion: device {} started moving ({:.3})",
+            event.device_id,
+            event.score
+        ),
+        MotionEventKind::Ended => log::info!("motion: device {} is still", event.device_id),
+    }
+    // No subscriber is not an error.
+    let _ = EVENTS.send(event);
+}
+
+#[cfg(test)]
+#[path = "motion_test.rs"]
+mod motion_test;
@@ -0,0 +1,172 @@
+use ffmpeg_next::format::Pixel;
+
+use super::*;
+
+const PIXELS: usize = GRID_WIDTH * GRID_HEIGHT;
+
+/// A grid of vertical stripes 8 pixels wide, moved right by `shift`.
+fn stripes(shift: usize) -> Vec<u8> {
+    (0..PIXELS)
+        .map(|i| {
+            if (i % GRID_WIDTH + shift) / 8 % 2 == 0 {
+                40
+            } else {
+                200
+            }
+        })
+        .collect()
+}
+
+/// `pixels` with every pixel nudged by up to ±6, as sensor noise would.
+fn noisy(mut pixels: Vec<u8>, seed: usize) -> Vec<u8> {
+    for (i, p) in pixels.iter_mut().enumerate() {
+        let nudge = ((i * 7 + seed * 13) % 13) as i16 - 6;
+        *p = (*p as i16 + nudge).clamp(0, 255) as u8;
+    }
+    pixels
+}
+
+fn settings() -> MotionSettings {
+    MotionSettings {
+        min_gap_secs: 2,
+        ..Default::default()
+    }
+}
+
+/// Feed one sample every 200 ms from `start_ms`; returns each change with
+/// the time it happened at.
+fn feed(
+    detector: &mut MotionDetector,
+    start_ms: i64,
+    pictures: Vec<Vec<u8>>,
+) -> Vec<(i64, MotionEventKind)> {
+    pictures
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, picture)| {
+            let ts = start_ms + i as i64 * 200;
+            detector.observe(ts, picture).map(|(kind, _)| (ts, kind))
+        })
+        .collect()
+}
+
+#[test]
+fn changed_ratio_counts_pixels_past_the_threshold() {
+    assert_eq!(changed_ratio(&stripes(0), &stripes(0), 25), 0.0);
+    assert_eq!(changed_ratio(&stripes(0), &noisy(stripes(0), 1), 25), 0.0);
+    // Moving the stripes by 4 turns half of every stripe.
+    assert_eq!(changed_ratio(&stripes(0), &stripes(4), 25), 0.5);
+    assert_eq!(changed_ratio(&[10, 10], &[40, 35], 25), 0.5);
+}
+
+#[test]
+fn a_still_picture_never_moves() {
+    let mut detector = MotionDetector::new(settings());
+    let still = (0..50).map(|i| noisy(stripes(0), i)).collect();
+    assert_eq!(feed(&mut detector, 0, still), []);
+    assert!(!detector.is_moving());
+}
+
+#[test]
+fn motion_starts_at_once_and_ends_after_the_gap() {
+    let mut detector = MotionDetector::new(settings());
+    let mut pictures: Vec<_> = (0..3).map(|_| stripes(0)).collect();
+    // Moving for a second, then still at the last position.
+    pictures.extend((1..=5).map(stripes));
+    pictures.extend((0..20).map(|i| noisy(stripes(5), i)));
+    assert_eq!(
+        feed(&mut detector, 0, pictures),
+        [
+            (600, MotionEventKind::Started),
+            // The first still sample at 1600, then 2 s of them.
+            (3600, MotionEventKind::Ended),
+        ]
+    );
+    assert!(!detector.is_moving());
+}
+
+#[test]
+fn a_pause_shorter_than_the_gap_keeps_one_event() {
+    let mut detector = MotionDetector::new(settings());
+    let mut pictures = vec![stripes(0), stripes(2)];
+    pictures.extend((0..5).map(|_| stripes(2)));
+    pictures.push(stripes(6));
+    assert_eq!(
+        feed(&mut detector, 0, pictures),
+        [(200, MotionEventKind::Started)]
+    );
+    assert!(detector.is_moving());
+}
+
+#[test]
+fn a_score_between_the_thresholds_neither_starts_nor_ends_motion() {
+    // Every sample changes two pixel columns of 64: above the exit ratio,
+    // below the enter ratio.
+    let column = |x: usize| -> Vec<u8> {
+        (0..PIXELS)
+            .map(|i| if i % GRID_WIDTH == x { 255 } else { 0 })
+            .collect()
+    };
+    let slight = MotionSettings {
+        enter_ratio: 0.05,
+        exit_ratio: 0.01,
+        ..settings()
+    };
+    let mut detector = MotionDetector::new(slight.clone());
+    let pictures = (0..30).map(|i| column(i % 2)).collect();
+    assert_eq!(feed(&mut detector, 0, pictures), []);
+
+    let mut detector = MotionDetector::new(slight);
+    let mut pictures = vec![stripes(0), stripes(4)];
+    pictures.extend((0..30).map(|i| column(i % 2)));
+    assert_eq!(
+        feed(&mut detector, 0, pictures),
+        [(200, MotionEventKind::Started)]
+    );
+}
+
+#[test]
+fn finish_ends_ongoing_motion() {
+    let mut detector = MotionDetector::new(settings());
+    feed(&mut detector, 0, vec![stripes(0), stripes(4)]);
+    assert!(detector.finish());
+    assert!(!detector.finish());
+    // The next picture is compared with nothing.
+    assert_eq!(feed(&mut detector, 1000, vec![stripes(0)]), []);
+}
+
+#[test]
+fn downsample_scales_a_frame_to_the_grid() {
+    let mut frame = ffmpeg_next::frame::Video::new(Pixel::GRAY8, 640, 360);
+    let stride = frame.stride(0);
+    for (y, row) in frame.data_mut(0).chunks_mut(stride).enumerate() {
+        row.fill(if y < 180 { 30 } else { 220 });
+    }
+    let picture = downsample(&RawVideoFrame::from(frame)).unwrap();
+    assert_eq!(picture.len(), PIXELS);
+    assert!(picture[..GRID_WIDTH].iter().all(|p| p.abs_diff(30) <= 2));
+    assert!(
+        picture[PIXELS - GRID_WIDTH..]
+            .iter()
+            .all(|p| p.abs_diff(220) <= 2)
+    );
+}
+
+#[tokio::test]
+async fn published_events_reach_subscribers() {
+    let mut events = subscribe();
+    let event = MotionEvent {
+        device_id: "cam-motion".to_string(),
+        kind: MotionEventKind::Started,
+        score: 0.5,
+        timestamp: 1,
+    };
+    publish(event.clone());
+    loop {
+        let got = events.recv().await.unwrap();
+        if got.device_id == "cam-motion" {
+            assert_eq!(got, event);
+            break;
+        }
+    }
+}
@@ -3,11 +3,13 @@
 use nvr_detect::ModelResult;
 use serde::Serialize;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, utoipa::ToSchema)]
 pub struct FrameResult {
     /// Unix seconds when this frame was processed.
     pub ts: i64,
     pub frame_w: u32,
     pub frame_h: u32,
+    /// Each model's detections (`nvr_detect::ModelResult`).
+    #[schema(value_type = Vec<Object>)]
     pub models: Vec<ModelResult>,
 }
@@ -0,0 +1,141 @@
+//! Detectors the per-device analytics taps (see [`super::analytics`]) run on
+//! sampled frames. Unlike the in-process ONNX models of the comparison taps
+//! ([`super::tap`]), these may live elsewhere: [`HttpDetector`] posts each
+//! frame as a JPEG to an inference sidecar (a CPU or GPU box serving a model
+//! over HTTP) and reads back its detections.
+//!
+//! The sidecar protocol: `POST NVR_DETECT_URL` with the JPEG as the body
+//! (`Content-Type: image/jpeg`, the device in `X-Device-Id`), answered with
+//! JSON, either a list of detections or `{"detections": [...]}`, each
+//! `{"label": "person", "confidence": 0.91, "bbox": {"x1": 10, "y1": 20,
+//! "x2": 110, "y2": 220}}` in pixels of the posted image (`class_id`
+//! optional).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use nvr_db::device::DetectionSettings;
+use nvr_detect::{BBox, Detection};
+use serde::Deserialize;
+
+/// A sampled frame of a device's live stream, as detectors get it.
+#[derive(Debug, Clone)]
+pub struct AnalyticsFrame {
+    pub device_id: String,
+    /// When the frame was sampled, unix milliseconds.
+    pub ts: i64,
+    pub width: u32,
+    pub height: u32,
+    /// The frame as a JPEG of `width` x `height`.
+    pub jpeg: Bytes,
+}
+
+#[async_trait::async_trait]
+pub trait Detector: Send + Sync {
+    fn name(&self) -> &str;
+    /// The objects in `frame`, boxes in its pixels.
+    async fn detect(&self, frame: &AnalyticsFrame) -> anyhow::Result<Vec<Detection>>;
+}
+
+/// Detections from an inference sidecar over HTTP.
+pub struct HttpDetector {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpDetector {
+    /// A detector posting to `url`; a request running past `timeout` fails.
+    pub fn new(url: &str, timeout: Duration) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Reply {
+    List(Vec<WireDetection>),
+    Wrapped { detections: Vec<WireDetection> },
+}
+
+#[derive(Deserialize)]
+struct WireDetection {
+    label: String,
+    confidence: f32,
+    bbox: BBox,
+    #[serde(default)]
+    class_id: usize,
+}
+
+#[async_trait::async_trait]
+impl Detector for HttpDetector {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn detect(&self, frame: &AnalyticsFrame) -> anyhow::Result<Vec<Detection>> {
+        let reply = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "image/jpeg")
+            .header("X-Device-Id", &frame.device_id)
+            .body(frame.jpeg.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Reply>()
+            .await?;
+        let (Reply::List(detections) | Reply::Wrapped { detections }) = reply;
+        Ok(detections
+            .into_iter()
+            .map(|d| Detection {
+                class_id: d.class_id,
+                label: d.label,
+                bbox: d.bbox,
+                confidence: d.confidence,
+            })
+            .collect())
+    }
+}
+
+/// A detector that never sees anything: runs a device's tap without a
+/// sidecar, e.g. to try the settings out.
+pub struct NullDetector;
+
+#[async_trait::async_trait]
+impl Detector for NullDetector {
+    fn name(&self) -> &str {
+        "stub"
+    }
+
+    async fn detect(&self, _frame: &AnalyticsFrame) -> anyhow::Result<Vec<Detection>> {
+        Ok(Vec::new())
+    }
+}
+
+/// The detector `settings` pick: `None` for `none`. `url` is the sidecar's
+/// (`NVR_DETECT_URL`), required by `http`.
+pub fn build_detector(
+    settings: &DetectionSettings,
+    url: Option<&str>,
+    timeout: Duration,
+) -> anyhow::Result<Option<Arc<dyn Detector>>> {
+    match settings.detector.as_str() {
+        "none" => Ok(None),
+        "stub" => Ok(Some(Arc::new(NullDetector))),
+        "http" => {
+            let url = url.ok_or_else(|| {
+                anyhow::anyhow!("the http detector needs an inference endpoint (NVR_DETECT_URL)")
+            })?;
+            Ok(Some(Arc::new(HttpDetector::new(url, timeout)?)))
+        }
+        other => anyhow::bail!("unknown detector: {other} (expected http, stub or none)"),
+    }
+}
+
+#[cfg(test)]
+#[path = "sidecar_test.rs"]
+mod sidecar_test;
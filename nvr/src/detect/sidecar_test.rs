@@ -0,0 +1,117 @@
+use axum::{
+    Router,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing::post,
+};
+use tokio::net::TcpListener;
+
+use super::*;
+
+async fn serve(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{addr}")
+}
+
+/// A sidecar answering every JPEG it gets with two canned detections, one in
+/// each reply form.
+async fn mock(headers: HeaderMap, body: Bytes) -> impl IntoResponse {
+    if headers.get(header::CONTENT_TYPE).map(|v| v.as_bytes()) != Some(b"image/jpeg") {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, String::new());
+    }
+    let device = headers["x-device-id"].to_str().unwrap().to_string();
+    let person =
+        r#"{"label":"person","confidence":0.91,"bbox":{"x1":10,"y1":20,"x2":110,"y2":220}}"#;
+    let car =
+        r#"{"label":"car","confidence":0.4,"class_id":2,"bbox":{"x1":0,"y1":0,"x2":5,"y2":5}}"#;
+    let reply = match device.as_str() {
+        "wrapped" => format!(r#"{{"detections":[{person},{car}]}}"#),
+        _ => format!("[{person},{car}]"),
+    };
+    assert_eq!(&body[..], b"jpeg-bytes");
+    (StatusCode::OK, reply)
+}
+
+fn frame(device_id: &str) -> AnalyticsFrame {
+    AnalyticsFrame {
+        device_id: device_id.to_string(),
+        ts: 1_760_400_000_000,
+        width: 640,
+        height: 360,
+        jpeg: Bytes::from_static(b"jpeg-bytes"),
+    }
+}
+
+#[tokio::test]
+async fn http_detector_reads_canned_detections() {
+    let base = serve(Router::new().route("/detect", post(mock))).await;
+    let detector = HttpDetector::new(&format!("{base}/detect"), Duration::from_secs(5)).unwrap();
+    for device in ["list", "wrapped"] {
+        let detections = detector.detect(&frame(device)).await.unwrap();
+        assert_eq!(detections.len(), 2, "{device}");
+        assert_eq!(detections[0].label, "person");
+        assert_eq!(detections[0].class_id, 0);
+        assert!((detections[0].confidence - 0.91).abs() < 1e-6);
+        assert_eq!(
+            detections[0].bbox,
+            BBox {
+                x1: 10.0,
+                y1: 20.0,
+                x2: 110.0,
+                y2: 220.0,
+            }
+        );
+        assert_eq!(detections[1].class_id, 2);
+    }
+}
+
+#[tokio::test]
+async fn http_detector_fails_on_error_status_and_timeout() {
+    let app = Router::new()
+        .route("/down", post(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+        .route(
+            "/slow",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "[]"
+            }),
+        );
+    let base = serve(app).await;
+    let down = HttpDetector::new(&format!("{base}/down"), Duration::from_secs(5)).unwrap();
+    assert!(down.detect(&frame("cam1")).await.is_err());
+    let slow = HttpDetector::new(&format!("{base}/slow"), Duration::from_millis(200)).unwrap();
+    assert!(slow.detect(&frame("cam1")).await.is_err());
+}
+
+#[tokio::test]
+async fn build_detector_picks_by_settings() {
+    let settings = |detector: &str| DetectionSettings {
+        detector: detector.to_string(),
+        labels: vec![],
+        thresholds: Default::default(),
+        min_confidence: 0.5,
+        bookmark: false,
+        dead_air: None,
+        motion: None,
+    };
+    let timeout = Duration::from_secs(1);
+    assert!(
+        build_detector(&settings("none"), None, timeout)
+            .unwrap()
+            .is_none()
+    );
+    let stub = build_detector(&settings("stub"), None, timeout)
+        .unwrap()
+        .unwrap();
+    assert!(stub.detect(&frame("cam1")).await.unwrap().is_empty());
+    assert!(build_detector(&settings("http"), None, timeout).is_err());
+    let http = build_detector(&settings("http"), Some("http://127.0.0.1:9"), timeout)
+        .unwrap()
+        .unwrap();
+    assert_eq!(http.name(), "http");
+    assert!(build_detector(&settings("yolo"), None, timeout).is_err());
+}
@@ -0,0 +1,221 @@
+//! A device's input in typed form. The database keeps it as the device's
+//! `input_type` and an `input_value` string, a URL or JSON depending on the
+//! type; [`DeviceInput::parse`] reads that pair into one variant per type, so
+//! a typo is refused when the device is saved, naming the type and the field,
+//! rather than when its pipe starts.
+//!
+//! As JSON (the `input` of a device payload) the type is the `input_type`
+//! tag and the rest are the fields of its `input_value`, a URL as `url`:
+//!
+//! ```json
+//! { "input_type": "rtsp", "url": "rtsp://10.0.0.9/main", "failover_urls": [] }
+//! { "input_type": "gb28181", "device_id": "3402…", "channel_id": "3402…" }
+//! ```
+//!
+//! The inputs ffmpeg opens directly turn into the pipe's [`InputConfig`]
+//! with `TryFrom`.
+
+use media_pipe_core::InputConfig;
+use nvr_db::device::DeviceInfo;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::failover::FailoverInput;
+use crate::gb::device::GbInput;
+use crate::xiaomi::XiaomiConfig;
+
+/// One device input, tagged with its `input_type`. The types ffmpeg opens
+/// take a URL, file path or device name, or the JSON form with failover URLs
+/// and tuning (see `crate::failover`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "input_type", rename_all = "snake_case")]
+pub enum DeviceInput {
+    Net(FailoverInput),
+    Rtsp(FailoverInput),
+    Rtmp(FailoverInput),
+    File(FailoverInput),
+    /// A file played over and over at its own pace, like a camera.
+    FileLoop(FailoverInput),
+    V4l2(FailoverInput),
+    X11grab(FailoverInput),
+    Lavfi(FailoverInput),
+    /// A Xiaomi camera pulled by its native worker (see `crate::xiaomi`).
+    Xiaomi(XiaomiConfig),
+    /// A GB28181 channel (see `crate::gb::device`).
+    Gb28181(GbInput),
+    /// A camera whose RTSP URL its ONVIF media service resolves.
+    Onvif(nvr_onvif::OnvifConfig),
+    /// A platform live room, resolved by yt-dlp (see `crate::livestream`).
+    Stream {
+        url: String,
+    },
+}
+
+/// Why a device's input was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidInput {
+    pub input_type: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.input_type.is_empty() {
+            write!(f, "invalid device input: {}", self.reason)
+        } else {
+            write!(f, "invalid {} input: {}", self.input_type, self.reason)
+        }
+    }
+}
+
+impl std::error::Error for InvalidInput {}
+
+impl DeviceInput {
+    /// Read a stored `input_type` and `input_value`. A plain value is the
+    /// `url` of the types that take one; the JSON ones take its fields.
+    pub fn parse(input_type: &str, input_value: &str) -> Result<Self, InvalidInput> {
+        let invalid = |reason: String| InvalidInput {
+            input_type: input_type.to_string(),
+            reason,
+        };
+        let value = input_value.trim();
+        let json = matches!(input_type, "xiaomi" | "gb28181" | "onvif")
+            || (input_type != "stream" && value.starts_with('{'));
+        let mut fields = if json {
+            serde_json::from_str::<Map<String, Value>>(value)
+                .map_err(|e| invalid(format!("input_value is not a JSON object: {e}")))?
+        } else {
+            Map::from_iter([("url".to_string(), Value::from(value))])
+        };
+        fields.insert("input_type".to_string(), Value::from(input_type));
+        Self::from_json(Value::Object(fields))
+    }
+
+    /// Read the JSON form, `input_type` and all.
+    pub fn from_json(json: Value) -> Result<Self, InvalidInput> {
+        let input_type = json.get("input_type").and_then(Value::as_str);
+        let input_type = input_type.unwrap_or_default().to_string();
+        let invalid = |reason: String| InvalidInput {
+            input_type: input_type.clone(),
+            reason,
+        };
+        if input_type.is_empty() {
+            return Err(invalid("input_type is required".to_string()));
+        }
+        let input: Self = serde_json::from_value(json).map_err(|e| invalid(e.to_string()))?;
+        input.check().map_err(invalid)?;
+        Ok(input)
+    }
+
+    /// The input of a stored device.
+    pub fn of(device: &DeviceInfo) -> Result<Self, InvalidInput> {
+        Self::parse(&device.input_type, &device.input_value)
+    }
+
+    /// What serde does not check.
+    fn check(&self) -> Result<(), String> {
+        if let Some(input) = self.ffmpeg() {
+            if input.url.trim().is_empty() {
+                return Err("url is required".to_string());
+            }
+            if input.failover_urls.iter().any(|url| url.trim().is_empty()) {
+                return Err("failover_urls has an empty url".to_string());
+            }
+        }
+        match self {
+            Self::Gb28181(gb) => gb.check().map_err(|e| format!("{e:#}")),
+            Self::Stream { url } if url.trim().is_empty() => Err("url is required".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The `input_type` it is stored under.
+    pub fn input_type(&self) -> &'static str {
+        match self {
+            Self::Net(_) => "net",
+            Self::Rtsp(_) => "rtsp",
+            Self::Rtmp(_) => "rtmp",
+            Self::File(_) => "file",
+            Self::FileLoop(_) => "file_loop",
+            Self::V4l2(_) => "v4l2",
+            Self::X11grab(_) => "x11grab",
+            Self::Lavfi(_) => "lavfi",
+            Self::Xiaomi(_) => "xiaomi",
+            Self::Gb28181(_) => "gb28181",
+            Self::Onvif(_) => "onvif",
+            Self::Stream { .. } => "stream",
+        }
+    }
+
+    /// The `input_value` it is stored as: the URL alone when nothing else
+    /// is set, JSON otherwise.
+    pub fn input_value(&self) -> String {
+        let json = match self {
+            Self::Xiaomi(config) => serde_json::to_string(config),
+            Self::Gb28181(gb) => serde_json::to_string(gb),
+            Self::Onvif(config) => serde_json::to_string(config),
+            Self::Stream { url } => return url.clone(),
+            _ => match self.ffmpeg() {
+                Some(input) if input.is_plain() => return input.url.clone(),
+                Some(input) => serde_json::to_string(input),
+                None => unreachable!("every other input is opened by ffmpeg"),
+            },
+        };
+        json.expect("device inputs serialize")
+    }
+
+    /// The URLs and tuning of an input ffmpeg opens directly.
+    pub fn ffmpeg(&self) -> Option<&FailoverInput> {
+        match self {
+            Self::Net(input)
+            | Self::Rtsp(input)
+            | Self::Rtmp(input)
+            | Self::File(input)
+            | Self::FileLoop(input)
+            | Self::V4l2(input)
+            | Self::X11grab(input)
+            | Self::Lavfi(input) => Some(input),
+            _ => None,
+        }
+    }
+
+    /// The pipe input opening `location` (the URL or one of the failover
+    /// URLs) the way this type is opened; `None` for types ffmpeg does not
+    /// open.
+    pub fn open(&self, location: &str) -> Option<InputConfig> {
+        let input = self.ffmpeg()?;
+        let location = location.to_string();
+        Some(match self {
+            Self::Net(_) | Self::Rtsp(_) | Self::Rtmp(_) => InputConfig::Network {
+                url: location,
+                net: input.net.clone(),
+            },
+            Self::File(_) => InputConfig::File { path: location },
+            // Paced like a camera, so a demo device behaves like a live one.
+            Self::FileLoop(_) => InputConfig::FileLoop {
+                path: location,
+                realtime: true,
+            },
+            _ => InputConfig::Device {
+                display: location,
+                format: self.input_type().to_string(),
+            },
+        })
+    }
+}
+
+/// The pipe input of a device ffmpeg opens directly, on its primary URL.
+impl TryFrom<&DeviceInput> for InputConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(input: &DeviceInput) -> anyhow::Result<Self> {
+        let url = input.ffmpeg().map(|ffmpeg| ffmpeg.url.as_str());
+        url.and_then(|url| input.open(url)).ok_or_else(|| {
+            anyhow::anyhow!("{} inputs are not opened by ffmpeg", input.input_type())
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "device_input_test.rs"]
+mod device_input_test;
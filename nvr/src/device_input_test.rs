@@ -0,0 +1,138 @@
+use serde_json::json;
+
+use super::*;
+
+/// Stored, read back and stored again, `input_value` stays the same.
+fn stored_again(input_type: &str, input_value: &str) -> String {
+    let input = DeviceInput::parse(input_type, input_value).unwrap();
+    assert_eq!(input.input_type(), input_type);
+    input.input_value()
+}
+
+#[test]
+fn a_plain_url_stays_plain() {
+    let input = DeviceInput::parse("rtsp", " rtsp://10.0.0.9/main ").unwrap();
+    let ffmpeg = input.ffmpeg().unwrap();
+    assert_eq!(ffmpeg.url, "rtsp://10.0.0.9/main");
+    assert!(ffmpeg.failover_urls.is_empty());
+    assert_eq!(input.input_value(), "rtsp://10.0.0.9/main");
+    assert_eq!(
+        stored_again("stream", "https://live.example/room/1"),
+        "https://live.example/room/1"
+    );
+}
+
+#[test]
+fn the_json_forms_round_trip() {
+    let failover = r#"{"url": "rtsp://a", "failover_urls": ["rtsp://b"], "retries": 5,
+        "latency_profile": "low", "transport": "tcp"}"#;
+    let stored = stored_again("rtsp", failover);
+    let again = DeviceInput::parse("rtsp", &stored).unwrap();
+    let ffmpeg = again.ffmpeg().unwrap();
+    assert_eq!(ffmpeg.urls(), ["rtsp://a", "rtsp://b"]);
+    assert_eq!(ffmpeg.retries, 5);
+    assert_eq!(
+        ffmpeg.tuning.latency_profile,
+        Some(crate::latency::LatencyProfile::Low)
+    );
+    assert_eq!(stored_again("rtsp", &stored), stored);
+
+    let gb = r#"{"device_id": "34020000001320000001", "channel_id": "34020000001310000001",
+        "transport": "tcp_passive", "always_on": true}"#;
+    let stored = stored_again("gb28181", gb);
+    assert_eq!(stored_again("gb28181", &stored), stored);
+}
+
+#[test]
+fn the_typed_form_is_tagged_by_input_type() {
+    let input = DeviceInput::from_json(json!({
+        "input_type": "file_loop",
+        "url": "/videos/demo.mp4",
+    }))
+    .unwrap();
+    assert_eq!(input.input_type(), "file_loop");
+    assert_eq!(input.input_value(), "/videos/demo.mp4");
+
+    let json = serde_json::to_value(&input).unwrap();
+    assert_eq!(json["input_type"], "file_loop");
+    assert_eq!(json["url"], "/videos/demo.mp4");
+    let again = DeviceInput::from_json(json).unwrap();
+    assert_eq!(again.input_value(), input.input_value());
+
+    let stream = serde_json::to_value(DeviceInput::Stream {
+        url: "https://live.example/room/1".to_string(),
+    })
+    .unwrap();
+    assert_eq!(
+        stream,
+        json!({"input_type": "stream", "url": "https://live.example/room/1"})
+    );
+}
+
+#[test]
+fn refusals_name_the_type_and_the_field() {
+    let reason = |input_type: &str, input_value: &str| {
+        DeviceInput::parse(input_type, input_value)
+            .unwrap_err()
+            .to_string()
+    };
+    let missing = reason("gb28181", r#"{"device_id": "34020000001320000001"}"#);
+    assert!(missing.starts_with("invalid gb28181 input"), "{missing}");
+    assert!(missing.contains("channel_id"), "{missing}");
+
+    let transport = reason(
+        "gb28181",
+        r#"{"device_id": "a", "channel_id": "b", "transport": "carrier_pigeon"}"#,
+    );
+    assert!(transport.contains("carrier_pigeon"), "{transport}");
+
+    let unknown = reason("rstp", "rtsp://10.0.0.9/main");
+    assert!(unknown.contains("unknown variant `rstp`"), "{unknown}");
+
+    let mistyped = reason("rtsp", r#"{"url": "rtsp://a", "retries": "many"}"#);
+    assert!(mistyped.contains("invalid type"), "{mistyped}");
+
+    assert!(reason("xiaomi", "not json").contains("not a JSON object"));
+    assert!(reason("rtsp", "").contains("url is required"));
+    assert_eq!(
+        reason("", "rtsp://a"),
+        "invalid device input: input_type is required"
+    );
+    let untyped = DeviceInput::from_json(json!({"url": "rtsp://a"})).unwrap_err();
+    assert_eq!(untyped.reason, "input_type is required");
+}
+
+#[test]
+fn ffmpeg_inputs_convert_to_pipe_inputs() {
+    let input = DeviceInput::parse("file_loop", "/videos/demo.mp4").unwrap();
+    match InputConfig::try_from(&input).unwrap() {
+        InputConfig::FileLoop { path, realtime } => {
+            assert_eq!(path, "/videos/demo.mp4");
+            assert!(realtime);
+        }
+        _ => panic!("unexpected input"),
+    }
+
+    let input = DeviceInput::parse("rtsp", r#"{"url": "rtsp://a", "transport": "tcp"}"#).unwrap();
+    match InputConfig::try_from(&input).unwrap() {
+        InputConfig::Network { url, net } => {
+            assert_eq!(url, "rtsp://a");
+            assert_eq!(net.transport, ffmpeg_bus::bus::RtspTransport::Tcp);
+        }
+        _ => panic!("unexpected input"),
+    }
+
+    let input = DeviceInput::parse("lavfi", "testsrc=size=320x240").unwrap();
+    match InputConfig::try_from(&input).unwrap() {
+        InputConfig::Device { display, format } => {
+            assert_eq!(
+                (display.as_str(), format.as_str()),
+                ("testsrc=size=320x240", "lavfi")
+            );
+        }
+        _ => panic!("unexpected input"),
+    }
+
+    let gb = DeviceInput::parse("gb28181", r#"{"device_id": "a", "channel_id": "b"}"#).unwrap();
+    assert!(InputConfig::try_from(&gb).is_err());
+}
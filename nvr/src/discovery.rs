@@ -0,0 +1,400 @@
+//! WS-Discovery of ONVIF cameras on the LAN, so they are added from a list
+//! instead of by typing RTSP URLs.
+//!
+//! `GET /api/v1/discovery/scan?timeout_ms=3000` multicasts one Probe for
+//! network video transmitters to 239.255.255.250:3702 and collects the
+//! ProbeMatches answering it until the timeout (500 ms to 10 s, default 3 s)
+//! or the server shutting down. Each request runs a scan of its own, on its
+//! own socket, so a scan holds nothing else up. `POST
+//! /api/v1/discovery/adopt` adds one of the cameras found as an `rtsp` device,
+//! its URL from a template.
+//!
+//! No ONVIF client is involved: the address comes from the camera's device
+//! service URLs (`XAddrs`), the name and model from its scopes. For its
+//! profiles and real stream URIs see `POST /api/v1/onvif/probe`.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use axum::{
+    Extension, Json, Router,
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    device_input::DeviceInput,
+    failover::FailoverInput,
+    handler::{ApiJsonResult, ApiResult, BaseResponse, device::DevicePayload, ok_json},
+};
+
+/// The WS-Discovery multicast group and port.
+const WS_DISCOVERY: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 3702);
+/// Hops a Probe may cross: the LAN, and routers set up to forward it.
+const MULTICAST_TTL: u32 = 4;
+const DEFAULT_TIMEOUT_MS: u64 = 3000;
+const MIN_TIMEOUT_MS: u64 = 500;
+const MAX_TIMEOUT_MS: u64 = 10_000;
+/// The RTSP URL of an adopted camera without a template of its own.
+pub(crate) const DEFAULT_RTSP_TEMPLATE: &str = "rtsp://{address}:554/";
+
+static SHUTDOWN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// End the scans in progress, and any started later, once `cancel` fires.
+pub(crate) fn init(cancel: CancellationToken) {
+    let _ = SHUTDOWN.set(cancel);
+}
+
+fn shutdown() -> CancellationToken {
+    SHUTDOWN.get().cloned().unwrap_or_default()
+}
+
+pub fn discovery_router() -> Router {
+    Router::new()
+        .route("/scan", get(scan_handler))
+        .route("/adopt", post(adopt))
+}
+
+/// The schema of [`discovery_router`], mounted at `/api/v1/discovery`.
+#[derive(OpenApi)]
+#[openapi(paths(scan_handler, adopt))]
+pub(crate) struct DiscoveryApi;
+
+/// A camera that answered a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub(crate) struct DiscoveredCamera {
+    /// Its host, from the first of its device service URLs (an IPv4 one if
+    /// it has one): what `adopt` fills the RTSP template in with.
+    pub address: String,
+    pub scopes: Vec<String>,
+    /// From its `onvif://www.onvif.org/name/…` scope.
+    pub name: Option<String>,
+    /// From its `hardware` scope, usually the model.
+    pub hardware: Option<String>,
+    /// Its device service URLs.
+    pub xaddrs: Vec<String>,
+    /// Its WS-Addressing endpoint (`urn:uuid:…`), the same on every scan.
+    pub endpoint: String,
+}
+
+/// A ProbeMatches message: the Probe it answers, and the cameras in it.
+#[derive(Debug, Default)]
+pub(crate) struct ProbeMatches {
+    pub relates_to: Option<String>,
+    pub matches: Vec<DiscoveredCamera>,
+}
+
+/// The fields of one `ProbeMatch` while it is read.
+#[derive(Default)]
+struct RawMatch {
+    endpoint: String,
+    scopes: Vec<String>,
+    xaddrs: Vec<String>,
+}
+
+impl RawMatch {
+    /// `None` for a match without a usable service URL (some cameras only
+    /// give theirs when resolved).
+    fn finish(self) -> Option<DiscoveredCamera> {
+        let hosts = self.xaddrs.iter().map(String::as_str).filter_map(host_of);
+        let address = hosts
+            .clone()
+            .find(|host| host.parse::<Ipv4Addr>().is_ok())
+            .or_else(|| hosts.clone().next())?;
+        let scope = |kind: &str| {
+            let prefix = format!("onvif://www.onvif.org/{kind}/");
+            self.scopes
+                .iter()
+                .find_map(|scope| scope.strip_prefix(&prefix))
+                .map(percent_decode)
+                .filter(|value| !value.is_empty())
+        };
+        Some(DiscoveredCamera {
+            address,
+            name: scope("name"),
+            hardware: scope("hardware"),
+            scopes: self.scopes,
+            xaddrs: self.xaddrs,
+            endpoint: self.endpoint,
+        })
+    }
+}
+
+/// The host of a service URL, without port or brackets: `192.168.1.64` of
+/// `http://192.168.1.64:80/onvif/device_service`.
+fn host_of(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(v6) => v6.split_once(']')?.0,
+        None => host.split(':').next()?,
+    };
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// `%XX` escapes of a scope value, decoded; scopes are URIs.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Read a ProbeMatches message. Any other well-formed message (a Probe or
+/// Hello of another client) has no matches; malformed XML is an error.
+pub(crate) fn parse_probe_matches(xml: &str) -> anyhow::Result<ProbeMatches> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let mut reply = ProbeMatches::default();
+    // Local names of the open elements; prefixes differ between cameras.
+    let mut open: Vec<String> = Vec::new();
+    let mut current: Option<RawMatch> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                if name == "ProbeMatch" {
+                    current = Some(RawMatch::default());
+                }
+                open.push(name);
+            }
+            Event::End(_) => {
+                if open.pop().as_deref() == Some("ProbeMatch")
+                    && let Some(camera) = current.take().and_then(RawMatch::finish)
+                {
+                    reply.matches.push(camera);
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?;
+                let list = || text.split_whitespace().map(str::to_string).collect();
+                match (open.last().map(String::as_str), current.as_mut()) {
+                    (Some("RelatesTo"), _) => reply.relates_to = Some(text.trim().to_string()),
+                    (Some("Address"), Some(raw)) => raw.endpoint = text.trim().to_string(),
+                    (Some("Scopes"), Some(raw)) => raw.scopes = list(),
+                    (Some("XAddrs"), Some(raw)) => raw.xaddrs = list(),
+                    _ => {}
+                }
+            }
+            Event::Eof => return Ok(reply),
+            _ => {}
+        }
+    }
+}
+
+/// A Probe for network video transmitters, as message `message_id`.
+pub(crate) fn probe_xml(message_id: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<e:Envelope xmlns:e="http://www.w3.org/2003/05/soap-envelope"
+ xmlns:w="http://schemas.xmlsoap.org/ws/2004/08/addressing"
+ xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery"
+ xmlns:dn="http://www.onvif.org/ver10/network/wsdl">
+<e:Header>
+<w:MessageID>{message_id}</w:MessageID>
+<w:To e:mustUnderstand="true">urn:schemas-xmlsoap-org:ws:2005:04:discovery</w:To>
+<w:Action e:mustUnderstand="true">http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</w:Action>
+</e:Header>
+<e:Body><d:Probe><d:Types>dn:NetworkVideoTransmitter</d:Types></d:Probe></e:Body>
+</e:Envelope>"#
+    )
+}
+
+/// Probe `target` (the multicast group, or one camera) and collect the
+/// cameras answering, once each, until `timeout` passes or `cancel` fires.
+/// Replies to other probes and unreadable ones are left out.
+pub(crate) async fn scan(
+    target: SocketAddr,
+    timeout: Duration,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Vec<DiscoveredCamera>> {
+    let bind: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    if target.ip().is_multicast() {
+        socket.set_multicast_ttl_v4(MULTICAST_TTL)?;
+    }
+    let message_id = format!("urn:uuid:{}", uuid::Uuid::new_v4());
+    socket
+        .send_to(probe_xml(&message_id).as_bytes(), target)
+        .await?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut found: Vec<DiscoveredCamera> = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let received = tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep_until(deadline) => break,
+            received = socket.recv_from(&mut buf) => received,
+        };
+        let (len, from) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                log::debug!("discovery: receive failed: {e}");
+                continue;
+            }
+        };
+        let reply = match parse_probe_matches(&String::from_utf8_lossy(&buf[..len])) {
+            Ok(reply) => reply,
+            Err(e) => {
+                log::debug!("discovery: unreadable reply from {from}: {e:#}");
+                continue;
+            }
+        };
+        if reply
+            .relates_to
+            .as_ref()
+            .is_some_and(|id| *id != message_id)
+        {
+            continue;
+        }
+        for camera in reply.matches {
+            let seen = found.iter().any(|known| {
+                known.address == camera.address
+                    || (!camera.endpoint.is_empty() && known.endpoint == camera.endpoint)
+            });
+            if !seen {
+                found.push(camera);
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ScanQuery {
+    /// How long to wait for answers, 500 to 10000.
+    timeout_ms: Option<u64>,
+}
+
+/// `GET /api/discovery/scan`: the ONVIF cameras on the LAN that answer a
+/// WS-Discovery probe within the timeout.
+#[utoipa::path(
+    get,
+    path = "/scan",
+    tag = "discovery",
+    params(ScanQuery),
+    responses((status = 200, body = BaseResponse<Vec<DiscoveredCamera>>))
+)]
+async fn scan_handler(Query(query): Query<ScanQuery>) -> ApiJsonResult<Vec<DiscoveredCamera>> {
+    let timeout = query.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+    let timeout = Duration::from_millis(timeout.clamp(MIN_TIMEOUT_MS, MAX_TIMEOUT_MS));
+    let found = scan(WS_DISCOVERY.into(), timeout, &shutdown()).await?;
+    log::info!("discovery: {} camera(s) answered", found.len());
+    Ok(ok_json(found))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct AdoptRequest {
+    /// The `address` of a scan result.
+    address: String,
+    /// The device name, e.g. the scan result's; the address when absent.
+    #[serde(default)]
+    name: Option<String>,
+    /// The camera's RTSP URL, `{address}` standing for the address;
+    /// `rtsp://{address}:554/` when absent.
+    #[serde(default)]
+    rtsp_template: Option<String>,
+    /// Put into the URL when given.
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+}
+
+impl AdoptRequest {
+    /// The RTSP URL of the device, or why there is none.
+    pub(crate) fn rtsp_url(&self) -> Result<String, String> {
+        let address = self.address.trim();
+        if address.is_empty() || address.contains(['/', '@', ' ']) {
+            return Err(format!("not a camera address: {:?}", self.address));
+        }
+        // A bare IPv6 address needs its brackets in a URL.
+        let host = if address.contains(':') && !address.starts_with('[') {
+            format!("[{address}]")
+        } else {
+            address.to_string()
+        };
+        let template = self.rtsp_template.as_deref().map(str::trim);
+        let template = template.filter(|t| !t.is_empty());
+        let template = template.unwrap_or(DEFAULT_RTSP_TEMPLATE);
+        if !template.starts_with("rtsp://") || !template.contains("{address}") {
+            return Err("rtsp_template must be an rtsp:// URL with {address} in it".to_string());
+        }
+        let url = template.replace("{address}", &host);
+        Ok(nvr_onvif::inject_credentials(
+            &url,
+            &self.username,
+            &self.password,
+        ))
+    }
+}
+
+/// `POST /api/discovery/adopt`: add a discovered camera as an `rtsp` device
+/// on its templated URL, as `POST /api/device/add` would.
+#[utoipa::path(
+    post,
+    path = "/adopt",
+    tag = "discovery",
+    request_body = AdoptRequest,
+    responses(
+        (status = 200, body = BaseResponse<nvr_db::device::DeviceInfo>),
+        (status = 400, description = "No usable address or template", body = String),
+        (status = 409, description = "Another device uses the stream key", body = String),
+    )
+)]
+async fn adopt(
+    Extension(user): Extension<AuthUser>,
+    Json(request): Json<AdoptRequest>,
+) -> ApiResult<Response> {
+    let url = match request.rtsp_url() {
+        Ok(url) => url,
+        Err(reason) => return Ok((StatusCode::BAD_REQUEST, reason).into_response()),
+    };
+    let name = request.name.as_deref().map(str::trim).unwrap_or_default();
+    let name = if name.is_empty() {
+        request.address.trim()
+    } else {
+        name
+    };
+    log::info!("discovery: adopting {} as {name:?}", request.address.trim());
+    let input = DeviceInput::Rtsp(FailoverInput::plain(url));
+    crate::handler::device::add(&user, DevicePayload::typed(name.to_string(), &input)).await
+}
+
+#[cfg(test)]
+#[path = "discovery_test.rs"]
+mod discovery_test;
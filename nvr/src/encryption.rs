@@ -0,0 +1,794 @@
+//! Encryption at rest of archived recordings. With `NVR_ENCRYPT_RECORDINGS=1`
+//! (every device) or a device's `encrypt` flag, each closed segment is
+//! sealed into `<name>.mp4.enc` once it is archived and probed, and the
+//! plaintext copy is removed.
+//!
+//! A sealed file is a header followed by the segment in [`CHUNK_SIZE`]
+//! chunks, each encrypted on its own with AES-256-GCM:
+//!
+//! ```text
+//! magic "NVRENC" 00 01 | chunk size u32 | plaintext length u64 | salt [16]
+//! | nonce prefix [8] | key id length u8 | key id
+//! chunk 0: ciphertext | tag [16]
+//! chunk 1: ...
+//! ```
+//!
+//! Integers are little-endian. A chunk's nonce is the file's nonce prefix
+//! and the chunk index (big-endian u32), its associated data the whole
+//! header, and its key the SHA-256 of the recording key and the file's salt.
+//! So any chunk decrypts without the others: [`EncryptedFile`] (blocking)
+//! and [`EncryptedFileReader`] (async) seek like the plaintext would, which
+//! keeps HTTP range requests and FFmpeg probing working
+//! ([`ffmpeg_bus::metadata::probe_reader`]).
+//!
+//! Keys come from the key file `NVR_ENCRYPTION_KEYS` (a mounted secret), one
+//! `<key id>:<64 hex digits>` per line. New segments use the last key;
+//! older files name theirs in the header, so rotating is appending a line
+//! and keeping the old ones. The file is read per use, without a restart.
+//!
+//! FFmpeg readers that need a path (posters, exports) get a [`Plaintext`]
+//! copy in `NVR_DECRYPT_DIR`, removed when dropped; the directory is also
+//! emptied at startup. An export of encrypted recordings is sealed too.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{Context as _, Result};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+use crate::config::config;
+
+/// Extension added to sealed files (`a.mp4` becomes `a.mp4.enc`).
+pub const EXTENSION: &str = "enc";
+/// Plaintext bytes per chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const MAGIC: &[u8; 8] = b"NVRENC\x00\x01";
+const TAG_LEN: usize = 16;
+/// Header bytes before the key id.
+const FIXED_HEADER_LEN: usize = 8 + 4 + 8 + 16 + 8 + 1;
+
+/// Encryption settings, parsed from environment variables.
+#[derive(Debug, Clone)]
+pub struct EncryptionConfig {
+    /// Seal the segments of every device (`NVR_ENCRYPT_RECORDINGS=1`), not
+    /// only of devices with `encrypt` set.
+    pub all_devices: bool,
+    /// The key file (`NVR_ENCRYPTION_KEYS`).
+    pub keys_file: Option<PathBuf>,
+    /// Where decrypted copies go (`NVR_DECRYPT_DIR`); defaults to
+    /// `<tmp>/nvr-decrypted`. The copies are kept in [`COPIES_DIR`] under
+    /// it, which the server creates and owns.
+    pub temp_dir: PathBuf,
+}
+
+/// The directory under [`EncryptionConfig::temp_dir`] decrypted copies are
+/// written to.
+pub const COPIES_DIR: &str = "nvr-plaintext";
+
+impl EncryptionConfig {
+    /// Whether every device's recordings are encrypted (`NVR_ENCRYPT_RECORDINGS`
+    /// of `1` or `true`), where the keys live and where decrypted copies go,
+    /// from the values `get` returns; copies default to the system temp dir.
+    pub fn from_map(get: impl Fn(&str) -> Option<String>) -> EncryptionConfig {
+        let path = |key: &str| {
+            get(key)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+        EncryptionConfig {
+            all_devices: get("NVR_ENCRYPT_RECORDINGS")
+                .is_some_and(|v| matches!(v.trim(), "1" | "true")),
+            keys_file: path("NVR_ENCRYPTION_KEYS"),
+            temp_dir: path("NVR_DECRYPT_DIR")
+                .unwrap_or_else(|| std::env::temp_dir().join("nvr-decrypted")),
+        }
+    }
+
+    /// Parse from the real process environment.
+    pub fn from_env() -> EncryptionConfig {
+        Self::from_map(|k| std::env::var(k).ok())
+    }
+
+    /// The directory decrypted copies are written to.
+    pub fn copies_dir(&self) -> PathBuf {
+        self.temp_dir.join(COPIES_DIR)
+    }
+}
+
+/// The recording keys, by id, and the one new files are sealed with.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRing {
+    keys: HashMap<String, [u8; 32]>,
+    current: Option<String>,
+}
+
+impl KeyRing {
+    /// Parse a key file: `<key id>:<hex key>` lines, the last one current.
+    /// Blank lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> Result<KeyRing> {
+        let mut ring = KeyRing::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (id, hex_key) = line
+                .split_once(':')
+                .with_context(|| format!("key file line {}: expected <id>:<key>", number + 1))?;
+            ring.insert(id.trim(), hex_key.trim())
+                .with_context(|| format!("key file line {}", number + 1))?;
+        }
+        Ok(ring)
+    }
+
+    /// Add key `id` (64 hex digits) and make it the current one.
+    pub fn insert(&mut self, id: &str, hex_key: &str) -> Result<()> {
+        anyhow::ensure!(
+            !id.is_empty() && id.len() <= u8::MAX as usize,
+            "key id must be 1 to 255 bytes"
+        );
+        let mut key = [0u8; 32];
+        hex::decode_to_slice(hex_key, &mut key).context("key must be 64 hex digits (32 bytes)")?;
+        self.keys.insert(id.to_string(), key);
+        self.current = Some(id.to_string());
+        Ok(())
+    }
+
+    /// The configured key file's keys; empty without one.
+    pub fn load() -> Result<KeyRing> {
+        match &config().encryption().keys_file {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("read key file {}", path.display()))?;
+                KeyRing::parse(&text)
+            }
+            None => Ok(KeyRing::default()),
+        }
+    }
+
+    /// The id new files are sealed with.
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    fn get(&self, id: &str) -> io::Result<&[u8; 32]> {
+        self.keys.get(id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("recording key {id:?} is not in the key file"),
+            )
+        })
+    }
+}
+
+/// The per-file cipher.
+fn file_cipher(key: &[u8; 32], salt: &[u8]) -> Aes256Gcm {
+    let file_key = Sha256::new()
+        .chain_update(key)
+        .chain_update(salt)
+        .finalize();
+    Aes256Gcm::new(&file_key)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// A sealed file's header, and what locating and opening its chunks takes.
+struct Sealed {
+    /// The raw header, the associated data of every chunk.
+    header: Vec<u8>,
+    chunk_size: u64,
+    plain_len: u64,
+    nonce_prefix: [u8; 8],
+    cipher: Aes256Gcm,
+}
+
+impl Sealed {
+    /// The header of a new file of `plain_len` bytes, under key `key_id`.
+    fn create(plain_len: u64, key_id: &str, key: &[u8; 32]) -> Sealed {
+        let mut salt = [0u8; 16];
+        let mut nonce_prefix = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce_prefix);
+        let mut header = Vec::with_capacity(FIXED_HEADER_LEN + key_id.len());
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&(CHUNK_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&plain_len.to_le_bytes());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce_prefix);
+        header.push(key_id.len() as u8);
+        header.extend_from_slice(key_id.as_bytes());
+        Sealed {
+            header,
+            chunk_size: CHUNK_SIZE as u64,
+            plain_len,
+            nonce_prefix,
+            cipher: file_cipher(key, &salt),
+        }
+    }
+
+    /// The key id length a fixed header announces.
+    fn key_id_len(fixed: &[u8; FIXED_HEADER_LEN]) -> io::Result<usize> {
+        if &fixed[..8] != MAGIC {
+            return Err(invalid("not a sealed recording (bad magic or version)"));
+        }
+        Ok(fixed[FIXED_HEADER_LEN - 1] as usize)
+    }
+
+    /// Parse a whole header (fixed part and key id).
+    fn parse(header: Vec<u8>, keys: &KeyRing) -> io::Result<Sealed> {
+        let field = |range: std::ops::Range<usize>| &header[range];
+        let chunk_size = u32::from_le_bytes(field(8..12).try_into().unwrap()) as u64;
+        let plain_len = u64::from_le_bytes(field(12..20).try_into().unwrap());
+        let salt = field(20..36).to_vec();
+        let nonce_prefix = field(36..44).try_into().unwrap();
+        if chunk_size == 0 {
+            return Err(invalid("sealed recording has a zero chunk size"));
+        }
+        let key_id = std::str::from_utf8(&header[FIXED_HEADER_LEN..])
+            .map_err(|_| invalid("sealed recording has a malformed key id"))?;
+        let cipher = file_cipher(keys.get(key_id)?, &salt);
+        Ok(Sealed {
+            header,
+            chunk_size,
+            plain_len,
+            nonce_prefix,
+            cipher,
+        })
+    }
+
+    fn chunks(&self) -> u64 {
+        self.plain_len.div_ceil(self.chunk_size)
+    }
+
+    /// Plaintext bytes of chunk `index`.
+    fn plain_chunk_len(&self, index: u64) -> usize {
+        (self.plain_len - index * self.chunk_size).min(self.chunk_size) as usize
+    }
+
+    /// Where chunk `index` starts in the file.
+    fn chunk_offset(&self, index: u64) -> u64 {
+        self.header.len() as u64 + index * (self.chunk_size + TAG_LEN as u64)
+    }
+
+    /// The size of the whole file.
+    fn file_len(&self) -> u64 {
+        self.header.len() as u64 + self.plain_len + self.chunks() * TAG_LEN as u64
+    }
+
+    fn nonce(&self, index: u64) -> io::Result<[u8; 12]> {
+        let index = u32::try_from(index).map_err(|_| invalid("recording too large to seal"))?;
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.nonce_prefix);
+        nonce[8..].copy_from_slice(&index.to_be_bytes());
+        Ok(nonce)
+    }
+
+    fn seal(&self, index: u64, plain: &[u8]) -> io::Result<Vec<u8>> {
+        let payload = Payload {
+            msg: plain,
+            aad: &self.header,
+        };
+        self.cipher
+            .encrypt(Nonce::from_slice(&self.nonce(index)?), payload)
+            .map_err(|_| invalid("encryption failed"))
+    }
+
+    fn open(&self, index: u64, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let payload = Payload {
+            msg: sealed,
+            aad: &self.header,
+        };
+        self.cipher
+            .decrypt(Nonce::from_slice(&self.nonce(index)?), payload)
+            .map_err(|_| {
+                invalid(format!(
+                    "chunk {index} of a sealed recording does not authenticate"
+                ))
+            })
+    }
+
+    /// The file position `pos` (relative to `whence`) seeks to.
+    fn seek_target(&self, pos: u64, whence: SeekFrom) -> io::Result<u64> {
+        let target = match whence {
+            SeekFrom::Start(at) => Some(at),
+            SeekFrom::Current(delta) => pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.plain_len.checked_add_signed(delta),
+        };
+        target.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))
+    }
+}
+
+/// Seal `plain` into `sealed_path` under the key ring's current key; returns
+/// the bytes written. Blocking. A partly written file is removed.
+pub fn encrypt_file(plain: &Path, sealed_path: &Path, keys: &KeyRing) -> Result<u64> {
+    let key_id = keys
+        .current()
+        .context("no recording key: set NVR_ENCRYPTION_KEYS to a key file")?;
+    let mut input = io::BufReader::new(std::fs::File::open(plain)?);
+    let plain_len = input.get_ref().metadata()?.len();
+    let sealed = Sealed::create(plain_len, key_id, keys.get(key_id)?);
+    let part = sealed_path.with_extension(format!("{EXTENSION}.part"));
+    let written = (|| -> Result<u64> {
+        let mut output = io::BufWriter::new(std::fs::File::create(&part)?);
+        output.write_all(&sealed.header)?;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        for index in 0..sealed.chunks() {
+            let len = sealed.plain_chunk_len(index);
+            input.read_exact(&mut chunk[..len])?;
+            output.write_all(&sealed.seal(index, &chunk[..len])?)?;
+        }
+        output
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(&part, sealed_path)?;
+        Ok(sealed.file_len())
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+    written.with_context(|| format!("seal {}", plain.display()))
+}
+
+/// Whether `path` is a sealed file, by its extension.
+pub fn is_encrypted(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(EXTENSION))
+}
+
+/// `path` with the sealed extension added.
+pub fn sealed_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{EXTENSION}"));
+    PathBuf::from(name)
+}
+
+/// Seal archived segment `path` if its device wants it, returning the path
+/// the recording lives at now. On failure the plaintext is kept (an
+/// unencrypted recording beats a lost one) and the error logged.
+pub async fn seal_segment(device_id: &str, path: PathBuf) -> PathBuf {
+    match wanted(device_id).await {
+        Ok(false) => return path,
+        Ok(true) => {}
+        Err(e) => {
+            log::error!("encryption: cannot tell whether {device_id} is encrypted: {e:#}");
+            return path;
+        }
+    }
+    let sealed = sealed_path(&path);
+    let sealing = tokio::task::spawn_blocking({
+        let (path, sealed) = (path.clone(), sealed.clone());
+        move || {
+            encrypt_file(&path, &sealed, &KeyRing::load()?)?;
+            std::fs::remove_file(&path)?;
+            anyhow::Ok(())
+        }
+    });
+    match sealing.await.map_err(anyhow::Error::from).and_then(|r| r) {
+        Ok(()) => sealed,
+        Err(e) => {
+            log::error!("encryption: {} kept unencrypted: {e:#}", path.display());
+            path
+        }
+    }
+}
+
+/// Whether `device_id`'s segments are sealed.
+async fn wanted(device_id: &str) -> Result<bool> {
+    if config().encryption().all_devices {
+        return Ok(true);
+    }
+    let conn = crate::db::app_db_conn()?;
+    Ok(nvr_db::device::get(device_id, &conn)
+        .await?
+        .is_some_and(|device| device.encrypt))
+}
+
+/// A sealed file read as its plaintext, blocking. Seeks are free: only the
+/// chunk holding the position is read and decrypted.
+pub struct EncryptedFile<R> {
+    inner: R,
+    sealed: Sealed,
+    pos: u64,
+    /// The last decrypted chunk and its index.
+    chunk: Option<(u64, Vec<u8>)>,
+}
+
+impl EncryptedFile<io::BufReader<std::fs::File>> {
+    pub fn open(path: impl AsRef<Path>, keys: &KeyRing) -> io::Result<Self> {
+        Self::new(io::BufReader::new(std::fs::File::open(path)?), keys)
+    }
+}
+
+impl<R: Read + Seek> EncryptedFile<R> {
+    /// Read the header of `inner`, which must be at its start.
+    pub fn new(mut inner: R, keys: &KeyRing) -> io::Result<Self> {
+        let mut fixed = [0u8; FIXED_HEADER_LEN];
+        inner.read_exact(&mut fixed)?;
+        let mut header = fixed.to_vec();
+        header.resize(FIXED_HEADER_LEN + Sealed::key_id_len(&fixed)?, 0);
+        inner.read_exact(&mut header[FIXED_HEADER_LEN..])?;
+        let sealed = Sealed::parse(header, keys)?;
+        if inner.seek(SeekFrom::End(0))? != sealed.file_len() {
+            return Err(invalid(
+                "sealed recording is truncated or has trailing data",
+            ));
+        }
+        Ok(EncryptedFile {
+            inner,
+            sealed,
+            pos: 0,
+            chunk: None,
+        })
+    }
+
+    /// Length of the plaintext.
+    pub fn len(&self) -> u64 {
+        self.sealed.plain_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<R: Read + Seek> Read for EncryptedFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.sealed.plain_len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.pos / self.sealed.chunk_size;
+        if self
+            .chunk
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != index)
+        {
+            self.inner
+                .seek(SeekFrom::Start(self.sealed.chunk_offset(index)))?;
+            let mut sealed = vec![0u8; self.sealed.plain_chunk_len(index) + TAG_LEN];
+            self.inner.read_exact(&mut sealed)?;
+            self.chunk = Some((index, self.sealed.open(index, &sealed)?));
+        }
+        let (_, plain) = self.chunk.as_ref().unwrap();
+        let at = (self.pos - index * self.sealed.chunk_size) as usize;
+        let len = buf.len().min(plain.len() - at);
+        buf[..len].copy_from_slice(&plain[at..at + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for EncryptedFile<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = self.sealed.seek_target(self.pos, pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// What an [`EncryptedFileReader`] is doing to the file.
+enum Fetch {
+    Idle,
+    /// Seeking to the start of chunk `.0`.
+    Seeking(u64),
+    /// Reading chunk `index`, `filled` of its bytes so far.
+    Reading {
+        index: u64,
+        buf: Vec<u8>,
+        filled: usize,
+    },
+}
+
+/// [`EncryptedFile`] for async readers: a sealed file read as its
+/// plaintext, e.g. to answer HTTP range requests.
+pub struct EncryptedFileReader {
+    file: tokio::fs::File,
+    sealed: Sealed,
+    pos: u64,
+    chunk: Option<(u64, Vec<u8>)>,
+    /// Where `file` is, while known.
+    file_pos: Option<u64>,
+    fetch: Fetch,
+}
+
+impl EncryptedFileReader {
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let keys = tokio::task::spawn_blocking(KeyRing::load)
+            .await?
+            .map_err(io::Error::other)?;
+        Self::open_with(path, &keys).await
+    }
+
+    pub async fn open_with(path: impl AsRef<Path>, keys: &KeyRing) -> io::Result<Self> {
+        use tokio::io::AsyncReadExt;
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut fixed = [0u8; FIXED_HEADER_LEN];
+        file.read_exact(&mut fixed).await?;
+        let mut header = fixed.to_vec();
+        header.resize(FIXED_HEADER_LEN + Sealed::key_id_len(&fixed)?, 0);
+        file.read_exact(&mut header[FIXED_HEADER_LEN..]).await?;
+        let sealed = Sealed::parse(header, keys)?;
+        if file.metadata().await?.len() != sealed.file_len() {
+            return Err(invalid(
+                "sealed recording is truncated or has trailing data",
+            ));
+        }
+        Ok(EncryptedFileReader {
+            file_pos: Some(sealed.header.len() as u64),
+            file,
+            sealed,
+            pos: 0,
+            chunk: None,
+            fetch: Fetch::Idle,
+        })
+    }
+
+    /// Length of the plaintext.
+    pub fn len(&self) -> u64 {
+        self.sealed.plain_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drive the fetch in progress, or start one of chunk `index`, until a
+    /// chunk is decrypted into the cache.
+    fn poll_fetch(&mut self, cx: &mut Context<'_>, index: u64) -> Poll<io::Result<()>> {
+        loop {
+            match std::mem::replace(&mut self.fetch, Fetch::Idle) {
+                Fetch::Idle => {
+                    let offset = self.sealed.chunk_offset(index);
+                    if self.file_pos == Some(offset) {
+                        self.fetch = Fetch::Reading {
+                            index,
+                            buf: vec![0u8; self.sealed.plain_chunk_len(index) + TAG_LEN],
+                            filled: 0,
+                        };
+                    } else {
+                        self.file_pos = None;
+                        Pin::new(&mut self.file).start_seek(SeekFrom::Start(offset))?;
+                        self.fetch = Fetch::Seeking(index);
+                    }
+                }
+                Fetch::Seeking(index) => match Pin::new(&mut self.file).poll_complete(cx) {
+                    Poll::Pending => {
+                        self.fetch = Fetch::Seeking(index);
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(offset) => {
+                        self.file_pos = Some(offset?);
+                        self.fetch = Fetch::Reading {
+                            index,
+                            buf: vec![0u8; self.sealed.plain_chunk_len(index) + TAG_LEN],
+                            filled: 0,
+                        };
+                    }
+                },
+                Fetch::Reading {
+                    index,
+                    mut buf,
+                    mut filled,
+                } => {
+                    while filled < buf.len() {
+                        let mut read = ReadBuf::new(&mut buf[filled..]);
+                        match Pin::new(&mut self.file).poll_read(cx, &mut read) {
+                            Poll::Pending => {
+                                self.fetch = Fetch::Reading { index, buf, filled };
+                                return Poll::Pending;
+                            }
+                            Poll::Ready(Err(e)) => {
+                                self.file_pos = None;
+                                return Poll::Ready(Err(e));
+                            }
+                            Poll::Ready(Ok(())) if read.filled().is_empty() => {
+                                self.file_pos = None;
+                                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                            }
+                            Poll::Ready(Ok(())) => filled += read.filled().len(),
+                        }
+                    }
+                    self.file_pos = self.file_pos.map(|at| at + buf.len() as u64);
+                    self.chunk = Some((index, self.sealed.open(index, &buf)?));
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for EncryptedFileReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.pos >= this.sealed.plain_len || out.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            let index = this.pos / this.sealed.chunk_size;
+            if let Some((cached, plain)) = &this.chunk
+                && *cached == index
+            {
+                let at = (this.pos - index * this.sealed.chunk_size) as usize;
+                let len = out.remaining().min(plain.len() - at);
+                out.put_slice(&plain[at..at + len]);
+                this.pos += len as u64;
+                return Poll::Ready(Ok(()));
+            }
+            ready!(this.poll_fetch(cx, index))?;
+        }
+    }
+}
+
+impl AsyncSeek for EncryptedFileReader {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        this.pos = this.sealed.seek_target(this.pos, position)?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+/// Plaintext length of the recording at `path`, sealed or not.
+pub async fn media_len(path: &str) -> io::Result<u64> {
+    if is_encrypted(path) {
+        Ok(EncryptedFileReader::open(path).await?.len())
+    } else {
+        Ok(tokio::fs::metadata(path).await?.len())
+    }
+}
+
+/// A path FFmpeg can open for a recording: the recording itself, or a
+/// decrypted copy in the temp dir that is removed on drop.
+pub struct Plaintext {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl Plaintext {
+    /// Decrypt `path` into the temp dir if it is sealed. Blocking.
+    pub fn of(path: &Path) -> Result<Plaintext> {
+        if !is_encrypted(path) {
+            return Ok(Plaintext {
+                path: path.to_path_buf(),
+                temporary: false,
+            });
+        }
+        // Keep the container extension, FFmpeg's muxers and demuxers go by it.
+        let extension = Path::new(path.file_stem().unwrap_or_default())
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "mp4".to_string());
+        let copy = Plaintext::temp(&extension)?;
+        let mut reader = EncryptedFile::open(path, &KeyRing::load()?)
+            .with_context(|| format!("open {}", path.display()))?;
+        let mut output = io::BufWriter::new(std::fs::File::create(&copy.path)?);
+        io::copy(&mut reader, &mut output)?;
+        output.flush()?;
+        Ok(copy)
+    }
+
+    /// A new, not yet created file in the temp dir, removed on drop.
+    pub fn temp(extension: &str) -> Result<Plaintext> {
+        let dir = config().encryption().copies_dir();
+        prepare_copies_dir(&dir)?;
+        Ok(Plaintext {
+            path: dir.join(format!("{}.{extension}", uuid::Uuid::new_v4().simple())),
+            temporary: true,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether this is a decrypted copy (the source was sealed).
+    pub fn is_temporary(&self) -> bool {
+        self.temporary
+    }
+}
+
+impl Drop for Plaintext {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Create `dir` for decrypted copies, readable by the server's user alone.
+/// An existing one is refused if it is not a directory (or is a symlink) and
+/// has its permissions tightened if others can reach into it.
+fn prepare_copies_dir(dir: &Path) -> Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(dir)
+        .with_context(|| format!("create {}", dir.display()))?;
+    let meta = std::fs::symlink_metadata(dir).with_context(|| format!("stat {}", dir.display()))?;
+    if !meta.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if meta.permissions().mode() & 0o077 != 0 {
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+                .with_context(|| format!("restrict {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is that of a copy [`Plaintext::temp`] makes: a simple
+/// UUID and an extension.
+fn is_copy_name(name: &str) -> bool {
+    name.split_once('.').is_some_and(|(stem, extension)| {
+        stem.len() == 32
+            && stem.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+            && !extension.is_empty()
+    })
+}
+
+/// Delete the copies in `dir` a previous run left behind, and nothing else
+/// found there. The number deleted.
+fn remove_leftovers(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && entry.file_name().to_str().is_some_and(is_copy_name) {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Startup: delete the copies a previous run left behind, and check the key
+/// file when every device is encrypted.
+pub fn init() {
+    let encryption = config().encryption();
+    let dir = encryption.copies_dir();
+    match remove_leftovers(&dir) {
+        Ok(0) => {}
+        Ok(removed) => log::info!(
+            "encryption: removed {removed} decrypted leftovers in {}",
+            dir.display()
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("encryption: cannot clean up {}: {e}", dir.display()),
+    }
+    if encryption.all_devices {
+        match KeyRing::load() {
+            Ok(keys) if keys.current().is_some() => {}
+            Ok(_) => log::error!(
+                "encryption: NVR_ENCRYPT_RECORDINGS is set but there is no key \
+                 (NVR_ENCRYPTION_KEYS); recordings stay unencrypted"
+            ),
+            Err(e) => log::error!("encryption: {e:#}; recordings stay unencrypted"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "encryption_test.rs"]
+mod encryption_test;
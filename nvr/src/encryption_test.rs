@@ -0,0 +1,212 @@
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::*;
+
+const KEY_1: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+const KEY_2: &str = "f00df00df00df00df00df00df00df00df00df00df00df00df00df00df00df00d";
+
+fn ring(keys: &[(&str, &str)]) -> KeyRing {
+    let mut ring = KeyRing::default();
+    for (id, key) in keys {
+        ring.insert(id, key).unwrap();
+    }
+    ring
+}
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nvr-encryption-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+/// Bytes that never repeat within a chunk, so a misplaced chunk shows.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 251) as u8).collect()
+}
+
+/// Seal `plain` under `keys`, returning the sealed file's path.
+fn sealed_copy(plain: &[u8], keys: &KeyRing) -> PathBuf {
+    let path = scratch("segment.mp4");
+    std::fs::write(&path, plain).unwrap();
+    let sealed = sealed_path(&path);
+    encrypt_file(&path, &sealed, keys).unwrap();
+    sealed
+}
+
+#[test]
+fn segments_round_trip_byte_identical() {
+    let keys = ring(&[("k1", KEY_1)]);
+    for len in [0, 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 123] {
+        let plain = pattern(len);
+        let sealed = sealed_copy(&plain, &keys);
+        assert!(sealed.to_string_lossy().ends_with(".mp4.enc"));
+        assert!(is_encrypted(&sealed));
+
+        let on_disk = std::fs::read(&sealed).unwrap();
+        let chunks = len.div_ceil(CHUNK_SIZE);
+        assert_eq!(
+            on_disk.len(),
+            FIXED_HEADER_LEN + "k1".len() + len + chunks * TAG_LEN
+        );
+        if len >= 64 {
+            assert!(
+                !on_disk.windows(64).any(|w| w == &plain[..64]),
+                "plaintext on disk"
+            );
+        }
+
+        let mut decrypted = Vec::new();
+        let mut file = EncryptedFile::open(&sealed, &keys).unwrap();
+        assert_eq!(file.len(), len as u64);
+        file.read_to_end(&mut decrypted).unwrap();
+        assert!(decrypted == plain, "{len} bytes changed in the round trip");
+    }
+}
+
+#[tokio::test]
+async fn serves_byte_ranges_from_a_sealed_file() {
+    let keys = ring(&[("k1", KEY_1)]);
+    let plain = pattern(3 * CHUNK_SIZE + 500);
+    let sealed = sealed_copy(&plain, &keys);
+    let mut reader = EncryptedFileReader::open_with(&sealed, &keys)
+        .await
+        .unwrap();
+    assert_eq!(reader.len(), plain.len() as u64);
+
+    // Within a chunk, across chunk boundaries, backwards, and the tail.
+    let ranges = [
+        (0, 100),
+        (CHUNK_SIZE - 10, 20),
+        (CHUNK_SIZE * 2 + 7, CHUNK_SIZE + 300),
+        (5, CHUNK_SIZE * 2),
+        (plain.len() - 1, 1),
+    ];
+    for (start, len) in ranges {
+        reader.seek(SeekFrom::Start(start as u64)).await.unwrap();
+        let mut buf = vec![0u8; len];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert!(buf == plain[start..start + len], "range {start}+{len}");
+    }
+
+    // A suffix range, as `bytes=-500` asks for.
+    let at = reader.seek(SeekFrom::End(-500)).await.unwrap();
+    assert_eq!(at, plain.len() as u64 - 500);
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).await.unwrap();
+    assert!(tail == plain[plain.len() - 500..]);
+    assert!(
+        reader
+            .seek(SeekFrom::Current(-(plain.len() as i64) - 1))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn probes_media_through_the_reader() {
+    ffmpeg_bus::init().unwrap();
+    let fixture = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let keys = ring(&[("k1", KEY_1)]);
+    let sealed = scratch("fixture.mp4.enc");
+    encrypt_file(&fixture, &sealed, &keys).unwrap();
+
+    let expected = ffmpeg_bus::metadata::probe(&fixture.to_string_lossy()).unwrap();
+    let probed =
+        ffmpeg_bus::metadata::probe_reader(EncryptedFile::open(&sealed, &keys).unwrap()).unwrap();
+    assert_eq!(probed.format.duration_sec, expected.format.duration_sec);
+    assert_eq!(probed.streams.len(), expected.streams.len());
+    for (probed, expected) in probed.streams.iter().zip(&expected.streams) {
+        assert_eq!(probed.codec_name, expected.codec_name);
+        assert_eq!(probed.width, expected.width);
+        assert_eq!(probed.sample_rate, expected.sample_rate);
+    }
+    // FFmpeg cannot make sense of the sealed bytes themselves.
+    assert!(ffmpeg_bus::metadata::probe(&sealed.to_string_lossy()).is_err());
+}
+
+#[test]
+fn rotation_keeps_old_files_readable() {
+    let old = ring(&[("k1", KEY_1)]);
+    let rotated = KeyRing::parse(&format!("# keys\nk1:{KEY_1}\n\nk2:{KEY_2}\n")).unwrap();
+    assert_eq!(rotated.current(), Some("k2"));
+
+    let plain = pattern(CHUNK_SIZE + 1);
+    let before = sealed_copy(&plain, &old);
+    let after = sealed_copy(&plain, &rotated);
+    // The header names the key each file was sealed with.
+    assert!(std::fs::read(&after).unwrap()[..FIXED_HEADER_LEN + 2].ends_with(b"k2"));
+
+    for path in [&before, &after] {
+        let mut decrypted = Vec::new();
+        EncryptedFile::open(path, &rotated)
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert!(decrypted == plain);
+    }
+    let missing = EncryptedFile::open(&after, &old).err().unwrap();
+    assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn tampering_and_truncation_are_caught() {
+    let keys = ring(&[("k1", KEY_1)]);
+    let plain = pattern(2 * CHUNK_SIZE);
+    let sealed = sealed_copy(&plain, &keys);
+    let bytes = std::fs::read(&sealed).unwrap();
+
+    let mut flipped = bytes.clone();
+    let in_second_chunk = FIXED_HEADER_LEN + 2 + CHUNK_SIZE + TAG_LEN + 9;
+    flipped[in_second_chunk] ^= 1;
+    std::fs::write(&sealed, &flipped).unwrap();
+    let mut file = EncryptedFile::open(&sealed, &keys).unwrap();
+    let mut first = vec![0u8; CHUNK_SIZE];
+    file.read_exact(&mut first).unwrap();
+    assert!(first == plain[..CHUNK_SIZE]);
+    let error = file.read(&mut first).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    std::fs::write(&sealed, &bytes[..bytes.len() - 1]).unwrap();
+    assert!(EncryptedFile::open(&sealed, &keys).is_err());
+    assert!(KeyRing::parse("k1:abcd").is_err());
+}
+
+#[test]
+fn only_the_servers_own_copies_are_cleaned_up() {
+    let root = scratch("decrypt");
+    // `NVR_DECRYPT_DIR` pointed at a directory with other files in it.
+    std::fs::create_dir_all(root.join("keep")).unwrap();
+    std::fs::write(root.join("notes.txt"), b"keep").unwrap();
+    let config = EncryptionConfig::from_map(|key| {
+        (key == "NVR_DECRYPT_DIR").then(|| root.to_string_lossy().into_owned())
+    });
+    let dir = config.copies_dir();
+    prepare_copies_dir(&dir).unwrap();
+    let copy = dir.join(format!("{}.mp4", uuid::Uuid::new_v4().simple()));
+    std::fs::write(&copy, b"plain").unwrap();
+    std::fs::write(dir.join("other.mp4"), b"keep").unwrap();
+
+    assert_eq!(remove_leftovers(&dir).unwrap(), 1);
+    assert!(!copy.exists());
+    assert!(dir.join("other.mp4").exists());
+    assert!(root.join("notes.txt").exists() && root.join("keep").is_dir());
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn an_existing_copies_dir_is_made_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = scratch(COPIES_DIR);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+    prepare_copies_dir(&dir).unwrap();
+    let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+
+    let file = scratch("not-a-dir");
+    std::fs::write(&file, b"").unwrap();
+    assert!(prepare_copies_dir(&file).is_err());
+}
@@ -0,0 +1,407 @@
+//! Clip export: remux a device's recordings covering a wall clock window into
+//! one MP4 under `<record_dir>/exports`, without transcoding (see
+//! `ffmpeg_bus::remux`), so a clip starts on the keyframe at or before the
+//! requested time. Exports are `export` jobs of the job runner (see
+//! [`crate::jobs`]): they are kept in the database, run again from the start
+//! when a restart cuts one off, and can be cancelled through
+//! `POST /api/jobs/{id}/cancel`. Finished files stay on disk.
+//!
+//! `GET /api/export` lists jobs, `GET /api/export/{id}` polls one,
+//! `GET /api/export/{id}/download` serves the clip and `POST /api/export`
+//! starts one for `{device_id, start, end}` (unix milliseconds).
+//!
+//! A clip of encrypted recordings is stored encrypted as well
+//! (`<id>.mp4.enc`, see [`crate::encryption`]) and decrypted as it is
+//! downloaded.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::Path,
+    http::{HeaderValue, header},
+    response::Response,
+    routing::get,
+};
+use ffmpeg_bus::remux::{ClipSource, TrimMode};
+use nvr_db::job::{Job, JobFilter, JobStatus};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::AuthUser;
+use crate::db::app_db_conn;
+use crate::encryption::{EncryptedFileReader, KeyRing, Plaintext, sealed_path};
+use crate::handler::{ApiJsonResult, ApiResult, BaseResponse, ok_json};
+use crate::jobs::JobContext;
+
+/// The job kind exports run as.
+pub const JOB_KIND: &str = "export";
+/// Longest clip that can be exported.
+const MAX_CLIP_MS: i64 = 60 * 60 * 1000;
+/// Most jobs [`list`] returns.
+const JOB_CAP: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ExportJob {
+    pub id: String,
+    pub device_id: String,
+    /// Requested window, unix milliseconds.
+    pub start: i64,
+    pub end: i64,
+    /// Cut exactly at `start` rather than at the keyframe before it.
+    pub exact: bool,
+    pub status: ExportStatus,
+    pub created_by: String,
+    /// Unix milliseconds.
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+    /// Actual clip window once done; starts at or before `start` (within a
+    /// frame of it if `exact`).
+    pub clip_start: Option<i64>,
+    pub clip_end: Option<i64>,
+    pub file_size: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// The payload of an export job.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportInput {
+    device_id: String,
+    start: i64,
+    end: i64,
+    #[serde(default)]
+    exact: bool,
+}
+
+/// The result of a done export job.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportOutput {
+    clip_start: i64,
+    clip_end: i64,
+    file_size: u64,
+}
+
+impl TryFrom<Job> for ExportJob {
+    type Error = anyhow::Error;
+
+    fn try_from(job: Job) -> Result<Self> {
+        let input: ExportInput = serde_json::from_value(job.payload)?;
+        let output = job
+            .result
+            .and_then(|result| serde_json::from_value::<ExportOutput>(result).ok());
+        // Waiting for a worker reads as running: it is on its way.
+        let (status, error) = match job.status {
+            JobStatus::Queued | JobStatus::Running => (ExportStatus::Running, None),
+            JobStatus::Done => (ExportStatus::Done, None),
+            JobStatus::Failed => (ExportStatus::Failed, job.error),
+            JobStatus::Cancelled => (
+                ExportStatus::Failed,
+                Some(job.error.unwrap_or_else(|| "cancelled".to_string())),
+            ),
+        };
+        Ok(Self {
+            id: job.id,
+            device_id: input.device_id,
+            start: input.start,
+            end: input.end,
+            exact: input.exact,
+            status,
+            created_by: job.created_by,
+            created_at: job.created_at,
+            finished_at: job.finished_at,
+            clip_start: output.as_ref().map(|o| o.clip_start),
+            clip_end: output.as_ref().map(|o| o.clip_end),
+            file_size: output.as_ref().map(|o| o.file_size),
+            error,
+        })
+    }
+}
+
+pub async fn get(id: &str) -> Result<Option<ExportJob>> {
+    match crate::jobs::get(id).await? {
+        Some(job) if job.kind == JOB_KIND => Ok(Some(job.try_into()?)),
+        _ => Ok(None),
+    }
+}
+
+/// The latest jobs, newest first.
+pub async fn list() -> Result<Vec<ExportJob>> {
+    let filter = JobFilter {
+        kind: Some(JOB_KIND.to_string()),
+        ..JobFilter::default()
+    };
+    crate::jobs::list(&filter, JOB_CAP)
+        .await?
+        .into_iter()
+        .map(ExportJob::try_from)
+        .collect()
+}
+
+fn clip_path(id: &str) -> PathBuf {
+    crate::config::config()
+        .record_dir()
+        .join("exports")
+        .join(format!("{id}.mp4"))
+}
+
+/// The recorded files of `device_id` overlapping `start..end` (unix
+/// milliseconds), in time order.
+async fn sources(device_id: &str, start: i64, end: i64) -> Result<Vec<ClipSource>> {
+    let conn = app_db_conn()?;
+    let segments =
+        crate::handler::playback::segments_overlapping(device_id, start, end, &conn).await?;
+    Ok(segments
+        .into_iter()
+        .map(|s| ClipSource {
+            start_ms: crate::handler::playback::segment_span(&s).0,
+            path: PathBuf::from(s.file_path),
+        })
+        .collect())
+}
+
+/// Start exporting `start..end` of `device_id`, cut exactly at `start` if
+/// `exact` (see [`TrimMode::Exact`]). Returns the job, still running; poll
+/// [`get`] for the outcome.
+pub async fn start(
+    device_id: &str,
+    start: i64,
+    end: i64,
+    exact: bool,
+    created_by: &str,
+) -> Result<ExportJob> {
+    anyhow::ensure!(
+        !crate::maintenance::is_active(),
+        "server in maintenance: no new exports"
+    );
+    anyhow::ensure!(end > start, "export end must be after its start");
+    anyhow::ensure!(
+        end - start <= MAX_CLIP_MS,
+        "export longer than {} minutes",
+        MAX_CLIP_MS / 60_000
+    );
+    anyhow::ensure!(
+        !sources(device_id, start, end).await?.is_empty(),
+        "no recordings of {device_id} in that window"
+    );
+    let input = ExportInput {
+        device_id: device_id.to_string(),
+        start,
+        end,
+        exact,
+    };
+    let job = crate::jobs::runner()
+        .enqueue(JOB_KIND, serde_json::to_value(&input)?, created_by)
+        .await?;
+    job.try_into()
+}
+
+/// The `export` job handler: remux the clip into [`clip_path`]. Idempotent,
+/// a rerun overwrites the file.
+pub async fn run(ctx: JobContext) -> Result<serde_json::Value> {
+    let ExportInput {
+        device_id,
+        start,
+        end,
+        exact,
+    } = ctx.input()?;
+    let trim = if exact {
+        TrimMode::Exact
+    } else {
+        TrimMode::KeyframeBefore
+    };
+    let sources = sources(&device_id, start, end).await?;
+    anyhow::ensure!(
+        !sources.is_empty(),
+        "no recordings of {device_id} in that window"
+    );
+    ctx.progress(10).await;
+
+    let output = clip_path(&ctx.id);
+    let mut remux = tokio::task::spawn_blocking({
+        let output = output.clone();
+        move || {
+            if let Some(dir) = output.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Sealed recordings are read from temporary plaintext copies, and
+            // their clip is remuxed in the temp dir and sealed in turn.
+            let plain = sources
+                .iter()
+                .map(|source| Plaintext::of(&source.path))
+                .collect::<Result<Vec<_>>>()?;
+            let sources = sources
+                .iter()
+                .zip(&plain)
+                .map(|(source, plain)| ClipSource {
+                    path: plain.path().to_path_buf(),
+                    start_ms: source.start_ms,
+                })
+                .collect::<Vec<_>>();
+            if plain.iter().any(Plaintext::is_temporary) {
+                let clip = Plaintext::temp("mp4")?;
+                let info =
+                    ffmpeg_bus::remux::remux_clip_with(&sources, start, end, clip.path(), trim)?;
+                let size = crate::encryption::encrypt_file(
+                    clip.path(),
+                    &sealed_path(&output),
+                    &KeyRing::load()?,
+                )?;
+                return anyhow::Ok((info, size));
+            }
+            let info = ffmpeg_bus::remux::remux_clip_with(&sources, start, end, &output, trim)?;
+            let size = std::fs::metadata(&output)?.len();
+            anyhow::Ok((info, size))
+        }
+    });
+    let result = tokio::select! {
+        result = &mut remux => result.map_err(anyhow::Error::from).and_then(|r| r),
+        _ = ctx.cancelled() => {
+            // A remux cannot stop midway: drop its file once it is through.
+            tokio::spawn(async move {
+                let _ = remux.await;
+                let _ = tokio::fs::remove_file(&output).await;
+                let _ = tokio::fs::remove_file(sealed_path(&output)).await;
+            });
+            anyhow::bail!("export cancelled");
+        }
+    };
+    match result {
+        Ok((info, size)) => {
+            log::info!(
+                "export: {} of {device_id} done ({} packets, {size} bytes)",
+                ctx.id,
+                info.packets
+            );
+            Ok(serde_json::to_value(ExportOutput {
+                clip_start: info.start_ms,
+                clip_end: info.end_ms,
+                file_size: size,
+            })?)
+        }
+        Err(e) => {
+            log::warn!("export: {} of {device_id} failed: {e:#}", ctx.id);
+            let _ = std::fs::remove_file(&output);
+            let _ = std::fs::remove_file(sealed_path(&output));
+            Err(e)
+        }
+    }
+}
+
+pub fn export_router() -> Router {
+    Router::new()
+        .route("/", get(list_exports).post(create_export))
+        .route("/{id}", get(get_export))
+        .route("/{id}/download", get(download_export))
+}
+
+/// The schema of [`export_router`], mounted at `/api/v1/export`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(list_exports, create_export, get_export, download_export))]
+pub(crate) struct ExportApi;
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+struct CreateExportRequest {
+    device_id: String,
+    start: i64,
+    end: i64,
+    /// Start on the requested frame, re-encoding up to the next keyframe;
+    /// by default the clip starts at the keyframe before `start`.
+    #[serde(default)]
+    exact: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "export",
+    responses((status = 200, body = BaseResponse<Vec<ExportJob>>))
+)]
+async fn list_exports() -> ApiJsonResult<Vec<ExportJob>> {
+    Ok(ok_json(list().await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "export",
+    request_body = CreateExportRequest,
+    responses((
+        status = 200,
+        description = "The job, still running",
+        body = BaseResponse<ExportJob>
+    ))
+)]
+async fn create_export(
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<CreateExportRequest>,
+) -> ApiJsonResult<ExportJob> {
+    Ok(ok_json(
+        start(
+            &req.device_id,
+            req.start,
+            req.end,
+            req.exact,
+            &user.username,
+        )
+        .await?,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "export",
+    params(("id" = String, Path)),
+    responses((status = 200, body = BaseResponse<ExportJob>))
+)]
+async fn get_export(Path(id): Path<String>) -> ApiJsonResult<ExportJob> {
+    let job = get(&id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("export {id} not found"))?;
+    Ok(ok_json(job))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/download",
+    tag = "export",
+    params(("id" = String, Path)),
+    responses((status = 200, body = [u8], content_type = "video/mp4"))
+)]
+async fn download_export(Path(id): Path<String>) -> ApiResult<Response> {
+    let job = get(&id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("export {id} not found"))?;
+    if job.status != ExportStatus::Done {
+        return Err(anyhow::anyhow!("export {id} is not finished").into());
+    }
+    let clip = clip_path(&id);
+    let sealed = sealed_path(&clip);
+    let body = if tokio::fs::try_exists(&sealed).await? {
+        let reader = EncryptedFileReader::open(&sealed).await?;
+        Body::from_stream(tokio_util::io::ReaderStream::new(reader))
+    } else {
+        Body::from(tokio::fs::read(clip).await?)
+    };
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("video/mp4"));
+    response.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!(
+            "attachment; filename=\"{}-{}.mp4\"",
+            job.device_id, job.start
+        ))?,
+    );
+    Ok(response)
+}
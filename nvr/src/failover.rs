@@ -0,0 +1,472 @@
+//! Input failover for cameras reachable over more than one path (wired +
+//! wifi, the camera's own port + a cloud relay).
+//!
+//! A device opened by ffmpeg directly (`net`/`rtsp`/`rtmp`/`file`…) may store
+//! a JSON object instead of a bare URL in `input_value`:
+//!
+//! ```json
+//! { "url": "rtsp://10.0.0.9/main", "failover_urls": ["rtsp://relay/cam9"] }
+//! ```
+//!
+//! With at least one failover URL the device is run by a supervisor instead of
+//! a plain pipe. It opens the inputs in priority order: an input that fails
+//! `retries` times in a row (to open, or by its session dying) hands over to
+//! the next one. While on a fallback, the higher-priority inputs are re-probed
+//! every `probe_interval_secs`; one that stays reachable for `sustain_secs`
+//! is switched back to. The new session only takes over at its first
+//! keyframe, and every session feeds the same ZLM `Media` through one
+//! [`TsNormalizer`], so the recording's timestamps keep increasing across a
+//! switch. Each switch is recorded as a [`FailoverEvent`]; the input in use is
+//! shown in the device list as `active_input`.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::Path;
+use ffmpeg_bus::bus::NetInputOptions;
+use media_pipe_core::{InputConfig, OutputConfig, Pipe, PipeConfig};
+use media_pipe_zlm::ts::{TsNormalizer, TsSession};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::handler::{ApiJsonResult, ok_json};
+use crate::latency::{InputTuning, LatencyProfile};
+
+const BACKOFF_MIN: Duration = Duration::from_secs(2);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A session that lived at least this long counts as healthy: its failure
+/// starts the retry count and the backoff over.
+const HEALTHY_SESSION: Duration = Duration::from_secs(30);
+/// How long a recovered input may take to reach its first keyframe before the
+/// switch back is abandoned.
+const SWITCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Failover events kept for diagnostics.
+const EVENT_CAP: usize = 100;
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_sustain_secs() -> u64 {
+    30
+}
+
+fn default_probe_interval_secs() -> u64 {
+    10
+}
+
+/// The JSON form of a device's `input_value`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailoverInput {
+    /// Primary URL (or path).
+    pub url: String,
+    /// Fallbacks, highest priority first.
+    #[serde(default)]
+    pub failover_urls: Vec<String>,
+    /// Consecutive failures of an input before moving to the next one.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// How long a higher-priority input must stay reachable before it is
+    /// switched back to.
+    #[serde(default = "default_sustain_secs")]
+    pub sustain_secs: u64,
+    /// Time between two probes of the higher-priority inputs.
+    #[serde(default = "default_probe_interval_secs")]
+    pub probe_interval_secs: u64,
+    /// `latency_profile`, `advanced` and `extra_input_options` (see
+    /// `crate::latency`), for every input.
+    #[serde(flatten)]
+    pub tuning: InputTuning,
+    /// `transport`, `stimeout_ms`, `max_delay_ms`, `user` and `password` of
+    /// network inputs, for every input.
+    #[serde(flatten)]
+    pub net: NetInputOptions,
+}
+
+impl FailoverInput {
+    /// What a plain URL stands for: `url` alone, with the defaults.
+    pub fn plain(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            failover_urls: Vec::new(),
+            retries: default_retries(),
+            sustain_secs: default_sustain_secs(),
+            probe_interval_secs: default_probe_interval_secs(),
+            tuning: InputTuning::default(),
+            net: NetInputOptions::default(),
+        }
+    }
+
+    /// Whether a plain URL says it all, so it is stored as one.
+    pub fn is_plain(&self) -> bool {
+        *self == Self::plain(self.url.clone())
+    }
+
+    /// `Ok(None)` for a plain URL; an error for malformed JSON.
+    pub fn parse(input_value: &str) -> anyhow::Result<Option<Self>> {
+        if !input_value.trim_start().starts_with('{') {
+            return Ok(None);
+        }
+        let input: Self = serde_json::from_str(input_value)
+            .map_err(|e| anyhow::anyhow!("invalid device input config: {e}"))?;
+        Ok(Some(input))
+    }
+
+    /// Every URL in priority order, the primary first.
+    pub fn urls(&self) -> Vec<String> {
+        std::iter::once(&self.url)
+            .chain(&self.failover_urls)
+            .cloned()
+            .collect()
+    }
+
+    pub fn policy(&self) -> FailoverPolicy {
+        FailoverPolicy {
+            retries: self.retries.max(1),
+            sustain: Duration::from_secs(self.sustain_secs),
+            probe_interval: Duration::from_secs(self.probe_interval_secs.max(1)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FailoverPolicy {
+    pub retries: u32,
+    pub sustain: Duration,
+    pub probe_interval: Duration,
+}
+
+/// The input a device is currently running on.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct ActiveInput {
+    /// Position in the priority list; 0 is the primary.
+    pub index: usize,
+    pub url: String,
+    pub since_ms: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchReason {
+    /// The input in use failed `retries` times.
+    Failover,
+    /// A higher-priority input came back.
+    Recovered,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct FailoverEvent {
+    pub device_id: String,
+    pub from: usize,
+    pub to: usize,
+    pub url: String,
+    pub reason: SwitchReason,
+    pub ts_ms: i64,
+}
+
+static ACTIVE: LazyLock<RwLock<HashMap<String, ActiveInput>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+static EVENTS: LazyLock<Mutex<VecDeque<FailoverEvent>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+/// The input `device_id` is running on, for devices with failover inputs.
+pub fn active_input(device_id: &str) -> Option<ActiveInput> {
+    ACTIVE.read().unwrap().get(device_id).cloned()
+}
+
+/// Recent switches, oldest first.
+pub fn recent_events() -> Vec<FailoverEvent> {
+    EVENTS.lock().unwrap().iter().cloned().collect()
+}
+
+/// Mark input `index` as active, recording a switch if it replaces another.
+fn set_active(device_id: &str, index: usize, url: &str, reason: SwitchReason) {
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    // The first session counts as a switch away from the primary.
+    let from = active_input(device_id).map_or(0, |active| active.index);
+    ACTIVE.write().unwrap().insert(
+        device_id.to_string(),
+        ActiveInput {
+            index,
+            url: url.to_string(),
+            since_ms: now_ms,
+        },
+    );
+    if from == index {
+        return;
+    }
+    log::warn!("failover {device_id}: input {from} -> {index} ({reason:?})");
+    let mut events = EVENTS.lock().unwrap();
+    if events.len() == EVENT_CAP {
+        events.pop_front();
+    }
+    events.push_back(FailoverEvent {
+        device_id: device_id.to_string(),
+        from,
+        to: index,
+        url: url.to_string(),
+        reason,
+        ts_ms: now_ms,
+    });
+}
+
+/// Display form of an input (its URL, path, device or format).
+fn location(input: &InputConfig) -> &str {
+    match input {
+        InputConfig::Network { url, .. } => url,
+        InputConfig::File { path } | InputConfig::FileLoop { path, .. } => path,
+        InputConfig::Device { display, .. } => display,
+        InputConfig::Reader { format, .. } => format,
+    }
+}
+
+/// Whether `input` can be opened right now, with the options its pipe
+/// would use.
+async fn probe(input: &InputConfig, tuning: &InputTuning) -> anyhow::Result<()> {
+    let options = crate::manager::input_options(input, tuning);
+    let (url, format) = match input {
+        InputConfig::Device { display, format } => (display.clone(), Some(format.clone())),
+        other => (location(other).to_string(), None),
+    };
+    tokio::task::spawn_blocking(move || {
+        let options = options.map(|options| {
+            let mut dict = ffmpeg_next::Dictionary::new();
+            for (k, v) in &options {
+                dict.set(k, v);
+            }
+            dict
+        });
+        ffmpeg_bus::input::AvInput::new(&url, format.as_deref(), options).map(|_| ())
+    })
+    .await?
+}
+
+/// One running pipe of the supervisor.
+struct Session {
+    pipe: Arc<Pipe>,
+    task: JoinHandle<()>,
+    ts: TsSession,
+    started: Instant,
+}
+
+impl Session {
+    fn start(
+        input: &InputConfig,
+        tuning: &InputTuning,
+        ts: &Arc<TsNormalizer>,
+        outputs: &impl Fn(TsSession) -> Vec<OutputConfig>,
+    ) -> Self {
+        let session = ts.session();
+        let options = crate::manager::input_options(input, tuning);
+        let pipe = Arc::new(Pipe::new(PipeConfig {
+            input: input.clone(),
+            outputs: outputs(session.clone()),
+        }));
+        let pipe_for_task = Arc::clone(&pipe);
+        let task = tokio::spawn(async move {
+            pipe_for_task.start(options).await;
+        });
+        Self {
+            pipe,
+            task,
+            ts: session,
+            started: Instant::now(),
+        }
+    }
+
+    async fn stop(self) {
+        self.pipe.cancel();
+        let _ = self.task.await;
+    }
+}
+
+/// Probe `inputs` (the ones ranked above the active input) every
+/// `probe_interval` and return the first that has been reachable on every
+/// probe for `sustain`.
+async fn recovered(inputs: &[InputConfig], tuning: &InputTuning, policy: FailoverPolicy) -> usize {
+    let mut healthy_since: Vec<Option<Instant>> = vec![None; inputs.len()];
+    loop {
+        tokio::time::sleep(policy.probe_interval).await;
+        for (i, input) in inputs.iter().enumerate() {
+            if probe(input, tuning).await.is_err() {
+                healthy_since[i] = None;
+                continue;
+            }
+            let since = *healthy_since[i].get_or_insert_with(Instant::now);
+            if since.elapsed() >= policy.sustain {
+                return i;
+            }
+        }
+    }
+}
+
+enum Outcome {
+    Cancelled,
+    Ended,
+    Recovered(usize),
+}
+
+/// Spawn the failover supervisor for one device. `inputs` are in priority
+/// order and all opened with `tuning`; `outputs` builds a session's outputs,
+/// mapping their timestamps through the session it is given. Registered in
+/// the manager as a `Task`; stops via `cancel`.
+pub(crate) fn spawn_failover_device(
+    device_id: String,
+    inputs: Vec<InputConfig>,
+    policy: FailoverPolicy,
+    tuning: InputTuning,
+    outputs: impl Fn(TsSession) -> Vec<OutputConfig> + Send + Sync + 'static,
+    cancel: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let ts = TsNormalizer::new();
+        let mut index = 0;
+        let mut failures = 0;
+        let mut backoff = BACKOFF_MIN;
+        let mut current: Option<Session> = None;
+        while !cancel.is_cancelled() {
+            let Some(session) = current.as_mut() else {
+                let input = &inputs[index];
+                match probe(input, &tuning).await {
+                    Ok(()) => {
+                        current = Some(Session::start(input, &tuning, &ts, &outputs));
+                        set_active(&device_id, index, location(input), SwitchReason::Failover);
+                        continue;
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        log::warn!(
+                            "failover {device_id}: input {index} failed ({failures}/{}): {e:#}",
+                            policy.retries
+                        );
+                    }
+                }
+                if failures >= policy.retries {
+                    failures = 0;
+                    index = (index + 1) % inputs.len();
+                    // Straight on to the next input; back off once every
+                    // input has failed.
+                    if index != 0 {
+                        backoff = BACKOFF_MIN;
+                        continue;
+                    }
+                }
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = tokio::time::sleep(backoff) => {}
+                }
+                backoff = (backoff * 2).min(BACKOFF_MAX);
+                continue;
+            };
+
+            let outcome = tokio::select! {
+                _ = cancel.cancelled() => Outcome::Cancelled,
+                _ = &mut session.task => Outcome::Ended,
+                i = recovered(&inputs[..index], &tuning, policy), if index > 0 => {
+                    Outcome::Recovered(i)
+                }
+            };
+            match outcome {
+                Outcome::Cancelled => break,
+                Outcome::Ended => {
+                    // The task already finished; dropping the session is all
+                    // that is left of it.
+                    let session = current.take().expect("session is running");
+                    if session.started.elapsed() >= HEALTHY_SESSION {
+                        failures = 0;
+                        backoff = BACKOFF_MIN;
+                    }
+                    failures += 1;
+                    log::warn!(
+                        "failover {device_id}: input {index} session ended ({failures}/{}), \
+                         retry in {backoff:?}",
+                        policy.retries
+                    );
+                    if failures >= policy.retries {
+                        failures = 0;
+                        index = (index + 1) % inputs.len();
+                    }
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = tokio::time::sleep(backoff) => {}
+                    }
+                    backoff = (backoff * 2).min(BACKOFF_MAX);
+                }
+                Outcome::Recovered(i) => {
+                    log::info!("failover {device_id}: input {i} is back, switching");
+                    let next = Session::start(&inputs[i], &tuning, &ts, &outputs);
+                    let switched = tokio::select! {
+                        _ = cancel.cancelled() => false,
+                        r = tokio::time::timeout(SWITCH_TIMEOUT, next.ts.wait_active()) => {
+                            r.is_ok()
+                        }
+                    };
+                    if !switched {
+                        if !cancel.is_cancelled() {
+                            log::warn!("failover {device_id}: input {i} produced no keyframe");
+                        }
+                        next.stop().await;
+                        continue;
+                    }
+                    if let Some(old) = current.replace(next) {
+                        old.stop().await;
+                    }
+                    index = i;
+                    failures = 0;
+                    backoff = BACKOFF_MIN;
+                    set_active(
+                        &device_id,
+                        index,
+                        location(&inputs[i]),
+                        SwitchReason::Recovered,
+                    );
+                }
+            }
+        }
+        if let Some(session) = current {
+            session.stop().await;
+        }
+        ACTIVE.write().unwrap().remove(&device_id);
+        log::info!("failover {device_id}: worker stopped");
+    })
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct InputStatus {
+    active_input: Option<ActiveInput>,
+    /// The latency profile the input was opened with, if any.
+    latency_profile: Option<LatencyProfile>,
+    /// The FFmpeg input options it was opened with, after merging the base
+    /// options, the profile, `advanced` and `extra_input_options`; the
+    /// primary input's for a device with failover URLs.
+    input_options: Option<BTreeMap<String, String>>,
+    /// This device's recent switches, oldest first.
+    events: Vec<FailoverEvent>,
+}
+
+/// `GET /api/device/{id}/input`: the input in use and the recent switches.
+#[utoipa::path(
+    get,
+    path = "/{id}/input",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses((status = 200, body = crate::handler::BaseResponse<InputStatus>))
+)]
+pub(crate) async fn input_status(Path(id): Path<String>) -> ApiJsonResult<InputStatus> {
+    Ok(ok_json(InputStatus {
+        active_input: active_input(&id),
+        latency_profile: crate::latency::active_profile(&id),
+        input_options: crate::input_options::effective(&id),
+        events: recent_events()
+            .into_iter()
+            .filter(|e| e.device_id == id)
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+#[path = "failover_test.rs"]
+mod failover_test;
@@ -0,0 +1,100 @@
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+
+use super::*;
+
+#[test]
+fn parse_plain_url_and_json() {
+    assert_eq!(FailoverInput::parse("rtsp://cam/main").unwrap(), None);
+    assert!(FailoverInput::parse("{ not json").is_err());
+
+    let input = FailoverInput::parse(r#"{"url": "rtsp://a", "failover_urls": ["rtsp://b"]}"#)
+        .unwrap()
+        .unwrap();
+    assert_eq!(input.urls(), vec!["rtsp://a", "rtsp://b"]);
+    assert_eq!(
+        input.policy(),
+        FailoverPolicy {
+            retries: 3,
+            sustain: Duration::from_secs(30),
+            probe_interval: Duration::from_secs(10),
+        }
+    );
+
+    let input = FailoverInput::parse(r#"{"url": "rtsp://a", "retries": 0}"#)
+        .unwrap()
+        .unwrap();
+    assert!(input.failover_urls.is_empty());
+    assert_eq!(input.policy().retries, 1);
+}
+
+#[test]
+fn parse_network_options() {
+    use ffmpeg_bus::bus::RtspTransport;
+    let input = FailoverInput::parse(
+        r#"{"url": "rtsp://a", "transport": "tcp", "stimeout_ms": 5000,
+            "user": "admin", "password": "secret", "latency_profile": "low"}"#,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(input.net.transport, RtspTransport::Tcp);
+    assert_eq!(input.net.stimeout_ms, Some(5000));
+    assert_eq!(input.net.max_delay_ms, None);
+    assert_eq!(input.net.user.as_deref(), Some("admin"));
+    assert_eq!(input.net.password.as_deref(), Some("secret"));
+    assert_eq!(input.tuning.latency_profile, Some(LatencyProfile::Low));
+    assert!(FailoverInput::parse(r#"{"url": "rtsp://a", "transport": "quic"}"#).is_err());
+}
+
+#[tokio::test]
+async fn missing_primary_fails_over_to_the_second_input() {
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let id = "failover-test-cam";
+    let missing = std::env::temp_dir().join(format!("nvr-missing-{}.mp4", uuid::Uuid::new_v4()));
+    let inputs = vec![
+        InputConfig::File {
+            path: missing.to_string_lossy().into_owned(),
+        },
+        InputConfig::FileLoop {
+            path: path.to_string_lossy().into_owned(),
+            realtime: true,
+        },
+    ];
+    let policy = FailoverPolicy {
+        retries: 1,
+        sustain: Duration::from_secs(30),
+        probe_interval: Duration::from_secs(10),
+    };
+    let cancel = CancellationToken::new();
+    let handle = spawn_failover_device(
+        id.to_string(),
+        inputs,
+        policy,
+        InputTuning::default(),
+        |_| vec![],
+        cancel.clone(),
+    );
+
+    let mut active = None;
+    for _ in 0..50 {
+        active = active_input(id);
+        if active.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let active = active.expect("no input became active");
+    assert_eq!(active.index, 1);
+    assert_eq!(active.url, path.to_string_lossy());
+
+    let events = recent_events()
+        .into_iter()
+        .filter(|e| e.device_id == id)
+        .collect::<Vec<_>>();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].from, events[0].to), (0, 1));
+    assert_eq!(events[0].reason, SwitchReason::Failover);
+
+    cancel.cancel();
+    handle.await.unwrap();
+    assert_eq!(active_input(id), None);
+}
@@ -0,0 +1,249 @@
+//! Federation routes: the health endpoint peers poll, peer status for the
+//! dashboard, and the reverse proxies that forward remote-device requests to
+//! the owning node.
+//!
+//! Two proxies, mirroring how the local node serves the same resources:
+//! - `/api/federation/{node}/proxy/{*path}` (behind session auth) forwards the
+//!   reads of the peer's devices — the device list, snapshots, recordings,
+//!   playlists, live previews — with the caller's token swapped for the
+//!   peer's. The peer's token is its operator's, so nothing but GET and HEAD
+//!   of those routes goes through (see [`proxied`]). Range headers pass
+//!   through untouched; HLS playlists get their absolute segment URIs
+//!   rewritten back through here.
+//! - `/federation/{node}/media/{*path}` (unauthenticated, like `/media`)
+//!   forwards live HTTP-FLV/HLS and upgrades WS-FLV to the peer's `/media`.
+//!
+//! Peers on `https://` are reached over TLS, WebSockets included (`wss://`).
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{FromRequestParts, Path, Request, ws::WebSocketUpgrade},
+    http::{HeaderValue, Method, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{any, get},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::config;
+use crate::federation::{self, PeerConfig, PeerStatus};
+use crate::handler::{ApiError, ApiJsonResult, ok_json};
+
+/// Upper bound for a buffered HLS playlist; a larger body fails the request.
+const PLAYLIST_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// Peer API routes the proxy forwards, relative to its `/api` or `/api/v1`:
+/// every one under these prefixes...
+const PROXIED_PREFIXES: &[&str] = &["snapshot/", "playback/", "recordings/"];
+/// ...the device list, and these per-device reads (`device/{id}/...`).
+const PROXIED_DEVICE_READS: &[&str] = &[
+    "thumbnail",
+    "mjpeg",
+    "ws-preview",
+    "live.mp4",
+    "recordings",
+];
+
+pub fn federation_router() -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/peers", get(peers))
+        .route("/{node}/proxy/{*path}", any(proxy_api))
+}
+
+/// Unauthenticated live-media proxy; merge at the root next to `/media`.
+pub(crate) fn federation_media_router() -> Router {
+    Router::new().route("/federation/{node}/media/{*path}", any(proxy_media))
+}
+
+/// Playable URL, through this node, for a peer's `/media/...` live URL.
+pub(crate) fn media_url(node: &str, peer_flv_url: &str) -> String {
+    format!("/federation/{node}{peer_flv_url}")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthResponse {
+    pub node: String,
+}
+
+async fn health() -> ApiJsonResult<HealthResponse> {
+    Ok(ok_json(HealthResponse {
+        node: config().federation().node_name.clone(),
+    }))
+}
+
+async fn peers() -> ApiJsonResult<Vec<PeerStatus>> {
+    Ok(ok_json(federation::peer_statuses()))
+}
+
+fn lookup_peer(node: &str) -> Result<PeerConfig, Response> {
+    config()
+        .federation()
+        .peer(node)
+        .cloned()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "unknown federation node").into_response())
+}
+
+/// Drop our own `token=` pair from a query string; the peer gets its own.
+pub(crate) fn strip_token_query(query: Option<&str>) -> Option<String> {
+    let kept = query?
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("token="))
+        .collect::<Vec<_>>();
+    (!kept.is_empty()).then(|| kept.join("&"))
+}
+
+fn with_query(mut url: String, query: Option<&str>) -> String {
+    if let Some(q) = query {
+        url.push('?');
+        url.push_str(q);
+    }
+    url
+}
+
+/// Whether a `method` call of the peer API `path` (as in the proxy route,
+/// e.g. `api/v1/snapshot/cam1`) may go through: GET and HEAD of the device
+/// list and of the media and playback reads, and only those.
+pub(crate) fn proxied(method: &Method, path: &str) -> bool {
+    if *method != Method::GET && *method != Method::HEAD {
+        return false;
+    }
+    // Nothing that would resolve to another route once the URL is parsed.
+    if path.contains(['?', '#', '\\'])
+        || path
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+    {
+        return false;
+    }
+    let Some(route) = path
+        .strip_prefix("api/v1/")
+        .or_else(|| path.strip_prefix("api/"))
+    else {
+        return false;
+    };
+    if route == "device/list" || PROXIED_PREFIXES.iter().any(|p| route.starts_with(p)) {
+        return true;
+    }
+    matches!(
+        route.split('/').collect::<Vec<_>>().as_slice(),
+        ["device", _, read] if PROXIED_DEVICE_READS.contains(read)
+    )
+}
+
+/// The WebSocket URL of `base_url` (`ws://` for `http://`, `wss://` for
+/// `https://`).
+fn ws_base(base_url: &str) -> String {
+    format!("ws{}", base_url.strip_prefix("http").unwrap_or(base_url))
+}
+
+async fn proxy_api(Path((node, path)): Path<(String, String)>, req: Request) -> Response {
+    match lookup_peer(&node) {
+        Ok(peer) => forward_api(&peer, &path, req).await,
+        Err(resp) => resp,
+    }
+}
+
+/// Forward an API request for `path` to `peer`, authenticated as the peer;
+/// 403 for any but the reads [`proxied`] lets through.
+async fn forward_api(peer: &PeerConfig, path: &str, req: Request) -> Response {
+    if !proxied(req.method(), path) {
+        return ApiError::forbidden("only reads of peer devices go through the federation proxy")
+            .into_response();
+    }
+    let (mut parts, body) = req.into_parts();
+    // Set by the auth middleware; echoed into rewritten playlist URIs so
+    // header-less players keep authenticating against this node.
+    let client_token = parts
+        .extensions
+        .get::<crate::auth::AuthUser>()
+        .map(|user| user.token.clone());
+    let query = strip_token_query(parts.uri.query());
+    let Ok(authorization) = HeaderValue::from_str(&format!("Bearer {}", peer.token)) else {
+        return (StatusCode::BAD_GATEWAY, "invalid peer token").into_response();
+    };
+
+    if let Ok(ws) = WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        let url = with_query(
+            format!("{}/{path}", ws_base(&peer.base_url)),
+            query.as_deref(),
+        );
+        return crate::proxy::forward_ws(ws, url, Some(authorization), None, None);
+    }
+
+    let mut req = Request::from_parts(parts, body);
+    req.headers_mut()
+        .insert(header::AUTHORIZATION, authorization);
+    let target = with_query(format!("{}/{path}", peer.base_url), query.as_deref());
+    let resp = crate::proxy::forward_http(req, &target).await;
+    if is_playlist(&resp) {
+        let prefix = format!("/api/federation/{}/proxy", peer.name);
+        let suffix = client_token
+            .map(|t| format!("?token={t}"))
+            .unwrap_or_default();
+        return rewrite_playlist_response(resp, &prefix, &suffix).await;
+    }
+    resp
+}
+
+async fn proxy_media(Path((node, path)): Path<(String, String)>, req: Request) -> Response {
+    match lookup_peer(&node) {
+        Ok(peer) => forward_media(&peer, &path, req).await,
+        Err(resp) => resp,
+    }
+}
+
+/// Forward a live-media request for `path` to the peer's `/media`.
+async fn forward_media(peer: &PeerConfig, path: &str, req: Request) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let query = parts.uri.query().map(str::to_owned);
+    if let Ok(ws) = WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        let url = with_query(
+            format!("{}/media/{path}", ws_base(&peer.base_url)),
+            query.as_deref(),
+        );
+        return crate::proxy::forward_ws(ws, url, None, None, None);
+    }
+    let target = with_query(format!("{}/media/{path}", peer.base_url), query.as_deref());
+    crate::proxy::forward_http(Request::from_parts(parts, body), &target).await
+}
+
+fn is_playlist(resp: &Response) -> bool {
+    resp.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("mpegurl"))
+}
+
+/// Point the absolute URIs in an HLS playlist (the peer emits `/api/...`
+/// segment paths) back through this node's proxy.
+pub(crate) fn rewrite_playlist(body: &str, prefix: &str, suffix: &str) -> String {
+    body.lines()
+        .map(|line| {
+            if line.starts_with('/') {
+                format!("{prefix}{line}{suffix}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn rewrite_playlist_response(resp: Response, prefix: &str, suffix: &str) -> Response {
+    let (mut parts, body) = resp.into_parts();
+    let bytes = match axum::body::to_bytes(body, PLAYLIST_MAX_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::warn!("federation proxy: failed to read playlist: {e}");
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    };
+    let rewritten = rewrite_playlist(&String::from_utf8_lossy(&bytes), prefix, suffix);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(rewritten))
+}
+
+#[cfg(test)]
+#[path = "api_test.rs"]
+mod api_test;
@@ -0,0 +1,269 @@
+use axum::{
+    extract::{Path, RawQuery, ws::Message},
+    http::HeaderMap,
+    routing::{get, post},
+};
+use futures::StreamExt;
+use tokio::net::TcpListener;
+
+use super::*;
+
+const PEER_TOKEN: &str = "peer-token";
+
+/// Serve `app` on an ephemeral loopback port and return its base URL.
+async fn serve(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{addr}")
+}
+
+fn authorized(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v == format!("Bearer {PEER_TOKEN}"))
+}
+
+/// Stand-in for the owning node: the real health handler plus a device list
+/// (one own device, one it learned elsewhere), a snapshot and a playlist.
+fn peer_app() -> Router {
+    Router::new()
+        .route("/api/federation/health", get(health))
+        .route(
+            "/api/device/list",
+            get(async || {
+                let now = chrono::Utc::now().to_rfc3339();
+                axum::Json(serde_json::json!({
+                    "code": 0,
+                    "message": "success",
+                    "data": [
+                        {
+                            "id": "cam1", "name": "Gate", "input_type": "rtsp",
+                            "input_value": "rtsp://cam", "description": "",
+                            "created_at": now, "updated_at": now,
+                            "flv_url": "/media/device/cam1.live.flv",
+                            "available": true
+                        },
+                        {
+                            "id": "far", "name": "Far", "input_type": "rtsp",
+                            "input_value": "", "description": "",
+                            "created_at": now, "updated_at": now,
+                            "flv_url": "/federation/other/media/device/far.live.flv",
+                            "node": "other", "available": true
+                        }
+                    ]
+                }))
+            }),
+        )
+        .route(
+            "/api/snapshot/{id}",
+            get(async |Path(id): Path<String>, headers: HeaderMap| {
+                if !authorized(&headers) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                let range = headers
+                    .get(header::RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("")
+                    .to_string();
+                ([("x-seen-range", range)], format!("jpeg:{id}")).into_response()
+            }),
+        )
+        .route(
+            "/api/device/{id}/ws-preview",
+            get(
+                async |ws: WebSocketUpgrade, headers: HeaderMap, RawQuery(query): RawQuery| {
+                    if !authorized(&headers) {
+                        return StatusCode::UNAUTHORIZED.into_response();
+                    }
+                    let query = query.unwrap_or_default();
+                    ws.on_upgrade(move |mut socket| async move {
+                        let _ = socket.send(Message::Text(query.into())).await;
+                    })
+                },
+            ),
+        )
+        .route("/api/device/remove/{id}", post(async || "removed"))
+        .route(
+            "/api/playback/segment-playlist/{id}",
+            get(async |Path(id): Path<String>| {
+                (
+                    [(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")],
+                    format!("#EXTM3U\n#EXTINF:1.000,\n/api/playback/segment/{id}\n#EXT-X-ENDLIST"),
+                )
+            }),
+        )
+}
+
+/// This node: only the federation proxy, pointed at `peer`.
+fn local_app(peer: PeerConfig) -> Router {
+    Router::new().route(
+        "/api/federation/east/proxy/{*path}",
+        any(move |Path(path): Path<String>, req: Request| {
+            let peer = peer.clone();
+            async move { forward_api(&peer, &path, req).await }
+        }),
+    )
+}
+
+#[test]
+fn strip_token_query_keeps_other_pairs() {
+    assert_eq!(strip_token_query(None), None);
+    assert_eq!(strip_token_query(Some("token=abc")), None);
+    assert_eq!(
+        strip_token_query(Some("a=1&token=abc&b=2")),
+        Some("a=1&b=2".to_string())
+    );
+}
+
+#[test]
+fn rewrite_playlist_prefixes_absolute_uris() {
+    let body = "#EXTM3U\n#EXTINF:1.000,\n/api/playback/segment/s1\n#EXT-X-ENDLIST";
+    assert_eq!(
+        rewrite_playlist(body, "/api/federation/east/proxy", "?token=t"),
+        "#EXTM3U\n#EXTINF:1.000,\n/api/federation/east/proxy/api/playback/segment/s1?token=t\n#EXT-X-ENDLIST"
+    );
+}
+
+#[test]
+fn media_url_routes_through_federation_mount() {
+    assert_eq!(
+        media_url("east", "/media/device/cam1.live.flv"),
+        "/federation/east/media/device/cam1.live.flv"
+    );
+}
+
+#[tokio::test]
+async fn peer_device_is_listed_and_snapshot_is_proxied() {
+    let peer = PeerConfig {
+        name: "east".to_string(),
+        base_url: serve(peer_app()).await,
+        token: PEER_TOKEN.to_string(),
+    };
+    let local = serve(local_app(peer.clone())).await;
+    let client = reqwest::Client::new();
+
+    // Poll the peer: only its own device comes back (loop prevention).
+    let devices = federation::poll_peer(&client, &peer).await.unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].device.id, "cam1");
+
+    // Snapshot through this node: the peer sees its own token and our Range.
+    let resp = client
+        .get(format!(
+            "{local}/api/federation/east/proxy/api/snapshot/cam1?token=local-token"
+        ))
+        .header(header::RANGE, "bytes=0-3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["x-seen-range"], "bytes=0-3");
+    assert_eq!(resp.text().await.unwrap(), "jpeg:cam1");
+
+    // Playlists come back with segment URIs pointing through the proxy.
+    let body = client
+        .get(format!(
+            "{local}/api/federation/east/proxy/api/playback/segment-playlist/s1"
+        ))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("\n/api/federation/east/proxy/api/playback/segment/s1\n"));
+}
+
+#[tokio::test]
+async fn proxy_authenticates_with_peer_token() {
+    let peer = PeerConfig {
+        name: "east".to_string(),
+        base_url: serve(peer_app()).await,
+        token: "bad".to_string(),
+    };
+    let local = serve(local_app(peer)).await;
+    let status = reqwest::get(format!(
+        "{local}/api/federation/east/proxy/api/snapshot/cam1"
+    ))
+    .await
+    .unwrap()
+    .status();
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn only_reads_of_peer_devices_are_proxied() {
+    for path in [
+        "api/device/list",
+        "api/v1/device/list",
+        "api/snapshot/cam1",
+        "api/v1/playback/segment/s1",
+        "api/recordings/a.mp4/download",
+        "api/device/cam1/ws-preview",
+        "api/v1/device/cam1/live.mp4",
+    ] {
+        assert!(proxied(&Method::GET, path), "{path}");
+        assert!(proxied(&Method::HEAD, path), "{path}");
+    }
+    assert!(!proxied(&Method::POST, "api/playback/segments/delete"));
+    assert!(!proxied(&Method::POST, "api/device/remove/cam1"));
+    for path in [
+        "api/user/list",
+        "api/device/remove/cam1",
+        "api/device/cam1/resume",
+        "api/snapshot/../user/list",
+        "api/snapshot/./x",
+        "api/snapshot//x",
+        "api/snapshot/x\\..\\..\\user",
+        "snapshot/cam1",
+    ] {
+        assert!(!proxied(&Method::GET, path), "{path}");
+    }
+}
+
+#[test]
+fn ws_base_keeps_tls() {
+    assert_eq!(ws_base("http://10.0.0.2:18080"), "ws://10.0.0.2:18080");
+    assert_eq!(ws_base("https://10.0.0.2:18443"), "wss://10.0.0.2:18443");
+}
+
+#[tokio::test]
+async fn proxy_refuses_changes_on_the_peer() {
+    let peer = PeerConfig {
+        name: "east".to_string(),
+        base_url: serve(peer_app()).await,
+        token: PEER_TOKEN.to_string(),
+    };
+    let local = serve(local_app(peer)).await;
+    let status = reqwest::Client::new()
+        .post(format!(
+            "{local}/api/federation/east/proxy/api/device/remove/cam1"
+        ))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn websockets_carry_the_peer_token_in_a_header() {
+    let peer = PeerConfig {
+        name: "east".to_string(),
+        base_url: serve(peer_app()).await,
+        token: PEER_TOKEN.to_string(),
+    };
+    let local = serve(local_app(peer)).await;
+    let url = format!(
+        "{}/api/federation/east/proxy/api/device/cam1/ws-preview?width=320&token=local-token",
+        local.replacen("http://", "ws://", 1)
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let message = socket.next().await.unwrap().unwrap();
+    // Authorized by the header; no token of either node in the query.
+    assert_eq!(message.into_text().unwrap().as_str(), "width=320");
+}
@@ -0,0 +1,302 @@
+//! Multi-instance federation: show cameras of peer NVR nodes in this node's
+//! dashboard. Peers are listed in `NVR_FEDERATION_PEERS`; a background worker
+//! polls each peer's health and *local* device list into an in-memory cache,
+//! which the device listing merges in (read-only, tagged with `node`). Live,
+//! snapshot and recording requests for those devices go through the proxy
+//! routes in [`api`] to the owning node.
+//!
+//! Loop prevention: peers are always polled with `?local=true`, so a node only
+//! ever advertises devices from its own DB, and anything that still arrives
+//! tagged with a `node` (learned elsewhere) is dropped.
+
+pub mod api;
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nvr_db::device::DeviceInfo;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::config;
+
+/// How often each peer is polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Per-request timeout for peer polls, so one dead peer can't stall the cycle.
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One peer node: `name` (unique, used in proxy paths), its API base URL
+/// (e.g. `https://10.0.0.2:18443`; `http://` works too, but sends the token in
+/// cleartext) and a session token valid there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerConfig {
+    pub name: String,
+    pub base_url: String,
+    pub token: String,
+}
+
+/// Federation config, parsed from environment variables. With no peers the
+/// worker is not started and the listing is local-only.
+#[derive(Debug, Clone, Default)]
+pub struct FederationConfig {
+    /// This node's name, reported by the health endpoint (`NVR_NODE_NAME`).
+    pub node_name: String,
+    pub peers: Vec<PeerConfig>,
+}
+
+impl FederationConfig {
+    /// This node's name (`NVR_NODE_NAME`, `local` unless set) and its peers,
+    /// from the values `get` returns.
+    /// `NVR_FEDERATION_PEERS` is a comma-separated list of `name=base_url|token`;
+    /// malformed entries are skipped with a warning.
+    pub fn from_map(get: impl Fn(&str) -> Option<String>) -> FederationConfig {
+        let node_name = get("NVR_NODE_NAME")
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "local".to_string());
+        let peers = get("NVR_FEDERATION_PEERS")
+            .map(|raw| parse_peers(&raw))
+            .unwrap_or_default();
+        FederationConfig { node_name, peers }
+    }
+
+    /// Parse from the real process environment.
+    pub fn from_env() -> FederationConfig {
+        Self::from_map(|k| std::env::var(k).ok())
+    }
+
+    pub fn peer(&self, name: &str) -> Option<&PeerConfig> {
+        self.peers.iter().find(|p| p.name == name)
+    }
+}
+
+fn parse_peers(raw: &str) -> Vec<PeerConfig> {
+    let mut peers: Vec<PeerConfig> = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let parsed = entry.split_once('=').and_then(|(name, rest)| {
+            let (url, token) = rest.split_once('|').unwrap_or((rest, ""));
+            let name = name.trim();
+            let url = url.trim().trim_end_matches('/');
+            if name.is_empty() || !(url.starts_with("https://") || url.starts_with("http://")) {
+                return None;
+            }
+            Some(PeerConfig {
+                name: name.to_string(),
+                base_url: url.to_string(),
+                token: token.trim().to_string(),
+            })
+        });
+        match parsed {
+            Some(peer) if peers.iter().any(|p| p.name == peer.name) => {
+                log::warn!("federation: duplicate peer name {}, skipped", peer.name);
+            }
+            Some(peer) => {
+                if peer.base_url.starts_with("http://") {
+                    log::warn!(
+                        "federation: peer {} is plain http, its token crosses the network \
+                         in cleartext; use https",
+                        peer.name
+                    );
+                }
+                peers.push(peer);
+            }
+            None => log::warn!("federation: malformed peer entry {entry:?}, skipped"),
+        }
+    }
+    peers
+}
+
+/// A device as advertised by a peer's `/api/device/list?local=true`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteDevice {
+    #[serde(flatten)]
+    pub device: DeviceInfo,
+    #[serde(default)]
+    pub flv_url: String,
+    /// Set when the peer itself learned the device from another node; such
+    /// entries are never re-advertised.
+    #[serde(default)]
+    pub node: Option<String>,
+    /// The peer has the device in privacy mode.
+    #[serde(default)]
+    pub privacy: bool,
+}
+
+/// A remote device merged into the local listing.
+#[derive(Debug, Clone)]
+pub struct FederatedDevice {
+    pub node: String,
+    /// False while the owning peer is unreachable (last known devices are kept).
+    pub available: bool,
+    pub device: RemoteDevice,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeerStatus {
+    pub name: String,
+    pub online: bool,
+    pub device_count: usize,
+    pub last_error: Option<String>,
+    /// Unix-epoch millisecond timestamp of the last successful poll; 0 = never.
+    pub last_seen_ms: u64,
+    #[serde(skip)]
+    devices: Vec<RemoteDevice>,
+}
+
+static PEERS: LazyLock<RwLock<HashMap<String, PeerStatus>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Drop devices the peer did not own itself (loop prevention).
+fn own_devices(devices: Vec<RemoteDevice>) -> Vec<RemoteDevice> {
+    devices
+        .into_iter()
+        .filter(|d| d.node.as_deref().is_none_or(str::is_empty))
+        .collect()
+}
+
+/// Record the outcome of one poll. A failure keeps the last known devices so
+/// they stay listed, just marked unavailable.
+fn record_poll(name: &str, result: anyhow::Result<Vec<RemoteDevice>>) {
+    let mut peers = PEERS.write().unwrap();
+    let status = peers.entry(name.to_string()).or_insert_with(|| PeerStatus {
+        name: name.to_string(),
+        ..Default::default()
+    });
+    match result {
+        Ok(devices) => {
+            status.online = true;
+            status.device_count = devices.len();
+            status.last_error = None;
+            status.last_seen_ms = now_ms();
+            status.devices = devices;
+        }
+        Err(e) => {
+            if status.online {
+                log::warn!("federation: peer {name} unreachable: {e:#}");
+            }
+            status.online = false;
+            status.last_error = Some(format!("{e:#}"));
+        }
+    }
+}
+
+/// Every remote device currently known, across all peers.
+pub fn remote_devices() -> Vec<FederatedDevice> {
+    let peers = PEERS.read().unwrap();
+    let mut out = Vec::new();
+    for status in peers.values() {
+        for device in &status.devices {
+            out.push(FederatedDevice {
+                node: status.name.clone(),
+                available: status.online,
+                device: device.clone(),
+            });
+        }
+    }
+    out.sort_by(|a, b| (&a.node, &a.device.device.id).cmp(&(&b.node, &b.device.device.id)));
+    out
+}
+
+/// Status of every configured peer, in config order.
+pub fn peer_statuses() -> Vec<PeerStatus> {
+    let peers = PEERS.read().unwrap();
+    config()
+        .federation()
+        .peers
+        .iter()
+        .map(|p| {
+            peers.get(&p.name).cloned().unwrap_or_else(|| PeerStatus {
+                name: p.name.clone(),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// `{code, message, data}` envelope of the peer's JSON API.
+#[derive(Deserialize)]
+struct Envelope<T> {
+    code: i32,
+    #[serde(default)]
+    message: String,
+    data: Option<T>,
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    peer: &PeerConfig,
+    path: &str,
+) -> anyhow::Result<T> {
+    let resp = client
+        .get(format!("{}{}", peer.base_url, path))
+        .bearer_auth(&peer.token)
+        .send()
+        .await?
+        .error_for_status()?;
+    let envelope = resp.json::<Envelope<T>>().await?;
+    if envelope.code != 0 {
+        anyhow::bail!("peer returned code {}: {}", envelope.code, envelope.message);
+    }
+    envelope
+        .data
+        .ok_or_else(|| anyhow::anyhow!("peer returned no data for {path}"))
+}
+
+/// Poll one peer: health first (cheap, validates the token), then its own
+/// devices.
+pub(crate) async fn poll_peer(
+    client: &reqwest::Client,
+    peer: &PeerConfig,
+) -> anyhow::Result<Vec<RemoteDevice>> {
+    // Unversioned paths, which peers a release behind answer too.
+    let _health: api::HealthResponse = get_json(client, peer, "/api/federation/health").await?;
+    let devices: Vec<RemoteDevice> = get_json(client, peer, "/api/device/list?local=true").await?;
+    Ok(own_devices(devices))
+}
+
+/// Spawn the peer poller; it runs until `cancel` fires. A no-op when no peers
+/// are configured.
+pub fn spawn_worker(cancel: CancellationToken) {
+    let peers = config().federation().peers.clone();
+    if peers.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        log::info!("federation: worker started ({} peers)", peers.len());
+        let client = match reqwest::Client::builder().timeout(POLL_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("federation: failed to build http client: {e}");
+                return;
+            }
+        };
+        loop {
+            let polls = peers.iter().map(|peer| {
+                let client = &client;
+                async move { (peer, poll_peer(client, peer).await) }
+            });
+            for (peer, result) in futures::future::join_all(polls).await {
+                record_poll(&peer.name, result);
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    log::info!("federation: worker stopped");
+                    return;
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+#[path = "mod_test.rs"]
+mod mod_test;
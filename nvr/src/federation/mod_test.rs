@@ -0,0 +1,125 @@
+use super::*;
+
+fn env(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let map: HashMap<String, String> = pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |k| map.get(k).cloned()
+}
+
+fn remote(id: &str, node: Option<&str>) -> RemoteDevice {
+    let now = chrono::Utc::now();
+    RemoteDevice {
+        device: DeviceInfo {
+            id: id.to_string(),
+            name: id.to_string(),
+            input_type: "rtsp".to_string(),
+            input_value: String::new(),
+            description: String::new(),
+            include_audio: false,
+            record: true,
+            stream_key: String::new(),
+            timezone: String::new(),
+            detection: None,
+            group: String::new(),
+            encrypt: false,
+            status: String::new(),
+            created_at: now,
+            updated_at: now,
+        },
+        flv_url: format!("/media/device/{id}.live.flv"),
+        node: node.map(str::to_string),
+        privacy: false,
+    }
+}
+
+#[test]
+fn from_map_defaults_to_local_without_peers() {
+    let cfg = FederationConfig::from_map(env(&[]));
+    assert_eq!(cfg.node_name, "local");
+    assert!(cfg.peers.is_empty());
+}
+
+#[test]
+fn from_map_parses_peer_list() {
+    let cfg = FederationConfig::from_map(env(&[
+        ("NVR_NODE_NAME", "hall"),
+        (
+            "NVR_FEDERATION_PEERS",
+            "east=http://10.0.0.2:18080/|tok-e, west=http://10.0.0.3:18080",
+        ),
+    ]));
+    assert_eq!(cfg.node_name, "hall");
+    assert_eq!(
+        cfg.peers,
+        vec![
+            PeerConfig {
+                name: "east".into(),
+                base_url: "http://10.0.0.2:18080".into(),
+                token: "tok-e".into(),
+            },
+            PeerConfig {
+                name: "west".into(),
+                base_url: "http://10.0.0.3:18080".into(),
+                token: String::new(),
+            },
+        ]
+    );
+    assert_eq!(cfg.peer("west").unwrap().base_url, "http://10.0.0.3:18080");
+    assert!(cfg.peer("north").is_none());
+}
+
+#[test]
+fn parse_peers_skips_malformed_and_duplicates() {
+    let peers = parse_peers("noequals,=http://x|t,a=ftp://x|t,b=http://b|t,b=http://c|t");
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].name, "b");
+    assert_eq!(peers[0].base_url, "http://b");
+}
+
+#[test]
+fn parse_peers_takes_https() {
+    let peers = parse_peers("east=https://10.0.0.2:18443/|tok-e");
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].base_url, "https://10.0.0.2:18443");
+}
+
+#[test]
+fn own_devices_drops_learned_entries() {
+    let kept = own_devices(vec![
+        remote("a", None),
+        remote("b", Some("other")),
+        remote("c", Some("")),
+    ]);
+    let ids = kept
+        .iter()
+        .map(|d| d.device.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["a", "c"]);
+}
+
+#[test]
+fn failed_poll_keeps_devices_marked_unavailable() {
+    let name = "record-poll-test";
+    record_poll(name, Ok(vec![remote("cam1", None)]));
+    let listed = remote_devices()
+        .into_iter()
+        .filter(|d| d.node == name)
+        .collect::<Vec<_>>();
+    assert_eq!(listed.len(), 1);
+    assert!(listed[0].available);
+
+    record_poll(name, Err(anyhow::anyhow!("connection refused")));
+    let listed = remote_devices()
+        .into_iter()
+        .filter(|d| d.node == name)
+        .collect::<Vec<_>>();
+    assert_eq!(listed.len(), 1);
+    assert!(!listed[0].available);
+    let peers = PEERS.read().unwrap();
+    assert_eq!(
+        peers[name].last_error.as_deref(),
+        Some("connection refused")
+    );
+}
@@ -23,6 +23,8 @@ pub fn gb_router() -> Router {
 struct GbDeviceItem {
     device_id: String,
     online: bool,
+    /// Its channels as last reported (queried when it registered).
+    channels: Vec<GbChannelItem>,
 }
 
 #[derive(Serialize)]
@@ -42,6 +44,11 @@ async fn list_devices() -> ApiJsonResult<Vec<GbDeviceItem>> {
         .devices()
         .into_iter()
         .map(|d| GbDeviceItem {
+            channels: crate::gb::device::catalog(&d.device_id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(GbChannelItem::from)
+                .collect(),
             device_id: d.device_id,
             online: d.online,
         })
@@ -49,22 +56,26 @@ async fn list_devices() -> ApiJsonResult<Vec<GbDeviceItem>> {
     Ok(ok_json(items))
 }
 
-/// Query a device's channel catalog (live MANSCDP Catalog). Returns an empty
-/// list (HTTP 200) when GB is disabled; propagates crate errors otherwise.
+impl From<gb28181::CatalogItem> for GbChannelItem {
+    fn from(c: gb28181::CatalogItem) -> Self {
+        Self {
+            channel_id: c.device_id,
+            name: c.name,
+            status: c.status,
+        }
+    }
+}
+
+/// Query a device's channel catalog (live MANSCDP Catalog), refreshing the
+/// one `/devices` shows. Returns an empty list (HTTP 200) when GB is
+/// disabled; propagates crate errors otherwise.
 async fn catalog(Path(device_id): Path<String>) -> ApiJsonResult<Vec<GbChannelItem>> {
     let Some(bridge) = crate::gb::bridge() else {
         return Ok(ok_json(Vec::new()));
     };
     let catalog = bridge.server().catalog_query(&device_id).await?;
-    let items = catalog
-        .items
-        .into_iter()
-        .map(|c| GbChannelItem {
-            channel_id: c.device_id,
-            name: c.name,
-            status: c.status,
-        })
-        .collect();
+    crate::gb::device::set_catalog(&device_id, catalog.items.clone());
+    let items = catalog.items.into_iter().map(GbChannelItem::from).collect();
     Ok(ok_json(items))
 }
 
@@ -150,7 +161,7 @@ struct PlayResponse {
 
 /// Parse the transport string; `None` (missing) defaults to Udp; an unknown
 /// value yields `None` (rejected by the handler).
-fn parse_transport(s: Option<&str>) -> Option<gb28181::Transport> {
+pub(crate) fn parse_transport(s: Option<&str>) -> Option<gb28181::Transport> {
     match s {
         None | Some("udp") => Some(gb28181::Transport::Udp),
         Some("tcp_passive") => Some(gb28181::Transport::TcpPassive),
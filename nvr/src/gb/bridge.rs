@@ -60,6 +60,11 @@ impl GbBridge {
         &self.server
     }
 
+    /// The local IP devices are told to send their media to.
+    pub fn media_ip(&self) -> &str {
+        &self.media_ip
+    }
+
     /// Snapshot of every gb stream mapping with its live flag and, for live
     /// streams, RTP receive detail.
     pub async fn stream_status(&self) -> Vec<GbStreamStatus> {
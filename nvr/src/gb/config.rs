@@ -11,7 +11,8 @@ pub struct GbConfig {
     pub domain: String,
     /// SIP UDP listen port (national standard default 5060).
     pub listen_port: u16,
-    /// Shared registration password. `None` = Open auth (no digest challenge).
+    /// Shared registration password. `None` = Open auth (no digest challenge)
+    /// for devices without a password of their own.
     pub password: Option<String>,
     /// Local IP advertised in INVITE SDP — where devices send their PS/RTP.
     pub media_ip: String,
@@ -52,10 +53,13 @@ impl GbConfig {
     pub fn to_server_config(&self) -> anyhow::Result<GbServerConfig> {
         let listen = format!("0.0.0.0:{}", self.listen_port).parse()?;
         let mut cfg = GbServerConfig::new(self.sip_id.clone(), self.domain.clone(), listen);
-        cfg.auth = match &self.password {
+        // A device's own password (see `crate::gb::device`) comes first.
+        let fallback = match &self.password {
             Some(pw) => AuthConfig::Shared(pw.clone()),
             None => AuthConfig::Open,
         };
+        cfg.auth =
+            AuthConfig::ProviderOr(Box::new(crate::gb::device::password), Box::new(fallback));
         Ok(cfg)
     }
 }
@@ -107,7 +111,10 @@ mod tests {
         assert_eq!(cfg.password.as_deref(), Some("s3cret"));
         assert_eq!(cfg.media_ip, "192.168.1.10");
         let sc = cfg.to_server_config().unwrap();
-        assert!(matches!(sc.auth, AuthConfig::Shared(_)));
+        assert!(matches!(
+            sc.auth,
+            AuthConfig::ProviderOr(_, fallback) if matches!(*fallback, AuthConfig::Shared(_))
+        ));
     }
 
     #[test]
@@ -0,0 +1,183 @@
+//! gb28181 devices as the rest of nvr sees them: the `input_value` that ties
+//! an nvr device to a GB device's channel, the registration passwords devices
+//! have of their own, and the channel catalog each registered device reported
+//! (queried when it registers), which the device list shows.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+use gb28181::{CatalogItem, Transport};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::gb::ingest::GbChannel;
+
+/// Pairs of (GB device id, its own password), by nvr device id. Several nvr
+/// devices may be channels of one GB device.
+static PASSWORDS: LazyLock<RwLock<HashMap<String, (String, String)>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// The last catalog of each registered GB device, by GB device id.
+static CATALOGS: LazyLock<RwLock<HashMap<String, Vec<CatalogItem>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// The `input_value` of a gb28181 device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct GbInput {
+    pub device_id: String,
+    pub channel_id: String,
+    /// Its registration password, instead of the platform's
+    /// `NVR_GB_PASSWORD`.
+    #[serde(default)]
+    pub password: Option<String>,
+    /// "udp" (the default) or "tcp_passive"; "tcp_active" on demand only.
+    #[serde(default)]
+    pub transport: Option<String>,
+    /// Ingest all the time (see [`crate::gb::ingest`]) rather than when a
+    /// viewer opens the stream.
+    #[serde(default)]
+    pub always_on: bool,
+}
+
+impl GbInput {
+    pub(crate) fn parse(input_value: &str) -> anyhow::Result<Self> {
+        let input: Self = serde_json::from_str(input_value)
+            .map_err(|e| anyhow::anyhow!("invalid gb28181 device config: {e}"))?;
+        input.check()?;
+        Ok(input)
+    }
+
+    /// What serde cannot tell: a known transport, and one an always-on
+    /// ingest can use.
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        let transport = self.transport()?;
+        if self.always_on && transport == Transport::TcpActive {
+            anyhow::bail!("always_on gb28181 devices take udp or tcp_passive");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn transport(&self) -> anyhow::Result<Transport> {
+        crate::gb::api::parse_transport(self.transport.as_deref()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "unknown gb28181 transport {:?}",
+                self.transport.as_deref().unwrap_or_default()
+            )
+        })
+    }
+
+    pub(crate) fn channel(&self) -> anyhow::Result<GbChannel> {
+        Ok(GbChannel {
+            device_id: self.device_id.clone(),
+            channel_id: self.channel_id.clone(),
+            transport: self.transport()?,
+        })
+    }
+}
+
+/// Record the password the nvr device `id` gives its GB device, or that it
+/// gives none.
+pub(crate) fn set_password(id: &str, input: &GbInput) {
+    let mut passwords = PASSWORDS.write().unwrap();
+    match input.password.as_ref().filter(|p| !p.is_empty()) {
+        Some(password) => {
+            passwords.insert(id.to_string(), (input.device_id.clone(), password.clone()));
+        }
+        None => {
+            passwords.remove(id);
+        }
+    }
+}
+
+/// Forget the password of the nvr device `id`, once it is removed or no
+/// longer a gb28181 device.
+pub(crate) fn forget_password(id: &str) {
+    PASSWORDS.write().unwrap().remove(id);
+}
+
+/// The password the GB device `gb_device_id` registers with, if one of its
+/// nvr devices gives it its own.
+pub(crate) fn password(gb_device_id: &str) -> Option<String> {
+    PASSWORDS
+        .read()
+        .unwrap()
+        .values()
+        .find(|(device, _)| device == gb_device_id)
+        .map(|(_, password)| password.clone())
+}
+
+/// Remember the catalog `gb_device_id` reported.
+pub(crate) fn set_catalog(gb_device_id: &str, items: Vec<CatalogItem>) {
+    CATALOGS
+        .write()
+        .unwrap()
+        .insert(gb_device_id.to_string(), items);
+}
+
+/// Forget the catalog of a device that unregistered.
+pub(crate) fn forget_catalog(gb_device_id: &str) {
+    CATALOGS.write().unwrap().remove(gb_device_id);
+}
+
+/// The catalog `gb_device_id` last reported, if it is registered.
+pub(crate) fn catalog(gb_device_id: &str) -> Option<Vec<CatalogItem>> {
+    CATALOGS.read().unwrap().get(gb_device_id).cloned()
+}
+
+/// Query the catalog of a device that just registered and remember it.
+pub(crate) async fn refresh_catalog(gb_device_id: String) {
+    let Some(bridge) = crate::gb::bridge() else {
+        return;
+    };
+    match bridge.server().catalog_query(&gb_device_id).await {
+        Ok(catalog) => {
+            log::info!(
+                "gb28181: device {gb_device_id} has {} channels{}",
+                catalog.items.len(),
+                if catalog.incomplete {
+                    " (incomplete)"
+                } else {
+                    ""
+                }
+            );
+            set_catalog(&gb_device_id, catalog.items);
+        }
+        Err(e) => log::warn!("gb28181: catalog of {gb_device_id} failed: {e:#}"),
+    }
+}
+
+/// Where a gb28181 device's GB device and channel are at, for the device
+/// list.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct GbStatus {
+    /// The GB device is registered and keeping alive.
+    pub online: bool,
+    /// The channel's name and status (`ON`/`OFF`) from the device's catalog.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_status: Option<String>,
+}
+
+/// The [`GbStatus`] of an nvr device with the gb28181 `input_value` given;
+/// `None` when GB support is off or the value is not one.
+pub(crate) fn status(input_value: &str) -> Option<GbStatus> {
+    let bridge = crate::gb::bridge()?;
+    let input = GbInput::parse(input_value).ok()?;
+    let online = bridge
+        .server()
+        .devices()
+        .iter()
+        .any(|d| d.device_id == input.device_id && d.online);
+    let channel = catalog(&input.device_id)
+        .and_then(|items| items.into_iter().find(|c| c.device_id == input.channel_id));
+    Some(GbStatus {
+        online,
+        channel_name: channel.as_ref().map(|c| c.name.clone()),
+        channel_status: channel.map(|c| c.status),
+    })
+}
+
+#[cfg(test)]
+#[path = "device_test.rs"]
+mod device_test;
@@ -0,0 +1,54 @@
+use super::*;
+
+#[test]
+fn input_defaults_and_rejects() {
+    let gb = GbInput::parse(
+        r#"{"device_id":"34020000001320000001","channel_id":"34020000001310000001"}"#,
+    )
+    .unwrap();
+    assert_eq!(gb.transport().unwrap(), Transport::Udp);
+    assert!(!gb.always_on);
+    assert_eq!(gb.password, None);
+
+    let always_on = GbInput::parse(
+        r#"{"device_id":"d","channel_id":"c","always_on":true,"transport":"tcp_passive"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        always_on.channel().unwrap().transport,
+        Transport::TcpPassive
+    );
+
+    // Only the device connects out for always-on ingest.
+    assert!(
+        GbInput::parse(
+            r#"{"device_id":"d","channel_id":"c","always_on":true,"transport":"tcp_active"}"#
+        )
+        .is_err()
+    );
+    assert!(GbInput::parse(r#"{"device_id":"d","channel_id":"c","transport":"sctp"}"#).is_err());
+    assert!(GbInput::parse(r#"{"device_id":"d"}"#).is_err());
+}
+
+#[test]
+fn passwords_follow_the_devices_giving_them() {
+    let input = |password: Option<&str>| GbInput {
+        device_id: "34020000001329990001".into(),
+        channel_id: "34020000001319990001".into(),
+        password: password.map(str::to_string),
+        transport: None,
+        always_on: false,
+    };
+    assert_eq!(password("34020000001329990001"), None);
+
+    // Two channels of one device.
+    set_password("gb-pw-a", &input(Some("own")));
+    set_password("gb-pw-b", &input(Some("own")));
+    assert_eq!(password("34020000001329990001").as_deref(), Some("own"));
+    forget_password("gb-pw-a");
+    assert_eq!(password("34020000001329990001").as_deref(), Some("own"));
+
+    // An empty password is none.
+    set_password("gb-pw-b", &input(Some("")));
+    assert_eq!(password("34020000001329990001"), None);
+}
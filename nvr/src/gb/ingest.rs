@@ -0,0 +1,309 @@
+//! Always-on ingest of a GB28181 channel, for devices with `"always_on": true`
+//! in their `input_value`. Instead of waiting for ZLM to ask for the stream
+//! when a viewer opens it (see [`crate::gb::bridge`]), this worker INVITEs the
+//! channel right away, receives its PS over RTP itself ([`crate::gb::ps`]) and
+//! runs it through a pipe into the device's ZLM media like any other camera,
+//! so it records and reports health with no one watching. A session ends when
+//! the device stops sending; the channel is then INVITEd again, with the same
+//! backoff as `onvif::ingest`.
+
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use gb28181::{MediaSpec, SsrcKind, StreamType, Transport};
+use media_pipe_core::{InputConfig, Pipe, PipeConfig};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_util::sync::CancellationToken;
+
+use crate::gb::ps::{Depacketizer, Rfc4571, parse_rtp};
+
+const BACKOFF_MIN: Duration = Duration::from_secs(2);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A session that lived at least this long counts as healthy: the next failure
+/// starts the backoff over instead of continuing where it left off.
+const HEALTHY_SESSION: Duration = Duration::from_secs(30);
+/// No media for this long (or no connection, over TCP) ends the session.
+const MEDIA_TIMEOUT: Duration = Duration::from_secs(10);
+/// PS chunks (about one RTP packet each) queued for the demuxer; more are
+/// dropped while it is that far behind.
+const QUEUE: usize = 1024;
+
+/// The channel an always-on GB28181 device ingests.
+#[derive(Debug, Clone)]
+pub(crate) struct GbChannel {
+    pub device_id: String,
+    pub channel_id: String,
+    /// `Udp` or `TcpPassive`: the device sends to us.
+    pub transport: Transport,
+}
+
+/// Spawn the INVITE → receive → backoff → re-INVITE loop publishing
+/// `channel` as the nvr device `stream_id`. Registered in the manager as an
+/// [`crate::manager`] `Task`; stops via `cancel`, sending a BYE.
+pub(crate) fn spawn_gb_device(
+    stream_id: String,
+    channel: GbChannel,
+    media: Arc<rszlm::media::Media>,
+    include_audio: bool,
+    cancel: CancellationToken,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut backoff = BACKOFF_MIN;
+        loop {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let started = Instant::now();
+            match run_session(
+                &stream_id,
+                &channel,
+                Arc::clone(&media),
+                include_audio,
+                &cancel,
+            )
+            .await
+            {
+                Ok(()) => {
+                    if cancel.is_cancelled() {
+                        break;
+                    }
+                    if started.elapsed() >= HEALTHY_SESSION {
+                        backoff = BACKOFF_MIN;
+                    }
+                    log::warn!("gb28181 {stream_id}: session ended, re-inviting in {backoff:?}");
+                }
+                Err(e) => {
+                    log::warn!("gb28181 {stream_id}: session failed: {e:#}, retry in {backoff:?}");
+                }
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tokio::time::sleep(backoff) => {}
+            }
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+        }
+        log::info!("gb28181 {stream_id}: worker stopped");
+    })
+}
+
+/// INVITE the channel and run its media into `media` until the device stops
+/// sending or `cancel` fires, then BYE.
+async fn run_session(
+    stream_id: &str,
+    channel: &GbChannel,
+    media: Arc<rszlm::media::Media>,
+    include_audio: bool,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let bridge = crate::gb::bridge().context("GB support is not active")?;
+    let socket = Socket::bind(channel.transport).await?;
+    let port = socket.port()?;
+    let (ssrc, ssrc_str) = bridge.server().next_ssrc(SsrcKind::Live);
+    let spec = MediaSpec {
+        ssrc,
+        ssrc_str,
+        transport: channel.transport,
+        media_addr: format!("{}:{port}", bridge.media_ip()).parse()?,
+        stream_type: StreamType::Play,
+        negotiated_remote: None,
+    };
+    let session = bridge
+        .server()
+        .invite_play(&channel.device_id, &channel.channel_id, spec)
+        .await?;
+    log::info!(
+        "gb28181 {stream_id}: receiving {} channel {} ({:?} port {port})",
+        channel.device_id,
+        channel.channel_id,
+        channel.transport
+    );
+
+    // Until now the socket only buffered what came in.
+    let (tx, rx) = std::sync::mpsc::sync_channel(QUEUE);
+    let receiving = tokio::spawn(socket.receive(stream_id.to_string(), tx));
+    let reader = Mutex::new(Some(PsReader::new(rx)));
+    let open: ffmpeg_bus::bus::ReaderFactory = Arc::new(move || {
+        let reader = reader
+            .lock()
+            .unwrap()
+            .take()
+            .context("the PS stream was already read")?;
+        Ok(Box::new(reader) as Box<dyn Read + Send>)
+    });
+    let config = PipeConfig {
+        input: InputConfig::Reader {
+            open,
+            format: "mpeg".to_string(),
+        },
+        outputs: media_pipe_zlm::zlm_outputs(media, include_audio),
+    };
+    let pipe = Arc::new(Pipe::new(config));
+    let pipe_for_task = Arc::clone(&pipe);
+    let mut task = tokio::spawn(async move {
+        pipe_for_task.start(None).await;
+    });
+    tokio::select! {
+        _ = cancel.cancelled() => {
+            // Dropping the sender ends the input at once.
+            receiving.abort();
+            pipe.cancel();
+            let _ = (&mut task).await;
+        }
+        _ = &mut task => {}
+    }
+    receiving.abort();
+    if let Err(e) = session.stop().await {
+        log::warn!("gb28181 {stream_id}: BYE failed: {e:#}");
+    }
+    Ok(())
+}
+
+/// Where the device sends its media.
+enum Socket {
+    Udp(UdpSocket),
+    /// TCP passive: the device connects to us.
+    Tcp(TcpListener),
+}
+
+impl Socket {
+    async fn bind(transport: Transport) -> Result<Self> {
+        let any = SocketAddr::from(([0, 0, 0, 0], 0));
+        Ok(match transport {
+            Transport::Udp => Self::Udp(UdpSocket::bind(any).await?),
+            Transport::TcpPassive => Self::Tcp(TcpListener::bind(any).await?),
+            Transport::TcpActive => bail!("always-on ingest takes udp or tcp_passive"),
+        })
+    }
+
+    fn port(&self) -> std::io::Result<u16> {
+        Ok(match self {
+            Self::Udp(socket) => socket.local_addr()?.port(),
+            Self::Tcp(listener) => listener.local_addr()?.port(),
+        })
+    }
+
+    /// Depacketize what the device sends into `tx` until the reader is gone
+    /// or the device hangs up.
+    async fn receive(self, stream_id: String, tx: SyncSender<Vec<u8>>) {
+        // Devices do not always use the SSRC offered: lock on to the first.
+        let mut depacketizer = Depacketizer::new(None);
+        let result = match self {
+            Self::Udp(socket) => receive_udp(&socket, &mut depacketizer, &tx).await,
+            Self::Tcp(listener) => receive_tcp(&listener, &mut depacketizer, &tx).await,
+        };
+        if let Err(e) = result {
+            log::warn!("gb28181 {stream_id}: media receive failed: {e:#}");
+        }
+        if depacketizer.lost() > 0 {
+            log::info!(
+                "gb28181 {stream_id}: {} rtp packets lost",
+                depacketizer.lost()
+            );
+        }
+    }
+}
+
+async fn receive_udp(
+    socket: &UdpSocket,
+    depacketizer: &mut Depacketizer,
+    tx: &SyncSender<Vec<u8>>,
+) -> Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = socket.recv(&mut buf).await?;
+        match parse_rtp(&buf[..n]) {
+            Ok(packet) => {
+                if !forward(tx, depacketizer.push(&packet)) {
+                    return Ok(());
+                }
+            }
+            Err(e) => log::debug!("gb28181: dropped datagram: {e:#}"),
+        }
+    }
+}
+
+async fn receive_tcp(
+    listener: &TcpListener,
+    depacketizer: &mut Depacketizer,
+    tx: &SyncSender<Vec<u8>>,
+) -> Result<()> {
+    let (mut stream, _) = tokio::time::timeout(MEDIA_TIMEOUT, listener.accept())
+        .await
+        .context("the device did not connect")??;
+    let mut framer = Rfc4571::default();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        framer.extend(&buf[..n]);
+        while let Some(packet) = framer.next_packet() {
+            let packet = parse_rtp(&packet)?;
+            if !forward(tx, depacketizer.push(&packet)) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Queue `ps` for the demuxer, dropping it while the queue is full; false
+/// once the reader is gone.
+fn forward(tx: &SyncSender<Vec<u8>>, ps: Vec<u8>) -> bool {
+    if ps.is_empty() {
+        return true;
+    }
+    match tx.try_send(ps) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            log::debug!("gb28181: demuxer behind, dropped ps data");
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    }
+}
+
+/// The PS stream as the pipe's input reads it. It ends when the receiver
+/// stops, or when no media came for [`MEDIA_TIMEOUT`].
+struct PsReader {
+    rx: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    at: usize,
+}
+
+impl PsReader {
+    fn new(rx: Receiver<Vec<u8>>) -> Self {
+        Self {
+            rx,
+            chunk: Vec::new(),
+            at: 0,
+        }
+    }
+}
+
+impl Read for PsReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.at == self.chunk.len() {
+            match self.rx.recv_timeout(MEDIA_TIMEOUT) {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.at = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    log::warn!("gb28181: no media for {MEDIA_TIMEOUT:?}");
+                    return Ok(0);
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.at);
+        buf[..n].copy_from_slice(&self.chunk[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
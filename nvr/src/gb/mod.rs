@@ -5,6 +5,9 @@
 pub mod api;
 pub mod bridge;
 pub mod config;
+pub mod device;
+pub mod ingest;
+pub mod ps;
 pub mod receiver;
 pub mod stream_map;
 
@@ -72,16 +75,19 @@ pub async fn shutdown() {
     bridge.server().shutdown();
 }
 
-/// Drain GbServer events for observability (device online/offline, session end).
+/// Drain GbServer events for observability (device online/offline, session end),
+/// and query each device's catalog as it registers.
 fn spawn_event_logger(mut events: tokio::sync::mpsc::UnboundedReceiver<GbEvent>) {
     tokio::spawn(async move {
         while let Some(e) = events.recv().await {
             match e {
                 GbEvent::Registered { device_id } => {
-                    log::info!("gb28181: device registered: {device_id}")
+                    log::info!("gb28181: device registered: {device_id}");
+                    tokio::spawn(device::refresh_catalog(device_id));
                 }
                 GbEvent::Unregistered { device_id } => {
-                    log::info!("gb28181: device unregistered: {device_id}")
+                    log::info!("gb28181: device unregistered: {device_id}");
+                    device::forget_catalog(&device_id);
                 }
                 GbEvent::Offline { device_id } => {
                     log::warn!("gb28181: device offline: {device_id}")
@@ -0,0 +1,178 @@
+//! PS over RTP, as GB28181 devices send it: RTP packets (RFC 3550) over UDP,
+//! or over TCP with each one behind a 2-byte length (RFC 4571), put back in
+//! sequence order and unwrapped into the MPEG-PS byte stream the device
+//! muxed, which FFmpeg's `mpeg` demuxer reads.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+
+/// Packets held waiting for a missing one before it is given up as lost.
+const REORDER_WINDOW: usize = 32;
+
+/// MPEG-PS pack header start code.
+const PACK_START: [u8; 4] = [0x00, 0x00, 0x01, 0xBA];
+
+/// The fields of an RTP packet the depacketizer uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpPacket<'a> {
+    pub sequence: u16,
+    pub ssrc: u32,
+    pub payload: &'a [u8],
+}
+
+/// Parse one RTP packet, skipping its CSRCs, header extension and padding.
+pub fn parse_rtp(buf: &[u8]) -> Result<RtpPacket<'_>> {
+    if buf.len() < 12 {
+        bail!(
+            "rtp packet of {} bytes is shorter than its header",
+            buf.len()
+        );
+    }
+    if buf[0] >> 6 != 2 {
+        bail!("not rtp version 2");
+    }
+    let padding = buf[0] & 0x20 != 0;
+    let extension = buf[0] & 0x10 != 0;
+    let mut start = 12 + usize::from(buf[0] & 0x0F) * 4;
+    if extension {
+        let Some(header) = buf.get(start..start + 4) else {
+            bail!("rtp header extension is cut short");
+        };
+        start += 4 + usize::from(u16::from_be_bytes([header[2], header[3]])) * 4;
+    }
+    let mut end = buf.len();
+    if padding {
+        end = end.saturating_sub(usize::from(buf[buf.len() - 1]));
+    }
+    if start > end {
+        bail!("rtp header is longer than the packet");
+    }
+    Ok(RtpPacket {
+        sequence: u16::from_be_bytes([buf[2], buf[3]]),
+        ssrc: u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]),
+        payload: &buf[start..end],
+    })
+}
+
+/// Reorders the RTP packets of one stream and yields their PS payload.
+/// Duplicates and packets older than the ones already passed on are dropped;
+/// a gap that [`REORDER_WINDOW`] later packets have not filled is skipped, and
+/// as many packets in a row from behind are taken for a sender that restarted.
+/// Nothing is passed on before the first pack header, so the demuxer starts
+/// on a pack boundary.
+#[derive(Debug, Default)]
+pub struct Depacketizer {
+    /// Only packets of this SSRC; the first one seen unless given.
+    ssrc: Option<u32>,
+    /// Sequence number of the next packet to pass on.
+    next: Option<u16>,
+    /// Packets ahead of `next`, by sequence number.
+    pending: BTreeMap<u16, Vec<u8>>,
+    /// Packets in a row that were behind `next`.
+    behind: usize,
+    synced: bool,
+    lost: u64,
+}
+
+impl Depacketizer {
+    pub fn new(ssrc: Option<u32>) -> Self {
+        Self {
+            ssrc,
+            ..Self::default()
+        }
+    }
+
+    /// Packets skipped as lost so far.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// Take one RTP packet and return the PS bytes it completes, in order;
+    /// empty while waiting for an earlier packet.
+    pub fn push(&mut self, packet: &RtpPacket<'_>) -> Vec<u8> {
+        if *self.ssrc.get_or_insert(packet.ssrc) != packet.ssrc {
+            return Vec::new();
+        }
+        let next = *self.next.get_or_insert(packet.sequence);
+        let ahead = packet.sequence.wrapping_sub(next);
+        // More than half the sequence space ahead is behind.
+        if ahead >= 0x8000 {
+            self.behind += 1;
+            // Not late packets but a sender that started over: follow it.
+            if self.behind > REORDER_WINDOW {
+                self.next = Some(packet.sequence);
+                self.pending.clear();
+                self.behind = 0;
+            } else {
+                return Vec::new();
+            }
+        }
+        self.behind = 0;
+        self.pending
+            .entry(packet.sequence)
+            .or_insert_with(|| packet.payload.to_vec());
+
+        let mut out = Vec::new();
+        loop {
+            self.drain(&mut out);
+            if self.pending.len() <= REORDER_WINDOW {
+                break;
+            }
+            // Give up on the gap: move on to the earliest packet held.
+            let next = self.next.unwrap_or_default();
+            let earliest = self
+                .pending
+                .keys()
+                .copied()
+                .min_by_key(|seq| seq.wrapping_sub(next))
+                .expect("more than a window of packets held");
+            self.lost += u64::from(earliest.wrapping_sub(next));
+            self.next = Some(earliest);
+        }
+        out
+    }
+
+    /// Pass on the packets held from `next` on, up to the first gap.
+    fn drain(&mut self, out: &mut Vec<u8>) {
+        while let Some(next) = self.next
+            && let Some(payload) = self.pending.remove(&next)
+        {
+            self.next = Some(next.wrapping_add(1));
+            if self.synced {
+                out.extend_from_slice(&payload);
+            } else if let Some(at) = payload.windows(4).position(|w| w == PACK_START) {
+                self.synced = true;
+                out.extend_from_slice(&payload[at..]);
+            }
+        }
+    }
+}
+
+/// Splits a TCP byte stream into the RTP packets framed in it (RFC 4571).
+#[derive(Debug, Default)]
+pub struct Rfc4571 {
+    buf: Vec<u8>,
+}
+
+impl Rfc4571 {
+    /// Append bytes read from the connection.
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// The next complete packet, if one has arrived whole.
+    pub fn next_packet(&mut self) -> Option<Vec<u8>> {
+        let len = usize::from(u16::from_be_bytes([*self.buf.first()?, *self.buf.get(1)?]));
+        if self.buf.len() < 2 + len {
+            return None;
+        }
+        let packet = self.buf[2..2 + len].to_vec();
+        self.buf.drain(..2 + len);
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+#[path = "ps_test.rs"]
+mod ps_test;
@@ -0,0 +1,144 @@
+use super::*;
+
+const SSRC: u32 = 200_000_001;
+
+/// An RTP packet of `payload` with sequence number `seq`, PT 96.
+fn rtp(seq: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0x80, 96];
+    buf.extend_from_slice(&seq.to_be_bytes());
+    buf.extend_from_slice(&(u32::from(seq) * 3600).to_be_bytes());
+    buf.extend_from_slice(&SSRC.to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// A pack, as the PS muxer starts one, followed by `n`.
+fn pack(n: u8) -> Vec<u8> {
+    let mut buf = PACK_START.to_vec();
+    buf.extend_from_slice(&[0x44, n]);
+    buf
+}
+
+fn push(d: &mut Depacketizer, seq: u16, payload: &[u8]) -> Vec<u8> {
+    d.push(&parse_rtp(&rtp(seq, payload)).unwrap())
+}
+
+#[test]
+fn parses_csrcs_extensions_and_padding() {
+    let mut buf = vec![0xB1, 0xE0];
+    buf.extend_from_slice(&7u16.to_be_bytes());
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(&SSRC.to_be_bytes());
+    // One CSRC.
+    buf.extend_from_slice(&[1, 2, 3, 4]);
+    // An extension one word long.
+    buf.extend_from_slice(&[0xBE, 0xDE, 0, 1, 9, 9, 9, 9]);
+    buf.extend_from_slice(b"ps");
+    // Three bytes of padding, the last one counting them.
+    buf.extend_from_slice(&[0, 0, 3]);
+
+    let packet = parse_rtp(&buf).unwrap();
+    assert_eq!(packet.sequence, 7);
+    assert_eq!(packet.ssrc, SSRC);
+    assert_eq!(packet.payload, b"ps");
+
+    assert!(parse_rtp(&buf[..11]).is_err());
+    assert!(parse_rtp(&[0x40; 12]).is_err(), "version 1");
+    // The extension claims more than there is.
+    assert!(parse_rtp(&buf[..18]).is_err());
+}
+
+#[test]
+fn reorders_and_drops_duplicates_and_late_packets() {
+    let mut d = Depacketizer::new(Some(SSRC));
+    let first = pack(1);
+    assert_eq!(push(&mut d, 10, &first), first);
+    // 12 waits for 11.
+    assert!(push(&mut d, 12, b"c").is_empty());
+    assert!(push(&mut d, 12, b"c").is_empty());
+    assert_eq!(push(&mut d, 11, b"b"), b"bc");
+    // Already passed on.
+    assert!(push(&mut d, 11, b"b").is_empty());
+    assert!(push(&mut d, 9, b"a").is_empty());
+    assert_eq!(push(&mut d, 13, b"d"), b"d");
+    assert_eq!(d.lost(), 0);
+
+    // Another stream on the same port.
+    let mut other = rtp(14, b"x");
+    other[8..12].copy_from_slice(&1u32.to_be_bytes());
+    assert!(d.push(&parse_rtp(&other).unwrap()).is_empty());
+}
+
+#[test]
+fn sequence_numbers_wrap() {
+    let mut d = Depacketizer::new(None);
+    let first = pack(1);
+    assert_eq!(push(&mut d, u16::MAX, &first), first);
+    assert!(push(&mut d, 1, b"b").is_empty());
+    assert_eq!(push(&mut d, 0, b"a"), b"ab");
+}
+
+#[test]
+fn gives_up_on_a_gap_after_a_window_of_packets() {
+    let mut d = Depacketizer::new(None);
+    push(&mut d, 0, &pack(0));
+    // 1 never comes.
+    let mut out = Vec::new();
+    for seq in 2..=(REORDER_WINDOW as u16 + 2) {
+        out.extend(push(&mut d, seq, &[seq as u8]));
+    }
+    let expected: Vec<u8> = (2..=(REORDER_WINDOW as u8 + 2)).collect();
+    assert_eq!(out, expected);
+    assert_eq!(d.lost(), 1);
+    // Too late now.
+    assert!(push(&mut d, 1, b"late").is_empty());
+}
+
+#[test]
+fn follows_a_sender_that_restarted() {
+    let mut d = Depacketizer::new(None);
+    push(&mut d, 40_000, &pack(0));
+    // Starting over behind: taken for late packets a window long, then followed.
+    let restart = 30_000u16;
+    let window = REORDER_WINDOW as u16;
+    let mut out = Vec::new();
+    for seq in restart..=restart + window + 1 {
+        out.extend(push(&mut d, seq, &[seq as u8]));
+    }
+    assert_eq!(
+        out,
+        [(restart + window) as u8, (restart + window + 1) as u8]
+    );
+}
+
+#[test]
+fn waits_for_a_pack_header() {
+    let mut d = Depacketizer::new(None);
+    // The middle of a pack, from before we joined.
+    assert!(push(&mut d, 5, b"tail of a frame").is_empty());
+    let mut payload = b"rest".to_vec();
+    payload.extend(pack(2));
+    assert_eq!(push(&mut d, 6, &payload), pack(2));
+    assert_eq!(push(&mut d, 7, b"more"), b"more");
+}
+
+#[test]
+fn splits_rfc4571_frames() {
+    let mut framer = Rfc4571::default();
+    let (a, b) = (rtp(1, b"one"), rtp(2, b"two"));
+    let mut stream = Vec::new();
+    for packet in [&a, &b] {
+        stream.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+        stream.extend_from_slice(packet);
+    }
+    // Arriving a byte at a time up to the middle of the second frame.
+    let split = a.len() + 5;
+    for byte in &stream[..split] {
+        framer.extend(std::slice::from_ref(byte));
+    }
+    assert_eq!(framer.next_packet(), Some(a));
+    assert_eq!(framer.next_packet(), None);
+    framer.extend(&stream[split..]);
+    assert_eq!(framer.next_packet(), Some(b));
+    assert_eq!(framer.next_packet(), None);
+}
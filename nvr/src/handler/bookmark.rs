@@ -0,0 +1,209 @@
+//! Operator bookmarks on a device's timeline. A bookmark at a moment with no
+//! recording is kept but flagged `uncovered`. Users with the viewer role
+//! (see [`crate::auth::is_viewer`]) may only list them.
+
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    routing::{get, post},
+};
+use nvr_db::bookmark::Bookmark;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{self, AuthUser},
+    db::app_db_conn,
+    handler::{
+        ApiError, ApiJsonResult, ApiResult, ok_json,
+        playback::{self, CoverageSpan},
+    },
+};
+
+/// Seconds either side of the bookmark an export covers by default.
+const DEFAULT_EXPORT_SECONDS: u32 = 10;
+const MAX_EXPORT_SECONDS: u32 = 300;
+
+pub fn bookmark_router() -> Router {
+    Router::new()
+        .route(
+            "/device/{device_id}",
+            get(list_bookmarks).post(create_bookmark),
+        )
+        .route("/{id}", get(get_bookmark))
+        .route("/{id}/update", post(update_bookmark))
+        .route("/{id}/delete", post(delete_bookmark))
+        .route("/{id}/export", post(export_bookmark))
+}
+
+/// A bookmark as returned by the API (and in the playback timeline).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct BookmarkItem {
+    #[serde(flatten)]
+    pub bookmark: Bookmark,
+    /// No recording covers the bookmarked moment.
+    pub uncovered: bool,
+}
+
+impl BookmarkItem {
+    pub(crate) fn new(bookmark: Bookmark, spans: &[CoverageSpan]) -> Self {
+        let uncovered = !playback::is_covered(spans, bookmark.ts);
+        Self {
+            bookmark,
+            uncovered,
+        }
+    }
+
+    /// Check `bookmark` against the device's recordings.
+    async fn load(bookmark: Bookmark, conn: &turso::Connection) -> anyhow::Result<Self> {
+        let ts = bookmark.ts;
+        let segments =
+            playback::segments_overlapping(&bookmark.device_id, ts, ts + 1, conn).await?;
+        Ok(Self::new(bookmark, &playback::coverage_spans(&segments)))
+    }
+}
+
+async fn ensure_writable(user: &AuthUser) -> ApiResult<()> {
+    if auth::is_viewer(&user.username).await? {
+        return Err(ApiError::forbidden("viewers cannot change bookmarks"));
+    }
+    Ok(())
+}
+
+async fn find(id: &str, conn: &turso::Connection) -> ApiResult<Bookmark> {
+    nvr_db::bookmark::get(id, conn)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("bookmark {id} not found")))
+}
+
+#[derive(Debug, Deserialize)]
+struct BookmarkListQuery {
+    /// Unix milliseconds, inclusive; default: everything.
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+async fn list_bookmarks(
+    Path(device_id): Path<String>,
+    Query(query): Query<BookmarkListQuery>,
+) -> ApiJsonResult<Vec<BookmarkItem>> {
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or(i64::MAX);
+    let timeline = playback::timeline(&device_id, from, to).await?;
+    Ok(ok_json(timeline.bookmarks))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateBookmarkRequest {
+    /// Unix milliseconds.
+    ts: i64,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    color: String,
+}
+
+async fn create_bookmark(
+    Extension(user): Extension<AuthUser>,
+    Path(device_id): Path<String>,
+    Json(req): Json<CreateBookmarkRequest>,
+) -> ApiJsonResult<BookmarkItem> {
+    ensure_writable(&user).await?;
+    let conn = app_db_conn()?;
+    if nvr_db::device::get(&device_id, &conn).await?.is_none() {
+        return Err(ApiError::not_found(format!("device {device_id} not found")));
+    }
+    if req.ts < 0 {
+        return Err(ApiError::bad_request(
+            "bookmark timestamp must not be negative",
+        ));
+    }
+    let bookmark = Bookmark {
+        id: uuid::Uuid::new_v4().to_string(),
+        device_id,
+        ts: req.ts,
+        label: req.label.trim().to_string(),
+        color: req.color.trim().to_string(),
+        created_by: user.username,
+        created_at: chrono::Utc::now().timestamp_millis(),
+        export_id: String::new(),
+    };
+    nvr_db::bookmark::insert(&bookmark, &conn).await?;
+    Ok(ok_json(BookmarkItem::load(bookmark, &conn).await?))
+}
+
+async fn get_bookmark(Path(id): Path<String>) -> ApiJsonResult<BookmarkItem> {
+    let conn = app_db_conn()?;
+    let bookmark = find(&id, &conn).await?;
+    Ok(ok_json(BookmarkItem::load(bookmark, &conn).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateBookmarkRequest {
+    label: Option<String>,
+    color: Option<String>,
+}
+
+async fn update_bookmark(
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(req): Json<UpdateBookmarkRequest>,
+) -> ApiJsonResult<BookmarkItem> {
+    ensure_writable(&user).await?;
+    let conn = app_db_conn()?;
+    let mut bookmark = find(&id, &conn).await?;
+    if let Some(label) = req.label {
+        bookmark.label = label.trim().to_string();
+    }
+    if let Some(color) = req.color {
+        bookmark.color = color.trim().to_string();
+    }
+    nvr_db::bookmark::update(&bookmark, &conn).await?;
+    Ok(ok_json(BookmarkItem::load(bookmark, &conn).await?))
+}
+
+#[derive(Debug, Serialize)]
+struct DeleteBookmarkResult {
+    deleted: bool,
+}
+
+async fn delete_bookmark(
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> ApiJsonResult<DeleteBookmarkResult> {
+    ensure_writable(&user).await?;
+    let deleted = nvr_db::bookmark::delete(&id, &app_db_conn()?).await?;
+    Ok(ok_json(DeleteBookmarkResult { deleted }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportBookmarkRequest {
+    /// Seconds before and after the bookmark; default 10, at most 300.
+    seconds: Option<u32>,
+}
+
+/// Start a clip export around the bookmark and link it to the bookmark.
+async fn export_bookmark(
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    req: Option<Json<ExportBookmarkRequest>>,
+) -> ApiJsonResult<crate::export::ExportJob> {
+    ensure_writable(&user).await?;
+    let conn = app_db_conn()?;
+    let mut bookmark = find(&id, &conn).await?;
+    let seconds = req
+        .and_then(|Json(req)| req.seconds)
+        .unwrap_or(DEFAULT_EXPORT_SECONDS)
+        .clamp(1, MAX_EXPORT_SECONDS);
+    let span = i64::from(seconds) * 1000;
+    let job = crate::export::start(
+        &bookmark.device_id,
+        bookmark.ts - span,
+        bookmark.ts + span,
+        false,
+        &user.username,
+    )
+    .await?;
+    bookmark.export_id = job.id.clone();
+    nvr_db::bookmark::update(&bookmark, &conn).await?;
+    Ok(ok_json(job))
+}
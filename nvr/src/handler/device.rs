@@ -1,16 +1,20 @@
 use axum::{
-    Json, Router,
-    extract::Path,
+    Extension, Json, Router,
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use chrono::Utc;
 use harsh::Harsh;
 use nvr_db::device::DeviceInfo;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 use crate::{
+    auth::AuthUser,
     db::app_db_conn,
-    handler::{ApiJsonResult, ok_json},
+    device_input::{DeviceInput, InvalidInput},
+    handler::{ApiError, ApiJsonResult, ApiResult, BaseResponse, ok_json},
     init::device::{build_flv_url, build_gb_flv_url, ensure_device_pipe},
     manager,
 };
@@ -35,108 +39,499 @@ pub fn device_router() -> Router {
         .route("/add", post(add_device))
         .route("/update/{id}", post(update_device))
         .route("/remove/{id}", post(remove_device))
+        .route("/privacy/{id}", get(get_privacy).post(set_privacy))
+        .route("/{id}/thumbnail", get(crate::thumbnail::thumbnail))
+        .route("/{id}/mjpeg", get(crate::mjpeg::mjpeg))
+        .route("/{id}/ws-preview", get(crate::ws_preview::ws_preview))
+        .route("/{id}/live.mp4", get(crate::handler::media_pipe::live_mp4))
+        .route("/{id}/health", get(crate::health::health))
+        .route("/{id}/input", get(crate::failover::input_status))
+        .route("/{id}/restart", get(crate::supervisor::restarts))
+        .route("/{id}/resume", post(crate::supervisor::resume_device))
+        .route("/{id}/usage", get(crate::usage::device_usage))
+        .route(
+            "/{id}/recordings",
+            get(crate::handler::recording::device_recordings),
+        )
+        .route("/{id}/stats", get(crate::handler::media_pipe::device_stats))
+        .route("/{id}/playback", post(crate::handler::media_pipe::playback))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct DevicePayload {
+/// The schema of [`device_router`], mounted at `/api/v1/device`.
+#[derive(OpenApi)]
+#[openapi(paths(
+    index,
+    list_devices,
+    add_device,
+    update_device,
+    remove_device,
+    get_privacy,
+    set_privacy,
+    crate::thumbnail::thumbnail,
+    crate::mjpeg::mjpeg,
+    crate::ws_preview::ws_preview,
+    crate::handler::media_pipe::live_mp4,
+    crate::health::health,
+    crate::failover::input_status,
+    crate::supervisor::restarts,
+    crate::supervisor::resume_device,
+    crate::usage::device_usage,
+    crate::handler::recording::device_recordings,
+    crate::handler::media_pipe::device_stats,
+    crate::handler::media_pipe::playback,
+))]
+pub(crate) struct DeviceApi;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(crate) struct DevicePayload {
     id: Option<String>,
     name: String,
+    /// With `input_value`, the stored form of the input; ignored when
+    /// `input` is given.
+    #[serde(default)]
     input_type: String,
+    #[serde(default)]
     input_value: String,
+    /// The input in typed form (see `crate::device_input`), e.g.
+    /// `{"input_type": "rtsp", "url": "rtsp://…"}`.
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    input: Option<serde_json::Value>,
     description: Option<String>,
     #[serde(default)]
     include_audio: bool,
     #[serde(default = "default_record")]
     record: bool,
+    /// ZLM stream name; defaults to a slug of the name plus the id on create
+    /// and to the current key on update.
+    #[serde(default)]
+    stream_key: Option<String>,
+    /// On update: move to the default key for the (new) name.
+    #[serde(default)]
+    regenerate_stream_key: bool,
+    /// IANA zone; empty for the server default. Unchanged on update when
+    /// absent.
+    #[serde(default)]
+    timezone: Option<String>,
+    /// Object detection (see `crate::detect::analytics`). Unchanged on update
+    /// when absent; `{"detector": "none"}` turns it off.
+    #[serde(default)]
+    detection: Option<nvr_db::device::DetectionSettings>,
+    /// Device group (see `crate::wall`). Unchanged on update when absent.
+    #[serde(default)]
+    group: Option<String>,
+    /// Encrypt its recordings at rest (see `crate::encryption`). Unchanged on
+    /// update when absent.
+    #[serde(default)]
+    encrypt: Option<bool>,
 }
 
 fn default_record() -> bool {
     true
 }
 
-#[derive(Debug, Serialize)]
+impl DevicePayload {
+    /// An add of `input` as the device `name`, all else the defaults.
+    pub(crate) fn typed(name: String, input: &DeviceInput) -> Self {
+        Self {
+            id: None,
+            name,
+            input_type: String::new(),
+            input_value: String::new(),
+            input: Some(serde_json::to_value(input).expect("device inputs serialize")),
+            description: None,
+            include_audio: false,
+            record: default_record(),
+            stream_key: None,
+            regenerate_stream_key: false,
+            timezone: None,
+            detection: None,
+            group: None,
+            encrypt: None,
+        }
+    }
+
+    /// The `input_type` and `input_value` to store, once the input checks
+    /// out: as sent, or those of the typed `input`.
+    fn stored_input(&self) -> Result<(String, String), InvalidInput> {
+        if let Some(input) = &self.input {
+            let input = DeviceInput::from_json(input.clone())?;
+            return Ok((input.input_type().to_string(), input.input_value()));
+        }
+        let input_type = self.input_type.trim();
+        let input_value = self.input_value.trim();
+        DeviceInput::parse(input_type, input_value)?;
+        Ok((input_type.to_string(), input_value.to_string()))
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 struct DeviceListItem {
     #[serde(flatten)]
     device: DeviceInfo,
     flv_url: String,
+    /// Owning peer node for federated (read-only) devices; absent for local ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<String>,
+    /// False while the owning peer is unreachable. Always true for local devices.
+    available: bool,
+    /// Latest measured camera clock offset (local devices only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock: Option<crate::clock::DeviceClock>,
+    /// Whether privacy mode currently blanks the device (local devices only).
+    privacy: bool,
+    /// The zone its schedules and timeline days are in: its own, or the
+    /// server default (local devices only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tz: Option<String>,
+    /// Latest stream health score (running local devices only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<crate::health::DeviceHealth>,
+    /// The input in use, for local devices with failover URLs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_input: Option<crate::failover::ActiveInput>,
+    /// Restart state of its pipe: backing off or parked after ending too
+    /// often (local pipe devices only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restart: Option<crate::supervisor::RestartStatus>,
+    /// Live viewer sessions (local devices only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    viewers: Option<usize>,
+    /// Registration and catalog status (local gb28181 devices only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gb: Option<crate::gb::device::GbStatus>,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+struct DeviceUpdate {
+    #[serde(flatten)]
+    device: DeviceInfo,
+    flv_url: String,
+    /// The stream key changed, so the pipe was restarted under the new name:
+    /// open viewers must reconnect to `flv_url`.
+    stream_key_changed: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct PrivacyPayload {
+    enabled: bool,
+    /// Manual privacy ends on its own at this time.
+    #[serde(default)]
+    until: Option<chrono::DateTime<Utc>>,
+    /// Replaces the device's recurring daily windows when present.
+    #[serde(default)]
+    windows: Option<Vec<crate::privacy::PrivacyWindow>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct PrivacyStatus {
+    #[serde(flatten)]
+    config: crate::privacy::DevicePrivacy,
+    /// Whether the device is private right now (manually or by a window).
+    active: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DeviceListQuery {
+    /// Only this node's own devices. Peers poll with this set so learned
+    /// devices are never re-advertised.
+    #[serde(default)]
+    local: bool,
+}
+
+#[utoipa::path(get, path = "/", tag = "device", responses((status = 200, body = String)))]
 async fn index() -> &'static str {
     "device route!"
 }
 
-async fn list_devices() -> ApiJsonResult<Vec<DeviceListItem>> {
+/// Local devices, then those learned from federation peers.
+#[utoipa::path(
+    get,
+    path = "/list",
+    tag = "device",
+    params(DeviceListQuery),
+    responses((status = 200, body = BaseResponse<Vec<DeviceListItem>>))
+)]
+async fn list_devices(Query(query): Query<DeviceListQuery>) -> ApiJsonResult<Vec<DeviceListItem>> {
     let conn = app_db_conn()?;
     let devices = nvr_db::device::list(&conn).await?;
-    Ok(ok_json(
-        devices
-            .into_iter()
-            .map(|device| DeviceListItem {
-                // GB28181 streams are published by ZLM's RtpServer under the
-                // `rtp` app, so they need a different FLV url than pipe streams.
-                flv_url: if device.input_type == "gb28181" {
-                    build_gb_flv_url(&device.id)
-                } else {
-                    build_flv_url(&device.id)
-                },
-                device,
-            })
-            .collect(),
-    ))
-}
-
-async fn add_device(Json(payload): Json<DevicePayload>) -> ApiJsonResult<DeviceInfo> {
+    let mut items = devices
+        .into_iter()
+        .map(|device| DeviceListItem {
+            flv_url: device_flv_url(&device),
+            clock: crate::clock::device_clock(&device.id),
+            privacy: crate::privacy::is_private(&device.id),
+            tz: Some(crate::tz::of(&device).name().to_string()),
+            health: crate::health::device_health(&device.id),
+            active_input: crate::failover::active_input(&device.id),
+            restart: crate::supervisor::restart_status(&device.id),
+            viewers: Some(crate::viewers::viewer_count(&device.id)),
+            gb: (device.input_type == "gb28181")
+                .then(|| crate::gb::device::status(&device.input_value))
+                .flatten(),
+            device,
+            node: None,
+            available: true,
+        })
+        .collect::<Vec<_>>();
+    if !query.local {
+        items.extend(
+            crate::federation::remote_devices()
+                .into_iter()
+                .map(|remote| DeviceListItem {
+                    flv_url: crate::federation::api::media_url(
+                        &remote.node,
+                        &remote.device.flv_url,
+                    ),
+                    device: remote.device.device,
+                    node: Some(remote.node),
+                    available: remote.available,
+                    clock: None,
+                    privacy: remote.device.privacy,
+                    tz: None,
+                    health: None,
+                    active_input: None,
+                    restart: None,
+                    viewers: None,
+                    gb: None,
+                }),
+        );
+    }
+    Ok(ok_json(items))
+}
+
+/// On-demand GB28181 streams are published by ZLM's RtpServer under the `rtp`
+/// app, so they need a different FLV url than pipe streams; always-on ones
+/// run through a pipe like the rest.
+fn device_flv_url(device: &DeviceInfo) -> String {
+    let on_demand = device.input_type == "gb28181"
+        && crate::gb::device::GbInput::parse(&device.input_value).is_ok_and(|gb| !gb.always_on);
+    if on_demand {
+        build_gb_flv_url(&device.id)
+    } else {
+        build_flv_url(device.stream_name())
+    }
+}
+
+/// The input tuning of a device ffmpeg opens directly, from its JSON input.
+fn input_tuning(device: &DeviceInfo) -> Option<crate::latency::InputTuning> {
+    if matches!(
+        device.input_type.as_str(),
+        "xiaomi" | "gb28181" | "onvif" | "stream"
+    ) {
+        return None;
+    }
+    crate::failover::FailoverInput::parse(&device.input_value)
+        .ok()
+        .flatten()
+        .map(|input| input.tuning)
+}
+
+/// 400 naming the first raw input option of `device` the guardrails refuse
+/// (see `crate::input_options`), 403 if `user` changes its extra input
+/// options from `existing`'s without being an admin.
+async fn check_input_options(
+    device: &DeviceInfo,
+    existing: Option<&DeviceInfo>,
+    user: &AuthUser,
+) -> ApiResult<()> {
+    let tuning = input_tuning(device).unwrap_or_default();
+    if let Err(rejected) = crate::input_options::validate(&tuning) {
+        return Err(ApiError::bad_request(rejected.to_string()));
+    }
+    let before = existing
+        .and_then(input_tuning)
+        .map(|tuning| tuning.extra_input_options)
+        .unwrap_or_default();
+    if tuning.extra_input_options != before && !crate::auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden("custom input options are admin-only"));
+    }
+    Ok(())
+}
+
+/// 409 if another device already publishes as `device`'s stream key.
+async fn check_stream_key(device: &DeviceInfo, conn: &turso::Connection) -> ApiResult<()> {
+    let devices = nvr_db::device::list(conn).await?;
+    let key = device.stream_name();
+    match crate::stream_key::conflicting_device(key, &device.id, &devices) {
+        Some(other) => Err(ApiError::conflict(format!(
+            "stream key {key} is already used by device {}",
+            other.id
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/add",
+    tag = "device",
+    request_body = DevicePayload,
+    responses(
+        (status = 200, body = BaseResponse<DeviceInfo>),
+        (
+            status = 400,
+            description = "Invalid input, or a refused input option",
+            body = BaseResponse<()>,
+        ),
+        (
+            status = 403,
+            description = "Custom input options are admin-only",
+            body = BaseResponse<()>,
+        ),
+        (
+            status = 409,
+            description = "Another device uses the stream key or, without an id, the name",
+            body = BaseResponse<()>,
+        ),
+    )
+)]
+async fn add_device(
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<DevicePayload>,
+) -> ApiResult<Response> {
+    add(&user, payload).await
+}
+
+/// Add the device of `payload` for `user` (replacing the one of its id, if
+/// it names one) and start it, as `POST /api/device/add` does.
+pub(crate) async fn add(user: &AuthUser, payload: DevicePayload) -> ApiResult<Response> {
     let conn = app_db_conn()?;
+    let (input_type, input_value) = match payload.stored_input() {
+        Ok(stored) => stored,
+        Err(invalid) => return Err(ApiError::bad_request(invalid.to_string())),
+    };
     let now = Utc::now();
     let name = payload.name.trim().to_string();
+    // An id given replaces its device; one made from the name must be new.
+    let replace = payload.id.is_some();
+    let id = payload.id.unwrap_or_else(|| device_id_from_name(&name));
+    let current = nvr_db::device::get(&id, &conn).await?;
+    let stream_key = crate::stream_key::next_key(
+        current.as_ref(),
+        &id,
+        &name,
+        payload.stream_key.as_deref(),
+        payload.regenerate_stream_key,
+    )?;
     let device = DeviceInfo {
-        id: payload.id.unwrap_or_else(|| device_id_from_name(&name)),
+        id,
         name,
-        input_type: payload.input_type.trim().to_string(),
-        input_value: payload.input_value.trim().to_string(),
+        input_type,
+        input_value,
         description: payload.description.unwrap_or_default().trim().to_string(),
         include_audio: payload.include_audio,
         record: payload.record,
+        stream_key,
+        timezone: payload.timezone.unwrap_or_default().trim().to_string(),
+        detection: payload.detection,
+        group: payload.group.unwrap_or_default().trim().to_string(),
+        encrypt: payload.encrypt.unwrap_or_default(),
+        status: current
+            .as_ref()
+            .map(|current| current.status.clone())
+            .unwrap_or_default(),
         created_at: now,
         updated_at: now,
     };
     validate_device(&device)?;
-    nvr_db::device::upsert(&device, &conn).await?;
+    check_input_options(&device, current.as_ref(), user).await?;
+    check_stream_key(&device, &conn).await?;
+    if replace {
+        nvr_db::device::upsert(&device, &conn).await?;
+    } else {
+        nvr_db::device::insert(&device, &conn).await?;
+    }
     ensure_device_pipe(&device).await?;
-    Ok(ok_json(device))
+    Ok(ok_json(device).into_response())
 }
 
+#[utoipa::path(
+    post,
+    path = "/update/{id}",
+    tag = "device",
+    params(("id" = String, Path)),
+    request_body = DevicePayload,
+    responses(
+        (status = 200, body = BaseResponse<DeviceUpdate>),
+        (
+            status = 400,
+            description = "Invalid input, or a refused input option",
+            body = BaseResponse<()>,
+        ),
+        (
+            status = 403,
+            description = "Custom input options are admin-only",
+            body = BaseResponse<()>,
+        ),
+        (
+            status = 409,
+            description = "Another device uses the stream key",
+            body = BaseResponse<()>,
+        ),
+        (status = 404, description = "No such device", body = BaseResponse<()>),
+    )
+)]
 async fn update_device(
+    Extension(user): Extension<AuthUser>,
     Path(id): Path<String>,
     Json(payload): Json<DevicePayload>,
-) -> ApiJsonResult<DeviceInfo> {
+) -> ApiResult<Response> {
     let conn = app_db_conn()?;
-    let existing = nvr_db::device::get(&id, &conn)
-        .await?
-        .ok_or_else(|| anyhow::anyhow!("device not found"))?;
+    let (input_type, input_value) = match payload.stored_input() {
+        Ok(stored) => stored,
+        Err(invalid) => return Err(ApiError::bad_request(invalid.to_string())),
+    };
+    let existing = nvr_db::device::require(&id, &conn).await?;
+    let name = payload.name.trim().to_string();
+    // A rename alone keeps the stream (and so the live URLs) where it is.
+    let stream_key = crate::stream_key::next_key(
+        Some(&existing),
+        &id,
+        &name,
+        payload.stream_key.as_deref(),
+        payload.regenerate_stream_key,
+    )?;
     let device = DeviceInfo {
         id,
-        name: payload.name.trim().to_string(),
-        input_type: payload.input_type.trim().to_string(),
-        input_value: payload.input_value.trim().to_string(),
+        name,
+        input_type,
+        input_value,
         description: payload.description.unwrap_or_default().trim().to_string(),
         include_audio: payload.include_audio,
         record: payload.record,
+        stream_key,
+        timezone: payload
+            .timezone
+            .map(|tz| tz.trim().to_string())
+            .unwrap_or_else(|| existing.timezone.clone()),
+        detection: payload.detection.or_else(|| existing.detection.clone()),
+        group: payload
+            .group
+            .map(|group| group.trim().to_string())
+            .unwrap_or_else(|| existing.group.clone()),
+        encrypt: payload.encrypt.unwrap_or(existing.encrypt),
+        status: existing.status.clone(),
         created_at: existing.created_at,
         updated_at: Utc::now(),
     };
     validate_device(&device)?;
+    check_input_options(&device, Some(&existing), &user).await?;
+    check_stream_key(&device, &conn).await?;
     nvr_db::device::upsert(&device, &conn).await?;
     // On an input_type change involving gb28181, clean up the old kind's
     // resources first: leaving gb28181 must drop the stale pull mapping (+ any
-    // active pull), and entering gb28181 must remove the old pipe (the gb arm
-    // builds none, so `ensure_device_pipe` won't replace it). Both are
-    // idempotent no-ops otherwise; non-gb↔non-gb keeps its upsert-in-place path.
+    // active pull) and its password, and entering gb28181 must remove the old
+    // pipe (an on-demand gb device has none, an always-on one a different
+    // kind). Both are idempotent no-ops otherwise; non-gb↔non-gb keeps its
+    // upsert-in-place path.
     if existing.input_type != device.input_type {
         if existing.input_type == "gb28181" {
             if let Some(bridge) = crate::gb::bridge() {
                 bridge.unregister_mapping(&device.id).await;
             }
+            crate::gb::device::forget_password(&device.id);
         }
         // Leaving onvif must drop the registry entry (PTZ / stream re-resolve
         // read from it), mirroring the gb28181 mapping cleanup above. The
@@ -148,32 +543,129 @@ async fn update_device(
             manager::remove_pipe(&device.id).await?;
         }
     }
+    // A new stream name needs a new ZLM Media: drop the pipe publishing under
+    // the old one before bringing it up again.
+    let stream_key_changed = existing.stream_name() != device.stream_name();
+    if stream_key_changed {
+        log::info!(
+            "device {}: stream key {} -> {}, restarting its pipe",
+            device.id,
+            existing.stream_name(),
+            device.stream_name()
+        );
+        manager::remove_pipe(&device.id).await?;
+    }
     ensure_device_pipe(&device).await?;
-    Ok(ok_json(device))
+    Ok(ok_json(DeviceUpdate {
+        flv_url: device_flv_url(&device),
+        device,
+        stream_key_changed,
+    })
+    .into_response())
 }
 
+#[utoipa::path(
+    post,
+    path = "/remove/{id}",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses(
+        (status = 200, body = BaseResponse<String>),
+        (status = 404, description = "No such device", body = BaseResponse<()>),
+    )
+)]
 async fn remove_device(Path(id): Path<String>) -> ApiJsonResult<String> {
     let conn = app_db_conn()?;
-    nvr_db::device::delete(&id, &conn).await?;
+    // A retry of a removal that failed partway finds no row, but still has
+    // the rest to clean up; it answers 404 once that is done.
+    let missing = match nvr_db::device::delete(&id, &conn).await {
+        Ok(()) => None,
+        Err(e)
+            if matches!(
+                e.downcast_ref(),
+                Some(nvr_db::device::DeviceError::NotFound(_))
+            ) =>
+        {
+            Some(e)
+        }
+        Err(e) => return Err(e.into()),
+    };
     manager::remove_pipe(&id).await?;
+    crate::stream_key::forget(&id);
     if let Some(bridge) = crate::gb::bridge() {
         bridge.unregister_mapping(&id).await;
     }
+    crate::gb::device::forget_password(&id);
     // Idempotent no-op for non-onvif devices; drops the onvif registry entry
     // otherwise so PTZ / re-resolve don't keep a stale config for a gone device.
     crate::onvif::remove(&id);
+    crate::privacy::forget(&id).await?;
+    nvr_db::bookmark::delete_by_device(&id, &conn).await?;
+    crate::thumbnail::remove(&id).await;
+    if let Some(missing) = missing {
+        return Err(missing.into());
+    }
     Ok(ok_json("success".to_string()))
 }
 
-fn validate_device(device: &DeviceInfo) -> anyhow::Result<()> {
+#[utoipa::path(
+    get,
+    path = "/privacy/{id}",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses((status = 200, body = BaseResponse<PrivacyStatus>))
+)]
+async fn get_privacy(Path(id): Path<String>) -> ApiJsonResult<PrivacyStatus> {
+    let config = crate::privacy::load_all()
+        .await?
+        .remove(&id)
+        .unwrap_or_default();
+    Ok(ok_json(PrivacyStatus {
+        config,
+        active: crate::privacy::is_private(&id),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/privacy/{id}",
+    tag = "device",
+    params(("id" = String, Path)),
+    request_body = PrivacyPayload,
+    responses(
+        (status = 200, body = BaseResponse<PrivacyStatus>),
+        (status = 404, description = "No such device", body = BaseResponse<()>),
+    )
+)]
+async fn set_privacy(
+    Path(id): Path<String>,
+    Json(payload): Json<PrivacyPayload>,
+) -> ApiJsonResult<PrivacyStatus> {
+    let conn = app_db_conn()?;
+    nvr_db::device::get(&id, &conn)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("device {id} not found")))?;
+    let config = crate::privacy::set(&id, payload.enabled, payload.until, payload.windows).await?;
+    Ok(ok_json(PrivacyStatus {
+        config,
+        active: crate::privacy::is_private(&id),
+    }))
+}
+
+/// 400 for a device without a name, with an unknown zone or with detection
+/// settings that do not check out.
+fn validate_device(device: &DeviceInfo) -> ApiResult<()> {
     if device.name.is_empty() {
-        return Err(anyhow::anyhow!("device name is required"));
+        return Err(ApiError::bad_request("device name is required"));
     }
-    if device.input_type.is_empty() {
-        return Err(anyhow::anyhow!("input type is required"));
-    }
-    if device.input_value.is_empty() {
-        return Err(anyhow::anyhow!("input value is required"));
+    crate::tz::validate(&device.timezone).map_err(|e| ApiError::bad_request(format!("{e:#}")))?;
+    if let Some(detection) = &device.detection {
+        crate::detect::analytics::validate(detection)
+            .map_err(|e| ApiError::bad_request(format!("{e:#}")))?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+#[path = "device_test.rs"]
+mod device_test;
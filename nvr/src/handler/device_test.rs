@@ -0,0 +1,211 @@
+use axum::{
+    body::Body,
+    http::{Request as HttpRequest, StatusCode},
+    middleware,
+};
+use serde_json::json;
+use tower::ServiceExt;
+
+use super::*;
+
+fn app() -> Router {
+    Router::new()
+        .nest("/device", device_router())
+        .layer(middleware::from_fn(crate::auth::require_auth))
+}
+
+/// Status and body of a POST of `body` to `uri`, as admin.
+async fn post(uri: &str, body: serde_json::Value) -> (StatusCode, String) {
+    let token = crate::auth::create_session("admin").await.unwrap();
+    let body = body.to_string();
+    let request = HttpRequest::post(uri)
+        .header("Authorization", format!("Bearer {token}"))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let res = app().oneshot(request).await.unwrap();
+    let status = res.status();
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The `message` of a JSON error `body`.
+fn message(body: &str) -> String {
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    body["message"].as_str().unwrap().to_string()
+}
+
+async fn stored(id: &str) -> Option<DeviceInfo> {
+    nvr_db::device::get(id, &app_db_conn().unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn invalid_inputs_are_refused_with_the_type_and_field() {
+    let _db = crate::db::test_db().await;
+    let id = "device-input-invalid-cam";
+
+    let (status, body) = post(
+        "/device/add",
+        json!({
+            "id": id,
+            "name": "bad gb cam",
+            "input_type": "gb28181",
+            "input_value": "{\"device_id\":\"34020000001320000001\"}",
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let refused = message(&body);
+    assert!(refused.starts_with("invalid gb28181 input"), "{refused}");
+    assert!(refused.contains("channel_id"), "{refused}");
+
+    let (status, body) = post(
+        "/device/add",
+        json!({
+            "id": id,
+            "name": "typo cam",
+            "input": {"input_type": "rstp", "url": "rtsp://10.0.0.9/main"},
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(message(&body).contains("unknown variant `rstp`"), "{body}");
+
+    let (status, body) = post("/device/add", json!({"id": id, "name": "no input cam"})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        message(&body),
+        "invalid device input: input_type is required"
+    );
+
+    assert!(stored(id).await.is_none());
+}
+
+#[tokio::test]
+async fn a_typed_input_is_stored_as_type_and_value() {
+    let _db = crate::db::test_db().await;
+    let id = "device-input-typed-cam";
+
+    // gb28181 devices only register a mapping (no pipe), so this runs
+    // without ZLM.
+    let (status, body) = post(
+        "/device/add",
+        json!({
+            "id": id,
+            "name": "typed gb cam",
+            "input": {
+                "input_type": "gb28181",
+                "device_id": "34020000001320000001",
+                "channel_id": "34020000001310000001",
+            },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let device = stored(id).await.unwrap();
+    assert_eq!(device.input_type, "gb28181");
+    match DeviceInput::of(&device).unwrap() {
+        DeviceInput::Gb28181(gb) => assert_eq!(gb.channel_id, "34020000001310000001"),
+        other => panic!("unexpected input {other:?}"),
+    }
+
+    // An update with a bad transport leaves the device as it was.
+    let (status, body) = post(
+        &format!("/device/update/{id}"),
+        json!({
+            "name": "typed gb cam",
+            "input": {
+                "input_type": "gb28181",
+                "device_id": "34020000001320000001",
+                "channel_id": "34020000001310000001",
+                "transport": "carrier_pigeon",
+            },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(message(&body).contains("carrier_pigeon"), "{body}");
+    assert_eq!(stored(id).await.unwrap().input_value, device.input_value);
+
+    let (status, _) = post(&format!("/device/remove/{id}"), json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unknown_and_duplicate_devices_are_404_and_409() {
+    let _db = crate::db::test_db().await;
+    let gb = json!({
+        "name": "device-status-duplicate-cam",
+        "input_type": "gb28181",
+        "input_value": json!({
+            "device_id": "34020000001320000002",
+            "channel_id": "34020000001310000002",
+        })
+        .to_string(),
+    });
+
+    // Without an id the name picks it, so a second device of the name clashes.
+    let (status, body) = post("/device/add", gb.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let (status, body) = post("/device/add", gb.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["code"], 409);
+    assert_eq!(body["message"], format!("device {id} exists already"));
+    let (status, _) = post(&format!("/device/remove/{id}"), json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    for uri in [
+        "/device/update/device-status-no-such-cam",
+        "/device/remove/device-status-no-such-cam",
+    ] {
+        let (status, body) = post(uri, gb.clone()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["message"],
+            "device device-status-no-such-cam not found"
+        );
+    }
+    let privacy = json!({"enabled": true});
+    let (status, body) = post("/device/privacy/device-status-no-such-cam", privacy).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(message(&body), "device device-status-no-such-cam not found");
+}
+
+#[tokio::test]
+async fn removing_a_removed_device_still_cleans_up_after_it() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let id = "device-remove-retried-cam";
+    // Left behind by a removal that deleted the row and failed after.
+    let bookmark = nvr_db::bookmark::Bookmark {
+        id: format!("{id}-bookmark"),
+        device_id: id.to_string(),
+        ts: 1_000,
+        label: "door".to_string(),
+        color: String::new(),
+        created_by: "admin".to_string(),
+        created_at: 1_000,
+        export_id: String::new(),
+    };
+    nvr_db::bookmark::insert(&bookmark, &conn).await.unwrap();
+
+    let (status, body) = post(&format!("/device/remove/{id}"), json!({})).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(message(&body), format!("device {id} not found"));
+    assert!(
+        nvr_db::bookmark::get(&bookmark.id, &conn)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
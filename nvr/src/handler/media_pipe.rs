@@ -1,17 +1,29 @@
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::sync::{Arc, LazyLock, Mutex};
 
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
+    body::Body,
     extract::Path,
+    http::header,
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
+use ffmpeg_bus::bus::{FrameEvent, OutputAvType, VideoRawFrameStream};
+use ffmpeg_bus::stats::BusStats;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    handler::{ApiJsonResult, ok_json},
+    auth::AuthUser,
+    handler::{ApiError, ApiJsonResult, ApiResult, BaseResponse, ok_json},
     manager,
+    viewers::Registry,
+};
+use media_pipe_core::{
+    EncodeConfig, HlsLadder, HlsRendition, InputConfig, OutputConfig, OutputDest, Pipe, PipeConfig,
 };
-use media_pipe_core::{EncodeConfig, InputConfig, OutputConfig, OutputDest, PipeConfig};
 
 pub fn media_pipe_router() -> Router {
     Router::new()
@@ -40,6 +52,8 @@ struct OutputRequest {
     net: Option<NetConfigRequest>,
     t: Option<String>,
     zlm: Option<ZlmConfigRequest>,
+    /// Required when `t` is `hls_adaptive`.
+    hls: Option<HlsAdaptiveRequest>,
     /// Optional encode config for faster encoding: preset ("ultrafast", "superfast", "fast"), bitrate (bps).
     encode: Option<EncodeRequest>,
 }
@@ -52,6 +66,27 @@ struct EncodeRequest {
     bitrate: Option<u64>,
 }
 
+/// Adaptive HLS ladder: `<dir>/master.m3u8` plus one variant per rendition
+/// under `<dir>/<name>/`. Renditions larger than the source are skipped.
+#[derive(Serialize, Deserialize)]
+struct HlsAdaptiveRequest {
+    dir: String,
+    /// Target segment length, shared by all renditions (default 2).
+    segment_seconds: Option<u32>,
+    /// Segments kept per variant playlist; 0 / absent = keep all.
+    list_size: Option<u32>,
+    renditions: Vec<RenditionRequest>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RenditionRequest {
+    name: String,
+    width: u32,
+    height: u32,
+    /// Video bitrate in bps (also the advertised BANDWIDTH).
+    bitrate: u64,
+}
+
 #[derive(Serialize, Deserialize)]
 struct ZlmConfigRequest {
     app: String,
@@ -87,7 +122,32 @@ async fn add_pipe(Json(config): Json<PipeRequest>) -> ApiJsonResult<String> {
                     ));
                     media_pipe_zlm::zlm_video_dest(media)
                 } else {
-                    return Err(anyhow::anyhow!("zlm config is required").into());
+                    return Err(ApiError::bad_request("zlm config is required"));
+                }
+            }
+            "hls_adaptive" => {
+                let Some(hls) = output.hls else {
+                    return Err(ApiError::bad_request("hls config is required"));
+                };
+                if hls.renditions.is_empty() {
+                    return Err(ApiError::bad_request("hls renditions are required"));
+                }
+                OutputDest::HlsAdaptive {
+                    ladder: HlsLadder {
+                        dir: hls.dir,
+                        segment_seconds: hls.segment_seconds.unwrap_or(2).max(1),
+                        list_size: hls.list_size.unwrap_or(0),
+                        renditions: hls
+                            .renditions
+                            .into_iter()
+                            .map(|r| HlsRendition {
+                                name: r.name,
+                                width: r.width,
+                                height: r.height,
+                                bitrate: r.bitrate,
+                            })
+                            .collect(),
+                    },
                 }
             }
             _ => {
@@ -97,7 +157,7 @@ async fn add_pipe(Json(config): Json<PipeRequest>) -> ApiJsonResult<String> {
                         format: net.format,
                     }
                 } else {
-                    return Err(anyhow::anyhow!("net config is required").into());
+                    return Err(ApiError::bad_request("net config is required"));
                 }
             }
         };
@@ -106,25 +166,36 @@ async fn add_pipe(Json(config): Json<PipeRequest>) -> ApiJsonResult<String> {
             bitrate: e.bitrate,
             ..EncodeConfig::default()
         });
+        if let Some(encode) = &encode {
+            // Every issue at once, rather than the first when the encoder opens.
+            encode.validate().map_err(|issues| {
+                ApiError::bad_request(ffmpeg_bus::encoder::InvalidEncodeConfig(issues).to_string())
+            })?;
+        }
         outputs.push(OutputConfig::new(dest, encode));
     }
 
     if outputs.is_empty() {
-        return Err(anyhow::anyhow!("outputs is required").into());
+        return Err(ApiError::bad_request("outputs is required"));
     }
 
     let input = match config.input.t.as_ref() {
         "net" => InputConfig::Network {
             url: config.input.i,
+            net: Default::default(),
         },
         "file" => InputConfig::File {
             path: config.input.i,
         },
+        "file_loop" => InputConfig::FileLoop {
+            path: config.input.i,
+            realtime: true,
+        },
         "v4l2" | "x11grab" | "lavfi" => InputConfig::Device {
             display: config.input.i,
             format: config.input.t.clone(),
         },
-        _ => return Err(anyhow::anyhow!("input type is not supported").into()),
+        _ => return Err(ApiError::bad_request("input type is not supported")),
     };
 
     let pipe_config = PipeConfig {
@@ -143,6 +214,273 @@ async fn remove_pipe(Path(id): Path<String>) -> ApiJsonResult<String> {
 async fn get_pipe_status(Path(id): Path<String>) -> ApiJsonResult<String> {
     match manager::status(&id).await {
         Some(started) => Ok(ok_json(started.to_string())),
-        None => Ok(ok_json("not found".to_string())),
+        None => Err(ApiError::not_found(format!("no pipe for {id}"))),
+    }
+}
+
+/// Bus outputs of the [`live_mp4`] responses being streamed.
+static LIVE_OUTPUTS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Mutex::default);
+
+/// A viewer's output on a device's bus, removed once its response body is
+/// dropped (the stream ended or the client went away), so a transcode made
+/// for it stops with it.
+struct LiveOutput {
+    pipe: Arc<Pipe>,
+    id: String,
+}
+
+impl LiveOutput {
+    async fn add(pipe: Arc<Pipe>) -> anyhow::Result<(Self, VideoRawFrameStream)> {
+        let id = format!("live-mp4-{}", uuid::Uuid::new_v4().simple());
+        let output = ffmpeg_bus::bus::OutputConfig::new(
+            id.clone(),
+            OutputAvType::Video,
+            ffmpeg_bus::bus::OutputDest::Mux {
+                format: "mp4".to_string(),
+            },
+        );
+        let (_, stream) = pipe.add_output(output).await?;
+        LIVE_OUTPUTS.lock().unwrap().insert(id.clone());
+        let output = Self { pipe, id };
+        Ok((output, stream.into_video()?))
     }
 }
+
+impl Drop for LiveOutput {
+    fn drop(&mut self) {
+        let pipe = self.pipe.clone();
+        let id = std::mem::take(&mut self.id);
+        tokio::spawn(async move {
+            if let Err(e) = pipe.remove_output(&id).await {
+                log::debug!("live.mp4: remove output {id}: {e:#}");
+            }
+            LIVE_OUTPUTS.lock().unwrap().remove(&id);
+        });
+    }
+}
+
+/// The device's live video as fragmented MP4, for a `<video>` element.
+#[utoipa::path(
+    get,
+    path = "/{id}/live.mp4",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses(
+        (
+            status = 200,
+            description = "MP4 fragments until the client disconnects or the device stops",
+            body = [u8],
+            content_type = "video/mp4",
+        ),
+        (status = 404, description = "No such device pipe", body = BaseResponse<()>),
+        (status = 409, description = "The device pipe is not started", body = BaseResponse<()>),
+        (status = 503, description = "Over a viewer limit"),
+    )
+)]
+pub(crate) async fn live_mp4(
+    Path(id): Path<String>,
+    user: Option<Extension<AuthUser>>,
+) -> ApiResult<Response> {
+    if crate::privacy::is_private(&id) {
+        return Ok(crate::snapshot::privacy_response());
+    }
+    // Held by the body, so an on-demand pipe runs while it is watched.
+    let Some(handle) = manager::acquire_pipe(&id).await else {
+        return Err(ApiError::not_found(format!("no pipe for {id}")));
+    };
+    if !handle.pipe().is_started() {
+        return Err(ApiError::conflict(format!("pipe {id} is not started")));
+    }
+    let user = user.map(|Extension(user)| user.username);
+    let viewer = match Registry::global().admit(&id, user.as_deref()) {
+        Ok(viewer) => viewer,
+        Err(rejection) => {
+            log::info!("live.mp4[{id}]: refused: {rejection:?}");
+            return Ok(rejection.into_response());
+        }
+    };
+    let (output, stream) = LiveOutput::add(handle.pipe().clone()).await?;
+    let meter = crate::usage::egress_meter(&id);
+    // Hyper drops the body when the client goes away, and with it the
+    // output, the viewer session and the pipe handle.
+    let body = futures::stream::unfold(
+        (stream, output, viewer, meter, handle),
+        |(mut stream, output, viewer, meter, handle)| async move {
+            let frame = stream.next().await.and_then(FrameEvent::into_frame)?;
+            meter.add(frame.data.len() as u64);
+            Some((
+                Ok::<_, Infallible>(frame.data),
+                (stream, output, viewer, meter, handle),
+            ))
+        },
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "video/mp4"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// Packet counters of a device pipe's bus, see `Bus::stats`.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub(crate) struct DeviceStats {
+    inputs: Vec<InputStatsDto>,
+    outputs: Vec<OutputStatsDto>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct InputStatsDto {
+    id: String,
+    packets: u64,
+    bytes: u64,
+    corrupt_packets: u64,
+    lagged_packets: u64,
+    reconnects: u64,
+    /// When the last packet was read, unix milliseconds.
+    last_packet_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct OutputStatsDto {
+    id: String,
+    packets_written: u64,
+    bytes_written: u64,
+    /// Over the last 5 seconds.
+    fps: f64,
+    /// Packets lost by falling behind.
+    lagged: u64,
+}
+
+impl From<BusStats> for DeviceStats {
+    fn from(stats: BusStats) -> Self {
+        let inputs = stats
+            .inputs
+            .into_iter()
+            .map(|(id, input)| InputStatsDto {
+                id,
+                packets: input.packets,
+                bytes: input.bytes,
+                corrupt_packets: input.corrupt_packets,
+                lagged_packets: input.lagged_packets,
+                reconnects: input.reconnects,
+                last_packet_ms: input.last_packet_us.map(|us| us / 1000),
+            })
+            .collect();
+        let outputs = stats
+            .outputs
+            .into_iter()
+            .map(|output| OutputStatsDto {
+                id: output.id,
+                packets_written: output.packets_written,
+                bytes_written: output.bytes_written,
+                fps: output.fps,
+                lagged: output.lagged,
+            })
+            .collect();
+        Self { inputs, outputs }
+    }
+}
+
+/// `GET /api/device/{id}/stats`: what the device's inputs read and its
+/// outputs wrote.
+#[utoipa::path(
+    get,
+    path = "/{id}/stats",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses(
+        (status = 200, body = BaseResponse<DeviceStats>),
+        (status = 404, description = "No such device pipe", body = BaseResponse<()>),
+        (status = 409, description = "The device pipe is not started", body = BaseResponse<()>),
+    )
+)]
+pub(crate) async fn device_stats(Path(id): Path<String>) -> ApiResult<Response> {
+    let Some(pipe) = manager::get_pipe(&id).await else {
+        return Err(ApiError::not_found(format!("no pipe for {id}")));
+    };
+    let Some(stats) = pipe.stats().await else {
+        return Err(ApiError::conflict(format!("pipe {id} is not started")));
+    };
+    Ok(ok_json(DeviceStats::from(stats)).into_response())
+}
+
+/// What `POST /api/device/{id}/playback` does to a device's input.
+#[derive(Debug, Clone, Copy, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PlaybackAction {
+    /// Stop reading, keeping the input open.
+    Pause,
+    Resume,
+    /// Play a file input at `rate`.
+    Rate,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct PlaybackRequest {
+    action: PlaybackAction,
+    /// For `rate`: times the file's own pace (2.0 is twice as fast), 1/16 to
+    /// 16.
+    rate: Option<f32>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub(crate) struct PlaybackStatus {
+    paused: bool,
+    /// `None` while the input is read at its own pace.
+    rate: Option<f32>,
+}
+
+/// `POST /api/device/{id}/playback`: pause or resume a device's input, or
+/// set the rate its file plays at.
+#[utoipa::path(
+    post,
+    path = "/{id}/playback",
+    tag = "device",
+    params(("id" = String, Path)),
+    request_body = PlaybackRequest,
+    responses(
+        (status = 200, body = BaseResponse<PlaybackStatus>),
+        (
+            status = 400,
+            description = "No rate, or a rate for a live input",
+            body = BaseResponse<()>,
+        ),
+        (status = 404, description = "No such device pipe", body = BaseResponse<()>),
+        (status = 409, description = "The device pipe is not started", body = BaseResponse<()>),
+    )
+)]
+pub(crate) async fn playback(
+    Path(id): Path<String>,
+    Json(request): Json<PlaybackRequest>,
+) -> ApiResult<Response> {
+    let Some(pipe) = manager::get_pipe(&id).await else {
+        return Err(ApiError::not_found(format!("no pipe for {id}")));
+    };
+    if !pipe.is_running() {
+        return Err(ApiError::conflict(format!("pipe {id} is not started")));
+    }
+    let state = match (request.action, request.rate) {
+        (PlaybackAction::Pause, _) => pipe.pause().await,
+        (PlaybackAction::Resume, _) => pipe.resume().await,
+        (PlaybackAction::Rate, Some(rate)) => pipe.set_playback_rate(rate).await,
+        (PlaybackAction::Rate, None) => return Err(ApiError::bad_request("rate is required")),
+    };
+    match state {
+        Ok(state) => {
+            log::info!("playback[{id}]: {:?} -> {state:?}", request.action);
+            Ok(ok_json(PlaybackStatus {
+                paused: state.paused,
+                rate: state.rate,
+            })
+            .into_response())
+        }
+        Err(e) => Err(ApiError::bad_request(format!("{e:#}"))),
+    }
+}
+
+#[cfg(test)]
+#[path = "media_pipe_test.rs"]
+mod media_pipe_test;
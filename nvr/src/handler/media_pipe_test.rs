@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+
+use super::*;
+
+fn live_outputs() -> usize {
+    LIVE_OUTPUTS.lock().unwrap().len()
+}
+
+#[tokio::test]
+async fn live_mp4_of_an_unknown_device_is_not_found() {
+    let response = live_mp4(Path("live-mp4-no-such-cam".to_string()), None)
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn live_mp4_streams_fragments_until_the_client_goes() {
+    // Not while the maintenance test has the server closed to viewers.
+    let _db = crate::db::test_db().await;
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let id = "live-mp4-test-cam";
+    manager::add_pipe(
+        id,
+        PipeConfig {
+            input: InputConfig::FileLoop {
+                path: path.to_string_lossy().into_owned(),
+                realtime: true,
+            },
+            outputs: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    manager::wait_running(id).await;
+    let response = live_mp4(Path(id.to_string()), None).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "video/mp4");
+    assert_eq!(live_outputs(), 1);
+
+    let mut body = response.into_body().into_data_stream();
+    let mut buf = Vec::new();
+    let has = |buf: &[u8], tag: &[u8]| buf.windows(4).any(|w| w == tag);
+    let read = async {
+        while !has(&buf, b"moof") {
+            let chunk = body.next().await.expect("stream ended").unwrap();
+            buf.extend_from_slice(&chunk);
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), read)
+        .await
+        .expect("no fragment");
+    assert_eq!(&buf[4..8], b"ftyp", "not an MP4");
+    assert!(has(&buf, b"moov"));
+
+    // The client going away removes its output from the bus.
+    drop(body);
+    let mut removed = false;
+    for _ in 0..50 {
+        if live_outputs() == 0 {
+            removed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    manager::remove_pipe(id).await.unwrap();
+    assert!(removed, "output still on the bus without a client");
+}
+
+#[tokio::test]
+async fn stats_of_an_unknown_device_are_not_found() {
+    let response = device_stats(Path("stats-no-such-cam".to_string()))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn playback_of_an_unknown_device_is_not_found() {
+    let request = PlaybackRequest {
+        action: PlaybackAction::Pause,
+        rate: None,
+    };
+    let response = playback(Path("playback-no-such-cam".to_string()), Json(request))
+        .await
+        .into_response();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
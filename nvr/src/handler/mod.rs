@@ -5,16 +5,20 @@ use axum::{
 use reqwest::StatusCode;
 use serde::Serialize;
 
+pub mod bookmark;
 pub mod device;
 pub mod media_pipe;
 pub mod playback;
+pub mod probe;
+pub mod recording;
 pub mod system;
 pub mod user;
 
 pub type ApiResult<T> = Result<T, ApiError>;
 pub type ApiJsonResult<T> = ApiResult<Json<BaseResponse<T>>>;
 
-#[derive(Debug, Serialize)]
+/// Envelope of every JSON API response; `code` is 0 on success.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
 pub struct BaseResponse<T> {
     pub code: i32,
     pub message: String,
@@ -40,16 +44,88 @@ pub fn ok_empty() -> Json<BaseResponse<()>> {
     })
 }
 
-pub struct ApiError(anyhow::Error);
+/// A failed API call, answered with its status and a [`BaseResponse`] whose
+/// `code` is that status and `message` the reason.
+#[derive(Debug)]
+pub enum ApiError {
+    /// What the call names does not exist: 404.
+    NotFound(String),
+    /// It clashes with what is there already: 409.
+    Conflict(String),
+    /// The request itself is wrong: 400.
+    BadRequest(String),
+    /// The caller is not signed in, or not who they claim to be: 401.
+    Unauthorized(String),
+    /// The caller is signed in but their role does not allow the call: 403.
+    Forbidden(String),
+    /// A camera, server or other remote the call reaches out to refused: 502.
+    BadGateway(String),
+    /// The server takes no such calls right now (e.g. before setup): 503.
+    Unavailable(String),
+    /// Anything else: 500.
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::Conflict(message.into())
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::BadRequest(message.into())
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::Unauthorized(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::Forbidden(message.into())
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::Unavailable(message.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        log::error!("ApiError: {:?}", self.0);
+        let status = self.status();
+        let message = match self {
+            Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::BadGateway(message)
+            | Self::Unavailable(message) => message,
+            Self::Internal(e) => {
+                log::error!("ApiError: {:?}", e);
+                e.to_string()
+            }
+        };
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            status,
             Json(BaseResponse::<()> {
-                code: 500,
-                message: self.0.to_string(),
+                code: i32::from(status.as_u16()),
+                message,
                 data: None,
             }),
         )
@@ -57,11 +133,37 @@ impl IntoResponse for ApiError {
     }
 }
 
+/// Internal, unless the error is one the database layer refused a device
+/// change with (see [`nvr_db::device::DeviceError`]) or a bus refused an
+/// output with (see [`ffmpeg_bus::bus::AddOutputError`]).
 impl<E> From<E> for ApiError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        use ffmpeg_bus::bus::AddOutputError;
+
+        let err = err.into();
+        if let Some(refused) = err.downcast_ref::<AddOutputError>() {
+            return match refused {
+                AddOutputError::StreamNotFound(_) => Self::NotFound(refused.to_string()),
+                AddOutputError::AlreadyExists(_) => Self::Conflict(refused.to_string()),
+                AddOutputError::MuxOpen { .. } => Self::BadGateway(refused.to_string()),
+                AddOutputError::EncoderInit(_) => Self::Internal(err),
+            };
+        }
+        match err.downcast_ref::<nvr_db::device::DeviceError>() {
+            Some(refused @ nvr_db::device::DeviceError::NotFound(_)) => {
+                Self::NotFound(refused.to_string())
+            }
+            Some(refused @ nvr_db::device::DeviceError::Exists(_)) => {
+                Self::Conflict(refused.to_string())
+            }
+            None => Self::Internal(err),
+        }
     }
 }
+
+#[cfg(test)]
+#[path = "mod_test.rs"]
+mod mod_test;
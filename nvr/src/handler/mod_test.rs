@@ -0,0 +1,113 @@
+use serde_json::Value;
+
+use super::*;
+
+/// Status and JSON body `error` answers with.
+async fn answer(error: ApiError) -> (StatusCode, Value) {
+    let response = error.into_response();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn each_error_answers_with_its_status_as_code() {
+    let cases = [
+        (
+            ApiError::not_found("no pipe for cam1"),
+            404,
+            "no pipe for cam1",
+        ),
+        (
+            ApiError::conflict("pipe cam1 is not started"),
+            409,
+            "pipe cam1 is not started",
+        ),
+        (
+            ApiError::bad_request("rate is required"),
+            400,
+            "rate is required",
+        ),
+        (ApiError::unauthorized("unauthorized"), 401, "unauthorized"),
+        (
+            ApiError::forbidden("audit log is admin-only"),
+            403,
+            "audit log is admin-only",
+        ),
+        (
+            ApiError::unavailable("setup required"),
+            503,
+            "setup required",
+        ),
+        (
+            anyhow::anyhow!("ffmpeg failed").into(),
+            500,
+            "ffmpeg failed",
+        ),
+    ];
+    for (error, code, message) in cases {
+        let (status, body) = answer(error).await;
+        assert_eq!(status.as_u16(), code);
+        assert_eq!(body["code"], code);
+        assert_eq!(body["message"], message);
+        assert_eq!(body["data"], Value::Null);
+    }
+}
+
+#[tokio::test]
+async fn refused_device_changes_keep_their_status() {
+    use nvr_db::device::DeviceError;
+
+    let missing = anyhow::Error::from(DeviceError::NotFound("cam1".to_string()));
+    let (status, body) = answer(missing.into()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "device cam1 not found");
+
+    // Through context added on the way up as well.
+    let taken = anyhow::Error::from(DeviceError::Exists("cam1".to_string())).context("add cam1");
+    assert_eq!(ApiError::from(taken).status(), StatusCode::CONFLICT);
+
+    let io = std::io::Error::other("disk full");
+    assert_eq!(
+        ApiError::from(io).status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[tokio::test]
+async fn refused_outputs_keep_their_status() {
+    use ffmpeg_bus::bus::{AddOutputError, OutputAvType};
+
+    let cases = [
+        (
+            AddOutputError::StreamNotFound(OutputAvType::Audio),
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            AddOutputError::AlreadyExists("record".to_string()),
+            StatusCode::CONFLICT,
+        ),
+        (
+            AddOutputError::MuxOpen {
+                url: "rtmp://relay/live".to_string(),
+                cause: "Connection refused".to_string(),
+            },
+            StatusCode::BAD_GATEWAY,
+        ),
+        (
+            AddOutputError::EncoderInit("no h264 encoder".to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    ];
+    for (refused, status) in cases {
+        let message = refused.to_string();
+        let error = anyhow::Error::from(refused).context("add the output");
+        let (answered, body) = answer(error.into()).await;
+        assert_eq!(answered, status, "{message}");
+        if status != StatusCode::INTERNAL_SERVER_ERROR {
+            assert_eq!(body["message"], message);
+        }
+    }
+}
@@ -10,7 +10,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     db::app_db_conn,
-    handler::{ApiJsonResult, ApiResult, ok_json},
+    handler::{ApiError, ApiJsonResult, ApiResult, ok_json},
 };
 
 async fn segment_file_exists(path: &str) -> bool {
@@ -45,6 +45,7 @@ pub fn playback_router() -> Router {
             post(delete_device_segments),
         )
         .route("/device/{device_id}/today", get(list_today_device_segments))
+        .route("/device/{device_id}/timeline", get(device_timeline))
         .route("/playlist/{device_id}", get(playback_playlist))
         .route("/segment-playlist/{id}", get(segment_playlist))
         .route("/segments/delete", post(delete_segments))
@@ -71,6 +72,9 @@ struct PlaybackSegmentItem {
     audio_bit_rate: i64,
     create_time: String,
     update_time: String,
+    /// Its copy on each enabled transport target taking it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    uploads: Vec<crate::transport::SegmentUpload>,
 }
 
 #[derive(Debug, Serialize)]
@@ -157,10 +161,7 @@ async fn list_device_segments(
     )
     .await;
     Ok(ok_json(PlaybackSegmentsResponse {
-        items: records
-            .into_iter()
-            .map(playback_segment_item_from_record)
-            .collect(),
+        items: playback_segment_items(records, &conn).await?,
         page,
         page_size,
         total,
@@ -171,40 +172,209 @@ async fn list_today_device_segments(
     Path(device_id): Path<String>,
 ) -> ApiJsonResult<Vec<PlaybackSegmentItem>> {
     let conn = app_db_conn()?;
-    let now = chrono::Local::now();
-    let day_start = now
-        .date_naive()
-        .and_hms_opt(0, 0, 0)
-        .ok_or_else(|| anyhow::anyhow!("invalid local day start"))?
-        .and_local_timezone(chrono::Local)
-        .single()
-        .ok_or_else(|| anyhow::anyhow!("invalid local timezone conversion"))?
-        .timestamp() as u64;
-    let day_end = day_start + 24 * 60 * 60;
+    let tz = crate::tz::of_device(&device_id).await?;
+    let (day_start, day_end) = crate::tz::day_bounds(crate::tz::today(tz), tz);
+    let (day_start, day_end) = ((day_start / 1000) as u64, (day_end / 1000) as u64);
     let records = filter_existing_records(
         nvr_db::record_segment::list_by_stream_time_range(&device_id, day_start, day_end, &conn)
             .await?,
     )
     .await;
-    Ok(ok_json(
-        records
-            .into_iter()
-            .map(playback_segment_item_from_record)
-            .collect(),
-    ))
+    Ok(ok_json(playback_segment_items(records, &conn).await?))
+}
+
+/// How far before a window a segment overlapping it may start; longer than
+/// any recorded segment.
+const SEGMENT_LOOKBACK_SECS: u64 = 60 * 60;
+
+/// Gaps up to this long between consecutive segments still count as
+/// continuous recording on the timeline.
+const COVERAGE_GAP_MS: i64 = 1000;
+
+/// Record segments of `device_id` overlapping `start..end` (unix
+/// milliseconds), in time order.
+pub(crate) async fn segments_overlapping(
+    device_id: &str,
+    start: i64,
+    end: i64,
+    conn: &turso::Connection,
+) -> anyhow::Result<Vec<nvr_db::record_segment::RecordSegment>> {
+    let from = (start.max(0) / 1000) as u64;
+    let to = (end.max(0) / 1000) as u64 + 1;
+    let segments = nvr_db::record_segment::list_by_stream_time_range(
+        device_id,
+        from.saturating_sub(SEGMENT_LOOKBACK_SECS),
+        to,
+        conn,
+    )
+    .await?;
+    Ok(segments
+        .into_iter()
+        .filter(|s| {
+            let (seg_start, seg_end) = segment_span(s);
+            seg_end > start && seg_start < end
+        })
+        .collect())
+}
+
+/// A segment's wall clock span, unix milliseconds.
+pub(crate) fn segment_span(segment: &nvr_db::record_segment::RecordSegment) -> (i64, i64) {
+    let start = segment.start_time as i64 * 1000;
+    (start, start + (segment.duration as f64 * 1000.0) as i64)
+}
+
+/// A stretch of continuous recording, unix milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub(crate) struct CoverageSpan {
+    pub start: i64,
+    pub end: i64,
+}
+
+/// Merge time-ordered segments into continuous spans.
+pub(crate) fn coverage_spans(
+    segments: &[nvr_db::record_segment::RecordSegment],
+) -> Vec<CoverageSpan> {
+    let mut spans: Vec<CoverageSpan> = Vec::new();
+    for segment in segments {
+        let (start, end) = segment_span(segment);
+        match spans.last_mut() {
+            Some(last) if start <= last.end + COVERAGE_GAP_MS => last.end = last.end.max(end),
+            _ => spans.push(CoverageSpan { start, end }),
+        }
+    }
+    spans
+}
+
+/// The stretches of `start..end` that none of the time-ordered `spans`
+/// cover.
+pub(crate) fn coverage_gaps(spans: &[CoverageSpan], start: i64, end: i64) -> Vec<CoverageSpan> {
+    let mut gaps = Vec::new();
+    let mut from = start;
+    for span in spans {
+        if span.start > from {
+            gaps.push(CoverageSpan {
+                start: from,
+                end: span.start.min(end),
+            });
+        }
+        from = from.max(span.end);
+        if from >= end {
+            return gaps;
+        }
+    }
+    if from < end {
+        gaps.push(CoverageSpan { start: from, end });
+    }
+    gaps
+}
+
+/// Whether `ts` (unix milliseconds) falls inside one of `spans`.
+pub(crate) fn is_covered(spans: &[CoverageSpan], ts: i64) -> bool {
+    spans.iter().any(|s| s.start <= ts && ts < s.end)
+}
+
+#[derive(Debug, Deserialize)]
+struct TimelineQuery {
+    /// Unix milliseconds; defaults to the 24 hours before `end`.
+    start: Option<i64>,
+    /// Unix milliseconds; defaults to now.
+    end: Option<i64>,
+    /// A local day (`YYYY-MM-DD`) in the device's zone, in place of `start`
+    /// and `end`.
+    day: Option<chrono::NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TimelineResponse {
+    pub device_id: String,
+    pub start: i64,
+    pub end: i64,
+    /// The device's zone, for showing the (UTC) times on its wall clock.
+    pub tz: String,
+    /// Recorded stretches overlapping the window.
+    pub spans: Vec<CoverageSpan>,
+    /// Bookmarks inside the window, oldest first.
+    pub bookmarks: Vec<crate::handler::bookmark::BookmarkItem>,
+}
+
+/// Recording coverage and bookmarks of `device_id` between `start` and `end`
+/// (unix milliseconds, inclusive for bookmarks).
+pub(crate) async fn timeline(
+    device_id: &str,
+    start: i64,
+    end: i64,
+) -> anyhow::Result<TimelineResponse> {
+    let conn = app_db_conn()?;
+    let spans = coverage_spans(&segments_overlapping(device_id, start, end, &conn).await?);
+    let bookmarks = nvr_db::bookmark::list_by_device(device_id, start, end, &conn)
+        .await?
+        .into_iter()
+        .map(|bookmark| crate::handler::bookmark::BookmarkItem::new(bookmark, &spans))
+        .collect();
+    Ok(TimelineResponse {
+        device_id: device_id.to_string(),
+        start,
+        end,
+        tz: crate::tz::of_device(device_id).await?.name().to_string(),
+        spans,
+        bookmarks,
+    })
+}
+
+/// [`timeline`] of local `day` in the device's zone: 23 or 25 hours on a
+/// DST change.
+pub(crate) async fn day_timeline(
+    device_id: &str,
+    day: chrono::NaiveDate,
+) -> anyhow::Result<TimelineResponse> {
+    let tz = crate::tz::of_device(device_id).await?;
+    let (start, end) = crate::tz::day_bounds(day, tz);
+    timeline(device_id, start, end).await
+}
+
+async fn device_timeline(
+    Path(device_id): Path<String>,
+    Query(query): Query<TimelineQuery>,
+) -> ApiJsonResult<TimelineResponse> {
+    if let Some(day) = query.day {
+        return Ok(ok_json(day_timeline(&device_id, day).await?));
+    }
+    let end = query
+        .end
+        .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    let start = query.start.unwrap_or(end - 24 * 60 * 60 * 1000);
+    if start > end {
+        return Err(ApiError::bad_request(
+            "timeline start must not be after its end",
+        ));
+    }
+    Ok(ok_json(timeline(&device_id, start, end).await?))
 }
 
 async fn play_segment(headers: HeaderMap, Path(id): Path<String>) -> ApiResult<Response> {
     let conn = app_db_conn()?;
     let segment = nvr_db::record_segment::get(&id, &conn)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("record segment not found"))?;
-    let content_len = match tokio::fs::metadata(&segment.file_path).await {
-        Ok(meta) => meta.len() as usize,
+        .ok_or_else(|| ApiError::not_found("record segment not found"))?;
+    serve_segment(&headers, &segment, "inline").await
+}
+
+/// The file of `segment`, whole or the part a `Range` header in `headers`
+/// asks for, with `disposition` (`inline` or `attachment`) as its
+/// Content-Disposition.
+pub(crate) async fn serve_segment(
+    headers: &HeaderMap,
+    segment: &nvr_db::record_segment::RecordSegment,
+    disposition: &str,
+) -> ApiResult<Response> {
+    // Sealed segments are served as their plaintext (see `crate::encryption`).
+    let content_len = match crate::encryption::media_len(&segment.file_path).await {
+        Ok(len) => len as usize,
         Err(_) => {
-            return Err(
-                anyhow::anyhow!("record segment file not found: {}", segment.file_path).into(),
-            );
+            return Err(ApiError::not_found(format!(
+                "record segment file not found: {}",
+                segment.file_path
+            )));
         }
     };
     let range = headers
@@ -240,22 +410,26 @@ async fn play_segment(headers: HeaderMap, Path(id): Path<String>) -> ApiResult<R
             len,
         )
     } else {
-        let content = tokio::fs::read(&segment.file_path).await?;
+        let content = read_file_range(&segment.file_path, 0, content_len).await?;
         let len = content.len();
         (StatusCode::OK, content, None, len)
     };
 
     let mut response = Response::new(Body::from(body));
     *response.status_mut() = status;
-    response
-        .headers_mut()
-        .insert(header::CONTENT_TYPE, HeaderValue::from_static("video/mp2t"));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        segment_content_type(&segment.file_name),
+    );
     response
         .headers_mut()
         .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
     response.headers_mut().insert(
         header::CONTENT_DISPOSITION,
-        HeaderValue::from_str(&format!("inline; filename=\"{}\"", segment.file_name))?,
+        HeaderValue::from_str(&format!(
+            "{disposition}; filename=\"{}\"",
+            segment.file_name
+        ))?,
     );
     response.headers_mut().insert(
         header::CONTENT_LENGTH,
@@ -274,6 +448,16 @@ async fn play_segment(headers: HeaderMap, Path(id): Path<String>) -> ApiResult<R
     Ok(response)
 }
 
+/// The media type of a recorded file named `file_name`; TS unless its
+/// extension says MP4.
+fn segment_content_type(file_name: &str) -> HeaderValue {
+    if file_name.ends_with(".mp4") {
+        HeaderValue::from_static("video/mp4")
+    } else {
+        HeaderValue::from_static("video/mp2t")
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct DeleteSegmentsResult {
     deleted: usize,
@@ -294,6 +478,7 @@ async fn remove_segment_file(path: &str) {
         Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
         Err(err) => log::warn!("Failed to delete segment file {}: {:#}", path, err),
     }
+    crate::storyboard::remove_cache(path).await;
 }
 
 async fn delete_segment(Path(id): Path<String>) -> ApiJsonResult<DeleteSegmentsResult> {
@@ -340,10 +525,16 @@ async fn delete_device_segments(
 /// the file into memory.
 async fn read_file_range(path: &str, start: u64, len: usize) -> anyhow::Result<Vec<u8>> {
     use tokio::io::{AsyncReadExt, AsyncSeekExt};
-    let mut file = tokio::fs::File::open(path).await?;
-    file.seek(std::io::SeekFrom::Start(start)).await?;
     let mut buf = vec![0u8; len];
-    file.read_exact(&mut buf).await?;
+    if crate::encryption::is_encrypted(path) {
+        let mut file = crate::encryption::EncryptedFileReader::open(path).await?;
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+        file.read_exact(&mut buf).await?;
+    } else {
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+        file.read_exact(&mut buf).await?;
+    }
     Ok(buf)
 }
 
@@ -412,9 +603,12 @@ async fn segment_playlist(
     let conn = app_db_conn()?;
     let segment = nvr_db::record_segment::get(&id, &conn)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("record segment not found"))?;
+        .ok_or_else(|| ApiError::not_found("record segment not found"))?;
     if !segment_file_exists(&segment.file_path).await {
-        return Err(anyhow::anyhow!("record segment file not found: {}", segment.file_path).into());
+        return Err(ApiError::not_found(format!(
+            "record segment file not found: {}",
+            segment.file_path
+        )));
     }
 
     // A single-entry VOD playlist: one #EXTINF spanning the whole .ts file, no
@@ -455,18 +649,10 @@ async fn playback_playlist(
     let conn = app_db_conn()?;
     let device = nvr_db::device::get(&device_id, &conn)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("device not found"))?;
+        .ok_or_else(|| ApiError::not_found(format!("device {device_id} not found")))?;
     let all_records = nvr_db::record_segment::list(&conn).await?;
-    let now = chrono::Local::now();
-    let day_start = now
-        .date_naive()
-        .and_hms_opt(0, 0, 0)
-        .ok_or_else(|| anyhow::anyhow!("invalid local day start"))?
-        .and_local_timezone(chrono::Local)
-        .single()
-        .ok_or_else(|| anyhow::anyhow!("invalid local timezone conversion"))?
-        .timestamp_millis();
-    let day_end = day_start + 24 * 60 * 60 * 1000;
+    let tz = crate::tz::of(&device);
+    let (day_start, day_end) = crate::tz::day_bounds(crate::tz::today(tz), tz);
 
     let mut segments = all_records
         .into_iter()
@@ -555,5 +741,28 @@ fn playback_segment_item_from_record(
         audio_bit_rate: record.audio_bit_rate,
         create_time: record.create_time.to_rfc3339(),
         update_time: record.update_time.to_rfc3339(),
+        uploads: Vec::new(),
     }
 }
+
+/// The listing items of `records`, with their upload status.
+async fn playback_segment_items(
+    records: Vec<nvr_db::record_segment::RecordSegment>,
+    conn: &turso::Connection,
+) -> anyhow::Result<Vec<PlaybackSegmentItem>> {
+    let mut uploads = crate::transport::upload_states(&records, conn).await?;
+    Ok(records
+        .into_iter()
+        .map(|record| {
+            let uploads = uploads.remove(&record.id).unwrap_or_default();
+            PlaybackSegmentItem {
+                uploads,
+                ..playback_segment_item_from_record(record)
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+#[path = "playback_test.rs"]
+mod playback_test;
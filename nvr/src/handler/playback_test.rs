@@ -0,0 +1,196 @@
+use chrono::Utc;
+use nvr_db::bookmark::Bookmark;
+use nvr_db::record_segment::RecordSegment;
+
+use super::*;
+
+fn segment(stream: &str, start_time: u64, duration: f32) -> RecordSegment {
+    RecordSegment {
+        id: format!("{stream}-{start_time}"),
+        record_type: 0,
+        start_time,
+        duration,
+        file_size: 0,
+        file_name: format!("{start_time}.ts"),
+        file_path: format!("/records/{stream}/{start_time}.ts"),
+        folder: String::new(),
+        app: "live".to_string(),
+        stream: stream.to_string(),
+        vhost: String::new(),
+        video_codec: "h264".to_string(),
+        video_width: 0,
+        video_height: 0,
+        video_fps: 0.0,
+        video_bit_rate: 0,
+        audio_codec: String::new(),
+        audio_sample_rate: 0,
+        audio_channels: 0,
+        audio_bit_rate: 0,
+        reserve_text1: String::new(),
+        reserve_text2: String::new(),
+        reserve_text3: String::new(),
+        reserve_int1: 0,
+        reserve_int2: 0,
+        create_time: Utc::now(),
+        update_time: Utc::now(),
+        status: String::new(),
+    }
+}
+
+fn bookmark(id: &str, device_id: &str, ts: i64) -> Bookmark {
+    Bookmark {
+        id: id.to_string(),
+        device_id: device_id.to_string(),
+        ts,
+        label: id.to_string(),
+        ..Bookmark::default()
+    }
+}
+
+#[test]
+fn coverage_merges_contiguous_segments() {
+    let spans = coverage_spans(&[
+        segment("cam", 100, 10.0),
+        // Starts half a second after the previous one ends: still continuous.
+        segment("cam", 110, 9.5),
+        segment("cam", 200, 10.0),
+    ]);
+    assert_eq!(
+        spans,
+        vec![
+            CoverageSpan {
+                start: 100_000,
+                end: 119_500
+            },
+            CoverageSpan {
+                start: 200_000,
+                end: 210_000
+            },
+        ]
+    );
+    assert!(is_covered(&spans, 100_000));
+    assert!(is_covered(&spans, 119_499));
+    assert!(!is_covered(&spans, 119_500));
+    assert!(!is_covered(&spans, 150_000));
+}
+
+#[test]
+fn gaps_are_what_the_spans_leave_of_the_window() {
+    let span = |start, end| CoverageSpan { start, end };
+    let spans = [span(100, 200), span(300, 400)];
+    assert_eq!(
+        coverage_gaps(&spans, 0, 500),
+        vec![span(0, 100), span(200, 300), span(400, 500)]
+    );
+    // Spans reaching past the window leave no gap at its edges.
+    assert_eq!(coverage_gaps(&spans, 150, 350), vec![span(200, 300)]);
+    assert!(coverage_gaps(&spans, 120, 180).is_empty());
+    assert_eq!(coverage_gaps(&[], 0, 50), vec![span(0, 50)]);
+}
+
+#[tokio::test]
+async fn timeline_includes_bookmarks_flagged_by_coverage() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let device = "timeline-test-cam";
+    nvr_db::record_segment::upsert(&segment(device, 1_000, 60.0), &conn)
+        .await
+        .unwrap();
+    for (id, ts) in [
+        ("tl-covered", 1_030_000),
+        ("tl-gap", 1_100_000),
+        ("tl-outside", 5_000_000),
+    ] {
+        nvr_db::bookmark::insert(&bookmark(id, device, ts), &conn)
+            .await
+            .unwrap();
+    }
+
+    let timeline = timeline(device, 1_000_000, 2_000_000).await.unwrap();
+    assert_eq!(
+        timeline.spans,
+        vec![CoverageSpan {
+            start: 1_000_000,
+            end: 1_060_000
+        }]
+    );
+    let marks = timeline
+        .bookmarks
+        .iter()
+        .map(|b| (b.bookmark.id.as_str(), b.uncovered))
+        .collect::<Vec<_>>();
+    assert_eq!(marks, vec![("tl-covered", false), ("tl-gap", true)]);
+
+    let json = serde_json::to_value(&timeline).unwrap();
+    assert_eq!(json["bookmarks"][1]["label"], "tl-gap");
+    assert_eq!(json["bookmarks"][1]["uncovered"], true);
+
+    nvr_db::bookmark::delete_by_device(device, &conn)
+        .await
+        .unwrap();
+    nvr_db::record_segment::delete_by_stream(device, &conn)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn day_timeline_uses_the_device_zone() {
+    use chrono::{NaiveDate, TimeZone};
+
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let device = nvr_db::device::DeviceInfo {
+        id: "tz-timeline-cam".to_string(),
+        name: "Porch".to_string(),
+        input_type: "rtsp".to_string(),
+        input_value: "rtsp://cam".to_string(),
+        description: String::new(),
+        include_audio: false,
+        record: true,
+        stream_key: String::new(),
+        timezone: "America/New_York".to_string(),
+        detection: None,
+        group: String::new(),
+        encrypt: false,
+        status: String::new(),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+    nvr_db::device::upsert(&device, &conn).await.unwrap();
+    // 23:55 to 00:05 UTC, across the UTC midnight: 19:55 to 20:05 EDT, all
+    // on the 14th in New York.
+    let start = Utc.with_ymd_and_hms(2026, 10, 14, 23, 55, 0).unwrap();
+    nvr_db::record_segment::upsert(&segment(&device.id, start.timestamp() as u64, 600.0), &conn)
+        .await
+        .unwrap();
+
+    let day = |d| NaiveDate::from_ymd_opt(2026, 10, d).unwrap();
+    let on_14th = day_timeline(&device.id, day(14)).await.unwrap();
+    assert_eq!(on_14th.tz, "America/New_York");
+    // The local day starts at 04:00 UTC.
+    assert_eq!(
+        on_14th.start,
+        Utc.with_ymd_and_hms(2026, 10, 14, 4, 0, 0)
+            .unwrap()
+            .timestamp_millis()
+    );
+    assert_eq!(
+        on_14th.spans,
+        vec![CoverageSpan {
+            start: start.timestamp_millis(),
+            end: start.timestamp_millis() + 600_000
+        }]
+    );
+    assert!(
+        day_timeline(&device.id, day(15))
+            .await
+            .unwrap()
+            .spans
+            .is_empty()
+    );
+
+    nvr_db::record_segment::delete_by_stream(&device.id, &conn)
+        .await
+        .unwrap();
+    nvr_db::device::delete(&device.id, &conn).await.unwrap();
+}
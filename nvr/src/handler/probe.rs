@@ -0,0 +1,79 @@
+//! `POST /api/v1/probe`: what a camera URL serves, so the dashboard can check
+//! it before creating a device with it.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::{
+    Extension, Json, Router,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use ffmpeg_bus::metadata::{ProbeTimedOut, probe_with_options};
+use serde::Deserialize;
+
+use crate::auth::{self, AuthUser};
+use crate::handler::{ApiError, ApiResult, ok_json};
+
+/// How long a probe waits for the input to open and show its streams.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn probe_router() -> Router {
+    Router::new().route("/", post(probe))
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbePayload {
+    url: String,
+    /// Demuxer options, checked like a device's extra input options.
+    #[serde(default)]
+    options: HashMap<String, String>,
+}
+
+/// The `MediaInfo` of `url`. 400 for a refused option or a URL that is not
+/// a network one, 502 when the input does not open and 504 when it does not
+/// answer within [`PROBE_TIMEOUT`].
+async fn probe(
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<ProbePayload>,
+) -> ApiResult<Response> {
+    if auth::is_viewer(&user.username).await? {
+        return Err(ApiError::forbidden("viewers cannot probe inputs"));
+    }
+    run(payload, PROBE_TIMEOUT).await
+}
+
+async fn run(payload: ProbePayload, timeout: Duration) -> ApiResult<Response> {
+    let url = payload.url.trim();
+    // Files and FFmpeg's other local protocols are not for the API to read.
+    let network = url
+        .split_once("://")
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && !scheme.eq_ignore_ascii_case("file"));
+    if !network {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "url is not a network url".to_string(),
+        )
+            .into_response());
+    }
+    let dirs = crate::config::config().input_file_dirs();
+    if let Err(rejected) = crate::input_options::check(&payload.options, dirs) {
+        return Ok((StatusCode::BAD_REQUEST, rejected.to_string()).into_response());
+    }
+    let options = (!payload.options.is_empty()).then_some(payload.options);
+    match probe_with_options(url, options, timeout).await {
+        Ok(info) => Ok(ok_json(info).into_response()),
+        Err(e) if e.is::<ProbeTimedOut>() => {
+            Ok((StatusCode::GATEWAY_TIMEOUT, e.to_string()).into_response())
+        }
+        Err(e) => {
+            log::info!("probe failed: {e:#}");
+            Ok((StatusCode::BAD_GATEWAY, format!("cannot open input: {e}")).into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "probe_test.rs"]
+mod probe_test;
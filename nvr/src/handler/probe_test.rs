@@ -0,0 +1,54 @@
+use super::*;
+
+fn payload(url: &str, options: &[(&str, &str)]) -> ProbePayload {
+    ProbePayload {
+        url: url.to_string(),
+        options: options
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+async fn status(payload: ProbePayload, timeout: Duration) -> StatusCode {
+    run(payload, timeout).await.into_response().status()
+}
+
+#[tokio::test]
+async fn local_files_and_refused_options_are_bad_requests() {
+    let timeout = Duration::from_secs(1);
+    for url in [
+        "",
+        "/etc/passwd",
+        "file:///etc/passwd",
+        "concat:a.mp4|b.mp4",
+    ] {
+        assert_eq!(
+            status(payload(url, &[]), timeout).await,
+            StatusCode::BAD_REQUEST,
+            "{url}"
+        );
+    }
+    let refused = payload("rtsp://10.0.0.9/main", &[("protocol_whitelist", "file")]);
+    assert_eq!(status(refused, timeout).await, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn an_unresponsive_camera_fails_within_the_timeout() {
+    ffmpeg_bus::init().unwrap();
+    // Takes the connection and never answers.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("rtsp://{}/main", listener.local_addr().unwrap());
+    let started = std::time::Instant::now();
+    let got = status(payload(&url, &[]), Duration::from_millis(500)).await;
+    assert!(
+        started.elapsed() < Duration::from_secs(3),
+        "{:?}",
+        started.elapsed()
+    );
+    // FFmpeg's own socket timeout may fire first.
+    assert!(
+        matches!(got, StatusCode::GATEWAY_TIMEOUT | StatusCode::BAD_GATEWAY),
+        "{got}"
+    );
+}
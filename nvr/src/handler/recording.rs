@@ -0,0 +1,343 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{Path, Query},
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use ffmpeg_bus::bus::{Bus, FrameEvent, InputConfig, OutputAvType, OutputConfig, OutputDest};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::db::app_db_conn;
+use crate::handler::playback::CoverageSpan;
+use crate::handler::{ApiError, ApiJsonResult, ApiResult, BaseResponse, ok_json};
+
+const DEFAULT_QUALITY: u8 = 80;
+const DEFAULT_THUMB_WIDTH: u32 = 320;
+
+pub fn recording_router() -> Router {
+    Router::new()
+        .route("/{file}/poster", get(poster))
+        .route("/{file}/thumb", get(thumb))
+        .route("/{file}/play", get(play))
+        .route("/{file}/download", get(download))
+        .route("/{file}/storyboard", get(crate::storyboard::storyboard))
+        .route("/{file}/storyboard/{sheet}", get(crate::storyboard::sheet))
+}
+
+/// The schema of [`recording_router`], mounted at `/api/v1/recordings`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(
+    poster,
+    thumb,
+    play,
+    download,
+    crate::storyboard::storyboard,
+    crate::storyboard::sheet
+))]
+pub(crate) struct RecordingApi;
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PosterQuery {
+    /// Seconds from the start of the recording; past its end gives the last
+    /// frame.
+    #[serde(default)]
+    at: f64,
+    /// JPEG quality, 1..=100.
+    quality: Option<u8>,
+}
+
+/// A JPEG of the frame shown `at` seconds into the recorded segment `file`
+/// (its id).
+#[utoipa::path(
+    get,
+    path = "/{file}/poster",
+    tag = "recordings",
+    params(("file" = String, Path, description = "Record segment id"), PosterQuery),
+    responses(
+        (status = 200, body = [u8], content_type = "image/jpeg"),
+        (status = 400, description = "`at` is negative or not a number", body = BaseResponse<()>),
+        (status = 404, description = "No such recording", body = BaseResponse<()>),
+    )
+)]
+async fn poster(Path(file): Path<String>, Query(query): Query<PosterQuery>) -> ApiResult<Response> {
+    if !query.at.is_finite() || query.at < 0.0 {
+        return Err(ApiError::bad_request("`at` must be a non-negative number"));
+    }
+    let conn = app_db_conn()?;
+    let Some(segment) = nvr_db::record_segment::get(&file, &conn)
+        .await?
+        .filter(|s| s.status != nvr_db::record_segment::STATUS_MISSING)
+    else {
+        return Err(ApiError::not_found(format!("no recording {file}")));
+    };
+    let at = Duration::from_secs_f64(query.at);
+    let quality = query.quality.unwrap_or(DEFAULT_QUALITY);
+    let path = segment.file_path;
+    let jpeg = tokio::task::spawn_blocking(move || {
+        // A sealed recording is decoded from a temporary plaintext copy.
+        let plain = crate::encryption::Plaintext::of(std::path::Path::new(&path))?;
+        ffmpeg_bus::snapshot::jpeg_at(&plain.path().to_string_lossy(), at, quality)
+    })
+    .await??;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            // A closed recording does not change.
+            (header::CACHE_CONTROL, "private, max-age=86400"),
+        ],
+        jpeg,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ThumbQuery {
+    /// Seconds from the start of the recording; past its end gives the last
+    /// frame.
+    #[serde(default)]
+    at: f64,
+    /// Largest width in pixels, 320 by default; narrower recordings keep
+    /// their width.
+    w: Option<u32>,
+}
+
+/// A thumbnail JPEG of the frame shown `at` seconds into the recorded segment
+/// `file` (its id), for the recordings timeline.
+#[utoipa::path(
+    get,
+    path = "/{file}/thumb",
+    tag = "recordings",
+    params(("file" = String, Path, description = "Record segment id"), ThumbQuery),
+    responses(
+        (status = 200, body = [u8], content_type = "image/jpeg"),
+        (status = 400, description = "Bad `at` or `w`", body = BaseResponse<()>),
+        (status = 404, description = "No such recording", body = BaseResponse<()>),
+    )
+)]
+async fn thumb(Path(file): Path<String>, Query(query): Query<ThumbQuery>) -> ApiResult<Response> {
+    if !query.at.is_finite() || query.at < 0.0 {
+        return Err(ApiError::bad_request("`at` must be a non-negative number"));
+    }
+    let width = query.w.unwrap_or(DEFAULT_THUMB_WIDTH);
+    if width == 0 {
+        return Err(ApiError::bad_request("`w` must be positive"));
+    }
+    let conn = app_db_conn()?;
+    let Some(segment) = nvr_db::record_segment::get(&file, &conn)
+        .await?
+        .filter(|s| s.status != nvr_db::record_segment::STATUS_MISSING)
+    else {
+        return Err(ApiError::not_found(format!("no recording {file}")));
+    };
+    let at = Duration::from_secs_f64(query.at);
+    let path = segment.file_path;
+    let jpeg = tokio::task::spawn_blocking(move || {
+        let plain = crate::encryption::Plaintext::of(std::path::Path::new(&path))?;
+        ffmpeg_bus::thumbnail::extract(&plain.path().to_string_lossy(), at, width)
+    })
+    .await??;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            (header::CACHE_CONTROL, "private, max-age=86400"),
+        ],
+        jpeg,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PlayQuery {
+    /// Seconds from the start of the recording to play from; its start by
+    /// default.
+    start: Option<f64>,
+    /// Seconds from the start of the recording to play to; its end by
+    /// default.
+    end: Option<f64>,
+}
+
+/// The `start..end` part of `query` as offsets into the recording.
+fn play_range(query: &PlayQuery) -> Result<(Option<Duration>, Option<Duration>), &'static str> {
+    let offset = |seconds: Option<f64>| match seconds {
+        Some(s) if !s.is_finite() || s < 0.0 => {
+            Err("`start` and `end` must be non-negative numbers")
+        }
+        Some(s) => Ok(Some(Duration::from_secs_f64(s))),
+        None => Ok(None),
+    };
+    let (start, end) = (offset(query.start)?, offset(query.end)?);
+    if let (Some(start), Some(end)) = (start, end)
+        && end <= start
+    {
+        return Err("`end` must be after `start`");
+    }
+    Ok((start, end))
+}
+
+/// The video of the recorded segment `file` (its id) from `start` to `end`
+/// seconds into it, as fragmented MP4. It begins on the keyframe at or before
+/// `start`.
+#[utoipa::path(
+    get,
+    path = "/{file}/play",
+    tag = "recordings",
+    params(("file" = String, Path, description = "Record segment id"), PlayQuery),
+    responses(
+        (status = 200, body = [u8], content_type = "video/mp4"),
+        (
+            status = 400,
+            description = "`start` or `end` is not a valid offset",
+            body = BaseResponse<()>,
+        ),
+        (status = 404, description = "No such recording", body = BaseResponse<()>),
+    )
+)]
+async fn play(Path(file): Path<String>, Query(query): Query<PlayQuery>) -> ApiResult<Response> {
+    let (start, end) = play_range(&query).map_err(ApiError::bad_request)?;
+    let conn = app_db_conn()?;
+    let Some(segment) = nvr_db::record_segment::get(&file, &conn)
+        .await?
+        .filter(|s| s.status != nvr_db::record_segment::STATUS_MISSING)
+    else {
+        return Err(ApiError::not_found(format!("no recording {file}")));
+    };
+    let path = segment.file_path;
+    // A sealed recording is played from a temporary plaintext copy, kept
+    // until the response ends.
+    let plain = tokio::task::spawn_blocking(move || {
+        crate::encryption::Plaintext::of(std::path::Path::new(&path))
+    })
+    .await??;
+
+    let bus = Bus::new(&format!("play-{file}"));
+    bus.add_input(
+        InputConfig::FileRange {
+            path: plain.path().to_string_lossy().into_owned(),
+            start,
+            end,
+        },
+        None,
+    )
+    .await?;
+    let output = OutputConfig::new(
+        "play".to_string(),
+        OutputAvType::Video,
+        OutputDest::Mux {
+            format: "mp4".to_string(),
+        },
+    );
+    let (_, stream) = bus.add_output(output).await?;
+    let stream = stream.into_video()?;
+    // Hyper drops the body when the client goes away, and with it the bus.
+    let body = futures::stream::unfold(
+        (stream, bus, plain),
+        |(mut stream, bus, plain)| async move {
+            let frame = stream.next().await.and_then(FrameEvent::into_frame)?;
+            Some((Ok::<_, Infallible>(frame.data), (stream, bus, plain)))
+        },
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "video/mp4"),
+            (header::CACHE_CONTROL, "private, max-age=86400"),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// The file of the recorded segment `file` (its id) as stored, for saving:
+/// `video/mp4` for an `.mp4` file (motion recordings, faststart archives),
+/// `video/mp2t` for the rest. A `Range` header gets just that part, so a
+/// video element can seek in it.
+#[utoipa::path(
+    get,
+    path = "/{file}/download",
+    tag = "recordings",
+    params(("file" = String, Path, description = "Record segment id")),
+    responses(
+        (status = 200, description = "The whole file, MP4 or MPEG-TS as recorded",
+            content(([u8] = "video/mp4"), ([u8] = "video/mp2t"))),
+        (status = 206, description = "The requested byte range",
+            content(([u8] = "video/mp4"), ([u8] = "video/mp2t"))),
+        (status = 404, description = "No such recording", body = BaseResponse<()>),
+        (status = 416, description = "The range is outside the file"),
+    )
+)]
+async fn download(headers: HeaderMap, Path(file): Path<String>) -> ApiResult<Response> {
+    let conn = app_db_conn()?;
+    let segment = nvr_db::record_segment::get(&file, &conn)
+        .await?
+        .filter(|s| s.status != nvr_db::record_segment::STATUS_MISSING)
+        .ok_or_else(|| ApiError::not_found(format!("no recording {file}")))?;
+    crate::handler::playback::serve_segment(&headers, &segment, "attachment").await
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct RecordingsQuery {
+    /// Unix milliseconds; defaults to the 24 hours before `to`.
+    from: Option<i64>,
+    /// Unix milliseconds; defaults to now.
+    to: Option<i64>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub(crate) struct DeviceRecordings {
+    pub device_id: String,
+    pub from: i64,
+    pub to: i64,
+    /// Continuous recording overlapping `from..to`, oldest first.
+    pub ranges: Vec<CoverageSpan>,
+    /// The stretches of `from..to` with nothing recorded.
+    pub gaps: Vec<CoverageSpan>,
+}
+
+/// What device `id` recorded between `from` and `to`, as continuous ranges
+/// and the gaps between them.
+#[utoipa::path(
+    get,
+    path = "/{id}/recordings",
+    tag = "device",
+    params(("id" = String, Path), RecordingsQuery),
+    responses(
+        (status = 200, body = BaseResponse<DeviceRecordings>),
+        (status = 400, description = "`from` is after `to`"),
+    )
+)]
+pub(crate) async fn device_recordings(
+    Path(id): Path<String>,
+    Query(query): Query<RecordingsQuery>,
+) -> ApiJsonResult<DeviceRecordings> {
+    let to = query
+        .to
+        .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    let from = query.from.unwrap_or(to - 24 * 60 * 60 * 1000);
+    if from > to {
+        return Err(ApiError::bad_request("`from` must not be after `to`"));
+    }
+    let conn = app_db_conn()?;
+    let segments = crate::handler::playback::segments_overlapping(&id, from, to, &conn).await?;
+    let ranges = crate::handler::playback::coverage_spans(&segments);
+    let gaps = crate::handler::playback::coverage_gaps(&ranges, from, to);
+    Ok(ok_json(DeviceRecordings {
+        device_id: id,
+        from,
+        to,
+        ranges,
+        gaps,
+    }))
+}
+
+#[cfg(test)]
+#[path = "recording_test.rs"]
+mod recording_test;
@@ -0,0 +1,223 @@
+use axum::http::Request as HttpRequest;
+use chrono::Utc;
+use nvr_db::record_segment::RecordSegment;
+use serde_json::json;
+use tower::ServiceExt;
+
+use super::*;
+
+fn query(start: Option<f64>, end: Option<f64>) -> PlayQuery {
+    PlayQuery { start, end }
+}
+
+#[test]
+fn play_range_takes_offsets_in_seconds() {
+    assert_eq!(play_range(&query(None, None)), Ok((None, None)));
+    assert_eq!(
+        play_range(&query(Some(12.5), Some(30.0))),
+        Ok((
+            Some(Duration::from_millis(12_500)),
+            Some(Duration::from_secs(30))
+        ))
+    );
+    assert_eq!(
+        play_range(&query(None, Some(5.0))),
+        Ok((None, Some(Duration::from_secs(5))))
+    );
+}
+
+#[test]
+fn play_range_rejects_bad_offsets() {
+    for (start, end) in [
+        (Some(-1.0), None),
+        (None, Some(f64::NAN)),
+        (Some(f64::INFINITY), None),
+        (Some(30.0), Some(12.5)),
+        (Some(10.0), Some(10.0)),
+    ] {
+        assert!(
+            play_range(&query(start, end)).is_err(),
+            "{start:?}..{end:?}"
+        );
+    }
+}
+
+/// A recorded segment of `stream` starting at `start_time` (unix seconds),
+/// stored at `file_path`.
+fn segment(stream: &str, start_time: u64, duration: f32, file_path: &str) -> RecordSegment {
+    RecordSegment {
+        id: format!("{stream}-{start_time}"),
+        record_type: 0,
+        start_time,
+        duration,
+        file_size: 0,
+        file_name: format!("{start_time}.ts"),
+        file_path: file_path.to_string(),
+        folder: String::new(),
+        app: "live".to_string(),
+        stream: stream.to_string(),
+        vhost: String::new(),
+        video_codec: "h264".to_string(),
+        video_width: 0,
+        video_height: 0,
+        video_fps: 0.0,
+        video_bit_rate: 0,
+        audio_codec: String::new(),
+        audio_sample_rate: 0,
+        audio_channels: 0,
+        audio_bit_rate: 0,
+        reserve_text1: String::new(),
+        reserve_text2: String::new(),
+        reserve_text3: String::new(),
+        reserve_int1: 0,
+        reserve_int2: 0,
+        create_time: Utc::now(),
+        update_time: Utc::now(),
+        status: String::new(),
+    }
+}
+
+/// Status, headers and body of a GET of `uri` with an optional `range`.
+async fn fetch(app: Router, uri: &str, range: Option<&str>) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let mut request = HttpRequest::get(uri);
+    if let Some(range) = range {
+        request = request.header(header::RANGE, range);
+    }
+    let res = app
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let (status, headers) = (res.status(), res.headers().clone());
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, headers, bytes.to_vec())
+}
+
+#[tokio::test]
+async fn device_recordings_merge_segments_and_report_the_gaps() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let device = "recordings-test-cam";
+    for (start, duration) in [(1_000, 60.0), (1_060, 30.0), (1_200, 60.0)] {
+        nvr_db::record_segment::upsert(&segment(device, start, duration, ""), &conn)
+            .await
+            .unwrap();
+    }
+
+    let app = Router::new().nest("/device", crate::handler::device::device_router());
+    let uri = format!("/device/{device}/recordings?from=900000&to=1300000");
+    let (status, _, body) = fetch(app.clone(), &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let data = &body["data"];
+    assert_eq!(
+        data["ranges"],
+        json!([
+            {"start": 1_000_000, "end": 1_090_000},
+            {"start": 1_200_000, "end": 1_260_000},
+        ])
+    );
+    assert_eq!(
+        data["gaps"],
+        json!([
+            {"start": 900_000, "end": 1_000_000},
+            {"start": 1_090_000, "end": 1_200_000},
+            {"start": 1_260_000, "end": 1_300_000},
+        ])
+    );
+
+    let backwards = format!("/device/{device}/recordings?from=2000&to=1000");
+    let (status, _, _) = fetch(app, &backwards, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    nvr_db::record_segment::delete_by_stream(device, &conn)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn downloads_honor_range_requests() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let path = std::env::temp_dir().join(format!("nvr-download-{}.ts", uuid::Uuid::new_v4()));
+    std::fs::write(&path, b"0123456789").unwrap();
+    let record = segment("download-test-cam", 1_000, 10.0, &path.to_string_lossy());
+    nvr_db::record_segment::upsert(&record, &conn)
+        .await
+        .unwrap();
+    let app = recording_router();
+    let uri = format!("/{}/download", record.id);
+
+    let (status, headers, body) = fetch(app.clone(), &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"0123456789");
+    assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(
+        headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"1000.ts\""
+    );
+
+    let (status, headers, body) = fetch(app.clone(), &uri, Some("bytes=2-5")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, b"2345");
+    assert_eq!(headers[header::CONTENT_RANGE], "bytes 2-5/10");
+    assert_eq!(headers[header::CONTENT_LENGTH], "4");
+
+    let (status, headers, body) = fetch(app.clone(), &uri, Some("bytes=-3")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, b"789");
+    assert_eq!(headers[header::CONTENT_RANGE], "bytes 7-9/10");
+
+    let (status, headers, _) = fetch(app.clone(), &uri, Some("bytes=20-")).await;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(headers[header::CONTENT_RANGE], "bytes */10");
+
+    let (status, _, _) = fetch(app, "/download-test-nothing/download", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    nvr_db::record_segment::delete(&record.id, &conn)
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn thumbs_are_jpegs_of_a_recording() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let path = ffmpeg_bus::fixture::ensure_fixture(&ffmpeg_bus::fixture::FixtureSpec::default())
+        .await
+        .unwrap();
+    let record = segment("thumb-test-cam", 1_000, 5.0, &path.to_string_lossy());
+    nvr_db::record_segment::upsert(&record, &conn)
+        .await
+        .unwrap();
+    let app = recording_router();
+
+    let uri = format!("/{}/thumb?at=1&w=160", record.id);
+    let (status, headers, body) = fetch(app.clone(), &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "image/jpeg");
+    assert!(body.starts_with(&[0xff, 0xd8]), "not a JPEG");
+
+    for (bad, message) in [
+        ("at=-1", "`at` must be a non-negative number"),
+        ("at=1&w=0", "`w` must be positive"),
+    ] {
+        let uri = format!("/{}/thumb?{bad}", record.id);
+        let (status, _, body) = fetch(app.clone(), &uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{bad}");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], 400, "{bad}");
+        assert_eq!(body["message"], message, "{bad}");
+    }
+    let (status, _, body) = fetch(app, "/thumb-test-nothing/thumb", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["message"], "no recording thumb-test-nothing");
+
+    nvr_db::record_segment::delete(&record.id, &conn)
+        .await
+        .unwrap();
+}
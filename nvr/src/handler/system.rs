@@ -18,6 +18,7 @@ pub fn system_router() -> Router {
         .route("/list/x11grab/devices", get(list_x11grab_device))
         .route("/settings", get(get_settings).post(save_settings))
         .route("/cleanup", get(get_cleanup).post(save_cleanup))
+        .route("/clock", get(get_clock).post(save_clock))
 }
 
 /// Persisted dashboard settings (stored as JSON under config key
@@ -81,6 +82,33 @@ async fn save_cleanup(
     Ok(ok_json(cfg))
 }
 
+#[derive(Serialize)]
+struct ClockResponse {
+    config: crate::clock::ClockConfig,
+    /// Latest offset per device id.
+    devices: std::collections::HashMap<String, crate::clock::DeviceClock>,
+    /// Recent threshold crossings, oldest first.
+    events: Vec<crate::clock::SkewEvent>,
+}
+
+/// Camera clock-skew diagnostics: policy, per-device offsets, recent events.
+async fn get_clock() -> ApiJsonResult<ClockResponse> {
+    Ok(ok_json(ClockResponse {
+        config: crate::clock::load_config().await?,
+        devices: crate::clock::device_clocks(),
+        events: crate::clock::recent_events(),
+    }))
+}
+
+/// Save the clock-skew policy (applied on the next measurement).
+async fn save_clock(
+    Json(cfg): Json<crate::clock::ClockConfig>,
+) -> ApiJsonResult<crate::clock::ClockConfig> {
+    let cfg = cfg.sanitized();
+    crate::clock::save_config(&cfg).await?;
+    Ok(ok_json(cfg))
+}
+
 #[derive(Serialize)]
 struct OverviewResponse {
     device_total: usize,
@@ -100,6 +128,10 @@ struct OverviewDevice {
     online: bool,
     record: bool,
     flv_url: String,
+    /// Latest camera clock offset, if measured.
+    clock_offset_ms: Option<i64>,
+    /// Whether that offset exceeds the warning threshold.
+    clock_skewed: bool,
 }
 
 /// System overview: device online/offline counts, recording storage totals, and
@@ -115,6 +147,7 @@ async fn overview() -> ApiJsonResult<OverviewResponse> {
         if is_online {
             online += 1;
         }
+        let clock = crate::clock::device_clock(&d.id);
         items.push(OverviewDevice {
             id: d.id.clone(),
             name: d.name.clone(),
@@ -123,6 +156,8 @@ async fn overview() -> ApiJsonResult<OverviewResponse> {
             online: is_online,
             record: d.record,
             flv_url: build_flv_url(&d.id),
+            clock_offset_ms: clock.as_ref().map(|c| c.offset_ms),
+            clock_skewed: clock.is_some_and(|c| c.skewed),
         });
     }
 
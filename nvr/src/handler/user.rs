@@ -9,7 +9,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     auth::{self, AuthUser},
     db::app_db_conn,
-    handler::{ApiJsonResult, ok_empty, ok_json},
+    handler::{ApiError, ApiJsonResult, ApiResult, BaseResponse, ok_empty, ok_json},
 };
 
 pub fn user_router() -> Router {
@@ -24,36 +24,65 @@ pub fn user_router() -> Router {
         .route("/remove/{username}", post(remove_user))
 }
 
-#[derive(Serialize, Deserialize)]
+/// The schema of [`user_router`], mounted at `/api/v1/user`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(
+    index,
+    login,
+    logout,
+    user_info,
+    change_password,
+    list_users,
+    add_user,
+    remove_user,
+))]
+pub(crate) struct UserApi;
+
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
 struct UserLoginRequest {
     username: String,
     password: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
 struct UserLoginResponse {
     token: String,
     username: String,
 }
 
+#[utoipa::path(get, path = "/", tag = "auth", responses((status = 200, body = String)))]
 async fn index() -> &'static str {
     "user route!"
 }
 
+/// Trade a username and password for a session token, sent back as
+/// `Authorization: Bearer <token>` (or `?token=`) on every other call.
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body = UserLoginRequest,
+    responses(
+        (status = 200, body = BaseResponse<UserLoginResponse>),
+        (status = 401, description = "Wrong username or password", body = BaseResponse<()>),
+    ),
+    security(())
+)]
 async fn login(Json(req): Json<UserLoginRequest>) -> ApiJsonResult<UserLoginResponse> {
     let conn = app_db_conn()?;
 
+    let refused = || ApiError::unauthorized("Invalid username or password");
     let username = req.username.trim();
     if username.is_empty() || req.password.is_empty() {
-        return Err(anyhow::anyhow!("Invalid username or password").into());
+        return Err(refused());
     }
 
     let user = nvr_db::user::get_by_username(username, &conn)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("Invalid username or password"))?;
+        .ok_or_else(refused)?;
 
     if !nvr_db::user::verify_password(&req.password, &user.password_hash) {
-        return Err(anyhow::anyhow!("Invalid username or password").into());
+        return Err(refused());
     }
 
     // Opportunistic GC of expired sessions; failure must not block login.
@@ -66,43 +95,71 @@ async fn login(Json(req): Json<UserLoginRequest>) -> ApiJsonResult<UserLoginResp
     }))
 }
 
+/// Revoke the caller's session token.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "auth",
+    responses((status = 200, body = BaseResponse<()>))
+)]
 async fn logout(Extension(user): Extension<AuthUser>) -> ApiJsonResult<()> {
     auth::revoke(&user.token).await?;
     Ok(ok_empty())
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 struct UserInfoResponse {
     username: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/info",
+    tag = "auth",
+    responses((status = 200, body = BaseResponse<UserInfoResponse>))
+)]
 async fn user_info(Extension(user): Extension<AuthUser>) -> ApiJsonResult<UserInfoResponse> {
     Ok(ok_json(UserInfoResponse {
         username: user.username,
     }))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 struct ChangePasswordRequest {
     old_password: String,
     new_password: String,
 }
 
+/// Change the caller's password, revoking their other sessions.
+#[utoipa::path(
+    post,
+    path = "/password",
+    tag = "auth",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, body = BaseResponse<()>),
+        (
+            status = 400,
+            description = "Empty new password, or a wrong old one",
+            body = BaseResponse<()>,
+        ),
+    )
+)]
 async fn change_password(
     Extension(user): Extension<AuthUser>,
     Json(req): Json<ChangePasswordRequest>,
 ) -> ApiJsonResult<()> {
     if req.new_password.is_empty() {
-        return Err(anyhow::anyhow!("New password must not be empty").into());
+        return Err(ApiError::bad_request("New password must not be empty"));
     }
 
     let conn = app_db_conn()?;
     let mut record = nvr_db::user::get_by_username(&user.username, &conn)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+        .ok_or_else(|| ApiError::not_found("User not found"))?;
 
     if !nvr_db::user::verify_password(&req.old_password, &record.password_hash) {
-        return Err(anyhow::anyhow!("Old password is incorrect").into());
+        return Err(ApiError::bad_request("Old password is incorrect"));
     }
 
     record.password_hash = nvr_db::user::hash_password(&req.new_password)?;
@@ -114,19 +171,27 @@ async fn change_password(
     Ok(ok_empty())
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 struct UserListItem {
     username: String,
+    role: String,
     create_time: DateTime<Utc>,
     update_time: DateTime<Utc>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/list",
+    tag = "auth",
+    responses((status = 200, body = BaseResponse<Vec<UserListItem>>))
+)]
 async fn list_users() -> ApiJsonResult<Vec<UserListItem>> {
     let conn = app_db_conn()?;
     let mut users: Vec<UserListItem> = nvr_db::user::list(&conn)
         .await?
         .into_iter()
         .map(|u| UserListItem {
+            role: u.metadata.get(auth::ROLE_KEY).cloned().unwrap_or_default(),
             username: u.username,
             create_time: u.create_time,
             update_time: u.update_time,
@@ -136,28 +201,63 @@ async fn list_users() -> ApiJsonResult<Vec<UserListItem>> {
     Ok(ok_json(users))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 struct AddUserRequest {
     username: String,
     password: String,
+    /// `viewer` for read-only access; empty for full access.
+    #[serde(default)]
+    role: String,
 }
 
-async fn add_user(Json(req): Json<AddUserRequest>) -> ApiJsonResult<()> {
+/// Create a user (admins only).
+#[utoipa::path(
+    post,
+    path = "/add",
+    tag = "auth",
+    request_body = AddUserRequest,
+    responses(
+        (status = 200, body = BaseResponse<()>),
+        (
+            status = 400,
+            description = "Empty username or password, or an unknown role",
+            body = BaseResponse<()>,
+        ),
+        (status = 403, description = "The caller is not an admin", body = BaseResponse<()>),
+        (status = 409, description = "The username is taken", body = BaseResponse<()>),
+    )
+)]
+async fn add_user(
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<AddUserRequest>,
+) -> ApiJsonResult<()> {
+    require_admin(&user).await?;
     let username = req.username.trim();
     if username.is_empty() || req.password.is_empty() {
-        return Err(anyhow::anyhow!("Username and password must not be empty").into());
+        return Err(ApiError::bad_request(
+            "Username and password must not be empty",
+        ));
     }
 
     let conn = app_db_conn()?;
     if nvr_db::user::exists(username, &conn).await? {
-        return Err(anyhow::anyhow!("User already exists").into());
+        return Err(ApiError::conflict("User already exists"));
+    }
+
+    let mut metadata = std::collections::HashMap::new();
+    match req.role.trim() {
+        "" => {}
+        auth::ROLE_VIEWER => {
+            metadata.insert(auth::ROLE_KEY.to_string(), auth::ROLE_VIEWER.to_string());
+        }
+        other => return Err(ApiError::bad_request(format!("Unknown role {other}"))),
     }
 
     let now = Utc::now();
     let user = nvr_db::user::UserInfo {
         username: username.to_string(),
         password_hash: nvr_db::user::hash_password(&req.password)?,
-        metadata: std::collections::HashMap::new(),
+        metadata,
         create_time: now,
         update_time: now,
     };
@@ -165,20 +265,49 @@ async fn add_user(Json(req): Json<AddUserRequest>) -> ApiJsonResult<()> {
     Ok(ok_empty())
 }
 
+/// Delete a user other than the caller, revoking their sessions (admins
+/// only).
+#[utoipa::path(
+    post,
+    path = "/remove/{username}",
+    tag = "auth",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, body = BaseResponse<()>),
+        (status = 400, description = "The caller's own account", body = BaseResponse<()>),
+        (status = 403, description = "The caller is not an admin", body = BaseResponse<()>),
+        (status = 404, description = "No such user", body = BaseResponse<()>),
+    )
+)]
 async fn remove_user(
     Extension(user): Extension<AuthUser>,
     Path(username): Path<String>,
 ) -> ApiJsonResult<()> {
+    require_admin(&user).await?;
     if username == user.username {
-        return Err(anyhow::anyhow!("Cannot remove the currently logged-in user").into());
+        return Err(ApiError::bad_request(
+            "Cannot remove the currently logged-in user",
+        ));
     }
 
     let conn = app_db_conn()?;
     if !nvr_db::user::exists(&username, &conn).await? {
-        return Err(anyhow::anyhow!("User not found").into());
+        return Err(ApiError::not_found("User not found"));
     }
 
     nvr_db::user::delete(&username, &conn).await?;
     auth::revoke_user(&username, None).await?;
     Ok(ok_empty())
 }
+
+/// Refuse user management to anyone without the admin role.
+async fn require_admin(user: &AuthUser) -> ApiResult<()> {
+    if !auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden("user management is admin-only"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "user_test.rs"]
+mod user_test;
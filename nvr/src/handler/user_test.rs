@@ -0,0 +1,229 @@
+use axum::{
+    body::Body,
+    http::{Request as HttpRequest, StatusCode},
+    middleware,
+};
+use chrono::Duration;
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+use super::*;
+
+fn app() -> Router {
+    Router::new()
+        .nest("/user", user_router())
+        .layer(middleware::from_fn(crate::auth::require_auth))
+}
+
+/// Status and JSON body of `request`.
+async fn call(request: HttpRequest<Body>) -> (StatusCode, Value) {
+    call_on(app(), request).await
+}
+
+/// Status and JSON body of `request` to `app`.
+async fn call_on(app: Router, request: HttpRequest<Body>) -> (StatusCode, Value) {
+    let res = app.oneshot(request).await.unwrap();
+    let status = res.status();
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn login_request(username: &str, password: &str) -> HttpRequest<Body> {
+    HttpRequest::post("/user/login")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({"username": username, "password": password}).to_string(),
+        ))
+        .unwrap()
+}
+
+fn info_request(token: &str) -> HttpRequest<Body> {
+    HttpRequest::get("/user/info")
+        .header("Authorization", format!("Bearer {token}"))
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn insert_user(username: &str, password: &str) {
+    let user = nvr_db::user::UserInfo {
+        username: username.to_string(),
+        password_hash: nvr_db::user::hash_password(password).unwrap(),
+        metadata: Default::default(),
+        create_time: Utc::now(),
+        update_time: Utc::now(),
+    };
+    nvr_db::user::insert(&user, &app_db_conn().unwrap())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn a_wrong_password_is_a_401() {
+    let _db = crate::db::test_db().await;
+    let username = "user-test-wrong-password";
+    insert_user(username, "s3cret").await;
+
+    for (username, password) in [(username, "guess"), ("user-test-nobody", "s3cret")] {
+        let (status, body) = call(login_request(username, password)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], 401);
+        assert_eq!(body["message"], "Invalid username or password");
+    }
+
+    let (status, body) = call(login_request(username, "s3cret")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["username"], username);
+    let token = body["data"]["token"].as_str().unwrap();
+    let (status, body) = call(info_request(token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["username"], username);
+
+    nvr_db::user::delete(username, &app_db_conn().unwrap())
+        .await
+        .unwrap();
+    auth::revoke_user(username, None).await.unwrap();
+}
+
+#[tokio::test]
+async fn an_expired_token_is_refused_on_a_protected_route() {
+    let _db = crate::db::test_db().await;
+    let expired = nvr_db::session::Session {
+        token: "user-test-expired-token".to_string(),
+        username: "admin".to_string(),
+        expires_at: Utc::now() - Duration::minutes(1),
+    };
+    nvr_db::session::insert(&expired, &app_db_conn().unwrap())
+        .await
+        .unwrap();
+
+    let (status, body) = call(info_request(&expired.token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], 401);
+
+    let token = auth::create_session("admin").await.unwrap();
+    let (status, body) = call(info_request(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["username"], "admin");
+    auth::revoke(&token).await.unwrap();
+}
+
+/// A POST of `body` to `uri` with `token`.
+fn post_request(uri: &str, token: &str, body: Value) -> HttpRequest<Body> {
+    HttpRequest::post(uri)
+        .header("Authorization", format!("Bearer {token}"))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn refused_user_changes_answer_with_their_status() {
+    let _db = crate::db::test_db().await;
+    let token = auth::admin_session().await;
+    let username = "user-test-refusals";
+    let add = json!({"username": username, "password": "s3cret"});
+
+    let (status, _) = call(post_request("/user/add", &token, add.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let cases = [
+        (
+            "/user/add",
+            add,
+            StatusCode::CONFLICT,
+            "User already exists",
+        ),
+        (
+            "/user/add",
+            json!({"username": "user-test-x", "password": "x", "role": "god"}),
+            StatusCode::BAD_REQUEST,
+            "Unknown role god",
+        ),
+        (
+            "/user/remove/user-test-nobody",
+            json!({}),
+            StatusCode::NOT_FOUND,
+            "User not found",
+        ),
+    ];
+    for (uri, body, expected, message) in cases {
+        let (status, body) = call(post_request(uri, &token, body)).await;
+        assert_eq!(status, expected, "{uri}");
+        assert_eq!(body["code"], expected.as_u16(), "{uri}");
+        assert_eq!(body["message"], message, "{uri}");
+    }
+
+    let own = auth::create_session(username).await.unwrap();
+    let password = json!({"old_password": "wrong", "new_password": "n3w"});
+    let (status, body) = call(post_request("/user/password", &own, password)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "Old password is incorrect");
+
+    let remove = format!("/user/remove/{username}");
+    let (status, _) = call(post_request(&remove, &token, json!({}))).await;
+    assert_eq!(status, StatusCode::OK);
+    auth::revoke(&token).await.unwrap();
+}
+
+#[tokio::test]
+async fn only_admins_manage_users_whatever_their_name() {
+    let _db = crate::db::test_db().await;
+    // A session named "admin" without the admin role.
+    let token = auth::create_session("admin").await.unwrap();
+    let add = json!({"username": "user-test-taken-over", "password": "s3cret"});
+
+    for (uri, body) in [("/user/add", add), ("/user/remove/test-admin", json!({}))] {
+        let (status, body) = call(post_request(uri, &token, body)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{uri}");
+        assert_eq!(body["message"], "user management is admin-only", "{uri}");
+    }
+    let conn = app_db_conn().unwrap();
+    assert!(
+        !nvr_db::user::exists("user-test-taken-over", &conn)
+            .await
+            .unwrap()
+    );
+    auth::revoke(&token).await.unwrap();
+}
+
+#[tokio::test]
+async fn a_viewer_gets_a_403_for_anything_but_their_own_session_and_password() {
+    let _db = crate::db::test_db().await;
+    let username = "user-test-viewer";
+    insert_user(username, "s3cret").await;
+    let conn = app_db_conn().unwrap();
+    let mut viewer = nvr_db::user::get_by_username(username, &conn)
+        .await
+        .unwrap()
+        .unwrap();
+    viewer
+        .metadata
+        .insert(auth::ROLE_KEY.to_string(), auth::ROLE_VIEWER.to_string());
+    nvr_db::user::update(&viewer, &conn).await.unwrap();
+    let token = auth::create_session(username).await.unwrap();
+    let api = || crate::api::api_router_with(crate::setup::Setup::new(false));
+
+    let add = json!({"username": "user-test-by-viewer", "password": "s3cret"});
+    let (status, body) = call_on(api(), post_request("/user/add", &token, add)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["message"], "viewers have read-only access");
+    assert!(
+        !nvr_db::user::exists("user-test-by-viewer", &conn)
+            .await
+            .unwrap()
+    );
+
+    // Reaches the handler, which refuses the wrong old password.
+    let password = json!({"old_password": "wrong", "new_password": "n3w"});
+    let (status, _) = call_on(api(), post_request("/user/password", &token, password)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call_on(api(), info_request(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    nvr_db::user::delete(username, &conn).await.unwrap();
+    auth::revoke_user(username, None).await.unwrap();
+}
@@ -0,0 +1,571 @@
+//! Stream health scores. A background worker samples the input counters of
+//! every running device pipe (`Pipe::input_stats`) every 10 seconds and keeps
+//! the last 5 minutes of them per device. Over that rolling window it derives a [`StatsSnapshot`] — frame rate against the stream's
+//! nominal rate, corrupt and lagged packets, restarts of the pipe, and how
+//! much the bitrate swings between samples, and whether the GOPs suddenly
+//! shrank (see [`ffmpeg_bus::gop`]) — and scores it 0–100 with
+//! [`score`], a pure function of the snapshot. A picture found frozen or
+//! black (see [`crate::detect::deadair`]) costs points on top.
+//!
+//! The latest score (with the factors that lowered it) is shown in the device
+//! list, and `GET /api/device/{id}/health` adds the recent scores. Dropping
+//! below [`DEGRADED_BELOW`] or [`CRITICAL_BELOW`], or recovering, emits an
+//! event; a device that stays degraded does not repeat it.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::Path;
+use ffmpeg_bus::gop::GopAggregate;
+use ffmpeg_bus::input::InputStats;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::detect::deadair::{self, DeadAir, DeadAirEvent};
+use crate::handler::{ApiJsonResult, ok_json};
+
+/// Time between two samples of a device's counters.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// Samples a score is computed over (5 minutes).
+const WINDOW_SAMPLES: usize = 30;
+const WINDOW: Duration = Duration::from_secs(SAMPLE_INTERVAL.as_secs() * WINDOW_SAMPLES as u64);
+/// Scores kept per device for `GET /api/device/{id}/health` (1 hour).
+const HISTORY_CAP: usize = 360;
+/// Health events kept for diagnostics.
+const EVENT_CAP: usize = 100;
+/// Scores below these are degraded / critical.
+pub const DEGRADED_BELOW: u8 = 80;
+pub const CRITICAL_BELOW: u8 = 50;
+
+/// Most points each factor can take off the score.
+const FPS_WEIGHT: f64 = 50.0;
+const LOSS_WEIGHT: f64 = 25.0;
+const RECONNECT_PENALTY: f64 = 15.0;
+const RECONNECT_WEIGHT: f64 = 60.0;
+const BITRATE_WEIGHT: f64 = 15.0;
+const GOP_WEIGHT: f64 = 25.0;
+/// GOPs the input must have aggregated before a shrinking one counts.
+const GOP_MIN_WINDOW: usize = 5;
+/// The newest GOP's bytes per frame, against the window's: halving is still
+/// normal (the scene calmed down), a fifth costs the full weight.
+const GOP_SHRINK_FREE: f64 = 0.5;
+const GOP_SHRINK_FULL: f64 = 0.2;
+/// A frozen or black picture is as bad as no stream at all, short of not
+/// knowing whether the camera came back.
+const DEAD_AIR_PENALTY: f64 = 60.0;
+/// Packet loss (corrupt + lagged per packet) that costs the full weight.
+const LOSS_FULL: f64 = 0.1;
+/// Bitrate swings (coefficient of variation) below this are normal VBR.
+const BITRATE_CV_FREE: f64 = 0.25;
+/// Swings at or above this cost the full weight.
+const BITRATE_CV_FULL: f64 = 1.0;
+
+/// What a device's stream did over one window; the input of [`score`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsSnapshot {
+    /// Length of the window.
+    pub window_secs: f64,
+    /// The stream's declared frame rate, when known.
+    pub nominal_fps: Option<f64>,
+    /// Video packets (about one per frame) read in the window.
+    pub frames: u64,
+    pub corrupt_packets: u64,
+    pub lagged_packets: u64,
+    /// Times the pipe came back after having stopped.
+    pub reconnects: u32,
+    /// Video bitrate of each sample interval, bits per second.
+    pub bitrates: Vec<f64>,
+    /// Whether the picture is frozen or black right now.
+    pub dead_air: Option<DeadAir>,
+    /// The newest GOP's bytes per frame over the input's recent average;
+    /// `None` until enough GOPs have closed.
+    pub gop_size_ratio: Option<f64>,
+}
+
+impl StatsSnapshot {
+    pub fn fps(&self) -> f64 {
+        if self.window_secs > 0.0 {
+            self.frames as f64 / self.window_secs
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthLevel {
+    Healthy,
+    Degraded,
+    Critical,
+}
+
+impl HealthLevel {
+    pub fn of(score: u8) -> Self {
+        if score < CRITICAL_BELOW {
+            HealthLevel::Critical
+        } else if score < DEGRADED_BELOW {
+            HealthLevel::Degraded
+        } else {
+            HealthLevel::Healthy
+        }
+    }
+}
+
+/// One input of a score: the measured value and the points it cost.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct HealthFactor {
+    /// `fps`, `packet_loss`, `reconnects`, `bitrate_variation`, `gop_size`,
+    /// or `frozen` / `black` (value 1) while the picture is dead air.
+    pub name: &'static str,
+    pub value: f64,
+    pub penalty: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct HealthScore {
+    /// 0 (unusable) to 100.
+    pub score: u8,
+    pub level: HealthLevel,
+    /// Every factor that was measured, including those that cost nothing.
+    pub factors: Vec<HealthFactor>,
+}
+
+/// Score one window of stream stats. Each factor takes up to its weight off
+/// 100: frames short of the nominal rate, lost packets, reconnects (flapping),
+/// bitrate swings beyond normal VBR, a GOP collapsing at a steady frame rate
+/// (the encoder starved of bitrate) and a dead picture. A window without
+/// frames scores 0.
+pub(crate) fn score(s: &StatsSnapshot) -> HealthScore {
+    let mut factors = Vec::new();
+    let mut push = |name, value: f64, penalty: f64| {
+        factors.push(HealthFactor {
+            name,
+            value: (value * 100.0).round() / 100.0,
+            penalty: penalty.round().clamp(0.0, 100.0) as u8,
+        });
+    };
+
+    let fps = s.fps();
+    let fps_penalty = match s.nominal_fps.filter(|n| *n > 0.0) {
+        _ if s.frames == 0 => 100.0,
+        Some(nominal) => (1.0 - fps / nominal).clamp(0.0, 1.0) * FPS_WEIGHT,
+        None => 0.0,
+    };
+    push("fps", fps, fps_penalty);
+
+    let lost = s.corrupt_packets + s.lagged_packets;
+    let loss = lost as f64 / s.frames.max(1) as f64;
+    push(
+        "packet_loss",
+        loss,
+        (loss / LOSS_FULL).min(1.0) * LOSS_WEIGHT,
+    );
+
+    push(
+        "reconnects",
+        s.reconnects as f64,
+        (s.reconnects as f64 * RECONNECT_PENALTY).min(RECONNECT_WEIGHT),
+    );
+
+    if let Some(cv) = coefficient_of_variation(&s.bitrates) {
+        let over = (cv - BITRATE_CV_FREE) / (BITRATE_CV_FULL - BITRATE_CV_FREE);
+        push(
+            "bitrate_variation",
+            cv,
+            over.clamp(0.0, 1.0) * BITRATE_WEIGHT,
+        );
+    }
+
+    // Frames missing already cost through `fps`.
+    if let Some(ratio) = s.gop_size_ratio
+        && fps_penalty == 0.0
+    {
+        let under = (GOP_SHRINK_FREE - ratio) / (GOP_SHRINK_FREE - GOP_SHRINK_FULL);
+        push("gop_size", ratio, under.clamp(0.0, 1.0) * GOP_WEIGHT);
+    }
+
+    match s.dead_air {
+        Some(DeadAir::Frozen) => push("frozen", 1.0, DEAD_AIR_PENALTY),
+        Some(DeadAir::Black) => push("black", 1.0, DEAD_AIR_PENALTY),
+        None => {}
+    }
+
+    let penalty = factors.iter().map(|f| f.penalty as u32).sum::<u32>();
+    let score = 100u32.saturating_sub(penalty) as u8;
+    HealthScore {
+        score,
+        level: HealthLevel::of(score),
+        factors,
+    }
+}
+
+/// The newest GOP's bytes per frame over the average of the window.
+fn gop_size_ratio(gop: &GopAggregate) -> Option<f64> {
+    if gop.gops < GOP_MIN_WINDOW || gop.avg_frames <= 0.0 || gop.last.frames == 0 {
+        return None;
+    }
+    let average = gop.avg_bytes / gop.avg_frames;
+    let last = gop.last.bytes as f64 / gop.last.frames as f64;
+    (average > 0.0).then(|| last / average)
+}
+
+/// Standard deviation over mean; `None` for fewer than two values or a zero
+/// mean.
+fn coefficient_of_variation(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    if mean <= 0.0 {
+        return None;
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    Some(variance.sqrt() / mean)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthEventKind {
+    /// The score fell below [`DEGRADED_BELOW`] (or rose back from critical).
+    Degraded,
+    /// The score fell below [`CRITICAL_BELOW`].
+    Critical,
+    /// The score is back at [`DEGRADED_BELOW`] or above.
+    Recovered,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct HealthEvent {
+    pub device_id: String,
+    pub kind: HealthEventKind,
+    pub score: u8,
+    /// Unix milliseconds.
+    pub ts_ms: i64,
+}
+
+/// The event (if any) a new score triggers for a device that was at `was`:
+/// only level changes fire, so a device that stays degraded warns once.
+pub(crate) fn evaluate(was: HealthLevel, now: HealthLevel) -> Option<HealthEventKind> {
+    if was == now {
+        return None;
+    }
+    Some(match now {
+        HealthLevel::Healthy => HealthEventKind::Recovered,
+        HealthLevel::Degraded => HealthEventKind::Degraded,
+        HealthLevel::Critical => HealthEventKind::Critical,
+    })
+}
+
+/// The latest score of one device.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct DeviceHealth {
+    #[serde(flatten)]
+    pub health: HealthScore,
+    /// Unix milliseconds.
+    pub measured_at_ms: i64,
+    pub window_secs: f64,
+    /// The input's recent GOPs, once some have closed.
+    pub gop: Option<GopWindowStats>,
+}
+
+/// Aggregates of a device's last GOPs (see [`ffmpeg_bus::gop::GopWindow`]).
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct GopWindowStats {
+    pub gops: usize,
+    pub avg_bytes: f64,
+    pub avg_duration_secs: f64,
+    pub avg_frames: f64,
+    pub min_keyframe_bytes: u64,
+    pub max_keyframe_bytes: u64,
+    /// Average slice QP, for H.264 streams whose headers parse.
+    pub avg_qp: Option<f64>,
+}
+
+impl From<GopAggregate> for GopWindowStats {
+    fn from(gop: GopAggregate) -> Self {
+        Self {
+            gops: gop.gops,
+            avg_bytes: gop.avg_bytes.round(),
+            avg_duration_secs: (gop.avg_duration_secs * 100.0).round() / 100.0,
+            avg_frames: (gop.avg_frames * 10.0).round() / 10.0,
+            min_keyframe_bytes: gop.min_keyframe_bytes,
+            max_keyframe_bytes: gop.max_keyframe_bytes,
+            avg_qp: gop.avg_qp.map(|qp| (qp * 10.0).round() / 10.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct HealthSample {
+    /// Unix milliseconds.
+    pub ts_ms: i64,
+    pub score: u8,
+}
+
+/// Rolling state of one device.
+#[derive(Default)]
+struct Tracker {
+    samples: VecDeque<(Instant, InputStats)>,
+    /// When the pipe came back after having stopped, within the window.
+    reconnects: VecDeque<Instant>,
+    /// Whether the pipe was running at the previous sample.
+    running: bool,
+    /// Whether it has ever run, so the first start is not a reconnect.
+    seen: bool,
+    current: Option<DeviceHealth>,
+    history: VecDeque<HealthSample>,
+}
+
+impl Tracker {
+    /// A sample while the pipe is stopped: the counters went with it.
+    fn stopped(&mut self) {
+        self.running = false;
+        self.samples.clear();
+        self.current = None;
+    }
+
+    /// Add a sample of a running pipe.
+    fn sample(&mut self, at: Instant, stats: InputStats) {
+        // A new pipe (counters back at zero) counts as a reconnect too.
+        let restarted = self
+            .samples
+            .back()
+            .is_some_and(|(_, last)| stats.video_packets < last.video_packets);
+        if restarted || (self.seen && !self.running) {
+            self.reconnects.push_back(at);
+            self.samples.clear();
+        }
+        self.running = true;
+        self.seen = true;
+        if self.samples.len() == WINDOW_SAMPLES + 1 {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((at, stats));
+        while self
+            .reconnects
+            .front()
+            .is_some_and(|t| at.duration_since(*t) > WINDOW)
+        {
+            self.reconnects.pop_front();
+        }
+    }
+
+    /// The window so far; `None` until there are two samples.
+    fn snapshot(&self) -> Option<StatsSnapshot> {
+        let (first_at, first) = self.samples.front()?;
+        let (last_at, last) = self.samples.back()?;
+        if self.samples.len() < 2 {
+            return None;
+        }
+        let bitrates = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|((a_at, a), (b_at, b))| {
+                let secs = b_at.duration_since(*a_at).as_secs_f64().max(0.001);
+                b.video_bytes.saturating_sub(a.video_bytes) as f64 * 8.0 / secs
+            })
+            .collect();
+        Some(StatsSnapshot {
+            window_secs: last_at.duration_since(*first_at).as_secs_f64(),
+            nominal_fps: last.nominal_fps,
+            frames: last.video_packets.saturating_sub(first.video_packets),
+            corrupt_packets: last.corrupt_packets.saturating_sub(first.corrupt_packets),
+            lagged_packets: last.lagged_packets.saturating_sub(first.lagged_packets),
+            reconnects: self.reconnects.len() as u32,
+            bitrates,
+            gop_size_ratio: last.gop.and_then(|gop| gop_size_ratio(&gop)),
+            dead_air: None,
+        })
+    }
+}
+
+static TRACKERS: LazyLock<RwLock<HashMap<String, Tracker>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+static EVENTS: LazyLock<Mutex<VecDeque<HealthEvent>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+/// The latest score of `device_id`, if it is running and has been scored.
+pub fn device_health(device_id: &str) -> Option<DeviceHealth> {
+    TRACKERS
+        .read()
+        .unwrap()
+        .get(device_id)
+        .and_then(|t| t.current.clone())
+}
+
+/// Scores of `device_id` over the last hour, oldest first.
+pub fn history(device_id: &str) -> Vec<HealthSample> {
+    TRACKERS
+        .read()
+        .unwrap()
+        .get(device_id)
+        .map(|t| t.history.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+/// Recent health events, oldest first.
+pub fn recent_events() -> Vec<HealthEvent> {
+    EVENTS.lock().unwrap().iter().cloned().collect()
+}
+
+/// Add a sample of `device_id` (`None`: its pipe is not running), rescore it
+/// and emit the event the new score triggers, if any.
+pub(crate) fn record(
+    device_id: &str,
+    stats: Option<InputStats>,
+    at: Instant,
+    now_ms: i64,
+) -> Option<HealthEvent> {
+    let mut trackers = TRACKERS.write().unwrap();
+    let tracker = trackers.entry(device_id.to_string()).or_default();
+    let Some(stats) = stats else {
+        tracker.stopped();
+        return None;
+    };
+    let gop = stats.gop.map(GopWindowStats::from);
+    tracker.sample(at, stats);
+    let mut snapshot = tracker.snapshot()?;
+    snapshot.dead_air = deadair::current(device_id);
+    let health = score(&snapshot);
+    let was = tracker
+        .history
+        .back()
+        .map_or(HealthLevel::Healthy, |s| HealthLevel::of(s.score));
+    if tracker.history.len() == HISTORY_CAP {
+        tracker.history.pop_front();
+    }
+    tracker.history.push_back(HealthSample {
+        ts_ms: now_ms,
+        score: health.score,
+    });
+    let kind = evaluate(was, health.level);
+    let event = kind.map(|kind| HealthEvent {
+        device_id: device_id.to_string(),
+        kind,
+        score: health.score,
+        ts_ms: now_ms,
+    });
+    tracker.current = Some(DeviceHealth {
+        health,
+        measured_at_ms: now_ms,
+        window_secs: snapshot.window_secs,
+        gop,
+    });
+    drop(trackers);
+
+    let event = event?;
+    match event.kind {
+        HealthEventKind::Recovered => log::info!(
+            "health: device {device_id} recovered (score {})",
+            event.score
+        ),
+        _ => log::warn!(
+            "health: device {device_id} is {:?} (score {})",
+            event.kind,
+            event.score
+        ),
+    }
+    let mut events = EVENTS.lock().unwrap();
+    if events.len() == EVENT_CAP {
+        events.pop_front();
+    }
+    events.push_back(event.clone());
+    Some(event)
+}
+
+/// Drop the state of devices not in `ids`.
+fn retain(ids: &[String]) {
+    TRACKERS.write().unwrap().retain(|id, _| ids.contains(id));
+}
+
+async fn run_once() {
+    let ids = crate::manager::list_pipe_ids().await;
+    retain(&ids);
+    for id in &ids {
+        // Native workers have no pipe to sample.
+        let Some(pipe) = crate::manager::get_pipe(id).await else {
+            continue;
+        };
+        let stats = if pipe.is_started() {
+            pipe.input_stats().await
+        } else {
+            None
+        };
+        record(
+            id,
+            stats,
+            Instant::now(),
+            chrono::Utc::now().timestamp_millis(),
+        );
+    }
+}
+
+/// Spawn the sampling worker; it runs until `cancel` fires.
+pub fn spawn_worker(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        log::info!("health: worker started");
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(crate::manager::STARTUP_DELAY) => {}
+        }
+        loop {
+            run_once().await;
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    log::info!("health: worker stopped");
+                    return;
+                }
+                _ = tokio::time::sleep(SAMPLE_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub(crate) struct HealthResponse {
+    /// `None` until the device has run for two samples.
+    current: Option<DeviceHealth>,
+    history: Vec<HealthSample>,
+    /// The device's recent level changes, oldest first.
+    events: Vec<HealthEvent>,
+    /// Whether its picture is frozen or black right now.
+    dead_air: Option<DeadAir>,
+    /// Its recent dead-air changes, oldest first.
+    dead_air_events: Vec<DeadAirEvent>,
+    degraded_below: u8,
+    critical_below: u8,
+}
+
+/// `GET /api/device/{id}/health`.
+#[utoipa::path(
+    get,
+    path = "/{id}/health",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses((status = 200, body = crate::handler::BaseResponse<HealthResponse>))
+)]
+pub(crate) async fn health(Path(id): Path<String>) -> ApiJsonResult<HealthResponse> {
+    Ok(ok_json(HealthResponse {
+        current: device_health(&id),
+        history: history(&id),
+        events: recent_events()
+            .into_iter()
+            .filter(|e| e.device_id == id)
+            .collect(),
+        dead_air: deadair::current(&id),
+        dead_air_events: deadair::recent_events()
+            .into_iter()
+            .filter(|e| e.device_id == id)
+            .collect(),
+        degraded_below: DEGRADED_BELOW,
+        critical_below: CRITICAL_BELOW,
+    }))
+}
+
+#[cfg(test)]
+#[path = "health_test.rs"]
+mod health_test;
@@ -0,0 +1,204 @@
+use super::*;
+
+fn snapshot(frames: u64, reconnects: u32) -> StatsSnapshot {
+    StatsSnapshot {
+        window_secs: 300.0,
+        nominal_fps: Some(25.0),
+        frames,
+        reconnects,
+        bitrates: vec![2_000_000.0; 30],
+        ..StatsSnapshot::default()
+    }
+}
+
+fn penalty(health: &HealthScore, name: &str) -> u8 {
+    health
+        .factors
+        .iter()
+        .find(|f| f.name == name)
+        .map(|f| f.penalty)
+        .unwrap()
+}
+
+/// Counters of a 25 fps stream after `packets` packets.
+fn stats(packets: u64) -> InputStats {
+    InputStats {
+        video_packets: packets,
+        video_bytes: packets * 10_000,
+        nominal_fps: Some(25.0),
+        ..InputStats::default()
+    }
+}
+
+#[test]
+fn healthy_stream_scores_full() {
+    let health = score(&snapshot(7_500, 0));
+    assert_eq!(health.score, 100);
+    assert_eq!(health.level, HealthLevel::Healthy);
+    let names = health.factors.iter().map(|f| f.name).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec!["fps", "packet_loss", "reconnects", "bitrate_variation"]
+    );
+    assert_eq!(health.factors[0].value, 25.0);
+}
+
+#[test]
+fn low_fps_degrades() {
+    // 10 of a nominal 25 fps.
+    let health = score(&snapshot(3_000, 0));
+    assert_eq!(penalty(&health, "fps"), 30);
+    assert_eq!(health.score, 70);
+    assert_eq!(health.level, HealthLevel::Degraded);
+
+    // Without a nominal rate the frame rate alone costs nothing...
+    let unknown = StatsSnapshot {
+        nominal_fps: None,
+        ..snapshot(3_000, 0)
+    };
+    assert_eq!(score(&unknown).score, 100);
+    // ...but a stalled stream is always critical.
+    let stalled = score(&StatsSnapshot {
+        nominal_fps: None,
+        ..snapshot(0, 0)
+    });
+    assert_eq!(stalled.score, 0);
+    assert_eq!(stalled.level, HealthLevel::Critical);
+}
+
+#[test]
+fn flapping_reconnects_and_loss_go_critical() {
+    // Four reconnects in the window, and the frames lost while down.
+    let health = score(&snapshot(6_000, 4));
+    assert_eq!(penalty(&health, "reconnects"), 60);
+    assert_eq!(penalty(&health, "fps"), 10);
+    assert_eq!(health.score, 30);
+    assert_eq!(health.level, HealthLevel::Critical);
+
+    let lossy = StatsSnapshot {
+        corrupt_packets: 600,
+        lagged_packets: 150,
+        bitrates: vec![4_000_000.0, 500_000.0, 4_000_000.0, 500_000.0],
+        ..snapshot(7_500, 0)
+    };
+    let health = score(&lossy);
+    // 10% loss costs the whole loss weight; swings cost some too.
+    assert_eq!(penalty(&health, "packet_loss"), 25);
+    assert!(penalty(&health, "bitrate_variation") > 0);
+    assert_eq!(health.level, HealthLevel::Degraded);
+}
+
+#[test]
+fn a_dead_picture_is_critical_on_a_perfect_stream() {
+    for (dead_air, name) in [(DeadAir::Frozen, "frozen"), (DeadAir::Black, "black")] {
+        let health = score(&StatsSnapshot {
+            dead_air: Some(dead_air),
+            ..snapshot(7_500, 0)
+        });
+        assert_eq!(penalty(&health, name), 60);
+        assert_eq!(health.score, 40);
+        assert_eq!(health.level, HealthLevel::Critical);
+    }
+}
+
+#[test]
+fn a_collapsing_gop_costs_only_at_a_steady_frame_rate() {
+    let at = |ratio, frames| {
+        score(&StatsSnapshot {
+            gop_size_ratio: Some(ratio),
+            ..snapshot(frames, 0)
+        })
+    };
+    // Half the usual bytes per frame is a calm scene; a fifth is starved.
+    assert_eq!(penalty(&at(0.5, 7_500), "gop_size"), 0);
+    let starved = at(0.2, 7_500);
+    assert_eq!(penalty(&starved, "gop_size"), 25);
+    assert_eq!(starved.score, 75);
+    assert_eq!(starved.level, HealthLevel::Degraded);
+    // Frames going missing shrink the GOPs too; `fps` already counts them.
+    let dropping = at(0.2, 3_000);
+    assert!(dropping.factors.iter().all(|f| f.name != "gop_size"));
+    assert_eq!(dropping.score, 70);
+}
+
+#[test]
+fn the_gop_ratio_waits_for_a_full_window() {
+    let window = |gops, last_bytes| GopAggregate {
+        gops,
+        avg_bytes: 50_000.0,
+        avg_frames: 25.0,
+        last: ffmpeg_bus::gop::GopSummary {
+            bytes: last_bytes,
+            frames: 25,
+            ..Default::default()
+        },
+        ..GopAggregate::default()
+    };
+    assert_eq!(gop_size_ratio(&window(4, 10_000)), None);
+    assert_eq!(gop_size_ratio(&window(5, 10_000)), Some(0.2));
+}
+
+#[test]
+fn only_level_changes_fire_events() {
+    use HealthLevel::*;
+    assert_eq!(evaluate(Healthy, Healthy), None);
+    assert_eq!(evaluate(Degraded, Degraded), None);
+    assert_eq!(evaluate(Healthy, Degraded), Some(HealthEventKind::Degraded));
+    assert_eq!(
+        evaluate(Degraded, Critical),
+        Some(HealthEventKind::Critical)
+    );
+    assert_eq!(
+        evaluate(Critical, Degraded),
+        Some(HealthEventKind::Degraded)
+    );
+    assert_eq!(
+        evaluate(Critical, Healthy),
+        Some(HealthEventKind::Recovered)
+    );
+}
+
+#[test]
+fn flapping_device_emits_each_transition_once() {
+    let id = "health-test-flapping";
+    let base = Instant::now();
+    let mut tick = 0u32;
+    let mut sample = |stats: Option<InputStats>| {
+        tick += 1;
+        record(id, stats, base + SAMPLE_INTERVAL * tick, tick as i64)
+    };
+
+    // A steady stream: scored from the second sample on, no event.
+    assert!(sample(Some(stats(0))).is_none());
+    for i in 1..5 {
+        assert!(sample(Some(stats(i * 250))).is_none());
+    }
+    let health = device_health(id).unwrap();
+    assert_eq!(health.health.score, 100);
+    assert_eq!(health.window_secs, 40.0);
+
+    // The pipe keeps dropping and coming back, each time as a new pipe.
+    let mut kinds = Vec::new();
+    for _ in 0..4 {
+        assert!(sample(None).is_none());
+        assert!(device_health(id).is_none());
+        for i in 0..3 {
+            kinds.extend(sample(Some(stats(i * 250))).map(|e| e.kind));
+        }
+    }
+    assert_eq!(
+        kinds,
+        vec![HealthEventKind::Degraded, HealthEventKind::Critical]
+    );
+    assert_eq!(device_health(id).unwrap().health.score, 40);
+
+    let events = recent_events()
+        .into_iter()
+        .filter(|e| e.device_id == id)
+        .collect::<Vec<_>>();
+    assert_eq!(events.len(), 2);
+    assert_eq!(history(id).len(), 4 + 4 * 2);
+
+    retain(&[]);
+    assert!(device_health(id).is_none());
+}
@@ -4,7 +4,7 @@ use nvr_db::device::DeviceInfo;
 use tokio::sync::oneshot;
 use tokio_util::sync::CancellationToken;
 
-use crate::{db::app_db_conn, manager};
+use crate::{db::app_db_conn, device_input::DeviceInput, latency::InputTuning, manager};
 use media_pipe_core::{InputConfig, PipeConfig};
 
 pub(crate) fn init_device_pipes(
@@ -29,6 +29,9 @@ pub(crate) fn init_device_pipes(
 }
 
 async fn init_device_pipes_inner() -> anyhow::Result<()> {
+    // Private devices must come up without outputs.
+    crate::privacy::restore().await;
+
     let conn = app_db_conn()?;
     let devices = nvr_db::device::list(&conn).await?;
     let total = devices.len();
@@ -58,57 +61,69 @@ async fn init_device_pipes_inner() -> anyhow::Result<()> {
 }
 
 pub(crate) async fn ensure_device_pipe(device: &DeviceInfo) -> anyhow::Result<()> {
+    crate::stream_key::register(&device.id, device.stream_name());
+    if crate::privacy::is_private(&device.id) {
+        return ensure_private_pipe(device).await;
+    }
+
+    let input = DeviceInput::of(device)?;
+
     // Xiaomi cameras bypass ffmpeg entirely: a native worker pushes the
     // decoded H264 straight into a ZLM Media. `input_value` carries the
     // XiaomiConfig as JSON.
-    if device.input_type == "xiaomi" {
-        let cfg: crate::xiaomi::XiaomiConfig = serde_json::from_str(&device.input_value)
-            .map_err(|e| anyhow::anyhow!("invalid xiaomi device config: {e}"))?;
+    if let DeviceInput::Xiaomi(cfg) = input {
         let media = Arc::new(rszlm::media::Media::new_with_default_vhost(
             DEVICE_APP,
-            device.id.as_str(),
+            device.stream_name(),
             0.0,
-            device.record,
+            records(device),
             false,
         ));
         return manager::upsert_xiaomi(&device.id, media, cfg, true).await;
     }
 
-    // GB28181 cameras have no always-on pipe: they only register a mapping so
-    // the on-demand bridge can INVITE-pull when a viewer opens the stream. The
-    // `input_value` carries `{ "device_id": "...", "channel_id": "..." }`.
-    if device.input_type == "gb28181" {
-        #[derive(serde::Deserialize)]
-        struct GbInput {
-            device_id: String,
-            channel_id: String,
-        }
-        let gb: GbInput = serde_json::from_str(&device.input_value)
-            .map_err(|e| anyhow::anyhow!("invalid gb28181 device config: {e}"))?;
-        match crate::gb::bridge() {
-            Some(bridge) => {
-                // stream id == nvr device id (the ZLM stream name we pull into).
-                bridge.register_mapping(
-                    &device.id,
-                    &gb.device_id,
-                    &gb.channel_id,
-                    gb28181::Transport::Udp,
-                );
-                log::info!(
-                    "gb28181: registered mapping {} -> {}/{}",
-                    device.id,
-                    gb.device_id,
-                    gb.channel_id
-                );
-            }
-            None => {
-                log::warn!(
-                    "gb28181 device {} added but GB support is not active \
-                     (NVR_GB_ENABLE!=1, or the platform failed to bind — see startup logs)",
-                    device.id
-                );
-            }
+    // GB28181 cameras: `input_value` carries `{ "device_id": "...",
+    // "channel_id": "..." }` (see `crate::gb::device::GbInput`). By default
+    // there is no pipe: only a mapping so the on-demand bridge can INVITE-pull
+    // when a viewer opens the stream. An `always_on` one runs a supervisor
+    // that ingests it like any other camera instead.
+    if let DeviceInput::Gb28181(gb) = input {
+        crate::gb::device::set_password(&device.id, &gb);
+        let Some(bridge) = crate::gb::bridge() else {
+            log::warn!(
+                "gb28181 device {} added but GB support is not active \
+                 (NVR_GB_ENABLE!=1, or the platform failed to bind — see startup logs)",
+                device.id
+            );
+            return Ok(());
+        };
+        if gb.always_on {
+            bridge.unregister_mapping(&device.id).await;
+            let media = Arc::new(rszlm::media::Media::new_with_default_vhost(
+                DEVICE_APP,
+                device.stream_name(),
+                0.0,
+                records(device),
+                false,
+            ));
+            return manager::upsert_gb(
+                &device.id,
+                media,
+                gb.channel()?,
+                device.include_audio,
+                true,
+            )
+            .await;
         }
+        manager::remove_pipe(&device.id).await?;
+        // stream id == nvr device id (the ZLM stream name we pull into).
+        bridge.register_mapping(&device.id, &gb.device_id, &gb.channel_id, gb.transport()?);
+        log::info!(
+            "gb28181: registered mapping {} -> {}/{}",
+            device.id,
+            gb.device_id,
+            gb.channel_id
+        );
         return Ok(());
     }
 
@@ -117,15 +132,13 @@ pub(crate) async fn ensure_device_pipe(device: &DeviceInfo) -> anyhow::Result<()
     // `input_value` carries the OnvifConfig; we register it (for PTZ / the REST
     // surface) and spawn a supervisor that resolves the RTSP URI just-in-time
     // and re-resolves on every reconnect, feeding the shared RTSP -> ZLM pipe.
-    if device.input_type == "onvif" {
-        let cfg: nvr_onvif::OnvifConfig = serde_json::from_str(&device.input_value)
-            .map_err(|e| anyhow::anyhow!("invalid onvif device config: {e}"))?;
+    if let DeviceInput::Onvif(cfg) = input {
         crate::onvif::register(&device.id, cfg.clone());
         let media = Arc::new(rszlm::media::Media::new_with_default_vhost(
             DEVICE_APP,
-            device.id.as_str(),
+            device.stream_name(),
             0.0,
-            device.record,
+            records(device),
             false,
         ));
         return manager::upsert_onvif(&device.id, media, cfg, device.include_audio, true).await;
@@ -135,57 +148,140 @@ pub(crate) async fn ensure_device_pipe(device: &DeviceInfo) -> anyhow::Result<()
     // stable URL to hand to ffmpeg — the pull address is temporary and signed.
     // `input_value` stores the room/page URL; a supervisor worker resolves it
     // via yt-dlp right before opening and again on every reconnect.
-    if device.input_type == "stream" {
+    if let DeviceInput::Stream { url } = input {
         let media = Arc::new(rszlm::media::Media::new_with_default_vhost(
             DEVICE_APP,
-            device.id.as_str(),
+            device.stream_name(),
             0.0,
-            device.record,
+            records(device),
             false,
         ));
-        return manager::upsert_stream(
+        return manager::upsert_stream(&device.id, media, url, device.include_audio, true).await;
+    }
+
+    // Several URLs for one camera: a supervisor picks the one in use.
+    if let Some(failover) = input.ffmpeg()
+        && !failover.failover_urls.is_empty()
+    {
+        crate::input_options::validate(&failover.tuning)?;
+        let inputs = failover
+            .urls()
+            .iter()
+            .filter_map(|url| input.open(url))
+            .collect();
+        let media = Arc::new(rszlm::media::Media::new_with_default_vhost(
+            DEVICE_APP,
+            device.stream_name(),
+            0.0,
+            records(device),
+            false,
+        ));
+        let include_audio = device.include_audio;
+        return manager::upsert_failover(
             &device.id,
-            media,
-            device.input_value.clone(),
-            device.include_audio,
+            inputs,
+            failover.policy(),
+            failover.tuning.clone(),
+            move |session| {
+                media_pipe_zlm::zlm_outputs_with_session(Arc::clone(&media), include_audio, session)
+            },
             true,
         )
         .await;
     }
 
-    let input = match device.input_type.as_str() {
-        "net" | "rtsp" | "rtmp" => InputConfig::Network {
-            url: device.input_value.clone(),
-        },
-        "file" => InputConfig::File {
-            path: device.input_value.clone(),
-        },
-        "v4l2" | "x11grab" | "lavfi" => InputConfig::Device {
-            display: device.input_value.clone(),
-            format: device.input_type.clone(),
-        },
-        _ => {
-            return Err(anyhow::anyhow!(
-                "unsupported input type: {}",
-                device.input_type
+    let tuning = input_tuning(&input)?;
+    let input = InputConfig::try_from(&input)?;
+
+    // Started when watched (see `manager::acquire_pipe`); a recording one
+    // runs regardless. The Media is made anew for every start.
+    if let Some(grace) = crate::config::config().on_demand_grace() {
+        let pinned = records(device);
+        let device = device.clone();
+        let config = move || {
+            let media = Arc::new(rszlm::media::Media::new_with_default_vhost(
+                DEVICE_APP,
+                device.stream_name(),
+                0.0,
+                records(&device),
+                false,
             ));
-        }
-    };
+            Ok(PipeConfig {
+                input: ffmpeg_input(&device)?,
+                outputs: media_pipe_zlm::zlm_outputs(media, device.include_audio),
+            })
+        };
+        let spec = manager::OnDemand {
+            config: Box::new(config),
+            tuning,
+            pinned,
+            grace,
+        };
+        return manager::upsert_on_demand(&device.id, spec).await;
+    }
 
     // hls_enabled drives recording: ZLM only produces the HLS segments that
     // get archived (on_record_ts) when this is on. Live view uses FLV, which
     // is independent, so disabling HLS just turns recording off.
     let media = Arc::new(rszlm::media::Media::new_with_default_vhost(
         DEVICE_APP,
-        device.id.as_str(),
+        device.stream_name(),
         0.0,
-        device.record,
+        records(device),
         false,
     ));
     let outputs = media_pipe_zlm::zlm_outputs(media, device.include_audio);
 
     let config = PipeConfig { input, outputs };
-    manager::update_pipe(&device.id, config).await
+    manager::update_tuned_pipe(&device.id, config, tuning).await
+}
+
+/// Whether the device's ZLM Media records: as configured, except in
+/// maintenance mode (see `crate::maintenance`).
+fn records(device: &DeviceInfo) -> bool {
+    device.record && !crate::maintenance::is_active()
+}
+
+/// The ffmpeg input of a device whose stream ffmpeg opens directly. A device
+/// with failover inputs (see `crate::failover`) uses its primary URL here.
+fn ffmpeg_input(device: &DeviceInfo) -> anyhow::Result<InputConfig> {
+    InputConfig::try_from(&DeviceInput::of(device)?)
+}
+
+/// The latency tuning (see `crate::latency`) of a device's JSON input, its
+/// raw options checked (see `crate::input_options`).
+fn input_tuning(input: &DeviceInput) -> anyhow::Result<InputTuning> {
+    let tuning = input
+        .ffmpeg()
+        .map(|input| input.tuning.clone())
+        .unwrap_or_default();
+    crate::input_options::validate(&tuning)?;
+    Ok(tuning)
+}
+
+/// A device in privacy mode (see `crate::privacy`) publishes nothing: its
+/// ZLM Media is dropped, which ends live view and finalizes the open record
+/// segment. Pipes ffmpeg opens directly keep reading their input with no
+/// outputs, so the device still reports as connected; other sources stop.
+async fn ensure_private_pipe(device: &DeviceInfo) -> anyhow::Result<()> {
+    match device.input_type.as_str() {
+        "gb28181" => {
+            if let Some(bridge) = crate::gb::bridge() {
+                bridge.unregister_mapping(&device.id).await;
+            }
+            // An always-on one's supervisor.
+            manager::remove_pipe(&device.id).await
+        }
+        "xiaomi" | "onvif" | "stream" => manager::remove_pipe(&device.id).await,
+        _ => {
+            let input = DeviceInput::of(device)?;
+            let config = PipeConfig {
+                input: InputConfig::try_from(&input)?,
+                outputs: Vec::new(),
+            };
+            manager::update_tuned_pipe(&device.id, config, input_tuning(&input)?).await
+        }
+    }
 }
 
 /// Playable HTTP-FLV URL as a same-origin path through the `/media` reverse
@@ -197,13 +293,14 @@ pub(crate) async fn ensure_device_pipe(device: &DeviceInfo) -> anyhow::Result<()
 /// apps so the four stream families never collide.
 pub(crate) const DEVICE_APP: &str = "device";
 
-pub(crate) fn build_flv_url(device_id: &str) -> String {
-    format!("/media/{}/{}.live.flv", DEVICE_APP, device_id)
+/// `stream` is the device's [`DeviceInfo::stream_name`].
+pub(crate) fn build_flv_url(stream: &str) -> String {
+    format!("/media/{}/{}.live.flv", DEVICE_APP, stream)
 }
 
-/// GB28181 streams are published by ZLM's RtpServer under the `rtp` app (not
-/// `live`), so their playable URL differs from `build_flv_url`. Same `/media`
-/// proxy path (see `build_flv_url`).
+/// On-demand GB28181 streams are published by ZLM's RtpServer under the `rtp`
+/// app (not `live`), so their playable URL differs from `build_flv_url`. Same
+/// `/media` proxy path (see `build_flv_url`).
 pub(crate) fn build_gb_flv_url(device_id: &str) -> String {
     format!("/media/rtp/{}.live.flv", device_id)
 }
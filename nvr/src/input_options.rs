@@ -0,0 +1,152 @@
+//! The custom FFmpeg input options of a device: an escape hatch for the odd
+//! camera that needs a demuxer or protocol option nothing else sets
+//! (`use_wallclock_as_timestamps`, `rtsp_flags`, a v4l2 `input_format`…).
+//! The JSON form of a device's `input_value` (see `crate::failover`) carries
+//! them as `extra_input_options`, merged over everything else:
+//!
+//! ```json
+//! { "url": "rtsp://10.0.0.9/main", "latency_profile": "low",
+//!   "extra_input_options": { "use_wallclock_as_timestamps": "1" } }
+//! ```
+//!
+//! [`check`] guards them, and the `advanced` options of the latency tuning
+//! alike: [`DENIED`] options would lift FFmpeg's protocol restrictions or
+//! fight the device's structured fields, path-valued [`PATH_OPTIONS`] must
+//! point into `NVR_INPUT_FILE_DIRS`, and values are capped in length. Only
+//! admins may change a device's extra options.
+//!
+//! The options a device's input was last opened with, after every merge, are
+//! kept for `GET /api/v1/device/{id}/input` (see [`effective`]).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+use crate::latency::InputTuning;
+
+/// Options no device may set: the protocol, format and codec whitelists
+/// (opening `file:` or `concat:` through a network URL), the structured
+/// input format (the device's `input_type`), and turning the input into a
+/// listening server or a file descriptor read.
+pub const DENIED: [&str; 8] = [
+    "f",
+    "format",
+    "protocol_whitelist",
+    "protocol_blacklist",
+    "format_whitelist",
+    "codec_whitelist",
+    "listen",
+    "fd",
+];
+
+/// Options whose value is a file the demuxer reads.
+pub const PATH_OPTIONS: [&str; 4] = ["ca_file", "cafile", "cert_file", "key_file"];
+
+/// The longest value an option may have, unless [`LONG_VALUES`] allows more.
+const MAX_VALUE_LEN: usize = 256;
+/// Options whose values are long by nature.
+const LONG_VALUES: [(&str, usize); 3] =
+    [("headers", 4096), ("cookies", 4096), ("user_agent", 1024)];
+const MAX_KEY_LEN: usize = 64;
+
+/// Why an input option was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedOption {
+    pub key: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for RejectedOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "input option {:?} rejected: {}", self.key, self.reason)
+    }
+}
+
+impl std::error::Error for RejectedOption {}
+
+/// Check raw input options against the guardrails, with path-valued options
+/// allowed under `file_dirs`. Reports the first offending key.
+pub fn check(
+    options: &HashMap<String, String>,
+    file_dirs: &[PathBuf],
+) -> Result<(), RejectedOption> {
+    let mut keys = options.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        let value = &options[key];
+        let reject = |reason: String| {
+            Err(RejectedOption {
+                key: key.clone(),
+                reason,
+            })
+        };
+        if key.is_empty()
+            || key.len() > MAX_KEY_LEN
+            || !key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        {
+            return reject("not an option name (give it without the leading -)".to_string());
+        }
+        if DENIED.contains(&key.as_str()) {
+            return reject("not allowed on a device input".to_string());
+        }
+        let max = LONG_VALUES
+            .iter()
+            .find(|(long, _)| long == key)
+            .map_or(MAX_VALUE_LEN, |(_, max)| *max);
+        if value.len() > max {
+            return reject(format!("value longer than {max} bytes"));
+        }
+        if value.contains('\0') {
+            return reject("value contains a NUL byte".to_string());
+        }
+        if PATH_OPTIONS.contains(&key.as_str()) && !within(Path::new(value), file_dirs) {
+            return reject("path outside NVR_INPUT_FILE_DIRS".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` is an absolute path inside one of `dirs`, without climbing
+/// out of it through `..`.
+fn within(path: &Path, dirs: &[PathBuf]) -> bool {
+    path.is_absolute()
+        && !path.components().any(|c| c == Component::ParentDir)
+        && dirs.iter().any(|dir| path.starts_with(dir))
+}
+
+/// [`check`] both raw option maps of a tuning, with the configured
+/// `NVR_INPUT_FILE_DIRS`.
+pub fn validate(tuning: &InputTuning) -> Result<(), RejectedOption> {
+    let dirs = crate::config::config().input_file_dirs();
+    check(&tuning.advanced, dirs)?;
+    check(&tuning.extra_input_options, dirs)
+}
+
+/// The merged options each managed device was last started with.
+static EFFECTIVE: LazyLock<RwLock<HashMap<String, BTreeMap<String, String>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// The input options `device_id`'s input was opened with; `None` when it
+/// runs with FFmpeg's defaults or is not running.
+pub fn effective(device_id: &str) -> Option<BTreeMap<String, String>> {
+    EFFECTIVE.read().unwrap().get(device_id).cloned()
+}
+
+/// Record the options `device_id` is (re)started with.
+pub(crate) fn set_effective(device_id: &str, options: Option<&HashMap<String, String>>) {
+    let mut effective = EFFECTIVE.write().unwrap();
+    match options {
+        Some(options) => effective.insert(
+            device_id.to_string(),
+            options
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        None => effective.remove(device_id),
+    };
+}
+
+#[cfg(test)]
+#[path = "input_options_test.rs"]
+mod input_options_test;
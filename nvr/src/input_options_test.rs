@@ -0,0 +1,78 @@
+use super::*;
+
+fn options(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn rejected(pairs: &[(&str, &str)]) -> String {
+    check(&options(pairs), &[PathBuf::from("/etc/nvr/certs")])
+        .unwrap_err()
+        .key
+}
+
+#[test]
+fn camera_quirks_are_allowed() {
+    let quirks = options(&[
+        ("use_wallclock_as_timestamps", "1"),
+        ("rtsp_flags", "prefer_tcp"),
+        ("input_format", "mjpeg"),
+        ("headers", &"X-Key: v\r\n".repeat(200)),
+        ("ca_file", "/etc/nvr/certs/camera-ca.pem"),
+    ]);
+    assert_eq!(check(&quirks, &[PathBuf::from("/etc/nvr/certs")]), Ok(()));
+}
+
+#[test]
+fn denylisted_options_are_rejected_by_name() {
+    for key in DENIED {
+        assert_eq!(rejected(&[("probesize", "32"), (key, "x")]), key);
+    }
+    let error = check(&options(&[("protocol_whitelist", "file,rtp")]), &[]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "input option \"protocol_whitelist\" rejected: not allowed on a device input"
+    );
+    // Given as on the ffmpeg command line.
+    assert_eq!(rejected(&[("-rtsp_flags", "prefer_tcp")]), "-rtsp_flags");
+}
+
+#[test]
+fn values_are_capped_in_length() {
+    let long = "x".repeat(300);
+    assert_eq!(rejected(&[("user_agent", &"x".repeat(1025))]), "user_agent");
+    assert_eq!(rejected(&[("rtsp_flags", &long)]), "rtsp_flags");
+    assert!(check(&options(&[("user_agent", &long)]), &[]).is_ok());
+    assert_eq!(rejected(&[("timeout", "1\0")]), "timeout");
+}
+
+#[test]
+fn paths_must_stay_in_the_allowed_dirs() {
+    for path in [
+        "/etc/passwd",
+        "certs/key.pem",
+        "/etc/nvr/certs/../../shadow",
+        "/etc/nvr/certsx/key.pem",
+    ] {
+        assert_eq!(rejected(&[("key_file", path)]), "key_file", "{path}");
+    }
+    // None allowed unless configured.
+    assert!(check(&options(&[("cert_file", "/etc/nvr/certs/a.pem")]), &[]).is_err());
+}
+
+#[test]
+fn the_effective_options_follow_the_device() {
+    let id = "input-options-test";
+    set_effective(id, Some(&options(&[("fflags", "nobuffer")])));
+    assert_eq!(
+        effective(id),
+        Some(BTreeMap::from([(
+            "fflags".to_string(),
+            "nobuffer".to_string()
+        )]))
+    );
+    set_effective(id, None);
+    assert_eq!(effective(id), None);
+}
@@ -0,0 +1,432 @@
+//! Job runner: long-running operations (clip export, ...) queued in the
+//! `jobs` table and run by a bounded pool of workers, so they survive a
+//! restart and can be followed and cancelled from the API.
+//!
+//! A kind of job is registered with a handler and whether it is idempotent.
+//! The runner claims queued jobs of its kinds oldest first (one transaction
+//! per claim, see `nvr_db::job::claim`), runs each handler with a
+//! [`JobContext`] for its payload, progress and cancellation, and records how
+//! it ended. Jobs still marked running at boot were cut off by a crash or
+//! restart: [`JobRunner::recover`] queues the idempotent ones again and fails
+//! the rest.
+//!
+//! `GET /api/jobs` lists jobs (`?kind=`, `?status=`, `?limit=`),
+//! `GET /api/jobs/{id}` polls one and `POST /api/jobs/{id}/cancel` cancels
+//! one (its creator or an admin).
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{
+    Extension, Router,
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use futures::future::BoxFuture;
+use nvr_db::job::{Job, JobFilter, JobStatus};
+use serde::Deserialize;
+use tokio::sync::{Notify, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::auth::AuthUser;
+use crate::db::app_db_conn;
+use crate::handler::{ApiError, ApiJsonResult, ApiResult, BaseResponse, ok_json};
+
+/// Jobs run at once by the global runner.
+const WORKERS: usize = 2;
+/// How often the dispatcher looks for queued jobs without being woken.
+const POLL: Duration = Duration::from_secs(5);
+/// The error of a job cut off by a restart that is not run again.
+const INTERRUPTED: &str = "interrupted by a restart";
+/// Most jobs one listing returns.
+const LIST_CAP: usize = 500;
+
+/// What a job handler gets: its job's id and payload, a way to report
+/// progress and the cancellation flag to check.
+pub struct JobContext {
+    pub id: String,
+    pub payload: serde_json::Value,
+    cancel: CancellationToken,
+}
+
+impl JobContext {
+    /// The payload as the kind's input type.
+    pub fn input<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_value(self.payload.clone())?)
+    }
+
+    /// Record progress (percent). Best-effort: a write lost to DB contention
+    /// is only logged, the next one catches up.
+    pub async fn progress(&self, percent: u8) {
+        let result =
+            async { nvr_db::job::set_progress(&self.id, percent, &app_db_conn()?).await }.await;
+        if let Err(e) = result {
+            log::debug!("jobs: progress of {} not recorded: {e:#}", self.id);
+        }
+    }
+
+    /// Whether the job was asked to stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Resolves once the job is asked to stop.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+}
+
+type Handler =
+    Arc<dyn Fn(JobContext) -> BoxFuture<'static, Result<serde_json::Value>> + Send + Sync>;
+
+struct Kind {
+    /// Safe to run again from the start after being cut off.
+    idempotent: bool,
+    handler: Handler,
+}
+
+pub struct JobRunner {
+    kinds: HashMap<String, Kind>,
+    workers: Arc<Semaphore>,
+    wake: Notify,
+    /// Cancellation of the jobs running in this process, by id.
+    running: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl JobRunner {
+    /// A runner of at most `workers` jobs at once, with no kinds yet.
+    pub fn new(workers: usize) -> Self {
+        Self {
+            kinds: HashMap::new(),
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            wake: Notify::new(),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run jobs of `kind` with `handler`; its `Ok` value is the job's result.
+    pub fn register<F, Fut>(mut self, kind: &str, idempotent: bool, handler: F) -> Self
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<serde_json::Value>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |ctx| Box::pin(handler(ctx)));
+        self.kinds.insert(
+            kind.to_string(),
+            Kind {
+                idempotent,
+                handler,
+            },
+        );
+        self
+    }
+
+    /// Queue a job of a registered `kind`; it starts once a worker is free.
+    pub async fn enqueue(
+        &self,
+        kind: &str,
+        payload: serde_json::Value,
+        created_by: &str,
+    ) -> Result<Job> {
+        anyhow::ensure!(self.kinds.contains_key(kind), "unknown job kind {kind}");
+        let job = Job::new(kind, payload, created_by, now_ms());
+        nvr_db::job::insert(&job, &app_db_conn()?).await?;
+        self.wake.notify_one();
+        Ok(job)
+    }
+
+    /// Cancel job `id`: a queued one never starts, a running one is told to
+    /// stop. Returns the job afterwards, `None` if there is none.
+    pub async fn cancel(&self, id: &str) -> Result<Option<Job>> {
+        let job = nvr_db::job::cancel(id, now_ms(), &mut app_db_conn()?).await?;
+        if let Some(token) = self.running.lock().unwrap().get(id) {
+            token.cancel();
+        }
+        Ok(job)
+    }
+
+    /// Settle the jobs a previous run left running: cancelled if a cancel was
+    /// pending, queued again if their kind is idempotent, failed otherwise
+    /// (or when their kind is not registered). Call before [`start`](Self::start).
+    pub async fn recover(&self) -> Result<usize> {
+        let conn = app_db_conn()?;
+        let filter = JobFilter {
+            status: Some(JobStatus::Running),
+            ..JobFilter::default()
+        };
+        let stale = nvr_db::job::list(&filter, LIST_CAP, &conn).await?;
+        let now = now_ms();
+        for job in &stale {
+            let idempotent = self.kinds.get(&job.kind).is_some_and(|k| k.idempotent);
+            if job.cancel_requested {
+                nvr_db::job::finish(&job.id, JobStatus::Cancelled, None, None, now, &conn).await?;
+            } else if idempotent {
+                nvr_db::job::requeue(&job.id, &conn).await?;
+                log::info!(
+                    "jobs: {} job {} queued again after a restart",
+                    job.kind,
+                    job.id
+                );
+            } else {
+                nvr_db::job::finish(
+                    &job.id,
+                    JobStatus::Failed,
+                    None,
+                    Some(INTERRUPTED),
+                    now,
+                    &conn,
+                )
+                .await?;
+                log::warn!("jobs: {} job {} {INTERRUPTED}", job.kind, job.id);
+            }
+        }
+        Ok(stale.len())
+    }
+
+    /// Start dispatching queued jobs until `cancel`.
+    pub fn start(self: &Arc<Self>, cancel: CancellationToken) {
+        let runner = self.clone();
+        tokio::spawn(async move {
+            log::info!("jobs: runner started");
+            runner.dispatch(cancel).await;
+            log::info!("jobs: runner stopped");
+        });
+    }
+
+    async fn dispatch(self: Arc<Self>, cancel: CancellationToken) {
+        let kinds: Vec<&str> = self.kinds.keys().map(String::as_str).collect();
+        loop {
+            let permit = tokio::select! {
+                _ = cancel.cancelled() => return,
+                permit = self.workers.clone().acquire_owned() => match permit {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                },
+            };
+            let claimed =
+                async { nvr_db::job::claim(&kinds, now_ms(), &mut app_db_conn()?).await }.await;
+            let job = match claimed {
+                Ok(Some(job)) => job,
+                Ok(None) => {
+                    drop(permit);
+                    tokio::select! {
+                        _ = cancel.cancelled() => return,
+                        _ = self.wake.notified() => {}
+                        _ = tokio::time::sleep(POLL) => {}
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    drop(permit);
+                    log::warn!("jobs: claiming failed: {e:#}");
+                    tokio::select! {
+                        _ = cancel.cancelled() => return,
+                        _ = tokio::time::sleep(POLL) => {}
+                    }
+                    continue;
+                }
+            };
+            let runner = self.clone();
+            tokio::spawn(async move {
+                runner.run(job).await;
+                drop(permit);
+            });
+        }
+    }
+
+    /// Run a claimed job to its end and record the outcome.
+    async fn run(&self, job: Job) {
+        let Some(kind) = self.kinds.get(&job.kind) else {
+            return;
+        };
+        let token = CancellationToken::new();
+        self.running
+            .lock()
+            .unwrap()
+            .insert(job.id.clone(), token.clone());
+        // A cancel between the claim and the line above only set the flag.
+        if let Ok(Some(current)) = get(&job.id).await
+            && current.cancel_requested
+        {
+            token.cancel();
+        }
+        let ctx = JobContext {
+            id: job.id.clone(),
+            payload: job.payload.clone(),
+            cancel: token.clone(),
+        };
+        // Its own task, so a panicking handler fails its job only.
+        let outcome = tokio::spawn((kind.handler)(ctx))
+            .await
+            .map_err(|e| anyhow::anyhow!("job panicked: {e}"))
+            .and_then(|r| r);
+        self.running.lock().unwrap().remove(&job.id);
+
+        let (status, result, error) = match outcome {
+            Ok(result) => (JobStatus::Done, Some(result), None),
+            Err(_) if token.is_cancelled() => (JobStatus::Cancelled, None, None),
+            Err(e) => (JobStatus::Failed, None, Some(format!("{e:#}"))),
+        };
+        match status {
+            JobStatus::Failed => log::warn!(
+                "jobs: {} job {} failed: {}",
+                job.kind,
+                job.id,
+                error.as_deref().unwrap_or_default()
+            ),
+            _ => log::info!("jobs: {} job {} {}", job.kind, job.id, status.as_str()),
+        }
+        // The outcome must land, or the job reads as running until the next
+        // restart: retry through write contention.
+        for attempt in 1..=5 {
+            let written = async {
+                nvr_db::job::finish(
+                    &job.id,
+                    status,
+                    result.as_ref(),
+                    error.as_deref(),
+                    now_ms(),
+                    &app_db_conn()?,
+                )
+                .await
+            }
+            .await;
+            match written {
+                Ok(_) => break,
+                Err(e) if attempt == 5 => {
+                    log::error!("jobs: outcome of {} not recorded: {e:#}", job.id)
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(100 * attempt)).await,
+            }
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// The server's runner, with every kind of job registered.
+pub fn runner() -> &'static Arc<JobRunner> {
+    static RUNNER: LazyLock<Arc<JobRunner>> = LazyLock::new(|| {
+        Arc::new(JobRunner::new(WORKERS).register(
+            crate::export::JOB_KIND,
+            true,
+            crate::export::run,
+        ))
+    });
+    &RUNNER
+}
+
+/// Recover the jobs cut off by the last stop, then start the runner.
+pub async fn start(cancel: CancellationToken) {
+    match runner().recover().await {
+        Ok(0) => {}
+        Ok(n) => log::info!("jobs: recovered {n} job(s) left running"),
+        Err(e) => log::error!("jobs: recovering interrupted jobs failed: {e:#}"),
+    }
+    runner().start(cancel);
+}
+
+pub async fn get(id: &str) -> Result<Option<Job>> {
+    nvr_db::job::get(id, &app_db_conn()?).await
+}
+
+/// Jobs matching `filter`, newest first, at most `limit`.
+pub async fn list(filter: &JobFilter, limit: usize) -> Result<Vec<Job>> {
+    nvr_db::job::list(filter, limit, &app_db_conn()?).await
+}
+
+pub fn jobs_router() -> Router {
+    Router::new()
+        .route("/", get(list_jobs))
+        .route("/{id}", get(get_job))
+        .route("/{id}/cancel", post(cancel_job))
+}
+
+/// The schema of [`jobs_router`], mounted at `/api/v1/jobs`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(list_jobs, get_job, cancel_job))]
+pub(crate) struct JobsApi;
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct JobsQuery {
+    /// Only jobs of this kind, e.g. `export`.
+    kind: Option<String>,
+    #[param(inline)]
+    status: Option<JobStatus>,
+    /// At most this many (default 100).
+    limit: Option<usize>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "jobs",
+    params(JobsQuery),
+    responses((status = 200, description = "Newest first", body = BaseResponse<Vec<Job>>))
+)]
+async fn list_jobs(Query(query): Query<JobsQuery>) -> ApiJsonResult<Vec<Job>> {
+    let filter = JobFilter {
+        kind: query.kind,
+        status: query.status,
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, LIST_CAP);
+    Ok(ok_json(list(&filter, limit).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "jobs",
+    params(("id" = String, Path)),
+    responses((status = 200, body = BaseResponse<Job>))
+)]
+async fn get_job(Path(id): Path<String>) -> ApiJsonResult<Job> {
+    let job = get(&id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("job {id} not found"))?;
+    Ok(ok_json(job))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/cancel",
+    tag = "jobs",
+    params(("id" = String, Path)),
+    responses(
+        (
+            status = 200,
+            description = "The job: cancelled if it was queued, still running with \
+                           `cancel_requested` set if it was running",
+            body = BaseResponse<Job>
+        ),
+        (status = 403, description = "Neither its creator nor an admin", body = BaseResponse<()>)
+    )
+)]
+async fn cancel_job(
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> ApiResult<Response> {
+    let job = get(&id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("job {id} not found"))?;
+    if job.created_by != user.username && !crate::auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden(
+            "only its creator or an admin can cancel a job",
+        ));
+    }
+    let job = runner()
+        .cancel(&id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("job {id} not found"))?;
+    Ok(ok_json(job).into_response())
+}
+
+#[cfg(test)]
+#[path = "jobs_test.rs"]
+mod jobs_test;
@@ -0,0 +1,164 @@
+use std::time::Instant;
+
+use serde_json::json;
+
+use super::*;
+
+/// A kind no other test runs, so runners of parallel tests leave it alone.
+fn kind(name: &str) -> String {
+    format!("test-{name}-{}", uuid::Uuid::new_v4())
+}
+
+/// Poll job `id` until `done` holds for it.
+async fn wait_for(id: &str, done: impl Fn(&Job) -> bool) -> Job {
+    let started = Instant::now();
+    loop {
+        let job = get(id).await.unwrap().unwrap();
+        if done(&job) {
+            return job;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "job stuck at {job:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Five steps of 30 ms, reporting progress after each.
+async fn slow(ctx: JobContext) -> Result<serde_json::Value> {
+    for step in 1..=5u8 {
+        tokio::select! {
+            _ = ctx.cancelled() => anyhow::bail!("cancelled"),
+            _ = tokio::time::sleep(Duration::from_millis(30)) => {}
+        }
+        ctx.progress(step * 20).await;
+    }
+    let input: serde_json::Value = ctx.input()?;
+    Ok(json!({ "echo": input["n"] }))
+}
+
+/// Runs until cancelled.
+async fn forever(ctx: JobContext) -> Result<serde_json::Value> {
+    ctx.cancelled().await;
+    anyhow::bail!("cancelled")
+}
+
+#[tokio::test]
+async fn slow_job_reports_progress_and_its_result() {
+    let _db = crate::db::test_db().await;
+    let slow_kind = kind("slow");
+    let runner = Arc::new(JobRunner::new(1).register(&slow_kind, true, slow));
+    let cancel = CancellationToken::new();
+    runner.start(cancel.clone());
+
+    let job = runner
+        .enqueue(&slow_kind, json!({ "n": 7 }), "alice")
+        .await
+        .unwrap();
+    assert_eq!(job.status, JobStatus::Queued);
+    let midway = wait_for(&job.id, |j| {
+        j.status == JobStatus::Running && j.progress > 0
+    })
+    .await;
+    assert!(midway.progress < 100, "{midway:?}");
+    assert!(midway.started_at.is_some());
+
+    let done = wait_for(&job.id, |j| j.status.is_finished()).await;
+    assert_eq!(done.status, JobStatus::Done);
+    assert_eq!(done.progress, 100);
+    assert_eq!(done.result, Some(json!({ "echo": 7 })));
+    assert_eq!(done.created_by, "alice");
+    assert!(done.finished_at.is_some());
+
+    assert!(runner.enqueue("nope", json!({}), "alice").await.is_err());
+    cancel.cancel();
+}
+
+#[tokio::test]
+async fn cancel_stops_a_running_job_and_drops_a_queued_one() {
+    let _db = crate::db::test_db().await;
+    let forever_kind = kind("forever");
+    // One worker: the second job waits behind the first.
+    let runner = Arc::new(JobRunner::new(1).register(&forever_kind, true, forever));
+    let cancel = CancellationToken::new();
+    runner.start(cancel.clone());
+
+    let running = runner
+        .enqueue(&forever_kind, json!({}), "alice")
+        .await
+        .unwrap();
+    let queued = runner
+        .enqueue(&forever_kind, json!({}), "alice")
+        .await
+        .unwrap();
+    wait_for(&running.id, |j| j.status == JobStatus::Running).await;
+
+    let dropped = runner.cancel(&queued.id).await.unwrap().unwrap();
+    assert_eq!(dropped.status, JobStatus::Cancelled);
+
+    let flagged = runner.cancel(&running.id).await.unwrap().unwrap();
+    assert!(flagged.cancel_requested);
+    let stopped = wait_for(&running.id, |j| j.status.is_finished()).await;
+    assert_eq!(stopped.status, JobStatus::Cancelled);
+    assert_eq!(stopped.error, None);
+
+    // The freed worker does not pick the cancelled job up.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let dropped = get(&queued.id).await.unwrap().unwrap();
+    assert_eq!(dropped.status, JobStatus::Cancelled);
+    assert_eq!(dropped.started_at, None);
+
+    assert!(runner.cancel("missing").await.unwrap().is_none());
+    cancel.cancel();
+}
+
+#[tokio::test]
+async fn restart_requeues_idempotent_jobs_and_fails_the_rest() {
+    let _db = crate::db::test_db().await;
+    let (again, once, gone) = (kind("again"), kind("once"), kind("gone"));
+    let conn = app_db_conn().unwrap();
+    // What a crash leaves behind: jobs marked running.
+    let left_running = |kind: &str, cancel_requested: bool| {
+        let mut job = Job::new(kind, json!({ "n": 1 }), "alice", now_ms());
+        job.status = JobStatus::Running;
+        job.started_at = Some(now_ms());
+        job.progress = 40;
+        job.cancel_requested = cancel_requested;
+        job
+    };
+    let jobs = [
+        left_running(&again, false),
+        left_running(&once, false),
+        left_running(&gone, false),
+        left_running(&again, true),
+    ];
+    for job in &jobs {
+        nvr_db::job::insert(job, &conn).await.unwrap();
+    }
+
+    let runner = Arc::new(
+        JobRunner::new(1)
+            .register(&again, true, slow)
+            .register(&once, false, slow),
+    );
+    assert_eq!(runner.recover().await.unwrap(), jobs.len());
+
+    let requeued = get(&jobs[0].id).await.unwrap().unwrap();
+    assert_eq!(requeued.status, JobStatus::Queued);
+    assert_eq!((requeued.progress, requeued.started_at), (0, None));
+    for job in &jobs[1..3] {
+        let failed = get(&job.id).await.unwrap().unwrap();
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some(INTERRUPTED));
+    }
+    let cancelled = get(&jobs[3].id).await.unwrap().unwrap();
+    assert_eq!(cancelled.status, JobStatus::Cancelled);
+
+    // Started, the runner takes the requeued job from the top.
+    let cancel = CancellationToken::new();
+    runner.start(cancel.clone());
+    let done = wait_for(&jobs[0].id, |j| j.status.is_finished()).await;
+    assert_eq!(done.status, JobStatus::Done);
+    cancel.cancel();
+}
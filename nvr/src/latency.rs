@@ -0,0 +1,174 @@
+//! RTSP latency tuning of device inputs. Cameras on a clean wire want the
+//! demuxer's jitter buffer as small as possible; cameras on wifi want it
+//! large enough to reorder and wait for late packets. The JSON form of a
+//! device's `input_value` (see `crate::failover`) picks a curated set:
+//!
+//! ```json
+//! { "url": "rtsp://10.0.0.9/main", "latency_profile": "robust",
+//!   "advanced": { "max_delay": "3000000" } }
+//! ```
+//!
+//! [`PROFILES`] maps each profile to its FFmpeg input options;
+//! `advanced` is merged on top, so any option can still be set by hand, and
+//! a device's `extra_input_options` (see `crate::input_options`) over that.
+//! `GET /api/v1/input_profiles` serves the table so the UI can explain the
+//! tradeoffs, and the device's input status shows the profile in use.
+//!
+//! Input options are only read when an input is opened, so a profile change
+//! reconnects the input: the manager restarts the device's source with the
+//! new options (see [`crate::manager::update_tuned_pipe`]).
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, RwLock};
+
+use axum::{Router, routing::get};
+use serde::{Deserialize, Serialize};
+
+use crate::handler::{ApiJsonResult, BaseResponse, ok_json};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyProfile {
+    Low,
+    Balanced,
+    Robust,
+}
+
+/// The profile table: what each profile is for, and the options it sets on
+/// an `rtsp://` input. Delays are in microseconds, `buffer_size` in bytes.
+pub const PROFILES: [(LatencyProfile, &str, &[(&str, &str)]); 3] = [
+    (
+        LatencyProfile::Low,
+        "Smallest delay for wired cameras: no demuxer buffering and no packet \
+         reordering. A lossy link shows up as glitches.",
+        &[
+            ("fflags", "nobuffer"),
+            ("max_delay", "50000"),
+            ("reorder_queue_size", "0"),
+        ],
+    ),
+    (
+        LatencyProfile::Balanced,
+        "Half a second of jitter buffer: absorbs the odd late packet at a small \
+         delay.",
+        &[("max_delay", "500000"), ("reorder_queue_size", "500")],
+    ),
+    (
+        LatencyProfile::Robust,
+        "Up to two seconds of jitter buffer, a larger socket buffer and a longer \
+         timeout, for wifi or congested links. Adds delay.",
+        &[
+            ("max_delay", "2000000"),
+            ("reorder_queue_size", "2000"),
+            ("buffer_size", "4194304"),
+            ("stimeout", "10000000"),
+        ],
+    ),
+];
+
+impl LatencyProfile {
+    /// The FFmpeg input options of the profile, from [`PROFILES`].
+    pub fn options(self) -> &'static [(&'static str, &'static str)] {
+        PROFILES
+            .iter()
+            .find(|(profile, ..)| *profile == self)
+            .map_or(&[], |(_, _, options)| options)
+    }
+}
+
+/// The tuning fields of a device's input JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputTuning {
+    /// Only applies to `rtsp://` inputs.
+    pub latency_profile: Option<LatencyProfile>,
+    /// Raw FFmpeg input options, merged over the profile's.
+    pub advanced: HashMap<String, String>,
+    /// The device's custom FFmpeg input options, merged over everything
+    /// else (see `crate::input_options`).
+    pub extra_input_options: HashMap<String, String>,
+}
+
+impl InputTuning {
+    /// Layer the tuning over `options` (the input's base options): the
+    /// profile's options when `rtsp` applies them, then `advanced`, then the
+    /// extra options.
+    pub fn apply(&self, rtsp: bool, options: &mut HashMap<String, String>) {
+        if rtsp && let Some(profile) = self.latency_profile {
+            for (key, value) in profile.options() {
+                options.insert(key.to_string(), value.to_string());
+            }
+        }
+        for (key, value) in self.advanced.iter().chain(&self.extra_input_options) {
+            options.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+/// The profile each managed device was last started with.
+static ACTIVE: LazyLock<RwLock<HashMap<String, LatencyProfile>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// The latency profile `device_id` runs with, `None` for FFmpeg's defaults.
+pub fn active_profile(device_id: &str) -> Option<LatencyProfile> {
+    ACTIVE.read().unwrap().get(device_id).copied()
+}
+
+/// Record the profile `device_id` is (re)started with; returns the previous
+/// one.
+pub(crate) fn set_active(
+    device_id: &str,
+    profile: Option<LatencyProfile>,
+) -> Option<LatencyProfile> {
+    let mut active = ACTIVE.write().unwrap();
+    match profile {
+        Some(profile) => active.insert(device_id.to_string(), profile),
+        None => active.remove(device_id),
+    }
+}
+
+/// One row of [`PROFILES`], as the API returns it.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub(crate) struct InputProfile {
+    profile: LatencyProfile,
+    /// What the profile trades for what.
+    summary: String,
+    /// The FFmpeg input options it sets.
+    options: BTreeMap<String, String>,
+}
+
+pub(crate) fn latency_router() -> Router {
+    Router::new().route("/", get(input_profiles))
+}
+
+/// The schema of [`latency_router`], mounted at `/api/v1/input_profiles`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(input_profiles))]
+pub(crate) struct LatencyApi;
+
+/// `GET /api/input_profiles`: the latency profiles and their options.
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "device",
+    responses((status = 200, body = BaseResponse<Vec<InputProfile>>))
+)]
+async fn input_profiles() -> ApiJsonResult<Vec<InputProfile>> {
+    Ok(ok_json(
+        PROFILES
+            .iter()
+            .map(|(profile, summary, options)| InputProfile {
+                profile: *profile,
+                summary: summary.to_string(),
+                options: options
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+#[path = "latency_test.rs"]
+mod latency_test;
@@ -0,0 +1,154 @@
+use media_pipe_core::InputConfig;
+
+use super::*;
+use crate::manager::input_options;
+
+fn rtsp() -> InputConfig {
+    InputConfig::Network {
+        url: "rtsp://cam/main".to_string(),
+        net: Default::default(),
+    }
+}
+
+fn options_with(
+    profile: Option<LatencyProfile>,
+    advanced: &[(&str, &str)],
+) -> HashMap<String, String> {
+    let tuning = InputTuning {
+        latency_profile: profile,
+        advanced: advanced
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        ..Default::default()
+    };
+    input_options(&rtsp(), &tuning).unwrap()
+}
+
+#[test]
+fn each_profile_maps_to_its_option_set() {
+    let base = options_with(None, &[]);
+    assert_eq!(
+        base,
+        HashMap::from([
+            ("rtsp_transport".to_string(), "tcp".to_string()),
+            ("stimeout".to_string(), "5000000".to_string()),
+        ])
+    );
+
+    let low = options_with(Some(LatencyProfile::Low), &[]);
+    assert_eq!(low["fflags"], "nobuffer");
+    assert_eq!(low["max_delay"], "50000");
+    assert_eq!(low["reorder_queue_size"], "0");
+    assert_eq!(low["rtsp_transport"], "tcp");
+
+    let balanced = options_with(Some(LatencyProfile::Balanced), &[]);
+    assert_eq!(balanced["max_delay"], "500000");
+    assert_eq!(balanced["reorder_queue_size"], "500");
+    assert!(!balanced.contains_key("fflags"));
+
+    let robust = options_with(Some(LatencyProfile::Robust), &[]);
+    assert_eq!(robust["max_delay"], "2000000");
+    assert_eq!(robust["reorder_queue_size"], "2000");
+    assert_eq!(robust["buffer_size"], "4194304");
+    // The profile's timeout replaces the base one.
+    assert_eq!(robust["stimeout"], "10000000");
+
+    // Every profile sets its whole table row, and keeps TCP transport.
+    for (profile, _, table) in PROFILES {
+        let options = options_with(Some(profile), &[]);
+        for (key, value) in table {
+            assert_eq!(options[*key], *value, "{profile:?} {key}");
+        }
+        assert_eq!(options["rtsp_transport"], "tcp");
+    }
+}
+
+#[test]
+fn advanced_overrides_the_profile() {
+    let options = options_with(
+        Some(LatencyProfile::Robust),
+        &[
+            ("max_delay", "3000000"),
+            ("rtsp_transport", "udp"),
+            ("probesize", "32"),
+        ],
+    );
+    assert_eq!(options["max_delay"], "3000000");
+    assert_eq!(options["rtsp_transport"], "udp");
+    assert_eq!(options["probesize"], "32");
+    assert_eq!(options["reorder_queue_size"], "2000");
+}
+
+#[test]
+fn profiles_only_tune_rtsp_inputs() {
+    let tuning = InputTuning {
+        latency_profile: Some(LatencyProfile::Low),
+        ..Default::default()
+    };
+    let file = InputConfig::File {
+        path: "/tmp/a.mp4".to_string(),
+    };
+    assert_eq!(input_options(&file, &tuning), None);
+
+    let tuning = InputTuning {
+        latency_profile: Some(LatencyProfile::Low),
+        advanced: HashMap::from([("rw_timeout".to_string(), "1000000".to_string())]),
+        ..Default::default()
+    };
+    let rtmp = InputConfig::Network {
+        url: "rtmp://cdn/live/x".to_string(),
+        net: Default::default(),
+    };
+    assert_eq!(
+        input_options(&rtmp, &tuning),
+        Some(HashMap::from([(
+            "rw_timeout".to_string(),
+            "1000000".to_string()
+        )]))
+    );
+}
+
+#[test]
+fn tuning_parses_from_the_device_input_json() {
+    let input = crate::failover::FailoverInput::parse(
+        r#"{"url": "rtsp://a", "latency_profile": "robust", "advanced": {"max_delay": "1"}}"#,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(input.tuning.latency_profile, Some(LatencyProfile::Robust));
+    assert_eq!(input.tuning.advanced["max_delay"], "1");
+    assert!(input.failover_urls.is_empty());
+
+    assert!(
+        crate::failover::FailoverInput::parse(r#"{"url": "rtsp://a", "latency_profile": "fast"}"#)
+            .is_err()
+    );
+}
+
+#[test]
+fn extra_options_override_the_profile_and_advanced() {
+    let tuning = InputTuning {
+        latency_profile: Some(LatencyProfile::Low),
+        advanced: HashMap::from([("max_delay".to_string(), "100000".to_string())]),
+        extra_input_options: HashMap::from([
+            ("max_delay".to_string(), "0".to_string()),
+            ("fflags".to_string(), "+genpts".to_string()),
+            ("use_wallclock_as_timestamps".to_string(), "1".to_string()),
+        ]),
+    };
+    // What `Pipe::start` hands on to `AvInput::new` as the demuxer options.
+    let options = input_options(&rtsp(), &tuning).unwrap();
+    assert_eq!(options["fflags"], "+genpts");
+    assert_eq!(options["max_delay"], "0");
+    assert_eq!(options["use_wallclock_as_timestamps"], "1");
+    assert_eq!(options["reorder_queue_size"], "0");
+    assert_eq!(options["rtsp_transport"], "tcp");
+
+    let input = crate::failover::FailoverInput::parse(
+        r#"{"url": "rtsp://a", "extra_input_options": {"rtsp_flags": "prefer_tcp"}}"#,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(input.tuning.extra_input_options["rtsp_flags"], "prefer_tcp");
+}
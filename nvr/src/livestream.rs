@@ -92,6 +92,7 @@ pub(crate) async fn run_session(
     let config = PipeConfig {
         input: InputConfig::Network {
             url: resolved.url.clone(),
+            net: Default::default(),
         },
         outputs: media_pipe_zlm::zlm_outputs(media, include_audio),
     };
@@ -6,22 +6,51 @@ use crate::db::init_app_db;
 mod api;
 mod asr;
 mod audiomixer;
+mod audit;
 mod auth;
+mod canvas;
 mod cleanup;
+mod clock;
 mod compositor;
 mod config;
 mod db;
 mod detect;
+mod device_input;
+mod discovery;
+mod encryption;
+mod export;
+mod failover;
+mod federation;
 mod gb;
 mod handler;
+mod health;
 mod init;
+mod input_options;
+mod jobs;
+mod latency;
 mod livestream;
+mod maintenance;
 mod manager;
 mod metrics;
+mod mjpeg;
 mod onvif;
+mod openapi;
+mod privacy;
 mod program;
 mod proxy;
+mod reconcile;
+mod setup;
+mod snapshot;
+mod storyboard;
+mod stream_key;
+mod supervisor;
+mod thumbnail;
 mod transport;
+mod tz;
+mod usage;
+mod viewers;
+mod wall;
+mod ws_preview;
 mod xiaomi;
 mod zlm;
 
@@ -42,6 +71,11 @@ async fn main() -> ! {
     init_logging();
     ffmpeg_bus::init().expect("ffmpeg_bus init");
 
+    // bound the media held in flight across all pipes
+    if let Some(bytes) = config::config().memory_budget() {
+        ffmpeg_bus::memory::MemoryBudget::global().set_limit(bytes);
+    }
+
     // migrate database
     let config = config::config();
     nvr_db::migrations::migrate(config.db_url())
@@ -53,14 +87,49 @@ async fn main() -> ! {
 
     // init app db
     init_app_db(config.db_url()).await.unwrap();
-    nvr_db::migrations::ensure_default_admin_user(config.db_url())
-        .await
-        .unwrap_or_else(|e| {
-            log::error!("Error ensuring default admin user: {}", e);
-            std::process::exit(1);
-        });
+
+    // `nvr reset [--confirm]`: reopen first-run setup, then exit
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("reset") {
+        let confirm = args.iter().any(|arg| arg == "--confirm");
+        match setup::reset(confirm).await {
+            Ok(()) => {
+                log::info!("Reset done: first-run setup runs on the next start");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                log::error!("{:#}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // enter first-run setup mode on a fresh install (no users yet), or apply
+    // the settings picked in it
+    setup::init().await.unwrap_or_else(|e| {
+        log::error!("Error reading first-run setup state: {:#}", e);
+        std::process::exit(1);
+    });
+
+    // give devices stored before stream keys existed their key, before any
+    // pipe publishes or the API lists URLs
+    match stream_key::backfill().await {
+        Ok(0) => {}
+        Ok(n) => log::info!("Assigned stream keys to {n} devices"),
+        Err(e) => log::error!("Error assigning device stream keys: {:#}", e),
+    }
+
+    // come back up in maintenance mode if the server stopped in it, before
+    // any pipe starts recording
+    maintenance::restore().await;
+
+    // drop decrypted copies a previous run left behind, and check the
+    // recording keys
+    encryption::init();
 
     let cancel = CancellationToken::new();
+    // let camera discovery scans end with the server
+    discovery::init(cancel.clone());
 
     let (ready_tx, ready_rx) = oneshot::channel();
     // start zlm server
@@ -86,10 +155,53 @@ async fn main() -> ! {
     // per the policy configured on the dashboard Settings page)
     cleanup::spawn_worker(cancel.clone());
 
+    // start the camera clock-skew monitor (RTCP / ONVIF offsets per device)
+    clock::spawn_worker(cancel.clone());
+
+    // start the privacy-mode scheduler (ends `until`-limited privacy, applies
+    // recurring privacy windows)
+    privacy::spawn_worker(cancel.clone());
+
     // start the system-metrics sampler (CPU / memory / network into a cache the
     // dashboard homepage polls)
     metrics::spawn_worker(cancel.clone());
 
+    // start the federation poller (merges peer nodes' devices into the
+    // listing; a no-op unless NVR_FEDERATION_PEERS is set)
+    federation::spawn_worker(cancel.clone());
+
+    // start the device-grid thumbnail capturer (a no-op when
+    // NVR_THUMBNAIL_INTERVAL_SECS=0)
+    thumbnail::spawn_worker(cancel.clone());
+
+    // start the stream health scorer (fps / loss / reconnects / bitrate per
+    // running device pipe)
+    health::spawn_worker(cancel.clone());
+
+    // save the restart attempts of supervised pipes (backoff / flap parking)
+    supervisor::spawn_worker(cancel.clone());
+
+    // start the data-usage sampler (ingress / egress bytes per device into
+    // hourly buckets, rolled up into days and months)
+    usage::spawn_worker(cancel.clone());
+
+    // start the live-viewer sweeper (ends HLS sessions whose player stopped
+    // fetching)
+    viewers::spawn_worker(cancel.clone());
+
+    // start the analytics worker (runs each device's configured detector and
+    // turns detections into events)
+    detect::analytics::spawn_worker(cancel.clone());
+
+    // start the job runner (clip exports, ...), after settling the jobs the
+    // last stop cut off: idempotent ones run again, the rest fail
+    jobs::start(cancel.clone()).await;
+
+    // reconcile the recordings root with the segment table (missing files,
+    // untracked or cut-off recordings); waits a few seconds at most, the rest
+    // of the pass runs in the background
+    reconcile::run_at_startup().await;
+
     // start api server
     let cancel_clone = cancel.clone();
     api::start_api_server(cancel_clone, 18080);
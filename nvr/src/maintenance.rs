@@ -0,0 +1,403 @@
+//! Maintenance mode, for upgrading the binary without cutting recordings off
+//! mid-segment. `POST /api/v1/admin/maintenance {enabled: true,
+//! drain_timeout_s}` switches it on:
+//!
+//! - new live viewer sessions (see [`crate::viewers`]) and new export jobs
+//!   are refused; the open ones go on;
+//! - every recording device records until its current segment closes (ZLM
+//!   reports each, see `crate::zlm::server`), then its pipe is rebuilt
+//!   without recording. Devices that reach no boundary within the drain
+//!   timeout are rebuilt then: the old pipe stops through the bus's graceful
+//!   shutdown, and ZLM finalizes the partial segment as its Media goes;
+//! - motion recordings (see `crate::manager::start_motion_recording`) stop
+//!   at once: the bus closes the file it is writing, so nothing is cut off,
+//!   and no new ones start;
+//! - `GET /readyz` answers 503 while `GET /healthz` stays 200, so a load
+//!   balancer stops sending traffic but nothing restarts the server.
+//!
+//! `GET /api/v1/admin/maintenance/status` reports what is left to drain;
+//! once nothing is, the binary can be replaced. Switching maintenance off
+//! rebuilds every pipe, recording again. The state lives in the KV config
+//! (`maintenance`), so a server restarted in maintenance comes back up in it,
+//! its pipes recording nothing.
+
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{
+    Extension, Json, Router,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::auth::AuthUser;
+use crate::db::app_db_conn;
+use crate::handler::{ApiError, ApiResult, ok_json};
+use crate::viewers::Registry;
+
+/// KV config key of the maintenance state.
+const MAINTENANCE_KEY: &str = "maintenance";
+/// Drain timeout when none is given: two ZLM record segments (60 s each).
+const DEFAULT_DRAIN_TIMEOUT_S: u64 = 120;
+/// Capacity of the closed-segment broadcast.
+const SEGMENT_CHAN_CAP: usize = 256;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceState {
+    #[serde(default)]
+    pub enabled: bool,
+    /// When maintenance was switched on, unix milliseconds.
+    #[serde(default)]
+    pub since: Option<i64>,
+    /// How long recordings get to reach a segment boundary.
+    #[serde(default)]
+    pub drain_timeout_s: u64,
+}
+
+/// Whether the server is in maintenance, as last applied.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether the server is in maintenance. Cheap; checked by the pipe
+/// builders and the export API.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// ZLM streams whose record segment just closed.
+static SEGMENTS: LazyLock<broadcast::Sender<String>> =
+    LazyLock::new(|| broadcast::channel(SEGMENT_CHAN_CAP).0);
+
+/// A record segment of ZLM stream `stream` closed.
+pub(crate) fn segment_closed(stream: &str) {
+    let _ = SEGMENTS.send(stream.to_string());
+}
+
+/// One drain of the recording outputs.
+pub(crate) struct Drain {
+    /// Devices whose recording is not finalized yet.
+    remaining: Mutex<BTreeSet<String>>,
+    /// Fires when maintenance is switched off before the drain is done.
+    cancel: CancellationToken,
+}
+
+/// The drain of the current maintenance, if any.
+static DRAIN: LazyLock<Mutex<Option<Arc<Drain>>>> = LazyLock::new(|| Mutex::new(None));
+
+impl Drain {
+    pub(crate) fn new(devices: impl IntoIterator<Item = String>) -> Arc<Self> {
+        Arc::new(Self {
+            remaining: Mutex::new(devices.into_iter().collect()),
+            cancel: CancellationToken::new(),
+        })
+    }
+
+    pub(crate) fn remaining(&self) -> Vec<String> {
+        self.remaining.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Finalize the recording of every device of `streams` (device id to
+    /// its ZLM stream) with `finalize`: each at its next segment boundary,
+    /// those still recording after `timeout` then. Ends early when
+    /// cancelled.
+    pub(crate) async fn run<F, Fut>(
+        &self,
+        streams: HashMap<String, String>,
+        timeout: Duration,
+        finalize: F,
+    ) where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut segments = SEGMENTS.subscribe();
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut waiting: HashMap<String, String> = streams
+            .into_iter()
+            .map(|(device, stream)| (stream, device))
+            .collect();
+        while !waiting.is_empty() {
+            let stream = tokio::select! {
+                _ = self.cancel.cancelled() => return,
+                _ = tokio::time::sleep_until(deadline) => break,
+                closed = segments.recv() => match closed {
+                    Ok(stream) => stream,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            if let Some(device) = waiting.remove(&stream) {
+                log::info!("maintenance: {device} closed its segment, stopping its recording");
+                self.finish(device, &finalize).await;
+            }
+        }
+        if !waiting.is_empty() {
+            log::warn!(
+                "maintenance: {} recording(s) reached no segment boundary in {timeout:?}, \
+                 finishing them now",
+                waiting.len()
+            );
+        }
+        for device in waiting.into_values() {
+            if self.cancel.is_cancelled() {
+                return;
+            }
+            self.finish(device, &finalize).await;
+        }
+    }
+
+    async fn finish<F, Fut>(&self, device: String, finalize: &F)
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        finalize(device.clone()).await;
+        self.remaining.lock().unwrap().remove(&device);
+    }
+}
+
+pub async fn load() -> Result<MaintenanceState> {
+    let conn = app_db_conn()?;
+    Ok(nvr_db::config::get_json(MAINTENANCE_KEY, &conn)
+        .await?
+        .unwrap_or_default())
+}
+
+async fn save(state: &MaintenanceState) -> Result<()> {
+    let conn = app_db_conn()?;
+    nvr_db::config::set_json(MAINTENANCE_KEY, state, &conn).await
+}
+
+/// Come back up in maintenance if the server was stopped in it. Runs before
+/// device pipes are first built, so they start without recording.
+pub async fn restore() {
+    match load().await {
+        Ok(state) if state.enabled => {
+            log::warn!("maintenance: still on, pipes start without recording");
+            close(true);
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("maintenance: failed to load state: {e:#}"),
+    }
+}
+
+/// Serializes switching maintenance on and off.
+static SWITCH: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Switch maintenance on (draining within `drain_timeout_s`, by default
+/// [`DEFAULT_DRAIN_TIMEOUT_S`]) or off, persist it and apply it at once.
+/// Switching to the state the server is in changes nothing.
+pub async fn set(enabled: bool, drain_timeout_s: Option<u64>) -> Result<MaintenanceState> {
+    let _switch = SWITCH.lock().await;
+    let current = load().await?;
+    if current.enabled == enabled && is_active() == enabled {
+        return Ok(current);
+    }
+    let state = if enabled {
+        MaintenanceState {
+            enabled: true,
+            since: Some(Utc::now().timestamp_millis()),
+            drain_timeout_s: drain_timeout_s.unwrap_or(DEFAULT_DRAIN_TIMEOUT_S),
+        }
+    } else {
+        MaintenanceState::default()
+    };
+    save(&state).await?;
+    if enabled {
+        enter(Duration::from_secs(state.drain_timeout_s)).await;
+    } else {
+        leave().await;
+    }
+    Ok(state)
+}
+
+/// Refuse (or admit again) new live sessions and exports.
+fn close(closed: bool) {
+    ACTIVE.store(closed, Ordering::SeqCst);
+    Registry::global().set_closed(closed);
+}
+
+async fn enter(timeout: Duration) {
+    close(true);
+    let streams = recording_streams().await;
+    let motion = crate::manager::motion_recording_ids();
+    log::warn!(
+        "maintenance: on, draining {} recording(s) within {timeout:?} and {} motion recording(s)",
+        streams.len(),
+        motion.len()
+    );
+    let drain = Drain::new(streams.keys().chain(&motion).cloned());
+    if let Some(old) = DRAIN.lock().unwrap().replace(drain.clone()) {
+        old.cancel.cancel();
+    }
+    tokio::spawn(async move {
+        for device in motion {
+            if let Err(e) = crate::manager::stop_motion_recording(&device).await {
+                log::warn!("maintenance: failed to stop motion recording of {device}: {e:#}");
+            }
+            if !streams.contains_key(&device) {
+                drain.remaining.lock().unwrap().remove(&device);
+            }
+        }
+        drain
+            .run(streams, timeout, |device| async move {
+                if let Err(e) = rebuild(&device).await {
+                    log::warn!("maintenance: failed to rebuild device {device}: {e:#}");
+                }
+            })
+            .await;
+        if !drain.cancel.is_cancelled() {
+            log::info!("maintenance: recordings drained");
+        }
+    });
+}
+
+async fn leave() {
+    close(false);
+    if let Some(drain) = DRAIN.lock().unwrap().take() {
+        drain.cancel.cancel();
+    }
+    log::info!("maintenance: off, restarting pipes");
+    for id in crate::manager::list_pipe_ids().await {
+        if let Err(e) = rebuild(&id).await {
+            log::warn!("maintenance: failed to rebuild device {id}: {e:#}");
+        }
+    }
+}
+
+/// The running devices that record, with their ZLM streams.
+async fn recording_streams() -> HashMap<String, String> {
+    let mut streams = HashMap::new();
+    let conn = match app_db_conn() {
+        Ok(conn) => conn,
+        Err(e) => {
+            log::warn!("maintenance: failed to list devices: {e:#}");
+            return streams;
+        }
+    };
+    for id in crate::manager::list_pipe_ids().await {
+        match nvr_db::device::get(&id, &conn).await {
+            Ok(Some(device)) if device.record && !crate::privacy::is_private(&id) => {
+                streams.insert(id, device.stream_name().to_string());
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("maintenance: failed to load device {id}: {e:#}"),
+        }
+    }
+    streams
+}
+
+/// Rebuild the device's pipe so it picks up the current maintenance state.
+async fn rebuild(device_id: &str) -> Result<()> {
+    let conn = app_db_conn()?;
+    match nvr_db::device::get(device_id, &conn).await? {
+        Some(device) => crate::init::device::ensure_device_pipe(&device).await,
+        None => Ok(()),
+    }
+}
+
+/// Liveness and readiness probes, outside the API (no session needed).
+pub(crate) fn probe_router() -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+/// The process is up.
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// The server takes traffic: not in maintenance.
+async fn readyz() -> Response {
+    if is_active() {
+        (StatusCode::SERVICE_UNAVAILABLE, "maintenance").into_response()
+    } else {
+        "ok".into_response()
+    }
+}
+
+pub fn admin_router() -> Router {
+    Router::new()
+        .route("/maintenance", post(set_maintenance))
+        .route("/maintenance/status", get(get_status))
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+    #[serde(default)]
+    drain_timeout_s: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct MaintenanceStatus {
+    #[serde(flatten)]
+    state: MaintenanceState,
+    /// Devices whose recording is not finalized yet.
+    outputs_remaining: Vec<String>,
+    /// Live viewer sessions still open.
+    sessions_remaining: usize,
+    /// Export jobs still queued or running.
+    exports_remaining: usize,
+    /// In maintenance with nothing left: the server can be stopped.
+    drained: bool,
+}
+
+async fn status() -> Result<MaintenanceStatus> {
+    let state = load().await?;
+    let outputs_remaining = DRAIN
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|drain| drain.remaining())
+        .unwrap_or_default();
+    let sessions_remaining = Registry::global().total();
+    let exports_remaining = crate::export::list()
+        .await?
+        .iter()
+        .filter(|job| job.status == crate::export::ExportStatus::Running)
+        .count();
+    let drained = state.enabled
+        && outputs_remaining.is_empty()
+        && sessions_remaining == 0
+        && exports_remaining == 0;
+    Ok(MaintenanceStatus {
+        state,
+        outputs_remaining,
+        sessions_remaining,
+        exports_remaining,
+        drained,
+    })
+}
+
+/// Switch maintenance on or off; answers with its status.
+async fn set_maintenance(
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<MaintenanceRequest>,
+) -> ApiResult<Response> {
+    if !crate::auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden("maintenance mode is admin-only"));
+    }
+    set(req.enabled, req.drain_timeout_s).await?;
+    Ok(ok_json(status().await?).into_response())
+}
+
+/// What is left to drain.
+async fn get_status(Extension(user): Extension<AuthUser>) -> ApiResult<Response> {
+    if !crate::auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden("maintenance mode is admin-only"));
+    }
+    Ok(ok_json(status().await?).into_response())
+}
+
+#[cfg(test)]
+#[path = "maintenance_test.rs"]
+mod maintenance_test;
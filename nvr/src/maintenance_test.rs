@@ -0,0 +1,230 @@
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::http::Request as HttpRequest;
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+use media_pipe_core::{InputConfig, PipeConfig};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+use super::*;
+
+fn stream() -> String {
+    format!("maint-{}", uuid::Uuid::new_v4())
+}
+
+#[tokio::test]
+async fn drain_finalizes_at_segment_boundaries_then_at_the_timeout() {
+    let (early, late) = (stream(), stream());
+    let streams = HashMap::from([
+        ("cam-early".to_string(), early.clone()),
+        ("cam-late".to_string(), late.clone()),
+    ]);
+    let drain = Drain::new(streams.keys().cloned());
+    assert_eq!(drain.remaining(), ["cam-early", "cam-late"]);
+
+    let started = Instant::now();
+    let finalized = Mutex::new(Vec::new());
+    let timeout = Duration::from_millis(400);
+    tokio::join!(
+        drain.run(streams, timeout, |device| {
+            finalized.lock().unwrap().push((device, started.elapsed()));
+            async {}
+        }),
+        async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            segment_closed("some-other-stream");
+            segment_closed(&early);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(drain.remaining(), ["cam-late"]);
+        },
+    );
+
+    let finalized = finalized.into_inner().unwrap();
+    assert_eq!(finalized.len(), 2);
+    assert_eq!(finalized[0].0, "cam-early");
+    assert!(finalized[0].1 < timeout, "{finalized:?}");
+    // No boundary for this one: finished at the timeout.
+    assert_eq!(finalized[1].0, "cam-late");
+    assert!(finalized[1].1 >= timeout, "{finalized:?}");
+    assert!(drain.remaining().is_empty());
+}
+
+#[tokio::test]
+async fn cancelled_drain_finalizes_nothing_more() {
+    let streams = HashMap::from([("cam".to_string(), stream())]);
+    let drain = Drain::new(streams.keys().cloned());
+    drain.cancel.cancel();
+    drain
+        .run(streams, Duration::from_millis(10), |device| async move {
+            panic!("finalized {device}")
+        })
+        .await;
+    assert_eq!(drain.remaining(), ["cam"]);
+}
+
+async fn call(app: Router, req: HttpRequest<Body>) -> (StatusCode, Value) {
+    let res = app.oneshot(req).await.unwrap();
+    let status = res.status();
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn request(method: &str, uri: &str, token: &str, body: Option<Value>) -> HttpRequest<Body> {
+    HttpRequest::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {token}"))
+        .header("Content-Type", "application/json")
+        .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+        .unwrap()
+}
+
+fn api() -> Router {
+    crate::api::api_router_with(crate::setup::Setup::new(false))
+}
+
+async fn probe(uri: &str) -> StatusCode {
+    let req = HttpRequest::get(uri).body(Body::empty()).unwrap();
+    probe_router().oneshot(req).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn maintenance_closes_the_server_until_switched_off() {
+    let _db = crate::db::test_db().await;
+    let admin = crate::auth::admin_session().await;
+    let on = json!({"enabled": true, "drain_timeout_s": 1});
+
+    // Admins only.
+    let someone = crate::auth::create_session("not-an-admin").await.unwrap();
+    let (status, _) = call(
+        api(),
+        request("POST", "/admin/maintenance", &someone, Some(on.clone())),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(!is_active());
+
+    let (status, body) = call(
+        api(),
+        request("POST", "/admin/maintenance", &admin, Some(on)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["data"]["enabled"], true);
+    assert_eq!(body["data"]["drain_timeout_s"], 1);
+    assert_eq!(body["data"]["outputs_remaining"], json!([]));
+    assert!(is_active());
+
+    // Not ready, still alive.
+    assert_eq!(probe("/readyz").await, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(probe("/healthz").await, StatusCode::OK);
+
+    // No new live sessions or exports.
+    let rejection = Registry::global().admit("cam", None).unwrap_err();
+    assert_eq!(rejection.scope, crate::viewers::LimitScope::Maintenance);
+    let export = crate::export::start("cam", 0, 1000, false, "admin").await;
+    assert!(format!("{:#}", export.unwrap_err()).contains("maintenance"));
+
+    let (status, body) = call(
+        api(),
+        request("GET", "/admin/maintenance/status", &admin, None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["enabled"], true);
+    assert!(body["data"]["since"].as_i64().is_some());
+    assert!(body["data"]["sessions_remaining"].is_u64());
+
+    // A restart comes back up in maintenance.
+    close(false);
+    restore().await;
+    assert!(is_active());
+    assert_eq!(probe("/readyz").await, StatusCode::SERVICE_UNAVAILABLE);
+
+    let off = json!({"enabled": false});
+    let (status, body) = call(
+        api(),
+        request("POST", "/admin/maintenance", &admin, Some(off)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["data"]["enabled"], false);
+    assert_eq!(body["data"]["drained"], false);
+    assert!(!is_active());
+    assert_eq!(probe("/readyz").await, StatusCode::OK);
+    let _viewer = Registry::global().admit("cam", None).unwrap();
+    assert!(!load().await.unwrap().enabled);
+}
+
+#[tokio::test]
+async fn a_drain_closes_motion_recordings_into_playable_indexed_files() {
+    let _db = crate::db::test_db().await;
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let id = "maint-motion-cam";
+    let dir = crate::config::config().record_dir().join("motion").join(id);
+    let _ = std::fs::remove_dir_all(&dir);
+    let conn = crate::db::app_db_conn().unwrap();
+    nvr_db::record_segment::delete_by_stream(id, &conn)
+        .await
+        .unwrap();
+    crate::manager::add_pipe(
+        id,
+        PipeConfig {
+            input: InputConfig::FileLoop {
+                path: path.to_string_lossy().into_owned(),
+                realtime: true,
+            },
+            outputs: vec![],
+        },
+    )
+    .await
+    .unwrap();
+    crate::manager::wait_running(id).await;
+    crate::manager::start_motion_recording(id).await.unwrap();
+    // Past a keyframe or two (one a second), so the file has video.
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    set(true, Some(1)).await.unwrap();
+    let mut drained = false;
+    for _ in 0..50 {
+        if status().await.unwrap().outputs_remaining.is_empty() {
+            drained = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(drained, "motion recording not drained");
+    assert!(!crate::manager::motion_recording_ids().contains(&id.to_string()));
+    assert!(crate::manager::start_motion_recording(id).await.is_err());
+
+    let files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "{files:?}");
+    let info = ffmpeg_bus::metadata::probe(&files[0].to_string_lossy()).unwrap();
+    assert!(info.streams.iter().any(|s| s.codec_type == "video"));
+    let duration = info.format.duration_sec.unwrap_or_default();
+    assert!(duration > 1.0, "{}: {duration}s", files[0].display());
+    // Indexed as the device's recording, for playback and its timeline.
+    let segments = nvr_db::record_segment::list_by_stream(id, &conn)
+        .await
+        .unwrap();
+    assert_eq!(segments.len(), 1, "{segments:?}");
+    assert_eq!(segments[0].file_path, files[0].to_string_lossy());
+    assert!(
+        (f64::from(segments[0].duration) - duration).abs() < 0.5,
+        "{} s indexed, {duration} s probed",
+        segments[0].duration
+    );
+
+    set(false, None).await.unwrap();
+    crate::manager::remove_pipe(id).await.unwrap();
+    nvr_db::record_segment::delete_by_stream(id, &conn)
+        .await
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,12 +1,20 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, LazyLock},
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
 };
 
-use media_pipe_core::{InputConfig, Pipe, PipeConfig};
-use tokio::{sync::RwLock, task::JoinHandle};
+use ffmpeg_bus::bus::BusEvent;
+use media_pipe_core::{InputConfig, OutputConfig, Pipe, PipeConfig};
+use media_pipe_zlm::ts::TsSession;
+use tokio::{
+    sync::{RwLock, broadcast},
+    task::JoinHandle,
+};
 use tokio_util::sync::CancellationToken;
 
+use crate::latency::{InputTuning, LatencyProfile};
+
 /// One managed background source per device id: either an ffmpeg-driven `Pipe`
 /// (RTSP/file/v4l2 -> transcode -> ZLM) or a native worker thread (Xiaomi ->
 /// ZLM) that bypasses ffmpeg. Keeping both in one registry lets device
@@ -26,6 +34,45 @@ enum Entry {
         cancel: CancellationToken,
         handle: JoinHandle<()>,
     },
+    /// A pipe that only runs while someone holds a [`PipeHandle`] of it (see
+    /// [`upsert_on_demand`]).
+    OnDemand(Box<Demand>),
+}
+
+/// How [`upsert_on_demand`] runs a device's pipe.
+pub(crate) struct OnDemand {
+    /// The pipe's config, built again for every start.
+    pub(crate) config: Box<dyn Fn() -> anyhow::Result<PipeConfig> + Send + Sync>,
+    pub(crate) tuning: InputTuning,
+    /// Keep the pipe running whether anyone holds it or not (the device
+    /// records).
+    pub(crate) pinned: bool,
+    /// How long the pipe keeps running once its last handle is dropped.
+    pub(crate) grace: Duration,
+}
+
+/// The state of an [`Entry::OnDemand`].
+struct Demand {
+    spec: OnDemand,
+    /// [`PipeHandle`]s alive.
+    users: usize,
+    /// The running pipe's [`Entry::Pipe`].
+    running: Option<Box<Entry>>,
+    /// Cancels the stop pending since the last handle was dropped.
+    idle: Option<CancellationToken>,
+    /// Held from an idle stop until the pipe has unwound, so a new start
+    /// never overlaps the old pipe's ZLM Media.
+    lifecycle: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Demand {
+    fn start(&mut self, id: &str) -> anyhow::Result<()> {
+        let config = (self.spec.config)()?;
+        let options = input_options(&config.input, &self.spec.tuning);
+        retune(id, self.spec.tuning.latency_profile, options.as_ref());
+        self.running = Some(Box::new(spawn_pipe(id, config, options)));
+        Ok(())
+    }
 }
 
 impl Entry {
@@ -35,6 +82,14 @@ impl Entry {
             Entry::Pipe { pipe, .. } => pipe.cancel(),
             Entry::Worker { cancel, .. } => cancel.cancel(),
             Entry::Task { cancel, .. } => cancel.cancel(),
+            Entry::OnDemand(demand) => {
+                if let Some(idle) = &demand.idle {
+                    idle.cancel();
+                }
+                if let Some(running) = &demand.running {
+                    running.stop();
+                }
+            }
         }
     }
 
@@ -71,6 +126,16 @@ impl Entry {
                     }
                 }
             }
+            Entry::OnDemand(demand) => {
+                // Always an `Entry::Pipe`.
+                if let Some(running) = demand.running
+                    && let Entry::Pipe { handle, .. } = *running
+                    && let Err(e) = handle.await
+                    && !e.is_cancelled()
+                {
+                    log::warn!("pipe task ended with error: {}", e);
+                }
+            }
         }
     }
 
@@ -78,6 +143,15 @@ impl Entry {
         match self {
             Entry::Pipe { pipe, .. } => pipe.is_started(),
             Entry::Worker { .. } | Entry::Task { .. } => true,
+            Entry::OnDemand(demand) => demand.running.as_ref().is_some_and(|e| e.is_started()),
+        }
+    }
+
+    fn pipe(&self) -> Option<Arc<Pipe>> {
+        match self {
+            Entry::Pipe { pipe, .. } => Some(pipe.clone()),
+            Entry::Worker { .. } | Entry::Task { .. } => None,
+            Entry::OnDemand(demand) => demand.running.as_ref()?.pipe(),
         }
     }
 }
@@ -85,6 +159,11 @@ impl Entry {
 static PIPE_MANAGER: LazyLock<RwLock<HashMap<String, Entry>>> =
     LazyLock::new(|| RwLock::new(HashMap::new()));
 
+/// How long the workers sampling running pipes (thumbnails, health, analytics,
+/// usage) wait after startup before their first pass, so the pipes of enabled
+/// devices have come up.
+pub(crate) const STARTUP_DELAY: Duration = Duration::from_secs(10);
+
 /// Replace any existing entry for `id` with a freshly built one. The old entry
 /// is cancelled and fully joined (outside the manager lock) BEFORE the new one
 /// is built, so same-id handles (ZLM Media etc.) never overlap.
@@ -119,40 +198,300 @@ async fn upsert_entry(
 /// RTSP over UDP (FFmpeg's default) drops packets on lossy/jittery links, which
 /// corrupts the H264 stream ("RTP: missed packets" -> decode errors). Force TCP
 /// transport with a socket timeout for rtsp:// inputs. Transport policy lives
-/// here (the app) so `media-pipe-core` stays input-agnostic.
-fn input_options(input: &InputConfig) -> Option<HashMap<String, String>> {
-    match input {
-        InputConfig::Network { url } if url.starts_with("rtsp://") => Some(HashMap::from([
+/// here (the app) so `media-pipe-core` stays input-agnostic. The input's typed
+/// network options replace these defaults (the bus would apply them anyway;
+/// here they also show in the effective options), and the device's `tuning`
+/// (see `crate::latency`) is layered on top.
+pub(crate) fn input_options(
+    input: &InputConfig,
+    tuning: &InputTuning,
+) -> Option<HashMap<String, String>> {
+    let rtsp = matches!(input, InputConfig::Network { url, .. } if url.starts_with("rtsp://"));
+    let mut options = if rtsp {
+        HashMap::from([
             ("rtsp_transport".to_string(), "tcp".to_string()),
             ("stimeout".to_string(), "5000000".to_string()),
-        ])),
-        _ => None,
+        ])
+    } else {
+        HashMap::new()
+    };
+    if let InputConfig::Network { net, .. } = input {
+        for (key, value) in net.options() {
+            options.insert(key.to_string(), value);
+        }
     }
+    tuning.apply(rtsp, &mut options);
+    (!options.is_empty()).then_some(options)
 }
 
-async fn upsert_pipe(id: &str, config: PipeConfig, update_if_exists: bool) -> anyhow::Result<()> {
+/// Record the latency profile and the input options device `id` is
+/// (re)started with. Input options are only read when the input opens, so a
+/// changed profile takes effect through the restart of the source that
+/// follows.
+fn retune(id: &str, profile: Option<LatencyProfile>, options: Option<&HashMap<String, String>>) {
+    crate::input_options::set_effective(id, options);
+    let previous = crate::latency::set_active(id, profile);
+    if previous != profile {
+        log::info!("device {id}: latency profile {previous:?} -> {profile:?}, input reconnected");
+    }
+}
+
+async fn upsert_pipe(
+    id: &str,
+    config: PipeConfig,
+    tuning: InputTuning,
+    update_if_exists: bool,
+) -> anyhow::Result<()> {
+    let options = input_options(&config.input, &tuning);
+    let effective = options.clone();
+    let device_id = id.to_string();
     upsert_entry(
         id,
-        move || {
-            let options = input_options(&config.input);
-            let pipe = Arc::new(Pipe::new(config));
-            let pipe_for_task = Arc::clone(&pipe);
-            let handle = tokio::spawn(async move {
-                pipe_for_task.start(options).await;
-            });
-            Entry::Pipe { pipe, handle }
-        },
+        move || spawn_pipe(&device_id, config, options),
         update_if_exists,
     )
-    .await
+    .await?;
+    retune(id, tuning.latency_profile, effective.as_ref());
+    Ok(())
+}
+
+fn spawn_pipe(id: &str, config: PipeConfig, options: Option<HashMap<String, String>>) -> Entry {
+    let pipe = Arc::new(Pipe::new(config));
+    tokio::spawn(track_status(id.to_string(), pipe.events()));
+    // Restarted whenever it ends on its own (see `crate::supervisor`).
+    let handle = tokio::spawn(crate::supervisor::supervise(
+        id.to_string(),
+        Arc::clone(&pipe),
+        options,
+        crate::config::config().restart_policy(),
+    ));
+    Entry::Pipe { pipe, handle }
+}
+
+/// Start (or replace) device `id`'s pipe as an on-demand one: it runs while
+/// [`PipeHandle`]s of it are held (see [`acquire_pipe`]) or `spec` pins it,
+/// and stops `spec.grace` after the last handle is dropped. Handles of the
+/// entry it replaces count for the new one.
+pub(crate) async fn upsert_on_demand(id: &str, spec: OnDemand) -> anyhow::Result<()> {
+    let existing = PIPE_MANAGER.write().await.remove(id);
+    let users = match &existing {
+        Some(Entry::OnDemand(demand)) => demand.users,
+        _ => 0,
+    };
+    if let Some(old) = existing {
+        old.stop();
+        old.join().await;
+    }
+    let mut demand = Demand {
+        spec,
+        users,
+        running: None,
+        idle: None,
+        lifecycle: Arc::default(),
+    };
+    if demand.spec.pinned || users > 0 {
+        demand.start(id)?;
+    }
+    let mut pipes = PIPE_MANAGER.write().await;
+    pipes.insert(id.to_string(), Entry::OnDemand(Box::new(demand)));
+    Ok(())
+}
+
+/// How long [`acquire_pipe`] waits for a pipe it started to come up.
+const STARTUP_WAIT: Duration = Duration::from_secs(10);
+
+/// A hold on device `id`'s pipe, starting an on-demand one (see
+/// [`upsert_on_demand`]) if it is stopped; a pipe that runs all the time is
+/// just handed out. Once it was started, waits up to [`STARTUP_WAIT`] for its
+/// bus. `None` if the device has no pipe, or its pipe does not start.
+pub(crate) async fn acquire_pipe(id: &str) -> Option<PipeHandle> {
+    let lifecycle = match PIPE_MANAGER.read().await.get(id)? {
+        Entry::OnDemand(demand) => Arc::clone(&demand.lifecycle),
+        entry => {
+            return entry.pipe().map(|pipe| PipeHandle {
+                id: id.to_string(),
+                pipe,
+                counted: false,
+            });
+        }
+    };
+    // An idle stop still unwinding ends before the pipe starts again.
+    let lifecycle = lifecycle.lock().await;
+    let (pipe, started) = {
+        let mut pipes = PIPE_MANAGER.write().await;
+        // Replaced meanwhile.
+        let Some(Entry::OnDemand(demand)) = pipes.get_mut(id) else {
+            return None;
+        };
+        let started = demand.running.is_none();
+        if started && let Err(e) = demand.start(id) {
+            log::warn!("device {id}: starting its pipe on demand: {e:#}");
+            return None;
+        }
+        let pipe = demand.running.as_ref()?.pipe()?;
+        demand.users += 1;
+        if let Some(idle) = demand.idle.take() {
+            idle.cancel();
+        }
+        (pipe, started)
+    };
+    drop(lifecycle);
+    let handle = PipeHandle {
+        id: id.to_string(),
+        pipe,
+        counted: true,
+    };
+    if started {
+        log::info!("device {id}: pipe started on demand");
+        let deadline = tokio::time::Instant::now() + STARTUP_WAIT;
+        while !handle.pipe.is_running()
+            && !handle.pipe.is_cancelled()
+            && tokio::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    Some(handle)
+}
+
+/// A [`Pipe`] from [`acquire_pipe`]. Dropping the last handle of an
+/// on-demand pipe starts its grace period.
+pub(crate) struct PipeHandle {
+    id: String,
+    pipe: Arc<Pipe>,
+    /// Counted among its [`Demand`]'s users.
+    counted: bool,
+}
+
+impl PipeHandle {
+    pub(crate) fn pipe(&self) -> &Arc<Pipe> {
+        &self.pipe
+    }
+}
+
+impl Drop for PipeHandle {
+    fn drop(&mut self) {
+        // No runtime left to release into once it is shutting down.
+        if self.counted
+            && let Ok(runtime) = tokio::runtime::Handle::try_current()
+        {
+            runtime.spawn(release(std::mem::take(&mut self.id)));
+        }
+    }
+}
+
+/// One [`PipeHandle`] of `id` dropped: past the last, stop the pipe once it
+/// stays unheld for its grace period.
+async fn release(id: String) {
+    let mut pipes = PIPE_MANAGER.write().await;
+    let Some(Entry::OnDemand(demand)) = pipes.get_mut(&id) else {
+        return;
+    };
+    demand.users = demand.users.saturating_sub(1);
+    if demand.users > 0 || demand.spec.pinned || demand.running.is_none() {
+        return;
+    }
+    let idle = CancellationToken::new();
+    demand.idle = Some(idle.clone());
+    let (grace, lifecycle) = (demand.spec.grace, Arc::clone(&demand.lifecycle));
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = idle.cancelled() => return,
+            _ = tokio::time::sleep(grace) => {}
+        }
+        let _lifecycle = lifecycle.lock().await;
+        let running = {
+            let mut pipes = PIPE_MANAGER.write().await;
+            // Acquired again, replaced or removed meanwhile.
+            if idle.is_cancelled() {
+                return;
+            }
+            let Some(Entry::OnDemand(demand)) = pipes.get_mut(&id) else {
+                return;
+            };
+            demand.idle = None;
+            demand.running.take()
+        };
+        if let Some(running) = running {
+            log::info!("device {id}: unwatched for {grace:?}, stopping its pipe");
+            running.stop();
+            running.join().await;
+            set_status(&id, nvr_db::device::STATUS_OFFLINE).await;
+        }
+    });
+}
+
+/// Handles held for ZLM's players of a device's stream, by device id (see
+/// [`hold_for_players`]).
+static PLAYERS: LazyLock<Mutex<HashMap<String, PipeHandle>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// A player asked ZLM for device `id`'s stream, which is not published: hold
+/// its pipe until [`release_for_players`], starting it if it is on demand.
+pub(crate) async fn hold_for_players(id: &str) {
+    if PLAYERS.lock().unwrap().contains_key(id) {
+        return;
+    }
+    let Some(handle) = acquire_pipe(id).await else {
+        return;
+    };
+    PLAYERS
+        .lock()
+        .unwrap()
+        .entry(id.to_string())
+        .or_insert(handle);
+}
+
+/// ZLM's last player of device `id`'s stream left.
+pub(crate) fn release_for_players(id: &str) {
+    let handle = PLAYERS.lock().unwrap().remove(id);
+    drop(handle);
+}
+
+/// Keep device `id`'s stored status in step with what its pipe's input
+/// reports, across restarts, until the pipe is dropped.
+async fn track_status(id: String, mut events: broadcast::Receiver<BusEvent>) {
+    use nvr_db::device::{STATUS_ERROR, STATUS_OFFLINE, STATUS_ONLINE};
+
+    loop {
+        let status = match events.recv().await {
+            Ok(BusEvent::InputOpened { .. }) => STATUS_ONLINE,
+            Ok(BusEvent::InputEof { .. }) => STATUS_OFFLINE,
+            Ok(BusEvent::InputError { .. }) => STATUS_ERROR,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        set_status(&id, status).await;
+    }
+}
+
+async fn set_status(id: &str, status: &str) {
+    let updated = match crate::db::app_db_conn() {
+        Ok(conn) => nvr_db::device::update_status(id, status, &conn).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = updated {
+        log::warn!("device {id}: saving status {status}: {e:#}");
+    }
 }
 
 pub(crate) async fn add_pipe(id: &str, config: PipeConfig) -> anyhow::Result<()> {
-    upsert_pipe(id, config, false).await
+    upsert_pipe(id, config, InputTuning::default(), false).await
 }
 
 pub(crate) async fn update_pipe(id: &str, config: PipeConfig) -> anyhow::Result<()> {
-    upsert_pipe(id, config, true).await
+    upsert_pipe(id, config, InputTuning::default(), true).await
+}
+
+/// [`update_pipe`] for a device input with latency tuning. A running pipe is
+/// restarted, which reconnects its input with the new options: a `Pipe`
+/// cannot reopen its input under outputs that stay up.
+pub(crate) async fn update_tuned_pipe(
+    id: &str,
+    config: PipeConfig,
+    tuning: InputTuning,
+) -> anyhow::Result<()> {
+    upsert_pipe(id, config, tuning, true).await
 }
 
 /// Start (or replace) a native Xiaomi worker that pushes the camera stream into
@@ -235,7 +574,74 @@ pub(crate) async fn upsert_onvif(
     .await
 }
 
+/// Start (or replace) the always-on ingest of a GB28181 channel (see
+/// `crate::gb::ingest`).
+pub(crate) async fn upsert_gb(
+    id: &str,
+    media: Arc<rszlm::media::Media>,
+    channel: crate::gb::ingest::GbChannel,
+    include_audio: bool,
+    update_if_exists: bool,
+) -> anyhow::Result<()> {
+    let device_id = id.to_string();
+    upsert_entry(
+        id,
+        move || {
+            let cancel = CancellationToken::new();
+            let handle = crate::gb::ingest::spawn_gb_device(
+                device_id,
+                channel,
+                media,
+                include_audio,
+                cancel.clone(),
+            );
+            Entry::Task { cancel, handle }
+        },
+        update_if_exists,
+    )
+    .await
+}
+
+/// Start (or replace) a failover supervisor: it runs `inputs` (one camera's
+/// URLs, highest priority first) into the outputs `outputs` builds, moving to
+/// the next input when one keeps failing and back once it recovers (see
+/// `crate::failover`).
+pub(crate) async fn upsert_failover(
+    id: &str,
+    inputs: Vec<InputConfig>,
+    policy: crate::failover::FailoverPolicy,
+    tuning: InputTuning,
+    outputs: impl Fn(TsSession) -> Vec<OutputConfig> + Send + Sync + 'static,
+    update_if_exists: bool,
+) -> anyhow::Result<()> {
+    let device_id = id.to_string();
+    let profile = tuning.latency_profile;
+    let effective = inputs
+        .first()
+        .and_then(|primary| input_options(primary, &tuning));
+    upsert_entry(
+        id,
+        move || {
+            let cancel = CancellationToken::new();
+            let handle = crate::failover::spawn_failover_device(
+                device_id,
+                inputs,
+                policy,
+                tuning,
+                outputs,
+                cancel.clone(),
+            );
+            Entry::Task { cancel, handle }
+        },
+        update_if_exists,
+    )
+    .await?;
+    retune(id, profile, effective.as_ref());
+    Ok(())
+}
+
 pub(crate) async fn remove_pipe(id: &str) -> anyhow::Result<()> {
+    release_for_players(id);
     let entry = {
         let mut pipes = PIPE_MANAGER.write().await;
         pipes.remove(id)
@@ -243,7 +649,11 @@ pub(crate) async fn remove_pipe(id: &str) -> anyhow::Result<()> {
     if let Some(entry) = entry {
         entry.stop();
         entry.join().await;
+        // A stopped bus reports no end of its input.
+        set_status(id, nvr_db::device::STATUS_OFFLINE).await;
     }
+    crate::latency::set_active(id, None);
+    crate::input_options::set_effective(id, None);
     Ok(())
 }
 
@@ -272,9 +682,176 @@ pub(crate) async fn list_pipe_ids() -> Vec<String> {
 
 /// Fetch a shared handle to the `Pipe` for `id`, if one is registered. Native
 /// worker entries have no `Pipe`, so they return `None`.
+/// A stopped on-demand pipe has none either (see [`acquire_pipe`]).
 pub(crate) async fn get_pipe(id: &str) -> Option<Arc<Pipe>> {
-    PIPE_MANAGER.read().await.get(id).and_then(|e| match e {
-        Entry::Pipe { pipe, .. } => Some(pipe.clone()),
-        Entry::Worker { .. } | Entry::Task { .. } => None,
-    })
+    PIPE_MANAGER.read().await.get(id).and_then(Entry::pipe)
+}
+
+/// The output [`start_motion_recording`] adds to a pipe.
+const MOTION_OUTPUT: &str = "motion-record";
+/// Longest file of a motion recording.
+const MOTION_SEGMENT_SECS: u32 = 60;
+/// How long [`stop_motion_recording`] waits for the last file to close.
+const MOTION_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A pipe recording motion, and the task indexing its files.
+struct MotionRecording {
+    pipe: Arc<Pipe>,
+    indexer: JoinHandle<()>,
+}
+
+/// The motion recordings, by device id.
+static MOTION_RECORDINGS: LazyLock<Mutex<HashMap<String, MotionRecording>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Record the video of the running pipe of `id`, as it comes (no transcode),
+/// into MP4 files of up to [`MOTION_SEGMENT_SECS`] under
+/// `<record dir>/motion/<id>`, named by their start time, until
+/// [`stop_motion_recording`]. The first file starts at the next keyframe.
+/// Each file is saved as a record segment of the device once closed, so it
+/// shows in its recordings and playback like any other.
+/// Nothing to do while it already records; refused in maintenance mode (see
+/// `crate::maintenance`).
+pub(crate) async fn start_motion_recording(id: &str) -> anyhow::Result<()> {
+    if MOTION_RECORDINGS.lock().unwrap().contains_key(id) {
+        return Ok(());
+    }
+    if crate::maintenance::is_active() {
+        anyhow::bail!("in maintenance, motion is not recorded");
+    }
+    let pipe = get_pipe(id)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("pipe not found"))?;
+    let dir = crate::config::config().record_dir().join("motion").join(id);
+    tokio::fs::create_dir_all(&dir).await?;
+    let output = ffmpeg_bus::bus::OutputConfig::new(
+        MOTION_OUTPUT.to_string(),
+        ffmpeg_bus::bus::OutputAvType::Video,
+        ffmpeg_bus::bus::OutputDest::Segments {
+            dir: dir.to_string_lossy().into_owned(),
+            pattern: "motion_%Y%m%d_%H%M%S.mp4".to_string(),
+            segment_seconds: MOTION_SEGMENT_SECS,
+        },
+    );
+    let events = pipe.events();
+    pipe.add_output(output).await?;
+    let indexer = tokio::spawn(index_motion_segments(id.to_string(), events));
+    MOTION_RECORDINGS
+        .lock()
+        .unwrap()
+        .insert(id.to_string(), MotionRecording { pipe, indexer });
+    log::info!("pipe {id}: motion recording started");
+    Ok(())
 }
+
+/// Save each file the motion recording of `id` closes, until its output
+/// ends.
+async fn index_motion_segments(id: String, mut events: broadcast::Receiver<BusEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(BusEvent::SegmentClosed {
+                id: output,
+                path,
+                start_ms,
+                duration_ms,
+            }) if output == MOTION_OUTPUT => {
+                if let Err(e) = index_motion_segment(&id, &path, start_ms, duration_ms).await {
+                    log::warn!("pipe {id}: motion recording {path} not indexed: {e:#}");
+                }
+            }
+            Ok(
+                BusEvent::OutputFinished { id: output } | BusEvent::OutputFailed { id: output, .. },
+            ) if output == MOTION_OUTPUT => {
+                break;
+            }
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                log::warn!("pipe {id}: {count} events missed, motion recordings may be unindexed");
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Save the motion recording file at `path` as a record segment of device
+/// `id`, starting at `start_ms` (the server's clock) for `duration_ms`.
+async fn index_motion_segment(
+    id: &str,
+    path: &str,
+    start_ms: i64,
+    duration_ms: u64,
+) -> anyhow::Result<()> {
+    let file_path = std::path::Path::new(path);
+    let meta = ffmpeg_bus::metadata::probe(path)?;
+    let file_size = tokio::fs::metadata(file_path).await?.len() as usize;
+    let now = chrono::Utc::now();
+    let mut record = nvr_db::record_segment::RecordSegment {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        start_time: (start_ms.max(0) / 1000) as u64,
+        duration: duration_ms as f32 / 1000.0,
+        file_size,
+        file_name: file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        file_path: path.to_string(),
+        folder: file_path
+            .parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        app: crate::init::device::DEVICE_APP.to_string(),
+        stream: id.to_string(),
+        vhost: crate::reconcile::DEFAULT_VHOST.to_string(),
+        create_time: now,
+        update_time: now,
+        ..Default::default()
+    };
+    crate::zlm::server::apply_media_info(&mut record, &meta);
+    let conn = crate::db::app_db_conn()?;
+    nvr_db::record_segment::upsert(&record, &conn).await?;
+    crate::transport::segment_closed();
+    Ok(())
+}
+
+/// The devices recording motion.
+pub(crate) fn motion_recording_ids() -> Vec<String> {
+    MOTION_RECORDINGS.lock().unwrap().keys().cloned().collect()
+}
+
+/// End the recording [`start_motion_recording`] started, returning once its
+/// last file is closed and indexed (or after [`MOTION_CLOSE_TIMEOUT`]).
+/// Nothing to do when `id` does not record motion.
+pub(crate) async fn stop_motion_recording(id: &str) -> anyhow::Result<()> {
+    let Some(MotionRecording { pipe, mut indexer }) = MOTION_RECORDINGS.lock().unwrap().remove(id)
+    else {
+        return Ok(());
+    };
+    pipe.remove_output(MOTION_OUTPUT).await?;
+    tokio::select! {
+        _ = &mut indexer => {}
+        // A stopped pipe took the output with it.
+        _ = pipe.cancelled() => {}
+        _ = tokio::time::sleep(MOTION_CLOSE_TIMEOUT) => {
+            log::warn!("pipe {id}: motion recording not closed in {MOTION_CLOSE_TIMEOUT:?}");
+        }
+    }
+    log::info!("pipe {id}: motion recording stopped");
+    Ok(())
+}
+
+/// Wait until the pipe of `id` has opened its input and its bus is up, so
+/// outputs can be added to it. Panics after 10 s.
+#[cfg(test)]
+pub(crate) async fn wait_running(id: &str) {
+    for _ in 0..50 {
+        if get_pipe(id).await.is_some_and(|pipe| pipe.is_running()) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    panic!("pipe {id} did not start");
+}
+
+#[cfg(test)]
+#[path = "manager_test.rs"]
+mod manager_test;
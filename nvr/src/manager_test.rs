@@ -0,0 +1,44 @@
+use super::*;
+
+fn on_demand(grace: Duration) -> OnDemand {
+    OnDemand {
+        config: Box::new(|| {
+            Ok(PipeConfig {
+                input: InputConfig::File {
+                    path: "/nonexistent/on-demand.mp4".to_string(),
+                },
+                outputs: Vec::new(),
+            })
+        }),
+        tuning: InputTuning::default(),
+        pinned: false,
+        grace,
+    }
+}
+
+#[tokio::test]
+async fn an_on_demand_pipe_stops_once_unheld_for_its_grace() {
+    tokio::time::pause();
+    let id = "on-demand-test-cam";
+    let grace = Duration::from_secs(30);
+    upsert_on_demand(id, on_demand(grace)).await.unwrap();
+    assert!(
+        get_pipe(id).await.is_none(),
+        "started before anyone held it"
+    );
+
+    let (first, second) = tokio::join!(acquire_pipe(id), acquire_pipe(id));
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert!(Arc::ptr_eq(first.pipe(), second.pipe()));
+    let pipe = Arc::clone(first.pipe());
+    drop(first);
+    drop(second);
+
+    tokio::time::sleep(grace / 2).await;
+    assert!(get_pipe(id).await.is_some(), "stopped within its grace");
+    tokio::time::sleep(grace).await;
+    assert!(get_pipe(id).await.is_none());
+    assert!(pipe.is_cancelled());
+
+    remove_pipe(id).await.unwrap();
+}
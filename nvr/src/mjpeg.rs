@@ -0,0 +1,374 @@
+//! `GET /api/v1/device/{id}/mjpeg?fps=5&width=640`: a device's live picture as
+//! MJPEG over `multipart/x-mixed-replace`, for wall displays and old browsers
+//! that can play nothing else.
+//!
+//! Pictures come from the pipe's decoded-video broadcast, from its first
+//! keyframe on, at most `fps` a second (default 5, at most 15) and downscaled
+//! to at most `width` pixels wide (default 640, between 160 and 1920). While
+//! the device has a fresh detection result (see [`crate::detect`]), its boxes
+//! and labels are drawn on the picture before it is encoded.
+//!
+//! One conversion (sampling, overlay, JPEG encoding) runs per device, `fps`
+//! and `width`, shared by every viewer asking for the same; each connection
+//! reads the parts from its own queue, so a viewer falling behind skips
+//! pictures rather than holding the others back. A conversion stops, letting
+//! go of the pipe's broadcast, at the first picture after its last viewer
+//! disconnected, and ends its viewers' responses when the broadcast ends.
+//!
+//! Each connection is a viewer session admitted under the limits of
+//! [`crate::viewers`] (503 when over one), and its bytes count as the
+//! device's egress. A device in privacy mode gets the placeholder image.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    Extension,
+    body::Body,
+    extract::{Path, Query},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use ffmpeg_bus::frame::{
+    RawFrame, RawFrameCmd, RawFrameReceiver, RawVideoFrame, convert_video, pack_planes,
+    unpack_planes,
+};
+use ffmpeg_next::format::Pixel;
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::auth::AuthUser;
+use crate::canvas::Canvas;
+use crate::detect::result::FrameResult;
+use crate::snapshot::{encode_jpeg, scaled_size};
+use crate::viewers::Registry;
+
+const DEFAULT_FPS: u32 = 5;
+const MAX_FPS: u32 = 15;
+const DEFAULT_WIDTH: u32 = 640;
+const MIN_WIDTH: u32 = 160;
+const MAX_WIDTH: u32 = 1920;
+/// Parts queued per viewer; one that falls further behind skips to the
+/// newest.
+const VIEWER_QUEUE: usize = 2;
+/// Detection results up to this old are drawn.
+const OVERLAY_MAX_AGE_SECS: i64 = 2;
+/// Separates the parts of the response.
+pub(crate) const BOUNDARY: &str = "mjpegframe";
+
+/// Box colors, one per model of a result.
+const PALETTE: [[u8; 3]; 4] = [[46, 204, 64], [255, 65, 54], [0, 116, 217], [255, 220, 0]];
+const LABEL_TEXT: [u8; 3] = [16, 16, 16];
+
+static CONVERSIONS: LazyLock<Conversions> = LazyLock::new(Conversions::default);
+
+/// A conversion: device, fps and width.
+type Key = (String, u32, u32);
+
+/// The running conversions, each a broadcast of ready-to-write parts whose
+/// only sender is shared by the map and the conversion task.
+#[derive(Default)]
+pub struct Conversions {
+    running: Mutex<HashMap<Key, broadcast::Sender<Bytes>>>,
+}
+
+impl Conversions {
+    /// Whether a conversion of `device` runs at `fps` and `width`.
+    pub fn is_running(&self, device: &str, fps: u32, width: u32) -> bool {
+        let key = (device.to_string(), fps, width);
+        self.running.lock().unwrap().contains_key(&key)
+    }
+
+    /// The parts of `device` at `fps` and `width`, joining the running
+    /// conversion or starting one on the broadcast from `subscribe`.
+    pub async fn parts<F, Fut>(
+        &'static self,
+        device: &str,
+        fps: u32,
+        width: u32,
+        subscribe: F,
+    ) -> anyhow::Result<broadcast::Receiver<Bytes>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<RawFrameReceiver>>,
+    {
+        let key = (device.to_string(), fps, width);
+        if let Some(tx) = self.running.lock().unwrap().get(&key) {
+            return Ok(tx.subscribe());
+        }
+        let video = subscribe().await?;
+        let mut running = self.running.lock().unwrap();
+        // A concurrent request may have started it meanwhile.
+        if let Some(tx) = running.get(&key) {
+            return Ok(tx.subscribe());
+        }
+        let (tx, rx) = broadcast::channel(VIEWER_QUEUE);
+        running.insert(key.clone(), tx.clone());
+        tokio::spawn(convert(self, key, video, tx));
+        Ok(rx)
+    }
+
+    /// Forget the conversion `tx` of `key` if nobody watches it. Under the
+    /// map's lock, so no viewer joins a conversion that is stopping.
+    fn stop_if_idle(&self, key: &Key, tx: &broadcast::Sender<Bytes>) -> bool {
+        let mut running = self.running.lock().unwrap();
+        if tx.receiver_count() > 0 {
+            return false;
+        }
+        if running.get(key).is_some_and(|cur| cur.same_channel(tx)) {
+            running.remove(key);
+        }
+        true
+    }
+
+    fn detach(&self, key: &Key, tx: &broadcast::Sender<Bytes>) {
+        let mut running = self.running.lock().unwrap();
+        if running.get(key).is_some_and(|cur| cur.same_channel(tx)) {
+            running.remove(key);
+        }
+    }
+}
+
+/// Turn `video` into parts on `tx` until the broadcast ends or nobody
+/// watches.
+async fn convert(
+    conversions: &'static Conversions,
+    key: Key,
+    mut video: RawFrameReceiver,
+    tx: broadcast::Sender<Bytes>,
+) {
+    let (device, fps, width) = key.clone();
+    log::info!("mjpeg[{device}]: conversion started ({fps} fps, {width} px)");
+    let interval = Duration::from_secs(1) / fps;
+    let mut seen_key = false;
+    let mut last: Option<Instant> = None;
+    loop {
+        let frame = match video.recv().await {
+            Ok(RawFrameCmd::Data(RawFrame::Video(frame))) => frame,
+            Ok(RawFrameCmd::Data(RawFrame::Audio(_))) => continue,
+            Ok(RawFrameCmd::EOF) | Err(RecvError::Closed) => break,
+            Err(RecvError::Lagged(n)) => {
+                log::debug!("mjpeg[{device}]: dropped {n} frames (lag)");
+                continue;
+            }
+        };
+        if conversions.stop_if_idle(&key, &tx) {
+            log::info!("mjpeg[{device}]: last viewer gone");
+            return;
+        }
+        if !seen_key {
+            if !frame.is_key() {
+                continue; // frames decoded before a keyframe may be garbage
+            }
+            seen_key = true;
+        }
+        let now = Instant::now();
+        if last.is_some_and(|l| now.duration_since(l) < interval) {
+            continue;
+        }
+        last = Some(now);
+        let overlay = fresh_detections(&device);
+        match tokio::task::spawn_blocking(move || render(&frame, width, overlay.as_ref())).await {
+            Ok(Ok(jpeg)) => {
+                let _ = tx.send(part(&jpeg));
+            }
+            Ok(Err(e)) => log::debug!("mjpeg[{device}]: frame not encoded: {e:#}"),
+            Err(e) => {
+                log::warn!("mjpeg[{device}]: encode task failed: {e}");
+                break;
+            }
+        }
+    }
+    conversions.detach(&key, &tx);
+    log::info!("mjpeg[{device}]: conversion stopped");
+}
+
+/// The device's latest detection result, if recent enough to still match
+/// the picture.
+fn fresh_detections(device: &str) -> Option<FrameResult> {
+    let result = crate::detect::hub::DetectHub::get()?.latest(device)?;
+    let age = chrono::Utc::now().timestamp() - result.ts;
+    (age <= OVERLAY_MAX_AGE_SECS).then_some(result)
+}
+
+/// A JPEG of `frame`, at most `width` pixels wide, with the boxes of
+/// `overlay` drawn on it.
+pub(crate) fn render(
+    frame: &RawVideoFrame,
+    width: u32,
+    overlay: Option<&FrameResult>,
+) -> anyhow::Result<Vec<u8>> {
+    if frame.width() == 0 || frame.height() == 0 {
+        anyhow::bail!("zero-sized frame");
+    }
+    let (w, h) = scaled_size(frame.width(), frame.height(), width);
+    let Some(result) = overlay.filter(|r| r.models.iter().any(|m| !m.detections.is_empty())) else {
+        // The MJPEG encoder takes full-range YUV.
+        return encode_jpeg(frame.scale(Pixel::YUVJ420P, w, h)?);
+    };
+    let mut rgb = pack_planes(&frame.scale(Pixel::RGB24, w, h)?)?;
+    draw_detections(
+        &mut Canvas {
+            rgb: &mut rgb,
+            w,
+            h,
+        },
+        result,
+    );
+    let rgb = unpack_planes(&rgb, Pixel::RGB24, w, h)?;
+    encode_jpeg(convert_video(&rgb, Pixel::YUVJ420P)?)
+}
+
+/// Draw each detection of `result` as a box with its label and confidence,
+/// scaled from the frame the detector saw to the canvas.
+pub(crate) fn draw_detections(canvas: &mut Canvas, result: &FrameResult) {
+    let sx = f64::from(canvas.w) / f64::from(result.frame_w.max(1));
+    let sy = f64::from(canvas.h) / f64::from(result.frame_h.max(1));
+    let scale = if canvas.w >= 1280 { 2 } else { 1 };
+    let thickness = scale + 1;
+    for (i, model) in result.models.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        for detection in &model.detections {
+            let b = &detection.bbox;
+            let x = (f64::from(b.x1) * sx).round() as i64;
+            let y = (f64::from(b.y1) * sy).round() as i64;
+            let w = ((f64::from(b.x2 - b.x1) * sx).round() as i64).max(1);
+            let h = ((f64::from(b.y2 - b.y1) * sy).round() as i64).max(1);
+            canvas.outline(x, y, w, h, thickness, color);
+
+            let label = format!("{} {:.0}", detection.label, detection.confidence * 100.0);
+            let label_w = (label.chars().count() as i64 * 6 + 1) * scale;
+            let label_h = 9 * scale;
+            // Above the box, or inside it when it touches the top.
+            let top = if y >= label_h { y - label_h } else { y };
+            canvas.fill(x, top, label_w, label_h, color);
+            canvas.text(x + scale, top + scale, &label, scale, label_w, LABEL_TEXT);
+        }
+    }
+}
+
+/// One part of the multipart response.
+pub(crate) fn part(jpeg: &[u8]) -> Bytes {
+    let mut part = format!(
+        "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        jpeg.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(jpeg);
+    part.extend_from_slice(b"\r\n");
+    Bytes::from(part)
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct MjpegQuery {
+    /// Pictures per second, 1 to 15.
+    fps: Option<u32>,
+    /// Widest picture in pixels, 160 to 1920; smaller sources keep their
+    /// size.
+    width: Option<u32>,
+}
+
+/// The device's live picture as MJPEG.
+#[utoipa::path(
+    get,
+    path = "/{id}/mjpeg",
+    tag = "device",
+    params(("id" = String, Path), MjpegQuery),
+    responses(
+        (
+            status = 200,
+            description = "JPEG parts until the client disconnects or the device stops",
+            body = [u8],
+            content_type = "multipart/x-mixed-replace",
+        ),
+        (status = 404, description = "No such device running, or it has no video", body = String),
+        (status = 503, description = "Over a viewer limit"),
+    )
+)]
+pub(crate) async fn mjpeg(
+    Path(id): Path<String>,
+    Query(query): Query<MjpegQuery>,
+    user: Option<Extension<AuthUser>>,
+) -> Response {
+    if crate::privacy::is_private(&id) {
+        return crate::snapshot::privacy_response();
+    }
+    let fps = query.fps.unwrap_or(DEFAULT_FPS).clamp(1, MAX_FPS);
+    let width = query
+        .width
+        .unwrap_or(DEFAULT_WIDTH)
+        .clamp(MIN_WIDTH, MAX_WIDTH);
+    let user = user.map(|Extension(user)| user.username);
+    let viewer = match Registry::global().admit(&id, user.as_deref()) {
+        Ok(viewer) => viewer,
+        Err(rejection) => {
+            log::info!("mjpeg[{id}]: refused: {rejection:?}");
+            return rejection.into_response();
+        }
+    };
+    // Held by the body, so an on-demand pipe runs while it is watched.
+    let Some(handle) = crate::manager::acquire_pipe(&id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("no video for {id}: pipe not found"),
+        )
+            .into_response();
+    };
+    let subscribe = || async {
+        let Some(pipe) = crate::manager::get_pipe(&id).await else {
+            anyhow::bail!("pipe not found");
+        };
+        pipe.subscribe_video().await
+    };
+    let parts = match CONVERSIONS.parts(&id, fps, width, subscribe).await {
+        Ok(parts) => parts,
+        Err(e) => {
+            return (StatusCode::NOT_FOUND, format!("no video for {id}: {e:#}")).into_response();
+        }
+    };
+    let meter = crate::usage::egress_meter(&id);
+    // Hyper drops the body when the client goes away, and with it the
+    // queue, the viewer session and the pipe handle.
+    let body = futures::stream::unfold(
+        (parts, viewer, meter, handle),
+        |(mut parts, viewer, meter, handle)| async move {
+            loop {
+                match parts.recv().await {
+                    Ok(part) => {
+                        meter.add(part.len() as u64);
+                        let state = (parts, viewer, meter, handle);
+                        return Some((Ok::<_, Infallible>(part), state));
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={BOUNDARY}"),
+            ),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// Whether a conversion of `device` runs, for tests.
+#[cfg(test)]
+pub(crate) fn is_converting(device: &str, fps: u32, width: u32) -> bool {
+    CONVERSIONS.is_running(device, fps, width)
+}
+
+#[cfg(test)]
+#[path = "mjpeg_test.rs"]
+mod mjpeg_test;
@@ -0,0 +1,163 @@
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+use futures::StreamExt;
+use media_pipe_core::{InputConfig, PipeConfig};
+use nvr_detect::{BBox, Detection, ModelResult};
+
+use super::*;
+
+fn result(bbox: BBox) -> FrameResult {
+    FrameResult {
+        ts: 0,
+        frame_w: 80,
+        frame_h: 60,
+        models: vec![ModelResult {
+            name: "yolo".to_string(),
+            infer_ms: 1.0,
+            detections: vec![Detection {
+                class_id: 0,
+                label: "person".to_string(),
+                bbox,
+                confidence: 0.9,
+            }],
+            error: None,
+        }],
+    }
+}
+
+#[test]
+fn detections_are_scaled_to_the_picture_and_clipped() {
+    let (w, h) = (40, 30);
+    let mut rgb = vec![0; w * h * 3];
+    let at = |rgb: &[u8], x: usize, y: usize| -> [u8; 3] {
+        rgb[(y * w + x) * 3..(y * w + x) * 3 + 3]
+            .try_into()
+            .unwrap()
+    };
+    // Half the detector's frame; the box runs off the right edge.
+    let bbox = BBox {
+        x1: 20.0,
+        y1: 20.0,
+        x2: 100.0,
+        y2: 50.0,
+    };
+    let mut canvas = Canvas {
+        rgb: &mut rgb,
+        w: w as u32,
+        h: h as u32,
+    };
+    draw_detections(&mut canvas, &result(bbox));
+
+    let color = PALETTE[0];
+    assert_eq!(at(&rgb, 10, 20), color, "left edge");
+    assert_eq!(at(&rgb, 30, 24), color, "bottom edge");
+    assert_eq!(at(&rgb, 20, 20), [0, 0, 0], "inside the box");
+    assert_eq!(at(&rgb, 5, 20), [0, 0, 0], "left of the box");
+    // The label sits above the box.
+    assert_ne!(at(&rgb, 11, 5), [0, 0, 0]);
+}
+
+#[test]
+fn parts_carry_their_length_between_boundaries() {
+    let part = part(&[0xFF, 0xD8, 0xFF, 0xD9]);
+    let (head, jpeg) = part.split_at(part.len() - 6);
+    assert_eq!(
+        head,
+        b"--mjpegframe\r\nContent-Type: image/jpeg\r\nContent-Length: 4\r\n\r\n"
+    );
+    assert_eq!(jpeg, b"\xFF\xD8\xFF\xD9\r\n");
+}
+
+/// The JPEGs of the parts in `buf`, leaving a trailing partial one.
+fn take_jpegs(buf: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let mut jpegs = Vec::new();
+    loop {
+        let text = String::from_utf8_lossy(buf);
+        let Some(header_end) = text.find("\r\n\r\n") else {
+            break;
+        };
+        let len: usize = text[..header_end]
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .expect("part without Content-Length")
+            .parse()
+            .unwrap();
+        let start = header_end + 4;
+        if buf.len() < start + len + 2 {
+            break;
+        }
+        jpegs.push(buf[start..start + len].to_vec());
+        buf.drain(..start + len + 2);
+    }
+    jpegs
+}
+
+#[tokio::test]
+async fn file_device_streams_jpegs_at_the_requested_rate() {
+    // Serialized with the maintenance test, which refuses new viewers.
+    let _db = crate::db::test_db().await;
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let id = "mjpeg-test-cam";
+    crate::manager::add_pipe(
+        id,
+        PipeConfig {
+            input: InputConfig::FileLoop {
+                path: path.to_string_lossy().into_owned(),
+                realtime: true,
+            },
+            outputs: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    crate::manager::wait_running(id).await;
+    let query = MjpegQuery {
+        fps: Some(4),
+        width: Some(320),
+    };
+    let response = mjpeg(Path(id.to_string()), Query(query), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "multipart/x-mixed-replace; boundary=mjpegframe"
+    );
+    assert!(is_converting(id, 4, 320));
+
+    let mut body = response.into_body().into_data_stream();
+    let mut buf = Vec::new();
+    let mut jpegs = Vec::new();
+    let mut arrivals = Vec::new();
+    let read = async {
+        while jpegs.len() < 3 {
+            let chunk = body.next().await.expect("stream ended").unwrap();
+            buf.extend_from_slice(&chunk);
+            for jpeg in take_jpegs(&mut buf) {
+                jpegs.push(jpeg);
+                arrivals.push(Instant::now());
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), read)
+        .await
+        .expect("no pictures");
+    for jpeg in &jpegs {
+        assert_eq!(&jpeg[..2], &[0xFF, 0xD8], "missing JPEG SOI marker");
+        assert_eq!(&jpeg[jpeg.len() - 2..], &[0xFF, 0xD9], "missing EOI marker");
+    }
+    // At most 4 a second, with some slack for delivery.
+    let spacing = arrivals[2].duration_since(arrivals[0]) / 2;
+    assert!(spacing >= Duration::from_millis(150), "{spacing:?}");
+
+    // The last viewer leaving stops the conversion.
+    drop(body);
+    let mut stopped = false;
+    for _ in 0..50 {
+        if !is_converting(id, 4, 320) {
+            stopped = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    crate::manager::remove_pipe(id).await.unwrap();
+    assert!(stopped, "conversion still running without viewers");
+}
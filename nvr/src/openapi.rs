@@ -0,0 +1,194 @@
+//! The OpenAPI 3 document of the HTTP API. It is derived, never written by
+//! hand: every documented handler carries a `#[utoipa::path]` and its DTOs
+//! derive `ToSchema`, and each documented router has an `…Api` document next
+//! to it that [`document`] nests under the prefix [`crate::api`] mounts the
+//! router at. Its tests walk the router to check that every `/api/v1` route of
+//! a documented area is in the document, and the other way round.
+//!
+//! `GET /api/v1/openapi.json` serves the document to any signed-in user;
+//! `GET /api/v1/docs` serves a Swagger UI for it when `NVR_API_DOCS=1`, to
+//! users with full access.
+
+use std::sync::LazyLock;
+
+use axum::{
+    Extension, Router,
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::auth::AuthUser;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "lite-nvr",
+        description = "HTTP API of a lite-nvr node.
+
+Every JSON endpoint answers with the `BaseResponse` envelope: `code` 0 and \
+`data` on success, the error in `message` otherwise. Calls other than \
+`/user/login` and `/setup` need a session token, as `Authorization: Bearer \
+<token>` or a `?token=` query parameter.
+
+A fresh install is in setup mode: until `POST /setup` has created the \
+admin, every other call answers 503.
+
+## Versioning
+
+The API is versioned in its path. Within `/api/v1` changes are additive \
+only: new endpoints, new optional request fields and new response fields, \
+which clients must ignore when they do not know them. Removing or renaming \
+an endpoint or a field, changing its type or meaning, or making a request \
+field required is a breaking change and only ships as a new version \
+(`/api/v2`), with `/api/v1` kept alongside it for at least one release.
+
+The unversioned `/api/...` paths of earlier releases still answer for this \
+one release, with a `Deprecation: true` header and a `Link` to their \
+`/api/v1` successor."
+    ),
+    paths(openapi_json, docs),
+    modifiers(&SessionAuth),
+    security(("bearer" = []), ("token" = []))
+)]
+struct ApiDoc;
+
+/// The session token schemes of [`crate::auth::require_auth`].
+struct SessionAuth;
+
+impl Modify for SessionAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new("token"))),
+        );
+    }
+}
+
+/// The documented areas of the API, by the prefix they are nested at under
+/// [`crate::api::V1`].
+fn areas() -> [(&'static str, utoipa::openapi::OpenApi); 11] {
+    [
+        ("/device", crate::handler::device::DeviceApi::openapi()),
+        ("/input_profiles", crate::latency::LatencyApi::openapi()),
+        (
+            "/recordings",
+            crate::handler::recording::RecordingApi::openapi(),
+        ),
+        ("/export", crate::export::ExportApi::openapi()),
+        ("/jobs", crate::jobs::JobsApi::openapi()),
+        ("/user", crate::handler::user::UserApi::openapi()),
+        ("/detect", crate::detect::api::DetectApi::openapi()),
+        ("/snapshot", crate::snapshot::SnapshotApi::openapi()),
+        ("/setup", crate::setup::SetupApi::openapi()),
+        ("/usage", crate::usage::UsageApi::openapi()),
+        ("/discovery", crate::discovery::DiscoveryApi::openapi()),
+    ]
+}
+
+/// The whole document, with absolute paths.
+pub(crate) fn document() -> utoipa::openapi::OpenApi {
+    areas()
+        .into_iter()
+        .fold(ApiDoc::openapi(), |doc, (prefix, api)| {
+            // axum mounts a nested `/` at the bare prefix.
+            doc.nest_with_path_composer(format!("{}{prefix}", crate::api::V1), api, |base, path| {
+                match path {
+                    "/" => base.to_string(),
+                    path => format!("{base}{path}"),
+                }
+            })
+        })
+}
+
+static DOCUMENT: LazyLock<String> = LazyLock::new(|| {
+    document()
+        .to_json()
+        .expect("the OpenAPI document serializes")
+});
+
+pub(crate) fn openapi_router() -> Router {
+    Router::new()
+        .route("/openapi.json", get(openapi_json))
+        .route("/docs", get(docs))
+}
+
+/// This document.
+#[utoipa::path(
+    get,
+    path = "/api/v1/openapi.json",
+    tag = "meta",
+    responses((
+        status = 200,
+        description = "OpenAPI 3 document",
+        body = Object,
+        content_type = "application/json"
+    ))
+)]
+async fn openapi_json() -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        DOCUMENT.as_str(),
+    )
+        .into_response()
+}
+
+/// A Swagger UI of this document; pass the session token as `?token=` to
+/// try calls out.
+#[utoipa::path(
+    get,
+    path = "/api/v1/docs",
+    tag = "meta",
+    responses(
+        (status = 200, description = "Swagger UI", body = String, content_type = "text/html"),
+        (status = 403, description = "The caller has the read-only viewer role"),
+        (status = 404, description = "Turned off (`NVR_API_DOCS`)"),
+    )
+)]
+async fn docs(Extension(user): Extension<AuthUser>) -> Response {
+    if !crate::config::config().api_docs() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match crate::auth::is_viewer(&user.username).await {
+        Ok(false) => Html(SWAGGER_UI).into_response(),
+        Ok(true) => StatusCode::FORBIDDEN.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")).into_response(),
+    }
+}
+
+/// Loads Swagger UI from a CDN, pointed at the document next to it.
+const SWAGGER_UI: &str = r#"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>lite-nvr API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    const token = new URLSearchParams(location.search).get('token') || '';
+    SwaggerUIBundle({
+      dom_id: '#ui',
+      url: 'openapi.json' + (token ? '?token=' + encodeURIComponent(token) : ''),
+      requestInterceptor: (req) => {
+        if (token) req.headers['Authorization'] = 'Bearer ' + token;
+        return req;
+      },
+    });
+  </script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+#[path = "openapi_test.rs"]
+mod openapi_test;
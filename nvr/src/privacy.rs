@@ -0,0 +1,374 @@
+//! Temporary per-device privacy mode. While a device is private its recording
+//! and live outputs are torn down without touching its config: ffmpeg-backed
+//! devices keep their input open (so health stays visible) but publish
+//! nothing to ZLM, which closes and finalizes the current record segment;
+//! native/supervised sources (xiaomi, onvif, stream) are stopped and GB28181
+//! pull mappings dropped. Snapshots and `/media` requests for the device get a
+//! generated placeholder instead, and detection/ASR are stopped and refused.
+//!
+//! Privacy is switched on manually (`POST /api/device/privacy/{id}`, optionally
+//! `until` a time) or by recurring daily windows stored with it, in the
+//! device's time zone. The state of every device lives in the KV config
+//! (`device_privacy`); a worker re-checks it so `until` and windows take
+//! effect (and lapse) on their own.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, OnceLock, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use ffmpeg_bus::frame::RawVideoFrame;
+use ffmpeg_next::format::Pixel;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::db::app_db_conn;
+
+/// KV config key for every device's privacy state.
+const PRIVACY_KEY: &str = "device_privacy";
+/// How often `until` and windows are re-evaluated.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A recurring daily window, `"HH:MM"` in the device's zone. `end` before
+/// `start` spans midnight.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PrivacyWindow {
+    pub start: String,
+    pub end: String,
+}
+
+impl PrivacyWindow {
+    fn bounds(&self) -> Result<(NaiveTime, NaiveTime)> {
+        let parse = |s: &str| {
+            NaiveTime::parse_from_str(s.trim(), "%H:%M")
+                .map_err(|e| anyhow::anyhow!("invalid window time {s:?} (want HH:MM): {e}"))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    /// The window of local `day` in `tz`, `start..end`. A start or end that a
+    /// DST change skips moves to just after the gap, and one that occurs twice
+    /// is its first occurrence, so the window opens exactly once every day.
+    fn occurrence(&self, day: NaiveDate, tz: Tz) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let (start, end) = self.bounds().ok()?;
+        let from = day.and_time(start);
+        let to = if end < start {
+            day.succ_opt()?.and_time(end)
+        } else {
+            day.and_time(end)
+        };
+        let open = crate::tz::resolve(from, tz);
+        let mut close = crate::tz::resolve(to, tz);
+        // Both ends in the same gap: keep the window's length after it.
+        if close <= open && to > from {
+            close = open + (to - from);
+        }
+        Some((open, close))
+    }
+
+    /// Whether `now` falls in the window in zone `tz` (start inclusive, end
+    /// exclusive).
+    pub fn contains_at(&self, now: DateTime<Utc>, tz: Tz) -> bool {
+        let today = now.with_timezone(&tz).date_naive();
+        // Yesterday's window may span midnight into today.
+        [today.pred_opt(), Some(today)]
+            .into_iter()
+            .flatten()
+            .filter_map(|day| self.occurrence(day, tz))
+            .any(|(open, close)| open <= now && now < close)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DevicePrivacy {
+    /// Manually switched on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// When manual privacy ends on its own; `None` keeps it until switched off.
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub windows: Vec<PrivacyWindow>,
+}
+
+impl DevicePrivacy {
+    /// Whether the device is private at `now`: manually (and `until` has not
+    /// passed) or inside one of its windows, in zone `tz`.
+    pub fn is_active(&self, now: DateTime<Utc>, tz: Tz) -> bool {
+        let manual = self.enabled && self.until.is_none_or(|until| now < until);
+        manual || self.windows.iter().any(|w| w.contains_at(now, tz))
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.enabled && self.windows.is_empty()
+    }
+}
+
+/// Devices currently private, as last applied.
+static ACTIVE: LazyLock<RwLock<HashSet<String>>> = LazyLock::new(|| RwLock::new(HashSet::new()));
+
+/// Whether `device_id` is currently private. Cheap; checked by the snapshot,
+/// media, detection and ASR paths.
+pub fn is_private(device_id: &str) -> bool {
+    ACTIVE.read().unwrap().contains(device_id)
+}
+
+pub async fn load_all() -> Result<HashMap<String, DevicePrivacy>> {
+    let conn = app_db_conn()?;
+    Ok(nvr_db::config::get_json(PRIVACY_KEY, &conn)
+        .await?
+        .unwrap_or_default())
+}
+
+async fn save_all(all: &HashMap<String, DevicePrivacy>) -> Result<()> {
+    let conn = app_db_conn()?;
+    nvr_db::config::set_json(PRIVACY_KEY, all, &conn).await
+}
+
+/// Update `device_id`'s manual privacy (and, if given, its windows), persist
+/// it and apply the result at once.
+pub async fn set(
+    device_id: &str,
+    enabled: bool,
+    until: Option<DateTime<Utc>>,
+    windows: Option<Vec<PrivacyWindow>>,
+) -> Result<DevicePrivacy> {
+    if let Some(windows) = &windows {
+        for w in windows {
+            w.bounds()?;
+        }
+    }
+    let mut all = load_all().await?;
+    let entry = all.entry(device_id.to_string()).or_default();
+    entry.enabled = enabled;
+    entry.until = if enabled { until } else { None };
+    if let Some(windows) = windows {
+        entry.windows = windows;
+    }
+    let privacy = entry.clone();
+    if privacy.is_empty() {
+        all.remove(device_id);
+    }
+    save_all(&all).await?;
+    reconcile(&all, Utc::now(), false).await;
+    Ok(privacy)
+}
+
+/// Drop a removed device's privacy state.
+pub async fn forget(device_id: &str) -> Result<()> {
+    ACTIVE.write().unwrap().remove(device_id);
+    let mut all = load_all().await?;
+    if all.remove(device_id).is_some() {
+        save_all(&all).await?;
+    }
+    Ok(())
+}
+
+/// Devices whose privacy flips at `now`, with the new state, their windows
+/// in `zones` (the server default for devices missing from it). Devices no
+/// longer configured (or gone) come back out of privacy.
+pub(crate) fn transitions(
+    all: &HashMap<String, DevicePrivacy>,
+    now: DateTime<Utc>,
+    zones: &HashMap<String, Tz>,
+    active: &HashSet<String>,
+) -> Vec<(String, bool)> {
+    let mut changes = all
+        .iter()
+        .filter_map(|(id, p)| {
+            let private = p.is_active(now, crate::tz::lookup(zones, id));
+            (private != active.contains(id)).then(|| (id.clone(), private))
+        })
+        .collect::<Vec<_>>();
+    changes.extend(
+        active
+            .iter()
+            .filter(|id| !all.contains_key(*id))
+            .map(|id| (id.clone(), false)),
+    );
+    changes.sort();
+    changes
+}
+
+/// Every device's zone; on failure all fall back to the server default.
+async fn zones() -> HashMap<String, Tz> {
+    crate::tz::of_devices().await.unwrap_or_else(|e| {
+        log::warn!("privacy: failed to load device time zones: {e:#}");
+        HashMap::new()
+    })
+}
+
+/// Load the persisted state into [`ACTIVE`] without touching any pipe. Runs
+/// once before device pipes are first built, so private devices start
+/// private.
+pub async fn restore() {
+    match load_all().await {
+        Ok(all) => {
+            let now = Utc::now();
+            let zones = zones().await;
+            let mut active = ACTIVE.write().unwrap();
+            active.extend(
+                all.iter()
+                    .filter(|(id, p)| p.is_active(now, crate::tz::lookup(&zones, id)))
+                    .map(|(id, _)| id.clone()),
+            );
+            if !active.is_empty() {
+                log::info!("privacy: {} device(s) start private", active.len());
+            }
+        }
+        Err(e) => log::warn!("privacy: failed to load state: {e:#}"),
+    }
+}
+
+/// Apply every transition due at `now`. Manual changes are audited by the
+/// API middleware; `scheduled` ones (`until` lapsed, window edge) are written
+/// to the audit log here, as `system`.
+async fn reconcile(all: &HashMap<String, DevicePrivacy>, now: DateTime<Utc>, scheduled: bool) {
+    let zones = zones().await;
+    let changes = {
+        let active = ACTIVE.read().unwrap().clone();
+        transitions(all, now, &zones, &active)
+    };
+    for (id, private) in changes {
+        {
+            let mut active = ACTIVE.write().unwrap();
+            if private {
+                active.insert(id.clone());
+            } else {
+                active.remove(&id);
+            }
+        }
+        log::info!(
+            "privacy: device {id} {}",
+            if private { "private" } else { "restored" }
+        );
+        if private {
+            stop_analysis(&id);
+        }
+        if let Err(e) = rebuild(&id).await {
+            log::warn!("privacy: failed to rebuild device {id}: {e:#}");
+        }
+        if scheduled {
+            audit_scheduled(&id, private).await;
+        }
+    }
+}
+
+fn stop_analysis(device_id: &str) {
+    if let Some(hub) = crate::detect::hub::DetectHub::get() {
+        hub.unregister(device_id);
+    }
+    if let Some(hub) = crate::asr::hub::AsrHub::get() {
+        hub.unregister(device_id);
+    }
+}
+
+/// Rebuild the device's pipe so it picks up the current privacy state.
+async fn rebuild(device_id: &str) -> Result<()> {
+    let conn = app_db_conn()?;
+    match nvr_db::device::get(device_id, &conn).await? {
+        Some(device) => crate::init::device::ensure_device_pipe(&device).await,
+        None => Ok(()),
+    }
+}
+
+async fn audit_scheduled(device_id: &str, private: bool) {
+    let entry = nvr_db::audit::AuditEntry {
+        id: 0,
+        ts: Utc::now().timestamp_millis(),
+        username: "system".to_string(),
+        method: "AUTO".to_string(),
+        route: "/device/privacy/{id}".to_string(),
+        resource_id: device_id.to_string(),
+        summary: serde_json::json!({ "enabled": private }).to_string(),
+        status: 200,
+    };
+    let result = match app_db_conn() {
+        Ok(conn) => nvr_db::audit::insert(&entry, &conn).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log::error!("privacy: failed to audit change of {device_id}: {e:#}");
+    }
+}
+
+/// Spawn the worker that applies `until` expiries and window edges; it runs
+/// until `cancel` fires.
+pub fn spawn_worker(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+            }
+            match load_all().await {
+                Ok(all) => reconcile(&all, Utc::now(), true).await,
+                Err(e) => log::warn!("privacy: failed to load state: {e:#}"),
+            }
+        }
+    });
+}
+
+const PLACEHOLDER_WIDTH: u32 = 640;
+const PLACEHOLDER_HEIGHT: u32 = 360;
+/// Pixels per font dot.
+const PLACEHOLDER_SCALE: usize = 8;
+
+/// 5x7 dot glyphs of "PRIVACY", one row per byte (low five bits, MSB left).
+const PLACEHOLDER_TEXT: [[u8; 7]; 7] = [
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10], // P
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11], // R
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // I
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04], // V
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // A
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E], // C
+    [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04], // Y
+];
+
+/// The frame shown instead of a private device: "PRIVACY" in light gray on
+/// dark gray.
+pub(crate) fn placeholder_frame() -> RawVideoFrame {
+    let (w, h) = (PLACEHOLDER_WIDTH as usize, PLACEHOLDER_HEIGHT as usize);
+    let mut frame = ffmpeg_next::frame::Video::new(Pixel::YUV420P, w as u32, h as u32);
+    for plane in 1..3 {
+        frame.data_mut(plane).fill(128);
+    }
+    let stride = frame.stride(0);
+    let luma = frame.data_mut(0);
+    luma.fill(40);
+
+    let cols = PLACEHOLDER_TEXT.len() * 6 - 1;
+    let left = (w - cols * PLACEHOLDER_SCALE) / 2;
+    let top = (h - 7 * PLACEHOLDER_SCALE) / 2;
+    for (i, glyph) in PLACEHOLDER_TEXT.iter().enumerate() {
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                let x0 = left + (i * 6 + col) * PLACEHOLDER_SCALE;
+                let y0 = top + row * PLACEHOLDER_SCALE;
+                for y in y0..y0 + PLACEHOLDER_SCALE {
+                    luma[y * stride + x0..y * stride + x0 + PLACEHOLDER_SCALE].fill(200);
+                }
+            }
+        }
+    }
+    RawVideoFrame::from(frame)
+}
+
+/// [`placeholder_frame`] as a JPEG, encoded once.
+pub fn placeholder_jpeg() -> Result<&'static [u8]> {
+    static JPEG: OnceLock<Vec<u8>> = OnceLock::new();
+    if let Some(jpeg) = JPEG.get() {
+        return Ok(jpeg);
+    }
+    let jpeg = crate::snapshot::to_jpeg(&placeholder_frame())?;
+    Ok(JPEG.get_or_init(|| jpeg))
+}
+
+#[cfg(test)]
+#[path = "privacy_test.rs"]
+mod privacy_test;
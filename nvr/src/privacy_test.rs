@@ -0,0 +1,200 @@
+use chrono::{TimeDelta, TimeZone};
+
+use super::*;
+
+const NEW_YORK: Tz = chrono_tz::America::New_York;
+
+fn at(h: u32, m: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 10, 14, h, m, 0).unwrap()
+}
+
+fn window(start: &str, end: &str) -> PrivacyWindow {
+    PrivacyWindow {
+        start: start.to_string(),
+        end: end.to_string(),
+    }
+}
+
+#[test]
+fn windows_match_local_time_and_span_midnight() {
+    let tz = chrono_tz::UTC;
+    let day = window("09:00", "17:30");
+    assert!(day.contains_at(at(9, 0), tz));
+    assert!(day.contains_at(at(17, 29), tz));
+    assert!(!day.contains_at(at(17, 30), tz));
+    assert!(!day.contains_at(at(8, 59), tz));
+
+    let night = window("22:00", "07:00");
+    assert!(night.contains_at(at(23, 0), tz));
+    assert!(night.contains_at(at(3, 0), tz));
+    assert!(!night.contains_at(at(12, 0), tz));
+
+    // A malformed window never matches (and is refused by `set`).
+    assert!(!window("25:00", "07:00").contains_at(at(3, 0), tz));
+}
+
+#[test]
+fn windows_are_in_the_device_zone() {
+    // 09:00-17:30 in New York is 13:00-21:30 UTC in October.
+    let day = window("09:00", "17:30");
+    assert!(!day.contains_at(at(12, 59), NEW_YORK));
+    assert!(day.contains_at(at(13, 0), NEW_YORK));
+    assert!(day.contains_at(at(21, 29), NEW_YORK));
+    assert!(!day.contains_at(at(21, 30), NEW_YORK));
+}
+
+/// Run the worker's check every minute for `hours` from `start` with a
+/// New York device private during `w`. Returns how many minutes each
+/// switch into privacy lasted.
+fn simulate(w: PrivacyWindow, start: DateTime<Utc>, hours: i64) -> Vec<i64> {
+    let all = HashMap::from([(
+        "cam".to_string(),
+        DevicePrivacy {
+            windows: vec![w],
+            ..Default::default()
+        },
+    )]);
+    let zones = HashMap::from([("cam".to_string(), NEW_YORK)]);
+    let mut active = HashSet::new();
+    let mut runs = Vec::new();
+    for minute in 0..hours * 60 {
+        let now = start + TimeDelta::minutes(minute);
+        for (id, private) in transitions(&all, now, &zones, &active) {
+            if private {
+                active.insert(id);
+                runs.push(0);
+            } else {
+                active.remove(&id);
+            }
+        }
+        if let Some(run) = runs.last_mut().filter(|_| !active.is_empty()) {
+            *run += 1;
+        }
+    }
+    runs
+}
+
+#[test]
+fn windows_fire_once_across_dst_changes() {
+    // 2026-11-01: New York falls back from 02:00 EDT to 01:00 EST, so
+    // 01:00-02:00 happens twice. Midnight EDT is 04:00 UTC.
+    let fall_back = Utc.with_ymd_and_hms(2026, 11, 1, 4, 0, 0).unwrap();
+    // Inside the repeated hour: only its first pass.
+    assert_eq!(simulate(window("01:30", "01:45"), fall_back, 6), vec![15]);
+    // Across it: wall clock 01:00 to 03:00, three real hours.
+    assert_eq!(simulate(window("01:00", "03:00"), fall_back, 6), vec![180]);
+
+    // 2026-03-08: New York springs forward from 02:00 EST to 03:00 EDT, so
+    // 02:00-03:00 never happens. Midnight EST is 05:00 UTC.
+    let spring = Utc.with_ymd_and_hms(2026, 3, 8, 5, 0, 0).unwrap();
+    // Entirely skipped: runs for its length from 03:00.
+    assert_eq!(simulate(window("02:15", "02:45"), spring, 6), vec![30]);
+    // Starting before the gap: wall clock 01:30 to 03:30, one real hour.
+    assert_eq!(simulate(window("01:30", "03:30"), spring, 6), vec![60]);
+}
+
+#[test]
+fn manual_privacy_lapses_at_until() {
+    let until = at(12, 0);
+    let privacy = DevicePrivacy {
+        enabled: true,
+        until: Some(until),
+        windows: Vec::new(),
+    };
+    assert!(privacy.is_active(at(11, 59), NEW_YORK));
+    assert!(!privacy.is_active(at(12, 0), NEW_YORK));
+
+    let open_ended = DevicePrivacy {
+        enabled: true,
+        ..Default::default()
+    };
+    assert!(open_ended.is_active(at(12, 0), NEW_YORK));
+    assert!(!DevicePrivacy::default().is_active(at(12, 0), NEW_YORK));
+}
+
+#[test]
+fn transitions_enable_then_restore_on_expiry() {
+    let mut all = HashMap::new();
+    all.insert(
+        "cam".to_string(),
+        DevicePrivacy {
+            enabled: true,
+            until: Some(at(12, 0)),
+            windows: Vec::new(),
+        },
+    );
+    let zones = HashMap::new();
+    let mut active = HashSet::new();
+
+    assert_eq!(
+        transitions(&all, at(11, 0), &zones, &active),
+        vec![("cam".to_string(), true)]
+    );
+    active.insert("cam".to_string());
+    // Still private: nothing to do.
+    assert!(transitions(&all, at(11, 30), &zones, &active).is_empty());
+    // `until` passed: outputs come back.
+    assert_eq!(
+        transitions(&all, at(12, 0), &zones, &active),
+        vec![("cam".to_string(), false)]
+    );
+    // Config dropped (switched off, device removed) while private.
+    assert_eq!(
+        transitions(&HashMap::new(), at(11, 0), &zones, &active),
+        vec![("cam".to_string(), false)]
+    );
+}
+
+#[test]
+fn placeholder_is_a_jpeg_with_text() {
+    let frame = placeholder_frame();
+    assert_eq!(
+        (frame.width(), frame.height()),
+        (PLACEHOLDER_WIDTH, PLACEHOLDER_HEIGHT)
+    );
+    let video = frame.as_video();
+    let stride = video.stride(0);
+    let luma = video.data(0);
+    // Top-left dot of the "P" is lit; the corner of the image is background.
+    let cols = PLACEHOLDER_TEXT.len() * 6 - 1;
+    let left = (PLACEHOLDER_WIDTH as usize - cols * PLACEHOLDER_SCALE) / 2;
+    let top = (PLACEHOLDER_HEIGHT as usize - 7 * PLACEHOLDER_SCALE) / 2;
+    assert_eq!(luma[top * stride + left], 200);
+    assert_eq!(luma[0], 40);
+
+    ffmpeg_bus::init().unwrap();
+    let jpeg = placeholder_jpeg().unwrap();
+    assert_eq!(&jpeg[..2], &[0xFF, 0xD8]);
+}
+
+#[tokio::test]
+async fn set_persists_and_applies() {
+    let _db = crate::db::test_db().await;
+    let id = "privacy-test-cam";
+
+    assert!(
+        set(id, true, None, Some(vec![window("7:xx", "08:00")]))
+            .await
+            .is_err()
+    );
+    assert!(!is_private(id));
+
+    let cfg = set(id, true, None, Some(vec![window("22:00", "07:00")]))
+        .await
+        .unwrap();
+    assert!(cfg.enabled);
+    assert!(is_private(id));
+    assert_eq!(load_all().await.unwrap().get(id), Some(&cfg));
+
+    // Switching off keeps the windows.
+    let cfg = set(id, false, None, None).await.unwrap();
+    assert_eq!(cfg.windows, vec![window("22:00", "07:00")]);
+    assert_eq!(
+        is_private(id),
+        cfg.is_active(Utc::now(), crate::config::config().timezone())
+    );
+
+    forget(id).await.unwrap();
+    assert!(!is_private(id));
+    assert!(load_all().await.unwrap().get(id).is_none());
+}
@@ -6,26 +6,38 @@
 //! `/rtp/x.live.flv`. Both plain HTTP — including long-lived HTTP-FLV and HLS,
 //! whose responses are streamed, not buffered — and WebSocket (ZLM's WS-FLV)
 //! are supported.
+//!
+//! Live playback requests count as viewer sessions of their device and are
+//! refused with 503 over the viewer limits (see `crate::viewers`).
+//!
+//! The upstream legs also speak TLS (`https://`, `wss://`) for the federation
+//! proxy, whose peers need not be on a trusted network; ZLM stays plain.
 
-use std::sync::LazyLock;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
 
 use axum::{
     Router,
     body::Body,
     extract::{
-        FromRequestParts, Request,
+        ConnectInfo, FromRequestParts, Request,
         ws::{Message, WebSocket, WebSocketUpgrade},
     },
-    http::{HeaderMap, HeaderName, StatusCode, header},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header, request::Parts},
     response::{IntoResponse, Response},
     routing::any,
 };
 use futures::{SinkExt, StreamExt};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyper_util::{
     client::legacy::{Client, connect::HttpConnector},
     rt::TokioExecutor,
 };
-use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+use crate::usage::EgressMeter;
+use crate::viewers::{Registry, ViewerGuard};
 
 /// ZLM HTTP/WS endpoint. Loopback is fine — ZLM's server binds all interfaces.
 const ZLM_HTTP_HOST: &str = "127.0.0.1";
@@ -34,8 +46,32 @@ const ZLM_HTTP_PORT: u16 = 8553;
 /// Low-level client for the upstream leg. It forwards the incoming `Request`
 /// after only rewriting its URI — request and response bodies stream through
 /// untouched (no buffering), so uploads and live FLV/HLS are both unbounded.
-static CLIENT: LazyLock<Client<HttpConnector, Body>> =
-    LazyLock::new(|| Client::builder(TokioExecutor::new()).build_http());
+static CLIENT: LazyLock<Client<HttpsConnector<HttpConnector>, Body>> = LazyLock::new(|| {
+    let connector = HttpsConnectorBuilder::new()
+        .with_tls_config((*TLS).clone())
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder(TokioExecutor::new()).build(connector)
+});
+
+/// TLS of the upstream legs: the system's root certificates, and an explicit
+/// crypto provider since the dependency tree enables more than one.
+static TLS: LazyLock<Arc<rustls::ClientConfig>> = LazyLock::new(|| {
+    let mut roots = rustls::RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for e in &native.errors {
+        log::warn!("proxy: failed to load a system root certificate: {e}");
+    }
+    roots.add_parsable_certificates(native.certs);
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("the default provider supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+});
 
 /// Router that forwards `/media` and `/media/*` to ZLM. Merge into the app.
 pub(crate) fn media_proxy_router() -> Router {
@@ -55,16 +91,147 @@ async fn proxy(req: Request) -> Response {
         (zlm_path, parts.uri.query().map(str::to_owned))
     };
 
+    // Private devices' streams are gone from ZLM; say why instead of a 404.
+    if private_device_stream(&zlm_path) {
+        return crate::snapshot::privacy_response();
+    }
+
+    // Live playback is a viewer session, admitted under the limits, and
+    // egress of its device.
+    let mut viewer = None;
+    let mut meter = None;
+    if let Some((device_id, kind)) = live_request(&zlm_path) {
+        meter = Some(crate::usage::egress_meter(&device_id));
+        let user = crate::auth::optional_user(&parts.headers, query.as_deref()).await;
+        let registry = Registry::global();
+        let admitted = match kind {
+            LiveKind::Stream => registry.admit(&device_id, user.as_deref()).map(Some),
+            LiveKind::Hls => registry
+                .heartbeat(
+                    &hls_client_key(&device_id, &parts),
+                    &device_id,
+                    user.as_deref(),
+                    Instant::now(),
+                )
+                .map(|()| None),
+        };
+        match admitted {
+            Ok(guard) => viewer = guard,
+            Err(rejection) => {
+                log::info!("media proxy: refused {zlm_path}: {rejection:?}");
+                return rejection.into_response();
+            }
+        }
+    }
+
     // A WebSocket upgrade request extracts cleanly; anything else is plain HTTP.
     match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
-        Ok(ws) => proxy_ws(ws, &zlm_path, query.as_deref()),
+        Ok(ws) => proxy_ws(ws, &zlm_path, query.as_deref(), viewer, meter),
         Err(_) => {
             let req = Request::from_parts(parts, body);
-            proxy_http(req, &zlm_path, query.as_deref()).await
+            let resp = proxy_http(req, &zlm_path, query.as_deref()).await;
+            match (viewer, meter) {
+                (None, None) => resp,
+                (viewer, meter) => hold_while_streaming(resp, viewer, meter),
+            }
         }
     }
 }
 
+/// How a live request is counted.
+#[derive(Debug, PartialEq, Eq)]
+enum LiveKind {
+    /// One long-lived response or socket (`.live.flv`, `.live.mp4`,
+    /// `.live.ts`, over HTTP or WS).
+    Stream,
+    /// One of an HLS player's playlist and segment fetches.
+    Hls,
+}
+
+/// The device and kind of a live playback request for `zlm_path`; `None` for
+/// anything else ZLM serves.
+fn live_request(zlm_path: &str) -> Option<(String, LiveKind)> {
+    let rest = zlm_path.strip_prefix('/')?;
+    let (app, rest) = rest.split_once('/')?;
+    let stream = rest.split(['/', '.']).next().unwrap_or_default();
+    if stream.is_empty() {
+        return None;
+    }
+    let device_id = match app {
+        crate::init::device::DEVICE_APP => crate::stream_key::device_of(stream),
+        // GB28181 streams are named after the device id.
+        "rtp" => stream.to_string(),
+        _ => return None,
+    };
+    let kind = if rest.contains(".live.") {
+        LiveKind::Stream
+    } else if [".m3u8", ".ts", ".m4s", ".mp4"]
+        .iter()
+        .any(|ext| rest.ends_with(ext))
+    {
+        LiveKind::Hls
+    } else {
+        return None;
+    };
+    Some((device_id, kind))
+}
+
+/// One HLS player, as far as requests tell: the device with the client's
+/// address (the first `X-Forwarded-For` hop behind a reverse proxy) and user
+/// agent.
+fn hls_client_key(device_id: &str, parts: &Parts) -> String {
+    let forwarded = parts
+        .headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string());
+    let addr = forwarded.or_else(|| {
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip().to_string())
+    });
+    let agent = parts
+        .headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    format!("{device_id}|{}|{agent}", addr.unwrap_or_default())
+}
+
+/// Keep `viewer`'s session for as long as `resp` streams: hyper drops the
+/// body when the client goes away. The bytes streamed count on `meter`.
+fn hold_while_streaming(
+    resp: Response,
+    viewer: Option<ViewerGuard>,
+    meter: Option<EgressMeter>,
+) -> Response {
+    resp.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _viewer = &viewer;
+            if let (Some(meter), Ok(bytes)) = (&meter, &chunk) {
+                meter.add(bytes.len() as u64);
+            }
+            chunk
+        }))
+    })
+}
+
+/// Whether `zlm_path` (`/device/{key}.live.flv`, `/device/{key}/hls.m3u8`, …)
+/// is a stream of a device in privacy mode.
+fn private_device_stream(zlm_path: &str) -> bool {
+    let Some(rest) = zlm_path
+        .strip_prefix('/')
+        .and_then(|p| p.strip_prefix(crate::init::device::DEVICE_APP))
+        .and_then(|p| p.strip_prefix('/'))
+    else {
+        return false;
+    };
+    let stream = rest.split(['/', '.']).next().unwrap_or_default();
+    !stream.is_empty() && crate::privacy::is_private(&crate::stream_key::device_of(stream))
+}
+
 /// Remove connection-specific (hop-by-hop) headers that must not cross a proxy.
 fn strip_hop_by_hop(headers: &mut HeaderMap) {
     for name in [
@@ -81,13 +248,19 @@ fn strip_hop_by_hop(headers: &mut HeaderMap) {
     headers.remove(HeaderName::from_static("keep-alive"));
 }
 
-async fn proxy_http(mut req: Request, zlm_path: &str, query: Option<&str>) -> Response {
+async fn proxy_http(req: Request, zlm_path: &str, query: Option<&str>) -> Response {
     // Point the request at ZLM; its body streams through untouched.
     let mut target = format!("http://{ZLM_HTTP_HOST}:{ZLM_HTTP_PORT}{zlm_path}");
     if let Some(q) = query {
         target.push('?');
         target.push_str(q);
     }
+    forward_http(req, &target).await
+}
+
+/// Forward `req` to the absolute `target` URI, streaming both bodies. Shared
+/// with the federation proxy, which points the same request path at a peer.
+pub(crate) async fn forward_http(mut req: Request, target: &str) -> Response {
     match target.parse() {
         Ok(uri) => *req.uri_mut() = uri,
         Err(e) => {
@@ -107,28 +280,61 @@ async fn proxy_http(mut req: Request, zlm_path: &str, query: Option<&str>) -> Re
         }
         Err(e) => {
             log::warn!("media proxy: upstream error for {target}: {e}");
-            (StatusCode::BAD_GATEWAY, "zlm upstream error").into_response()
+            (StatusCode::BAD_GATEWAY, "upstream error").into_response()
         }
     }
 }
 
-fn proxy_ws(ws: WebSocketUpgrade, zlm_path: &str, query: Option<&str>) -> Response {
+fn proxy_ws(
+    ws: WebSocketUpgrade,
+    zlm_path: &str,
+    query: Option<&str>,
+    viewer: Option<ViewerGuard>,
+    meter: Option<EgressMeter>,
+) -> Response {
     let mut url = format!("ws://{ZLM_HTTP_HOST}:{ZLM_HTTP_PORT}{zlm_path}");
     if let Some(q) = query {
         url.push('?');
         url.push_str(q);
     }
+    forward_ws(ws, url, None, viewer, meter)
+}
+
+/// Accept the client upgrade and relay it to the upstream WebSocket `url`,
+/// sending `authorization` in the upstream handshake when given.
+/// `viewer`'s session lasts until either side closes; what the upstream sends
+/// counts on `meter`.
+pub(crate) fn forward_ws(
+    ws: WebSocketUpgrade,
+    url: String,
+    authorization: Option<HeaderValue>,
+    viewer: Option<ViewerGuard>,
+    meter: Option<EgressMeter>,
+) -> Response {
     ws.on_upgrade(move |client| async move {
-        if let Err(e) = relay_ws(client, &url).await {
+        let _viewer = viewer;
+        if let Err(e) = relay_ws(client, &url, authorization, meter).await {
             log::debug!("media ws proxy for {url} ended: {e}");
         }
     })
 }
 
-/// Bridge the client WebSocket to an upstream WS connection to ZLM, relaying
-/// frames both ways until either side closes.
-async fn relay_ws(client: WebSocket, url: &str) -> anyhow::Result<()> {
-    let (upstream, _resp) = tokio_tungstenite::connect_async(url).await?;
+/// Bridge the client WebSocket to an upstream WS connection (ZLM, or a
+/// federation peer), relaying frames both ways until either side closes.
+async fn relay_ws(
+    client: WebSocket,
+    url: &str,
+    authorization: Option<HeaderValue>,
+    meter: Option<EgressMeter>,
+) -> anyhow::Result<()> {
+    let mut request = url.into_client_request()?;
+    if let Some(value) = authorization {
+        request.headers_mut().insert(header::AUTHORIZATION, value);
+    }
+    let connector = tokio_tungstenite::Connector::Rustls(TLS.clone());
+    let (upstream, _resp) =
+        tokio_tungstenite::connect_async_tls_with_config(request, None, false, Some(connector))
+            .await?;
     let (mut up_tx, mut up_rx) = upstream.split();
     let (mut cl_tx, mut cl_rx) = client.split();
 
@@ -140,7 +346,11 @@ async fn relay_ws(client: WebSocket, url: &str) -> anyhow::Result<()> {
     };
     let upstream_to_client = async {
         while let Some(msg) = up_rx.next().await {
-            if let Some(m) = ts_to_axum(msg?) {
+            let msg = msg?;
+            if let Some(meter) = &meter {
+                meter.add(msg.len() as u64);
+            }
+            if let Some(m) = ts_to_axum(msg) {
                 cl_tx.send(m).await?;
             }
         }
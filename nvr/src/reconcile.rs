@@ -0,0 +1,488 @@
+//! Reconciliation of the recordings root with the `record_segments` table. A
+//! crash or a disk swap leaves the two out of step: rows whose file is gone,
+//! and archived files that never got a row (the process died between the copy
+//! and the insert). A pass walks the root and matches files to rows by path:
+//!
+//! - a row under the root whose file is gone is marked
+//!   [`STATUS_MISSING`](record_segment::STATUS_MISSING): playback no longer
+//!   lists it, but it is kept for audit (and cleared if the file comes back);
+//! - an untracked file is probed and registered, its device and start time
+//!   taken from the archive layout (`<root>/<stream>/<file_name>`, see
+//!   [`infer_start_time`]);
+//! - a fragmented MP4 whose last fragment was cut off is first rewritten with
+//!   [`RemuxJob`], which keeps every complete fragment.
+//!
+//! A pass runs at startup and on `POST /api/admin/reconcile_recordings`; only
+//! one runs at a time. Its [`ReconcileReport`] is saved under the KV config
+//! key `record_reconcile` after every batch of files, so a pass cut short by a
+//! restart resumes after the last file it handled. Callers wait a few seconds
+//! for the pass and leave the rest to the background.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use axum::{
+    Extension, Router,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use chrono::{DateTime, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
+use ffmpeg_bus::RemuxJob;
+use futures::StreamExt;
+use nvr_db::record_segment::{self, RecordSegment, STATUS_MISSING};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::AuthUser;
+use crate::db::app_db_conn;
+use crate::handler::{ApiError, ApiResult, ok_json};
+
+/// KV config key of the last pass's report.
+const REPORT_KEY: &str = "record_reconcile";
+/// How long startup and the API wait for a pass before leaving it running in
+/// the background.
+const WAIT_BUDGET: Duration = Duration::from_secs(3);
+/// Untracked files probed (and repaired) at once.
+const PROBE_CONCURRENCY: usize = 4;
+/// Untracked files handled between two saves of the report (resume points).
+const BATCH: usize = 32;
+/// Files modified more recently than this may still be being archived, and
+/// are left to the next pass.
+const SETTLE: Duration = Duration::from_secs(120);
+/// Failures kept in the report; the rest are only counted.
+const MAX_ERRORS: usize = 50;
+/// Containers the recorder archives.
+const MEDIA_EXTENSIONS: &[&str] = &["mp4", "ts", "flv"];
+/// ZLM's vhost, which every archived recording belongs to.
+pub(crate) const DEFAULT_VHOST: &str = "__defaultVhost__";
+
+/// Outcome of a reconciliation pass; `finished_ms` is `None` while it runs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    pub started_ms: i64,
+    pub finished_ms: Option<i64>,
+    /// Media files found under the recordings root.
+    pub files: usize,
+    /// Files that already had a row.
+    pub tracked: usize,
+    /// Rows marked missing by this pass.
+    pub missing: usize,
+    /// Missing rows whose file is back.
+    pub restored: usize,
+    /// Untracked files given a row.
+    pub registered: usize,
+    /// Of those, files rewritten because their last fragment was cut off.
+    pub repaired: usize,
+    /// Untracked files that could not be registered.
+    pub failed: usize,
+    /// The first [`MAX_ERRORS`] failures.
+    pub errors: Vec<ReconcileError>,
+    /// Path (relative to the root) of the last untracked file handled; an
+    /// unfinished pass resumes after it.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReconcileError {
+    pub path: String,
+    pub error: String,
+}
+
+/// Held by the running pass.
+static PASS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub fn admin_router() -> Router {
+    Router::new().route(
+        "/reconcile_recordings",
+        post(reconcile_recordings).get(get_report),
+    )
+}
+
+/// Run a pass at startup, waiting at most [`WAIT_BUDGET`] for it.
+pub async fn run_at_startup() {
+    match reconcile(WAIT_BUDGET).await {
+        Ok(report) if report.finished_ms.is_some() => log::info!(
+            "record reconcile: {} file(s), {} missing, {} registered ({} repaired), {} failed",
+            report.files,
+            report.missing,
+            report.registered,
+            report.repaired,
+            report.failed
+        ),
+        Ok(_) => log::info!("record reconcile: continuing in the background"),
+        // Already logged by the pass.
+        Err(_) => {}
+    }
+}
+
+/// Start a pass over the configured recordings root, unless one is running,
+/// and wait up to `budget` for it. Returns its report, still in progress
+/// (`finished_ms: None`) if the pass outlives the wait.
+pub async fn reconcile(budget: Duration) -> Result<ReconcileReport> {
+    let root = crate::config::config().record_dir();
+    let pass = tokio::spawn(async move {
+        run_pass(&root)
+            .await
+            .inspect_err(|e| log::warn!("record reconcile: pass failed: {e:#}"))
+    });
+    match tokio::time::timeout(budget, pass).await {
+        Ok(report) => report?,
+        Err(_) => Ok(load_report().await?.unwrap_or_default()),
+    }
+}
+
+/// The last saved report, if a pass ever ran.
+pub async fn load_report() -> Result<Option<ReconcileReport>> {
+    let conn = app_db_conn()?;
+    nvr_db::config::get_json::<ReconcileReport>(REPORT_KEY, &conn).await
+}
+
+async fn save_report(report: &ReconcileReport) -> Result<()> {
+    let conn = app_db_conn()?;
+    nvr_db::config::set_json(REPORT_KEY, report, &conn).await
+}
+
+/// One pass over `root`, resuming the saved one if it did not finish. If a
+/// pass is already running, returns its last saved report instead.
+pub(crate) async fn run_pass(root: &Path) -> Result<ReconcileReport> {
+    let Ok(_pass) = PASS.try_lock() else {
+        return Ok(load_report().await?.unwrap_or_default());
+    };
+    let mut report = match load_report().await? {
+        Some(report) if report.finished_ms.is_none() => report,
+        _ => ReconcileReport {
+            started_ms: chrono::Utc::now().timestamp_millis(),
+            ..Default::default()
+        },
+    };
+    // A root that is not there (e.g. an unmounted disk) says nothing about
+    // the files in it.
+    if !tokio::fs::try_exists(root).await.unwrap_or(false) {
+        log::warn!(
+            "record reconcile: {} does not exist, skipped",
+            root.display()
+        );
+        return finish(report).await;
+    }
+
+    let files = {
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || scan(&root)).await??
+    };
+    let conn = app_db_conn()?;
+    let rows = record_segment::list_all(&conn).await?;
+    let file_set = files.iter().map(|(path, _)| path).collect::<HashSet<_>>();
+    let mut tracked = HashSet::new();
+    for row in &rows {
+        let path = PathBuf::from(&row.file_path);
+        if !path.starts_with(root) {
+            continue;
+        }
+        let exists =
+            file_set.contains(&path) || tokio::fs::try_exists(&path).await.unwrap_or(false);
+        if !exists && row.status != STATUS_MISSING {
+            record_segment::set_status(&row.id, STATUS_MISSING, &conn).await?;
+            log::warn!("record reconcile: {} is missing", row.file_path);
+            report.missing += 1;
+        } else if exists && row.status == STATUS_MISSING {
+            record_segment::set_status(&row.id, "", &conn).await?;
+            report.restored += 1;
+        }
+        tracked.insert(path);
+    }
+    report.files = files.len();
+    report.tracked = files
+        .iter()
+        .filter(|(path, _)| tracked.contains(path))
+        .count();
+
+    let settled = SystemTime::now() - SETTLE;
+    let untracked = files
+        .into_iter()
+        .filter(|(path, modified)| !tracked.contains(path) && *modified <= settled)
+        .map(|(path, _)| path)
+        // Compared as paths, the order `scan` sorts in.
+        .filter(|path| {
+            report
+                .cursor
+                .as_deref()
+                .is_none_or(|cursor| path.strip_prefix(root).unwrap_or(path) > Path::new(cursor))
+        })
+        .collect::<Vec<_>>();
+    save_report(&report).await?;
+
+    // File names are on each device's wall clock.
+    let zones = Arc::new(crate::tz::of_devices().await?);
+    for batch in untracked.chunks(BATCH) {
+        let results = futures::stream::iter(batch.iter().cloned())
+            .map(|path| {
+                let (root, file) = (root.to_path_buf(), path.clone());
+                let zones = zones.clone();
+                async move {
+                    let inspected =
+                        tokio::task::spawn_blocking(move || inspect(&root, &file, &zones))
+                            .await
+                            .map_err(anyhow::Error::from)
+                            .and_then(|inspected| inspected);
+                    (path, inspected)
+                }
+            })
+            .buffer_unordered(PROBE_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+        for (path, inspected) in results {
+            let registered = match inspected {
+                Ok((record, repaired)) => record_segment::upsert(&record, &conn)
+                    .await
+                    .map(|()| repaired),
+                Err(e) => Err(e),
+            };
+            match registered {
+                Ok(repaired) => {
+                    report.registered += 1;
+                    report.repaired += repaired as usize;
+                }
+                Err(e) => {
+                    log::warn!("record reconcile: {} not registered: {e:#}", path.display());
+                    report.failed += 1;
+                    if report.errors.len() < MAX_ERRORS {
+                        report.errors.push(ReconcileError {
+                            path: path.to_string_lossy().into_owned(),
+                            error: format!("{e:#}"),
+                        });
+                    }
+                }
+            }
+        }
+        report.cursor = batch.last().map(|path| relative(root, path));
+        save_report(&report).await?;
+    }
+    finish(report).await
+}
+
+async fn finish(mut report: ReconcileReport) -> Result<ReconcileReport> {
+    report.finished_ms = Some(chrono::Utc::now().timestamp_millis());
+    report.cursor = None;
+    save_report(&report).await?;
+    Ok(report)
+}
+
+/// Media files under `root` with their modification time, sorted by path.
+/// Hidden entries (e.g. a remux's temporary file) are skipped.
+fn scan(root: &Path) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("read {}", dir.display()))? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            let path = entry.path();
+            if meta.is_dir() {
+                dirs.push(path);
+            } else if meta.is_file() && has_media_extension(&path) {
+                files.push((path, meta.modified()?));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn has_media_extension(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        MEDIA_EXTENSIONS
+            .iter()
+            .any(|media| ext.eq_ignore_ascii_case(media))
+    })
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Repair `path` if needed and probe it into a new segment row. Blocking.
+fn inspect(root: &Path, path: &Path, zones: &HashMap<String, Tz>) -> Result<(RecordSegment, bool)> {
+    let relative = path.strip_prefix(root)?;
+    let mut components = relative.components();
+    let stream = components
+        .next()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .context("empty path")?;
+    let file_name = components.as_path().to_string_lossy().into_owned();
+    if file_name.is_empty() {
+        anyhow::bail!("not in a device directory");
+    }
+
+    let is_mp4 = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mp4"));
+    let repaired = is_mp4 && {
+        let layout = mp4_layout(path)?;
+        layout.fragmented && layout.truncated
+    };
+    if repaired {
+        RemuxJob::new(path).run()?;
+        log::info!("record reconcile: repaired {}", path.display());
+    }
+
+    let path_string = path.to_string_lossy().into_owned();
+    let meta = ffmpeg_bus::metadata::probe(&path_string)?;
+    let file = std::fs::metadata(path)?;
+    let duration = meta.format.duration_sec.unwrap_or_default();
+    let tz = crate::tz::lookup(zones, &stream);
+    let start_time = infer_start_time(relative, tz).unwrap_or_else(|| {
+        let modified = file
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        (modified.as_secs_f64() - duration).max(0.0) as u64
+    });
+    let now = chrono::Utc::now();
+    let mut record = RecordSegment {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        start_time,
+        duration: duration as f32,
+        file_size: file.len() as usize,
+        file_name,
+        folder: path
+            .parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        file_path: path_string,
+        app: crate::init::device::DEVICE_APP.to_string(),
+        stream,
+        vhost: DEFAULT_VHOST.to_string(),
+        create_time: now,
+        update_time: now,
+        ..Default::default()
+    };
+    crate::zlm::server::apply_media_info(&mut record, &meta);
+    Ok((record, repaired))
+}
+
+/// Wall clock start (unix seconds) of an archived file, from its path
+/// relative to the recordings root: a `<stream>/<unix seconds or ms>.<ext>`
+/// name, or the `<stream>/<YYYY-MM-DD>/<HH-MM-SS>….<ext>` layout on the
+/// device's wall clock, in `tz`.
+pub(crate) fn infer_start_time(relative: &Path, tz: Tz) -> Option<u64> {
+    let stem = relative.file_stem()?.to_str()?;
+    if !stem.is_empty() && stem.bytes().all(|b| b.is_ascii_digit()) {
+        let ts = stem.parse::<u64>().ok()?;
+        // Thirteen digits and up are milliseconds.
+        if ts >= 1_000_000_000_000 {
+            return Some(ts / 1000);
+        }
+        return Some(ts);
+    }
+    let date = relative.parent()?.file_name()?.to_str()?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let time = NaiveTime::parse_from_str(stem.get(..8)?, "%H-%M-%S").ok()?;
+    let start = crate::tz::resolve(date.and_time(time), tz);
+    u64::try_from(start.timestamp()).ok()
+}
+
+/// ZLM's `YYYY-MM-DD/HH-MM-SS….<ext>` name of a segment started at
+/// `start_time` (unix seconds) with the date and time on `tz`'s wall clock
+/// instead of the server's, so [`infer_start_time`] reads it back. Other names
+/// are kept as they are.
+pub(crate) fn archive_name(file_name: &str, start_time: u64, tz: Tz) -> String {
+    let Some((date, rest)) = file_name.split_once('/') else {
+        return file_name.to_string();
+    };
+    let zlm_layout = NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()
+        && rest
+            .get(..8)
+            .is_some_and(|time| NaiveTime::parse_from_str(time, "%H-%M-%S").is_ok());
+    let Some(start) = DateTime::from_timestamp(start_time as i64, 0).filter(|_| zlm_layout) else {
+        return file_name.to_string();
+    };
+    let local = start.with_timezone(&tz);
+    format!(
+        "{}/{}{}",
+        local.format("%Y-%m-%d"),
+        local.format("%H-%M-%S"),
+        &rest[8..]
+    )
+}
+
+/// Top-level box layout of an MP4 file.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Mp4Layout {
+    /// Has a `moof` box.
+    fragmented: bool,
+    /// The last box runs past the end of the file: writing was cut off.
+    truncated: bool,
+}
+
+fn mp4_layout(path: &Path) -> Result<Mp4Layout> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut layout = Mp4Layout::default();
+    let mut pos = 0u64;
+    while pos < len {
+        if len - pos < 8 {
+            layout.truncated = true;
+            break;
+        }
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header[..8])?;
+        let size = match u32::from_be_bytes(header[..4].try_into()?) {
+            // Extends to the end of the file.
+            0 => len - pos,
+            1 if len - pos < 16 => {
+                layout.truncated = true;
+                break;
+            }
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                u64::from_be_bytes(header[8..].try_into()?)
+            }
+            size => size as u64,
+        };
+        if &header[4..8] == b"moof" {
+            layout.fragmented = true;
+        }
+        if size < 8 || size > len - pos {
+            layout.truncated = true;
+            break;
+        }
+        pos += size;
+    }
+    Ok(layout)
+}
+
+/// Run a pass (or join the running one) and return its report.
+async fn reconcile_recordings(Extension(user): Extension<AuthUser>) -> ApiResult<Response> {
+    if !crate::auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden(
+            "recording reconciliation is admin-only",
+        ));
+    }
+    Ok(ok_json(reconcile(WAIT_BUDGET).await?).into_response())
+}
+
+/// The last pass's report (`null` if none ran yet).
+async fn get_report(Extension(user): Extension<AuthUser>) -> ApiResult<Response> {
+    if !crate::auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden(
+            "recording reconciliation is admin-only",
+        ));
+    }
+    Ok(ok_json(load_report().await?).into_response())
+}
+
+#[cfg(test)]
+#[path = "reconcile_test.rs"]
+mod reconcile_test;
@@ -0,0 +1,228 @@
+use std::time::{Duration, SystemTime};
+
+use chrono::TimeZone;
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+use ffmpeg_bus::{RemuxOptions, remux_file};
+
+use super::*;
+
+/// Copy `src` to `dst`, dated an hour ago so the pass does not take it for a
+/// file still being archived.
+fn settled_copy(src: &Path, dst: &Path) {
+    std::fs::create_dir_all(dst.parent().unwrap()).unwrap();
+    std::fs::copy(src, dst).unwrap();
+    let file = std::fs::File::options().write(true).open(dst).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(3600))
+        .unwrap();
+}
+
+fn segment(stream: &str, path: &Path) -> RecordSegment {
+    RecordSegment {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        start_time: 1_000,
+        duration: 5.0,
+        file_name: path.file_name().unwrap().to_string_lossy().into_owned(),
+        file_path: path.to_string_lossy().into_owned(),
+        app: crate::init::device::DEVICE_APP.to_string(),
+        stream: stream.to_string(),
+        create_time: chrono::Utc::now(),
+        update_time: chrono::Utc::now(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn start_time_from_the_archive_layout() {
+    let tz = chrono_tz::America::New_York;
+    assert_eq!(
+        infer_start_time(Path::new("cam/1760000000.ts"), tz),
+        Some(1_760_000_000)
+    );
+    assert_eq!(
+        infer_start_time(Path::new("cam/1760000000123.mp4"), tz),
+        Some(1_760_000_000)
+    );
+    // 12:30:05 EDT.
+    assert_eq!(
+        infer_start_time(Path::new("cam/2026-10-14/12-30-05-0.mp4"), tz),
+        Some(1_791_995_405)
+    );
+    assert_eq!(infer_start_time(Path::new("cam/clip.mp4"), tz), None);
+    assert_eq!(
+        infer_start_time(Path::new("cam/misc/12-30-05.mp4"), tz),
+        None
+    );
+}
+
+#[test]
+fn archive_names_are_on_the_device_clock() {
+    let tz = chrono_tz::America::New_York;
+    // 2026-10-15 00:30:05 UTC is still the 14th in New York.
+    let start = 1_792_024_205;
+    let name = archive_name("2026-10-15/00-30-05-0.mp4", start, tz);
+    assert_eq!(name, "2026-10-14/20-30-05-0.mp4");
+    assert_eq!(
+        infer_start_time(&Path::new("cam").join(&name), tz),
+        Some(start)
+    );
+    assert_eq!(archive_name("1760000000.ts", start, tz), "1760000000.ts");
+    assert_eq!(archive_name("misc/clip.mp4", start, tz), "misc/clip.mp4");
+}
+
+#[tokio::test]
+async fn pass_marks_missing_registers_untracked_and_repairs_truncated() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let fixture = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let root = std::env::temp_dir().join(format!("nvr-reconcile-{}", uuid::Uuid::new_v4()));
+
+    // Tracked and present.
+    let tracked = root.join("cam-a").join("1700000000.mp4");
+    settled_copy(&fixture, &tracked);
+    record_segment::upsert(&segment("cam-a", &tracked), &conn)
+        .await
+        .unwrap();
+    // Tracked, but the file is gone.
+    let gone = root.join("cam-a").join("1700000100.mp4");
+    let gone_row = segment("cam-a", &gone);
+    record_segment::upsert(&gone_row, &conn).await.unwrap();
+    // Untracked.
+    let untracked = root.join("cam-b").join("1760000000.mp4");
+    settled_copy(&fixture, &untracked);
+    // Untracked fragmented MP4 whose last fragment was cut off.
+    let fragmented = std::env::temp_dir().join(format!("{}.mp4", uuid::Uuid::new_v4()));
+    remux_file(
+        &fixture,
+        &fragmented,
+        &RemuxOptions {
+            format: Some("mp4".to_string()),
+            movflags: Some("+frag_keyframe+empty_moov".to_string()),
+        },
+    )
+    .unwrap();
+    let truncated = root.join("cam-b").join("2026-10-14").join("12-00-00-0.mp4");
+    settled_copy(&fragmented, &truncated);
+    let _ = std::fs::remove_file(&fragmented);
+    let file = std::fs::File::options()
+        .write(true)
+        .open(&truncated)
+        .unwrap();
+    file.set_len(file.metadata().unwrap().len() - 200).unwrap();
+    let layout = mp4_layout(&truncated).unwrap();
+    assert!(layout.fragmented && layout.truncated, "{layout:?}");
+    // Hidden files (e.g. a remux in progress) are not recordings.
+    settled_copy(&fixture, &root.join("cam-b").join(".x.remux.tmp.mp4"));
+
+    let report = run_pass(&root).await.unwrap();
+    assert!(report.finished_ms.is_some());
+    assert_eq!(report.cursor, None);
+    assert_eq!(
+        (
+            report.files,
+            report.tracked,
+            report.missing,
+            report.restored
+        ),
+        (3, 1, 1, 0),
+        "{report:?}"
+    );
+    assert_eq!(
+        (report.registered, report.repaired, report.failed),
+        (2, 1, 0),
+        "{report:?}"
+    );
+    assert_eq!(load_report().await.unwrap(), Some(report));
+
+    // The missing row is kept but no longer listed.
+    let row = record_segment::get(&gone_row.id, &conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.status, STATUS_MISSING);
+    let listed = record_segment::list_by_stream("cam-a", &conn)
+        .await
+        .unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].file_path, tracked.to_string_lossy());
+
+    // Untracked files are registered under their device, at the time in
+    // their name; the truncated one was rewritten into a playable file.
+    let mut registered = record_segment::list_by_stream("cam-b", &conn)
+        .await
+        .unwrap();
+    registered.sort_by_key(|s| s.start_time);
+    // Not a configured device: the server's zone.
+    let noon = crate::config::config()
+        .timezone()
+        .with_ymd_and_hms(2026, 10, 14, 12, 0, 0)
+        .unwrap();
+    assert_eq!(
+        registered.iter().map(|s| s.start_time).collect::<Vec<_>>(),
+        vec![1_760_000_000, noon.timestamp() as u64]
+    );
+    assert_eq!(registered[1].file_name, "2026-10-14/12-00-00-0.mp4");
+    assert_eq!(registered[1].video_codec, "h264");
+    assert!(
+        registered.iter().all(|s| s.duration > 0.0),
+        "{registered:?}"
+    );
+    assert_eq!(mp4_layout(&truncated).unwrap(), Mp4Layout::default());
+
+    // A second pass finds nothing to do; the file coming back clears the
+    // missing mark.
+    settled_copy(&fixture, &gone);
+    let report = run_pass(&root).await.unwrap();
+    assert_eq!(
+        (
+            report.files,
+            report.tracked,
+            report.missing,
+            report.restored
+        ),
+        (4, 4, 0, 1),
+        "{report:?}"
+    );
+    assert_eq!(report.registered, 0);
+    let row = record_segment::get(&gone_row.id, &conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.status, "");
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[tokio::test]
+async fn unfinished_pass_resumes_after_its_cursor() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let fixture = ensure_fixture(&FixtureSpec::default().video_only())
+        .await
+        .unwrap();
+    let root = std::env::temp_dir().join(format!("nvr-reconcile-{}", uuid::Uuid::new_v4()));
+    let done = root.join("cam-c").join("1760000000.mp4");
+    let todo = root.join("cam-c").join("1760000060.mp4");
+    settled_copy(&fixture, &done);
+    settled_copy(&fixture, &todo);
+
+    // As saved by a pass cut short after the first file.
+    save_report(&ReconcileReport {
+        started_ms: 1,
+        registered: 1,
+        cursor: Some(relative(&root, &done)),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let report = run_pass(&root).await.unwrap();
+    assert_eq!(report.started_ms, 1);
+    assert_eq!(report.registered, 2, "{report:?}");
+    assert!(report.finished_ms.is_some());
+    let rows = record_segment::list_by_stream("cam-c", &conn)
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].file_path, todo.to_string_lossy());
+
+    let _ = std::fs::remove_dir_all(&root);
+}
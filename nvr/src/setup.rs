@@ -0,0 +1,309 @@
+//! First-run setup. A fresh install has no users, so nobody could sign in:
+//! until an admin exists the server is in setup mode, where every API call
+//! but `GET /setup/status` and `POST /setup` answers 503 "setup required".
+//! The dashboard is still served, so it can show its setup wizard.
+//!
+//! `POST /setup` creates the admin (with the admin role, see
+//! [`crate::auth::is_admin`]) and records the recordings root and the default
+//! time zone in one transaction, then leaves setup mode. Calls are
+//! serialized, so of two concurrent ones exactly one succeeds; every later
+//! one gets 409. A completed setup stays recorded
+//! (`nvr_db::setup::SETUP_KEY`), so removing every user does not reopen it;
+//! only `nvr reset --confirm` forgets it, along with every user and session.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+
+use axum::{
+    Json, Router,
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::Utc;
+use nvr_db::setup::SETUP_KEY;
+use serde::{Deserialize, Serialize};
+
+use crate::config::config;
+use crate::db::app_db_conn;
+use crate::handler::{ApiError, ApiJsonResult, BaseResponse, ok_json};
+
+/// The setup routes, relative to the API router: the ones answered in setup
+/// mode, and without a session.
+const PATHS: &[&str] = &["/setup", "/setup/status"];
+
+/// Written and removed again to check that the recordings root is writable.
+const PROBE_FILE: &str = ".nvr-setup-probe";
+
+/// Whether setup is still to be done, shared by the gate and the setup
+/// routes.
+pub(crate) struct Setup {
+    required: AtomicBool,
+    /// Held by the `POST /setup` running.
+    completing: tokio::sync::Mutex<()>,
+}
+
+impl Setup {
+    pub(crate) fn new(required: bool) -> Arc<Self> {
+        Arc::new(Self {
+            required: AtomicBool::new(required),
+            completing: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub(crate) fn required(&self) -> bool {
+        self.required.load(Ordering::SeqCst)
+    }
+}
+
+/// The server's setup state, set by [`init`].
+static SETUP: LazyLock<Arc<Setup>> = LazyLock::new(|| Setup::new(false));
+
+pub(crate) fn global() -> Arc<Setup> {
+    SETUP.clone()
+}
+
+/// What setup recorded, as JSON under [`SETUP_KEY`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SetupSettings {
+    /// Recordings root; empty for the default.
+    #[serde(default)]
+    record_dir: String,
+    /// IANA zone of devices without their own; empty for the server's.
+    #[serde(default)]
+    timezone: String,
+    /// When setup completed, unix milliseconds.
+    #[serde(default)]
+    completed_at: i64,
+}
+
+impl SetupSettings {
+    fn apply(&self) {
+        config().choose(
+            (!self.record_dir.is_empty()).then(|| PathBuf::from(&self.record_dir)),
+            crate::tz::parse(&self.timezone).ok(),
+        );
+    }
+}
+
+/// At boot: apply the settings of the completed setup, or enter setup mode
+/// if there is none and no user either. An install from before setup
+/// existed (users, no record) is recorded as set up with the defaults.
+pub(crate) async fn init() -> anyhow::Result<()> {
+    let conn = app_db_conn()?;
+    if let Some(settings) = nvr_db::config::get_json::<SetupSettings>(SETUP_KEY, &conn).await? {
+        settings.apply();
+        return Ok(());
+    }
+    if nvr_db::user::list(&conn).await?.is_empty() {
+        log::warn!(
+            "No users yet: first-run setup required, open the dashboard to create the admin"
+        );
+        SETUP.required.store(true, Ordering::SeqCst);
+        return Ok(());
+    }
+    let settings = SetupSettings {
+        completed_at: Utc::now().timestamp_millis(),
+        ..SetupSettings::default()
+    };
+    nvr_db::config::set_json(SETUP_KEY, &settings, &conn).await
+}
+
+/// `nvr reset [--confirm]`: forget the completed setup, every user and
+/// every session, so the next start is in setup mode again.
+pub(crate) async fn reset(confirm: bool) -> anyhow::Result<()> {
+    if !confirm {
+        anyhow::bail!(
+            "reset deletes every user and session and reopens first-run setup; \
+             run `nvr reset --confirm` to go ahead"
+        );
+    }
+    nvr_db::setup::reset(&mut app_db_conn()?).await
+}
+
+/// Middleware of the API router: while setup is required, everything but
+/// the setup routes answers 503. Layered outside auth, so callers learn that
+/// setup is due rather than that they are not signed in.
+pub(crate) async fn gate(State(setup): State<Arc<Setup>>, req: Request, next: Next) -> Response {
+    if setup.required() && !PATHS.contains(&req.uri().path()) {
+        return ApiError::unavailable("setup required").into_response();
+    }
+    next.run(req).await
+}
+
+pub(crate) fn setup_router(setup: Arc<Setup>) -> Router {
+    Router::new()
+        .route("/", post(complete))
+        .route("/status", get(status))
+        .with_state(setup)
+}
+
+/// The schema of [`setup_router`], mounted at `/api/v1/setup`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(status, complete))]
+pub(crate) struct SetupApi;
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct SetupStatus {
+    /// Whether setup is still to be done; every other call answers 503 until
+    /// it is.
+    required: bool,
+    /// While it is, the recordings root and zone used if none is picked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+}
+
+/// Whether first-run setup is due, with the defaults for the wizard.
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "setup",
+    responses((status = 200, body = BaseResponse<SetupStatus>)),
+    security(())
+)]
+async fn status(State(setup): State<Arc<Setup>>) -> Json<BaseResponse<SetupStatus>> {
+    let required = setup.required();
+    let defaults = required.then(|| {
+        (
+            config().record_dir().to_string_lossy().into_owned(),
+            config().timezone().name().to_string(),
+        )
+    });
+    let (record_dir, timezone) = defaults.unzip();
+    ok_json(SetupStatus {
+        required,
+        record_dir,
+        timezone,
+    })
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SetupRequest {
+    /// The admin's credentials.
+    username: String,
+    password: String,
+    /// Absolute path of the recordings root, created if missing; empty for
+    /// the default.
+    #[serde(default)]
+    record_dir: String,
+    /// IANA zone of devices without their own, e.g. `Europe/Berlin`; empty
+    /// for the server's.
+    #[serde(default)]
+    timezone: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct SetupResponse {
+    /// A session of the new admin, as from `/user/login`.
+    token: String,
+    username: String,
+}
+
+/// Create the admin and record the settings, leaving setup mode.
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "setup",
+    request_body = SetupRequest,
+    responses(
+        (status = 200, body = BaseResponse<SetupResponse>),
+        (
+            status = 400,
+            description = "Invalid credentials, directory or zone",
+            body = BaseResponse<()>,
+        ),
+        (status = 409, description = "Setup was already completed", body = BaseResponse<()>),
+    ),
+    security(())
+)]
+async fn complete(
+    State(setup): State<Arc<Setup>>,
+    Json(req): Json<SetupRequest>,
+) -> ApiJsonResult<SetupResponse> {
+    let _completing = setup.completing.lock().await;
+    if !setup.required() {
+        return Err(ApiError::conflict("setup already completed"));
+    }
+    let settings = validate(&req)
+        .await
+        .map_err(|e| ApiError::bad_request(format!("{e:#}")))?;
+    let username = req.username.trim();
+    let token = finish(&setup, username, &req.password, settings).await?;
+    Ok(ok_json(SetupResponse {
+        token,
+        username: username.to_string(),
+    }))
+}
+
+/// Check a setup request: credentials given, a known zone and a recordings
+/// root that can be created and written to.
+async fn validate(req: &SetupRequest) -> anyhow::Result<SetupSettings> {
+    if req.username.trim().is_empty() || req.password.is_empty() {
+        anyhow::bail!("username and password must not be empty");
+    }
+    let timezone = req.timezone.trim();
+    crate::tz::validate(timezone)?;
+    let record_dir = req.record_dir.trim();
+    let root = if record_dir.is_empty() {
+        config().record_dir()
+    } else if Path::new(record_dir).is_absolute() {
+        PathBuf::from(record_dir)
+    } else {
+        anyhow::bail!("recordings directory must be an absolute path: {record_dir}");
+    };
+    check_writable(&root).await.map_err(|e| {
+        anyhow::anyhow!(
+            "recordings directory {} is not writable: {e}",
+            root.display()
+        )
+    })?;
+    Ok(SetupSettings {
+        record_dir: record_dir.to_string(),
+        timezone: timezone.to_string(),
+        completed_at: 0,
+    })
+}
+
+async fn check_writable(dir: &Path) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    let probe = dir.join(PROBE_FILE);
+    tokio::fs::write(&probe, b"").await?;
+    tokio::fs::remove_file(&probe).await
+}
+
+/// Store the admin and the settings, apply them and leave setup mode.
+/// Returns a session of the admin.
+async fn finish(
+    setup: &Setup,
+    username: &str,
+    password: &str,
+    mut settings: SetupSettings,
+) -> anyhow::Result<String> {
+    let now = Utc::now();
+    let admin = nvr_db::user::UserInfo {
+        username: username.to_string(),
+        password_hash: nvr_db::user::hash_password(password)?,
+        metadata: HashMap::from([(
+            crate::auth::ROLE_KEY.to_string(),
+            crate::auth::ROLE_ADMIN.to_string(),
+        )]),
+        create_time: now,
+        update_time: now,
+    };
+    settings.completed_at = now.timestamp_millis();
+    let json = serde_json::to_string(&settings)?;
+    nvr_db::setup::complete(&admin, &json, &mut app_db_conn()?).await?;
+    settings.apply();
+    setup.required.store(false, Ordering::SeqCst);
+    log::info!("First-run setup completed, admin: {username}");
+    crate::auth::create_session(username).await
+}
+
+#[cfg(test)]
+#[path = "setup_test.rs"]
+mod setup_test;
@@ -0,0 +1,151 @@
+use axum::body::Body;
+use axum::http::Request as HttpRequest;
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+use super::*;
+
+fn app(setup: &Arc<Setup>) -> Router {
+    crate::api::api_router_with(setup.clone())
+}
+
+/// Send a request through the API router; the status and the JSON body.
+async fn call(app: Router, req: HttpRequest<Body>) -> (StatusCode, Value) {
+    let res = app.oneshot(req).await.unwrap();
+    let status = res.status();
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn get(uri: &str, token: Option<&str>) -> HttpRequest<Body> {
+    let mut req = HttpRequest::get(uri);
+    if let Some(token) = token {
+        req = req.header("Authorization", format!("Bearer {token}"));
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+fn post(uri: &str, body: &Value) -> HttpRequest<Body> {
+    HttpRequest::post(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// A setup request for a fresh admin. Root and zone are the current
+/// defaults, so applying them changes nothing for other tests.
+fn setup_body() -> (String, Value) {
+    let username = format!("setup-{}", uuid::Uuid::new_v4());
+    let body = json!({
+        "username": username,
+        "password": "s3cret",
+        "record_dir": config().record_dir().to_string_lossy(),
+        "timezone": config().timezone().name(),
+    });
+    (username, body)
+}
+
+#[tokio::test]
+async fn setup_mode_answers_503_except_for_setup() {
+    let setup = Setup::new(true);
+    // Even without a session: setup comes first.
+    for uri in ["/device/list", "/user/info", "/openapi.json"] {
+        let (status, body) = call(app(&setup), get(uri, None)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+        assert_eq!(body["message"], "setup required");
+    }
+    let login = post("/user/login", &json!({"username": "a", "password": "b"}));
+    assert_eq!(
+        call(app(&setup), login).await.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let (status, body) = call(app(&setup), get("/setup/status", None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["required"], true);
+    assert_eq!(body["data"]["timezone"], config().timezone().name());
+
+    // Out of setup mode the gate lets everything through to auth.
+    let done = Setup::new(false);
+    assert_eq!(
+        call(app(&done), get("/device/list", None)).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    let (_, body) = call(app(&done), get("/setup/status", None)).await;
+    assert_eq!(body["data"], json!({"required": false}));
+}
+
+#[tokio::test]
+async fn setup_creates_the_admin_and_happens_once() {
+    let _db = crate::db::test_db().await;
+    let setup = Setup::new(true);
+
+    // Invalid requests leave setup mode on.
+    for bad in [
+        json!({"username": "", "password": "x"}),
+        json!({"username": "a", "password": "x", "timezone": "Mars/Olympus"}),
+        json!({"username": "a", "password": "x", "record_dir": "relative/dir"}),
+        json!({"username": "a", "password": "x", "record_dir": "/proc/nvr-records"}),
+    ] {
+        let (status, body) = call(app(&setup), post("/setup", &bad)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{bad} -> {body}");
+    }
+    assert!(setup.required());
+
+    let (username, request) = setup_body();
+    let (status, body) = call(app(&setup), post("/setup", &request)).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert!(!setup.required());
+    let token = body["data"]["token"].as_str().unwrap().to_string();
+
+    // The admin is signed in, has the admin role and can log in again.
+    let (status, body) = call(app(&setup), get("/user/info", Some(&token))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["username"], username.as_str());
+    assert!(crate::auth::is_admin(&username).await.unwrap());
+    let login = json!({"username": username, "password": "s3cret"});
+    assert_eq!(
+        call(app(&setup), post("/user/login", &login)).await.0,
+        StatusCode::OK
+    );
+    let conn = app_db_conn().unwrap();
+    let recorded = nvr_db::config::get_json::<SetupSettings>(SETUP_KEY, &conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(recorded.record_dir, request["record_dir"].as_str().unwrap());
+    assert!(recorded.completed_at > 0);
+    assert!(Path::new(&recorded.record_dir).is_dir());
+
+    // Done is done.
+    let (_, again) = setup_body();
+    let (status, body) = call(app(&setup), post("/setup", &again)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["message"], "setup already completed");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_setups_one_wins() {
+    let _db = crate::db::test_db().await;
+    let setup = Setup::new(true);
+    let (first, first_body) = setup_body();
+    let (second, second_body) = setup_body();
+
+    let (a, b) = tokio::join!(
+        tokio::spawn(call(app(&setup), post("/setup", &first_body))),
+        tokio::spawn(call(app(&setup), post("/setup", &second_body))),
+    );
+    let mut statuses = [a.unwrap().0, b.unwrap().0];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+
+    // Only the winner's admin exists.
+    let conn = app_db_conn().unwrap();
+    let created = [
+        nvr_db::user::exists(&first, &conn).await.unwrap(),
+        nvr_db::user::exists(&second, &conn).await.unwrap(),
+    ];
+    assert_eq!(created.iter().filter(|c| **c).count(), 1, "{created:?}");
+}
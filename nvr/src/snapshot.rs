@@ -0,0 +1,386 @@
+//! `GET /api/snapshot/{id}`: a JPEG of a pipe's latest decoded video frame.
+//!
+//! A decoder cannot produce a picture before it has seen a keyframe, and
+//! cameras with long GOPs send one only every few (or tens of) seconds. So
+//! snapshots come from a single-slot per-pipe cache fed by a lightweight
+//! subscriber on the pipe's decoded-video broadcast that keeps about one frame
+//! per second. The first request for a pipe starts that feed and waits up to
+//! `?timeout_ms=` (default 5000) for the first frame decoded from a keyframe,
+//! answering 504 if none arrives; later requests are served from the cache at
+//! once. The feed stays on until the pipe's broadcast ends. `X-Frame-Age-Ms`
+//! reports how old the returned frame is. Devices in privacy mode get a
+//! placeholder image instead.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    Router,
+    extract::{Path, Query},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use ffmpeg_bus::frame::{RawFrame, RawFrameCmd, RawFrameReceiver, RawVideoFrame};
+use ffmpeg_bus::scaler::ScalerCache;
+use ffmpeg_next::format::Pixel;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+
+/// How often the feed replaces the cached frame.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Cached frames up to this old are served without waiting; one sample
+/// interval plus headroom for a late sample.
+const MAX_CACHED_AGE: Duration = Duration::from_millis(1500);
+
+/// Conversions [`SCALERS`] keeps: a few source and output sizes each.
+const SCALER_CACHE_SIZE: usize = 16;
+
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+const MAX_TIMEOUT_MS: u64 = 60_000;
+
+static SNAPSHOTS: LazyLock<Snapshots> = LazyLock::new(Snapshots::default);
+
+/// Scalers of the downscaled JPEGs (thumbnails, detection crops), shared by
+/// the devices and sizes asking for them.
+static SCALERS: LazyLock<Mutex<ScalerCache>> =
+    LazyLock::new(|| Mutex::new(ScalerCache::new(SCALER_CACHE_SIZE)));
+
+/// A cached decoded frame and when it was decoded.
+#[derive(Clone)]
+pub struct Slot {
+    pub frame: RawVideoFrame,
+    pub at: Instant,
+}
+
+impl Slot {
+    fn is_fresh(&self) -> bool {
+        self.at.elapsed() <= MAX_CACHED_AGE
+    }
+}
+
+/// Why no frame could be returned.
+#[derive(Debug)]
+pub enum Miss {
+    /// The pipe is unknown, not started, or has no video.
+    NoVideo(anyhow::Error),
+    /// No keyframe-decoded frame arrived within the timeout.
+    Timeout,
+}
+
+/// Latest frame per pipe. Each pipe's slot is a `watch` channel whose only
+/// sender is shared by the map and the pipe's feed task.
+#[derive(Default)]
+pub struct Snapshots {
+    feeds: Mutex<HashMap<String, Arc<watch::Sender<Option<Slot>>>>>,
+}
+
+impl Snapshots {
+    /// Whether a feed is running for `pipe`.
+    pub fn is_feeding(&self, pipe: &str) -> bool {
+        self.feeds.lock().unwrap().contains_key(pipe)
+    }
+
+    /// A fresh frame of `pipe`: the cached one if it is recent enough,
+    /// otherwise the next one the feed stores, waiting at most `timeout`.
+    /// `subscribe` is only called to start a feed when none is running.
+    pub async fn frame<F, Fut>(
+        &'static self,
+        pipe: &str,
+        timeout: Duration,
+        subscribe: F,
+    ) -> Result<Slot, Miss>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<RawFrameReceiver>>,
+    {
+        let existing = self
+            .feeds
+            .lock()
+            .unwrap()
+            .get(pipe)
+            .map(|tx| tx.subscribe());
+        let mut latest = match existing {
+            Some(rx) => rx,
+            None => self.attach(pipe, subscribe().await.map_err(Miss::NoVideo)?),
+        };
+        if let Some(slot) = latest.borrow().as_ref().filter(|s| s.is_fresh()) {
+            return Ok(slot.clone());
+        }
+        match tokio::time::timeout(
+            timeout,
+            latest.wait_for(|s| s.as_ref().is_some_and(Slot::is_fresh)),
+        )
+        .await
+        {
+            Ok(Ok(slot)) => Ok(slot.clone().expect("wait_for matched a stored frame")),
+            // Timed out, or the feed ended (broadcast closed) while waiting.
+            Ok(Err(_)) | Err(_) => Err(Miss::Timeout),
+        }
+    }
+
+    /// Start feeding `pipe`'s slot from `video` (unless a concurrent request
+    /// already did) and return a receiver on the slot.
+    fn attach(&'static self, pipe: &str, video: RawFrameReceiver) -> watch::Receiver<Option<Slot>> {
+        let mut feeds = self.feeds.lock().unwrap();
+        if let Some(tx) = feeds.get(pipe) {
+            return tx.subscribe();
+        }
+        let (tx, rx) = watch::channel(None);
+        let tx = Arc::new(tx);
+        feeds.insert(pipe.to_string(), tx.clone());
+        tokio::spawn(feed(self, pipe.to_string(), video, tx));
+        rx
+    }
+
+    fn detach(&self, pipe: &str, tx: &Arc<watch::Sender<Option<Slot>>>) {
+        let mut feeds = self.feeds.lock().unwrap();
+        if feeds.get(pipe).is_some_and(|cur| Arc::ptr_eq(cur, tx)) {
+            feeds.remove(pipe);
+        }
+    }
+}
+
+/// Keep `tx` updated with a frame of `video` per [`SAMPLE_INTERVAL`], starting
+/// at the first keyframe (frames decoded before it may be garbage), until the
+/// broadcast ends.
+async fn feed(
+    snapshots: &'static Snapshots,
+    pipe: String,
+    mut video: RawFrameReceiver,
+    tx: Arc<watch::Sender<Option<Slot>>>,
+) {
+    log::info!("snapshot[{pipe}]: feed started");
+    let mut seen_key = false;
+    let mut last: Option<Instant> = None;
+    loop {
+        match video.recv().await {
+            Ok(RawFrameCmd::Data(RawFrame::Video(vf))) => {
+                if !seen_key {
+                    if !vf.is_key() {
+                        continue; // still waiting for the GOP to start
+                    }
+                    seen_key = true;
+                }
+                let now = Instant::now();
+                if last.is_some_and(|l| now.duration_since(l) < SAMPLE_INTERVAL) {
+                    continue;
+                }
+                last = Some(now);
+                tx.send_replace(Some(Slot { frame: vf, at: now }));
+            }
+            Ok(RawFrameCmd::Data(RawFrame::Audio(_))) => {}
+            Ok(RawFrameCmd::EOF) => break,
+            Err(RecvError::Lagged(n)) => {
+                log::debug!("snapshot[{pipe}]: dropped {n} frames (lag)");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    snapshots.detach(&pipe, &tx);
+    log::info!("snapshot[{pipe}]: feed stopped");
+}
+
+/// Encode a decoded frame (any pixel format) as a baseline JPEG.
+pub fn to_jpeg(frame: &RawVideoFrame) -> anyhow::Result<Vec<u8>> {
+    if frame.width() == 0 || frame.height() == 0 {
+        anyhow::bail!("zero-sized frame");
+    }
+    // The MJPEG encoder takes full-range YUV.
+    encode_jpeg(frame.convert(Pixel::YUVJ420P)?)
+}
+
+/// Like [`to_jpeg`], downscaled (keeping the aspect ratio) to at most
+/// `max_width` pixels wide.
+pub fn to_jpeg_scaled(frame: &RawVideoFrame, max_width: u32) -> anyhow::Result<Vec<u8>> {
+    let (w, h) = (frame.width(), frame.height());
+    if w == 0 || h == 0 {
+        anyhow::bail!("zero-sized frame");
+    }
+    if w <= max_width {
+        return to_jpeg(frame);
+    }
+    let (width, height) = scaled_size(w, h, max_width);
+    // A caller finding the cache busy scales on a scaler of its own rather
+    // than wait.
+    let yuv = match SCALERS.try_lock() {
+        Ok(mut scalers) => scalers.scale(frame.as_video(), Pixel::YUVJ420P, width, height)?,
+        Err(_) => frame.scale(Pixel::YUVJ420P, width, height)?,
+    };
+    encode_jpeg(yuv)
+}
+
+/// The size [`to_jpeg_scaled`] gives a `w` x `h` frame.
+pub(crate) fn scaled_size(w: u32, h: u32, max_width: u32) -> (u32, u32) {
+    if w <= max_width || w == 0 {
+        return (w, h);
+    }
+    // Even dimensions for the 4:2:0 chroma planes.
+    let width = (max_width & !1).max(2);
+    let height = ((u64::from(h) * u64::from(width) / u64::from(w)) as u32 & !1).max(2);
+    (width, height)
+}
+
+/// Encode a full-range YUV 4:2:0 frame as a JPEG.
+pub(crate) fn encode_jpeg(mut yuv: ffmpeg_next::frame::Video) -> anyhow::Result<Vec<u8>> {
+    let (w, h) = (yuv.width(), yuv.height());
+    yuv.set_pts(Some(0));
+
+    let codec = ffmpeg_next::encoder::find(ffmpeg_next::codec::Id::MJPEG)
+        .ok_or_else(|| anyhow::anyhow!("mjpeg encoder not available"))?;
+    let mut encoder = ffmpeg_next::codec::Context::new_with_codec(codec)
+        .encoder()
+        .video()?;
+    encoder.set_width(w);
+    encoder.set_height(h);
+    encoder.set_format(Pixel::YUVJ420P);
+    encoder.set_time_base(ffmpeg_next::Rational(1, 25));
+    let mut encoder = encoder.open()?;
+
+    encoder.send_frame(&yuv)?;
+    encoder.send_eof()?;
+    let mut packet = ffmpeg_next::Packet::empty();
+    encoder.receive_packet(&mut packet)?;
+    packet
+        .data()
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow::anyhow!("mjpeg encoder produced an empty packet"))
+}
+
+pub fn snapshot_router() -> Router {
+    Router::new().route("/{id}", get(snapshot))
+}
+
+/// The schema of [`snapshot_router`], mounted at `/api/v1/snapshot`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(snapshot))]
+pub(crate) struct SnapshotApi;
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SnapshotQuery {
+    /// How long to wait for a first frame when none is cached.
+    timeout_ms: Option<u64>,
+}
+
+/// A fresh frame of the managed pipe `id` from the shared cache, starting its
+/// feed if needed (see [`Snapshots::frame`]). An on-demand pipe is held (and
+/// started) for the call.
+pub(crate) async fn latest_frame(id: &str, timeout: Duration) -> Result<Slot, Miss> {
+    let _handle = crate::manager::acquire_pipe(id).await;
+    let subscribe = || async {
+        let Some(pipe) = crate::manager::get_pipe(id).await else {
+            anyhow::bail!("pipe not found");
+        };
+        pipe.subscribe_video().await
+    };
+    SNAPSHOTS.frame(id, timeout, subscribe).await
+}
+
+/// A JPEG of the pipe's current frame.
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "snapshot",
+    params(("id" = String, Path), SnapshotQuery),
+    responses(
+        (
+            status = 200,
+            description = "`X-Frame-Age-Ms` tells how old the frame is",
+            body = [u8],
+            content_type = "image/jpeg",
+        ),
+        (status = 404, description = "No such pipe, or it has no video", body = String),
+        (status = 504, description = "No frame within the timeout", body = String),
+    )
+)]
+async fn snapshot(Path(id): Path<String>, Query(query): Query<SnapshotQuery>) -> Response {
+    if crate::privacy::is_private(&id) {
+        return privacy_response();
+    }
+    let timeout_ms = query
+        .timeout_ms
+        .unwrap_or(DEFAULT_TIMEOUT_MS)
+        .min(MAX_TIMEOUT_MS);
+    let slot = match latest_frame(&id, Duration::from_millis(timeout_ms)).await {
+        Ok(slot) => slot,
+        Err(miss) => return miss_response(&id, timeout_ms, miss),
+    };
+
+    let frame = slot.frame.clone();
+    let jpeg = match tokio::task::spawn_blocking(move || to_jpeg(&frame)).await {
+        Ok(Ok(jpeg)) => jpeg,
+        Ok(Err(e)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("jpeg encode failed: {e:#}"),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("jpeg encode task failed: {e}"),
+            )
+                .into_response();
+        }
+    };
+    (
+        [
+            (header::CONTENT_TYPE, "image/jpeg".to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (
+                header::HeaderName::from_static("x-frame-age-ms"),
+                slot.at.elapsed().as_millis().to_string(),
+            ),
+        ],
+        jpeg,
+    )
+        .into_response()
+}
+
+/// The generated placeholder served for a device in privacy mode; also used
+/// by the media proxy.
+pub(crate) fn privacy_response() -> Response {
+    match crate::privacy::placeholder_jpeg() {
+        Ok(jpeg) => (
+            [
+                (header::CONTENT_TYPE, "image/jpeg"),
+                (header::CACHE_CONTROL, "no-store"),
+                (header::HeaderName::from_static("x-privacy"), "enabled"),
+            ],
+            jpeg,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("privacy placeholder failed: {e:#}"),
+        )
+            .into_response(),
+    }
+}
+
+fn miss_response(id: &str, timeout_ms: u64, miss: Miss) -> Response {
+    match miss {
+        Miss::NoVideo(e) => {
+            (StatusCode::NOT_FOUND, format!("no video for {id}: {e:#}")).into_response()
+        }
+        Miss::Timeout => (
+            StatusCode::GATEWAY_TIMEOUT,
+            format!(
+                "no keyframe from {id} within {timeout_ms} ms; the camera's keyframe interval \
+                 (GOP) may be longer than that. Retry with a larger ?timeout_ms= (up to \
+                 {MAX_TIMEOUT_MS}) or lower the camera's keyframe interval."
+            ),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+#[path = "snapshot_test.rs"]
+mod snapshot_test;
@@ -0,0 +1,120 @@
+use super::*;
+
+use ffmpeg_bus::frame::RawFrameSender;
+
+/// A decoded 64x48 frame, flagged as a keyframe or not.
+fn frame(key: bool) -> RawFrameCmd {
+    let mut video = ffmpeg_next::frame::Video::new(Pixel::YUV420P, 64, 48);
+    if key {
+        unsafe {
+            (*video.as_mut_ptr()).flags |= ffmpeg_next::ffi::AV_FRAME_FLAG_KEY as i32;
+        }
+    }
+    RawFrameCmd::Data(RawFrame::Video(RawVideoFrame::from(video)))
+}
+
+/// Long-GOP fixture: a camera joined mid-GOP, 10 fps with a keyframe only
+/// every 100 frames, so the decoder first sees `p_frames` non-key frames.
+fn long_gop(p_frames: usize) -> (RawFrameSender, RawFrameReceiver) {
+    let (tx, rx) = tokio::sync::broadcast::channel(256);
+    for _ in 0..p_frames {
+        tx.send(frame(false)).unwrap();
+    }
+    (tx, rx)
+}
+
+fn snapshots() -> &'static Snapshots {
+    Box::leak(Box::new(Snapshots::default()))
+}
+
+async fn not_subscribable() -> anyhow::Result<RawFrameReceiver> {
+    panic!("feed is already running; must not subscribe again")
+}
+
+#[tokio::test]
+async fn times_out_until_the_first_keyframe() {
+    let snapshots = snapshots();
+    let (_tx, rx) = long_gop(30);
+
+    let res = snapshots
+        .frame("cam", Duration::from_millis(200), || async { Ok(rx) })
+        .await;
+    assert!(matches!(res, Err(Miss::Timeout)), "{:?}", res.err());
+    // The feed keeps running for the next request.
+    assert!(snapshots.is_feeding("cam"));
+}
+
+#[tokio::test]
+async fn first_request_waits_for_keyframe_then_cache_is_instant() {
+    let snapshots = snapshots();
+    let (tx, rx) = long_gop(30);
+
+    let keyframe = tokio::spawn({
+        let tx = tx.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            tx.send(frame(true)).unwrap();
+            tx.send(frame(false)).unwrap();
+        }
+    });
+    let first = snapshots
+        .frame("cam", Duration::from_secs(5), || async { Ok(rx) })
+        .await
+        .expect("frame after keyframe");
+    assert!(first.frame.is_key());
+    keyframe.await.unwrap();
+
+    let start = Instant::now();
+    let cached = snapshots
+        .frame("cam", Duration::from_secs(5), not_subscribable)
+        .await
+        .expect("cached frame");
+    assert!(start.elapsed() < Duration::from_millis(50));
+    assert_eq!(cached.at, first.at);
+    assert!(cached.at.elapsed() <= MAX_CACHED_AGE);
+}
+
+#[tokio::test]
+async fn feed_stops_with_the_broadcast() {
+    let snapshots = snapshots();
+    let (tx, rx) = long_gop(0);
+    tx.send(frame(true)).unwrap();
+    snapshots
+        .frame("cam", Duration::from_secs(1), || async { Ok(rx) })
+        .await
+        .expect("frame");
+
+    tx.send(RawFrameCmd::EOF).unwrap();
+    for _ in 0..50 {
+        if !snapshots.is_feeding("cam") {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("feed still registered after EOF");
+}
+
+#[tokio::test]
+async fn unknown_pipe_is_no_video() {
+    let res = snapshots()
+        .frame("missing", Duration::from_millis(10), || async {
+            anyhow::bail!("pipe not found")
+        })
+        .await;
+    assert!(matches!(res, Err(Miss::NoVideo(_))));
+    let response = miss_response("missing", 10, res.err().unwrap());
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        miss_response("cam", 10, Miss::Timeout).status(),
+        StatusCode::GATEWAY_TIMEOUT
+    );
+}
+
+#[test]
+fn encodes_a_decoded_frame_as_jpeg() {
+    let RawFrameCmd::Data(RawFrame::Video(video)) = frame(true) else {
+        unreachable!()
+    };
+    let jpeg = to_jpeg(&video).unwrap();
+    assert_eq!(&jpeg[..2], &[0xFF, 0xD8], "missing JPEG SOI marker");
+}
@@ -0,0 +1,207 @@
+//! Storyboards of recorded segments for timeline scrubbing
+//! ([`ffmpeg_bus::storyboard`]): a 160x90 tile every 10 seconds on JPEG sprite
+//! sheets, indexed by WebVTT. A segment's storyboard is generated on its first
+//! request and cached next to it in `<segment file>.storyboard/`
+//! (`storyboard.vtt`, `sheet1.jpg`, …); the sheets of a sealed segment are
+//! sealed too. The cache goes when the segment is deleted.
+//!
+//! `GET /api/v1/recordings/{file}/storyboard` answers the WebVTT, whose cues
+//! point at `storyboard/sheetN.jpg#xywh=…`, relative to it, which
+//! `GET /api/v1/recordings/{file}/storyboard/{sheet}` serves.
+
+use std::io::Read;
+use std::path::{Path as FsPath, PathBuf};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::Path,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use ffmpeg_bus::storyboard::StoryboardOpts;
+
+use crate::db::app_db_conn;
+use crate::encryption::{EncryptedFile, KeyRing, Plaintext, encrypt_file, is_encrypted};
+use crate::handler::{ApiError, ApiResult, BaseResponse};
+
+const VTT: &str = "storyboard.vtt";
+
+/// One storyboard is generated at a time; it decodes a keyframe per tile.
+static GENERATING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Where the storyboard of the segment at `recording` is cached.
+pub(crate) fn cache_dir(recording: &FsPath) -> PathBuf {
+    let mut name = recording.as_os_str().to_os_string();
+    name.push(".storyboard");
+    PathBuf::from(name)
+}
+
+/// Best-effort removal of the storyboard cached for the segment at
+/// `recording`; no storyboard is not an error.
+pub(crate) async fn remove_cache(recording: &str) {
+    if recording.is_empty() {
+        return;
+    }
+    let dir = cache_dir(FsPath::new(recording));
+    match tokio::fs::remove_dir_all(&dir).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("storyboard: delete '{}' failed: {e:#}", dir.display()),
+    }
+}
+
+/// The file of the recorded segment `file` (its id), unless it is unknown or
+/// missing.
+async fn recording(file: &str) -> Result<Option<PathBuf>> {
+    let conn = app_db_conn()?;
+    Ok(nvr_db::record_segment::get(file, &conn)
+        .await?
+        .filter(|s| s.status != nvr_db::record_segment::STATUS_MISSING)
+        .map(|s| PathBuf::from(s.file_path)))
+}
+
+/// The cache directory of `recording`, generating the storyboard first if
+/// there is none yet.
+async fn ensure(recording: PathBuf) -> Result<PathBuf> {
+    let dir = cache_dir(&recording);
+    if tokio::fs::try_exists(dir.join(VTT)).await? {
+        return Ok(dir);
+    }
+    let _generating = GENERATING.lock().await;
+    // Someone else's request may have generated it meanwhile.
+    if tokio::fs::try_exists(dir.join(VTT)).await? {
+        return Ok(dir);
+    }
+    let out = dir.clone();
+    tokio::task::spawn_blocking(move || generate(&recording, &out)).await??;
+    Ok(dir)
+}
+
+/// Generate the storyboard of `recording` into a scratch directory beside
+/// `dir`, then move it into place whole. Blocking.
+fn generate(recording: &FsPath, dir: &FsPath) -> Result<()> {
+    let mut scratch = dir.as_os_str().to_os_string();
+    scratch.push(format!(".partial-{}", uuid::Uuid::new_v4().simple()));
+    let scratch = PathBuf::from(scratch);
+    let result = generate_into(recording, &scratch).and_then(|()| {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        std::fs::rename(&scratch, dir).with_context(|| format!("move into {}", dir.display()))
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+    result
+}
+
+fn generate_into(recording: &FsPath, scratch: &FsPath) -> Result<()> {
+    // A sealed recording is decoded from a temporary plaintext copy.
+    let plain = Plaintext::of(recording)?;
+    let opts = StoryboardOpts {
+        sheet_prefix: "storyboard/".to_string(),
+        ..StoryboardOpts::default()
+    };
+    let storyboard =
+        ffmpeg_bus::storyboard::generate(&plain.path().to_string_lossy(), scratch, &opts)?;
+    if plain.is_temporary() {
+        let keys = KeyRing::load()?;
+        for sheet in &storyboard.sheets {
+            encrypt_file(sheet, &crate::encryption::sealed_path(sheet), &keys)?;
+            std::fs::remove_file(sheet)?;
+        }
+    }
+    // Written last: its presence marks a complete storyboard.
+    std::fs::write(scratch.join(VTT), storyboard.vtt)?;
+    Ok(())
+}
+
+fn not_found(file: &str) -> ApiError {
+    ApiError::not_found(format!("no recording {file}"))
+}
+
+/// The WebVTT storyboard of the recorded segment `file` (its id), generated
+/// on the first request.
+#[utoipa::path(
+    get,
+    path = "/{file}/storyboard",
+    tag = "recordings",
+    params(("file" = String, Path, description = "Record segment id")),
+    responses(
+        (status = 200, body = String, content_type = "text/vtt"),
+        (status = 404, description = "No such recording", body = BaseResponse<()>),
+    )
+)]
+pub(crate) async fn storyboard(Path(file): Path<String>) -> ApiResult<Response> {
+    let Some(recording) = recording(&file).await? else {
+        return Err(not_found(&file));
+    };
+    let vtt = tokio::fs::read(ensure(recording).await?.join(VTT)).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/vtt; charset=utf-8"),
+            // A closed recording does not change.
+            (header::CACHE_CONTROL, "private, max-age=86400"),
+        ],
+        vtt,
+    )
+        .into_response())
+}
+
+/// One sprite sheet (`sheetN.jpg`) of the storyboard of `file`.
+#[utoipa::path(
+    get,
+    path = "/{file}/storyboard/{sheet}",
+    tag = "recordings",
+    params(
+        ("file" = String, Path, description = "Record segment id"),
+        ("sheet" = String, Path, description = "`sheet1.jpg`, `sheet2.jpg`, …"),
+    ),
+    responses(
+        (status = 200, body = [u8], content_type = "image/jpeg"),
+        (status = 404, description = "No such recording or sheet", body = BaseResponse<()>),
+    )
+)]
+pub(crate) async fn sheet(Path((file, sheet)): Path<(String, String)>) -> ApiResult<Response> {
+    let valid = sheet
+        .strip_prefix("sheet")
+        .and_then(|rest| rest.strip_suffix(".jpg"))
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    if !valid {
+        return Err(ApiError::not_found(format!("no sheet {sheet}")));
+    }
+    let Some(recording) = recording(&file).await? else {
+        return Err(not_found(&file));
+    };
+    let sealed = is_encrypted(&recording);
+    let path = ensure(recording).await?.join(&sheet);
+    let jpeg = if sealed {
+        tokio::task::spawn_blocking(move || -> Result<Option<Vec<u8>>> {
+            let sealed = crate::encryption::sealed_path(&path);
+            if !sealed.exists() {
+                return Ok(None);
+            }
+            let mut jpeg = Vec::new();
+            EncryptedFile::open(&sealed, &KeyRing::load()?)?.read_to_end(&mut jpeg)?;
+            Ok(Some(jpeg))
+        })
+        .await??
+    } else {
+        match tokio::fs::read(&path).await {
+            Ok(jpeg) => Some(jpeg),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    let Some(jpeg) = jpeg else {
+        return Err(ApiError::not_found(format!("no sheet {sheet}")));
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            (header::CACHE_CONTROL, "private, max-age=86400"),
+        ],
+        jpeg,
+    )
+        .into_response())
+}
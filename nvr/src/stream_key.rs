@@ -0,0 +1,211 @@
+//! Device stream keys: the name each device publishes under in ZLM's `device`
+//! app, and so the name in its live URLs (`/media/device/{key}.live.flv`). A
+//! key defaults to a slug of the device name with the id appended, can be set
+//! explicitly, and is unique across devices. It survives renames: only an
+//! explicit change (a new key, or `regenerate_stream_key`) moves the stream,
+//! which restarts the device's pipe.
+//!
+//! Everything else stays keyed by device id — recordings, health, privacy —
+//! so the ZLM hooks map stream names back through [`device_of`]. GB28181
+//! devices are published by the RTP server under their id in the `rtp` app and
+//! do not use their key.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, RwLock};
+
+use anyhow::Result;
+use nvr_db::device::DeviceInfo;
+
+use crate::db::app_db_conn;
+
+/// Longest accepted key.
+pub(crate) const MAX_LEN: usize = 64;
+
+/// Device id -> stream name of every device brought up since startup.
+static STREAMS: LazyLock<RwLock<HashMap<String, String>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Record that `device_id` publishes as `stream`.
+pub(crate) fn register(device_id: &str, stream: &str) {
+    STREAMS
+        .write()
+        .unwrap()
+        .insert(device_id.to_string(), stream.to_string());
+}
+
+pub(crate) fn forget(device_id: &str) {
+    STREAMS.write().unwrap().remove(device_id);
+}
+
+/// The ZLM stream name of `device_id`; its id if it has not been registered.
+pub(crate) fn stream_of(device_id: &str) -> String {
+    STREAMS
+        .read()
+        .unwrap()
+        .get(device_id)
+        .cloned()
+        .unwrap_or_else(|| device_id.to_string())
+}
+
+/// The device publishing as `stream` in the `device` app; `stream` itself if
+/// none is registered, as streams were named by device id before keys.
+pub(crate) fn device_of(stream: &str) -> String {
+    STREAMS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|(_, name)| name.as_str() == stream)
+        .map(|(id, _)| id.clone())
+        .unwrap_or_else(|| stream.to_string())
+}
+
+/// `name` in lowercase ASCII letters, digits and single dashes. Accented Latin
+/// letters are folded (`é` -> `e`, `ß` -> `ss`); other scripts are dropped.
+pub(crate) fn slug(name: &str) -> String {
+    let mut out = String::new();
+    let mut dash = false;
+    for c in name.chars().flat_map(char::to_lowercase) {
+        let mut buf = [0; 4];
+        let folded = if c.is_ascii_alphanumeric() {
+            Some(&*c.encode_utf8(&mut buf))
+        } else {
+            fold(c)
+        };
+        match folded {
+            Some(s) => {
+                if dash && !out.is_empty() {
+                    out.push('-');
+                }
+                dash = false;
+                out.push_str(s);
+            }
+            None => dash = true,
+        }
+    }
+    out
+}
+
+/// The ASCII spelling of a lowercase accented Latin letter.
+fn fold(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'ğ' => "g",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' => "i",
+        'ł' | 'ľ' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' => "r",
+        'ś' | 'š' | 'ş' => "s",
+        'ß' => "ss",
+        'ť' | 'ţ' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+/// The key a device gets unless one is given: `{slug of name}-{id}`, the slug
+/// shortened to fit [`MAX_LEN`], or the id alone when nothing of the name
+/// survives slugging.
+pub(crate) fn default_key(name: &str, id: &str) -> String {
+    let mut id: String = id
+        .chars()
+        .map(|c| if key_char(c) { c } else { '-' })
+        .collect();
+    id.truncate(MAX_LEN);
+    let room = MAX_LEN.saturating_sub(id.len() + 1);
+    let mut name = slug(name);
+    name.truncate(room);
+    let name = name.trim_end_matches('-');
+    if name.is_empty() {
+        id
+    } else {
+        format!("{name}-{id}")
+    }
+}
+
+fn key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Keys are 1 to [`MAX_LEN`] ASCII letters, digits, `-` and `_`, so they fit
+/// in a URL path segment and a directory name as they are.
+pub(crate) fn validate(key: &str) -> Result<()> {
+    if key.is_empty() || key.len() > MAX_LEN || !key.chars().all(key_char) {
+        anyhow::bail!(
+            "invalid stream key {key:?}: use 1 to {MAX_LEN} ASCII letters, digits, '-' or '_'"
+        );
+    }
+    Ok(())
+}
+
+/// The device other than `device_id` already publishing as `key`.
+pub(crate) fn conflicting_device<'a>(
+    key: &str,
+    device_id: &str,
+    devices: &'a [DeviceInfo],
+) -> Option<&'a DeviceInfo> {
+    devices
+        .iter()
+        .find(|d| d.id != device_id && d.stream_name() == key)
+}
+
+/// The key of device `id` after a create (`current` is `None`) or update:
+/// `requested` if given, the default for `name` on create or when
+/// `regenerate` is set, and the current key otherwise — a rename alone keeps
+/// it.
+pub(crate) fn next_key(
+    current: Option<&DeviceInfo>,
+    id: &str,
+    name: &str,
+    requested: Option<&str>,
+    regenerate: bool,
+) -> Result<String> {
+    let key = match (requested, current) {
+        (Some(key), _) => key.trim().to_string(),
+        (None, Some(device)) if !regenerate => return Ok(device.stream_key.clone()),
+        (None, _) => default_key(name, id),
+    };
+    validate(&key)?;
+    Ok(key)
+}
+
+/// Give every device stored before keys existed its default key (suffixed
+/// `-2`, `-3`, … if another device already took it). Returns how many were
+/// keyed.
+pub(crate) async fn backfill() -> Result<usize> {
+    let conn = app_db_conn()?;
+    let devices = nvr_db::device::list(&conn).await?;
+    let mut taken: HashSet<String> = devices
+        .iter()
+        .filter(|d| !d.stream_key.is_empty())
+        .map(|d| d.stream_key.clone())
+        .collect();
+    let mut keyed = 0;
+    for mut device in devices.into_iter().filter(|d| d.stream_key.is_empty()) {
+        let base = default_key(&device.name, &device.id);
+        let mut key = base.clone();
+        let mut n = 2;
+        while taken.contains(&key) {
+            key = format!("{base}-{n}");
+            n += 1;
+        }
+        log::info!("stream key: device {} publishes as {key}", device.id);
+        taken.insert(key.clone());
+        device.stream_key = key;
+        nvr_db::device::upsert(&device, &conn).await?;
+        keyed += 1;
+    }
+    Ok(keyed)
+}
+
+#[cfg(test)]
+#[path = "stream_key_test.rs"]
+mod stream_key_test;
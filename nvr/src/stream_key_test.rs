@@ -0,0 +1,162 @@
+use chrono::Utc;
+
+use super::*;
+
+fn device(id: &str, name: &str, stream_key: &str) -> DeviceInfo {
+    DeviceInfo {
+        id: id.to_string(),
+        name: name.to_string(),
+        input_type: "rtsp".to_string(),
+        input_value: "rtsp://cam".to_string(),
+        description: String::new(),
+        include_audio: false,
+        record: true,
+        stream_key: stream_key.to_string(),
+        timezone: String::new(),
+        detection: None,
+        group: String::new(),
+        encrypt: false,
+        status: String::new(),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+#[test]
+fn slugs_fold_latin_and_drop_other_scripts() {
+    assert_eq!(slug("Front Door"), "front-door");
+    assert_eq!(slug("  Café  Straße / Ünter "), "cafe-strasse-unter");
+    assert_eq!(slug("Łódź #2"), "lodz-2");
+    assert_eq!(slug("前门 Cam"), "cam");
+    assert_eq!(slug("前门"), "");
+
+    assert_eq!(
+        default_key("Café Door", "Ab12Cd34Ef56"),
+        "cafe-door-Ab12Cd34Ef56"
+    );
+    // Nothing left of the name: the id alone.
+    assert_eq!(default_key("前门摄像头", "Ab12Cd34Ef56"), "Ab12Cd34Ef56");
+    assert_eq!(default_key("x", "a.b c"), "x-a-b-c");
+    let long = default_key(&"camera ".repeat(20), "Ab12Cd34Ef56");
+    assert_eq!(long.len(), MAX_LEN, "{long}");
+    assert!(long.ends_with("-Ab12Cd34Ef56"), "{long}");
+    assert!(validate(&long).is_ok());
+}
+
+#[test]
+fn validates_the_charset() {
+    assert!(validate("cam_1-Front").is_ok());
+    for bad in [
+        "",
+        "a b",
+        "cam/1",
+        "cam.1",
+        "前门",
+        &"a".repeat(MAX_LEN + 1),
+    ] {
+        assert!(validate(bad).is_err(), "{bad:?}");
+    }
+}
+
+#[test]
+fn collisions_are_with_other_devices_only() {
+    // A device stored before keys existed publishes under its id.
+    let devices = vec![device("a", "A", "door"), device("b", "B", "")];
+    assert_eq!(
+        conflicting_device("door", "c", &devices).map(|d| d.id.as_str()),
+        Some("a")
+    );
+    assert_eq!(
+        conflicting_device("b", "c", &devices).map(|d| d.id.as_str()),
+        Some("b")
+    );
+    assert!(conflicting_device("door", "a", &devices).is_none());
+    assert!(conflicting_device("garden", "c", &devices).is_none());
+}
+
+#[test]
+fn renames_keep_the_key_unless_asked() {
+    let existing = device("Ab12", "Front Door", "front-door-Ab12");
+    // A plain rename.
+    assert_eq!(
+        next_key(Some(&existing), "Ab12", "Back Door", None, false).unwrap(),
+        "front-door-Ab12"
+    );
+    // Repeating the current key is no change either.
+    assert_eq!(
+        next_key(
+            Some(&existing),
+            "Ab12",
+            "Back Door",
+            Some("front-door-Ab12"),
+            false
+        )
+        .unwrap(),
+        "front-door-Ab12"
+    );
+    assert_eq!(
+        next_key(Some(&existing), "Ab12", "Back Door", None, true).unwrap(),
+        "back-door-Ab12"
+    );
+    assert_eq!(
+        next_key(Some(&existing), "Ab12", "Back Door", Some("back"), false).unwrap(),
+        "back"
+    );
+    assert!(next_key(Some(&existing), "Ab12", "Back Door", Some("back/1"), false).is_err());
+    // New devices get the default.
+    assert_eq!(
+        next_key(None, "Ab12", "Front Door", None, false).unwrap(),
+        "front-door-Ab12"
+    );
+    // Not yet backfilled: stays on the id until asked.
+    let legacy = device("Ab12", "Front Door", "");
+    assert_eq!(
+        next_key(Some(&legacy), "Ab12", "Back Door", None, false).unwrap(),
+        ""
+    );
+}
+
+async fn key_of(id: &str, conn: &turso::Connection) -> String {
+    nvr_db::device::get(id, conn)
+        .await
+        .unwrap()
+        .unwrap()
+        .stream_key
+}
+
+#[tokio::test]
+async fn backfill_keys_devices_stored_without_one() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    // Stored before keys existed; the second one's default is already taken.
+    let legacy = device("Ab12", "Front Door", "");
+    let taken = device("Cd34", "Garden", "");
+    let keyed = device("Ef56", "Porch", "garden-Cd34");
+    for d in [&legacy, &taken, &keyed] {
+        nvr_db::device::upsert(d, &conn).await.unwrap();
+    }
+
+    assert_eq!(backfill().await.unwrap(), 2);
+    assert_eq!(key_of("Ab12", &conn).await, "front-door-Ab12");
+    assert_eq!(key_of("Cd34", &conn).await, "garden-Cd34-2");
+    assert_eq!(key_of("Ef56", &conn).await, "garden-Cd34");
+    // Updated in place: the listing order stays put.
+    let stored = nvr_db::device::get("Ab12", &conn).await.unwrap().unwrap();
+    assert_eq!(stored.updated_at, legacy.updated_at);
+
+    assert_eq!(backfill().await.unwrap(), 0);
+    for d in [&legacy, &taken, &keyed] {
+        nvr_db::device::delete(&d.id, &conn).await.unwrap();
+    }
+}
+
+#[test]
+fn maps_streams_back_to_devices() {
+    register("dev-map-1", "porch-dev-map-1");
+    assert_eq!(stream_of("dev-map-1"), "porch-dev-map-1");
+    assert_eq!(device_of("porch-dev-map-1"), "dev-map-1");
+    forget("dev-map-1");
+    // Unknown names are taken as ids, as streams were named before keys.
+    assert_eq!(stream_of("dev-map-1"), "dev-map-1");
+    assert_eq!(device_of("porch-dev-map-1"), "porch-dev-map-1");
+}
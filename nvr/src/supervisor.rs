@@ -0,0 +1,500 @@
+//! Automatic restart of pipes. A pipe whose session ends without being
+//! stopped (a fatal input error, an encoder failure, the end of a file) is
+//! started again after a backoff that doubles from `NVR_RESTART_INITIAL_SECS`
+//! up to `NVR_RESTART_MAX_SECS`; a session that runs for
+//! `NVR_RESTART_HEALTHY_SECS` starts the backoff over. A pipe that ends
+//! `NVR_RESTART_FLAP_COUNT` times within `NVR_RESTART_FLAP_WINDOW_SECS` is
+//! parked as `failed` instead of flapping against a broken camera forever: it
+//! is retried after `NVR_RESTART_COOL_DOWN_SECS`, or at once on
+//! `POST /api/v1/device/{id}/resume`.
+//!
+//! The policy is [`Restarts`], a pure state machine over Unix millisecond
+//! timestamps. Each transition is recorded as a [`RestartEvent`]; the state,
+//! attempt count and `next_retry_at` are shown in the device list as
+//! `restart`. Attempt counts (and parked devices) are saved every minute, so
+//! a restarted server picks up about where it left off.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::Duration;
+
+use axum::extract::Path;
+use media_pipe_core::Pipe;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::handler::{ApiJsonResult, ok_json};
+
+/// Time between two saves of the attempt counts.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Config key the attempt counts are saved under.
+const SAVE_KEY: &str = "pipe_restarts";
+/// Restart events kept for diagnostics.
+const EVENT_CAP: usize = 100;
+
+/// When pipes that ended are started again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Wait before the first restart; doubled for each one after.
+    pub initial: Duration,
+    /// Longest wait between two restarts.
+    pub max: Duration,
+    /// A session running this long starts the backoff over.
+    pub healthy_after: Duration,
+    /// Ends within [`Self::flap_window`] that park the pipe.
+    pub flap_count: u32,
+    pub flap_window: Duration,
+    /// How long a parked pipe waits before it is tried again on its own.
+    pub cool_down: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(2),
+            max: Duration::from_secs(300),
+            healthy_after: Duration::from_secs(60),
+            flap_count: 10,
+            flap_window: Duration::from_secs(600),
+            cool_down: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// The backoff and flap limits from the `NVR_RESTART_*` values `get`
+    /// returns; unset, zero or unparsable ones keep their default, and the
+    /// longest wait is never shorter than the first.
+    pub fn from_map(get: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = Self::default();
+        let number = |key: &str| {
+            get(key)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|n| *n > 0)
+        };
+        let secs =
+            |key: &str, default: Duration| number(key).map(Duration::from_secs).unwrap_or(default);
+        let initial = secs("NVR_RESTART_INITIAL_SECS", defaults.initial);
+        Self {
+            initial,
+            max: secs("NVR_RESTART_MAX_SECS", defaults.max).max(initial),
+            healthy_after: secs("NVR_RESTART_HEALTHY_SECS", defaults.healthy_after),
+            flap_count: number("NVR_RESTART_FLAP_COUNT")
+                .map(|n| n.min(u64::from(u32::MAX)) as u32)
+                .unwrap_or(defaults.flap_count),
+            flap_window: secs("NVR_RESTART_FLAP_WINDOW_SECS", defaults.flap_window),
+            cool_down: secs("NVR_RESTART_COOL_DOWN_SECS", defaults.cool_down),
+        }
+    }
+
+    /// Parse from the real process environment.
+    pub fn from_env() -> Self {
+        Self::from_map(|k| std::env::var(k).ok())
+    }
+
+    /// The wait before restart number `attempts` (1 for the first).
+    pub(crate) fn delay(&self, attempts: u32) -> Duration {
+        let doublings = attempts.saturating_sub(1).min(20);
+        self.initial
+            .checked_mul(2u32.pow(doublings))
+            .map_or(self.max, |d| d.min(self.max))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RestartState {
+    #[default]
+    Running,
+    /// Ended; restarts at `next_retry_at`.
+    Backoff,
+    /// Ended too often; parked until `next_retry_at` or a resume.
+    Failed,
+}
+
+/// Where a pipe's restarts are at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct RestartStatus {
+    pub state: RestartState,
+    /// Restarts since the last healthy session.
+    pub attempts: u32,
+    /// Unix milliseconds.
+    pub next_retry_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartEventKind {
+    /// The pipe ended and restarts after a backoff.
+    Restarting,
+    /// The pipe ended too often and is parked.
+    Parked,
+    /// A parked pipe is retried, resumed by hand or after its cool-down.
+    Resumed,
+    /// A session ran long enough for the backoff to start over.
+    Healthy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct RestartEvent {
+    pub device_id: String,
+    pub kind: RestartEventKind,
+    pub attempts: u32,
+    /// Unix milliseconds.
+    pub next_retry_at: Option<i64>,
+    pub ts_ms: i64,
+}
+
+/// The restart policy of one pipe, applied to the times its sessions start
+/// and end (Unix milliseconds).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Restarts {
+    pub state: RestartState,
+    pub attempts: u32,
+    pub next_retry_at: Option<i64>,
+    started_at: Option<i64>,
+    /// When sessions ended, within the flap window.
+    exits: VecDeque<i64>,
+}
+
+impl Restarts {
+    pub(crate) fn status(&self) -> RestartStatus {
+        RestartStatus {
+            state: self.state,
+            attempts: self.attempts,
+            next_retry_at: self.next_retry_at,
+        }
+    }
+
+    /// A session started at `now_ms`.
+    pub(crate) fn started(&mut self, now_ms: i64) {
+        self.state = RestartState::Running;
+        self.next_retry_at = None;
+        self.started_at = Some(now_ms);
+    }
+
+    /// Whether the session running at `now_ms` has just become healthy, which
+    /// starts the backoff over.
+    pub(crate) fn healthy(&mut self, policy: &RestartPolicy, now_ms: i64) -> bool {
+        let Some(started_at) = self.started_at else {
+            return false;
+        };
+        if self.attempts == 0 || now_ms - started_at < millis(policy.healthy_after) {
+            return false;
+        }
+        self.attempts = 0;
+        true
+    }
+
+    /// The session ended at `now_ms`: back off, or park the pipe if it has
+    /// been ending too often.
+    pub(crate) fn exited(&mut self, policy: &RestartPolicy, now_ms: i64) -> RestartEventKind {
+        self.healthy(policy, now_ms);
+        self.started_at = None;
+        self.exits.push_back(now_ms);
+        while self
+            .exits
+            .front()
+            .is_some_and(|at| now_ms - at > millis(policy.flap_window))
+        {
+            self.exits.pop_front();
+        }
+        self.attempts += 1;
+        if self.exits.len() >= policy.flap_count as usize {
+            self.state = RestartState::Failed;
+            self.next_retry_at = Some(now_ms + millis(policy.cool_down));
+            RestartEventKind::Parked
+        } else {
+            self.state = RestartState::Backoff;
+            self.next_retry_at = Some(now_ms + millis(policy.delay(self.attempts)));
+            RestartEventKind::Restarting
+        }
+    }
+
+    /// Retry a parked pipe now; its flap window and backoff start over.
+    /// False if it is not parked.
+    pub(crate) fn resume(&mut self) -> bool {
+        if self.state != RestartState::Failed {
+            return false;
+        }
+        self.state = RestartState::Backoff;
+        self.attempts = 0;
+        self.next_retry_at = None;
+        self.exits.clear();
+        true
+    }
+
+    /// The state saved before a server restart, as of `now_ms`: the attempt
+    /// count, and parked if its cool-down is not over yet.
+    fn restore(saved: &Saved, now_ms: i64) -> Self {
+        let parked = saved.failed && saved.next_retry_at.is_some_and(|at| at > now_ms);
+        Self {
+            state: if parked {
+                RestartState::Failed
+            } else {
+                RestartState::Running
+            },
+            attempts: saved.attempts,
+            next_retry_at: if parked { saved.next_retry_at } else { None },
+            ..Self::default()
+        }
+    }
+}
+
+/// What is saved of a pipe's [`Restarts`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct Saved {
+    attempts: u32,
+    failed: bool,
+    next_retry_at: Option<i64>,
+}
+
+fn millis(d: Duration) -> i64 {
+    d.as_millis().min(i64::MAX as u128) as i64
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// One supervised pipe.
+struct Supervised {
+    restarts: Restarts,
+    /// Cuts a backoff or cool-down short.
+    resume: Arc<Notify>,
+}
+
+static SUPERVISED: LazyLock<RwLock<HashMap<String, Supervised>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+static EVENTS: LazyLock<Mutex<VecDeque<RestartEvent>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+/// Where the restarts of `device_id`'s pipe are at, if it is supervised.
+pub fn restart_status(device_id: &str) -> Option<RestartStatus> {
+    SUPERVISED
+        .read()
+        .unwrap()
+        .get(device_id)
+        .map(|s| s.restarts.status())
+}
+
+/// Recent restart events, oldest first.
+pub fn recent_events() -> Vec<RestartEvent> {
+    EVENTS.lock().unwrap().iter().cloned().collect()
+}
+
+/// Apply `change` to the restarts of `device_id` and record the event it
+/// returns, if any.
+fn update(device_id: &str, change: impl FnOnce(&mut Restarts) -> Option<RestartEventKind>) {
+    let mut supervised = SUPERVISED.write().unwrap();
+    let Some(entry) = supervised.get_mut(device_id) else {
+        return;
+    };
+    let Some(kind) = change(&mut entry.restarts) else {
+        return;
+    };
+    let event = RestartEvent {
+        device_id: device_id.to_string(),
+        kind,
+        attempts: entry.restarts.attempts,
+        next_retry_at: entry.restarts.next_retry_at,
+        ts_ms: now_ms(),
+    };
+    drop(supervised);
+
+    let wait_secs = event
+        .next_retry_at
+        .map(|at| (at - event.ts_ms).max(0) / 1000)
+        .unwrap_or_default();
+    match kind {
+        RestartEventKind::Restarting => log::warn!(
+            "supervisor: {device_id} ended, restart {} in {wait_secs}s",
+            event.attempts
+        ),
+        RestartEventKind::Parked => log::warn!(
+            "supervisor: {device_id} keeps ending, parked as failed (retry in {wait_secs}s or on resume)"
+        ),
+        RestartEventKind::Resumed => log::info!("supervisor: {device_id} resumed"),
+        RestartEventKind::Healthy => log::info!("supervisor: {device_id} is healthy again"),
+    }
+    let mut events = EVENTS.lock().unwrap();
+    if events.len() == EVENT_CAP {
+        events.pop_front();
+    }
+    events.push_back(event);
+}
+
+/// The saved restarts of `device_id`, if any.
+async fn load(device_id: &str) -> Option<Saved> {
+    let conn = crate::db::app_db_conn().ok()?;
+    match nvr_db::config::get_json::<HashMap<String, Saved>>(SAVE_KEY, &conn).await {
+        Ok(saved) => saved?.remove(device_id),
+        Err(e) => {
+            log::warn!("supervisor: load saved restarts failed: {e:#}");
+            None
+        }
+    }
+}
+
+/// Save the restarts of every supervised pipe.
+async fn save() -> anyhow::Result<()> {
+    let saved = SUPERVISED
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(id, s)| {
+            let r = &s.restarts;
+            let saved = Saved {
+                attempts: r.attempts,
+                failed: r.state == RestartState::Failed,
+                next_retry_at: r.next_retry_at,
+            };
+            (id.clone(), saved)
+        })
+        .collect::<HashMap<_, _>>();
+    let conn = crate::db::app_db_conn()?;
+    nvr_db::config::set_json(SAVE_KEY, &saved, &conn).await
+}
+
+/// Run `pipe` for `device_id`, starting it again as `policy` says each time
+/// it ends, until it is cancelled. The manager spawns this as the task of
+/// every pipe entry.
+pub(crate) async fn supervise(
+    device_id: String,
+    pipe: Arc<Pipe>,
+    input_options: Option<HashMap<String, String>>,
+    policy: RestartPolicy,
+) {
+    let restarts = load(&device_id)
+        .await
+        .map(|saved| Restarts::restore(&saved, now_ms()))
+        .unwrap_or_default();
+    let resume = Arc::new(Notify::new());
+    SUPERVISED.write().unwrap().insert(
+        device_id.clone(),
+        Supervised {
+            restarts,
+            resume: Arc::clone(&resume),
+        },
+    );
+    loop {
+        // Backing off or parked: wait for the retry time or a resume.
+        if let Some(at) = restart_status(&device_id).and_then(|s| s.next_retry_at) {
+            let wait = Duration::from_millis((at - now_ms()).max(0) as u64);
+            tokio::select! {
+                _ = pipe.cancelled() => break,
+                _ = resume.notified() => {}
+                _ = tokio::time::sleep(wait) => {
+                    // A parked pipe's cool-down is over.
+                    update(&device_id, |r| r.resume().then_some(RestartEventKind::Resumed));
+                }
+            }
+        }
+        update(&device_id, |r| {
+            r.started(now_ms());
+            None
+        });
+        let run = pipe.start(input_options.clone());
+        tokio::pin!(run);
+        let healthy = tokio::time::sleep(policy.healthy_after);
+        tokio::pin!(healthy);
+        let mut checked = false;
+        loop {
+            tokio::select! {
+                _ = &mut run => break,
+                _ = &mut healthy, if !checked => {
+                    checked = true;
+                    update(&device_id, |r| {
+                        r.healthy(&policy, now_ms()).then_some(RestartEventKind::Healthy)
+                    });
+                }
+            }
+        }
+        if pipe.is_cancelled() {
+            break;
+        }
+        update(&device_id, |r| Some(r.exited(&policy, now_ms())));
+    }
+    SUPERVISED.write().unwrap().remove(&device_id);
+}
+
+/// Retry `device_id`'s pipe now: a parked one starts over, one backing off
+/// skips the rest of its wait. `None` if the device has no supervised pipe.
+pub(crate) fn resume(device_id: &str) -> Option<RestartStatus> {
+    let notify = {
+        let supervised = SUPERVISED.read().unwrap();
+        let entry = supervised.get(device_id)?;
+        (entry.restarts.state != RestartState::Running).then(|| Arc::clone(&entry.resume))
+    };
+    update(device_id, |r| {
+        r.resume().then_some(RestartEventKind::Resumed)
+    });
+    if let Some(notify) = notify {
+        notify.notify_one();
+    }
+    restart_status(device_id)
+}
+
+/// Spawn the worker saving the attempt counts; it runs until `cancel` fires.
+pub fn spawn_worker(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep(SAVE_INTERVAL) => {}
+            }
+            if let Err(e) = save().await {
+                log::warn!("supervisor: save restarts failed: {e:#}");
+            }
+        }
+    });
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub(crate) struct RestartResponse {
+    /// `None` if the device has no supervised pipe.
+    current: Option<RestartStatus>,
+    /// The device's recent transitions, oldest first.
+    events: Vec<RestartEvent>,
+}
+
+fn restart_response(id: &str, current: Option<RestartStatus>) -> RestartResponse {
+    RestartResponse {
+        current,
+        events: recent_events()
+            .into_iter()
+            .filter(|e| e.device_id == id)
+            .collect(),
+    }
+}
+
+/// `GET /api/device/{id}/restart`.
+#[utoipa::path(
+    get,
+    path = "/{id}/restart",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses((status = 200, body = crate::handler::BaseResponse<RestartResponse>))
+)]
+pub(crate) async fn restarts(Path(id): Path<String>) -> ApiJsonResult<RestartResponse> {
+    Ok(ok_json(restart_response(&id, restart_status(&id))))
+}
+
+/// `POST /api/device/{id}/resume`: retry a parked (or backing off) device now.
+#[utoipa::path(
+    post,
+    path = "/{id}/resume",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses((status = 200, body = crate::handler::BaseResponse<RestartResponse>))
+)]
+pub(crate) async fn resume_device(Path(id): Path<String>) -> ApiJsonResult<RestartResponse> {
+    let current = resume(&id).ok_or_else(|| anyhow::anyhow!("device {id} has no running pipe"))?;
+    Ok(ok_json(restart_response(&id, Some(current))))
+}
+
+#[cfg(test)]
+#[path = "supervisor_test.rs"]
+mod supervisor_test;
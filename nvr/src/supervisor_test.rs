@@ -0,0 +1,158 @@
+use super::*;
+
+/// 1s doubling up to 8s, healthy after 30s, parked at 4 ends within 60s for
+/// 10 minutes.
+fn policy() -> RestartPolicy {
+    RestartPolicy {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(8),
+        healthy_after: Duration::from_secs(30),
+        flap_count: 4,
+        flap_window: Duration::from_secs(60),
+        cool_down: Duration::from_secs(600),
+    }
+}
+
+/// Run a session from `start_ms` that ends `lasted_ms` later; returns the
+/// transition and the wait it set.
+fn session(r: &mut Restarts, start_ms: i64, lasted_ms: i64) -> (RestartEventKind, i64) {
+    r.started(start_ms);
+    let kind = r.exited(&policy(), start_ms + lasted_ms);
+    (kind, r.next_retry_at.unwrap() - (start_ms + lasted_ms))
+}
+
+#[test]
+fn policy_parses_the_environment() {
+    let env = HashMap::from([
+        ("NVR_RESTART_INITIAL_SECS", "5"),
+        ("NVR_RESTART_MAX_SECS", "3"),
+        ("NVR_RESTART_FLAP_COUNT", "0"),
+        ("NVR_RESTART_COOL_DOWN_SECS", "soon"),
+    ]);
+    let policy = RestartPolicy::from_map(|k| env.get(k).map(|v| v.to_string()));
+    assert_eq!(
+        policy,
+        RestartPolicy {
+            initial: Duration::from_secs(5),
+            // Never below the first wait.
+            max: Duration::from_secs(5),
+            ..RestartPolicy::default()
+        }
+    );
+    assert_eq!(RestartPolicy::from_map(|_| None), RestartPolicy::default());
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let policy = policy();
+    let waits = (1..=6)
+        .map(|n| policy.delay(n).as_secs())
+        .collect::<Vec<_>>();
+    assert_eq!(waits, [1, 2, 4, 8, 8, 8]);
+    assert_eq!(policy.delay(u32::MAX), policy.max);
+
+    // Ends spread out enough not to flap back off in sequence.
+    let mut r = Restarts::default();
+    let mut now = 0;
+    let mut waits = Vec::new();
+    for _ in 0..4 {
+        let (kind, wait) = session(&mut r, now, 1000);
+        assert_eq!(kind, RestartEventKind::Restarting);
+        assert_eq!(r.state, RestartState::Backoff);
+        waits.push(wait);
+        now += 30_000;
+    }
+    assert_eq!(waits, [1000, 2000, 4000, 8000]);
+    assert_eq!(r.attempts, 4);
+}
+
+#[test]
+fn a_healthy_session_starts_the_backoff_over() {
+    let policy = policy();
+    let mut r = Restarts::default();
+    session(&mut r, 0, 1000);
+    session(&mut r, 2000, 1000);
+    assert_eq!(r.attempts, 2);
+
+    r.started(10_000);
+    assert!(!r.healthy(&policy, 39_999));
+    assert!(r.healthy(&policy, 40_000));
+    assert_eq!((r.state, r.attempts), (RestartState::Running, 0));
+    // Only once.
+    assert!(!r.healthy(&policy, 50_000));
+
+    // A healthy session that ends waits the base delay again, even unchecked.
+    let (_, wait) = session(&mut r, 100_000, 5000);
+    assert_eq!(wait, 1000);
+    let (_, wait) = session(&mut r, 200_000, 40_000);
+    assert_eq!((wait, r.attempts), (1000, 1));
+}
+
+#[test]
+fn flapping_parks_the_pipe_until_resumed() {
+    let mut r = Restarts::default();
+    for start in [0, 10_000, 20_000] {
+        assert_eq!(session(&mut r, start, 1000).0, RestartEventKind::Restarting);
+    }
+    // The fourth end within a minute.
+    let (kind, wait) = session(&mut r, 30_000, 1000);
+    assert_eq!(kind, RestartEventKind::Parked);
+    assert_eq!(wait, 600_000);
+    assert_eq!(
+        r.status(),
+        RestartStatus {
+            state: RestartState::Failed,
+            attempts: 4,
+            next_retry_at: Some(631_000),
+        }
+    );
+
+    assert!(r.resume());
+    assert_eq!(
+        (r.state, r.attempts, r.next_retry_at),
+        (RestartState::Backoff, 0, None)
+    );
+    assert!(!r.resume());
+    // The flap window starts over too.
+    assert_eq!(
+        session(&mut r, 700_000, 1000).0,
+        RestartEventKind::Restarting
+    );
+}
+
+#[test]
+fn ends_outside_the_flap_window_do_not_park() {
+    let mut r = Restarts::default();
+    for start in (0..10).map(|i| i * 25_000) {
+        assert_eq!(session(&mut r, start, 1000).0, RestartEventKind::Restarting);
+    }
+}
+
+#[test]
+fn saved_state_is_restored_approximately() {
+    let parked = Saved {
+        attempts: 7,
+        failed: true,
+        next_retry_at: Some(5000),
+    };
+    let r = Restarts::restore(&parked, 1000);
+    assert_eq!(
+        r.status(),
+        RestartStatus {
+            state: RestartState::Failed,
+            attempts: 7,
+            next_retry_at: Some(5000),
+        }
+    );
+    // A cool-down that ran out while the server was down: start right away,
+    // still counting the attempts.
+    let r = Restarts::restore(&parked, 6000);
+    assert_eq!(
+        r.status(),
+        RestartStatus {
+            state: RestartState::Running,
+            attempts: 7,
+            next_retry_at: None,
+        }
+    );
+}
@@ -0,0 +1,315 @@
+//! Warm thumbnails for the dashboard device grid. A background worker
+//! captures a small JPEG of every running device every
+//! `NVR_THUMBNAIL_INTERVAL_SECS` (default 30, 0 disables) from the snapshot
+//! frame cache (see [`crate::snapshot`]) and stores it on disk as
+//! `<dir>/<device>/latest.jpg`, replaced atomically, plus the last
+//! `NVR_THUMBNAIL_HISTORY` (default 0) captures as `<capture ms>.jpg`. So disk
+//! use is bounded by that count times the devices.
+//!
+//! `GET /api/device/{id}/thumbnail` serves the latest one. Its ETag is the
+//! capture time (kept as the file's mtime, so it survives restarts), letting
+//! the grid revalidate with `If-None-Match` for a 304. A device that is not
+//! running gets its last-known thumbnail with `X-Stale: true`;
+//! `X-Thumbnail-Age-Ms` tells how old it is either way.
+
+use std::io::Write;
+use std::path::{Path as FsPath, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use axum::{
+    extract::Path,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::config;
+
+/// How long one capture waits for a frame when the device's snapshot feed
+/// has none cached yet.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
+const LATEST: &str = "latest.jpg";
+
+/// Thumbnail settings, parsed from environment variables.
+#[derive(Debug, Clone)]
+pub struct ThumbnailConfig {
+    /// Time between captures of a device; zero disables the worker.
+    pub interval: Duration,
+    /// Earlier captures kept per device besides the latest.
+    pub history: usize,
+    /// Thumbnails are downscaled to at most this width.
+    pub width: u32,
+    /// Root directory (`NVR_THUMBNAIL_DIR`); defaults to
+    /// `<cwd>/data/thumbnails`.
+    pub dir: PathBuf,
+}
+
+impl ThumbnailConfig {
+    /// The capture settings from the `NVR_THUMBNAIL_*` values `get` returns:
+    /// every 30s, 320 wide (16 at least), no history, and `data/thumbnails`
+    /// under the working directory unless set.
+    pub fn from_map(get: impl Fn(&str) -> Option<String>) -> ThumbnailConfig {
+        let number = |key: &str, default: u64| {
+            get(key)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        let dir = get("NVR_THUMBNAIL_DIR")
+            .map(|dir| dir.trim().to_string())
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                std::env::current_dir()
+                    .map(|cwd| cwd.join("data").join("thumbnails"))
+                    .unwrap_or_else(|_| PathBuf::from("data").join("thumbnails"))
+            });
+        ThumbnailConfig {
+            interval: Duration::from_secs(number("NVR_THUMBNAIL_INTERVAL_SECS", 30)),
+            history: number("NVR_THUMBNAIL_HISTORY", 0) as usize,
+            width: (number("NVR_THUMBNAIL_WIDTH", 320) as u32).max(16),
+            dir,
+        }
+    }
+
+    /// Parse from the real process environment.
+    pub fn from_env() -> ThumbnailConfig {
+        Self::from_map(|k| std::env::var(k).ok())
+    }
+
+    /// The directory of `device_id`'s thumbnails; `None` for ids that are not
+    /// a plain path component.
+    fn device_dir(&self, device_id: &str) -> Option<PathBuf> {
+        let plain = !device_id.is_empty()
+            && !device_id.starts_with('.')
+            && !device_id.contains(['/', '\\']);
+        plain.then(|| self.dir.join(device_id))
+    }
+
+    /// Where `device_id`'s latest thumbnail is stored.
+    pub(crate) fn latest_path(&self, device_id: &str) -> Option<PathBuf> {
+        self.device_dir(device_id).map(|dir| dir.join(LATEST))
+    }
+}
+
+/// Spawn the capture worker; it runs until `cancel` fires. Not started when
+/// the interval is zero.
+pub fn spawn_worker(cancel: CancellationToken) {
+    let cfg = config().thumbnail().clone();
+    if cfg.interval.is_zero() {
+        log::info!("thumbnail: disabled");
+        return;
+    }
+    tokio::spawn(async move {
+        log::info!(
+            "thumbnail: worker started (every {:?}, {} kept, under {})",
+            cfg.interval,
+            cfg.history,
+            cfg.dir.display()
+        );
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(crate::manager::STARTUP_DELAY) => {}
+        }
+        loop {
+            run_once(&cfg).await;
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    log::info!("thumbnail: worker stopped");
+                    return;
+                }
+                _ = tokio::time::sleep(cfg.interval) => {}
+            }
+        }
+    });
+}
+
+/// Capture every running device that is not in privacy mode, one after the
+/// other.
+async fn run_once(cfg: &ThumbnailConfig) {
+    for id in crate::manager::list_pipe_ids().await {
+        if crate::privacy::is_private(&id) || crate::manager::status(&id).await != Some(true) {
+            continue;
+        }
+        if let Err(e) = capture(&id, cfg).await {
+            log::debug!("thumbnail[{id}]: capture failed: {e:#}");
+        }
+    }
+}
+
+/// Capture and store one thumbnail of `device_id`. Returns the capture time,
+/// unix milliseconds.
+pub(crate) async fn capture(device_id: &str, cfg: &ThumbnailConfig) -> Result<i64> {
+    let dir = cfg
+        .device_dir(device_id)
+        .ok_or_else(|| anyhow::anyhow!("invalid device id {device_id:?}"))?;
+    let slot = crate::snapshot::latest_frame(device_id, CAPTURE_TIMEOUT)
+        .await
+        .map_err(|miss| anyhow::anyhow!("no frame: {miss:?}"))?;
+    let captured_at = SystemTime::now() - slot.at.elapsed();
+    let (width, history) = (cfg.width, cfg.history);
+    tokio::task::spawn_blocking(move || {
+        let jpeg = crate::snapshot::to_jpeg_scaled(&slot.frame, width)?;
+        store(&dir, &jpeg, captured_at, history)
+    })
+    .await??;
+    Ok(unix_ms(captured_at))
+}
+
+/// Write `jpeg` as the latest thumbnail in `dir` (via a temporary file and a
+/// rename, so readers never see a partial image), stamped with the capture
+/// time, and keep at most `history` earlier captures.
+fn store(dir: &FsPath, jpeg: &[u8], captured_at: SystemTime, history: usize) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!("{LATEST}.tmp"));
+    {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(jpeg)?;
+        file.set_modified(captured_at)?;
+    }
+    if history > 0 {
+        std::fs::copy(&tmp, dir.join(format!("{}.jpg", unix_ms(captured_at))))?;
+    }
+    std::fs::rename(&tmp, dir.join(LATEST))?;
+    prune_history(dir, history)
+}
+
+/// Remove all but the newest `keep` history files of a device.
+fn prune_history(dir: &FsPath, keep: usize) -> Result<()> {
+    let mut stamps = std::fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_suffix(".jpg")?.parse::<i64>().ok()
+        })
+        .collect::<Vec<_>>();
+    if stamps.len() <= keep {
+        return Ok(());
+    }
+    stamps.sort_unstable();
+    for stamp in &stamps[..stamps.len() - keep] {
+        let _ = std::fs::remove_file(dir.join(format!("{stamp}.jpg")));
+    }
+    Ok(())
+}
+
+/// Delete every thumbnail of a removed device (best-effort).
+pub(crate) async fn remove(device_id: &str) {
+    let Some(dir) = config().thumbnail().device_dir(device_id) else {
+        return;
+    };
+    match tokio::fs::remove_dir_all(&dir).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!(
+            "thumbnail[{device_id}]: delete {} failed: {e:#}",
+            dir.display()
+        ),
+    }
+}
+
+pub(crate) fn unix_ms(at: SystemTime) -> i64 {
+    at.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// `GET /api/device/{id}/thumbnail`.
+#[utoipa::path(
+    get,
+    path = "/{id}/thumbnail",
+    tag = "device",
+    params(("id" = String, Path)),
+    responses(
+        (
+            status = 200,
+            description = "The latest capture; `X-Stale` while the device is offline",
+            body = [u8],
+            content_type = "image/jpeg",
+        ),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 404, description = "Nothing captured yet", body = String),
+    )
+)]
+pub(crate) async fn thumbnail(Path(id): Path<String>, headers: HeaderMap) -> Response {
+    if crate::privacy::is_private(&id) {
+        return crate::snapshot::privacy_response();
+    }
+    let online = crate::manager::status(&id).await == Some(true);
+    respond(config().thumbnail(), &id, &headers, online).await
+}
+
+/// The latest stored thumbnail of `device_id`, or 304 when `headers` carry
+/// its ETag.
+pub(crate) async fn respond(
+    cfg: &ThumbnailConfig,
+    device_id: &str,
+    headers: &HeaderMap,
+    online: bool,
+) -> Response {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            format!("no thumbnail for {device_id}"),
+        )
+            .into_response()
+    };
+    let Some(path) = cfg.latest_path(device_id) else {
+        return not_found();
+    };
+    // Stat the opened file, so the ETag matches the image served even if a
+    // capture replaces it meanwhile.
+    let Ok(mut file) = tokio::fs::File::open(&path).await else {
+        return not_found();
+    };
+    let Ok(captured_at) = file.metadata().await.and_then(|m| m.modified()) else {
+        return not_found();
+    };
+    let etag = format!("\"{}\"", unix_ms(captured_at));
+    let age_ms = captured_at
+        .elapsed()
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+        .to_string();
+    let mut meta = HeaderMap::new();
+    meta.insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).expect("ascii etag"),
+    );
+    meta.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    meta.insert(
+        header::HeaderName::from_static("x-thumbnail-age-ms"),
+        HeaderValue::from_str(&age_ms).expect("ascii age"),
+    );
+    if !online {
+        meta.insert(
+            header::HeaderName::from_static("x-stale"),
+            HeaderValue::from_static("true"),
+        );
+    }
+
+    let matches = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if matches {
+        return (StatusCode::NOT_MODIFIED, meta).into_response();
+    }
+    let mut jpeg = Vec::new();
+    if let Err(e) = file.read_to_end(&mut jpeg).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("read thumbnail of {device_id}: {e}"),
+        )
+            .into_response();
+    }
+    meta.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/jpeg"));
+    (meta, jpeg).into_response()
+}
+
+#[cfg(test)]
+#[path = "thumbnail_test.rs"]
+mod thumbnail_test;
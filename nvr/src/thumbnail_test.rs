@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+use media_pipe_core::{InputConfig, PipeConfig};
+
+use super::*;
+
+fn temp_config(history: usize) -> ThumbnailConfig {
+    let dir = std::env::temp_dir().join(format!("nvr-thumbnail-{}", uuid::Uuid::new_v4()));
+    ThumbnailConfig {
+        dir,
+        history,
+        ..ThumbnailConfig::from_map(|_| None)
+    }
+}
+
+fn if_none_match(etag: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(etag).unwrap());
+    headers
+}
+
+#[test]
+fn config_defaults_and_overrides() {
+    let cfg = ThumbnailConfig::from_map(|_| None);
+    assert_eq!(cfg.interval, Duration::from_secs(30));
+    assert_eq!(cfg.history, 0);
+    assert_eq!(cfg.width, 320);
+    assert!(cfg.dir.ends_with("data/thumbnails"));
+
+    let env = HashMap::from([
+        ("NVR_THUMBNAIL_INTERVAL_SECS", "0"),
+        ("NVR_THUMBNAIL_HISTORY", "5"),
+        ("NVR_THUMBNAIL_DIR", "/var/thumbs"),
+    ]);
+    let cfg = ThumbnailConfig::from_map(|k| env.get(k).map(|v| v.to_string()));
+    assert!(cfg.interval.is_zero());
+    assert_eq!(cfg.history, 5);
+    assert_eq!(cfg.dir, PathBuf::from("/var/thumbs"));
+
+    assert!(cfg.device_dir("../etc").is_none());
+    assert!(cfg.device_dir("a/b").is_none());
+    assert_eq!(
+        cfg.device_dir("cam1"),
+        Some(PathBuf::from("/var/thumbs/cam1"))
+    );
+}
+
+#[test]
+fn store_replaces_latest_and_bounds_history() {
+    let cfg = temp_config(2);
+    let dir = cfg.device_dir("cam").unwrap();
+    for i in 0..4u64 {
+        let at = UNIX_EPOCH + Duration::from_secs(1_000 + i);
+        store(&dir, &[i as u8], at, cfg.history).unwrap();
+    }
+    assert_eq!(std::fs::read(dir.join(LATEST)).unwrap(), vec![3]);
+    let mut names = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["1002000.jpg", "1003000.jpg", LATEST]);
+    std::fs::remove_dir_all(&cfg.dir).unwrap();
+}
+
+#[tokio::test]
+async fn file_device_gets_a_thumbnail_served_with_etag() {
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let id = "thumbnail-test-cam";
+    crate::manager::add_pipe(
+        id,
+        PipeConfig {
+            input: InputConfig::FileLoop {
+                path: path.to_string_lossy().into_owned(),
+                realtime: true,
+            },
+            outputs: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let cfg = temp_config(0);
+    let mut captured = None;
+    for _ in 0..50 {
+        match capture(id, &cfg).await {
+            Ok(at) => {
+                captured = Some(at);
+                break;
+            }
+            // The pipe is still opening its input.
+            Err(_) => tokio::time::sleep(Duration::from_millis(200)).await,
+        }
+    }
+    crate::manager::remove_pipe(id).await.unwrap();
+    let captured = captured.expect("no thumbnail captured");
+
+    let file = cfg.device_dir(id).unwrap().join(LATEST);
+    let jpeg = std::fs::read(&file).unwrap();
+    assert_eq!(&jpeg[..2], &[0xFF, 0xD8], "missing JPEG SOI marker");
+
+    let response = respond(&cfg, id, &HeaderMap::new(), true).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(etag, format!("\"{captured}\""));
+    assert!(response.headers().get("x-stale").is_none());
+
+    let response = respond(&cfg, id, &if_none_match(&etag), true).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // Offline: still served, flagged stale.
+    let response = respond(&cfg, id, &if_none_match("\"1\""), false).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-stale"], "true");
+
+    assert_eq!(
+        respond(&cfg, "other-cam", &HeaderMap::new(), true)
+            .await
+            .status(),
+        StatusCode::NOT_FOUND
+    );
+    std::fs::remove_dir_all(&cfg.dir).unwrap();
+}
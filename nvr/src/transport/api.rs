@@ -1,21 +1,25 @@
 //! REST API for managing transport targets and inspecting upload jobs. GET/POST
-//! only (the dashboard convention). Passwords are redacted in responses and
-//! preserved on update when the client sends a blank one.
+//! only (the dashboard convention). Passwords and secret keys are redacted in
+//! responses and preserved on update when the client sends a blank one.
 
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     extract::Path,
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use serde::{Deserialize, Serialize};
 
+use nvr_db::record_segment;
 use nvr_db::transport_job::{self, TransportJob};
 use nvr_db::transport_target::{self, TransportTarget};
 
+use crate::auth::AuthUser;
 use crate::db::app_db_conn;
-use crate::handler::{ApiJsonResult, ok_empty, ok_json};
+use crate::handler::{ApiError, ApiJsonResult, ApiResult, ok_empty, ok_json};
 use crate::transport::backend::build_backend;
-use crate::transport::config::redact_config;
+use crate::transport::config::{SECRET_FIELDS, TargetOptions, redact_config};
+use crate::transport::worker::{MAX_ATTEMPTS, uploading};
 
 pub fn transport_router() -> Router {
     Router::new()
@@ -27,6 +31,11 @@ pub fn transport_router() -> Router {
         .route("/jobs/{target_id}", get(list_jobs))
 }
 
+/// Admin views of the uploads, mounted at `/api/v1/storage`.
+pub fn storage_router() -> Router {
+    Router::new().route("/upload_queue", get(upload_queue))
+}
+
 #[derive(Deserialize)]
 struct TargetPayload {
     name: String,
@@ -78,7 +87,7 @@ fn to_dto(target: TransportTarget, done: i64, failed: i64, pending: i64) -> Targ
 
 fn validate_kind(kind: &str) -> anyhow::Result<()> {
     match kind {
-        "ftp" | "smb" => Ok(()),
+        "ftp" | "smb" | "s3" => Ok(()),
         other => anyhow::bail!("unsupported transport kind: {other}"),
     }
 }
@@ -121,7 +130,7 @@ async fn update_target(
     let existing = transport_target::get(&id, &conn)
         .await?
         .ok_or_else(|| anyhow::anyhow!("transport target not found"))?;
-    let config = merge_password(&existing.config, payload.config);
+    let config = merge_secrets(&existing.config, payload.config);
     let now = chrono::Utc::now().to_rfc3339();
     let target = TransportTarget {
         id: existing.id,
@@ -138,20 +147,19 @@ async fn update_target(
     Ok(ok_json(to_dto(target, done, failed, pending)))
 }
 
-/// Keep the stored password when the client sends a blank one (the redacted
-/// value round-tripped from a GET).
-fn merge_password(existing_config: &str, mut incoming: serde_json::Value) -> String {
+/// Keep the stored password or secret key when the client sends a blank one
+/// (the redacted value round-tripped from a GET).
+fn merge_secrets(existing_config: &str, mut incoming: serde_json::Value) -> String {
+    let old = serde_json::from_str::<serde_json::Value>(existing_config).ok();
     if let Some(obj) = incoming.as_object_mut() {
-        let blank = obj
-            .get("password")
-            .and_then(|value| value.as_str())
-            .map(str::is_empty)
-            .unwrap_or(true);
-        if blank {
-            if let Ok(old) = serde_json::from_str::<serde_json::Value>(existing_config) {
-                if let Some(password) = old.get("password") {
-                    obj.insert("password".to_string(), password.clone());
-                }
+        for field in SECRET_FIELDS {
+            let blank = obj
+                .get(field)
+                .and_then(|value| value.as_str())
+                .map(str::is_empty)
+                .unwrap_or(true);
+            if blank && let Some(secret) = old.as_ref().and_then(|old| old.get(field)) {
+                obj.insert(field.to_string(), secret.clone());
             }
         }
     }
@@ -180,3 +188,48 @@ async fn list_jobs(Path(target_id): Path<String>) -> ApiJsonResult<Vec<Transport
     let jobs = transport_job::list_recent(&target_id, 50, &conn).await?;
     Ok(ok_json(jobs))
 }
+
+/// How far one enabled target's uploads are.
+#[derive(Serialize)]
+struct QueuedTarget {
+    id: String,
+    name: String,
+    kind: String,
+    /// Segments still to upload, retries of failed ones included.
+    queued: usize,
+    /// Ids of the segments being uploaded now.
+    uploading: Vec<String>,
+    /// The failed jobs, the soonest retried first; those at `max_attempts`
+    /// are not retried.
+    failed: Vec<TransportJob>,
+    max_attempts: i64,
+}
+
+/// The upload queue of every enabled target. Admin-only.
+async fn upload_queue(Extension(user): Extension<AuthUser>) -> ApiResult<Response> {
+    if !crate::auth::is_admin(&user.username).await? {
+        return Err(ApiError::forbidden("the upload queue is admin-only"));
+    }
+    let conn = app_db_conn()?;
+    let mut out = Vec::new();
+    for target in transport_target::list_enabled(&conn).await? {
+        let options = TargetOptions::of(&target);
+        let queued = record_segment::count_needing_transport(
+            &target.id,
+            &options.devices,
+            MAX_ATTEMPTS,
+            &conn,
+        )
+        .await?;
+        out.push(QueuedTarget {
+            uploading: uploading(&target.id),
+            failed: transport_job::list_failed(&target.id, 50, &conn).await?,
+            id: target.id,
+            name: target.name,
+            kind: target.kind,
+            queued,
+            max_attempts: MAX_ATTEMPTS,
+        });
+    }
+    Ok(ok_json(out).into_response())
+}
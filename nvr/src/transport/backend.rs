@@ -1,4 +1,4 @@
-//! The storage-backend abstraction. New destinations (S3, …) implement
+//! The storage-backend abstraction. New destinations implement
 //! `StorageBackend` and get wired into `build_backend`.
 
 use std::path::Path;
@@ -7,6 +7,7 @@ use anyhow::Result;
 use nvr_db::transport_target::TransportTarget;
 
 use crate::transport::ftp::FtpBackend;
+use crate::transport::s3::S3Backend;
 #[cfg(feature = "smb")]
 use crate::transport::smb::SmbBackend;
 
@@ -24,6 +25,7 @@ pub trait StorageBackend: Send + Sync {
 pub fn build_backend(target: &TransportTarget) -> Result<Box<dyn StorageBackend>> {
     match target.kind.as_str() {
         "ftp" => Ok(Box::new(FtpBackend::from_json(&target.config)?)),
+        "s3" => Ok(Box::new(S3Backend::from_json(&target.config)?)),
         #[cfg(feature = "smb")]
         "smb" => Ok(Box::new(SmbBackend::from_json(&target.config)?)),
         #[cfg(not(feature = "smb"))]
@@ -43,6 +43,101 @@ fn default_workgroup() -> String {
     "WORKGROUP".to_string()
 }
 
+/// An S3-compatible object store (AWS S3, MinIO, Ceph RGW, …).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    /// Service URL, e.g. "https://s3.eu-west-1.amazonaws.com" or
+    /// "http://minio:9000".
+    pub endpoint: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub bucket: String,
+    /// Object key prefix. `{device}`, `{date}` (YYYY-MM-DD), `{year}`,
+    /// `{month}`, `{day}` and `{hour}` are filled in from the segment's device
+    /// and start time (UTC).
+    #[serde(default = "default_s3_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub access_key: String,
+    #[serde(default)]
+    pub secret_key: String,
+    /// Address objects as `endpoint/bucket/key` (MinIO and most self-hosted
+    /// stores) rather than `bucket.endpoint/key`.
+    #[serde(default = "default_true")]
+    pub path_style: bool,
+    /// Files over this many MiB go up as a multipart upload.
+    #[serde(default = "default_multipart_threshold")]
+    pub multipart_threshold_mb: u64,
+    /// Part size of a multipart upload, in MiB (S3 takes no less than 5).
+    #[serde(default = "default_part_size")]
+    pub part_size_mb: u64,
+    /// Upload bandwidth cap in bytes per second, across the target's uploads;
+    /// 0 for none.
+    #[serde(default)]
+    pub max_bytes_per_sec: u64,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_s3_prefix() -> String {
+    "{device}".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_multipart_threshold() -> u64 {
+    16
+}
+
+fn default_part_size() -> u64 {
+    8
+}
+
+/// What every target takes besides its kind's own settings, from the same
+/// config JSON.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TargetOptions {
+    /// Only segments of these devices go to the target; empty for all.
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Uploads to the target running at once.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Delete a segment's local file once every enabled target taking it has
+    /// a copy.
+    #[serde(default)]
+    pub delete_local: bool,
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+impl Default for TargetOptions {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            concurrency: default_concurrency(),
+            delete_local: false,
+        }
+    }
+}
+
+impl TargetOptions {
+    pub fn of(target: &TransportTarget) -> Self {
+        serde_json::from_str(&target.config).unwrap_or_default()
+    }
+
+    /// Whether segments of `stream` go to the target.
+    pub fn takes(&self, stream: &str) -> bool {
+        self.devices.is_empty() || self.devices.iter().any(|d| d == stream)
+    }
+}
+
 /// Build the remote key `[base_path/]<stream>/<file_name>` with clean slashes.
 pub fn remote_key(base_path: &str, stream: &str, file_name: &str) -> String {
     let mut parts: Vec<&str> = Vec::new();
@@ -60,9 +155,27 @@ pub fn remote_key(base_path: &str, stream: &str, file_name: &str) -> String {
 
 /// The remote key a given segment should be uploaded to for `target`.
 pub fn remote_key_for(target: &TransportTarget, segment: &RecordSegment) -> String {
+    if target.kind == "s3" {
+        let prefix = serde_json::from_str::<S3Config>(&target.config)
+            .map(|c| c.prefix)
+            .unwrap_or_else(|_| default_s3_prefix());
+        return remote_key(&render_prefix(&prefix, segment), "", &segment.file_name);
+    }
     remote_key(&base_path_of(target), &segment.stream, &segment.file_name)
 }
 
+/// Fill in the variables of an S3 `prefix` template for `segment`.
+pub fn render_prefix(template: &str, segment: &RecordSegment) -> String {
+    let start = chrono::DateTime::from_timestamp(segment.start_time as i64, 0).unwrap_or_default();
+    template
+        .replace("{device}", &segment.stream)
+        .replace("{date}", &start.format("%Y-%m-%d").to_string())
+        .replace("{year}", &start.format("%Y").to_string())
+        .replace("{month}", &start.format("%m").to_string())
+        .replace("{day}", &start.format("%d").to_string())
+        .replace("{hour}", &start.format("%H").to_string())
+}
+
 fn base_path_of(target: &TransportTarget) -> String {
     match target.kind.as_str() {
         "ftp" => serde_json::from_str::<FtpConfig>(&target.config)
@@ -75,17 +188,20 @@ fn base_path_of(target: &TransportTarget) -> String {
     }
 }
 
-/// Return `config` with any `password` field blanked, for safe display in API
+/// Config fields holding credentials: redacted in API responses, kept on
+/// update when sent blank.
+pub const SECRET_FIELDS: [&str; 2] = ["password", "secret_key"];
+
+/// Return `config` with any [`SECRET_FIELDS`] blanked, for safe display in API
 /// responses (the real value stays in the DB).
 pub fn redact_config(config: &str) -> String {
     match serde_json::from_str::<serde_json::Value>(config) {
         Ok(mut value) => {
             if let Some(obj) = value.as_object_mut() {
-                if obj.contains_key("password") {
-                    obj.insert(
-                        "password".to_string(),
-                        serde_json::Value::String(String::new()),
-                    );
+                for field in SECRET_FIELDS {
+                    if obj.contains_key(field) {
+                        obj.insert(field.to_string(), serde_json::Value::String(String::new()));
+                    }
                 }
             }
             value.to_string()
@@ -20,6 +20,54 @@ fn redact_blanks_only_password() {
     assert_eq!(v["base_path"], "p");
 }
 
+#[test]
+fn redact_blanks_the_s3_secret_key() {
+    let out = redact_config(r#"{"bucket":"b","access_key":"AK","secret_key":"SK"}"#);
+    let v: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(v["secret_key"], "");
+    assert_eq!(v["access_key"], "AK");
+}
+
+#[test]
+fn s3_keys_follow_the_prefix_template() {
+    let target = |config: &str| TransportTarget {
+        id: "t".into(),
+        name: "s3".into(),
+        kind: "s3".into(),
+        enabled: true,
+        config: config.into(),
+        remark: String::new(),
+        create_time: String::new(),
+        update_time: String::new(),
+    };
+    let segment = RecordSegment {
+        stream: "cam1".into(),
+        // 2026-10-14 12:30:05 UTC.
+        start_time: 1_791_981_005,
+        file_name: "12-30-05-0.mp4".into(),
+        ..Default::default()
+    };
+    let templated =
+        target(r#"{"endpoint":"http://s3","bucket":"b","prefix":"nvr/{device}/{date}/{hour}"}"#);
+    assert_eq!(
+        remote_key_for(&templated, &segment),
+        "nvr/cam1/2026-10-14/12/12-30-05-0.mp4"
+    );
+    let plain = target(r#"{"endpoint":"http://s3","bucket":"b"}"#);
+    assert_eq!(remote_key_for(&plain, &segment), "cam1/12-30-05-0.mp4");
+}
+
+#[test]
+fn options_default_to_every_device_one_at_a_time() {
+    let options: TargetOptions = serde_json::from_str(r#"{"host":"h"}"#).unwrap();
+    assert!(options.takes("cam1"));
+    assert_eq!(options.concurrency, 1);
+    assert!(!options.delete_local);
+    let options: TargetOptions = serde_json::from_str(r#"{"devices":["cam2"]}"#).unwrap();
+    assert!(!options.takes("cam1"));
+    assert!(options.takes("cam2"));
+}
+
 #[test]
 fn redact_passthrough_on_non_json() {
     assert_eq!(redact_config("not json"), "not json");
@@ -0,0 +1,326 @@
+//! S3-compatible backend: objects PUT over plain HTTP with AWS Signature V4,
+//! files over the multipart threshold uploaded in parts. No SDK, so any store
+//! speaking the S3 API (AWS, MinIO, Ceph RGW, …) works.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::transport::backend::StorageBackend;
+use crate::transport::config::S3Config;
+
+/// The least part size S3 takes (all but the last part).
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+const MIB: u64 = 1024 * 1024;
+
+pub struct S3Backend {
+    cfg: S3Config,
+    client: reqwest::Client,
+    throttle: Throttle,
+}
+
+impl S3Backend {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let cfg: S3Config = serde_json::from_str(json).context("parse s3 config")?;
+        Url::parse(&cfg.endpoint).with_context(|| format!("s3 endpoint {:?}", cfg.endpoint))?;
+        if cfg.bucket.is_empty() {
+            bail!("s3 config has no bucket");
+        }
+        Ok(Self {
+            throttle: Throttle::new(cfg.max_bytes_per_sec),
+            cfg,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn part_size(&self) -> u64 {
+        (self.cfg.part_size_mb * MIB).max(MIN_PART_SIZE)
+    }
+
+    /// The URL of `key` in the bucket (the bucket itself for an empty key).
+    fn url(&self, key: &str) -> Result<Url> {
+        let mut url = Url::parse(&self.cfg.endpoint)?;
+        let base = url.path().trim_end_matches('/').to_string();
+        let key = uri_encode(key.trim_start_matches('/'), false);
+        if self.cfg.path_style {
+            url.set_path(&format!(
+                "{base}/{}/{key}",
+                uri_encode(&self.cfg.bucket, true)
+            ));
+        } else {
+            let host = url.host_str().context("s3 endpoint has no host")?;
+            url.set_host(Some(&format!("{}.{host}", self.cfg.bucket)))?;
+            url.set_path(&format!("{base}/{key}"));
+        }
+        Ok(url)
+    }
+
+    /// Send a signed request; `query` is already canonical (sorted, encoded).
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &str,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response> {
+        let mut url = self.url(key)?;
+        url.set_query((!query.is_empty()).then_some(query));
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let mut request = self
+            .client
+            .request(method.clone(), url.clone())
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("x-amz-date", &amz_date);
+        if !self.cfg.access_key.is_empty() {
+            let authorization = sign(
+                &self.cfg,
+                &Signed {
+                    method: method.as_str(),
+                    url: &url,
+                    query,
+                    payload_hash: &payload_hash,
+                    amz_date: &amz_date,
+                },
+            )?;
+            request = request.header(reqwest::header::AUTHORIZATION, authorization);
+        }
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("s3 {method} {key}: {status}: {}", text.trim());
+        }
+        Ok(response)
+    }
+
+    async fn put_object(&self, local_path: &Path, key: &str) -> Result<()> {
+        let body = tokio::fs::read(local_path)
+            .await
+            .with_context(|| format!("read {}", local_path.display()))?;
+        self.throttle.take(body.len() as u64).await;
+        self.send(Method::PUT, key, "", body).await?;
+        Ok(())
+    }
+
+    async fn put_multipart(&self, local_path: &Path, key: &str, size: u64) -> Result<()> {
+        let created = self.send(Method::POST, key, "uploads=", Vec::new()).await?;
+        let upload_id = xml_value(&created.text().await?, "UploadId")
+            .context("s3 did not return an UploadId")?;
+        let result = self.put_parts(local_path, key, size, &upload_id).await;
+        if result.is_err() {
+            // Leave no orphaned parts behind (best effort).
+            let query = format!("uploadId={}", uri_encode(&upload_id, true));
+            if let Err(e) = self.send(Method::DELETE, key, &query, Vec::new()).await {
+                log::warn!("transport: aborting s3 upload of {key} failed: {e:#}");
+            }
+        }
+        result
+    }
+
+    async fn put_parts(
+        &self,
+        local_path: &Path,
+        key: &str,
+        size: u64,
+        upload_id: &str,
+    ) -> Result<()> {
+        let upload_id = uri_encode(upload_id, true);
+        let part_size = self.part_size();
+        let mut file = tokio::fs::File::open(local_path)
+            .await
+            .with_context(|| format!("open {}", local_path.display()))?;
+        let mut etags = Vec::new();
+        let mut sent = 0u64;
+        while sent < size {
+            let len = part_size.min(size - sent);
+            let mut part = vec![0u8; len as usize];
+            file.read_exact(&mut part).await?;
+            self.throttle.take(len).await;
+            let number = etags.len() + 1;
+            let query = format!("partNumber={number}&uploadId={upload_id}");
+            let response = self.send(Method::PUT, key, &query, part).await?;
+            let etag = response
+                .headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .context("s3 part upload returned no ETag")?
+                .to_string();
+            etags.push(etag);
+            sent += len;
+        }
+        let mut complete = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in etags.iter().enumerate() {
+            complete.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{etag}</ETag></Part>",
+                i + 1
+            ));
+        }
+        complete.push_str("</CompleteMultipartUpload>");
+        let query = format!("uploadId={upload_id}");
+        let response = self
+            .send(Method::POST, key, &query, complete.into_bytes())
+            .await?;
+        // Completion can fail after the 200 has been sent.
+        let text = response.text().await?;
+        if text.contains("<Error>") {
+            bail!(
+                "s3 completing {key}: {}",
+                xml_value(&text, "Message").unwrap_or(text)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for S3Backend {
+    async fn upload(&self, local_path: &Path, remote_key: &str) -> Result<()> {
+        let size = tokio::fs::metadata(local_path)
+            .await
+            .with_context(|| format!("stat {}", local_path.display()))?
+            .len();
+        if size > self.cfg.multipart_threshold_mb * MIB {
+            self.put_multipart(local_path, remote_key, size).await
+        } else {
+            self.put_object(local_path, remote_key).await
+        }
+    }
+
+    async fn test(&self) -> Result<()> {
+        self.send(Method::HEAD, "", "", Vec::new()).await?;
+        Ok(())
+    }
+}
+
+/// What a Signature V4 signature covers.
+struct Signed<'a> {
+    method: &'a str,
+    url: &'a Url,
+    query: &'a str,
+    payload_hash: &'a str,
+    amz_date: &'a str,
+}
+
+/// The `Authorization` header of a request signed with Signature V4, over
+/// its host, `x-amz-content-sha256` and `x-amz-date` headers.
+fn sign(cfg: &S3Config, req: &Signed) -> Result<String> {
+    let mut host = req
+        .url
+        .host_str()
+        .context("s3 url has no host")?
+        .to_string();
+    if let Some(port) = req.url.port() {
+        host.push_str(&format!(":{port}"));
+    }
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical = format!(
+        "{}\n{}\n{}\nhost:{host}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{signed_headers}\n{}",
+        req.method,
+        req.url.path(),
+        req.query,
+        req.payload_hash,
+        req.amz_date,
+        req.payload_hash,
+    );
+    let date = &req.amz_date[..8];
+    let scope = format!("{date}/{}/s3/aws4_request", cfg.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{scope}\n{}",
+        req.amz_date,
+        hex::encode(Sha256::digest(canonical.as_bytes()))
+    );
+    let key = signing_key(&cfg.secret_key, date, &cfg.region);
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+    Ok(format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        cfg.access_key
+    ))
+}
+
+/// The Signature V4 key for `date` (YYYYMMDD) in `region`.
+fn signing_key(secret_key: &str, date: &str, region: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{secret_key}").as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, b"s3");
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// HMAC-SHA256 (RFC 2104).
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// Percent-encode all but the unreserved characters, and `/` too unless
+/// `slash` is false (object keys keep theirs).
+fn uri_encode(value: &str, slash: bool) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            b'/' if !slash => out.push('/'),
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+/// The text of the first `<tag>` in an S3 XML reply.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{tag}>");
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].to_string())
+}
+
+/// Paces uploads to a byte rate: each chunk waits for the time the ones
+/// before it take at that rate, so the cap holds averaged over whole parts.
+struct Throttle {
+    bytes_per_sec: u64,
+    next: tokio::sync::Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            next: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until `bytes` more may be sent.
+    async fn take(&self, bytes: u64) {
+        if self.bytes_per_sec == 0 {
+            return;
+        }
+        let start = {
+            let mut next = self.next.lock().await;
+            let start = (*next).max(Instant::now());
+            *next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+            start
+        };
+        tokio::time::sleep_until(start.into()).await;
+    }
+}
+
+#[cfg(test)]
+#[path = "s3_test.rs"]
+pub(crate) mod s3_test;
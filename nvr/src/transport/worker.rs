@@ -1,21 +1,40 @@
-//! Background transport worker: periodically copies not-yet-uploaded record
-//! segments to every enabled target, retrying failures up to a cap. Copy only —
-//! local files are kept so dashboard playback is unaffected.
+//! Background transport worker: copies not-yet-uploaded record segments to
+//! every enabled target that takes them, woken when a segment is closed and on
+//! a poll. Failures are retried with exponential backoff up to a cap. Local
+//! files are kept unless a target taking them sets `delete_local`, and even
+//! then only once every target taking the segment has its copy.
 
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, RwLock};
 use std::time::Duration;
 
 use anyhow::Result;
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::sync::Notify;
 use tokio_util::sync::CancellationToken;
 
+use nvr_db::record_segment::{self, RecordSegment};
 use nvr_db::transport_job::{self, STATUS_DONE, STATUS_FAILED, TransportJob};
-use nvr_db::{record_segment, transport_target};
+use nvr_db::transport_target::{self, TransportTarget};
 
-use crate::transport::backend::build_backend;
-use crate::transport::config::remote_key_for;
+use crate::transport::backend::{StorageBackend, build_backend};
+use crate::transport::config::{TargetOptions, remote_key_for};
 
 const POLL_INTERVAL: Duration = Duration::from_secs(30);
-const MAX_ATTEMPTS: i64 = 5;
+pub(crate) const MAX_ATTEMPTS: i64 = 5;
 const BATCH_PER_TARGET: usize = 20;
+/// Wait after the first failure; doubled for each one after, up to
+/// [`RETRY_MAX`].
+const RETRY_BASE: Duration = Duration::from_secs(60);
+const RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// Wakes the worker ahead of its poll.
+static WAKE: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+/// (target id, segment id) of the uploads under way.
+static UPLOADING: LazyLock<RwLock<HashSet<(String, String)>>> =
+    LazyLock::new(|| RwLock::new(HashSet::new()));
 
 /// Spawn the transport worker; it runs until `cancel` fires.
 pub fn spawn_worker(cancel: CancellationToken) {
@@ -32,6 +51,7 @@ pub fn spawn_worker(cancel: CancellationToken) {
                     return;
                 }
                 _ = tick.tick() => {}
+                _ = WAKE.notified() => {}
             }
             if let Err(e) = sweep().await {
                 log::warn!("transport: sweep failed: {e:#}");
@@ -40,12 +60,24 @@ pub fn spawn_worker(cancel: CancellationToken) {
     });
 }
 
+/// A record segment was closed and saved: upload it now rather than at the
+/// next poll.
+pub(crate) fn segment_closed() {
+    WAKE.notify_one();
+}
+
+/// How long after its `attempts`-th failure an upload is tried again.
+pub(crate) fn retry_delay(attempts: i64) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    (RETRY_BASE * 2u32.pow(doublings)).min(RETRY_MAX)
+}
+
 /// One pass: for each enabled target, upload a batch of pending segments.
 async fn sweep() -> Result<()> {
     let conn = crate::db::app_db_conn()?;
     let targets = transport_target::list_enabled(&conn).await?;
-    for target in targets {
-        let backend = match build_backend(&target) {
+    for target in &targets {
+        let backend = match build_backend(target) {
             Ok(backend) => backend,
             Err(e) => {
                 log::warn!(
@@ -55,63 +87,223 @@ async fn sweep() -> Result<()> {
                 continue;
             }
         };
-        let segments = record_segment::list_needing_transport(
-            &target.id,
-            MAX_ATTEMPTS,
-            BATCH_PER_TARGET,
-            &conn,
-        )
-        .await?;
-        for segment in segments {
-            let remote_key = remote_key_for(&target, &segment);
-            let existing = transport_job::get(&segment.id, &target.id, &conn).await?;
-            let attempts = existing.as_ref().map(|job| job.attempts).unwrap_or(0) + 1;
-            let now = chrono::Utc::now().to_rfc3339();
-            let job_id = existing
-                .as_ref()
-                .map(|job| job.id.clone())
-                .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
-            let create_time = existing
-                .as_ref()
-                .map(|job| job.create_time.clone())
-                .unwrap_or_else(|| now.clone());
+        sweep_target(target, &*backend, &targets, &conn).await?;
+    }
+    Ok(())
+}
 
+/// Upload a batch of `target`'s pending segments, up to its `concurrency` at
+/// once, recording each outcome as it comes in.
+async fn sweep_target(
+    target: &TransportTarget,
+    backend: &dyn StorageBackend,
+    targets: &[TransportTarget],
+    conn: &turso::Connection,
+) -> Result<()> {
+    let options = TargetOptions::of(target);
+    let now = chrono::Utc::now().to_rfc3339();
+    let segments = record_segment::list_needing_transport(
+        &target.id,
+        &options.devices,
+        MAX_ATTEMPTS,
+        &now,
+        BATCH_PER_TARGET,
+        conn,
+    )
+    .await?;
+    let mut uploads = futures::stream::iter(segments)
+        .map(|segment| async move {
+            let remote_key = remote_key_for(target, &segment);
+            let key = (target.id.clone(), segment.id.clone());
+            UPLOADING.write().unwrap().insert(key.clone());
             let result = backend
                 .upload(std::path::Path::new(&segment.file_path), &remote_key)
                 .await;
-            let (status, error) = match &result {
-                Ok(()) => (STATUS_DONE, String::new()),
-                Err(e) => (STATUS_FAILED, format!("{e:#}")),
-            };
-            let job = TransportJob {
-                id: job_id,
-                segment_id: segment.id.clone(),
-                target_id: target.id.clone(),
-                status,
-                attempts,
-                remote_key,
-                file_size: segment.file_size as i64,
-                error,
-                create_time,
-                update_time: now,
-            };
-            transport_job::upsert(&job, &conn).await?;
-            match result {
-                Ok(()) => log::info!(
-                    "transport: '{}' -> {} ({})",
-                    segment.file_name,
-                    target.name,
-                    job.remote_key
-                ),
-                Err(e) => log::warn!(
-                    "transport: '{}' -> {} failed (attempt {}/{}): {e:#}",
-                    segment.file_name,
-                    target.name,
-                    attempts,
-                    MAX_ATTEMPTS
-                ),
+            UPLOADING.write().unwrap().remove(&key);
+            (segment, remote_key, result)
+        })
+        .buffer_unordered(options.concurrency.max(1));
+    while let Some((segment, remote_key, result)) = uploads.next().await {
+        record_upload(target, &segment, remote_key, &result, conn).await?;
+        match result {
+            Ok(()) => {
+                // Uploads are egress of the recording's device.
+                crate::usage::egress_meter(&segment.stream).add(segment.file_size as u64);
+                if offloads(&segment, targets) && !awaits_upload(&segment, targets, conn).await? {
+                    crate::cleanup::remove_segment(&segment, conn).await;
+                    log::info!(
+                        "transport: '{}' is stored remotely only now",
+                        segment.file_name
+                    );
+                }
             }
+            Err(e) => log::warn!(
+                "transport: '{}' -> {} failed: {e:#}",
+                segment.file_name,
+                target.name
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Save the outcome of one upload as the segment's job for `target`.
+async fn record_upload(
+    target: &TransportTarget,
+    segment: &RecordSegment,
+    remote_key: String,
+    result: &Result<()>,
+    conn: &turso::Connection,
+) -> Result<()> {
+    let existing = transport_job::get(&segment.id, &target.id, conn).await?;
+    let attempts = existing.as_ref().map(|job| job.attempts).unwrap_or(0) + 1;
+    let now = chrono::Utc::now();
+    let job_id = existing
+        .as_ref()
+        .map(|job| job.id.clone())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    let create_time = existing
+        .as_ref()
+        .map(|job| job.create_time.clone())
+        .unwrap_or_else(|| now.to_rfc3339());
+    let (status, error, next_attempt_time) = match result {
+        Ok(()) => (STATUS_DONE, String::new(), String::new()),
+        Err(e) => {
+            let retry = now + retry_delay(attempts);
+            (STATUS_FAILED, format!("{e:#}"), retry.to_rfc3339())
         }
+    };
+    let job = TransportJob {
+        id: job_id,
+        segment_id: segment.id.clone(),
+        target_id: target.id.clone(),
+        status,
+        attempts,
+        remote_key,
+        file_size: segment.file_size as i64,
+        error,
+        create_time,
+        update_time: now.to_rfc3339(),
+        next_attempt_time,
+    };
+    transport_job::upsert(&job, conn).await?;
+    match status {
+        STATUS_DONE => log::info!(
+            "transport: '{}' -> {} ({})",
+            segment.file_name,
+            target.name,
+            job.remote_key
+        ),
+        _ if attempts >= MAX_ATTEMPTS => log::warn!(
+            "transport: '{}' -> {} gave up after {attempts} attempts",
+            segment.file_name,
+            target.name
+        ),
+        _ => log::info!(
+            "transport: '{}' -> {} retries at {} (attempt {attempts}/{MAX_ATTEMPTS})",
+            segment.file_name,
+            target.name,
+            job.next_attempt_time
+        ),
     }
     Ok(())
 }
+
+/// Whether the local file of `segment` goes once it is uploaded: one of the
+/// enabled `targets` taking it sets `delete_local`.
+fn offloads(segment: &RecordSegment, targets: &[TransportTarget]) -> bool {
+    targets.iter().any(|target| {
+        let options = TargetOptions::of(target);
+        target.enabled && options.delete_local && options.takes(&segment.stream)
+    })
+}
+
+/// Whether one of the enabled `targets` takes `segment` and has no copy of it
+/// yet, so the local file is its only one. Never for a missing segment: there
+/// is nothing left to upload.
+pub(crate) async fn awaits_upload(
+    segment: &RecordSegment,
+    targets: &[TransportTarget],
+    conn: &turso::Connection,
+) -> Result<bool> {
+    if segment.status == record_segment::STATUS_MISSING {
+        return Ok(false);
+    }
+    for target in targets {
+        if !target.enabled || !TargetOptions::of(target).takes(&segment.stream) {
+            continue;
+        }
+        let done = transport_job::get(&segment.id, &target.id, conn)
+            .await?
+            .is_some_and(|job| job.status == STATUS_DONE);
+        if !done {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The ids of the segments being uploaded to `target_id` right now.
+pub(crate) fn uploading(target_id: &str) -> Vec<String> {
+    UPLOADING
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(target, _)| target == target_id)
+        .map(|(_, segment)| segment.clone())
+        .collect()
+}
+
+/// Where a segment's copy on one target is at.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SegmentUpload {
+    pub target_id: String,
+    pub target_name: String,
+    /// "pending", "uploaded" or "failed" (the last attempt did).
+    pub status: &'static str,
+    pub attempts: i64,
+}
+
+/// The [`SegmentUpload`]s of each of `segments`, by segment id, for every
+/// enabled target taking it.
+pub(crate) async fn upload_states(
+    segments: &[RecordSegment],
+    conn: &turso::Connection,
+) -> Result<HashMap<String, Vec<SegmentUpload>>> {
+    let targets = transport_target::list_enabled(conn).await?;
+    if targets.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let ids = segments.iter().map(|s| s.id.clone()).collect::<Vec<_>>();
+    let jobs = transport_job::list_by_segments(&ids, conn)
+        .await?
+        .into_iter()
+        .map(|job| ((job.segment_id.clone(), job.target_id.clone()), job))
+        .collect::<HashMap<_, _>>();
+    let mut states = HashMap::new();
+    for segment in segments {
+        let mut uploads = Vec::new();
+        for target in &targets {
+            if !TargetOptions::of(target).takes(&segment.stream) {
+                continue;
+            }
+            let job = jobs.get(&(segment.id.clone(), target.id.clone()));
+            uploads.push(SegmentUpload {
+                target_id: target.id.clone(),
+                target_name: target.name.clone(),
+                status: match job.map(|job| job.status) {
+                    Some(STATUS_DONE) => "uploaded",
+                    Some(STATUS_FAILED) => "failed",
+                    _ => "pending",
+                },
+                attempts: job.map(|job| job.attempts).unwrap_or(0),
+            });
+        }
+        states.insert(segment.id.clone(), uploads);
+    }
+    Ok(states)
+}
+
+#[cfg(test)]
+#[path = "worker_test.rs"]
+mod worker_test;
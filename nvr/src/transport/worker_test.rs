@@ -0,0 +1,166 @@
+use std::sync::atomic::Ordering;
+
+use super::*;
+use crate::db::app_db_conn;
+use crate::transport::s3::s3_test::{mock_config, mock_s3};
+
+fn s3_target(config: String) -> TransportTarget {
+    let now = chrono::Utc::now().to_rfc3339();
+    TransportTarget {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        name: "s3".to_string(),
+        kind: "s3".to_string(),
+        enabled: true,
+        config,
+        remark: String::new(),
+        create_time: now.clone(),
+        update_time: now,
+    }
+}
+
+/// A recorded segment of a device of its own, so other tests' segments stay
+/// out of the targets taking it.
+fn recorded_segment() -> RecordSegment {
+    let stream = format!("cam-{}", uuid::Uuid::new_v4().simple());
+    let path = std::env::temp_dir().join(format!("nvr-transport-{stream}.mp4"));
+    std::fs::write(&path, b"segment bytes").unwrap();
+    RecordSegment {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        start_time: 1_791_981_005,
+        duration: 5.0,
+        file_size: 13,
+        file_name: "12-30-05-0.mp4".to_string(),
+        file_path: path.to_string_lossy().into_owned(),
+        stream,
+        create_time: chrono::Utc::now(),
+        update_time: chrono::Utc::now(),
+        ..Default::default()
+    }
+}
+
+/// Make the failed job of `segment` on `target` due now.
+async fn due_now(segment: &RecordSegment, target: &TransportTarget, conn: &turso::Connection) {
+    let job = transport_job::get(&segment.id, &target.id, conn)
+        .await
+        .unwrap()
+        .unwrap();
+    let job = TransportJob {
+        next_attempt_time: String::new(),
+        ..job
+    };
+    transport_job::upsert(&job, conn).await.unwrap();
+}
+
+#[test]
+fn retries_back_off_exponentially_up_to_an_hour() {
+    assert_eq!(retry_delay(1), Duration::from_secs(60));
+    assert_eq!(retry_delay(2), Duration::from_secs(120));
+    assert_eq!(retry_delay(3), Duration::from_secs(240));
+    assert_eq!(retry_delay(50), Duration::from_secs(3600));
+}
+
+#[tokio::test]
+async fn failed_uploads_back_off_then_retry() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let (endpoint, mock) = mock_s3().await;
+    let segment = recorded_segment();
+    record_segment::upsert(&segment, &conn).await.unwrap();
+    let extra = format!(
+        r#","devices":["{}"],"prefix":"nvr/{{device}}/{{date}}""#,
+        segment.stream
+    );
+    let target = s3_target(mock_config(&endpoint, &extra));
+    transport_target::upsert(&target, &conn).await.unwrap();
+
+    mock.fail.store(1, Ordering::SeqCst);
+    sweep().await.unwrap();
+    let job = transport_job::get(&segment.id, &target.id, &conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((job.status, job.attempts), (STATUS_FAILED, 1));
+    assert!(job.next_attempt_time > chrono::Utc::now().to_rfc3339());
+    let states = upload_states(std::slice::from_ref(&segment), &conn)
+        .await
+        .unwrap();
+    assert_eq!(states[&segment.id][0].status, "failed");
+
+    // Not due yet: left alone.
+    sweep().await.unwrap();
+    let job = transport_job::get(&segment.id, &target.id, &conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(job.attempts, 1);
+
+    due_now(&segment, &target, &conn).await;
+    sweep().await.unwrap();
+    let job = transport_job::get(&segment.id, &target.id, &conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((job.status, job.attempts), (STATUS_DONE, 2));
+    let key = format!("nvr/{}/2026-10-14/12-30-05-0.mp4", segment.stream);
+    assert_eq!(job.remote_key, key);
+    assert_eq!(mock.objects.lock().unwrap()[&key], b"segment bytes");
+    // Copy only: the local file stays.
+    assert!(std::path::Path::new(&segment.file_path).exists());
+    assert!(
+        !awaits_upload(&segment, &[target.clone()], &conn)
+            .await
+            .unwrap()
+    );
+
+    transport_target::delete(&target.id, &conn).await.unwrap();
+    record_segment::delete(&segment.id, &conn).await.unwrap();
+    std::fs::remove_file(&segment.file_path).unwrap();
+}
+
+#[tokio::test]
+async fn local_copies_go_once_every_target_has_one() {
+    let _db = crate::db::test_db().await;
+    let conn = app_db_conn().unwrap();
+    let (offloading_endpoint, _) = mock_s3().await;
+    let (keeping_endpoint, keeping) = mock_s3().await;
+    let segment = recorded_segment();
+    record_segment::upsert(&segment, &conn).await.unwrap();
+    let devices = format!(r#","devices":["{}"]"#, segment.stream);
+    let offloading = s3_target(mock_config(
+        &offloading_endpoint,
+        &format!(r#"{devices},"delete_local":true"#),
+    ));
+    let keeping_target = s3_target(mock_config(&keeping_endpoint, &devices));
+    transport_target::upsert(&offloading, &conn).await.unwrap();
+    transport_target::upsert(&keeping_target, &conn)
+        .await
+        .unwrap();
+
+    // One copy is not enough while the other target still needs it.
+    keeping.fail.store(1, Ordering::SeqCst);
+    sweep().await.unwrap();
+    assert!(std::path::Path::new(&segment.file_path).exists());
+    assert!(
+        record_segment::get(&segment.id, &conn)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    due_now(&segment, &keeping_target, &conn).await;
+    sweep().await.unwrap();
+    assert!(!std::path::Path::new(&segment.file_path).exists());
+    assert!(
+        record_segment::get(&segment.id, &conn)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    transport_target::delete(&offloading.id, &conn)
+        .await
+        .unwrap();
+    transport_target::delete(&keeping_target.id, &conn)
+        .await
+        .unwrap();
+}
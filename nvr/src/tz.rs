@@ -0,0 +1,101 @@
+//! Time zones. Timestamps are stored and returned in UTC (RFC 3339 text, or
+//! unix seconds/milliseconds); a zone only decides how wall-clock things map
+//! onto them: privacy windows, the date/time in archived segment file names
+//! and where a device's timeline days start. Each device may name an IANA
+//! zone (`DeviceInfo::timezone`); devices without one use the server default
+//! ([`crate::config::NvrConfig::timezone`]).
+//!
+//! Around DST changes a wall-clock time may not exist or may occur twice.
+//! [`resolve`] picks one instant for it either way, so a daily event neither
+//! skips a day nor fires twice.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, LocalResult, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use nvr_db::device::DeviceInfo;
+
+use crate::db::app_db_conn;
+
+/// Longest DST gap searched past by [`resolve`].
+const MAX_GAP_MINUTES: i64 = 24 * 60;
+
+/// Check a device's `timezone`: empty (the server default) or an IANA name.
+pub(crate) fn validate(name: &str) -> Result<()> {
+    if !name.is_empty() {
+        parse(name)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn parse(name: &str) -> Result<Tz> {
+    name.parse::<Tz>().map_err(|_| {
+        anyhow::anyhow!("unknown time zone {name:?}: use an IANA name like Europe/Berlin")
+    })
+}
+
+/// The zone `device` keeps its wall-clock times in.
+pub(crate) fn of(device: &DeviceInfo) -> Tz {
+    parse(&device.timezone).unwrap_or_else(|_| crate::config::config().timezone())
+}
+
+/// The zone of device `device_id`; the server default if it is unknown.
+pub(crate) async fn of_device(device_id: &str) -> Result<Tz> {
+    let conn = app_db_conn()?;
+    Ok(match nvr_db::device::get(device_id, &conn).await? {
+        Some(device) => of(&device),
+        None => crate::config::config().timezone(),
+    })
+}
+
+/// The zone of every device, by id.
+pub(crate) async fn of_devices() -> Result<HashMap<String, Tz>> {
+    let conn = app_db_conn()?;
+    Ok(nvr_db::device::list(&conn)
+        .await?
+        .iter()
+        .map(|device| (device.id.clone(), of(device)))
+        .collect())
+}
+
+/// Device `device_id`'s zone in `zones`, as from [`of_devices`]; the server
+/// default if it is missing.
+pub(crate) fn lookup(zones: &HashMap<String, Tz>, device_id: &str) -> Tz {
+    zones
+        .get(device_id)
+        .copied()
+        .unwrap_or_else(|| crate::config::config().timezone())
+}
+
+/// The instant wall-clock `local` is in `tz`. A time skipped by a DST change
+/// moves forward to the first one after the gap; a time that occurs twice
+/// takes its first occurrence.
+pub(crate) fn resolve(local: NaiveDateTime, tz: Tz) -> DateTime<Utc> {
+    let mut time = local;
+    for _ in 0..=MAX_GAP_MINUTES {
+        match tz.from_local_datetime(&time) {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => return t.to_utc(),
+            LocalResult::None => time += TimeDelta::minutes(1),
+        }
+    }
+    Utc.from_utc_datetime(&local)
+}
+
+/// Local `day` of `tz` as unix milliseconds, `start..end`. Not always 24
+/// hours: days with a DST change are 23 or 25.
+pub(crate) fn day_bounds(day: NaiveDate, tz: Tz) -> (i64, i64) {
+    let start = resolve(day.and_time(chrono::NaiveTime::MIN), tz);
+    let next = day.succ_opt().unwrap_or(day);
+    let end = resolve(next.and_time(chrono::NaiveTime::MIN), tz);
+    (start.timestamp_millis(), end.timestamp_millis())
+}
+
+/// Today in `tz`.
+pub(crate) fn today(tz: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&tz).date_naive()
+}
+
+#[cfg(test)]
+#[path = "tz_test.rs"]
+mod tz_test;
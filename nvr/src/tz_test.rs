@@ -0,0 +1,56 @@
+use chrono::NaiveTime;
+
+use super::*;
+
+const NEW_YORK: Tz = chrono_tz::America::New_York;
+
+fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(y, mo, d)
+        .unwrap()
+        .and_time(NaiveTime::from_hms_opt(h, mi, 0).unwrap())
+}
+
+fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&local(y, mo, d, h, mi))
+}
+
+#[test]
+fn validates_iana_names() {
+    assert!(validate("").is_ok());
+    assert!(validate("America/New_York").is_ok());
+    assert!(validate("UTC").is_ok());
+    assert!(validate("Mars/Olympus_Mons").is_err());
+    assert!(validate("+02:00").is_err());
+}
+
+#[test]
+fn resolves_skipped_and_repeated_times() {
+    // Plain EDT.
+    assert_eq!(
+        resolve(local(2026, 10, 14, 12, 0), NEW_YORK),
+        utc(2026, 10, 14, 16, 0)
+    );
+    // 2026-03-08 02:30 does not exist in New York: clocks jump to 03:00 EDT.
+    assert_eq!(
+        resolve(local(2026, 3, 8, 2, 30), NEW_YORK),
+        utc(2026, 3, 8, 7, 0)
+    );
+    // 2026-11-01 01:30 happens twice: the first, in EDT.
+    assert_eq!(
+        resolve(local(2026, 11, 1, 1, 30), NEW_YORK),
+        utc(2026, 11, 1, 5, 30)
+    );
+}
+
+#[test]
+fn days_follow_dst() {
+    let hours = |day| {
+        let (start, end) = day_bounds(day, NEW_YORK);
+        (end - start) / 3_600_000
+    };
+    assert_eq!(hours(NaiveDate::from_ymd_opt(2026, 10, 14).unwrap()), 24);
+    assert_eq!(hours(NaiveDate::from_ymd_opt(2026, 3, 8).unwrap()), 23);
+    assert_eq!(hours(NaiveDate::from_ymd_opt(2026, 11, 1).unwrap()), 25);
+    let (start, _) = day_bounds(NaiveDate::from_ymd_opt(2026, 10, 14).unwrap(), NEW_YORK);
+    assert_eq!(start, utc(2026, 10, 14, 4, 0).timestamp_millis());
+}
@@ -0,0 +1,352 @@
+//! Data usage per device, for sites on metered uplinks. A background worker
+//! samples two kinds of byte counters every minute:
+//!
+//! - ingress: what each running device pipe has read from its camera
+//!   (`InputStats::bytes`, every stream);
+//! - egress: what was served from each device, counted by an
+//!   [`EgressMeter`] — live sessions through the `/media` proxy (see
+//!   `crate::proxy`) and recordings uploaded by `crate::transport`.
+//!
+//! Counters only grow while their source runs, and start over from zero when
+//! it restarts. [`Deltas`] turns the readings into the bytes moved since the
+//! last one, per device and direction, and adds them to the current hour of
+//! the `usage` table (see [`nvr_db::usage`]).
+//!
+//! Every ten minutes the completed hours are rolled up into days and months
+//! of the default zone ([`crate::config::NvrConfig::timezone`]), and rolled
+//! hours older than `NVR_USAGE_HOUR_RETENTION_DAYS` (default 31) are pruned;
+//! days and months are kept. `GET /api/v1/device/{id}/usage` returns a
+//! device's hours, days or months, `GET /api/v1/usage/summary` each device's
+//! total over a range of days.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{
+    Router,
+    extract::{Path, Query},
+    routing::get,
+};
+use chrono::{Datelike, TimeZone, Utc};
+use chrono_tz::Tz;
+use nvr_db::usage::{Granularity, Usage};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::db::app_db_conn;
+use crate::handler::{ApiJsonResult, BaseResponse, ok_json};
+
+/// Time between two samples of the counters.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+/// Time between two rollup passes.
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Default days of hourly buckets kept (`NVR_USAGE_HOUR_RETENTION_DAYS`).
+pub const DEFAULT_HOUR_RETENTION_DAYS: u32 = 31;
+const HOUR_MS: i64 = 3_600_000;
+
+/// Which way bytes went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Direction {
+    /// Read from the camera.
+    Ingress,
+    /// Served from the device.
+    Egress,
+}
+
+/// Bytes served from one device since the server started. Clones count into
+/// the same total.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EgressMeter(Arc<AtomicU64>);
+
+impl EgressMeter {
+    pub(crate) fn add(&self, bytes: u64) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+static EGRESS: LazyLock<Mutex<HashMap<String, EgressMeter>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The egress meter of `device_id`.
+pub(crate) fn egress_meter(device_id: &str) -> EgressMeter {
+    EGRESS
+        .lock()
+        .unwrap()
+        .entry(device_id.to_string())
+        .or_default()
+        .clone()
+}
+
+/// The last reading of each counter, to turn the next one into a delta.
+#[derive(Debug, Default)]
+pub(crate) struct Deltas {
+    last: HashMap<(String, Direction), u64>,
+}
+
+impl Deltas {
+    /// Bytes `device_id` moved in `direction` since the counter's last
+    /// reading, given its reading now. A counter below its last reading
+    /// started over (its pipe restarted), so all it holds is new; the same
+    /// goes for a counter read for the first time, which started with the
+    /// server. Never negative.
+    pub(crate) fn delta(&mut self, device_id: &str, direction: Direction, value: u64) -> u64 {
+        let last = self.last.insert((device_id.to_string(), direction), value);
+        match last {
+            Some(last) if value >= last => value - last,
+            _ => value,
+        }
+    }
+
+    /// The deltas of `readings` (device, direction, counter value), summed
+    /// per device into buckets of the hour `now_ms` is in. Devices that moved
+    /// nothing are left out.
+    pub(crate) fn buckets(
+        &mut self,
+        readings: impl IntoIterator<Item = (String, Direction, u64)>,
+        now_ms: i64,
+    ) -> Vec<Usage> {
+        let hour = hour_start(now_ms);
+        let mut buckets: HashMap<String, Usage> = HashMap::new();
+        for (device_id, direction, value) in readings {
+            let delta = self.delta(&device_id, direction, value) as i64;
+            if delta == 0 {
+                continue;
+            }
+            let bucket = buckets.entry(device_id.clone()).or_insert_with(|| Usage {
+                device_id,
+                start: hour,
+                ..Usage::default()
+            });
+            match direction {
+                Direction::Ingress => bucket.ingress_bytes += delta,
+                Direction::Egress => bucket.egress_bytes += delta,
+            }
+        }
+        let mut buckets = buckets.into_values().collect::<Vec<_>>();
+        buckets.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        buckets
+    }
+}
+
+/// Start of the hour `ms` (unix milliseconds) is in.
+pub(crate) fn hour_start(ms: i64) -> i64 {
+    ms - ms.rem_euclid(HOUR_MS)
+}
+
+/// Starts (unix milliseconds) of the local day and month of `tz` that the
+/// instant `ms` is in.
+pub(crate) fn day_and_month(ms: i64, tz: Tz) -> (i64, i64) {
+    let local = Utc
+        .timestamp_millis_opt(ms)
+        .single()
+        .unwrap_or_default()
+        .with_timezone(&tz)
+        .date_naive();
+    let first = local.with_day(1).unwrap_or(local);
+    (
+        crate::tz::day_bounds(local, tz).0,
+        crate::tz::day_bounds(first, tz).0,
+    )
+}
+
+/// Every counter's reading now.
+async fn readings() -> Vec<(String, Direction, u64)> {
+    let mut readings = Vec::new();
+    for id in crate::manager::list_pipe_ids().await {
+        // Native workers have no pipe to read.
+        let Some(pipe) = crate::manager::get_pipe(&id).await else {
+            continue;
+        };
+        if pipe.is_started()
+            && let Some(stats) = pipe.input_stats().await
+        {
+            readings.push((id, Direction::Ingress, stats.bytes));
+        }
+    }
+    let meters = EGRESS.lock().unwrap();
+    for (id, meter) in meters.iter() {
+        readings.push((id.clone(), Direction::Egress, meter.total()));
+    }
+    readings
+}
+
+async fn sample(deltas: &mut Deltas, now_ms: i64) -> Result<()> {
+    let buckets = deltas.buckets(readings().await, now_ms);
+    if buckets.is_empty() {
+        return Ok(());
+    }
+    let conn = app_db_conn()?;
+    for bucket in &buckets {
+        nvr_db::usage::add_hour(bucket, &conn).await?;
+    }
+    Ok(())
+}
+
+/// Roll the hours completed by `now_ms` up into their days and months of
+/// `tz`, then drop rolled hours older than `retention`. Returns how many
+/// hours were rolled up and how many pruned.
+pub(crate) async fn roll_up(
+    now_ms: i64,
+    tz: Tz,
+    retention: Duration,
+    conn: &mut turso::Connection,
+) -> Result<(usize, u64)> {
+    let hours = nvr_db::usage::unrolled_before(hour_start(now_ms), conn).await?;
+    for hour in &hours {
+        let (day, month) = day_and_month(hour.start, tz);
+        nvr_db::usage::roll_up(hour, day, month, conn).await?;
+    }
+    let cutoff = now_ms - retention.as_millis() as i64;
+    let pruned = nvr_db::usage::prune_rolled(cutoff, conn).await?;
+    Ok((hours.len(), pruned))
+}
+
+async fn roll_up_now() -> Result<()> {
+    let config = crate::config::config();
+    let mut conn = app_db_conn()?;
+    let (rolled, pruned) = roll_up(
+        Utc::now().timestamp_millis(),
+        config.timezone(),
+        config.usage_hour_retention(),
+        &mut conn,
+    )
+    .await?;
+    if rolled > 0 || pruned > 0 {
+        log::debug!("usage: rolled up {rolled} hours, pruned {pruned}");
+    }
+    Ok(())
+}
+
+/// Spawn the sampling and rollup worker; it runs until `cancel` fires.
+pub fn spawn_worker(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        log::info!("usage: worker started");
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(crate::manager::STARTUP_DELAY) => {}
+        }
+        let mut deltas = Deltas::default();
+        let mut samples = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut rollups = tokio::time::interval(ROLLUP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    // What moved since the last sample still counts.
+                    if let Err(e) = sample(&mut deltas, Utc::now().timestamp_millis()).await {
+                        log::warn!("usage: final sample failed: {e:#}");
+                    }
+                    log::info!("usage: worker stopped");
+                    return;
+                }
+                _ = samples.tick() => {
+                    if let Err(e) = sample(&mut deltas, Utc::now().timestamp_millis()).await {
+                        log::warn!("usage: sample failed: {e:#}");
+                    }
+                }
+                _ = rollups.tick() => {
+                    if let Err(e) = roll_up_now().await {
+                        log::warn!("usage: rollup failed: {e:#}");
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct UsageQuery {
+    /// Default `hour`. Days and months hold completed hours only.
+    #[param(inline)]
+    granularity: Option<Granularity>,
+    /// Periods starting at or after this, unix milliseconds; default 0.
+    from: Option<i64>,
+    /// Periods starting at or before this, unix milliseconds; default now.
+    to: Option<i64>,
+}
+
+/// `GET /api/device/{id}/usage`: the device's data usage per period.
+#[utoipa::path(
+    get,
+    path = "/{id}/usage",
+    tag = "device",
+    params(("id" = String, Path), UsageQuery),
+    responses((status = 200, description = "Oldest first", body = BaseResponse<Vec<Usage>>))
+)]
+pub(crate) async fn device_usage(
+    Path(id): Path<String>,
+    Query(query): Query<UsageQuery>,
+) -> ApiJsonResult<Vec<Usage>> {
+    let granularity = query.granularity.unwrap_or(Granularity::Hour);
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or_else(|| Utc::now().timestamp_millis());
+    let usage = nvr_db::usage::list_by_device(&id, granularity, from, to, &app_db_conn()?).await?;
+    Ok(ok_json(usage))
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SummaryQuery {
+    /// Days starting at or after this, unix milliseconds; default the start
+    /// of the current month.
+    from: Option<i64>,
+    /// Days starting at or before this, unix milliseconds; default now.
+    to: Option<i64>,
+}
+
+/// Data usage of the whole site over a range of days.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub(crate) struct UsageSummary {
+    /// Unix milliseconds.
+    from: i64,
+    to: i64,
+    ingress_bytes: i64,
+    egress_bytes: i64,
+    /// Each device's share, by device id.
+    devices: Vec<Usage>,
+}
+
+pub(crate) fn usage_router() -> Router {
+    Router::new().route("/summary", get(summary))
+}
+
+/// The schema of [`usage_router`], mounted at `/api/v1/usage`.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(summary))]
+pub(crate) struct UsageApi;
+
+/// `GET /api/usage/summary`: every device's usage summed over completed
+/// days.
+#[utoipa::path(
+    get,
+    path = "/summary",
+    tag = "usage",
+    params(SummaryQuery),
+    responses((status = 200, body = BaseResponse<UsageSummary>))
+)]
+async fn summary(Query(query): Query<SummaryQuery>) -> ApiJsonResult<UsageSummary> {
+    let now = Utc::now().timestamp_millis();
+    let from = query
+        .from
+        .unwrap_or_else(|| day_and_month(now, crate::config::config().timezone()).1);
+    let to = query.to.unwrap_or(now);
+    let devices = nvr_db::usage::totals_by_device(from, to, &app_db_conn()?).await?;
+    Ok(ok_json(UsageSummary {
+        from,
+        to,
+        ingress_bytes: devices.iter().map(|d| d.ingress_bytes).sum(),
+        egress_bytes: devices.iter().map(|d| d.egress_bytes).sum(),
+        devices,
+    }))
+}
+
+#[cfg(test)]
+#[path = "usage_test.rs"]
+mod usage_test;
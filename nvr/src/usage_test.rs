@@ -0,0 +1,108 @@
+use super::*;
+
+const BASE: i64 = 1_790_000_000_000;
+
+#[test]
+fn counter_restarts_never_go_negative() {
+    let mut deltas = Deltas::default();
+    let hour = hour_start(BASE);
+    let at = |minutes: i64| hour + minutes * 60_000;
+    let sample = |deltas: &mut Deltas, ingress: u64, egress: u64, now: i64| {
+        deltas.buckets(
+            [
+                ("cam".to_string(), Direction::Ingress, ingress),
+                ("cam".to_string(), Direction::Egress, egress),
+            ],
+            now,
+        )
+    };
+
+    // The first reading counts whole: the counter started with the server.
+    let first = sample(&mut deltas, 1_000, 200, at(1));
+    assert_eq!(
+        first,
+        vec![Usage {
+            device_id: "cam".into(),
+            start: hour,
+            ingress_bytes: 1_000,
+            egress_bytes: 200,
+        }]
+    );
+    let second = sample(&mut deltas, 1_500, 200, at(2));
+    assert_eq!((second[0].ingress_bytes, second[0].egress_bytes), (500, 0));
+
+    // The pipe restarted: 300 bytes since, not -1200.
+    let restarted = sample(&mut deltas, 300, 250, at(3));
+    assert_eq!(
+        (restarted[0].ingress_bytes, restarted[0].egress_bytes),
+        (300, 50)
+    );
+    let after = sample(&mut deltas, 400, 250, at(61));
+    assert_eq!(after[0].ingress_bytes, 100);
+    assert_eq!(after[0].start, hour + HOUR_MS);
+
+    // Nothing moved, nothing to add.
+    assert!(sample(&mut deltas, 400, 250, at(62)).is_empty());
+}
+
+#[test]
+fn days_and_months_follow_the_zone() {
+    let tz: Tz = "Asia/Shanghai".parse().unwrap();
+    // 2026-10-31 17:30 UTC is 2026-11-01 01:30 in Shanghai.
+    let ms = Utc
+        .with_ymd_and_hms(2026, 10, 31, 17, 30, 0)
+        .unwrap()
+        .timestamp_millis();
+    let (day, month) = day_and_month(ms, tz);
+    let midnight = Utc
+        .with_ymd_and_hms(2026, 10, 31, 16, 0, 0)
+        .unwrap()
+        .timestamp_millis();
+    assert_eq!(day, midnight);
+    assert_eq!(month, midnight);
+}
+
+#[tokio::test]
+async fn completed_hours_roll_up_and_old_ones_are_pruned() {
+    let _db = crate::db::test_db().await;
+    let mut conn = app_db_conn().unwrap();
+    let tz = chrono_tz::UTC;
+    let now = hour_start(BASE) + 30 * 60_000;
+    let hour = |ago: i64| hour_start(now) - ago * HOUR_MS;
+    let device = "usage-rollup-cam";
+    for (start, ingress) in [(hour(50), 10), (hour(2), 20), (hour(1), 30), (hour(0), 40)] {
+        let usage = Usage {
+            device_id: device.into(),
+            start,
+            ingress_bytes: ingress,
+            egress_bytes: 1,
+        };
+        nvr_db::usage::add_hour(&usage, &conn).await.unwrap();
+    }
+
+    let retention = Duration::from_secs(24 * 3600);
+    let (rolled, pruned) = roll_up(now, tz, retention, &mut conn).await.unwrap();
+    // The current hour is still filling up.
+    assert_eq!((rolled, pruned), (3, 1));
+    assert_eq!(
+        roll_up(now, tz, retention, &mut conn).await.unwrap(),
+        (0, 0)
+    );
+
+    let hours = nvr_db::usage::list_by_device(device, Granularity::Hour, 0, now, &conn)
+        .await
+        .unwrap();
+    assert_eq!(
+        hours.iter().map(|h| h.start).collect::<Vec<_>>(),
+        [hour(2), hour(1), hour(0)]
+    );
+    let months = nvr_db::usage::list_by_device(device, Granularity::Month, 0, now, &conn)
+        .await
+        .unwrap();
+    let total: i64 = months.iter().map(|m| m.ingress_bytes).sum();
+    assert_eq!(total, 60, "the pruned hour lives on in its month");
+    let days = nvr_db::usage::list_by_device(device, Granularity::Day, 0, now, &conn)
+        .await
+        .unwrap();
+    assert_eq!(days.iter().map(|d| d.egress_bytes).sum::<i64>(), 3);
+}
@@ -0,0 +1,361 @@
+//! Live viewer sessions and admission control. Every live playback through
+//! the `/media` proxy (see `crate::proxy`) is a session of one device:
+//!
+//! - a WebSocket (WS-FLV / WS-fMP4) or a long-lived HTTP-FLV / fMP4 / TS
+//!   response holds a [`ViewerGuard`], so the session ends with the socket or
+//!   response body however the client goes away;
+//! - HLS is a series of short requests, so a player's session is a heartbeat
+//!   refreshed by each playlist or segment fetch and ends [`HLS_IDLE`] after
+//!   the last one.
+//!
+//! A new session is admitted only under the limits of [`ViewerLimits`]
+//! (global, per device and per user), and not at all while the registry is
+//! closed (maintenance mode, see `crate::maintenance`); otherwise the proxy
+//! answers 503 with a [`Rejection`]. The counts feed the device list, and a device gaining its
+//! first viewer or losing its last emits a [`ViewerEvent`].
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::handler::BaseResponse;
+
+/// An HLS session ends this long after the player's last request; players
+/// re-fetch the playlist every segment (a few seconds).
+pub const HLS_IDLE: Duration = Duration::from_secs(30);
+/// Time between sweeps for ended HLS sessions.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+/// Capacity of the [`ViewerEvent`] broadcast.
+const EVENT_CHAN_CAP: usize = 64;
+
+/// Most concurrent live sessions; `None` is no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ViewerLimits {
+    /// Across all devices (`NVR_MAX_VIEWERS`).
+    pub global: Option<usize>,
+    /// Of each device (`NVR_MAX_VIEWERS_PER_DEVICE`).
+    pub per_device: Option<usize>,
+    /// Of each signed-in user (`NVR_MAX_VIEWERS_PER_USER`); anonymous
+    /// sessions only count against the other limits.
+    pub per_user: Option<usize>,
+}
+
+impl ViewerLimits {
+    /// The global, per-device and per-user caps from the `NVR_MAX_VIEWERS*`
+    /// values `get` returns. Unset, zero or unparsable values are no limit.
+    pub fn from_map(get: impl Fn(&str) -> Option<String>) -> ViewerLimits {
+        let limit = |key: &str| {
+            get(key)
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .filter(|n| *n > 0)
+        };
+        ViewerLimits {
+            global: limit("NVR_MAX_VIEWERS"),
+            per_device: limit("NVR_MAX_VIEWERS_PER_DEVICE"),
+            per_user: limit("NVR_MAX_VIEWERS_PER_USER"),
+        }
+    }
+
+    /// Parse from the real process environment.
+    pub fn from_env() -> ViewerLimits {
+        Self::from_map(|k| std::env::var(k).ok())
+    }
+}
+
+/// Which limit refused a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitScope {
+    Global,
+    Device,
+    User,
+    /// No new sessions at all: the server is in maintenance.
+    Maintenance,
+}
+
+/// A session refused for being over a limit: `current` sessions already
+/// count against its `max`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rejection {
+    pub scope: LimitScope,
+    pub current: usize,
+    pub max: usize,
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(BaseResponse {
+                code: 503,
+                message: match self.scope {
+                    LimitScope::Maintenance => "server in maintenance",
+                    _ => "too many viewers",
+                }
+                .to_string(),
+                data: Some(self),
+            }),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViewerEvent {
+    /// The device had no viewers and now has one.
+    FirstViewer { device_id: String },
+    /// The device's last viewer left.
+    LastViewerGone { device_id: String },
+}
+
+struct Session {
+    device_id: String,
+    user: Option<String>,
+    /// When a heartbeat session ends unless refreshed; `None` for sessions
+    /// held by a [`ViewerGuard`].
+    expires: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    next_id: u64,
+    by_id: HashMap<u64, Session>,
+    /// Heartbeat sessions by their client key.
+    heartbeats: HashMap<String, u64>,
+}
+
+impl Sessions {
+    fn of_device(&self, device_id: &str) -> usize {
+        self.by_id
+            .values()
+            .filter(|s| s.device_id == device_id)
+            .count()
+    }
+
+    fn check(
+        &self,
+        limits: &ViewerLimits,
+        device_id: &str,
+        user: Option<&str>,
+    ) -> Result<(), Rejection> {
+        let over = |scope, current, max: Option<usize>| match max {
+            Some(max) if current >= max => Err(Rejection {
+                scope,
+                current,
+                max,
+            }),
+            _ => Ok(()),
+        };
+        over(LimitScope::Global, self.by_id.len(), limits.global)?;
+        over(
+            LimitScope::Device,
+            self.of_device(device_id),
+            limits.per_device,
+        )?;
+        if let Some(user) = user {
+            let current = self
+                .by_id
+                .values()
+                .filter(|s| s.user.as_deref() == Some(user))
+                .count();
+            over(LimitScope::User, current, limits.per_user)?;
+        }
+        Ok(())
+    }
+}
+
+/// The live sessions of every device.
+pub struct Registry {
+    limits: ViewerLimits,
+    /// Refusing every new session; running ones go on.
+    closed: AtomicBool,
+    sessions: Mutex<Sessions>,
+    events: broadcast::Sender<ViewerEvent>,
+}
+
+impl Registry {
+    pub fn new(limits: ViewerLimits) -> Arc<Registry> {
+        Arc::new(Registry {
+            limits,
+            closed: AtomicBool::new(false),
+            sessions: Mutex::new(Sessions::default()),
+            events: broadcast::channel(EVENT_CHAN_CAP).0,
+        })
+    }
+
+    /// The process-wide registry, limited by the server config.
+    pub fn global() -> &'static Arc<Registry> {
+        static REGISTRY: LazyLock<Arc<Registry>> =
+            LazyLock::new(|| Registry::new(crate::config::config().viewer_limits()));
+        &REGISTRY
+    }
+
+    // For on-demand pipes that start with the first viewer and stop after
+    // the last; no subscriber yet (events are logged meanwhile).
+    #[allow(dead_code)]
+    pub fn subscribe(&self) -> broadcast::Receiver<ViewerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Start a session of `device_id` that lasts as long as the returned
+    /// guard.
+    pub fn admit(
+        self: &Arc<Self>,
+        device_id: &str,
+        user: Option<&str>,
+    ) -> Result<ViewerGuard, Rejection> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let id = self.start(&mut sessions, device_id, user, None)?;
+        Ok(ViewerGuard {
+            registry: Arc::clone(self),
+            id,
+        })
+    }
+
+    /// Refresh the heartbeat session `key` (one player's HLS session) of
+    /// `device_id`, starting it if there is none: it ends [`HLS_IDLE`] after
+    /// `now` unless refreshed again.
+    pub fn heartbeat(
+        &self,
+        key: &str,
+        device_id: &str,
+        user: Option<&str>,
+        now: Instant,
+    ) -> Result<(), Rejection> {
+        let expires = now + HLS_IDLE;
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(id) = sessions.heartbeats.get(key).copied()
+            && let Some(session) = sessions.by_id.get_mut(&id)
+        {
+            session.expires = Some(expires);
+            return Ok(());
+        }
+        let id = self.start(&mut sessions, device_id, user, Some(expires))?;
+        sessions.heartbeats.insert(key.to_string(), id);
+        Ok(())
+    }
+
+    /// End the heartbeat sessions not refreshed since [`HLS_IDLE`] before
+    /// `now`.
+    pub fn sweep(&self, now: Instant) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let expired = sessions
+            .by_id
+            .iter()
+            .filter(|(_, s)| s.expires.is_some_and(|at| at <= now))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            self.end(&mut sessions, id);
+        }
+    }
+
+    /// Live sessions of `device_id`.
+    pub fn count(&self, device_id: &str) -> usize {
+        self.sessions.lock().unwrap().of_device(device_id)
+    }
+
+    /// Live sessions of all devices.
+    pub fn total(&self) -> usize {
+        self.sessions.lock().unwrap().by_id.len()
+    }
+
+    /// Refuse (`true`) or admit again every new session. Open sessions, HLS
+    /// heartbeats included, are not affected.
+    pub fn set_closed(&self, closed: bool) {
+        self.closed.store(closed, Ordering::SeqCst);
+    }
+
+    fn start(
+        &self,
+        sessions: &mut Sessions,
+        device_id: &str,
+        user: Option<&str>,
+        expires: Option<Instant>,
+    ) -> Result<u64, Rejection> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Rejection {
+                scope: LimitScope::Maintenance,
+                current: sessions.by_id.len(),
+                max: 0,
+            });
+        }
+        sessions.check(&self.limits, device_id, user)?;
+        let first = sessions.of_device(device_id) == 0;
+        sessions.next_id += 1;
+        let id = sessions.next_id;
+        sessions.by_id.insert(
+            id,
+            Session {
+                device_id: device_id.to_string(),
+                user: user.map(str::to_string),
+                expires,
+            },
+        );
+        if first {
+            log::info!("viewers: {device_id} has its first viewer");
+            let _ = self.events.send(ViewerEvent::FirstViewer {
+                device_id: device_id.to_string(),
+            });
+        }
+        Ok(id)
+    }
+
+    fn end(&self, sessions: &mut Sessions, id: u64) {
+        let Some(session) = sessions.by_id.remove(&id) else {
+            return;
+        };
+        sessions.heartbeats.retain(|_, other| *other != id);
+        if sessions.of_device(&session.device_id) == 0 {
+            log::info!("viewers: {} lost its last viewer", session.device_id);
+            let _ = self.events.send(ViewerEvent::LastViewerGone {
+                device_id: session.device_id,
+            });
+        }
+    }
+}
+
+/// A connection-bound session; dropping it ends the session.
+pub struct ViewerGuard {
+    registry: Arc<Registry>,
+    id: u64,
+}
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        let mut sessions = self.registry.sessions.lock().unwrap();
+        self.registry.end(&mut sessions, self.id);
+    }
+}
+
+/// Live sessions of `device_id` in the process-wide registry.
+pub(crate) fn viewer_count(device_id: &str) -> usize {
+    Registry::global().count(device_id)
+}
+
+/// Sweep ended HLS sessions of the process-wide registry until `cancel`.
+pub fn spawn_worker(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tick.tick() => Registry::global().sweep(Instant::now()),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+#[path = "viewers_test.rs"]
+mod viewers_test;
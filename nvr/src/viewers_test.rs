@@ -0,0 +1,143 @@
+use super::*;
+
+fn per_device(max: usize) -> Arc<Registry> {
+    Registry::new(ViewerLimits {
+        per_device: Some(max),
+        ..ViewerLimits::default()
+    })
+}
+
+#[test]
+fn limits_parse_from_env() {
+    let env = HashMap::from([
+        ("NVR_MAX_VIEWERS", "50"),
+        ("NVR_MAX_VIEWERS_PER_DEVICE", " 4 "),
+        ("NVR_MAX_VIEWERS_PER_USER", "0"),
+    ]);
+    let limits = ViewerLimits::from_map(|k| env.get(k).map(|v| v.to_string()));
+    assert_eq!(
+        limits,
+        ViewerLimits {
+            global: Some(50),
+            per_device: Some(4),
+            per_user: None,
+        }
+    );
+    assert_eq!(ViewerLimits::from_map(|_| None), ViewerLimits::default());
+}
+
+#[test]
+fn device_limit_admits_after_a_session_closes() {
+    let registry = per_device(2);
+    let first = registry.admit("cam1", None).unwrap();
+    let _second = registry.admit("cam1", None).unwrap();
+    let third = registry.admit("cam1", None).err().unwrap();
+    assert_eq!(
+        third,
+        Rejection {
+            scope: LimitScope::Device,
+            current: 2,
+            max: 2,
+        }
+    );
+    // Other devices have their own count.
+    let _other = registry.admit("cam2", None).unwrap();
+    assert_eq!(registry.count("cam1"), 2);
+    assert_eq!(registry.count("cam2"), 1);
+
+    // The socket of the first session closes.
+    drop(first);
+    assert_eq!(registry.count("cam1"), 1);
+    let _fourth = registry.admit("cam1", None).unwrap();
+    assert_eq!(registry.count("cam1"), 2);
+}
+
+#[test]
+fn global_and_user_limits() {
+    let registry = Registry::new(ViewerLimits {
+        global: Some(3),
+        per_user: Some(1),
+        ..ViewerLimits::default()
+    });
+    let _alice = registry.admit("cam1", Some("alice")).unwrap();
+    let again = registry.admit("cam2", Some("alice")).err().unwrap();
+    assert_eq!(again.scope, LimitScope::User);
+    let _bob = registry.admit("cam1", Some("bob")).unwrap();
+    let _anonymous = registry.admit("cam2", None).unwrap();
+    let full = registry.admit("cam3", Some("carol")).err().unwrap();
+    assert_eq!(
+        full,
+        Rejection {
+            scope: LimitScope::Global,
+            current: 3,
+            max: 3,
+        }
+    );
+}
+
+#[test]
+fn hls_sessions_expire_without_heartbeats() {
+    let registry = per_device(2);
+    let start = Instant::now();
+    registry.heartbeat("a", "cam1", None, start).unwrap();
+    registry.heartbeat("b", "cam1", None, start).unwrap();
+    // A known player is refreshed, not counted again.
+    registry
+        .heartbeat("a", "cam1", None, start + Duration::from_secs(20))
+        .unwrap();
+    assert!(registry.heartbeat("c", "cam1", None, start).is_err());
+    assert_eq!(registry.count("cam1"), 2);
+
+    // Player b stopped fetching; a kept going.
+    registry.sweep(start + HLS_IDLE);
+    assert_eq!(registry.count("cam1"), 1);
+    registry
+        .heartbeat("c", "cam1", None, start + HLS_IDLE)
+        .unwrap();
+    assert_eq!(registry.count("cam1"), 2);
+}
+
+#[test]
+fn first_and_last_viewer_events() {
+    let registry = Registry::new(ViewerLimits::default());
+    let mut events = registry.subscribe();
+    let one = registry.admit("cam1", None).unwrap();
+    let two = registry.admit("cam1", None).unwrap();
+    drop(one);
+    drop(two);
+    assert_eq!(
+        events.try_recv().unwrap(),
+        ViewerEvent::FirstViewer {
+            device_id: "cam1".to_string()
+        }
+    );
+    assert_eq!(
+        events.try_recv().unwrap(),
+        ViewerEvent::LastViewerGone {
+            device_id: "cam1".to_string()
+        }
+    );
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn closed_registry_keeps_open_sessions_only() {
+    let registry = Registry::new(ViewerLimits::default());
+    let now = Instant::now();
+    let open = registry.admit("cam1", None).unwrap();
+    registry.heartbeat("hls", "cam1", None, now).unwrap();
+
+    registry.set_closed(true);
+    let rejection = registry.admit("cam2", None).unwrap_err();
+    assert_eq!(rejection.scope, LimitScope::Maintenance);
+    assert_eq!(rejection.current, 2);
+    assert!(registry.heartbeat("new", "cam1", None, now).is_err());
+    // A running HLS player keeps refreshing its session.
+    registry.heartbeat("hls", "cam1", None, now).unwrap();
+    assert_eq!(registry.total(), 2);
+
+    drop(open);
+    registry.set_closed(false);
+    let _again = registry.admit("cam2", None).unwrap();
+    assert_eq!(registry.total(), 2);
+}
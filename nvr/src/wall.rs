@@ -0,0 +1,542 @@
+//! `GET /api/groups/{id}/wall?cols=4&width=1920`: one JPEG showing every
+//! device of a group (the devices whose `group` is `id`), for control-room
+//! monitors.
+//!
+//! Tiles come from the warm thumbnails (see [`crate::thumbnail`]), so a wall
+//! never waits for a camera. Devices are laid out by name, `cols` per row
+//! (default 4, at most 8) in 16:9 cells filling `width` pixels (default 1920,
+//! at most 3840); rows that would make the image taller than 2160 pixels
+//! shrink the cells instead, and at most 64 devices are shown. Each tile
+//! carries the device name and a dot: green for a current thumbnail, amber
+//! for one older than two capture intervals. Devices that are offline, in
+//! privacy mode or without a thumbnail get a placeholder tile.
+//!
+//! Tiles are decoded, scaled and copied into the output one at a time, so
+//! memory stays at one canvas plus one thumbnail. The ETag covers the layout
+//! and every tile's state and capture time; it is known before anything is
+//! decoded, so polling clients get a 304 from a few `stat`s, and the last
+//! wall of each group is kept for clients without a cached copy.
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use axum::{
+    Router,
+    extract::{Path, Query},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use bytes::Bytes;
+use ffmpeg_bus::frame::{convert_video, pack_planes, scale_video, unpack_planes};
+use ffmpeg_next::format::Pixel;
+use serde::Deserialize;
+
+use crate::config::config;
+use crate::db::app_db_conn;
+
+const DEFAULT_COLS: u32 = 4;
+const MAX_COLS: u32 = 8;
+const DEFAULT_WIDTH: u32 = 1920;
+const MIN_WIDTH: u32 = 160;
+const MAX_WIDTH: u32 = 3840;
+const MAX_HEIGHT: u32 = 2160;
+const MAX_TILES: usize = 64;
+/// Thumbnails are never called stale before this age, even with a short (or
+/// disabled) capture interval.
+const MIN_STALE_AFTER: Duration = Duration::from_secs(60);
+
+const BACKGROUND: [u8; 3] = [16, 16, 16];
+const PLACEHOLDER: [u8; 3] = [48, 48, 48];
+const TEXT: [u8; 3] = [230, 230, 230];
+const LIVE: [u8; 3] = [46, 204, 64];
+const STALE: [u8; 3] = [240, 160, 32];
+const DOWN: [u8; 3] = [200, 48, 48];
+
+/// What a tile shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum TileState {
+    /// A current thumbnail.
+    Live,
+    /// A thumbnail older than the device's capture cadence explains.
+    Stale,
+    /// The device is not running.
+    Offline,
+    /// Running, but no thumbnail captured yet.
+    Missing,
+    /// In privacy mode.
+    Private,
+}
+
+impl TileState {
+    /// The state of a device's tile; `age` is that of its latest thumbnail.
+    pub(crate) fn of(
+        private: bool,
+        online: bool,
+        age: Option<Duration>,
+        stale_after: Duration,
+    ) -> TileState {
+        match age {
+            _ if private => TileState::Private,
+            _ if !online => TileState::Offline,
+            None => TileState::Missing,
+            Some(age) if age > stale_after => TileState::Stale,
+            Some(_) => TileState::Live,
+        }
+    }
+
+    fn shows_image(self) -> bool {
+        matches!(self, TileState::Live | TileState::Stale)
+    }
+
+    fn placeholder_text(self) -> &'static str {
+        match self {
+            TileState::Live | TileState::Stale | TileState::Missing => "NO IMAGE",
+            TileState::Offline => "OFFLINE",
+            TileState::Private => "PRIVACY",
+        }
+    }
+
+    fn dot(self) -> [u8; 3] {
+        match self {
+            TileState::Live => LIVE,
+            TileState::Stale | TileState::Missing => STALE,
+            TileState::Offline | TileState::Private => DOWN,
+        }
+    }
+}
+
+pub(crate) struct Tile {
+    pub name: String,
+    pub state: TileState,
+    /// The JPEG shown, read when the tile is drawn.
+    pub jpeg: Option<PathBuf>,
+}
+
+/// Cells of a wall: `cols` x `rows` tiles of `tile_w` x `tile_h`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Layout {
+    pub cols: u32,
+    pub rows: u32,
+    pub tile_w: u32,
+    pub tile_h: u32,
+}
+
+impl Layout {
+    /// The cells for `tiles` tiles, `cols` per row, about `width` wide, with
+    /// both clamped to the limits above and no empty columns.
+    pub(crate) fn new(tiles: usize, cols: u32, width: u32) -> Layout {
+        let tiles = tiles.clamp(1, MAX_TILES) as u32;
+        let cols = cols.clamp(1, MAX_COLS).min(tiles);
+        let rows = tiles.div_ceil(cols);
+        let width = width.clamp(MIN_WIDTH, MAX_WIDTH);
+        // Even sizes, for the 4:2:0 chroma planes of the encoded image.
+        let mut tile_w = (width / cols) & !1;
+        let mut tile_h = (tile_w * 9 / 16) & !1;
+        if tile_h * rows > MAX_HEIGHT {
+            tile_h = ((MAX_HEIGHT / rows) & !1).max(2);
+            tile_w = ((tile_h * 16 / 9) & !1).max(2);
+        }
+        Layout {
+            cols,
+            rows,
+            tile_w,
+            tile_h,
+        }
+    }
+
+    pub(crate) fn width(&self) -> u32 {
+        self.cols * self.tile_w
+    }
+
+    pub(crate) fn height(&self) -> u32 {
+        self.rows * self.tile_h
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        (self.cols * self.rows) as usize
+    }
+
+    /// Top-left corner of tile `i`.
+    pub(crate) fn origin(&self, i: usize) -> (u32, u32) {
+        let i = i as u32;
+        ((i % self.cols) * self.tile_w, (i / self.cols) * self.tile_h)
+    }
+
+    /// Sizes of the name bar along the bottom of each tile.
+    fn chrome(&self) -> Chrome {
+        let scale = (self.tile_h / 90).max(1);
+        let pad = 2 * scale;
+        let bar_h = (7 * scale + 2 * pad).min(self.tile_h);
+        Chrome {
+            scale,
+            pad,
+            bar_h,
+            dot_r: 3 * scale,
+        }
+    }
+}
+
+struct Chrome {
+    /// Pixels per font dot.
+    scale: u32,
+    pad: u32,
+    bar_h: u32,
+    dot_r: u32,
+}
+
+impl Chrome {
+    /// Centre of the state dot, relative to the tile origin.
+    fn dot_centre(&self, layout: &Layout) -> (u32, u32) {
+        (
+            layout.tile_w.saturating_sub(self.pad + self.dot_r + 1),
+            layout.tile_h - self.bar_h / 2 - 1,
+        )
+    }
+}
+
+/// A packed RGB24 image of a whole wall.
+pub(crate) struct Canvas {
+    layout: Layout,
+    rgb: Vec<u8>,
+}
+
+/// Draw `tiles` into a new canvas in order; tiles beyond the layout's
+/// capacity are ignored. A tile whose picture cannot be read or decoded gets
+/// a placeholder.
+pub(crate) fn compose(layout: Layout, tiles: impl IntoIterator<Item = Tile>) -> Canvas {
+    let mut canvas = Canvas::new(layout);
+    for (i, tile) in tiles.into_iter().take(layout.capacity()).enumerate() {
+        canvas.draw(i, tile);
+    }
+    canvas
+}
+
+impl Canvas {
+    fn new(layout: Layout) -> Canvas {
+        let pixels = (layout.width() * layout.height()) as usize;
+        Canvas {
+            layout,
+            rgb: BACKGROUND.repeat(pixels),
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, color: [u8; 3]) {
+        let at = ((y * self.layout.width() + x) * 3) as usize;
+        self.rgb[at..at + 3].copy_from_slice(&color);
+    }
+
+    /// Darken a rectangle to a third, so text over a picture stays readable.
+    fn shade(&mut self, x: u32, y: u32, w: u32, h: u32) {
+        for row in y..y + h {
+            let start = ((row * self.layout.width() + x) * 3) as usize;
+            for v in &mut self.rgb[start..start + (w * 3) as usize] {
+                *v /= 3;
+            }
+        }
+    }
+
+    /// Copy a packed RGB24 `w` x `h` image to (`x`, `y`).
+    fn blit(&mut self, x: u32, y: u32, rgb: &[u8], w: u32, h: u32) {
+        let row_len = (w * 3) as usize;
+        for (row, src) in rgb.chunks_exact(row_len).take(h as usize).enumerate() {
+            let start = (((y + row as u32) * self.layout.width() + x) * 3) as usize;
+            self.rgb[start..start + row_len].copy_from_slice(src);
+        }
+    }
+
+    fn disc(&mut self, cx: u32, cy: u32, r: u32, color: [u8; 3]) {
+        let r2 = (r * r) as i64;
+        for y in cy.saturating_sub(r)..=cy + r {
+            for x in cx.saturating_sub(r)..=cx + r {
+                let (dx, dy) = (x as i64 - cx as i64, y as i64 - cy as i64);
+                if dx * dx + dy * dy <= r2 {
+                    self.put(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// The canvas as a clipped drawing surface.
+    fn pen(&mut self) -> crate::canvas::Canvas<'_> {
+        crate::canvas::Canvas {
+            w: self.layout.width(),
+            h: self.layout.height(),
+            rgb: &mut self.rgb,
+        }
+    }
+
+    fn draw(&mut self, i: usize, tile: Tile) {
+        let layout = self.layout;
+        let (x0, y0) = layout.origin(i);
+        let (tw, th) = (layout.tile_w, layout.tile_h);
+        let chrome = layout.chrome();
+
+        let mut state = tile.state;
+        let image = if state.shows_image() {
+            load(tile.jpeg, tw, th)
+                .inspect_err(|e| log::debug!("wall: tile {:?} not drawn: {e:#}", tile.name))
+                .ok()
+        } else {
+            None
+        };
+        match image {
+            Some((rgb, w, h)) => self.blit(x0 + (tw - w) / 2, y0 + (th - h) / 2, &rgb, w, h),
+            None => {
+                if state.shows_image() {
+                    state = TileState::Missing;
+                }
+                self.pen()
+                    .fill(x0.into(), y0.into(), tw.into(), th.into(), PLACEHOLDER);
+                let text = state.placeholder_text();
+                let chars = text.chars().count() as u32;
+                let room = tw.saturating_sub(2 * chrome.pad);
+                let scale = (th / 40).min(room / (6 * chars)).max(1);
+                let text_w = (6 * chars - 1) * scale;
+                let left = x0 + tw.saturating_sub(text_w) / 2;
+                let top = y0 + (th - chrome.bar_h).saturating_sub(7 * scale) / 2;
+                self.pen().text(
+                    left.into(),
+                    top.into(),
+                    text,
+                    scale.into(),
+                    room.into(),
+                    TEXT,
+                );
+            }
+        }
+
+        let bar_y = y0 + th - chrome.bar_h;
+        self.shade(x0, bar_y, tw, chrome.bar_h);
+        let name_w = tw.saturating_sub(3 * chrome.pad + 2 * chrome.dot_r);
+        let name = tile.name.to_uppercase();
+        self.pen().text(
+            (x0 + chrome.pad).into(),
+            (bar_y + chrome.pad).into(),
+            &name,
+            chrome.scale.into(),
+            name_w.into(),
+            TEXT,
+        );
+        let (dx, dy) = chrome.dot_centre(&layout);
+        self.disc(x0 + dx, y0 + dy, chrome.dot_r, state.dot());
+    }
+
+    /// Encode the canvas as a JPEG.
+    pub(crate) fn to_jpeg(&self) -> anyhow::Result<Vec<u8>> {
+        let (w, h) = (self.layout.width(), self.layout.height());
+        let rgb = unpack_planes(&self.rgb, Pixel::RGB24, w, h)?;
+        // The MJPEG encoder takes full-range YUV.
+        crate::snapshot::encode_jpeg(convert_video(&rgb, Pixel::YUVJ420P)?)
+    }
+}
+
+/// A tile's picture as packed RGB24, scaled to fit `tw` x `th` keeping its
+/// aspect ratio.
+fn load(jpeg: Option<PathBuf>, tw: u32, th: u32) -> anyhow::Result<(Vec<u8>, u32, u32)> {
+    let path = jpeg.ok_or_else(|| anyhow::anyhow!("no picture"))?;
+    let frame = decode_jpeg(&std::fs::read(&path)?)?;
+    let (w, h) = fit(frame.width(), frame.height(), tw, th);
+    let scaled = scale_video(&frame, Pixel::RGB24, w, h)?;
+    Ok((pack_planes(&scaled)?, w, h))
+}
+
+/// The largest size of a `w` x `h` picture's aspect ratio within `tw` x `th`.
+fn fit(w: u32, h: u32, tw: u32, th: u32) -> (u32, u32) {
+    let (w, h) = (u64::from(w.max(1)), u64::from(h.max(1)));
+    let (tw, th) = (u64::from(tw), u64::from(th));
+    if w * th > h * tw {
+        (tw as u32, (h * tw / w).clamp(1, th) as u32)
+    } else {
+        ((w * th / h).clamp(1, tw) as u32, th as u32)
+    }
+}
+
+/// Decode one JPEG image.
+pub(crate) fn decode_jpeg(jpeg: &[u8]) -> anyhow::Result<ffmpeg_next::frame::Video> {
+    let codec = ffmpeg_next::decoder::find(ffmpeg_next::codec::Id::MJPEG)
+        .ok_or_else(|| anyhow::anyhow!("mjpeg decoder not available"))?;
+    let mut decoder = ffmpeg_next::codec::Context::new_with_codec(codec)
+        .decoder()
+        .video()?;
+    decoder.send_packet(&ffmpeg_next::Packet::copy(jpeg))?;
+    decoder.send_eof()?;
+    let mut frame = ffmpeg_next::frame::Video::empty();
+    decoder.receive_frame(&mut frame)?;
+    Ok(frame)
+}
+
+pub fn wall_router() -> Router {
+    Router::new().route("/{id}/wall", get(wall))
+}
+
+#[derive(Debug, Deserialize)]
+struct WallQuery {
+    cols: Option<u32>,
+    width: Option<u32>,
+}
+
+/// One device's tile, resolved before anything is decoded.
+#[derive(Hash)]
+struct Entry {
+    id: String,
+    name: String,
+    state: TileState,
+    path: Option<PathBuf>,
+    /// Capture time of the thumbnail shown, unix milliseconds.
+    captured_ms: Option<i64>,
+}
+
+/// The last wall served per group, with its ETag.
+static RENDERED: LazyLock<Mutex<HashMap<String, (String, Bytes)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+async fn wall(
+    Path(id): Path<String>,
+    Query(query): Query<WallQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let devices = match group_devices(&id).await {
+        Ok(devices) => devices,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("list devices: {e:#}"),
+            )
+                .into_response();
+        }
+    };
+    if devices.is_empty() {
+        return (StatusCode::NOT_FOUND, format!("no devices in group {id}")).into_response();
+    }
+    let omitted = devices.len().saturating_sub(MAX_TILES);
+    let layout = Layout::new(
+        devices.len(),
+        query.cols.unwrap_or(DEFAULT_COLS),
+        query.width.unwrap_or(DEFAULT_WIDTH),
+    );
+
+    let cfg = config().thumbnail();
+    let stale_after = (cfg.interval * 2).max(MIN_STALE_AFTER);
+    let mut entries = Vec::new();
+    for (device_id, name) in devices.into_iter().take(MAX_TILES) {
+        let private = crate::privacy::is_private(&device_id);
+        let online = crate::manager::status(&device_id).await == Some(true);
+        let path = cfg.latest_path(&device_id);
+        let captured_at = match &path {
+            Some(path) if !private && online => tokio::fs::metadata(path)
+                .await
+                .and_then(|m| m.modified())
+                .ok(),
+            _ => None,
+        };
+        let age = captured_at.map(|at| at.elapsed().unwrap_or_default());
+        let state = TileState::of(private, online, age, stale_after);
+        entries.push(Entry {
+            id: device_id,
+            name,
+            state,
+            path: path.filter(|_| state.shows_image()),
+            captured_ms: captured_at
+                .filter(|_| state.shows_image())
+                .map(crate::thumbnail::unix_ms),
+        });
+    }
+
+    let mut hasher = DefaultHasher::new();
+    (layout, &entries).hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+    let newest = entries.iter().filter_map(|e| e.captured_ms).max();
+
+    let mut meta = HeaderMap::new();
+    meta.insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).expect("ascii etag"),
+    );
+    meta.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    if let Some(last_modified) = newest
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .map(|at| at.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+    {
+        meta.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&last_modified).expect("ascii date"),
+        );
+    }
+    if omitted > 0 {
+        meta.insert(
+            header::HeaderName::from_static("x-wall-omitted"),
+            HeaderValue::from(omitted),
+        );
+    }
+
+    let matches = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if matches {
+        return (StatusCode::NOT_MODIFIED, meta).into_response();
+    }
+    meta.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/jpeg"));
+    let cached = RENDERED
+        .lock()
+        .unwrap()
+        .get(&id)
+        .filter(|(tag, _)| *tag == etag)
+        .map(|(_, jpeg)| jpeg.clone());
+    if let Some(jpeg) = cached {
+        return (meta, jpeg).into_response();
+    }
+
+    // A thumbnail replaced after the stat above shows up here under the old
+    // ETag; the next poll sees the new capture time and renders again.
+    let tiles = entries
+        .into_iter()
+        .map(|e| Tile {
+            name: e.name,
+            state: e.state,
+            jpeg: e.path,
+        })
+        .collect::<Vec<_>>();
+    let jpeg = match tokio::task::spawn_blocking(move || compose(layout, tiles).to_jpeg()).await {
+        Ok(Ok(jpeg)) => Bytes::from(jpeg),
+        Ok(Err(e)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("wall encode failed: {e:#}"),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("wall task failed: {e}"),
+            )
+                .into_response();
+        }
+    };
+    RENDERED.lock().unwrap().insert(id, (etag, jpeg.clone()));
+    (meta, jpeg).into_response()
+}
+
+/// Ids and names of the devices in group `id`, by name.
+async fn group_devices(id: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut devices = nvr_db::device::list(&app_db_conn()?)
+        .await?
+        .into_iter()
+        .filter(|d| !id.is_empty() && d.group == id)
+        .map(|d| (d.id, d.name))
+        .collect::<Vec<_>>();
+    devices.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(devices)
+}
+
+#[cfg(test)]
+#[path = "wall_test.rs"]
+mod wall_test;
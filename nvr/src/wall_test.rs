@@ -0,0 +1,160 @@
+use std::path::Path;
+
+use ffmpeg_bus::frame::RawVideoFrame;
+
+use super::*;
+
+const RED: [u8; 3] = [220, 30, 30];
+const GREEN: [u8; 3] = [30, 200, 60];
+const BLUE: [u8; 3] = [40, 60, 220];
+
+/// A `w` x `h` JPEG of one color, written to `dir/name`.
+fn solid_jpeg(dir: &Path, name: &str, color: [u8; 3], w: u32, h: u32) -> PathBuf {
+    let rgb = unpack_planes(&color.repeat((w * h) as usize), Pixel::RGB24, w, h).unwrap();
+    let jpeg = crate::snapshot::to_jpeg(&RawVideoFrame::from(rgb)).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, jpeg).unwrap();
+    path
+}
+
+fn pixel(canvas: &Canvas, x: u32, y: u32) -> [u8; 3] {
+    let at = ((y * canvas.layout.width() + x) * 3) as usize;
+    [canvas.rgb[at], canvas.rgb[at + 1], canvas.rgb[at + 2]]
+}
+
+#[track_caller]
+fn assert_near(got: [u8; 3], want: [u8; 3]) {
+    let close = got.iter().zip(want).all(|(g, w)| g.abs_diff(w) <= 12);
+    assert!(close, "got {got:?}, want about {want:?}");
+}
+
+fn tile(name: &str, state: TileState, jpeg: Option<PathBuf>) -> Tile {
+    Tile {
+        name: name.to_string(),
+        state,
+        jpeg,
+    }
+}
+
+#[test]
+fn layout_clamps_columns_and_caps_the_size() {
+    let layout = Layout::new(3, 4, 1920);
+    assert_eq!((layout.cols, layout.rows), (3, 1));
+    assert_eq!((layout.tile_w, layout.tile_h), (640, 360));
+    assert_eq!((layout.width(), layout.height()), (1920, 360));
+
+    let layout = Layout::new(10, 4, 1920);
+    assert_eq!((layout.cols, layout.rows), (4, 3));
+    assert_eq!((layout.tile_w, layout.tile_h), (480, 270));
+    assert_eq!(layout.origin(5), (480, 270));
+
+    // Too many rows for the height cap: the cells shrink.
+    let layout = Layout::new(1000, 1, 100_000);
+    assert_eq!((layout.cols, layout.rows), (1, MAX_TILES as u32));
+    assert!(layout.width() <= MAX_WIDTH && layout.height() <= MAX_HEIGHT);
+    assert_eq!(layout.tile_h % 2, 0);
+
+    let layout = Layout::new(2, 0, 0);
+    assert_eq!((layout.cols, layout.width()), (1, MIN_WIDTH));
+}
+
+#[test]
+fn tile_state_follows_privacy_status_and_age() {
+    let after = Duration::from_secs(60);
+    let fresh = Some(Duration::from_secs(5));
+    let old = Some(Duration::from_secs(61));
+    assert_eq!(TileState::of(false, true, fresh, after), TileState::Live);
+    assert_eq!(TileState::of(false, true, old, after), TileState::Stale);
+    assert_eq!(TileState::of(false, true, None, after), TileState::Missing);
+    assert_eq!(
+        TileState::of(false, false, fresh, after),
+        TileState::Offline
+    );
+    assert_eq!(TileState::of(true, true, fresh, after), TileState::Private);
+}
+
+#[test]
+fn three_frames_land_in_their_tiles() {
+    ffmpeg_bus::init().unwrap();
+    let dir = std::env::temp_dir().join(format!("nvr-wall-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // Exactly the tile size, smaller (scaled up) and 4:3 (letterboxed).
+    let red = solid_jpeg(&dir, "red.jpg", RED, 320, 180);
+    let green = solid_jpeg(&dir, "green.jpg", GREEN, 160, 90);
+    let blue = solid_jpeg(&dir, "blue.jpg", BLUE, 640, 480);
+
+    let layout = Layout::new(3, 2, 640);
+    assert_eq!((layout.tile_w, layout.tile_h), (320, 180));
+    let canvas = compose(
+        layout,
+        [
+            tile("red", TileState::Live, Some(red)),
+            tile("green", TileState::Stale, Some(green)),
+            tile("blue", TileState::Live, Some(blue)),
+        ],
+    );
+    assert_eq!((layout.width(), layout.height()), (640, 360));
+
+    assert_near(pixel(&canvas, 160, 90), RED);
+    assert_near(pixel(&canvas, 10, 10), RED);
+    assert_near(pixel(&canvas, 480, 90), GREEN);
+    assert_near(pixel(&canvas, 630, 10), GREEN);
+    assert_near(pixel(&canvas, 160, 270), BLUE);
+    // The 4:3 frame is 240 wide, centred: bars left and right.
+    assert_eq!(pixel(&canvas, 10, 270), BACKGROUND);
+    assert_eq!(pixel(&canvas, 310, 270), BACKGROUND);
+    // The fourth cell is empty.
+    assert_eq!(pixel(&canvas, 480, 270), BACKGROUND);
+
+    // Each tile's dot tells its state.
+    let (dx, dy) = layout.chrome().dot_centre(&layout);
+    assert_eq!(pixel(&canvas, dx, dy), LIVE);
+    assert_eq!(pixel(&canvas, 320 + dx, dy), STALE);
+
+    // The encoded wall keeps the tiles where they are.
+    let decoded = decode_jpeg(&canvas.to_jpeg().unwrap()).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (640, 360));
+    let rgb = pack_planes(&convert_video(&decoded, Pixel::RGB24).unwrap()).unwrap();
+    let at = |x: usize, y: usize| {
+        let i = (y * 640 + x) * 3;
+        [rgb[i], rgb[i + 1], rgb[i + 2]]
+    };
+    assert_near(at(160, 90), RED);
+    assert_near(at(480, 90), GREEN);
+    assert_near(at(160, 270), BLUE);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unavailable_devices_get_placeholders() {
+    ffmpeg_bus::init().unwrap();
+    let layout = Layout::new(3, 3, 960);
+    let canvas = compose(
+        layout,
+        [
+            tile("down", TileState::Offline, None),
+            tile("private", TileState::Private, None),
+            // Claims a picture that is not there.
+            tile(
+                "broken",
+                TileState::Live,
+                Some(PathBuf::from("/nonexistent/latest.jpg")),
+            ),
+        ],
+    );
+    let (dx, dy) = layout.chrome().dot_centre(&layout);
+    for i in 0..3 {
+        let (x, y) = layout.origin(i);
+        assert_eq!(pixel(&canvas, x + 2, y + 2), PLACEHOLDER, "tile {i}");
+    }
+    assert_eq!(pixel(&canvas, dx, dy), DOWN);
+    assert_eq!(pixel(&canvas, 320 + dx, dy), DOWN);
+    assert_eq!(pixel(&canvas, 640 + dx, dy), STALE);
+
+    // Placeholder text is drawn somewhere in the middle of the tile.
+    let text = (0..layout.tile_w)
+        .flat_map(|x| (0..layout.tile_h / 2 + 20).map(move |y| (x, y)))
+        .any(|(x, y)| pixel(&canvas, x, y) == TEXT);
+    assert!(text);
+}
@@ -0,0 +1,256 @@
+//! `GET /api/v1/device/{id}/ws-preview?fps=5&quality=70&width=480`: a
+//! device's live picture pushed over a WebSocket, one binary message per
+//! JPEG, for dashboards whose browser has no MSE to play `live.mp4` with.
+//!
+//! Each connection adds a Raw output of its own to the device's bus, read
+//! through a queue of two frames that drops the oldest, so a slow socket
+//! skips pictures instead of holding the decoder back. Pictures start at the
+//! first keyframe and come at most `fps` a second (default 5, at most 15);
+//! the frames in between are skipped. Each is converted on a blocking thread,
+//! downscaled to at most `width` pixels wide (160 to 1920; the source size
+//! without it) by a scaler kept while the source size and format stay the
+//! same, and encoded at `quality` (1 to 100, default 70). The output is
+//! removed when the socket closes or the stream ends.
+//!
+//! Like [`crate::mjpeg`], each connection is a viewer session under the
+//! limits of [`crate::viewers`] (503 when over one) and its bytes count as
+//! the device's egress; a device in privacy mode gets the placeholder image
+//! instead of the upgrade.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    Extension,
+    extract::{
+        Path, Query,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use ffmpeg_bus::bus::{FrameEvent, OutputAvType, OutputConfig, OutputDest, VideoRawFrameStream};
+use ffmpeg_bus::frame::VideoFrame;
+use ffmpeg_bus::frame_hub::{DropPolicy, FrameQueueConfig};
+use ffmpeg_bus::scaler::ScalerCache;
+use ffmpeg_next::format::Pixel;
+use futures::StreamExt;
+use media_pipe_core::Pipe;
+use serde::Deserialize;
+
+use crate::auth::AuthUser;
+use crate::snapshot::scaled_size;
+use crate::usage::EgressMeter;
+use crate::viewers::Registry;
+
+const DEFAULT_FPS: u32 = 5;
+const MAX_FPS: u32 = 15;
+const DEFAULT_QUALITY: u8 = 70;
+const MIN_WIDTH: u32 = 160;
+const MAX_WIDTH: u32 = 1920;
+/// Decoded frames held for a connection busy converting or sending.
+const FRAME_QUEUE: usize = 2;
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct PreviewQuery {
+    /// Pictures per second, 1 to 15.
+    fps: Option<u32>,
+    /// JPEG quality, 1 to 100.
+    quality: Option<u8>,
+    /// Widest picture in pixels, 160 to 1920; smaller sources keep their
+    /// size. The source size without it.
+    width: Option<u32>,
+}
+
+/// A connection's Raw output, removed from the pipe when this is dropped
+/// (the socket closed, or the upgrade never happened).
+struct PreviewOutput {
+    pipe: Arc<Pipe>,
+    id: String,
+}
+
+impl PreviewOutput {
+    async fn add(pipe: Arc<Pipe>) -> anyhow::Result<(Self, VideoRawFrameStream)> {
+        let id = format!("ws-preview-{}", uuid::Uuid::new_v4().simple());
+        let output = OutputConfig::new(id.clone(), OutputAvType::Video, OutputDest::Raw)
+            .with_frame_queue(FrameQueueConfig {
+                capacity: FRAME_QUEUE,
+                policy: DropPolicy::DropOldest,
+            });
+        let (_, stream) = pipe.add_output(output).await?;
+        Ok((Self { pipe, id }, stream.into_video()?))
+    }
+}
+
+impl Drop for PreviewOutput {
+    fn drop(&mut self) {
+        let pipe = self.pipe.clone();
+        let id = std::mem::take(&mut self.id);
+        tokio::spawn(async move {
+            if let Err(e) = pipe.remove_output(&id).await {
+                log::debug!("ws-preview: remove output {id}: {e:#}");
+            }
+        });
+    }
+}
+
+/// Turns decoded frames into JPEGs, keeping the scaler of the last source
+/// size and format.
+pub(crate) struct Converter {
+    width: Option<u32>,
+    quality: u8,
+    scalers: ScalerCache,
+}
+
+impl Converter {
+    pub(crate) fn new(width: Option<u32>, quality: u8) -> Self {
+        Self {
+            width,
+            quality,
+            scalers: ScalerCache::new(1),
+        }
+    }
+
+    pub(crate) fn jpeg(&mut self, frame: &VideoFrame) -> anyhow::Result<Vec<u8>> {
+        if frame.width == 0 || frame.height == 0 {
+            anyhow::bail!("zero-sized frame");
+        }
+        let source = frame.to_video()?;
+        let (w, h) = match self.width {
+            Some(width) => scaled_size(source.width(), source.height(), width),
+            None => (source.width(), source.height()),
+        };
+        // The MJPEG encoder takes full-range YUV.
+        let yuv = self.scalers.scale(&source, Pixel::YUVJ420P, w, h)?;
+        ffmpeg_bus::snapshot::encode_jpeg(yuv, self.quality)
+    }
+
+    /// The source the scaler was built for, for tests.
+    #[cfg(test)]
+    pub(crate) fn scaler_input(&self) -> Option<(Pixel, u32, u32)> {
+        self.scalers.keys().next().map(|key| key.src)
+    }
+}
+
+/// The device's live picture as JPEG messages on a WebSocket.
+#[utoipa::path(
+    get,
+    path = "/{id}/ws-preview",
+    tag = "device",
+    params(("id" = String, Path), PreviewQuery),
+    responses(
+        (status = 101, description = "Upgraded; one binary message per JPEG"),
+        (status = 404, description = "No such device running, or it has no video", body = String),
+        (status = 503, description = "Over a viewer limit"),
+    )
+)]
+pub(crate) async fn ws_preview(
+    ws: WebSocketUpgrade,
+    Path(id): Path<String>,
+    Query(query): Query<PreviewQuery>,
+    user: Option<Extension<AuthUser>>,
+) -> Response {
+    if crate::privacy::is_private(&id) {
+        return crate::snapshot::privacy_response();
+    }
+    let fps = query.fps.unwrap_or(DEFAULT_FPS).clamp(1, MAX_FPS);
+    let quality = query.quality.unwrap_or(DEFAULT_QUALITY).clamp(1, 100);
+    let width = query.width.map(|w| w.clamp(MIN_WIDTH, MAX_WIDTH));
+    let user = user.map(|Extension(user)| user.username);
+    let viewer = match Registry::global().admit(&id, user.as_deref()) {
+        Ok(viewer) => viewer,
+        Err(rejection) => {
+            log::info!("ws-preview[{id}]: refused: {rejection:?}");
+            return rejection.into_response();
+        }
+    };
+    // Held by the socket task, so an on-demand pipe runs while it is open.
+    let Some(handle) = crate::manager::acquire_pipe(&id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("no video for {id}: pipe not found"),
+        )
+            .into_response();
+    };
+    let (output, frames) = match PreviewOutput::add(handle.pipe().clone()).await {
+        Ok(added) => added,
+        Err(e) => {
+            return (StatusCode::NOT_FOUND, format!("no video for {id}: {e:#}")).into_response();
+        }
+    };
+    let meter = crate::usage::egress_meter(&id);
+    ws.on_upgrade(move |socket| async move {
+        let _viewer = viewer;
+        let _handle = handle;
+        let _output = output;
+        let converter = Converter::new(width, quality);
+        if let Err(e) = push(socket, frames, converter, fps, meter).await {
+            log::debug!("ws-preview[{id}]: ended: {e:#}");
+        }
+    })
+}
+
+/// Send a JPEG of at most `fps` frames a second of `frames` until the client
+/// goes away or the stream ends.
+async fn push(
+    mut socket: WebSocket,
+    mut frames: VideoRawFrameStream,
+    converter: Converter,
+    fps: u32,
+    meter: EgressMeter,
+) -> anyhow::Result<()> {
+    let interval = Duration::from_secs(1) / fps;
+    let mut converter = Some(converter);
+    let mut seen_key = false;
+    let mut last: Option<Instant> = None;
+    loop {
+        let frame = tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+            },
+            frame = frames.next() => match frame {
+                Some(FrameEvent::Frame(frame)) => frame,
+                // Decoded frames need no keyframe to follow a gap.
+                Some(FrameEvent::Lagged(_) | FrameEvent::Error(_)) => continue,
+                Some(FrameEvent::Eof) | None => break,
+            },
+        };
+        if !seen_key {
+            if !frame.is_key {
+                continue; // frames decoded before a keyframe may be garbage
+            }
+            seen_key = true;
+        }
+        let now = Instant::now();
+        if last.is_some_and(|l| now.duration_since(l) < interval) {
+            continue;
+        }
+        last = Some(now);
+        let mut working = converter
+            .take()
+            .expect("converter is back after each frame");
+        let (back, jpeg) = tokio::task::spawn_blocking(move || {
+            let jpeg = working.jpeg(&frame);
+            (working, jpeg)
+        })
+        .await?;
+        converter = Some(back);
+        match jpeg {
+            Ok(jpeg) => {
+                meter.add(jpeg.len() as u64);
+                socket.send(Message::Binary(jpeg.into())).await?;
+            }
+            Err(e) => log::debug!("ws-preview: frame not encoded: {e:#}"),
+        }
+    }
+    // The device stopped.
+    let _ = socket.send(Message::Close(None)).await;
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "ws_preview_test.rs"]
+mod ws_preview_test;
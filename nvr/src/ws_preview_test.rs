@@ -0,0 +1,104 @@
+use axum::{Router, routing::get};
+use ffmpeg_bus::fixture::{FixtureSpec, ensure_fixture};
+use ffmpeg_bus::frame::pack_planes;
+use media_pipe_core::{InputConfig, PipeConfig};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite;
+
+use super::*;
+
+fn frame(width: u32, height: u32) -> VideoFrame {
+    let mut frame = ffmpeg_next::frame::Video::new(Pixel::YUV420P, width, height);
+    for plane in 0..3 {
+        frame.data_mut(plane).fill(128);
+    }
+    let data = pack_planes(&frame).unwrap();
+    let format = ffmpeg_next::ffi::AVPixelFormat::from(Pixel::YUV420P) as i32;
+    VideoFrame::new(data, width, height, format, 0, 0, true, 0)
+}
+
+fn is_jpeg(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8]) && data.ends_with(&[0xFF, 0xD9])
+}
+
+#[test]
+fn converter_keeps_its_scaler_while_the_source_stays_the_same() {
+    let mut converter = Converter::new(Some(320), 70);
+    assert!(is_jpeg(&converter.jpeg(&frame(640, 360)).unwrap()));
+    assert_eq!(converter.scaler_input(), Some((Pixel::YUV420P, 640, 360)));
+    assert!(is_jpeg(&converter.jpeg(&frame(640, 360)).unwrap()));
+    // A new source size gets a scaler of its own.
+    assert!(is_jpeg(&converter.jpeg(&frame(1280, 720)).unwrap()));
+    assert_eq!(converter.scaler_input(), Some((Pixel::YUV420P, 1280, 720)));
+    assert!(converter.jpeg(&VideoFrame::default()).is_err());
+}
+
+/// The Raw outputs the preview added to `id`'s pipe.
+async fn preview_outputs(id: &str) -> usize {
+    let pipe = crate::manager::get_pipe(id).await.unwrap();
+    let stats = pipe.stats().await.unwrap_or_default();
+    let outputs = stats.outputs.iter();
+    outputs.filter(|o| o.id.starts_with("ws-preview-")).count()
+}
+
+#[tokio::test]
+async fn file_device_pushes_jpegs_until_the_socket_closes() {
+    // The maintenance test closes the viewer registry this session needs.
+    let _db = crate::db::test_db().await;
+    let path = ensure_fixture(&FixtureSpec::default()).await.unwrap();
+    let id = "ws-preview-test-cam";
+    crate::manager::add_pipe(
+        id,
+        PipeConfig {
+            input: InputConfig::FileLoop {
+                path: path.to_string_lossy().into_owned(),
+                realtime: true,
+            },
+            outputs: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let app = Router::new().route("/{id}/ws-preview", get(ws_preview));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    let url = format!("ws://{addr}/{id}/ws-preview?fps=10&width=320&quality=60");
+    crate::manager::wait_running(id).await;
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url)
+        .await
+        .expect("upgrade refused");
+    assert_eq!(preview_outputs(id).await, 1);
+
+    let mut jpegs = Vec::new();
+    let read = async {
+        while jpegs.len() < 3 {
+            match socket.next().await.expect("socket closed").unwrap() {
+                tungstenite::Message::Binary(data) => jpegs.push(data),
+                other => panic!("unexpected message {other:?}"),
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), read)
+        .await
+        .expect("no pictures");
+    for jpeg in &jpegs {
+        assert!(is_jpeg(jpeg), "not a JPEG (SOI/EOI markers)");
+    }
+
+    // Closing the socket detaches the output.
+    socket.close(None).await.unwrap();
+    let mut detached = false;
+    for _ in 0..50 {
+        if preview_outputs(id).await == 0 {
+            detached = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    crate::manager::remove_pipe(id).await.unwrap();
+    assert!(detached, "preview output still attached after close");
+}
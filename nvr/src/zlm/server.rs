@@ -96,6 +96,13 @@ pub(crate) fn start_zlm_server(
                     let app = media.url_info.app();
                     let stream = media.url_info.stream();
                     log::info!("ZLM: media not found, app: {app}, stream: {stream}");
+                    if app == crate::init::device::DEVICE_APP {
+                        // An on-demand pipe starts publishing for the player.
+                        let device = crate::stream_key::device_of(&stream);
+                        runtime_nf.spawn(async move {
+                            crate::manager::hold_for_players(&device).await;
+                        });
+                    }
                     let Some(bridge) = crate::gb::bridge() else {
                         return true; // GB disabled: nothing to provide, but don't error
                     };
@@ -110,6 +117,9 @@ pub(crate) fn start_zlm_server(
                 let runtime_nr = runtime.clone();
                 events.on_media_no_reader(move |media| {
                     let stream = media.sender.stream();
+                    if media.sender.app() == crate::init::device::DEVICE_APP {
+                        crate::manager::release_for_players(&crate::stream_key::device_of(&stream));
+                    }
                     let Some(bridge) = crate::gb::bridge() else {
                         return;
                     };
@@ -131,6 +141,7 @@ pub(crate) fn start_zlm_server(
                     let record_file_size = record.ts.file_size();
                     let runtime_inner = runtime_clone.clone();
                     runtime_inner.spawn(async move {
+                        let closed = record_stream.clone();
                         if let Err(err) = persist_record_ts(
                             record_start_time,
                             record_duration,
@@ -146,6 +157,8 @@ pub(crate) fn start_zlm_server(
                         {
                             log::error!("ZLM: persist record ts failed: {:#}", err);
                         }
+                        // A segment boundary: what maintenance mode waits for.
+                        crate::maintenance::segment_closed(&closed);
                     });
                 });
             }
@@ -188,6 +201,26 @@ async fn archive_record_file(
     Ok(target_path)
 }
 
+/// Rewrite a closed fragmented MP4 segment as a faststart one, in place. Other
+/// containers are left alone, as is the segment if the remux fails.
+async fn faststart(path: &Path) {
+    let is_mp4 = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mp4"));
+    if !is_mp4 {
+        return;
+    }
+    match ffmpeg_bus::RemuxJob::new(path).spawn().await {
+        Ok(Ok(summary)) => log::debug!(
+            "ZLM: {} rewritten as faststart mp4 ({} bytes)",
+            path.display(),
+            summary.bytes
+        ),
+        Ok(Err(e)) => log::warn!("ZLM: faststart remux failed, keeping the segment: {e:#}"),
+        Err(e) => log::warn!("ZLM: faststart remux task failed: {e}"),
+    }
+}
+
 async fn persist_record_ts(
     start_time: u64,
     duration: f32,
@@ -201,23 +234,29 @@ async fn persist_record_ts(
 ) -> anyhow::Result<()> {
     let conn = crate::db::app_db_conn()?;
     let now = chrono::Utc::now();
+    // Recordings are kept by device id, whatever stream key it publishes as.
+    let stream = if app == crate::init::device::DEVICE_APP {
+        crate::stream_key::device_of(&stream)
+    } else {
+        stream
+    };
+    // ZLM names segments on the server's clock; archive them on the device's.
+    let tz = crate::tz::of_device(&stream).await?;
+    let file_name = crate::reconcile::archive_name(&file_name, start_time, tz);
     let archived_path = archive_record_file(&stream, &file_name, &file_path).await?;
+    if crate::config::config().record_faststart() {
+        faststart(&archived_path).await;
+    }
+    let meta = ffmpeg_bus::metadata::probe(&archived_path.to_string_lossy())?;
+    let archived_path = crate::encryption::seal_segment(&stream, archived_path).await;
     let archived_path_string = archived_path.to_string_lossy().to_string();
     let archived_folder = archived_path
         .parent()
         .map(|path| path.to_string_lossy().to_string())
         .unwrap_or_default();
     let archived_size = tokio::fs::metadata(&archived_path).await?.len() as usize;
-    let meta = ffmpeg_bus::metadata::probe(&archived_path_string)?;
-    let video_stream = meta
-        .streams
-        .iter()
-        .find(|stream| stream.codec_type == "video");
-    let audio_stream = meta
-        .streams
-        .iter()
-        .find(|stream| stream.codec_type == "audio");
-    let record = nvr_db::record_segment::RecordSegment {
+    let start_time = crate::clock::recording_start_time(&stream, start_time).await;
+    let mut record = nvr_db::record_segment::RecordSegment {
         id: uuid::Uuid::new_v4().simple().to_string(),
         record_type: 0,
         start_time,
@@ -229,38 +268,41 @@ async fn persist_record_ts(
         app,
         stream,
         vhost,
-        video_codec: video_stream
-            .map(|stream| stream.codec_name.clone())
-            .unwrap_or_default(),
-        video_width: video_stream
-            .and_then(|stream| stream.width)
-            .unwrap_or_default() as i32,
-        video_height: video_stream
-            .and_then(|stream| stream.height)
-            .unwrap_or_default() as i32,
-        video_fps: video_stream
-            .and_then(|stream| parse_rate(&stream.rate))
-            .unwrap_or_default(),
-        video_bit_rate: meta.format.bit_rate,
-        audio_codec: audio_stream
-            .map(|stream| stream.codec_name.clone())
-            .unwrap_or_default(),
-        audio_sample_rate: audio_stream
-            .and_then(|stream| stream.sample_rate)
-            .unwrap_or_default() as i32,
-        audio_channels: audio_stream
-            .and_then(|stream| stream.channels)
-            .unwrap_or_default() as i32,
-        audio_bit_rate: 0,
-        reserve_text1: String::new(),
-        reserve_text2: String::new(),
-        reserve_text3: String::new(),
-        reserve_int1: 0,
-        reserve_int2: 0,
         create_time: now,
         update_time: now,
+        ..Default::default()
     };
-    nvr_db::record_segment::upsert(&record, &conn).await
+    apply_media_info(&mut record, &meta);
+    nvr_db::record_segment::upsert(&record, &conn).await?;
+    crate::transport::segment_closed();
+    Ok(())
+}
+
+/// Fill a segment's codec fields from a probe of its file.
+pub(crate) fn apply_media_info(
+    record: &mut nvr_db::record_segment::RecordSegment,
+    meta: &ffmpeg_bus::metadata::MediaInfo,
+) {
+    let video_stream = meta
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type == "video");
+    let audio_stream = meta
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type == "audio");
+    if let Some(video) = video_stream {
+        record.video_codec = video.codec_name.clone();
+        record.video_width = video.width.unwrap_or_default() as i32;
+        record.video_height = video.height.unwrap_or_default() as i32;
+        record.video_fps = parse_rate(&video.rate).unwrap_or_default();
+    }
+    record.video_bit_rate = meta.format.bit_rate;
+    if let Some(audio) = audio_stream {
+        record.audio_codec = audio.codec_name.clone();
+        record.audio_sample_rate = audio.sample_rate.unwrap_or_default() as i32;
+        record.audio_channels = audio.channels.unwrap_or_default() as i32;
+    }
 }
 
 fn parse_rate(value: &str) -> Option<f32> {